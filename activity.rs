@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+
+// Kinds of resources that keep an activity feed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    Ticket,
+    Script,
+    FirewallRule,
+    // The change request chain of a staged firewall changeset, kept after it is applied
+    // or rejected
+    FirewallChangeset,
+    Alert,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Created,
+    Updated,
+    StatusChanged,
+    AssignmentChanged,
+    CommentAdded,
+    InternalNote,
+    AttachmentAdded,
+    SlaEvent,
+    AlertLinked,
+    Deleted,
+    Correction,
+    WorklogAdded,
+    WorklogUpdated,
+    WorklogDeleted,
+    ReferenceAdded,
+    ReferenceUpdated,
+    ReferenceRemoved,
+}
+
+impl ActivityKind {
+    // Kinds only staff may see
+    pub fn is_internal(&self) -> bool {
+        matches!(self, ActivityKind::InternalNote | ActivityKind::AlertLinked
+            | ActivityKind::WorklogAdded | ActivityKind::WorklogUpdated | ActivityKind::WorklogDeleted
+            | ActivityKind::ReferenceAdded | ActivityKind::ReferenceUpdated | ActivityKind::ReferenceRemoved)
+    }
+}
+
+// One immutable entry of a resource's activity feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceActivity {
+    pub id: Uuid,
+    pub resource_kind: ResourceKind,
+    pub resource_id: String,
+    pub actor: String,
+    pub timestamp: DateTime<Utc>,
+    pub kind: ActivityKind,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityPage {
+    pub total: usize,
+    pub offset: usize,
+    pub items: Vec<ResourceActivity>,
+}
+
+// Append-only store of activity feeds. There is no way to edit or remove an entry;
+// a mistake is fixed by recording a correction that references it.
+#[derive(Clone)]
+pub struct ActivityLog {
+    feeds: Arc<Mutex<HashMap<(ResourceKind, String), Vec<ResourceActivity>>>>,
+}
+
+impl ActivityLog {
+    pub fn new() -> Self {
+        Self {
+            feeds: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn record(&self,
+                  resource_kind: ResourceKind,
+                  resource_id: &str,
+                  actor: &str,
+                  kind: ActivityKind,
+                  payload: serde_json::Value) -> Result<ResourceActivity> {
+        let activity = ResourceActivity {
+            id: Uuid::new_v4(),
+            resource_kind,
+            resource_id: resource_id.to_string(),
+            actor: actor.to_string(),
+            timestamp: Utc::now(),
+            kind,
+            payload,
+        };
+
+        match self.feeds.lock() {
+            Ok(mut feeds) => {
+                feeds.entry((resource_kind, resource_id.to_string()))
+                    .or_default()
+                    .push(activity.clone());
+                Ok(activity)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on activity log")),
+        }
+    }
+
+    pub fn record_correction(&self,
+                             resource_kind: ResourceKind,
+                             resource_id: &str,
+                             actor: &str,
+                             corrects: Uuid,
+                             note: &str) -> Result<ResourceActivity> {
+        let exists = match self.feeds.lock() {
+            Ok(feeds) => feeds.get(&(resource_kind, resource_id.to_string()))
+                .map_or(false, |feed| feed.iter().any(|a| a.id == corrects)),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on activity log")),
+        };
+
+        if !exists {
+            return Err(anyhow!("Activity not found: {}", corrects));
+        }
+
+        self.record(resource_kind, resource_id, actor, ActivityKind::Correction, serde_json::json!({
+            "corrects": corrects,
+            "note": note,
+        }))
+    }
+
+    // Entries of every feed of one resource kind within the range, oldest first
+    pub fn between(&self,
+                   resource_kind: ResourceKind,
+                   from: DateTime<Utc>,
+                   to: DateTime<Utc>) -> Result<Vec<ResourceActivity>> {
+        match self.feeds.lock() {
+            Ok(feeds) => {
+                let mut entries: Vec<ResourceActivity> = feeds.iter()
+                    .filter(|((kind, _), _)| *kind == resource_kind)
+                    .flat_map(|(_, feed)| feed.iter())
+                    .filter(|a| a.timestamp >= from && a.timestamp <= to)
+                    .cloned()
+                    .collect();
+                entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+                Ok(entries)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on activity log")),
+        }
+    }
+
+    // Chronological page of a feed, optionally without internal-only entries
+    pub fn page(&self,
+                resource_kind: ResourceKind,
+                resource_id: &str,
+                include_internal: bool,
+                offset: usize,
+                limit: usize) -> Result<ActivityPage> {
+        match self.feeds.lock() {
+            Ok(feeds) => {
+                let visible: Vec<&ResourceActivity> = feeds.get(&(resource_kind, resource_id.to_string()))
+                    .map(|feed| feed.iter()
+                        .filter(|a| include_internal || !a.kind.is_internal())
+                        .collect())
+                    .unwrap_or_default();
+
+                Ok(ActivityPage {
+                    total: visible.len(),
+                    offset,
+                    items: visible.into_iter().skip(offset).take(limit).cloned().collect(),
+                })
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on activity log")),
+        }
+    }
+}
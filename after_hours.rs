@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use anyhow::{Result, anyhow};
+
+use crate::alerts::AlertsManager;
+use crate::calendar::CalendarManager;
+use crate::config::AfterHoursConfig;
+use crate::security::{AuditEvent, SecurityManager};
+
+// Actions listed per alert, the rest are counted
+const MAX_LISTED: usize = 20;
+
+// Raises an alert per user for watched audit actions recorded outside business hours.
+// Each event is looked at once, when it is new, against the calendar as it is then; a
+// later calendar change does not go back over events already checked.
+#[derive(Clone)]
+pub struct AfterHoursDetector {
+    config: AfterHoursConfig,
+    security: SecurityManager,
+    alerts: AlertsManager,
+    calendar: CalendarManager,
+    // Events up to here have been checked; those from before startup are not
+    checked_until: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl AfterHoursDetector {
+    pub fn new(config: AfterHoursConfig, security: SecurityManager, alerts: AlertsManager, calendar: CalendarManager) -> Self {
+        Self {
+            config,
+            security,
+            alerts,
+            calendar,
+            checked_until: Arc::new(Mutex::new(Utc::now())),
+        }
+    }
+
+    fn watched(&self, event: &AuditEvent) -> bool {
+        !self.config.ignore_users.iter().any(|u| u.eq_ignore_ascii_case(&event.user))
+            && self.config.actions.iter().any(|prefix| event.action.starts_with(prefix.as_str()))
+    }
+
+    // Returns how many alerts were raised
+    pub fn run(&self) -> Result<usize> {
+        let calendar = self.calendar.calendar()?;
+        let mut checked_until = self.checked_until.lock().map_err(|_| anyhow!("Failed to acquire lock on after-hours detector"))?;
+        let since = *checked_until;
+
+        let events: Vec<AuditEvent> = self.security.get_audit_logs()
+            .into_iter()
+            .filter(|e| e.timestamp > since)
+            .collect();
+        if let Some(latest) = events.iter().map(|e| e.timestamp).max() {
+            *checked_until = latest;
+        }
+
+        let mut by_user: BTreeMap<String, Vec<&AuditEvent>> = BTreeMap::new();
+        for event in events.iter().filter(|e| self.watched(e) && !calendar.is_business_time(e.timestamp)) {
+            by_user.entry(event.user.clone()).or_default().push(event);
+        }
+
+        let timezone = calendar.timezone();
+        for (user, events) in &by_user {
+            let mut lines: Vec<String> = events.iter()
+                .take(MAX_LISTED)
+                .map(|e| format!("{} {} on {} ({:?})",
+                                 e.timestamp.with_timezone(&timezone).format("%Y-%m-%d %H:%M"), e.action, e.resource, e.status))
+                .collect();
+            if events.len() > MAX_LISTED {
+                lines.push(format!("... and {} more", events.len() - MAX_LISTED));
+            }
+
+            self.alerts.create_alert(
+                self.config.severity.clone(),
+                format!("After-hours activity by {}", user),
+                format!("{} audited actions outside business hours (calendar version {}):\n{}",
+                        events.len(), calendar.version, lines.join("\n")),
+                "after_hours".to_string(),
+                Vec::new(),
+            )?;
+        }
+        Ok(by_user.len())
+    }
+}
@@ -0,0 +1,100 @@
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::ingestion::IngestionPipeline;
+use crate::models::{Alert, AlertSeverity, EventCategory, LogEntry, LogParser, LogSeverity};
+
+pub const EVENT_TYPE: &str = "alert.lifecycle";
+
+// Marks entries generated from our own alerts. Only set on the internal path; external
+// entries carrying it have it removed at ingestion.
+pub const LIFECYCLE_TAG: &str = "siem:alert_lifecycle";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertLifecycle {
+    Created,
+    Escalated,
+    Resolved,
+}
+
+impl AlertLifecycle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertLifecycle::Created => "created",
+            AlertLifecycle::Escalated => "escalated",
+            AlertLifecycle::Resolved => "resolved",
+        }
+    }
+}
+
+pub fn is_lifecycle(entry: &LogEntry) -> bool {
+    entry.tags.iter().any(|t| t == LIFECYCLE_TAG)
+}
+
+pub fn log_severity(severity: &AlertSeverity) -> LogSeverity {
+    match severity {
+        AlertSeverity::Low => LogSeverity::Info,
+        AlertSeverity::Medium => LogSeverity::Warning,
+        AlertSeverity::High => LogSeverity::Error,
+        AlertSeverity::Critical => LogSeverity::Critical,
+    }
+}
+
+// Stable across repeated alerts of the same kind from the same source
+pub fn fingerprint(alert: &Alert) -> String {
+    hex::encode(&Sha256::digest(format!("{}\n{}", alert.source, alert.title).as_bytes())[..8])
+}
+
+pub fn lifecycle_entry(alert: &Alert, event: AlertLifecycle, detail: Option<String>) -> LogEntry {
+    let fingerprint = fingerprint(alert);
+    let status = format!("{:?}", alert.status);
+    let message = match &detail {
+        Some(detail) => format!("Alert {}: {} ({})", event.as_str(), alert.title, detail),
+        None => format!("Alert {}: {}", event.as_str(), alert.title),
+    };
+
+    LogEntry {
+        id: Uuid::new_v4(),
+        timestamp: chrono::Utc::now(),
+        source: "siem".to_string(),
+        event_type: EVENT_TYPE.to_string(),
+        severity: log_severity(&alert.severity),
+        message,
+        raw_data: json!({
+            "event": event.as_str(),
+            "alert_id": alert.id,
+            "fingerprint": fingerprint,
+            "status": status,
+            "severity": alert.severity,
+            "title": alert.title,
+            "alert_source": alert.source,
+            "detail": detail,
+        }).to_string(),
+        host: None,
+        user: alert.assigned_to.clone(),
+        application: Some(alert.source.clone()),
+        tags: vec![
+            LIFECYCLE_TAG.to_string(),
+            format!("alert_event:{}", event.as_str()),
+            format!("alert_id:{}", alert.id),
+            format!("alert_fingerprint:{}", fingerprint),
+            format!("alert_status:{}", status),
+        ],
+        category: EventCategory::Audit,
+        hostname: None,
+        site_id: None,
+        parser: LogParser::Internal,
+    }
+}
+
+// Stores lifecycle entries queued by the alerts manager; runs until the manager is gone
+pub async fn run(mut events: mpsc::UnboundedReceiver<LogEntry>, pipeline: IngestionPipeline) {
+    while let Some(entry) = events.recv().await {
+        if let Err(e) = pipeline.ingest_alert_event(entry) {
+            warn!("Failed to store alert lifecycle event: {}", e);
+        }
+    }
+}
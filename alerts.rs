@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context, anyhow};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::alert_events::{self, AlertLifecycle};
+use crate::config::ReferencesConfig;
+use crate::models::{Alert, AlertSeverity, AlertStatus, LogEntry, NotificationAttempt, RemediationRecord};
+use crate::references::{self, ExternalReference, ReferenceInput};
+
+// Alerts are persisted so acknowledgement state and escalations survive a restart
+#[derive(Clone)]
+pub struct AlertsManager {
+    alerts_dir: PathBuf,
+    alerts: Arc<Mutex<HashMap<Uuid, Alert>>>,
+    // Where lifecycle events go when they are enabled, see alert_events
+    lifecycle: Arc<Mutex<Option<mpsc::UnboundedSender<LogEntry>>>>,
+    // New alerts for automatic remediation, see remediation
+    remediation: Arc<Mutex<Option<mpsc::UnboundedSender<Alert>>>>,
+}
+
+impl AlertsManager {
+    pub fn new(alerts_dir: &str) -> Result<Self> {
+        let alerts_dir = PathBuf::from(alerts_dir);
+
+        if !alerts_dir.exists() {
+            fs::create_dir_all(&alerts_dir)
+                .context(format!("Failed to create alerts directory: {:?}", alerts_dir))?;
+            info!("Created alerts directory: {:?}", alerts_dir);
+        }
+
+        let mut alerts = HashMap::new();
+
+        for entry in fs::read_dir(&alerts_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read alert file: {:?}", path))?;
+            match serde_json::from_str::<Alert>(&contents) {
+                Ok(alert) => {
+                    alerts.insert(alert.id, alert);
+                },
+                Err(e) => warn!("Skipping invalid alert file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} alerts", alerts.len());
+
+        Ok(Self {
+            alerts_dir,
+            alerts: Arc::new(Mutex::new(alerts)),
+            lifecycle: Arc::new(Mutex::new(None)),
+            remediation: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // Events queue until the receiver runs, so alerts raised during startup are kept
+    pub fn enable_lifecycle_events(&self) -> mpsc::UnboundedReceiver<LogEntry> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Ok(mut lifecycle) = self.lifecycle.lock() {
+            *lifecycle = Some(sender);
+        }
+        receiver
+    }
+
+    pub fn enable_remediation(&self) -> mpsc::UnboundedReceiver<Alert> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Ok(mut remediation) = self.remediation.lock() {
+            *remediation = Some(sender);
+        }
+        receiver
+    }
+
+    fn emit(&self, alert: &Alert, event: AlertLifecycle, detail: Option<String>) {
+        if let Ok(lifecycle) = self.lifecycle.lock() {
+            if let Some(sender) = lifecycle.as_ref() {
+                let _ = sender.send(alert_events::lifecycle_entry(alert, event, detail));
+            }
+        }
+    }
+
+    fn save_alert(&self, alert: &Alert) -> Result<()> {
+        let path = self.alerts_dir.join(format!("{}.json", alert.id));
+        let json = serde_json::to_string_pretty(alert)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write alert file: {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn create_alert(&self,
+                        severity: AlertSeverity,
+                        title: String,
+                        description: String,
+                        source: String,
+                        related_logs: Vec<Uuid>) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+
+        let alert = Alert {
+            id,
+            created_at: Utc::now(),
+            severity,
+            title,
+            description,
+            status: AlertStatus::New,
+            source,
+            related_logs,
+            assigned_to: None,
+            notifications: Vec::new(),
+            resolved_at: None,
+            remediations: Vec::new(),
+            references: Vec::new(),
+        };
+
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                match alert.severity {
+                    AlertSeverity::High | AlertSeverity::Critical => {
+                        warn!("Alert raised [{:?}] {}: {}", alert.severity, alert.source, alert.title)
+                    },
+                    _ => info!("Alert raised [{:?}] {}: {}", alert.severity, alert.source, alert.title),
+                }
+
+                self.save_alert(&alert)?;
+                self.emit(&alert, AlertLifecycle::Created, None);
+                if let Ok(remediation) = self.remediation.lock() {
+                    if let Some(sender) = remediation.as_ref() {
+                        let _ = sender.send(alert.clone());
+                    }
+                }
+                alerts.insert(id, alert);
+                Ok(id)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn update_status(&self, id: Uuid, status: AlertStatus) -> Result<()> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                let mut resolved = false;
+                match status {
+                    AlertStatus::Resolved | AlertStatus::Closed => {
+                        if alert.resolved_at.is_none() {
+                            alert.resolved_at = Some(Utc::now());
+                            resolved = true;
+                        }
+                    },
+                    _ => alert.resolved_at = None,
+                }
+                alert.status = status;
+                self.save_alert(alert)?;
+                if resolved {
+                    self.emit(alert, AlertLifecycle::Resolved, None);
+                }
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn record_notification(&self, id: Uuid, attempt: NotificationAttempt) -> Result<()> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                // The first successful notification of a step is the escalation to it
+                let escalated = attempt.success && !alert.notifications.iter()
+                    .any(|n| n.success && n.policy_id == attempt.policy_id && n.step == attempt.step);
+                let detail = format!("step {} notified {}", attempt.step + 1, attempt.target);
+                alert.notifications.push(attempt);
+                self.save_alert(alert)?;
+                if escalated {
+                    self.emit(alert, AlertLifecycle::Escalated, Some(detail));
+                }
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn record_remediation(&self, id: Uuid, record: RemediationRecord) -> Result<()> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                alert.remediations.push(record);
+                self.save_alert(alert)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn add_reference(&self, id: Uuid, input: ReferenceInput, added_by: &str, config: &ReferencesConfig) -> Result<ExternalReference> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                let reference = references::add(&mut alert.references, input, added_by, config)?;
+                self.save_alert(alert)?;
+                Ok(reference)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    // Returns the reference before and after the update
+    pub fn update_reference(&self,
+                            id: Uuid,
+                            reference_id: Uuid,
+                            input: ReferenceInput,
+                            config: &ReferencesConfig) -> Result<(ExternalReference, ExternalReference)> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                let updated = references::update(&mut alert.references, reference_id, input, config)?;
+                self.save_alert(alert)?;
+                Ok(updated)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn remove_reference(&self, id: Uuid, reference_id: Uuid) -> Result<ExternalReference> {
+        match self.alerts.lock() {
+            Ok(mut alerts) => {
+                let alert = alerts.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))?;
+                let reference = references::remove(&mut alert.references, reference_id)?;
+                self.save_alert(alert)?;
+                Ok(reference)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    pub fn get_alert(&self, id: Uuid) -> Result<Alert> {
+        match self.alerts.lock() {
+            Ok(alerts) => {
+                alerts.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Alert not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    // Alerts raised within the range that satisfy the predicate, newest first
+    pub fn alerts_between<F>(&self,
+                             from: Option<DateTime<Utc>>,
+                             to: Option<DateTime<Utc>>,
+                             limit: usize,
+                             predicate: F) -> Result<Vec<Alert>>
+    where
+        F: Fn(&Alert) -> bool,
+    {
+        match self.alerts.lock() {
+            Ok(alerts) => {
+                let mut matching: Vec<&Alert> = alerts.values()
+                    .filter(|a| from.map_or(true, |from| a.created_at >= from)
+                        && to.map_or(true, |to| a.created_at <= to)
+                        && predicate(a))
+                    .collect();
+                matching.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                Ok(matching.into_iter().take(limit).cloned().collect())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+
+    // Returns all alerts, newest first
+    pub fn get_all_alerts(&self) -> Result<Vec<Alert>> {
+        match self.alerts.lock() {
+            Ok(alerts) => {
+                let mut all: Vec<Alert> = alerts.values().cloned().collect();
+                all.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on alerts")),
+        }
+    }
+}
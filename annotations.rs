@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+// What an annotation is about; global ones show up on every chart
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AnnotationScope {
+    Global,
+    Interface { name: String },
+    Asset { id: Uuid },
+    Alert { id: Uuid },
+}
+
+// A note pinned to a point in time (no end) or a time range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: Uuid,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub text: String,
+    pub scope: AnnotationScope,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub updated_by: String,
+    // Incremented on every edit
+    pub revision: u32,
+}
+
+impl Annotation {
+    // Whether the annotation touches the window; open bounds are unlimited
+    pub fn overlaps(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+        let end = self.end.unwrap_or(self.start);
+        from.map_or(true, |from| end >= from) && to.map_or(true, |to| self.start <= to)
+    }
+}
+
+// Editable part of an annotation, used for both creation and updates
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnnotationFields {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub text: String,
+    pub scope: AnnotationScope,
+}
+
+impl AnnotationFields {
+    fn validate(&self) -> Result<()> {
+        if self.text.trim().is_empty() {
+            return Err(anyhow!("Annotation text cannot be empty"));
+        }
+        if self.end.map_or(false, |end| end < self.start) {
+            return Err(anyhow!("Annotation end must not be before its start"));
+        }
+        if let AnnotationScope::Interface { name } = &self.scope {
+            if name.is_empty() {
+                return Err(anyhow!("Interface name cannot be empty"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // Only these scopes; global annotations are included when include_global is set
+    pub scopes: Vec<AnnotationScope>,
+    pub include_global: bool,
+}
+
+impl AnnotationFilter {
+    fn matches(&self, annotation: &Annotation) -> bool {
+        if !annotation.overlaps(self.from, self.to) {
+            return false;
+        }
+        if self.scopes.is_empty() {
+            return true;
+        }
+
+        (self.include_global && annotation.scope == AnnotationScope::Global)
+            || self.scopes.contains(&annotation.scope)
+    }
+}
+
+#[derive(Clone)]
+pub struct AnnotationManager {
+    annotations_dir: PathBuf,
+    annotations: Arc<Mutex<HashMap<Uuid, Annotation>>>,
+}
+
+impl AnnotationManager {
+    pub fn new(annotations_dir: &str) -> Result<Self> {
+        let annotations_dir = PathBuf::from(annotations_dir);
+
+        if !annotations_dir.exists() {
+            fs::create_dir_all(&annotations_dir)
+                .context(format!("Failed to create annotations directory: {:?}", annotations_dir))?;
+            info!("Created annotations directory: {:?}", annotations_dir);
+        }
+
+        let mut annotations = HashMap::new();
+
+        for entry in fs::read_dir(&annotations_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read annotation file: {:?}", path))?;
+            match serde_json::from_str::<Annotation>(&contents) {
+                Ok(annotation) => {
+                    annotations.insert(annotation.id, annotation);
+                },
+                Err(e) => warn!("Skipping invalid annotation file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} annotations", annotations.len());
+
+        Ok(Self {
+            annotations_dir,
+            annotations: Arc::new(Mutex::new(annotations)),
+        })
+    }
+
+    fn save_annotation(&self, annotation: &Annotation) -> Result<()> {
+        let path = self.annotations_dir.join(format!("{}.json", annotation.id));
+        let json = serde_json::to_string_pretty(annotation)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write annotation file: {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn create_annotation(&self, fields: AnnotationFields, author: &str) -> Result<Annotation> {
+        fields.validate()?;
+
+        let now = Utc::now();
+        let annotation = Annotation {
+            id: Uuid::new_v4(),
+            start: fields.start,
+            end: fields.end,
+            text: fields.text,
+            scope: fields.scope,
+            author: author.to_string(),
+            created_at: now,
+            updated_at: now,
+            updated_by: author.to_string(),
+            revision: 1,
+        };
+
+        match self.annotations.lock() {
+            Ok(mut annotations) => {
+                self.save_annotation(&annotation)?;
+                annotations.insert(annotation.id, annotation.clone());
+                info!("Created annotation {} by {}", annotation.id, author);
+                Ok(annotation)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on annotations")),
+        }
+    }
+
+    pub fn update_annotation(&self, id: Uuid, fields: AnnotationFields, updated_by: &str) -> Result<Annotation> {
+        fields.validate()?;
+
+        match self.annotations.lock() {
+            Ok(mut annotations) => {
+                let annotation = annotations.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Annotation not found: {}", id))?;
+
+                annotation.start = fields.start;
+                annotation.end = fields.end;
+                annotation.text = fields.text;
+                annotation.scope = fields.scope;
+                annotation.updated_at = Utc::now();
+                annotation.updated_by = updated_by.to_string();
+                annotation.revision += 1;
+
+                self.save_annotation(annotation)?;
+                Ok(annotation.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on annotations")),
+        }
+    }
+
+    // Only the author or an admin may delete an annotation
+    pub fn delete_annotation(&self, id: Uuid, username: &str, is_admin: bool) -> Result<Annotation> {
+        match self.annotations.lock() {
+            Ok(mut annotations) => {
+                let annotation = annotations.get(&id)
+                    .ok_or_else(|| anyhow!("Annotation not found: {}", id))?;
+
+                if annotation.author != username && !is_admin {
+                    return Err(anyhow!("Only the author or an admin may delete annotation {}", id));
+                }
+
+                let path = self.annotations_dir.join(format!("{}.json", id));
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .context(format!("Failed to delete annotation file: {:?}", path))?;
+                }
+
+                info!("Deleted annotation {} by {}", id, username);
+                annotations.remove(&id)
+                    .ok_or_else(|| anyhow!("Annotation not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on annotations")),
+        }
+    }
+
+    pub fn get_annotation(&self, id: Uuid) -> Result<Annotation> {
+        match self.annotations.lock() {
+            Ok(annotations) => annotations.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Annotation not found: {}", id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on annotations")),
+        }
+    }
+
+    // Matching annotations, oldest first
+    pub fn query(&self, filter: &AnnotationFilter) -> Result<Vec<Annotation>> {
+        match self.annotations.lock() {
+            Ok(annotations) => {
+                let mut matching: Vec<Annotation> = annotations.values()
+                    .filter(|a| filter.matches(a))
+                    .cloned()
+                    .collect();
+                matching.sort_by(|a, b| a.start.cmp(&b.start));
+                Ok(matching)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on annotations")),
+        }
+    }
+
+    pub fn for_alert(&self, alert_id: Uuid) -> Result<Vec<Annotation>> {
+        self.query(&AnnotationFilter {
+            scopes: vec![AnnotationScope::Alert { id: alert_id }],
+            ..Default::default()
+        })
+    }
+}
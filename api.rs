@@ -1,67 +1,498 @@
 use axum::{
     Router,
-    routing::{get, post},
-    extract::{Path, State, Json},
+    routing::{get, post, put, patch, delete},
+    extract::{FromRequestParts, MatchedPath, Path, Query, Request, State, Json},
     http::StatusCode,
-    response::IntoResponse,
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    response::sse::{Event, KeepAlive, Sse},
 };
+use futures::StreamExt;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
+use uuid::Uuid;
 
-use crate::config::Config;
+use crate::config::{self, Config, SmtpConfig};
 use crate::security::SecurityManager;
-use crate::scripts::ScriptsManager;
-use crate::tickets::TicketsManager;
+use crate::scripts::{ReviewStatus, Script, ScriptCategory, ScriptDependencies, ScriptOutputFormat, ScriptParameter, ScriptsManager};
+use crate::tickets::{TicketStatus, TicketSummary, TicketsManager, WorklogInput};
+use crate::ticket_snippets::{self, SnippetManager};
 use crate::network::NetworkManager;
 use crate::visualizations::VisualizationManager;
+use crate::logs::{LogFilter, LogsManager};
+use crate::searches::SavedSearchManager;
+use crate::auth::{self, AuthUser, ClientInfo};
+use crate::models::{EventCategory, LogEntry, LogParser, LogSeverity};
+use crate::classification;
+use crate::alerts::AlertsManager;
+use crate::extraction::{ExtractionManager, ExtractionRule, ExtractionTarget};
+use crate::ingestion::IngestionPipeline;
+use crate::database::DatabaseManager;
+use crate::ingestion_quotas::IngestionQuotas;
+use crate::script_approvals::ScriptApprovals;
+use crate::oidc::OidcClient;
+use crate::notifications::Notifier;
+use crate::digest;
+use crate::config_history::ConfigHistory;
+use crate::resolver::Resolver;
+use crate::sites::{SiteFields, SiteManager, SiteScope};
+use crate::config::QuotaConfig;
+use crate::capture::{CaptureManager, CaptureStatus};
+use crate::security::{AccessControl, AuditStatus, APPLY_PERMISSION, required_permissions};
+use crate::services::{ServiceProtocol, ServiceRegistry};
+use crate::firewall_import::{self, ImportFormat, UnconvertedLine};
+use crate::ticket_import::{self, ColumnMapping, ImportRowResult};
+use crate::network::{self, BondConfig, Direction, ForwardPolicy, PreviewConflict, RulePosition, RuleSelectors, RuleSpec, SelfService, ServiceRuleChanges, StagedChangeset};
+use crate::tasks::TaskRegistry;
+use crate::users::UserManager;
+use crate::password_policy::PolicyViolations;
+use crate::sessions::SessionManager;
+use crate::models::{NotificationPreferences, User, UserRole};
+use crate::locations::{location_path, LocationKind, LocationManager, LocationNode};
+use crate::printers::{PrintJob, PrinterManager, PrinterSupply};
+use crate::printer_reorder::ReorderHook;
+use crate::tagging::{TaggingManager, TaggingRuleSpec};
+use crate::attachment_scan::{AttachmentScanner, Verdict};
+use crate::chargeback::{ChargebackFormat, ChargebackManager};
+use crate::remediation::{RemediationManager, RemediationSpec};
+use crate::traffic_history::Window;
+use crate::ups::UpsMonitor;
+use crate::ticket_portal::{self, PortalTicket, TicketPortal};
+use crate::outbound::{self, HttpClients};
+use crate::redaction::{self, Redacted};
+use crate::correlation::{Backtest, BacktestStatus, Backtester, CorrelationRule};
+use crate::prometheus_rules::{self, ExportSource};
+use crate::tags::{self, TagRegistry};
+use crate::bandwidth_quota::BandwidthQuotas;
+use crate::inventory_export::{self, ExportFormat};
+use crate::heartbeat::Heartbeat;
+use crate::request_trace;
+use crate::firewall_backup::FirewallBackups;
+use crate::worklog_report::{self, WorklogGrouping};
+use crate::ingest_timing::IngestTimings;
+use crate::api_usage::{self, ApiUsage, UsageGrouping, UsageSort, UsageWindow};
+use crate::replication::{self, ObjectKind, Replication};
+use crate::reparse::LogReparser;
+use crate::integrations::{self, IntegrationSource, Integrations};
+use crate::calendar::{BusinessPeriod, CalendarManager, CalendarSettings};
+use crate::sla::SlaTracker;
+use crate::ticket_board::{self, BoardPreferences, BoardPreferencesManager};
+use crate::host_inventory::InventoryCollector;
+use crate::firewall_approvals::{ChangeDecision, FirewallApprovals};
+use crate::anonymize::Anonymizer;
+use crate::dhcp_relay::DhcpRelayManager;
+use crate::references::{self, ExternalReference, ReferenceInput, ReferenceMatch, ReferenceSearch};
+use crate::startup::Startup;
+use crate::print_accounting::{self, PrintAccounting};
+use crate::evidence::{EvidenceJob, EvidenceManager, EvidenceQuery, EvidenceStatus};
+use crate::log_tail::{LogTail, TailEvent, TailFilter, TailParams};
+use crate::source_health::{Expectation, SourceHealthMonitor};
+use crate::graph_snapshots::{self, GraphSnapshotStore};
+use crate::graph_grouping::{self, GroupingQuery};
+use crate::body_limits;
+use crate::version::{self, UpdateChecker};
+use crate::link_flap::LinkFlapDetector;
+use crate::flow_export::{self, FlowExportFormat};
+use crate::paths::Paths;
+use crate::disk_monitor::{DiskMonitor, ProtectiveAction};
+use crate::activity::{ActivityKind, ActivityLog, ResourceKind};
+use crate::syslog::SyslogTlsListener;
+use crate::reports::{self, ReportFormat, ReportOverrides, ReportSettings};
+use crate::assets::{AssetFields, AssetManager, AssetObservation};
+use crate::scans::ScanManager;
+use crate::models::{Asset, ScanFinding, ScanStatus};
+use crate::timeline::{self, AssetMatcher, TimelineItem, TimelineItemKind, TimelinePage};
+use crate::escalation::{EscalationEngine, EscalationPolicyFields};
+use crate::geoip::GeoIpResolver;
+use crate::interface_metadata::{InterfaceMetadataPatch, InterfaceMetadataStore};
+use crate::fleet::{AssetFilter, FleetRunner};
+use crate::annotations::{Annotation, AnnotationFields, AnnotationFilter, AnnotationManager, AnnotationScope};
+use crate::setup::{self, SetupState};
+use crate::models::{AlertSeverity, AlertStatus};
+use std::sync::Mutex;
+use std::collections::HashMap;
 
 // Define application state that will be shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub security_manager: SecurityManager,
-    pub scripts_manager: Arc<ScriptsManager>,
+    pub access_control: AccessControl,
+    pub scripts_manager: Arc<Mutex<ScriptsManager>>,
     pub tickets_manager: Arc<TicketsManager>,
     pub network_manager: Arc<NetworkManager>,
     pub visualization_manager: Arc<VisualizationManager>,
+    pub logs_manager: Arc<LogsManager>,
+    pub saved_search_manager: Arc<SavedSearchManager>,
+    pub alerts_manager: Arc<AlertsManager>,
+    pub extraction_manager: Arc<ExtractionManager>,
+    pub ingestion_pipeline: Arc<IngestionPipeline>,
+    pub capture_manager: Arc<CaptureManager>,
+    pub service_registry: Arc<ServiceRegistry>,
+    pub task_registry: Arc<TaskRegistry>,
+    pub user_manager: Arc<UserManager>,
+    pub session_manager: Arc<SessionManager>,
+    pub location_manager: Arc<LocationManager>,
+    pub printer_manager: Arc<Mutex<PrinterManager>>,
+    pub paths: Arc<Paths>,
+    pub disk_monitor: Arc<DiskMonitor>,
+    pub activity_log: Arc<ActivityLog>,
+    pub syslog_listener: Arc<SyslogTlsListener>,
+    pub asset_manager: Arc<AssetManager>,
+    pub scan_manager: Arc<ScanManager>,
+    pub escalation_engine: Arc<EscalationEngine>,
+    pub geoip: Arc<GeoIpResolver>,
+    pub interface_metadata: Arc<InterfaceMetadataStore>,
+    pub fleet_runner: Arc<FleetRunner>,
+    pub annotation_manager: Arc<AnnotationManager>,
+    pub setup: SetupState,
+    pub ingestion_quotas: Arc<IngestionQuotas>,
+    pub script_approvals: Arc<ScriptApprovals>,
+    // Set when single sign-on is enabled
+    pub oidc: Option<Arc<OidcClient>>,
+    pub notifier: Arc<Notifier>,
+    pub config_history: Arc<ConfigHistory>,
+    pub resolver: Arc<Resolver>,
+    pub sites: Arc<SiteManager>,
+    pub print_accounting: Arc<PrintAccounting>,
+    pub evidence: Arc<EvidenceManager>,
+    pub log_tail: Arc<LogTail>,
+    pub source_health: Arc<SourceHealthMonitor>,
+    pub graph_snapshots: Arc<GraphSnapshotStore>,
+    pub update_checker: Arc<UpdateChecker>,
+    pub link_flaps: Arc<LinkFlapDetector>,
+    pub snippets: Arc<SnippetManager>,
+    pub database: Option<Arc<DatabaseManager>>,
+    pub printer_reorder: ReorderHook,
+    pub tagging: TaggingManager,
+    pub attachment_scanner: AttachmentScanner,
+    pub chargeback: ChargebackManager,
+    pub remediation: RemediationManager,
+    pub ups: UpsMonitor,
+    pub portal: TicketPortal,
+    pub http_clients: HttpClients,
+    pub backtester: Backtester,
+    pub tags: TagRegistry,
+    pub bandwidth_quotas: BandwidthQuotas,
+    pub heartbeat: Heartbeat,
+    pub firewall_backups: FirewallBackups,
+    pub ingest_timings: IngestTimings,
+    pub api_usage: ApiUsage,
+    pub replication: Replication,
+    pub log_reparser: LogReparser,
+    pub integrations: Integrations,
+    pub calendar: CalendarManager,
+    pub sla: SlaTracker,
+    pub ticket_board: BoardPreferencesManager,
+    pub inventory: InventoryCollector,
+    pub firewall_approvals: FirewallApprovals,
+    pub dhcp_relays: DhcpRelayManager,
+    // Which subsystems started, see startup
+    pub startup: Startup,
 }
 
 // Setup routes for API
 pub fn setup_routes(
     config: Config,
     security_manager: SecurityManager,
-    scripts_manager: ScriptsManager,
+    access_control: AccessControl,
+    scripts_manager: Arc<Mutex<ScriptsManager>>,
     tickets_manager: TicketsManager,
-    network_manager: NetworkManager,
+    network_manager: Arc<NetworkManager>,
     visualization_manager: VisualizationManager,
+    logs_manager: LogsManager,
+    saved_search_manager: SavedSearchManager,
+    alerts_manager: AlertsManager,
+    extraction_manager: ExtractionManager,
+    ingestion_pipeline: IngestionPipeline,
+    capture_manager: CaptureManager,
+    service_registry: ServiceRegistry,
+    task_registry: TaskRegistry,
+    user_manager: UserManager,
+    session_manager: SessionManager,
+    location_manager: LocationManager,
+    printer_manager: PrinterManager,
+    paths: Paths,
+    disk_monitor: DiskMonitor,
+    activity_log: ActivityLog,
+    syslog_listener: SyslogTlsListener,
+    asset_manager: AssetManager,
+    scan_manager: ScanManager,
+    escalation_engine: EscalationEngine,
+    geoip: GeoIpResolver,
+    interface_metadata: InterfaceMetadataStore,
+    fleet_runner: FleetRunner,
+    annotation_manager: AnnotationManager,
+    setup: SetupState,
+    ingestion_quotas: IngestionQuotas,
+    script_approvals: ScriptApprovals,
+    oidc: Option<OidcClient>,
+    notifier: Notifier,
+    config_history: ConfigHistory,
+    resolver: Resolver,
+    site_manager: SiteManager,
+    print_accounting: PrintAccounting,
+    evidence_manager: EvidenceManager,
+    log_tail: LogTail,
+    source_health: SourceHealthMonitor,
+    graph_snapshots: GraphSnapshotStore,
+    update_checker: UpdateChecker,
+    link_flaps: LinkFlapDetector,
+    snippets: SnippetManager,
+    database: Option<DatabaseManager>,
+    printer_reorder: ReorderHook,
+    tagging: TaggingManager,
+    attachment_scanner: AttachmentScanner,
+    chargeback: ChargebackManager,
+    remediation: RemediationManager,
+    ups: UpsMonitor,
+    portal: TicketPortal,
+    http_clients: HttpClients,
+    backtester: Backtester,
+    tags: TagRegistry,
+    bandwidth_quotas: BandwidthQuotas,
+    heartbeat: Heartbeat,
+    firewall_backups: FirewallBackups,
+    ingest_timings: IngestTimings,
+    api_usage: ApiUsage,
+    replication: Replication,
+    log_reparser: LogReparser,
+    integrations: Integrations,
+    calendar: CalendarManager,
+    sla: SlaTracker,
+    ticket_board: BoardPreferencesManager,
+    inventory: InventoryCollector,
+    firewall_approvals: FirewallApprovals,
+    dhcp_relays: DhcpRelayManager,
+    startup: Startup,
 ) -> Router {
     let app_state = Arc::new(AppState {
         config,
         security_manager,
-        scripts_manager: Arc::new(scripts_manager),
+        access_control,
+        scripts_manager,
         tickets_manager: Arc::new(tickets_manager),
-        network_manager: Arc::new(network_manager),
+        network_manager,
         visualization_manager: Arc::new(visualization_manager),
+        logs_manager: Arc::new(logs_manager),
+        saved_search_manager: Arc::new(saved_search_manager),
+        alerts_manager: Arc::new(alerts_manager),
+        extraction_manager: Arc::new(extraction_manager),
+        ingestion_pipeline: Arc::new(ingestion_pipeline),
+        capture_manager: Arc::new(capture_manager),
+        service_registry: Arc::new(service_registry),
+        task_registry: Arc::new(task_registry),
+        user_manager: Arc::new(user_manager),
+        session_manager: Arc::new(session_manager),
+        location_manager: Arc::new(location_manager),
+        printer_manager: Arc::new(Mutex::new(printer_manager)),
+        paths: Arc::new(paths),
+        disk_monitor: Arc::new(disk_monitor),
+        activity_log: Arc::new(activity_log),
+        syslog_listener: Arc::new(syslog_listener),
+        asset_manager: Arc::new(asset_manager),
+        scan_manager: Arc::new(scan_manager),
+        escalation_engine: Arc::new(escalation_engine),
+        geoip: Arc::new(geoip),
+        interface_metadata: Arc::new(interface_metadata),
+        fleet_runner: Arc::new(fleet_runner),
+        annotation_manager: Arc::new(annotation_manager),
+        setup,
+        ingestion_quotas: Arc::new(ingestion_quotas),
+        script_approvals: Arc::new(script_approvals),
+        oidc: oidc.map(Arc::new),
+        notifier: Arc::new(notifier),
+        config_history: Arc::new(config_history),
+        resolver: Arc::new(resolver),
+        sites: Arc::new(site_manager),
+        print_accounting: Arc::new(print_accounting),
+        evidence: Arc::new(evidence_manager),
+        log_tail: Arc::new(log_tail),
+        source_health: Arc::new(source_health),
+        graph_snapshots: Arc::new(graph_snapshots),
+        update_checker: Arc::new(update_checker),
+        link_flaps: Arc::new(link_flaps),
+        snippets: Arc::new(snippets),
+        database: database.map(Arc::new),
+        printer_reorder,
+        tagging,
+        attachment_scanner,
+        chargeback,
+        remediation,
+        ups,
+        portal,
+        http_clients,
+        backtester,
+        tags,
+        bandwidth_quotas,
+        heartbeat,
+        firewall_backups,
+        ingest_timings,
+        api_usage,
+        replication,
+        log_reparser,
+        integrations,
+        calendar,
+        sla,
+        ticket_board,
+        inventory,
+        firewall_approvals,
+        dhcp_relays,
+        startup,
     });
 
+    // Tasks that read across managers run on the shared state
+    if let Err(e) = digest::spawn_tasks(&app_state) {
+        warn!("Failed to start digest tasks: {}", e);
+    }
+    if let Err(e) = graph_snapshots::spawn_tasks(&app_state) {
+        warn!("Failed to start network graph snapshots: {}", e);
+    }
+
     Router::new()
         .route("/", get(root_handler))
         .route("/api/health", get(health_check))
+        .route("/metrics", get(metrics))
+
+        // Authentication routes
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .route("/api/auth/oidc/login", get(oidc_login))
+        .route("/api/auth/oidc/callback", get(oidc_callback))
+        .route("/api/auth/sessions", get(list_sessions))
+        .route("/api/auth/sessions", delete(revoke_user_sessions))
+        .route("/api/auth/sessions/:id", delete(revoke_session))
+
+        // User routes
+        .route("/api/users", get(list_users))
+        .route("/api/users", post(create_user))
+        .route("/api/users/:username/password", put(change_password))
+        .route("/api/users/:username/active", put(set_user_active))
+        .route("/api/users/:username/notifications", put(set_user_notifications))
+        .route("/api/users/:username/role", put(assign_user_role))
+
+        // Location and printer routes
+        .route("/api/locations", get(list_locations))
+        .route("/api/locations", post(create_location))
+        .route("/api/locations/:id", get(get_location))
+        .route("/api/locations/:id", put(update_location))
+        .route("/api/locations/:id", delete(delete_location))
+        .route("/api/printers/summary", get(printer_summary))
+        .route("/api/printers/export", get(export_printers))
+        .route("/api/printers/accounting", get(printer_accounting))
+        .route("/api/printers/:id/location", put(set_printer_location))
+        .route("/api/printers/:id/site", put(set_printer_site))
+        .route("/api/printers/:id/supplies", put(update_printer_supplies))
+        .route("/api/printers/:id/jobs", put(update_printer_jobs))
+
+        // Site routes
+        .route("/api/sites", get(list_sites))
+        .route("/api/sites", post(create_site))
+        .route("/api/sites/:id", get(get_site))
+        .route("/api/sites/:id", put(update_site))
+        .route("/api/sites/:id", delete(delete_site))
+
+        // Admin routes
+        .route("/api/admin/tasks", get(list_background_tasks))
+        .route("/api/admin/usage", get(get_api_usage))
+        .route("/api/admin/support-export", get(get_support_export))
+        .route("/api/admin/calendar", get(get_calendar))
+        .route("/api/admin/calendar", put(update_calendar))
+        .route("/api/admin/calendar/holidays", post(add_calendar_holiday))
+        .route("/api/admin/calendar/holidays/:date", delete(remove_calendar_holiday))
+        .route("/api/admin/calendar/import", post(import_calendar))
+        .route("/api/admin/calendar/business-time", get(check_business_time))
+        .route("/api/replication/status", get(get_replication_status))
+        .route("/api/replication/promote", post(promote_replica))
+        .route("/api/replication/conflicts/:kind/:id", delete(resolve_replication_conflict))
+        .route("/api/admin/version", get(get_version))
+        .route("/api/admin/logs/reclassify", post(reclassify_logs))
+        .route("/api/admin/config", get(get_config))
+        .route("/api/redactions", get(list_redactions))
+        .route("/api/admin/config", patch(update_config))
+        .route("/api/admin/config/history", get(get_config_history))
+        .route("/api/admin/config/rollback/:version", post(rollback_config))
+        .route("/api/admin/connectivity-test", get(connectivity_test))
 
         // Network routes
         .route("/api/network/interfaces", get(get_interfaces))
+        .route("/api/network/interfaces/metadata", get(list_interface_metadata))
+        .route("/api/network/interfaces/flaps", get(list_link_flaps))
+        .route("/api/network/interfaces/:name/metadata", patch(update_interface_metadata))
+        .route("/api/network/usage", get(get_bandwidth_usage))
         .route("/api/network/firewall/rules", get(get_firewall_rules))
         .route("/api/network/firewall/rules", post(add_firewall_rule))
+        .route("/api/network/firewall/rules/unused", get(get_unused_firewall_rules))
         .route("/api/network/firewall/rules/:handle", delete(delete_firewall_rule))
+        .route("/api/network/firewall/rules/:handle/counters", get(get_firewall_rule_counters))
+        .route("/api/network/firewall/rules/:handle/counters/reset", post(reset_firewall_rule_counters))
+        .route("/api/network/firewall/rules/:handle/history", get(get_firewall_rule_history))
+        .route("/api/network/firewall/rules/:handle/position", patch(stage_firewall_rule_move))
+        .route("/api/network/firewall/managed", get(get_managed_rules))
+        .route("/api/network/firewall/import", post(import_firewall_rules))
+        .route("/api/network/firewall/staged", get(list_staged_changesets))
+        .route("/api/network/firewall/staged", post(stage_firewall_changes))
+        .route("/api/network/firewall/staged/:id", get(get_staged_changeset))
+        .route("/api/network/firewall/staged/:id", delete(discard_staged_changeset))
+        .route("/api/network/firewall/staged/:id/diff", get(get_staged_changeset_diff))
+        .route("/api/network/firewall/staged/:id/comments", post(comment_staged_changeset))
+        .route("/api/network/firewall/staged/:id/history", get(get_staged_changeset_history))
+        .route("/api/network/firewall/staged/:id/apply", post(apply_staged_changeset))
+        .route("/api/network/firewall/staged/:id/reject", post(reject_staged_changeset))
+        .route("/api/network/firewall/backups", get(list_firewall_backups))
+        .route("/api/network/firewall/backups/restore/:id", post(stage_firewall_restore))
+        .route("/api/network/firewall/templates", post(apply_firewall_template))
+        .route("/api/network/firewall/templates/:group", delete(delete_firewall_template))
+        .route("/api/network/firewall/threat-intel", get(get_threat_intel))
+        .route("/api/network/firewall/threat-intel", put(set_threat_intel))
+        .route("/api/network/firewall/presets/threat-intel-egress", post(apply_threat_intel_preset))
+        .route("/api/network/zones/forwarding", get(list_zone_forwarding))
+        .route("/api/network/zones/forwarding", post(set_zone_forwarding))
+        .route("/api/network/zones/forwarding/presets/lan-wan", post(apply_lan_wan_forwarding))
+        .route("/api/network/zones/forwarding/:id", delete(delete_zone_forwarding))
+        .route("/api/network/zones/egress", get(list_zone_egress))
+        .route("/api/network/zones/egress", post(set_zone_egress))
+        .route("/api/network/zones/egress/:id", delete(delete_zone_egress))
+        .route("/api/network/zones/drop-logging", get(list_zone_drop_logging))
+        .route("/api/network/zones/drop-logging/:zone", put(set_zone_drop_logging))
+        .route("/api/network/zones/drop-logging/:zone", delete(disable_zone_drop_logging))
+        .route("/api/network/zones/services", get(list_zone_services))
+        .route("/api/network/zones/services/:zone", put(set_zone_services))
+        .route("/api/network/zones/services/:zone", delete(reset_zone_services))
+        .route("/api/network/zones/services/:zone/:service", put(enable_zone_service))
+        .route("/api/network/zones/services/:zone/:service", delete(disable_zone_service))
+        .route("/api/network/services", get(list_services))
+        .route("/api/network/services", post(create_service))
+        .route("/api/network/services/:name", get(get_service))
+        .route("/api/network/services/:name", put(update_service))
+        .route("/api/network/services/:name", delete(delete_service))
         .route("/api/network/setup/:interface", post(setup_interface))
+        .route("/api/network/config/preview", post(preview_network_config))
+        .route("/api/network/config", put(apply_network_config))
+        .route("/api/network/resolve/:ip", get(resolve_address))
+        .route("/api/network/bonds", get(list_bonds))
+        .route("/api/network/bonds", post(create_bond))
+        .route("/api/network/bonds/:name", delete(delete_bond))
+        .route("/api/network/capture", post(start_capture))
+        .route("/api/network/capture/:id/status", get(get_capture_status))
+        .route("/api/network/capture/:id/download", get(download_capture))
 
         // Visualization routes
         .route("/api/visualizations/network-graph", get(get_network_graph))
+        .route("/api/visualizations/network-graph/groups", get(expand_graph_group))
+        .route("/api/visualizations/snapshots", get(list_graph_snapshots))
+        .route("/api/visualizations/snapshots/diff", get(diff_graph_snapshots))
         .route("/api/visualizations/network-diagram/:format", get(get_network_diagram))
         .route("/api/visualizations/traffic-flows", get(get_traffic_flows))
+        .route("/api/visualizations/traffic-flows/export", get(export_traffic_flows))
+        .route("/api/visualizations/geo-flows", get(get_geo_flows))
         .route("/api/visualizations/traffic-stats", get(get_traffic_stats))
+        .route("/api/visualizations/traffic-stats/collector", get(get_traffic_collector))
+        .route("/api/visualizations/traffic-stats/refresh", post(refresh_traffic_stats))
         .route("/api/visualizations/traffic-history/:interface", get(get_traffic_history))
+        .route("/api/visualizations/traffic-compare", get(compare_traffic))
 
         // Scripts routes
         .route("/api/scripts", get(list_scripts))
@@ -70,253 +501,4811 @@ pub fn setup_routes(
         .route("/api/scripts/:id", put(update_script))
         .route("/api/scripts/:id", delete(delete_script))
         .route("/api/scripts/:id/execute", post(execute_script))
+        .route("/api/scripts/:id/preflight", post(preflight_script))
+        .route("/api/scripts/:id/copy", post(copy_script))
+        .route("/api/scripts/pending-approvals", get(list_pending_approvals))
+        .route("/api/scripts/:id/approve", post(approve_script))
+        .route("/api/scripts/:id/reject", post(reject_script))
+        .route("/api/scripts/:id/lint/acknowledge", post(acknowledge_script_lint))
+        .route("/api/scripts/:id/execute-bulk", post(execute_script_bulk))
+        .route("/api/scripts/batches", get(list_script_batches))
+        .route("/api/scripts/batches/:id", get(get_script_batch))
+        .route("/api/scripts/batches/:id/output", get(get_script_batch_output))
+        .route("/api/scripts/batches/:id/cancel", post(cancel_script_batch))
+        .route("/api/scripts/executions/:id/structured", get(get_structured_output))
+        .route("/api/scripts/:id/executions/diff", get(diff_script_executions))
+        .route("/api/scripts/:id/schedules", get(list_script_schedules))
+        .route("/api/scripts/:id/schedules", post(create_script_schedule))
+        .route("/api/scripts/:id/schedules/:schedule_id", delete(delete_script_schedule))
+        .route("/api/scripts/:id/schedules/:schedule_id/runs", get(list_schedule_runs))
 
         // Tickets routes
         .route("/api/tickets", get(list_tickets))
         .route("/api/tickets/:id", get(get_ticket))
+        .route("/api/tickets/:id/sla", get(get_ticket_sla))
+        .route("/api/tickets/:id/board-position", patch(move_ticket_on_board))
+        .route("/api/tickets/board", get(get_ticket_board))
+        .route("/api/tickets/board/preferences", get(get_board_preferences))
+        .route("/api/tickets/board/preferences", put(set_board_preferences))
         .route("/api/tickets", post(create_ticket))
+        .route("/api/tickets/import", post(import_tickets))
         .route("/api/tickets/:id", put(update_ticket))
+        .route("/api/tickets/:id/attachments", post(upload_attachment))
+        .route("/api/tickets/:id/comments", post(add_ticket_comment))
+        .route("/api/tickets/:id/requester", put(set_ticket_requester))
+        .route("/api/tickets/:id/portal/rotate", post(rotate_ticket_portal))
+        .route("/api/tickets/:id/worklogs", get(list_ticket_worklogs))
+        .route("/api/tickets/:id/worklogs", post(add_ticket_worklog))
+        .route("/api/tickets/:id/worklogs/:worklog_id", put(update_ticket_worklog))
+        .route("/api/tickets/:id/worklogs/:worklog_id", delete(delete_ticket_worklog))
+        .route("/api/tickets/:id/alerts", post(link_ticket_alert))
+        .route("/api/tickets/:id/references", get(list_ticket_references))
+        .route("/api/tickets/:id/references", post(add_ticket_reference))
+        .route("/api/tickets/:id/references/:reference_id", put(update_ticket_reference))
+        .route("/api/tickets/:id/references/:reference_id", delete(remove_ticket_reference))
+        .route("/api/portal/tickets/:token", get(get_portal_ticket))
+        .route("/api/portal/tickets/:token/comments", post(add_portal_comment))
+        .route("/api/tickets/snippets", get(list_snippets))
+        .route("/api/tickets/snippets", post(create_snippet))
+        .route("/api/tickets/snippets/:id", get(get_snippet))
+        .route("/api/tickets/snippets/:id", put(update_snippet))
+        .route("/api/tickets/snippets/:id", delete(delete_snippet))
+        .route("/api/tickets/:id/activity", get(get_ticket_activity))
+        .route("/api/tickets/:id/activity/:activity_id/correction", post(correct_ticket_activity))
+
+        // Log routes
+        .route("/api/logs", get(query_logs))
+        .route("/api/logs/stats", get(log_stats))
+        .route("/api/logs/tail", get(tail_logs))
+        .route("/api/logs/searches", get(list_saved_searches))
+        .route("/api/logs/searches", post(create_saved_search))
+        .route("/api/logs/searches/:id", get(get_saved_search))
+        .route("/api/logs/searches/:id", put(update_saved_search))
+        .route("/api/logs/searches/:id", delete(delete_saved_search))
+        .route("/api/logs/searches/:id/run", get(run_saved_search))
+        .route("/api/logs/ingest", post(ingest_log))
+        .route("/api/logs/sources", get(list_log_sources))
+        .route("/api/logs/sources/:source/expected", put(set_log_source_expected))
+        .route("/api/logs/sources/:source/expected", delete(clear_log_source_expected))
+        .route("/api/logs/quotas", get(get_log_quotas))
+        .route("/api/logs/quotas", put(set_default_log_quota))
+        .route("/api/logs/quotas/:source", put(set_log_quota_override))
+        .route("/api/logs/quotas/:source", delete(delete_log_quota_override))
+        .route("/api/logs/reparse", post(start_bulk_reparse))
+        .route("/api/logs/reparse/:id", get(get_reparse_job))
+        .route("/api/logs/:id", get(get_log_entry))
+        .route("/api/logs/:id/reparse", post(reparse_log_entry))
+        .route("/api/assets", get(list_assets))
+        .route("/api/assets", post(create_asset))
+        .route("/api/assets/export", get(export_assets))
+        .route("/api/assets/:id", get(get_asset))
+        .route("/api/assets/:id", put(update_asset))
+        .route("/api/assets/:id", delete(delete_asset))
+        .route("/api/assets/:id/timeline", get(get_asset_timeline))
+        .route("/api/assets/:id/history", get(get_asset_history))
+        .route("/api/assets/:id/inventory", get(get_asset_inventory))
+        .route("/api/assets/:id/drift/accept", post(accept_asset_drift))
+        .route("/api/assets/observations", post(submit_asset_observations))
+        .route("/api/scans", get(list_scans))
+        .route("/api/scans", post(record_scan))
+        .route("/api/reports/incident", get(incident_report))
+        .route("/api/reports/compliance", get(compliance_report))
+        .route("/api/reports/chargeback", get(chargeback_report))
+        .route("/api/reports/worklogs", get(worklog_report))
+        .route("/api/reports/digest", post(send_digest))
+        .route("/api/reports/evidence", post(start_evidence_package))
+        .route("/api/reports/evidence/:id", get(get_evidence_package))
+        .route("/api/reports/evidence/:id/download", get(download_evidence_package))
+        .route("/api/logs/extractions", get(list_extraction_rules))
+        .route("/api/logs/extractions", post(create_extraction_rule))
+        .route("/api/logs/extractions/test", post(test_extraction_rule))
+        .route("/api/logs/extractions/:id", get(get_extraction_rule))
+        .route("/api/logs/extractions/:id", put(update_extraction_rule))
+        .route("/api/logs/extractions/:id", delete(delete_extraction_rule))
+        .route("/api/logs/tagging-rules", get(list_tagging_rules))
+        .route("/api/logs/tagging-rules", post(create_tagging_rule))
+        .route("/api/logs/tagging-rules/backfills/:id", get(get_tagging_backfill))
+        .route("/api/logs/tagging-rules/:id", get(get_tagging_rule))
+        .route("/api/logs/tagging-rules/:id", put(update_tagging_rule))
+        .route("/api/logs/tagging-rules/:id", delete(delete_tagging_rule))
+        .route("/api/logs/tagging-rules/:id/backfill", post(start_tagging_backfill))
+
+        // Alert routes
+        .route("/api/correlation/rules/backtest", post(start_backtest))
+        .route("/api/correlation/rules/backtest", get(list_backtests))
+        .route("/api/correlation/rules/backtest/:id", get(get_backtest))
+        .route("/api/correlation/rules/backtest/:id/cancel", post(cancel_backtest))
+        .route("/api/correlation/rules/export", get(export_correlation_rules))
+
+        // Tag routes
+        .route("/api/tags", get(list_tags))
+        .route("/api/tags/merge", post(merge_tags))
+        .route("/api/tags/:name", put(define_tag))
+        .route("/api/tags/:name", delete(undefine_tag))
+        .route("/api/tags/:name/rename", post(rename_tag))
+        .route("/api/alerts", get(list_alerts))
+        .route("/api/alerts/:id", get(get_alert))
+        .route("/api/alerts/:id/acknowledge", post(acknowledge_alert))
+        .route("/api/alerts/:id/export", get(export_alert))
+        .route("/api/alerts/:id/activity", get(get_alert_activity))
+        .route("/api/alerts/:id/references", get(list_alert_references))
+        .route("/api/alerts/:id/references", post(add_alert_reference))
+        .route("/api/alerts/:id/references/:reference_id", put(update_alert_reference))
+        .route("/api/alerts/:id/references/:reference_id", delete(remove_alert_reference))
+        .route("/api/references/search", get(search_references))
+        .route("/api/audit/verify", get(verify_audit_chain))
+        .route("/api/annotations", get(list_annotations))
+        .route("/api/annotations", post(create_annotation))
+        .route("/api/annotations/:id", get(get_annotation))
+        .route("/api/annotations/:id", put(update_annotation))
+        .route("/api/annotations/:id", delete(delete_annotation))
+        .route("/api/alerts/escalation-policies", get(list_escalation_policies))
+        .route("/api/alerts/escalation-policies", post(create_escalation_policy))
+        .route("/api/alerts/escalation-policies/:id", get(get_escalation_policy))
+        .route("/api/alerts/escalation-policies/:id", put(update_escalation_policy))
+        .route("/api/alerts/escalation-policies/:id", delete(delete_escalation_policy))
+        .route("/api/alerts/escalations", get(list_active_escalations))
+        .route("/api/alerts/remediations", get(list_remediation_bindings))
+        .route("/api/alerts/remediations", post(create_remediation_binding))
+        .route("/api/alerts/remediations/automation", get(get_remediation_automation))
+        .route("/api/alerts/remediations/automation", put(set_remediation_automation))
+        .route("/api/alerts/remediations/:id", get(get_remediation_binding))
+        .route("/api/alerts/remediations/:id", put(update_remediation_binding))
+        .route("/api/alerts/remediations/:id", delete(delete_remediation_binding))
+        .route("/api/power/ups", get(get_ups_status))
+
+        // Role management routes
+        .route("/api/roles", get(list_roles))
+        .route("/api/roles", post(create_role))
+        .route("/api/roles/:role", delete(delete_role))
+        .route("/api/roles/:role/permissions", post(add_role_permission))
+        .route("/api/roles/:role/permissions", delete(remove_role_permission))
+
+        // Inside the permission check, so only signed-in users see initialization errors
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), subsystem_gate))
+
+        // Enforce the live permission matrix on every matched route
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), rbac_middleware))
+
+        // Alert webhooks, sent with the integration's token rather than a user token
+        .route("/api/integrations/alertmanager", post(alertmanager_webhook))
+        .route("/api/integrations/grafana", post(grafana_webhook))
+
+        // First-run setup, the only routes served until setup is complete
+        .route("/api/setup/status", get(setup_status))
+        .route("/api/setup/complete", post(complete_setup))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), setup_gate))
+
+        // Pulled by the standby with the shared key rather than a user token
+        .route("/api/replication/changes", get(get_replication_changes))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), standby_gate))
+
+        // Cap every request body; axum's fixed default is replaced by the configured limits
+        .layer(middleware::from_fn_with_state(app_state.clone(), body_limits::limit_body))
+        .layer(axum::extract::DefaultBodyLimit::disable())
+
+        // Counts rejected requests too, under the route they were sent to
+        .layer(middleware::from_fn_with_state(app_state.clone(), record_usage))
+
+        // Outermost, so rejected requests are traced and carry a correlation id too
+        .layer(middleware::from_fn(request_trace::trace_request))
 
         // Add the app state
         .with_state(app_state)
 }
 
-// Basic handlers
-async fn root_handler() -> &'static str {
-    "SIEM Admin Center API"
+// Routes of a subsystem that failed to start are answered with the recorded error until
+// the cause is fixed and the server restarted, see startup
+async fn subsystem_gate(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some((subsystem, error)) = state.startup.blocking(request.uri().path()) {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({
+            "error": format!("The {} subsystem is disabled, it failed to start", subsystem),
+            "subsystem": subsystem,
+            "initialization_error": error,
+        }))).into_response();
+    }
+
+    next.run(request).await
 }
 
-async fn health_check() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "ok",
-        "version": env!("CARGO_PKG_VERSION"),
-    }))
+// Until the first-run setup is done nothing but the setup routes is served
+async fn setup_gate(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.setup.is_required() && !request.uri().path().starts_with("/api/setup/") {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({
+            "error": "setup required",
+            "setup_status": "/api/setup/status",
+        }))).into_response();
+    }
+
+    next.run(request).await
 }
 
-// Network API handlers
-async fn get_interfaces(
+// Request paths a standby still accepts changes on: signing in and out, its own logs,
+// setup and replication itself
+const STANDBY_WRITABLE: [&str; 4] = ["/api/auth/", "/api/logs/ingest", "/api/setup/", "/api/replication/"];
+
+// A standby follows the primary; changing anything here would be overwritten or conflict,
+// so only reads are served until it is promoted
+async fn standby_gate(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<crate::network::InterfaceInfo>>, StatusCode> {
-    match state.network_manager.get_interfaces().await {
-        Ok(interfaces) => Ok(Json(interfaces)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    request: Request,
+    next: Next,
+) -> Response {
+    let read_only = matches!(*request.method(), axum::http::Method::GET | axum::http::Method::HEAD | axum::http::Method::OPTIONS);
+    let path = request.uri().path();
+    if state.replication.role().is_standby() && !read_only && !STANDBY_WRITABLE.iter().any(|p| path.starts_with(p)) {
+        return (StatusCode::CONFLICT, Json(serde_json::json!({
+            "error": "This instance is a standby and is read-only; make changes on the primary, or promote this instance with POST /api/replication/promote",
+            "mode": "standby",
+            "primary_url": state.replication.primary_url(),
+        }))).into_response();
     }
+
+    next.run(request).await
 }
 
-async fn get_firewall_rules(
+async fn setup_status(
     State(state): State<Arc<AppState>>,
-) -> Json<Vec<String>> {
-    let rules = state.network_manager.get_nftables_rules().await;
-    Json(rules)
+) -> impl IntoResponse {
+    let checks = setup::self_test(&state.config, &state.user_manager);
+    let missing: Vec<&str> = checks.iter().filter(|c| !c.ok).map(|c| c.name).collect();
+
+    // The details are only useful (and only shown) before anyone can log in
+    let setup_required = state.setup.is_required();
+    (StatusCode::OK, Json(serde_json::json!({
+        "setup_required": setup_required,
+        "missing": missing,
+        "checks": if setup_required { Some(checks) } else { None },
+    })))
 }
 
 #[derive(Deserialize)]
-struct InterfaceConfig {
-    dhcp: Option<bool>,
-    address: Option<String>,
-    nftables_zone: Option<String>,
+struct SetupAdmin {
+    username: String,
+    password: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    full_name: String,
 }
 
-async fn setup_interface(
+#[derive(Deserialize)]
+struct SetupRequest {
+    admin: SetupAdmin,
+    server_port: Option<u16>,
+    admin_email: Option<String>,
+    database_url: Option<String>,
+    smtp: Option<SmtpConfig>,
+}
+
+// Validates the settings (connecting to the database and SMTP server when given),
+// writes the config file and creates the admin account
+async fn complete_setup(
     State(state): State<Arc<AppState>>,
-    Path(interface_name): Path<String>,
-    Json(config): Json<InterfaceConfig>,
+    Json(request): Json<SetupRequest>,
 ) -> impl IntoResponse {
-    let interface_config = crate::network::InterfaceConfig {
-        name: interface_name,
-        dhcp: config.dhcp,
-        address: config.address,
-        nftables_zone: config.nftables_zone,
-    };
+    // Claiming the setup up front keeps two concurrent requests from both running it
+    if !state.setup.complete() {
+        return (StatusCode::CONFLICT, "Setup has already been completed".to_string()).into_response();
+    }
 
-    match state.network_manager.setup_interface(&interface_config).await {
-        Ok(_) => (StatusCode::OK, "Interface configured successfully"),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to configure interface: {}", e)),
+    match run_setup(&state, request).await {
+        Ok(response) => response,
+        Err(response) => {
+            state.setup.reopen();
+            response
+        },
     }
 }
 
-// Visualization API handlers
-async fn get_network_graph(
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let graph = state.visualization_manager.get_network_graph();
-    (StatusCode::OK, Json(graph))
+async fn run_setup(state: &AppState, request: SetupRequest) -> Result<Response, Response> {
+    let mut errors = Vec::new();
+
+    if let Err(e) = state.user_manager.check_password(&request.admin.username, &request.admin.password) {
+        match e.downcast_ref::<PolicyViolations>() {
+            Some(PolicyViolations(violations)) => errors.extend(violations.iter().map(|v| format!("admin password {}", v))),
+            None => errors.push(e.to_string()),
+        }
+    }
+    if request.server_port == Some(0) {
+        errors.push("server_port must not be 0".to_string());
+    }
+    if let Some(email) = &request.admin_email {
+        if !email.contains('@') {
+            errors.push(format!("Invalid admin email: {}", email));
+        }
+    }
+
+    if let Some(url) = &request.database_url {
+        let connection = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(std::time::Duration::from_secs(10))
+            .connect(url)
+            .await;
+        match connection {
+            Ok(pool) => pool.close().await,
+            Err(e) => errors.push(format!("Database connection failed: {}", e)),
+        }
+    }
+
+    if let Some(smtp) = &request.smtp {
+        let sender = request.admin_email.clone().unwrap_or_else(|| state.config.admin_email.clone());
+        let verified = match Notifier::new(smtp.clone(), sender, &state.http_clients) {
+            Ok(notifier) => notifier.verify_smtp().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = verified {
+            errors.push(format!("SMTP check failed: {}", e));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({ "errors": errors }))).into_response());
+    }
+
+    let config_path = state.setup.config_path();
+    let mut new_config = config::load(config_path)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    if let Some(port) = request.server_port {
+        new_config.server_port = port;
+    }
+    if let Some(email) = request.admin_email {
+        new_config.admin_email = email;
+    }
+    if let Some(smtp) = request.smtp {
+        new_config.smtp = smtp;
+    }
+    new_config.database_url = request.database_url;
+    new_config.security.jwt_secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+    state.config_history.save(&new_config, &request.admin.username, "POST /api/setup/complete")
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+
+    let admin = state.user_manager.create_user(
+        &request.admin.username,
+        &request.admin.email,
+        &request.admin.full_name,
+        UserRole::Admin,
+        &request.admin.password,
+    ).map_err(password_error_response)?;
+
+    state.security_manager.log_audit_event(
+        &admin.username,
+        "setup:complete",
+        config_path,
+        AuditStatus::Success,
+        None,
+    );
+
+    // Accounts and the gate switch over right away; listener and secrets are read at startup
+    Ok((StatusCode::OK, Json(serde_json::json!({
+        "admin": admin,
+        "restart_required_for": ["server_port", "database_url", "smtp", "jwt_secret"],
+    }))).into_response())
 }
 
-async fn get_traffic_flows(
+// Checks the caller's role against the permission matrix on every request,
+// so permission changes apply to existing tokens without a restart
+async fn rbac_middleware(
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let flows = state.visualization_manager.get_traffic_flows();
-    (StatusCode::OK, Json(flows))
+    request: Request,
+    next: Next,
+) -> Response {
+    let required = match required_permissions(request.method(), request.uri().path()) {
+        Some(required) => required,
+        None => return next.run(request).await,
+    };
+
+    let (mut parts, body) = request.into_parts();
+
+    let user = match AuthUser::from_request_parts(&mut parts, &state).await {
+        Ok(user) => user,
+        Err(status) => return status.into_response(),
+    };
+
+    let role = user.role.role_name();
+    if !required.iter().any(|permission| state.access_control.check_permission(&role, permission)) {
+        state.security_manager.log_audit_event(
+            &user.username,
+            parts.method.as_str(),
+            parts.uri.path(),
+            AuditStatus::Failure,
+            Some(format!("Permission denied, requires one of: {}", required.join(", "))),
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(Request::from_parts(parts, body)).await
 }
 
-async fn get_traffic_stats(
+// Counts the request under its route template and the user of its token, see api_usage.
+// The token is only decoded here; whether it is still good is for the handler to decide.
+async fn record_usage(
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let stats = state.visualization_manager.get_traffic_statistics();
-    (StatusCode::OK, Json(stats))
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = api_usage::route_key(
+        request.method().as_str(),
+        request.extensions().get::<MatchedPath>().map(|path| path.as_str()),
+    );
+    let principal = request.headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| auth::verify_token(&state.config.security, token).ok())
+        .map(|claims| claims.sub)
+        .unwrap_or_else(|| api_usage::ANONYMOUS_PRINCIPAL.to_string());
+
+    let started = std::time::Instant::now();
+    let response = next.run(request).await;
+    state.api_usage.record(route, &principal, response.status().as_u16(), started.elapsed());
+    response
 }
 
-async fn get_traffic_history(
+// Basic handlers
+async fn root_handler() -> &'static str {
+    "SIEM Admin Center API"
+}
+
+async fn health_check(
     State(state): State<Arc<AppState>>,
-    Path(interface): Path<String>,
 ) -> impl IntoResponse {
-    let history = state.visualization_manager.get_traffic_history(&interface);
-    (StatusCode::OK, Json(history))
+    let database = state.database.as_ref().map(|db| db.health());
+    // Managers that recovered from a panic inside a critical section keep serving, but
+    // their state may be inconsistent until restart
+    let locks = [
+        state.tickets_manager.lock_health(),
+        state.security_manager.lock_health(),
+        state.visualization_manager.lock_health(),
+    ];
+    // The service keeps running on local storage while the database is out
+    let healthy = database.as_ref().map_or(true, |db| db.healthy) && locks.iter().all(|l| l.healthy)
+        && !state.startup.is_degraded();
+    let status = if healthy { "ok" } else { "degraded" };
+
+    Json(serde_json::json!({
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "paths": state.paths.status(),
+        "disk": state.disk_monitor.status().ok(),
+        "database": database,
+        "attachment_scanner": state.attachment_scanner.status().ok(),
+        "locks": locks,
+        "heartbeat": state.heartbeat.status().ok(),
+        "subsystems": state.startup.statuses(),
+    }))
 }
 
-async fn get_network_diagram(
+async fn metrics(
     State(state): State<Arc<AppState>>,
-    Path(format): Path<String>,
 ) -> impl IntoResponse {
-    match state.visualization_manager.export_network_diagram(&format) {
-        Ok(data) => {
-            let content_type = match format.as_str() {
-                "json" => "application/json",
-                "dot" => "text/plain",
-                _ => "application/octet-stream",
-            };
-            
-            (
-                StatusCode::OK,
-                [(axum::http::header::CONTENT_TYPE, content_type)],
-                data
-            )
-        },
-        Err(e) => (
-            StatusCode::BAD_REQUEST,
-            [(axum::http::header::CONTENT_TYPE, "text/plain")],
-            e.into_bytes()
-        ),
+    let metrics = state.task_registry.render_metrics()
+        .and_then(|tasks| Ok(tasks + &state.disk_monitor.render_metrics()?))
+        .and_then(|body| Ok(body + &state.ingestion_quotas.render_metrics()?))
+        .map(|body| body + &state.syslog_listener.render_metrics())
+        .map(|body| body + &state.ingest_timings.render_metrics())
+        .and_then(|body| Ok(body + &state.ingest_timings.render_event_metrics()?))
+        .and_then(|body| Ok(body + &state.ups.render_metrics()?))
+        .and_then(|body| match &state.database {
+            Some(db) => Ok(body + &db.render_metrics()?),
+            None => Ok(body),
+        });
+
+    match metrics {
+        Ok(body) => (
+            StatusCode::OK,
+            [("Content-Type", "text/plain; version=0.0.4")],
+            body,
+        ).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+// Authentication API handlers
 #[derive(Deserialize)]
-struct FirewallRuleRequest {
-    chain: String,
-    protocol: String,
-    port: Option<u16>,
-    source: Option<String>,
-    action: String,
+struct LoginRequest {
+    username: String,
+    password: String,
 }
 
-async fn add_firewall_rule(
+// Our own logins go through the ingestion pipeline like any other authentication
+// event, so they are geolocated and, once successful, checked for impossible travel.
+// The address is the client's as ClientInfo resolves it, see auth::client_address.
+fn record_login_event(state: &AppState, username: &str, client: &ClientInfo, success: bool) {
+    let outcome = if success { "succeeded" } else { "failed" };
+    let source_ip = client.source_ip.as_deref().unwrap_or("unknown");
+
+    let tags = client.source_ip.iter()
+        .map(|ip| format!("src_ip:{}", ip))
+        .collect();
+
+    let message = format!("Login {} for {} from {}", outcome, username, source_ip);
+    let entry = LogEntry {
+        id: Uuid::new_v4(),
+        timestamp: Utc::now(),
+        source: "siem".to_string(),
+        event_type: if success { "auth:login" } else { "auth:login_failed" }.to_string(),
+        severity: if success { LogSeverity::Info } else { LogSeverity::Warning },
+        raw_data: message.clone(),
+        message,
+        host: None,
+        user: Some(username.to_string()),
+        application: Some("siem".to_string()),
+        tags,
+        category: EventCategory::Authentication,
+        hostname: None,
+        site_id: None,
+        parser: LogParser::Internal,
+    };
+
+    if let Err(e) = state.ingestion_pipeline.ingest(entry) {
+        tracing::warn!("Failed to record login event for {}: {}", username, e);
+    }
+}
+
+async fn login(
     State(state): State<Arc<AppState>>,
-    Json(rule): Json<FirewallRuleRequest>,
+    client: ClientInfo,
+    Json(request): Json<LoginRequest>,
 ) -> impl IntoResponse {
-    match state.network_manager.add_firewall_rule(
-        &rule.chain,
-        &rule.protocol,
-        rule.port,
-        rule.source.as_deref(),
-        &rule.action
-    ).await {
-        Ok(_) => (StatusCode::CREATED, "Firewall rule added successfully"),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add firewall rule: {}", e)),
+    let user = match state.user_manager.authenticate(&request.username, &request.password) {
+        Ok(user) => user,
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &request.username,
+                "auth:login",
+                "session",
+                AuditStatus::Failure,
+                Some(format!("{} from {}", e, client.source_ip.as_deref().unwrap_or("unknown"))),
+            );
+            record_login_event(&state, &request.username, &client, false);
+            return (StatusCode::UNAUTHORIZED, "Invalid username or password").into_response();
+        }
+    };
+
+    let password_change_required = state.user_manager.password_change_required(&user);
+    open_session(&state, &user, &client, password_change_required, "password")
+}
+
+// Session and bearer token for an authenticated user, shared by all login methods
+fn open_session(state: &AppState,
+                user: &User,
+                client: &ClientInfo,
+                password_change_required: bool,
+                method: &str) -> Response {
+    let expires_at = Utc::now() + chrono::Duration::hours(state.config.security.token_expiration_hours as i64);
+    let session = match state.session_manager.create_session(
+        &user.username,
+        user.role.clone(),
+        expires_at,
+        client.source_ip.clone(),
+        client.user_agent.clone(),
+        password_change_required,
+        user.sites.clone(),
+    ) {
+        Ok(session) => session,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    match auth::issue_token(&state.config.security, &session) {
+        Ok(token) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "auth:login",
+                &session.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{} from {}", method, client.source_ip.as_deref().unwrap_or("unknown"))),
+            );
+            record_login_event(state, &user.username, client, true);
+            (StatusCode::OK, Json(serde_json::json!({
+                "token": token,
+                "session_id": session.id,
+                "expires_at": session.expires_at,
+                "password_change_required": password_change_required,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-async fn delete_firewall_rule(
+async fn oidc_login(
     State(state): State<Arc<AppState>>,
-    Path(handle): Path<u32>,
 ) -> impl IntoResponse {
-    match state.network_manager.delete_firewall_rule(handle).await {
-        Ok(_) => (StatusCode::OK, "Firewall rule deleted successfully"),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete firewall rule: {}", e)),
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return (StatusCode::NOT_FOUND, "Single sign-on is not configured").into_response(),
+    };
+
+    match oidc.login_url().await {
+        Ok((url, login_state)) => (
+            [(axum::http::header::SET_COOKIE, oidc.state_cookie(&login_state))],
+            Redirect::to(&url),
+        ).into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
     }
 }
 
-// Scripts API handlers - placeholder implementations
-#[derive(Serialize, Deserialize)]
-struct Script {
-    id: String,
-    name: String,
-    content: String,
-    description: Option<String>,
+#[derive(Deserialize)]
+struct OidcCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
 }
 
-async fn list_scripts(
-    State(_state): State<Arc<AppState>>,
+async fn oidc_callback(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<OidcCallbackQuery>,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    (StatusCode::OK, Json(Vec::<Script>::new()))
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return (StatusCode::NOT_FOUND, "Single sign-on is not configured").into_response(),
+    };
+
+    let cookies = headers.get_all(axum::http::header::COOKIE).iter().filter_map(|v| v.to_str().ok());
+    let cookie_state = crate::oidc::cookie_state(cookies);
+    let identity = match (query.code, query.state, query.error) {
+        (Some(code), Some(login_state), None) => oidc.complete_login(&code, &login_state, cookie_state).await,
+        (_, _, Some(error)) => Err(anyhow::anyhow!("Provider returned {}: {}", error, query.error_description.unwrap_or_default())),
+        _ => Err(anyhow::anyhow!("Callback without code and state")),
+    };
+
+    let user = identity.and_then(|identity| state.user_manager.upsert_external_user(
+        &identity.username,
+        &identity.external_id,
+        &identity.email,
+        &identity.full_name,
+        identity.role,
+    ));
+
+    let clear_cookie = [(axum::http::header::SET_COOKIE, oidc.clear_state_cookie())];
+    match user {
+        Ok(user) => (clear_cookie, open_session(&state, &user, &client, false, "oidc")).into_response(),
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                "unknown",
+                "auth:login",
+                "session",
+                AuditStatus::Failure,
+                Some(format!("oidc: {} from {}", e, client.source_ip.as_deref().unwrap_or("unknown"))),
+            );
+            (StatusCode::UNAUTHORIZED, clear_cookie, "Single sign-on failed").into_response()
+        },
+    }
 }
 
-async fn get_script(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+    match state.session_manager.revoke(user.session_id) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "auth:logout",
+                &user.session_id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+
+            // Single sign-on users may also have to end their session at the provider
+            let external = state.user_manager.get_user(&user.username)
+                .map_or(false, |u| u.external_id.is_some());
+            if let (Some(oidc), true) = (&state.oidc, external) {
+                match oidc.end_session_url().await {
+                    Ok(Some(url)) => return (StatusCode::OK, Json(serde_json::json!({
+                        "end_session_url": url,
+                    }))).into_response(),
+                    Ok(None) => {},
+                    Err(e) => warn!("Failed to build end-session URL: {}", e),
+                }
+            }
+
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
-async fn create_script(
-    State(_state): State<Arc<AppState>>,
-    Json(_script): Json<Script>,
+async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+    // Admins see every session, everyone else only their own
+    let owner = if user.is_admin() { None } else { Some(user.username.as_str()) };
+
+    match state.session_manager.get_sessions(owner) {
+        Ok(sessions) => (StatusCode::OK, Json(sessions)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
-async fn update_script(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-    Json(_script): Json<Script>,
+async fn revoke_session(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+    let session = match state.session_manager.get_session(id) {
+        Ok(session) => session,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    if !user.is_admin() && session.username != user.username {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.session_manager.revoke(id) {
+        Ok(session) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "auth:revoke_session",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(format!("session of {}", session.username)),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
 }
 
-async fn delete_script(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+#[derive(Deserialize)]
+struct RevokeSessionsParams {
+    user: String,
 }
 
-async fn execute_script(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
+async fn revoke_user_sessions(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<RevokeSessionsParams>,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
-}
+    if !user.is_admin() && params.user != user.username {
+        return StatusCode::FORBIDDEN.into_response();
+    }
 
-// Tickets API handlers - placeholder implementations
-#[derive(Serialize, Deserialize)]
-struct Ticket {
-    id: String,
-    title: String,
-    description: String,
-    status: String,
-    priority: String,
-    created_at: String,
-    updated_at: String,
-    assignee: Option<String>,
+    match state.session_manager.revoke_user(&params.user) {
+        Ok(revoked) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "auth:revoke_sessions",
+                &params.user,
+                AuditStatus::Success,
+                Some(format!("{} sessions revoked", revoked)),
+            );
+            (StatusCode::OK, Json(serde_json::json!({ "revoked": revoked }))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
-async fn list_tickets(
-    State(_state): State<Arc<AppState>>,
+// User API handlers
+async fn list_users(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    (StatusCode::OK, Json(Vec::<Ticket>::new()))
+    match state.user_manager.get_all_users() {
+        Ok(users) => (StatusCode::OK, Redacted(users, user)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
-async fn get_ticket(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+#[derive(Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    full_name: String,
+    role: UserRole,
+    password: String,
+    // Sites the role applies to, all when empty
+    #[serde(default)]
+    sites: Vec<Uuid>,
 }
 
-async fn create_ticket(
-    State(_state): State<Arc<AppState>>,
-    Json(_ticket): Json<Ticket>,
+async fn create_user(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<CreateUserRequest>,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
+    if let Err(e) = check_sites_exist(&state, &request.sites) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    match state.user_manager.create_user(
+        &request.username,
+        &request.email,
+        &request.full_name,
+        request.role.clone(),
+        &request.password,
+    ) {
+        Ok(mut created) => {
+            if !request.sites.is_empty() {
+                created = match state.user_manager.assign_role(&created.username, request.role, request.sites) {
+                    Ok(created) => created,
+                    Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+                };
+            }
+            state.security_manager.log_audit_event(
+                &user.username,
+                "user:create",
+                &created.username,
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::CREATED, Redacted(created, user)).into_response()
+        },
+        Err(e) => password_error_response(e),
+    }
+}
+
+// Policy violations are listed individually so clients can show all of them at once
+fn password_error_response(e: anyhow::Error) -> Response {
+    match e.downcast_ref::<PolicyViolations>() {
+        Some(PolicyViolations(violations)) => (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({
+            "error": "Password does not meet the policy",
+            "violations": violations,
+        }))).into_response(),
+        None => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordRequest {
+    current_password: Option<String>,
+    new_password: String,
+}
+
+async fn change_password(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(username): Path<String>,
+    Json(request): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    // Users change their own password with the current one, admins may reset anyone's.
+    // A reset makes the user choose a new password at the next login.
+    let forced = username != user.username;
+    if !forced {
+        let current = request.current_password.as_deref().unwrap_or("");
+        match state.user_manager.verify_credentials(&username, current) {
+            Ok(true) => {},
+            Ok(false) => return (StatusCode::FORBIDDEN, "Current password is incorrect".to_string()).into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    } else if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if let Err(e) = state.user_manager.change_password(&username, &request.new_password, forced) {
+        return password_error_response(e);
+    }
+
+    let revoked = state.session_manager.revoke_user(&username).unwrap_or(0);
+    state.security_manager.log_audit_event(
+        &user.username,
+        if forced { "user:reset_password" } else { "user:change_password" },
+        &username,
+        AuditStatus::Success,
+        Some(format!("{} sessions revoked", revoked)),
+    );
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+struct SetActiveRequest {
+    active: bool,
+}
+
+async fn set_user_active(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(username): Path<String>,
+    Json(request): Json<SetActiveRequest>,
+) -> impl IntoResponse {
+    let updated = match state.user_manager.set_active(&username, request.active) {
+        Ok(updated) => updated,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+
+    let revoked = if request.active {
+        0
+    } else {
+        state.session_manager.revoke_user(&username).unwrap_or(0)
+    };
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        if request.active { "user:activate" } else { "user:deactivate" },
+        &username,
+        AuditStatus::Success,
+        Some(format!("{} sessions revoked", revoked)),
+    );
+
+    (StatusCode::OK, Redacted(updated, user)).into_response()
+}
+
+// Users set their own preferences, admins anyone's
+async fn set_user_notifications(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(username): Path<String>,
+    Json(request): Json<NotificationPreferences>,
+) -> impl IntoResponse {
+    if username != user.username && !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.user_manager.set_notifications(&username, request) {
+        Ok(updated) => (StatusCode::OK, Redacted(updated, user)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+fn check_sites_exist(state: &AppState, sites: &[Uuid]) -> anyhow::Result<()> {
+    for id in sites {
+        state.sites.get_site(*id)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct AssignRoleRequest {
+    role: UserRole,
+    // Sites the role applies to, all when empty
+    #[serde(default)]
+    sites: Vec<Uuid>,
+}
+
+async fn assign_user_role(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(username): Path<String>,
+    Json(request): Json<AssignRoleRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = check_sites_exist(&state, &request.sites) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    let updated = match state.user_manager.assign_role(&username, request.role, request.sites) {
+        Ok(updated) => updated,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+
+    // Sessions carry the sites of the user, so the new ones apply at the next login
+    let revoked = state.session_manager.revoke_user(&username).unwrap_or(0);
+    state.security_manager.log_audit_event(
+        &user.username,
+        "user:assign_role",
+        &username,
+        AuditStatus::Success,
+        Some(format!("{} for {} sites, {} sessions revoked", updated.role.role_name(), updated.sites.len(), revoked)),
+    );
+
+    (StatusCode::OK, Redacted(updated, user)).into_response()
+}
+
+// Site API handlers
+#[derive(Deserialize)]
+struct SiteQuery {
+    site: Option<Uuid>,
+}
+
+// The sites of the caller, narrowed to the one asked for. A site outside the scope of
+// a restricted caller is answered as if it did not exist.
+fn site_scope(user: &AuthUser, site: Option<Uuid>) -> Result<SiteScope, Response> {
+    user.site_scope()
+        .narrow(site)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Site not found".to_string()).into_response())
+}
+
+async fn list_sites(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    match state.sites.get_all_sites() {
+        Ok(sites) => {
+            let visible: Vec<_> = sites.into_iter()
+                .filter(|s| scope.allows(Some(s.id)))
+                .collect();
+            (StatusCode::OK, Json(visible)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_site(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.site_scope().allows(Some(id)) {
+        return (StatusCode::NOT_FOUND, "Site not found".to_string()).into_response();
+    }
+
+    match state.sites.get_site(id) {
+        Ok(site) => (StatusCode::OK, Json(site)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn create_site(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<SiteFields>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.sites.create_site(request) {
+        Ok(site) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "site:create",
+                &site.id.to_string(),
+                AuditStatus::Success,
+                Some(site.name.clone()),
+            );
+            (StatusCode::CREATED, Json(site)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn update_site(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<SiteFields>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if state.sites.get_site(id).is_err() {
+        return (StatusCode::NOT_FOUND, "Site not found".to_string()).into_response();
+    }
+
+    match state.sites.update_site(id, request) {
+        Ok(site) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "site:update",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(site.name.clone()),
+            );
+            (StatusCode::OK, Json(site)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_site(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.sites.delete_site(id) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "site:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Location API handlers
+#[derive(Serialize)]
+struct LocationResponse {
+    #[serde(flatten)]
+    node: LocationNode,
+    path: String,
+}
+
+async fn list_locations(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.location_manager.get_all_locations() {
+        Ok(nodes) => {
+            let mut list: Vec<LocationResponse> = nodes.values()
+                .map(|node| LocationResponse {
+                    node: node.clone(),
+                    path: location_path(&nodes, node.id),
+                })
+                .collect();
+            list.sort_by(|a, b| a.path.cmp(&b.path));
+            (StatusCode::OK, Json(list)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_location(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.location_manager.get_location(id) {
+        Ok(node) => (StatusCode::OK, Json(node)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateLocationRequest {
+    name: String,
+    kind: LocationKind,
+    parent_id: Option<Uuid>,
+}
+
+async fn create_location(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CreateLocationRequest>,
+) -> impl IntoResponse {
+    match state.location_manager.create_location(request.name, request.kind, request.parent_id) {
+        Ok(node) => (StatusCode::CREATED, Json(node)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct UpdateLocationRequest {
+    name: Option<String>,
+    parent_id: Option<Uuid>,
+}
+
+async fn update_location(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<UpdateLocationRequest>,
+) -> impl IntoResponse {
+    match state.location_manager.update_location(id, request.name, request.parent_id) {
+        Ok(node) => (StatusCode::OK, Json(node)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteLocationParams {
+    reassign_to: Option<Uuid>,
+}
+
+async fn delete_location(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DeleteLocationParams>,
+) -> impl IntoResponse {
+    let mut printers = match state.printer_manager.lock() {
+        Ok(printers) => printers,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    };
+
+    // Printers still placed here block the delete unless a new location is given
+    let referenced = printers.count_at_location(&id);
+    if referenced > 0 {
+        match params.reassign_to {
+            None => return (
+                StatusCode::CONFLICT,
+                format!("{} printers reference this location, pass reassign_to to move them", referenced),
+            ).into_response(),
+            Some(target) if target == id => {
+                return (StatusCode::BAD_REQUEST, "Cannot reassign printers to the deleted location".to_string()).into_response();
+            },
+            Some(target) => {
+                if state.location_manager.get_location(target).is_err() {
+                    return (StatusCode::BAD_REQUEST, format!("Location not found: {}", target)).into_response();
+                }
+            },
+        }
+    }
+
+    if let Err(e) = state.location_manager.delete_location(id) {
+        return (StatusCode::CONFLICT, e.to_string()).into_response();
+    }
+
+    if referenced > 0 {
+        printers.reassign_location(&id, params.reassign_to);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn printer_summary(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SiteQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let locations = match state.location_manager.get_all_locations() {
+        Ok(locations) => locations,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    match state.printer_manager.lock() {
+        Ok(printers) => (StatusCode::OK, Json(printers.summarize_by_location(&locations, &scope))).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct InventoryExportQuery {
+    // Same filter as the list
+    site: Option<Uuid>,
+    #[serde(default)]
+    format: ExportFormat,
+    // Comma-separated columns, in output order
+    fields: Option<String>,
+    // Prefix the CSV with a UTF-8 byte order mark for Excel
+    #[serde(default)]
+    bom: bool,
+}
+
+fn inventory_export_response(name: &str, query: &InventoryExportQuery, body: axum::body::Body) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (axum::http::header::CONTENT_TYPE, query.format.content_type().to_string()),
+            (axum::http::header::CONTENT_DISPOSITION,
+             format!("attachment; filename=\"{}-{}.{}\"", name, Utc::now().format("%Y%m%dT%H%M%S"), query.format.extension())),
+        ],
+        body,
+    ).into_response()
+}
+
+async fn export_printers(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<InventoryExportQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let printers: Vec<_> = match state.printer_manager.lock() {
+        Ok(printers) => printers.get_printers().into_iter()
+            .filter(|p| scope.allows(p.site_id))
+            .cloned()
+            .collect(),
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    };
+    let defaults = inventory_export::default_printer_fields(&printers);
+    let fields = match inventory_export::select_fields(query.fields.as_deref(), &inventory_export::printer_fields(), defaults) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        "printers:export",
+        "printers",
+        AuditStatus::Success,
+        Some(format!("{} printers as {}, site {:?}, fields {}", printers.len(), query.format.extension(), query.site, fields.join(","))),
+    );
+
+    let stream = inventory_export::export_stream(query.format, query.bom, fields, printers, inventory_export::printer_value);
+    inventory_export_response("printers", &query, axum::body::Body::from_stream(stream))
+}
+
+#[derive(Deserialize)]
+struct AccountingQuery {
+    user: Option<String>,
+    // YYYY-MM, defaults to the current month
+    month: Option<String>,
+}
+
+// Staff see every user, everyone else only their own printing
+async fn printer_accounting(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<AccountingQuery>,
+) -> impl IntoResponse {
+    let filter = match query.user {
+        Some(name) if name != user.username && !user.is_staff() => return StatusCode::FORBIDDEN.into_response(),
+        Some(name) => Some(name),
+        None if user.is_staff() => None,
+        None => Some(user.username.clone()),
+    };
+    let month = query.month.unwrap_or_else(|| print_accounting::month_of(Utc::now()));
+
+    match state.print_accounting.accounting(filter.as_deref(), &month) {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PrinterLocationRequest {
+    location_id: Option<Uuid>,
+}
+
+async fn set_printer_location(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PrinterLocationRequest>,
+) -> impl IntoResponse {
+    if let Some(location_id) = request.location_id {
+        if state.location_manager.get_location(location_id).is_err() {
+            return (StatusCode::BAD_REQUEST, format!("Location not found: {}", location_id)).into_response();
+        }
+    }
+
+    let scope = user.site_scope();
+    match state.printer_manager.lock() {
+        Ok(mut printers) => {
+            if !printers.get_printer(&id).map_or(false, |p| scope.allows(p.site_id)) {
+                return (StatusCode::NOT_FOUND, format!("Printer not found: {}", id)).into_response();
+            }
+            match printers.set_printer_location(&id, request.location_id) {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+            }
+        },
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PrinterSiteRequest {
+    site_id: Option<Uuid>,
+}
+
+async fn set_printer_site(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PrinterSiteRequest>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    if let Some(site_id) = request.site_id {
+        if !scope.allows(Some(site_id)) || state.sites.get_site(site_id).is_err() {
+            return (StatusCode::BAD_REQUEST, format!("Site not found: {}", site_id)).into_response();
+        }
+    }
+
+    match state.printer_manager.lock() {
+        Ok(mut printers) => {
+            if !printers.get_printer(&id).map_or(false, |p| scope.allows(p.site_id)) {
+                return (StatusCode::NOT_FOUND, format!("Printer not found: {}", id)).into_response();
+            }
+            match printers.set_printer_site(&id, request.site_id) {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+            }
+        },
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    }
+}
+
+// Reported supply levels; supplies that ran low are handed to the reorder hook
+async fn update_printer_supplies(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(supplies): Json<Vec<PrinterSupply>>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    let reorders = match state.printer_manager.lock() {
+        Ok(mut printers) => {
+            if !printers.get_printer(&id).map_or(false, |p| scope.allows(p.site_id)) {
+                return (StatusCode::NOT_FOUND, format!("Printer not found: {}", id)).into_response();
+            }
+            match printers.update_supply_levels(&id, supplies) {
+                Ok(reorders) => reorders,
+                Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+            }
+        },
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    };
+
+    let count = reorders.len();
+    if count > 0 {
+        let hook = state.printer_reorder.clone();
+        request_trace::spawn(async move { hook.dispatch(reorders).await });
+    }
+    (StatusCode::OK, Json(serde_json::json!({ "reorders": count }))).into_response()
+}
+
+// Reported job queue; jobs completing are added to the print accounting
+async fn update_printer_jobs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(jobs): Json<Vec<PrintJob>>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    match state.printer_manager.lock() {
+        Ok(mut printers) => {
+            if !printers.get_printer(&id).map_or(false, |p| scope.allows(p.site_id)) {
+                return (StatusCode::NOT_FOUND, format!("Printer not found: {}", id)).into_response();
+            }
+            match printers.sync_print_queue(&id, jobs) {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            }
+        },
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to acquire lock on printers".to_string()).into_response(),
+    }
+}
+
+// Admin API handlers
+// Build metadata, and the result of the last update check when it is enabled
+async fn get_version(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.update_checker.last_status() {
+        Ok(update) => (StatusCode::OK, Json(serde_json::json!({
+            "build": version::build_info(),
+            "update_check_enabled": state.update_checker.enabled(),
+            "update": update,
+        }))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn list_background_tasks(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.task_registry.get_all_tasks() {
+        Ok(tasks) => (StatusCode::OK, Json(tasks)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct UsageQuery {
+    #[serde(default)]
+    window: UsageWindow,
+    #[serde(default)]
+    by: UsageGrouping,
+    #[serde(default)]
+    sort: UsageSort,
+    // Top N after sorting
+    limit: Option<usize>,
+}
+
+async fn get_api_usage(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<UsageQuery>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.api_usage.report(query.window, query.by, query.sort, query.limit.unwrap_or(20)) {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SupportExportQuery {
+    // Logs of the last 24 hours when absent
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    max_logs: Option<usize>,
+    // Anonymized as [anonymize] says unless false
+    anonymize: Option<bool>,
+}
+
+// Tickets, alerts, assets, recent logs and the latest firewall backup in one document
+// for support or a demo. Everything is anonymized together, so a user or address
+// reads the same in every section.
+fn build_support_export(state: &AppState, query: &SupportExportQuery) -> anyhow::Result<serde_json::Value> {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - chrono::Duration::hours(24));
+    let filter = LogFilter {
+        from: Some(from),
+        to: Some(to),
+        limit: Some(query.max_logs.unwrap_or(10_000).min(100_000)),
+        ..Default::default()
+    };
+    let firewall = match state.firewall_backups.list()?.first() {
+        Some(latest) => {
+            let (created_at, model) = state.firewall_backups.model(latest.id)?;
+            Some(serde_json::json!({ "backup_created_at": created_at, "model": model }))
+        }
+        None => None,
+    };
+    let assets = state.asset_manager.get_all_assets()?;
+
+    let mut sections = vec![
+        serde_json::to_value(state.tickets_manager.get_all_tickets()?)?,
+        serde_json::to_value(state.logs_manager.query(&filter)?)?,
+        serde_json::to_value(state.alerts_manager.get_all_alerts()?)?,
+        serde_json::to_value(&assets)?,
+        serde_json::to_value(firewall)?,
+    ];
+
+    let mut anonymized = Vec::new();
+    if query.anonymize.unwrap_or(true) {
+        let mut anonymizer = Anonymizer::new(&state.config.anonymize)?;
+        for asset in &assets {
+            anonymizer.learn_hostname(&asset.name);
+        }
+        anonymizer.anonymize(&mut sections)?;
+        anonymized = anonymizer.classes();
+    }
+
+    let mut sections = sections.into_iter();
+    let mut next = || sections.next().unwrap_or_default();
+    Ok(serde_json::json!({
+        "generated_at": Utc::now(),
+        "from": from,
+        "to": to,
+        "anonymized": anonymized,
+        "tickets": next(),
+        "logs": next(),
+        "alerts": next(),
+        "assets": next(),
+        "firewall": next(),
+    }))
+}
+
+async fn get_support_export(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SupportExportQuery>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match build_support_export(&state, &query) {
+        Ok(export) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "admin:support_export",
+                "support-export",
+                AuditStatus::Success,
+                Some(format!("anonymized: {}", export["anonymized"])),
+            );
+            (StatusCode::OK, Json(export)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_calendar(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.calendar.get() {
+        Ok(calendar) => (StatusCode::OK, Json(calendar)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Replaces the working days, hours and holidays; breaches and alerts already raised
+// stay as they are
+async fn update_calendar(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(settings): Json<CalendarSettings>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.calendar.update(settings, &user.username) {
+        Ok(calendar) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "calendar:update",
+                &format!("version {}", calendar.version),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(calendar)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct HolidayRequest {
+    date: NaiveDate,
+    name: String,
+}
+
+async fn add_calendar_holiday(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<HolidayRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.calendar.add_holiday(request.date, request.name.clone(), &user.username) {
+        Ok(calendar) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "calendar:add_holiday",
+                &request.date.to_string(),
+                AuditStatus::Success,
+                Some(request.name),
+            );
+            (StatusCode::CREATED, Json(calendar)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn remove_calendar_holiday(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(date): Path<NaiveDate>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.calendar.remove_holiday(date, &user.username) {
+        Ok(Some(calendar)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "calendar:remove_holiday",
+                &date.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(calendar)).into_response()
+        },
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CalendarImportQuery {
+    // Drop every holiday of earlier imports, not only those of the events imported again
+    #[serde(default)]
+    replace: bool,
+}
+
+// Holidays from an iCalendar (.ics) file sent as the request body
+async fn import_calendar(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<CalendarImportQuery>,
+    body: String,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.calendar.import_ics(&body, query.replace, &user.username) {
+        Ok((calendar, import)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "calendar:import",
+                &format!("version {}", calendar.version),
+                AuditStatus::Success,
+                Some(format!("{} days imported, {} replaced, {} events skipped", import.imported, import.replaced, import.skipped.len())),
+            );
+            (StatusCode::OK, Json(serde_json::json!({
+                "calendar": calendar,
+                "import": import,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct BusinessTimeQuery {
+    at: Option<DateTime<Utc>>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+// Whether an instant is business time, and the business time between two instants
+async fn check_business_time(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<BusinessTimeQuery>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let calendar = match state.calendar.calendar() {
+        Ok(calendar) => calendar,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let at = query.at.unwrap_or_else(Utc::now);
+    let business_seconds = match (query.from, query.to) {
+        (Some(from), Some(to)) => Some(calendar.business_duration(from, to).num_seconds()),
+        _ => None,
+    };
+    (StatusCode::OK, Json(serde_json::json!({
+        "at": at,
+        "business_time": calendar.is_business_time(at),
+        "from": query.from,
+        "to": query.to,
+        "business_seconds": business_seconds,
+        "calendar_version": calendar.version,
+    }))).into_response()
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    #[serde(default)]
+    since: u64,
+}
+
+// The changes feed of the primary, see replication
+async fn get_replication_changes(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<ChangesQuery>,
+) -> impl IntoResponse {
+    let presented = headers.get(replication::KEY_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if !state.replication.accepts_key(presented) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match state.replication.changes(query.since).await {
+        Ok(page) => (StatusCode::OK, Json(page)).into_response(),
+        Err(e) if state.replication.role().is_standby() => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// A notification in the tool's native webhook format, see integrations. Unknown fields are
+// ignored; a payload missing what is needed is refused whole with every problem listed.
+async fn receive_alert_webhook(
+    state: &AppState,
+    source: IntegrationSource,
+    headers: &axum::http::HeaderMap,
+    body: &[u8],
+) -> Response {
+    if !state.integrations.is_enabled(source) {
+        return (StatusCode::NOT_FOUND, format!("The {} integration has no token configured", source.as_str())).into_response();
+    }
+    let presented = headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !state.integrations.accepts_token(source, presented) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let payload: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(payload) => payload,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+    };
+    let alerts = match integrations::parse_notification(source, &payload) {
+        Ok(alerts) => alerts,
+        Err(errors) => {
+            warn!("Refused {} notification: {}", source.as_str(), errors.join("; "));
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({
+                "error": format!("Not a valid {} webhook payload", source.as_str()),
+                "errors": errors,
+            }))).into_response();
+        },
+    };
+
+    match state.integrations.receive(source, alerts) {
+        Ok(outcome) => {
+            info!("{} notification: {} alerts opened, {} resolved, {} already open",
+                  source.as_str(), outcome.created.len(), outcome.resolved.len(), outcome.duplicates);
+            (StatusCode::OK, Json(outcome)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn alertmanager_webhook(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    receive_alert_webhook(&state, IntegrationSource::Alertmanager, &headers, &body).await
+}
+
+async fn grafana_webhook(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    receive_alert_webhook(&state, IntegrationSource::Grafana, &headers, &body).await
+}
+
+async fn get_replication_status(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.replication.status().await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn promote_replica(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.replication.promote(&user.username).await {
+        Ok(promotion) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "replication:promote",
+                state.replication.primary_url().unwrap_or_default(),
+                AuditStatus::Success,
+                promotion.staged_changeset.map(|id| format!("replicated firewall model staged as changeset {}", id)),
+            );
+            (StatusCode::OK, Json(promotion)).into_response()
+        },
+        Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+    }
+}
+
+// Resolves a conflict in favour of the primary; its version is applied at the next sync
+async fn resolve_replication_conflict(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((kind, id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(kind) = ObjectKind::parse(&kind) else {
+        return (StatusCode::BAD_REQUEST, format!("Unknown object kind: {}", kind)).into_response();
+    };
+
+    match state.replication.resolve_conflict(kind, &id).await {
+        Ok(conflict) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "replication:resolve_conflict",
+                &format!("{}/{}", kind.as_str(), id),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(conflict)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Fields of config, user, ticket and script responses that depend on the caller's role
+async fn list_redactions(
+    _user: AuthUser,
+) -> Json<Vec<redaction::RedactedType>> {
+    Json(redaction::schema())
+}
+
+// The configuration as saved, secrets masked
+async fn get_config(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.config_history.current() {
+        Ok(config) => (StatusCode::OK, Redacted(config, user)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Partial update as a JSON merge patch; the response lists which changed fields apply
+// now and which wait for a restart
+async fn update_config(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(patch): Json<serde_json::Value>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.config_history.update(&patch, &user.username, "PATCH /api/admin/config") {
+        Ok(update) => {
+            if update.version.is_some() {
+                let mut details: Vec<String> = update.changes.iter()
+                    .map(|c| format!("{}: {} -> {}",
+                                     c.field,
+                                     c.old.as_ref().map_or("unset".to_string(), |v| v.to_string()),
+                                     c.new.as_ref().map_or("unset".to_string(), |v| v.to_string())))
+                    .collect();
+                details.extend(update.secrets_changed.iter().map(|field| format!("{}: changed", field)));
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "config:update",
+                    "config",
+                    AuditStatus::Success,
+                    Some(details.join("; ")),
+                );
+            }
+            (StatusCode::OK, Json(update)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "config:update",
+                "config",
+                AuditStatus::Failure,
+                Some(e.to_string()),
+            );
+            (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct ConnectivityTestParams {
+    // Defaults to proxy.test_url
+    url: Option<String>,
+    // Whose proxy settings to test with
+    integration: Option<String>,
+}
+
+// Fetches a URL through the effective outbound settings and reports each step
+async fn connectivity_test(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<ConnectivityTestParams>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let integration = params.integration.as_deref().unwrap_or(outbound::CONNECTIVITY_TEST);
+    match state.http_clients.connectivity_test(params.url.as_deref(), integration).await {
+        Ok(report) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "admin:connectivity_test",
+                &report.url,
+                if report.success { AuditStatus::Success } else { AuditStatus::Failure },
+                Some(format!("integration {}, via {}", integration, report.via.as_deref().unwrap_or("direct"))),
+            );
+            (StatusCode::OK, Json(report)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_config_history(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.config_history.history() {
+        Ok(history) => (StatusCode::OK, Json(history)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn rollback_config(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(version): Path<u64>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.config_history.get_versions() {
+        Ok(versions) if !versions.iter().any(|v| v.version == version) => {
+            return (StatusCode::NOT_FOUND, format!("Unknown config version: {}", version)).into_response();
+        },
+        Ok(_) => {},
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+
+    let source = format!("POST /api/admin/config/rollback/{}", version);
+    match state.config_history.rollback(version, &user.username, &source) {
+        Ok(saved) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "config:rollback",
+                &format!("config version {}", version),
+                AuditStatus::Success,
+                Some(format!("Saved as version {}", saved.version)),
+            );
+            (StatusCode::OK, Json(saved)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "config:rollback",
+                &format!("config version {}", version),
+                AuditStatus::Failure,
+                Some(e.to_string()),
+            );
+            (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct ReclassifyParams {
+    #[serde(default)]
+    force: bool,
+}
+
+async fn reclassify_logs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<ReclassifyParams>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Without force only entries that never got a category are revisited
+    let result = if params.force {
+        state.logs_manager.reclassify(classification::reclassify)
+    } else {
+        state.logs_manager.reclassify(classification::classify)
+    };
+
+    match result {
+        Ok(changed) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "logs:reclassify",
+                "logs",
+                AuditStatus::Success,
+                Some(format!("{} entries changed, force: {}", changed, params.force)),
+            );
+            (StatusCode::OK, Json(serde_json::json!({ "changed": changed }))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to reclassify logs: {}", e)).into_response(),
+    }
+}
+
+// Network API handlers
+// Interfaces whose metadata puts them in the caller's sites, like list_interface_metadata
+async fn get_interfaces(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<Vec<crate::network::InterfaceInfo>>, StatusCode> {
+    let scope = user.site_scope();
+    match interfaces_with_metadata(&state).await {
+        Ok(mut interfaces) => {
+            interfaces.retain(|i| scope.allows(i.metadata.as_ref().and_then(|m| m.site_id)));
+            Ok(Json(interfaces))
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+// Interfaces present on the host with their description, owner and tags, whatever
+// their site
+async fn interfaces_with_metadata(state: &AppState) -> anyhow::Result<Vec<crate::network::InterfaceInfo>> {
+    let mut interfaces = state.network_manager.get_interfaces().await?;
+    state.interface_metadata.apply(&mut interfaces)?;
+    state.link_flaps.mark(&mut interfaces)?;
+    state.dhcp_relays.mark(&mut interfaces, &state.network_manager.dhcp_relay_counters().await)?;
+    Ok(interfaces)
+}
+
+async fn list_link_flaps(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.link_flaps.statuses() {
+        Ok(statuses) => (StatusCode::OK, Json(statuses)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Billing cycle usage and projection of every interface with a monthly quota
+async fn get_bandwidth_usage(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    // WAN links are shared by every site
+    if !user.is_staff() || !user.sites.is_empty() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.bandwidth_quotas.usage() {
+        Ok(usage) => (StatusCode::OK, Json(usage)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// All stored metadata, including entries of interfaces that are currently missing
+async fn list_interface_metadata(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SiteQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    match state.interface_metadata.get_all() {
+        Ok(metadata) => {
+            let visible: Vec<_> = metadata.into_iter()
+                .filter(|m| scope.allows(m.site_id))
+                .collect();
+            (StatusCode::OK, Json(visible)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn update_interface_metadata(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(name): Path<String>,
+    Json(patch): Json<InterfaceMetadataPatch>,
+) -> impl IntoResponse {
+    // Restricted users only edit interfaces of their sites and cannot move them elsewhere
+    let scope = user.site_scope();
+    match state.interface_metadata.get(&name) {
+        Ok(Some(existing)) if !scope.allows(existing.site_id) => {
+            return (StatusCode::NOT_FOUND, format!("Interface not found: {}", name)).into_response();
+        },
+        Ok(_) => {},
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    if let Some(site) = patch.site_id.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        let site_id = match Uuid::parse_str(site) {
+            Ok(id) => id,
+            Err(_) => return (StatusCode::BAD_REQUEST, format!("Invalid site id: {}", site)).into_response(),
+        };
+        if !scope.allows(Some(site_id)) || state.sites.get_site(site_id).is_err() {
+            return (StatusCode::BAD_REQUEST, format!("Site not found: {}", site_id)).into_response();
+        }
+    } else if patch.site_id.is_some() && scope != SiteScope::All {
+        return (StatusCode::BAD_REQUEST, "Only unrestricted users can clear the site".to_string()).into_response();
+    }
+
+    match state.interface_metadata.update(&name, patch, &user.username) {
+        Ok(metadata) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "network:interface_metadata",
+                &name,
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(metadata)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_firewall_rules(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<String>> {
+    let rules = state.network_manager.get_nftables_rules().await;
+    Json(rules)
+}
+
+#[derive(Deserialize)]
+struct InterfaceConfig {
+    dhcp: Option<bool>,
+    address: Option<String>,
+    nftables_zone: Option<String>,
+    bond: Option<BondConfig>,
+}
+
+async fn setup_interface(
+    State(state): State<Arc<AppState>>,
+    Path(interface_name): Path<String>,
+    Json(config): Json<InterfaceConfig>,
+) -> impl IntoResponse {
+    let interface_config = crate::network::InterfaceConfig {
+        name: interface_name,
+        dhcp: config.dhcp,
+        address: config.address,
+        nftables_zone: config.nftables_zone,
+        bond: config.bond,
+        dhcp_relay: None,
+    };
+
+    match state.network_manager.setup_interface(&interface_config).await {
+        Ok(_) => (StatusCode::OK, "Interface configured successfully"),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to configure interface: {}", e)),
+    }
+}
+
+// Local address the client connected to, taken from the Host header when it is an IP literal
+fn request_host_address(headers: &axum::http::HeaderMap) -> Option<std::net::IpAddr> {
+    let host = headers.get(axum::http::header::HOST)?.to_str().ok()?;
+    let address = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next()?,
+        None => host.rsplit_once(':').map_or(host, |(address, _)| address),
+    };
+    address.parse().ok()
+}
+
+async fn preview_network_config(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    headers: axum::http::HeaderMap,
+    Json(interfaces): Json<Vec<crate::network::InterfaceConfig>>,
+) -> impl IntoResponse {
+    match state.network_manager.preview_config(interfaces, request_host_address(&headers), &user.username).await {
+        Ok(preview) => (StatusCode::OK, Json(preview)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to preview interface config: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ApplyNetworkConfigRequest {
+    preview_token: Uuid,
+}
+
+async fn apply_network_config(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ApplyNetworkConfigRequest>,
+) -> impl IntoResponse {
+    match state.network_manager.apply_config_preview(request.preview_token, &state.config.firewall).await {
+        Ok((preview, changes)) => {
+            record_service_rule_changes(&state, &changes, &user.username);
+            if let Err(e) = state.dhcp_relays.sync(&preview.interfaces) {
+                warn!("Failed to update DHCP relays: {}", e);
+            }
+            state.security_manager.log_audit_event(
+                &user.username,
+                "network:apply_config",
+                &preview.token.to_string(),
+                AuditStatus::Success,
+                Some(format!("{} interfaces changed, zones regenerated: {}", preview.changes.len(), preview.regenerated_zones.join(", "))),
+            );
+            (StatusCode::OK, Json(preview)).into_response()
+        },
+        Err(e) if e.downcast_ref::<PreviewConflict>().is_some() => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to apply interface config: {}", e)).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveQuery {
+    // Look the address up now instead of answering from the cache
+    #[serde(default)]
+    refresh: bool,
+}
+
+// Reverse lookup of an address, or forward lookup when given a name. Without refresh
+// a cache miss answers "pending" and queues the lookup.
+async fn resolve_address(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+    Query(query): Query<ResolveQuery>,
+) -> impl IntoResponse {
+    let resolver = &state.resolver;
+
+    let body = match address.parse::<std::net::IpAddr>() {
+        Ok(ip) => {
+            let entry = if query.refresh {
+                Some(resolver.lookup_reverse(ip).await)
+            } else {
+                let cached = resolver.cached_reverse(&ip);
+                resolver.hostname(ip);
+                cached
+            };
+            serde_json::json!({
+                "address": ip,
+                "hostname": entry.as_ref().and_then(|e| e.value.clone()),
+                "resolved_at": entry.as_ref().map(|e| e.resolved_at),
+                "expires_at": entry.as_ref().map(|e| e.expires_at),
+                "pending": entry.is_none(),
+            })
+        },
+        Err(_) => {
+            let entry = if query.refresh {
+                Some(resolver.lookup_forward(&address).await)
+            } else {
+                let cached = resolver.cached_forward(&address);
+                resolver.addresses(&address);
+                cached
+            };
+            serde_json::json!({
+                "name": address,
+                "addresses": entry.as_ref().map(|e| e.value.clone()).unwrap_or_default(),
+                "resolved_at": entry.as_ref().map(|e| e.resolved_at),
+                "expires_at": entry.as_ref().map(|e| e.expires_at),
+                "pending": entry.is_none(),
+            })
+        },
+    };
+
+    (StatusCode::OK, Json(body))
+}
+
+async fn list_bonds(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.network_manager.get_bonds().await {
+        Ok(bonds) => (StatusCode::OK, Json(bonds)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list bonds: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateBondRequest {
+    name: String,
+    #[serde(flatten)]
+    bond: BondConfig,
+}
+
+async fn create_bond(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<CreateBondRequest>,
+) -> impl IntoResponse {
+    match state.network_manager.create_bond(&request.name, &request.bond).await {
+        Ok(bond) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "create_bond",
+                &request.name,
+                AuditStatus::Success,
+                Some(format!("{:?} with members {:?}", request.bond.mode, request.bond.members)),
+            );
+            (StatusCode::CREATED, Json(bond)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "create_bond",
+                &request.name,
+                AuditStatus::Failure,
+                Some(e.to_string()),
+            );
+            (StatusCode::BAD_REQUEST, format!("Failed to create bond: {:#}", e)).into_response()
+        },
+    }
+}
+
+async fn delete_bond(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.network_manager.delete_bond(&name).await {
+        Ok(()) => {
+            state.security_manager.log_audit_event(&user.username, "delete_bond", &name, AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to delete bond: {:#}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CaptureRequest {
+    interface: String,
+    duration_seconds: Option<u64>,
+    packet_count: Option<u32>,
+    filter: Option<String>,
+}
+
+async fn start_capture(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<CaptureRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let details = format!("filter: {}", request.filter.as_deref().unwrap_or("<none>"));
+
+    match state.capture_manager.start_capture(
+        request.interface.clone(),
+        request.duration_seconds,
+        request.packet_count,
+        request.filter,
+        user.username.clone(),
+    ).await {
+        Ok(session) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "capture:start",
+                &request.interface,
+                AuditStatus::Success,
+                Some(details),
+            );
+            (StatusCode::CREATED, Json(session)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "capture:start",
+                &request.interface,
+                AuditStatus::Failure,
+                Some(format!("{}; {}", details, e)),
+            );
+            (StatusCode::BAD_REQUEST, format!("Failed to start capture: {}", e)).into_response()
+        }
+    }
+}
+
+async fn get_capture_status(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.capture_manager.get_capture(id) {
+        Ok(session) => (StatusCode::OK, Json(session)).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn download_capture(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let session = match state.capture_manager.get_capture(id) {
+        Ok(session) => session,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match session.status {
+        CaptureStatus::Completed => {},
+        CaptureStatus::Running => return (StatusCode::CONFLICT, "Capture is still running".to_string()).into_response(),
+        CaptureStatus::Failed | CaptureStatus::Expired => return StatusCode::GONE.into_response(),
+    }
+
+    let file = match tokio::fs::File::open(state.capture_manager.capture_path(id)).await {
+        Ok(file) => file,
+        Err(_) => return StatusCode::GONE.into_response(),
+    };
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        "capture:download",
+        &session.interface,
+        AuditStatus::Success,
+        Some(format!("capture: {}, filter: {}", id, session.filter.as_deref().unwrap_or("<none>"))),
+    );
+
+    let body = axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(file));
+
+    (
+        StatusCode::OK,
+        [
+            (axum::http::header::CONTENT_TYPE, "application/vnd.tcpdump.pcap".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}-{}.pcap\"", session.interface, id)),
+        ],
+        body,
+    ).into_response()
+}
+
+// Visualization API handlers
+#[derive(Deserialize)]
+struct GraphAtQuery {
+    // Returns the snapshot nearest to this instant instead of the live graph
+    at: Option<DateTime<Utc>>,
+}
+
+// The live graph, refreshed from the interfaces, narrowed to the scope and collapsed as
+// the query asks
+async fn current_network_graph(
+    state: &AppState,
+    scope: &SiteScope,
+    grouping: &GroupingQuery,
+) -> Result<crate::visualizations::NetworkGraph, String> {
+    let group_by = grouping.group_by().map_err(|e| e.to_string())?;
+
+    // Refresh the interface nodes (bond membership, metadata) before rendering
+    match interfaces_with_metadata(state).await {
+        Ok(interfaces) => state.visualization_manager.update_from_interfaces(&interfaces),
+        Err(e) => tracing::warn!("Failed to refresh interfaces for the network graph: {}", e),
+    }
+
+    let manager = &state.visualization_manager;
+    let graph = manager.scope_graph(manager.get_network_graph(), scope);
+    Ok(match group_by {
+        Some(by) => graph_grouping::collapse(&graph, &by, grouping.max_nodes),
+        None => graph,
+    })
+}
+
+async fn get_network_graph(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<GraphAtQuery>,
+    Query(grouping): Query<GroupingQuery>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    if let Some(at) = query.at {
+        let group_by = match grouping.group_by() {
+            Ok(group_by) => group_by,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+        return match state.graph_snapshots.nearest(at) {
+            Ok(mut snapshot) => {
+                snapshot.graph = state.visualization_manager.scope_graph(snapshot.graph, &scope);
+                if let Some(by) = group_by {
+                    snapshot.graph = graph_grouping::collapse(&snapshot.graph, &by, grouping.max_nodes);
+                }
+                (StatusCode::OK, Json(snapshot)).into_response()
+            },
+            Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        };
+    }
+
+    match current_network_graph(&state, &scope, &grouping).await {
+        Ok(graph) => (StatusCode::OK, Json(graph)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExpandGroupQuery {
+    collapse: String,
+    // group_key of the group node
+    key: String,
+}
+
+// Members of one collapsed group within the caller's sites, with the links touching them
+async fn expand_graph_group(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<ExpandGroupQuery>,
+) -> impl IntoResponse {
+    let by = match query.collapse.parse() {
+        Ok(by) => by,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}", e)).into_response(),
+    };
+
+    let graph = match current_network_graph(&state, &user.site_scope(), &GroupingQuery::default()).await {
+        Ok(graph) => graph,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    match graph_grouping::members(&graph, &by, &query.key) {
+        Ok(members) => (StatusCode::OK, Json(members)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotRangeQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+async fn list_graph_snapshots(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Query(query): Query<SnapshotRangeQuery>,
+) -> impl IntoResponse {
+    match state.graph_snapshots.list(query.from, query.to) {
+        Ok(snapshots) => (StatusCode::OK, Json(snapshots)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotDiffQuery {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+// Differences between the snapshots nearest to the two instants, within the caller's sites
+async fn diff_graph_snapshots(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SnapshotDiffQuery>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    let snapshots = state.graph_snapshots.nearest(query.from)
+        .and_then(|from| Ok((from, state.graph_snapshots.nearest(query.to)?)));
+
+    match snapshots {
+        Ok((mut from, mut to)) => {
+            from.graph = state.visualization_manager.scope_graph(from.graph, &scope);
+            to.graph = state.visualization_manager.scope_graph(to.graph, &scope);
+            (StatusCode::OK, Json(graph_snapshots::diff(&from, &to))).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn get_traffic_flows(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SiteQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let mut flows = state.visualization_manager.get_traffic_flows();
+    flows.retain(|flow| scope.allows(flow.site_id));
+    for flow in &mut flows {
+        flow.source_hostname = state.resolver.hostname_of(&flow.source);
+        flow.destination_hostname = state.resolver.hostname_of(&flow.destination);
+    }
+    (StatusCode::OK, Json(flows)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct FlowExportQuery {
+    format: Option<FlowExportFormat>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+async fn export_traffic_flows(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<FlowExportQuery>,
+) -> impl IntoResponse {
+    let format = query.format.unwrap_or(FlowExportFormat::Csv);
+    let stream = flow_export::export_stream(state.visualization_manager.clone(), format, query.from, query.to, user.site_scope());
+
+    (
+        StatusCode::OK,
+        [
+            (axum::http::header::CONTENT_TYPE, format.content_type().to_string()),
+            (axum::http::header::CONTENT_DISPOSITION,
+             format!("attachment; filename=\"traffic-flows-{}.{}\"", Utc::now().format("%Y%m%dT%H%M%S"), format.extension())),
+        ],
+        axum::body::Body::from_stream(stream),
+    )
+}
+
+// The flow store only holds recent flows, longer windows would not add anything
+const GEO_FLOWS_MAX_WINDOW_MINUTES: i64 = 7 * 24 * 60;
+const GEO_FLOWS_MAX_TOP: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct GeoFlowsQuery {
+    // Window ending at `to` (default now); ignored when `from` is given
+    window_minutes: Option<i64>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    top: Option<usize>,
+}
+
+async fn get_geo_flows(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<GeoFlowsQuery>,
+) -> impl IntoResponse {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let earliest = to - chrono::Duration::minutes(GEO_FLOWS_MAX_WINDOW_MINUTES);
+    let from = query.from
+        .unwrap_or_else(|| to - chrono::Duration::minutes(query.window_minutes.unwrap_or(60).clamp(1, GEO_FLOWS_MAX_WINDOW_MINUTES)))
+        .max(earliest);
+
+    if from > to {
+        return (StatusCode::BAD_REQUEST, "from must not be after to".to_string()).into_response();
+    }
+
+    let site = &state.config.geoip;
+    let collection = state.visualization_manager.geo_flows(
+        &state.geoip,
+        &site.site_name,
+        geo::Point::new(site.site_longitude, site.site_latitude),
+        from,
+        to,
+        &user.site_scope(),
+        query.top.unwrap_or(50).clamp(1, GEO_FLOWS_MAX_TOP),
+    );
+
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "application/geo+json")],
+        Json(collection),
+    ).into_response()
+}
+
+async fn get_traffic_stats(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    let stats = state.visualization_manager.scoped_traffic_statistics(&user.site_scope());
+    (StatusCode::OK, Json(stats))
+}
+
+async fn get_traffic_collector(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    (StatusCode::OK, Json(state.visualization_manager.traffic_collector_status()))
+}
+
+// Runs a collection cycle now instead of waiting for the next one
+async fn refresh_traffic_stats(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.visualization_manager.collect_traffic().await {
+        Ok(stats) => {
+            state.security_manager.log_audit_event(&user.username, "refresh_traffic_stats", "traffic_stats", AuditStatus::Success, None);
+            (StatusCode::OK, Json(serde_json::json!({
+                "collector": state.visualization_manager.traffic_collector_status(),
+                "stats": stats,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to collect traffic statistics: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TrafficHistoryParams {
+    // Wraps the result as { history, annotations } for chart markers
+    #[serde(default)]
+    include_annotations: bool,
+}
+
+#[derive(Deserialize)]
+struct TrafficCompareParams {
+    // Interface name, or "all" for every interface combined
+    interface: String,
+    // "<from>/<to>" in RFC 3339; both ranges must be the same length
+    range_a: String,
+    range_b: String,
+    // Seconds per step
+    resolution: Option<i64>,
+}
+
+async fn compare_traffic(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<TrafficCompareParams>,
+) -> impl IntoResponse {
+    let windows = Window::parse(&params.range_a)
+        .and_then(|a| Window::parse(&params.range_b).map(|b| (a, b)));
+    let (range_a, range_b) = match windows {
+        Ok(windows) => windows,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    // Interfaces in the caller's sites only, also for "all"
+    let scope = user.site_scope();
+    let visible = |name: &str| state.visualization_manager.interface_in_scope(name, &scope);
+    match state.visualization_manager.traffic_history().compare(&params.interface, range_a, range_b, params.resolution, visible) {
+        Ok(comparison) => (StatusCode::OK, Json(comparison)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_traffic_history(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(interface): Path<String>,
+    Query(params): Query<TrafficHistoryParams>,
+) -> impl IntoResponse {
+    if !state.visualization_manager.interface_in_scope(&interface, &user.site_scope()) {
+        return (StatusCode::NOT_FOUND, "Interface not found".to_string()).into_response();
+    }
+
+    let history = state.visualization_manager.get_traffic_history(&interface);
+    if !params.include_annotations {
+        return (StatusCode::OK, Json(history)).into_response();
+    }
+
+    // Global and interface annotations within the span of the history
+    let filter = AnnotationFilter {
+        from: history.first().map(|p| p.timestamp),
+        to: history.last().map(|p| p.timestamp),
+        scopes: vec![AnnotationScope::Interface { name: interface.clone() }],
+        include_global: true,
+    };
+    match state.annotation_manager.query(&filter) {
+        Ok(annotations) => (StatusCode::OK, Json(serde_json::json!({
+            "history": history,
+            "annotations": annotations,
+        }))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_network_diagram(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(format): Path<String>,
+    Query(grouping): Query<GroupingQuery>,
+) -> impl IntoResponse {
+    // Labels come from the interface metadata, so refresh like the graph endpoint does;
+    // scope and grouping are applied the same way too
+    let exported = current_network_graph(&state, &user.site_scope(), &grouping).await
+        .and_then(|graph| crate::visualizations::export_graph(&graph, &format));
+
+    match exported {
+        Ok(data) => {
+            let content_type = match format.as_str() {
+                "json" => "application/json",
+                "dot" => "text/plain",
+                "svg" => "image/svg+xml",
+                _ => "application/octet-stream",
+            };
+            
+            (
+                StatusCode::OK,
+                [(axum::http::header::CONTENT_TYPE, content_type)],
+                data
+            )
+        },
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            [(axum::http::header::CONTENT_TYPE, "text/plain")],
+            e.into_bytes()
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct FirewallRuleRequest {
+    // One of chain or direction is required; a direction alone picks its base chain
+    #[serde(default)]
+    chain: String,
+    direction: Option<Direction>,
+    service: Option<String>,
+    protocol: Option<String>,
+    port: Option<u16>,
+    source: Option<String>,
+    destination: Option<String>,
+    in_interface: Option<String>,
+    out_interface: Option<String>,
+    action: String,
+    // Last in the default priority band when not given
+    position: Option<RulePosition>,
+}
+
+async fn add_firewall_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(rule): Json<FirewallRuleRequest>,
+) -> impl IntoResponse {
+    let chain = match (rule.chain.as_str(), rule.direction) {
+        ("", Some(direction)) => direction.chain().to_string(),
+        ("", None) => return (StatusCode::BAD_REQUEST, "A chain or direction is required".to_string()).into_response(),
+        (chain, _) => chain.to_string(),
+    };
+
+    let selectors = RuleSelectors {
+        source: rule.source.clone(),
+        destination: rule.destination.clone(),
+        in_interface: rule.in_interface.clone(),
+        out_interface: rule.out_interface.clone(),
+    };
+    if let Err(e) = network::check_selectors(&chain, rule.direction, &selectors) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    // The selector family follows the address, a malformed address would otherwise surface as a 500
+    let addresses: Vec<&str> = rule.source.iter().chain(rule.destination.iter()).map(|a| a.as_str()).collect();
+    for address in &addresses {
+        if let Err(e) = network::address_family(address) {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    }
+    let protocols: Vec<&str> = rule.protocol.iter().map(|p| p.as_str()).collect();
+    if let Err(e) = network::check_rule_family(&addresses, &protocols) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    // A named service replaces the raw protocol/port pair
+    let result = match &rule.service {
+        Some(name) => {
+            if rule.protocol.is_some() || rule.port.is_some() {
+                return (StatusCode::BAD_REQUEST, "Specify either a service or protocol/port, not both".to_string()).into_response();
+            }
+
+            let service = match state.service_registry.get_service(name) {
+                Ok(service) => service,
+                Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            };
+
+            state.network_manager.add_service_rule(
+                &chain,
+                &service,
+                &selectors,
+                &rule.action,
+                rule.position.clone(),
+            ).await
+        },
+        None => {
+            state.network_manager.add_firewall_rule(
+                &chain,
+                rule.protocol.as_deref().unwrap_or("any"),
+                rule.port,
+                &selectors,
+                &rule.action,
+                rule.position.clone(),
+            ).await
+        },
+    };
+
+    match result {
+        Ok(managed) => {
+            record_firewall_activity(&state, managed.handle, &user.username, ActivityKind::Created, serde_json::json!({
+                "chain": managed.chain,
+                "rule": managed.rule,
+                "description": managed.description,
+            }));
+            (StatusCode::CREATED, Json(managed)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add firewall rule: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RuleMoveRequest {
+    position: RulePosition,
+}
+
+// Stages the move as a changeset showing the resulting chain order; it takes effect
+// through POST /api/network/firewall/staged/:id/apply like any other staged change
+async fn stage_firewall_rule_move(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(handle): Path<u32>,
+    Json(request): Json<RuleMoveRequest>,
+) -> impl IntoResponse {
+    match state.network_manager.stage_move(handle, request.position, user.username.clone()).await {
+        Ok(changeset) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "firewall:stage_move",
+                &changeset.id.to_string(),
+                AuditStatus::Success,
+                Some(changeset.description.clone()),
+            );
+            changeset_proposed(&state, &changeset);
+            (StatusCode::ACCEPTED, Json(changeset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to stage rule move: {}", e)).into_response(),
+    }
+}
+
+// Firewall rule history lives in the activity log; failing to record it must not fail the change
+fn record_firewall_activity(state: &AppState, handle: u32, actor: &str, kind: ActivityKind, payload: serde_json::Value) {
+    if let Err(e) = state.activity_log.record(ResourceKind::FirewallRule, &handle.to_string(), actor, kind, payload) {
+        tracing::warn!("Failed to record history of firewall rule {}: {}", handle, e);
+    }
+}
+
+async fn delete_firewall_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(handle): Path<u32>,
+) -> impl IntoResponse {
+    // Final counts go into the history, they are gone with the rule
+    let counters = state.network_manager.get_rule_counter(handle).await.ok();
+
+    match state.network_manager.delete_firewall_rule(handle).await {
+        Ok(_) => {
+            record_firewall_activity(&state, handle, &user.username, ActivityKind::Deleted, serde_json::json!({
+                "counters": counters,
+            }));
+            (StatusCode::OK, "Firewall rule deleted successfully".to_string())
+        },
+        Err(e) => (StatusCode::NOT_FOUND, format!("Failed to delete firewall rule: {}", e)),
+    }
+}
+
+async fn get_firewall_rule_counters(
+    State(state): State<Arc<AppState>>,
+    Path(handle): Path<u32>,
+) -> impl IntoResponse {
+    match state.network_manager.get_rule_counter(handle).await {
+        Ok(counters) => (StatusCode::OK, Json(counters)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn reset_firewall_rule_counters(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(handle): Path<u32>,
+) -> impl IntoResponse {
+    match state.network_manager.reset_rule_counters(handle).await {
+        Ok(before) => {
+            record_firewall_activity(&state, handle, &user.username, ActivityKind::Updated, serde_json::json!({
+                "action": "counters_reset",
+                "counters": before,
+            }));
+            (StatusCode::OK, Json(before)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct UnusedRulesQuery {
+    older_than_days: Option<i64>,
+}
+
+// Rules that have not matched anything since they were added, candidates for pruning
+async fn get_unused_firewall_rules(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UnusedRulesQuery>,
+) -> impl IntoResponse {
+    let days = query.older_than_days.unwrap_or(30).clamp(0, 3650);
+    let cutoff = Utc::now() - chrono::Duration::days(days);
+
+    match state.network_manager.unused_rules(cutoff).await {
+        Ok(rules) => (StatusCode::OK, Json(serde_json::json!({
+            "older_than_days": days,
+            "rules": rules,
+        }))).into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("Firewall counters unavailable: {}", e)).into_response(),
+    }
+}
+
+// Change history of a rule, each entry with the counters captured at that change,
+// plus the current counters while the rule exists
+async fn get_firewall_rule_history(
+    State(state): State<Arc<AppState>>,
+    Path(handle): Path<u32>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    let counters = state.network_manager.get_rule_counter(handle).await.ok();
+
+    match state.activity_log.page(
+        ResourceKind::FirewallRule,
+        &handle.to_string(),
+        true,
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(50).min(500),
+    ) {
+        Ok(page) => (StatusCode::OK, Json(serde_json::json!({
+            "handle": handle,
+            "counters": counters,
+            "history": page,
+        }))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_managed_rules(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::ManagedRuleStatus>> {
+    Json(state.network_manager.get_managed_rules_with_counters().await)
+}
+
+#[derive(Deserialize)]
+struct FirewallTemplateRequest {
+    service: String,
+    from_zone: String,
+    to_zone: String,
+    action: Option<String>,
+}
+
+async fn apply_firewall_template(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<FirewallTemplateRequest>,
+) -> impl IntoResponse {
+    let service = match state.service_registry.get_service(&request.service) {
+        Ok(service) => service,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    match state.network_manager.apply_service_template(
+        &service,
+        &request.from_zone,
+        &request.to_zone,
+        request.action.as_deref().unwrap_or("accept")
+    ).await {
+        Ok(group) => (StatusCode::CREATED, Json(group)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to apply firewall template: {}", e)).into_response(),
+    }
+}
+
+async fn delete_firewall_template(
+    State(state): State<Arc<AppState>>,
+    Path(group): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.network_manager.remove_rule_group(group).await {
+        Ok(removed) => (StatusCode::OK, format!("Removed {} firewall rules", removed)),
+        Err(e) => (StatusCode::NOT_FOUND, format!("Failed to remove firewall template: {}", e)),
+    }
+}
+
+async fn list_zone_forwarding(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::ZoneForwarding>> {
+    Json(state.network_manager.get_zone_forwarding().await)
+}
+
+#[derive(Deserialize)]
+struct ZoneForwardingRequest {
+    from_zone: String,
+    to_zone: String,
+    policy: ForwardPolicy,
+    #[serde(default)]
+    services: Vec<String>,
+    #[serde(default)]
+    masquerade: bool,
+}
+
+async fn set_zone_forwarding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ZoneForwardingRequest>,
+) -> impl IntoResponse {
+    let mut services = Vec::new();
+    for name in &request.services {
+        match state.service_registry.get_service(name) {
+            Ok(service) => services.push(service),
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        }
+    }
+
+    let pair = format!("{}->{}", request.from_zone, request.to_zone);
+
+    match state.network_manager.set_zone_forwarding(
+        &request.from_zone,
+        &request.to_zone,
+        request.policy,
+        services,
+        request.masquerade,
+    ).await {
+        Ok(entry) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "set_zone_forwarding",
+                &pair,
+                AuditStatus::Success,
+                Some(format!("{:?} services {:?} masquerade {}", request.policy, request.services, request.masquerade)),
+            );
+            (StatusCode::OK, Json(entry)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set zone forwarding: {}", e)).into_response(),
+    }
+}
+
+async fn apply_lan_wan_forwarding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    match state.network_manager.apply_lan_wan_preset().await {
+        Ok(entry) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "set_zone_forwarding",
+                "lan->wan",
+                AuditStatus::Success,
+                Some("Preset: accept with masquerade".to_string()),
+            );
+            (StatusCode::OK, Json(entry)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to apply forwarding preset: {}", e)).into_response(),
+    }
+}
+
+async fn delete_zone_forwarding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.network_manager.delete_zone_forwarding(id).await {
+        Ok(()) => {
+            state.security_manager.log_audit_event(&user.username, "delete_zone_forwarding", &id.to_string(), AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, format!("Failed to delete zone forwarding: {}", e)).into_response(),
+    }
+}
+
+async fn list_zone_egress(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::ZoneEgress>> {
+    Json(state.network_manager.get_zone_egress().await)
+}
+
+#[derive(Deserialize)]
+struct ZoneEgressRequest {
+    zone: String,
+    policy: ForwardPolicy,
+    // Exceptions to the policy
+    #[serde(default)]
+    services: Vec<String>,
+}
+
+async fn set_zone_egress(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ZoneEgressRequest>,
+) -> impl IntoResponse {
+    let mut services = Vec::new();
+    for name in &request.services {
+        match state.service_registry.get_service(name) {
+            Ok(service) => services.push(service),
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        }
+    }
+
+    match state.network_manager.set_zone_egress(&request.zone, request.policy, services).await {
+        Ok(entry) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "set_zone_egress",
+                &request.zone,
+                AuditStatus::Success,
+                Some(format!("{:?} except services {:?}", request.policy, request.services)),
+            );
+            (StatusCode::OK, Json(entry)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set zone egress policy: {}", e)).into_response(),
+    }
+}
+
+async fn delete_zone_egress(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.network_manager.delete_zone_egress(id).await {
+        Ok(()) => {
+            state.security_manager.log_audit_event(&user.username, "delete_zone_egress", &id.to_string(), AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, format!("Failed to delete zone egress policy: {}", e)).into_response(),
+    }
+}
+
+async fn list_zone_services(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::ZoneServices>> {
+    Json(state.network_manager.get_zone_services().await)
+}
+
+// Zone service rules are added and removed like any other firewall rule, so their
+// history is recorded the same way
+fn record_service_rule_changes(state: &AppState, changes: &ServiceRuleChanges, actor: &str) {
+    for rule in &changes.removed {
+        record_firewall_activity(state, rule.handle, actor, ActivityKind::Deleted, serde_json::json!({
+            "chain": rule.chain,
+            "rule": rule.rule,
+            "description": rule.description,
+        }));
+    }
+    for rule in &changes.added {
+        record_firewall_activity(state, rule.handle, actor, ActivityKind::Created, serde_json::json!({
+            "chain": rule.chain,
+            "rule": rule.rule,
+            "description": rule.description,
+        }));
+    }
+}
+
+fn zone_services_response(state: &AppState,
+                          user: &AuthUser,
+                          action: &str,
+                          zone: &str,
+                          result: anyhow::Result<(crate::network::ZoneServices, ServiceRuleChanges)>) -> Response {
+    match result {
+        Ok((entry, changes)) => {
+            record_service_rule_changes(state, &changes, &user.username);
+            state.security_manager.log_audit_event(
+                &user.username,
+                action,
+                zone,
+                AuditStatus::Success,
+                Some(format!("services {:?}, {} rules added, {} removed", entry.services, changes.added.len(), changes.removed.len())),
+            );
+            (StatusCode::OK, Json(serde_json::json!({
+                "zone": entry,
+                "rules_added": changes.added,
+                "rules_removed": changes.removed,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to change zone services: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ZoneServicesRequest {
+    services: Vec<SelfService>,
+}
+
+async fn set_zone_services(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(zone): Path<String>,
+    Json(request): Json<ZoneServicesRequest>,
+) -> impl IntoResponse {
+    let result = state.network_manager.set_zone_services(&zone, request.services).await;
+    zone_services_response(&state, &user, "set_zone_services", &zone, result)
+}
+
+async fn reset_zone_services(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(zone): Path<String>,
+) -> impl IntoResponse {
+    let result = state.network_manager.reset_zone_services(&zone).await;
+    zone_services_response(&state, &user, "reset_zone_services", &zone, result)
+}
+
+// Opens a service of this host to the zone, e.g. dhcp when the zone gets a DHCP server
+async fn enable_zone_service(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((zone, service)): Path<(String, SelfService)>,
+) -> impl IntoResponse {
+    let result = state.network_manager.set_zone_service(&zone, service, true).await;
+    zone_services_response(&state, &user, "enable_zone_service", &zone, result)
+}
+
+async fn disable_zone_service(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((zone, service)): Path<(String, SelfService)>,
+) -> impl IntoResponse {
+    let result = state.network_manager.set_zone_service(&zone, service, false).await;
+    zone_services_response(&state, &user, "disable_zone_service", &zone, result)
+}
+
+async fn list_zone_drop_logging(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::ZoneDropLogging>> {
+    Json(state.network_manager.get_zone_drop_logging().await)
+}
+
+#[derive(Deserialize)]
+struct ZoneDropLoggingRequest {
+    // Both input and forward when not given
+    chains: Option<Vec<String>>,
+    // drop_log.default_rate_per_second when not given
+    rate_per_second: Option<u32>,
+}
+
+// Logs the zone's dropped packets; the lines come back as NetworkTraffic log entries
+async fn set_zone_drop_logging(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(zone): Path<String>,
+    Json(request): Json<ZoneDropLoggingRequest>,
+) -> impl IntoResponse {
+    let chains = request.chains
+        .unwrap_or_else(|| crate::network::DROP_LOG_CHAINS.iter().map(|c| c.to_string()).collect());
+    let rate = request.rate_per_second.unwrap_or(state.config.drop_log.default_rate_per_second);
+
+    match state.network_manager.set_zone_drop_logging(&zone, chains, rate).await {
+        Ok((entry, changes)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "set_zone_drop_logging",
+                &zone,
+                AuditStatus::Success,
+                Some(format!("chains {:?} at {}/s", entry.chains, entry.rate_per_second)),
+            );
+            (StatusCode::OK, Json(serde_json::json!({
+                "logging": entry,
+                "changes": changes,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set drop logging: {}", e)).into_response(),
+    }
+}
+
+async fn disable_zone_drop_logging(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(zone): Path<String>,
+) -> impl IntoResponse {
+    match state.network_manager.disable_zone_drop_logging(&zone).await {
+        Ok(changes) => {
+            state.security_manager.log_audit_event(&user.username, "disable_zone_drop_logging", &zone, AuditStatus::Success, None);
+            (StatusCode::OK, Json(changes)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn get_threat_intel(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<String>> {
+    Json(state.network_manager.get_threat_intel().await)
+}
+
+#[derive(Deserialize)]
+struct ThreatIntelRequest {
+    addresses: Vec<String>,
+}
+
+// Replaces the known-bad addresses the threat intel preset drops traffic to
+async fn set_threat_intel(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ThreatIntelRequest>,
+) -> impl IntoResponse {
+    match state.network_manager.set_threat_intel(request.addresses).await {
+        Ok(count) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "set_threat_intel",
+                "threat_intel",
+                AuditStatus::Success,
+                Some(format!("{} addresses", count)),
+            );
+            (StatusCode::OK, Json(serde_json::json!({ "addresses": count }))).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set threat intel addresses: {}", e)).into_response(),
+    }
+}
+
+async fn apply_threat_intel_preset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    match state.network_manager.apply_threat_intel_preset().await {
+        Ok(group) => {
+            for rule in &group.rules {
+                record_firewall_activity(&state, rule.handle, &user.username, ActivityKind::Created, serde_json::json!({
+                    "chain": rule.chain,
+                    "rule": rule.rule,
+                    "description": rule.description,
+                }));
+            }
+            state.security_manager.log_audit_event(
+                &user.username,
+                "apply_firewall_preset",
+                "threat-intel-egress",
+                AuditStatus::Success,
+                Some(format!("group {}", group.id)),
+            );
+            (StatusCode::CREATED, Json(group)).into_response()
+        },
+        Err(e) => (StatusCode::CONFLICT, format!("Failed to apply threat intel preset: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct FirewallImportRequest {
+    format: ImportFormat,
+    content: String,
+    description: Option<String>,
+    #[serde(default = "default_dry_run")]
+    dry_run: bool,
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+struct ImportedRule {
+    line_number: usize,
+    spec: RuleSpec,
+    rendered: String,
+}
+
+#[derive(Serialize)]
+struct FirewallImportResponse {
+    dry_run: bool,
+    changeset_id: Option<Uuid>,
+    rules: Vec<ImportedRule>,
+    unconverted: Vec<UnconvertedLine>,
+}
+
+async fn import_firewall_rules(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<FirewallImportRequest>,
+) -> impl IntoResponse {
+    let parsed = firewall_import::parse(request.format, &request.content);
+    let mut unconverted = parsed.unconverted;
+    let mut rules = Vec::new();
+
+    // Rendering validates the spec, so the preview is exactly what would be applied
+    for (line_number, spec) in parsed.rules {
+        match spec.render() {
+            Ok(rendered) => rules.push(ImportedRule { line_number, spec, rendered }),
+            Err(e) => unconverted.push(UnconvertedLine {
+                line_number,
+                line: request.content.lines().nth(line_number - 1).unwrap_or("").trim().to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+    unconverted.sort_by_key(|u| u.line_number);
+
+    let mut changeset_id = None;
+
+    if !request.dry_run && !rules.is_empty() {
+        let description = request.description
+            .unwrap_or_else(|| format!("Imported {} rules", rules.len()));
+        let specs = rules.iter().map(|r| r.spec.clone()).collect();
+
+        match state.network_manager.stage_rules(description, user.username.clone(), specs).await {
+            Ok(changeset) => {
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "firewall:import",
+                    &changeset.id.to_string(),
+                    AuditStatus::Success,
+                    Some(format!("{} rules staged, {} lines not converted", rules.len(), unconverted.len())),
+                );
+                changeset_proposed(&state, &changeset);
+                changeset_id = Some(changeset.id);
+            },
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to stage imported rules: {}", e)).into_response(),
+        }
+    }
+
+    (StatusCode::OK, Json(FirewallImportResponse {
+        dry_run: request.dry_run,
+        changeset_id,
+        rules,
+        unconverted,
+    })).into_response()
+}
+
+// The change request chain goes into the changeset's own feed, which outlives it;
+// failing to record it must not fail the change
+fn record_changeset_activity(state: &AppState, id: Uuid, actor: &str, kind: ActivityKind, payload: serde_json::Value) {
+    if let Err(e) = state.activity_log.record(ResourceKind::FirewallChangeset, &id.to_string(), actor, kind, payload) {
+        tracing::warn!("Failed to record history of firewall changeset {}: {}", id, e);
+    }
+}
+
+// Records a new change request and tells the appliers about it
+fn changeset_proposed(state: &Arc<AppState>, changeset: &StagedChangeset) {
+    record_changeset_activity(state, changeset.id, &changeset.created_by, ActivityKind::Created, serde_json::json!({
+        "description": changeset.description,
+        "rules": changeset.rules.len(),
+        "moves": changeset.moves.len(),
+        "restore": changeset.restore.is_some(),
+    }));
+
+    let approvals = state.firewall_approvals.clone();
+    let changeset = changeset.clone();
+    request_trace::spawn(async move {
+        if let Err(e) = approvals.proposed(&changeset).await {
+            warn!("Failed to notify appliers of firewall change request {}: {}", changeset.id, e);
+        }
+    });
+}
+
+fn changeset_decided(state: &Arc<AppState>, changeset: StagedChangeset, decision: ChangeDecision, decided_by: &str, reason: Option<String>) {
+    record_changeset_activity(state, changeset.id, decided_by, ActivityKind::StatusChanged, serde_json::json!({
+        "decision": match decision {
+            ChangeDecision::Applied => "applied",
+            ChangeDecision::Rejected => "rejected",
+        },
+        "proposed_by": changeset.created_by,
+        "reason": reason,
+    }));
+
+    let approvals = state.firewall_approvals.clone();
+    let decided_by = decided_by.to_string();
+    request_trace::spawn(async move {
+        if let Err(e) = approvals.decided(&changeset, decision, &decided_by, reason.as_deref()).await {
+            warn!("Failed to notify the proposer of firewall change request {}: {}", changeset.id, e);
+        }
+    });
+}
+
+async fn list_staged_changesets(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::network::StagedChangeset>> {
+    Json(state.network_manager.get_staged_changesets().await)
+}
+
+#[derive(Deserialize)]
+struct StageFirewallChangesRequest {
+    description: String,
+    rules: Vec<RuleSpec>,
+}
+
+// Proposes rules as a change request; they reach the ruleset when someone with
+// network:apply applies it
+async fn stage_firewall_changes(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<StageFirewallChangesRequest>,
+) -> impl IntoResponse {
+    let description = request.description.trim().to_string();
+    if description.is_empty() {
+        return (StatusCode::BAD_REQUEST, "A description is required".to_string()).into_response();
+    }
+
+    match state.network_manager.stage_rules(description, user.username.clone(), request.rules).await {
+        Ok(changeset) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "firewall:propose",
+                &changeset.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{} rules staged", changeset.rules.len())),
+            );
+            changeset_proposed(&state, &changeset);
+            (StatusCode::ACCEPTED, Json(changeset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to stage firewall changes: {}", e)).into_response(),
+    }
+}
+
+// 409 when the changeset no longer fits the rules, e.g. a rule it moves is gone
+async fn get_staged_changeset_diff(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(e) = state.network_manager.get_staged_changeset(id).await {
+        return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+    }
+
+    match state.network_manager.changeset_diff(id).await {
+        Ok(diff) => (StatusCode::OK, Json(diff)).into_response(),
+        Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangesetCommentRequest {
+    text: String,
+}
+
+async fn comment_staged_changeset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ChangesetCommentRequest>,
+) -> impl IntoResponse {
+    let text = request.text.trim();
+    if text.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Comment is empty".to_string()).into_response();
+    }
+
+    match state.network_manager.comment_changeset(id, &user.username, text).await {
+        Ok(changeset) => {
+            record_changeset_activity(&state, id, &user.username, ActivityKind::CommentAdded, serde_json::json!({
+                "text": text,
+            }));
+            (StatusCode::OK, Json(changeset)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Proposal, comments and decision of a change request, also once it is applied or rejected
+async fn get_staged_changeset_history(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    match state.activity_log.page(
+        ResourceKind::FirewallChangeset,
+        &id.to_string(),
+        true,
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(50).min(500),
+    ) {
+        Ok(page) if page.total == 0 => (StatusCode::NOT_FOUND, format!("No history for changeset {}", id)).into_response(),
+        Ok(page) => (StatusCode::OK, Json(page)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_staged_changeset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.network_manager.get_staged_changeset(id).await {
+        Ok(changeset) => (StatusCode::OK, Json(changeset)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Approves and applies a change request. Proposers cannot apply their own unless
+// firewall.allow_self_approval is set; the model staged on promotion is nobody's proposal.
+async fn apply_staged_changeset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let changeset = match state.network_manager.get_staged_changeset(id).await {
+        Ok(changeset) => changeset,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    let promoted = changeset.restore.as_ref().map_or(false, |r| r.backup_id.is_none());
+    if changeset.created_by == user.username && !promoted && !state.config.firewall.allow_self_approval {
+        state.security_manager.log_audit_event(
+            &user.username,
+            "firewall:apply",
+            &id.to_string(),
+            AuditStatus::Failure,
+            Some("Own change request, self-approval is not allowed".to_string()),
+        );
+        return (StatusCode::FORBIDDEN, "Your own change request has to be applied by someone else".to_string()).into_response();
+    }
+
+    match state.network_manager.apply_changeset(id).await {
+        Ok(group) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "firewall:apply",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{} rules applied", group.rules.len())),
+            );
+            for rule in &group.rules {
+                let (kind, change) = if rule.group == Some(id) {
+                    (ActivityKind::Created, serde_json::json!({
+                        "chain": rule.chain,
+                        "rule": rule.rule,
+                        "description": rule.description,
+                    }))
+                } else {
+                    (ActivityKind::Updated, serde_json::json!({
+                        "chain": rule.chain,
+                        "priority": rule.priority,
+                    }))
+                };
+                let mut payload = change;
+                payload["changeset"] = serde_json::json!(id);
+                payload["proposed_by"] = serde_json::json!(changeset.created_by);
+                payload["approved_by"] = serde_json::json!(user.username);
+                record_firewall_activity(&state, rule.handle, &user.username, kind, payload);
+            }
+            changeset_decided(&state, changeset, ChangeDecision::Applied, &user.username, None);
+            (StatusCode::OK, Json(group)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to apply changeset: {}", e)).into_response(),
+    }
+}
+
+async fn list_firewall_backups(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.firewall_backups.list() {
+        Ok(backups) => (StatusCode::OK, Json(backups)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Restores go through the staged changesets like any other firewall change, applied
+// with POST /api/network/firewall/staged/:id/apply
+async fn stage_firewall_restore(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let (created_at, model) = match state.firewall_backups.model(id) {
+        Ok(backup) => backup,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+
+    match state.network_manager.stage_restore(id, created_at, model, user.username.clone()).await {
+        Ok(changeset) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "firewall:stage_restore",
+                &changeset.id.to_string(),
+                AuditStatus::Success,
+                Some(changeset.description.clone()),
+            );
+            changeset_proposed(&state, &changeset);
+            (StatusCode::ACCEPTED, Json(changeset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to stage restore: {}", e)).into_response(),
+    }
+}
+
+// Withdraws a change request; only its proposer or someone with network:apply may
+async fn discard_staged_changeset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let changeset = match state.network_manager.get_staged_changeset(id).await {
+        Ok(changeset) => changeset,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    if changeset.created_by != user.username
+        && !state.access_control.check_permission(&user.role.role_name(), APPLY_PERMISSION) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.network_manager.discard_changeset(id).await {
+        Ok(_) => {
+            record_changeset_activity(&state, id, &user.username, ActivityKind::StatusChanged, serde_json::json!({
+                "decision": "withdrawn",
+                "proposed_by": changeset.created_by,
+            }));
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RejectChangesetRequest {
+    reason: String,
+}
+
+async fn reject_staged_changeset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RejectChangesetRequest>,
+) -> impl IntoResponse {
+    let reason = request.reason.trim().to_string();
+    if reason.is_empty() {
+        return (StatusCode::BAD_REQUEST, "A reason is required".to_string()).into_response();
+    }
+
+    match state.network_manager.discard_changeset(id).await {
+        Ok(changeset) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "firewall:reject",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(reason.clone()),
+            );
+            changeset_decided(&state, changeset.clone(), ChangeDecision::Rejected, &user.username, Some(reason));
+            (StatusCode::OK, Json(changeset)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceRequest {
+    name: Option<String>,
+    protocol: ServiceProtocol,
+    ports: Vec<u16>,
+    #[serde(default)]
+    description: String,
+}
+
+async fn list_services(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.service_registry.get_all_services() {
+        Ok(services) => (StatusCode::OK, Json(services)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_service(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.service_registry.get_service(&name) {
+        Ok(service) => (StatusCode::OK, Json(service)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn create_service(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ServiceRequest>,
+) -> impl IntoResponse {
+    let name = match request.name {
+        Some(name) => name,
+        None => return (StatusCode::BAD_REQUEST, "Service name is required".to_string()).into_response(),
+    };
+
+    if state.service_registry.get_service(&name).is_ok() {
+        return (StatusCode::CONFLICT, format!("Service already exists: {}", name)).into_response();
+    }
+
+    match state.service_registry.upsert_service(&name, request.protocol, request.ports, request.description) {
+        Ok(service) => (StatusCode::CREATED, Json(service)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn update_service(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<ServiceRequest>,
+) -> impl IntoResponse {
+    match state.service_registry.upsert_service(&name, request.protocol, request.ports, request.description) {
+        Ok(service) => (StatusCode::OK, Json(service)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_service(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.service_registry.delete_service(&name) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// Scripts API handlers
+#[derive(Deserialize)]
+struct CreateScriptRequest {
+    name: String,
+    #[serde(default)]
+    description: String,
+    content: String,
+    category: ScriptCategory,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    output_format: ScriptOutputFormat,
+    #[serde(default)]
+    parameters: Vec<ScriptParameter>,
+    #[serde(default)]
+    dependencies: ScriptDependencies,
+}
+
+#[derive(Deserialize)]
+struct UpdateScriptRequest {
+    name: Option<String>,
+    description: Option<String>,
+    content: Option<String>,
+    category: Option<ScriptCategory>,
+    tags: Option<Vec<String>>,
+    output_format: Option<ScriptOutputFormat>,
+    parameters: Option<Vec<ScriptParameter>>,
+    dependencies: Option<ScriptDependencies>,
+}
+
+#[derive(Deserialize, Default)]
+struct ExecuteScriptRequest {
+    #[serde(default)]
+    arguments: HashMap<String, String>,
+}
+
+fn builtin_forbidden(id: Uuid) -> Response {
+    (StatusCode::FORBIDDEN, format!("Built-in script {} is read-only, copy it to make changes", id)).into_response()
+}
+
+async fn list_scripts(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => (StatusCode::OK, Redacted(manager.get_all_scripts(), user)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => match manager.get_script(id) {
+            Some(script) => (StatusCode::OK, Redacted(script, user)).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn create_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<CreateScriptRequest>,
+) -> impl IntoResponse {
+    let mut manager = match state.scripts_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match manager.create_script(
+        request.name,
+        request.description,
+        request.content,
+        user.username.clone(),
+        request.category,
+        request.tags,
+        request.output_format,
+        request.parameters,
+        request.dependencies,
+    ) {
+        Ok(id) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:create",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            let script = manager.get_script(id);
+            if let Some(script) = script.clone() {
+                notify_review_requested(&state, script);
+            }
+            (StatusCode::CREATED, Redacted(script, user)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn update_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<UpdateScriptRequest>,
+) -> impl IntoResponse {
+    let mut manager = match state.scripts_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if manager.is_builtin(id) {
+        return builtin_forbidden(id);
+    }
+
+    let previous_request = manager.get_script(id).and_then(|s| s.review.requested_at);
+
+    match manager.update_script(
+        id,
+        request.name,
+        request.description,
+        request.content,
+        request.category,
+        request.tags,
+        request.output_format,
+        request.parameters,
+        request.dependencies,
+        &user.username,
+    ) {
+        Ok(script) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:update",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            // Only an edit that (re)started the review notifies the approvers
+            if script.review.status == ReviewStatus::ReviewRequested && script.review.requested_at != previous_request {
+                notify_review_requested(&state, script.clone());
+            }
+            (StatusCode::OK, Redacted(script, user)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let mut manager = match state.scripts_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if manager.is_builtin(id) {
+        return builtin_forbidden(id);
+    }
+
+    match manager.delete_script(id) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Notifications go out in the background, SMTP and webhooks must not hold up the request
+fn notify_review_requested(state: &Arc<AppState>, script: Script) {
+    let approvals = state.script_approvals.clone();
+    request_trace::spawn(async move {
+        if let Err(e) = approvals.review_requested(&script).await {
+            warn!("Failed to notify approvers of script {}: {}", script.id, e);
+        }
+    });
+}
+
+fn notify_review_decided(state: &Arc<AppState>, script: Script) {
+    let approvals = state.script_approvals.clone();
+    request_trace::spawn(async move {
+        if let Err(e) = approvals.decided(&script).await {
+            warn!("Failed to notify the author of script {}: {}", script.id, e);
+        }
+    });
+}
+
+async fn list_pending_approvals(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => (StatusCode::OK, Json(manager.pending_approvals(Utc::now()))).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn approve_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(mut manager) => {
+            if manager.is_builtin(id) {
+                return builtin_forbidden(id);
+            }
+            if let Some(blocker) = manager.approval_blocker(id) {
+                return (StatusCode::CONFLICT, blocker).into_response();
+            }
+            if manager.get_script(id).is_some_and(|s| s.review.status != ReviewStatus::ReviewRequested) {
+                return (StatusCode::CONFLICT, format!("Script {} is not awaiting review", id)).into_response();
+            }
+            if manager.is_author(id, &user.username) && !state.config.script_approval.allow_self_approval {
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "script:approve",
+                    &id.to_string(),
+                    AuditStatus::Failure,
+                    Some("Own script, self-approval is not allowed".to_string()),
+                );
+                return (StatusCode::FORBIDDEN, "Your own script has to be approved by someone else".to_string()).into_response();
+            }
+            manager.approve_script(id, user.username.clone())
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match result {
+        Ok(script) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:approve",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            notify_review_decided(&state, script.clone());
+            (StatusCode::OK, Redacted(script, user)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AcknowledgeLintRequest {
+    reason: String,
+}
+
+async fn acknowledge_script_lint(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<AcknowledgeLintRequest>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(mut manager) => {
+            if manager.is_builtin(id) {
+                return builtin_forbidden(id);
+            }
+            manager.acknowledge_lint(id, &user.username, &request.reason)
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match result {
+        Ok(script) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:lint_acknowledge",
+                &id.to_string(),
+                AuditStatus::Success,
+                script.lint.acknowledgement.as_ref().map(|a| a.reason.clone()),
+            );
+            (StatusCode::OK, Redacted(script, user)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RejectScriptRequest {
+    reason: String,
+}
+
+async fn reject_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RejectScriptRequest>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(mut manager) => {
+            if manager.is_builtin(id) {
+                return builtin_forbidden(id);
+            }
+            manager.reject_script(id, &user.username, &request.reason)
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match result {
+        Ok(script) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:reject",
+                &id.to_string(),
+                AuditStatus::Success,
+                script.review.reason.clone(),
+            );
+            notify_review_decided(&state, script.clone());
+            (StatusCode::OK, Redacted(script, user)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// "Copy to editable": a user script starting from the content of any script
+async fn copy_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let mut manager = match state.scripts_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match manager.copy_script(id, user.username.clone()) {
+        Ok(copy) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:copy",
+                &copy.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("copied from {}", id)),
+            );
+            notify_review_requested(&state, copy.clone());
+            (StatusCode::CREATED, Json(copy)).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn execute_script(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    request: Option<Json<ExecuteScriptRequest>>,
+) -> impl IntoResponse {
+    let arguments = request.map(|Json(r)| r.arguments).unwrap_or_default();
+    let scripts = state.scripts_manager.clone();
+    let executed_by = user.username.clone();
+
+    // Script execution blocks, keep it off the runtime threads
+    let result = request_trace::spawn_blocking(move || {
+        let mut manager = scripts.lock()
+            .map_err(|_| anyhow::anyhow!("Failed to acquire lock on scripts manager"))?;
+        manager.execute_script_with_arguments(id, executed_by, &arguments)
+    }).await;
+
+    match result {
+        Ok(Ok(result)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "script:execute",
+                &id.to_string(),
+                if result.success { AuditStatus::Success } else { AuditStatus::Failure },
+                Some(format!("execution {}", result.id)),
+            );
+            (StatusCode::OK, Json(result)).into_response()
+        },
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct PreflightRequest {
+    // Asset to check over SSH; without one the local machine is checked
+    asset_id: Option<Uuid>,
+}
+
+// Checks the script's dependencies against a target without executing it
+async fn preflight_script(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+    request: Option<Json<PreflightRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    match state.fleet_runner.preflight(id, request.asset_id).await {
+        Ok(result) => (StatusCode::OK, Json(result)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExecuteBulkRequest {
+    filter: AssetFilter,
+    #[serde(default)]
+    arguments: HashMap<String, String>,
+    concurrency: Option<usize>,
+}
+
+// Starts the script on every matching asset in the caller's sites and returns the batch
+// right away
+async fn execute_script_bulk(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ExecuteBulkRequest>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, None) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    for asset_id in &request.filter.ids {
+        if let Err(response) = visible_asset(&state, &user, *asset_id) {
+            return response;
+        }
+    }
+
+    match state.fleet_runner.start_batch(id, request.filter, request.arguments, request.concurrency, &user.username, &scope) {
+        Ok(batch) => (StatusCode::ACCEPTED, Json(batch)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchListParams {
+    script_id: Option<Uuid>,
+}
+
+// Batches with targets in the caller's sites, showing only those targets
+async fn list_script_batches(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<BatchListParams>,
+) -> impl IntoResponse {
+    match state.fleet_runner.get_batches(params.script_id, &user.site_scope()) {
+        Ok(batches) => (StatusCode::OK, Json(batches)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_script_batch(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.fleet_runner.get_batch(id, &user.site_scope()) {
+        Ok(batch) => (StatusCode::OK, Json(batch)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn get_script_batch_output(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let scope = user.site_scope();
+    if let Err(e) = state.fleet_runner.get_batch(id, &scope) {
+        return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+    }
+
+    match state.fleet_runner.combined_output(id, &scope) {
+        Ok(records) => (StatusCode::OK, Json(records)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn cancel_script_batch(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Cancelling stops every target, so all of them must be in the caller's sites
+    let scope = user.site_scope();
+    match state.fleet_runner.get_batch(id, &SiteScope::All) {
+        Ok(batch) if batch.targets.iter().all(|target| scope.allows(target.site_id)) => {},
+        _ => return (StatusCode::NOT_FOUND, format!("Batch not found: {}", id)).into_response(),
+    }
+
+    match state.fleet_runner.cancel_batch(id, &user.username) {
+        Ok(batch) => (StatusCode::OK, Json(batch)).into_response(),
+        Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct StructuredOutputParams {
+    field: Option<String>,
+    equals: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn get_structured_output(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<StructuredOutputParams>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(manager) => match manager.get_execution_result(id) {
+            Some(result) => result,
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let records: Vec<serde_json::Value> = result.structured_output.into_iter()
+        .filter(|record| match &params.field {
+            Some(field) => match record.get(field) {
+                Some(value) => match &params.equals {
+                    Some(expected) => match value {
+                        serde_json::Value::String(s) => s == expected,
+                        other => other.to_string() == *expected,
+                    },
+                    None => true,
+                },
+                None => false,
+            },
+            None => true,
+        })
+        .take(params.limit.unwrap_or(usize::MAX))
+        .collect();
+
+    (StatusCode::OK, Json(serde_json::json!({
+        "execution_id": result.id,
+        "script_id": result.script_id,
+        "executed_at": result.executed_at,
+        "records": records,
+        "parse_errors": result.parse_errors,
+    }))).into_response()
+}
+
+#[derive(Deserialize)]
+struct ExecutionDiffParams {
+    base: Uuid,
+    compare: Uuid,
+    // Record key for JSON Lines output
+    key: Option<String>,
+}
+
+async fn diff_script_executions(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ExecutionDiffParams>,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => match manager.diff_executions(id, params.base, params.compare, params.key.as_deref()) {
+            Ok(diff) => (StatusCode::OK, Json(diff)).into_response(),
+            Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        },
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScriptScheduleRequest {
+    interval_minutes: u64,
+    #[serde(default)]
+    alert_on_change: bool,
+    diff_key: Option<String>,
+}
+
+async fn list_script_schedules(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => (StatusCode::OK, Json(manager.get_schedules(id))).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn create_script_schedule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ScriptScheduleRequest>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(mut manager) => manager.create_schedule(
+            id,
+            request.interval_minutes,
+            request.alert_on_change,
+            request.diff_key,
+            user.username.clone(),
+        ),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match result {
+        Ok(schedule) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "create_script_schedule",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(format!("every {} minutes", schedule.interval_minutes)),
+            );
+            (StatusCode::CREATED, Json(schedule)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_script_schedule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, schedule_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let result = match state.scripts_manager.lock() {
+        Ok(mut manager) => match manager.get_schedule(schedule_id) {
+            Some(schedule) if schedule.script_id == id => manager.delete_schedule(schedule_id),
+            _ => return StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match result {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "delete_script_schedule",
+                &schedule_id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn list_schedule_runs(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path((id, schedule_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    match state.scripts_manager.lock() {
+        Ok(manager) => match manager.get_schedule(schedule_id) {
+            Some(schedule) if schedule.script_id == id => {
+                (StatusCode::OK, Json(manager.get_schedule_runs(schedule_id))).into_response()
+            },
+            _ => StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+// Tickets API handlers - placeholder implementations
+#[derive(Serialize, Deserialize)]
+struct Ticket {
+    id: String,
+    title: String,
+    description: String,
+    status: String,
+    priority: String,
+    created_at: String,
+    updated_at: String,
+    assignee: Option<String>,
+}
+
+// Newest first. Like get_ticket, non-staff only see their own tickets and staff
+// restricted to sites the tickets of those sites.
+async fn list_tickets(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SiteQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => {
+            let mut visible: Vec<_> = tickets.into_iter()
+                .filter(|t| (t.created_by == user.username && (query.site.is_none() || t.site_id == query.site))
+                    || (user.is_staff() && scope.allows(t.site_id)))
+                .collect();
+            visible.sort_by_key(|t| std::cmp::Reverse(t.created_at));
+            (StatusCode::OK, Redacted(visible, user)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_ticket(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Non-staff only see their own tickets; staff restricted to sites see the tickets of
+    // those sites
+    match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) if ticket.created_by == user.username
+            || (user.is_staff() && user.site_scope().allows(ticket.site_id)) => {
+            (StatusCode::OK, Redacted(ticket, user)).into_response()
+        },
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Where the ticket stands against its resolution target, see sla
+async fn get_ticket_sla(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let ticket = match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) if ticket.created_by == user.username
+            || (user.is_staff() && user.site_scope().allows(ticket.site_id)) => ticket,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match state.sla.status(&ticket, Utc::now()) {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct BoardMoveRequest {
+    // Column to move into, the current one when absent
+    status: Option<TicketStatus>,
+    after: Option<Uuid>,
+    before: Option<Uuid>,
+}
+
+// Places the ticket between its new neighbours on the board; moving it to another column
+// changes its status. 409 when the neighbours are not where the caller saw them.
+async fn move_ticket_on_board(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<BoardMoveRequest>,
+) -> impl IntoResponse {
+    let previous = match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) if user.is_staff() && user.site_scope().allows(ticket.site_id) => ticket.status,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match state.tickets_manager.move_on_board(id, request.status, request.after, request.before, &user.username) {
+        Ok(moved) => {
+            if moved.status != previous {
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "ticket:board_move",
+                    &id.to_string(),
+                    AuditStatus::Success,
+                    Some(format!("{:?} to {:?}", previous, moved.status)),
+                );
+            }
+            (StatusCode::OK, Json(moved)).into_response()
+        },
+        Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+    }
+}
+
+// The caller's board as laid out by their saved preferences, limited to their sites
+async fn get_ticket_board(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let preferences = match state.ticket_board.get(&user.username) {
+        Ok(preferences) => preferences,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let scope = user.site_scope();
+    match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => {
+            let tickets = tickets.into_iter().filter(|t| scope.allows(t.site_id)).collect();
+            (StatusCode::OK, Json(ticket_board::build(tickets, &preferences))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_board_preferences(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    match state.ticket_board.get(&user.username) {
+        Ok(preferences) => (StatusCode::OK, Json(preferences)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn set_board_preferences(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<BoardPreferences>,
+) -> impl IntoResponse {
+    match state.ticket_board.set(&user.username, request) {
+        Ok(preferences) => (StatusCode::OK, Json(preferences)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn create_ticket(
+    State(_state): State<Arc<AppState>>,
+    Json(_ticket): Json<Ticket>,
+) -> impl IntoResponse {
+    // Placeholder implementation
+    StatusCode::NOT_IMPLEMENTED
 }
 
 async fn update_ticket(
@@ -324,6 +5313,3499 @@ async fn update_ticket(
     Path(_id): Path<String>,
     Json(_ticket): Json<Ticket>,
 ) -> impl IntoResponse {
-    // Placeholder implementation
-    StatusCode::NOT_IMPLEMENTED
-}
\ No newline at end of file
+    // Placeholder implementation
+    StatusCode::NOT_IMPLEMENTED
+}
+
+#[derive(Deserialize)]
+struct TicketImportRequest {
+    content: String,
+    // Without a mapping only the detected columns and a proposed mapping are returned
+    mapping: Option<ColumnMapping>,
+    #[serde(default = "default_dry_run")]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct TicketImportPreview {
+    columns: Vec<String>,
+    proposed_mapping: ColumnMapping,
+    rows: usize,
+}
+
+#[derive(Serialize)]
+struct TicketImportReport {
+    dry_run: bool,
+    imported: usize,
+    failed: usize,
+    rows: Vec<ImportRowResult>,
+}
+
+// Bulk import from another helpdesk's CSV export. A bad record is reported and skipped,
+// the rest of the file is still imported.
+async fn import_tickets(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<TicketImportRequest>,
+) -> impl IntoResponse {
+    let table = match ticket_import::parse_csv(&request.content) {
+        Ok(table) => table,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let mapping = match request.mapping {
+        Some(mapping) => mapping,
+        None => return (StatusCode::OK, Json(TicketImportPreview {
+            proposed_mapping: ticket_import::propose_mapping(&table.columns),
+            columns: table.columns,
+            rows: table.rows.len(),
+        })).into_response(),
+    };
+
+    if let Err(e) = ticket_import::validate_mapping(&table.columns, &mapping) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let mut rows = Vec::new();
+    for (line_number, values) in &table.rows {
+        let result = ticket_import::ticket_from_row(&table.columns, &mapping, values, &user.username)
+            .and_then(|ticket| if request.dry_run {
+                Ok(None)
+            } else {
+                state.tickets_manager.import_ticket(ticket, &user.username)
+                    .map(Some)
+                    .map_err(|e| vec![e.to_string()])
+            });
+
+        rows.push(match result {
+            Ok(ticket_id) => ImportRowResult { line_number: *line_number, ticket_id, errors: Vec::new() },
+            Err(errors) => ImportRowResult { line_number: *line_number, ticket_id: None, errors },
+        });
+    }
+
+    let failed = rows.iter().filter(|r| !r.errors.is_empty()).count();
+    let imported = rows.iter().filter(|r| r.ticket_id.is_some()).count();
+
+    if !request.dry_run {
+        state.security_manager.log_audit_event(
+            &user.username,
+            "ticket:import",
+            "tickets",
+            if failed == 0 { AuditStatus::Success } else { AuditStatus::Warning },
+            Some(format!("{} tickets imported, {} rows rejected", imported, failed)),
+        );
+    }
+
+    (StatusCode::OK, Json(TicketImportReport {
+        dry_run: request.dry_run,
+        imported,
+        failed,
+        rows,
+    })).into_response()
+}
+
+// False for tickets of sites the caller is restricted from; missing tickets are left
+// to the handler to report
+#[derive(Deserialize)]
+struct CommentRequest {
+    // Text of the comment, or
+    content: Option<String>,
+    // a snippet expanded with the ticket, the user and these values
+    snippet_id: Option<Uuid>,
+    #[serde(default)]
+    values: HashMap<String, String>,
+    #[serde(default)]
+    is_internal: bool,
+}
+
+// Snippets are expanded here, so the stored comment is the final text
+async fn add_ticket_comment(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<CommentRequest>,
+) -> impl IntoResponse {
+    if !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let ticket = match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) => ticket,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+
+    let content = match (request.content, request.snippet_id) {
+        (Some(content), None) => content,
+        (None, Some(snippet_id)) => {
+            let snippet = match state.snippets.get_snippet(snippet_id) {
+                Ok(Some(snippet)) if snippet.can_view(&user) => snippet,
+                Ok(_) => return (StatusCode::NOT_FOUND, format!("Snippet not found: {}", snippet_id)).into_response(),
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            };
+
+            let account = state.user_manager.get_user(&user.username).ok();
+            let mut values = ticket_snippets::context_values(&ticket, &user.username, account.as_ref());
+            values.extend(request.values.into_iter().filter(|(_, v)| !v.trim().is_empty()));
+
+            match ticket_snippets::expand(&snippet.body, &values) {
+                Ok(content) => content,
+                Err(missing) => return (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({
+                    "error": "Snippet has placeholders without a value",
+                    "unresolved": missing,
+                }))).into_response(),
+            }
+        },
+        _ => return (StatusCode::BAD_REQUEST, "Give either content or snippet_id".to_string()).into_response(),
+    };
+
+    match state.tickets_manager.add_comment(id, content.clone(), user.username.clone(), request.is_internal) {
+        Ok(comment_id) => {
+            if let Some(snippet_id) = request.snippet_id {
+                if let Err(e) = state.snippets.record_use(snippet_id) {
+                    tracing::warn!("Failed to record use of snippet {}: {}", snippet_id, e);
+                }
+            }
+            if !request.is_internal {
+                ticket_portal::spawn_notification(&state.portal, id, format!("{} replied to your ticket:\n\n{}", user.username, content));
+            }
+            (StatusCode::CREATED, Json(serde_json::json!({ "id": comment_id }))).into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RequesterRequest {
+    email: Option<String>,
+    // Email the requester a portal link
+    #[serde(default = "default_notify")]
+    notify: bool,
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+async fn set_ticket_requester(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RequesterRequest>,
+) -> impl IntoResponse {
+    if !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let email = request.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+    if email.as_ref().map_or(false, |e| !e.contains('@') || e.contains(char::is_whitespace)) {
+        return (StatusCode::BAD_REQUEST, "Invalid email address".to_string()).into_response();
+    }
+
+    match state.tickets_manager.set_requester(id, email.clone(), &user.username) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:set_requester",
+                &id.to_string(),
+                AuditStatus::Success,
+                email.clone(),
+            );
+            if email.is_some() && request.notify {
+                ticket_portal::spawn_notification(&state.portal, id, "Your request has been recorded.".to_string());
+            }
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RotatePortalRequest {
+    // Email the requester a new link right away
+    #[serde(default)]
+    resend: bool,
+}
+
+// Revokes every portal link issued for the ticket
+async fn rotate_ticket_portal(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RotatePortalRequest>,
+) -> impl IntoResponse {
+    if !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.tickets_manager.rotate_portal_secret(id, &user.username) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:portal_rotate",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            if request.resend {
+                ticket_portal::spawn_notification(&state.portal, id, "Earlier links to your ticket no longer work, use this one.".to_string());
+            }
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Requester portal: no account, the token in the path is the credential. Every access is
+// audited with the source address; bad tokens get 401 whether or not the ticket exists.
+fn portal_access(state: &AppState, client: &ClientInfo, token: &str, action: &str) -> Result<crate::tickets::Ticket, Response> {
+    let source_ip = client.source_ip.clone().unwrap_or_else(|| "unknown".to_string());
+    if !state.portal.allow(&source_ip) {
+        state.security_manager.log_audit_event(
+            "portal", action, "ticket_portal", AuditStatus::Failure,
+            Some(format!("Rate limited, source {}", source_ip)),
+        );
+        return Err(StatusCode::TOO_MANY_REQUESTS.into_response());
+    }
+
+    match state.portal.resolve(token) {
+        Some(ticket) => {
+            state.security_manager.log_audit_event(
+                ticket.requester_email.as_deref().unwrap_or("portal"),
+                action,
+                &ticket.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("Source {}", source_ip)),
+            );
+            Ok(ticket)
+        },
+        None => {
+            state.security_manager.log_audit_event(
+                "portal", action, "ticket_portal", AuditStatus::Failure,
+                Some(format!("Invalid or expired token, source {}", source_ip)),
+            );
+            Err((StatusCode::UNAUTHORIZED, "Invalid or expired link".to_string()).into_response())
+        },
+    }
+}
+
+async fn get_portal_ticket(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match portal_access(&state, &client, &token, "portal:view") {
+        Ok(ticket) => (StatusCode::OK, Json(PortalTicket::from(&ticket))).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct PortalCommentRequest {
+    content: String,
+}
+
+async fn add_portal_comment(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Path(token): Path<String>,
+    Json(request): Json<PortalCommentRequest>,
+) -> impl IntoResponse {
+    let ticket = match portal_access(&state, &client, &token, "portal:comment") {
+        Ok(ticket) => ticket,
+        Err(response) => return response,
+    };
+
+    match state.portal.add_reply(&ticket, &request.content) {
+        Ok(comment_id) => (StatusCode::CREATED, Json(serde_json::json!({ "id": comment_id }))).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SnippetQuery {
+    category: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SnippetRequest {
+    name: String,
+    body: String,
+    category: Option<String>,
+    #[serde(default)]
+    shared: bool,
+}
+
+async fn list_snippets(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SnippetQuery>,
+) -> impl IntoResponse {
+    match state.snippets.get_visible_snippets(&user, query.category.as_deref()) {
+        Ok(snippets) => (StatusCode::OK, Json(snippets)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list snippets: {}", e)).into_response(),
+    }
+}
+
+async fn create_snippet(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<SnippetRequest>,
+) -> impl IntoResponse {
+    match state.snippets.create_snippet(request.name, request.body, request.category, user.username.clone(), request.shared) {
+        Ok(snippet) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "snippet:create",
+                &snippet.id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::CREATED, Json(snippet)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create snippet: {}", e)).into_response(),
+    }
+}
+
+async fn get_snippet(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.snippets.get_snippet(id) {
+        Ok(Some(snippet)) if snippet.can_view(&user) => (StatusCode::OK, Json(snippet)).into_response(),
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get snippet: {}", e)).into_response(),
+    }
+}
+
+// Personal snippets of other users answer 404 rather than 403, so they stay invisible
+fn snippet_editable(state: &AppState, user: &AuthUser, id: Uuid) -> Result<(), Response> {
+    match state.snippets.get_snippet(id) {
+        Ok(Some(snippet)) if snippet.can_view(user) => {
+            if snippet.can_edit(user) {
+                Ok(())
+            } else {
+                Err(StatusCode::FORBIDDEN.into_response())
+            }
+        },
+        Ok(_) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get snippet: {}", e)).into_response()),
+    }
+}
+
+async fn update_snippet(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<SnippetRequest>,
+) -> impl IntoResponse {
+    if let Err(response) = snippet_editable(&state, &user, id) {
+        return response;
+    }
+
+    match state.snippets.update_snippet(id, Some(request.name), Some(request.body), Some(request.category), Some(request.shared)) {
+        Ok(snippet) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "snippet:update",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(snippet)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update snippet: {}", e)).into_response(),
+    }
+}
+
+async fn delete_snippet(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = snippet_editable(&state, &user, id) {
+        return response;
+    }
+
+    match state.snippets.delete_snippet(id) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "snippet:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete snippet: {}", e)).into_response(),
+    }
+}
+
+fn ticket_in_scope(state: &AppState, user: &AuthUser, id: Uuid) -> bool {
+    match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) => ticket.created_by == user.username || user.site_scope().allows(ticket.site_id),
+        Err(_) => true,
+    }
+}
+
+// Time tracking is staff work; requesters do not see or log it
+async fn list_ticket_worklogs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.tickets_manager.worklogs(id) {
+        Ok(worklogs) => (StatusCode::OK, Json(worklogs)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn add_ticket_worklog(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<WorklogInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if state.tickets_manager.get_ticket(id).is_err() {
+        return (StatusCode::NOT_FOUND, format!("Ticket not found: {}", id)).into_response();
+    }
+
+    match state.tickets_manager.add_worklog(id, &user.username, request, &state.config.ticket_worklog) {
+        Ok(worklog) => (StatusCode::CREATED, Json(worklog)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// The author or an admin, or 404 when the entry does not exist
+fn check_worklog_owner(state: &AppState, user: &AuthUser, id: Uuid, worklog_id: Uuid) -> Result<(), Response> {
+    let worklogs = state.tickets_manager.worklogs(id)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()).into_response())?;
+    match worklogs.entries.iter().find(|w| w.id == worklog_id) {
+        Some(worklog) if worklog.author == user.username || user.is_admin() => Ok(()),
+        Some(_) => Err(StatusCode::FORBIDDEN.into_response()),
+        None => Err((StatusCode::NOT_FOUND, format!("Worklog not found: {}", worklog_id)).into_response()),
+    }
+}
+
+async fn update_ticket_worklog(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, worklog_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<WorklogInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = check_worklog_owner(&state, &user, id, worklog_id) {
+        return response;
+    }
+
+    match state.tickets_manager.update_worklog(id, worklog_id, &user.username, user.is_admin(), request, &state.config.ticket_worklog) {
+        Ok((before, after)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:worklog_update",
+                &format!("{}/{}", id, worklog_id),
+                AuditStatus::Success,
+                Some(format!("{} -> {} minutes, billable {} -> {}, started {} -> {}",
+                             before.duration_minutes, after.duration_minutes,
+                             before.billable, after.billable,
+                             before.started_at, after.started_at)),
+            );
+            (StatusCode::OK, Json(after)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:worklog_update",
+                &format!("{}/{}", id, worklog_id),
+                AuditStatus::Failure,
+                Some(e.to_string()),
+            );
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        },
+    }
+}
+
+async fn delete_ticket_worklog(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, worklog_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = check_worklog_owner(&state, &user, id, worklog_id) {
+        return response;
+    }
+
+    match state.tickets_manager.delete_worklog(id, worklog_id, &user.username, user.is_admin()) {
+        Ok(worklog) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:worklog_delete",
+                &format!("{}/{}", id, worklog_id),
+                AuditStatus::Success,
+                Some(format!("{} minutes by {}", worklog.duration_minutes, worklog.author)),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// References are staff work like worklogs; requesters do not see them
+async fn list_ticket_references(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) => (StatusCode::OK, Json(ticket.references)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn add_ticket_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ReferenceInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if state.tickets_manager.get_ticket(id).is_err() {
+        return (StatusCode::NOT_FOUND, format!("Ticket not found: {}", id)).into_response();
+    }
+
+    match state.tickets_manager.add_reference(id, request, &user.username, &state.config.references) {
+        Ok(reference) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:reference_add",
+                &format!("{}/{}", id, reference.id),
+                AuditStatus::Success,
+                Some(reference.value.clone()),
+            );
+            (StatusCode::CREATED, Json(reference)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LinkAlertRequest {
+    alert_id: Uuid,
+}
+
+// Links an alert to the ticket; the link shows in the ticket's activity feed, with the
+// alert in alert listings and in evidence packages
+async fn link_ticket_alert(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<LinkAlertRequest>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if state.alerts_manager.get_alert(request.alert_id).is_err() {
+        return (StatusCode::NOT_FOUND, format!("Alert not found: {}", request.alert_id)).into_response();
+    }
+
+    match state.tickets_manager.link_alert(id, request.alert_id, &user.username) {
+        Ok(linked) => {
+            if linked {
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "ticket:alert_link",
+                    &format!("{}/{}", id, request.alert_id),
+                    AuditStatus::Success,
+                    None,
+                );
+            }
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// 404 when the ticket or the reference does not exist
+fn check_ticket_reference(state: &AppState, id: Uuid, reference_id: Uuid) -> Result<(), Response> {
+    let ticket = state.tickets_manager.get_ticket(id)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()).into_response())?;
+    if !ticket.references.iter().any(|r| r.id == reference_id) {
+        return Err((StatusCode::NOT_FOUND, format!("Reference not found: {}", reference_id)).into_response());
+    }
+    Ok(())
+}
+
+async fn update_ticket_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, reference_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<ReferenceInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = check_ticket_reference(&state, id, reference_id) {
+        return response;
+    }
+
+    match state.tickets_manager.update_reference(id, reference_id, request, &user.username, &state.config.references) {
+        Ok((before, after)) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:reference_update",
+                &format!("{}/{}", id, reference_id),
+                AuditStatus::Success,
+                Some(format!("{} -> {}", before.value, after.value)),
+            );
+            (StatusCode::OK, Json(after)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn remove_ticket_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, reference_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if !user.is_staff() || !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.tickets_manager.remove_reference(id, reference_id, &user.username) {
+        Ok(reference) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "ticket:reference_remove",
+                &format!("{}/{}", id, reference_id),
+                AuditStatus::Success,
+                Some(reference.value),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ActivityQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+async fn get_ticket_activity(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    // Non-staff only see the feed of their own, existing tickets. Staff restricted to
+    // sites see the tickets of those sites.
+    let visible = match state.tickets_manager.get_ticket(id) {
+        Ok(ticket) => ticket.created_by == user.username
+            || (user.is_staff() && user.site_scope().allows(ticket.site_id)),
+        Err(_) => user.is_staff(),
+    };
+    if !visible {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.activity_log.page(
+        ResourceKind::Ticket,
+        &id.to_string(),
+        user.is_staff(),
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(50).min(500),
+    ) {
+        Ok(page) => (StatusCode::OK, Redacted(page, user)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CorrectionRequest {
+    note: String,
+}
+
+async fn correct_ticket_activity(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, activity_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<CorrectionRequest>,
+) -> impl IntoResponse {
+    if !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.activity_log.record_correction(ResourceKind::Ticket, &id.to_string(), &user.username, activity_id, &request.note) {
+        Ok(activity) => (StatusCode::CREATED, Json(activity)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AttachmentQuery {
+    filename: String,
+}
+
+// Stores the raw request body as an attachment under <attachments_dir>/<ticket id>/
+async fn upload_attachment(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<AttachmentQuery>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Body,
+) -> impl IntoResponse {
+    if !ticket_in_scope(&state, &user, id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if state.disk_monitor.is_active(ProtectiveAction::RejectUploads) {
+        return (StatusCode::INSUFFICIENT_STORAGE, "Uploads are suspended while disk space is critical".to_string()).into_response();
+    }
+
+    let filename = match std::path::Path::new(&query.filename).file_name().and_then(|n| n.to_str()) {
+        Some(name) => name.to_string(),
+        None => return (StatusCode::BAD_REQUEST, "Invalid filename".to_string()).into_response(),
+    };
+    let content_type = headers.get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    // Refused before the body is read when the name or claimed type already rules it out
+    if let Verdict::Rejected(reason) = state.attachment_scanner.check_name(&filename, &content_type) {
+        reject_attachment(&state, &user, id, &filename, &reason);
+        return (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response();
+    }
+
+    let dir = state.paths.attachments_dir.join(id.to_string());
+    let upload = dir.join(format!(".upload-{}", Uuid::new_v4()));
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store attachment: {}", e)).into_response();
+    }
+    // Streamed to disk so memory use does not grow with the attachment size
+    let size = match body_limits::stream_to_file(body.into_data_stream(), &upload, state.config.body_limits.attachment_bytes).await {
+        Ok(size) => size,
+        Err(e) => return e.into_response(),
+    };
+
+    match state.attachment_scanner.scan(&upload, &filename, &content_type).await {
+        Verdict::Clean => {},
+        Verdict::Rejected(reason) => {
+            let _ = tokio::fs::remove_file(&upload).await;
+            reject_attachment(&state, &user, id, &filename, &reason);
+            return (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response();
+        },
+        Verdict::Infected(signature) => {
+            let _ = tokio::fs::remove_file(&upload).await;
+            reject_attachment(&state, &user, id, &filename, &format!("infected: {}", signature));
+            if let Err(e) = state.alerts_manager.create_alert(
+                AlertSeverity::High,
+                format!("Infected attachment uploaded by {}", user.username),
+                format!("{} uploaded {} to ticket {}; the scanner found {}. The file was not stored.",
+                        user.username, filename, id, signature),
+                "attachment_scan".to_string(),
+                Vec::new(),
+            ) {
+                warn!("Failed to raise alert for infected attachment: {}", e);
+            }
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("Attachment rejected: {} found", signature)).into_response();
+        },
+        Verdict::Unavailable(reason) => {
+            if !state.attachment_scanner.fail_open() {
+                let _ = tokio::fs::remove_file(&upload).await;
+                reject_attachment(&state, &user, id, &filename, &format!("not scanned: {}", reason));
+                return (StatusCode::SERVICE_UNAVAILABLE, "Attachment could not be scanned, try again later".to_string()).into_response();
+            }
+            warn!("Accepting unscanned attachment {} on ticket {}: {}", filename, id, reason);
+        },
+    }
+
+    match state.tickets_manager.add_attachment(id, filename, content_type, size as usize, user.username.clone()) {
+        Ok(attachment_id) => {
+            if let Err(e) = tokio::fs::rename(&upload, dir.join(attachment_id.to_string())).await {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store attachment: {}", e)).into_response();
+            }
+            (StatusCode::CREATED, Json(serde_json::json!({ "id": attachment_id }))).into_response()
+        },
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&upload).await;
+            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+        },
+    }
+}
+
+fn reject_attachment(state: &AppState, user: &AuthUser, ticket_id: Uuid, filename: &str, reason: &str) {
+    state.security_manager.log_audit_event(
+        &user.username,
+        "ticket:attachment_rejected",
+        &format!("ticket:{}/{}", ticket_id, filename),
+        AuditStatus::Failure,
+        Some(reason.to_string()),
+    );
+}
+
+// Log API handlers
+#[derive(Deserialize)]
+struct LogQueryParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    min_severity: Option<LogSeverity>,
+    source: Option<String>,
+    host: Option<String>,
+    user: Option<String>,
+    event_type: Option<String>,
+    category: Option<EventCategory>,
+    message_contains: Option<String>,
+    tags: Option<String>, // Comma-separated
+    limit: Option<usize>,
+    // Defaults to all sites of the caller
+    site: Option<Uuid>,
+    // Wraps the result as { logs, annotations } with the annotations overlapping the window
+    #[serde(default)]
+    include_annotations: bool,
+}
+
+impl From<LogQueryParams> for LogFilter {
+    fn from(params: LogQueryParams) -> Self {
+        LogFilter {
+            from: params.from,
+            to: params.to,
+            min_severity: params.min_severity,
+            source: params.source,
+            host: params.host,
+            user: params.user,
+            event_type: params.event_type,
+            category: params.category,
+            message_contains: params.message_contains,
+            tags: params.tags
+                .map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                .unwrap_or_default(),
+            limit: params.limit,
+            sites: None,
+        }
+    }
+}
+
+// The filter of the query, limited to the sites of the caller
+fn scoped_log_filter(user: &AuthUser, params: LogQueryParams) -> Result<LogFilter, Response> {
+    let scope = site_scope(user, params.site)?;
+    let mut filter = LogFilter::from(params);
+    filter.sites = Some(scope);
+    Ok(filter)
+}
+
+async fn query_logs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<LogQueryParams>,
+) -> impl IntoResponse {
+    let annotation_filter = AnnotationFilter {
+        from: params.from,
+        to: params.to,
+        ..Default::default()
+    };
+    let include_annotations = params.include_annotations;
+
+    let filter = match scoped_log_filter(&user, params) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+
+    let logs = state.logs_manager.query(&filter).map(|mut logs| {
+        for log in &mut logs {
+            log.hostname = log.host.as_deref().and_then(|host| state.resolver.hostname_of(host));
+        }
+        logs
+    });
+
+    match logs {
+        Ok(logs) if include_annotations => match state.annotation_manager.query(&annotation_filter) {
+            Ok(annotations) => (StatusCode::OK, Json(serde_json::json!({
+                "logs": logs,
+                "annotations": annotations,
+            }))).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        },
+        Ok(logs) => (StatusCode::OK, Json(logs)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to query logs: {}", e)).into_response(),
+    }
+}
+
+// Server-sent events of newly ingested entries: "log" events carry an entry, "dropped"
+// ones the number of entries skipped because the client fell behind
+async fn tail_logs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<TailParams>,
+) -> impl IntoResponse {
+    let filter = match TailFilter::compile(params, user.site_scope()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response(),
+    };
+    let events = match state.log_tail.subscribe(filter) {
+        Ok(events) => events,
+        Err(e) => return (StatusCode::TOO_MANY_REQUESTS, e.to_string()).into_response(),
+    };
+
+    let stream = events.map(|event| match event {
+        TailEvent::Log(entry) => Event::default().event("log").json_data(&*entry),
+        TailEvent::Dropped { count } => Event::default().event("dropped").json_data(serde_json::json!({ "dropped": count })),
+    });
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn log_stats(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<LogQueryParams>,
+) -> impl IntoResponse {
+    let filter = match scoped_log_filter(&user, params) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+
+    match state.logs_manager.stats(&filter) {
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compute log statistics: {}", e)).into_response(),
+    }
+}
+
+async fn list_assets(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<SiteQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    match state.asset_manager.get_all_assets() {
+        Ok(assets) => {
+            let visible: Vec<Asset> = assets.into_iter()
+                .filter(|a| scope.allows(a.site_id))
+                .collect();
+            (StatusCode::OK, Json(visible)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list assets: {}", e)).into_response(),
+    }
+}
+
+async fn export_assets(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<InventoryExportQuery>,
+) -> impl IntoResponse {
+    let scope = match site_scope(&user, query.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let assets: Vec<Asset> = match state.asset_manager.get_all_assets() {
+        Ok(assets) => assets.into_iter().filter(|a| scope.allows(a.site_id)).collect(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list assets: {}", e)).into_response(),
+    };
+    let allowed = inventory_export::asset_fields();
+    let fields = match inventory_export::select_fields(query.fields.as_deref(), &allowed, allowed.clone()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        "assets:export",
+        "assets",
+        AuditStatus::Success,
+        Some(format!("{} assets as {}, site {:?}, fields {}", assets.len(), query.format.extension(), query.site, fields.join(","))),
+    );
+
+    let stream = inventory_export::export_stream(query.format, query.bom, fields, assets, inventory_export::asset_value);
+    inventory_export_response("assets", &query, axum::body::Body::from_stream(stream))
+}
+
+// Assets outside the sites of the caller are answered as missing
+fn visible_asset(state: &AppState, user: &AuthUser, id: Uuid) -> Result<Asset, Response> {
+    match state.asset_manager.get_asset(id) {
+        Ok(asset) if user.site_scope().allows(asset.site_id) => Ok(asset),
+        Ok(_) => Err((StatusCode::NOT_FOUND, format!("Asset not found: {}", id)).into_response()),
+        Err(e) => Err((StatusCode::NOT_FOUND, e.to_string()).into_response()),
+    }
+}
+
+// Attributes the asset to the site of its IP address unless one is given, and keeps
+// restricted users from placing it outside their sites
+fn assign_asset_site(state: &AppState, user: &AuthUser, fields: &mut AssetFields) -> Result<(), Response> {
+    match fields.site_id {
+        Some(site_id) => {
+            if state.sites.get_site(site_id).is_err() {
+                return Err((StatusCode::BAD_REQUEST, format!("Site not found: {}", site_id)).into_response());
+            }
+        },
+        None => {
+            fields.site_id = fields.ip_address.as_deref().and_then(|ip| state.sites.site_for_address(ip));
+        },
+    }
+
+    if !user.site_scope().allows(fields.site_id) {
+        return Err((StatusCode::BAD_REQUEST, "The asset must belong to one of your sites".to_string()).into_response());
+    }
+    Ok(())
+}
+
+async fn create_asset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(mut fields): Json<AssetFields>,
+) -> impl IntoResponse {
+    if let Err(response) = assign_asset_site(&state, &user, &mut fields) {
+        return response;
+    }
+
+    match state.asset_manager.create_asset(fields, &user.username) {
+        Ok(asset) => {
+            state.security_manager.log_audit_event(&user.username, "create_asset", &asset.id.to_string(), AuditStatus::Success, Some(asset.name.clone()));
+            (StatusCode::CREATED, Json(asset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to create asset: {}", e)).into_response(),
+    }
+}
+
+async fn get_asset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match visible_asset(&state, &user, id) {
+        Ok(asset) => (StatusCode::OK, Json(asset)).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn update_asset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(mut fields): Json<AssetFields>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_asset(&state, &user, id) {
+        return response;
+    }
+    if let Err(response) = assign_asset_site(&state, &user, &mut fields) {
+        return response;
+    }
+
+    match state.asset_manager.update_asset(id, fields, &user.username) {
+        Ok(asset) => {
+            state.security_manager.log_audit_event(&user.username, "update_asset", &id.to_string(), AuditStatus::Success, None);
+            (StatusCode::OK, Json(asset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to update asset: {}", e)).into_response(),
+    }
+}
+
+async fn delete_asset(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_asset(&state, &user, id) {
+        return response;
+    }
+
+    match state.asset_manager.delete_asset(id, &user.username) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(&user.username, "delete_asset", &id.to_string(), AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, format!("Failed to delete asset: {}", e)).into_response(),
+    }
+}
+
+async fn get_asset_history(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Deleted assets keep their history, which only admins can still read
+    if !user.is_admin() {
+        if let Err(response) = visible_asset(&state, &user, id) {
+            return response;
+        }
+    }
+
+    match state.asset_manager.history(id) {
+        Ok(history) => (StatusCode::OK, Json(history)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read asset history: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct InventoryQuery {
+    changes_since: Option<DateTime<Utc>>,
+}
+
+// The latest inventory snapshot, and with changes_since what changed since then
+async fn get_asset_inventory(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<InventoryQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_asset(&state, &user, id) {
+        return response;
+    }
+
+    match state.inventory.view(id, query.changes_since) {
+        Ok(view) => (StatusCode::OK, Json(view)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Matches an observation to its asset and raises an alert for drift not seen before
+fn observe_asset(state: &AppState, observation: &AssetObservation) -> anyhow::Result<Option<Asset>> {
+    let Some((asset, raised)) = state.asset_manager.observe(observation)? else {
+        return Ok(None);
+    };
+
+    if state.config.asset_drift.alert {
+        for drift in &raised {
+            if let Err(e) = state.alerts_manager.create_alert(
+                AlertSeverity::Medium,
+                format!("Asset {} drifted: {}", asset.name, drift.field),
+                format!("{} reported {} = {} for asset {} ({}), the record has {}. Accept the observation or correct the record.",
+                        drift.source, drift.field, drift.observed, asset.name, asset.id,
+                        drift.recorded.as_deref().unwrap_or("nothing")),
+                "asset_drift".to_string(),
+                Vec::new(),
+            ) {
+                warn!("Failed to raise alert for drift of asset {}: {}", asset.id, e);
+            }
+        }
+    }
+    Ok(Some(asset))
+}
+
+#[derive(Serialize)]
+struct ObservationResult {
+    matched: usize,
+    unmatched: usize,
+    review_needed: Vec<Uuid>,
+}
+
+async fn submit_asset_observations(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(observations): Json<Vec<AssetObservation>>,
+) -> impl IntoResponse {
+    if let Some(e) = observations.iter().find_map(|o| o.observed_ip().err()) {
+        return (StatusCode::BAD_REQUEST, format!("Invalid observation: {}", e)).into_response();
+    }
+
+    let mut result = ObservationResult { matched: 0, unmatched: 0, review_needed: Vec::new() };
+    for observation in &observations {
+        match observe_asset(&state, observation) {
+            Ok(Some(asset)) => {
+                result.matched += 1;
+                if asset.review_needed() && !result.review_needed.contains(&asset.id) {
+                    result.review_needed.push(asset.id);
+                }
+            },
+            Ok(None) => result.unmatched += 1,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to record observation: {}", e)).into_response(),
+        }
+    }
+
+    state.security_manager.log_audit_event(&user.username, "submit_asset_observations", "assets", AuditStatus::Success,
+                                           Some(format!("{} observation(s), {} matched", observations.len(), result.matched)));
+    (StatusCode::OK, Json(result)).into_response()
+}
+
+#[derive(Deserialize)]
+struct AcceptDriftRequest {
+    // Every pending field when empty
+    #[serde(default)]
+    fields: Vec<String>,
+}
+
+async fn accept_asset_drift(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<AcceptDriftRequest>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_asset(&state, &user, id) {
+        return response;
+    }
+
+    match state.asset_manager.accept_drift(id, &request.fields, &user.username) {
+        Ok(asset) => {
+            let detail = if request.fields.is_empty() { "all fields".to_string() } else { request.fields.join(", ") };
+            state.security_manager.log_audit_event(&user.username, "accept_asset_drift", &id.to_string(), AuditStatus::Success, Some(detail));
+            (StatusCode::OK, Json(asset)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to accept drift: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TimelineQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+fn timeline_item<T: Serialize>(kind: TimelineItemKind, timestamp: DateTime<Utc>, id: String, summary: String, record: &T) -> TimelineItem {
+    TimelineItem {
+        kind,
+        timestamp,
+        id,
+        summary,
+        data: serde_json::to_value(record).unwrap_or(serde_json::Value::Null),
+    }
+}
+
+// Every store gets the time range and only returns what could land on the requested page
+async fn build_asset_timeline(state: &AppState, asset: Asset, params: &TimelineQuery) -> anyhow::Result<TimelinePage> {
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+    let fetch = offset.saturating_add(limit + 1);
+    let matcher = AssetMatcher::new(asset);
+
+    let log_filter = LogFilter {
+        from: params.from,
+        to: params.to,
+        limit: Some(fetch),
+        ..Default::default()
+    };
+    let logs = state.logs_manager.query_where(&log_filter, |e| {
+        e.host.as_deref().map_or(false, |h| matcher.host_matches(h, e.timestamp))
+            || matcher.address_mentioned_in(&e.message, e.timestamp)
+    })?;
+
+    let alerts = state.alerts_manager.alerts_between(params.from, params.to, fetch, |a| {
+        matcher.mentioned_in(&a.title, a.created_at) || matcher.mentioned_in(&a.description, a.created_at)
+    })?;
+
+    let tickets = state.tickets_manager.tickets_between(params.from, params.to, fetch, |t| matcher.tagged(&t.tags))?;
+
+    let findings = state.scan_manager.findings_between(params.from, params.to, fetch, |scan, finding| {
+        matcher.address_matches(&finding.ip_address, scan.timestamp)
+    })?;
+
+    let rules = state.network_manager.managed_rules_between(params.from, params.to, fetch, |r| {
+        matcher.address_mentioned_in(&r.rule, r.created_at)
+    }).await;
+
+    let sources = vec![
+        logs.iter()
+            .map(|e| timeline_item(TimelineItemKind::Log, e.timestamp, e.id.to_string(),
+                                   format!("[{}] {}: {}", e.severity, e.source, e.message), e))
+            .collect(),
+        alerts.iter()
+            .map(|a| timeline_item(TimelineItemKind::Alert, a.created_at, a.id.to_string(),
+                                   format!("[{:?}] {}", a.severity, a.title), a))
+            .collect(),
+        tickets.iter()
+            .map(|t| timeline_item(TimelineItemKind::Ticket, t.created_at, t.id.to_string(),
+                                   format!("[{:?}] {}", t.status, t.title), t))
+            .collect(),
+        findings.iter()
+            .map(|f| timeline_item(TimelineItemKind::ScanFinding, f.scanned_at, f.finding.id.to_string(),
+                                   format!("[{:?}] {}", f.finding.severity, f.finding.description), f))
+            .collect(),
+        rules.iter()
+            .map(|r| timeline_item(TimelineItemKind::FirewallRule, r.created_at, r.handle.to_string(),
+                                   r.description.clone(), r))
+            .collect(),
+    ];
+
+    Ok(timeline::merge(sources, offset, limit))
+}
+
+async fn get_asset_timeline(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<TimelineQuery>,
+) -> impl IntoResponse {
+    let asset = match visible_asset(&state, &user, id) {
+        Ok(asset) => asset,
+        Err(response) => return response,
+    };
+
+    match build_asset_timeline(&state, asset, &params).await {
+        Ok(page) => (StatusCode::OK, Json(page)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build asset timeline: {}", e)).into_response(),
+    }
+}
+
+async fn list_scans(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.scan_manager.get_scans() {
+        Ok(scans) => (StatusCode::OK, Json(scans)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list scans: {}", e)).into_response(),
+    }
+}
+
+// Results submitted by an external scanner
+#[derive(Deserialize)]
+struct ScanRequest {
+    timestamp: Option<DateTime<Utc>>,
+    target_ip: String,
+    scan_type: String,
+    status: ScanStatus,
+    #[serde(default)]
+    findings: Vec<ScanFinding>,
+    // Identity of the target as seen by the scanner, compared with the asset record
+    mac_address: Option<String>,
+    os_fingerprint: Option<String>,
+}
+
+async fn record_scan(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ScanRequest>,
+) -> impl IntoResponse {
+    let observation = AssetObservation {
+        source: format!("scan:{}", request.scan_type),
+        ip_address: Some(request.target_ip.clone()),
+        mac_address: request.mac_address,
+        operating_system: request.os_fingerprint,
+        observed_at: request.timestamp,
+    };
+
+    match state.scan_manager.record_scan(
+        request.timestamp,
+        request.target_ip,
+        request.scan_type,
+        request.status,
+        request.findings,
+        user.username,
+    ) {
+        Ok(scan) => {
+            if let Err(e) = observe_asset(&state, &observation) {
+                warn!("Failed to compare scan of {} with the asset record: {}", scan.target_ip, e);
+            }
+            (StatusCode::CREATED, Json(scan)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to record scan: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    title: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    format: ReportFormat,
+    // Defaults to all sites of the caller
+    site: Option<Uuid>,
+    // Replaces from and to with a period of the business calendar
+    period: Option<BusinessPeriod>,
+    #[serde(flatten)]
+    overrides: ReportOverrides,
+}
+
+impl ReportQuery {
+    fn settings(&self, state: &AppState) -> ReportSettings {
+        ReportSettings::resolve(&state.config.reports, &self.overrides, &state.paths.reports_dir.join("locales"))
+    }
+
+    // The requested from and to, or the bounds of the business period
+    fn range(&self, state: &AppState) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), Response> {
+        let Some(period) = self.period else { return Ok((self.from, self.to)) };
+        let calendar = state.calendar.calendar()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+        match calendar.period(period, Utc::now()) {
+            Some((from, to)) => Ok((Some(from), Some(to))),
+            None => Err((StatusCode::UNPROCESSABLE_ENTITY, "The business calendar has no working days in that period").into_response()),
+        }
+    }
+}
+
+async fn incident_report(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<ReportQuery>,
+) -> impl IntoResponse {
+    let sites = match site_scope(&user, params.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let (from, to) = match params.range(&state) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    let filter = LogFilter {
+        from,
+        to,
+        sites: Some(sites),
+        ..Default::default()
+    };
+
+    match state.logs_manager.query(&filter) {
+        Ok(entries) => {
+            let settings = params.settings(&state);
+            let report = reports::incident_report(&entries, params.title.as_deref().unwrap_or("Incident"), &settings);
+            (
+                StatusCode::OK,
+                [("Content-Type", params.format.content_type())],
+                report.render(&settings, params.format),
+            ).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate incident report: {}", e)).into_response(),
+    }
+}
+
+// Generates, stores and sends the daily digest now, outside its schedule
+async fn send_digest(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match digest::generate_and_send(&state, Utc::now()).await {
+        Ok(path) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "report:digest",
+                &path.display().to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(serde_json::json!({
+                "path": path,
+                "recipients": state.config.digest.recipients,
+            }))).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Staff without site restrictions may request a package; audit events and alerts
+// belong to no site, so a restricted user could not be given a partial one
+async fn start_evidence_package(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(query): Json<EvidenceQuery>,
+) -> impl IntoResponse {
+    if !user.is_staff() || user.site_scope() != SiteScope::All {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if state.disk_monitor.is_active(ProtectiveAction::PauseReports) {
+        return (StatusCode::INSUFFICIENT_STORAGE, "Report generation is paused while disk space is critical".to_string()).into_response();
+    }
+
+    let details = serde_json::to_string(&query).unwrap_or_default();
+    match state.evidence.start(query, &user.username) {
+        Ok(job) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "report:evidence",
+                &job.id.to_string(),
+                AuditStatus::Success,
+                Some(details),
+            );
+            (StatusCode::ACCEPTED, Json(job)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// Packages are only visible to whoever requested them and to admins
+fn visible_evidence_job(state: &AppState, user: &AuthUser, id: Uuid) -> Option<EvidenceJob> {
+    state.evidence.get_job(id)
+        .ok()
+        .filter(|job| job.requested_by == user.username || user.is_admin())
+}
+
+async fn get_evidence_package(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match visible_evidence_job(&state, &user, id) {
+        Some(job) => (StatusCode::OK, Json(job)).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn download_evidence_package(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let job = match visible_evidence_job(&state, &user, id) {
+        Some(job) => job,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match job.status {
+        EvidenceStatus::Completed => {},
+        EvidenceStatus::Running => return (StatusCode::CONFLICT, "Package is still being generated".to_string()).into_response(),
+        EvidenceStatus::Failed | EvidenceStatus::Expired => return StatusCode::GONE.into_response(),
+    }
+
+    let file = match tokio::fs::File::open(state.evidence.archive_path(id)).await {
+        Ok(file) => file,
+        Err(_) => return StatusCode::GONE.into_response(),
+    };
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        "report:evidence_download",
+        &id.to_string(),
+        AuditStatus::Success,
+        job.sha256.map(|hash| format!("sha256: {}", hash)),
+    );
+
+    let body = axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(file));
+
+    (
+        StatusCode::OK,
+        [
+            (axum::http::header::CONTENT_TYPE, "application/zip".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"evidence-{}.zip\"", id)),
+        ],
+        body,
+    ).into_response()
+}
+
+async fn compliance_report(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<ReportQuery>,
+) -> impl IntoResponse {
+    let sites = match site_scope(&user, params.site) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    // Defaults to the last 30 days
+    let (from, to) = match params.range(&state) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+    let to = to.unwrap_or_else(Utc::now);
+    let from = from.unwrap_or_else(|| to - chrono::Duration::days(30));
+
+    let filter = LogFilter {
+        from: Some(from),
+        to: Some(to),
+        sites: Some(sites),
+        ..Default::default()
+    };
+
+    match state.logs_manager.query(&filter) {
+        Ok(entries) => {
+            let settings = params.settings(&state);
+            let audit_chain = match state.security_manager.verify_audit_chain() {
+                Ok(verification) => verification,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to verify the audit trail: {}", e)).into_response(),
+            };
+            let report = reports::compliance_report(&entries, from, to, &audit_chain, &settings);
+            (
+                StatusCode::OK,
+                [("Content-Type", params.format.content_type())],
+                report.render(&settings, params.format),
+            ).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate compliance report: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChargebackQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    // Comma-separated accounting group names, "other" included; all groups by default
+    group: Option<String>,
+    #[serde(default)]
+    format: ChargebackFormat,
+}
+
+async fn chargeback_report(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<ChargebackQuery>,
+) -> impl IntoResponse {
+    // Traffic of every site and department, so not for site-scoped users
+    if !user.is_staff() || !user.sites.is_empty() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Defaults to the current month
+    let to = params.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = params.from.unwrap_or_else(|| to.with_day(1).unwrap_or(to));
+    let groups: Option<Vec<String>> = params.group.as_deref().map(|g| {
+        g.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect()
+    });
+
+    let report = state.chargeback.report(from, to, groups.as_deref()).and_then(|mut report| {
+        report.bandwidth = state.bandwidth_quotas.archived_between(from, to)?;
+        Ok(report)
+    });
+    match report {
+        Ok(report) => match params.format {
+            ChargebackFormat::Json => (StatusCode::OK, Json(report)).into_response(),
+            ChargebackFormat::Csv => (
+                StatusCode::OK,
+                [
+                    (axum::http::header::CONTENT_TYPE, "text/csv".to_string()),
+                    (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"chargeback-{}-{}.csv\"", from, to)),
+                ],
+                report.to_csv(),
+            ).into_response(),
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct WorklogReportQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    #[serde(default)]
+    group_by: WorklogGrouping,
+    #[serde(default)]
+    format: ChargebackFormat,
+}
+
+// Logged time per ticket, assignee, author or category; site-scoped staff see the
+// tickets of their sites
+async fn worklog_report(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<WorklogReportQuery>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Defaults to the current month
+    let to = params.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = params.from.unwrap_or_else(|| to.with_day(1).unwrap_or(to));
+    if from > to {
+        return (StatusCode::BAD_REQUEST, "from must not be after to".to_string()).into_response();
+    }
+
+    let tickets: Vec<_> = match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => tickets.into_iter().filter(|t| user.site_scope().allows(t.site_id)).collect(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let report = worklog_report::worklog_report(&tickets, from, to, params.group_by);
+
+    match params.format {
+        ChargebackFormat::Json => (StatusCode::OK, Json(report)).into_response(),
+        ChargebackFormat::Csv => (
+            StatusCode::OK,
+            [
+                (axum::http::header::CONTENT_TYPE, "text/csv".to_string()),
+                (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"worklogs-{}-{}.csv\"", from, to)),
+            ],
+            report.to_csv(),
+        ).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SavedSearchRequest {
+    name: String,
+    #[serde(default)]
+    shared: bool,
+    filter: LogFilter,
+    default_window_hours: Option<u32>,
+}
+
+async fn list_saved_searches(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    match state.saved_search_manager.get_visible_searches(&user) {
+        Ok(searches) => (StatusCode::OK, Json(searches)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list saved searches: {}", e)).into_response(),
+    }
+}
+
+async fn create_saved_search(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<SavedSearchRequest>,
+) -> impl IntoResponse {
+    match state.saved_search_manager.create_search(
+        request.name,
+        user.username,
+        request.shared,
+        request.filter,
+        request.default_window_hours,
+    ) {
+        Ok(search) => (StatusCode::CREATED, Json(search)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create saved search: {}", e)).into_response(),
+    }
+}
+
+async fn get_saved_search(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.saved_search_manager.get_search(id) {
+        Ok(Some(search)) if search.can_view(&user) => (StatusCode::OK, Json(search)).into_response(),
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get saved search: {}", e)).into_response(),
+    }
+}
+
+async fn update_saved_search(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<SavedSearchRequest>,
+) -> impl IntoResponse {
+    match state.saved_search_manager.get_search(id) {
+        Ok(Some(search)) if search.can_view(&user) => {
+            if !search.can_edit(&user) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+        },
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get saved search: {}", e)).into_response(),
+    }
+
+    match state.saved_search_manager.update_search(
+        id,
+        Some(request.name),
+        Some(request.shared),
+        Some(request.filter),
+        Some(request.default_window_hours),
+    ) {
+        Ok(search) => (StatusCode::OK, Json(search)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update saved search: {}", e)).into_response(),
+    }
+}
+
+async fn delete_saved_search(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.saved_search_manager.get_search(id) {
+        Ok(Some(search)) if search.can_view(&user) => {
+            if !search.can_edit(&user) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+        },
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get saved search: {}", e)).into_response(),
+    }
+
+    match state.saved_search_manager.delete_search(id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete saved search: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TimeRangeParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+async fn run_saved_search(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(range): Query<TimeRangeParams>,
+) -> impl IntoResponse {
+    let search = match state.saved_search_manager.get_search(id) {
+        Ok(Some(search)) if search.can_view(&user) => search,
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get saved search: {}", e)).into_response(),
+    };
+
+    let mut filter = search.effective_filter(range.from, range.to);
+    filter.sites = Some(user.site_scope());
+
+    match state.logs_manager.query(&filter) {
+        Ok(logs) => (StatusCode::OK, Json(logs)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to run saved search: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct IngestRequest {
+    source: String,
+    message: String,
+    timestamp: Option<DateTime<Utc>>,
+    severity: Option<LogSeverity>,
+    event_type: Option<String>,
+    category: Option<EventCategory>,
+    host: Option<String>,
+    user: Option<String>,
+    application: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+async fn ingest_log(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Json(request): Json<IngestRequest>,
+) -> impl IntoResponse {
+    let entry = LogEntry {
+        id: Uuid::new_v4(),
+        timestamp: request.timestamp.unwrap_or_else(Utc::now),
+        source: request.source,
+        event_type: request.event_type.unwrap_or_default(),
+        severity: request.severity.unwrap_or(LogSeverity::Info),
+        raw_data: request.message.clone(),
+        message: request.message,
+        host: request.host,
+        user: request.user,
+        application: request.application,
+        tags: tags::normalize_all(request.tags),
+        category: request.category.unwrap_or_default(),
+        hostname: None,
+        site_id: None,
+        parser: LogParser::Api,
+    };
+
+    match state.ingestion_pipeline.ingest(entry) {
+        Ok(Some(entry)) => (StatusCode::CREATED, Json(entry)).into_response(),
+        Ok(None) => (StatusCode::TOO_MANY_REQUESTS, "Source is over its ingestion quota, event dropped".to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to ingest log entry: {}", e)).into_response(),
+    }
+}
+
+// The stored entry with its raw data, parser and the extraction rules matching it now
+async fn get_log_entry(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.log_reparser.detail(id).await {
+        Ok(Some(mut detail)) if user.site_scope().allows(detail.entry.site_id) => {
+            detail.entry.hostname = detail.entry.host.as_deref().and_then(|host| state.resolver.hostname_of(host));
+            (StatusCode::OK, Json(detail)).into_response()
+        },
+        Ok(_) => (StatusCode::NOT_FOUND, format!("Log entry not found: {}", id)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReparseParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// Parses the raw data again with the current extraction and tagging rules; a dry run only
+// shows the result, anyone who can see the entry may ask for one
+async fn reparse_log_entry(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ReparseParams>,
+) -> impl IntoResponse {
+    if !params.dry_run && !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.log_reparser.detail(id).await {
+        Ok(Some(detail)) if user.site_scope().allows(detail.entry.site_id) => {},
+        Ok(_) => return (StatusCode::NOT_FOUND, format!("Log entry not found: {}", id)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+
+    match state.log_reparser.reparse(id, params.dry_run).await {
+        Ok(Some(outcome)) => {
+            if !outcome.dry_run {
+                state.security_manager.log_audit_event(
+                    &user.username,
+                    "logs:reparse",
+                    &id.to_string(),
+                    AuditStatus::Success,
+                    Some(if outcome.changed.is_empty() {
+                        "unchanged".to_string()
+                    } else {
+                        format!("changed: {}", outcome.changed.join(", "))
+                    }),
+                );
+            }
+            (StatusCode::OK, Json(outcome)).into_response()
+        },
+        Ok(None) => (StatusCode::NOT_FOUND, format!("Log entry not found: {}", id)).into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to re-parse log entry: {}", e)).into_response(),
+    }
+}
+
+// Re-parses every stored entry matching the filter as a background job, polled at
+// GET /api/logs/reparse/:id
+async fn start_bulk_reparse(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<LogQueryParams>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let filter = match scoped_log_filter(&user, params) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+
+    match state.log_reparser.start_bulk(filter, &user.username).await {
+        Ok(job) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "logs:reparse_bulk",
+                &job.id.to_string(),
+                AuditStatus::Success,
+                Some(serde_json::to_string(&job.filter).unwrap_or_default()),
+            );
+            (StatusCode::ACCEPTED, Json(job)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_reparse_job(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.log_reparser.get_job(id) {
+        Ok(job) => (StatusCode::OK, Json(job)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn list_log_sources(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    let mut active: HashMap<String, _> = match state.ingestion_quotas.sources() {
+        Ok(sources) => sources.into_iter().map(|s| (s.source.clone(), s)).collect(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    match state.source_health.sources(Utc::now()) {
+        Ok(mut sources) => {
+            for source in sources.iter_mut() {
+                source.ingestion = active.remove(&source.source);
+                source.events_per_minute = source.ingestion.as_ref().map_or(0, |s| s.events_per_minute);
+            }
+            (StatusCode::OK, Json(sources)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Marks a source as expected, with a fixed or learned maximum silence and an optional schedule
+async fn set_log_source_expected(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(source): Path<String>,
+    Json(expectation): Json<Expectation>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.source_health.set_expected(&source, expectation, &user.username) {
+        Ok(expected) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "log_source:expect",
+                &source,
+                AuditStatus::Success,
+                Some(format!("max silence {} minutes{}", expected.max_silence_minutes,
+                             if expected.learned { " (learned)" } else { "" })),
+            );
+            (StatusCode::OK, Json(expected)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set expected source: {}", e)).into_response(),
+    }
+}
+
+async fn clear_log_source_expected(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(source): Path<String>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.source_health.clear_expected(&source) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(&user.username, "log_source:unexpect", &source, AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn get_log_quotas(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.ingestion_quotas.get_settings() {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn set_default_log_quota(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(quota): Json<QuotaConfig>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.ingestion_quotas.set_default(quota) {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set quota: {}", e)).into_response(),
+    }
+}
+
+async fn set_log_quota_override(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(source): Path<String>,
+    Json(quota): Json<QuotaConfig>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.ingestion_quotas.set_override(&source, Some(quota)) {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to set quota: {}", e)).into_response(),
+    }
+}
+
+async fn delete_log_quota_override(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(source): Path<String>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.ingestion_quotas.set_override(&source, None) {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+// Extraction rule API handlers
+#[derive(Deserialize)]
+struct ExtractionRuleRequest {
+    name: String,
+    #[serde(default)]
+    order: u32,
+    enabled: Option<bool>,
+    source_match: Option<String>,
+    program_match: Option<String>,
+    pattern: String,
+    #[serde(default)]
+    field_mapping: HashMap<String, ExtractionTarget>,
+}
+
+async fn list_extraction_rules(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.extraction_manager.get_all_rules() {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list extraction rules: {}", e)).into_response(),
+    }
+}
+
+async fn get_extraction_rule(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.extraction_manager.get_rule(id) {
+        Ok(rule) => (StatusCode::OK, Json(rule)).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn create_extraction_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<ExtractionRuleRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.extraction_manager.create_rule(
+        request.name,
+        request.order,
+        request.source_match,
+        request.program_match,
+        request.pattern,
+        request.field_mapping,
+    ) {
+        Ok(rule) => (StatusCode::CREATED, Json(rule)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to create extraction rule: {}", e)).into_response(),
+    }
+}
+
+async fn update_extraction_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ExtractionRuleRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if state.extraction_manager.get_rule(id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.extraction_manager.update_rule(
+        id,
+        Some(request.name),
+        Some(request.order),
+        request.enabled,
+        Some(request.source_match),
+        Some(request.program_match),
+        Some(request.pattern),
+        Some(request.field_mapping),
+    ) {
+        Ok(rule) => (StatusCode::OK, Json(rule)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to update extraction rule: {}", e)).into_response(),
+    }
+}
+
+async fn delete_extraction_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.extraction_manager.delete_rule(id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Tagging rule API handlers
+async fn list_tagging_rules(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.tagging.get_all_rules() {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list tagging rules: {}", e)).into_response(),
+    }
+}
+
+async fn get_tagging_rule(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.tagging.get_rule(id) {
+        Ok(rule) => (StatusCode::OK, Json(rule)).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn create_tagging_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(spec): Json<TaggingRuleSpec>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tagging.create_rule(spec) {
+        Ok(rule) => (StatusCode::CREATED, Json(rule)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to create tagging rule: {:#}", e)).into_response(),
+    }
+}
+
+async fn update_tagging_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(spec): Json<TaggingRuleSpec>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if state.tagging.get_rule(id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.tagging.update_rule(id, spec) {
+        Ok(rule) => (StatusCode::OK, Json(rule)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to update tagging rule: {:#}", e)).into_response(),
+    }
+}
+
+async fn delete_tagging_rule(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tagging.delete_rule(id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Applies the rule to the entries already stored; poll the returned job for progress
+async fn start_tagging_backfill(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if state.tagging.get_rule(id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.tagging.start_backfill(id, &user.username) {
+        Ok(job) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "logs:tagging_backfill",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{} stored entries", job.total)),
+            );
+            (StatusCode::ACCEPTED, Json(job)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_tagging_backfill(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.tagging.get_backfill(id) {
+        Ok(job) => (StatusCode::OK, Json(job)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExtractionTestRequest {
+    rule_id: Option<Uuid>,
+    rule: Option<ExtractionRuleRequest>,
+    sample: String,
+}
+
+async fn test_extraction_rule(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Json(request): Json<ExtractionTestRequest>,
+) -> impl IntoResponse {
+    let rule = match (request.rule_id, request.rule) {
+        (Some(id), _) => match state.extraction_manager.get_rule(id) {
+            Ok(rule) => rule,
+            Err(_) => return StatusCode::NOT_FOUND.into_response(),
+        },
+        (None, Some(rule)) => ExtractionRule {
+            id: Uuid::nil(),
+            name: rule.name,
+            order: rule.order,
+            enabled: true,
+            source_match: rule.source_match,
+            program_match: rule.program_match,
+            pattern: rule.pattern,
+            field_mapping: rule.field_mapping,
+            disabled_reason: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        },
+        (None, None) => {
+            return (StatusCode::BAD_REQUEST, "Either rule_id or rule must be provided".to_string()).into_response();
+        }
+    };
+
+    match state.extraction_manager.test_rule(&rule, &request.sample) {
+        Ok(result) => (StatusCode::OK, Json(result)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to test extraction rule: {}", e)).into_response(),
+    }
+}
+
+// Related logs inlined by ?expand=logs; the list endpoint never expands them
+const ALERT_EXPANDED_LOGS: usize = 50;
+
+#[derive(Deserialize)]
+struct ExpandQuery {
+    // Comma separated, e.g. expand=logs,ticket
+    expand: Option<String>,
+}
+
+// The requested expansions, or a 400 naming the ones the endpoint supports
+fn parse_expand<'a>(query: &'a ExpandQuery, supported: &[&str]) -> Result<Vec<&'a str>, Response> {
+    let mut expand = Vec::new();
+    for value in query.expand.iter().flat_map(|e| e.split(',')).map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        if !supported.contains(&value) {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown expand value '{}', supported: {}", value, supported.join(", "))).into_response());
+        }
+        expand.push(value);
+    }
+    Ok(expand)
+}
+
+// Linked tickets of the alerts, limited to the tickets the user may see
+fn linked_tickets(state: &AppState, user: &AuthUser, alert_ids: &[Uuid]) -> anyhow::Result<HashMap<Uuid, TicketSummary>> {
+    let scope = user.site_scope();
+    state.tickets_manager.tickets_for_alerts(alert_ids, |t| t.created_by == user.username || scope.allows(t.site_id))
+}
+
+#[derive(Serialize)]
+struct ExpandedLogs {
+    // Number of related logs on the alert; entries holds at most ALERT_EXPANDED_LOGS of
+    // those still in the log store
+    total: usize,
+    entries: Vec<crate::models::LogEntry>,
+}
+
+#[derive(Serialize)]
+struct AlertListItem {
+    #[serde(flatten)]
+    alert: crate::models::Alert,
+    // Present when expanded, null when no ticket links the alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ticket: Option<Option<TicketSummary>>,
+}
+
+// Alert API handlers
+#[derive(Deserialize)]
+struct BacktestRequest {
+    rule: CorrelationRule,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+// Replays the range through the rule without raising anything. Short backtests come back
+// finished (200), longer ones as a running job to poll (202).
+async fn start_backtest(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<BacktestRequest>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let mut filter = match state.saved_search_manager.resolve_filter(&request.rule.filter) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    filter.sites = Some(user.site_scope());
+
+    let (backtest, handle) = match state.backtester.start(request.rule, filter, request.from, request.to, &user.username) {
+        Ok(started) => started,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    state.security_manager.log_audit_event(
+        &user.username,
+        "correlation:backtest",
+        &backtest.id.to_string(),
+        AuditStatus::Success,
+        Some(format!("{} over {} to {}", backtest.rule.name, backtest.from, backtest.to)),
+    );
+
+    let _ = tokio::time::timeout(state.backtester.wait_time(), handle).await;
+    match state.backtester.get(backtest.id) {
+        Ok(backtest) if backtest.status == BacktestStatus::Running => (StatusCode::ACCEPTED, Json(backtest)).into_response(),
+        Ok(backtest) => (StatusCode::OK, Json(backtest)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn list_backtests(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.backtester.list() {
+        Ok(backtests) => (StatusCode::OK, Json(backtests)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_backtest(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.backtester.get(id) {
+        Ok(backtest) => (StatusCode::OK, Json(backtest)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn cancel_backtest(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.backtester.cancel(id, &user.username) {
+        Ok(backtest) => (StatusCode::OK, Json(backtest)).into_response(),
+        Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RuleExportQuery {
+    format: Option<String>,
+}
+
+// Rules are only kept with their backtests, so the export takes the rule of the latest
+// retained backtest of each rule name and refers to it by that backtest's id
+async fn export_correlation_rules(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<RuleExportQuery>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if query.format.as_deref() != Some("prometheus") {
+        return (StatusCode::BAD_REQUEST, "Unsupported export format, expected format=prometheus").into_response();
+    }
+
+    let backtests = match state.backtester.list() {
+        Ok(backtests) => backtests,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let mut latest: HashMap<String, Backtest> = HashMap::new();
+    for backtest in backtests {
+        match latest.get(&backtest.rule.name) {
+            Some(kept) if kept.created_at >= backtest.created_at => {},
+            _ => {
+                latest.insert(backtest.rule.name.clone(), backtest);
+            },
+        }
+    }
+
+    let sources = latest.into_values()
+        .map(|backtest| ExportSource {
+            id: backtest.id,
+            filter: state.saved_search_manager.resolve_filter(&backtest.rule.filter).map_err(|e| e.to_string()),
+            rule: backtest.rule,
+        })
+        .collect();
+    (StatusCode::OK, Json(prometheus_rules::export(sources))).into_response()
+}
+
+async fn list_tags(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.tags.usage() {
+        Ok(usage) => (StatusCode::OK, Json(usage)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DefineTagRequest {
+    color: Option<String>,
+    description: Option<String>,
+}
+
+async fn define_tag(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(name): Path<String>,
+    Json(request): Json<DefineTagRequest>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tags.define(&name, request.color, request.description) {
+        Ok(definition) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:define",
+                &definition.name,
+                AuditStatus::Success,
+                None,
+            );
+            (StatusCode::OK, Json(definition)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn undefine_tag(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tags.undefine(&name) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:undefine",
+                &name,
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameTagRequest {
+    to: String,
+}
+
+// Renames and merges rewrite resources across every module, so they are for admins
+async fn rename_tag(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(name): Path<String>,
+    Json(request): Json<RenameTagRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tags.rename(&name, &request.to) {
+        Ok(report) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:rename",
+                &name,
+                AuditStatus::Success,
+                Some(format!("Renamed to {}: {:?}", report.to, report.changed)),
+            );
+            (StatusCode::OK, Json(report)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:rename",
+                &name,
+                AuditStatus::Failure,
+                Some(format!("{:#}", e)),
+            );
+            (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response()
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeTagsRequest {
+    sources: Vec<String>,
+    into: String,
+}
+
+async fn merge_tags(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<MergeTagsRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.tags.merge(&request.sources, &request.into) {
+        Ok(report) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:merge",
+                &report.to,
+                AuditStatus::Success,
+                Some(format!("Merged {:?}: {:?}", report.from, report.changed)),
+            );
+            (StatusCode::OK, Json(report)).into_response()
+        },
+        Err(e) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "tag:merge",
+                &request.into,
+                AuditStatus::Failure,
+                Some(format!("{:#}", e)),
+            );
+            (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response()
+        },
+    }
+}
+
+async fn list_alerts(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<ExpandQuery>,
+) -> impl IntoResponse {
+    let expand = match parse_expand(&query, &["ticket"]) {
+        Ok(expand) => expand,
+        Err(response) => return response,
+    };
+
+    let alerts = match state.alerts_manager.get_all_alerts() {
+        Ok(alerts) => alerts,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list alerts: {}", e)).into_response(),
+    };
+
+    let mut tickets = if expand.contains(&"ticket") {
+        let ids: Vec<Uuid> = alerts.iter().map(|a| a.id).collect();
+        match linked_tickets(&state, &user, &ids) {
+            Ok(tickets) => Some(tickets),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list alerts: {}", e)).into_response(),
+        }
+    } else {
+        None
+    };
+
+    let items: Vec<AlertListItem> = alerts.into_iter()
+        .map(|alert| AlertListItem {
+            ticket: tickets.as_mut().map(|t| t.remove(&alert.id)),
+            alert,
+        })
+        .collect();
+    (StatusCode::OK, Json(items)).into_response()
+}
+
+async fn get_alert(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ExpandQuery>,
+) -> impl IntoResponse {
+    let expand = match parse_expand(&query, &["logs", "ticket"]) {
+        Ok(expand) => expand,
+        Err(response) => return response,
+    };
+
+    let alert = match state.alerts_manager.get_alert(id) {
+        Ok(alert) => alert,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let logs = if expand.contains(&"logs") {
+        let ids = &alert.related_logs[..alert.related_logs.len().min(ALERT_EXPANDED_LOGS)];
+        match state.logs_manager.get_many(ids) {
+            Ok(entries) => Some(ExpandedLogs { total: alert.related_logs.len(), entries }),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    } else {
+        None
+    };
+
+    let ticket = if expand.contains(&"ticket") {
+        match linked_tickets(&state, &user, &[id]) {
+            Ok(mut tickets) => Some(tickets.remove(&id)),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    } else {
+        None
+    };
+
+    match state.annotation_manager.for_alert(id) {
+        Ok(annotations) => (StatusCode::OK, Json(AlertDetail { alert, annotations, logs, ticket })).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct AlertDetail {
+    #[serde(flatten)]
+    alert: crate::models::Alert,
+    annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logs: Option<ExpandedLogs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ticket: Option<Option<TicketSummary>>,
+}
+
+#[derive(Deserialize)]
+struct AlertExportQuery {
+    #[serde(default)]
+    format: ReportFormat,
+    #[serde(flatten)]
+    overrides: ReportOverrides,
+}
+
+async fn export_alert(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<AlertExportQuery>,
+) -> impl IntoResponse {
+    let alert = match state.alerts_manager.get_alert(id) {
+        Ok(alert) => alert,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let annotations = match state.annotation_manager.for_alert(id) {
+        Ok(annotations) => annotations,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let settings = ReportSettings::resolve(&state.config.reports, &params.overrides, &state.paths.reports_dir.join("locales"));
+    let report = reports::alert_report(&alert, &annotations, &settings);
+    (
+        StatusCode::OK,
+        [("Content-Type", params.format.content_type())],
+        report.render(&settings, params.format),
+    ).into_response()
+}
+
+// Re-walks the stored audit events and reports the first break in the hash chain
+async fn verify_audit_chain(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.security_manager.verify_audit_chain() {
+        Ok(verification) => (StatusCode::OK, Json(verification)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AnnotationQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    interface: Option<String>,
+    asset_id: Option<Uuid>,
+    alert_id: Option<Uuid>,
+    // Include global annotations when filtering by interface, asset or alert
+    #[serde(default)]
+    include_global: bool,
+}
+
+async fn list_annotations(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Query(params): Query<AnnotationQuery>,
+) -> impl IntoResponse {
+    let mut scopes = Vec::new();
+    scopes.extend(params.interface.map(|name| AnnotationScope::Interface { name }));
+    scopes.extend(params.asset_id.map(|id| AnnotationScope::Asset { id }));
+    scopes.extend(params.alert_id.map(|id| AnnotationScope::Alert { id }));
+
+    let filter = AnnotationFilter {
+        from: params.from,
+        to: params.to,
+        scopes,
+        include_global: params.include_global,
+    };
+    match state.annotation_manager.query(&filter) {
+        Ok(annotations) => (StatusCode::OK, Json(annotations)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn create_annotation(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(fields): Json<AnnotationFields>,
+) -> impl IntoResponse {
+    match state.annotation_manager.create_annotation(fields, &user.username) {
+        Ok(annotation) => (StatusCode::CREATED, Json(annotation)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_annotation(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.annotation_manager.get_annotation(id) {
+        Ok(annotation) => (StatusCode::OK, Json(annotation)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn update_annotation(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(fields): Json<AnnotationFields>,
+) -> impl IntoResponse {
+    match state.annotation_manager.update_annotation(id, fields, &user.username) {
+        Ok(annotation) => (StatusCode::OK, Json(annotation)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_annotation(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Tell "not yours" apart from "not there"
+    let annotation = match state.annotation_manager.get_annotation(id) {
+        Ok(annotation) => annotation,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    if annotation.author != user.username && !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.annotation_manager.delete_annotation(id, &user.username, user.is_admin()) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "annotation:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                Some(annotation.text),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Acknowledging an alert stops its escalation right away
+async fn acknowledge_alert(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(e) = state.alerts_manager.update_status(id, AlertStatus::Acknowledged) {
+        return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+    }
+
+    if let Err(e) = state.escalation_engine.stop(id) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to stop escalation: {}", e)).into_response();
+    }
+
+    state.security_manager.log_audit_event(
+        &user.username,
+        "alert:acknowledge",
+        &id.to_string(),
+        AuditStatus::Success,
+        None,
+    );
+
+    match state.alerts_manager.get_alert(id) {
+        Ok(alert) => (StatusCode::OK, Json(alert)).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Alert activity lives in the activity log like ticket activity; failing to record it must
+// not fail the change
+fn record_alert_activity(state: &AppState, id: Uuid, actor: &str, kind: ActivityKind, payload: serde_json::Value) {
+    if let Err(e) = state.activity_log.record(ResourceKind::Alert, &id.to_string(), actor, kind, payload) {
+        tracing::warn!("Failed to record activity of alert {}: {}", id, e);
+    }
+}
+
+async fn get_alert_activity(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    if state.alerts_manager.get_alert(id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match state.activity_log.page(
+        ResourceKind::Alert,
+        &id.to_string(),
+        user.is_staff(),
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(50).min(500),
+    ) {
+        Ok(page) => (StatusCode::OK, Json(page)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn list_alert_references(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.alerts_manager.get_alert(id) {
+        Ok(alert) => (StatusCode::OK, Json(alert.references)).into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn add_alert_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ReferenceInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if state.alerts_manager.get_alert(id).is_err() {
+        return (StatusCode::NOT_FOUND, format!("Alert not found: {}", id)).into_response();
+    }
+
+    match state.alerts_manager.add_reference(id, request, &user.username, &state.config.references) {
+        Ok(reference) => {
+            record_alert_activity(&state, id, &user.username, ActivityKind::ReferenceAdded, references::payload(&reference));
+            state.security_manager.log_audit_event(
+                &user.username,
+                "alert:reference_add",
+                &format!("{}/{}", id, reference.id),
+                AuditStatus::Success,
+                Some(reference.value.clone()),
+            );
+            (StatusCode::CREATED, Json(reference)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// 404 when the alert or the reference does not exist
+fn check_alert_reference(state: &AppState, id: Uuid, reference_id: Uuid) -> Result<(), Response> {
+    let alert = state.alerts_manager.get_alert(id)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()).into_response())?;
+    if !alert.references.iter().any(|r| r.id == reference_id) {
+        return Err((StatusCode::NOT_FOUND, format!("Reference not found: {}", reference_id)).into_response());
+    }
+    Ok(())
+}
+
+async fn update_alert_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, reference_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<ReferenceInput>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if let Err(response) = check_alert_reference(&state, id, reference_id) {
+        return response;
+    }
+
+    match state.alerts_manager.update_reference(id, reference_id, request, &state.config.references) {
+        Ok((before, after)) => {
+            record_alert_activity(&state, id, &user.username, ActivityKind::ReferenceUpdated, serde_json::json!({
+                "before": references::payload(&before),
+                "after": references::payload(&after),
+            }));
+            state.security_manager.log_audit_event(
+                &user.username,
+                "alert:reference_update",
+                &format!("{}/{}", id, reference_id),
+                AuditStatus::Success,
+                Some(format!("{} -> {}", before.value, after.value)),
+            );
+            (StatusCode::OK, Json(after)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn remove_alert_reference(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path((id, reference_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.alerts_manager.remove_reference(id, reference_id) {
+        Ok(reference) => {
+            record_alert_activity(&state, id, &user.username, ActivityKind::ReferenceRemoved, references::payload(&reference));
+            state.security_manager.log_audit_event(
+                &user.username,
+                "alert:reference_remove",
+                &format!("{}/{}", id, reference_id),
+                AuditStatus::Success,
+                Some(reference.value),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReferenceSearchQuery {
+    value: String,
+}
+
+fn matching_references(list: &[ExternalReference], value: &str) -> Vec<ExternalReference> {
+    list.iter()
+        .filter(|r| references::matches(r, value))
+        .cloned()
+        .collect()
+}
+
+// Every alert and ticket referencing an identifier or URL, newest first; tickets outside the
+// user's sites are left out
+async fn search_references(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(query): Query<ReferenceSearchQuery>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let value = query.value.trim().to_string();
+    if value.is_empty() {
+        return (StatusCode::BAD_REQUEST, "value is required".to_string()).into_response();
+    }
+
+    let alerts = match state.alerts_manager.get_all_alerts() {
+        Ok(alerts) => alerts,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let tickets = match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => tickets,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let mut alert_matches: Vec<ReferenceMatch> = alerts.into_iter()
+        .filter_map(|alert| {
+            let matched = matching_references(&alert.references, &value);
+            (!matched.is_empty()).then(|| ReferenceMatch {
+                id: alert.id,
+                title: alert.title,
+                status: format!("{:?}", alert.status),
+                created_at: alert.created_at,
+                references: matched,
+            })
+        })
+        .collect();
+    let mut ticket_matches: Vec<ReferenceMatch> = tickets.into_iter()
+        .filter(|ticket| user.site_scope().allows(ticket.site_id))
+        .filter_map(|ticket| {
+            let matched = matching_references(&ticket.references, &value);
+            (!matched.is_empty()).then(|| ReferenceMatch {
+                id: ticket.id,
+                title: ticket.title,
+                status: format!("{:?}", ticket.status),
+                created_at: ticket.created_at,
+                references: matched,
+            })
+        })
+        .collect();
+    alert_matches.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    ticket_matches.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    (StatusCode::OK, Json(ReferenceSearch {
+        value,
+        alerts: alert_matches,
+        tickets: ticket_matches,
+    })).into_response()
+}
+
+// Scripts run automatically for alerts; only admins may bind them, staff may look
+async fn list_remediation_bindings(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.get_bindings() {
+        Ok(bindings) => (StatusCode::OK, Json(bindings)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list remediation bindings: {}", e)).into_response(),
+    }
+}
+
+async fn create_remediation_binding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(spec): Json<RemediationSpec>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.create_binding(spec, user.username.clone()) {
+        Ok(binding) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "remediation:create",
+                &binding.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{}: script {} on alerts from {}", binding.name, binding.script_id, binding.alert_source)),
+            );
+            (StatusCode::CREATED, Json(binding)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to create remediation binding: {}", e)).into_response(),
+    }
+}
+
+async fn get_remediation_binding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_staff() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.get_binding(id) {
+        Ok(Some(binding)) => (StatusCode::OK, Json(binding)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn update_remediation_binding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(spec): Json<RemediationSpec>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.update_binding(id, spec) {
+        Ok(binding) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "remediation:update",
+                &binding.id.to_string(),
+                AuditStatus::Success,
+                Some(format!("{}: script {} on alerts from {}", binding.name, binding.script_id, binding.alert_source)),
+            );
+            (StatusCode::OK, Json(binding)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to update remediation binding: {}", e)).into_response(),
+    }
+}
+
+async fn delete_remediation_binding(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.delete_binding(id) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "remediation:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AutomationRequest {
+    enabled: bool,
+}
+
+async fn get_remediation_automation(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    Json(serde_json::json!({ "enabled": state.remediation.automation_enabled() }))
+}
+
+// Global switch for automatic remediation, e.g. to stop all actions during an incident
+async fn set_remediation_automation(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<AutomationRequest>,
+) -> impl IntoResponse {
+    if !user.is_admin() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state.remediation.set_automation_enabled(request.enabled) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                if request.enabled { "remediation:enable" } else { "remediation:disable" },
+                "remediation",
+                AuditStatus::Success,
+                None,
+            );
+            Json(serde_json::json!({ "enabled": request.enabled })).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Power state of the configured UPS units, see ups
+async fn get_ups_status(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.ups.status() {
+        Ok(units) => (StatusCode::OK, Json(units)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Escalation policy API handlers
+async fn list_escalation_policies(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.escalation_engine.get_all_policies() {
+        Ok(policies) => (StatusCode::OK, Json(policies)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list escalation policies: {}", e)).into_response(),
+    }
+}
+
+async fn create_escalation_policy(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(fields): Json<EscalationPolicyFields>,
+) -> impl IntoResponse {
+    match state.escalation_engine.create_policy(fields, &user.username) {
+        Ok(policy) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "escalation_policy:create",
+                &policy.id.to_string(),
+                AuditStatus::Success,
+                Some(policy.name.clone()),
+            );
+            (StatusCode::CREATED, Json(policy)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_escalation_policy(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.escalation_engine.get_policy(id) {
+        Ok(policy) => (StatusCode::OK, Json(policy)).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn update_escalation_policy(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(fields): Json<EscalationPolicyFields>,
+) -> impl IntoResponse {
+    match state.escalation_engine.update_policy(id, fields) {
+        Ok(policy) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "escalation_policy:update",
+                &policy.id.to_string(),
+                AuditStatus::Success,
+                Some(policy.name.clone()),
+            );
+            (StatusCode::OK, Json(policy)).into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_escalation_policy(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.escalation_engine.delete_policy(id) {
+        Ok(()) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "escalation_policy:delete",
+                &id.to_string(),
+                AuditStatus::Success,
+                None,
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn list_active_escalations(
+    State(state): State<Arc<AppState>>,
+    _user: AuthUser,
+) -> impl IntoResponse {
+    match state.escalation_engine.get_active() {
+        Ok(active) => (StatusCode::OK, Json(active)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list escalations: {}", e)).into_response(),
+    }
+}
+
+// Role management API handlers
+async fn list_roles(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.access_control.list_roles() {
+        Ok(roles) => (StatusCode::OK, Json(roles)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list roles: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateRoleRequest {
+    role: String,
+    #[serde(default)]
+    permissions: Vec<String>,
+}
+
+async fn create_role(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(request): Json<CreateRoleRequest>,
+) -> impl IntoResponse {
+    match state.access_control.create_role(&request.role, request.permissions.clone()) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "role:create",
+                &request.role,
+                AuditStatus::Success,
+                Some(format!("permissions: {}", request.permissions.join(", "))),
+            );
+            StatusCode::CREATED.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to create role: {}", e)).into_response(),
+    }
+}
+
+async fn delete_role(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(role): Path<String>,
+) -> impl IntoResponse {
+    match state.access_control.delete_role(&role) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(&user.username, "role:delete", &role, AuditStatus::Success, None);
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to delete role: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RolePermissionRequest {
+    permission: String,
+}
+
+async fn add_role_permission(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(role): Path<String>,
+    Json(request): Json<RolePermissionRequest>,
+) -> impl IntoResponse {
+    match state.access_control.add_permission(&role, &request.permission) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "role:add_permission",
+                &role,
+                AuditStatus::Success,
+                Some(request.permission),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to add permission: {}", e)).into_response(),
+    }
+}
+
+async fn remove_role_permission(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Path(role): Path<String>,
+    Json(request): Json<RolePermissionRequest>,
+) -> impl IntoResponse {
+    match state.access_control.remove_permission(&role, &request.permission) {
+        Ok(_) => {
+            state.security_manager.log_audit_event(
+                &user.username,
+                "role:remove_permission",
+                &role,
+                AuditStatus::Success,
+                Some(request.permission),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        },
+        Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to remove permission: {}", e)).into_response(),
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::config::ApiUsageConfig;
+
+// Upper bounds of the latency buckets in milliseconds, for the p95
+const LATENCY_BOUNDS_MS: [u64; 12] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+// Minute buckets back the 1h window, hour buckets the 24h and 7d ones
+const MINUTES_KEPT: i64 = 60;
+const HOURS_KEPT: i64 = 7 * 24;
+
+// Requests of users past max_principals
+pub const OTHER_PRINCIPAL: &str = "other";
+// Requests without a valid token
+pub const ANONYMOUS_PRINCIPAL: &str = "anonymous";
+// Requests no route matched, counted together whatever their path
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+const METHODS: [&str; 7] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+pub const MAX_TOP: usize = 500;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum UsageWindow {
+    #[serde(rename = "1h")]
+    Hour,
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    #[default]
+    Route,
+    Principal,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageSort {
+    #[default]
+    Requests,
+    Errors,
+    ErrorRate,
+    P95,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Bucket {
+    requests: u64,
+    // Answered 4xx or 5xx
+    errors: u64,
+    server_errors: u64,
+    // Requests per latency bucket, the last one past the largest bound
+    latency: [u64; LATENCY_BOUNDS_MS.len() + 1],
+}
+
+impl Bucket {
+    fn add(&mut self, other: &Bucket) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.server_errors += other.server_errors;
+        for (total, count) in self.latency.iter_mut().zip(&other.latency) {
+            *total += count;
+        }
+    }
+
+    // Upper bound of the bucket holding the 95th percentile, None past the largest
+    fn p95_ms(&self) -> Option<u64> {
+        if self.requests == 0 {
+            return None;
+        }
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BOUNDS_MS.iter().zip(&self.latency) {
+            cumulative += count;
+            if cumulative as f64 >= 0.95 * self.requests as f64 {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+}
+
+// Buckets of one route or principal, keyed by minute and hour since the epoch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Counters {
+    minutes: BTreeMap<i64, Bucket>,
+    hours: BTreeMap<i64, Bucket>,
+}
+
+impl Counters {
+    fn record(&mut self, at: DateTime<Utc>, status: u16, latency: Duration) {
+        let seconds = at.timestamp();
+        let latency_ms = latency.as_millis() as u64;
+        let slot = LATENCY_BOUNDS_MS.iter().position(|bound| latency_ms <= *bound).unwrap_or(LATENCY_BOUNDS_MS.len());
+        for bucket in [
+            self.minutes.entry(seconds.div_euclid(60)).or_default(),
+            self.hours.entry(seconds.div_euclid(3600)).or_default(),
+        ] {
+            bucket.requests += 1;
+            if status >= 400 {
+                bucket.errors += 1;
+            }
+            if status >= 500 {
+                bucket.server_errors += 1;
+            }
+            bucket.latency[slot] += 1;
+        }
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let minute = now.timestamp().div_euclid(60);
+        let hour = now.timestamp().div_euclid(3600);
+        self.minutes.retain(|m, _| *m > minute - MINUTES_KEPT);
+        self.hours.retain(|h, _| *h > hour - HOURS_KEPT);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.minutes.is_empty() && self.hours.is_empty()
+    }
+
+    fn total(&self, window: UsageWindow, now: DateTime<Utc>) -> Bucket {
+        let (buckets, current, span) = match window {
+            UsageWindow::Hour => (&self.minutes, now.timestamp().div_euclid(60), MINUTES_KEPT),
+            UsageWindow::Day => (&self.hours, now.timestamp().div_euclid(3600), 24),
+            UsageWindow::Week => (&self.hours, now.timestamp().div_euclid(3600), HOURS_KEPT),
+        };
+        let mut total = Bucket::default();
+        for (_, bucket) in buckets.range(current - span + 1..) {
+            total.add(bucket);
+        }
+        total
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UsageState {
+    routes: HashMap<String, Counters>,
+    principals: HashMap<String, Counters>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageEntry {
+    pub key: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub server_errors: u64,
+    pub error_rate: f64,
+    // Upper bound of the latency bucket, None past 30s
+    pub p95_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub window: UsageWindow,
+    pub group_by: UsageGrouping,
+    pub sort: UsageSort,
+    pub generated_at: DateTime<Utc>,
+    pub total_requests: u64,
+    pub total_errors: u64,
+    // Routes or principals with requests in the window, before the top-N cut
+    pub tracked: usize,
+    pub entries: Vec<UsageEntry>,
+}
+
+// The route template a request matched (never the raw path, which would make every
+// ticket id a key of its own) with its method
+pub fn route_key(method: &str, template: Option<&str>) -> String {
+    let method = if METHODS.contains(&method) { method } else { "OTHER" };
+    match template {
+        Some(template) => format!("{} {}", method, template),
+        None => UNMATCHED_ROUTE.to_string(),
+    }
+}
+
+// Rolling request counters per route template and per authenticated user, see
+// api::record_usage. Persisted periodically so a restart keeps the 7-day view.
+#[derive(Clone)]
+pub struct ApiUsage {
+    config: ApiUsageConfig,
+    path: PathBuf,
+    state: Arc<Mutex<UsageState>>,
+}
+
+impl ApiUsage {
+    pub fn new(config: ApiUsageConfig, dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir).context(format!("Failed to create API usage directory: {}", dir))?;
+
+        let path = Path::new(dir).join("usage.json");
+        let mut state: UsageState = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring invalid API usage file {:?}: {}", path, e);
+                UsageState::default()
+            }),
+            Err(_) => UsageState::default(),
+        };
+        Self::prune_state(&mut state, Utc::now());
+
+        info!("Loaded API usage of {} routes and {} users", state.routes.len(), state.principals.len());
+
+        Ok(Self {
+            config,
+            path,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn persist_interval(&self) -> Duration {
+        Duration::from_secs(self.config.persist_interval_secs.max(10))
+    }
+
+    fn prune_state(state: &mut UsageState, now: DateTime<Utc>) {
+        for counters in [&mut state.routes, &mut state.principals] {
+            counters.retain(|_, c| {
+                c.prune(now);
+                !c.is_empty()
+            });
+        }
+    }
+
+    // Counted under the route and the principal; a principal beyond max_principals is
+    // counted as "other" until a tracked one has been idle for seven days
+    pub fn record(&self, route: String, principal: &str, status: u16, latency: Duration) {
+        let Ok(mut state) = self.state.lock() else { return };
+        let now = Utc::now();
+
+        state.routes.entry(route).or_default().record(now, status, latency);
+
+        let tracked = state.principals.contains_key(principal)
+            || state.principals.len() < self.config.max_principals;
+        let principal = if tracked { principal } else { OTHER_PRINCIPAL };
+        state.principals.entry(principal.to_string()).or_default().record(now, status, latency);
+    }
+
+    // Drops expired buckets and writes the counters to disk
+    pub fn persist(&self) -> Result<()> {
+        let json = {
+            let mut state = self.state.lock().map_err(|_| anyhow!("Failed to acquire lock on API usage"))?;
+            Self::prune_state(&mut state, Utc::now());
+            serde_json::to_string(&*state)?
+        };
+        fs::write(&self.path, json)
+            .context(format!("Failed to write API usage file: {:?}", self.path))?;
+        Ok(())
+    }
+
+    pub fn report(&self, window: UsageWindow, group_by: UsageGrouping, sort: UsageSort, limit: usize) -> Result<UsageReport> {
+        let state = self.state.lock().map_err(|_| anyhow!("Failed to acquire lock on API usage"))?;
+        let now = Utc::now();
+        let counters = match group_by {
+            UsageGrouping::Route => &state.routes,
+            UsageGrouping::Principal => &state.principals,
+        };
+
+        let mut entries: Vec<UsageEntry> = counters.iter()
+            .map(|(key, counters)| (key, counters.total(window, now)))
+            .filter(|(_, total)| total.requests > 0)
+            .map(|(key, total)| UsageEntry {
+                key: key.clone(),
+                requests: total.requests,
+                errors: total.errors,
+                server_errors: total.server_errors,
+                error_rate: total.errors as f64 / total.requests as f64,
+                p95_ms: total.p95_ms(),
+            })
+            .collect();
+        drop(state);
+
+        // Highest first; a p95 past the largest bucket ranks above any other
+        entries.sort_by(|a, b| {
+            let order = match sort {
+                UsageSort::Requests => b.requests.cmp(&a.requests),
+                UsageSort::Errors => b.errors.cmp(&a.errors),
+                UsageSort::ErrorRate => b.error_rate.total_cmp(&a.error_rate),
+                UsageSort::P95 => b.p95_ms.unwrap_or(u64::MAX).cmp(&a.p95_ms.unwrap_or(u64::MAX)),
+            };
+            order.then_with(|| a.key.cmp(&b.key))
+        });
+
+        let tracked = entries.len();
+        let total_requests = entries.iter().map(|e| e.requests).sum();
+        let total_errors = entries.iter().map(|e| e.errors).sum();
+        entries.truncate(limit.clamp(1, MAX_TOP));
+
+        Ok(UsageReport {
+            window,
+            group_by,
+            sort,
+            generated_at: now,
+            total_requests,
+            total_errors,
+            tracked,
+            entries,
+        })
+    }
+}
@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::models::{AddressAssignment, Asset, AssetDrift, AssetStatus, AssetType};
+use crate::tags::{self, TaggedStore};
+
+// Fields left out of the history: the address history is derived from ip_address and
+// drift is tracked on its own
+const UNTRACKED_FIELDS: &[&str] = &["id", "address_history", "drift"];
+
+// Fields an observation can disagree on
+const DRIFT_FIELDS: &[&str] = &["ip_address", "mac_address", "operating_system"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetChangeAction {
+    Created,
+    Updated,
+    ObservationAccepted,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+// One mutation of an asset, as a field-level diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetHistoryEntry {
+    pub id: Uuid,
+    pub at: DateTime<Utc>,
+    pub changed_by: String,
+    pub action: AssetChangeAction,
+    // Sources of accepted observations
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub changes: Vec<FieldChange>,
+}
+
+// Facts about a device seen by a scanner, a DHCP server or another feed. The asset is
+// found by MAC address, else by IP address.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetObservation {
+    pub source: String,
+    pub ip_address: Option<String>,
+    pub mac_address: Option<String>,
+    pub operating_system: Option<String>,
+    pub observed_at: Option<DateTime<Utc>>,
+}
+
+impl AssetObservation {
+    // An observed address that does not parse is refused rather than kept as drift: once
+    // accepted it becomes the address scripts and SSH connect to
+    pub fn observed_ip(&self) -> Result<Option<IpAddr>> {
+        match self.ip_address.as_deref().map(str::trim).filter(|ip| !ip.is_empty()) {
+            Some(ip) => parse_ip(ip).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_ip(value: &str) -> Result<IpAddr> {
+    value.trim().parse().map_err(|_| anyhow!("Invalid IP address: {}", value))
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+fn as_object(asset: &Asset) -> Map<String, Value> {
+    match serde_json::to_value(asset) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+fn diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Vec<FieldChange> {
+    let fields: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    fields.into_iter()
+        .filter(|field| !UNTRACKED_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let previous = old.get(field).cloned().unwrap_or(Value::Null);
+            let value = new.get(field).cloned().unwrap_or(Value::Null);
+            (previous != value).then(|| FieldChange {
+                field: field.clone(),
+                old: previous,
+                new: value,
+            })
+        })
+        .collect()
+}
+
+fn recorded_value(asset: &Asset, field: &str) -> Option<String> {
+    match field {
+        "ip_address" => asset.ip_address.clone(),
+        "mac_address" => asset.mac_address.clone(),
+        "operating_system" => asset.operating_system.clone(),
+        _ => None,
+    }
+}
+
+// OS fingerprints are fuzzy: one naming the other, e.g. "Windows" and "Windows 10", agree
+fn agrees(field: &str, recorded: Option<&str>, observed: &str) -> bool {
+    let Some(recorded) = recorded else {
+        return false;
+    };
+    match field {
+        "ip_address" => matches!((parse_ip(recorded), parse_ip(observed)), (Ok(a), Ok(b)) if a == b),
+        "mac_address" => normalize_mac(recorded) == normalize_mac(observed),
+        _ => {
+            let (recorded, observed) = (recorded.to_lowercase(), observed.to_lowercase());
+            recorded.contains(&observed) || observed.contains(&recorded)
+        },
+    }
+}
+
+// A changed IP closes the current address assignment and opens a new one
+fn reassign_address(asset: &mut Asset, address: Option<&String>, now: DateTime<Utc>) {
+    if asset.ip_address.as_ref() == address {
+        return;
+    }
+
+    // Assets stored before address tracking keep their old IP as the first entry
+    if asset.address_history.is_empty() {
+        if let Some(previous) = &asset.ip_address {
+            asset.address_history.push(AddressAssignment {
+                address: previous.clone(),
+                assigned_at: now,
+                released_at: None,
+            });
+        }
+    }
+
+    for assignment in asset.address_history.iter_mut().filter(|a| a.released_at.is_none()) {
+        assignment.released_at = Some(now);
+    }
+
+    if let Some(address) = address {
+        asset.address_history.push(AddressAssignment {
+            address: address.clone(),
+            assigned_at: now,
+            released_at: None,
+        });
+    }
+    asset.ip_address = address.cloned();
+}
+
+// Pending drift the record now agrees with is resolved
+fn prune_drift(asset: &mut Asset) {
+    let snapshot = asset.clone();
+    asset.drift.retain(|d| !agrees(&d.field, recorded_value(&snapshot, &d.field).as_deref(), &d.observed));
+}
+
+// Editable part of an asset, used for both creation and updates
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetFields {
+    pub name: String,
+    pub asset_type: AssetType,
+    pub ip_address: Option<String>,
+    pub mac_address: Option<String>,
+    pub operating_system: Option<String>,
+    pub owner: Option<String>,
+    pub location: Option<String>,
+    pub location_id: Option<Uuid>,
+    // Defaults to the site of the IP address
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
+    pub purchase_date: Option<DateTime<Utc>>,
+    pub status: AssetStatus,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct AssetManager {
+    assets_dir: PathBuf,
+    assets: Arc<Mutex<HashMap<Uuid, Asset>>>,
+}
+
+impl AssetManager {
+    pub fn new(assets_dir: &str) -> Result<Self> {
+        let assets_dir = PathBuf::from(assets_dir);
+
+        if !assets_dir.exists() {
+            fs::create_dir_all(&assets_dir)
+                .context(format!("Failed to create assets directory: {:?}", assets_dir))?;
+            info!("Created assets directory: {:?}", assets_dir);
+        }
+
+        let mut assets = HashMap::new();
+
+        for entry in fs::read_dir(&assets_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read asset file: {:?}", path))?;
+            match serde_json::from_str::<Asset>(&contents) {
+                Ok(asset) => {
+                    assets.insert(asset.id, asset);
+                },
+                Err(e) => warn!("Skipping invalid asset file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} assets", assets.len());
+
+        let history_dir = assets_dir.join("history");
+        if !history_dir.exists() {
+            fs::create_dir_all(&history_dir)
+                .context(format!("Failed to create asset history directory: {:?}", history_dir))?;
+        }
+
+        Ok(Self {
+            assets_dir,
+            assets: Arc::new(Mutex::new(assets)),
+        })
+    }
+
+    fn save_asset(&self, asset: &Asset) -> Result<()> {
+        let path = self.assets_dir.join(format!("{}.json", asset.id));
+        let json = serde_json::to_string_pretty(asset)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write asset file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn history_path(&self, id: Uuid) -> PathBuf {
+        self.assets_dir.join("history").join(format!("{}.jsonl", id))
+    }
+
+    // Appended while the asset lock is held, so entries are in mutation order
+    fn record_history(&self,
+                      id: Uuid,
+                      changed_by: &str,
+                      action: AssetChangeAction,
+                      sources: Vec<String>,
+                      changes: Vec<FieldChange>) -> Result<()> {
+        if changes.is_empty() && action == AssetChangeAction::Updated {
+            return Ok(());
+        }
+        let entry = AssetHistoryEntry {
+            id: Uuid::new_v4(),
+            at: Utc::now(),
+            changed_by: changed_by.to_string(),
+            action,
+            sources,
+            changes,
+        };
+        let path = self.history_path(id);
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)
+            .context(format!("Failed to open asset history: {:?}", path))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+            .context(format!("Failed to write asset history: {:?}", path))?;
+        Ok(())
+    }
+
+    // Oldest first; the history outlives a deleted asset
+    pub fn history(&self, id: Uuid) -> Result<Vec<AssetHistoryEntry>> {
+        let path = self.history_path(id);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read asset history: {:?}", path))?;
+        let mut entries = Vec::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping invalid entry in asset history {:?}: {}", path, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn validate(fields: &AssetFields) -> Result<()> {
+        if fields.name.trim().is_empty() {
+            return Err(anyhow!("Asset name cannot be empty"));
+        }
+
+        if let Some(ip) = &fields.ip_address {
+            parse_ip(ip)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn create_asset(&self, fields: AssetFields, created_by: &str) -> Result<Asset> {
+        Self::validate(&fields)?;
+
+        let address_history = fields.ip_address.iter()
+            .map(|address| AddressAssignment {
+                address: address.clone(),
+                assigned_at: Utc::now(),
+                released_at: None,
+            })
+            .collect();
+
+        let asset = Asset {
+            id: Uuid::new_v4(),
+            name: fields.name,
+            asset_type: fields.asset_type,
+            ip_address: fields.ip_address,
+            mac_address: fields.mac_address,
+            operating_system: fields.operating_system,
+            owner: fields.owner,
+            location: fields.location,
+            location_id: fields.location_id,
+            site_id: fields.site_id,
+            purchase_date: fields.purchase_date,
+            status: fields.status,
+            tags: tags::normalize_all(fields.tags),
+            address_history,
+            drift: Vec::new(),
+        };
+
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                self.save_asset(&asset)?;
+                self.record_history(asset.id, created_by, AssetChangeAction::Created, Vec::new(),
+                                    diff(&Map::new(), &as_object(&asset)))?;
+                assets.insert(asset.id, asset.clone());
+                Ok(asset)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    // Recorded in the history as a field-level diff. Pending drift the new values agree
+    // with is resolved.
+    pub fn update_asset(&self, id: Uuid, fields: AssetFields, updated_by: &str) -> Result<Asset> {
+        Self::validate(&fields)?;
+
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                let asset = assets.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Asset not found: {}", id))?;
+                let before = as_object(asset);
+
+                reassign_address(asset, fields.ip_address.as_ref(), Utc::now());
+                asset.name = fields.name;
+                asset.asset_type = fields.asset_type;
+                asset.mac_address = fields.mac_address;
+                asset.operating_system = fields.operating_system;
+                asset.owner = fields.owner;
+                asset.location = fields.location;
+                asset.location_id = fields.location_id;
+                asset.site_id = fields.site_id;
+                asset.purchase_date = fields.purchase_date;
+                asset.status = fields.status;
+                asset.tags = tags::normalize_all(fields.tags);
+                prune_drift(asset);
+
+                let asset = asset.clone();
+                self.save_asset(&asset)?;
+                self.record_history(id, updated_by, AssetChangeAction::Updated, Vec::new(),
+                                    diff(&before, &as_object(&asset)))?;
+                Ok(asset)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    pub fn get_asset(&self, id: Uuid) -> Result<Asset> {
+        match self.assets.lock() {
+            Ok(assets) => {
+                assets.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Asset not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    pub fn get_all_assets(&self) -> Result<Vec<Asset>> {
+        match self.assets.lock() {
+            Ok(assets) => {
+                let mut all: Vec<Asset> = assets.values().cloned().collect();
+                all.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    // The primary's copy of an asset, replacing the local one, see replication
+    pub fn replicate_asset(&self, asset: Asset, replicated_by: &str) -> Result<()> {
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                let (action, before) = match assets.get(&asset.id) {
+                    Some(current) => (AssetChangeAction::Updated, as_object(current)),
+                    None => (AssetChangeAction::Created, Map::new()),
+                };
+                self.save_asset(&asset)?;
+                self.record_history(asset.id, replicated_by, action, vec!["replication".to_string()],
+                                    diff(&before, &as_object(&asset)))?;
+                assets.insert(asset.id, asset);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    pub fn delete_asset(&self, id: Uuid, deleted_by: &str) -> Result<()> {
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                let asset = assets.remove(&id)
+                    .ok_or_else(|| anyhow!("Asset not found: {}", id))?;
+                self.record_history(id, deleted_by, AssetChangeAction::Deleted, Vec::new(),
+                                    diff(&as_object(&asset), &Map::new()))?;
+
+                let path = self.assets_dir.join(format!("{}.json", id));
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .context(format!("Failed to delete asset file: {:?}", path))?;
+                }
+
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    // Compares an observation with the asset it belongs to. Disagreeing fields become
+    // pending drift instead of overwriting the record; the drift that is new or now
+    // observed differently is returned with the asset. Unknown devices are ignored.
+    pub fn observe(&self, observation: &AssetObservation) -> Result<Option<(Asset, Vec<AssetDrift>)>> {
+        let ip = observation.observed_ip()?.map(|ip| ip.to_string());
+        let mac = observation.mac_address.as_deref().map(normalize_mac).filter(|m| !m.is_empty());
+        let now = observation.observed_at.unwrap_or_else(Utc::now);
+
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                let by_mac = mac.as_ref().and_then(|mac| assets.values()
+                    .find(|a| a.mac_address.as_deref().map(normalize_mac).as_ref() == Some(mac))
+                    .map(|a| a.id));
+                let by_ip = || ip.as_ref().and_then(|ip| assets.values()
+                    .find(|a| agrees("ip_address", a.ip_address.as_deref(), ip))
+                    .map(|a| a.id));
+                let Some(id) = by_mac.or_else(by_ip) else {
+                    return Ok(None);
+                };
+                let asset = match assets.get_mut(&id) {
+                    Some(asset) => asset,
+                    None => return Ok(None),
+                };
+
+                let mut raised = Vec::new();
+                let mut changed = false;
+                for &field in DRIFT_FIELDS {
+                    let value = match field {
+                        "ip_address" => ip.clone(),
+                        "mac_address" => mac.clone(),
+                        _ => observation.operating_system.clone(),
+                    };
+                    let Some(value) = value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) else {
+                        continue;
+                    };
+                    let recorded = recorded_value(asset, field);
+                    let pending = asset.drift.iter().position(|d| d.field == field);
+
+                    if agrees(field, recorded.as_deref(), &value) {
+                        if let Some(index) = pending {
+                            asset.drift.remove(index);
+                            changed = true;
+                        }
+                        continue;
+                    }
+
+                    match pending {
+                        Some(index) if agrees(field, Some(&asset.drift[index].observed), &value) => {
+                            asset.drift[index].last_seen = now;
+                            asset.drift[index].source = observation.source.clone();
+                        },
+                        _ => {
+                            let drift = AssetDrift {
+                                field: field.to_string(),
+                                recorded,
+                                observed: value,
+                                source: observation.source.clone(),
+                                first_seen: now,
+                                last_seen: now,
+                            };
+                            if let Some(index) = pending {
+                                asset.drift.remove(index);
+                            }
+                            asset.drift.push(drift.clone());
+                            raised.push(drift);
+                        },
+                    }
+                    changed = true;
+                }
+
+                let asset = asset.clone();
+                if changed {
+                    self.save_asset(&asset)?;
+                }
+                Ok(Some((asset, raised)))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    // Takes the observed values of the given drift fields (all pending ones when empty)
+    // into the record; the change lands in the history like any update
+    pub fn accept_drift(&self, id: Uuid, fields: &[String], accepted_by: &str) -> Result<Asset> {
+        match self.assets.lock() {
+            Ok(mut assets) => {
+                let asset = assets.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Asset not found: {}", id))?;
+                if let Some(unknown) = fields.iter().find(|f| !asset.drift.iter().any(|d| &d.field == *f)) {
+                    return Err(anyhow!("No pending drift on field {}", unknown));
+                }
+                let accepted: Vec<AssetDrift> = asset.drift.iter()
+                    .filter(|d| fields.is_empty() || fields.contains(&d.field))
+                    .cloned()
+                    .collect();
+                if accepted.is_empty() {
+                    return Err(anyhow!("Asset {} has no pending drift", id));
+                }
+                // Drift recorded before observations were validated may hold anything
+                for drift in accepted.iter().filter(|d| d.field == "ip_address") {
+                    parse_ip(&drift.observed)?;
+                }
+
+                let before = as_object(asset);
+                let now = Utc::now();
+                for drift in &accepted {
+                    match drift.field.as_str() {
+                        "ip_address" => reassign_address(asset, Some(&drift.observed), now),
+                        "mac_address" => asset.mac_address = Some(drift.observed.clone()),
+                        "operating_system" => asset.operating_system = Some(drift.observed.clone()),
+                        _ => {},
+                    }
+                }
+                asset.drift.retain(|d| !accepted.iter().any(|a| a.field == d.field));
+                prune_drift(asset);
+
+                let asset = asset.clone();
+                self.save_asset(&asset)?;
+                let mut sources: Vec<String> = accepted.iter().map(|d| d.source.clone()).collect();
+                sources.dedup();
+                self.record_history(id, accepted_by, AssetChangeAction::ObservationAccepted, sources,
+                                    diff(&before, &as_object(&asset)))?;
+                Ok(asset)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+}
+
+impl TaggedStore for AssetManager {
+    fn resource(&self) -> &'static str {
+        "assets"
+    }
+
+    fn tag_counts(&self) -> Result<HashMap<String, usize>> {
+        match self.assets.lock() {
+            Ok(assets) => Ok(tags::count(assets.values().map(|a| &a.tags))),
+            Err(_) => Err(anyhow!("Failed to acquire lock on assets")),
+        }
+    }
+
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        let mut assets = self.assets.lock().map_err(|_| anyhow!("Failed to acquire lock on assets"))?;
+        let mut previous: Vec<(Uuid, Vec<String>)> = Vec::new();
+        let ids: Vec<Uuid> = assets.keys().copied().collect();
+        for id in ids {
+            let mut asset = assets[&id].clone();
+            let Some(tags) = tags::replaced(&asset.tags, from, to) else { continue };
+            let old = std::mem::replace(&mut asset.tags, tags);
+            if let Err(e) = self.save_asset(&asset) {
+                for (id, tags) in &previous {
+                    if let Some(asset) = assets.get_mut(id) {
+                        asset.tags = tags.clone();
+                        if let Err(e) = self.save_asset(asset) {
+                            warn!("Failed to restore the tags of asset {}: {}", id, e);
+                        }
+                    }
+                }
+                return Err(e);
+            }
+            assets.insert(id, asset);
+            previous.push((id, old));
+        }
+        Ok(previous)
+    }
+
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        let mut assets = self.assets.lock().map_err(|_| anyhow!("Failed to acquire lock on assets"))?;
+        for (id, tags) in previous {
+            if let Some(asset) = assets.get_mut(id) {
+                asset.tags = tags.clone();
+                self.save_asset(asset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(ip: &str) -> AssetFields {
+        AssetFields {
+            name: "Fileserver".to_string(),
+            asset_type: AssetType::Server,
+            ip_address: Some(ip.to_string()),
+            mac_address: Some("00:11:22:33:44:55".to_string()),
+            operating_system: None,
+            owner: None,
+            location: None,
+            location_id: None,
+            site_id: None,
+            purchase_date: None,
+            status: AssetStatus::Active,
+            tags: Vec::new(),
+        }
+    }
+
+    fn observation(ip: &str) -> AssetObservation {
+        AssetObservation {
+            source: "dhcp".to_string(),
+            ip_address: Some(ip.to_string()),
+            mac_address: Some("00-11-22-33-44-55".to_string()),
+            operating_system: None,
+            observed_at: None,
+        }
+    }
+
+    #[test]
+    fn unparseable_observed_address_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AssetManager::new(&dir.path().display().to_string()).unwrap();
+        let asset = manager.create_asset(fields("10.0.0.5"), "alice").unwrap();
+
+        for bad in ["-oProxyCommand=touch /tmp/x", "10.0.0.5 -p 2222", "fileserver"] {
+            assert!(manager.observe(&observation(bad)).is_err(), "{:?} accepted", bad);
+        }
+        assert!(manager.get_asset(asset.id).unwrap().drift.is_empty());
+
+        // A valid change is kept as drift in canonical form and can be accepted
+        let (_, raised) = manager.observe(&observation(" 10.0.0.7 ")).unwrap().unwrap();
+        assert_eq!(raised[0].observed, "10.0.0.7");
+        let accepted = manager.accept_drift(asset.id, &[], "alice").unwrap();
+        assert_eq!(accepted.ip_address.as_deref(), Some("10.0.0.7"));
+    }
+
+    #[test]
+    fn stored_drift_with_an_invalid_address_cannot_be_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AssetManager::new(&dir.path().display().to_string()).unwrap();
+        let mut asset = manager.create_asset(fields("10.0.0.5"), "alice").unwrap();
+
+        // As written by a version that did not validate observations
+        asset.drift.push(AssetDrift {
+            field: "ip_address".to_string(),
+            recorded: asset.ip_address.clone(),
+            observed: "-oProxyCommand=touch /tmp/x".to_string(),
+            source: "scan".to_string(),
+            first_seen: Utc::now(),
+            last_seen: Utc::now(),
+        });
+        manager.save_asset(&asset).unwrap();
+        let manager = AssetManager::new(&dir.path().display().to_string()).unwrap();
+
+        assert!(manager.accept_drift(asset.id, &[], "alice").is_err());
+        assert_eq!(manager.get_asset(asset.id).unwrap().ip_address.as_deref(), Some("10.0.0.5"));
+    }
+}
@@ -0,0 +1,318 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::process::Command;
+use anyhow::{Result, Context, anyhow};
+use tracing::warn;
+
+use crate::config::AttachmentScanConfig;
+
+// Bytes read from the start of a file for the type and text checks
+const SNIFF_BYTES: usize = 8192;
+// clamd INSTREAM chunk size, well below its default StreamMaxLength
+const CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Clean,
+    // Not acceptable as uploaded: type not allowed or content not matching it
+    Rejected(String),
+    // The scanner found something; carries the signature name
+    Infected(String),
+    // Scanning did not finish; whether that accepts the file is the fail_open policy
+    Unavailable(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScannerStatus {
+    // clamd, command or none
+    pub scanner: String,
+    pub available: bool,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub fail_open: bool,
+}
+
+fn extension(filename: &str) -> String {
+    Path::new(filename).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+// Leading bytes a file of the extension starts with; None for types without a reliable
+// signature, which are checked as text where that applies
+fn signatures(extension: &str) -> Option<&'static [&'static [u8]]> {
+    let signatures: &'static [&'static [u8]] = match extension {
+        "pdf" => &[b"%PDF-"],
+        "png" => &[b"\x89PNG\r\n\x1a\n"],
+        "jpg" | "jpeg" => &[b"\xff\xd8\xff"],
+        "gif" => &[b"GIF87a", b"GIF89a"],
+        "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" => &[b"PK\x03\x04", b"PK\x05\x06"],
+        "gz" | "tgz" => &[b"\x1f\x8b"],
+        "7z" => &[b"7z\xbc\xaf\x27\x1c"],
+        "pcap" => &[b"\xd4\xc3\xb2\xa1", b"\xa1\xb2\xc3\xd4", b"\x4d\x3c\xb2\xa1", b"\xa1\xb2\x3c\x4d"],
+        "pcapng" => &[b"\x0a\x0d\x0d\x0a"],
+        _ => return None,
+    };
+    Some(signatures)
+}
+
+fn is_text(extension: &str) -> bool {
+    matches!(extension, "txt" | "log" | "csv" | "json" | "xml" | "yaml" | "yml" | "eml" | "md" | "conf" | "ini")
+}
+
+// Media types the client may claim for the extension; application/octet-stream fits any
+fn mime_matches(extension: &str, content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if mime.is_empty() || mime == "application/octet-stream" {
+        return true;
+    }
+    match extension {
+        "pdf" => mime == "application/pdf",
+        "png" => mime == "image/png",
+        "jpg" | "jpeg" => mime == "image/jpeg",
+        "gif" => mime == "image/gif",
+        "zip" => mime == "application/zip" || mime == "application/x-zip-compressed",
+        "docx" | "xlsx" | "pptx" => mime.starts_with("application/vnd.openxmlformats-officedocument."),
+        "odt" | "ods" => mime.starts_with("application/vnd.oasis.opendocument."),
+        "gz" | "tgz" => mime == "application/gzip" || mime == "application/x-gzip",
+        "json" => mime == "application/json" || mime.starts_with("text/"),
+        "xml" => mime == "application/xml" || mime.starts_with("text/"),
+        "eml" => mime == "message/rfc822" || mime.starts_with("text/"),
+        ext if is_text(ext) => mime.starts_with("text/"),
+        // Types without a known mapping are not second-guessed
+        _ => true,
+    }
+}
+
+// Inspects uploaded attachments before they are accepted: name and claimed type, content
+// against the type, then the external scanner when one is configured
+#[derive(Clone)]
+pub struct AttachmentScanner {
+    config: AttachmentScanConfig,
+    status: Arc<Mutex<ScannerStatus>>,
+}
+
+impl AttachmentScanner {
+    pub fn new(config: AttachmentScanConfig) -> Self {
+        let status = ScannerStatus {
+            scanner: config.scanner_name().to_string(),
+            available: config.scanner_name() == "none",
+            checked_at: None,
+            error: None,
+            fail_open: config.fail_open,
+        };
+
+        Self {
+            config,
+            status: Arc::new(Mutex::new(status)),
+        }
+    }
+
+    pub fn fail_open(&self) -> bool {
+        self.config.fail_open
+    }
+
+    // Checks that need no content, so a disallowed file is refused before it is uploaded
+    pub fn check_name(&self, filename: &str, content_type: &str) -> Verdict {
+        let ext = extension(filename);
+        let allowed = &self.config.allowed_extensions;
+        if !allowed.is_empty() && !allowed.iter().any(|a| a.trim_start_matches('.').eq_ignore_ascii_case(&ext)) {
+            return Verdict::Rejected(format!("Attachments of type .{} are not allowed", ext));
+        }
+        if !mime_matches(&ext, content_type) {
+            return Verdict::Rejected(format!("Content type {} does not match a .{} file", content_type, ext));
+        }
+        Verdict::Clean
+    }
+
+    // Full inspection of a stored upload
+    pub async fn scan(&self, path: &Path, filename: &str, content_type: &str) -> Verdict {
+        let verdict = self.check_name(filename, content_type);
+        if verdict != Verdict::Clean {
+            return verdict;
+        }
+
+        if self.config.verify_content {
+            match self.check_content(path, filename).await {
+                Ok(Verdict::Clean) => {},
+                Ok(verdict) => return verdict,
+                Err(e) => return Verdict::Unavailable(e.to_string()),
+            }
+        }
+
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let result = match tokio::time::timeout(timeout, self.run_scanner(path)).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("Scanner did not answer within {}s", self.config.timeout_secs)),
+        };
+        match result {
+            Ok(verdict) => verdict,
+            Err(e) => {
+                warn!("Attachment scan of {} failed: {}", filename, e);
+                self.set_status(Err(e.to_string()));
+                Verdict::Unavailable(e.to_string())
+            },
+        }
+    }
+
+    async fn check_content(&self, path: &Path, filename: &str) -> Result<Verdict> {
+        let mut head = vec![0u8; SNIFF_BYTES];
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut read = 0;
+        while read < head.len() {
+            let n = file.read(&mut head[read..]).await?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        head.truncate(read);
+
+        if head.is_empty() {
+            return Ok(Verdict::Rejected("Attachment is empty".to_string()));
+        }
+
+        let ext = extension(filename);
+        if let Some(signatures) = signatures(&ext) {
+            if !signatures.iter().any(|s| head.starts_with(s)) {
+                return Ok(Verdict::Rejected(format!("Content is not a valid .{} file", ext)));
+            }
+        } else if is_text(&ext) && head.contains(&0) {
+            return Ok(Verdict::Rejected(format!("Content is not a valid .{} file", ext)));
+        }
+        Ok(Verdict::Clean)
+    }
+
+    async fn run_scanner(&self, path: &Path) -> Result<Verdict> {
+        if let Some(socket) = &self.config.clamd_socket {
+            self.scan_clamd(socket, path).await
+        } else if !self.config.command.is_empty() {
+            self.scan_command(path).await
+        } else {
+            Ok(Verdict::Clean)
+        }
+    }
+
+    // clamd INSTREAM: length-prefixed chunks ending with a zero length, answered with
+    // "stream: OK" or "stream: <signature> FOUND"
+    async fn scan_clamd(&self, socket: &str, path: &Path) -> Result<Verdict> {
+        let mut stream = UnixStream::connect(socket).await
+            .context(format!("Failed to connect to clamd at {}", socket))?;
+        stream.write_all(b"zINSTREAM\0").await?;
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut chunk = vec![0u8; CHUNK_BYTES];
+        loop {
+            let n = file.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&(n as u32).to_be_bytes()).await?;
+            stream.write_all(&chunk[..n]).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        let reply = String::from_utf8_lossy(&reply).trim_end_matches('\0').trim().to_string();
+        self.set_status(Ok(()));
+
+        if reply.ends_with("OK") {
+            Ok(Verdict::Clean)
+        } else if let Some(found) = reply.strip_suffix("FOUND") {
+            let signature = found.trim().trim_start_matches("stream:").trim();
+            Ok(Verdict::Infected(signature.to_string()))
+        } else {
+            Err(anyhow!("Unexpected clamd reply: {}", reply))
+        }
+    }
+
+    // Command gets the file on stdin; exit 0 is clean and 1 infected, as with clamscan
+    async fn scan_command(&self, path: &Path) -> Result<Verdict> {
+        let mut child = Command::new(&self.config.command[0])
+            .args(&self.config.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("Failed to start scanner {}", self.config.command[0]))?;
+
+        let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("Scanner has no stdin"))?;
+        let mut file = tokio::fs::File::open(path).await?;
+        tokio::io::copy(&mut file, &mut stdin).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        self.set_status(Ok(()));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        match output.status.code() {
+            Some(0) => Ok(Verdict::Clean),
+            Some(1) => {
+                // clamscan prints "stream: <signature> FOUND"
+                let signature = stdout.lines()
+                    .find_map(|line| line.strip_suffix("FOUND"))
+                    .map(|found| found.rsplit(':').next().unwrap_or(found).trim().to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                Ok(Verdict::Infected(signature))
+            },
+            code => Err(anyhow!("Scanner exited with {:?}", code)),
+        }
+    }
+
+    // Availability for /api/health: clamd must answer PING, a command must exist
+    pub async fn probe(&self) {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let result = if let Some(socket) = &self.config.clamd_socket {
+            match tokio::time::timeout(timeout, Self::ping_clamd(socket)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("clamd did not answer PING within {}s", self.config.timeout_secs)),
+            }
+        } else if let Some(program) = self.config.command.first() {
+            match tokio::time::timeout(timeout, Command::new(program).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status()).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(anyhow!("Scanner {} cannot be run: {}", program, e)),
+                Err(_) => Err(anyhow!("Scanner {} did not start within {}s", program, self.config.timeout_secs)),
+            }
+        } else {
+            Ok(())
+        };
+        self.set_status(result.map_err(|e| e.to_string()));
+    }
+
+    async fn ping_clamd(socket: &str) -> Result<()> {
+        let mut stream = UnixStream::connect(socket).await
+            .context(format!("Failed to connect to clamd at {}", socket))?;
+        stream.write_all(b"zPING\0").await?;
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        if String::from_utf8_lossy(&reply).trim_end_matches('\0').trim() == "PONG" {
+            Ok(())
+        } else {
+            Err(anyhow!("Unexpected clamd reply to PING"))
+        }
+    }
+
+    fn set_status(&self, result: std::result::Result<(), String>) {
+        if let Ok(mut status) = self.status.lock() {
+            status.available = result.is_ok();
+            status.error = result.err();
+            status.checked_at = Some(Utc::now());
+        }
+    }
+
+    pub fn status(&self) -> Result<ScannerStatus> {
+        match self.status.lock() {
+            Ok(status) => Ok(status.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on scanner status")),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+
+use crate::security::{AuditEvent, AuditStatus};
+
+// Hash the first event is chained to
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const EVENTS_FILE: &str = "audit.jsonl";
+const CHECKPOINT_FILE: &str = "chain_head.json";
+
+// The hashed part of an event, in a fixed field order
+#[derive(Serialize)]
+struct CanonicalEvent<'a> {
+    id: &'a Uuid,
+    timestamp: &'a DateTime<Utc>,
+    user: &'a str,
+    action: &'a str,
+    resource: &'a str,
+    status: &'a AuditStatus,
+    details: &'a Option<String>,
+    parent_id: &'a Option<Uuid>,
+    // Left out when absent, so events from before correlation ids keep their hashes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<&'a String>,
+}
+
+// SHA-256 of the previous hash followed by the canonical JSON of the event
+pub fn chain_hash(prev_hash: &str, event: &AuditEvent) -> String {
+    let canonical = serde_json::to_string(&CanonicalEvent {
+        id: &event.id,
+        timestamp: &event.timestamp,
+        user: &event.user,
+        action: &event.action,
+        resource: &event.resource,
+        status: &event.status,
+        details: &event.details,
+        parent_id: &event.parent_id,
+        correlation_id: event.correlation_id.as_ref(),
+    }).unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(canonical.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+// Chain head as last written to the checkpoint file, kept apart from the events so
+// truncating or rewriting the event file is detected too
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainCheckpoint {
+    pub events: u64,
+    pub head: String,
+    pub written_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainBreak {
+    // Zero-based position in the event file
+    pub index: u64,
+    pub event_id: Option<Uuid>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub events: u64,
+    pub head: String,
+    pub first_break: Option<ChainBreak>,
+    pub checkpoint: Option<ChainCheckpoint>,
+    pub verified_at: DateTime<Utc>,
+}
+
+// Append-only, hash-chained audit event file
+pub struct AuditChain {
+    dir: PathBuf,
+    file: File,
+    head: String,
+    events: u64,
+}
+
+impl AuditChain {
+    // Opens the chain and returns the stored events; the chain continues from the last
+    // stored hash, verification is a separate step
+    pub fn open(dir: &str) -> Result<(Self, Vec<AuditEvent>)> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create audit directory: {:?}", dir))?;
+            info!("Created audit directory: {:?}", dir);
+        }
+
+        let path = dir.join(EVENTS_FILE);
+        let mut events = Vec::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)
+                .context(format!("Failed to open audit log: {:?}", path))?);
+            for (index, line) in reader.lines().enumerate() {
+                match serde_json::from_str::<AuditEvent>(&line?) {
+                    Ok(event) => events.push(event),
+                    Err(e) => warn!("Unreadable audit event at line {}: {}", index + 1, e),
+                }
+            }
+        }
+
+        let head = events.last()
+            .map(|event: &AuditEvent| event.hash.clone())
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .context(format!("Failed to open audit log: {:?}", path))?;
+
+        info!("Loaded {} audit events, chain head {}", events.len(), head);
+
+        Ok((Self {
+            dir,
+            file,
+            head,
+            events: events.len() as u64,
+        }, events))
+    }
+
+    // Links the event to the chain and writes it. Callers serialize appends, otherwise
+    // two events could claim the same predecessor.
+    pub fn append(&mut self, event: &mut AuditEvent) -> Result<()> {
+        event.prev_hash = self.head.clone();
+        event.hash = chain_hash(&self.head, event);
+
+        let line = serde_json::to_string(event)?;
+        writeln!(self.file, "{}", line)
+            .context("Failed to write audit event")?;
+        self.file.flush()?;
+
+        self.head = event.hash.clone();
+        self.events += 1;
+        Ok(())
+    }
+
+    pub fn checkpoint(&self) -> Result<ChainCheckpoint> {
+        let checkpoint = ChainCheckpoint {
+            events: self.events,
+            head: self.head.clone(),
+            written_at: Utc::now(),
+        };
+
+        let path = self.dir.join(CHECKPOINT_FILE);
+        fs::write(&path, serde_json::to_string_pretty(&checkpoint)?)
+            .context(format!("Failed to write audit checkpoint: {:?}", path))?;
+        Ok(checkpoint)
+    }
+
+    // The chain as written so far, to verify without holding up appends
+    pub fn snapshot(&mut self) -> Result<ChainSnapshot> {
+        self.file.flush()?;
+        let checkpoint = match fs::read_to_string(self.dir.join(CHECKPOINT_FILE)) {
+            Ok(contents) => Some(serde_json::from_str::<ChainCheckpoint>(&contents)
+                .context("Failed to parse audit checkpoint")?),
+            Err(_) => None,
+        };
+
+        Ok(ChainSnapshot {
+            dir: self.dir.clone(),
+            events: self.events,
+            head: self.head.clone(),
+            checkpoint,
+        })
+    }
+}
+
+// Events written and the head when the snapshot was taken; events appended since are
+// not verified
+pub struct ChainSnapshot {
+    dir: PathBuf,
+    events: u64,
+    head: String,
+    checkpoint: Option<ChainCheckpoint>,
+}
+
+impl ChainSnapshot {
+    // Re-reads the stored events and recomputes every link
+    pub fn verify(self) -> Result<ChainVerification> {
+        verify_dir(&self.dir, self.events, &self.head, self.checkpoint)
+    }
+}
+
+fn verify_dir(dir: &Path, written: u64, written_head: &str, checkpoint: Option<ChainCheckpoint>) -> Result<ChainVerification> {
+    let mut head = GENESIS_HASH.to_string();
+    let mut events = 0u64;
+    let mut first_break = None;
+    let mut checkpoint_hash = None;
+
+    let path = dir.join(EVENTS_FILE);
+    if path.exists() {
+        let reader = BufReader::new(File::open(&path)
+            .context(format!("Failed to open audit log: {:?}", path))?);
+
+        for line in reader.lines().take(written as usize) {
+            let line = line?;
+            let index = events;
+            events += 1;
+
+            let event = match serde_json::from_str::<AuditEvent>(&line) {
+                Ok(event) => event,
+                Err(e) => {
+                    first_break = Some(ChainBreak { index, event_id: None, reason: format!("Unreadable event: {}", e) });
+                    break;
+                },
+            };
+
+            let reason = if event.prev_hash != head {
+                Some("Previous hash does not match the preceding event")
+            } else if event.hash != chain_hash(&head, &event) {
+                Some("Event content does not match its hash")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                first_break = Some(ChainBreak { index, event_id: Some(event.id), reason: reason.to_string() });
+                break;
+            }
+
+            head = event.hash;
+            if checkpoint.as_ref().map_or(false, |c| c.events == events) {
+                checkpoint_hash = Some(head.clone());
+            }
+        }
+    }
+
+    // Everything written must still be there, ending in the head appended last
+    if first_break.is_none() && (events < written || head != written_head) {
+        first_break = Some(ChainBreak {
+            index: events,
+            event_id: None,
+            reason: format!("Event log does not reproduce the head after {} written events", written),
+        });
+    }
+
+    // Everything up to the checkpoint must still be there, unchanged
+    if first_break.is_none() {
+        if let Some(checkpoint) = &checkpoint {
+            if checkpoint.events > 0 && checkpoint_hash.as_deref() != Some(checkpoint.head.as_str()) {
+                first_break = Some(ChainBreak {
+                    index: checkpoint.events.min(events),
+                    event_id: None,
+                    reason: format!("Event log does not reproduce the checkpointed head after {} events", checkpoint.events),
+                });
+            }
+        }
+    }
+
+    Ok(ChainVerification {
+        valid: first_break.is_none(),
+        events,
+        head,
+        first_break,
+        checkpoint,
+        verified_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(action: &str) -> AuditEvent {
+        AuditEvent {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            user: "alice".to_string(),
+            action: action.to_string(),
+            resource: "ticket".to_string(),
+            status: AuditStatus::Success,
+            details: None,
+            parent_id: None,
+            correlation_id: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    fn chain(dir: &tempfile::TempDir, actions: &[&str]) -> AuditChain {
+        let (mut chain, _) = AuditChain::open(dir.path().to_str().unwrap()).unwrap();
+        for action in actions {
+            chain.append(&mut event(action)).unwrap();
+        }
+        chain
+    }
+
+    fn rewrite(dir: &tempfile::TempDir, change: impl FnOnce(&mut Vec<String>)) {
+        let path = dir.path().join(EVENTS_FILE);
+        let mut lines: Vec<String> = fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        change(&mut lines);
+        fs::write(&path, lines.iter().map(|l| format!("{}\n", l)).collect::<String>()).unwrap();
+    }
+
+    #[test]
+    fn an_untouched_chain_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = chain(&dir, &["login", "update_ticket", "logout"]);
+        chain.checkpoint().unwrap();
+        chain.append(&mut event("login")).unwrap();
+
+        let verification = chain.snapshot().unwrap().verify().unwrap();
+        assert!(verification.valid, "{:?}", verification.first_break);
+        assert_eq!(verification.events, 4);
+
+        // Reopened, the chain continues where it ended
+        drop(chain);
+        let (mut chain, events) = AuditChain::open(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(events.len(), 4);
+        chain.append(&mut event("logout")).unwrap();
+        assert!(chain.snapshot().unwrap().verify().unwrap().valid);
+    }
+
+    #[test]
+    fn edited_events_break_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = chain(&dir, &["login", "update_ticket", "logout"]);
+        rewrite(&dir, |lines| lines[1] = lines[1].replace("update_ticket", "read_ticket"));
+
+        let verification = chain.snapshot().unwrap().verify().unwrap();
+        assert!(!verification.valid);
+        let first_break = verification.first_break.unwrap();
+        assert_eq!(first_break.index, 1);
+        assert_eq!(first_break.reason, "Event content does not match its hash");
+
+        // Dropping an event from the middle breaks the link of the next one
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = self::chain(&dir, &["login", "update_ticket", "logout"]);
+        rewrite(&dir, |lines| { lines.remove(1); });
+        let first_break = chain.snapshot().unwrap().verify().unwrap().first_break.unwrap();
+        assert_eq!(first_break.index, 1);
+        assert_eq!(first_break.reason, "Previous hash does not match the preceding event");
+    }
+
+    #[test]
+    fn truncation_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = chain(&dir, &["login", "update_ticket", "logout"]);
+        rewrite(&dir, |lines| { lines.pop(); });
+        let verification = chain.snapshot().unwrap().verify().unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_break.unwrap().index, 2);
+
+        // After a restart only the checkpoint remembers how long the log was
+        let dir = tempfile::tempdir().unwrap();
+        let chain = self::chain(&dir, &["login", "update_ticket", "logout"]);
+        chain.checkpoint().unwrap();
+        drop(chain);
+        rewrite(&dir, |lines| { lines.pop(); });
+        let (mut chain, _) = AuditChain::open(dir.path().to_str().unwrap()).unwrap();
+        let verification = chain.snapshot().unwrap().verify().unwrap();
+        assert!(!verification.valid);
+        assert!(verification.first_break.unwrap().reason.contains("checkpointed head after 3 events"));
+    }
+
+    #[test]
+    fn events_appended_after_the_snapshot_are_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = chain(&dir, &["login", "update_ticket"]);
+        let snapshot = chain.snapshot().unwrap();
+        chain.append(&mut event("logout")).unwrap();
+
+        let verification = snapshot.verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.events, 2);
+    }
+}
@@ -0,0 +1,192 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::{AUTHORIZATION, USER_AGENT}, request::Parts, Method, StatusCode},
+};
+use std::net::{IpAddr, SocketAddr};
+use ipnetwork::IpNetwork;
+use uuid::Uuid;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use anyhow::Result;
+
+use crate::api::AppState;
+use crate::config::SecurityConfig;
+use crate::models::UserRole;
+use crate::sessions::Session;
+use crate::sites::SiteScope;
+
+// JWT claims issued to API clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: UserRole,
+    pub sid: Uuid,
+    pub exp: i64,
+}
+
+// Authenticated caller, extracted from the Authorization bearer token
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub username: String,
+    pub role: UserRole,
+    pub session_id: Uuid,
+    pub sites: Vec<Uuid>,
+}
+
+impl AuthUser {
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
+    }
+
+    pub fn is_staff(&self) -> bool {
+        matches!(self.role, UserRole::Admin | UserRole::Technician)
+    }
+
+    pub fn site_scope(&self) -> SiteScope {
+        SiteScope::new(&self.sites)
+    }
+}
+
+// Issues the bearer token for a session; the token expires together with the session
+pub fn issue_token(config: &SecurityConfig, session: &Session) -> Result<String> {
+    let claims = Claims {
+        sub: session.username.clone(),
+        role: session.role.clone(),
+        sid: session.id,
+        exp: session.expires_at.timestamp(),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )?;
+
+    Ok(token)
+}
+
+pub fn verify_token(config: &SecurityConfig, token: &str) -> Result<Claims> {
+    let data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+        &Validation::default(),
+    )?;
+
+    Ok(data.claims)
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let token = parts.headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let claims = verify_token(&state.config.security, token)
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // A valid signature is not enough, the session must not have been revoked
+        let session = state.session_manager.touch(claims.sid)
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // Sessions opened with an expired or reset password only reach the password change
+        if session.password_change_required
+            && !(parts.method == Method::PUT && parts.uri.path() == format!("/api/users/{}/password", claims.sub))
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(AuthUser {
+            username: claims.sub,
+            role: claims.role,
+            session_id: claims.sid,
+            sites: session.sites,
+        })
+    }
+}
+
+// Source address and user agent of the client, recorded on its session
+pub struct ClientInfo {
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// The connecting address, or when that is a trusted proxy the nearest address in
+// X-Forwarded-For that is not. Each proxy appends the address it was reached from, so
+// the list is read from the right; anything left of an untrusted hop could be forged.
+pub fn client_address(peer: IpAddr, forwarded: Option<&str>, trusted_proxies: &[String]) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter()
+        .filter_map(|proxy| proxy.parse::<IpNetwork>().ok())
+        .any(|network| network.contains(ip));
+
+    let mut client = peer;
+    if !trusted(peer) {
+        return client;
+    }
+    for hop in forwarded.unwrap_or_default().rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted(ip) {
+                    break;
+                }
+            },
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientInfo {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let forwarded = parts.headers
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok());
+        let source_ip = parts.extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| client_address(addr.ip(), forwarded, &state.config.security.trusted_proxies))
+            .map(|ip| ip.to_string());
+
+        let user_agent = parts.headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        Ok(ClientInfo { source_ip, user_agent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(peer: &str, forwarded: Option<&str>, trusted_proxies: &[&str]) -> String {
+        let trusted_proxies: Vec<String> = trusted_proxies.iter().map(|proxy| proxy.to_string()).collect();
+        client_address(peer.parse().unwrap(), forwarded, &trusted_proxies).to_string()
+    }
+
+    #[test]
+    fn forwarded_for_is_only_believed_from_trusted_proxies() {
+        assert_eq!(address("198.51.100.7", Some("203.0.113.9"), &[]), "198.51.100.7");
+        assert_eq!(address("198.51.100.7", Some("203.0.113.9"), &["10.0.0.0/8"]), "198.51.100.7");
+        assert_eq!(address("10.0.0.2", Some("203.0.113.9"), &["10.0.0.0/8"]), "203.0.113.9");
+        assert_eq!(address("10.0.0.2", None, &["10.0.0.2"]), "10.0.0.2");
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right_up_to_the_first_untrusted_hop() {
+        // The client forged the first entry, the proxies appended the rest
+        let forwarded = Some("192.0.2.1, 203.0.113.9, 10.0.0.3");
+        assert_eq!(address("10.0.0.2", forwarded, &["10.0.0.0/8"]), "203.0.113.9");
+        assert_eq!(address("10.0.0.2", Some("not-an-address, 10.0.0.3"), &["10.0.0.0/8"]), "10.0.0.3");
+    }
+}
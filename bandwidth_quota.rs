@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::{BandwidthQuotaConfig, InterfaceQuotaConfig, QuotaActionConfig};
+use crate::models::AlertSeverity;
+use crate::remediation::{RemediationBinding, RemediationManager};
+use crate::scripts::ScriptsManager;
+
+// Source of the alerts raised here; remediation bindings can attach to it as well
+pub const BANDWIDTH_SOURCE: &str = "bandwidth_quota";
+
+// Finished cycles kept per interface
+const MAX_ARCHIVED_CYCLES: usize = 36;
+
+// Start of the billing cycle holding `date`; start days past 28 are clamped so every
+// month has one
+fn cycle_start(date: NaiveDate, start_day: u32) -> NaiveDate {
+    let day = start_day.clamp(1, 28);
+    let this_month = date.with_day(day).unwrap_or(date);
+    if date.day() >= day {
+        this_month
+    } else {
+        previous_month(this_month)
+    }
+}
+
+fn previous_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 1 { (date.year() - 1, 12) } else { (date.year(), date.month() - 1) };
+    NaiveDate::from_ymd_opt(year, month, date.day()).unwrap_or(date)
+}
+
+fn next_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, date.day()).unwrap_or(date)
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+// Bytes counted during the current cycle of an interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CycleCounter {
+    start: NaiveDate,
+    // When counting began: the cycle start, or the first sample when tracking started
+    // partway through the cycle
+    counted_since: DateTime<Utc>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    alerted_percents: Vec<u8>,
+    exceeded_alerted: bool,
+}
+
+impl CycleCounter {
+    fn new(start: NaiveDate, counted_since: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            counted_since,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            alerted_percents: Vec::new(),
+            exceeded_alerted: false,
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.rx_bytes + self.tx_bytes
+    }
+}
+
+// Total of a finished billing cycle, listed in the chargeback report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedCycle {
+    pub interface: String,
+    pub cycle_start: NaiveDate,
+    // Exclusive
+    pub cycle_end: NaiveDate,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub total_bytes: u64,
+    pub quota_bytes: u64,
+    pub counted_since: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct QuotaState {
+    current: HashMap<String, CycleCounter>,
+    archive: Vec<ArchivedCycle>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InterfaceUsage {
+    pub interface: String,
+    pub cycle_start: NaiveDate,
+    pub cycle_end: NaiveDate,
+    pub quota_bytes: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub used_bytes: u64,
+    pub used_percent: f64,
+    // Usage at the end of the cycle at the month-to-date rate; None before any time has
+    // been counted
+    pub projected_bytes: Option<u64>,
+    pub projected_percent: Option<f64>,
+    pub counted_since: Option<DateTime<Utc>>,
+    pub exceeded: bool,
+}
+
+// A threshold crossed since the last check, alerted once the state lock is released
+enum QuotaEvent {
+    Threshold(InterfaceUsage, u8),
+    Exceeded(InterfaceUsage),
+}
+
+fn percent(used: u64, quota: u64) -> f64 {
+    if quota == 0 {
+        0.0
+    } else {
+        used as f64 * 100.0 / quota as f64
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// Monthly transfer counters of metered interfaces, fed with the counter deltas of each
+// traffic collection. Counters are saved with every check, so a restart loses at most
+// one check interval; interface counter resets leave a gap, as in the traffic history.
+#[derive(Clone)]
+pub struct BandwidthQuotas {
+    config: BandwidthQuotaConfig,
+    path: PathBuf,
+    state: Arc<Mutex<QuotaState>>,
+}
+
+impl BandwidthQuotas {
+    pub fn new(config: BandwidthQuotaConfig, dir: &str) -> Result<Self> {
+        for quota in &config.interfaces {
+            if quota.quota_bytes == 0 {
+                return Err(anyhow!("Bandwidth quota of {} must be above zero", quota.interface));
+            }
+            if !(1..=28).contains(&quota.cycle_start_day) {
+                return Err(anyhow!("Billing cycle of {} must start on day 1 to 28", quota.interface));
+            }
+        }
+
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create bandwidth quota directory: {:?}", dir))?;
+        }
+        let path = dir.join("usage.json");
+        let state = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .context(format!("Failed to read bandwidth usage: {:?}", path))?;
+            serde_json::from_str(&content)
+                .context(format!("Failed to parse bandwidth usage: {:?}", path))?
+        } else {
+            QuotaState::default()
+        };
+
+        Ok(Self {
+            config,
+            path,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.config.interfaces.is_empty()
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.check_interval_secs.max(1))
+    }
+
+    fn quota(&self, interface: &str) -> Option<&InterfaceQuotaConfig> {
+        self.config.interfaces.iter().find(|q| q.interface == interface)
+    }
+
+    fn save(&self, state: &QuotaState) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string(state)?)
+            .context(format!("Failed to write bandwidth usage: {:?}", self.path))?;
+        Ok(())
+    }
+
+    // Archives the counter when `at` is past the end of its cycle, then returns the
+    // counter of the current cycle
+    fn counter<'a>(state: &'a mut QuotaState, quota: &InterfaceQuotaConfig, at: DateTime<Utc>) -> &'a mut CycleCounter {
+        let start = cycle_start(at.date_naive(), quota.cycle_start_day);
+        let stale = state.current.get(&quota.interface).map_or(false, |c| c.start < start);
+        if stale {
+            if let Some(finished) = state.current.remove(&quota.interface) {
+                info!("Billing cycle of {} from {} closed at {}", quota.interface, finished.start, format_bytes(finished.used()));
+                state.archive.push(ArchivedCycle {
+                    interface: quota.interface.clone(),
+                    cycle_start: finished.start,
+                    cycle_end: next_month(finished.start),
+                    rx_bytes: finished.rx_bytes,
+                    tx_bytes: finished.tx_bytes,
+                    total_bytes: finished.used(),
+                    quota_bytes: quota.quota_bytes,
+                    counted_since: finished.counted_since,
+                });
+                let kept = state.archive.iter().filter(|c| c.interface == quota.interface).count();
+                if kept > MAX_ARCHIVED_CYCLES {
+                    if let Some(oldest) = state.archive.iter().position(|c| c.interface == quota.interface) {
+                        state.archive.remove(oldest);
+                    }
+                }
+            }
+            // A cycle that follows a tracked one is counted from its start
+            state.current.insert(quota.interface.clone(), CycleCounter::new(start, midnight(start)));
+        }
+        state.current.entry(quota.interface.clone()).or_insert_with(|| CycleCounter::new(start, at))
+    }
+
+    // Adds bytes seen on an interface since the previous collection
+    pub fn record(&self, interface: &str, at: DateTime<Utc>, rx_bytes: u64, tx_bytes: u64) -> Result<()> {
+        let Some(quota) = self.quota(interface) else { return Ok(()) };
+        let mut state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on bandwidth usage"))?;
+        let counter = Self::counter(&mut state, quota, at);
+        counter.rx_bytes += rx_bytes;
+        counter.tx_bytes += tx_bytes;
+        Ok(())
+    }
+
+    fn describe(quota: &InterfaceQuotaConfig, counter: &CycleCounter, now: DateTime<Utc>) -> InterfaceUsage {
+        let used = counter.used();
+        let end = next_month(counter.start);
+        let elapsed = (now - counter.counted_since).num_seconds();
+        let remaining = (midnight(end) - now).num_seconds().max(0);
+        let projected_bytes = (elapsed > 0)
+            .then(|| used + (used as f64 / elapsed as f64 * remaining as f64) as u64);
+
+        InterfaceUsage {
+            interface: quota.interface.clone(),
+            cycle_start: counter.start,
+            cycle_end: end,
+            quota_bytes: quota.quota_bytes,
+            rx_bytes: counter.rx_bytes,
+            tx_bytes: counter.tx_bytes,
+            used_bytes: used,
+            used_percent: percent(used, quota.quota_bytes),
+            projected_bytes,
+            projected_percent: projected_bytes.map(|p| percent(p, quota.quota_bytes)),
+            counted_since: Some(counter.counted_since),
+            exceeded: used > quota.quota_bytes,
+        }
+    }
+
+    // Current cycle of every configured interface
+    pub fn usage(&self) -> Result<Vec<InterfaceUsage>> {
+        let now = Utc::now();
+        let state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on bandwidth usage"))?;
+
+        Ok(self.config.interfaces.iter()
+            .map(|quota| {
+                let start = cycle_start(now.date_naive(), quota.cycle_start_day);
+                match state.current.get(&quota.interface).filter(|c| c.start == start) {
+                    Some(counter) => Self::describe(quota, counter, now),
+                    // Nothing counted yet in this cycle
+                    None => {
+                        let mut usage = Self::describe(quota, &CycleCounter::new(start, now), now);
+                        usage.projected_bytes = None;
+                        usage.projected_percent = None;
+                        usage.counted_since = None;
+                        usage
+                    },
+                }
+            })
+            .collect())
+    }
+
+    // Finished cycles overlapping the dates, inclusive
+    pub fn archived_between(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<ArchivedCycle>> {
+        let state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on bandwidth usage"))?;
+        Ok(state.archive.iter()
+            .filter(|c| c.cycle_start <= to && c.cycle_end > from)
+            .cloned()
+            .collect())
+    }
+
+    // Rolls over finished cycles, raises the alerts of crossed thresholds and saves the
+    // counters
+    pub async fn check(&self, alerts: &AlertsManager, remediation: &RemediationManager, scripts: &Arc<Mutex<ScriptsManager>>) -> Result<()> {
+        let now = Utc::now();
+        let mut thresholds: Vec<u8> = self.config.alert_percents.iter().copied().filter(|p| *p > 0 && *p < 100).collect();
+        thresholds.sort_unstable();
+
+        let events = {
+            let mut state = self.state.lock()
+                .map_err(|_| anyhow!("Failed to acquire lock on bandwidth usage"))?;
+            let mut events = Vec::new();
+            for quota in &self.config.interfaces {
+                let counter = Self::counter(&mut state, quota, now);
+                let usage = Self::describe(quota, counter, now);
+
+                if usage.exceeded {
+                    if !counter.exceeded_alerted {
+                        counter.exceeded_alerted = true;
+                        counter.alerted_percents = thresholds.clone();
+                        events.push(QuotaEvent::Exceeded(usage));
+                    }
+                    continue;
+                }
+                // Only the highest threshold crossed since the last check is alerted
+                let crossed: Vec<u8> = thresholds.iter().copied()
+                    .filter(|p| usage.used_percent >= *p as f64 && !counter.alerted_percents.contains(p))
+                    .collect();
+                if let Some(highest) = crossed.last().copied() {
+                    counter.alerted_percents.extend(crossed);
+                    events.push(QuotaEvent::Threshold(usage, highest));
+                }
+            }
+            self.save(&state)?;
+            events
+        };
+
+        for event in events {
+            self.handle(event, alerts, remediation, scripts).await;
+        }
+        Ok(())
+    }
+
+    fn raise(&self, alerts: &AlertsManager, severity: AlertSeverity, title: String, description: String) -> Option<Uuid> {
+        match alerts.create_alert(severity, title.clone(), description, BANDWIDTH_SOURCE.to_string(), Vec::new()) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                error!("Failed to raise bandwidth alert {}: {}", title, e);
+                None
+            },
+        }
+    }
+
+    async fn handle(&self, event: QuotaEvent, alerts: &AlertsManager, remediation: &RemediationManager, scripts: &Arc<Mutex<ScriptsManager>>) {
+        match event {
+            QuotaEvent::Threshold(usage, threshold) => {
+                self.raise(
+                    alerts,
+                    if threshold >= 90 { AlertSeverity::High } else { AlertSeverity::Medium },
+                    format!("{} used {}% of its monthly quota", usage.interface, threshold),
+                    format!("{} has used {} of its {} allowance ({:.1}%) since {}; at the current rate it will reach {} by {}.",
+                            usage.interface,
+                            format_bytes(usage.used_bytes),
+                            format_bytes(usage.quota_bytes),
+                            usage.used_percent,
+                            usage.cycle_start,
+                            usage.projected_bytes.map_or_else(|| "unknown".to_string(), format_bytes),
+                            usage.cycle_end),
+                );
+            },
+            QuotaEvent::Exceeded(usage) => {
+                let action = self.config.exceeded_action.as_ref();
+                let Some(alert_id) = self.raise(
+                    alerts,
+                    AlertSeverity::Critical,
+                    format!("{} exceeded its monthly quota", usage.interface),
+                    format!("{} has used {} of its {} allowance since {}{}.",
+                            usage.interface,
+                            format_bytes(usage.used_bytes),
+                            format_bytes(usage.quota_bytes),
+                            usage.cycle_start,
+                            action.map(|a| format!(", running script \"{}\"", a.script)).unwrap_or_default()),
+                ) else {
+                    return;
+                };
+                if let Some(action) = action {
+                    self.run_action(&usage, action, alert_id, alerts, remediation, scripts).await;
+                }
+            },
+        }
+    }
+
+    async fn run_action(&self,
+                        usage: &InterfaceUsage,
+                        action: &QuotaActionConfig,
+                        alert_id: Uuid,
+                        alerts: &AlertsManager,
+                        remediation: &RemediationManager,
+                        scripts: &Arc<Mutex<ScriptsManager>>) {
+        let alert = match alerts.get_alert(alert_id) {
+            Ok(alert) => alert,
+            Err(e) => {
+                error!("Failed to load bandwidth alert {}: {}", alert_id, e);
+                return;
+            },
+        };
+
+        // An unknown name runs as the nil script and fails like any missing script
+        let script_id = match scripts.lock() {
+            Ok(scripts) => scripts.get_all_scripts().into_iter()
+                .find(|s| s.name == action.script)
+                .map(|s| s.id)
+                .unwrap_or_else(Uuid::nil),
+            Err(_) => Uuid::nil(),
+        };
+
+        let now = Utc::now();
+        let binding = RemediationBinding {
+            id: Uuid::nil(),
+            name: format!("bandwidth_quota:{}", usage.interface),
+            alert_source: BANDWIDTH_SOURCE.to_string(),
+            severities: Vec::new(),
+            script_id,
+            arguments: action.arguments.clone(),
+            cooldown_secs: 0,
+            dry_run: action.dry_run,
+            enabled: true,
+            created_by: "config".to_string(),
+            created_at: now,
+            updated_at: now,
+            last_fired_at: Some(now),
+        };
+
+        let mut context = HashMap::new();
+        context.insert("quota.interface".to_string(), usage.interface.clone());
+        context.insert("quota.used_bytes".to_string(), usage.used_bytes.to_string());
+        context.insert("quota.quota_bytes".to_string(), usage.quota_bytes.to_string());
+        remediation.run_hook(&alert, &binding, context).await;
+    }
+}
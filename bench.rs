@@ -0,0 +1,659 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, anyhow};
+use chrono::{SecondsFormat, Utc};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::MissedTickBehavior;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, pki_types::{CertificateDer, ServerName, pem::PemObject}};
+use tokio_rustls::TlsConnector;
+use uuid::Uuid;
+
+use crate::classification;
+use crate::config;
+use crate::models::UserRole;
+use crate::password_policy::PasswordPolicy;
+use crate::syslog::parse_syslog;
+use crate::users::UserManager;
+
+// Source of every generated JSON event, so bench traffic can be told apart
+pub const BENCH_SOURCE: &str = "siem-bench";
+
+// Bearer token for --url; kept off the command line, where other users can read it
+pub const TOKEN_VAR: &str = "SIEM_BENCH_TOKEN";
+
+const BENCH_USERNAME: &str = "bench";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A probe event not queryable after this long counts as lost
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+const PROBE_POLL: Duration = Duration::from_millis(5);
+// Pause between probes; one probe is in flight at a time, so polling adds little load
+const PROBE_PAUSE: Duration = Duration::from_millis(100);
+
+const STAGE_METRIC: &str = "siem_ingest_stage_seconds_bucket";
+
+#[derive(Subcommand)]
+pub enum BenchCommand {
+    #[command(about = "Generate events at a fixed rate and report throughput, latency and errors")]
+    Ingest(IngestArgs),
+    #[command(about = "Time syslog parsing and classification of generated events, without a server")]
+    Parse(ParseArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventFormat {
+    // POSTed to /api/logs/ingest
+    Json,
+    // RFC 5424 over the syslog TLS listener
+    Syslog,
+}
+
+#[derive(Args)]
+pub struct IngestArgs {
+    #[clap(long, default_value_t = 1000, help = "Events per second")]
+    rate: u32,
+    #[clap(long, default_value_t = 30, help = "Seconds to generate events for")]
+    duration: u64,
+    #[clap(long, value_enum, default_value_t = EventFormat::Json)]
+    format: EventFormat,
+    #[clap(long, default_value_t = 8, help = "Senders working in parallel, each with its own connection")]
+    concurrency: usize,
+    #[clap(long, help = "Running instance to load; one is started in process when absent. Token in SIEM_BENCH_TOKEN")]
+    url: Option<String>,
+    #[clap(long, help = "host:port of the instance's syslog TLS listener, for --format syslog")]
+    syslog: Option<String>,
+    #[clap(long, help = "CA certificate (PEM) of the syslog listener; the public roots when absent")]
+    ca: Option<String>,
+    #[clap(long, help = "In process: database to store the events in")]
+    database_url: Option<String>,
+    #[clap(long, help = "In process: rows per database insert")]
+    batch_size: Option<usize>,
+    #[clap(long, help = "In process: age at which a partial database batch is written")]
+    flush_interval_ms: Option<u64>,
+    #[clap(long, help = "Print the report as JSON")]
+    json: bool,
+}
+
+#[derive(Args)]
+pub struct ParseArgs {
+    #[clap(long, default_value_t = 200_000, help = "Events to parse")]
+    events: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub target: String,
+    pub in_process: bool,
+    pub format: EventFormat,
+    pub rate: u32,
+    pub duration_secs: f64,
+    pub sent: u64,
+    // Acknowledged by the API; for syslog, written to the connection
+    pub accepted: u64,
+    // Failures by HTTP status or kind
+    pub errors: BTreeMap<String, u64>,
+    pub throughput_per_sec: f64,
+    // Until the API answered, JSON only
+    pub ack_latency: Percentiles,
+    // From sending a probe event until a query finds it
+    pub queryable_latency: Percentiles,
+    pub probes_lost: u64,
+    // Server side, from the change of the stage histograms in /metrics over the run.
+    // Values are bucket upper bounds; None beyond the largest bucket.
+    pub stages: BTreeMap<String, Percentiles>,
+}
+
+// The instance under load: its API, and with --format syslog its listener
+struct Target {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+    syslog: Option<(String, TlsConnector)>,
+}
+
+// Where one sender writes its events
+enum Sink {
+    Http,
+    Syslog(TlsStream<TcpStream>),
+}
+
+#[derive(Default)]
+struct SenderResult {
+    sent: u64,
+    accepted: u64,
+    errors: BTreeMap<String, u64>,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Default)]
+struct ProbeResult {
+    latencies: Vec<Duration>,
+    lost: u64,
+    errors: BTreeMap<String, u64>,
+}
+
+// An instance started for the run, over a temporary directory
+struct InProcess {
+    url: String,
+    token: String,
+    _dir: TempDir,
+}
+
+const USERS: [&str; 6] = ["alice", "bob", "carol", "dave", "erin", "frank"];
+
+// Varied enough to exercise extraction, classification and tagging
+fn message(seq: u64, marker: Option<&str>) -> String {
+    let user = USERS[seq as usize % USERS.len()];
+    let address = format!("10.{}.{}.{}", (seq >> 16) % 256, (seq >> 8) % 256, seq % 256);
+    let text = match seq % 5 {
+        0 => format!("Accepted password for {} from {} port {} ssh2", user, address, 1024 + seq % 60000),
+        1 => format!("Failed password for {} from {} port {} ssh2", user, address, 1024 + seq % 60000),
+        2 => format!("IN=eth0 OUT= SRC={} DST=10.0.0.1 PROTO=TCP SPT={} DPT=443", address, 1024 + seq % 60000),
+        3 => format!("session opened for user {} by (uid=0)", user),
+        _ => format!("GET /index.html HTTP/1.1 200 {} {}", 512 + seq % 4096, address),
+    };
+    match marker {
+        Some(marker) => format!("{} {}", text, marker),
+        None => text,
+    }
+}
+
+fn host(seq: u64) -> String {
+    format!("bench-{:02}", seq % 16)
+}
+
+fn json_event(seq: u64, marker: Option<&str>) -> Value {
+    json!({
+        "source": BENCH_SOURCE,
+        "message": message(seq, marker),
+        "host": host(seq),
+        "user": USERS[seq as usize % USERS.len()],
+        "tags": ["bench"],
+    })
+}
+
+// Octet-counted RFC 5424 frame, as the listener reads them
+fn syslog_frame(seq: u64, marker: Option<&str>) -> String {
+    let line = format!(
+        "<{}>1 {} {} sshd {} - - {}",
+        4 * 8 + 6,
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        host(seq),
+        1000 + seq % 30000,
+        message(seq, marker),
+    );
+    format!("{} {}", line.len(), line)
+}
+
+fn percentiles(samples: &mut [Duration]) -> Percentiles {
+    samples.sort();
+    let at = |q: f64| -> Option<f64> {
+        let index = ((q * samples.len() as f64).ceil() as usize).checked_sub(1)?;
+        samples.get(index).map(|d| d.as_secs_f64() * 1000.0)
+    };
+    Percentiles {
+        samples: samples.len(),
+        p50_ms: at(0.50),
+        p95_ms: at(0.95),
+        p99_ms: at(0.99),
+    }
+}
+
+// Cumulative bucket counts by stage and upper bound from a /metrics scrape
+fn stage_buckets(metrics: &str) -> BTreeMap<String, Vec<(f64, u64)>> {
+    let mut stages: BTreeMap<String, Vec<(f64, u64)>> = BTreeMap::new();
+    for line in metrics.lines() {
+        let Some(rest) = line.strip_prefix(STAGE_METRIC) else { continue };
+        let Some((labels, count)) = rest.rsplit_once(' ') else { continue };
+        let label = |name: &str| {
+            labels.split(&[',', '{', '}'][..])
+                .find_map(|pair| pair.strip_prefix(name)?.strip_prefix("=\"")?.strip_suffix('"'))
+        };
+        let (Some(stage), Some(bound), Ok(count)) = (label("stage"), label("le"), count.parse::<u64>()) else { continue };
+        let bound = if bound == "+Inf" { f64::INFINITY } else { bound.parse().unwrap_or(f64::INFINITY) };
+        stages.entry(stage.to_string()).or_default().push((bound, count));
+    }
+    stages
+}
+
+// Percentiles of what was observed between two scrapes
+fn stage_percentiles(before: &str, after: &str) -> BTreeMap<String, Percentiles> {
+    let before = stage_buckets(before);
+    stage_buckets(after).into_iter()
+        .filter_map(|(stage, buckets)| {
+            let earlier = before.get(&stage);
+            let buckets: Vec<(f64, u64)> = buckets.iter()
+                .map(|(bound, count)| {
+                    let previous = earlier
+                        .and_then(|b| b.iter().find(|(b, _)| b == bound))
+                        .map_or(0, |(_, c)| *c);
+                    (*bound, count.saturating_sub(previous))
+                })
+                .collect();
+            let total = buckets.last().map_or(0, |(_, count)| *count);
+            if total == 0 {
+                return None;
+            }
+
+            let at = |q: f64| buckets.iter()
+                .find(|(_, count)| *count as f64 >= q * total as f64)
+                .map(|(bound, _)| *bound)
+                .filter(|bound| bound.is_finite())
+                .map(|bound| bound * 1000.0);
+            Some((stage, Percentiles {
+                samples: total as usize,
+                p50_ms: at(0.50),
+                p95_ms: at(0.95),
+                p99_ms: at(0.99),
+            }))
+        })
+        .collect()
+}
+
+fn count_error(errors: &mut BTreeMap<String, u64>, kind: String) {
+    *errors.entry(kind).or_default() += 1;
+}
+
+impl Target {
+    fn new(url: String, token: String, syslog: Option<String>, ca: Option<&str>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to build the HTTP client")?;
+
+        let syslog = match syslog {
+            Some(address) => {
+                let roots = match ca {
+                    Some(path) => {
+                        let mut roots = rustls::RootCertStore::empty();
+                        for cert in CertificateDer::pem_file_iter(path)
+                            .map_err(|e| anyhow!("Failed to read CA certificate {}: {}", path, e))? {
+                            roots.add(cert.map_err(|e| anyhow!("Invalid CA certificate {}: {}", path, e))?)?;
+                        }
+                        roots
+                    },
+                    None => rustls::RootCertStore {
+                        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                    },
+                };
+                let tls = rustls::ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+                Some((address, TlsConnector::from(Arc::new(tls))))
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            http,
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            syslog,
+        })
+    }
+
+    async fn sink(&self) -> Result<Sink> {
+        let Some((address, tls)) = &self.syslog else { return Ok(Sink::Http) };
+        let host = address.rsplit_once(':').map_or(address.as_str(), |(host, _)| host);
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|_| anyhow!("Invalid TLS server name: {}", host))?;
+        let stream = TcpStream::connect(address).await
+            .context(format!("Failed to connect to the syslog listener at {}", address))?;
+        let stream = tls.connect(server_name, stream).await
+            .context(format!("TLS handshake with {} failed", address))?;
+        Ok(Sink::Syslog(stream))
+    }
+
+    // Err is the kind of failure, for the report
+    async fn send(&self, sink: &mut Sink, seq: u64, marker: Option<&str>) -> std::result::Result<(), String> {
+        match sink {
+            Sink::Http => {
+                let response = self.http.post(format!("{}/api/logs/ingest", self.url))
+                    .bearer_auth(&self.token)
+                    .json(&json_event(seq, marker))
+                    .send().await
+                    .map_err(|e| if e.is_timeout() { "timeout".to_string() } else { "transport".to_string() })?;
+                match response.status() {
+                    status if status.is_success() => Ok(()),
+                    status if status.as_u16() == 429 => Err("429 over quota".to_string()),
+                    status => Err(status.as_u16().to_string()),
+                }
+            },
+            Sink::Syslog(stream) => stream.write_all(syslog_frame(seq, marker).as_bytes()).await
+                .map_err(|_| "syslog write".to_string()),
+        }
+    }
+
+    async fn queryable(&self, marker: &str) -> Result<bool> {
+        let logs: Vec<Value> = self.http.get(format!("{}/api/logs", self.url))
+            .bearer_auth(&self.token)
+            .query(&[("message_contains", marker), ("limit", "1")])
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        Ok(!logs.is_empty())
+    }
+
+    async fn metrics(&self) -> Result<String> {
+        Ok(self.http.get(format!("{}/metrics", self.url))
+            .bearer_auth(&self.token)
+            .send().await?
+            .error_for_status()?
+            .text().await?)
+    }
+}
+
+// Sends every `stride`th event from `first` on, one per period, until the deadline
+async fn run_sender(target: Arc<Target>, first: u64, stride: u64, start: tokio::time::Instant, period: Duration, until: Instant) -> SenderResult {
+    let mut result = SenderResult::default();
+    let mut sink = match target.sink().await {
+        Ok(sink) => sink,
+        Err(e) => {
+            count_error(&mut result.errors, format!("connect: {:#}", e));
+            return result;
+        },
+    };
+
+    let mut ticks = tokio::time::interval_at(start, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let mut seq = first;
+    while Instant::now() < until {
+        ticks.tick().await;
+        let sent = Instant::now();
+        result.sent += 1;
+        match target.send(&mut sink, seq, None).await {
+            Ok(()) => {
+                result.accepted += 1;
+                if matches!(sink, Sink::Http) {
+                    result.latencies.push(sent.elapsed());
+                }
+            },
+            Err(kind) => count_error(&mut result.errors, kind),
+        }
+        seq += stride;
+    }
+    result
+}
+
+// One marked event at a time, polled for until a query finds it
+async fn run_probe(target: Arc<Target>, until: Instant) -> ProbeResult {
+    let mut result = ProbeResult::default();
+    let mut sink = match target.sink().await {
+        Ok(sink) => sink,
+        Err(e) => {
+            count_error(&mut result.errors, format!("connect: {:#}", e));
+            return result;
+        },
+    };
+
+    let mut seq = 0;
+    while Instant::now() < until {
+        let marker = format!("bench-probe-{}", Uuid::new_v4().simple());
+        let sent = Instant::now();
+        if let Err(kind) = target.send(&mut sink, seq, Some(&marker)).await {
+            count_error(&mut result.errors, kind);
+            tokio::time::sleep(PROBE_PAUSE).await;
+            continue;
+        }
+        seq += 1;
+
+        loop {
+            match target.queryable(&marker).await {
+                Ok(true) => {
+                    result.latencies.push(sent.elapsed());
+                    break;
+                },
+                Ok(false) => {},
+                Err(_) => count_error(&mut result.errors, "query".to_string()),
+            }
+            if sent.elapsed() > PROBE_TIMEOUT {
+                result.lost += 1;
+                break;
+            }
+            tokio::time::sleep(PROBE_POLL).await;
+        }
+        tokio::time::sleep(PROBE_PAUSE).await;
+    }
+    result
+}
+
+// The full application on a loopback port, over a temporary directory like the
+// integration tests, with a fresh admin logged in
+async fn start_in_process(args: &IngestArgs) -> Result<InProcess> {
+    let dir = tempfile::tempdir().context("Failed to create a temporary directory")?;
+
+    let mut config = config::default_config();
+    config.data_dir = "data".to_string();
+    config.scripts_dir = "scripts".to_string();
+    config.log_dir = "logs".to_string();
+    config.link_flap.enabled = false;
+    config.drop_log.ingest = false;
+    // All generated events come from one source, which a quota would throttle
+    config.ingestion_quota.events_per_minute = 0;
+    config.database_url = args.database_url.clone();
+    if let Some(batch_size) = args.batch_size {
+        config.database.batch_size = batch_size;
+    }
+    if let Some(flush_interval_ms) = args.flush_interval_ms {
+        config.database.flush_interval_ms = flush_interval_ms;
+    }
+
+    let config_path = dir.path().join("config.toml").display().to_string();
+    config::save(&config, &config_path)?;
+
+    let password = format!("Bench-{}-7!", Uuid::new_v4().simple());
+    UserManager::new(
+        &dir.path().join("data/users").display().to_string(),
+        PasswordPolicy::new(config.password_policy.clone()),
+    )?.create_user(BENCH_USERNAME, "bench@localhost", "Benchmark", UserRole::Admin, &password)?;
+
+    let app = crate::build_app(&config_path, config.clone(), config, crate::startup::Startup::new()).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await.context("Failed to bind a loopback port")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app.into_make_service()).await {
+            eprintln!("In-process server stopped: {}", e);
+        }
+    });
+
+    let login: Value = reqwest::Client::new().post(format!("{}/api/auth/login", url))
+        .json(&json!({ "username": BENCH_USERNAME, "password": password }))
+        .send().await?
+        .error_for_status()
+        .context("Login to the in-process instance failed")?
+        .json().await?;
+    let token = login["token"].as_str()
+        .ok_or_else(|| anyhow!("No token in the login response"))?
+        .to_string();
+
+    Ok(InProcess { url, token, _dir: dir })
+}
+
+pub async fn run_ingest(args: IngestArgs) -> Result<BenchReport> {
+    if args.rate == 0 || args.duration == 0 || args.concurrency == 0 {
+        return Err(anyhow!("--rate, --duration and --concurrency must be at least 1"));
+    }
+    if args.format == EventFormat::Syslog && (args.url.is_none() || args.syslog.is_none()) {
+        return Err(anyhow!("--format syslog needs --url and --syslog of a running instance with its syslog TLS listener enabled"));
+    }
+    if args.url.is_some() && (args.database_url.is_some() || args.batch_size.is_some() || args.flush_interval_ms.is_some()) {
+        return Err(anyhow!("--database-url, --batch-size and --flush-interval-ms only apply in process; set [database] on the instance instead"));
+    }
+
+    let (in_process, url, token) = match &args.url {
+        Some(url) => {
+            let token = std::env::var(TOKEN_VAR)
+                .map_err(|_| anyhow!("Set {} to a bearer token of the instance", TOKEN_VAR))?;
+            (None, url.clone(), token)
+        },
+        None => {
+            let instance = start_in_process(&args).await?;
+            let (url, token) = (instance.url.clone(), instance.token.clone());
+            (Some(instance), url, token)
+        },
+    };
+
+    let syslog = if args.format == EventFormat::Syslog { args.syslog.clone() } else { None };
+    let target = Arc::new(Target::new(url.clone(), token, syslog, args.ca.as_deref())?);
+    let before = target.metrics().await.context("Failed to read /metrics before the run")?;
+
+    let concurrency = args.concurrency.min(args.rate as usize);
+    let period = Duration::from_secs_f64(concurrency as f64 / args.rate as f64);
+    let started = Instant::now();
+    let until = started + Duration::from_secs(args.duration);
+    let start = tokio::time::Instant::now();
+
+    // Senders start staggered across one period, so the events are spread evenly
+    let senders: Vec<_> = (0..concurrency)
+        .map(|i| tokio::spawn(run_sender(
+            target.clone(),
+            i as u64,
+            concurrency as u64,
+            start + period.mul_f64(i as f64 / concurrency as f64),
+            period,
+            until,
+        )))
+        .collect();
+    let probe = tokio::spawn(run_probe(target.clone(), until));
+
+    let mut totals = SenderResult::default();
+    for sender in senders {
+        let result = sender.await.context("Sender failed")?;
+        totals.sent += result.sent;
+        totals.accepted += result.accepted;
+        totals.latencies.extend(result.latencies);
+        for (kind, count) in result.errors {
+            *totals.errors.entry(kind).or_default() += count;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let mut probe = probe.await.context("Probe failed")?;
+    for (kind, count) in probe.errors {
+        *totals.errors.entry(format!("probe {}", kind)).or_default() += count;
+    }
+
+    // The database writer may still hold a partial batch
+    let flush_wait = Duration::from_millis(args.flush_interval_ms.unwrap_or(config::DatabaseConfig::default().flush_interval_ms)) * 2;
+    tokio::time::sleep(flush_wait).await;
+    let after = target.metrics().await.context("Failed to read /metrics after the run")?;
+
+    let report = BenchReport {
+        target: url,
+        in_process: in_process.is_some(),
+        format: args.format,
+        rate: args.rate,
+        duration_secs: elapsed,
+        sent: totals.sent,
+        accepted: totals.accepted,
+        errors: totals.errors,
+        throughput_per_sec: totals.accepted as f64 / elapsed,
+        ack_latency: percentiles(&mut totals.latencies),
+        queryable_latency: percentiles(&mut probe.latencies),
+        probes_lost: probe.lost,
+        stages: stage_percentiles(&before, &after),
+    };
+    drop(in_process);
+    Ok(report)
+}
+
+fn render_ms(value: Option<f64>) -> String {
+    match value {
+        Some(ms) => format!("{:.3}ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+fn render_percentiles(name: &str, p: &Percentiles) -> String {
+    format!("  {:<18} p50 {:>11}  p95 {:>11}  p99 {:>11}  ({} samples)\n",
+            name, render_ms(p.p50_ms), render_ms(p.p95_ms), render_ms(p.p99_ms), p.samples)
+}
+
+impl BenchReport {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Ingest benchmark: {:?} events at {}/s for {:.1}s against {}{}\n",
+                              self.format, self.rate, self.duration_secs, self.target,
+                              if self.in_process { " (in process)" } else { "" });
+        out.push_str(&format!("  sent {}, accepted {}, throughput {:.1} events/s\n", self.sent, self.accepted, self.throughput_per_sec));
+        if self.errors.is_empty() {
+            out.push_str("  no errors\n");
+        }
+        for (kind, count) in &self.errors {
+            out.push_str(&format!("  error {}: {}\n", kind, count));
+        }
+        out.push_str("Client latency\n");
+        if self.ack_latency.samples > 0 {
+            out.push_str(&render_percentiles("acknowledged", &self.ack_latency));
+        }
+        out.push_str(&render_percentiles("queryable", &self.queryable_latency));
+        out.push_str(&format!("  probes lost        {}\n", self.probes_lost));
+        out.push_str("Server stages (bucket upper bounds, - beyond 1s)\n");
+        for (stage, p) in &self.stages {
+            out.push_str(&render_percentiles(stage, p));
+        }
+        out
+    }
+}
+
+// Per event cost of the parsing and classification every syslog event goes through. The
+// frames are generated up front so only the parsing is timed.
+fn run_parse(args: ParseArgs) -> Result<()> {
+    if args.events == 0 {
+        return Err(anyhow!("--events must be at least 1"));
+    }
+    let lines: Vec<String> = (0..args.events)
+        .map(|seq| {
+            let frame = syslog_frame(seq, None);
+            frame.split_once(' ').map_or(frame.clone(), |(_, line)| line.to_string())
+        })
+        .collect();
+
+    let started = Instant::now();
+    let mut parse_time = Duration::ZERO;
+    for line in &lines {
+        let parsing = Instant::now();
+        let entry = parse_syslog(line);
+        parse_time += parsing.elapsed();
+        std::hint::black_box(classification::classify(&entry));
+    }
+    let elapsed = started.elapsed();
+
+    println!("Parsed and classified {} syslog events in {:.3}s", args.events, elapsed.as_secs_f64());
+    println!("  {:.0} events/s, {:.0}ns per event, of which parsing {:.0}ns",
+             args.events as f64 / elapsed.as_secs_f64(),
+             elapsed.as_nanos() as f64 / args.events as f64,
+             parse_time.as_nanos() as f64 / args.events as f64);
+    Ok(())
+}
+
+pub async fn run(command: BenchCommand) -> Result<()> {
+    match command {
+        BenchCommand::Ingest(args) => {
+            let json = args.json;
+            let report = run_ingest(args).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report.to_text());
+            }
+            Ok(())
+        },
+        BenchCommand::Parse(args) => run_parse(args),
+    }
+}
@@ -0,0 +1,229 @@
+use std::error::Error as StdError;
+use std::path::Path;
+use std::sync::Arc;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use http_body_util::{LengthLimitError, Limited};
+use tokio::io::AsyncWriteExt;
+
+use crate::api::AppState;
+use crate::config::BodyLimitsConfig;
+
+pub fn too_large(limit: u64) -> Response {
+    (StatusCode::PAYLOAD_TOO_LARGE, format!("Request body exceeds the limit of {} bytes", limit)).into_response()
+}
+
+// Limit for a request path: a configured route override (longest prefix wins), then the
+// built-in classes of routes that take files, then the default for JSON APIs
+pub fn limit_for(config: &BodyLimitsConfig, path: &str) -> u64 {
+    if let Some((_, limit)) = config.routes.iter()
+        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len()) {
+        return *limit;
+    }
+
+    if path.starts_with("/api/tickets/") && path.ends_with("/attachments") {
+        config.attachment_bytes
+    } else if path.ends_with("/import") {
+        config.import_bytes
+    } else {
+        config.default_bytes
+    }
+}
+
+// Every request body is capped: a declared length over the limit is refused before
+// anything is read, and the body itself stops yielding data once it passes the limit,
+// so buffering extractors fail instead of growing without bound
+pub async fn limit_body(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = limit_for(&state.config.body_limits, request.uri().path());
+    apply_limit(limit, request, next).await
+}
+
+async fn apply_limit(limit: u64, request: Request, next: Next) -> Response {
+    let declared = request.headers().get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.map_or(false, |length| length > limit) {
+        return too_large(limit);
+    }
+
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+    let response = next.run(request).await;
+
+    // Extractor rejections do not say what the limit is
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        too_large(limit)
+    } else {
+        response
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    TooLarge(u64),
+    Io(std::io::Error),
+    Body(String),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match self {
+            UploadError::TooLarge(limit) => too_large(limit),
+            UploadError::Io(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store upload: {}", e)).into_response(),
+            UploadError::Body(e) => (StatusCode::BAD_REQUEST, format!("Failed to read upload: {}", e)).into_response(),
+        }
+    }
+}
+
+fn is_length_limit(error: &(dyn StdError + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+async fn write_chunks<S, E>(stream: &mut S, file: &mut tokio::fs::File, limit: u64) -> Result<u64, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: StdError + 'static,
+{
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) if is_length_limit(&e) => return Err(UploadError::TooLarge(limit)),
+            Err(e) => return Err(UploadError::Body(e.to_string())),
+        };
+        written += chunk.len() as u64;
+        if written > limit {
+            return Err(UploadError::TooLarge(limit));
+        }
+        file.write_all(&chunk).await.map_err(UploadError::Io)?;
+    }
+    file.flush().await.map_err(UploadError::Io)?;
+    Ok(written)
+}
+
+// Writes the body to the file one chunk at a time; at most one chunk is held in memory.
+// On any failure, including passing the limit, the partial file is removed.
+pub async fn stream_to_file<S, E>(mut stream: S, path: &Path, limit: u64) -> Result<u64, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: StdError + 'static,
+{
+    let mut file = tokio::fs::File::create(path).await.map_err(UploadError::Io)?;
+    let result = write_chunks(&mut stream, &mut file, limit).await;
+    drop(file);
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use axum::{routing::post, Router};
+    use tower::ServiceExt;
+
+    const CHUNK: usize = 64 * 1024;
+
+    // An endless body of CHUNK sized pieces counting how many were pulled
+    fn endless(pulled: Arc<AtomicUsize>) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Unpin + Send + 'static {
+        futures::stream::repeat_with(move || {
+            pulled.fetch_add(1, Ordering::SeqCst);
+            Ok(Bytes::from(vec![0u8; CHUNK]))
+        })
+    }
+
+    #[tokio::test]
+    async fn oversize_upload_stops_at_the_limit_and_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload");
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let limit = 1024 * 1024;
+
+        let result = stream_to_file(endless(pulled.clone()), &path, limit).await;
+
+        assert!(matches!(result, Err(UploadError::TooLarge(l)) if l == limit));
+        assert!(!path.exists());
+        // Only what fits under the limit plus the chunk that crossed it was ever read
+        assert_eq!(pulled.load(Ordering::SeqCst), limit as usize / CHUNK + 1);
+    }
+
+    #[tokio::test]
+    async fn upload_within_the_limit_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload");
+        let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from_static(b"hello ")), Ok(Bytes::from_static(b"world"))]);
+
+        assert_eq!(stream_to_file(chunks, &path, 11).await.unwrap(), 11);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn limited_body_fails_buffering_extractors_with_413() {
+        let limit: u64 = 256 * 1024;
+        let app = Router::new()
+            .route("/api/logs/ingest", post(|body: Bytes| async move { body.len().to_string() }))
+            .layer(axum::middleware::from_fn(move |request: Request, next: Next| apply_limit(limit, request, next)))
+            .layer(axum::extract::DefaultBodyLimit::disable());
+
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let request = Request::post("/api/logs/ingest")
+            .body(Body::from_stream(endless(pulled.clone())))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let text = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        assert_eq!(&text[..], format!("Request body exceeds the limit of {} bytes", limit).as_bytes());
+        assert!(pulled.load(Ordering::SeqCst) <= limit as usize / CHUNK + 1);
+    }
+
+    #[tokio::test]
+    async fn declared_oversize_length_is_refused_unread() {
+        let app = Router::new()
+            .route("/api/logs/ingest", post(|body: Bytes| async move { body.len().to_string() }))
+            .layer(axum::middleware::from_fn(|request: Request, next: Next| apply_limit(1024, request, next)));
+
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let request = Request::post("/api/logs/ingest")
+            .header(header::CONTENT_LENGTH, "1073741824")
+            .body(Body::from_stream(endless(pulled.clone())))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(pulled.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn file_routes_get_their_own_limits() {
+        let mut config = BodyLimitsConfig::default();
+        let id = "/api/tickets/7f1c/attachments";
+
+        assert_eq!(limit_for(&config, id), config.attachment_bytes);
+        assert_eq!(limit_for(&config, "/api/tickets/import"), config.import_bytes);
+        assert_eq!(limit_for(&config, "/api/logs/ingest"), config.default_bytes);
+
+        config.routes.insert("/api/logs/".to_string(), 4096);
+        assert_eq!(limit_for(&config, "/api/logs/ingest"), 4096);
+    }
+}
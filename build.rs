@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(dead_code)]
+#[path = "client_types.rs"]
+mod client_types;
+
+// Build metadata reported at /api/admin/version, see version.rs
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=SIEM_GIT_HASH={}", git_hash);
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    println!("cargo:rustc-env=SIEM_BUILD_TIMESTAMP={}", timestamp);
+
+    // The client types for `generate-types` and the API fingerprint, and with SIEM_TYPES_OUT
+    // set the client types also written there
+    let types = client_types::generate().expect("Failed to generate client types");
+    println!("cargo:rustc-env=SIEM_API_FINGERPRINT={}", types.fingerprint);
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(Path::new(&out_dir).join("client.ts"), &types.document).expect("Failed to write client types");
+    if let Ok(path) = std::env::var("SIEM_TYPES_OUT") {
+        fs::write(&path, &types.document).expect("Failed to write client types");
+    }
+    for unresolved in &types.unresolved {
+        println!("cargo:warning=No TypeScript type for {}, generated as unknown", unresolved);
+    }
+    let unregistered = client_types::unregistered_modules().expect("Failed to check the client type sources");
+    println!("cargo:rustc-env=SIEM_UNREGISTERED_DTO_MODULES={}", unregistered.join(","));
+    for (module, _) in client_types::SOURCES.iter().chain(&[("main", "")]) {
+        println!("cargo:rerun-if-changed={}", client_types::source_path(module).display());
+    }
+    println!("cargo:rerun-if-env-changed=SIEM_TYPES_OUT");
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-env-changed=SIEM_UPDATE_PUBLIC_KEY");
+}
@@ -0,0 +1,221 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::scripts::{Script, ScriptCategory, ScriptOutputFormat, ScriptParameter, ScriptReview};
+
+// Scripts shipped with the binary. Ids are fixed so schedules and execution history
+// keep pointing at the same script across upgrades; the content always comes from
+// the running binary and is never written to the scripts directory.
+struct BuiltinScript {
+    id: u128,
+    name: &'static str,
+    description: &'static str,
+    category: ScriptCategory,
+    output_format: ScriptOutputFormat,
+    parameters: &'static [(&'static str, &'static str, bool, Option<&'static str>)],
+    content: &'static str,
+}
+
+const SYSTEM_INFO: &str = r#"$os = Get-CimInstance Win32_OperatingSystem
+$cs = Get-CimInstance Win32_ComputerSystem
+$cpu = Get-CimInstance Win32_Processor | Select-Object -First 1
+
+[pscustomobject]@{
+    hostname = $env:COMPUTERNAME
+    domain = $cs.Domain
+    manufacturer = $cs.Manufacturer
+    model = $cs.Model
+    os = $os.Caption
+    os_version = $os.Version
+    os_build = $os.BuildNumber
+    cpu = $cpu.Name
+    cores = $cpu.NumberOfCores
+    memory_mb = [math]::Round($cs.TotalPhysicalMemory / 1MB)
+    last_boot = $os.LastBootUpTime.ToUniversalTime().ToString("o")
+} | ConvertTo-Json -Compress
+"#;
+
+const LISTENING_PORTS: &str = r#"$processes = @{}
+Get-Process | ForEach-Object { $processes[$_.Id] = $_.ProcessName }
+
+Get-NetTCPConnection -State Listen | ForEach-Object {
+    [pscustomobject]@{
+        endpoint = "tcp/$($_.LocalAddress):$($_.LocalPort)"
+        protocol = "tcp"
+        address = $_.LocalAddress
+        port = $_.LocalPort
+        pid = $_.OwningProcess
+        process = $processes[[int]$_.OwningProcess]
+    } | ConvertTo-Json -Compress
+}
+
+Get-NetUDPEndpoint | ForEach-Object {
+    [pscustomobject]@{
+        endpoint = "udp/$($_.LocalAddress):$($_.LocalPort)"
+        protocol = "udp"
+        address = $_.LocalAddress
+        port = $_.LocalPort
+        pid = $_.OwningProcess
+        process = $processes[[int]$_.OwningProcess]
+    } | ConvertTo-Json -Compress
+}
+"#;
+
+const ROTATE_LOGS: &str = r#"param(
+    [Parameter(Mandatory = $true)][string]$LogDirectory,
+    [int]$KeepDays = 14,
+    [string]$Filter = "*.log"
+)
+
+$ErrorActionPreference = "Stop"
+$cutoff = (Get-Date).AddDays(-$KeepDays)
+$archiveDir = Join-Path $LogDirectory "archive"
+New-Item -ItemType Directory -Force -Path $archiveDir | Out-Null
+
+Get-ChildItem -Path $LogDirectory -Filter $Filter -File |
+    Where-Object { $_.LastWriteTime -lt $cutoff } |
+    ForEach-Object {
+        $archive = Join-Path $archiveDir "$($_.BaseName)-$($_.LastWriteTime.ToString('yyyyMMdd')).zip"
+        Compress-Archive -Path $_.FullName -DestinationPath $archive -Update
+        Remove-Item -Path $_.FullName
+        "file=""$($_.Name)"" archive=""$archive"" bytes=$($_.Length)"
+    }
+
+Get-ChildItem -Path $archiveDir -Filter "*.zip" -File |
+    Where-Object { $_.LastWriteTime -lt $cutoff.AddDays(-$KeepDays) } |
+    ForEach-Object {
+        Remove-Item -Path $_.FullName
+        "deleted=""$($_.Name)"""
+    }
+"#;
+
+const DISK_SMART: &str = r#"$predictions = @{}
+Get-CimInstance -Namespace root\wmi -ClassName MSStorageDriver_FailurePredictStatus -ErrorAction SilentlyContinue |
+    ForEach-Object { $predictions[$_.InstanceName] = $_.PredictFailure }
+
+Get-PhysicalDisk | ForEach-Object {
+    $disk = $_
+    $counters = $disk | Get-StorageReliabilityCounter -ErrorAction SilentlyContinue
+    $predicted = $predictions.GetEnumerator() |
+        Where-Object { $_.Key -like "*$($disk.SerialNumber)*" } |
+        Select-Object -First 1
+
+    [pscustomobject]@{
+        disk = $disk.FriendlyName
+        serial = $disk.SerialNumber
+        media_type = "$($disk.MediaType)"
+        health = "$($disk.HealthStatus)"
+        operational_status = "$($disk.OperationalStatus)"
+        predict_failure = [bool]$predicted.Value
+        temperature_c = $counters.Temperature
+        wear_percent = $counters.Wear
+        read_errors = $counters.ReadErrorsTotal
+        write_errors = $counters.WriteErrorsTotal
+        power_on_hours = $counters.PowerOnHours
+    } | ConvertTo-Json -Compress
+}
+"#;
+
+const RESTART_SERVICE: &str = r#"param(
+    [Parameter(Mandatory = $true)][string]$ServiceName,
+    [int]$TimeoutSeconds = 60
+)
+
+$ErrorActionPreference = "Stop"
+$service = Get-Service -Name $ServiceName
+$before = $service.Status
+
+Restart-Service -Name $ServiceName -Force
+$service.WaitForStatus("Running", [TimeSpan]::FromSeconds($TimeoutSeconds))
+$service.Refresh()
+
+"service=""$($service.Name)"" display_name=""$($service.DisplayName)"" before=$before after=$($service.Status)"
+"#;
+
+const LIBRARY: &[BuiltinScript] = &[
+    BuiltinScript {
+        id: 0x6f1c2a4e_0001_4b1e_9a52_5e4d1c0b0001,
+        name: "Gather system information",
+        description: "Reports host name, operating system, hardware model, CPU, memory and last boot time",
+        category: ScriptCategory::System,
+        output_format: ScriptOutputFormat::JsonLines,
+        parameters: &[],
+        content: SYSTEM_INFO,
+    },
+    BuiltinScript {
+        id: 0x6f1c2a4e_0001_4b1e_9a52_5e4d1c0b0002,
+        name: "List listening ports",
+        description: "Lists listening TCP ports and bound UDP endpoints with the owning process; diff on the endpoint field",
+        category: ScriptCategory::Network,
+        output_format: ScriptOutputFormat::JsonLines,
+        parameters: &[],
+        content: LISTENING_PORTS,
+    },
+    BuiltinScript {
+        id: 0x6f1c2a4e_0001_4b1e_9a52_5e4d1c0b0003,
+        name: "Rotate logs",
+        description: "Archives log files older than KeepDays into per-file zip archives and removes expired archives",
+        category: ScriptCategory::Maintenance,
+        output_format: ScriptOutputFormat::KeyValue,
+        parameters: &[
+            ("LogDirectory", "Directory containing the log files", true, None),
+            ("KeepDays", "Age in days after which a log file is archived", false, Some("14")),
+            ("Filter", "File name pattern of the log files", false, Some("*.log")),
+        ],
+        content: ROTATE_LOGS,
+    },
+    BuiltinScript {
+        id: 0x6f1c2a4e_0001_4b1e_9a52_5e4d1c0b0004,
+        name: "Check disk SMART status",
+        description: "Reports health, failure prediction, temperature, wear and error counters of every physical disk",
+        category: ScriptCategory::Maintenance,
+        output_format: ScriptOutputFormat::JsonLines,
+        parameters: &[],
+        content: DISK_SMART,
+    },
+    BuiltinScript {
+        id: 0x6f1c2a4e_0001_4b1e_9a52_5e4d1c0b0005,
+        name: "Restart service",
+        description: "Restarts a Windows service and waits until it is running again",
+        category: ScriptCategory::System,
+        output_format: ScriptOutputFormat::KeyValue,
+        parameters: &[
+            ("ServiceName", "Name of the service to restart", true, None),
+            ("TimeoutSeconds", "How long to wait for the service to come back", false, Some("60")),
+        ],
+        content: RESTART_SERVICE,
+    },
+];
+
+// The built-in library as read-only, pre-approved scripts
+pub fn builtin_scripts(loaded_at: DateTime<Utc>) -> Vec<Script> {
+    LIBRARY.iter()
+        .map(|builtin| Script {
+            id: Uuid::from_u128(builtin.id),
+            name: builtin.name.to_string(),
+            description: builtin.description.to_string(),
+            content: builtin.content.to_string(),
+            created_at: loaded_at,
+            updated_at: loaded_at,
+            created_by: "system".to_string(),
+            is_approved: true,
+            approved_by: Some("system".to_string()),
+            category: builtin.category.clone(),
+            tags: vec!["builtin".to_string()],
+            output_format: builtin.output_format.clone(),
+            parameters: builtin.parameters.iter()
+                .map(|(name, description, required, default)| ScriptParameter {
+                    name: name.to_string(),
+                    description: description.to_string(),
+                    required: *required,
+                    default: default.map(|d| d.to_string()),
+                })
+                .collect(),
+            is_builtin: true,
+            cloned_from: None,
+            dependencies: Default::default(),
+            review: ScriptReview::approved("system", builtin.content),
+            lint: Default::default(),
+        })
+        .collect()
+}
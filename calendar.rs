@@ -0,0 +1,833 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+// Recurring ICS events are expanded from the start of the previous year to the end of
+// the year this many years ahead; importing the calendar again extends it
+const ICS_YEARS_AHEAD: i32 = 5;
+
+// Longest span the business time calculations walk, day by day
+const MAX_DAYS: i64 = 10 * 366;
+
+// Repetitions of one recurrence rule looked at before giving up
+const MAX_REPETITIONS: usize = 10_000;
+
+// Largest INTERVAL accepted, far beyond any holiday calendar's need
+const MAX_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Holiday {
+    pub date: NaiveDate,
+    pub name: String,
+    // UID of the ICS event it was imported from; None for holidays entered by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ics_uid: Option<String>,
+}
+
+// Business hours as edited through /api/admin/calendar
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarSettings {
+    // IANA timezone of the hours and holidays
+    pub timezone: String,
+    pub working_days: Vec<Weekday>,
+    // Local time, "HH:MM"; business time runs from start up to end
+    pub start: String,
+    pub end: String,
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+}
+
+impl CalendarSettings {
+    fn defaults(timezone: &str) -> Self {
+        Self {
+            timezone: timezone.to_string(),
+            working_days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            start: "08:00".to_string(),
+            end: "17:00".to_string(),
+            holidays: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarState {
+    #[serde(flatten)]
+    pub settings: CalendarSettings,
+    // Raised by every change and recorded with decisions made on the calendar, such as
+    // SLA breaches, so it is clear which hours they were measured against
+    pub version: u64,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub updated_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BusinessPeriod {
+    // Working hours of the last business day that has ended
+    PreviousBusinessDay,
+    // From the first to the last working hour of the previous calendar week
+    PreviousBusinessWeek,
+}
+
+// A point in local time as a UTC instant. An ambiguous time (clocks going back) is the
+// earlier one; a time skipped by clocks going forward is the one an hour later.
+fn local_to_utc(timezone: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match timezone.from_local_datetime(&local) {
+        LocalResult::Single(at) => at.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+        LocalResult::None => timezone.from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .map(|at| at.with_timezone(&Utc))
+            .unwrap_or_else(|| Utc.from_utc_datetime(&local)),
+    }
+}
+
+// The calendar ready for lookups. A snapshot: edits made later do not change it.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    pub version: u64,
+    timezone: Tz,
+    // Indexed by days from Monday
+    working_days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+    holidays: HashSet<NaiveDate>,
+}
+
+impl Calendar {
+    pub fn compile(state: &CalendarState) -> Result<Self> {
+        let settings = &state.settings;
+        let timezone: Tz = settings.timezone.parse()
+            .map_err(|_| anyhow!("Unknown timezone: {}", settings.timezone))?;
+        let start = NaiveTime::parse_from_str(&settings.start, "%H:%M")
+            .map_err(|_| anyhow!("Invalid start time, expected HH:MM: {}", settings.start))?;
+        let end = NaiveTime::parse_from_str(&settings.end, "%H:%M")
+            .map_err(|_| anyhow!("Invalid end time, expected HH:MM: {}", settings.end))?;
+        if end <= start {
+            return Err(anyhow!("Business hours must end after they start"));
+        }
+
+        let mut working_days = [false; 7];
+        for day in &settings.working_days {
+            working_days[day.num_days_from_monday() as usize] = true;
+        }
+
+        Ok(Self {
+            version: state.version,
+            timezone,
+            working_days,
+            start,
+            end,
+            holidays: settings.holidays.iter().map(|h| h.date).collect(),
+        })
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        self.working_days[date.weekday().num_days_from_monday() as usize] && !self.holidays.contains(&date)
+    }
+
+    pub fn is_business_time(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.timezone);
+        let time = local.time();
+        self.is_business_day(local.date_naive()) && time >= self.start && time < self.end
+    }
+
+    // The business hours of a local date, None on days off
+    fn window(&self, date: NaiveDate) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        if !self.is_business_day(date) {
+            return None;
+        }
+        Some((local_to_utc(&self.timezone, date.and_time(self.start)),
+              local_to_utc(&self.timezone, date.and_time(self.end))))
+    }
+
+    // Business time between two instants; zero when `to` is not after `from`
+    pub fn business_duration(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Duration {
+        let mut total = Duration::zero();
+        if to <= from {
+            return total;
+        }
+
+        let last = to.with_timezone(&self.timezone).date_naive();
+        let mut date = from.with_timezone(&self.timezone).date_naive();
+        for _ in 0..MAX_DAYS {
+            if date > last {
+                break;
+            }
+            if let Some((open, close)) = self.window(date) {
+                let (start, end) = (open.max(from), close.min(to));
+                if end > start {
+                    total += end - start;
+                }
+            }
+            match date.succ_opt() {
+                Some(next) => date = next,
+                None => break,
+            }
+        }
+        total
+    }
+
+    // When `duration` of business time has passed since `from`; None if the calendar has
+    // no business hours within reach
+    pub fn add_business_duration(&self, from: DateTime<Utc>, duration: Duration) -> Option<DateTime<Utc>> {
+        let mut remaining = duration;
+        let mut date = from.with_timezone(&self.timezone).date_naive();
+        for _ in 0..MAX_DAYS {
+            if let Some((open, close)) = self.window(date) {
+                let start = open.max(from);
+                if close > start {
+                    if close - start >= remaining {
+                        return Some(start + remaining);
+                    }
+                    remaining -= close - start;
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    pub fn period(&self, period: BusinessPeriod, now: DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        match period {
+            BusinessPeriod::PreviousBusinessDay => {
+                let mut date = today;
+                for _ in 0..366 {
+                    if let Some((open, close)) = self.window(date) {
+                        if close <= now {
+                            return Some((open, close));
+                        }
+                    }
+                    date = date.pred_opt()?;
+                }
+                None
+            },
+            BusinessPeriod::PreviousBusinessWeek => {
+                let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64 + 7);
+                let windows: Vec<_> = (0..7).filter_map(|i| self.window(monday + Duration::days(i))).collect();
+                Some((windows.first()?.0, windows.last()?.1))
+            },
+        }
+    }
+}
+
+// Holidays read from an ICS file, with the events that could not be used
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IcsImport {
+    #[serde(skip)]
+    pub holidays: Vec<Holiday>,
+    pub imported: usize,
+    // Holidays of earlier imports of the same events, replaced by this one
+    pub replaced: usize,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone)]
+struct RecurrenceRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<NaiveDate>,
+    by_month: Vec<u32>,
+    by_month_day: Vec<i32>,
+    // Weekday with its position in the month, e.g. -1MO for the last Monday
+    by_day: Vec<(Option<i32>, Weekday)>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct IcsEvent {
+    uid: String,
+    summary: String,
+    start: Option<NaiveDate>,
+    all_day: bool,
+    end: Option<NaiveDate>,
+    duration_days: Option<i64>,
+    rrule: Option<String>,
+    rdates: Vec<NaiveDate>,
+    exdates: Vec<NaiveDate>,
+    recurrence_id: Option<NaiveDate>,
+    cancelled: bool,
+}
+
+// Lines continued on the next line, starting with a space or tab, joined back
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.split('\n') {
+        let line = raw.trim_end_matches('\r');
+        match lines.last_mut() {
+            Some(last) if line.starts_with(' ') || line.starts_with('\t') => last.push_str(&line[1..]),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// Name and value of a content line; parameters are dropped, except that a colon inside
+// a quoted parameter value does not end the name
+fn split_property(line: &str) -> Option<(String, &str)> {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                let name = line[..i].split(';').next().unwrap_or_default().to_uppercase();
+                return Some((name, &line[i + 1..]));
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\n", " ").replace("\\N", " ").replace("\\,", ",").replace("\\;", ";").replace("\\\\", "\\")
+}
+
+// The date of a DATE or DATE-TIME value: UTC times are moved to the calendar's timezone,
+// local and floating times are taken as written
+fn parse_date(value: &str, timezone: &Tz) -> Option<(NaiveDate, bool)> {
+    let value = value.trim();
+    if value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(|date| (date, true));
+    }
+    match value.strip_suffix('Z') {
+        Some(utc) => NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()
+            .map(|at| (Utc.from_utc_datetime(&at).with_timezone(timezone).date_naive(), false)),
+        None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok().map(|at| (at.date(), false)),
+    }
+}
+
+fn parse_dates(value: &str, timezone: &Tz) -> Vec<NaiveDate> {
+    value.split(',').filter_map(|v| parse_date(v, timezone)).map(|(date, _)| date).collect()
+}
+
+// Whole days of a DURATION such as P1D or P2W; anything shorter is one day
+fn parse_duration_days(value: &str) -> Option<i64> {
+    let value = value.trim().trim_start_matches('+').strip_prefix('P')?;
+    let value = value.split('T').next().unwrap_or_default();
+    if let Some(weeks) = value.strip_suffix('W') {
+        return weeks.parse::<i64>().ok().and_then(|w| w.checked_mul(7));
+    }
+    match value.strip_suffix('D') {
+        Some(days) => days.parse().ok(),
+        None => Some(1),
+    }
+}
+
+fn parse_weekday(code: &str) -> Option<Weekday> {
+    match code {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn parse_rrule(value: &str, timezone: &Tz) -> std::result::Result<RecurrenceRule, String> {
+    let mut rule = RecurrenceRule {
+        frequency: Frequency::Yearly,
+        interval: 1,
+        count: None,
+        until: None,
+        by_month: Vec::new(),
+        by_month_day: Vec::new(),
+        by_day: Vec::new(),
+    };
+    let mut frequency = None;
+
+    for part in value.split(';').filter(|p| !p.is_empty()) {
+        let (key, value) = part.split_once('=').ok_or_else(|| format!("malformed rule part {}", part))?;
+        let invalid = || format!("invalid {} {}", key, value);
+        match key.to_uppercase().as_str() {
+            "FREQ" => frequency = Some(match value.to_uppercase().as_str() {
+                "DAILY" => Frequency::Daily,
+                "WEEKLY" => Frequency::Weekly,
+                "MONTHLY" => Frequency::Monthly,
+                "YEARLY" => Frequency::Yearly,
+                other => return Err(format!("unsupported frequency {}", other)),
+            }),
+            "INTERVAL" => rule.interval = value.parse().ok().filter(|i| (1..=MAX_INTERVAL).contains(i)).ok_or_else(invalid)?,
+            "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid())?),
+            "UNTIL" => rule.until = Some(parse_date(value, timezone).ok_or_else(invalid)?.0),
+            "BYMONTH" => rule.by_month = value.split(',')
+                .map(|m| m.parse().ok().filter(|m| (1..=12).contains(m)))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?,
+            "BYMONTHDAY" => rule.by_month_day = value.split(',')
+                .map(|d| d.parse().ok().filter(|d: &i32| *d != 0 && d.abs() <= 31))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?,
+            "BYDAY" => rule.by_day = value.split(',')
+                .map(|day| {
+                    let day = day.trim().to_uppercase();
+                    if !day.is_ascii() {
+                        return None;
+                    }
+                    let (position, code) = day.split_at(day.len().saturating_sub(2));
+                    let weekday = parse_weekday(code)?;
+                    match position {
+                        "" => Some((None, weekday)),
+                        position => position.trim_start_matches('+').parse().ok()
+                            .filter(|n: &i32| *n != 0 && n.abs() <= 5)
+                            .map(|n| (Some(n), weekday)),
+                    }
+                })
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?,
+            "WKST" => {},
+            other => return Err(format!("unsupported rule part {}", other)),
+        }
+    }
+
+    rule.frequency = frequency.ok_or_else(|| "rule without FREQ".to_string())?;
+    if rule.frequency == Frequency::Yearly && rule.by_month.is_empty() && !rule.by_day.is_empty() {
+        return Err("BYDAY in a yearly rule without BYMONTH is not supported".to_string());
+    }
+    Ok(rule)
+}
+
+fn last_day_of_month(year: i32, month: u32) -> Option<NaiveDate> {
+    if month == 12 {
+        return NaiveDate::from_ymd_opt(year, 12, 31);
+    }
+    NaiveDate::from_ymd_opt(year, month + 1, 1)?.pred_opt()
+}
+
+// Dates of a month the rule selects; `default_day` is the start's day of the month
+fn month_dates(year: i32, month: u32, rule: &RecurrenceRule, default_day: u32) -> Vec<NaiveDate> {
+    let Some(last) = last_day_of_month(year, month) else { return Vec::new() };
+    let mut dates = Vec::new();
+
+    if !rule.by_month_day.is_empty() {
+        for day in &rule.by_month_day {
+            let day = if *day > 0 { *day } else { last.day() as i32 + day + 1 };
+            if let Some(date) = u32::try_from(day).ok().and_then(|d| NaiveDate::from_ymd_opt(year, month, d)) {
+                dates.push(date);
+            }
+        }
+    } else if !rule.by_day.is_empty() {
+        for (position, weekday) in &rule.by_day {
+            let matching: Vec<NaiveDate> = (1..=last.day())
+                .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+                .filter(|d| d.weekday() == *weekday)
+                .collect();
+            match position {
+                None => dates.extend(matching),
+                Some(n) if *n > 0 => dates.extend(matching.get(*n as usize - 1)),
+                Some(n) => dates.extend(matching.len().checked_sub(n.unsigned_abs() as usize).and_then(|i| matching.get(i))),
+            }
+        }
+    } else if let Some(date) = NaiveDate::from_ymd_opt(year, month, default_day) {
+        dates.push(date);
+    }
+    dates
+}
+
+// Start dates of the occurrences of a recurring event, up to `until`. Dates past the
+// range chrono can represent end the expansion.
+fn occurrences(start: NaiveDate, rule: &RecurrenceRule, until: NaiveDate) -> Vec<NaiveDate> {
+    let until = rule.until.map_or(until, |u| u.min(until));
+    let weekday_offset = |day: Weekday| Duration::days(day.num_days_from_monday() as i64);
+    let Some(start_monday) = start.checked_sub_signed(weekday_offset(start.weekday())) else { return Vec::new() };
+    let mut found = Vec::new();
+
+    for repetition in 0..MAX_REPETITIONS as i64 {
+        let step = repetition * rule.interval as i64;
+        let months = start.month0() as i64 + step;
+        let year = i32::try_from(months / 12).ok().and_then(|years| start.year().checked_add(years));
+        let month = (months % 12) as u32 + 1;
+
+        // First day of the period this repetition covers
+        let period_start = match rule.frequency {
+            Frequency::Daily => Duration::try_days(step).and_then(|step| start.checked_add_signed(step)),
+            Frequency::Weekly => Duration::try_weeks(step).and_then(|step| start_monday.checked_add_signed(step)),
+            Frequency::Monthly => year.and_then(|year| NaiveDate::from_ymd_opt(year, month, 1)),
+            Frequency::Yearly => i32::try_from(step).ok()
+                .and_then(|step| start.year().checked_add(step))
+                .and_then(|year| NaiveDate::from_ymd_opt(year, 1, 1)),
+        };
+        let Some(period_start) = period_start.filter(|p| *p <= until) else { break };
+
+        let mut dates = match rule.frequency {
+            Frequency::Daily => vec![period_start],
+            Frequency::Weekly if rule.by_day.is_empty() => {
+                period_start.checked_add_signed(weekday_offset(start.weekday())).into_iter().collect()
+            },
+            Frequency::Weekly => rule.by_day.iter()
+                .filter_map(|(_, day)| period_start.checked_add_signed(weekday_offset(*day)))
+                .collect(),
+            Frequency::Monthly => month_dates(period_start.year(), month, rule, start.day()),
+            Frequency::Yearly => {
+                let months: Vec<u32> = if !rule.by_month.is_empty() {
+                    rule.by_month.clone()
+                } else if !rule.by_month_day.is_empty() {
+                    (1..=12).collect()
+                } else {
+                    vec![start.month()]
+                };
+                months.iter().flat_map(|month| month_dates(period_start.year(), *month, rule, start.day())).collect()
+            },
+        };
+
+        // BY parts narrow down rules that repeat more often than they select
+        if matches!(rule.frequency, Frequency::Daily | Frequency::Weekly | Frequency::Monthly) && !rule.by_month.is_empty() {
+            dates.retain(|d| rule.by_month.contains(&d.month()));
+        }
+        if rule.frequency == Frequency::Daily && !rule.by_day.is_empty() {
+            dates.retain(|d| rule.by_day.iter().any(|(_, day)| *day == d.weekday()));
+        }
+        if rule.frequency == Frequency::Daily && !rule.by_month_day.is_empty() {
+            dates.retain(|d| rule.by_month_day.contains(&(d.day() as i32)));
+        }
+        dates.sort();
+        dates.dedup();
+
+        for date in dates.into_iter().filter(|d| *d >= start) {
+            if date > until || rule.count.map_or(false, |count| found.len() >= count) {
+                return found;
+            }
+            found.push(date);
+        }
+    }
+    found
+}
+
+// Every day an ICS calendar marks, from `from` to `until`. Recurring events (RRULE with
+// RDATE, EXDATE and changed occurrences) are expanded; events that cannot be read are
+// skipped and listed rather than failing the import.
+pub fn parse_ics(text: &str, timezone: &Tz, from: NaiveDate, until: NaiveDate) -> Result<IcsImport> {
+    let lines = unfold(text);
+    if !lines.iter().any(|l| l.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(anyhow!("Not an iCalendar file: BEGIN:VCALENDAR is missing"));
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<IcsEvent> = None;
+    for line in &lines {
+        let Some((name, value)) = split_property(line) else { continue };
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => current = Some(IcsEvent::default()),
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => events.extend(current.take()),
+            ("UID", Some(event)) => event.uid = value.trim().to_string(),
+            ("SUMMARY", Some(event)) => event.summary = unescape(value.trim()),
+            ("DTSTART", Some(event)) => if let Some((date, all_day)) = parse_date(value, timezone) {
+                event.start = Some(date);
+                event.all_day = all_day;
+            },
+            ("DTEND", Some(event)) => event.end = parse_date(value, timezone).map(|(date, _)| date),
+            ("DURATION", Some(event)) => event.duration_days = parse_duration_days(value),
+            ("RRULE", Some(event)) => event.rrule = Some(value.trim().to_string()),
+            ("RDATE", Some(event)) => event.rdates.extend(parse_dates(value, timezone)),
+            ("EXDATE", Some(event)) => event.exdates.extend(parse_dates(value, timezone)),
+            ("RECURRENCE-ID", Some(event)) => event.recurrence_id = parse_date(value, timezone).map(|(date, _)| date),
+            ("STATUS", Some(event)) => event.cancelled = value.trim().eq_ignore_ascii_case("CANCELLED"),
+            _ => {},
+        }
+    }
+
+    // A changed occurrence replaces the one its recurring event would have had
+    let mut moved: HashMap<String, Vec<NaiveDate>> = HashMap::new();
+    for event in events.iter().filter(|e| e.recurrence_id.is_some()) {
+        moved.entry(event.uid.clone()).or_default().extend(event.recurrence_id);
+    }
+
+    let mut import = IcsImport::default();
+    for event in &events {
+        let label = if event.summary.is_empty() { event.uid.clone() } else { event.summary.clone() };
+        let Some(start) = event.start else {
+            import.skipped.push(format!("{}: no DTSTART", label));
+            continue;
+        };
+        if event.cancelled {
+            continue;
+        }
+        if event.uid.is_empty() {
+            import.skipped.push(format!("{}: no UID", label));
+            continue;
+        }
+
+        // DTEND of an all-day event is the day after it
+        let days = match (event.end, event.duration_days) {
+            (Some(end), _) if event.all_day => (end - start).num_days().max(1),
+            (Some(end), _) => (end - start).num_days().max(0) + 1,
+            (None, Some(days)) => days.max(1),
+            (None, None) => 1,
+        };
+
+        let mut starts = match (&event.rrule, event.recurrence_id) {
+            (Some(rrule), None) => match parse_rrule(rrule, timezone) {
+                Ok(rule) => occurrences(start, &rule, until),
+                Err(e) => {
+                    import.skipped.push(format!("{}: {}", label, e));
+                    continue;
+                },
+            },
+            _ => vec![start],
+        };
+        if event.recurrence_id.is_none() {
+            starts.extend(&event.rdates);
+            let excluded = moved.get(&event.uid);
+            starts.retain(|d| !event.exdates.contains(d) && !excluded.map_or(false, |m| m.contains(d)));
+        }
+
+        for first in starts {
+            for offset in 0..days.min(366) {
+                let Some(date) = first.checked_add_signed(Duration::days(offset)) else { break };
+                if date >= from && date <= until {
+                    import.holidays.push(Holiday {
+                        date,
+                        name: if event.summary.is_empty() { "Holiday".to_string() } else { event.summary.clone() },
+                        ics_uid: Some(event.uid.clone()),
+                    });
+                }
+            }
+        }
+    }
+
+    import.holidays.sort_by(|a, b| a.date.cmp(&b.date));
+    import.holidays.dedup_by(|a, b| a.date == b.date && a.ics_uid == b.ics_uid);
+    import.imported = import.holidays.len();
+    Ok(import)
+}
+
+struct Loaded {
+    state: CalendarState,
+    calendar: Calendar,
+}
+
+// Working days, hours and holidays, read by SLA tracking, the after-hours detector and
+// report periods. Each of them takes a snapshot when it decides something, so an edit
+// affects what is decided from then on and never revisits earlier decisions.
+#[derive(Clone)]
+pub struct CalendarManager {
+    path: PathBuf,
+    loaded: Arc<Mutex<Loaded>>,
+}
+
+impl CalendarManager {
+    // Without a calendar file, Monday to Friday 08:00-17:00 in `default_timezone`
+    pub fn new(path: &str, default_timezone: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let stored = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<CalendarState>(&contents) {
+                Ok(state) => match Calendar::compile(&state) {
+                    Ok(calendar) => Some(Loaded { state, calendar }),
+                    Err(e) => {
+                        warn!("Ignoring invalid calendar file {:?}: {}", path, e);
+                        None
+                    },
+                },
+                Err(e) => {
+                    warn!("Ignoring invalid calendar file {:?}: {}", path, e);
+                    None
+                },
+            },
+            Err(_) => None,
+        };
+
+        let loaded = match stored {
+            Some(loaded) => loaded,
+            None => {
+                let timezone = if default_timezone.parse::<Tz>().is_ok() { default_timezone } else { "UTC" };
+                let state = CalendarState {
+                    settings: CalendarSettings::defaults(timezone),
+                    version: 0,
+                    updated_at: None,
+                    updated_by: None,
+                };
+                let calendar = Calendar::compile(&state)?;
+                Loaded { state, calendar }
+            },
+        };
+        info!("Business calendar version {} with {} holidays in {}",
+              loaded.state.version, loaded.state.settings.holidays.len(), loaded.state.settings.timezone);
+
+        Ok(Self {
+            path,
+            loaded: Arc::new(Mutex::new(loaded)),
+        })
+    }
+
+    pub fn get(&self) -> Result<CalendarState> {
+        match self.loaded.lock() {
+            Ok(loaded) => Ok(loaded.state.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on calendar")),
+        }
+    }
+
+    pub fn calendar(&self) -> Result<Calendar> {
+        match self.loaded.lock() {
+            Ok(loaded) => Ok(loaded.calendar.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on calendar")),
+        }
+    }
+
+    // Applies a change to the settings, validated and saved as a new version
+    fn change<F, T>(&self, by: &str, change: F) -> Result<(CalendarState, T)>
+    where F: FnOnce(&mut CalendarSettings) -> Result<T> {
+        let mut loaded = self.loaded.lock().map_err(|_| anyhow!("Failed to acquire lock on calendar"))?;
+
+        let mut settings = loaded.state.settings.clone();
+        let result = change(&mut settings)?;
+        settings.holidays.sort_by(|a, b| a.date.cmp(&b.date));
+        let state = CalendarState {
+            settings,
+            version: loaded.state.version + 1,
+            updated_at: Some(Utc::now()),
+            updated_by: Some(by.to_string()),
+        };
+        let calendar = Calendar::compile(&state)?;
+
+        fs::write(&self.path, serde_json::to_string_pretty(&state)?)
+            .context(format!("Failed to write calendar file: {:?}", self.path))?;
+        *loaded = Loaded { state: state.clone(), calendar };
+        Ok((state, result))
+    }
+
+    pub fn update(&self, settings: CalendarSettings, by: &str) -> Result<CalendarState> {
+        self.change(by, |current| {
+            *current = settings;
+            Ok(())
+        }).map(|(state, _)| state)
+    }
+
+    pub fn add_holiday(&self, date: NaiveDate, name: String, by: &str) -> Result<CalendarState> {
+        self.change(by, |settings| {
+            settings.holidays.push(Holiday { date, name, ics_uid: None });
+            Ok(())
+        }).map(|(state, _)| state)
+    }
+
+    // Removes every holiday on the date, imported or not; None when there was none
+    pub fn remove_holiday(&self, date: NaiveDate, by: &str) -> Result<Option<CalendarState>> {
+        if !self.get()?.settings.holidays.iter().any(|h| h.date == date) {
+            return Ok(None);
+        }
+        self.change(by, |settings| {
+            settings.holidays.retain(|h| h.date != date);
+            Ok(())
+        }).map(|(state, _)| Some(state))
+    }
+
+    // Adds the days of an ICS calendar as holidays. Days imported earlier from the same
+    // events are replaced; with `replace_all`, every earlier imported day is.
+    pub fn import_ics(&self, text: &str, replace_all: bool, by: &str) -> Result<(CalendarState, IcsImport)> {
+        let timezone = self.calendar()?.timezone();
+        let year = Utc::now().with_timezone(&timezone).year();
+        let from = NaiveDate::from_ymd_opt(year - 1, 1, 1).ok_or_else(|| anyhow!("Invalid import range"))?;
+        let until = NaiveDate::from_ymd_opt(year + ICS_YEARS_AHEAD, 12, 31).ok_or_else(|| anyhow!("Invalid import range"))?;
+
+        let mut import = parse_ics(text, &timezone, from, until)?;
+        if import.holidays.is_empty() && !import.skipped.is_empty() {
+            return Err(anyhow!("No usable events: {}", import.skipped.join("; ")));
+        }
+
+        self.change(by, |settings| {
+            let uids: HashSet<String> = import.holidays.iter().filter_map(|h| h.ics_uid.clone()).collect();
+            let before = settings.holidays.len();
+            settings.holidays.retain(|h| match &h.ics_uid {
+                Some(uid) => !replace_all && !uids.contains(uid),
+                None => true,
+            });
+            import.replaced = before - settings.holidays.len();
+            settings.holidays.extend(import.holidays.drain(..));
+            Ok(import)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(holidays: &[&str]) -> Calendar {
+        let mut settings = CalendarSettings::defaults("UTC");
+        settings.holidays = holidays.iter()
+            .map(|date| Holiday { date: date.parse().unwrap(), name: "Holiday".to_string(), ics_uid: None })
+            .collect();
+        Calendar::compile(&CalendarState { settings, version: 1, updated_at: None, updated_by: None }).unwrap()
+    }
+
+    fn at(value: &str) -> DateTime<Utc> {
+        format!("{}:00Z", value).parse().unwrap()
+    }
+
+    fn expand(rrule: &str, start: &str, until: &str) -> Vec<String> {
+        let rule = parse_rrule(rrule, &Tz::UTC).unwrap();
+        occurrences(start.parse().unwrap(), &rule, until.parse().unwrap()).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn business_duration_skips_nights_weekends_and_holidays() {
+        // 2026-10-16 is a Friday
+        let calendar = calendar(&[]);
+        assert_eq!(calendar.business_duration(at("2026-10-16T16:00"), at("2026-10-19T09:00")), Duration::hours(2));
+        assert_eq!(calendar.business_duration(at("2026-10-17T10:00"), at("2026-10-18T10:00")), Duration::zero());
+        assert_eq!(calendar.business_duration(at("2026-10-19T09:00"), at("2026-10-16T16:00")), Duration::zero());
+
+        let calendar = self::calendar(&["2026-10-19"]);
+        assert_eq!(calendar.business_duration(at("2026-10-16T16:00"), at("2026-10-20T09:00")), Duration::hours(2));
+    }
+
+    #[test]
+    fn add_business_duration_carries_over_to_the_next_business_day() {
+        let calendar = calendar(&["2026-10-19"]);
+        assert_eq!(calendar.add_business_duration(at("2026-10-16T16:30"), Duration::hours(1)), Some(at("2026-10-20T08:30")));
+        assert_eq!(calendar.add_business_duration(at("2026-10-20T06:00"), Duration::minutes(30)), Some(at("2026-10-20T08:30")));
+        assert_eq!(calendar.add_business_duration(at("2026-10-20T10:00"), Duration::zero()), Some(at("2026-10-20T10:00")));
+
+        let mut settings = CalendarSettings::defaults("UTC");
+        settings.working_days.clear();
+        let closed = Calendar::compile(&CalendarState { settings, version: 1, updated_at: None, updated_by: None }).unwrap();
+        assert_eq!(closed.add_business_duration(at("2026-10-16T10:00"), Duration::hours(1)), None);
+    }
+
+    #[test]
+    fn rrules_expand_to_the_selected_dates() {
+        assert_eq!(expand("FREQ=YEARLY;BYMONTH=12;BYMONTHDAY=25", "2024-12-25", "2027-12-31"),
+                   ["2024-12-25", "2025-12-25", "2026-12-25", "2027-12-25"]);
+        assert_eq!(expand("FREQ=MONTHLY;BYDAY=-1MO;COUNT=3", "2026-01-01", "2030-01-01"),
+                   ["2026-01-26", "2026-02-23", "2026-03-30"]);
+        assert_eq!(expand("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR", "2026-10-16", "2026-11-01"),
+                   ["2026-10-16", "2026-10-26", "2026-10-30"]);
+        assert_eq!(expand("FREQ=DAILY;UNTIL=20261018", "2026-10-16", "2030-01-01"),
+                   ["2026-10-16", "2026-10-17", "2026-10-18"]);
+    }
+
+    #[test]
+    fn rrules_stop_at_the_end_of_the_representable_dates() {
+        assert!(parse_rrule("FREQ=DAILY;INTERVAL=4294967295", &Tz::UTC).is_err());
+        assert!(parse_rrule("FREQ=DAILY;INTERVAL=0", &Tz::UTC).is_err());
+
+        let near_end = NaiveDate::MAX.checked_sub_signed(Duration::days(3)).unwrap();
+        for rrule in ["FREQ=DAILY", "FREQ=WEEKLY", "FREQ=MONTHLY", "FREQ=YEARLY", "FREQ=DAILY;INTERVAL=1000"] {
+            let rule = parse_rrule(rrule, &Tz::UTC).unwrap();
+            let dates = occurrences(near_end, &rule, NaiveDate::MAX);
+            assert!(!dates.is_empty() && dates.len() <= 4, "{}: {:?}", rrule, dates);
+        }
+        assert_eq!(parse_duration_days(&format!("P{}W", i64::MAX)), None);
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::process::Command;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error, warn};
+
+use crate::config::CaptureConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSession {
+    pub id: Uuid,
+    pub interface: String,
+    pub filter: Option<String>,
+    pub max_packets: u32,
+    pub max_duration_seconds: u64,
+    pub started_by: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub status: CaptureStatus,
+    pub file_size: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CaptureStatus {
+    Running,
+    Completed,
+    Failed,
+    Expired,
+}
+
+// The filter reaches tcpdump as one expression argument after "--"; a word that looks
+// like an option is refused anyway, so nothing in it is ever read as one
+fn check_filter(filter: &str) -> Result<()> {
+    if filter.len() > 1024 || filter.chars().any(|c| c.is_control()) {
+        return Err(anyhow!("Invalid capture filter"));
+    }
+    if let Some(word) = filter.split_whitespace().find(|word| word.starts_with('-')) {
+        return Err(anyhow!("Invalid capture filter: {} looks like a tcpdump option", word));
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct CaptureManager {
+    config: CaptureConfig,
+    capture_dir: PathBuf,
+    captures: Arc<Mutex<HashMap<Uuid, CaptureSession>>>,
+}
+
+impl CaptureManager {
+    pub fn new(config: CaptureConfig, capture_dir: &str) -> Result<Self> {
+        let capture_dir = PathBuf::from(capture_dir);
+
+        if !capture_dir.exists() {
+            fs::create_dir_all(&capture_dir)
+                .context(format!("Failed to create capture directory: {:?}", capture_dir))?;
+            info!("Created capture directory: {:?}", capture_dir);
+        }
+
+        Ok(Self {
+            config,
+            capture_dir,
+            captures: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn capture_path(&self, id: Uuid) -> PathBuf {
+        self.capture_dir.join(format!("{}.pcap", id))
+    }
+
+    // Compiles the filter with tcpdump without capturing anything
+    async fn validate_filter(&self, filter: &str) -> Result<()> {
+        check_filter(filter)?;
+
+        let output = Command::new(&self.config.tcpdump_path)
+            .arg("-d")
+            .arg("--")
+            .arg(filter)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .context("Failed to run tcpdump for filter validation")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Invalid capture filter: {}", stderr.trim()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn start_capture(&self,
+                               interface: String,
+                               duration_seconds: Option<u64>,
+                               packet_count: Option<u32>,
+                               filter: Option<String>,
+                               started_by: String) -> Result<CaptureSession> {
+        if interface.is_empty()
+            || !interface.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_') {
+            return Err(anyhow!("Invalid interface name: {}", interface));
+        }
+
+        let max_duration_seconds = duration_seconds
+            .unwrap_or(self.config.max_duration_seconds)
+            .min(self.config.max_duration_seconds);
+        let max_packets = packet_count
+            .unwrap_or(self.config.max_packets)
+            .min(self.config.max_packets);
+
+        if let Some(filter) = &filter {
+            self.validate_filter(filter).await?;
+        }
+
+        let session = CaptureSession {
+            id: Uuid::new_v4(),
+            interface: interface.clone(),
+            filter: filter.clone(),
+            max_packets,
+            max_duration_seconds,
+            started_by,
+            started_at: Utc::now(),
+            finished_at: None,
+            status: CaptureStatus::Running,
+            file_size: None,
+            error: None,
+        };
+
+        // Only one capture may run on an interface at a time
+        match self.captures.lock() {
+            Ok(mut captures) => {
+                if captures.values().any(|c| c.interface == interface && c.status == CaptureStatus::Running) {
+                    return Err(anyhow!("A capture is already running on interface {}", interface));
+                }
+                captures.insert(session.id, session.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on captures")),
+        }
+
+        let path = self.capture_path(session.id);
+        let mut command = Command::new(&self.config.tcpdump_path);
+        command
+            .arg("-i").arg(&interface)
+            .arg("-c").arg(max_packets.to_string())
+            .arg("-w").arg(&path)
+            .arg("-U")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(filter) = &filter {
+            command.arg("--").arg(filter);
+        }
+
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let message = format!("Failed to start tcpdump: {}", e);
+                self.finish(session.id, CaptureStatus::Failed, Some(message.clone()));
+                return Err(anyhow!(message));
+            }
+        };
+
+        info!("Started capture {} on {} (max {} packets, {}s)",
+              session.id, interface, max_packets, max_duration_seconds);
+
+        let manager = self.clone();
+        let id = session.id;
+        tokio::spawn(async move {
+            manager.supervise(id, child, Duration::from_secs(max_duration_seconds)).await;
+        });
+
+        Ok(session)
+    }
+
+    // Waits for tcpdump to exit on its own or kills it when the duration cap is reached
+    async fn supervise(&self, id: Uuid, mut child: tokio::process::Child, max_duration: Duration) {
+        let outcome = tokio::time::timeout(max_duration, child.wait()).await;
+
+        let (status, error) = match outcome {
+            Ok(Ok(exit)) if exit.success() => (CaptureStatus::Completed, None),
+            Ok(Ok(exit)) => {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    use tokio::io::AsyncReadExt;
+                    let _ = pipe.read_to_string(&mut stderr).await;
+                }
+                (CaptureStatus::Failed, Some(format!("tcpdump exited with {}: {}", exit, stderr.trim())))
+            },
+            Ok(Err(e)) => (CaptureStatus::Failed, Some(format!("Failed to wait for tcpdump: {}", e))),
+            Err(_) => {
+                // Duration cap reached, stop the capture and keep what was written
+                if let Err(e) = child.kill().await {
+                    warn!("Failed to stop capture {}: {}", id, e);
+                }
+                (CaptureStatus::Completed, None)
+            }
+        };
+
+        self.finish(id, status, error);
+
+        // Remove the file once the retention window has passed
+        tokio::time::sleep(Duration::from_secs(self.config.retention_minutes * 60)).await;
+        self.expire(id);
+    }
+
+    fn finish(&self, id: Uuid, status: CaptureStatus, error: Option<String>) {
+        let file_size = fs::metadata(self.capture_path(id)).ok().map(|m| m.len());
+
+        match self.captures.lock() {
+            Ok(mut captures) => {
+                if let Some(session) = captures.get_mut(&id) {
+                    session.status = status;
+                    session.finished_at = Some(Utc::now());
+                    session.file_size = file_size;
+                    session.error = error;
+                    info!("Capture {} finished: {:?}", id, session.status);
+                }
+            },
+            Err(_) => error!("Failed to acquire lock on captures"),
+        }
+    }
+
+    fn expire(&self, id: Uuid) {
+        let path = self.capture_path(id);
+        if path.exists() {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove capture file {:?}: {}", path, e);
+            }
+        }
+
+        if let Ok(mut captures) = self.captures.lock() {
+            if let Some(session) = captures.get_mut(&id) {
+                session.status = CaptureStatus::Expired;
+            }
+        }
+
+        info!("Capture {} expired", id);
+    }
+
+    pub fn get_capture(&self, id: Uuid) -> Result<CaptureSession> {
+        match self.captures.lock() {
+            Ok(captures) => {
+                captures.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Capture not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on captures")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn filters_with_option_words_are_rejected() {
+        for filter in ["-z /bin/sh -G 1 -w x", "tcp port 80 -w /tmp/out", "--version", "host 10.0.0.1 and\n-z x"] {
+            assert!(check_filter(filter).is_err(), "{:?} accepted", filter);
+        }
+        for filter in ["tcp port 80", "host 10.0.0.1 and not port 22", "portrange 20-30", "tcp[13] & 2 != 0"] {
+            assert!(check_filter(filter).is_ok(), "{:?} rejected", filter);
+        }
+    }
+
+    #[tokio::test]
+    async fn filter_is_passed_as_one_expression_after_the_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let tcpdump = dir.path().join("tcpdump");
+        let args = dir.path().join("args");
+        fs::write(&tcpdump, format!("#!/bin/sh\nprintf '%s\\n' \"$@\" >> {}\n", args.display())).unwrap();
+        fs::set_permissions(&tcpdump, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = CaptureConfig { tcpdump_path: tcpdump.display().to_string(), ..CaptureConfig::default() };
+        let manager = CaptureManager::new(config, &dir.path().join("captures").display().to_string()).unwrap();
+
+        let injected = manager.start_capture("eth0".to_string(), Some(1), Some(1),
+                                             Some("-z /bin/sh -G 1 -w x".to_string()), "alice".to_string()).await;
+        assert!(injected.is_err());
+        assert!(!args.exists(), "tcpdump ran for a rejected filter");
+
+        let session = manager.start_capture("eth0".to_string(), Some(5), Some(1),
+                                            Some("tcp port 80".to_string()), "alice".to_string()).await.unwrap();
+        for _ in 0..50 {
+            if manager.get_capture(session.id).unwrap().status != CaptureStatus::Running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let recorded = fs::read_to_string(&args).unwrap();
+        let lines: Vec<&str> = recorded.lines().collect();
+        let (validation, capture) = lines.split_at(3);
+        assert_eq!(validation, ["-d", "--", "tcp port 80"]);
+        assert_eq!(capture.last(), Some(&"tcp port 80"));
+        assert_eq!(capture[capture.len() - 2], "--");
+    }
+}
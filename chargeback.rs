@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{Datelike, NaiveDate};
+use ipnetwork::IpNetwork;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn, error};
+
+use crate::assets::AssetManager;
+use crate::bandwidth_quota::ArchivedCycle;
+use crate::config::ChargebackConfig;
+use crate::sites::SiteScope;
+use crate::visualizations::{TrafficFlow, VisualizationManager};
+
+const CHUNK_SIZE: usize = 500;
+
+// Traffic no accounting group is party to
+pub const OTHER_GROUP: &str = "other";
+
+// Which group an address goes to when it matches several
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargebackPrecedence {
+    // The first matching group in configuration order
+    #[default]
+    Order,
+    // The group with the narrowest matching subnet; an asset tag counts as a host route
+    MostSpecific,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChargebackFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct GroupUsage {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub flows: u64,
+}
+
+impl GroupUsage {
+    fn add(&mut self, other: &GroupUsage) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.flows += other.flows;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayUsage {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub usage: GroupUsage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupTotal {
+    pub group: String,
+    #[serde(flatten)]
+    pub total: GroupUsage,
+    pub days: Vec<DayUsage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChargebackReport {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub groups: Vec<GroupTotal>,
+    // Finished billing cycles of metered interfaces, see bandwidth_quota
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bandwidth: Vec<ArchivedCycle>,
+}
+
+impl ChargebackReport {
+    // One row per group and day, groups in report order
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("date,group,bytes_in,bytes_out,flows\n");
+        for group in &self.groups {
+            let name = if group.group.contains(|c| c == ',' || c == '"' || c == '\n') {
+                format!("\"{}\"", group.group.replace('"', "\"\""))
+            } else {
+                group.group.clone()
+            };
+            for day in &group.days {
+                csv.push_str(&format!("{},{},{},{},{}\n", day.date, name, day.usage.bytes_in, day.usage.bytes_out, day.usage.flows));
+            }
+        }
+        csv
+    }
+}
+
+struct CompiledGroup {
+    name: String,
+    subnets: Vec<IpNetwork>,
+    asset_tags: Vec<String>,
+}
+
+impl CompiledGroup {
+    // Prefix length of the narrowest match, None when the address is not a member
+    fn matches(&self, address: IpAddr, tags: &[String]) -> Option<u8> {
+        let host = if address.is_ipv4() { 32 } else { 128 };
+        if tags.iter().any(|t| self.asset_tags.contains(t)) {
+            return Some(host);
+        }
+        self.subnets.iter()
+            .filter(|net| net.contains(address))
+            .map(|net| net.prefix())
+            .max()
+    }
+}
+
+struct RollupState {
+    // Date (UTC) -> group -> usage
+    days: BTreeMap<NaiveDate, BTreeMap<String, GroupUsage>>,
+    next_seq: u64,
+}
+
+// Daily per-group traffic rollups built incrementally from the flow store, so reports over
+// long periods read the rollups instead of raw flows. Flows are attributed when rolled up;
+// changing the groups does not rewrite earlier days.
+#[derive(Clone)]
+pub struct ChargebackManager {
+    config: ChargebackConfig,
+    groups: Arc<Vec<CompiledGroup>>,
+    rollups_dir: PathBuf,
+    assets: AssetManager,
+    state: Arc<Mutex<RollupState>>,
+}
+
+impl ChargebackManager {
+    pub fn new(config: ChargebackConfig, rollups_dir: &str, assets: AssetManager) -> Result<Self> {
+        let mut groups = Vec::new();
+        for group in &config.groups {
+            if group.name.trim().is_empty() || group.name == OTHER_GROUP {
+                return Err(anyhow!("Invalid accounting group name: {:?}", group.name));
+            }
+            let subnets = group.subnets.iter()
+                .map(|s| s.parse::<IpNetwork>().context(format!("Invalid subnet {} in accounting group {}", s, group.name)))
+                .collect::<Result<Vec<_>>>()?;
+            groups.push(CompiledGroup {
+                name: group.name.clone(),
+                subnets,
+                asset_tags: group.asset_tags.clone(),
+            });
+        }
+
+        let rollups_dir = PathBuf::from(rollups_dir);
+        if !rollups_dir.exists() {
+            fs::create_dir_all(&rollups_dir)
+                .context(format!("Failed to create chargeback directory: {:?}", rollups_dir))?;
+            info!("Created chargeback directory: {:?}", rollups_dir);
+        }
+
+        let mut days = BTreeMap::new();
+        for entry in fs::read_dir(&rollups_dir)? {
+            let path = entry?.path();
+            let date = path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok());
+
+            if let (Some(date), true) = (date, path.extension().map_or(false, |ext| ext == "json")) {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<BTreeMap<String, GroupUsage>>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(usage) => {
+                        days.insert(date, usage);
+                    },
+                    Err(e) => {
+                        error!("Failed to load chargeback rollup {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} days of chargeback rollups for {} accounting groups", days.len(), groups.len());
+
+        Ok(Self {
+            config,
+            groups: Arc::new(groups),
+            rollups_dir,
+            assets,
+            state: Arc::new(Mutex::new(RollupState { days, next_seq: 0 })),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs.max(1))
+    }
+
+    fn group_of(&self, address: &str, asset_tags: &HashMap<IpAddr, Vec<String>>) -> Option<usize> {
+        let address: IpAddr = address.parse().ok()?;
+        let tags = asset_tags.get(&address).map(|t| t.as_slice()).unwrap_or(&[]);
+        let mut matches = self.groups.iter().enumerate()
+            .filter_map(|(index, group)| group.matches(address, tags).map(|prefix| (index, prefix)));
+
+        match self.config.precedence {
+            ChargebackPrecedence::Order => matches.next().map(|(index, _)| index),
+            // Ties go to the group listed first
+            ChargebackPrecedence::MostSpecific => matches
+                .fold(None, |best: Option<(usize, u8)>, (index, prefix)| match best {
+                    Some((_, best_prefix)) if best_prefix >= prefix => best,
+                    _ => Some((index, prefix)),
+                })
+                .map(|(index, _)| index),
+        }
+    }
+
+    // The source's group is billed the bytes as out, the destination's as in. Traffic
+    // inside one group is not billed; flows without any group go to "other" as out.
+    fn attribute(&self, flow: &TrafficFlow, asset_tags: &HashMap<IpAddr, Vec<String>>) -> Vec<(String, GroupUsage)> {
+        let source = self.group_of(&flow.source, asset_tags);
+        let destination = self.group_of(&flow.destination, asset_tags);
+        let out = GroupUsage { bytes_in: 0, bytes_out: flow.bytes, flows: 1 };
+        let inbound = GroupUsage { bytes_in: flow.bytes, bytes_out: 0, flows: 1 };
+
+        match (source, destination) {
+            (Some(s), Some(d)) if s == d => Vec::new(),
+            (None, None) => vec![(OTHER_GROUP.to_string(), out)],
+            (source, destination) => source.map(|s| (self.groups[s].name.clone(), out)).into_iter()
+                .chain(destination.map(|d| (self.groups[d].name.clone(), inbound)))
+                .collect(),
+        }
+    }
+
+    fn asset_tags(&self) -> HashMap<IpAddr, Vec<String>> {
+        match self.assets.get_all_assets() {
+            Ok(assets) => assets.into_iter()
+                .filter(|a| !a.tags.is_empty())
+                .filter_map(|a| a.ip_address.as_deref().and_then(|ip| ip.parse().ok()).map(|ip| (ip, a.tags)))
+                .collect(),
+            Err(e) => {
+                warn!("Chargeback rollup without asset tags: {}", e);
+                HashMap::new()
+            },
+        }
+    }
+
+    fn save_day(&self, date: NaiveDate, usage: &BTreeMap<String, GroupUsage>) -> Result<()> {
+        let file_path = self.rollups_dir.join(format!("{}.json", date));
+        let json = serde_json::to_string_pretty(usage)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    // Adds flows collected since the previous run to their day's rollup, then drops
+    // rollups older than the retention
+    pub fn run_once(&self, flows: &VisualizationManager) -> Result<()> {
+        let asset_tags = self.asset_tags();
+        let mut state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on chargeback rollups"))?;
+
+        let until_seq = flows.next_flow_seq();
+        let mut seq = state.next_seq;
+        let mut changed = BTreeSet::new();
+
+        loop {
+            let (chunk, next) = flows.get_traffic_flows_chunk(seq, until_seq, None, None, &SiteScope::All, CHUNK_SIZE);
+            seq = next;
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            for flow in &chunk {
+                let date = flow.timestamp.date_naive();
+                for (group, usage) in self.attribute(flow, &asset_tags) {
+                    state.days.entry(date).or_default().entry(group).or_default().add(&usage);
+                    changed.insert(date);
+                }
+            }
+        }
+        state.next_seq = seq;
+
+        for date in changed {
+            if let Some(usage) = state.days.get(&date) {
+                self.save_day(date, usage)?;
+            }
+        }
+
+        let cutoff = chrono::Utc::now().date_naive() - chrono::Duration::days(self.config.retention_days as i64);
+        let expired: Vec<NaiveDate> = state.days.range(..cutoff).map(|(date, _)| *date).collect();
+        for date in expired {
+            state.days.remove(&date);
+            if let Err(e) = fs::remove_file(self.rollups_dir.join(format!("{}.json", date))) {
+                warn!("Failed to remove expired chargeback rollup {}: {}", date, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Usage per group and day between the dates, inclusive. Groups are listed in
+    // configuration order with "other" last; `only` restricts the report to some of them.
+    pub fn report(&self, from: NaiveDate, to: NaiveDate, only: Option<&[String]>) -> Result<ChargebackReport> {
+        if from > to {
+            return Err(anyhow!("from must not be after to"));
+        }
+
+        let names: Vec<String> = self.groups.iter()
+            .map(|g| g.name.clone())
+            .chain(std::iter::once(OTHER_GROUP.to_string()))
+            .filter(|name| only.map_or(true, |only| only.contains(name)))
+            .collect();
+        if let Some(unknown) = only.and_then(|only| only.iter().find(|name| !names.contains(name))) {
+            return Err(anyhow!("Unknown accounting group: {}", unknown));
+        }
+
+        let state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on chargeback rollups"))?;
+
+        let groups = names.into_iter()
+            .map(|name| {
+                let days: Vec<DayUsage> = state.days.range(from..=to)
+                    .filter_map(|(date, usage)| usage.get(&name).map(|u| DayUsage { date: *date, usage: u.clone() }))
+                    .collect();
+                let mut total = GroupUsage::default();
+                for day in &days {
+                    total.add(&day.usage);
+                }
+                GroupTotal { group: name, total, days }
+            })
+            .collect();
+
+        Ok(ChargebackReport { from, to, groups, bandwidth: Vec::new() })
+    }
+
+    // Month-to-date totals for the digest
+    pub fn month_to_date(&self, date: NaiveDate) -> Result<Vec<String>> {
+        let first = date.with_day(1).unwrap_or(date);
+        let report = self.report(first, date, None)?;
+        Ok(report.groups.iter()
+            .filter(|g| g.total.flows > 0)
+            .map(|g| format!("{}: {} in, {} out since {}", g.group, format_bytes(g.total.bytes_in), format_bytes(g.total.bytes_out), first))
+            .collect())
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
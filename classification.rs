@@ -0,0 +1,64 @@
+use crate::models::{EventCategory, LogEntry};
+
+// Keywords checked against event_type first, then application, source and message.
+// Order matters: the first category with a matching keyword wins.
+const KEYWORDS: &[(EventCategory, &[&str])] = &[
+    (EventCategory::Malware, &["malware", "virus", "trojan", "ransomware", "quarantine", "infected"]),
+    (EventCategory::Authentication, &["authentication", "login", "logon", "logout", "logoff", "sshd", "password", "kerberos", "pam_unix"]),
+    (EventCategory::Authorization, &["authorization", "access denied", "permission", "forbidden", "privilege", "sudo"]),
+    (EventCategory::ConfigChange, &["config", "configuration", "policy change", "firewall rule", "modified setting"]),
+    (EventCategory::SystemAvailability, &["availability", "service stopped", "service started", "shutdown", "reboot", "unreachable", "heartbeat", "outage"]),
+    (EventCategory::NetworkTraffic, &["traffic", "connection", "netflow", "firewall", "packet", "dns query"]),
+    (EventCategory::Audit, &["audit"]),
+];
+
+// Tag prefix that parsers and extraction rules use to hint the category, e.g. "category:malware"
+const CATEGORY_TAG_PREFIX: &str = "category:";
+
+fn match_keywords(text: &str) -> Option<EventCategory> {
+    let text = text.to_lowercase();
+
+    KEYWORDS.iter()
+        .find(|(_, words)| words.iter().any(|w| text.contains(w)))
+        .map(|(category, _)| *category)
+}
+
+fn tag_hint(entry: &LogEntry) -> Option<EventCategory> {
+    entry.tags.iter()
+        .filter_map(|tag| tag.strip_prefix(CATEGORY_TAG_PREFIX))
+        .find_map(EventCategory::parse)
+}
+
+// Assigns a category to the entry. An explicit category set by the parser or an
+// extraction rule is kept; otherwise tag hints are used before keyword heuristics.
+pub fn classify(entry: &LogEntry) -> EventCategory {
+    if entry.category != EventCategory::Other {
+        return entry.category;
+    }
+
+    if let Some(category) = tag_hint(entry) {
+        return category;
+    }
+
+    if let Some(category) = EventCategory::parse(&entry.event_type) {
+        return category;
+    }
+
+    [
+        Some(entry.event_type.as_str()),
+        entry.application.as_deref(),
+        Some(entry.source.as_str()),
+        Some(entry.message.as_str()),
+    ]
+        .into_iter()
+        .flatten()
+        .find_map(match_keywords)
+        .unwrap_or(EventCategory::Other)
+}
+
+// Classification for already stored entries, ignoring any previously assigned category
+pub fn reclassify(entry: &LogEntry) -> EventCategory {
+    let mut probe = entry.clone();
+    probe.category = EventCategory::Other;
+    classify(&probe)
+}
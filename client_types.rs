@@ -0,0 +1,803 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use quote::ToTokens;
+use sha2::{Digest, Sha256};
+use syn::visit::{self, Visit};
+use syn::{Attribute, Expr, Fields, FnArg, GenericArgument, Item, Lit, Meta, PathArguments, Type, UseTree, Visibility};
+
+// TypeScript definitions and a typed fetch client generated from the DTO structs by
+// build.rs, which includes this file; it is not a module of the crate, so parsing the
+// sources stays out of the binary. build.rs writes the document to OUT_DIR for
+// `generate-types` to print, reports the fingerprint to /api/admin/version and also writes
+// the file when SIEM_TYPES_OUT is set.
+
+// Modules whose serde types are the API's DTOs: every type deriving Serialize or Deserialize
+// that is pub, or declared in api, is generated. A new module with such types has to be
+// added here, the tests fail otherwise, see unregistered_modules.
+pub const SOURCES: &[(&str, &str)] = &[
+    ("activity", include_str!("activity.rs")),
+    ("annotations", include_str!("annotations.rs")),
+    ("api", include_str!("api.rs")),
+    ("api_usage", include_str!("api_usage.rs")),
+    ("assets", include_str!("assets.rs")),
+    ("attachment_scan", include_str!("attachment_scan.rs")),
+    ("audit_chain", include_str!("audit_chain.rs")),
+    ("auth", include_str!("auth.rs")),
+    ("bandwidth_quota", include_str!("bandwidth_quota.rs")),
+    ("calendar", include_str!("calendar.rs")),
+    ("capture", include_str!("capture.rs")),
+    ("chargeback", include_str!("chargeback.rs")),
+    ("config", include_str!("config.rs")),
+    ("config_history", include_str!("config_history.rs")),
+    ("correlation", include_str!("correlation.rs")),
+    ("database", include_str!("database.rs")),
+    ("dhcp_relay", include_str!("dhcp_relay.rs")),
+    ("disk_monitor", include_str!("disk_monitor.rs")),
+    ("escalation", include_str!("escalation.rs")),
+    ("evidence", include_str!("evidence.rs")),
+    ("extraction", include_str!("extraction.rs")),
+    ("firewall_backup", include_str!("firewall_backup.rs")),
+    ("firewall_import", include_str!("firewall_import.rs")),
+    ("fleet", include_str!("fleet.rs")),
+    ("flow_export", include_str!("flow_export.rs")),
+    ("geoip", include_str!("geoip.rs")),
+    ("graph_grouping", include_str!("graph_grouping.rs")),
+    ("graph_snapshots", include_str!("graph_snapshots.rs")),
+    ("heartbeat", include_str!("heartbeat.rs")),
+    ("host_inventory", include_str!("host_inventory.rs")),
+    ("ingestion_quotas", include_str!("ingestion_quotas.rs")),
+    ("integrations", include_str!("integrations.rs")),
+    ("interface_metadata", include_str!("interface_metadata.rs")),
+    ("inventory_export", include_str!("inventory_export.rs")),
+    ("link_flap", include_str!("link_flap.rs")),
+    ("locations", include_str!("locations.rs")),
+    ("locks", include_str!("locks.rs")),
+    ("log_tail", include_str!("log_tail.rs")),
+    ("logs", include_str!("logs.rs")),
+    ("models", include_str!("models.rs")),
+    ("network", include_str!("network.rs")),
+    ("oidc", include_str!("oidc.rs")),
+    ("outbound", include_str!("outbound.rs")),
+    ("paths", include_str!("paths.rs")),
+    ("print_accounting", include_str!("print_accounting.rs")),
+    ("printers", include_str!("printers.rs")),
+    ("prometheus_rules", include_str!("prometheus_rules.rs")),
+    ("redaction", include_str!("redaction.rs")),
+    ("references", include_str!("references.rs")),
+    ("remediation", include_str!("remediation.rs")),
+    ("reparse", include_str!("reparse.rs")),
+    ("replication", include_str!("replication.rs")),
+    ("reports", include_str!("reports.rs")),
+    ("resolver", include_str!("resolver.rs")),
+    ("scans", include_str!("scans.rs")),
+    ("script_diff", include_str!("script_diff.rs")),
+    ("script_lint", include_str!("script_lint.rs")),
+    ("scripts", include_str!("scripts.rs")),
+    ("searches", include_str!("searches.rs")),
+    ("security", include_str!("security.rs")),
+    ("services", include_str!("services.rs")),
+    ("sessions", include_str!("sessions.rs")),
+    ("setup", include_str!("setup.rs")),
+    ("sites", include_str!("sites.rs")),
+    ("sla", include_str!("sla.rs")),
+    ("source_health", include_str!("source_health.rs")),
+    ("startup", include_str!("startup.rs")),
+    ("tagging", include_str!("tagging.rs")),
+    ("tags", include_str!("tags.rs")),
+    ("tasks", include_str!("tasks.rs")),
+    ("ticket_board", include_str!("ticket_board.rs")),
+    ("ticket_import", include_str!("ticket_import.rs")),
+    ("ticket_portal", include_str!("ticket_portal.rs")),
+    ("ticket_snippets", include_str!("ticket_snippets.rs")),
+    ("tickets", include_str!("tickets.rs")),
+    ("timeline", include_str!("timeline.rs")),
+    ("traffic_history", include_str!("traffic_history.rs")),
+    ("ups", include_str!("ups.rs")),
+    ("users", include_str!("users.rs")),
+    ("version", include_str!("version.rs")),
+    ("visualizations", include_str!("visualizations.rs")),
+    ("worklog_report", include_str!("worklog_report.rs")),
+];
+
+pub const API_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Where a registered module is read from, for build.rs to watch
+pub fn source_path(module: &str) -> PathBuf {
+    Path::new(file!()).with_file_name(format!("{}.rs", module))
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratedTypes {
+    pub document: String,
+    // Over everything but the header, so it changes exactly when the generated API does
+    pub fingerprint: String,
+    // Field types no TypeScript type is known for, generated as `unknown`
+    pub unresolved: Vec<String>,
+}
+
+// The serde attributes that change the JSON shape
+#[derive(Debug, Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    tag: Option<String>,
+    content: Option<String>,
+    into: Option<String>,
+    untagged: bool,
+    transparent: bool,
+    flatten: bool,
+    skip: bool,
+    optional: bool,
+    default: bool,
+}
+
+fn serde_attrs(attrs: &[Attribute]) -> SerdeAttrs {
+    let mut serde = SerdeAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            let name = meta.path.get_ident().map(|i| i.to_string()).unwrap_or_default();
+            let value = match meta.input.peek(syn::Token![=]) {
+                true => match meta.value()?.parse::<Lit>()? {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                },
+                false => None,
+            };
+            match name.as_str() {
+                "rename" => serde.rename = value,
+                "rename_all" => serde.rename_all = value,
+                "tag" => serde.tag = value,
+                "content" => serde.content = value,
+                "into" => serde.into = value,
+                "untagged" => serde.untagged = true,
+                "transparent" => serde.transparent = true,
+                "flatten" => serde.flatten = true,
+                "skip" => serde.skip = true,
+                "skip_serializing" | "skip_deserializing" | "skip_serializing_if" => serde.optional = true,
+                "default" => serde.default = true,
+                _ => {}
+            }
+            Ok(())
+        });
+    }
+    serde
+}
+
+fn derives(attrs: &[Attribute]) -> Vec<String> {
+    let mut derived = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("derive")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if let Some(last) = meta.path.segments.last() {
+                derived.push(last.ident.to_string());
+            }
+            Ok(())
+        });
+    }
+    derived
+}
+
+fn is_test_only(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|a| match &a.meta {
+        Meta::List(list) => list.path.is_ident("cfg") && list.tokens.to_string() == "test",
+        _ => false,
+    })
+}
+
+// Serde's rename_all on a Rust name: variants are PascalCase, fields snake_case
+fn rename_all(name: &str, rule: Option<&str>, variant: bool) -> String {
+    let words: Vec<String> = if variant {
+        let mut words = Vec::new();
+        for c in name.chars() {
+            if c.is_uppercase() || words.is_empty() {
+                words.push(String::new());
+            }
+            words.last_mut().unwrap().push(c.to_ascii_lowercase());
+        }
+        words
+    } else {
+        name.split('_').filter(|w| !w.is_empty()).map(|w| w.to_string()).collect()
+    };
+    let capitalized = || words.iter()
+        .map(|w| w[..1].to_uppercase() + &w[1..])
+        .collect::<String>();
+
+    match rule {
+        Some("lowercase") => if variant { name.to_lowercase() } else { name.to_string() },
+        Some("UPPERCASE") => name.to_uppercase(),
+        Some("PascalCase") => if variant { name.to_string() } else { capitalized() },
+        Some("camelCase") => {
+            let pascal = if variant { name.to_string() } else { capitalized() };
+            pascal[..1].to_lowercase() + &pascal[1..]
+        }
+        Some("snake_case") => words.join("_"),
+        Some("SCREAMING_SNAKE_CASE") => words.join("_").to_uppercase(),
+        Some("kebab-case") => words.join("-"),
+        Some("SCREAMING-KEBAB-CASE") => words.join("-").to_uppercase(),
+        _ => name.to_string(),
+    }
+}
+
+fn key(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain { name.to_string() } else { format!("{:?}", name) }
+}
+
+fn camel_case(name: &str) -> String {
+    rename_all(name, Some("camelCase"), false)
+}
+
+fn module_prefix(module: &str) -> String {
+    rename_all(module, Some("PascalCase"), false)
+}
+
+struct Dto {
+    module: String,
+    item: Item,
+    serialize: bool,
+}
+
+struct Handler {
+    query: Option<Type>,
+    body: Option<Type>,
+}
+
+struct Endpoint {
+    name: String,
+    method: String,
+    path: String,
+    module: String,
+    handler: String,
+}
+
+// Route registrations, `.route("/path", get(handler).post(other))`, in source order
+#[derive(Default)]
+struct Routes(Vec<(String, Vec<(String, syn::Path)>)>);
+
+impl<'ast> Visit<'ast> for Routes {
+    fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+        visit::visit_expr_method_call(self, call);
+        if call.method != "route" || call.args.len() != 2 {
+            return;
+        }
+        let Expr::Lit(syn::ExprLit { lit: Lit::Str(path), .. }) = &call.args[0] else { return };
+
+        let mut methods = Vec::new();
+        let mut chain = &call.args[1];
+        loop {
+            match chain {
+                Expr::MethodCall(m) => {
+                    if let Some(Expr::Path(handler)) = m.args.first() {
+                        methods.push((m.method.to_string(), handler.path.clone()));
+                    }
+                    chain = &m.receiver;
+                }
+                Expr::Call(c) => {
+                    if let (Expr::Path(method), Some(Expr::Path(handler))) = (&*c.func, c.args.first()) {
+                        if let Some(method) = method.path.get_ident() {
+                            methods.push((method.to_string(), handler.path.clone()));
+                        }
+                    }
+                    break;
+                }
+                _ => break,
+            }
+        }
+        methods.reverse();
+        self.0.push((path.value(), methods));
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    dtos: Vec<Dto>,
+    // (module, Rust name) to the TypeScript name
+    names: BTreeMap<(String, String), String>,
+    by_name: HashMap<String, Vec<String>>,
+    // Per module, names brought in by `use crate::...` to (module, name)
+    imports: HashMap<String, HashMap<String, (String, String)>>,
+    // `pub type` aliases, written out where they are used
+    aliases: HashMap<(String, String), Type>,
+    handlers: HashMap<(String, String), Handler>,
+    unresolved: Vec<String>,
+}
+
+fn collect_imports(tree: &UseTree, prefix: &mut Vec<String>, imports: &mut HashMap<String, (String, String)>) {
+    match tree {
+        UseTree::Path(p) => {
+            prefix.push(p.ident.to_string());
+            collect_imports(&p.tree, prefix, imports);
+            prefix.pop();
+        }
+        UseTree::Name(n) if prefix.len() == 2 && prefix[0] == "crate" => {
+            imports.insert(n.ident.to_string(), (prefix[1].clone(), n.ident.to_string()));
+        }
+        UseTree::Rename(r) if prefix.len() == 2 && prefix[0] == "crate" => {
+            imports.insert(r.rename.to_string(), (prefix[1].clone(), r.ident.to_string()));
+        }
+        UseTree::Group(g) => {
+            for tree in &g.items {
+                collect_imports(tree, prefix, imports);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Json, Query and Path extractors a handler takes; only Json and Query are typed
+fn handler(function: &syn::ItemFn) -> Handler {
+    let mut handler = Handler { query: None, body: None };
+    for input in &function.sig.inputs {
+        let FnArg::Typed(arg) = input else { continue };
+        let Type::Path(ty) = &*arg.ty else { continue };
+        let Some(last) = ty.path.segments.last() else { continue };
+        let PathArguments::AngleBracketed(args) = &last.arguments else { continue };
+        let Some(GenericArgument::Type(inner)) = args.args.first() else { continue };
+        match last.ident.to_string().as_str() {
+            "Json" => handler.body = Some(inner.clone()),
+            "Query" => handler.query = Some(inner.clone()),
+            _ => {}
+        }
+    }
+    handler
+}
+
+impl Generator {
+    fn new() -> Result<Self> {
+        let mut generator = Generator::default();
+
+        for (module, source) in SOURCES {
+            let file = syn::parse_file(source).map_err(|e| anyhow!("Failed to parse {}.rs: {}", module, e))?;
+            let mut imports = HashMap::new();
+            generator.collect(module, file.items, &mut imports);
+            generator.imports.insert(module.to_string(), imports);
+        }
+
+        for dto in &generator.dtos {
+            let name = dto_name(&dto.item);
+            generator.by_name.entry(name).or_default().push(dto.module.clone());
+        }
+        // Names declared in more than one module, or clashing with TypeScript's own, get
+        // the module as a prefix
+        const RESERVED: &[&str] = &["Array", "Date", "Error", "Map", "Object", "Partial", "Promise", "Record", "Set"];
+        for dto in &generator.dtos {
+            let name = dto_name(&dto.item);
+            let ts_name = if generator.by_name[&name].len() > 1 || RESERVED.contains(&name.as_str()) {
+                format!("{}{}", module_prefix(&dto.module), name)
+            } else {
+                name.clone()
+            };
+            generator.names.insert((dto.module.clone(), name), ts_name);
+        }
+        Ok(generator)
+    }
+
+    fn collect(&mut self, module: &str, items: Vec<Item>, imports: &mut HashMap<String, (String, String)>) {
+        for item in items {
+            let (attrs, vis) = match &item {
+                Item::Struct(s) => (&s.attrs, &s.vis),
+                Item::Enum(e) => (&e.attrs, &e.vis),
+                Item::Use(u) => {
+                    collect_imports(&u.tree, &mut Vec::new(), imports);
+                    continue;
+                }
+                Item::Type(t) => {
+                    if !matches!(t.vis, Visibility::Inherited) && t.generics.params.is_empty() {
+                        self.aliases.insert((module.to_string(), t.ident.to_string()), (*t.ty).clone());
+                    }
+                    continue;
+                }
+                Item::Fn(f) => {
+                    self.handlers.insert((module.to_string(), f.sig.ident.to_string()), handler(f));
+                    continue;
+                }
+                Item::Mod(m) => {
+                    if let (false, Some((_, items))) = (is_test_only(&m.attrs), &m.content) {
+                        self.collect(module, items.clone(), imports);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let derived = derives(attrs);
+            let serialize = derived.iter().any(|d| d == "Serialize");
+            let serde = serialize || derived.iter().any(|d| d == "Deserialize");
+            if serde && (module == "api" || !matches!(vis, Visibility::Inherited)) {
+                self.dtos.push(Dto { module: module.to_string(), item: item.clone(), serialize });
+            }
+        }
+    }
+
+    fn resolve(&self, module: &str, name: &str) -> Option<&String> {
+        if let Some(ts) = self.names.get(&(module.to_string(), name.to_string())) {
+            return Some(ts);
+        }
+        if let Some((from, original)) = self.imports.get(module).and_then(|i| i.get(name)) {
+            return self.names.get(&(from.clone(), original.clone()));
+        }
+        match self.by_name.get(name).map(|m| m.as_slice()) {
+            Some([only]) => self.names.get(&(only.clone(), name.to_string())),
+            _ => None,
+        }
+    }
+
+    // The TypeScript for a Rust type as serde writes it; `generics` are the type
+    // parameters in scope
+    fn ts_type(&mut self, module: &str, ty: &Type, generics: &[String]) -> String {
+        match ty {
+            Type::Reference(r) => self.ts_type(module, &r.elem, generics),
+            Type::Paren(p) => self.ts_type(module, &p.elem, generics),
+            Type::Group(g) => self.ts_type(module, &g.elem, generics),
+            Type::Array(a) => format!("{}[]", self.wrapped(module, &a.elem, generics)),
+            Type::Slice(s) => format!("{}[]", self.wrapped(module, &s.elem, generics)),
+            Type::Tuple(t) if t.elems.is_empty() => "null".to_string(),
+            Type::Tuple(t) => {
+                let elems: Vec<String> = t.elems.iter().map(|e| self.ts_type(module, e, generics)).collect();
+                format!("[{}]", elems.join(", "))
+            }
+            Type::Path(p) => self.ts_path(module, &p.path, generics),
+            other => self.unknown(module, other.to_token_stream().to_string()),
+        }
+    }
+
+    // Parenthesized where needed as an array element
+    fn wrapped(&mut self, module: &str, ty: &Type, generics: &[String]) -> String {
+        let ts = self.ts_type(module, ty, generics);
+        if ts.contains(' ') { format!("({})", ts) } else { ts }
+    }
+
+    fn unknown(&mut self, module: &str, rust: String) -> String {
+        self.unresolved.push(format!("{}: {}", module, rust));
+        "unknown".to_string()
+    }
+
+    fn ts_path(&mut self, module: &str, path: &syn::Path, generics: &[String]) -> String {
+        let Some(last) = path.segments.last() else { return "unknown".to_string() };
+        let name = last.ident.to_string();
+        let args: Vec<Type> = match &last.arguments {
+            PathArguments::AngleBracketed(a) => a.args.iter()
+                .filter_map(|arg| match arg {
+                    GenericArgument::Type(t) => Some(t.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if path.segments.len() == 1 && generics.contains(&name) {
+            return name;
+        }
+        match (name.as_str(), args.as_slice()) {
+            ("bool", _) => return "boolean".to_string(),
+            ("u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "f32" | "f64", _) => {
+                return "number".to_string();
+            }
+            ("String" | "str" | "char" | "Uuid" | "DateTime" | "NaiveDate" | "NaiveDateTime" | "NaiveTime" | "IpAddr"
+             | "Ipv4Addr" | "Ipv6Addr" | "SocketAddr" | "PathBuf" | "IpNetwork" | "Ipv4Network" | "Ipv6Network"
+             | "Tz" | "Url", _) => return "string".to_string(),
+            ("Value", []) => return "unknown".to_string(),
+            ("Duration", []) => return "{ secs: number; nanos: number }".to_string(),
+            ("Weekday", []) => return r#""Mon" | "Tue" | "Wed" | "Thu" | "Fri" | "Sat" | "Sun""#.to_string(),
+            ("Point" | "Coord", [_]) => return "{ x: number; y: number }".to_string(),
+            ("LineString", [_]) => return "{ x: number; y: number }[]".to_string(),
+            ("Polygon", [_]) => return "{ exterior: { x: number; y: number }[]; interiors: { x: number; y: number }[][] }".to_string(),
+            ("Option", [inner]) => {
+                // Option<Option<T>> is still just null when empty
+                let inner = self.ts_type(module, inner, generics);
+                return if inner.ends_with("| null") || inner == "unknown" { inner } else { format!("{} | null", inner) };
+            }
+            ("Box" | "Arc" | "Rc" | "Cow", [.., inner]) => return self.ts_type(module, inner, generics),
+            ("Vec" | "VecDeque" | "HashSet" | "BTreeSet", [inner]) => return format!("{}[]", self.wrapped(module, inner, generics)),
+            ("HashMap" | "BTreeMap" | "Map", [_, value]) => return format!("Record<string, {}>", self.ts_type(module, value, generics)),
+            _ => {}
+        }
+
+        // Our own types, by name or as module::Name
+        let segments: Vec<String> = path.segments.iter()
+            .map(|s| s.ident.to_string())
+            .filter(|s| s != "crate" && s != "self" && s != "super")
+            .collect();
+        let resolved = match segments.as_slice() {
+            [name] => self.resolve(module, name).cloned(),
+            // Also a module declared inside the current one
+            [from, name] => self.names.get(&(from.clone(), name.clone())).or_else(|| self.resolve(module, name)).cloned(),
+            _ => None,
+        };
+        if let (None, Some(name)) = (&resolved, segments.last()) {
+            let alias = self.imports.get(module)
+                .and_then(|i| i.get(name))
+                .cloned()
+                .unwrap_or_else(|| (module.to_string(), name.clone()));
+            if let Some(target) = self.aliases.get(&alias).cloned() {
+                return self.ts_type(&alias.0, &target, generics);
+            }
+        }
+        match resolved {
+            Some(ts) if args.is_empty() => ts,
+            Some(ts) => {
+                let args: Vec<String> = args.iter().map(|a| self.ts_type(module, a, generics)).collect();
+                format!("{}<{}>", ts, args.join(", "))
+            }
+            None => self.unknown(module, path.to_token_stream().to_string()),
+        }
+    }
+
+    // `{ a: string; b?: number }`, with flattened fields as an intersection; `container` is
+    // the struct's or the variant's serde attributes
+    fn ts_fields(&mut self, module: &str, fields: &syn::FieldsNamed, container: &SerdeAttrs,
+                 serialize: bool, generics: &[String], extra: Option<String>) -> String {
+        let mut members: Vec<String> = extra.into_iter().collect();
+        let mut flattened = Vec::new();
+        for field in &fields.named {
+            let serde = serde_attrs(&field.attrs);
+            if serde.skip {
+                continue;
+            }
+            let ty = self.ts_type(module, &field.ty, generics);
+            if serde.flatten {
+                flattened.push(ty);
+                continue;
+            }
+            let rust = field.ident.as_ref().map(|i| i.to_string()).unwrap_or_default();
+            let rust = rust.trim_start_matches("r#");
+            let name = serde.rename.unwrap_or_else(|| rename_all(rust, container.rename_all.as_deref(), false));
+            // Requests may leave out what serde fills in; responses always carry it
+            let optional = serde.optional
+                || (!serialize && (serde.default || container.default || ty.ends_with("| null")));
+            members.push(format!("{}{}: {}", key(&name), if optional { "?" } else { "" }, ty));
+        }
+
+        let object = if members.is_empty() { "{}".to_string() } else { format!("{{ {} }}", members.join("; ")) };
+        if flattened.is_empty() {
+            object
+        } else if members.is_empty() {
+            flattened.join(" & ")
+        } else {
+            format!("{} & {}", object, flattened.join(" & "))
+        }
+    }
+
+    fn ts_unnamed(&mut self, module: &str, fields: &syn::FieldsUnnamed, generics: &[String]) -> String {
+        let elems: Vec<String> = fields.unnamed.iter().map(|f| self.ts_type(module, &f.ty, generics)).collect();
+        match elems.as_slice() {
+            [single] => single.clone(),
+            _ => format!("[{}]", elems.join(", ")),
+        }
+    }
+
+    fn ts_enum(&mut self, module: &str, item: &syn::ItemEnum, serde: &SerdeAttrs, serialize: bool, generics: &[String]) -> String {
+        let rule = serde.rename_all.as_deref();
+        let mut variants = Vec::new();
+        for variant in &item.variants {
+            let attrs = serde_attrs(&variant.attrs);
+            if attrs.skip {
+                continue;
+            }
+            let name = attrs.rename.clone().unwrap_or_else(|| rename_all(&variant.ident.to_string(), rule, true));
+            let tag = format!("{:?}", name);
+            let fields = |g: &mut Self, extra: Option<String>| match &variant.fields {
+                Fields::Named(f) => g.ts_fields(module, f, &attrs, serialize, generics, extra),
+                Fields::Unnamed(f) => g.ts_unnamed(module, f, generics),
+                Fields::Unit => "null".to_string(),
+            };
+
+            let ts = match (&serde.tag, &serde.content, serde.untagged, &variant.fields) {
+                (_, _, true, _) => fields(self, None),
+                (Some(t), None, _, Fields::Unit) | (Some(t), Some(_), _, Fields::Unit) => format!("{{ {}: {} }}", key(t), tag),
+                (Some(t), None, _, Fields::Named(_)) => fields(self, Some(format!("{}: {}", key(t), tag))),
+                (Some(t), None, _, Fields::Unnamed(_)) => format!("{{ {}: {} }} & {}", key(t), tag, fields(self, None)),
+                (Some(t), Some(c), _, _) => format!("{{ {}: {}; {}: {} }}", key(t), tag, key(c), fields(self, None)),
+                (None, _, _, Fields::Unit) => tag,
+                (None, _, _, _) => format!("{{ {}: {} }}", key(&name), fields(self, None)),
+            };
+            variants.push(ts);
+        }
+        if variants.is_empty() { "never".to_string() } else { variants.join(" | ") }
+    }
+
+    fn definition(&mut self, index: usize) -> String {
+        let module = self.dtos[index].module.clone();
+        let item = self.dtos[index].item.clone();
+        let serialize = self.dtos[index].serialize;
+        let (attrs, syn_generics) = match &item {
+            Item::Struct(s) => (&s.attrs, &s.generics),
+            Item::Enum(e) => (&e.attrs, &e.generics),
+            _ => unreachable!(),
+        };
+        let generics: Vec<String> = syn_generics.type_params().map(|p| p.ident.to_string()).collect();
+        let serde = serde_attrs(attrs);
+
+        let body = match (&item, &serde.into) {
+            (_, Some(into)) => match syn::parse_str::<Type>(into) {
+                Ok(ty) => self.ts_type(&module, &ty, &generics),
+                Err(_) => self.unknown(&module, into.clone()),
+            },
+            (Item::Struct(s), None) => match &s.fields {
+                Fields::Named(f) if serde.transparent && f.named.len() == 1 => self.ts_type(&module, &f.named[0].ty, &generics),
+                Fields::Named(f) => self.ts_fields(&module, f, &serde, serialize, &generics, None),
+                Fields::Unnamed(f) => self.ts_unnamed(&module, f, &generics),
+                Fields::Unit => "null".to_string(),
+            },
+            (Item::Enum(e), None) => self.ts_enum(&module, e, &serde, serialize, &generics),
+            _ => unreachable!(),
+        };
+
+        let name = &self.names[&(module, dto_name(&item))];
+        let params = if generics.is_empty() { String::new() } else { format!("<{}>", generics.join(", ")) };
+        format!("export type {}{} = {};\n", name, params, body)
+    }
+
+    fn endpoints(&self) -> Result<Vec<Endpoint>> {
+        let (_, api) = SOURCES.iter()
+            .find(|(module, _)| *module == "api")
+            .ok_or_else(|| anyhow!("The api module is not registered"))?;
+        let mut routes = Routes::default();
+        routes.visit_file(&syn::parse_file(api)?);
+
+        let mut endpoints = Vec::new();
+        let mut taken: HashMap<String, usize> = HashMap::new();
+        for (path, methods) in routes.0 {
+            for (method, handler) in methods {
+                let segments: Vec<String> = handler.segments.iter().map(|s| s.ident.to_string()).collect();
+                let (module, handler) = match segments.as_slice() {
+                    [name] => ("api".to_string(), name.clone()),
+                    [.., from, name] => (from.clone(), name.clone()),
+                    [] => continue,
+                };
+                // A handler behind several routes is named once per route
+                let base = camel_case(&handler);
+                let count = taken.entry(base.clone()).or_default();
+                *count += 1;
+                let name = if *count == 1 { base } else { format!("{}{}", base, count) };
+                endpoints.push(Endpoint { name, method: method.to_uppercase(), path: path.clone(), module, handler });
+            }
+        }
+        Ok(endpoints)
+    }
+}
+
+fn dto_name(item: &Item) -> String {
+    match item {
+        Item::Struct(s) => s.ident.to_string(),
+        Item::Enum(e) => e.ident.to_string(),
+        _ => String::new(),
+    }
+}
+
+const CLIENT: &str = r#"export type EndpointName = keyof typeof ENDPOINTS;
+
+export class ApiError extends Error {
+  constructor(public status: number, public body: string) {
+    super(`HTTP ${status}: ${body}`);
+  }
+}
+
+// Responses are not typed: handlers do not declare them. Path parameters fill the
+// `:name` segments of the route.
+export class SiemClient {
+  constructor(private baseUrl: string, private token?: string) {}
+
+  async call<K extends EndpointName, R = unknown>(
+    name: K,
+    options: {
+      params?: Record<string, string | number>;
+      query?: EndpointRequests[K]["query"];
+      body?: EndpointRequests[K]["body"];
+    } = {},
+  ): Promise<R> {
+    const endpoint = ENDPOINTS[name];
+    let path: string = endpoint.path;
+    for (const [param, value] of Object.entries(options.params ?? {})) {
+      path = path.replace(`:${param}`, encodeURIComponent(String(value)));
+    }
+    const query = new URLSearchParams();
+    for (const [param, value] of Object.entries((options.query ?? {}) as Record<string, unknown>)) {
+      if (value !== undefined && value !== null) {
+        query.set(param, String(value));
+      }
+    }
+    const search = query.toString();
+
+    const headers: Record<string, string> = {};
+    if (this.token) {
+      headers["Authorization"] = `Bearer ${this.token}`;
+    }
+    if (options.body !== undefined) {
+      headers["Content-Type"] = "application/json";
+    }
+    const response = await fetch(this.baseUrl.replace(/\/$/, "") + path + (search ? `?${search}` : ""), {
+      method: endpoint.method,
+      headers,
+      body: options.body !== undefined ? JSON.stringify(options.body) : undefined,
+    });
+    const text = await response.text();
+    if (!response.ok) {
+      throw new ApiError(response.status, text);
+    }
+    return (text ? JSON.parse(text) : null) as R;
+  }
+
+  // False when the server runs another API than these types were generated from
+  async checkVersion(): Promise<boolean> {
+    const version = await this.call<"getVersion", { build: { version: string; api_fingerprint: string } }>("getVersion");
+    return version.build.version === API_VERSION && version.build.api_fingerprint === API_FINGERPRINT;
+  }
+}
+"#;
+
+// The same sources always give the same document: types are sorted by name and routes
+// kept in the order they are registered
+pub fn generate() -> Result<GeneratedTypes> {
+    let mut generator = Generator::new()?;
+
+    let mut definitions: Vec<(String, String)> = (0..generator.dtos.len())
+        .map(|i| {
+            let name = generator.names[&(generator.dtos[i].module.clone(), dto_name(&generator.dtos[i].item))].clone();
+            (name, generator.definition(i))
+        })
+        .collect();
+    definitions.sort();
+
+    let mut body = String::new();
+    for (_, definition) in &definitions {
+        body.push_str(definition);
+    }
+
+    let endpoints = generator.endpoints()?;
+    body.push_str("\nexport const ENDPOINTS = {\n");
+    for endpoint in &endpoints {
+        writeln!(body, "  {}: {{ method: {:?}, path: {:?} }},", endpoint.name, endpoint.method, endpoint.path)?;
+    }
+    body.push_str("} as const;\n\nexport interface EndpointRequests {\n");
+    for endpoint in &endpoints {
+        let (query, request) = match generator.handlers.get(&(endpoint.module.clone(), endpoint.handler.clone())) {
+            Some(handler) => (handler.query.clone(), handler.body.clone()),
+            None => (None, None),
+        };
+        let query = query.map_or("never".to_string(), |t| generator.ts_type(&endpoint.module, &t, &[]));
+        let request = request.map_or("never".to_string(), |t| generator.ts_type(&endpoint.module, &t, &[]));
+        writeln!(body, "  {}: {{ query: {}; body: {} }};", endpoint.name, query, request)?;
+    }
+    body.push_str("}\n");
+
+    let fingerprint = hex::encode(&Sha256::digest(format!("{}\n{}", API_VERSION, body).as_bytes())[..8]);
+    let mut document = String::new();
+    writeln!(document, "// Generated from the rust-siem API DTOs by `generate-types`, do not edit.")?;
+    writeln!(document, "// Compare with GET /api/admin/version to detect a server running another API.\n")?;
+    writeln!(document, "export const API_VERSION = {:?};", API_VERSION)?;
+    writeln!(document, "export const API_FINGERPRINT = {:?};\n", fingerprint)?;
+    document.push_str(&body);
+    document.push('\n');
+    document.push_str(CLIENT);
+
+    let mut unresolved = generator.unresolved;
+    unresolved.sort();
+    unresolved.dedup();
+    Ok(GeneratedTypes { document, fingerprint, unresolved })
+}
+
+// Modules with serde types that never cross the API
+const NOT_API: &[&str] = &["bench"];
+
+// Modules declared in main.rs with DTOs that are missing from SOURCES. build.rs hands them
+// to the crate, whose tests fail while there are any.
+pub fn unregistered_modules() -> Result<Vec<String>> {
+    let main = fs::read_to_string(source_path("main"))?;
+    let mut missing = Vec::new();
+    for module in main.lines().filter_map(|l| l.trim().strip_prefix("mod ")?.strip_suffix(';')) {
+        if NOT_API.contains(&module) || SOURCES.iter().any(|(m, _)| *m == module) {
+            continue;
+        }
+        let mut generator = Generator::default();
+        let file = syn::parse_file(&fs::read_to_string(source_path(module))?)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", module, e))?;
+        generator.collect(module, file.items, &mut HashMap::new());
+        if !generator.dtos.is_empty() {
+            missing.push(module.to_string());
+        }
+    }
+    Ok(missing)
+}
@@ -1,17 +1,149 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, anyhow};
+use uuid::Uuid;
+
+use crate::disk_monitor::ProtectiveAction;
+use crate::flow_export::FlowExportFormat;
+use crate::chargeback::ChargebackPrecedence;
+use crate::script_lint::{LintRule, LintSeverity};
+use crate::tickets::{TicketCategory, TicketPriority};
+use crate::models::AlertSeverity;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub server_port: u16,
+    // Empty directories are resolved at startup, see paths::Paths
+    #[serde(default)]
     pub scripts_dir: String,
+    #[serde(default)]
     pub log_dir: String,
     pub retention_days: u32,
     pub admin_email: String,
+    #[serde(default)]
+    pub data_dir: String,
+    #[serde(default)]
+    pub paths: PathsConfig,
     pub smtp: SmtpConfig,
     pub ad_integration: ActiveDirectoryConfig,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    #[serde(default)]
+    pub tasks: TasksConfig,
+    #[serde(default)]
+    pub flow_export: FlowExportConfig,
+    #[serde(default)]
+    pub disk: DiskConfig,
+    // Server certificate, shared by the TLS listeners
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub syslog_tls: SyslogTlsConfig,
+    #[serde(default)]
+    pub reports: ReportsConfig,
+    #[serde(default)]
+    pub geoip: GeoIpConfig,
+    #[serde(default)]
+    pub impossible_travel: ImpossibleTravelConfig,
+    // Re-read from the config file while running, see password_policy::PasswordPolicy
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+    #[serde(default)]
+    pub fleet: FleetConfig,
+    #[serde(default)]
+    pub firewall: FirewallConfig,
+    // Initial default quota; later changes go through /api/logs/quotas
+    #[serde(default)]
+    pub ingestion_quota: QuotaConfig,
+    #[serde(default)]
+    pub script_approval: ScriptApprovalConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub digest: DigestConfig,
+    #[serde(default)]
+    pub ticket_autoclose: TicketAutoCloseConfig,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    #[serde(default)]
+    pub print_accounting: PrintAccountingConfig,
+    #[serde(default)]
+    pub evidence: EvidenceConfig,
+    #[serde(default)]
+    pub log_tail: LogTailConfig,
+    #[serde(default)]
+    pub source_health: SourceHealthConfig,
+    #[serde(default)]
+    pub graph_snapshots: GraphSnapshotConfig,
+    #[serde(default)]
+    pub body_limits: BodyLimitsConfig,
+    #[serde(default)]
+    pub update_check: UpdateCheckConfig,
+    #[serde(default)]
+    pub clock_skew: ClockSkewConfig,
+    #[serde(default)]
+    pub alert_events: AlertEventsConfig,
+    #[serde(default)]
+    pub link_flap: LinkFlapConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub printer_reorder: PrinterReorderConfig,
+    #[serde(default)]
+    pub attachment_scan: AttachmentScanConfig,
+    #[serde(default)]
+    pub chargeback: ChargebackConfig,
+    #[serde(default)]
+    pub script_lint: ScriptLintConfig,
+    #[serde(default)]
+    pub ups: UpsConfig,
+    #[serde(default)]
+    pub portal: PortalConfig,
+    #[serde(default)]
+    pub asset_drift: AssetDriftConfig,
+    #[serde(default)]
+    pub traffic_monitoring: TrafficMonitoringConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub drop_log: DropLogConfig,
+    #[serde(default)]
+    pub correlation: CorrelationConfig,
+    #[serde(default)]
+    pub bandwidth_quota: BandwidthQuotaConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    #[serde(default)]
+    pub firewall_backup: FirewallBackupConfig,
+    #[serde(default)]
+    pub ticket_worklog: TicketWorklogConfig,
+    #[serde(default)]
+    pub api_usage: ApiUsageConfig,
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    #[serde(default)]
+    pub integrations: IntegrationsConfig,
+    #[serde(default)]
+    pub sla: SlaConfig,
+    #[serde(default)]
+    pub after_hours: AfterHoursConfig,
+    #[serde(default)]
+    pub inventory: InventoryConfig,
+    #[serde(default)]
+    pub anonymize: AnonymizeConfig,
+    #[serde(default)]
+    pub dhcp_relay: DhcpRelayConfig,
+    #[serde(default)]
+    pub references: ReferencesConfig,
+    // PostgreSQL connection string, set by the setup wizard
+    #[serde(default)]
+    pub database_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,13 +164,1489 @@ pub struct ActiveDirectoryConfig {
     pub bind_password: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    pub jwt_secret: String,
+    pub token_expiration_hours: u32,
+    // Reverse proxies whose X-Forwarded-For is believed, as addresses or networks.
+    // Clients connecting from anywhere else are known by their own address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: "change_this_to_a_secure_random_string".to_string(),
+            token_expiration_hours: 24,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    pub tcpdump_path: String,
+    pub max_duration_seconds: u64,
+    pub max_packets: u32,
+    pub retention_minutes: u64,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            tcpdump_path: "tcpdump".to_string(),
+            max_duration_seconds: 300,
+            max_packets: 100_000,
+            retention_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TasksConfig {
+    pub failure_alert_threshold: u32,
+    pub max_backoff_seconds: u64,
+}
+
+impl Default for TasksConfig {
+    fn default() -> Self {
+        Self {
+            failure_alert_threshold: 5,
+            max_backoff_seconds: 600,
+        }
+    }
+}
+
+// Continuous traffic flow export to rotating files under <log_dir>/flows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowExportConfig {
+    pub enabled: bool,
+    pub format: FlowExportFormat,
+    pub interval_seconds: u64,
+    pub rotate_size_mb: u64,
+    pub rotate_minutes: u64,
+    pub retention_days: u32,
+}
+
+impl Default for FlowExportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: FlowExportFormat::Csv,
+            interval_seconds: 60,
+            rotate_size_mb: 64,
+            rotate_minutes: 60,
+            retention_days: 7,
+        }
+    }
+}
+
+// Free-space thresholds (percent of the volume) and what to do once a volume is critical
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskConfig {
+    pub interval_seconds: u64,
+    pub warning_free_percent: f64,
+    pub critical_free_percent: f64,
+    pub protective_actions: Vec<ProtectiveAction>,
+    // Data older than this is deleted by the emergency retention purge
+    pub emergency_retention_hours: u64,
+    // Directory on the database volume, when the database runs on this host
+    pub database_dir: Option<String>,
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 60,
+            warning_free_percent: 15.0,
+            critical_free_percent: 5.0,
+            protective_actions: vec![
+                ProtectiveAction::PauseFlowExport,
+                ProtectiveAction::PauseReports,
+                ProtectiveAction::RetentionPurge,
+                ProtectiveAction::RejectUploads,
+            ],
+            emergency_retention_hours: 24,
+            database_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+// RFC 5425 syslog over TLS listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyslogTlsConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    // Dedicated certificate; the server [tls] certificate is used when unset
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    // When set, clients must present a certificate signed by this CA
+    pub client_ca_path: Option<String>,
+    // Client certificate CN -> source tag; unmapped CNs are tagged as-is
+    #[serde(default)]
+    pub cn_sources: HashMap<String, String>,
+    pub max_connections: usize,
+    pub max_events_per_second: u32,
+    pub max_message_bytes: usize,
+    pub idle_timeout_seconds: u64,
+}
+
+impl Default for SyslogTlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "0.0.0.0:6514".to_string(),
+            cert_path: None,
+            key_path: None,
+            client_ca_path: None,
+            cn_sources: HashMap::new(),
+            max_connections: 256,
+            max_events_per_second: 1000,
+            max_message_bytes: 64 * 1024,
+            idle_timeout_seconds: 300,
+        }
+    }
+}
+
+// Branding and localization applied to generated reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportsConfig {
+    pub organization_name: String,
+    // PNG, JPEG, GIF or SVG, embedded into HTML reports
+    pub logo_path: Option<String>,
+    // IANA timezone used for every timestamp in a report
+    pub timezone: String,
+    // "en" and "cs" are bundled, other locales are read from <reports_dir>/locales/<locale>.json
+    pub locale: String,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        Self {
+            organization_name: String::new(),
+            logo_path: None,
+            timezone: "UTC".to_string(),
+            locale: "en".to_string(),
+        }
+    }
+}
+
+// GeoIP lookups for traffic maps; the site is where arcs to remote endpoints start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeoIpConfig {
+    // MaxMind City database, e.g. GeoLite2-City.mmdb
+    pub database_path: Option<String>,
+    // Networks treated as internal in addition to the private and local ranges
+    pub internal_networks: Vec<String>,
+    pub site_name: String,
+    pub site_latitude: f64,
+    pub site_longitude: f64,
+}
+
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            database_path: None,
+            internal_networks: Vec::new(),
+            site_name: "Site".to_string(),
+            site_latitude: 0.0,
+            site_longitude: 0.0,
+        }
+    }
+}
+
+// Detection of logins from implausibly distant places and from watched countries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImpossibleTravelConfig {
+    pub enabled: bool,
+    // Faster than a commercial flight by default
+    pub max_speed_kmh: f64,
+    // GeoIP locations are not precise, closer logins are never flagged
+    pub min_distance_km: f64,
+    // How long a user's last login is remembered
+    pub cache_ttl_hours: u32,
+    // ISO country codes that raise an alert on any login
+    pub watch_countries: Vec<String>,
+}
+
+impl Default for ImpossibleTravelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_speed_kmh: 900.0,
+            min_distance_km: 300.0,
+            cache_ttl_hours: 24,
+            watch_countries: Vec::new(),
+        }
+    }
+}
+
+// Rules for new passwords, enforced on account creation and password change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordPolicyConfig {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub disallow_username: bool,
+    pub disallow_common: bool,
+    // Number of previous passwords that may not be reused
+    pub history_size: usize,
+    // Passwords older than this only allow changing the password
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: false,
+            disallow_username: true,
+            disallow_common: true,
+            history_size: 5,
+            max_age_days: None,
+        }
+    }
+}
+
+// Running scripts on assets over SSH
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FleetConfig {
+    // Account used on the targets; the key must be authorized there
+    pub ssh_user: Option<String>,
+    pub ssh_identity_file: Option<String>,
+    pub connect_timeout_secs: u32,
+    // A target still running after this is killed and counted as failed
+    pub execution_timeout_secs: u64,
+    // Targets executed at the same time unless the request asks for fewer
+    pub max_concurrency: usize,
+}
+
+impl Default for FleetConfig {
+    fn default() -> Self {
+        Self {
+            ssh_user: None,
+            ssh_identity_file: None,
+            connect_timeout_secs: 10,
+            execution_timeout_secs: 600,
+            max_concurrency: 10,
+        }
+    }
+}
+
+// Base ruleset generated at startup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FirewallConfig {
+    // Accept ICMP and ICMPv6 echo requests (ping) on all interfaces
+    #[serde(default)]
+    pub allow_icmp_echo: bool,
+    // Lets a user with network:apply apply their own change requests, for teams too small
+    // for a second person to review them
+    #[serde(default)]
+    pub allow_self_approval: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    // Keep one in sample_every events beyond the limit, tagged as sampled
+    Soft,
+    // Drop everything beyond the limit
+    Hard,
+}
+
+// Events per minute a single source may send, see ingestion_quotas
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuotaConfig {
+    // 0 disables the quota
+    pub events_per_minute: u64,
+    pub action: QuotaAction,
+    pub sample_every: u32,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            events_per_minute: 6000,
+            action: QuotaAction::Soft,
+            sample_every: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptApprovalConfig {
+    // Approvers are reminded of reviews pending longer than this, then again every
+    // further interval
+    pub reminder_after_hours: u64,
+    // Lets an approver approve a script they wrote or last edited, for teams too small
+    // for a second person to review it
+    #[serde(default)]
+    pub allow_self_approval: bool,
+}
+
+impl Default for ScriptApprovalConfig {
+    fn default() -> Self {
+        Self {
+            reminder_after_hours: 24,
+            allow_self_approval: false,
+        }
+    }
+}
+
+// Users holding a claim value get the role; rules are tried in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcRoleRule {
+    pub value: String,
+    pub role: String,
+}
+
+// OpenID Connect single sign-on, see oidc
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OidcConfig {
+    pub enabled: bool,
+    // Discovery is read from {issuer_url}/.well-known/openid-configuration
+    pub issuer_url: String,
+    pub client_id: String,
+    // The client secret is never kept in this file: it is read from client_secret_file,
+    // or from the environment variable client_secret_env
+    pub client_secret_file: Option<String>,
+    pub client_secret_env: Option<String>,
+    // Our callback, registered with the provider: https://<host>/api/auth/oidc/callback
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    pub username_claim: String,
+    pub role_claim: String,
+    pub role_mapping: Vec<OidcRoleRule>,
+    pub default_role: String,
+    pub clock_skew_secs: u64,
+    pub jwks_cache_secs: u64,
+    // Logout also returns the provider's end-session URL for the client to visit
+    pub end_session_on_logout: bool,
+    pub post_logout_redirect_url: Option<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer_url: String::new(),
+            client_id: String::new(),
+            client_secret_file: None,
+            client_secret_env: Some("SIEM_OIDC_CLIENT_SECRET".to_string()),
+            redirect_url: String::new(),
+            scopes: vec!["openid".to_string(), "profile".to_string(), "email".to_string()],
+            username_claim: "preferred_username".to_string(),
+            role_claim: "groups".to_string(),
+            role_mapping: Vec::new(),
+            default_role: "user".to_string(),
+            clock_skew_secs: 60,
+            jwks_cache_secs: 3600,
+            end_session_on_logout: false,
+            post_logout_redirect_url: None,
+        }
+    }
+}
+
+// Daily activity digest, see digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestConfig {
+    pub enabled: bool,
+    // Local time (HH:MM, in the reports timezone) the digest is sent at each day
+    pub send_at: String,
+    pub recipients: Vec<String>,
+    // Number of log sources listed by volume
+    pub top_sources: usize,
+    // Add month-to-date traffic per accounting group, see chargeback
+    #[serde(default)]
+    pub chargeback: bool,
+    // Saved log searches whose matches over the day are counted, see searches
+    #[serde(default)]
+    pub saved_searches: Vec<Uuid>,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            send_at: "07:00".to_string(),
+            recipients: Vec::new(),
+            top_sources: 10,
+            chargeback: false,
+            saved_searches: Vec::new(),
+        }
+    }
+}
+
+// Resolved tickets without activity get a warning comment after warn_after_days and
+// are closed close_after_days after the warning
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoClosePolicy {
+    pub enabled: bool,
+    pub warn_after_days: u32,
+    pub close_after_days: u32,
+}
+
+impl Default for AutoClosePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            warn_after_days: 7,
+            close_after_days: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TicketAutoCloseConfig {
+    #[serde(flatten)]
+    pub policy: AutoClosePolicy,
+    // Replaces the policy above for tickets of the category
+    #[serde(default)]
+    pub categories: HashMap<TicketCategory, AutoClosePolicy>,
+}
+
+impl TicketAutoCloseConfig {
+    pub fn policy_for(&self, category: &TicketCategory) -> &AutoClosePolicy {
+        self.categories.get(category).unwrap_or(&self.policy)
+    }
+}
+
+// Hostname enrichment, see resolver::Resolver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    // "ip" or "ip:port"; empty uses the system resolver configuration
+    pub servers: Vec<String>,
+    // Entries per cache (reverse and forward each)
+    pub cache_size: usize,
+    // Upper bound of the record TTL an answer is kept for
+    pub max_ttl_secs: u64,
+    // How long a failed or empty lookup is kept
+    pub negative_ttl_secs: u64,
+    pub timeout_ms: u64,
+    pub max_concurrent_lookups: usize,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            cache_size: 10_000,
+            max_ttl_secs: 3600,
+            negative_ttl_secs: 300,
+            timeout_ms: 2000,
+            max_concurrent_lookups: 16,
+        }
+    }
+}
+
+// A named set of users sharing one monthly page quota
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrintGroup {
+    pub members: Vec<String>,
+    pub monthly_pages: u64,
+}
+
+// Per-user page accounting, see print_accounting::PrintAccounting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintAccountingConfig {
+    // Estimate for jobs the printer reports no page count for
+    pub kb_per_page: f64,
+    // Username -> monthly page quota
+    pub user_quotas: HashMap<String, u64>,
+    pub groups: HashMap<String, PrintGroup>,
+}
+
+impl Default for PrintAccountingConfig {
+    fn default() -> Self {
+        Self {
+            kb_per_page: 100.0,
+            user_quotas: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+}
+
+// Evidence packages, see evidence::EvidenceManager
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvidenceConfig {
+    // Packages are deleted this long after they were generated
+    pub retention_hours: u64,
+}
+
+impl Default for EvidenceConfig {
+    fn default() -> Self {
+        Self {
+            retention_hours: 72,
+        }
+    }
+}
+
+// GET /api/logs/tail, see log_tail::LogTail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogTailConfig {
+    pub max_sessions: usize,
+    // Entries a client may fall behind before it starts losing them
+    pub buffer: usize,
+}
+
+impl Default for LogTailConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 10,
+            buffer: 1024,
+        }
+    }
+}
+
+// Silence detection for expected log sources, see source_health
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceHealthConfig {
+    pub check_interval_secs: u64,
+    // Floor for intervals learned from a source's cadence
+    pub min_silence_minutes: i64,
+}
+
+impl Default for SourceHealthConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_secs: 60,
+            min_silence_minutes: 15,
+        }
+    }
+}
+
+// Difference between an event's own timestamp and when it was received, see source_health
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockSkewConfig {
+    // Store events skewed beyond rewrite_after_secs at their receipt time; the original
+    // timestamp is kept in a tag. Audited at startup when enabled.
+    pub rewrite_timestamps: bool,
+    pub rewrite_after_secs: i64,
+    // A source whose median skew stays beyond this raises an alert
+    pub alert_after_secs: i64,
+    // Events needed before a source's median skew is trusted
+    pub min_samples: usize,
+}
+
+impl Default for ClockSkewConfig {
+    fn default() -> Self {
+        Self {
+            rewrite_timestamps: false,
+            rewrite_after_secs: 300,
+            alert_after_secs: 120,
+            min_samples: 20,
+        }
+    }
+}
+
+// Log entries for our own alerts being created, escalated and resolved, see alert_events
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct AlertEventsConfig {
+    pub enabled: bool,
+}
+
+// Interface flap detection from link events, see link_flap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkFlapConfig {
+    pub enabled: bool,
+    // Sliding window the flap score (link transitions) is counted over
+    pub window_secs: u64,
+    // Score at which the interface is flapping and an alert is raised
+    pub flap_threshold: usize,
+    // Score at or below which the alert is resolved
+    pub clear_below: usize,
+}
+
+impl Default for LinkFlapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 60,
+            flap_threshold: 6,
+            clear_below: 1,
+        }
+    }
+}
+
+// Connection pool and outage handling for database_url, see database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    // Retries of a transient failure, the delay doubling from retry_base_ms
+    pub retry_attempts: u32,
+    pub retry_base_ms: u64,
+    pub probe_interval_secs: u64,
+    // A pool unreachable for this long is replaced by a fresh one
+    pub recycle_after_secs: u64,
+    // Log writes are spooled to disk during an outage, up to this size
+    pub spool_max_bytes: u64,
+    // Logs are inserted in batches of up to batch_size rows, a partial batch written
+    // once it is flush_interval_ms old
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            acquire_timeout_secs: 5,
+            retry_attempts: 3,
+            retry_base_ms: 200,
+            probe_interval_secs: 10,
+            recycle_after_secs: 60,
+            spool_max_bytes: 100 * 1024 * 1024,
+            batch_size: 500,
+            flush_interval_ms: 200,
+        }
+    }
+}
+
+// What happens when a printer supply runs low, see printer_reorder
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PrinterReorderConfig {
+    // Receives a printer.supply_reorder payload for purchasing
+    pub webhook_url: Option<String>,
+    // Also open a pre-filled Hardware ticket
+    pub create_ticket: bool,
+    // Printer model -> supply name -> part number, for supplies without their own
+    pub part_numbers: HashMap<String, HashMap<String, String>>,
+}
+
+// Inspection of ticket attachments on upload, see attachment_scan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttachmentScanConfig {
+    // Extensions accepted without the dot; empty accepts any
+    pub allowed_extensions: Vec<String>,
+    // Check the leading bytes against the extension
+    pub verify_content: bool,
+    // clamd socket to stream uploads to; takes precedence over command
+    pub clamd_socket: Option<String>,
+    // Scanner reading the file on stdin, e.g. ["clamscan", "--no-summary", "-"]
+    pub command: Vec<String>,
+    pub timeout_secs: u64,
+    // Accept uploads when the scanner is down or times out
+    pub fail_open: bool,
+}
+
+impl AttachmentScanConfig {
+    pub fn scanner_name(&self) -> &'static str {
+        if self.clamd_socket.is_some() {
+            "clamd"
+        } else if !self.command.is_empty() {
+            "command"
+        } else {
+            "none"
+        }
+    }
+}
+
+impl Default for AttachmentScanConfig {
+    fn default() -> Self {
+        Self {
+            allowed_extensions: ["pdf", "png", "jpg", "jpeg", "gif", "txt", "log", "csv", "json", "xml",
+                                 "eml", "zip", "gz", "7z", "pcap", "pcapng", "docx", "xlsx", "pptx", "odt", "ods"]
+                .iter().map(|e| e.to_string()).collect(),
+            verify_content: true,
+            clamd_socket: None,
+            command: Vec::new(),
+            timeout_secs: 30,
+            fail_open: false,
+        }
+    }
+}
+
+// Departments or other cost centres whose traffic is reported, see chargeback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountingGroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub subnets: Vec<String>,
+    // Assets carrying any of these tags belong to the group by their address
+    #[serde(default)]
+    pub asset_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChargebackConfig {
+    pub groups: Vec<AccountingGroupConfig>,
+    pub precedence: ChargebackPrecedence,
+    // How often new flows are rolled up; must be shorter than flows stay in the store
+    pub interval_secs: u64,
+    pub retention_days: u32,
+}
+
+impl Default for ChargebackConfig {
+    fn default() -> Self {
+        Self {
+            groups: Vec::new(),
+            precedence: ChargebackPrecedence::Order,
+            interval_secs: 60,
+            retention_days: 400,
+        }
+    }
+}
+
+// Lint pass over script content on create and update, see script_lint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptLintConfig {
+    // Unacknowledged findings at or above this block approval; unset never blocks
+    pub block_severity: Option<LintSeverity>,
+    // Added to the built-in table; a rule with a built-in id replaces it
+    pub rules: Vec<LintRule>,
+    // Ids of rules to skip, built-in or configured
+    pub disabled_rules: Vec<String>,
+}
+
+impl Default for ScriptLintConfig {
+    fn default() -> Self {
+        Self {
+            block_severity: Some(LintSeverity::High),
+            rules: Vec::new(),
+            disabled_rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpsDriver {
+    // upsd, the NUT network server (port 3493)
+    Nut,
+    // apcupsd's network information server (port 3551)
+    Apcupsd,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsUnitConfig {
+    pub name: String,
+    pub driver: UpsDriver,
+    pub host: String,
+    // Defaults to the driver's standard port
+    #[serde(default)]
+    pub port: Option<u16>,
+    // UPS name on the NUT server, e.g. "ups" of ups@host
+    #[serde(default)]
+    pub ups: Option<String>,
+}
+
+// Approved script run when a unit on battery has less runtime left than below_secs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsActionConfig {
+    pub script: String,
+    pub below_secs: u64,
+    // Argument templates, filled from the alert and ups.name, ups.charge, ups.runtime_secs
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    #[serde(default = "default_ups_action_cooldown")]
+    pub cooldown_secs: u64,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_ups_action_cooldown() -> u64 {
+    3600
+}
+
+// UPS units polled for power state, see ups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpsConfig {
+    pub units: Vec<UpsUnitConfig>,
+    pub poll_interval_secs: u64,
+    pub timeout_secs: u64,
+    // Failed polls in a row before communication counts as lost
+    pub comm_loss_polls: u32,
+    // Battery counts as low at or below either threshold, or when the UPS says so
+    pub low_battery_percent: f64,
+    pub low_runtime_secs: u64,
+    pub runtime_action: Option<UpsActionConfig>,
+}
+
+impl Default for UpsConfig {
+    fn default() -> Self {
+        Self {
+            units: Vec::new(),
+            poll_interval_secs: 30,
+            timeout_secs: 5,
+            comm_loss_polls: 3,
+            low_battery_percent: 30.0,
+            low_runtime_secs: 600,
+            runtime_action: None,
+        }
+    }
+}
+
+// Ticket links for requesters without an account, see ticket_portal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortalConfig {
+    // Page the emailed links point at, the token is appended; unset links the API
+    pub base_url: Option<String>,
+    pub token_ttl_days: u32,
+    // Portal requests allowed per source address and minute
+    pub requests_per_minute: u32,
+}
+
+impl Default for PortalConfig {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            token_ttl_days: 30,
+            requests_per_minute: 20,
+        }
+    }
+}
+
+// Observed asset values disagreeing with the record, see assets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetDriftConfig {
+    // Raise an alert the first time a field is seen drifting
+    pub alert: bool,
+}
+
+impl Default for AssetDriftConfig {
+    fn default() -> Self {
+        Self { alert: true }
+    }
+}
+
+// Interface counter sampling for the traffic statistics; the interval follows config
+// reloads, see ConfigHistory::apply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrafficMonitoringConfig {
+    pub interval_secs: u64,
+}
+
+impl Default for TrafficMonitoringConfig {
+    fn default() -> Self {
+        Self { interval_secs: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    // Through the configured proxies, except for no_proxy destinations
+    Proxy,
+    // Straight to the destination, ignoring the proxies
+    Direct,
+}
+
+// Outbound HTTP of the integrations (webhooks, oidc, update_check, connectivity_test,
+// heartbeat, firewall_backup), see outbound
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    // e.g. http://proxy.example.com:3128; without credentials in the URL
+    pub http_proxy: Option<String>,
+    // Used for https destinations, tunnelled with CONNECT
+    pub https_proxy: Option<String>,
+    // Hosts, domains (".example.com") and networks (10.0.0.0/8) reached directly
+    pub no_proxy: Vec<String>,
+    // Basic authentication towards the proxy. The password is never kept in this file:
+    // it is read from password_file, or from the environment variable password_env
+    pub username: Option<String>,
+    pub password_file: Option<String>,
+    pub password_env: Option<String>,
+    // Integrations deviating from `default_mode`
+    pub integrations: HashMap<String, ProxyMode>,
+    pub default_mode: ProxyMode,
+    // Fetched by GET /api/admin/connectivity-test when the request names no URL
+    pub test_url: String,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()],
+            username: None,
+            password_file: None,
+            password_env: None,
+            integrations: HashMap::new(),
+            default_mode: ProxyMode::Proxy,
+            test_url: "https://www.google.com/generate_204".to_string(),
+        }
+    }
+}
+
+// Kernel log lines of packets dropped in zones with drop logging, see drop_log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropLogConfig {
+    // Read the lines back into log entries
+    pub ingest: bool,
+    pub kmsg_path: String,
+    // Log lines per second of a zone when enabling doesn't say
+    pub default_rate_per_second: u32,
+}
+
+impl Default for DropLogConfig {
+    fn default() -> Self {
+        Self {
+            ingest: true,
+            kmsg_path: "/dev/kmsg".to_string(),
+            default_rate_per_second: 5,
+        }
+    }
+}
+
+// Backtests of correlation rules over stored logs, see correlation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorrelationConfig {
+    // Longest range a backtest may cover
+    pub max_backtest_days: i64,
+    pub max_running_backtests: usize,
+    // How long the request waits for a backtest before returning it as a running job
+    pub backtest_wait_secs: u64,
+    // Would-be alerts kept per backtest; the rest are only counted
+    pub max_backtest_alerts: usize,
+    // Finished backtests are dropped after this
+    pub backtest_retention_minutes: i64,
+}
+
+impl Default for CorrelationConfig {
+    fn default() -> Self {
+        Self {
+            max_backtest_days: 92,
+            max_running_backtests: 2,
+            backtest_wait_secs: 5,
+            max_backtest_alerts: 1000,
+            backtest_retention_minutes: 60,
+        }
+    }
+}
+
+// Monthly transfer allowance of a metered interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceQuotaConfig {
+    pub interface: String,
+    pub quota_bytes: u64,
+    // Day of the month the billing cycle starts, 1 to 28
+    #[serde(default = "default_cycle_start_day")]
+    pub cycle_start_day: u32,
+}
+
+fn default_cycle_start_day() -> u32 {
+    1
+}
+
+// Approved script run when an interface exceeds its quota, e.g. to apply a QoS limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaActionConfig {
+    pub script: String,
+    // Argument templates, filled from the alert and quota.interface, quota.used_bytes,
+    // quota.quota_bytes
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+// Monthly quotas on metered WAN links, see bandwidth_quota
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthQuotaConfig {
+    pub interfaces: Vec<InterfaceQuotaConfig>,
+    // Usage percentages that raise an alert, each once per cycle
+    pub alert_percents: Vec<u8>,
+    pub check_interval_secs: u64,
+    pub exceeded_action: Option<QuotaActionConfig>,
+}
+
+impl Default for BandwidthQuotaConfig {
+    fn default() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            alert_percents: vec![80, 90],
+            check_interval_secs: 60,
+            exceeded_action: None,
+        }
+    }
+}
+
+// Outbound heartbeat to an external monitor, see heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub enabled: bool,
+    // Pinged with a POST carrying the status summary, e.g. a healthchecks.io check URL
+    pub url: Option<String>,
+    // host:port of a syslog collector, sent an RFC 5424 message over UDP
+    pub syslog: Option<String>,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    // A target failing for this long raises a local alert
+    pub unreachable_alert_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: None,
+            syslog: None,
+            interval_secs: 60,
+            timeout_secs: 10,
+            unreachable_alert_secs: 600,
+        }
+    }
+}
+
+// Request spans and their export, see request_trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    // OTLP/gRPC collector the spans are exported to, e.g. http://localhost:4317; spans
+    // only go to the log when unset
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "siem".to_string(),
+        }
+    }
+}
+
+// Scheduled firewall backups, see firewall_backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirewallBackupConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    // Backups beyond the newest `keep`, or older than max_age_days, are removed
+    pub keep: usize,
+    pub max_age_days: u64,
+    pub offsite: Option<OffsiteBackupConfig>,
+}
+
+impl Default for FirewallBackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 86400,
+            keep: 30,
+            max_age_days: 90,
+            offsite: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsiteMethod {
+    // PUT to url followed by the file name, with basic auth when username is set
+    Http,
+    // Uploaded with the sftp client to target, authenticated with identity_file
+    Sftp,
+}
+
+// Where each backup is copied after it is written. Passwords are never kept in this
+// file: they are read from password_file, or from the environment variable password_env.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OffsiteBackupConfig {
+    pub method: OffsiteMethod,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password_file: Option<String>,
+    pub password_env: Option<String>,
+    // user@host:directory
+    pub target: Option<String>,
+    pub identity_file: Option<String>,
+    #[serde(default = "default_offsite_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_offsite_timeout_secs() -> u64 {
+    60
+}
+
+// Time tracking on tickets, see TicketsManager::add_worklog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TicketWorklogConfig {
+    // Longest single entry; longer work is logged as several entries
+    pub max_duration_minutes: u32,
+    // How long after logging an entry its author can still edit it
+    pub edit_grace_minutes: u64,
+}
+
+impl Default for TicketWorklogConfig {
+    fn default() -> Self {
+        Self {
+            max_duration_minutes: 720,
+            edit_grace_minutes: 60,
+        }
+    }
+}
+
+// Request counters per route and per user, see api_usage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiUsageConfig {
+    // Users counted on their own; the rest are counted together as "other"
+    pub max_principals: usize,
+    // How often the counters are written to disk, so restarts keep the 7-day view
+    pub persist_interval_secs: u64,
+}
+
+impl Default for ApiUsageConfig {
+    fn default() -> Self {
+        Self {
+            max_principals: 200,
+            persist_interval_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationMode {
+    // Serves the changes feed to a standby, when a key is configured
+    #[default]
+    Primary,
+    // Pulls from primary_url and refuses local changes until promoted
+    Standby,
+}
+
+// Warm standby, see replication. Both instances share one key: the primary accepts it on
+// the changes feed, the standby sends it. Like other secrets it is never kept in this
+// file; it is read from key_file, or from the environment variable key_env.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    pub mode: ReplicationMode,
+    pub primary_url: Option<String>,
+    pub key_file: Option<String>,
+    pub key_env: Option<String>,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            mode: ReplicationMode::Primary,
+            primary_url: None,
+            key_file: None,
+            key_env: None,
+            interval_secs: 30,
+            timeout_secs: 30,
+        }
+    }
+}
+
+// One alert webhook, see integrations. It is accepted only when a token is configured,
+// read from token_file or the environment variable token_env and sent by the tool as
+// "Authorization: Bearer <token>".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookIntegrationConfig {
+    pub token_file: Option<String>,
+    pub token_env: Option<String>,
+    // Label holding the severity, e.g. severity="critical"
+    pub severity_label: String,
+    // For alerts without the label or with a value that is not recognised
+    pub default_severity: AlertSeverity,
+}
+
+impl Default for WebhookIntegrationConfig {
+    fn default() -> Self {
+        Self {
+            token_file: None,
+            token_env: None,
+            severity_label: "severity".to_string(),
+            default_severity: AlertSeverity::Medium,
+        }
+    }
+}
+
+// Alerts of Prometheus Alertmanager and Grafana received on their native webhooks
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct IntegrationsConfig {
+    pub alertmanager: WebhookIntegrationConfig,
+    pub grafana: WebhookIntegrationConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaPolicy {
+    // Hours an open ticket of the priority has until it must be resolved
+    pub resolve_within_hours: f64,
+    // Count only business hours of the calendar, so the clock pauses outside them
+    pub business_hours: bool,
+}
+
+// Resolution targets per ticket priority, see sla
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlaConfig {
+    pub enabled: bool,
+    pub low: SlaPolicy,
+    pub medium: SlaPolicy,
+    pub high: SlaPolicy,
+    pub critical: SlaPolicy,
+}
+
+impl Default for SlaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            low: SlaPolicy { resolve_within_hours: 72.0, business_hours: true },
+            medium: SlaPolicy { resolve_within_hours: 24.0, business_hours: true },
+            high: SlaPolicy { resolve_within_hours: 8.0, business_hours: true },
+            critical: SlaPolicy { resolve_within_hours: 4.0, business_hours: false },
+        }
+    }
+}
+
+impl SlaConfig {
+    pub fn policy_for(&self, priority: &TicketPriority) -> &SlaPolicy {
+        match priority {
+            TicketPriority::Low => &self.low,
+            TicketPriority::Medium => &self.medium,
+            TicketPriority::High => &self.high,
+            TicketPriority::Critical => &self.critical,
+        }
+    }
+}
+
+// Alerts on audited actions done outside business hours, see after_hours
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AfterHoursConfig {
+    pub enabled: bool,
+    // Audit actions watched, by prefix, e.g. "firewall:" or "auth:login"
+    pub actions: Vec<String>,
+    // Accounts expected to act at any hour, such as service accounts
+    pub ignore_users: Vec<String>,
+    pub severity: AlertSeverity,
+}
+
+impl Default for AfterHoursConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            actions: ["auth:login", "config:", "firewall:", "network:", "role:", "script:execute", "user:"]
+                .iter().map(|a| a.to_string()).collect(),
+            ignore_users: vec!["system".to_string()],
+            severity: AlertSeverity::Medium,
+        }
+    }
+}
+
+// Process and service inventory of assets over the fleet SSH settings, see host_inventory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InventoryConfig {
+    pub enabled: bool,
+    pub interval_minutes: u64,
+    // Hosts collected from at the same time
+    pub max_concurrency: usize,
+    // A host taking longer is recorded as failed for this run
+    pub timeout_secs: u64,
+    // Snapshots kept per asset, the latest included
+    pub keep_snapshots: usize,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: 360,
+            max_concurrency: 4,
+            timeout_secs: 120,
+            keep_snapshots: 30,
+        }
+    }
+}
+
+// Classes of personal data pseudonymized or stripped in support exports and anonymized
+// data copies, see anonymize
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnonymizeConfig {
+    pub usernames: bool,
+    pub emails: bool,
+    // Hostnames in host fields and asset names
+    pub hostnames: bool,
+    pub ip_addresses: bool,
+    // Contents dropped and file names replaced, the size and type are kept
+    pub attachments: bool,
+    // Credential fields and credentials in free text masked
+    pub credentials: bool,
+}
+
+impl Default for AnonymizeConfig {
+    fn default() -> Self {
+        Self {
+            usernames: true,
+            emails: true,
+            hostnames: true,
+            ip_addresses: true,
+            attachments: true,
+            credentials: true,
+        }
+    }
+}
+
+// The relay processes run for interfaces with a DHCP relay, see dhcp_relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DhcpRelayConfig {
+    pub dhcrelay_path: String,
+    // A relay that exits is started again after this, doubling up to max_restart_delay_secs
+    // while it keeps exiting
+    pub restart_delay_secs: u64,
+    pub max_restart_delay_secs: u64,
+}
+
+impl Default for DhcpRelayConfig {
+    fn default() -> Self {
+        Self {
+            dhcrelay_path: "dhcrelay".to_string(),
+            restart_delay_secs: 5,
+            max_restart_delay_secs: 300,
+        }
+    }
+}
+
+// External references on alerts and tickets, see references
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReferencesConfig {
+    // Link of an identifier reference per kind (issue, vendor_case, cve, other); {value} is
+    // replaced by the escaped identifier. Identifiers of kinds without one are not linked.
+    pub link_templates: HashMap<String, String>,
+}
+
+impl Default for ReferencesConfig {
+    fn default() -> Self {
+        Self {
+            link_templates: HashMap::from([
+                ("cve".to_string(), "https://nvd.nist.gov/vuln/detail/{value}".to_string()),
+            ]),
+        }
+    }
+}
+
+// Periodic network graph snapshots, see graph_snapshots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphSnapshotConfig {
+    pub interval_secs: u64,
+    pub retention_days: i64,
+}
+
+impl Default for GraphSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 300,
+            retention_days: 30,
+        }
+    }
+}
+
+// Request body size caps in bytes, see body_limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BodyLimitsConfig {
+    // JSON APIs, log ingestion included
+    pub default_bytes: u64,
+    // Ticket, firewall and other imports
+    pub import_bytes: u64,
+    pub attachment_bytes: u64,
+    // Path prefix -> limit, overriding the above; the longest matching prefix wins
+    pub routes: HashMap<String, u64>,
+}
+
+impl Default for BodyLimitsConfig {
+    fn default() -> Self {
+        Self {
+            default_bytes: 2 * 1024 * 1024,
+            import_bytes: 20 * 1024 * 1024,
+            attachment_bytes: 50 * 1024 * 1024,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+// Periodic check of the release manifest, see version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateCheckConfig {
+    // Off for air-gapped sites
+    pub enabled: bool,
+    // Signed manifest with the latest and minimum supported versions
+    pub manifest_url: String,
+    pub interval_hours: u64,
+}
+
+impl Default for UpdateCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            manifest_url: String::new(),
+            interval_hours: 24,
+        }
+    }
+}
+
+// Optional overrides, each defaults to a subdirectory of data_dir
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathsConfig {
+    pub temp_dir: Option<String>,
+    pub reports_dir: Option<String>,
+    pub topology_dir: Option<String>,
+    pub attachments_dir: Option<String>,
+    pub backups_dir: Option<String>,
+}
+
 pub fn default_config() -> Config {
     Config {
         server_port: 8080,
-        scripts_dir: "scripts".to_string(),
-        log_dir: "logs".to_string(),
+        scripts_dir: String::new(),
+        log_dir: String::new(),
         retention_days: 365, // 1 year retention as per regulation
         admin_email: "admin@example.com".to_string(),
+        data_dir: String::new(),
+        paths: PathsConfig::default(),
         smtp: SmtpConfig {
             server: "smtp.example.com".to_string(),
             port: 587,
@@ -53,6 +1661,62 @@ pub fn default_config() -> Config {
             bind_dn: "cn=siem,ou=Service Accounts,dc=example,dc=com".to_string(),
             bind_password: "change-me".to_string(),
         },
+        security: SecurityConfig::default(),
+        capture: CaptureConfig::default(),
+        tasks: TasksConfig::default(),
+        flow_export: FlowExportConfig::default(),
+        disk: DiskConfig::default(),
+        tls: None,
+        syslog_tls: SyslogTlsConfig::default(),
+        reports: ReportsConfig::default(),
+        geoip: GeoIpConfig::default(),
+        impossible_travel: ImpossibleTravelConfig::default(),
+        password_policy: PasswordPolicyConfig::default(),
+        fleet: FleetConfig::default(),
+        firewall: FirewallConfig::default(),
+        ingestion_quota: QuotaConfig::default(),
+        script_approval: ScriptApprovalConfig::default(),
+        oidc: OidcConfig::default(),
+        digest: DigestConfig::default(),
+        ticket_autoclose: TicketAutoCloseConfig::default(),
+        dns: DnsConfig::default(),
+        print_accounting: PrintAccountingConfig::default(),
+        evidence: EvidenceConfig::default(),
+        log_tail: LogTailConfig::default(),
+        source_health: SourceHealthConfig::default(),
+        graph_snapshots: GraphSnapshotConfig::default(),
+        body_limits: BodyLimitsConfig::default(),
+        update_check: UpdateCheckConfig::default(),
+        clock_skew: ClockSkewConfig::default(),
+        alert_events: AlertEventsConfig::default(),
+        link_flap: LinkFlapConfig::default(),
+        database: DatabaseConfig::default(),
+        printer_reorder: PrinterReorderConfig::default(),
+        attachment_scan: AttachmentScanConfig::default(),
+        chargeback: ChargebackConfig::default(),
+        script_lint: ScriptLintConfig::default(),
+        ups: UpsConfig::default(),
+        portal: PortalConfig::default(),
+        asset_drift: AssetDriftConfig::default(),
+        traffic_monitoring: TrafficMonitoringConfig::default(),
+        proxy: ProxyConfig::default(),
+        drop_log: DropLogConfig::default(),
+        correlation: CorrelationConfig::default(),
+        bandwidth_quota: BandwidthQuotaConfig::default(),
+        heartbeat: HeartbeatConfig::default(),
+        tracing: TracingConfig::default(),
+        firewall_backup: FirewallBackupConfig::default(),
+        ticket_worklog: TicketWorklogConfig::default(),
+        api_usage: ApiUsageConfig::default(),
+        replication: ReplicationConfig::default(),
+        integrations: IntegrationsConfig::default(),
+        sla: SlaConfig::default(),
+        after_hours: AfterHoursConfig::default(),
+        inventory: InventoryConfig::default(),
+        anonymize: AnonymizeConfig::default(),
+        dhcp_relay: DhcpRelayConfig::default(),
+        references: ReferencesConfig::default(),
+        database_url: None,
     }
 }
 
@@ -66,6 +1730,28 @@ pub fn load(config_path: &str) -> Result<Config> {
     Ok(config)
 }
 
+// Checks every config saved through the API, see config_history::ConfigHistory
+pub fn validate(config: &Config) -> Result<()> {
+    if config.server_port == 0 {
+        return Err(anyhow!("server_port must not be 0"));
+    }
+    if !config.admin_email.contains('@') {
+        return Err(anyhow!("Invalid admin email: {}", config.admin_email));
+    }
+    if config.retention_days == 0 {
+        return Err(anyhow!("retention_days must be at least 1"));
+    }
+    if config.traffic_monitoring.interval_secs == 0 {
+        return Err(anyhow!("traffic_monitoring.interval_secs must be at least 1"));
+    }
+    for proxy in &config.security.trusted_proxies {
+        if proxy.parse::<ipnetwork::IpNetwork>().is_err() {
+            return Err(anyhow!("Invalid trusted proxy: {}", proxy));
+        }
+    }
+    Ok(())
+}
+
 pub fn save(config: &Config, config_path: &str) -> Result<()> {
     let config_str = toml::to_string_pretty(config)
         .context("Failed to serialize config")?;
@@ -0,0 +1,532 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::config::{self, Config};
+use crate::password_policy::PasswordPolicy;
+use crate::paths::write_private;
+use crate::security::SecurityManager;
+use crate::tasks::TaskRegistry;
+use crate::visualizations::VisualizationManager;
+
+// Versions kept; the copies of older ones are deleted
+const MAX_VERSIONS: usize = 50;
+
+// Source of changes found in the file rather than made through the API
+pub const EXTERNAL_EDIT: &str = "external edit";
+
+// Marks a secret encrypted under the instance key in a history copy
+const ENCRYPTED_PREFIX: &str = "sealed:";
+// Copies written before secrets were encrypted hold them base64 encoded; read only
+const LEGACY_PREFIX: &str = "enc:";
+
+// A field holds a secret when its own name says so, wherever it is in the config.
+// Files and environment variables naming where a secret is kept are not secrets.
+fn is_secret_name(name: &str) -> bool {
+    name == "password" || name.ends_with("_password") || name.ends_with("_secret") || name == "database_url"
+}
+
+// Dotted names of the fields holding secrets, found in the config schema
+pub fn secret_fields() -> &'static [String] {
+    static FIELDS: OnceLock<Vec<String>> = OnceLock::new();
+    FIELDS.get_or_init(|| {
+        let mut out = BTreeMap::new();
+        if let Ok(schema) = serde_json::to_value(config::default_config()) {
+            flatten("", &schema, &mut out);
+        }
+        out.into_keys()
+            .filter(|field| is_secret_name(field.rsplit('.').next().unwrap_or(field)))
+            .collect()
+    })
+}
+
+fn is_secret_field(field: &str) -> bool {
+    secret_fields().iter().any(|secret| secret == field)
+}
+
+// Shown instead of a secret by GET /api/admin/config, see redaction; sending it back leaves the secret as is
+pub const SECRET_MASK: &str = "********";
+
+// Sections followed without a restart, see ConfigHistory::apply
+const RELOADABLE_SECTIONS: [&str; 2] = ["password_policy", "traffic_monitoring"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigVersion {
+    pub version: u64,
+    pub saved_at: DateTime<Utc>,
+    pub changed_by: String,
+    // Method and path of the API call that saved the config, or "external edit"
+    pub source: String,
+    // SHA-256 of the config file as written
+    pub hash: String,
+}
+
+// Result of a partial update through PATCH /api/admin/config
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigUpdate {
+    // None when the update changed nothing and no version was saved
+    pub version: Option<ConfigVersion>,
+    pub changes: Vec<FieldChange>,
+    // Only the secrets that changed, the values are never shown
+    pub secrets_changed: Vec<String>,
+    // Changed fields that apply now and those that wait for the next start
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+}
+
+// A version and what it changed against the one before; the oldest version kept has
+// nothing to compare with and lists no changes
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionDiff {
+    #[serde(flatten)]
+    pub version: ConfigVersion,
+    pub changes: Vec<FieldChange>,
+    // Secret field -> "changed" or "unchanged", the values are never shown
+    pub secrets: BTreeMap<String, &'static str>,
+}
+
+// The config with every secret that is set replaced by what `f` makes of it
+fn map_secrets(config: &Config, mut f: impl FnMut(&str, &str) -> Result<String>) -> Result<Config> {
+    let mut value = serde_json::to_value(config)?;
+    for field in secret_fields() {
+        let mut target = Some(&mut value);
+        for key in field.split('.') {
+            target = target.and_then(|v| v.get_mut(key));
+        }
+        if let Some(serde_json::Value::String(secret)) = target {
+            if !secret.is_empty() {
+                *secret = f(field, secret)?;
+            }
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, out: &mut BTreeMap<String, serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, out);
+            }
+        },
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        },
+    }
+}
+
+fn fields(config: &Config) -> Result<BTreeMap<String, serde_json::Value>> {
+    let mut out = BTreeMap::new();
+    flatten("", &serde_json::to_value(config)?, &mut out);
+    Ok(out)
+}
+
+fn diff(old: &Config, new: &Config) -> Result<(Vec<FieldChange>, BTreeMap<String, &'static str>)> {
+    let old = fields(old)?;
+    let new = fields(new)?;
+
+    let mut secrets = BTreeMap::new();
+    for field in secret_fields() {
+        let changed = old.get(field) != new.get(field);
+        secrets.insert(field.to_string(), if changed { "changed" } else { "unchanged" });
+    }
+
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let changes = names.into_iter()
+        .filter(|name| !is_secret_field(name))
+        .filter(|name| old.get(*name) != new.get(*name))
+        .map(|name| FieldChange {
+            field: name.clone(),
+            old: old.get(name).cloned(),
+            new: new.get(name).cloned(),
+        })
+        .collect();
+
+    Ok((changes, secrets))
+}
+
+// JSON merge patch: objects are merged key by key, anything else replaces the value.
+// A secret sent back as the mask is skipped.
+fn merge(path: &str, target: &mut serde_json::Value, patch: &serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                let field = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match target.get_mut(key) {
+                    Some(existing) => merge(&field, existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    },
+                }
+            }
+        },
+        (target, patch) => {
+            if is_secret_field(path) && patch.as_str() == Some(SECRET_MASK) {
+                return;
+            }
+            *target = patch.clone();
+        },
+    }
+}
+
+// Patched fields that did not survive parsing, i.e. that the config does not have
+fn unknown_fields(path: &str, patch: &serde_json::Value, parsed: &serde_json::Value, out: &mut Vec<String>) {
+    if let serde_json::Value::Object(patch) = patch {
+        for (key, value) in patch {
+            let field = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            match parsed.get(key) {
+                Some(parsed) => unknown_fields(&field, value, parsed, out),
+                None => out.push(field),
+            }
+        }
+    }
+}
+
+fn is_reloadable(field: &str) -> bool {
+    RELOADABLE_SECTIONS.iter().any(|section| field == *section || field.starts_with(&format!("{}.", section)))
+}
+
+fn hash_file(path: &str) -> Result<String> {
+    let contents = fs::read(path)
+        .context(format!("Failed to read config file: {}", path))?;
+    let mut hasher = Sha256::new();
+    hasher.update(&contents);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Every save of the config file goes through here: the saved version is copied to the
+// history with its secrets encrypted, readable by the service account only, and edits
+// made to the file by hand are picked up at reload
+#[derive(Clone)]
+pub struct ConfigHistory {
+    config_path: String,
+    dir: PathBuf,
+    index_path: PathBuf,
+    versions: Arc<Mutex<Vec<ConfigVersion>>>,
+    security: SecurityManager,
+    password_policy: PasswordPolicy,
+    visualizations: VisualizationManager,
+    tasks: TaskRegistry,
+}
+
+impl ConfigHistory {
+    pub fn new(dir: &str,
+               config_path: &str,
+               security: SecurityManager,
+               password_policy: PasswordPolicy,
+               visualizations: VisualizationManager,
+               tasks: TaskRegistry) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create config history directory: {:?}", dir))?;
+            info!("Created config history directory: {:?}", dir);
+        }
+
+        // Copies written before they were kept private
+        for entry in fs::read_dir(&dir)?.flatten() {
+            if let Err(e) = fs::set_permissions(entry.path(), fs::Permissions::from_mode(0o600)) {
+                warn!("Failed to restrict config history file {:?}: {}", entry.path(), e);
+            }
+        }
+
+        let index_path = dir.join("versions.json");
+        let versions = match fs::read_to_string(&index_path) {
+            Ok(contents) => match serde_json::from_str::<Vec<ConfigVersion>>(&contents) {
+                Ok(versions) => versions,
+                Err(e) => {
+                    warn!("Ignoring invalid config history index {:?}: {}", index_path, e);
+                    Vec::new()
+                },
+            },
+            Err(_) => Vec::new(),
+        };
+
+        info!("Loaded {} config versions", versions.len());
+
+        Ok(Self {
+            config_path: config_path.to_string(),
+            dir,
+            index_path,
+            versions: Arc::new(Mutex::new(versions)),
+            security,
+            password_policy,
+            visualizations,
+            tasks,
+        })
+    }
+
+    fn version_path(&self, version: u64) -> PathBuf {
+        self.dir.join(format!("{}.toml", version))
+    }
+
+    fn encrypt_secrets(&self, config: &Config) -> Result<Config> {
+        map_secrets(config, |field, secret| {
+            let sealed = self.security.encrypt_data(secret)
+                .with_context(|| format!("Failed to encrypt {}", field))?;
+            Ok(format!("{}{}", ENCRYPTED_PREFIX, sealed))
+        })
+    }
+
+    fn decrypt_secrets(&self, config: &Config) -> Result<Config> {
+        map_secrets(config, |field, secret| {
+            if let Some(sealed) = secret.strip_prefix(ENCRYPTED_PREFIX) {
+                self.security.decrypt_data(sealed)
+                    .with_context(|| format!("Failed to decrypt {}", field))
+            } else if let Some(encoded) = secret.strip_prefix(LEGACY_PREFIX) {
+                general_purpose::STANDARD.decode(encoded).ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(|| anyhow!("Failed to decode {}", field))
+            } else {
+                Ok(secret.to_string())
+            }
+        })
+    }
+
+    // Copies the config as now in the file to the history
+    fn record(&self,
+              versions: &mut Vec<ConfigVersion>,
+              config: &Config,
+              changed_by: &str,
+              source: &str) -> Result<ConfigVersion> {
+        let entry = ConfigVersion {
+            version: versions.last().map_or(1, |v| v.version + 1),
+            saved_at: Utc::now(),
+            changed_by: changed_by.to_string(),
+            source: source.to_string(),
+            hash: hash_file(&self.config_path)?,
+        };
+
+        let copy = toml::to_string_pretty(&self.encrypt_secrets(config)?)
+            .context("Failed to serialize config")?;
+        let path = self.version_path(entry.version);
+        write_private(&path, copy)
+            .context(format!("Failed to write config version: {:?}", path))?;
+
+        versions.push(entry.clone());
+        while versions.len() > MAX_VERSIONS {
+            let oldest = versions.remove(0);
+            if let Err(e) = fs::remove_file(self.version_path(oldest.version)) {
+                warn!("Failed to delete config version {}: {}", oldest.version, e);
+            }
+        }
+
+        let json = serde_json::to_string_pretty(versions)?;
+        write_private(&self.index_path, json)
+            .context(format!("Failed to write config history index: {:?}", self.index_path))?;
+
+        info!("Recorded config version {} by {} ({})", entry.version, entry.changed_by, entry.source);
+        Ok(entry)
+    }
+
+    // Records the file as a new version when it no longer matches the last one saved
+    fn capture_external_edit(&self, versions: &mut Vec<ConfigVersion>) -> Result<Option<ConfigVersion>> {
+        let hash = hash_file(&self.config_path)?;
+        if versions.last().map_or(false, |v| v.hash == hash) {
+            return Ok(None);
+        }
+
+        let config = config::load(&self.config_path)?;
+        let (changed_by, source) = if versions.is_empty() {
+            ("system", "initial")
+        } else {
+            warn!("Config file {} was edited outside the API", self.config_path);
+            ("unknown", EXTERNAL_EDIT)
+        };
+        self.record(versions, &config, changed_by, source).map(Some)
+    }
+
+    // Validates and writes the config; a pending hand edit is recorded first so that
+    // it stays in the history
+    pub fn save(&self, config: &Config, changed_by: &str, source: &str) -> Result<ConfigVersion> {
+        config::validate(config)?;
+
+        match self.versions.lock() {
+            Ok(mut versions) => {
+                if let Err(e) = self.capture_external_edit(&mut versions) {
+                    warn!("Failed to check the config file for edits: {}", e);
+                }
+                config::save(config, &self.config_path)?;
+                self.record(&mut versions, config, changed_by, source)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on config history")),
+        }
+    }
+
+    // Settings followed without a restart; everything else applies at the next start
+    fn apply(&self, config: &Config) {
+        self.password_policy.reload(config.password_policy.clone());
+        if let Err(e) = self.visualizations.set_traffic_interval(&self.tasks, config.traffic_monitoring.interval_secs) {
+            warn!("Failed to apply the traffic monitoring interval: {}", e);
+        }
+    }
+
+    // The config as saved in the file
+    pub fn current(&self) -> Result<Config> {
+        config::load(&self.config_path)
+    }
+
+    // Applies a partial update to the config in the file, validates and saves it as a new
+    // version and applies the reloadable sections
+    pub fn update(&self, patch: &serde_json::Value, changed_by: &str, source: &str) -> Result<ConfigUpdate> {
+        if !patch.is_object() {
+            return Err(anyhow!("The update must be a JSON object"));
+        }
+
+        let current = self.current()?;
+        let mut value = serde_json::to_value(&current)?;
+        merge("", &mut value, patch);
+        let updated: Config = serde_json::from_value(value)
+            .map_err(|e| anyhow!("Invalid configuration: {}", e))?;
+
+        let mut unknown = Vec::new();
+        unknown_fields("", patch, &serde_json::to_value(&updated)?, &mut unknown);
+        if !unknown.is_empty() {
+            return Err(anyhow!("Unknown configuration fields: {}", unknown.join(", ")));
+        }
+
+        let (changes, secrets) = diff(&current, &updated)?;
+        let secrets_changed: Vec<String> = secrets.into_iter()
+            .filter(|(_, state)| *state == "changed")
+            .map(|(field, _)| field)
+            .collect();
+        let (applied, restart_required) = changes.iter()
+            .map(|c| c.field.clone())
+            .chain(secrets_changed.iter().cloned())
+            .partition(|field| is_reloadable(field));
+
+        let version = if changes.is_empty() && secrets_changed.is_empty() {
+            None
+        } else {
+            let saved = self.save(&updated, changed_by, source)?;
+            self.apply(&updated);
+            Some(saved)
+        };
+
+        Ok(ConfigUpdate {
+            version,
+            changes,
+            secrets_changed,
+            applied,
+            restart_required,
+        })
+    }
+
+    // Re-reads the config file, recording hand edits, and applies it
+    pub fn reload(&self) -> Result<Config> {
+        match self.versions.lock() {
+            Ok(mut versions) => {
+                self.capture_external_edit(&mut versions)?;
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on config history")),
+        }
+
+        let config = config::load(&self.config_path)?;
+        self.apply(&config);
+        Ok(config)
+    }
+
+    pub fn get_versions(&self) -> Result<Vec<ConfigVersion>> {
+        match self.versions.lock() {
+            Ok(versions) => Ok(versions.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on config history")),
+        }
+    }
+
+    fn load_version(&self, version: u64) -> Result<Config> {
+        let path = self.version_path(version);
+        let contents = fs::read_to_string(&path)
+            .context(format!("Failed to read config version: {:?}", path))?;
+        let config: Config = toml::from_str(&contents)
+            .context(format!("Failed to parse config version: {:?}", path))?;
+        self.decrypt_secrets(&config)
+    }
+
+    // Newest first
+    pub fn history(&self) -> Result<Vec<VersionDiff>> {
+        let versions = self.get_versions()?;
+
+        let mut diffs = Vec::new();
+        let mut previous: Option<Config> = None;
+        for version in versions {
+            let config = self.load_version(version.version)?;
+            let (changes, secrets) = match &previous {
+                Some(previous) => diff(previous, &config)?,
+                None => (Vec::new(), BTreeMap::new()),
+            };
+            diffs.push(VersionDiff { version, changes, secrets });
+            previous = Some(config);
+        }
+
+        diffs.reverse();
+        Ok(diffs)
+    }
+
+    // Saves an earlier version as a new one, through the same validation and reload
+    // as any other change
+    pub fn rollback(&self, version: u64, changed_by: &str, source: &str) -> Result<ConfigVersion> {
+        if !self.get_versions()?.iter().any(|v| v.version == version) {
+            return Err(anyhow!("Unknown config version: {}", version));
+        }
+
+        let config = self.load_version(version)?;
+        let saved = self.save(&config, changed_by, source)?;
+        self.apply(&config);
+        Ok(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_fields_come_from_the_schema() {
+        let fields = secret_fields();
+        for field in ["smtp.password", "ad_integration.bind_password", "security.jwt_secret", "database_url"] {
+            assert!(fields.iter().any(|f| f == field), "{} missing from {:?}", field, fields);
+        }
+        assert!(fields.iter().all(|f| !f.ends_with("_file") && !f.ends_with("_env")), "{:?}", fields);
+    }
+
+    #[test]
+    fn history_copies_hold_secrets_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let security = SecurityManager::new([7u8; 32], dir.path().to_str().unwrap()).unwrap();
+        let mut config = config::default_config();
+        config.smtp.password = "hunter2".to_string();
+        config.database_url = Some("postgres://siem:s3cret@db/siem".to_string());
+
+        let sealed = map_secrets(&config, |_, secret| {
+            Ok(format!("{}{}", ENCRYPTED_PREFIX, security.encrypt_data(secret)?))
+        }).unwrap();
+        let written = toml::to_string_pretty(&sealed).unwrap();
+        assert!(!written.contains("hunter2") && !written.contains("s3cret"));
+
+        let opened = map_secrets(&sealed, |_, secret| {
+            security.decrypt_data(secret.strip_prefix(ENCRYPTED_PREFIX).unwrap())
+        }).unwrap();
+        assert_eq!(opened.smtp.password, "hunter2");
+        assert_eq!(opened.database_url, config.database_url);
+        assert_eq!(opened.security.jwt_secret, config.security.jwt_secret);
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::{info, warn};
+
+use crate::config::CorrelationConfig;
+use crate::database::DatabaseManager;
+use crate::logs::{LogFilter, LogsManager};
+use crate::models::{AlertSeverity, LogEntry};
+use crate::searches::FilterSource;
+use crate::tags::TagSelector;
+
+// Entries read from the store at a time
+const PAGE_SIZE: usize = 1000;
+
+// Buckets of a backtest's histogram of would-be alerts
+const HISTOGRAM_BUCKETS: i64 = 48;
+
+// Ids of the triggering entries kept with a would-be alert
+const MAX_ALERT_LOG_IDS: usize = 20;
+
+// Largest threshold; the entries of an open window are held until it fires or expires
+const MAX_THRESHOLD: usize = 10_000;
+
+// Matching entries between sweeps of groups whose window has expired
+const PRUNE_EVERY: u64 = 10_000;
+
+// Field whose values are counted separately
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum GroupField {
+    Host,
+    User,
+    Source,
+    EventType,
+}
+
+impl GroupField {
+    fn value(self, entry: &LogEntry) -> Option<String> {
+        match self {
+            GroupField::Host => entry.host.clone(),
+            GroupField::User => entry.user.clone(),
+            GroupField::Source => Some(entry.source.clone()),
+            GroupField::EventType => Some(entry.event_type.clone()),
+        }
+    }
+}
+
+// Fires when `threshold` entries matching the filter fall within `window_secs`, counted
+// per value of `group_by` when given. Entries without a value for it are not counted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrelationRule {
+    pub name: String,
+    pub filter: FilterSource,
+    // Tags the entries must carry on top of the filter
+    #[serde(default)]
+    pub tags: TagSelector,
+    pub group_by: Option<GroupField>,
+    pub threshold: usize,
+    pub window_secs: i64,
+    pub severity: AlertSeverity,
+}
+
+impl CorrelationRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Rule name must not be empty"));
+        }
+        if self.threshold == 0 || self.threshold > MAX_THRESHOLD {
+            return Err(anyhow!("Threshold must be between 1 and {}", MAX_THRESHOLD));
+        }
+        if self.window_secs <= 0 {
+            return Err(anyhow!("Window must be at least one second"));
+        }
+        Ok(())
+    }
+}
+
+// An alert the rule would have raised
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WouldBeAlert {
+    pub title: String,
+    pub severity: AlertSeverity,
+    pub group: Option<String>,
+    // Timestamps of the first and the last entry of the window that fired
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub count: usize,
+    pub log_ids: Vec<Uuid>,
+}
+
+// Runs a rule over entries fed in timestamp order. A window that fires starts over,
+// so one burst is one alert.
+pub struct RuleEvaluator {
+    rule: CorrelationRule,
+    filter: LogFilter,
+    windows: HashMap<String, VecDeque<(DateTime<Utc>, Uuid)>>,
+    pub matched: u64,
+}
+
+impl RuleEvaluator {
+    // `filter` is the rule's filter resolved from its source
+    pub fn new(rule: CorrelationRule, filter: LogFilter) -> Self {
+        Self {
+            rule,
+            filter,
+            windows: HashMap::new(),
+            matched: 0,
+        }
+    }
+
+    pub fn evaluate(&mut self, entry: &LogEntry) -> Option<WouldBeAlert> {
+        if !self.filter.matches(entry) || !self.rule.tags.matches(&entry.tags) {
+            return None;
+        }
+        let group = match self.rule.group_by {
+            Some(field) => Some(field.value(entry)?),
+            None => None,
+        };
+
+        self.matched += 1;
+        let cutoff = entry.timestamp - Duration::seconds(self.rule.window_secs);
+        if self.matched % PRUNE_EVERY == 0 {
+            self.windows.retain(|_, events| events.back().map_or(false, |(t, _)| *t >= cutoff));
+        }
+
+        let events = self.windows.entry(group.clone().unwrap_or_default()).or_default();
+        while events.front().map_or(false, |(t, _)| *t < cutoff) {
+            events.pop_front();
+        }
+        events.push_back((entry.timestamp, entry.id));
+        if events.len() < self.rule.threshold {
+            return None;
+        }
+
+        let events = std::mem::take(events);
+        let (window_start, _) = events[0];
+        Some(WouldBeAlert {
+            title: match &group {
+                Some(group) => format!("{}: {} events from {}", self.rule.name, events.len(), group),
+                None => format!("{}: {} events", self.rule.name, events.len()),
+            },
+            severity: self.rule.severity.clone(),
+            group,
+            window_start,
+            window_end: entry.timestamp,
+            count: events.len(),
+            log_ids: events.iter().take(MAX_ALERT_LOG_IDS).map(|(_, id)| *id).collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum BacktestStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub start: DateTime<Utc>,
+    pub alerts: usize,
+}
+
+// A rule replayed over the stored logs of a time range; nothing is raised
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backtest {
+    pub id: Uuid,
+    pub rule: CorrelationRule,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub requested_by: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub cancelled_by: Option<String>,
+    pub status: BacktestStatus,
+    pub error: Option<String>,
+    // Entries read, and those matching the rule's filter
+    pub scanned: u64,
+    pub matched: u64,
+    // Timestamp the replay has reached
+    pub position: Option<DateTime<Utc>>,
+    pub progress_percent: f64,
+    pub total_alerts: usize,
+    // The first max_backtest_alerts of them
+    pub alerts: Vec<WouldBeAlert>,
+    pub bucket_secs: i64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl Backtest {
+    fn record(&mut self, alert: WouldBeAlert, max_alerts: usize) {
+        let bucket = ((alert.window_end - self.from).num_seconds() / self.bucket_secs)
+            .clamp(0, self.histogram.len() as i64 - 1);
+        self.histogram[bucket as usize].alerts += 1;
+        self.total_alerts += 1;
+        if self.alerts.len() < max_alerts {
+            self.alerts.push(alert);
+        }
+    }
+
+    fn finish(&mut self, status: BacktestStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.finished_at = Some(Utc::now());
+        if status == BacktestStatus::Completed {
+            self.progress_percent = 100.0;
+        }
+    }
+}
+
+// Replays stored logs through correlation rules in the background, reading the range
+// page by page from the database when there is one, or else from the in-memory store.
+// Results are kept for review for backtest_retention_minutes.
+#[derive(Clone)]
+pub struct Backtester {
+    config: CorrelationConfig,
+    logs: LogsManager,
+    database: Option<DatabaseManager>,
+    backtests: Arc<Mutex<HashMap<Uuid, Backtest>>>,
+    cancels: Arc<Mutex<HashMap<Uuid, watch::Sender<bool>>>>,
+}
+
+impl Backtester {
+    pub fn new(config: CorrelationConfig, logs: LogsManager, database: Option<DatabaseManager>) -> Self {
+        Self {
+            config,
+            logs,
+            database,
+            backtests: Arc::new(Mutex::new(HashMap::new())),
+            cancels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn wait_time(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.backtest_wait_secs)
+    }
+
+    // Drops finished backtests past their retention
+    fn purge(&self, backtests: &mut HashMap<Uuid, Backtest>) {
+        let cutoff = Utc::now() - Duration::minutes(self.config.backtest_retention_minutes);
+        backtests.retain(|_, b| b.finished_at.map_or(true, |finished| finished > cutoff));
+    }
+
+    // `filter` is the rule's filter resolved from its source and narrowed to the sites
+    // of the caller
+    pub fn start(&self,
+                 rule: CorrelationRule,
+                 filter: LogFilter,
+                 from: DateTime<Utc>,
+                 to: DateTime<Utc>,
+                 requested_by: &str) -> Result<(Backtest, JoinHandle<()>)> {
+        rule.validate()?;
+        if to <= from {
+            return Err(anyhow!("The range must end after it starts"));
+        }
+        if to - from > Duration::days(self.config.max_backtest_days) {
+            return Err(anyhow!("The range must not be longer than {} days", self.config.max_backtest_days));
+        }
+
+        let span = (to - from).num_seconds().max(1);
+        let bucket_secs = (span + HISTOGRAM_BUCKETS - 1) / HISTOGRAM_BUCKETS;
+        let buckets = (span + bucket_secs - 1) / bucket_secs;
+        let backtest = Backtest {
+            id: Uuid::new_v4(),
+            rule: rule.clone(),
+            from,
+            to,
+            requested_by: requested_by.to_string(),
+            created_at: Utc::now(),
+            finished_at: None,
+            cancelled_by: None,
+            status: BacktestStatus::Running,
+            error: None,
+            scanned: 0,
+            matched: 0,
+            position: None,
+            progress_percent: 0.0,
+            total_alerts: 0,
+            alerts: Vec::new(),
+            bucket_secs,
+            histogram: (0..buckets)
+                .map(|i| HistogramBucket { start: from + Duration::seconds(i * bucket_secs), alerts: 0 })
+                .collect(),
+        };
+
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        match (self.backtests.lock(), self.cancels.lock()) {
+            (Ok(mut backtests), Ok(mut cancels)) => {
+                self.purge(&mut backtests);
+                let running = backtests.values().filter(|b| b.status == BacktestStatus::Running).count();
+                if running >= self.config.max_running_backtests {
+                    return Err(anyhow!("{} backtests are already running, try again later", running));
+                }
+                backtests.insert(backtest.id, backtest.clone());
+                cancels.insert(backtest.id, cancel_tx);
+            },
+            _ => return Err(anyhow!("Failed to acquire lock on backtests")),
+        }
+
+        info!("Started backtest {} of rule {} over {} to {}", backtest.id, rule.name, from, to);
+
+        let backtester = self.clone();
+        let id = backtest.id;
+        let evaluator = RuleEvaluator::new(rule, LogFilter { from: None, to: None, limit: None, ..filter });
+        let handle = tokio::spawn(async move {
+            backtester.run(id, evaluator, from, to, cancel_rx).await;
+        });
+
+        Ok((backtest, handle))
+    }
+
+    async fn page(&self, from: DateTime<Utc>, to: DateTime<Utc>, after: Option<(DateTime<Utc>, Uuid)>) -> Result<Vec<LogEntry>> {
+        match &self.database {
+            Some(database) => database.logs_page(from, to, after, PAGE_SIZE as i64).await,
+            None => self.logs.page_between(from, to, after, PAGE_SIZE),
+        }
+    }
+
+    async fn run(&self,
+                 id: Uuid,
+                 mut evaluator: RuleEvaluator,
+                 from: DateTime<Utc>,
+                 to: DateTime<Utc>,
+                 cancel: watch::Receiver<bool>) {
+        let mut cursor = None;
+        let outcome = loop {
+            if *cancel.borrow() {
+                break (BacktestStatus::Cancelled, None);
+            }
+
+            let page = match self.page(from, to, cursor).await {
+                Ok(page) => page,
+                Err(e) => break (BacktestStatus::Failed, Some(e.to_string())),
+            };
+            let Some(last) = page.last() else {
+                break (BacktestStatus::Completed, None);
+            };
+            cursor = Some((last.timestamp, last.id));
+
+            let alerts: Vec<WouldBeAlert> = page.iter().filter_map(|entry| evaluator.evaluate(entry)).collect();
+            let updated = self.update(id, |backtest| {
+                backtest.scanned += page.len() as u64;
+                backtest.matched = evaluator.matched;
+                backtest.position = Some(last.timestamp);
+                backtest.progress_percent = ((last.timestamp - from).num_milliseconds() as f64
+                    / (to - from).num_milliseconds().max(1) as f64 * 100.0).clamp(0.0, 100.0);
+                for alert in alerts {
+                    backtest.record(alert, self.config.max_backtest_alerts);
+                }
+            });
+            if !updated {
+                // Purged while running; nobody is waiting for it anymore
+                return;
+            }
+
+            if page.len() < PAGE_SIZE {
+                break (BacktestStatus::Completed, None);
+            }
+            tokio::task::yield_now().await;
+        };
+
+        if let Ok(mut cancels) = self.cancels.lock() {
+            cancels.remove(&id);
+        }
+        let (status, error) = outcome;
+        self.update(id, |backtest| {
+            if let Some(error) = &error {
+                warn!("Backtest {} failed: {}", id, error);
+            }
+            backtest.finish(status, error.clone());
+            info!("Backtest {} {:?}: {} entries read, {} would-be alerts",
+                  id, status, backtest.scanned, backtest.total_alerts);
+        });
+    }
+
+    fn update<F: FnOnce(&mut Backtest)>(&self, id: Uuid, update: F) -> bool {
+        match self.backtests.lock() {
+            Ok(mut backtests) => match backtests.get_mut(&id) {
+                Some(backtest) => {
+                    update(backtest);
+                    true
+                },
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    pub fn cancel(&self, id: Uuid, cancelled_by: &str) -> Result<Backtest> {
+        let backtest = match self.backtests.lock() {
+            Ok(mut backtests) => {
+                let backtest = backtests.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Backtest not found: {}", id))?;
+                if backtest.status != BacktestStatus::Running {
+                    return Err(anyhow!("Backtest {} has already finished", id));
+                }
+                backtest.cancelled_by = Some(cancelled_by.to_string());
+                backtest.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on backtests")),
+        };
+
+        match self.cancels.lock() {
+            Ok(cancels) => {
+                if let Some(cancel) = cancels.get(&id) {
+                    let _ = cancel.send(true);
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on backtests")),
+        }
+
+        info!("Backtest {} cancelled by {}", id, cancelled_by);
+        Ok(backtest)
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<Backtest> {
+        match self.backtests.lock() {
+            Ok(mut backtests) => {
+                self.purge(&mut backtests);
+                backtests.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Backtest not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on backtests")),
+        }
+    }
+
+    // Newest first, without their alerts
+    pub fn list(&self) -> Result<Vec<Backtest>> {
+        match self.backtests.lock() {
+            Ok(mut backtests) => {
+                self.purge(&mut backtests);
+                let mut all: Vec<Backtest> = backtests.values()
+                    .map(|b| Backtest { alerts: Vec::new(), ..b.clone() })
+                    .collect();
+                all.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on backtests")),
+        }
+    }
+}
@@ -1,36 +1,311 @@
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgPool, PgPoolOptions, Postgres};
+use sqlx::QueryBuilder;
+use std::future::Future;
 use std::net::IpAddr;
+use std::path::Path;
 use std::str::FromStr;
-use tracing::{info, error};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, error, warn};
 use uuid::Uuid;
 
-use crate::models::LogEntry;
+use crate::config::DatabaseConfig;
+use crate::ingest_timing::{IngestTimings, Stage};
+use crate::models::{EventCategory, LogEntry, LogParser};
+use crate::spool::Spool;
+
+// 13 parameters a row stay under the 65535 a Postgres statement may have
+const MAX_BATCH_SIZE: usize = 5000;
+
+// Errors worth retrying: the connection or the server went away, or no connection
+// could be had in time. Anything else (bad SQL, constraint violations) fails at once.
+fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(e) => e.code().map_or(false, |code| {
+            // Connection exceptions, server shutting down or starting, too many connections
+            code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03" | "53300")
+        }),
+        _ => false,
+    }
+}
+
+// Connectivity and pool counters, shared with the probe task and /metrics
+#[derive(Default)]
+struct DatabaseStats {
+    healthy: AtomicBool,
+    acquires: AtomicU64,
+    acquire_wait_us: AtomicU64,
+    timeouts: AtomicU64,
+    retries: AtomicU64,
+    recycles: AtomicU64,
+    replayed: AtomicU64,
+    log_batches: AtomicU64,
+    batched_logs: AtomicU64,
+    // When the current outage started, None while healthy
+    failing_since: Mutex<Option<Instant>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseHealth {
+    pub healthy: bool,
+    pub failing_for_secs: Option<u64>,
+    pub pool_size: u32,
+    pub pool_idle: usize,
+    pub spooled: u64,
+}
 
-// Database configuration
 #[derive(Clone)]
 pub struct DatabaseManager {
-    pool: PgPool,
+    url: String,
+    config: DatabaseConfig,
+    // Replaced wholesale when the pool is recycled after a prolonged outage
+    pool: Arc<RwLock<PgPool>>,
+    stats: Arc<DatabaseStats>,
+    initialized: Arc<AtomicBool>,
+    // Log writes that failed during an outage, replayed when the database returns
+    spool: Spool,
 }
 
 impl DatabaseManager {
-    pub async fn new(database_url: &str) -> Result<Self> {
-        info!("Connecting to database at {}", database_url);
-        
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(database_url)
-            .await?;
-            
-        info!("Database connection established");
-        
-        // Initialize tables if they don't exist
-        Self::initialize_tables(&pool).await?;
-        
-        Ok(Self { pool })
+    // Never fails for an unreachable server; the pool connects lazily and the probe
+    // reports the outage, so the application starts while the database is down
+    pub async fn new(database_url: &str, config: DatabaseConfig, spool_dir: &Path) -> Result<Self> {
+        info!("Connecting to database...");
+
+        let manager = Self {
+            url: database_url.to_string(),
+            pool: Arc::new(RwLock::new(Self::build_pool(database_url, &config)?)),
+            spool: Spool::new(spool_dir, "database_logs", config.spool_max_bytes)?,
+            config,
+            stats: Arc::new(DatabaseStats::default()),
+            initialized: Arc::new(AtomicBool::new(false)),
+        };
+
+        manager.probe().await;
+        if manager.is_healthy() {
+            info!("Database connection established");
+        } else {
+            warn!("Database is not reachable, continuing without it until it returns");
+        }
+
+        Ok(manager)
+    }
+
+    fn build_pool(url: &str, config: &DatabaseConfig) -> Result<PgPool> {
+        Ok(PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+            .connect_lazy(url)?)
+    }
+
+    fn pool(&self) -> Result<PgPool> {
+        match self.pool.read() {
+            Ok(pool) => Ok(pool.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on database pool")),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.stats.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn probe_interval(&self) -> Duration {
+        Duration::from_secs(self.config.probe_interval_secs.max(1))
+    }
+
+    async fn acquire(&self) -> std::result::Result<PoolConnection<Postgres>, sqlx::Error> {
+        let pool = self.pool().map_err(|_| sqlx::Error::PoolClosed)?;
+        let started = Instant::now();
+        let result = pool.acquire().await;
+
+        self.stats.acquires.fetch_add(1, Ordering::Relaxed);
+        self.stats.acquire_wait_us.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        if matches!(result, Err(sqlx::Error::PoolTimedOut)) {
+            self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // Runs the operation on a pooled connection, retrying transient failures with
+    // exponential backoff
+    async fn with_retry<T, F, Fut>(&self, operation: F) -> Result<T>
+    where
+        F: Fn(PoolConnection<Postgres>) -> Fut,
+        Fut: Future<Output = std::result::Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = match self.acquire().await {
+                Ok(connection) => operation(connection).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(value) => return Ok(value),
+                Err(e) if is_transient(&e) && attempt < self.config.retry_attempts => {
+                    let delay = Duration::from_millis(self.config.retry_base_ms << attempt.min(10));
+                    warn!("Transient database error, retrying in {:?}: {}", delay, e);
+                    self.stats.retries.fetch_add(1, Ordering::Relaxed);
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Checks connectivity and flips the health flag. On recovery the tables are
+    // (re)initialized and spooled writes replayed; a pool failing longer than
+    // recycle_after_secs is replaced so no broken connection state survives the outage.
+    pub async fn probe(&self) {
+        let result = match self.acquire().await {
+            Ok(mut connection) => sqlx::query("SELECT 1").execute(&mut *connection).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                let recovered = !self.stats.healthy.swap(true, Ordering::Relaxed);
+                if let Ok(mut failing_since) = self.stats.failing_since.lock() {
+                    *failing_since = None;
+                }
+
+                if !self.initialized.load(Ordering::Relaxed) {
+                    match self.pool() {
+                        Ok(pool) => match Self::initialize_tables(&pool).await {
+                            Ok(()) => self.initialized.store(true, Ordering::Relaxed),
+                            Err(e) => error!("Failed to initialize database tables: {}", e),
+                        },
+                        Err(e) => error!("{}", e),
+                    }
+                }
+                if recovered {
+                    info!("Database is reachable");
+                    self.replay_spool().await;
+                }
+            },
+            Err(e) => {
+                if self.stats.healthy.swap(false, Ordering::Relaxed) {
+                    error!("Database became unreachable: {}", e);
+                }
+
+                let recycle = match self.stats.failing_since.lock() {
+                    Ok(mut failing_since) => {
+                        let since = *failing_since.get_or_insert_with(Instant::now);
+                        let recycle = since.elapsed() >= Duration::from_secs(self.config.recycle_after_secs);
+                        if recycle {
+                            // Measure the next period from the new pool
+                            *failing_since = Some(Instant::now());
+                        }
+                        recycle
+                    },
+                    Err(_) => false,
+                };
+                if recycle {
+                    self.recycle_pool().await;
+                }
+            },
+        }
+    }
+
+    async fn recycle_pool(&self) {
+        let fresh = match Self::build_pool(&self.url, &self.config) {
+            Ok(pool) => pool,
+            Err(e) => {
+                error!("Failed to create a new database pool: {}", e);
+                return;
+            },
+        };
+
+        let old = match self.pool.write() {
+            Ok(mut pool) => std::mem::replace(&mut *pool, fresh),
+            Err(_) => return,
+        };
+        self.stats.recycles.fetch_add(1, Ordering::Relaxed);
+        warn!("Database unreachable for over {}s, recycled the connection pool", self.config.recycle_after_secs);
+        old.close().await;
+    }
+
+    pub fn health(&self) -> DatabaseHealth {
+        let (pool_size, pool_idle) = self.pool()
+            .map(|pool| (pool.size(), pool.num_idle()))
+            .unwrap_or_default();
+        DatabaseHealth {
+            healthy: self.is_healthy(),
+            failing_for_secs: self.stats.failing_since.lock().ok()
+                .and_then(|since| since.map(|s| s.elapsed().as_secs())),
+            pool_size,
+            pool_idle,
+            spooled: self.spool.counts().map(|(entries, _)| entries).unwrap_or_default(),
+        }
+    }
+
+    pub fn render_metrics(&self) -> Result<String> {
+        let health = self.health();
+        let (_, dropped) = self.spool.counts()?;
+        let stats = &self.stats;
+        let mut out = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+        };
+        metric("siem_db_healthy", "gauge", "Whether the last database probe succeeded", (health.healthy as u8).to_string());
+        metric("siem_db_pool_size", "gauge", "Connections in the database pool", health.pool_size.to_string());
+        metric("siem_db_pool_idle", "gauge", "Idle connections in the database pool", health.pool_idle.to_string());
+        metric("siem_db_acquires_total", "counter", "Connections acquired from the pool", stats.acquires.load(Ordering::Relaxed).to_string());
+        metric("siem_db_acquire_wait_seconds_total", "counter", "Time spent waiting for a pool connection",
+               format!("{:.6}", stats.acquire_wait_us.load(Ordering::Relaxed) as f64 / 1_000_000.0));
+        metric("siem_db_acquire_timeouts_total", "counter", "Pool acquisitions that timed out", stats.timeouts.load(Ordering::Relaxed).to_string());
+        metric("siem_db_retries_total", "counter", "Database operations retried after a transient error", stats.retries.load(Ordering::Relaxed).to_string());
+        metric("siem_db_pool_recycles_total", "counter", "Pools replaced after a prolonged outage", stats.recycles.load(Ordering::Relaxed).to_string());
+        metric("siem_db_spooled_logs", "gauge", "Log writes waiting in the spool for the database", health.spooled.to_string());
+        metric("siem_db_spool_dropped_total", "counter", "Log writes dropped because the spool was full", dropped.to_string());
+        metric("siem_db_replayed_logs_total", "counter", "Spooled log writes stored after the database returned", stats.replayed.load(Ordering::Relaxed).to_string());
+        metric("siem_db_log_batches_total", "counter", "Multi-row log inserts committed", stats.log_batches.load(Ordering::Relaxed).to_string());
+        metric("siem_db_batched_logs_total", "counter", "Logs stored by multi-row inserts", stats.batched_logs.load(Ordering::Relaxed).to_string());
+
+        Ok(out)
+    }
+
+    // Stores spooled entries in order; the first failure puts the rest back
+    async fn replay_spool(&self) {
+        let entries: Vec<LogEntry> = match self.spool.take() {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read the database spool: {}", e);
+                return;
+            },
+        };
+        if entries.is_empty() {
+            return;
+        }
+
+        info!("Replaying {} spooled log writes", entries.len());
+        let mut remaining = entries.into_iter();
+        while let Some(entry) = remaining.next() {
+            if let Err(e) = self.insert_log(&entry).await {
+                warn!("Replay of spooled log writes stopped: {}", e);
+                for entry in std::iter::once(entry).chain(remaining) {
+                    if let Err(e) = self.spool.push(&entry) {
+                        error!("Failed to put log entry {} back into the spool: {}", entry.id, e);
+                    }
+                }
+                return;
+            }
+            self.stats.replayed.fetch_add(1, Ordering::Relaxed);
+        }
     }
     
     async fn initialize_tables(pool: &PgPool) -> Result<()> {
@@ -61,71 +336,374 @@ impl DatabaseManager {
         .execute(pool)
         .await?;
         
-        info!("Database tables initialized successfully");
-        Ok(())
-    }
-    
-    pub async fn store_log(&self, entry: &LogEntry) -> Result<()> {
+        // Migration: event type and normalized category columns
         sqlx::query(r#"
-            INSERT INTO logs (
-                id, timestamp, ip_address, log_message, log_level, 
-                source, raw_data, host, user_id, application, tags
-            ) VALUES (
-                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11
-            )
+            ALTER TABLE logs ADD COLUMN IF NOT EXISTS event_type TEXT NOT NULL DEFAULT '';
+            ALTER TABLE logs ADD COLUMN IF NOT EXISTS category TEXT NOT NULL DEFAULT 'Other';
+            
+            CREATE INDEX IF NOT EXISTS idx_logs_category ON logs (category);
         "#)
-        .bind(entry.id)
-        .bind(entry.timestamp)
-        .bind(entry.host.as_ref().and_then(|h| IpAddr::from_str(h).ok().map(|ip| ip.to_string())).unwrap_or_default())
-        .bind(&entry.message)
-        .bind(entry.severity.to_string())
-        .bind(&entry.source)
-        .bind(&entry.raw_data)
-        .bind(&entry.host)
-        .bind(&entry.user)
-        .bind(&entry.application)
-        .bind(&entry.tags)
-        .execute(&self.pool)
+        .execute(pool)
         .await?;
         
+        // Migration: the parser, so stored logs can be parsed again
+        sqlx::query(r#"
+            ALTER TABLE logs ADD COLUMN IF NOT EXISTS parser TEXT NOT NULL DEFAULT 'unknown';
+        "#)
+        .execute(pool)
+        .await?;
+        
+        info!("Database tables initialized successfully");
         Ok(())
     }
     
+    // Stores the entry, spooling it when the database stays unreachable through the retries
+    pub async fn store_log(&self, entry: &LogEntry) -> Result<()> {
+        let result = if self.is_healthy() {
+            self.insert_log(entry).await
+        } else {
+            Err(anyhow!("Database is unreachable"))
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if !self.is_healthy() || e.downcast_ref::<sqlx::Error>().map_or(false, is_transient) => {
+                if !self.spool.push(entry)? {
+                    warn!("Database spool is full, dropped log entry {}", entry.id);
+                }
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn insert_log(&self, entry: &LogEntry) -> Result<()> {
+        self.with_retry(|mut connection| async move {
+            sqlx::query(r#"
+                INSERT INTO logs (
+                    id, timestamp, ip_address, log_message, log_level, 
+                    source, raw_data, host, user_id, application, tags,
+                    event_type, category, parser
+                ) VALUES (
+                    $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14
+                )
+            "#)
+            .bind(entry.id)
+            .bind(entry.timestamp)
+            .bind(entry.host.as_ref().and_then(|h| IpAddr::from_str(h).ok().map(|ip| ip.to_string())).unwrap_or_default())
+            .bind(&entry.message)
+            .bind(entry.severity.to_string())
+            .bind(&entry.source)
+            .bind(&entry.raw_data)
+            .bind(&entry.host)
+            .bind(&entry.user)
+            .bind(&entry.application)
+            .bind(&entry.tags)
+            .bind(&entry.event_type)
+            .bind(entry.category.as_str())
+            .bind(entry.parser.as_str())
+            .execute(&mut *connection)
+            .await
+            .map(|_| ())
+        }).await
+    }
+
+    // One statement for the whole batch, so a failure stores none of it
+    async fn insert_logs(&self, entries: &[&LogEntry]) -> Result<()> {
+        self.with_retry(|mut connection| async move {
+            let mut query: QueryBuilder<Postgres> = QueryBuilder::new(r#"
+                INSERT INTO logs (
+                    id, timestamp, ip_address, log_message, log_level,
+                    source, raw_data, host, user_id, application, tags,
+                    event_type, category, parser
+                ) "#);
+            query.push_values(entries, |mut row, entry| {
+                row.push_bind(entry.id)
+                    .push_bind(entry.timestamp)
+                    .push_bind(entry.host.as_ref().and_then(|h| IpAddr::from_str(h).ok().map(|ip| ip.to_string())).unwrap_or_default())
+                    .push_bind(&entry.message)
+                    .push_bind(entry.severity.to_string())
+                    .push_bind(&entry.source)
+                    .push_bind(&entry.raw_data)
+                    .push_bind(&entry.host)
+                    .push_bind(&entry.user)
+                    .push_bind(&entry.application)
+                    .push_bind(&entry.tags)
+                    .push_bind(&entry.event_type)
+                    .push_bind(entry.category.as_str())
+                    .push_bind(entry.parser.as_str());
+            });
+            query.build()
+                .execute(&mut *connection)
+                .await
+                .map(|_| ())
+        }).await
+    }
+
+    // Stores a batch from the writer. While the database is unreachable the batch is
+    // spooled; a batch it rejects is stored entry by entry, so one bad row costs only
+    // itself.
+    async fn store_batch(&self, batch: &[(LogEntry, Instant)], timings: &IngestTimings) {
+        let entries: Vec<&LogEntry> = batch.iter().map(|(entry, _)| entry).collect();
+        let started = Instant::now();
+        let result = if self.is_healthy() {
+            self.insert_logs(&entries).await
+        } else {
+            Err(anyhow!("Database is unreachable"))
+        };
+
+        match result {
+            Ok(()) => {
+                timings.observe(Stage::DatabaseBatch, started.elapsed());
+                for (_, ingested) in batch {
+                    timings.observe(Stage::DatabaseCommit, ingested.elapsed());
+                }
+                self.stats.log_batches.fetch_add(1, Ordering::Relaxed);
+                self.stats.batched_logs.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            },
+            Err(e) if !self.is_healthy() || e.downcast_ref::<sqlx::Error>().map_or(false, is_transient) => {
+                for entry in entries {
+                    match self.spool.push(entry) {
+                        Ok(true) => {},
+                        Ok(false) => warn!("Database spool is full, dropped log entry {}", entry.id),
+                        Err(e) => error!("Failed to spool log entry {}: {}", entry.id, e),
+                    }
+                }
+            },
+            Err(e) => {
+                warn!("Batch insert of {} log entries failed, storing them one by one: {}", entries.len(), e);
+                for (entry, ingested) in batch {
+                    match self.store_log(entry).await {
+                        Ok(()) => timings.observe(Stage::DatabaseCommit, ingested.elapsed()),
+                        Err(e) => error!("Failed to store log entry {} in the database: {}", entry.id, e),
+                    }
+                }
+            },
+        }
+    }
+
+    // Ingestion is synchronous, so entries are queued here with the time their ingestion
+    // started and written by a task. The task waits for an entry, gathers more until the
+    // batch is full or flush_interval_ms has passed, and writes them in one insert.
+    pub fn log_writer(&self, timings: IngestTimings) -> mpsc::UnboundedSender<(LogEntry, Instant)> {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(LogEntry, Instant)>();
+        let manager = self.clone();
+        let batch_size = self.config.batch_size.clamp(1, MAX_BATCH_SIZE);
+        let flush_interval = Duration::from_millis(self.config.flush_interval_ms);
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(batch_size);
+            while let Some(queued) = receiver.recv().await {
+                batch.push(queued);
+                let deadline = tokio::time::Instant::now() + flush_interval;
+                while batch.len() < batch_size {
+                    match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                        Ok(Some(queued)) => batch.push(queued),
+                        // Closed, or the interval is over
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+
+                manager.store_batch(&batch, &timings).await;
+                batch.clear();
+            }
+        });
+        sender
+    }
+    
     pub async fn query_logs_by_ip(&self, ip_address: &str) -> Result<Vec<LogEntry>> {
-        let logs = sqlx::query_as!(
-            LogEntryRow,
-            r#"
-            SELECT id, timestamp, ip_address, log_message, log_level, 
-                   source, raw_data, host, user_id as user, application, tags
-            FROM logs
-            WHERE ip_address = $1::inet
-            ORDER BY timestamp DESC
-            "#,
-            ip_address
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        let logs = self.with_retry(|mut connection| async move {
+            sqlx::query_as!(
+                LogEntryRow,
+                r#"
+                SELECT id, timestamp, ip_address, log_message, log_level, 
+                       source, raw_data, host, user_id as user, application, tags,
+                       event_type, category, parser
+                FROM logs
+                WHERE ip_address = $1::inet
+                ORDER BY timestamp DESC
+                "#,
+                ip_address
+            )
+            .fetch_all(&mut *connection)
+            .await
+        }).await?;
         
         Ok(logs.into_iter().map(|row| row.into()).collect())
     }
     
     pub async fn query_logs_by_ip_range(&self, ip_range: &str) -> Result<Vec<LogEntry>> {
-        let logs = sqlx::query_as!(
-            LogEntryRow,
-            r#"
-            SELECT id, timestamp, ip_address, log_message, log_level, 
-                   source, raw_data, host, user_id as user, application, tags
-            FROM logs
-            WHERE ip_address <<= $1::inet
-            ORDER BY timestamp DESC
-            "#,
-            ip_range
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        let logs = self.with_retry(|mut connection| async move {
+            sqlx::query_as!(
+                LogEntryRow,
+                r#"
+                SELECT id, timestamp, ip_address, log_message, log_level, 
+                       source, raw_data, host, user_id as user, application, tags,
+                       event_type, category, parser
+                FROM logs
+                WHERE ip_address <<= $1::inet
+                ORDER BY timestamp DESC
+                "#,
+                ip_range
+            )
+            .fetch_all(&mut *connection)
+            .await
+        }).await?;
         
         Ok(logs.into_iter().map(|row| row.into()).collect())
     }
+    
+    // Up to `limit` logs in [from, to] after the cursor, oldest first by timestamp and
+    // then id, so a range can be walked page by page without loading it
+    pub async fn logs_page(&self,
+                           from: chrono::DateTime<Utc>,
+                           to: chrono::DateTime<Utc>,
+                           after: Option<(chrono::DateTime<Utc>, Uuid)>,
+                           limit: i64) -> Result<Vec<LogEntry>> {
+        let first = after.is_none();
+        let (after_timestamp, after_id) = after.unwrap_or((from, Uuid::nil()));
+        let logs = self.with_retry(|mut connection| async move {
+            sqlx::query_as!(
+                LogEntryRow,
+                r#"
+                SELECT id, timestamp, ip_address, log_message, log_level, 
+                       source, raw_data, host, user_id as user, application, tags,
+                       event_type, category, parser
+                FROM logs
+                WHERE timestamp >= $1 AND timestamp <= $2
+                  AND ($3 OR (timestamp, id) > ($4, $5))
+                ORDER BY timestamp, id
+                LIMIT $6
+                "#,
+                from,
+                to,
+                first,
+                after_timestamp,
+                after_id,
+                limit
+            )
+            .fetch_all(&mut *connection)
+            .await
+        }).await?;
+
+        Ok(logs.into_iter().map(|row| row.into()).collect())
+    }
+
+    pub async fn get_log(&self, id: Uuid) -> Result<Option<LogEntry>> {
+        let row = self.with_retry(|mut connection| async move {
+            sqlx::query_as!(
+                LogEntryRow,
+                r#"
+                SELECT id, timestamp, ip_address, log_message, log_level, 
+                       source, raw_data, host, user_id as user, application, tags,
+                       event_type, category, parser
+                FROM logs
+                WHERE id = $1
+                "#,
+                id
+            )
+            .fetch_optional(&mut *connection)
+            .await
+        }).await?;
+
+        Ok(row.map(|row| row.into()))
+    }
+
+    // Logs in [from, to], the total a walk with logs_page goes through
+    pub async fn count_logs_between(&self,
+                                    from: chrono::DateTime<Utc>,
+                                    to: chrono::DateTime<Utc>) -> Result<i64> {
+        let count = self.with_retry(|mut connection| async move {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM logs WHERE timestamp >= $1 AND timestamp <= $2")
+                .bind(from)
+                .bind(to)
+                .fetch_one(&mut *connection)
+                .await
+        }).await?;
+
+        Ok(count)
+    }
+
+    // Writes the fields a parse produces; id, timestamp, source and raw_data stay as they
+    // were stored. Returns false when the log is not in the database.
+    pub async fn update_parsed_fields(&self, entry: &LogEntry) -> Result<bool> {
+        let updated = self.with_retry(|mut connection| async move {
+            sqlx::query(r#"
+                UPDATE logs SET
+                    ip_address = $2, log_message = $3, log_level = $4, host = $5, user_id = $6,
+                    application = $7, tags = $8, event_type = $9, category = $10, parser = $11
+                WHERE id = $1
+            "#)
+            .bind(entry.id)
+            .bind(entry.host.as_ref().and_then(|h| IpAddr::from_str(h).ok().map(|ip| ip.to_string())).unwrap_or_default())
+            .bind(&entry.message)
+            .bind(entry.severity.to_string())
+            .bind(&entry.host)
+            .bind(&entry.user)
+            .bind(&entry.application)
+            .bind(&entry.tags)
+            .bind(&entry.event_type)
+            .bind(entry.category.as_str())
+            .bind(entry.parser.as_str())
+            .execute(&mut *connection)
+            .await
+        }).await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+
+    // Recomputes the category of stored logs in batches, returns how many changed.
+    // Without `force` only logs still in the Other category are touched.
+    pub async fn reclassify_logs<F>(&self, force: bool, classify: F) -> Result<u64>
+    where
+        F: Fn(&LogEntry) -> EventCategory,
+    {
+        const BATCH_SIZE: i64 = 1000;
+        let mut changed = 0;
+        let mut last_id = Uuid::nil();
+        
+        let pool = self.pool()?;
+        loop {
+            let rows = sqlx::query_as!(
+                LogEntryRow,
+                r#"
+                SELECT id, timestamp, ip_address, log_message, log_level, 
+                       source, raw_data, host, user_id as user, application, tags,
+                       event_type, category, parser
+                FROM logs
+                WHERE id > $1 AND ($2 OR category = 'Other')
+                ORDER BY id
+                LIMIT $3
+                "#,
+                last_id,
+                force,
+                BATCH_SIZE
+            )
+            .fetch_all(&pool)
+            .await?;
+            
+            if rows.is_empty() {
+                break;
+            }
+            
+            for row in rows {
+                let entry: LogEntry = row.into();
+                last_id = entry.id;
+                
+                let category = classify(&entry);
+                if category != entry.category {
+                    sqlx::query("UPDATE logs SET category = $1 WHERE id = $2")
+                        .bind(category.as_str())
+                        .bind(entry.id)
+                        .execute(&pool)
+                        .await?;
+                    changed += 1;
+                }
+            }
+        }
+        
+        info!("Reclassified {} stored logs", changed);
+        Ok(changed)
+    }
 }
 
 // Database row representation matching the logs table
@@ -142,6 +720,9 @@ struct LogEntryRow {
     user: Option<String>,
     application: Option<String>,
     tags: Option<Vec<String>>,
+    event_type: String,
+    category: String,
+    parser: String,
 }
 
 // Convert from database row to LogEntry model
@@ -161,7 +742,7 @@ impl From<LogEntryRow> for LogEntry {
             id: row.id,
             timestamp: row.timestamp,
             source: row.source,
-            event_type: "".to_string(), // This would need to be mapped or added to the schema
+            event_type: row.event_type,
             severity,
             message: row.log_message,
             raw_data: row.raw_data,
@@ -169,6 +750,10 @@ impl From<LogEntryRow> for LogEntry {
             user: row.user,
             application: row.application,
             tags: row.tags.unwrap_or_default(),
+            category: EventCategory::parse(&row.category).unwrap_or_default(),
+            hostname: None,
+            site_id: None,
+            parser: LogParser::parse(&row.parser),
         }
     }
 }
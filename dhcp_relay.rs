@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::watch;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::{info, warn, error};
+
+use crate::config::DhcpRelayConfig;
+use crate::network::{DhcpRelayCounters, InterfaceConfig, InterfaceInfo};
+
+// A relay that ran this long before exiting is restarted after the initial delay again
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DhcpRelayStatus {
+    pub servers: Vec<String>,
+    pub running: bool,
+    pub pid: Option<u32>,
+    pub started_at: Option<DateTime<Utc>>,
+    // Times the process exited or failed to start
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    // From the relay's firewall rules; None when they cannot be read
+    pub counters: Option<DhcpRelayCounters>,
+}
+
+struct Relay {
+    servers: Vec<String>,
+    // Tells a supervisor its status entry was replaced
+    generation: Uuid,
+    status: DhcpRelayStatus,
+    stop: watch::Sender<bool>,
+}
+
+// One dhcrelay process per interface with a relay switched on, started again when it
+// exits. The firewall rules the relay needs are kept by NetworkManager.
+#[derive(Clone)]
+pub struct DhcpRelayManager {
+    config: DhcpRelayConfig,
+    relays: Arc<Mutex<HashMap<String, Relay>>>,
+}
+
+impl DhcpRelayManager {
+    pub fn new(config: DhcpRelayConfig) -> Self {
+        Self {
+            config,
+            relays: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Starts, stops and restarts relays to match the interface config; a relay whose
+    // servers changed is restarted
+    pub fn sync(&self, interfaces: &[InterfaceConfig]) -> Result<()> {
+        let wanted: HashMap<&str, &Vec<String>> = interfaces.iter()
+            .filter_map(|iface| iface.dhcp_relay.as_ref()
+                .filter(|relay| relay.enabled)
+                .map(|relay| (iface.name.as_str(), &relay.servers)))
+            .collect();
+
+        let mut relays = self.relays.lock().map_err(|_| anyhow!("Failed to acquire lock on DHCP relays"))?;
+        relays.retain(|interface, relay| {
+            let keep = wanted.get(interface.as_str()).is_some_and(|servers| **servers == relay.servers);
+            if !keep {
+                let _ = relay.stop.send(true);
+                info!("Stopping DHCP relay on {}", interface);
+            }
+            keep
+        });
+
+        for (interface, servers) in wanted {
+            if relays.contains_key(interface) {
+                continue;
+            }
+
+            let (stop, stopped) = watch::channel(false);
+            let generation = Uuid::new_v4();
+            relays.insert(interface.to_string(), Relay {
+                servers: servers.clone(),
+                generation,
+                status: DhcpRelayStatus {
+                    servers: servers.clone(),
+                    running: false,
+                    pid: None,
+                    started_at: None,
+                    restarts: 0,
+                    last_error: None,
+                    counters: None,
+                },
+                stop,
+            });
+
+            let manager = self.clone();
+            let interface = interface.to_string();
+            let servers = servers.clone();
+            tokio::spawn(async move {
+                manager.supervise(interface, servers, generation, stopped).await;
+            });
+        }
+
+        Ok(())
+    }
+
+    fn update<F>(&self, interface: &str, generation: Uuid, change: F)
+    where
+        F: FnOnce(&mut DhcpRelayStatus),
+    {
+        match self.relays.lock() {
+            Ok(mut relays) => {
+                if let Some(relay) = relays.get_mut(interface).filter(|r| r.generation == generation) {
+                    change(&mut relay.status);
+                }
+            },
+            Err(_) => error!("Failed to acquire lock on DHCP relays"),
+        }
+    }
+
+    // Runs dhcrelay in the foreground until told to stop, starting it again with a growing
+    // delay whenever it exits
+    async fn supervise(&self, interface: String, servers: Vec<String>, generation: Uuid, mut stop: watch::Receiver<bool>) {
+        let initial_delay = Duration::from_secs(self.config.restart_delay_secs.max(1));
+        let max_delay = Duration::from_secs(self.config.max_restart_delay_secs).max(initial_delay);
+        let mut delay = initial_delay;
+
+        loop {
+            let mut command = Command::new(&self.config.dhcrelay_path);
+            command
+                .args(dhcrelay_args(&interface, &servers))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
+
+            let failure = match command.spawn() {
+                Ok(mut child) => {
+                    let started = Instant::now();
+                    let pid = child.id();
+                    self.update(&interface, generation, |status| {
+                        status.running = true;
+                        status.pid = pid;
+                        status.started_at = Some(Utc::now());
+                    });
+                    info!("Started DHCP relay on {} to {} (pid {:?})", interface, servers.join(", "), pid);
+
+                    // dhcrelay logs every relayed packet in the foreground, so stderr is
+                    // drained as it comes and only the last line kept for the status
+                    let last_line = Arc::new(Mutex::new(String::new()));
+                    if let Some(stderr) = child.stderr.take() {
+                        let last_line = last_line.clone();
+                        tokio::spawn(async move {
+                            let mut lines = BufReader::new(stderr).lines();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line.trim().is_empty() {
+                                    continue;
+                                }
+                                if let Ok(mut last) = last_line.lock() {
+                                    *last = line;
+                                }
+                            }
+                        });
+                    }
+
+                    tokio::select! {
+                        exit = child.wait() => {
+                            if started.elapsed() >= STABLE_RUN {
+                                delay = initial_delay;
+                            }
+                            let last = last_line.lock().map(|l| l.clone()).unwrap_or_default();
+                            match exit {
+                                Ok(exit) => format!("dhcrelay exited with {}: {}", exit, last.trim()),
+                                Err(e) => format!("Failed to wait for dhcrelay: {}", e),
+                            }
+                        },
+                        _ = stop.changed() => {
+                            if let Err(e) = child.kill().await {
+                                warn!("Failed to stop DHCP relay on {}: {}", interface, e);
+                            }
+                            info!("Stopped DHCP relay on {}", interface);
+                            return;
+                        },
+                    }
+                },
+                Err(e) => format!("Failed to start {}: {}", self.config.dhcrelay_path, e),
+            };
+
+            warn!("DHCP relay on {}: {}, starting it again in {}s", interface, failure, delay.as_secs());
+            self.update(&interface, generation, |status| {
+                status.running = false;
+                status.pid = None;
+                status.restarts += 1;
+                status.last_error = Some(failure);
+            });
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = stop.changed() => return,
+            }
+            delay = (delay * 2).min(max_delay);
+        }
+    }
+
+    // Sets the relay status of the interfaces that relay, with the counters of their
+    // firewall rules
+    pub fn mark(&self, interfaces: &mut [InterfaceInfo], counters: &HashMap<String, DhcpRelayCounters>) -> Result<()> {
+        let relays = self.relays.lock().map_err(|_| anyhow!("Failed to acquire lock on DHCP relays"))?;
+        for interface in interfaces.iter_mut() {
+            interface.dhcp_relay = relays.get(&interface.name).map(|relay| DhcpRelayStatus {
+                counters: counters.get(&interface.name).cloned(),
+                ..relay.status.clone()
+            });
+        }
+        Ok(())
+    }
+}
+
+// Runs dhcrelay in the foreground for IPv4 only, listening on the interface and relaying
+// to the servers
+fn dhcrelay_args(interface: &str, servers: &[String]) -> Vec<String> {
+    let mut args = vec!["-d".to_string(), "-4".to_string(), "-i".to_string(), interface.to_string()];
+    args.extend(servers.iter().cloned());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::DhcpRelay;
+
+    fn relaying(name: &str, enabled: bool, servers: &[&str]) -> InterfaceConfig {
+        InterfaceConfig {
+            name: name.to_string(),
+            dhcp: Some(false),
+            address: Some("192.0.2.1/24".to_string()),
+            nftables_zone: None,
+            bond: None,
+            dhcp_relay: Some(DhcpRelay {
+                enabled,
+                servers: servers.iter().map(|s| s.to_string()).collect(),
+            }),
+        }
+    }
+
+    fn info(name: &str) -> InterfaceInfo {
+        InterfaceInfo {
+            name: name.to_string(),
+            addresses: Vec::new(),
+            is_up: true,
+            mac_address: String::new(),
+            bond_master: None,
+            bond: None,
+            metadata: None,
+            flapping: false,
+            dhcp_relay: None,
+        }
+    }
+
+    #[test]
+    fn dhcrelay_runs_in_the_foreground_on_the_interface() {
+        let servers = vec!["198.51.100.10".to_string(), "198.51.100.11".to_string()];
+
+        assert_eq!(
+            dhcrelay_args("lan0", &servers),
+            vec!["-d", "-4", "-i", "lan0", "198.51.100.10", "198.51.100.11"]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_tracks_enabled_relays_and_records_start_failures() {
+        let manager = DhcpRelayManager::new(DhcpRelayConfig {
+            dhcrelay_path: "/nonexistent/dhcrelay".to_string(),
+            restart_delay_secs: 60,
+            max_restart_delay_secs: 60,
+        });
+        manager.sync(&[
+            relaying("lan0", true, &["198.51.100.10"]),
+            relaying("lan1", false, &["198.51.100.10"]),
+        ]).unwrap();
+
+        let mut interfaces = vec![info("lan0"), info("lan1")];
+        let mut status = None;
+        for _ in 0..50 {
+            manager.mark(&mut interfaces, &HashMap::new()).unwrap();
+            status = interfaces[0].dhcp_relay.clone().filter(|s| s.restarts > 0);
+            if status.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = status.expect("failed start was not recorded");
+        assert!(!status.running);
+        assert_eq!(status.servers, vec!["198.51.100.10"]);
+        assert!(status.last_error.unwrap().contains("/nonexistent/dhcrelay"));
+        assert!(interfaces[1].dhcp_relay.is_none());
+
+        manager.sync(&[relaying("lan0", false, &["198.51.100.10"])]).unwrap();
+        manager.mark(&mut interfaces, &HashMap::new()).unwrap();
+        assert!(interfaces[0].dhcp_relay.is_none());
+    }
+}
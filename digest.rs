@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::activity::{ActivityKind, ResourceKind};
+use crate::api::AppState;
+use crate::ingestion_quotas::source_key;
+use crate::logs::LogFilter;
+use crate::models::AlertSeverity;
+use crate::printers::SupplyStatus;
+use crate::reports::{self, DigestSection, ReportFormat, ReportOverrides, ReportSettings, SectionContent};
+use crate::searches::FilterSource;
+use crate::tickets::TicketStatus;
+
+// Background task sampling link states for the interface flap section
+const LINK_MONITOR_TASK: &str = "link_monitor";
+
+const SEVERITIES: [AlertSeverity; 4] = [
+    AlertSeverity::Critical,
+    AlertSeverity::High,
+    AlertSeverity::Medium,
+    AlertSeverity::Low,
+];
+
+fn is_closed(status: &TicketStatus) -> bool {
+    matches!(status, TicketStatus::Resolved | TicketStatus::Closed)
+}
+
+fn alerts_section(state: &AppState, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let alerts = match state.alerts_manager.get_all_alerts() {
+        Ok(alerts) => alerts,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+
+    SEVERITIES.iter()
+        .map(|severity| {
+            let new = alerts.iter()
+                .filter(|a| a.severity == *severity && a.created_at >= from && a.created_at <= to)
+                .count();
+            let resolved = alerts.iter()
+                .filter(|a| a.severity == *severity && a.resolved_at.map_or(false, |at| at >= from && at <= to))
+                .count();
+            format!("{:?}: {} new, {} resolved", severity, new, resolved)
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn tickets_section(state: &AppState, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let tickets = match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => tickets,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+    let changes = match state.activity_log.between(ResourceKind::Ticket, from, to) {
+        Ok(changes) => changes,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+
+    let status = |value: Option<&serde_json::Value>| value
+        .and_then(|v| serde_json::from_value::<TicketStatus>(v.clone()).ok());
+    let (mut closed, mut reopened) = (0i64, 0i64);
+    for change in changes.iter().filter(|c| c.kind == ActivityKind::StatusChanged) {
+        match (status(change.payload.get("from")), status(change.payload.get("to"))) {
+            (Some(before), Some(after)) if !is_closed(&before) && is_closed(&after) => closed += 1,
+            (Some(before), Some(after)) if is_closed(&before) && !is_closed(&after) => reopened += 1,
+            _ => {},
+        }
+    }
+
+    let opened = tickets.iter().filter(|t| t.created_at >= from && t.created_at <= to).count() as i64;
+    let backlog = tickets.iter().filter(|t| !is_closed(&t.status)).count();
+    let delta = opened + reopened - closed;
+
+    SectionContent::Lines(vec![
+        format!("Open tickets: {} ({:+} in the period)", backlog, delta),
+        format!("Opened: {}, resolved or closed: {}, reopened: {}", opened, closed, reopened),
+    ])
+}
+
+// Tickets that passed their due date while still open, and breaches recorded by SLA tracking
+fn sla_section(state: &AppState, settings: &ReportSettings, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let tickets = match state.tickets_manager.get_all_tickets() {
+        Ok(tickets) => tickets,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+    let events = match state.activity_log.between(ResourceKind::Ticket, from, to) {
+        Ok(events) => events,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+
+    let mut lines: Vec<String> = tickets.iter()
+        .filter(|t| !is_closed(&t.status) && t.due_date.map_or(false, |due| due >= from && due <= to))
+        .map(|t| format!("{} ({:?}) overdue since {}", t.title, t.priority,
+                         settings.timestamp(t.due_date.unwrap_or(to))))
+        .collect();
+
+    lines.extend(events.iter()
+        .filter(|e| e.kind == ActivityKind::SlaEvent)
+        .filter(|e| e.payload.get("event").and_then(|v| v.as_str()).map_or(false, |v| v.contains("breach")))
+        .map(|e| format!("[{}] Ticket {}: SLA breach", settings.timestamp(e.timestamp), e.resource_id)));
+
+    SectionContent::Lines(lines)
+}
+
+fn firewall_section(state: &AppState, settings: &ReportSettings, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    match state.activity_log.between(ResourceKind::FirewallRule, from, to) {
+        Ok(changes) => changes.iter()
+            .map(|c| format!("[{}] {} rule {} ({:?})", settings.timestamp(c.timestamp), c.actor, c.resource_id, c.kind))
+            .collect::<Vec<_>>()
+            .into(),
+        Err(e) => SectionContent::Failed(e.to_string()),
+    }
+}
+
+async fn link_flaps_section(state: &AppState, from: DateTime<Utc>) -> SectionContent {
+    // Without a successful sample there is no link history to judge by
+    let monitor = state.task_registry.get_all_tasks().ok()
+        .and_then(|tasks| tasks.into_iter().find(|t| t.name == LINK_MONITOR_TASK));
+    match monitor {
+        None => return SectionContent::Disabled("link monitoring is not running".to_string()),
+        Some(task) if task.last_success.is_none() => {
+            return SectionContent::Failed(task.last_error.unwrap_or_else(|| "no link state sampled yet".to_string()));
+        },
+        Some(_) => {},
+    }
+
+    state.network_manager.link_flaps_since(from).await.iter()
+        .map(|f| format!("{}: {} state changes, now {}", f.interface, f.changes, if f.is_up { "up" } else { "down" }))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn top_sources_section(state: &AppState, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let entries = match state.logs_manager.query(&LogFilter {
+        from: Some(from),
+        to: Some(to),
+        ..Default::default()
+    }) {
+        Ok(entries) => entries,
+        Err(e) => return SectionContent::Failed(e.to_string()),
+    };
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for entry in &entries {
+        *counts.entry(source_key(entry)).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    counts.into_iter()
+        .take(state.config.digest.top_sources)
+        .map(|(source, count)| format!("{}: {} events", source, count))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// Matches of each attached saved search over the digest period, whatever window the
+// search defaults to
+fn saved_searches_section(state: &AppState, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let ids = &state.config.digest.saved_searches;
+    if ids.is_empty() {
+        return SectionContent::Disabled("no saved searches are attached".to_string());
+    }
+
+    let searches = &state.saved_search_manager;
+    ids.iter()
+        .map(|id| {
+            let name = searches.get_search(*id).ok().flatten().map(|s| s.name).unwrap_or_else(|| id.to_string());
+            let count = searches.resolve_filter(&FilterSource::SavedSearch(*id))
+                .and_then(|filter| state.logs_manager.query(&LogFilter {
+                    from: Some(from),
+                    to: Some(to),
+                    limit: None,
+                    ..filter
+                }));
+            match count {
+                Ok(entries) => format!("{}: {} events", name, entries.len()),
+                Err(e) => format!("{}: {}", name, e),
+            }
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn script_failures_section(state: &AppState, settings: &ReportSettings, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let manager = match state.scripts_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return SectionContent::Failed("Failed to acquire lock on scripts manager".to_string()),
+    };
+
+    manager.get_execution_results(None).iter()
+        .filter(|r| !r.success && r.executed_at >= from && r.executed_at <= to)
+        .map(|r| {
+            let name = manager.get_script(r.script_id).map(|s| s.name).unwrap_or_else(|| r.script_id.to_string());
+            format!("[{}] {} by {}{}: {}",
+                    settings.timestamp(r.executed_at),
+                    name,
+                    r.executed_by,
+                    r.target.as_ref().map(|t| format!(" on {}", t)).unwrap_or_default(),
+                    r.error.as_deref().unwrap_or("failed"))
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn printer_supplies_section(state: &AppState, from: DateTime<Utc>, to: DateTime<Utc>) -> SectionContent {
+    let manager = match state.printer_manager.lock() {
+        Ok(manager) => manager,
+        Err(_) => return SectionContent::Failed("Failed to acquire lock on printer manager".to_string()),
+    };
+
+    let printers = manager.get_printers();
+    if printers.is_empty() {
+        return SectionContent::Disabled("no printers are monitored".to_string());
+    }
+
+    printers.iter()
+        .flat_map(|p| p.supplies.iter()
+            .filter(|s| s.low_since.map_or(false, |at| at >= from && at <= to))
+            .map(move |s| format!("{} ({}): {} at {}%{}", p.name, p.location, s.name, s.level,
+                                  if s.status == SupplyStatus::Empty { ", empty" } else { "" })))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn chargeback_section(state: &AppState, settings: &ReportSettings, to: DateTime<Utc>) -> SectionContent {
+    if state.config.chargeback.groups.is_empty() {
+        return SectionContent::Disabled("no accounting groups are configured".to_string());
+    }
+
+    match state.chargeback.month_to_date(to.with_timezone(&settings.timezone).date_naive()) {
+        Ok(lines) => lines.into(),
+        Err(e) => SectionContent::Failed(e.to_string()),
+    }
+}
+
+pub async fn build(state: &AppState, settings: &ReportSettings, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<DigestSection> {
+    let mut sections = vec![
+        DigestSection { heading_key: "digest_alerts", content: alerts_section(state, from, to) },
+        DigestSection { heading_key: "digest_tickets", content: tickets_section(state, from, to) },
+        DigestSection { heading_key: "digest_sla_breaches", content: sla_section(state, settings, from, to) },
+        DigestSection { heading_key: "digest_firewall_changes", content: firewall_section(state, settings, from, to) },
+        DigestSection { heading_key: "digest_link_flaps", content: link_flaps_section(state, from).await },
+        DigestSection { heading_key: "digest_top_sources", content: top_sources_section(state, from, to) },
+        DigestSection { heading_key: "digest_saved_searches", content: saved_searches_section(state, from, to) },
+        DigestSection { heading_key: "digest_script_failures", content: script_failures_section(state, settings, from, to) },
+        DigestSection { heading_key: "digest_printer_supplies", content: printer_supplies_section(state, from, to) },
+    ];
+    // Optional, as only some sites bill traffic back to departments
+    if state.config.digest.chargeback {
+        sections.push(DigestSection { heading_key: "digest_chargeback", content: chargeback_section(state, settings, to) });
+    }
+    sections
+}
+
+fn digest_path(state: &AppState, settings: &ReportSettings, at: DateTime<Utc>) -> PathBuf {
+    let date = at.with_timezone(&settings.timezone).format("%Y-%m-%d");
+    state.paths.reports_dir.join("digests").join(format!("digest-{}.html", date))
+}
+
+// Renders the digest of the 24 hours before `to`, stores it in the reports directory
+// and emails it to the configured recipients
+pub async fn generate_and_send(state: &AppState, to: DateTime<Utc>) -> Result<PathBuf> {
+    let settings = ReportSettings::resolve(&state.config.reports, &ReportOverrides::default(), &state.paths.reports_dir.join("locales"));
+    let from = to - Duration::hours(24);
+
+    let sections = build(state, &settings, from, to).await;
+    let html = reports::digest_report(sections, from, to, &settings).render(&settings, ReportFormat::Html);
+
+    let path = digest_path(state, &settings, to);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create digest directory: {:?}", parent))?;
+    }
+    fs::write(&path, &html)
+        .context(format!("Failed to write digest: {:?}", path))?;
+    info!("Stored daily digest at {:?}", path);
+
+    let recipients = &state.config.digest.recipients;
+    if recipients.is_empty() {
+        warn!("Daily digest has no recipients, it is only stored");
+        return Ok(path);
+    }
+
+    let subject = format!("{} {}", settings.translations.get("daily_digest"),
+                          to.with_timezone(&settings.timezone).format("%Y-%m-%d"));
+    state.notifier.send_html_email(recipients, &subject, &html).await?;
+    Ok(path)
+}
+
+// Once per day, after send_at local time; the stored file marks the day as done, so a
+// restart does not send the digest again
+async fn send_if_due(state: &AppState) -> Result<()> {
+    let settings = ReportSettings::resolve(&state.config.reports, &ReportOverrides::default(), &state.paths.reports_dir.join("locales"));
+    let send_at = NaiveTime::parse_from_str(&state.config.digest.send_at, "%H:%M")
+        .map_err(|_| anyhow!("Invalid digest send_at: {}", state.config.digest.send_at))?;
+
+    let now = Utc::now();
+    if now.with_timezone(&settings.timezone).time() < send_at || digest_path(state, &settings, now).exists() {
+        return Ok(());
+    }
+
+    generate_and_send(state, now).await.map(|_| ())
+}
+
+// Registers the link monitor and, when enabled, the daily digest
+pub fn spawn_tasks(state: &Arc<AppState>) -> Result<()> {
+    let monitor = state.clone();
+    state.task_registry.spawn(LINK_MONITOR_TASK, std::time::Duration::from_secs(30), move || {
+        let state = monitor.clone();
+        async move {
+            let interfaces = state.network_manager.get_interfaces().await?;
+            state.network_manager.record_link_states(&interfaces).await;
+            Ok(())
+        }
+    })?;
+
+    if state.config.digest.enabled {
+        let digest = state.clone();
+        state.task_registry.spawn("daily_digest", std::time::Duration::from_secs(300), move || {
+            let state = digest.clone();
+            async move {
+                send_if_due(&state).await
+            }
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,357 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow};
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::DiskConfig;
+use crate::models::AlertSeverity;
+use crate::paths::disk_space;
+use crate::security::{AuditStatus, SecurityManager};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiskLevel {
+    Ok,
+    Warning,
+    Critical,
+}
+
+// Self-protection applied, in the configured order, once a volume is critical
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtectiveAction {
+    PauseFlowExport,
+    PauseReports,
+    RetentionPurge,
+    RejectUploads,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeStatus {
+    pub name: String,
+    pub path: PathBuf,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    pub free_percent: Option<f64>,
+    pub level: DiskLevel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskStatus {
+    pub level: DiskLevel,
+    pub volumes: Vec<VolumeStatus>,
+    pub active_actions: Vec<ProtectiveAction>,
+    pub last_check: Option<DateTime<Utc>>,
+}
+
+// Frees space by deleting data older than the given age, returns the number of files removed
+type PurgeFn = Arc<dyn Fn(Duration) -> Result<usize> + Send + Sync>;
+
+struct MonitorState {
+    volumes: Vec<VolumeStatus>,
+    level: DiskLevel,
+    active: BTreeSet<ProtectiveAction>,
+    last_check: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct DiskMonitor {
+    config: DiskConfig,
+    volumes: Vec<(String, PathBuf)>,
+    state: Arc<Mutex<MonitorState>>,
+    purgers: Arc<Mutex<Vec<(String, PurgeFn)>>>,
+    alerts_manager: AlertsManager,
+    security_manager: SecurityManager,
+}
+
+impl DiskMonitor {
+    pub fn new(config: DiskConfig,
+               volumes: Vec<(String, PathBuf)>,
+               alerts_manager: AlertsManager,
+               security_manager: SecurityManager) -> Self {
+        Self {
+            config,
+            volumes,
+            state: Arc::new(Mutex::new(MonitorState {
+                volumes: Vec::new(),
+                level: DiskLevel::Ok,
+                active: BTreeSet::new(),
+                last_check: None,
+            })),
+            purgers: Arc::new(Mutex::new(Vec::new())),
+            alerts_manager,
+            security_manager,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_seconds.max(1))
+    }
+
+    pub fn register_purge<F>(&self, name: &str, purge: F) -> Result<()>
+    where
+        F: Fn(Duration) -> Result<usize> + Send + Sync + 'static,
+    {
+        match self.purgers.lock() {
+            Ok(mut purgers) => {
+                purgers.push((name.to_string(), Arc::new(purge)));
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on disk purgers")),
+        }
+    }
+
+    // Writers and upload handlers check this before touching the disk
+    pub fn is_active(&self, action: ProtectiveAction) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.active.contains(&action),
+            Err(_) => false,
+        }
+    }
+
+    fn level_for(&self, free_percent: f64) -> DiskLevel {
+        if free_percent <= self.config.critical_free_percent {
+            DiskLevel::Critical
+        } else if free_percent <= self.config.warning_free_percent {
+            DiskLevel::Warning
+        } else {
+            DiskLevel::Ok
+        }
+    }
+
+    // Samples every volume and moves the protection state on level changes
+    pub fn check(&self) -> Result<()> {
+        let volumes: Vec<VolumeStatus> = self.volumes.iter()
+            .map(|(name, path)| {
+                let space = disk_space(path);
+                let free_percent = space
+                    .filter(|(_, total)| *total > 0)
+                    .map(|(free, total)| free as f64 * 100.0 / total as f64);
+
+                VolumeStatus {
+                    name: name.clone(),
+                    path: path.clone(),
+                    free_bytes: space.map(|(free, _)| free),
+                    total_bytes: space.map(|(_, total)| total),
+                    free_percent,
+                    level: free_percent.map_or(DiskLevel::Ok, |p| self.level_for(p)),
+                }
+            })
+            .collect();
+
+        self.apply(volumes)
+    }
+
+    fn apply(&self, volumes: Vec<VolumeStatus>) -> Result<()> {
+        let level = volumes.iter().map(|v| v.level).max().unwrap_or(DiskLevel::Ok);
+        let summary = volumes.iter()
+            .filter(|v| v.level != DiskLevel::Ok)
+            .map(|v| format!("{} ({}) {:.1}% free", v.name, v.path.display(), v.free_percent.unwrap_or(0.0)))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let previous = match self.state.lock() {
+            Ok(mut state) => {
+                let previous = state.level;
+                state.volumes = volumes;
+                state.level = level;
+                state.last_check = Some(Utc::now());
+                previous
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on disk monitor")),
+        };
+
+        if level > previous {
+            let severity = match level {
+                DiskLevel::Critical => AlertSeverity::Critical,
+                _ => AlertSeverity::Medium,
+            };
+
+            if let Err(e) = self.alerts_manager.create_alert(
+                severity,
+                format!("Disk space {:?}", level),
+                format!("Low free space: {}", summary),
+                "disk_monitor".to_string(),
+                Vec::new(),
+            ) {
+                error!("Failed to raise disk space alert: {}", e);
+            }
+        }
+
+        if level == DiskLevel::Critical && previous != DiskLevel::Critical {
+            self.protect(&summary)?;
+        } else if level != DiskLevel::Critical && previous == DiskLevel::Critical {
+            self.release()?;
+        }
+
+        Ok(())
+    }
+
+    fn protect(&self, summary: &str) -> Result<()> {
+        for action in &self.config.protective_actions {
+            warn!("Disk space critical, applying protective action {:?}", action);
+
+            if *action == ProtectiveAction::RetentionPurge {
+                self.purge();
+            }
+
+            match self.state.lock() {
+                Ok(mut state) => {
+                    state.active.insert(*action);
+                },
+                Err(_) => return Err(anyhow!("Failed to acquire lock on disk monitor")),
+            }
+        }
+
+        self.security_manager.log_audit_event(
+            "system",
+            "disk_protection_activated",
+            "disk",
+            AuditStatus::Warning,
+            Some(format!("{} ({:?})", summary, self.config.protective_actions)),
+        );
+
+        Ok(())
+    }
+
+    // Space was freed: resume paused writers and accept uploads again
+    fn release(&self) -> Result<()> {
+        let released = match self.state.lock() {
+            Ok(mut state) => std::mem::take(&mut state.active),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on disk monitor")),
+        };
+
+        info!("Disk space recovered, releasing protective actions {:?}", released);
+        self.security_manager.log_audit_event(
+            "system",
+            "disk_protection_released",
+            "disk",
+            AuditStatus::Success,
+            Some(format!("Resumed {:?}", released)),
+        );
+
+        Ok(())
+    }
+
+    fn purge(&self) {
+        let purgers = match self.purgers.lock() {
+            Ok(purgers) => purgers.clone(),
+            Err(_) => {
+                error!("Failed to acquire lock on disk purgers");
+                return;
+            },
+        };
+
+        let max_age = Duration::from_secs(self.config.emergency_retention_hours * 60 * 60);
+
+        for (name, purge) in purgers {
+            match purge(max_age) {
+                Ok(removed) => info!("Emergency purge of {} removed {} files", name, removed),
+                Err(e) => error!("Emergency purge of {} failed: {}", name, e),
+            }
+        }
+    }
+
+    pub fn status(&self) -> Result<DiskStatus> {
+        match self.state.lock() {
+            Ok(state) => Ok(DiskStatus {
+                level: state.level,
+                volumes: state.volumes.clone(),
+                active_actions: state.active.iter().copied().collect(),
+                last_check: state.last_check,
+            }),
+            Err(_) => Err(anyhow!("Failed to acquire lock on disk monitor")),
+        }
+    }
+
+    // Prometheus text exposition of the last sample
+    pub fn render_metrics(&self) -> Result<String> {
+        let status = self.status()?;
+        let mut out = String::new();
+
+        out.push_str("# HELP siem_disk_free_bytes Free bytes on the volume holding a data directory\n");
+        out.push_str("# TYPE siem_disk_free_bytes gauge\n");
+        for volume in &status.volumes {
+            if let Some(free) = volume.free_bytes {
+                out.push_str(&format!("siem_disk_free_bytes{{volume=\"{}\"}} {}\n", volume.name, free));
+            }
+        }
+
+        out.push_str("# HELP siem_disk_total_bytes Size of the volume holding a data directory\n");
+        out.push_str("# TYPE siem_disk_total_bytes gauge\n");
+        for volume in &status.volumes {
+            if let Some(total) = volume.total_bytes {
+                out.push_str(&format!("siem_disk_total_bytes{{volume=\"{}\"}} {}\n", volume.name, total));
+            }
+        }
+
+        out.push_str("# HELP siem_disk_protection_active Whether disk self-protection is engaged\n");
+        out.push_str("# TYPE siem_disk_protection_active gauge\n");
+        out.push_str(&format!("siem_disk_protection_active {}\n", if status.active_actions.is_empty() { 0 } else { 1 }));
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn volume(level: DiskLevel) -> VolumeStatus {
+        VolumeStatus {
+            name: "logs".to_string(),
+            path: PathBuf::from("/var/log/siem"),
+            free_bytes: Some(1),
+            total_bytes: Some(100),
+            free_percent: Some(1.0),
+            level,
+        }
+    }
+
+    fn monitor(dir: &tempfile::TempDir) -> DiskMonitor {
+        DiskMonitor::new(
+            DiskConfig::default(),
+            Vec::new(),
+            AlertsManager::new(dir.path().join("alerts").to_str().unwrap()).unwrap(),
+            SecurityManager::new([7u8; 32], dir.path().join("audit").to_str().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn critical_level_applies_and_recovery_releases_the_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let monitor = monitor(&dir);
+        let purged = Arc::new(AtomicUsize::new(0));
+        for name in ["evidence", "graph_snapshots"] {
+            let purged = purged.clone();
+            monitor.register_purge(name, move |max_age| {
+                assert_eq!(max_age, Duration::from_secs(24 * 60 * 60));
+                purged.fetch_add(1, Ordering::SeqCst);
+                Ok(0)
+            }).unwrap();
+        }
+
+        monitor.apply(vec![volume(DiskLevel::Warning)]).unwrap();
+        assert!(!monitor.is_active(ProtectiveAction::PauseReports));
+        assert_eq!(purged.load(Ordering::SeqCst), 0);
+
+        monitor.apply(vec![volume(DiskLevel::Critical)]).unwrap();
+        for action in [ProtectiveAction::PauseFlowExport, ProtectiveAction::PauseReports, ProtectiveAction::RejectUploads] {
+            assert!(monitor.is_active(action), "{:?}", action);
+        }
+        assert_eq!(purged.load(Ordering::SeqCst), 2);
+
+        // Still critical: nothing is purged again
+        monitor.apply(vec![volume(DiskLevel::Critical)]).unwrap();
+        assert_eq!(purged.load(Ordering::SeqCst), 2);
+
+        monitor.apply(vec![volume(DiskLevel::Ok)]).unwrap();
+        assert!(monitor.status().unwrap().active_actions.is_empty());
+        assert_eq!(monitor.alerts_manager.get_all_alerts().unwrap().len(), 2);
+    }
+}
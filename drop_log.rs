@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use chrono::Utc;
+use uuid::Uuid;
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+
+use crate::config::DropLogConfig;
+use crate::ingestion::IngestionPipeline;
+use crate::models::{EventCategory, LogEntry, LogParser, LogSeverity};
+use crate::network::{DROP_LOG_CHAINS, DROP_LOG_PREFIX};
+
+// A /dev/kmsg record is at most this long
+const RECORD_BYTES: usize = 8192;
+
+// A packet dropped by a zone's drop log rule, as the kernel logged it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedPacket {
+    pub chain: String,
+    pub zone: String,
+    pub in_interface: Option<String>,
+    pub out_interface: Option<String>,
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    // Lowercase, e.g. tcp, udp, icmp
+    pub proto: Option<String>,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
+}
+
+// Reads a kernel log line of the form
+// `siem-drop-<chain>-<zone> IN=eth0 OUT= MAC=... SRC=... DST=... PROTO=TCP SPT=... DPT=...`;
+// None for any other line
+pub fn parse(line: &str) -> Option<DroppedPacket> {
+    let start = line.find(DROP_LOG_PREFIX)?;
+    let (label, fields) = line[start + DROP_LOG_PREFIX.len()..].split_once(' ')?;
+    // Chains have no dash, zones may
+    let (chain, zone) = label.split_once('-')?;
+    if !DROP_LOG_CHAINS.contains(&chain) || zone.is_empty() {
+        return None;
+    }
+
+    let mut packet = DroppedPacket {
+        chain: chain.to_string(),
+        zone: zone.to_string(),
+        in_interface: None,
+        out_interface: None,
+        src: None,
+        dst: None,
+        proto: None,
+        sport: None,
+        dport: None,
+    };
+
+    for field in fields.split_whitespace() {
+        let Some((key, value)) = field.split_once('=') else { continue };
+        let value = Some(value.to_string()).filter(|v| !v.is_empty());
+        match key {
+            "IN" => packet.in_interface = value,
+            "OUT" => packet.out_interface = value,
+            "SRC" => packet.src = value,
+            "DST" => packet.dst = value,
+            "PROTO" => packet.proto = value.map(|p| p.to_lowercase()),
+            "SPT" => packet.sport = value.and_then(|v| v.parse().ok()),
+            "DPT" => packet.dport = value.and_then(|v| v.parse().ok()),
+            _ => {},
+        }
+    }
+
+    Some(packet)
+}
+
+fn endpoint(address: &Option<String>, port: Option<u16>) -> String {
+    let address = address.as_deref().unwrap_or("?");
+    match port {
+        Some(port) if address.contains(':') => format!("[{}]:{}", address, port),
+        Some(port) => format!("{}:{}", address, port),
+        None => address.to_string(),
+    }
+}
+
+// The fields go into tags (`chain:input`, `src:10.0.0.5`, `dport:22`, ...) so the log
+// query can filter on them; the host is the sender of the packet
+pub fn to_entry(packet: &DroppedPacket, raw: &str) -> LogEntry {
+    let proto = packet.proto.as_deref().unwrap_or("ip");
+    let mut tags = vec![
+        "firewall-drop".to_string(),
+        format!("chain:{}", packet.chain),
+        format!("zone:{}", packet.zone),
+        format!("proto:{}", proto),
+    ];
+    let optional = [
+        ("in", packet.in_interface.clone()),
+        ("out", packet.out_interface.clone()),
+        ("src", packet.src.clone()),
+        ("dst", packet.dst.clone()),
+        ("sport", packet.sport.map(|p| p.to_string())),
+        ("dport", packet.dport.map(|p| p.to_string())),
+    ];
+    tags.extend(optional.into_iter().filter_map(|(name, value)| value.map(|v| format!("{}:{}", name, v))));
+
+    let message = format!(
+        "Dropped {} {} -> {} in {} ({}) from zone {}",
+        proto.to_uppercase(),
+        endpoint(&packet.src, packet.sport),
+        endpoint(&packet.dst, packet.dport),
+        packet.chain,
+        packet.in_interface.as_deref().unwrap_or("no interface"),
+        packet.zone,
+    );
+
+    LogEntry {
+        id: Uuid::new_v4(),
+        timestamp: Utc::now(),
+        source: "firewall".to_string(),
+        event_type: "firewall:drop".to_string(),
+        severity: LogSeverity::Info,
+        message,
+        raw_data: raw.to_string(),
+        host: packet.src.clone(),
+        user: None,
+        application: Some("nftables".to_string()),
+        tags,
+        category: EventCategory::NetworkTraffic,
+        hostname: None,
+        site_id: None,
+        parser: LogParser::DropLog,
+    }
+}
+
+// One read returns one record, `<priority>,<sequence>,<usec>,<flags>;<message>` followed
+// by continuation lines
+fn follow(path: &str, pipeline: &IngestionPipeline) -> Result<()> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path))?;
+    // Drops logged before startup were ingested by the previous run, or are too old to matter
+    file.seek(SeekFrom::End(0)).context(format!("Failed to seek to the end of {}", path))?;
+    info!("Reading dropped packets from {}", path);
+
+    let mut buffer = vec![0u8; RECORD_BYTES];
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            // Records were overwritten before they could be read
+            Err(e) if e.kind() == ErrorKind::BrokenPipe || e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context(format!("Failed to read {}", path)),
+        };
+
+        let record = String::from_utf8_lossy(&buffer[..read]);
+        let Some((_, message)) = record.split_once(';') else { continue };
+        let message = message.lines().next().unwrap_or_default();
+        if let Some(packet) = parse(message) {
+            if let Err(e) = pipeline.ingest(to_entry(&packet, message)) {
+                warn!("Failed to store dropped packet: {}", e);
+            }
+        }
+    }
+}
+
+// Follows the kernel log until it can no longer be read, e.g. without CAP_SYSLOG
+pub async fn run(config: DropLogConfig, pipeline: IngestionPipeline) {
+    let path = config.kmsg_path.clone();
+    match tokio::task::spawn_blocking(move || follow(&config.kmsg_path, &pipeline)).await {
+        Ok(Ok(())) => info!("Kernel log {} closed, no longer reading dropped packets", path),
+        Ok(Err(e)) => warn!("Dropped packets are not ingested: {:#}", e),
+        Err(e) => warn!("Dropped packet reader stopped: {}", e),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_drops_are_parsed() {
+        let line = "6,1234,5678,-;siem-drop-input-guest-wifi IN=wlan0 OUT= MAC=aa:bb:cc:dd:ee:ff:00:11:22:33:44:55:08:00 \
+                    SRC=192.168.50.23 DST=192.168.50.1 LEN=60 TOS=0x00 TTL=64 ID=4242 DF PROTO=TCP SPT=51514 DPT=22 WINDOW=64240 SYN";
+        let packet = parse(line).unwrap();
+        assert_eq!(packet, DroppedPacket {
+            chain: "input".to_string(),
+            zone: "guest-wifi".to_string(),
+            in_interface: Some("wlan0".to_string()),
+            out_interface: None,
+            src: Some("192.168.50.23".to_string()),
+            dst: Some("192.168.50.1".to_string()),
+            proto: Some("tcp".to_string()),
+            sport: Some(51514),
+            dport: Some(22),
+        });
+
+        let entry = to_entry(&packet, line);
+        assert_eq!(entry.message, "Dropped TCP 192.168.50.23:51514 -> 192.168.50.1:22 in input (wlan0) from zone guest-wifi");
+        assert!(entry.tags.contains(&"dport:22".to_string()));
+        assert!(!entry.tags.iter().any(|t| t.starts_with("out:")));
+    }
+
+    #[test]
+    fn ipv6_drops_are_parsed() {
+        let line = "siem-drop-forward-lan IN=eth1 OUT=eth0 SRC=fd00:0000:0000:0000:0000:0000:0000:0005 \
+                    DST=2001:0db8:0000:0000:0000:0000:0000:0001 LEN=80 TC=0 HOPLIMIT=63 PROTO=UDP SPT=5353 DPT=53 LEN=40";
+        let packet = parse(line).unwrap();
+        assert_eq!(packet.chain, "forward");
+        assert_eq!(packet.out_interface.as_deref(), Some("eth0"));
+        assert_eq!(packet.src.as_deref(), Some("fd00:0000:0000:0000:0000:0000:0000:0005"));
+        assert_eq!((packet.sport, packet.dport), (Some(5353), Some(53)));
+        assert!(to_entry(&packet, line).message.contains("[2001:0db8:0000:0000:0000:0000:0000:0001]:53"));
+    }
+
+    #[test]
+    fn missing_fields_are_left_empty() {
+        let packet = parse("siem-drop-input-wan IN=eth0 SRC=203.0.113.9 DST=198.51.100.1 PROTO=ICMP TYPE=8 CODE=0").unwrap();
+        assert_eq!(packet.proto.as_deref(), Some("icmp"));
+        assert_eq!((packet.sport, packet.dport, packet.out_interface), (None, None, None));
+
+        let packet = parse("siem-drop-input-wan SPT=notaport DPT=70000").unwrap();
+        assert_eq!((packet.src.as_deref(), packet.sport, packet.dport), (None, None, None));
+        assert_eq!(to_entry(&packet, "").message, "Dropped IP ? -> ? in input (no interface) from zone wan");
+    }
+
+    #[test]
+    fn other_lines_are_ignored() {
+        for line in [
+            "",
+            "usb 1-1: new high-speed USB device number 2",
+            "siem-drop-input-wan",
+            "siem-drop-output-wan IN=eth0 SRC=10.0.0.1",
+            "siem-drop-input- IN=eth0",
+            "siem-drop-input IN=eth0",
+        ] {
+            assert_eq!(parse(line), None, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use reqwest::Url;
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::models::{Alert, AlertSeverity, AlertStatus, NotificationAttempt};
+use crate::notifications::Notifier;
+use crate::users::UserManager;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifyTarget {
+    User { username: String },
+    Role { role: String },
+    Webhook { url: String },
+}
+
+impl NotifyTarget {
+    fn describe(&self) -> String {
+        match self {
+            NotifyTarget::User { username } => format!("user:{}", username),
+            NotifyTarget::Role { role } => format!("role:{}", role),
+            NotifyTarget::Webhook { url } => format!("webhook:{}", url),
+        }
+    }
+}
+
+// Cloud instance metadata services outside the link-local range
+const METADATA_ADDRESSES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(100, 100, 100, 200)),
+    IpAddr::V6(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)),
+];
+const METADATA_HOSTS: [&str; 2] = ["metadata", "metadata.google.internal"];
+
+// Webhooks must not reach the host itself or the metadata service of the cloud it runs in
+fn blocked_address(ip: IpAddr) -> bool {
+    if METADATA_ADDRESSES.contains(&ip) {
+        return true;
+    }
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_link_local() || v4.is_unspecified() || v4.is_broadcast(),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => blocked_address(IpAddr::V4(v4)),
+            None => v6.is_loopback() || v6.is_unspecified() || (v6.segments()[0] & 0xffc0) == 0xfe80,
+        },
+    }
+}
+
+// An http(s) URL whose host is neither a blocked address nor a name for one
+fn check_webhook_url(url: &str) -> Result<Url> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid webhook URL {}: {}", url, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(anyhow!("Webhook URL {} must use http or https", url));
+    }
+
+    let host = match parsed.host_str() {
+        Some(host) if !host.is_empty() => host.trim_start_matches('[').trim_end_matches(']'),
+        _ => return Err(anyhow!("Webhook URL {} has no host", url)),
+    };
+    let blocked = match host.parse::<IpAddr>() {
+        Ok(ip) => blocked_address(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost") || METADATA_HOSTS.contains(&domain.as_str())
+        },
+    };
+    if blocked {
+        return Err(anyhow!("Webhook URL {} points at a local or metadata address", url));
+    }
+    Ok(parsed)
+}
+
+// Checked again before every delivery, as the name may resolve differently than it did
+// when the policy was saved
+async fn check_webhook_target(url: &str) -> Result<()> {
+    let parsed = check_webhook_url(url)?;
+    let (Some(host), Some(port)) = (parsed.host_str(), parsed.port_or_known_default()) else {
+        return Err(anyhow!("Webhook URL {} has no host", url));
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses = tokio::net::lookup_host((host, port)).await
+        .with_context(|| format!("Failed to resolve webhook host {}", host))?;
+    for address in addresses {
+        if blocked_address(address.ip()) {
+            return Err(anyhow!("Webhook host {} resolves to the blocked address {}", host, address.ip()));
+        }
+    }
+    Ok(())
+}
+
+// Notifies the targets, then waits; repeats the notification `repeat` more times
+// before moving on to the next step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationStep {
+    pub targets: Vec<NotifyTarget>,
+    pub wait_minutes: u32,
+    #[serde(default)]
+    pub repeat: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationPolicy {
+    pub id: Uuid,
+    pub name: String,
+    // Alert sources and severities this policy covers, empty means any
+    pub sources: Vec<String>,
+    pub severities: Vec<AlertSeverity>,
+    pub steps: Vec<EscalationStep>,
+    pub enabled: bool,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl EscalationPolicy {
+    fn applies_to(&self, alert: &Alert) -> bool {
+        self.enabled
+            && alert.created_at >= self.created_at
+            && (self.sources.is_empty() || self.sources.iter().any(|s| *s == alert.source))
+            && (self.severities.is_empty() || self.severities.contains(&alert.severity))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EscalationPolicyFields {
+    pub name: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub severities: Vec<AlertSeverity>,
+    pub steps: Vec<EscalationStep>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+// Progress of one running escalation, persisted so it resumes after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationState {
+    pub alert_id: Uuid,
+    pub policy_id: Uuid,
+    pub step: usize,
+    // Notifications already sent for the current step
+    pub sent: u32,
+    pub next_at: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct EscalationEngine {
+    policies_dir: PathBuf,
+    state_file: PathBuf,
+    policies: Arc<Mutex<HashMap<Uuid, EscalationPolicy>>>,
+    active: Arc<Mutex<HashMap<Uuid, EscalationState>>>,
+    alerts: AlertsManager,
+    users: UserManager,
+    notifier: Notifier,
+}
+
+impl EscalationEngine {
+    pub fn new(escalations_dir: &str,
+               alerts: AlertsManager,
+               users: UserManager,
+               notifier: Notifier) -> Result<Self> {
+        let policies_dir = PathBuf::from(escalations_dir).join("policies");
+        let state_file = PathBuf::from(escalations_dir).join("active.json");
+
+        if !policies_dir.exists() {
+            fs::create_dir_all(&policies_dir)
+                .context(format!("Failed to create escalation policies directory: {:?}", policies_dir))?;
+            info!("Created escalation policies directory: {:?}", policies_dir);
+        }
+
+        let mut policies = HashMap::new();
+
+        for entry in fs::read_dir(&policies_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read escalation policy file: {:?}", path))?;
+            match serde_json::from_str::<EscalationPolicy>(&contents) {
+                Ok(policy) => {
+                    policies.insert(policy.id, policy);
+                },
+                Err(e) => warn!("Skipping invalid escalation policy file {:?}: {}", path, e),
+            }
+        }
+
+        let active: HashMap<Uuid, EscalationState> = if state_file.exists() {
+            let contents = fs::read_to_string(&state_file)
+                .context(format!("Failed to read escalation state: {:?}", state_file))?;
+            match serde_json::from_str::<Vec<EscalationState>>(&contents) {
+                Ok(states) => states.into_iter().map(|s| (s.alert_id, s)).collect(),
+                Err(e) => {
+                    warn!("Ignoring invalid escalation state {:?}: {}", state_file, e);
+                    HashMap::new()
+                },
+            }
+        } else {
+            HashMap::new()
+        };
+
+        info!("Loaded {} escalation policies, resuming {} escalations", policies.len(), active.len());
+
+        Ok(Self {
+            policies_dir,
+            state_file,
+            policies: Arc::new(Mutex::new(policies)),
+            active: Arc::new(Mutex::new(active)),
+            alerts,
+            users,
+            notifier,
+        })
+    }
+
+    fn save_policy(&self, policy: &EscalationPolicy) -> Result<()> {
+        let path = self.policies_dir.join(format!("{}.json", policy.id));
+        let json = serde_json::to_string_pretty(policy)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write escalation policy file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn save_state(&self, active: &HashMap<Uuid, EscalationState>) -> Result<()> {
+        let states: Vec<&EscalationState> = active.values().collect();
+        let json = serde_json::to_string_pretty(&states)?;
+        fs::write(&self.state_file, json)
+            .context(format!("Failed to write escalation state: {:?}", self.state_file))?;
+        Ok(())
+    }
+
+    fn validate(fields: &EscalationPolicyFields) -> Result<()> {
+        if fields.name.trim().is_empty() {
+            return Err(anyhow!("Policy name cannot be empty"));
+        }
+
+        if fields.steps.is_empty() {
+            return Err(anyhow!("Policy needs at least one step"));
+        }
+
+        for (index, step) in fields.steps.iter().enumerate() {
+            if step.targets.is_empty() {
+                return Err(anyhow!("Step {} has no notification targets", index + 1));
+            }
+
+            for target in &step.targets {
+                if let NotifyTarget::Webhook { url } = target {
+                    check_webhook_url(url)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn create_policy(&self, fields: EscalationPolicyFields, created_by: &str) -> Result<EscalationPolicy> {
+        Self::validate(&fields)?;
+
+        let now = Utc::now();
+        let policy = EscalationPolicy {
+            id: Uuid::new_v4(),
+            name: fields.name,
+            sources: fields.sources,
+            severities: fields.severities,
+            steps: fields.steps,
+            enabled: fields.enabled,
+            created_by: created_by.to_string(),
+            created_at: now,
+            updated_at: now,
+        };
+
+        match self.policies.lock() {
+            Ok(mut policies) => {
+                self.save_policy(&policy)?;
+                policies.insert(policy.id, policy.clone());
+                Ok(policy)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalation policies")),
+        }
+    }
+
+    pub fn update_policy(&self, id: Uuid, fields: EscalationPolicyFields) -> Result<EscalationPolicy> {
+        Self::validate(&fields)?;
+
+        match self.policies.lock() {
+            Ok(mut policies) => {
+                let policy = policies.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Escalation policy not found: {}", id))?;
+
+                policy.name = fields.name;
+                policy.sources = fields.sources;
+                policy.severities = fields.severities;
+                policy.steps = fields.steps;
+                policy.enabled = fields.enabled;
+                policy.updated_at = Utc::now();
+
+                let policy = policy.clone();
+                self.save_policy(&policy)?;
+                Ok(policy)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalation policies")),
+        }
+    }
+
+    pub fn get_policy(&self, id: Uuid) -> Result<EscalationPolicy> {
+        match self.policies.lock() {
+            Ok(policies) => {
+                policies.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Escalation policy not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalation policies")),
+        }
+    }
+
+    pub fn get_all_policies(&self) -> Result<Vec<EscalationPolicy>> {
+        match self.policies.lock() {
+            Ok(policies) => {
+                let mut all: Vec<EscalationPolicy> = policies.values().cloned().collect();
+                all.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalation policies")),
+        }
+    }
+
+    // Running escalations of the policy end with it
+    pub fn delete_policy(&self, id: Uuid) -> Result<()> {
+        match self.policies.lock() {
+            Ok(mut policies) => {
+                if policies.remove(&id).is_none() {
+                    return Err(anyhow!("Escalation policy not found: {}", id));
+                }
+
+                let path = self.policies_dir.join(format!("{}.json", id));
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .context(format!("Failed to delete escalation policy file: {:?}", path))?;
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on escalation policies")),
+        }
+
+        match self.active.lock() {
+            Ok(mut active) => {
+                active.retain(|_, state| state.policy_id != id);
+                self.save_state(&active)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalations")),
+        }
+    }
+
+    pub fn get_active(&self) -> Result<Vec<EscalationState>> {
+        match self.active.lock() {
+            Ok(active) => {
+                let mut all: Vec<EscalationState> = active.values().cloned().collect();
+                all.sort_by(|a, b| a.next_at.cmp(&b.next_at));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalations")),
+        }
+    }
+
+    // Called on acknowledgement, no further notifications go out for the alert
+    pub fn stop(&self, alert_id: Uuid) -> Result<()> {
+        match self.active.lock() {
+            Ok(mut active) => {
+                if active.remove(&alert_id).is_some() {
+                    info!("Stopped escalation of alert {}", alert_id);
+                    self.save_state(&active)?;
+                }
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalations")),
+        }
+    }
+
+    // Starts escalations for new alerts and sends every notification that is due
+    pub async fn tick(&self) -> Result<()> {
+        let now = Utc::now();
+        let alerts = self.alerts.get_all_alerts()?;
+        let policies = self.get_all_policies()?;
+
+        // Work out what to send while holding the lock, send after releasing it
+        let due: Vec<(Alert, EscalationPolicy, EscalationState)> = match self.active.lock() {
+            Ok(mut active) => {
+                for alert in &alerts {
+                    // Alerts that already went through an escalation are not started again
+                    if alert.status != AlertStatus::New
+                        || !alert.notifications.is_empty()
+                        || active.contains_key(&alert.id) {
+                        continue;
+                    }
+
+                    if let Some(policy) = policies.iter().find(|p| p.applies_to(alert)) {
+                        active.insert(alert.id, EscalationState {
+                            alert_id: alert.id,
+                            policy_id: policy.id,
+                            step: 0,
+                            sent: 0,
+                            next_at: now,
+                            started_at: now,
+                        });
+                        info!("Escalating alert {} with policy {}", alert.id, policy.name);
+                    }
+                }
+
+                // Acknowledged or resolved alerts and deleted policies end the escalation
+                active.retain(|id, state| {
+                    alerts.iter().any(|a| a.id == *id && a.status == AlertStatus::New)
+                        && policies.iter().any(|p| p.id == state.policy_id)
+                });
+                self.save_state(&active)?;
+
+                active.values()
+                    .filter(|state| state.next_at <= now)
+                    .filter_map(|state| {
+                        let alert = alerts.iter().find(|a| a.id == state.alert_id)?;
+                        let policy = policies.iter().find(|p| p.id == state.policy_id)?;
+                        Some((alert.clone(), policy.clone(), state.clone()))
+                    })
+                    .collect()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on escalations")),
+        };
+
+        for (alert, policy, state) in due {
+            let step = match policy.steps.get(state.step) {
+                Some(step) => step,
+                None => {
+                    self.stop(alert.id)?;
+                    continue;
+                },
+            };
+
+            // The alert may have been acknowledged while earlier notifications were sent
+            if !self.is_running(alert.id)? {
+                continue;
+            }
+
+            for target in &step.targets {
+                let result = self.notify(target, &alert, &policy, state.step).await;
+                if let Err(e) = &result {
+                    warn!("Escalation notification for alert {} to {} failed: {}", alert.id, target.describe(), e);
+                }
+
+                self.alerts.record_notification(alert.id, NotificationAttempt {
+                    at: Utc::now(),
+                    policy_id: policy.id,
+                    step: state.step,
+                    target: target.describe(),
+                    success: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                })?;
+            }
+
+            self.advance(&policy, state)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_running(&self, alert_id: Uuid) -> Result<bool> {
+        match self.active.lock() {
+            Ok(active) => Ok(active.contains_key(&alert_id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalations")),
+        }
+    }
+
+    fn advance(&self, policy: &EscalationPolicy, state: EscalationState) -> Result<()> {
+        match self.active.lock() {
+            Ok(mut active) => {
+                // Acknowledged while notifying
+                let current = match active.get_mut(&state.alert_id) {
+                    Some(current) => current,
+                    None => return Ok(()),
+                };
+
+                let step = &policy.steps[state.step];
+                current.next_at = Utc::now() + Duration::minutes(step.wait_minutes as i64);
+
+                if state.sent < step.repeat {
+                    current.sent = state.sent + 1;
+                } else if state.step + 1 < policy.steps.len() {
+                    current.step = state.step + 1;
+                    current.sent = 0;
+                } else {
+                    info!("Escalation of alert {} exhausted policy {}", state.alert_id, policy.name);
+                    active.remove(&state.alert_id);
+                }
+
+                self.save_state(&active)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on escalations")),
+        }
+    }
+
+    async fn notify(&self, target: &NotifyTarget, alert: &Alert, policy: &EscalationPolicy, step: usize) -> Result<()> {
+        let subject = format!("[{:?}] {}", alert.severity, alert.title);
+        let body = format!(
+            "Alert {} from {} has not been acknowledged.\n\n{}\n\nEscalation policy: {} (step {} of {})\nRaised at: {}\n",
+            alert.id,
+            alert.source,
+            alert.description,
+            policy.name,
+            step + 1,
+            policy.steps.len(),
+            alert.created_at.to_rfc3339(),
+        );
+
+        match target {
+            NotifyTarget::User { username } => {
+                let user = self.users.get_user(username)?;
+                if !user.is_active {
+                    return Err(anyhow!("User {} is disabled", username));
+                }
+                self.notifier.send_email(&[user.email], &subject, &body).await
+            },
+            NotifyTarget::Role { role } => {
+                let recipients: Vec<String> = self.users.get_all_users()?
+                    .into_iter()
+                    .filter(|u| u.is_active && u.role.role_name() == role.to_lowercase())
+                    .map(|u| u.email)
+                    .collect();
+                if recipients.is_empty() {
+                    return Err(anyhow!("No active users with role {}", role));
+                }
+                self.notifier.send_email(&recipients, &subject, &body).await
+            },
+            NotifyTarget::Webhook { url } => {
+                let payload = serde_json::json!({
+                    "alert": alert,
+                    "policy_id": policy.id,
+                    "policy": policy.name,
+                    "step": step + 1,
+                });
+                check_webhook_target(url).await?;
+                self.notifier.post_webhook(url, &payload).await
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhooks_to_local_and_metadata_addresses_are_refused() {
+        for url in [
+            "ftp://hooks.example.com/alert",
+            "file:///etc/passwd",
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://api.localhost./hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://metadata.google.internal/computeMetadata/v1/",
+            "http://100.100.100.200/latest/meta-data/",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://[fd00:ec2::254]/latest/meta-data/",
+            "http://0.0.0.0/hook",
+        ] {
+            assert!(check_webhook_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn webhooks_to_other_hosts_are_accepted() {
+        for url in ["https://hooks.example.com/alert", "http://10.0.0.5:8080/hook", "https://[2001:db8::1]/hook"] {
+            assert!(check_webhook_url(url).is_ok(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::EvidenceConfig;
+use crate::logs::{LogFilter, LogsManager};
+use crate::models::{Alert, LogEntry};
+use crate::request_trace;
+use crate::security::{AuditEvent, SecurityManager};
+use crate::tickets::{Ticket, TicketsManager};
+
+// Time range and scope of a package. With no hosts, users or alerts given, everything
+// in the range is included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub alert_ids: Vec<Uuid>,
+}
+
+impl EvidenceQuery {
+    fn unscoped(&self) -> bool {
+        self.hosts.is_empty() && self.users.is_empty() && self.alert_ids.is_empty()
+    }
+
+    fn mentions_scope(&self, text: &str) -> bool {
+        self.hosts.iter().any(|h| text.contains(h.as_str()))
+            || self.users.iter().any(|u| text.contains(u.as_str()))
+            || self.alert_ids.iter().any(|id| text.contains(&id.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum EvidenceStatus {
+    Running,
+    Completed,
+    Failed,
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceJob {
+    pub id: Uuid,
+    pub query: EvidenceQuery,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    pub status: EvidenceStatus,
+    // 0-100
+    pub progress: u8,
+    // What is being collected while running
+    pub stage: String,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub file_size: Option<u64>,
+    // SHA-256 of the archive
+    pub sha256: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ManifestEntry {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+// The statement of what the package holds, signed through the manifest
+#[derive(Debug, Clone, Serialize)]
+struct Summary<'a> {
+    package_id: Uuid,
+    generated_at: DateTime<Utc>,
+    generated_by: &'a str,
+    query: &'a EvidenceQuery,
+    logs: usize,
+    audit_events: usize,
+    alerts: usize,
+    tickets: usize,
+    signature_algorithm: &'static str,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+// Adds the manifest of every file so far, with its size and SHA-256, and the signature
+// of the manifest; changing or dropping any file breaks one or the other
+fn seal(security: &SecurityManager, files: &mut Vec<(&str, Vec<u8>)>) -> Result<()> {
+    let manifest: Vec<ManifestEntry> = files.iter()
+        .map(|(path, data)| ManifestEntry {
+            path: path.to_string(),
+            size: data.len() as u64,
+            sha256: sha256_hex(data),
+        })
+        .collect();
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let signature = security.sign(&manifest);
+    files.push(("manifest.json", manifest));
+    files.push(("manifest.sig", format!("{}\n", signature).into_bytes()));
+    Ok(())
+}
+
+fn to_jsonl<T: Serialize>(items: &[T]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut out, item)?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+// Builds evidence packages in the background. Archives are readable by the service
+// user only and deleted once they expire.
+#[derive(Clone)]
+pub struct EvidenceManager {
+    dir: PathBuf,
+    config: EvidenceConfig,
+    jobs: Arc<Mutex<HashMap<Uuid, EvidenceJob>>>,
+    logs: LogsManager,
+    alerts: AlertsManager,
+    tickets: TicketsManager,
+    security: SecurityManager,
+}
+
+impl EvidenceManager {
+    pub fn new(dir: &str,
+               config: EvidenceConfig,
+               logs: LogsManager,
+               alerts: AlertsManager,
+               tickets: TicketsManager,
+               security: SecurityManager) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create evidence directory: {:?}", dir))?;
+            info!("Created evidence directory: {:?}", dir);
+        }
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))
+            .context(format!("Failed to restrict evidence directory: {:?}", dir))?;
+
+        let mut jobs = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read evidence job: {:?}", path))?;
+            match serde_json::from_str::<EvidenceJob>(&contents) {
+                Ok(mut job) => {
+                    // A restart interrupts a running job
+                    if job.status == EvidenceStatus::Running {
+                        job.status = EvidenceStatus::Failed;
+                        job.error = Some("Interrupted by a restart".to_string());
+                    }
+                    jobs.insert(job.id, job);
+                },
+                Err(e) => warn!("Skipping invalid evidence job {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} evidence packages", jobs.len());
+
+        Ok(Self {
+            dir,
+            config,
+            jobs: Arc::new(Mutex::new(jobs)),
+            logs,
+            alerts,
+            tickets,
+            security,
+        })
+    }
+
+    pub fn archive_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.zip", id))
+    }
+
+    fn save_job(&self, job: &EvidenceJob) -> Result<()> {
+        let path = self.dir.join(format!("{}.json", job.id));
+        let json = serde_json::to_string_pretty(job)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write evidence job: {:?}", path))?;
+        Ok(())
+    }
+
+    fn update<F: FnOnce(&mut EvidenceJob)>(&self, id: Uuid, change: F) {
+        match self.jobs.lock() {
+            Ok(mut jobs) => {
+                if let Some(job) = jobs.get_mut(&id) {
+                    change(job);
+                    if let Err(e) = self.save_job(job) {
+                        warn!("Failed to save evidence job {}: {}", id, e);
+                    }
+                }
+            },
+            Err(_) => error!("Failed to acquire lock on evidence jobs"),
+        }
+    }
+
+    fn progress(&self, id: Uuid, progress: u8, stage: &str) {
+        self.update(id, |job| {
+            job.progress = progress;
+            job.stage = stage.to_string();
+        });
+    }
+
+    pub fn start(&self, query: EvidenceQuery, requested_by: &str) -> Result<EvidenceJob> {
+        if query.from > query.to {
+            return Err(anyhow!("from must not be after to"));
+        }
+
+        let now = Utc::now();
+        let job = EvidenceJob {
+            id: Uuid::new_v4(),
+            query,
+            requested_by: requested_by.to_string(),
+            requested_at: now,
+            status: EvidenceStatus::Running,
+            progress: 0,
+            stage: "queued".to_string(),
+            finished_at: None,
+            expires_at: now + Duration::hours(self.config.retention_hours as i64),
+            file_size: None,
+            sha256: None,
+            error: None,
+        };
+
+        match self.jobs.lock() {
+            Ok(mut jobs) => {
+                self.save_job(&job)?;
+                jobs.insert(job.id, job.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on evidence jobs")),
+        }
+
+        let manager = self.clone();
+        let started = job.clone();
+        request_trace::spawn_blocking(move || {
+            let result = manager.build(&started);
+            let file_size = fs::metadata(manager.archive_path(started.id)).ok().map(|m| m.len());
+            manager.update(started.id, |job| {
+                job.finished_at = Some(Utc::now());
+                match result {
+                    Ok(hash) => {
+                        job.status = EvidenceStatus::Completed;
+                        job.progress = 100;
+                        job.stage = "done".to_string();
+                        job.file_size = file_size;
+                        job.sha256 = Some(hash);
+                        info!("Evidence package {} completed", job.id);
+                    },
+                    Err(e) => {
+                        job.status = EvidenceStatus::Failed;
+                        job.error = Some(e.to_string());
+                        error!("Evidence package {} failed: {}", job.id, e);
+                    },
+                }
+            });
+        });
+
+        Ok(job)
+    }
+
+    fn collect_logs(&self, query: &EvidenceQuery, alerts: &[Alert]) -> Result<Vec<LogEntry>> {
+        let related: HashSet<Uuid> = alerts.iter()
+            .flat_map(|a| a.related_logs.iter().copied())
+            .collect();
+        let filter = LogFilter {
+            from: Some(query.from),
+            to: Some(query.to),
+            ..Default::default()
+        };
+
+        let mut logs = self.logs.query_where(&filter, |e| {
+            query.unscoped()
+                || related.contains(&e.id)
+                || e.host.as_ref().map_or(false, |h| query.hosts.contains(h))
+                || e.user.as_ref().map_or(false, |u| query.users.contains(u))
+        })?;
+        logs.reverse();
+        Ok(logs)
+    }
+
+    fn collect_audit(&self, query: &EvidenceQuery) -> Vec<AuditEvent> {
+        self.security.get_audit_logs()
+            .into_iter()
+            .filter(|e| e.timestamp >= query.from && e.timestamp <= query.to)
+            .filter(|e| query.unscoped()
+                || query.users.contains(&e.user)
+                || query.mentions_scope(&e.resource)
+                || e.details.as_deref().map_or(false, |d| query.mentions_scope(d)))
+            .collect()
+    }
+
+    fn collect_alerts(&self, query: &EvidenceQuery) -> Result<Vec<Alert>> {
+        let mut alerts: Vec<Alert> = self.alerts.get_all_alerts()?
+            .into_iter()
+            .filter(|a| if query.alert_ids.is_empty() {
+                query.unscoped() && a.created_at >= query.from && a.created_at <= query.to
+            } else {
+                query.alert_ids.contains(&a.id)
+            })
+            .collect();
+        alerts.sort_by_key(|a| a.created_at);
+        Ok(alerts)
+    }
+
+    fn collect_tickets(&self, query: &EvidenceQuery, alerts: &[Alert]) -> Result<Vec<Ticket>> {
+        let alert_ids: HashSet<Uuid> = alerts.iter().map(|a| a.id).collect();
+        let mut tickets: Vec<Ticket> = self.tickets.get_all_tickets()?
+            .into_iter()
+            .filter(|t| t.linked_alerts.iter().any(|id| alert_ids.contains(id))
+                || (t.created_at <= query.to && t.updated_at >= query.from
+                    && (query.unscoped()
+                        || query.users.contains(&t.created_by)
+                        || t.tags.iter().any(|tag| query.hosts.contains(tag)))))
+            .collect();
+        tickets.sort_by_key(|t| t.created_at);
+        Ok(tickets)
+    }
+
+    // Writes the archive and returns its SHA-256
+    fn build(&self, job: &EvidenceJob) -> Result<String> {
+        let query = &job.query;
+
+        self.progress(job.id, 5, "alerts");
+        let alerts = self.collect_alerts(query)?;
+
+        self.progress(job.id, 15, "logs");
+        let logs = self.collect_logs(query, &alerts)?;
+
+        self.progress(job.id, 50, "audit events");
+        let audit = self.collect_audit(query);
+
+        self.progress(job.id, 65, "tickets");
+        let tickets = self.collect_tickets(query, &alerts)?;
+
+        self.progress(job.id, 75, "writing archive");
+        let mut files: Vec<(&str, Vec<u8>)> = vec![
+            ("logs.jsonl", to_jsonl(&logs)?),
+            ("audit.jsonl", to_jsonl(&audit)?),
+            ("alerts.json", serde_json::to_vec_pretty(&alerts)?),
+            ("tickets.json", serde_json::to_vec_pretty(&tickets)?),
+        ];
+
+        let summary = serde_json::to_vec_pretty(&Summary {
+            package_id: job.id,
+            generated_at: Utc::now(),
+            generated_by: &job.requested_by,
+            query,
+            logs: logs.len(),
+            audit_events: audit.len(),
+            alerts: alerts.len(),
+            tickets: tickets.len(),
+            signature_algorithm: "HMAC-SHA256",
+        })?;
+        files.push(("summary.json", summary));
+        seal(&self.security, &mut files)?;
+
+        let path = self.archive_path(job.id);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .context(format!("Failed to create evidence archive: {:?}", path))?;
+
+        let mut archive = zip::ZipWriter::new(file);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        let total = files.len();
+        for (i, (name, data)) in files.iter().enumerate() {
+            archive.start_file(*name, options)?;
+            archive.write_all(data)?;
+            self.progress(job.id, 75 + (20 * (i + 1) / total) as u8, "writing archive");
+        }
+        archive.finish()?;
+
+        let contents = fs::read(&path)
+            .context(format!("Failed to read evidence archive: {:?}", path))?;
+        Ok(sha256_hex(&contents))
+    }
+
+    pub fn get_job(&self, id: Uuid) -> Result<EvidenceJob> {
+        match self.jobs.lock() {
+            Ok(jobs) => jobs.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Evidence package not found: {}", id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on evidence jobs")),
+        }
+    }
+
+    // Deletes the archives past their expiry; the job record stays as Expired
+    pub fn expire(&self, now: DateTime<Utc>) -> Result<usize> {
+        self.expire_where(|job| job.expires_at <= now)
+    }
+
+    // Emergency purge while disk space is critical: archives finished longer ago than
+    // max_age go before their expiry
+    pub fn expire_older_than(&self, max_age: std::time::Duration) -> Result<usize> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(max_age)?;
+        self.expire_where(|job| job.finished_at.is_some_and(|finished| finished <= cutoff))
+    }
+
+    fn expire_where<F>(&self, due: F) -> Result<usize>
+    where
+        F: Fn(&EvidenceJob) -> bool,
+    {
+        let expired: Vec<Uuid> = match self.jobs.lock() {
+            Ok(jobs) => jobs.values()
+                .filter(|j| due(j) && matches!(j.status, EvidenceStatus::Completed | EvidenceStatus::Failed))
+                .map(|j| j.id)
+                .collect(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on evidence jobs")),
+        };
+
+        for id in &expired {
+            let path = self.archive_path(*id);
+            if path.exists() {
+                fs::remove_file(&path)
+                    .context(format!("Failed to delete evidence archive: {:?}", path))?;
+            }
+            self.update(*id, |job| job.status = EvidenceStatus::Expired);
+            info!("Evidence package {} expired", id);
+        }
+
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_lists_every_file_and_is_signed() {
+        let dir = tempfile::tempdir().unwrap();
+        let security = SecurityManager::new([7u8; 32], dir.path().to_str().unwrap()).unwrap();
+        let mut files = vec![
+            ("logs.jsonl", b"{}\n".to_vec()),
+            ("summary.json", b"{\"logs\": 1}".to_vec()),
+        ];
+        seal(&security, &mut files).unwrap();
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["logs.jsonl", "summary.json", "manifest.json", "manifest.sig"]);
+        let manifest: serde_json::Value = serde_json::from_slice(&files[2].1).unwrap();
+        assert_eq!(manifest[1]["path"], "summary.json");
+        assert_eq!(manifest[1]["sha256"], sha256_hex(b"{\"logs\": 1}"));
+        assert_eq!(String::from_utf8_lossy(&files[3].1).trim(), security.sign(&files[2].1));
+
+        // Another installation's key gives another signature
+        let other = SecurityManager::new([8u8; 32], dir.path().to_str().unwrap()).unwrap();
+        assert_ne!(other.sign(&files[2].1), security.sign(&files[2].1));
+    }
+}
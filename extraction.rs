@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error, warn};
+
+use crate::alerts::AlertsManager;
+use crate::models::{AlertSeverity, EventCategory, LogEntry};
+
+// A single rule evaluation taking longer than this disables the rule
+const MAX_EVALUATION_TIME: Duration = Duration::from_millis(50);
+// Upper bound on the compiled program size, keeps pathological patterns from compiling at all
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractionRule {
+    pub id: Uuid,
+    pub name: String,
+    pub order: u32,
+    pub enabled: bool,
+    pub source_match: Option<String>,
+    pub program_match: Option<String>,
+    pub pattern: String,
+    pub field_mapping: HashMap<String, ExtractionTarget>,
+    pub disabled_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ExtractionTarget {
+    User,
+    Host,
+    Tag,
+    EventType,
+    Category,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractionResult {
+    pub matched: bool,
+    pub captures: HashMap<String, String>,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub event_type: Option<String>,
+    pub category: Option<EventCategory>,
+    pub tags: Vec<String>,
+    pub elapsed_us: u64,
+}
+
+// A rule that matched an entry, see ExtractionManager::apply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleMatch {
+    pub id: Uuid,
+    pub name: String,
+}
+
+impl ExtractionRule {
+    pub fn applies_to(&self, source: &str, program: Option<&str>) -> bool {
+        if let Some(expected) = &self.source_match {
+            if expected != source {
+                return false;
+            }
+        }
+
+        if let Some(expected) = &self.program_match {
+            if program != Some(expected.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// Expands grok-style %{PATTERN:name} references into named regex groups
+pub fn expand_grok(pattern: &str) -> Result<String> {
+    let grok = Regex::new(r"%\{(\w+)(?::(\w+))?\}").expect("valid grok reference pattern");
+    let mut unknown = None;
+
+    let expanded = grok.replace_all(pattern, |caps: &regex::Captures| {
+        let body = match &caps[1] {
+            "INT" => r"[+-]?\d+",
+            "NUMBER" => r"[+-]?\d+(?:\.\d+)?",
+            "WORD" => r"\w+",
+            "NOTSPACE" => r"\S+",
+            "USERNAME" | "USER" => r"[a-zA-Z0-9._-]+",
+            "IP" => r"(?:\d{1,3}(?:\.\d{1,3}){3}|[0-9a-fA-F:]+:[0-9a-fA-F:.]*)",
+            "HOSTNAME" => r"[a-zA-Z0-9][a-zA-Z0-9.-]*",
+            "DATA" => r".*?",
+            "GREEDYDATA" => r".*",
+            other => {
+                unknown = Some(other.to_string());
+                ""
+            }
+        };
+
+        match caps.get(2) {
+            Some(name) => format!("(?P<{}>{})", name.as_str(), body),
+            None => format!("(?:{})", body),
+        }
+    });
+
+    match unknown {
+        Some(name) => Err(anyhow!("Unknown grok pattern: {}", name)),
+        None => Ok(expanded.into_owned()),
+    }
+}
+
+pub fn compile_pattern(pattern: &str) -> Result<Regex> {
+    let expanded = expand_grok(pattern)?;
+
+    RegexBuilder::new(&expanded)
+        .size_limit(MAX_COMPILED_SIZE)
+        .dfa_size_limit(MAX_COMPILED_SIZE)
+        .build()
+        .context("Invalid extraction pattern")
+}
+
+// Runs a compiled rule against a message and maps the captures to log fields
+pub fn evaluate(rule: &ExtractionRule, regex: &Regex, message: &str) -> ExtractionResult {
+    let start = Instant::now();
+    let caps = regex.captures(message);
+    let elapsed_us = start.elapsed().as_micros() as u64;
+
+    let mut result = ExtractionResult {
+        matched: caps.is_some(),
+        captures: HashMap::new(),
+        user: None,
+        host: None,
+        event_type: None,
+        category: None,
+        tags: Vec::new(),
+        elapsed_us,
+    };
+
+    if let Some(caps) = caps {
+        for name in regex.capture_names().flatten() {
+            if let Some(value) = caps.name(name) {
+                result.captures.insert(name.to_string(), value.as_str().to_string());
+            }
+        }
+
+        for (capture, target) in &rule.field_mapping {
+            if let Some(value) = result.captures.get(capture).cloned() {
+                match target {
+                    ExtractionTarget::User => result.user = Some(value),
+                    ExtractionTarget::Host => result.host = Some(value),
+                    ExtractionTarget::EventType => result.event_type = Some(value),
+                    ExtractionTarget::Category => result.category = EventCategory::parse(&value),
+                    ExtractionTarget::Tag => result.tags.push(format!("{}:{}", capture, value)),
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Clone)]
+pub struct ExtractionManager {
+    rules_dir: PathBuf,
+    rules: Arc<Mutex<HashMap<Uuid, ExtractionRule>>>,
+    compiled: Arc<Mutex<HashMap<Uuid, Regex>>>,
+    alerts_manager: AlertsManager,
+}
+
+impl ExtractionManager {
+    pub fn new(rules_dir: &str, alerts_manager: AlertsManager) -> Result<Self> {
+        let rules_dir = PathBuf::from(rules_dir);
+
+        if !rules_dir.exists() {
+            fs::create_dir_all(&rules_dir)
+                .context(format!("Failed to create extraction rules directory: {:?}", rules_dir))?;
+            info!("Created extraction rules directory: {:?}", rules_dir);
+        }
+
+        let mut rules = HashMap::new();
+        let mut compiled = HashMap::new();
+
+        for entry in fs::read_dir(&rules_dir)? {
+            let path = entry?.path();
+
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<ExtractionRule>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(rule) => {
+                        match compile_pattern(&rule.pattern) {
+                            Ok(regex) => {
+                                compiled.insert(rule.id, regex);
+                            },
+                            Err(e) => error!("Failed to compile extraction rule {} ({}): {}", rule.name, rule.id, e),
+                        }
+                        rules.insert(rule.id, rule);
+                    },
+                    Err(e) => {
+                        error!("Failed to load extraction rule {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} extraction rules", rules.len());
+
+        Ok(Self {
+            rules_dir,
+            rules: Arc::new(Mutex::new(rules)),
+            compiled: Arc::new(Mutex::new(compiled)),
+            alerts_manager,
+        })
+    }
+
+    fn save_rule(&self, rule: &ExtractionRule) -> Result<()> {
+        let file_path = self.rules_dir.join(format!("{}.json", rule.id));
+        let json = serde_json::to_string_pretty(rule)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    fn cache_regex(&self, id: Uuid, regex: Regex) -> Result<()> {
+        match self.compiled.lock() {
+            Ok(mut compiled) => {
+                compiled.insert(id, regex);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on compiled extraction rules")),
+        }
+    }
+
+    pub fn create_rule(&self,
+                       name: String,
+                       order: u32,
+                       source_match: Option<String>,
+                       program_match: Option<String>,
+                       pattern: String,
+                       field_mapping: HashMap<String, ExtractionTarget>) -> Result<ExtractionRule> {
+        let regex = compile_pattern(&pattern)?;
+        let now = Utc::now();
+
+        let rule = ExtractionRule {
+            id: Uuid::new_v4(),
+            name,
+            order,
+            enabled: true,
+            source_match,
+            program_match,
+            pattern,
+            field_mapping,
+            disabled_reason: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.save_rule(&rule)?;
+        self.cache_regex(rule.id, regex)?;
+
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                rules.insert(rule.id, rule.clone());
+                Ok(rule)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on extraction rules")),
+        }
+    }
+
+    pub fn update_rule(&self,
+                       id: Uuid,
+                       name: Option<String>,
+                       order: Option<u32>,
+                       enabled: Option<bool>,
+                       source_match: Option<Option<String>>,
+                       program_match: Option<Option<String>>,
+                       pattern: Option<String>,
+                       field_mapping: Option<HashMap<String, ExtractionTarget>>) -> Result<ExtractionRule> {
+        let regex = match &pattern {
+            Some(pattern) => Some(compile_pattern(pattern)?),
+            None => None,
+        };
+
+        let updated = match self.rules.lock() {
+            Ok(mut rules) => {
+                let rule = rules.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Extraction rule not found: {}", id))?;
+
+                if let Some(name) = name {
+                    rule.name = name;
+                }
+
+                if let Some(order) = order {
+                    rule.order = order;
+                }
+
+                if let Some(enabled) = enabled {
+                    rule.enabled = enabled;
+                    if enabled {
+                        rule.disabled_reason = None;
+                    }
+                }
+
+                if let Some(source_match) = source_match {
+                    rule.source_match = source_match;
+                }
+
+                if let Some(program_match) = program_match {
+                    rule.program_match = program_match;
+                }
+
+                if let Some(pattern) = pattern {
+                    rule.pattern = pattern;
+                }
+
+                if let Some(field_mapping) = field_mapping {
+                    rule.field_mapping = field_mapping;
+                }
+
+                rule.updated_at = Utc::now();
+                rule.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on extraction rules")),
+        };
+
+        if let Some(regex) = regex {
+            self.cache_regex(id, regex)?;
+        }
+
+        self.save_rule(&updated)?;
+        Ok(updated)
+    }
+
+    pub fn delete_rule(&self, id: Uuid) -> Result<()> {
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                if rules.remove(&id).is_none() {
+                    return Err(anyhow!("Extraction rule not found: {}", id));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on extraction rules")),
+        }
+
+        if let Ok(mut compiled) = self.compiled.lock() {
+            compiled.remove(&id);
+        }
+
+        let file_path = self.rules_dir.join(format!("{}.json", id));
+        fs::remove_file(file_path)?;
+
+        Ok(())
+    }
+
+    pub fn get_rule(&self, id: Uuid) -> Result<ExtractionRule> {
+        match self.rules.lock() {
+            Ok(rules) => {
+                rules.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Extraction rule not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on extraction rules")),
+        }
+    }
+
+    // Returns all rules in evaluation order
+    pub fn get_all_rules(&self) -> Result<Vec<ExtractionRule>> {
+        match self.rules.lock() {
+            Ok(rules) => {
+                let mut all: Vec<ExtractionRule> = rules.values().cloned().collect();
+                all.sort_by(|a, b| a.order.cmp(&b.order).then(a.created_at.cmp(&b.created_at)));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on extraction rules")),
+        }
+    }
+
+    // Runs a rule (stored or ad hoc) against sample text without touching any log entry
+    pub fn test_rule(&self, rule: &ExtractionRule, sample: &str) -> Result<ExtractionResult> {
+        let regex = compile_pattern(&rule.pattern)?;
+        Ok(evaluate(rule, &regex, sample))
+    }
+
+    // Applies every enabled, matching rule to the entry in order; returns the rules that matched
+    pub fn apply(&self, entry: &mut LogEntry) -> Result<Vec<RuleMatch>> {
+        let rules = self.get_all_rules()?;
+        let mut matched = Vec::new();
+
+        for rule in rules.iter().filter(|r| r.enabled) {
+            if !rule.applies_to(&entry.source, entry.application.as_deref()) {
+                continue;
+            }
+
+            let regex = match self.compiled.lock() {
+                Ok(compiled) => compiled.get(&rule.id).cloned(),
+                Err(_) => return Err(anyhow!("Failed to acquire lock on compiled extraction rules")),
+            };
+
+            let regex = match regex {
+                Some(regex) => regex,
+                None => continue,
+            };
+
+            let result = evaluate(rule, &regex, &entry.message);
+
+            if result.elapsed_us > MAX_EVALUATION_TIME.as_micros() as u64 {
+                self.disable_slow_rule(rule, result.elapsed_us);
+                continue;
+            }
+
+            if !result.matched {
+                continue;
+            }
+            matched.push(RuleMatch { id: rule.id, name: rule.name.clone() });
+
+            if let Some(user) = result.user {
+                entry.user = Some(user);
+            }
+
+            if let Some(host) = result.host {
+                entry.host = Some(host);
+            }
+
+            if let Some(event_type) = result.event_type {
+                entry.event_type = event_type;
+            }
+
+            if let Some(category) = result.category {
+                entry.category = category;
+            }
+
+            for tag in result.tags {
+                if !entry.tags.contains(&tag) {
+                    entry.tags.push(tag);
+                }
+            }
+        }
+
+        Ok(matched)
+    }
+
+    fn disable_slow_rule(&self, rule: &ExtractionRule, elapsed_us: u64) {
+        let reason = format!(
+            "Evaluation took {}us, exceeding the {}ms limit",
+            elapsed_us,
+            MAX_EVALUATION_TIME.as_millis()
+        );
+
+        warn!("Disabling extraction rule {} ({}): {}", rule.name, rule.id, reason);
+
+        match self.update_rule(rule.id, None, None, Some(false), None, None, None, None) {
+            Ok(_) => {
+                if let Ok(mut rules) = self.rules.lock() {
+                    if let Some(stored) = rules.get_mut(&rule.id) {
+                        stored.disabled_reason = Some(reason.clone());
+                        if let Err(e) = self.save_rule(stored) {
+                            error!("Failed to persist disabled extraction rule {}: {}", rule.id, e);
+                        }
+                    }
+                }
+            },
+            Err(e) => error!("Failed to disable extraction rule {}: {}", rule.id, e),
+        }
+
+        if let Err(e) = self.alerts_manager.create_alert(
+            AlertSeverity::High,
+            format!("Extraction rule '{}' disabled", rule.name),
+            reason,
+            "extraction".to_string(),
+            Vec::new(),
+        ) {
+            error!("Failed to raise alert for slow extraction rule {}: {}", rule.id, e);
+        }
+    }
+}
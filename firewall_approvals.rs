@@ -0,0 +1,120 @@
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::models::User;
+use crate::network::StagedChangeset;
+use crate::notifications::Notifier;
+use crate::security::{AccessControl, APPLY_PERMISSION};
+use crate::users::UserManager;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeDecision {
+    Applied,
+    Rejected,
+}
+
+// Notifies appliers of new firewall change requests and proposers of the decision, by
+// email and/or webhook as each user's notification preferences say
+#[derive(Clone)]
+pub struct FirewallApprovals {
+    users: UserManager,
+    access_control: AccessControl,
+    notifier: Notifier,
+}
+
+impl FirewallApprovals {
+    pub fn new(users: UserManager, access_control: AccessControl, notifier: Notifier) -> Self {
+        Self {
+            users,
+            access_control,
+            notifier,
+        }
+    }
+
+    // The proposer is left out, they cannot apply their own change request unless
+    // self-approval is allowed, and know about it anyway
+    fn appliers(&self, proposer: &str) -> Result<Vec<User>> {
+        Ok(self.users.get_all_users()?
+            .into_iter()
+            .filter(|u| u.is_active && u.username != proposer
+                && self.access_control.check_permission(&u.role.role_name(), APPLY_PERMISSION))
+            .collect())
+    }
+
+    // Failed deliveries are logged, a notification never fails the firewall change
+    async fn deliver(&self, recipients: &[User], subject: &str, body: &str, payload: serde_json::Value) {
+        let emails: Vec<String> = recipients.iter()
+            .filter(|u| u.notifications.email && !u.email.is_empty())
+            .map(|u| u.email.clone())
+            .collect();
+        if !emails.is_empty() {
+            if let Err(e) = self.notifier.send_email(&emails, subject, body).await {
+                warn!("Failed to email firewall change notification: {}", e);
+            }
+        }
+
+        for user in recipients {
+            if let Some(url) = &user.notifications.webhook_url {
+                if let Err(e) = self.notifier.post_webhook(url, &payload).await {
+                    warn!("Failed to post firewall change notification for {}: {}", user.username, e);
+                }
+            }
+        }
+    }
+
+    pub async fn proposed(&self, changeset: &StagedChangeset) -> Result<()> {
+        let appliers = self.appliers(&changeset.created_by)?;
+        if appliers.is_empty() {
+            warn!("No other active user holds {}, firewall change request {} waits unnoticed", APPLY_PERMISSION, changeset.id);
+            return Ok(());
+        }
+
+        let subject = format!("Firewall change request: {}", changeset.description);
+        let body = format!(
+            "{} proposes a firewall change: {} ({}).\n\nThe changes it would make are shown by GET /api/network/firewall/staged/{}/diff.\n",
+            changeset.created_by,
+            changeset.description,
+            changeset.id,
+            changeset.id,
+        );
+        let payload = serde_json::json!({
+            "event": "firewall.change_proposed",
+            "changeset_id": changeset.id,
+            "description": changeset.description,
+            "proposed_by": changeset.created_by,
+        });
+
+        self.deliver(&appliers, &subject, &body, payload).await;
+        info!("Notified {} appliers of firewall change request {}", appliers.len(), changeset.id);
+        Ok(())
+    }
+
+    pub async fn decided(&self, changeset: &StagedChangeset, decision: ChangeDecision, decided_by: &str, reason: Option<&str>) -> Result<()> {
+        // The proposer may have been removed since
+        let user = match self.users.get_user(&changeset.created_by) {
+            Ok(user) if user.is_active => user,
+            _ => return Ok(()),
+        };
+
+        let (verdict, event) = match decision {
+            ChangeDecision::Applied => ("applied", "firewall.change_applied"),
+            ChangeDecision::Rejected => ("rejected", "firewall.change_rejected"),
+        };
+        let subject = format!("Firewall change request {}: {}", verdict, changeset.description);
+        let mut body = format!("Firewall change request \"{}\" ({}) was {} by {}.\n",
+                               changeset.description, changeset.id, verdict, decided_by);
+        if let Some(reason) = reason {
+            body.push_str(&format!("\nReason: {}\n", reason));
+        }
+        let payload = serde_json::json!({
+            "event": event,
+            "changeset_id": changeset.id,
+            "description": changeset.description,
+            "decided_by": decided_by,
+            "reason": reason,
+        });
+
+        self.deliver(&[user], &subject, &body, payload).await;
+        Ok(())
+    }
+}
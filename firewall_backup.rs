@@ -0,0 +1,430 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::{FirewallBackupConfig, OffsiteBackupConfig, OffsiteMethod};
+use crate::models::AlertSeverity;
+use crate::network::{FirewallModel, ForeignRule, NetworkManager};
+use crate::outbound::{self, HttpClients};
+use crate::paths::write_private;
+
+// Source of the alert raised when the kernel ruleset has rules we did not write
+pub const ALERT_SOURCE: &str = "firewall_backup";
+
+const INDEX_FILE: &str = "index.json";
+
+// What is written for each backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupFile {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    // `nft -j list ruleset`, None when nft could not be read
+    ruleset: Option<serde_json::Value>,
+    ruleset_error: Option<String>,
+    model: FirewallModel,
+    foreign_rules: Vec<ForeignRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OffsiteCopy {
+    // URL or sftp target, without credentials
+    pub destination: String,
+    pub copied_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupSummary {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub managed_rules: usize,
+    // Whether the live ruleset could be read and is part of the backup
+    pub live_ruleset: bool,
+    // Rules in the live ruleset that were not written by us, at the time of the backup
+    pub foreign_rules: usize,
+    pub offsite: Option<OffsiteCopy>,
+}
+
+// Timestamped backups of the firewall under the backups directory: the live kernel
+// ruleset next to the model it is generated from, which is what a restore puts back.
+// Each run also compares the two and raises an alert for rules someone added by hand.
+#[derive(Clone)]
+pub struct FirewallBackups {
+    config: FirewallBackupConfig,
+    dir: PathBuf,
+    network: Arc<NetworkManager>,
+    alerts: AlertsManager,
+    http: Option<reqwest::Client>,
+    // HTTP basic auth of the off-box copy
+    credentials: Option<(String, String)>,
+    index: Arc<Mutex<Vec<BackupSummary>>>,
+    // Kernel handles of the foreign rules already alerted
+    alerted: Arc<Mutex<Vec<u64>>>,
+}
+
+fn read_password(offsite: &OffsiteBackupConfig) -> Result<String> {
+    match (&offsite.password_file, &offsite.password_env) {
+        (Some(path), _) => Ok(fs::read_to_string(path)
+            .context(format!("Failed to read backup password file: {}", path))?
+            .trim()
+            .to_string()),
+        (None, Some(var)) => std::env::var(var)
+            .context(format!("Backup password environment variable {} is not set", var)),
+        (None, None) => Err(anyhow!("firewall_backup.offsite.username needs password_file or password_env")),
+    }
+}
+
+// Scheme, host and path of the URL, without any credentials in it
+fn describe_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => format!("{}://{}{}", url.scheme(), url.host_str().unwrap_or_default(), url.path()),
+        Err(_) => "invalid url".to_string(),
+    }
+}
+
+fn describe_rule(rule: &ForeignRule) -> String {
+    format!("{} {} {} handle {}: {}", rule.family, rule.table, rule.chain, rule.kernel_handle, rule.expr)
+}
+
+impl FirewallBackups {
+    pub fn new(config: FirewallBackupConfig,
+               dir: &str,
+               network: Arc<NetworkManager>,
+               alerts: AlertsManager,
+               clients: &HttpClients) -> Result<Self> {
+        let (http, credentials) = match &config.offsite {
+            Some(offsite) if offsite.method == OffsiteMethod::Http => {
+                if offsite.url.is_none() {
+                    return Err(anyhow!("firewall_backup.offsite with method http needs url"));
+                }
+                let credentials = match &offsite.username {
+                    Some(username) => Some((username.clone(), read_password(offsite)?)),
+                    None => None,
+                };
+                let http = clients.client(outbound::FIREWALL_BACKUP, Duration::from_secs(offsite.timeout_secs.max(1)))?;
+                (Some(http), credentials)
+            },
+            Some(offsite) => {
+                if offsite.target.as_deref().and_then(|t| t.split_once(':')).is_none() {
+                    return Err(anyhow!("firewall_backup.offsite with method sftp needs target as user@host:directory"));
+                }
+                (None, None)
+            },
+            None => (None, None),
+        };
+
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create firewall backup directory: {:?}", dir))?;
+        }
+
+        // Backups removed by hand drop out of the listing
+        let index_path = dir.join(INDEX_FILE);
+        let index: Vec<BackupSummary> = if index_path.exists() {
+            let content = fs::read_to_string(&index_path)
+                .context(format!("Failed to read firewall backup index: {:?}", index_path))?;
+            serde_json::from_str(&content)
+                .context(format!("Failed to parse firewall backup index: {:?}", index_path))?
+        } else {
+            Vec::new()
+        };
+        let index = index.into_iter().filter(|b| dir.join(&b.file_name).exists()).collect();
+
+        Ok(Self {
+            config,
+            dir,
+            network,
+            alerts,
+            http,
+            credentials,
+            index: Arc::new(Mutex::new(index)),
+            alerted: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs.max(60))
+    }
+
+    // Newest first
+    pub fn list(&self) -> Result<Vec<BackupSummary>> {
+        let index = self.index.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on firewall backups"))?;
+        let mut list = index.clone();
+        list.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(list)
+    }
+
+    fn read(&self, id: Uuid) -> Result<BackupFile> {
+        let file_name = self.list()?.into_iter()
+            .find(|b| b.id == id)
+            .map(|b| b.file_name)
+            .ok_or_else(|| anyhow!("Firewall backup not found: {}", id))?;
+        let path = self.dir.join(file_name);
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read firewall backup: {:?}", path))?;
+        serde_json::from_str(&content)
+            .context(format!("Failed to parse firewall backup: {:?}", path))
+    }
+
+    // The model of a backup with its time, for NetworkManager::stage_restore
+    pub fn model(&self, id: Uuid) -> Result<(DateTime<Utc>, FirewallModel)> {
+        let backup = self.read(id)?;
+        Ok((backup.created_at, backup.model))
+    }
+
+    fn save_index(&self, index: &[BackupSummary]) -> Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        write_private(&path, serde_json::to_string_pretty(index)?)
+            .context(format!("Failed to write firewall backup index: {:?}", path))?;
+        Ok(())
+    }
+
+    pub async fn run(&self) -> Result<BackupSummary> {
+        let id = Uuid::new_v4();
+        let created_at = Utc::now();
+
+        // Without nft the model alone is still worth keeping
+        let (ruleset, ruleset_error, foreign_rules) = match self.network.live_ruleset_json().await {
+            Ok(json) => {
+                let foreign_rules = self.network.find_foreign_rules(&json).await?;
+                let ruleset = serde_json::from_str(&json).context("Failed to parse nft JSON output")?;
+                (Some(ruleset), None, foreign_rules)
+            },
+            Err(e) => {
+                warn!("Firewall backup {} without the live ruleset: {}", id, e);
+                (None, Some(e.to_string()), Vec::new())
+            },
+        };
+
+        let backup = BackupFile {
+            id,
+            created_at,
+            ruleset,
+            ruleset_error,
+            model: self.network.export_model().await,
+            foreign_rules,
+        };
+
+        let file_name = format!("firewall-{}-{}.json", created_at.format("%Y%m%dT%H%M%SZ"), id);
+        let path = self.dir.join(&file_name);
+        let content = serde_json::to_vec_pretty(&backup)?;
+        // The ruleset maps the network, readable by the service only
+        write_private(&path, &content)
+            .context(format!("Failed to write firewall backup: {:?}", path))?;
+
+        let offsite = match &self.config.offsite {
+            Some(offsite) => Some(self.copy_offsite(offsite, &path, &file_name, content).await),
+            None => None,
+        };
+
+        let summary = BackupSummary {
+            id,
+            created_at,
+            file_name,
+            size_bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            managed_rules: backup.model.managed_rules.len(),
+            live_ruleset: backup.ruleset.is_some(),
+            foreign_rules: backup.foreign_rules.len(),
+            offsite,
+        };
+        info!("Wrote firewall backup {} ({} managed rules)", summary.file_name, summary.managed_rules);
+
+        {
+            let mut index = self.index.lock()
+                .map_err(|_| anyhow!("Failed to acquire lock on firewall backups"))?;
+            index.push(summary.clone());
+            self.prune(&mut index);
+            self.save_index(&index)?;
+        }
+
+        self.check_divergence(&backup.foreign_rules);
+        Ok(summary)
+    }
+
+    // Removes the backups past the retention; off-box copies are left to the other side
+    fn prune(&self, index: &mut Vec<BackupSummary>) {
+        index.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        let cutoff = Utc::now() - chrono::Duration::days(self.config.max_age_days as i64);
+        let mut kept = 0;
+        index.retain(|backup| {
+            if kept < self.config.keep.max(1) && backup.created_at >= cutoff {
+                kept += 1;
+                return true;
+            }
+            if let Err(e) = fs::remove_file(self.dir.join(&backup.file_name)) {
+                warn!("Failed to remove firewall backup {}: {}", backup.file_name, e);
+            }
+            false
+        });
+    }
+
+    async fn copy_offsite(&self, offsite: &OffsiteBackupConfig, path: &Path, file_name: &str, content: Vec<u8>) -> OffsiteCopy {
+        let (destination, result) = match offsite.method {
+            OffsiteMethod::Http => {
+                let url = format!("{}/{}", offsite.url.as_deref().unwrap_or_default().trim_end_matches('/'), file_name);
+                (describe_url(&url), self.put(&url, content).await)
+            },
+            OffsiteMethod::Sftp => {
+                let target = offsite.target.clone().unwrap_or_default();
+                (format!("sftp://{}", target), self.sftp(offsite, &target, path, file_name).await)
+            },
+        };
+
+        match result {
+            Ok(()) => OffsiteCopy { destination, copied_at: Some(Utc::now()), error: None },
+            Err(e) => {
+                error!("Failed to copy firewall backup {} to {}: {:#}", file_name, destination, e);
+                OffsiteCopy { destination, copied_at: None, error: Some(format!("{:#}", e)) }
+            },
+        }
+    }
+
+    async fn put(&self, url: &str, content: Vec<u8>) -> Result<()> {
+        let http = self.http.as_ref().ok_or_else(|| anyhow!("No HTTP client for the off-box copy"))?;
+        let mut request = http.put(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(content);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        // Errors would otherwise carry the URL
+        request.send().await
+            .map_err(|e| e.without_url())
+            .context("Upload failed")?
+            .error_for_status()
+            .map_err(|e| e.without_url())
+            .context("Upload rejected")?;
+        Ok(())
+    }
+
+    async fn sftp(&self, offsite: &OffsiteBackupConfig, target: &str, path: &Path, file_name: &str) -> Result<()> {
+        let (host, directory) = target.split_once(':')
+            .ok_or_else(|| anyhow!("sftp target must be user@host:directory"))?;
+
+        let mut command = Command::new("sftp");
+        command.arg("-b").arg("-")
+            .arg("-o").arg("BatchMode=yes");
+        if let Some(identity) = &offsite.identity_file {
+            command.arg("-i").arg(identity);
+        }
+        command.arg(host)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command.spawn().context("Failed to start sftp")?;
+        let remote = if directory.is_empty() { file_name.to_string() } else { format!("{}/{}", directory.trim_end_matches('/'), file_name) };
+        let batch = format!("put \"{}\" \"{}\"\n", path.display(), remote);
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(batch.as_bytes()).await.context("Failed to write sftp commands")?;
+        }
+
+        let output = tokio::time::timeout(Duration::from_secs(offsite.timeout_secs.max(1)), child.wait_with_output()).await
+            .map_err(|_| anyhow!("sftp timed out"))?
+            .context("sftp failed")?;
+        if !output.status.success() {
+            return Err(anyhow!("sftp failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    }
+
+    // One alert per set of new foreign rules; rules already alerted are not repeated
+    fn check_divergence(&self, foreign_rules: &[ForeignRule]) {
+        let Ok(mut alerted) = self.alerted.lock() else { return };
+        let new: Vec<&ForeignRule> = foreign_rules.iter()
+            .filter(|r| !alerted.contains(&r.kernel_handle))
+            .collect();
+        *alerted = foreign_rules.iter().map(|r| r.kernel_handle).collect();
+        if new.is_empty() {
+            return;
+        }
+
+        warn!("Kernel ruleset has {} rules that were not added through the SIEM", new.len());
+        let rules = new.iter().map(|r| describe_rule(r)).collect::<Vec<_>>().join("\n");
+        if let Err(e) = self.alerts.create_alert(
+            AlertSeverity::Medium,
+            "Firewall rules changed outside the SIEM".to_string(),
+            format!("The live ruleset has {} rules the SIEM did not create, someone may have edited it with nft by hand. \
+                     They are lost when the ruleset is regenerated.\n{}", new.len(), rules),
+            ALERT_SOURCE.to_string(),
+            Vec::new(),
+        ) {
+            error!("Failed to raise firewall divergence alert: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn backups(dir: &tempfile::TempDir, keep: usize) -> FirewallBackups {
+        let root = dir.path().to_str().unwrap();
+        FirewallBackups::new(
+            FirewallBackupConfig { keep, ..Default::default() },
+            &format!("{}/backups", root),
+            Arc::new(NetworkManager::unavailable()),
+            AlertsManager::new(&format!("{}/alerts", root)).unwrap(),
+            &HttpClients::new(&crate::config::default_config().proxy).unwrap(),
+        ).unwrap()
+    }
+
+    #[tokio::test]
+    async fn backups_round_trip_and_are_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = backups(&dir, 30);
+        let summary = backups.run().await.unwrap();
+
+        let (created_at, model) = backups.model(summary.id).unwrap();
+        assert_eq!(created_at, summary.created_at);
+        assert_eq!(model.managed_rules.len(), summary.managed_rules);
+        assert!(backups.model(Uuid::new_v4()).is_err());
+
+        for file in [summary.file_name.as_str(), INDEX_FILE] {
+            let mode = fs::metadata(backups.dir.join(file)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", file);
+        }
+
+        // The index survives a restart, without backups removed by hand
+        let second = backups.run().await.unwrap();
+        fs::remove_file(backups.dir.join(&summary.file_name)).unwrap();
+        let reopened = self::backups(&dir, 30);
+        let ids: Vec<Uuid> = reopened.list().unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(ids, [second.id]);
+    }
+
+    #[tokio::test]
+    async fn only_the_newest_backups_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = backups(&dir, 2);
+        let mut runs = Vec::new();
+        for _ in 0..3 {
+            runs.push(backups.run().await.unwrap());
+        }
+
+        let ids: Vec<Uuid> = backups.list().unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(ids, [runs[2].id, runs[1].id]);
+        assert!(!backups.dir.join(&runs[0].file_name).exists());
+        assert!(backups.dir.join(&runs[1].file_name).exists());
+    }
+}
@@ -0,0 +1,449 @@
+use serde::{Serialize, Deserialize};
+
+use crate::network::RuleSpec;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Nft,
+    Iptables,
+}
+
+// A source line that could not be converted, with the reason
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnconvertedLine {
+    pub line_number: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportResult {
+    pub rules: Vec<(usize, RuleSpec)>,
+    pub unconverted: Vec<UnconvertedLine>,
+}
+
+impl ImportResult {
+    fn skip(&mut self, line_number: usize, line: &str, reason: impl Into<String>) {
+        self.unconverted.push(UnconvertedLine {
+            line_number,
+            line: line.to_string(),
+            reason: reason.into(),
+        });
+    }
+}
+
+pub fn parse(format: ImportFormat, content: &str) -> ImportResult {
+    match format {
+        ImportFormat::Nft => parse_nft(content),
+        ImportFormat::Iptables => parse_iptables(content),
+    }
+}
+
+// Splits on whitespace, keeping double-quoted strings together; an unquoted # ends the line
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                if !in_quotes {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            },
+            c if in_quotes => current.push(c),
+            // The rest of the line is a comment
+            '#' => break,
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+// Reads a single value or a `{ a, b }` set starting at tokens[*pos]; the set may span
+// several tokens or be written without spaces
+fn take_values(tokens: &[String], pos: &mut usize) -> Option<Vec<String>> {
+    let first = tokens.get(*pos)?;
+    *pos += 1;
+
+    if !first.starts_with('{') {
+        return Some(vec![first.clone()]);
+    }
+
+    let mut set = first.clone();
+    while !set.ends_with('}') {
+        set.push(' ');
+        set.push_str(tokens.get(*pos)?);
+        *pos += 1;
+    }
+
+    let values = set[1..set.len() - 1]
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect();
+    Some(values)
+}
+
+fn parse_ports(values: &[String]) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+
+    for value in values {
+        if value.contains('-') || value.contains(':') {
+            return Err(format!("Port ranges are not supported: {}", value));
+        }
+        ports.push(value.parse::<u16>().map_err(|_| format!("Invalid port: {}", value))?);
+    }
+
+    Ok(ports)
+}
+
+fn single(values: Vec<String>, what: &str) -> Result<String, String> {
+    match values.len() {
+        1 => Ok(values.into_iter().next().unwrap_or_default()),
+        _ => Err(format!("Sets of {} are not supported", what)),
+    }
+}
+
+// Converts the statement part of an nft rule (after the chain) into a spec
+fn nft_rule(chain: &str, tokens: &[String]) -> Result<RuleSpec, String> {
+    let mut spec = RuleSpec {
+        chain: chain.to_string(),
+        ..Default::default()
+    };
+    let mut pos = 0;
+
+    while pos < tokens.len() {
+        let token = tokens[pos].as_str();
+        pos += 1;
+
+        let next_values = |pos: &mut usize| take_values(tokens, pos)
+            .ok_or_else(|| format!("Missing value after '{}'", token));
+
+        match token {
+            "counter" => {
+                // Counters are always added; skip an explicit "packets N bytes M"
+                if tokens.get(pos).map(|t| t == "packets").unwrap_or(false) {
+                    pos += 4;
+                }
+            },
+            "accept" | "drop" => spec.action = token.to_string(),
+            "reject" => return Err("reject is not supported, use drop".to_string()),
+            "iifname" | "iif" => spec.in_interface = Some(single(next_values(&mut pos)?, "interfaces")?),
+            "oifname" | "oif" => spec.out_interface = Some(single(next_values(&mut pos)?, "interfaces")?),
+            "meta" => {
+                match tokens.get(pos).map(|t| t.as_str()) {
+                    Some("l4proto") => {
+                        pos += 1;
+                        spec.protocols = next_values(&mut pos)?;
+                    },
+                    Some("iifname") => {
+                        pos += 1;
+                        spec.in_interface = Some(single(next_values(&mut pos)?, "interfaces")?);
+                    },
+                    Some("oifname") => {
+                        pos += 1;
+                        spec.out_interface = Some(single(next_values(&mut pos)?, "interfaces")?);
+                    },
+                    other => return Err(format!("Unsupported meta match: {}", other.unwrap_or(""))),
+                }
+            },
+            "ip" | "ip6" => {
+                match tokens.get(pos).map(|t| t.as_str()) {
+                    Some("saddr") => {
+                        pos += 1;
+                        spec.source = Some(single(next_values(&mut pos)?, "addresses")?);
+                    },
+                    Some("daddr") => {
+                        pos += 1;
+                        spec.destination = Some(single(next_values(&mut pos)?, "addresses")?);
+                    },
+                    Some("protocol") if token == "ip" => {
+                        pos += 1;
+                        spec.protocols = next_values(&mut pos)?;
+                    },
+                    Some("nexthdr") if token == "ip6" => {
+                        pos += 1;
+                        spec.protocols = next_values(&mut pos)?;
+                    },
+                    other => return Err(format!("Unsupported {} match: {}", token, other.unwrap_or(""))),
+                }
+            },
+            "tcp" | "udp" | "th" => {
+                match tokens.get(pos).map(|t| t.as_str()) {
+                    Some("dport") => {
+                        pos += 1;
+                        spec.ports = parse_ports(&next_values(&mut pos)?)?;
+                        if token != "th" {
+                            spec.protocols = vec![token.to_string()];
+                        }
+                    },
+                    other => return Err(format!("Unsupported {} match: {}", token, other.unwrap_or(""))),
+                }
+            },
+            "comment" => spec.description = single(next_values(&mut pos)?, "comments")?,
+            "ct" => return Err("Connection tracking matches are not modeled".to_string()),
+            "jump" | "goto" | "return" => return Err("Jumps between chains are not modeled".to_string()),
+            "log" | "limit" | "masquerade" | "snat" | "dnat" => {
+                return Err(format!("'{}' statements are not modeled", token));
+            },
+            other => return Err(format!("Unrecognized token: {}", other)),
+        }
+    }
+
+    if spec.action.is_empty() {
+        return Err("Rule has no accept or drop verdict".to_string());
+    }
+
+    Ok(spec)
+}
+
+// Native parser for the nft subset we model. Accepts both `add rule <family> <table>
+// <chain> ...` commands and `table { chain { ... } }` blocks as printed by `nft list ruleset`.
+fn parse_nft(content: &str) -> ImportResult {
+    let mut result = ImportResult::default();
+    let mut table: Option<String> = None;
+    let mut chain: Option<String> = None;
+
+    for (index, raw) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw.trim();
+        let tokens = tokenize(line);
+
+        // Blank and comment-only lines
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+
+        match first.as_str() {
+            "table" => {
+                table = tokens.get(2).cloned();
+                chain = None;
+                if tokens.get(1).map(|f| !matches!(f.as_str(), "inet" | "ip" | "ip6")).unwrap_or(true) {
+                    result.skip(line_number, line, "Only inet, ip and ip6 tables are imported");
+                    table = Some(String::new());
+                }
+            },
+            "chain" => chain = tokens.get(1).cloned(),
+            "}" => {
+                if chain.take().is_none() {
+                    table = None;
+                }
+            },
+            "type" | "policy" | "flush" => {},
+            "add" | "insert" => {
+                if tokens.get(1).map(|t| t != "rule").unwrap_or(true) {
+                    // add table / add chain carry no filter rules
+                    continue;
+                }
+
+                if tokens.len() < 5 {
+                    result.skip(line_number, line, "Incomplete rule command");
+                    continue;
+                }
+
+                if !matches!(tokens[2].as_str(), "inet" | "ip" | "ip6") {
+                    result.skip(line_number, line, format!("Family {} is not imported", tokens[2]));
+                    continue;
+                }
+
+                if tokens[3] != "filter" {
+                    result.skip(line_number, line, format!("Only the filter table is imported, not {}", tokens[3]));
+                    continue;
+                }
+
+                match nft_rule(&tokens[4], &tokens[5..]) {
+                    Ok(spec) => result.rules.push((line_number, spec)),
+                    Err(reason) => result.skip(line_number, line, reason),
+                }
+            },
+            _ => {
+                match (&table, &chain) {
+                    (Some(t), Some(c)) if t == "filter" => {
+                        match nft_rule(c, &tokens) {
+                            Ok(spec) => result.rules.push((line_number, spec)),
+                            Err(reason) => result.skip(line_number, line, reason),
+                        }
+                    },
+                    (Some(t), Some(_)) if !t.is_empty() => {
+                        result.skip(line_number, line, format!("Only the filter table is imported, not {}", t));
+                    },
+                    (Some(_), Some(_)) => {},
+                    _ => result.skip(line_number, line, "Rule outside of a chain"),
+                }
+            },
+        }
+    }
+
+    result
+}
+
+// Constrained iptables-save parser: filter table, built-in chains, simple matches
+fn parse_iptables(content: &str) -> ImportResult {
+    let mut result = ImportResult::default();
+    let mut table = String::new();
+
+    for (index, raw) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(':') || line == "COMMIT" {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('*') {
+            table = name.to_string();
+            continue;
+        }
+
+        if table != "filter" {
+            result.skip(line_number, line, format!("Only the filter table is imported, not {}", table));
+            continue;
+        }
+
+        match iptables_rule(&tokenize(line)) {
+            Ok(spec) => result.rules.push((line_number, spec)),
+            Err(reason) => result.skip(line_number, line, reason),
+        }
+    }
+
+    result
+}
+
+fn iptables_rule(tokens: &[String]) -> Result<RuleSpec, String> {
+    let mut spec = RuleSpec::default();
+    let mut pos = 0;
+
+    let value = |pos: &mut usize, flag: &str| -> Result<String, String> {
+        let v = tokens.get(*pos).cloned().ok_or_else(|| format!("Missing value after {}", flag))?;
+        *pos += 1;
+        Ok(v)
+    };
+
+    while pos < tokens.len() {
+        let token = tokens[pos].clone();
+        pos += 1;
+
+        if token == "!" {
+            return Err("Negated matches are not modeled".to_string());
+        }
+
+        match token.as_str() {
+            "-A" | "--append" => {
+                let chain = value(&mut pos, &token)?;
+                spec.chain = match chain.as_str() {
+                    "INPUT" | "FORWARD" | "OUTPUT" => chain.to_lowercase(),
+                    other => return Err(format!("Custom chain {} is not modeled", other)),
+                };
+            },
+            "-p" | "--protocol" => {
+                let protocol = value(&mut pos, &token)?;
+                if protocol != "all" {
+                    spec.protocols = vec![protocol];
+                }
+            },
+            "-s" | "--source" => spec.source = Some(value(&mut pos, &token)?),
+            "-d" | "--destination" => spec.destination = Some(value(&mut pos, &token)?),
+            "-i" | "--in-interface" => spec.in_interface = Some(value(&mut pos, &token)?),
+            "-o" | "--out-interface" => spec.out_interface = Some(value(&mut pos, &token)?),
+            "--dport" | "--destination-port" => {
+                spec.ports = parse_ports(&[value(&mut pos, &token)?])?;
+            },
+            "--dports" | "--destination-ports" => {
+                let list = value(&mut pos, &token)?;
+                let ports: Vec<String> = list.split(',').map(|p| p.to_string()).collect();
+                spec.ports = parse_ports(&ports)?;
+            },
+            "-m" | "--match" => {
+                let module = value(&mut pos, &token)?;
+                if !matches!(module.as_str(), "tcp" | "udp" | "multiport" | "comment") {
+                    return Err(format!("Match module {} is not modeled", module));
+                }
+            },
+            "--comment" => spec.description = value(&mut pos, &token)?,
+            "-j" | "--jump" => {
+                let target = value(&mut pos, &token)?;
+                spec.action = match target.as_str() {
+                    "ACCEPT" => "accept".to_string(),
+                    "DROP" => "drop".to_string(),
+                    other => return Err(format!("Target {} is not supported", other)),
+                };
+            },
+            other => return Err(format!("Unrecognized option: {}", other)),
+        }
+    }
+
+    if spec.chain.is_empty() {
+        return Err("Only -A rule lines are imported".to_string());
+    }
+
+    if spec.action.is_empty() {
+        return Err("Rule has no ACCEPT or DROP target".to_string());
+    }
+
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_split_on_whitespace_only() {
+        assert_eq!(tokenize(r#"tcp dport {22,80} comment "ssh, web # admin" # trailing"#),
+                   vec!["tcp", "dport", "{22,80}", "comment", "ssh, web # admin"]);
+        assert!(tokenize("   ").is_empty());
+        assert!(tokenize("# only a comment").is_empty());
+    }
+
+    #[test]
+    fn blank_and_comment_lines_are_skipped() {
+        let content = "\n# ruleset\n   \n\"\nadd rule inet filter input tcp dport 22 accept # ssh\n";
+        let result = parse(ImportFormat::Nft, content);
+        assert_eq!(result.rules.len(), 1);
+        assert_eq!(result.rules[0].0, 5);
+        assert_eq!(result.rules[0].1.ports, vec![22]);
+        assert!(result.unconverted.is_empty());
+    }
+
+    #[test]
+    fn nft_sets_with_and_without_spaces() {
+        let content = "table inet filter {\n  chain input {\n    tcp dport { 22, 443 } accept\n    meta l4proto {tcp,udp} th dport 53 accept\n  }\n}\n";
+        let result = parse(ImportFormat::Nft, content);
+        assert!(result.unconverted.is_empty(), "{:?}", result.unconverted);
+        assert_eq!(result.rules[0].1.ports, vec![22, 443]);
+        assert_eq!(result.rules[1].1.protocols, vec!["tcp", "udp"]);
+        assert_eq!(result.rules[1].1.ports, vec![53]);
+    }
+
+    #[test]
+    fn unterminated_nft_set_is_reported() {
+        let result = parse(ImportFormat::Nft, "add rule inet filter input tcp dport { 22, 80 accept");
+        assert!(result.rules.is_empty());
+        assert_eq!(result.unconverted.len(), 1);
+    }
+
+    #[test]
+    fn iptables_port_lists_stay_one_value() {
+        let content = "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -p tcp -m multiport --dports 22,80,443 -m comment --comment \"web, ssh\" -j ACCEPT\nCOMMIT\n";
+        let result = parse(ImportFormat::Iptables, content);
+        assert!(result.unconverted.is_empty(), "{:?}", result.unconverted);
+        let spec = &result.rules[0].1;
+        assert_eq!(spec.ports, vec![22, 80, 443]);
+        assert_eq!(spec.description, "web, ssh");
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::sync::{watch, Semaphore};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::{info, warn};
+
+use crate::assets::AssetManager;
+use crate::config::FleetConfig;
+use crate::models::{Asset, AssetType};
+use crate::request_trace;
+use crate::scripts::{self, PreflightResult, RemoteTarget, Script, ScriptOutputFormat, ScriptsManager};
+use crate::security::{AuditStatus, SecurityManager};
+use crate::sites::SiteScope;
+use crate::tags::TagSelector;
+
+// Which assets a bulk execution runs on: explicit ids plus every asset matching the tag
+// selector and the type (when given)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AssetFilter {
+    #[serde(default)]
+    pub ids: Vec<Uuid>,
+    #[serde(default)]
+    pub tags: TagSelector,
+    pub asset_type: Option<AssetType>,
+}
+
+impl AssetFilter {
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.tags.is_empty() && self.asset_type.is_none()
+    }
+
+    fn matches(&self, asset: &Asset) -> bool {
+        if self.ids.contains(&asset.id) {
+            return true;
+        }
+        if self.tags.is_empty() && self.asset_type.is_none() {
+            return false;
+        }
+
+        self.tags.matches(&asset.tags)
+            && self.asset_type.as_ref().map_or(true, |t| *t == asset.asset_type)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum TargetStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    // The asset has no address to connect to, or fails the script's pre-flight
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchTarget {
+    pub asset_id: Uuid,
+    pub asset_name: String,
+    // Site of the asset when the batch started, which decides who sees the target
+    pub site_id: Option<Uuid>,
+    pub address: Option<String>,
+    pub status: TargetStatus,
+    pub execution_id: Option<Uuid>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub pending: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub skipped: usize,
+}
+
+// One script executed on a set of assets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptBatch {
+    pub id: Uuid,
+    pub script_id: Uuid,
+    pub script_name: String,
+    pub output_format: ScriptOutputFormat,
+    pub filter: AssetFilter,
+    pub arguments: HashMap<String, String>,
+    pub concurrency: usize,
+    pub requested_by: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub cancelled_by: Option<String>,
+    // Audit event of the whole batch, parent of the per-target events
+    pub audit_event_id: Uuid,
+    pub targets: Vec<BatchTarget>,
+    pub summary: BatchSummary,
+}
+
+impl ScriptBatch {
+    fn summarize(&mut self) {
+        let count = |status: TargetStatus| self.targets.iter().filter(|t| t.status == status).count();
+        self.summary = BatchSummary {
+            total: self.targets.len(),
+            pending: count(TargetStatus::Pending),
+            running: count(TargetStatus::Running),
+            succeeded: count(TargetStatus::Succeeded),
+            failed: count(TargetStatus::Failed),
+            cancelled: count(TargetStatus::Cancelled),
+            skipped: count(TargetStatus::Skipped),
+        };
+    }
+
+    // The batch as seen from the scope: only the targets at its sites, None when there
+    // are none
+    fn scoped(mut self, scope: &SiteScope) -> Option<ScriptBatch> {
+        self.targets.retain(|target| scope.allows(target.site_id));
+        if self.targets.is_empty() {
+            return None;
+        }
+        self.summarize();
+        Some(self)
+    }
+}
+
+// Fans script executions out over assets with a concurrency limit
+#[derive(Clone)]
+pub struct FleetRunner {
+    config: FleetConfig,
+    scripts: Arc<Mutex<ScriptsManager>>,
+    assets: AssetManager,
+    security: SecurityManager,
+    batches: Arc<Mutex<HashMap<Uuid, ScriptBatch>>>,
+    cancels: Arc<Mutex<HashMap<Uuid, watch::Sender<bool>>>>,
+}
+
+impl FleetRunner {
+    pub fn new(config: FleetConfig,
+               scripts: Arc<Mutex<ScriptsManager>>,
+               assets: AssetManager,
+               security: SecurityManager) -> Self {
+        Self {
+            config,
+            scripts,
+            assets,
+            security,
+            batches: Arc::new(Mutex::new(HashMap::new())),
+            cancels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn start_batch(&self,
+                       script_id: Uuid,
+                       filter: AssetFilter,
+                       arguments: HashMap<String, String>,
+                       concurrency: Option<usize>,
+                       requested_by: &str,
+                       scope: &SiteScope) -> Result<ScriptBatch> {
+        if filter.is_empty() {
+            return Err(anyhow!("The asset filter must name ids, tags or a type"));
+        }
+
+        let (script, resolved) = match self.scripts.lock() {
+            Ok(scripts) => scripts.prepare_execution(script_id, &arguments)?,
+            Err(_) => return Err(anyhow!("Failed to acquire lock on scripts")),
+        };
+
+        let assets: Vec<Asset> = self.assets.get_all_assets()?
+            .into_iter()
+            .filter(|asset| filter.matches(asset) && scope.allows(asset.site_id))
+            .collect();
+        if assets.is_empty() {
+            return Err(anyhow!("No assets match the filter"));
+        }
+
+        let concurrency = concurrency.unwrap_or(self.config.max_concurrency)
+            .clamp(1, self.config.max_concurrency.max(1));
+        let audit_event_id = self.security.log_nested_audit_event(
+            None,
+            requested_by,
+            "script:execute_bulk",
+            &script_id.to_string(),
+            AuditStatus::Success,
+            Some(format!("{} on {} assets", script.name, assets.len())),
+        );
+
+        let mut batch = ScriptBatch {
+            id: Uuid::new_v4(),
+            script_id,
+            script_name: script.name.clone(),
+            output_format: script.output_format.clone(),
+            filter,
+            arguments,
+            concurrency,
+            requested_by: requested_by.to_string(),
+            created_at: Utc::now(),
+            finished_at: None,
+            cancelled_by: None,
+            audit_event_id,
+            targets: assets.iter()
+                .map(|asset| BatchTarget {
+                    asset_id: asset.id,
+                    asset_name: asset.name.clone(),
+                    site_id: asset.site_id,
+                    address: asset.ip_address.clone(),
+                    status: if asset.ip_address.is_some() { TargetStatus::Pending } else { TargetStatus::Skipped },
+                    execution_id: None,
+                    started_at: None,
+                    finished_at: None,
+                    error: if asset.ip_address.is_some() { None } else { Some("Asset has no IP address".to_string()) },
+                })
+                .collect(),
+            summary: BatchSummary::default(),
+        };
+        batch.summarize();
+
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        match (self.batches.lock(), self.cancels.lock()) {
+            (Ok(mut batches), Ok(mut cancels)) => {
+                batches.insert(batch.id, batch.clone());
+                cancels.insert(batch.id, cancel_tx);
+            },
+            _ => return Err(anyhow!("Failed to acquire lock on script batches")),
+        }
+
+        info!("Started batch {} of script {} on {} assets", batch.id, script.name, batch.targets.len());
+
+        let runner = self.clone();
+        let batch_id = batch.id;
+        request_trace::spawn(async move {
+            runner.run_batch(batch_id, script, resolved, cancel_rx).await;
+        });
+
+        Ok(batch)
+    }
+
+    async fn run_batch(&self,
+                       batch_id: Uuid,
+                       script: Script,
+                       arguments: Vec<(String, String)>,
+                       cancel: watch::Receiver<bool>) {
+        let (targets, concurrency) = match self.get_batch(batch_id, &SiteScope::All) {
+            Ok(batch) => (batch.targets, batch.concurrency),
+            Err(e) => {
+                warn!("Batch {} disappeared before it ran: {}", batch_id, e);
+                return;
+            },
+        };
+
+        let semaphore = Arc::new(Semaphore::new(concurrency));
+        let script = Arc::new(script);
+        let arguments = Arc::new(arguments);
+        let mut handles = Vec::new();
+
+        for (index, target) in targets.into_iter().enumerate() {
+            let address = match (target.status, target.address) {
+                (TargetStatus::Pending, Some(address)) => address,
+                _ => continue,
+            };
+
+            let runner = self.clone();
+            let semaphore = semaphore.clone();
+            let script = script.clone();
+            let arguments = arguments.clone();
+            let mut cancel = cancel.clone();
+
+            handles.push(request_trace::spawn(async move {
+                // Targets that have not started when the batch is cancelled never start
+                let permit = tokio::select! {
+                    permit = semaphore.acquire_owned() => permit.ok(),
+                    _ = cancel.wait_for(|cancelled| *cancelled) => None,
+                };
+                if permit.is_none() || *cancel.borrow() {
+                    runner.finish_target(batch_id, index, TargetStatus::Cancelled, None, None);
+                    return;
+                }
+
+                runner.update_target(batch_id, index, |t| {
+                    t.status = TargetStatus::Running;
+                    t.started_at = Some(Utc::now());
+                });
+
+                let remote = runner.remote_target(address);
+                let requested_by = runner.get_batch(batch_id, &SiteScope::All).map(|b| b.requested_by).unwrap_or_default();
+                let timeout = Duration::from_secs(runner.config.execution_timeout_secs);
+
+                // Dropping the execution future kills the ssh process (best effort on the target)
+                tokio::select! {
+                    result = tokio::time::timeout(timeout, scripts::execute_remote(&script, &arguments, &remote, requested_by)) => {
+                        match result {
+                            Ok(result) => {
+                                // A failed pre-flight means the script never ran there
+                                let status = if result.success {
+                                    TargetStatus::Succeeded
+                                } else if result.preflight.as_ref().map_or(false, |p| !p.passed) {
+                                    TargetStatus::Skipped
+                                } else {
+                                    TargetStatus::Failed
+                                };
+                                let error = result.error.clone().filter(|_| !result.success);
+                                let execution_id = result.id;
+                                if let Ok(mut scripts) = runner.scripts.lock() {
+                                    scripts.record_execution(result);
+                                }
+                                runner.finish_target(batch_id, index, status, Some(execution_id), error);
+                            },
+                            Err(_) => runner.finish_target(batch_id, index, TargetStatus::Failed, None,
+                                Some(format!("Timed out after {} seconds", timeout.as_secs()))),
+                        }
+                    },
+                    _ = cancel.wait_for(|cancelled| *cancelled) => {
+                        runner.finish_target(batch_id, index, TargetStatus::Cancelled, None,
+                            Some("Killed by cancellation".to_string()));
+                    },
+                }
+            }));
+        }
+
+        // A failed target never aborts the others
+        for handle in handles {
+            if let Err(e) = handle.await {
+                warn!("Target task of batch {} panicked: {}", batch_id, e);
+            }
+        }
+
+        if let Ok(mut cancels) = self.cancels.lock() {
+            cancels.remove(&batch_id);
+        }
+        if let Ok(mut batches) = self.batches.lock() {
+            if let Some(batch) = batches.get_mut(&batch_id) {
+                batch.finished_at = Some(Utc::now());
+                batch.summarize();
+                info!("Batch {} finished: {} succeeded, {} failed, {} cancelled, {} skipped",
+                      batch_id, batch.summary.succeeded, batch.summary.failed,
+                      batch.summary.cancelled, batch.summary.skipped);
+            }
+        }
+    }
+
+    fn remote_target(&self, address: String) -> RemoteTarget {
+        RemoteTarget {
+            address,
+            ssh_user: self.config.ssh_user.clone(),
+            identity_file: self.config.ssh_identity_file.clone(),
+            connect_timeout_secs: self.config.connect_timeout_secs,
+        }
+    }
+
+    // Checks a script's dependencies on an asset, or on this machine without one,
+    // without running the script. Unapproved scripts can be checked too.
+    pub async fn preflight(&self, script_id: Uuid, asset_id: Option<Uuid>) -> Result<PreflightResult> {
+        let script = match self.scripts.lock() {
+            Ok(scripts) => scripts.get_script(script_id)
+                .ok_or_else(|| anyhow!("Script not found: {}", script_id))?,
+            Err(_) => return Err(anyhow!("Failed to acquire lock on scripts")),
+        };
+
+        let asset_id = match asset_id {
+            Some(asset_id) => asset_id,
+            None => {
+                let dependencies = script.dependencies.clone();
+                return Ok(request_trace::spawn_blocking(move || scripts::preflight_local(&dependencies)).await?);
+            },
+        };
+
+        let asset = self.assets.get_asset(asset_id)?;
+        let address = asset.ip_address
+            .ok_or_else(|| anyhow!("Asset {} has no IP address", asset.name))?;
+        let timeout = Duration::from_secs(self.config.execution_timeout_secs);
+
+        match tokio::time::timeout(timeout, scripts::preflight_remote(&script.dependencies, &self.remote_target(address))).await {
+            Ok(result) => Ok(result),
+            Err(_) => Err(anyhow!("Pre-flight timed out after {} seconds", timeout.as_secs())),
+        }
+    }
+
+    fn update_target<F: FnOnce(&mut BatchTarget)>(&self, batch_id: Uuid, index: usize, update: F) -> Option<ScriptBatch> {
+        let mut batches = self.batches.lock().ok()?;
+        let batch = batches.get_mut(&batch_id)?;
+        update(batch.targets.get_mut(index)?);
+        batch.summarize();
+        Some(batch.clone())
+    }
+
+    // Records the outcome of a target as a child of the batch's audit event
+    fn finish_target(&self,
+                     batch_id: Uuid,
+                     index: usize,
+                     status: TargetStatus,
+                     execution_id: Option<Uuid>,
+                     error: Option<String>) {
+        let batch = self.update_target(batch_id, index, |t| {
+            t.status = status;
+            t.execution_id = execution_id;
+            t.finished_at = Some(Utc::now());
+            t.error = error.clone();
+        });
+
+        if let Some(batch) = batch {
+            let target = &batch.targets[index];
+            self.security.log_nested_audit_event(
+                Some(batch.audit_event_id),
+                &batch.requested_by,
+                "script:execute_bulk_target",
+                &format!("{}@{}", batch.script_id, target.asset_name),
+                match status {
+                    TargetStatus::Succeeded => AuditStatus::Success,
+                    TargetStatus::Cancelled => AuditStatus::Warning,
+                    _ => AuditStatus::Failure,
+                },
+                error.or_else(|| execution_id.map(|id| format!("execution {}", id))),
+            );
+        }
+    }
+
+    // Stops targets that have not started and kills the running ones
+    pub fn cancel_batch(&self, batch_id: Uuid, cancelled_by: &str) -> Result<ScriptBatch> {
+        let batch = match self.batches.lock() {
+            Ok(mut batches) => {
+                let batch = batches.get_mut(&batch_id)
+                    .ok_or_else(|| anyhow!("Batch not found: {}", batch_id))?;
+                if batch.finished_at.is_some() {
+                    return Err(anyhow!("Batch {} has already finished", batch_id));
+                }
+                batch.cancelled_by = Some(cancelled_by.to_string());
+                batch.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on script batches")),
+        };
+
+        match self.cancels.lock() {
+            Ok(cancels) => {
+                if let Some(cancel) = cancels.get(&batch_id) {
+                    let _ = cancel.send(true);
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on script batches")),
+        }
+
+        self.security.log_nested_audit_event(
+            Some(batch.audit_event_id),
+            cancelled_by,
+            "script:execute_bulk_cancel",
+            &batch_id.to_string(),
+            AuditStatus::Success,
+            None,
+        );
+        info!("Batch {} cancelled by {}", batch_id, cancelled_by);
+        Ok(batch)
+    }
+
+    pub fn get_batch(&self, batch_id: Uuid, scope: &SiteScope) -> Result<ScriptBatch> {
+        match self.batches.lock() {
+            Ok(batches) => batches.get(&batch_id)
+                .cloned()
+                .and_then(|batch| batch.scoped(scope))
+                .ok_or_else(|| anyhow!("Batch not found: {}", batch_id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on script batches")),
+        }
+    }
+
+    pub fn get_batches(&self, script_id: Option<Uuid>, scope: &SiteScope) -> Result<Vec<ScriptBatch>> {
+        match self.batches.lock() {
+            Ok(batches) => {
+                let mut all: Vec<ScriptBatch> = batches.values()
+                    .filter(|b| script_id.map_or(true, |id| b.script_id == id))
+                    .filter_map(|b| b.clone().scoped(scope))
+                    .collect();
+                all.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on script batches")),
+        }
+    }
+
+    // JSON Lines records of every successful target in the scope, each tagged with its asset
+    pub fn combined_output(&self, batch_id: Uuid, scope: &SiteScope) -> Result<Vec<serde_json::Value>> {
+        let batch = self.get_batch(batch_id, scope)?;
+        if batch.output_format != ScriptOutputFormat::JsonLines {
+            return Err(anyhow!("Script {} does not produce JSON Lines output", batch.script_name));
+        }
+
+        let scripts = self.scripts.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on scripts"))?;
+
+        let mut records = Vec::new();
+        for target in &batch.targets {
+            let result = match target.execution_id.and_then(|id| scripts.get_execution_result(id)) {
+                Some(result) => result,
+                None => continue,
+            };
+
+            for record in result.structured_output {
+                let mut record = match record {
+                    serde_json::Value::Object(map) => map,
+                    other => {
+                        let mut map = serde_json::Map::new();
+                        map.insert("value".to_string(), other);
+                        map
+                    },
+                };
+                record.insert("asset_id".to_string(), serde_json::json!(target.asset_id));
+                record.insert("asset_name".to_string(), serde_json::json!(target.asset_name));
+                records.push(serde_json::Value::Object(record));
+            }
+        }
+
+        Ok(records)
+    }
+}
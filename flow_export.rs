@@ -0,0 +1,254 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::config::FlowExportConfig;
+use crate::sites::SiteScope;
+use crate::visualizations::{TrafficFlow, VisualizationManager};
+
+const CHUNK_SIZE: usize = 500;
+
+// Column order of the CSV export; never reorder, only append
+const CSV_COLUMNS: [&str; 7] = ["timestamp", "source", "destination", "protocol", "port", "bytes", "packets"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl FlowExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FlowExportFormat::Csv => "text/csv",
+            FlowExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FlowExportFormat::Csv => "csv",
+            FlowExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    fn header(&self) -> Option<String> {
+        match self {
+            FlowExportFormat::Csv => Some(format!("{}\n", CSV_COLUMNS.join(","))),
+            FlowExportFormat::Jsonl => None,
+        }
+    }
+
+    fn format_flow(&self, flow: &TrafficFlow) -> String {
+        match self {
+            FlowExportFormat::Csv => format!(
+                "{},{},{},{},{},{},{}\n",
+                flow.timestamp.to_rfc3339(),
+                csv_field(&flow.source),
+                csv_field(&flow.destination),
+                csv_field(&flow.protocol),
+                flow.port,
+                flow.bytes,
+                flow.packets,
+            ),
+            FlowExportFormat::Jsonl => {
+                let mut line = serde_json::to_string(flow).unwrap_or_default();
+                line.push('\n');
+                line
+            },
+        }
+    }
+}
+
+// Quotes a CSV field when it contains a separator, quote or line break (RFC 4180)
+pub fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Streams the stored flows of the window and scope chunk by chunk, holding the store
+// lock only while a chunk is copied. Flows arriving after the request started are not
+// included.
+pub fn export_stream(manager: Arc<VisualizationManager>,
+                     format: FlowExportFormat,
+                     from: Option<DateTime<Utc>>,
+                     to: Option<DateTime<Utc>>,
+                     scope: SiteScope) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let until_seq = manager.next_flow_seq();
+    let header = format.header().map(|h| Ok::<_, std::io::Error>(Bytes::from(h)));
+
+    let body = stream::unfold(Some(0u64), move |cursor| {
+        let manager = manager.clone();
+        let scope = scope.clone();
+        async move {
+            let seq = cursor?;
+            let (flows, next) = manager.get_traffic_flows_chunk(seq, until_seq, from, to, &scope, CHUNK_SIZE);
+
+            if flows.is_empty() {
+                return None;
+            }
+
+            let chunk: String = flows.iter().map(|f| format.format_flow(f)).collect();
+            Some((Ok(Bytes::from(chunk)), Some(next)))
+        }
+    });
+
+    stream::iter(header).chain(body)
+}
+
+struct ExportFile {
+    path: PathBuf,
+    file: File,
+    opened_at: DateTime<Utc>,
+    size: u64,
+}
+
+struct ExporterState {
+    current: Option<ExportFile>,
+    next_seq: u64,
+}
+
+// Continuous export: appends new flows to rotating files under <log_dir>/flows
+#[derive(Clone)]
+pub struct FlowExporter {
+    config: FlowExportConfig,
+    dir: PathBuf,
+    state: Arc<Mutex<ExporterState>>,
+}
+
+impl FlowExporter {
+    pub fn new(config: FlowExportConfig, log_dir: &str) -> Result<Self> {
+        let dir = PathBuf::from(log_dir).join("flows");
+
+        fs::create_dir_all(&dir)
+            .context(format!("Failed to create flow export directory: {:?}", dir))?;
+
+        Ok(Self {
+            config,
+            dir,
+            state: Arc::new(Mutex::new(ExporterState {
+                current: None,
+                next_seq: 0,
+            })),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_seconds.max(1))
+    }
+
+    fn open_file(&self) -> Result<ExportFile> {
+        let opened_at = Utc::now();
+        let path = self.dir.join(format!(
+            "flows-{}.{}",
+            opened_at.format("%Y%m%dT%H%M%S"),
+            self.config.format.extension(),
+        ));
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("Failed to open flow export file: {:?}", path))?;
+
+        let mut size = 0;
+        if let Some(header) = self.config.format.header() {
+            file.write_all(header.as_bytes())?;
+            size += header.len() as u64;
+        }
+
+        info!("Writing flow export to {:?}", path);
+        Ok(ExportFile { path, file, opened_at, size })
+    }
+
+    fn needs_rotation(&self, current: &ExportFile) -> bool {
+        current.size >= self.config.rotate_size_mb * 1024 * 1024
+            || Utc::now() - current.opened_at >= chrono::Duration::minutes(self.config.rotate_minutes as i64)
+    }
+
+    // Writes flows collected since the previous run, then rotates and prunes files
+    pub fn run_once(&self, manager: &VisualizationManager) -> Result<()> {
+        let mut state = self.state.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on flow exporter"))?;
+
+        if state.current.as_ref().map_or(true, |current| self.needs_rotation(current)) {
+            state.current = Some(self.open_file()?);
+        }
+
+        let until_seq = manager.next_flow_seq();
+        let mut seq = state.next_seq;
+
+        loop {
+            let (flows, next) = manager.get_traffic_flows_chunk(seq, until_seq, None, None, &SiteScope::All, CHUNK_SIZE);
+            seq = next;
+
+            if flows.is_empty() {
+                break;
+            }
+
+            let chunk: String = flows.iter().map(|f| self.config.format.format_flow(f)).collect();
+
+            if let Some(current) = state.current.as_mut() {
+                current.file.write_all(chunk.as_bytes())
+                    .context(format!("Failed to write flow export file: {:?}", current.path))?;
+                current.size += chunk.len() as u64;
+            }
+        }
+
+        state.next_seq = seq;
+        drop(state);
+
+        self.prune()
+    }
+
+    fn prune(&self) -> Result<()> {
+        self.prune_older_than(Duration::from_secs(self.config.retention_days as u64 * 24 * 60 * 60))?;
+        Ok(())
+    }
+
+    // Deletes export files not modified within `max_age`, except the one being written
+    pub fn prune_older_than(&self, max_age: Duration) -> Result<usize> {
+        let cutoff = std::time::SystemTime::now() - max_age;
+        let current = match self.state.lock() {
+            Ok(state) => state.current.as_ref().map(|c| c.path.clone()),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on flow exporter")),
+        };
+        let mut removed = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if Some(&path) == current.as_ref() {
+                continue;
+            }
+
+            let expired = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .map(|modified| modified < cutoff)
+                .unwrap_or(false);
+
+            if expired {
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        info!("Removed expired flow export {:?}", path);
+                        removed += 1;
+                    },
+                    Err(e) => warn!("Failed to remove expired flow export {:?}: {}", path, e),
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+}
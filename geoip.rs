@@ -0,0 +1,102 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use ipnetwork::IpNetwork;
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::config::GeoIpConfig;
+
+// Where an address is, as far as the GeoIP database knows. The coordinates are the
+// city centroid, or the country centroid when the city is unknown.
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoLocation {
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// GeoIP enrichment backed by a MaxMind City database (GeoLite2-City.mmdb or compatible)
+#[derive(Clone)]
+pub struct GeoIpResolver {
+    reader: Option<Arc<Reader<Vec<u8>>>>,
+    internal_networks: Vec<IpNetwork>,
+}
+
+impl GeoIpResolver {
+    pub fn new(config: &GeoIpConfig) -> Result<Self> {
+        let internal_networks = config.internal_networks.iter()
+            .map(|network| IpNetwork::from_str(network)
+                .map_err(|e| anyhow!("Invalid internal network {}: {}", network, e)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Without a database every external address resolves to "unknown"
+        let reader = match &config.database_path {
+            Some(path) => {
+                let reader = Reader::open_readfile(path)
+                    .context(format!("Failed to open GeoIP database: {}", path))?;
+                info!("Loaded GeoIP database {} ({})", path, reader.metadata.database_type);
+                Some(Arc::new(reader))
+            },
+            None => {
+                warn!("No GeoIP database configured, external addresses will not be located");
+                None
+            },
+        };
+
+        Ok(Self {
+            reader,
+            internal_networks,
+        })
+    }
+
+    // Private, loopback, link-local, CGNAT and unique local ranges plus the configured networks
+    pub fn is_internal(&self, ip: IpAddr) -> bool {
+        let builtin = match ip {
+            IpAddr::V4(v4) => v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || (v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64),
+            IpAddr::V6(v6) => v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (v6.segments()[0] & 0xfe00) == 0xfc00
+                || (v6.segments()[0] & 0xffc0) == 0xfe80,
+        };
+
+        builtin || self.internal_networks.iter().any(|network| network.contains(ip))
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        let reader = self.reader.as_ref()?;
+        let city: geoip2::City = reader.lookup(ip).ok()?;
+
+        let location = city.location.as_ref()?;
+        let (latitude, longitude) = (location.latitude?, location.longitude?);
+
+        let english = |names: &Option<std::collections::BTreeMap<&str, &str>>| names.as_ref()
+            .and_then(|names| names.get("en"))
+            .map(|name| name.to_string());
+
+        Some(GeoLocation {
+            country_code: city.country.as_ref().and_then(|c| c.iso_code).map(|c| c.to_string()),
+            country: city.country.as_ref().and_then(|c| english(&c.names)),
+            city: city.city.as_ref().and_then(|c| english(&c.names)),
+            latitude,
+            longitude,
+        })
+    }
+}
+
+// Flow endpoints are plain addresses or address:port pairs
+pub fn parse_endpoint(endpoint: &str) -> Option<IpAddr> {
+    endpoint.parse::<IpAddr>().ok()
+        .or_else(|| endpoint.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+}
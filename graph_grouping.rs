@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use geo::{Contains, LineString, Point};
+use ipnetwork::IpNetwork;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow};
+
+use crate::visualizations::{NetworkGraph, NetworkLink, NetworkNode, NodeType};
+
+// Property a group node carries, so the frontend can tell it from a real node
+pub const GROUP_PROPERTY: &str = "group";
+
+// What nodes are grouped by; anything other than zone or subnet names a node property
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupBy {
+    Zone,
+    Subnet,
+    Property(String),
+}
+
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" => Err(anyhow!("collapse must name zone, subnet or a node property")),
+            "zone" => Ok(GroupBy::Zone),
+            "subnet" => Ok(GroupBy::Subnet),
+            property => Ok(GroupBy::Property(property.to_string())),
+        }
+    }
+}
+
+impl GroupBy {
+    fn as_str(&self) -> &str {
+        match self {
+            GroupBy::Zone => "zone",
+            GroupBy::Subnet => "subnet",
+            GroupBy::Property(name) => name,
+        }
+    }
+}
+
+// Query parameters shared by the graph, the expand endpoint and the diagram exports
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GroupingQuery {
+    pub collapse: Option<String>,
+    // Groups are collapsed, largest first, until the graph has at most this many nodes;
+    // without it every group of two or more nodes is collapsed
+    pub max_nodes: Option<usize>,
+}
+
+impl GroupingQuery {
+    pub fn group_by(&self) -> Result<Option<GroupBy>> {
+        self.collapse.as_deref().map(GroupBy::from_str).transpose()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupMembers {
+    pub group_id: String,
+    pub nodes: Vec<NetworkNode>,
+    // Links touching a member, with their original endpoints
+    pub links: Vec<NetworkLink>,
+}
+
+pub fn group_id(by: &GroupBy, key: &str) -> String {
+    format!("group:{}:{}", by.as_str(), key)
+}
+
+// The group a node belongs to, None for nodes that stay on their own. A node in several
+// zones goes with the first one.
+fn group_key(graph: &NetworkGraph, node: &NetworkNode, by: &GroupBy) -> Option<String> {
+    match by {
+        GroupBy::Zone => graph.zones.iter()
+            .find(|zone| zone.boundary.contains(&node.position))
+            .map(|zone| zone.name.clone()),
+        GroupBy::Subnet => node.properties.get("ip_address_0")
+            .and_then(|address| IpNetwork::from_str(address).ok())
+            .and_then(|net| IpNetwork::new(net.network(), net.prefix()).ok())
+            .map(|net| net.to_string()),
+        GroupBy::Property(name) => node.properties.get(name)
+            .filter(|value| !value.is_empty())
+            .cloned(),
+    }
+}
+
+// Members of each group by key, in graph order
+fn groups(graph: &NetworkGraph, by: &GroupBy) -> BTreeMap<String, Vec<usize>> {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, node) in graph.nodes.iter().enumerate() {
+        if let Some(key) = group_key(graph, node, by) {
+            groups.entry(key).or_default().push(index);
+        }
+    }
+    groups
+}
+
+fn link_weight(link: &NetworkLink) -> f64 {
+    link.properties.get("weight")
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(1.0)
+}
+
+fn group_node(by: &GroupBy, key: &str, members: &[&NetworkNode]) -> NetworkNode {
+    let count = members.len() as f64;
+    let x = members.iter().map(|n| n.position.x()).sum::<f64>() / count;
+    let y = members.iter().map(|n| n.position.y()).sum::<f64>() / count;
+
+    // The most common member type, so a group of servers still looks like servers
+    let mut types: HashMap<String, (usize, NodeType)> = HashMap::new();
+    for member in members {
+        types.entry(format!("{:?}", member.node_type))
+            .or_insert((0, member.node_type.clone())).0 += 1;
+    }
+    let node_type = types.into_values()
+        .max_by_key(|(n, _)| *n)
+        .map(|(_, t)| t)
+        .unwrap_or(NodeType::Switch);
+
+    let mut properties = HashMap::new();
+    properties.insert(GROUP_PROPERTY.to_string(), "true".to_string());
+    properties.insert("group_by".to_string(), by.as_str().to_string());
+    properties.insert("group_key".to_string(), key.to_string());
+    properties.insert("member_count".to_string(), members.len().to_string());
+    // Down when any member is, so a failure inside a group stays visible
+    let down = members.iter().any(|n| n.properties.get("is_up").map_or(false, |v| v == "false"));
+    properties.insert("is_up".to_string(), (!down).to_string());
+
+    NetworkNode {
+        id: group_id(by, key),
+        name: format!("{} ({})", key, members.len()),
+        node_type,
+        position: Point::new(x, y),
+        properties,
+    }
+}
+
+// Collapses groups into single nodes. Links to members are re-pointed to their group,
+// links inside a group dropped and parallel links merged with their weights summed.
+pub fn collapse(graph: &NetworkGraph, by: &GroupBy, max_nodes: Option<usize>) -> NetworkGraph {
+    let mut candidates: Vec<(String, Vec<usize>)> = groups(graph, by).into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .collect();
+    // Largest first; ties by key so the result is stable
+    candidates.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+
+    let mut node_count = graph.nodes.len();
+    let mut collapsed = Vec::new();
+    for (key, members) in candidates {
+        if max_nodes.map_or(false, |max| node_count <= max) {
+            break;
+        }
+        node_count -= members.len() - 1;
+        collapsed.push((key, members));
+    }
+
+    if collapsed.is_empty() {
+        return graph.clone();
+    }
+
+    let mut replaced: HashMap<&str, String> = HashMap::new();
+    let mut group_nodes = Vec::new();
+    for (key, members) in &collapsed {
+        let member_nodes: Vec<&NetworkNode> = members.iter().map(|&i| &graph.nodes[i]).collect();
+        let node = group_node(by, key, &member_nodes);
+        for member in &member_nodes {
+            replaced.insert(member.id.as_str(), node.id.clone());
+        }
+        group_nodes.push(node);
+    }
+
+    let mut nodes: Vec<NetworkNode> = graph.nodes.iter()
+        .filter(|n| !replaced.contains_key(n.id.as_str()))
+        .cloned()
+        .collect();
+    nodes.extend(group_nodes);
+
+    let endpoint = |id: &str| replaced.get(id).cloned().unwrap_or_else(|| id.to_string());
+    let position = |id: &str| nodes.iter()
+        .find(|n| n.id == id)
+        .map(|n| n.position)
+        .unwrap_or_else(|| Point::new(0.0, 0.0));
+
+    let mut links: Vec<NetworkLink> = Vec::new();
+    let mut merged: HashMap<(String, String), usize> = HashMap::new();
+    for link in &graph.links {
+        let (source, target) = (endpoint(&link.source_id), endpoint(&link.target_id));
+        if source == target {
+            continue;
+        }
+        if !replaced.contains_key(link.source_id.as_str()) && !replaced.contains_key(link.target_id.as_str()) {
+            links.push(link.clone());
+            continue;
+        }
+
+        match merged.get(&(source.clone(), target.clone())) {
+            Some(&index) => {
+                let existing = &mut links[index];
+                let weight = link_weight(existing) + link_weight(link);
+                let count = existing.properties.get("link_count")
+                    .and_then(|c| c.parse::<usize>().ok())
+                    .unwrap_or(1) + 1;
+                existing.properties.insert("weight".to_string(), weight.to_string());
+                existing.properties.insert("link_count".to_string(), count.to_string());
+            },
+            None => {
+                let mut properties = HashMap::new();
+                properties.insert("weight".to_string(), link_weight(link).to_string());
+                properties.insert("link_count".to_string(), "1".to_string());
+                merged.insert((source.clone(), target.clone()), links.len());
+                links.push(NetworkLink {
+                    id: format!("{}->{}", source, target),
+                    path: LineString::from(vec![position(&source).x_y(), position(&target).x_y()]),
+                    source_id: source,
+                    target_id: target,
+                    link_type: link.link_type.clone(),
+                    properties,
+                });
+            },
+        }
+    }
+
+    NetworkGraph {
+        nodes,
+        links,
+        zones: graph.zones.clone(),
+    }
+}
+
+// The members of one group of the full graph, for expanding it on demand
+pub fn members(graph: &NetworkGraph, by: &GroupBy, key: &str) -> Result<GroupMembers> {
+    let indices = groups(graph, by).remove(key)
+        .ok_or_else(|| anyhow!("No {} group named {}", by.as_str(), key))?;
+    let nodes: Vec<NetworkNode> = indices.iter().map(|&i| graph.nodes[i].clone()).collect();
+    let links = graph.links.iter()
+        .filter(|l| nodes.iter().any(|n| n.id == l.source_id || n.id == l.target_id))
+        .cloned()
+        .collect();
+
+    Ok(GroupMembers {
+        group_id: group_id(by, key),
+        nodes,
+        links,
+    })
+}
@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::api::AppState;
+use crate::config::GraphSnapshotConfig;
+use crate::visualizations::{NetworkGraph, NetworkLink, NetworkNode};
+
+// The network graph and interface link states at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub graph: NetworkGraph,
+    // Interface name -> link up
+    pub interfaces: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub taken_at: DateTime<Utc>,
+    // Short content digest; consecutive snapshots always differ in it
+    pub digest: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PropertyChange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+// A node or link present in both snapshots whose properties differ
+#[derive(Debug, Clone, Serialize)]
+pub struct ElementChange {
+    pub id: String,
+    pub properties: BTreeMap<String, PropertyChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InterfaceChange {
+    pub name: String,
+    pub from: Option<bool>,
+    pub to: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotDiff {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub nodes_added: Vec<NetworkNode>,
+    pub nodes_removed: Vec<NetworkNode>,
+    pub nodes_changed: Vec<ElementChange>,
+    pub links_added: Vec<NetworkLink>,
+    pub links_removed: Vec<NetworkLink>,
+    pub links_changed: Vec<ElementChange>,
+    pub interfaces_changed: Vec<InterfaceChange>,
+}
+
+fn property_changes(from: &HashMap<String, String>, to: &HashMap<String, String>) -> BTreeMap<String, PropertyChange> {
+    let keys: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    keys.into_iter()
+        .filter(|k| from.get(*k) != to.get(*k))
+        .map(|k| (k.clone(), PropertyChange { from: from.get(k).cloned(), to: to.get(k).cloned() }))
+        .collect()
+}
+
+// Elements only in `to`, only in `from`, and the property changes of those in both
+fn diff_elements<T: Clone>(from: &[T],
+                           to: &[T],
+                           id: impl Fn(&T) -> &str,
+                           properties: impl Fn(&T) -> &HashMap<String, String>) -> (Vec<T>, Vec<T>, Vec<ElementChange>) {
+    let before: HashMap<&str, &T> = from.iter().map(|e| (id(e), e)).collect();
+    let after: HashMap<&str, &T> = to.iter().map(|e| (id(e), e)).collect();
+
+    let added = to.iter().filter(|e| !before.contains_key(id(e))).cloned().collect();
+    let removed = from.iter().filter(|e| !after.contains_key(id(e))).cloned().collect();
+    let changed = to.iter()
+        .filter_map(|e| before.get(id(e)).map(|old| ElementChange {
+            id: id(e).to_string(),
+            properties: property_changes(properties(old), properties(e)),
+        }))
+        .filter(|c| !c.properties.is_empty())
+        .collect();
+
+    (added, removed, changed)
+}
+
+pub fn diff(from: &GraphSnapshot, to: &GraphSnapshot) -> SnapshotDiff {
+    let (nodes_added, nodes_removed, nodes_changed) =
+        diff_elements(&from.graph.nodes, &to.graph.nodes, |n| n.id.as_str(), |n| &n.properties);
+    let (links_added, links_removed, links_changed) =
+        diff_elements(&from.graph.links, &to.graph.links, |l| l.id.as_str(), |l| &l.properties);
+
+    let names: BTreeSet<&String> = from.interfaces.keys().chain(to.interfaces.keys()).collect();
+    let interfaces_changed = names.into_iter()
+        .filter(|n| from.interfaces.get(*n) != to.interfaces.get(*n))
+        .map(|n| InterfaceChange {
+            name: n.clone(),
+            from: from.interfaces.get(n).copied(),
+            to: to.interfaces.get(n).copied(),
+        })
+        .collect();
+
+    SnapshotDiff {
+        from: from.taken_at,
+        to: to.taken_at,
+        nodes_added,
+        nodes_removed,
+        nodes_changed,
+        links_added,
+        links_removed,
+        links_changed,
+        interfaces_changed,
+    }
+}
+
+// Digest of the content; the graph is rendered through serde_json::Value first so the
+// property maps hash in key order
+fn content_digest(graph: &NetworkGraph, interfaces: &BTreeMap<String, bool>) -> Result<String> {
+    let canonical = serde_json::to_string(&serde_json::json!({
+        "graph": serde_json::to_value(graph)?,
+        "interfaces": interfaces,
+    }))?;
+    Ok(hex::encode(&Sha256::digest(canonical.as_bytes())[..8]))
+}
+
+// Snapshots are gzipped JSON files named <unix millis>-<digest>.json.gz, so the index
+// is rebuilt from the directory listing without reading any of them
+#[derive(Clone)]
+pub struct GraphSnapshotStore {
+    dir: PathBuf,
+    config: GraphSnapshotConfig,
+    index: Arc<Mutex<BTreeMap<DateTime<Utc>, SnapshotInfo>>>,
+}
+
+impl GraphSnapshotStore {
+    pub fn new(dir: &str, config: GraphSnapshotConfig) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create graph snapshot directory: {:?}", dir))?;
+            info!("Created graph snapshot directory: {:?}", dir);
+        }
+
+        let mut index = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let parsed = name.strip_suffix(".json.gz")
+                .and_then(|stem| stem.split_once('-'))
+                .and_then(|(millis, digest)| Some((Utc.timestamp_millis_opt(millis.parse().ok()?).single()?, digest.to_string())));
+            match parsed {
+                Some((taken_at, digest)) => {
+                    let size_bytes = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                    index.insert(taken_at, SnapshotInfo { taken_at, digest, size_bytes });
+                },
+                None => warn!("Skipping unexpected file in graph snapshot directory: {}", name),
+            }
+        }
+
+        info!("Loaded {} network graph snapshots", index.len());
+
+        Ok(Self {
+            dir,
+            config,
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.interval_secs.max(60))
+    }
+
+    fn path_of(&self, info: &SnapshotInfo) -> PathBuf {
+        self.dir.join(format!("{}-{}.json.gz", info.taken_at.timestamp_millis(), info.digest))
+    }
+
+    // Writes a snapshot unless nothing changed since the last one; returns it when written
+    pub fn take(&self, graph: NetworkGraph, interfaces: BTreeMap<String, bool>, now: DateTime<Utc>) -> Result<Option<SnapshotInfo>> {
+        let digest = content_digest(&graph, &interfaces)?;
+
+        let last = match self.index.lock() {
+            Ok(index) => index.values().next_back().map(|i| i.digest.clone()),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on graph snapshots")),
+        };
+        if last.as_deref() == Some(digest.as_str()) {
+            return Ok(None);
+        }
+
+        let snapshot = GraphSnapshot { taken_at: now, graph, interfaces };
+        let mut info = SnapshotInfo { taken_at: now, digest, size_bytes: 0 };
+        let path = self.path_of(&info);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(&snapshot)?)?;
+        let compressed = encoder.finish()?;
+        info.size_bytes = compressed.len() as u64;
+        fs::write(&path, compressed)
+            .context(format!("Failed to write graph snapshot: {:?}", path))?;
+
+        match self.index.lock() {
+            Ok(mut index) => {
+                index.insert(now, info.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on graph snapshots")),
+        }
+        Ok(Some(info))
+    }
+
+    // Removes snapshots past retention
+    pub fn prune(&self, now: DateTime<Utc>) -> Result<usize> {
+        self.prune_before(now - chrono::Duration::days(self.config.retention_days))
+    }
+
+    // Emergency purge while disk space is critical, see disk_monitor
+    pub fn prune_older_than(&self, max_age: std::time::Duration) -> Result<usize> {
+        self.prune_before(Utc::now() - chrono::Duration::from_std(max_age)?)
+    }
+
+    // The index is updated first and the files are deleted without holding the lock
+    fn prune_before(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let expired: Vec<SnapshotInfo> = match self.index.lock() {
+            Ok(mut index) => {
+                let kept = index.split_off(&cutoff);
+                std::mem::replace(&mut *index, kept).into_values().collect()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on graph snapshots")),
+        };
+
+        for info in &expired {
+            let path = self.path_of(info);
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to delete graph snapshot {:?}: {}", path, e);
+            }
+        }
+        if !expired.is_empty() {
+            info!("Pruned {} network graph snapshots", expired.len());
+        }
+        Ok(expired.len())
+    }
+
+    pub fn list(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<SnapshotInfo>> {
+        match self.index.lock() {
+            Ok(index) => Ok(index.values()
+                .filter(|i| from.map_or(true, |from| i.taken_at >= from) && to.map_or(true, |to| i.taken_at <= to))
+                .cloned()
+                .collect()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on graph snapshots")),
+        }
+    }
+
+    // The snapshot closest in time to the instant, on either side
+    pub fn nearest(&self, at: DateTime<Utc>) -> Result<GraphSnapshot> {
+        let info = match self.index.lock() {
+            Ok(index) => {
+                let before = index.range(..=at).next_back().map(|(_, i)| i);
+                let after = index.range(at..).next().map(|(_, i)| i);
+                match (before, after) {
+                    (Some(b), Some(a)) => if at - b.taken_at <= a.taken_at - at { b.clone() } else { a.clone() },
+                    (Some(i), None) | (None, Some(i)) => i.clone(),
+                    (None, None) => return Err(anyhow!("No network graph snapshots")),
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on graph snapshots")),
+        };
+
+        let path = self.path_of(&info);
+        let mut json = Vec::new();
+        GzDecoder::new(File::open(&path).context(format!("Failed to open graph snapshot: {:?}", path))?)
+            .read_to_end(&mut json)
+            .context(format!("Failed to decompress graph snapshot: {:?}", path))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+pub fn spawn_tasks(state: &Arc<AppState>) -> Result<()> {
+    let snapshots = state.clone();
+    state.task_registry.spawn("graph_snapshots", state.graph_snapshots.interval(), move || {
+        let state = snapshots.clone();
+        async move {
+            let mut interfaces = state.network_manager.get_interfaces().await?;
+            state.interface_metadata.apply(&mut interfaces)?;
+            state.link_flaps.mark(&mut interfaces)?;
+            state.visualization_manager.update_from_interfaces(&interfaces);
+
+            let graph = state.visualization_manager.get_network_graph();
+            let links = interfaces.iter().map(|i| (i.name.clone(), i.is_up)).collect();
+            let store = state.graph_snapshots.clone();
+
+            // File work stays off the runtime threads the collectors share
+            tokio::task::spawn_blocking(move || {
+                let now = Utc::now();
+                store.take(graph, links, now)?;
+                store.prune(now).map(|_| ())
+            }).await?
+        }
+    })
+}
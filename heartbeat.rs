@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::HeartbeatConfig;
+use crate::models::AlertSeverity;
+use crate::outbound::{self, HttpClients};
+
+// Source of the alert raised when the heartbeat target stays unreachable
+pub const ALERT_SOURCE: &str = "heartbeat";
+
+// First retry after a failed heartbeat; doubled per failure up to the interval
+const RETRY_BASE: Duration = Duration::from_secs(5);
+
+// Compact status sent with every heartbeat
+#[derive(Debug, Clone, Serialize)]
+pub struct HeartbeatSummary {
+    // "ok", or "degraded" when any component is not healthy
+    pub status: &'static str,
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    // Health of each component, false when degraded
+    pub components: BTreeMap<&'static str, bool>,
+    // Backlogs waiting to be written or processed
+    pub queues: BTreeMap<&'static str, u64>,
+}
+
+// Builds the summary from the live components and the time the heartbeat started, see main
+pub type SummarySource = Arc<dyn Fn(Instant) -> HeartbeatSummary + Send + Sync>;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HeartbeatStatus {
+    pub enabled: bool,
+    // What the heartbeat goes to, without any token in the URL
+    pub targets: Vec<String>,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    // Start of the current run of failures
+    pub failing_since: Option<DateTime<Utc>>,
+}
+
+// Pings an external monitor (a healthchecks.io style URL, a syslog collector or both)
+// so that the monitor notices when this service stops. Detecting a missed heartbeat is
+// the monitor's job; here the outcome is kept for /api/health and a target that stays
+// unreachable raises a local alert.
+#[derive(Clone)]
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    http: reqwest::Client,
+    alerts: AlertsManager,
+    summary: SummarySource,
+    started: Instant,
+    status: Arc<Mutex<HeartbeatStatus>>,
+    // Whether the current outage was alerted
+    alerted: Arc<Mutex<bool>>,
+}
+
+// Scheme, host and path of the URL; ping URLs often carry their token in the query
+fn describe_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => format!("{}://{}{}", url.scheme(), url.host_str().unwrap_or_default(), url.path()),
+        Err(_) => "invalid url".to_string(),
+    }
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig, alerts: AlertsManager, clients: &HttpClients, summary: SummarySource) -> Result<Self> {
+        let http = clients.client(outbound::HEARTBEAT, Duration::from_secs(config.timeout_secs.max(1)))?;
+        if config.enabled && config.url.is_none() && config.syslog.is_none() {
+            return Err(anyhow!("heartbeat is enabled but neither url nor syslog is set"));
+        }
+
+        let targets = config.url.iter().map(|u| describe_url(u))
+            .chain(config.syslog.iter().map(|s| format!("syslog://{}", s)))
+            .collect();
+        let status = HeartbeatStatus {
+            enabled: config.enabled,
+            targets,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            config,
+            http,
+            alerts,
+            summary,
+            started: Instant::now(),
+            status: Arc::new(Mutex::new(status)),
+            alerted: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn status(&self) -> Result<HeartbeatStatus> {
+        match self.status.lock() {
+            Ok(status) => Ok(status.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on heartbeat status")),
+        }
+    }
+
+    fn summary(&self) -> HeartbeatSummary {
+        (self.summary)(self.started)
+    }
+
+    async fn ping_url(&self, url: &str, summary: &HeartbeatSummary) -> Result<()> {
+        // Errors would otherwise carry the URL, token included
+        self.http.post(url)
+            .json(summary)
+            .send().await
+            .map_err(|e| e.without_url())
+            .context("Heartbeat request failed")?
+            .error_for_status()
+            .map_err(|e| e.without_url())
+            .context("Heartbeat rejected")?;
+        Ok(())
+    }
+
+    // RFC 5424 over UDP, daemon.info (or daemon.warning while degraded), with the
+    // summary as the message
+    async fn send_syslog(&self, peer: &str, summary: &HeartbeatSummary) -> Result<()> {
+        let severity = if summary.status == "ok" { 6 } else { 4 };
+        let hostname = std::fs::read_to_string("/etc/hostname")
+            .map(|h| h.trim().to_string())
+            .ok()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "-".to_string());
+        let message = format!(
+            "<{}>1 {} {} siem - heartbeat - {}",
+            3 * 8 + severity,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            hostname,
+            serde_json::to_string(summary)?,
+        );
+
+        let socket = UdpSocket::bind("0.0.0.0:0").await.context("Failed to open heartbeat socket")?;
+        socket.send_to(message.as_bytes(), peer).await
+            .context(format!("Failed to send heartbeat to {}", peer))?;
+        Ok(())
+    }
+
+    // One heartbeat to every target; fails when any target fails
+    pub async fn beat(&self) -> Result<()> {
+        let summary = self.summary();
+        let mut errors = Vec::new();
+        if let Some(url) = &self.config.url {
+            if let Err(e) = self.ping_url(url, &summary).await {
+                errors.push(format!("{}: {:#}", describe_url(url), e));
+            }
+        }
+        if let Some(peer) = &self.config.syslog {
+            if let Err(e) = self.send_syslog(peer, &summary).await {
+                errors.push(format!("syslog://{}: {:#}", peer, e));
+            }
+        }
+
+        let now = Utc::now();
+        let mut status = self.status.lock().map_err(|_| anyhow!("Failed to acquire lock on heartbeat status"))?;
+        status.last_attempt = Some(now);
+        if errors.is_empty() {
+            if status.failing_since.is_some() {
+                info!("Heartbeat target reachable again after {} failed attempts", status.consecutive_failures);
+            }
+            status.last_success = Some(now);
+            status.last_error = None;
+            status.consecutive_failures = 0;
+            status.failing_since = None;
+            drop(status);
+            if let Ok(mut alerted) = self.alerted.lock() {
+                *alerted = false;
+            }
+            return Ok(());
+        }
+
+        let error = errors.join("; ");
+        status.last_error = Some(error.clone());
+        status.consecutive_failures += 1;
+        let failing_since = *status.failing_since.get_or_insert(now);
+        drop(status);
+
+        let unreachable_secs = (now - failing_since).num_seconds();
+        if unreachable_secs >= self.config.unreachable_alert_secs as i64 {
+            self.raise_unreachable(failing_since, &error);
+        }
+        Err(anyhow!(error))
+    }
+
+    fn raise_unreachable(&self, since: DateTime<Utc>, error: &str) {
+        let Ok(mut alerted) = self.alerted.lock() else { return };
+        if *alerted {
+            return;
+        }
+        *alerted = true;
+
+        warn!("Heartbeat target unreachable since {}, the external monitor is not receiving heartbeats: {}", since, error);
+        if let Err(e) = self.alerts.create_alert(
+            AlertSeverity::Medium,
+            "Heartbeat target unreachable".to_string(),
+            format!("No heartbeat has been delivered since {}. The external monitor will report this service as down. Last error: {}", since, error),
+            ALERT_SOURCE.to_string(),
+            Vec::new(),
+        ) {
+            error!("Failed to raise heartbeat alert: {}", e);
+        }
+    }
+
+    // Beats every interval; after a failure retries sooner, backing off up to the interval
+    pub async fn run(self) {
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        info!("Sending heartbeats every {}s", interval.as_secs());
+        let mut retry = RETRY_BASE;
+        loop {
+            let delay = match self.beat().await {
+                Ok(()) => {
+                    retry = RETRY_BASE;
+                    interval
+                },
+                Err(e) => {
+                    warn!("Heartbeat failed, retrying in {}s: {}", retry.min(interval).as_secs(), e);
+                    let delay = retry.min(interval);
+                    retry = (retry * 2).min(interval);
+                    delay
+                },
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
@@ -0,0 +1,644 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+use anyhow::{Context, Result, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::assets::AssetManager;
+use crate::config::{FleetConfig, InventoryConfig};
+use crate::models::{AlertSeverity, Asset, AssetStatus, AssetType};
+use crate::scripts::{self, RemoteTarget};
+
+// The probes only read. Each prints one record per line, fields separated by tabs:
+//   os <version>
+//   listen <protocol> <address> <port> <process>
+//   service <name>
+//   packages <sha256 of the sorted package list> <count>
+//   error <message>, when something the inventory depends on is missing
+const WINDOWS_PROBE: &str = r#"$ErrorActionPreference = "SilentlyContinue"
+$os = Get-CimInstance Win32_OperatingSystem
+"os`t$($os.Caption) $($os.Version)"
+
+$processes = @{}
+Get-Process | ForEach-Object { $processes[$_.Id] = $_.ProcessName }
+Get-NetTCPConnection -State Listen | ForEach-Object {
+    "listen`ttcp`t$($_.LocalAddress)`t$($_.LocalPort)`t$($processes[[int]$_.OwningProcess])"
+}
+Get-NetUDPEndpoint | ForEach-Object {
+    "listen`tudp`t$($_.LocalAddress)`t$($_.LocalPort)`t$($processes[[int]$_.OwningProcess])"
+}
+
+Get-Service | Where-Object { $_.Status -eq "Running" } | ForEach-Object { "service`t$($_.Name)" }
+
+$packages = @(Get-ItemProperty "HKLM:\Software\Microsoft\Windows\CurrentVersion\Uninstall\*",
+                               "HKLM:\Software\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall\*" |
+    Where-Object { $_.DisplayName } |
+    ForEach-Object { "$($_.DisplayName) $($_.DisplayVersion)" } |
+    Sort-Object -Unique)
+$bytes = [Text.Encoding]::UTF8.GetBytes($packages -join "`n")
+$hash = -join ([Security.Cryptography.SHA256]::Create().ComputeHash($bytes) | ForEach-Object { $_.ToString("x2") })
+"packages`t$hash`t$($packages.Count)"
+"#;
+
+const POSIX_PROBE: &str = r#"LC_ALL=C
+export LC_ALL
+
+if [ -r /etc/os-release ]; then
+    . /etc/os-release
+fi
+printf 'os\t%s (%s)\n' "${PRETTY_NAME:-$(uname -s)}" "$(uname -r)"
+
+if command -v ss >/dev/null 2>&1; then
+    ss -H -lntup 2>/dev/null | awk '{
+        n = split($5, parts, ":"); port = parts[n]
+        address = substr($5, 1, length($5) - length(port) - 1)
+        process = ""
+        if (match($0, /users:\(\("[^"]+"/)) { process = substr($0, RSTART + 9, RLENGTH - 10) }
+        printf "listen\t%s\t%s\t%s\t%s\n", $1, address, port, process
+    }'
+else
+    printf 'error\tss is not installed\n'
+fi
+
+if command -v systemctl >/dev/null 2>&1; then
+    systemctl list-units --type=service --state=running --no-legend --plain --no-pager 2>/dev/null |
+        awk '{ printf "service\t%s\n", $1 }'
+elif command -v rc-status >/dev/null 2>&1; then
+    rc-status --servicelist 2>/dev/null | awk '$3 == "started" { printf "service\t%s\n", $1 }'
+fi
+
+packages=""
+if command -v dpkg-query >/dev/null 2>&1; then
+    packages=$(dpkg-query -W -f='${Package} ${Version}\n' 2>/dev/null | sort)
+elif command -v rpm >/dev/null 2>&1; then
+    packages=$(rpm -qa --qf '%{NAME} %{VERSION}-%{RELEASE}.%{ARCH}\n' 2>/dev/null | sort)
+elif command -v apk >/dev/null 2>&1; then
+    packages=$(apk info -v 2>/dev/null | sort)
+fi
+if [ -n "$packages" ]; then
+    printf 'packages\t%s\t%s\n' "$(printf '%s\n' "$packages" | sha256sum | cut -d' ' -f1)" \
+        "$(printf '%s\n' "$packages" | wc -l | tr -d ' ')"
+fi
+"#;
+
+// Recorded operating systems taken for Linux
+const LINUX_NAMES: [&str; 11] = [
+    "linux", "ubuntu", "debian", "centos", "red hat", "rhel", "fedora", "suse", "almalinux", "rocky", "alpine",
+];
+
+// Which probe an asset gets, from its recorded operating system
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OsFamily {
+    Windows,
+    Linux,
+}
+
+impl OsFamily {
+    fn of(asset: &Asset) -> Option<Self> {
+        let os = asset.operating_system.as_deref()?.to_lowercase();
+        if os.contains("windows") {
+            Some(OsFamily::Windows)
+        } else if LINUX_NAMES.iter().any(|name| os.contains(name)) {
+            Some(OsFamily::Linux)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListeningSocket {
+    pub protocol: String,
+    pub address: String,
+    pub port: u16,
+    pub process: Option<String>,
+}
+
+impl ListeningSocket {
+    fn endpoint(&self) -> (&str, &str, u16) {
+        (&self.protocol, &self.address, self.port)
+    }
+}
+
+// What changed between two snapshots of an asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryDiff {
+    // Collection time of the earlier snapshot
+    pub since: DateTime<Utc>,
+    pub opened: Vec<ListeningSocket>,
+    pub closed: Vec<ListeningSocket>,
+    pub services_started: Vec<String>,
+    pub services_stopped: Vec<String>,
+    // The earlier OS version, when it changed
+    pub previous_os_version: Option<String>,
+    pub packages_changed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventorySnapshot {
+    pub id: Uuid,
+    pub asset_id: Uuid,
+    pub collected_at: DateTime<Utc>,
+    pub os_family: OsFamily,
+    pub os_version: String,
+    pub listening: Vec<ListeningSocket>,
+    pub services: Vec<String>,
+    pub packages_hash: Option<String>,
+    pub package_count: Option<usize>,
+    // Against the snapshot before this one
+    pub changes: Option<InventoryDiff>,
+}
+
+pub fn diff(before: &InventorySnapshot, after: &InventorySnapshot) -> InventoryDiff {
+    let was: BTreeSet<_> = before.listening.iter().map(ListeningSocket::endpoint).collect();
+    let now: BTreeSet<_> = after.listening.iter().map(ListeningSocket::endpoint).collect();
+    let services = |snapshot: &InventorySnapshot| -> BTreeSet<String> { snapshot.services.iter().cloned().collect() };
+    let (ran, runs) = (services(before), services(after));
+
+    InventoryDiff {
+        since: before.collected_at,
+        opened: after.listening.iter()
+            .filter(|s| !was.contains(&s.endpoint()))
+            .cloned()
+            .collect(),
+        closed: before.listening.iter()
+            .filter(|s| !now.contains(&s.endpoint()))
+            .cloned()
+            .collect(),
+        services_started: runs.difference(&ran).cloned().collect(),
+        services_stopped: ran.difference(&runs).cloned().collect(),
+        previous_os_version: Some(before.os_version.clone()).filter(|os| *os != after.os_version),
+        packages_changed: before.packages_hash != after.packages_hash,
+    }
+}
+
+// Probe output without the fields the collector fills in
+struct Probe {
+    os_version: String,
+    listening: Vec<ListeningSocket>,
+    services: Vec<String>,
+    packages_hash: Option<String>,
+    package_count: Option<usize>,
+}
+
+fn parse(output: &str) -> Result<Probe> {
+    let mut os_version = None;
+    let mut listening = BTreeSet::new();
+    let mut services = BTreeSet::new();
+    let mut packages = None;
+
+    for line in output.lines().map(|l| l.trim_end_matches('\r')) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["os", version] => os_version = Some(version.trim().to_string()),
+            ["listen", protocol, address, port, process] => {
+                let Ok(port) = port.parse() else { continue };
+                listening.insert(ListeningSocket {
+                    protocol: protocol.to_lowercase(),
+                    address: address.to_string(),
+                    port,
+                    process: Some(process.trim().to_string()).filter(|p| !p.is_empty()),
+                });
+            },
+            ["service", name] if !name.trim().is_empty() => {
+                services.insert(name.trim().to_string());
+            },
+            ["packages", hash, count] => packages = Some((hash.to_string(), count.parse().ok())),
+            ["error", message] => return Err(anyhow!("{}", message)),
+            _ => {},
+        }
+    }
+
+    // One entry per endpoint; of several processes sharing a port the first by name is kept
+    let mut listening: Vec<ListeningSocket> = listening.into_iter().collect();
+    listening.dedup_by(|a, b| a.endpoint() == b.endpoint());
+    Ok(Probe {
+        os_version: os_version.ok_or_else(|| anyhow!("Probe output has no OS version"))?,
+        listening,
+        services: services.into_iter().collect(),
+        packages_hash: packages.as_ref().map(|(hash, _)| hash.clone()),
+        package_count: packages.and_then(|(_, count)| count),
+    })
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum InventoryState {
+    Inventoried,
+    // Not reachable over SSH as configured, so never probed
+    Uninventoried,
+    // The last collection failed; earlier snapshots are kept
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryStatus {
+    pub state: InventoryState,
+    pub reason: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+// Stored per asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetInventory {
+    pub asset_id: Uuid,
+    pub status: InventoryStatus,
+    // Oldest first
+    pub snapshots: Vec<InventorySnapshot>,
+}
+
+// GET /api/assets/:id/inventory
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryView {
+    pub asset_id: Uuid,
+    // None until the asset was looked at by a collection run
+    pub status: Option<InventoryStatus>,
+    pub snapshot: Option<InventorySnapshot>,
+    // With changes_since: the latest snapshot against the last one taken at or before
+    // that time (or the oldest kept), collected at baseline_at
+    pub baseline_at: Option<DateTime<Utc>>,
+    pub changes: Option<InventoryDiff>,
+}
+
+// Collects the inventory of active assets on a schedule over the fleet SSH settings,
+// keeping keep_snapshots snapshots per asset with the changes from one to the next.
+// New listening ports or services on servers raise an alert.
+#[derive(Clone)]
+pub struct InventoryCollector {
+    config: InventoryConfig,
+    fleet: FleetConfig,
+    dir: PathBuf,
+    assets: AssetManager,
+    alerts: AlertsManager,
+    inventories: Arc<Mutex<HashMap<Uuid, AssetInventory>>>,
+}
+
+impl InventoryCollector {
+    pub fn new(config: InventoryConfig,
+               fleet: FleetConfig,
+               dir: &str,
+               assets: AssetManager,
+               alerts: AlertsManager) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)
+            .context(format!("Failed to create inventory directory: {:?}", dir))?;
+
+        let mut inventories = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let loaded = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| serde_json::from_str::<AssetInventory>(&contents).map_err(anyhow::Error::from));
+            match loaded {
+                Ok(inventory) => {
+                    inventories.insert(inventory.asset_id, inventory);
+                },
+                Err(e) => warn!("Ignoring invalid inventory file {:?}: {}", path, e),
+            }
+        }
+
+        Ok(Self {
+            config,
+            fleet,
+            dir,
+            assets,
+            alerts,
+            inventories: Arc::new(Mutex::new(inventories)),
+        })
+    }
+
+    // The address and probe of an asset, or why it is not inventoried
+    fn reachability(&self, asset: &Asset) -> Result<(String, OsFamily), String> {
+        if self.fleet.ssh_user.is_none() {
+            return Err("SSH is not configured ([fleet] ssh_user)".to_string());
+        }
+        if asset.status != AssetStatus::Active {
+            return Err("Asset is not active".to_string());
+        }
+        let address = asset.ip_address.clone().ok_or_else(|| "Asset has no IP address".to_string())?;
+        let family = OsFamily::of(asset)
+            .ok_or_else(|| format!("No inventory probe for operating system {:?}", asset.operating_system))?;
+        Ok((address, family))
+    }
+
+    async fn probe(&self, address: String, family: OsFamily) -> Result<String> {
+        let target = RemoteTarget {
+            address,
+            ssh_user: self.fleet.ssh_user.clone(),
+            identity_file: self.fleet.ssh_identity_file.clone(),
+            connect_timeout_secs: self.fleet.connect_timeout_secs,
+        };
+        match family {
+            OsFamily::Windows => scripts::run_probe_powershell(WINDOWS_PROBE, &target).await,
+            OsFamily::Linux => scripts::run_probe_shell(POSIX_PROBE, &target).await,
+        }
+    }
+
+    fn save(&self, inventory: &AssetInventory) -> Result<()> {
+        let path = self.dir.join(format!("{}.json", inventory.asset_id));
+        fs::write(&path, serde_json::to_string_pretty(inventory)?)
+            .context(format!("Failed to write inventory file: {:?}", path))
+    }
+
+    // Saved only when the state or the reason changes, not on every run
+    fn mark(&self, asset_id: Uuid, state: InventoryState, reason: Option<String>) -> Result<()> {
+        let mut inventories = self.inventories.lock().map_err(|_| anyhow!("Failed to acquire lock on inventories"))?;
+        let status = InventoryStatus { state, reason, checked_at: Utc::now() };
+        match inventories.get_mut(&asset_id) {
+            Some(inventory) => {
+                let changed = inventory.status.state != status.state || inventory.status.reason != status.reason;
+                inventory.status = status;
+                if changed {
+                    self.save(inventory)?;
+                }
+            },
+            None => {
+                let inventory = AssetInventory { asset_id, status, snapshots: Vec::new() };
+                self.save(&inventory)?;
+                inventories.insert(asset_id, inventory);
+            },
+        }
+        Ok(())
+    }
+
+    // Returns whether a snapshot was taken
+    fn record(&self, asset: &Asset, family: OsFamily, result: Result<String>) -> Result<bool> {
+        let probe = match result.and_then(|output| parse(&output)) {
+            Ok(probe) => probe,
+            Err(e) => {
+                warn!("Inventory collection from {} failed: {}", asset.name, e);
+                self.mark(asset.id, InventoryState::Failed, Some(e.to_string()))?;
+                return Ok(false);
+            },
+        };
+
+        let now = Utc::now();
+        let mut snapshot = InventorySnapshot {
+            id: Uuid::new_v4(),
+            asset_id: asset.id,
+            collected_at: now,
+            os_family: family,
+            os_version: probe.os_version,
+            listening: probe.listening,
+            services: probe.services,
+            packages_hash: probe.packages_hash,
+            package_count: probe.package_count,
+            changes: None,
+        };
+
+        let changes = {
+            let mut inventories = self.inventories.lock().map_err(|_| anyhow!("Failed to acquire lock on inventories"))?;
+            let inventory = inventories.entry(asset.id).or_insert_with(|| AssetInventory {
+                asset_id: asset.id,
+                status: InventoryStatus { state: InventoryState::Inventoried, reason: None, checked_at: now },
+                snapshots: Vec::new(),
+            });
+            snapshot.changes = inventory.snapshots.last().map(|previous| diff(previous, &snapshot));
+            inventory.status = InventoryStatus { state: InventoryState::Inventoried, reason: None, checked_at: now };
+            inventory.snapshots.push(snapshot.clone());
+            let excess = inventory.snapshots.len().saturating_sub(self.config.keep_snapshots.max(1));
+            inventory.snapshots.drain(..excess);
+            self.save(inventory)?;
+            snapshot.changes.clone()
+        };
+
+        if let Some(changes) = changes.filter(|c| !c.opened.is_empty() || !c.services_started.is_empty()) {
+            if asset.asset_type == AssetType::Server {
+                self.raise(asset, &snapshot, &changes)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn raise(&self, asset: &Asset, snapshot: &InventorySnapshot, changes: &InventoryDiff) -> Result<()> {
+        let mut lines: Vec<String> = changes.opened.iter()
+            .map(|s| format!("New listening {} {}:{}{}", s.protocol, s.address, s.port,
+                             s.process.as_ref().map(|p| format!(" ({})", p)).unwrap_or_default()))
+            .collect();
+        lines.extend(changes.services_started.iter().map(|s| format!("New service {}", s)));
+
+        self.alerts.create_alert(
+            AlertSeverity::Medium,
+            format!("New listening ports or services on {}", asset.name),
+            format!("{}\n\nSnapshot {} against the one of {}, see GET /api/assets/{}/inventory?changes_since={}",
+                    lines.join("\n"), snapshot.id, changes.since.to_rfc3339(), asset.id, changes.since.to_rfc3339()),
+            "inventory".to_string(),
+            Vec::new(),
+        )?;
+        Ok(())
+    }
+
+    // One collection run over every asset; returns how many were inventoried
+    pub async fn run(&self) -> Result<usize> {
+        let semaphore = Arc::new(Semaphore::new(self.config.max_concurrency.max(1)));
+        let mut handles = Vec::new();
+
+        for asset in self.assets.get_all_assets()? {
+            let (address, family) = match self.reachability(&asset) {
+                Ok(reachable) => reachable,
+                Err(reason) => {
+                    self.mark(asset.id, InventoryState::Uninventoried, Some(reason))?;
+                    continue;
+                },
+            };
+
+            let collector = self.clone();
+            let semaphore = semaphore.clone();
+            handles.push(tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let timeout = Duration::from_secs(collector.config.timeout_secs);
+                let result = match tokio::time::timeout(timeout, collector.probe(address, family)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow!("Timed out after {} seconds", timeout.as_secs())),
+                };
+                collector.record(&asset, family, result)
+            }));
+        }
+
+        let mut collected = 0;
+        for handle in handles {
+            match handle.await {
+                Ok(Ok(true)) => collected += 1,
+                Ok(Ok(false)) => {},
+                Ok(Err(e)) => warn!("Failed to record inventory: {}", e),
+                Err(e) => warn!("Inventory collection task panicked: {}", e),
+            }
+        }
+        if collected > 0 {
+            info!("Collected the inventory of {} assets", collected);
+        }
+        Ok(collected)
+    }
+
+    pub fn view(&self, asset_id: Uuid, changes_since: Option<DateTime<Utc>>) -> Result<InventoryView> {
+        let inventories = self.inventories.lock().map_err(|_| anyhow!("Failed to acquire lock on inventories"))?;
+        let Some(inventory) = inventories.get(&asset_id) else {
+            return Ok(InventoryView { asset_id, status: None, snapshot: None, baseline_at: None, changes: None });
+        };
+
+        let latest = inventory.snapshots.last();
+        let baseline = changes_since.and_then(|since| {
+            inventory.snapshots.iter()
+                .rev()
+                .find(|s| s.collected_at <= since)
+                .or(inventory.snapshots.first())
+        });
+        Ok(InventoryView {
+            asset_id,
+            status: Some(inventory.status.clone()),
+            snapshot: latest.cloned(),
+            baseline_at: baseline.map(|b| b.collected_at),
+            changes: latest.zip(baseline).map(|(latest, baseline)| diff(baseline, latest)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINUX_PROBE: &str = include_str!("fixtures/inventory_linux.txt");
+    const WINDOWS_PROBE_OUTPUT: &str = include_str!("fixtures/inventory_windows.txt");
+
+    fn socket(protocol: &str, address: &str, port: u16, process: Option<&str>) -> ListeningSocket {
+        ListeningSocket {
+            protocol: protocol.to_string(),
+            address: address.to_string(),
+            port,
+            process: process.map(|p| p.to_string()),
+        }
+    }
+
+    fn snapshot(probe: Probe) -> InventorySnapshot {
+        InventorySnapshot {
+            id: Uuid::new_v4(),
+            asset_id: Uuid::nil(),
+            collected_at: Utc::now(),
+            os_family: OsFamily::Linux,
+            os_version: probe.os_version,
+            listening: probe.listening,
+            services: probe.services,
+            packages_hash: probe.packages_hash,
+            package_count: probe.package_count,
+            changes: None,
+        }
+    }
+
+    fn asset(operating_system: Option<&str>) -> Asset {
+        Asset {
+            id: Uuid::new_v4(),
+            name: "host".to_string(),
+            asset_type: AssetType::Server,
+            ip_address: None,
+            mac_address: None,
+            operating_system: operating_system.map(|os| os.to_string()),
+            owner: None,
+            location: None,
+            location_id: None,
+            site_id: None,
+            purchase_date: None,
+            status: AssetStatus::Active,
+            tags: Vec::new(),
+            address_history: Vec::new(),
+            drift: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_linux_probe_output() {
+        let probe = parse(LINUX_PROBE).unwrap();
+
+        assert_eq!(probe.os_version, "Ubuntu 22.04.4 LTS (5.15.0-105-generic)");
+        // The line with an unparsable port is skipped, and of the two processes on port 80
+        // the first by name is kept
+        assert_eq!(probe.listening, vec![
+            socket("tcp", "0.0.0.0", 22, Some("sshd")),
+            socket("tcp", "0.0.0.0", 80, Some("apache2")),
+            socket("tcp", "127.0.0.53%lo", 53, Some("systemd-resolve")),
+            socket("tcp", "[::]", 22, Some("sshd")),
+            socket("udp", "0.0.0.0", 68, None),
+            socket("udp", "127.0.0.53%lo", 53, Some("systemd-resolve")),
+        ]);
+        assert_eq!(probe.services, vec!["cron.service", "nginx.service", "ssh.service"]);
+        assert_eq!(probe.packages_hash.as_deref(), Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"));
+        assert_eq!(probe.package_count, Some(612));
+    }
+
+    #[test]
+    fn parses_windows_probe_output_with_crlf() {
+        let probe = parse(WINDOWS_PROBE_OUTPUT).unwrap();
+
+        assert_eq!(probe.os_version, "Microsoft Windows Server 2022 Standard 10.0.20348");
+        assert_eq!(probe.listening, vec![
+            socket("tcp", "0.0.0.0", 3389, Some("svchost")),
+            socket("tcp", "::", 445, Some("System")),
+            socket("udp", "0.0.0.0", 123, Some("svchost")),
+        ]);
+        assert_eq!(probe.services, vec!["TermService", "W32Time"]);
+        assert_eq!(probe.package_count, Some(0));
+    }
+
+    #[test]
+    fn probe_errors_and_missing_os_fail_the_parse() {
+        let Err(error) = parse("os\tDebian GNU/Linux 12 (6.1.0)\nerror\tss is not installed\n") else {
+            panic!("probe error was not reported");
+        };
+        assert_eq!(error.to_string(), "ss is not installed");
+
+        assert!(parse("listen\ttcp\t0.0.0.0\t22\tsshd\n").is_err());
+
+        let probe = parse("os\tAlpine Linux v3.19 (6.6.7)\n").unwrap();
+        assert!(probe.listening.is_empty());
+        assert_eq!(probe.packages_hash, None);
+    }
+
+    #[test]
+    fn diff_compares_endpoints_services_and_packages() {
+        let before = snapshot(parse(LINUX_PROBE).unwrap());
+        let mut after = snapshot(parse(LINUX_PROBE).unwrap());
+
+        let unchanged = diff(&before, &after);
+        assert!(unchanged.opened.is_empty() && unchanged.closed.is_empty());
+        assert!(unchanged.services_started.is_empty() && unchanged.services_stopped.is_empty());
+        assert_eq!(unchanged.previous_os_version, None);
+        assert!(!unchanged.packages_changed);
+
+        // A different process on the same endpoint is not an opened port
+        after.listening.retain(|s| s.port != 22);
+        after.listening[0].process = Some("nginx".to_string());
+        after.listening.push(socket("tcp", "0.0.0.0", 8080, Some("java")));
+        after.services.retain(|s| s != "nginx.service");
+        after.services.push("tomcat.service".to_string());
+        after.os_version = "Ubuntu 22.04.5 LTS (5.15.0-119-generic)".to_string();
+        after.packages_hash = Some("other".to_string());
+
+        let changes = diff(&before, &after);
+        assert_eq!(changes.opened, vec![socket("tcp", "0.0.0.0", 8080, Some("java"))]);
+        assert_eq!(changes.closed, vec![
+            socket("tcp", "0.0.0.0", 22, Some("sshd")),
+            socket("tcp", "[::]", 22, Some("sshd")),
+        ]);
+        assert_eq!(changes.services_started, vec!["tomcat.service"]);
+        assert_eq!(changes.services_stopped, vec!["nginx.service"]);
+        assert_eq!(changes.previous_os_version.as_deref(), Some("Ubuntu 22.04.4 LTS (5.15.0-105-generic)"));
+        assert!(changes.packages_changed);
+    }
+
+    #[test]
+    fn os_family_comes_from_the_recorded_operating_system() {
+        assert_eq!(OsFamily::of(&asset(Some("Windows 11 Pro"))), Some(OsFamily::Windows));
+        assert_eq!(OsFamily::of(&asset(Some("Rocky Linux 9"))), Some(OsFamily::Linux));
+        assert_eq!(OsFamily::of(&asset(Some("Debian 12"))), Some(OsFamily::Linux));
+        assert_eq!(OsFamily::of(&asset(Some("FreeBSD 14"))), None);
+        assert_eq!(OsFamily::of(&asset(None)), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::time::Instant;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::alert_events;
+use crate::classification;
+use crate::config::ClockSkewConfig;
+use crate::extraction::{ExtractionManager, RuleMatch};
+use crate::ingest_timing::{IngestTimings, Stage};
+use crate::ingestion_quotas::{self, IngestionQuotas, QuotaDecision};
+use crate::log_tail::LogTail;
+use crate::logs::LogsManager;
+use crate::models::LogEntry;
+use crate::reparse;
+use crate::sites::SiteManager;
+use crate::source_health::SourceHealthMonitor;
+use crate::tagging::TaggingManager;
+use crate::travel::TravelDetector;
+
+// Tag holding the event's own timestamp when it was stored at receipt time
+pub const ORIGINAL_TIMESTAMP_TAG: &str = "original_timestamp:";
+
+// Every ingested log entry passes through here before it is stored
+#[derive(Clone)]
+pub struct IngestionPipeline {
+    logs_manager: LogsManager,
+    extraction_manager: ExtractionManager,
+    travel_detector: TravelDetector,
+    quotas: IngestionQuotas,
+    sites: SiteManager,
+    tail: LogTail,
+    health: SourceHealthMonitor,
+    clock_skew: ClockSkewConfig,
+    // Copies of stored entries for the database with the time their ingestion started,
+    // see DatabaseManager::log_writer
+    database: Option<mpsc::UnboundedSender<(LogEntry, Instant)>>,
+    tagging: TaggingManager,
+    timings: IngestTimings,
+}
+
+impl IngestionPipeline {
+    pub fn new(logs_manager: LogsManager,
+               extraction_manager: ExtractionManager,
+               travel_detector: TravelDetector,
+               quotas: IngestionQuotas,
+               sites: SiteManager,
+               tail: LogTail,
+               health: SourceHealthMonitor,
+               clock_skew: ClockSkewConfig,
+               database: Option<mpsc::UnboundedSender<(LogEntry, Instant)>>,
+               tagging: TaggingManager,
+               timings: IngestTimings) -> Self {
+        Self {
+            logs_manager,
+            extraction_manager,
+            travel_detector,
+            quotas,
+            sites,
+            tail,
+            health,
+            clock_skew,
+            database,
+            tagging,
+            timings,
+        }
+    }
+
+    // Stores the entry at its receipt time when rewriting is enabled and the skew is past
+    // the bound; the original timestamp stays in a tag. Returns whether it was rewritten.
+    fn correct_timestamp(&self, entry: &mut LogEntry, received: DateTime<Utc>, skew_secs: i64) -> bool {
+        if !self.clock_skew.rewrite_timestamps || skew_secs.abs() <= self.clock_skew.rewrite_after_secs {
+            return false;
+        }
+        entry.tags.push(format!("{}{}", ORIGINAL_TIMESTAMP_TAG, entry.timestamp.to_rfc3339()));
+        entry.tags.push(format!("clock_skew:{}s", skew_secs));
+        entry.timestamp = received;
+        true
+    }
+
+    // Returns None when the source is over its quota and the event was dropped
+    pub fn ingest(&self, mut entry: LogEntry) -> Result<Option<LogEntry>> {
+        // Only our own alert lifecycle events may skip detection
+        entry.tags.retain(|t| t != alert_events::LIFECYCLE_TAG);
+
+        let started = Instant::now();
+        let received = Utc::now();
+        let skew_secs = (received - entry.timestamp).num_seconds();
+        let rewritten = self.correct_timestamp(&mut entry, received, skew_secs);
+
+        if let Err(e) = self.health.record(&ingestion_quotas::source_key(&entry), received, skew_secs, rewritten) {
+            warn!("Failed to record log source activity for {}: {}", entry.id, e);
+        }
+
+        let stage = Instant::now();
+        let decision = self.quotas.check(&entry)?;
+        self.timings.observe(Stage::Quota, stage.elapsed());
+        match decision {
+            QuotaDecision::Accept => {},
+            QuotaDecision::Sampled(tag) => entry.tags.push(tag),
+            QuotaDecision::Drop => return Ok(None),
+        }
+
+        // Extraction failures must never drop the event itself
+        let stage = Instant::now();
+        if let Err(e) = self.extraction_manager.apply(&mut entry) {
+            warn!("Field extraction failed for log entry {}: {}", entry.id, e);
+        }
+        self.timings.observe(Stage::Extraction, stage.elapsed());
+
+        let stage = Instant::now();
+        entry.category = classification::classify(&entry);
+        self.timings.observe(Stage::Classification, stage.elapsed());
+
+        if entry.site_id.is_none() {
+            entry.site_id = entry.host.as_deref().and_then(|host| self.sites.site_for_address(host));
+        }
+
+        // Runs on the extracted fields and the category
+        let stage = Instant::now();
+        if let Err(e) = self.tagging.apply(&mut entry) {
+            warn!("Tagging failed for log entry {}: {}", entry.id, e);
+        }
+        self.timings.observe(Stage::Tagging, stage.elapsed());
+
+        let login = self.travel_detector.enrich(&mut entry);
+
+        let stage = Instant::now();
+        self.logs_manager.ingest(entry.clone())?;
+        self.timings.observe(Stage::Store, stage.elapsed());
+        self.count_stored(&entry);
+        self.tail.publish(&entry);
+        self.forward_to_database(&entry, started);
+
+        // Detection failures must not fail ingestion either
+        if let (Some(login), Some(user)) = (login, &entry.user) {
+            if let Err(e) = self.travel_detector.check(user, login) {
+                warn!("Impossible travel check failed for log entry {}: {}", entry.id, e);
+            }
+        }
+
+        self.timings.observe(Stage::Pipeline, started.elapsed());
+        Ok(Some(entry))
+    }
+
+    // Runs the parser, extraction, classification and tagging again on the raw data of a
+    // stored entry, without quotas, source health, detection or storing it, see reparse.
+    // Returns the entry as it would now be ingested with the extraction rules that matched.
+    pub fn reparse(&self, stored: &LogEntry) -> Result<(LogEntry, Vec<RuleMatch>)> {
+        let mut entry = reparse::parse_raw(stored)?;
+        let rules = self.extraction_manager.apply(&mut entry)?;
+        entry.category = classification::classify(&entry);
+
+        entry.site_id = if entry.host == stored.host {
+            stored.site_id
+        } else {
+            entry.host.as_deref().and_then(|host| self.sites.site_for_address(host))
+        };
+
+        self.tagging.apply_uncounted(&mut entry)?;
+        Ok((entry, rules))
+    }
+
+    // Lifecycle events of our own alerts are stored and tailed like any entry but skip
+    // quotas, extraction, source health and detection, so they can never raise alerts
+    // that would generate more of them
+    pub fn ingest_alert_event(&self, entry: LogEntry) -> Result<()> {
+        debug_assert!(alert_events::is_lifecycle(&entry));
+        self.logs_manager.ingest(entry.clone())?;
+        self.count_stored(&entry);
+        self.tail.publish(&entry);
+        self.forward_to_database(&entry, Instant::now());
+        Ok(())
+    }
+
+    fn count_stored(&self, entry: &LogEntry) {
+        if let Err(e) = self.timings.count(entry) {
+            warn!("Failed to count log entry {}: {}", entry.id, e);
+        }
+    }
+
+    // The writer spools on its own during an outage, so this only fails once it is gone
+    fn forward_to_database(&self, entry: &LogEntry, started: Instant) {
+        if let Some(database) = &self.database {
+            if database.send((entry.clone(), started)).is_err() {
+                warn!("Database writer stopped, log entry {} not stored in the database", entry.id);
+            }
+        }
+    }
+}
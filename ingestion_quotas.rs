@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::{QuotaAction, QuotaConfig};
+use crate::models::{AlertSeverity, LogEntry, LogSeverity};
+
+// Sources silent for this long are forgotten, counters included
+const IDLE_MINUTES: i64 = 60;
+// Sources tracked at once; events of further sources are counted together under
+// OVERFLOW_SOURCE until idle ones are forgotten
+const MAX_SOURCES: usize = 10_000;
+pub const OVERFLOW_SOURCE: &str = "(other sources)";
+
+// The default quota and per-source overrides, persisted as one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaSettings {
+    pub default: QuotaConfig,
+    #[serde(default)]
+    pub overrides: HashMap<String, QuotaConfig>,
+}
+
+// What happens to an event after the quota check
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuotaDecision {
+    Accept,
+    // Over a soft quota but picked by sampling; carries the tag to add
+    Sampled(String),
+    Drop,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SourceState {
+    // Minute (since the epoch) the current count belongs to
+    minute: i64,
+    count: u64,
+    previous_count: u64,
+    over_quota: bool,
+    accepted_total: u64,
+    sampled_total: u64,
+    dropped_total: u64,
+    last_seen: Option<DateTime<Utc>>,
+    exceeded_at: Option<DateTime<Utc>>,
+}
+
+// Rate and counters of one source, as shown by GET /api/logs/sources
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStatus {
+    pub source: String,
+    // Events in the last complete minute, or the current one once it is higher
+    pub events_per_minute: u64,
+    pub quota: QuotaConfig,
+    pub over_quota: bool,
+    pub accepted_total: u64,
+    pub sampled_total: u64,
+    pub dropped_total: u64,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub exceeded_at: Option<DateTime<Utc>>,
+}
+
+// Key quotas are tracked by: the sending device, or the source name without a host
+pub fn source_key(entry: &LogEntry) -> String {
+    entry.host.clone()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| entry.source.clone())
+}
+
+// Excess events are sampled by position, so a burst keeps every Nth event in order;
+// errors and worse are always kept
+fn keep_sample(entry: &LogEntry, excess: u64, sample_every: u32) -> bool {
+    entry.severity >= LogSeverity::Error || (excess - 1) % u64::from(sample_every.max(1)) == 0
+}
+
+#[derive(Clone)]
+pub struct IngestionQuotas {
+    settings_path: PathBuf,
+    settings: Arc<Mutex<QuotaSettings>>,
+    sources: Arc<Mutex<HashMap<String, SourceState>>>,
+    // Minute idle sources were last forgotten at because the map was full
+    pruned_minute: Arc<AtomicI64>,
+    alerts: AlertsManager,
+}
+
+fn is_idle(state: &SourceState, now: DateTime<Utc>) -> bool {
+    state.last_seen.map_or(true, |seen| (now - seen).num_minutes() >= IDLE_MINUTES)
+}
+
+impl IngestionQuotas {
+    pub fn new(dir: &str, default: QuotaConfig, alerts: AlertsManager) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create ingestion directory: {:?}", dir))?;
+            info!("Created ingestion directory: {:?}", dir);
+        }
+
+        let settings_path = dir.join("quotas.json");
+        let settings = match fs::read_to_string(&settings_path) {
+            Ok(contents) => match serde_json::from_str::<QuotaSettings>(&contents) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    warn!("Ignoring invalid quota file {:?}: {}", settings_path, e);
+                    QuotaSettings { default, overrides: HashMap::new() }
+                },
+            },
+            Err(_) => QuotaSettings { default, overrides: HashMap::new() },
+        };
+
+        info!("Loaded ingestion quotas with {} source overrides", settings.overrides.len());
+
+        Ok(Self {
+            settings_path,
+            settings: Arc::new(Mutex::new(settings)),
+            sources: Arc::new(Mutex::new(HashMap::new())),
+            pruned_minute: Arc::new(AtomicI64::new(i64::MIN)),
+            alerts,
+        })
+    }
+
+    fn save_settings(&self, settings: &QuotaSettings) -> Result<()> {
+        let json = serde_json::to_string_pretty(settings)?;
+        fs::write(&self.settings_path, json)
+            .context(format!("Failed to write quota file: {:?}", self.settings_path))?;
+        Ok(())
+    }
+
+    fn validate(quota: &QuotaConfig) -> Result<()> {
+        if quota.action == QuotaAction::Soft && quota.sample_every == 0 {
+            return Err(anyhow!("sample_every must be at least 1"));
+        }
+        Ok(())
+    }
+
+    pub fn get_settings(&self) -> Result<QuotaSettings> {
+        match self.settings.lock() {
+            Ok(settings) => Ok(settings.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on quota settings")),
+        }
+    }
+
+    pub fn set_default(&self, quota: QuotaConfig) -> Result<QuotaSettings> {
+        Self::validate(&quota)?;
+
+        match self.settings.lock() {
+            Ok(mut settings) => {
+                settings.default = quota;
+                self.save_settings(&settings)?;
+                Ok(settings.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on quota settings")),
+        }
+    }
+
+    // None removes the override, the source falls back to the default
+    pub fn set_override(&self, source: &str, quota: Option<QuotaConfig>) -> Result<QuotaSettings> {
+        if let Some(quota) = &quota {
+            Self::validate(quota)?;
+        }
+
+        match self.settings.lock() {
+            Ok(mut settings) => {
+                match quota {
+                    Some(quota) => {
+                        settings.overrides.insert(source.to_string(), quota);
+                    },
+                    None => {
+                        if settings.overrides.remove(source).is_none() {
+                            return Err(anyhow!("No quota override for source: {}", source));
+                        }
+                    },
+                }
+                self.save_settings(&settings)?;
+                Ok(settings.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on quota settings")),
+        }
+    }
+
+    // Quotas of the source and of OVERFLOW_SOURCE, in case the source is not tracked
+    fn quotas_for(&self, source: &str) -> Result<(QuotaConfig, QuotaConfig)> {
+        match self.settings.lock() {
+            Ok(settings) => {
+                let quota = |source: &str| settings.overrides.get(source).unwrap_or(&settings.default).clone();
+                Ok((quota(source), quota(OVERFLOW_SOURCE)))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on quota settings")),
+        }
+    }
+
+    // Counts the event against its source and decides whether it is stored
+    pub fn check(&self, entry: &LogEntry) -> Result<QuotaDecision> {
+        self.check_at(entry, Utc::now())
+    }
+
+    fn check_at(&self, entry: &LogEntry, now: DateTime<Utc>) -> Result<QuotaDecision> {
+        let mut key = source_key(entry);
+        let (mut quota, overflow_quota) = self.quotas_for(&key)?;
+        let minute = now.timestamp() / 60;
+
+        let (decision, exceeded_rate) = match self.sources.lock() {
+            Ok(mut sources) => {
+                if sources.len() >= MAX_SOURCES && !sources.contains_key(&key) {
+                    // At most once a minute, so a flood of new sources does not scan the map per event
+                    if self.pruned_minute.swap(minute, Ordering::Relaxed) != minute {
+                        sources.retain(|_, s| !is_idle(s, now));
+                    }
+                    if sources.len() >= MAX_SOURCES {
+                        key = OVERFLOW_SOURCE.to_string();
+                        quota = overflow_quota;
+                    }
+                }
+
+                let state = sources.entry(key.clone()).or_default();
+                if state.minute != minute {
+                    state.previous_count = if state.minute == minute - 1 { state.count } else { 0 };
+                    state.minute = minute;
+                    state.count = 0;
+                    // An episode ends with a minute spent within the quota
+                    if state.previous_count <= quota.events_per_minute {
+                        state.over_quota = false;
+                    }
+                }
+                state.count += 1;
+                state.last_seen = Some(now);
+
+                let excess = state.count.saturating_sub(quota.events_per_minute);
+                let decision = if quota.events_per_minute == 0 || excess == 0 {
+                    QuotaDecision::Accept
+                } else if quota.action == QuotaAction::Soft && keep_sample(entry, excess, quota.sample_every) {
+                    QuotaDecision::Sampled(format!("sampled:1/{}", quota.sample_every))
+                } else {
+                    QuotaDecision::Drop
+                };
+
+                match decision {
+                    QuotaDecision::Accept => state.accepted_total += 1,
+                    QuotaDecision::Sampled(_) => state.sampled_total += 1,
+                    QuotaDecision::Drop => state.dropped_total += 1,
+                }
+
+                let mut exceeded_rate = None;
+                if excess > 0 && quota.events_per_minute > 0 && !state.over_quota {
+                    state.over_quota = true;
+                    state.exceeded_at = Some(now);
+                    exceeded_rate = Some(state.count.max(state.previous_count));
+                }
+
+                (decision, exceeded_rate)
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on ingestion sources")),
+        };
+
+        if let Some(observed) = exceeded_rate {
+            warn!("Source {} exceeded its ingestion quota of {} events/min", key, quota.events_per_minute);
+
+            // Medium is the warning level of alerts; the event is stored or dropped either way
+            if let Err(e) = self.alerts.create_alert(
+                AlertSeverity::Medium,
+                format!("Source {} exceeded its ingestion quota", key),
+                format!("Observed {} events/min, quota {} events/min; events beyond it are {}",
+                        observed,
+                        quota.events_per_minute,
+                        match quota.action {
+                            QuotaAction::Soft => format!("sampled 1 in {}", quota.sample_every),
+                            QuotaAction::Hard => "dropped".to_string(),
+                        }),
+                "ingestion_quota".to_string(),
+                vec![entry.id],
+            ) {
+                warn!("Failed to raise the quota alert of source {}: {}", key, e);
+            }
+        }
+
+        Ok(decision)
+    }
+
+    // Sources seen recently, busiest first
+    pub fn sources(&self) -> Result<Vec<SourceStatus>> {
+        let settings = self.get_settings()?;
+        let now = Utc::now();
+        let minute = now.timestamp() / 60;
+
+        match self.sources.lock() {
+            Ok(mut sources) => {
+                sources.retain(|_, s| !is_idle(s, now));
+
+                let mut statuses: Vec<SourceStatus> = sources.iter()
+                    .map(|(source, state)| {
+                        let (current, previous) = match minute - state.minute {
+                            0 => (state.count, state.previous_count),
+                            1 => (0, state.count),
+                            _ => (0, 0),
+                        };
+                        SourceStatus {
+                            source: source.clone(),
+                            events_per_minute: current.max(previous),
+                            quota: settings.overrides.get(source).unwrap_or(&settings.default).clone(),
+                            over_quota: state.over_quota,
+                            accepted_total: state.accepted_total,
+                            sampled_total: state.sampled_total,
+                            dropped_total: state.dropped_total,
+                            last_seen: state.last_seen,
+                            exceeded_at: state.exceeded_at,
+                        }
+                    })
+                    .collect();
+                statuses.sort_by(|a, b| b.events_per_minute.cmp(&a.events_per_minute).then(a.source.cmp(&b.source)));
+                Ok(statuses)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on ingestion sources")),
+        }
+    }
+
+    // Prometheus text exposition of per-source rates and counters
+    pub fn render_metrics(&self) -> Result<String> {
+        let sources = self.sources()?;
+        let label = |source: &str| source.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+
+        let mut out = String::new();
+        out.push_str("# HELP siem_ingestion_source_events_per_minute Events per minute received from a source\n");
+        out.push_str("# TYPE siem_ingestion_source_events_per_minute gauge\n");
+        for s in &sources {
+            out.push_str(&format!("siem_ingestion_source_events_per_minute{{source=\"{}\"}} {}\n", label(&s.source), s.events_per_minute));
+        }
+        out.push_str("# HELP siem_ingestion_source_sampled_total Events over a soft quota kept by sampling\n");
+        out.push_str("# TYPE siem_ingestion_source_sampled_total counter\n");
+        for s in &sources {
+            out.push_str(&format!("siem_ingestion_source_sampled_total{{source=\"{}\"}} {}\n", label(&s.source), s.sampled_total));
+        }
+        out.push_str("# HELP siem_ingestion_source_dropped_total Events over the quota that were not stored\n");
+        out.push_str("# TYPE siem_ingestion_source_dropped_total counter\n");
+        for s in &sources {
+            out.push_str(&format!("siem_ingestion_source_dropped_total{{source=\"{}\"}} {}\n", label(&s.source), s.dropped_total));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, DurationRound};
+    use uuid::Uuid;
+
+    fn entry(host: &str, severity: LogSeverity) -> LogEntry {
+        LogEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            source: "syslog".to_string(),
+            event_type: "message".to_string(),
+            severity,
+            message: String::new(),
+            raw_data: String::new(),
+            host: Some(host.to_string()),
+            user: None,
+            application: None,
+            tags: Vec::new(),
+            category: Default::default(),
+            hostname: None,
+            site_id: None,
+            parser: Default::default(),
+        }
+    }
+
+    fn quotas(dir: &tempfile::TempDir, events_per_minute: u64, action: QuotaAction) -> IngestionQuotas {
+        let alerts = AlertsManager::new(dir.path().join("alerts").to_str().unwrap()).unwrap();
+        let quota = QuotaConfig { events_per_minute, action, sample_every: 3 };
+        IngestionQuotas::new(dir.path().join("ingestion").to_str().unwrap(), quota, alerts).unwrap()
+    }
+
+    // Start of the current minute, so every check of a test counts in the same minute
+    fn minute() -> DateTime<Utc> {
+        Utc::now().duration_trunc(Duration::minutes(1)).unwrap()
+    }
+
+    #[test]
+    fn soft_quota_keeps_every_nth_excess_event_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = quotas(&dir, 2, QuotaAction::Soft);
+        let now = minute();
+
+        let decisions: Vec<_> = (0..8)
+            .map(|_| quotas.check_at(&entry("fw1", LogSeverity::Info), now).unwrap())
+            .collect();
+        let sampled = QuotaDecision::Sampled("sampled:1/3".to_string());
+        assert_eq!(decisions, vec![
+            QuotaDecision::Accept, QuotaDecision::Accept,
+            sampled.clone(), QuotaDecision::Drop, QuotaDecision::Drop,
+            sampled, QuotaDecision::Drop, QuotaDecision::Drop,
+        ]);
+        assert!(matches!(quotas.check_at(&entry("fw1", LogSeverity::Error), now).unwrap(), QuotaDecision::Sampled(_)));
+
+        let status = &quotas.sources().unwrap()[0];
+        assert_eq!((status.accepted_total, status.sampled_total, status.dropped_total), (2, 3, 4));
+        assert!(status.over_quota);
+    }
+
+    #[test]
+    fn hard_quota_drops_and_alerts_once_per_episode() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = quotas(&dir, 1, QuotaAction::Hard);
+        let now = minute();
+
+        assert_eq!(quotas.check_at(&entry("fw1", LogSeverity::Info), now).unwrap(), QuotaDecision::Accept);
+        for _ in 0..3 {
+            assert_eq!(quotas.check_at(&entry("fw1", LogSeverity::Critical), now).unwrap(), QuotaDecision::Drop);
+        }
+        assert_eq!(quotas.alerts.get_all_alerts().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_alert_does_not_lose_the_decision() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = quotas(&dir, 1, QuotaAction::Hard);
+        let now = minute();
+        fs::remove_dir_all(dir.path().join("alerts")).unwrap();
+
+        assert_eq!(quotas.check_at(&entry("fw1", LogSeverity::Info), now).unwrap(), QuotaDecision::Accept);
+        assert_eq!(quotas.check_at(&entry("fw1", LogSeverity::Info), now).unwrap(), QuotaDecision::Drop);
+    }
+
+    #[test]
+    fn sources_beyond_the_cap_share_the_overflow_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = quotas(&dir, 0, QuotaAction::Hard);
+        let now = minute();
+
+        for i in 0..MAX_SOURCES + 5 {
+            quotas.check_at(&entry(&format!("host-{}", i), LogSeverity::Info), now).unwrap();
+        }
+        let sources = quotas.sources().unwrap();
+        assert_eq!(sources.len(), MAX_SOURCES + 1);
+        let overflow = sources.iter().find(|s| s.source == OVERFLOW_SOURCE).unwrap();
+        assert_eq!(overflow.accepted_total, 5);
+
+        // Idle sources make room again
+        let later = now + Duration::minutes(IDLE_MINUTES);
+        quotas.check_at(&entry("new-host", LogSeverity::Info), later).unwrap();
+        assert!(quotas.sources.lock().unwrap().contains_key("new-host"));
+    }
+
+    #[test]
+    fn metric_labels_are_escaped() {
+        let dir = tempfile::tempdir().unwrap();
+        let quotas = quotas(&dir, 0, QuotaAction::Hard);
+        quotas.check_at(&entry("evil\"}\n{x=\"1\\", LogSeverity::Info), minute()).unwrap();
+
+        let metrics = quotas.render_metrics().unwrap();
+        assert!(metrics.contains(r#"{source="evil\"}\n{x=\"1\\"}"#), "{}", metrics);
+        assert!(metrics.lines().all(|line| line.starts_with('#') || line.starts_with("siem_")));
+    }
+}
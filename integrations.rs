@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::{IntegrationsConfig, WebhookIntegrationConfig};
+use crate::models::{AlertSeverity, AlertStatus};
+use crate::security::constant_time_eq;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrationSource {
+    Alertmanager,
+    Grafana,
+}
+
+impl IntegrationSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IntegrationSource::Alertmanager => "alertmanager",
+            IntegrationSource::Grafana => "grafana",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationStatus {
+    Firing,
+    Resolved,
+}
+
+// One alert of a webhook notification. Alertmanager and Grafana's unified alerting send
+// alerts of the same shape; Grafana adds links to the dashboard and panel.
+#[derive(Debug, Clone)]
+pub struct ExternalAlert {
+    pub status: NotificationStatus,
+    // Sent by the tool, or made from the labels when it is not
+    pub fingerprint: String,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    pub starts_at: Option<String>,
+    pub links: Vec<(&'static str, String)>,
+}
+
+// What a notification did to the alert queue
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebhookOutcome {
+    pub created: Vec<Uuid>,
+    // Still firing and already open here
+    pub duplicates: usize,
+    pub resolved: Vec<Uuid>,
+    // Resolved, but no open alert here has the fingerprint
+    pub unmatched: usize,
+}
+
+fn read_token(source: IntegrationSource, config: &WebhookIntegrationConfig) -> Result<Option<String>> {
+    let token = match (&config.token_file, &config.token_env) {
+        (Some(path), _) => fs::read_to_string(path)
+            .context(format!("Failed to read {} token file: {}", source.as_str(), path))?
+            .trim()
+            .to_string(),
+        (None, Some(var)) => std::env::var(var)
+            .context(format!("{} token environment variable {} is not set", source.as_str(), var))?,
+        (None, None) => return Ok(None),
+    };
+    if token.len() < 16 {
+        return Err(anyhow!("The {} token must be at least 16 characters", source.as_str()));
+    }
+    Ok(Some(token))
+}
+
+// Severity label values of common alerting rule sets
+pub fn severity_from_label(value: &str) -> Option<AlertSeverity> {
+    match value.trim().to_lowercase().as_str() {
+        "critical" | "crit" | "fatal" | "emergency" | "disaster" | "page" | "p1" => Some(AlertSeverity::Critical),
+        "high" | "error" | "major" | "p2" => Some(AlertSeverity::High),
+        "warning" | "warn" | "medium" | "average" | "minor" | "p3" => Some(AlertSeverity::Medium),
+        "low" | "info" | "informational" | "notice" | "none" | "p4" | "p5" => Some(AlertSeverity::Low),
+        _ => None,
+    }
+}
+
+// Label or annotation values are strings in both tools; anything else is kept as JSON
+fn string_map(value: Option<&Value>, path: &str, errors: &mut Vec<String>) -> BTreeMap<String, String> {
+    match value {
+        None | Some(Value::Null) => BTreeMap::new(),
+        Some(Value::Object(map)) => map.iter()
+            .map(|(key, value)| (key.clone(), match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            }))
+            .collect(),
+        Some(_) => {
+            errors.push(format!("{}: must be an object", path));
+            BTreeMap::new()
+        },
+    }
+}
+
+fn optional_string(alert: &serde_json::Map<String, Value>, field: &str) -> Option<String> {
+    alert.get(field).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+// Same labels, same alert, as Alertmanager does it
+fn labels_fingerprint(labels: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    for (name, value) in labels {
+        hasher.update(name.as_bytes());
+        hasher.update([0xff]);
+        hasher.update(value.as_bytes());
+        hasher.update([0xff]);
+    }
+    hex::encode(&hasher.finalize()[..8])
+}
+
+// The alerts of a notification in the tool's native webhook format. Fields this does not
+// use are ignored, so newer payload versions keep working; every problem with the fields
+// it does need is reported, with its path, and nothing is taken from an invalid payload.
+pub fn parse_notification(source: IntegrationSource, payload: &Value) -> std::result::Result<Vec<ExternalAlert>, Vec<String>> {
+    let Some(payload) = payload.as_object() else {
+        return Err(vec!["the payload must be a JSON object".to_string()]);
+    };
+    let alerts = match payload.get("alerts") {
+        Some(Value::Array(alerts)) => alerts,
+        Some(_) => return Err(vec!["alerts: must be an array".to_string()]),
+        None if source == IntegrationSource::Grafana && payload.contains_key("evalMatches") => {
+            return Err(vec!["alerts: missing; legacy Grafana alerting webhooks are not supported, use a unified alerting contact point".to_string()]);
+        },
+        None => return Err(vec!["alerts: missing".to_string()]),
+    };
+
+    let mut errors = Vec::new();
+    let mut parsed = Vec::new();
+    for (i, alert) in alerts.iter().enumerate() {
+        let Some(alert) = alert.as_object() else {
+            errors.push(format!("alerts[{}]: must be an object", i));
+            continue;
+        };
+
+        let status = match alert.get("status").and_then(|v| v.as_str()) {
+            Some(status) if status.eq_ignore_ascii_case("firing") => Some(NotificationStatus::Firing),
+            Some(status) if status.eq_ignore_ascii_case("resolved") => Some(NotificationStatus::Resolved),
+            Some(status) => {
+                errors.push(format!("alerts[{}].status: \"{}\" is neither \"firing\" nor \"resolved\"", i, status));
+                None
+            },
+            None => {
+                errors.push(format!("alerts[{}].status: missing", i));
+                None
+            },
+        };
+
+        if !alert.contains_key("labels") {
+            errors.push(format!("alerts[{}].labels: missing", i));
+        }
+        let labels = string_map(alert.get("labels"), &format!("alerts[{}].labels", i), &mut errors);
+        let annotations = string_map(alert.get("annotations"), &format!("alerts[{}].annotations", i), &mut errors);
+        if alert.get("labels").map_or(false, |l| l.is_object()) && labels.is_empty() && alert.get("fingerprint").is_none() {
+            errors.push(format!("alerts[{}]: neither labels nor a fingerprint identify the alert", i));
+        }
+
+        let mut links = Vec::new();
+        for (name, field) in [("source", "generatorURL"), ("dashboard", "dashboardURL"), ("panel", "panelURL"), ("silence", "silenceURL")] {
+            if let Some(url) = optional_string(alert, field) {
+                links.push((name, url));
+            }
+        }
+
+        if let Some(status) = status {
+            parsed.push(ExternalAlert {
+                status,
+                fingerprint: optional_string(alert, "fingerprint").unwrap_or_else(|| labels_fingerprint(&labels)),
+                labels,
+                annotations,
+                starts_at: optional_string(alert, "startsAt"),
+                links,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(errors)
+    }
+}
+
+fn title(source: IntegrationSource, alert: &ExternalAlert) -> String {
+    let name = alert.labels.get("alertname").cloned()
+        .unwrap_or_else(|| format!("{} alert {}", source.as_str(), alert.fingerprint));
+    match alert.annotations.get("summary") {
+        Some(summary) if !summary.is_empty() && *summary != name => format!("{}: {}", name, summary),
+        _ => name,
+    }
+}
+
+fn description(alert: &ExternalAlert) -> String {
+    let mut lines = Vec::new();
+    if let Some(text) = alert.annotations.get("description").or_else(|| alert.annotations.get("message")) {
+        lines.push(text.clone());
+        lines.push(String::new());
+    }
+    let labels: Vec<String> = alert.labels.iter().map(|(name, value)| format!("{}=\"{}\"", name, value)).collect();
+    lines.push(format!("Labels: {}", labels.join(", ")));
+    if let Some(starts_at) = &alert.starts_at {
+        lines.push(format!("Firing since: {}", starts_at));
+    }
+    for (name, url) in &alert.links {
+        lines.push(format!("{} link: {}", name, url));
+    }
+    lines.join("\n")
+}
+
+// Alerts pushed by Alertmanager and Grafana. A firing alert opens an alert here, keyed by
+// source and fingerprint, unless one is still open for it; the resolved notification
+// resolves that alert. The keys of open alerts are persisted so a restart does not make
+// repeated notifications open duplicates.
+#[derive(Clone)]
+pub struct Integrations {
+    config: IntegrationsConfig,
+    tokens: HashMap<IntegrationSource, String>,
+    alerts: AlertsManager,
+    path: PathBuf,
+    open: Arc<Mutex<HashMap<String, Uuid>>>,
+}
+
+impl Integrations {
+    pub fn new(config: IntegrationsConfig, dir: &str, alerts: AlertsManager) -> Result<Self> {
+        fs::create_dir_all(dir).context(format!("Failed to create integrations directory: {}", dir))?;
+
+        let mut tokens = HashMap::new();
+        for source in [IntegrationSource::Alertmanager, IntegrationSource::Grafana] {
+            let settings = match source {
+                IntegrationSource::Alertmanager => &config.alertmanager,
+                IntegrationSource::Grafana => &config.grafana,
+            };
+            if let Some(token) = read_token(source, settings)? {
+                info!("Accepting {} alerts at /api/integrations/{}", source.as_str(), source.as_str());
+                tokens.insert(source, token);
+            }
+        }
+
+        let path = Path::new(dir).join("open_alerts.json");
+        let open: HashMap<String, Uuid> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring invalid integrations file {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Ok(Self {
+            config,
+            tokens,
+            alerts,
+            path,
+            open: Arc::new(Mutex::new(open)),
+        })
+    }
+
+    fn settings(&self, source: IntegrationSource) -> &WebhookIntegrationConfig {
+        match source {
+            IntegrationSource::Alertmanager => &self.config.alertmanager,
+            IntegrationSource::Grafana => &self.config.grafana,
+        }
+    }
+
+    pub fn is_enabled(&self, source: IntegrationSource) -> bool {
+        self.tokens.contains_key(&source)
+    }
+
+    pub fn accepts_token(&self, source: IntegrationSource, presented: &str) -> bool {
+        match self.tokens.get(&source) {
+            Some(token) => constant_time_eq(token.as_bytes(), presented.as_bytes()),
+            None => false,
+        }
+    }
+
+    fn severity(&self, source: IntegrationSource, alert: &ExternalAlert) -> AlertSeverity {
+        let settings = self.settings(source);
+        alert.labels.get(&settings.severity_label)
+            .and_then(|value| severity_from_label(value))
+            .unwrap_or_else(|| settings.default_severity.clone())
+    }
+
+    // Whether the alert is still waiting for someone; one resolved or closed by hand is not
+    fn is_open(&self, id: Uuid) -> bool {
+        match self.alerts.get_alert(id) {
+            Ok(alert) => !matches!(alert.status, AlertStatus::Resolved | AlertStatus::Closed),
+            Err(_) => false,
+        }
+    }
+
+    pub fn receive(&self, source: IntegrationSource, notification: Vec<ExternalAlert>) -> Result<WebhookOutcome> {
+        let mut open = self.open.lock().map_err(|_| anyhow!("Failed to acquire lock on integration alerts"))?;
+        let mut outcome = WebhookOutcome::default();
+
+        for alert in notification {
+            let key = format!("{}:{}", source.as_str(), alert.fingerprint);
+            let existing = open.get(&key).copied().filter(|id| self.is_open(*id));
+            match (alert.status, existing) {
+                (NotificationStatus::Firing, Some(_)) => outcome.duplicates += 1,
+                (NotificationStatus::Firing, None) => {
+                    let id = self.alerts.create_alert(
+                        self.severity(source, &alert),
+                        title(source, &alert),
+                        description(&alert),
+                        source.as_str().to_string(),
+                        Vec::new(),
+                    )?;
+                    open.insert(key, id);
+                    outcome.created.push(id);
+                },
+                (NotificationStatus::Resolved, Some(id)) => {
+                    self.alerts.update_status(id, AlertStatus::Resolved)?;
+                    open.remove(&key);
+                    outcome.resolved.push(id);
+                },
+                (NotificationStatus::Resolved, None) => {
+                    open.remove(&key);
+                    outcome.unmatched += 1;
+                },
+            }
+        }
+
+        fs::write(&self.path, serde_json::to_string(&*open)?)
+            .context(format!("Failed to write integrations file: {:?}", self.path))?;
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALERTMANAGER_FIRING: &str = include_str!("fixtures/alertmanager_firing.json");
+    const ALERTMANAGER_RESOLVED: &str = include_str!("fixtures/alertmanager_resolved.json");
+    const GRAFANA_FIRING: &str = include_str!("fixtures/grafana_firing.json");
+    const GRAFANA_RESOLVED: &str = include_str!("fixtures/grafana_resolved.json");
+
+    fn integrations() -> (Integrations, AlertsManager, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let alerts = AlertsManager::new(dir.path().join("alerts").to_str().unwrap()).unwrap();
+        let integrations = Integrations::new(
+            IntegrationsConfig::default(),
+            dir.path().join("integrations").to_str().unwrap(),
+            alerts.clone(),
+        ).unwrap();
+        (integrations, alerts, dir)
+    }
+
+    fn parse(source: IntegrationSource, fixture: &str) -> Vec<ExternalAlert> {
+        parse_notification(source, &serde_json::from_str(fixture).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn alertmanager_firing_opens_alerts_once() {
+        let (integrations, alerts, _dir) = integrations();
+        let notification = parse(IntegrationSource::Alertmanager, ALERTMANAGER_FIRING);
+        assert_eq!(notification.len(), 2);
+        assert_eq!(notification[0].fingerprint, "c0a1b2c3d4e5f601");
+
+        let outcome = integrations.receive(IntegrationSource::Alertmanager, notification.clone()).unwrap();
+        assert_eq!(outcome.created.len(), 2);
+
+        let alert = alerts.get_alert(outcome.created[0]).unwrap();
+        assert_eq!(alert.severity, AlertSeverity::Critical);
+        assert_eq!(alert.source, "alertmanager");
+        assert_eq!(alert.title, "NodeDiskFull: Disk almost full on fs01");
+        assert!(alert.description.contains("instance=\"fs01:9100\""));
+        assert!(alert.description.contains("source link: http://prometheus:9090/graph"));
+        assert_eq!(alerts.get_alert(outcome.created[1]).unwrap().severity, AlertSeverity::Medium);
+
+        // Alertmanager repeats the notification while the alerts fire
+        let repeat = integrations.receive(IntegrationSource::Alertmanager, notification).unwrap();
+        assert!(repeat.created.is_empty());
+        assert_eq!(repeat.duplicates, 2);
+    }
+
+    #[test]
+    fn alertmanager_resolved_resolves_the_alert() {
+        let (integrations, alerts, _dir) = integrations();
+        let created = integrations.receive(IntegrationSource::Alertmanager, parse(IntegrationSource::Alertmanager, ALERTMANAGER_FIRING)).unwrap().created;
+
+        let outcome = integrations.receive(IntegrationSource::Alertmanager, parse(IntegrationSource::Alertmanager, ALERTMANAGER_RESOLVED)).unwrap();
+        assert_eq!(outcome.resolved, vec![created[0]]);
+        assert_eq!(alerts.get_alert(created[0]).unwrap().status, AlertStatus::Resolved);
+        assert_eq!(alerts.get_alert(created[1]).unwrap().status, AlertStatus::New);
+
+        // Firing again after being resolved is a new alert
+        let again = integrations.receive(IntegrationSource::Alertmanager, parse(IntegrationSource::Alertmanager, ALERTMANAGER_FIRING)).unwrap();
+        assert_eq!(again.created.len(), 1);
+        assert_eq!(again.duplicates, 1);
+    }
+
+    #[test]
+    fn grafana_firing_and_resolved() {
+        let (integrations, alerts, _dir) = integrations();
+        let firing = parse(IntegrationSource::Grafana, GRAFANA_FIRING);
+        assert_eq!(firing.len(), 1);
+
+        let created = integrations.receive(IntegrationSource::Grafana, firing).unwrap().created;
+        assert_eq!(created.len(), 1);
+        let alert = alerts.get_alert(created[0]).unwrap();
+        assert_eq!(alert.severity, AlertSeverity::High);
+        assert_eq!(alert.source, "grafana");
+        assert_eq!(alert.title, "HighLatency: API latency above 2s");
+        assert!(alert.description.contains("dashboard link: https://grafana.example/d/api-latency"));
+
+        let outcome = integrations.receive(IntegrationSource::Grafana, parse(IntegrationSource::Grafana, GRAFANA_RESOLVED)).unwrap();
+        assert_eq!(outcome.resolved, created);
+        assert_eq!(alerts.get_alert(created[0]).unwrap().status, AlertStatus::Resolved);
+    }
+
+    #[test]
+    fn sources_do_not_share_fingerprints() {
+        let (integrations, _alerts, _dir) = integrations();
+        integrations.receive(IntegrationSource::Grafana, parse(IntegrationSource::Grafana, GRAFANA_FIRING)).unwrap();
+
+        // Same fingerprint, other tool
+        let outcome = integrations.receive(IntegrationSource::Alertmanager, parse(IntegrationSource::Grafana, GRAFANA_RESOLVED)).unwrap();
+        assert!(outcome.resolved.is_empty());
+        assert_eq!(outcome.unmatched, 1);
+    }
+
+    #[test]
+    fn open_alerts_survive_a_restart() {
+        let (integrations, alerts, dir) = integrations();
+        integrations.receive(IntegrationSource::Grafana, parse(IntegrationSource::Grafana, GRAFANA_FIRING)).unwrap();
+
+        let restarted = Integrations::new(
+            IntegrationsConfig::default(),
+            dir.path().join("integrations").to_str().unwrap(),
+            alerts,
+        ).unwrap();
+        let outcome = restarted.receive(IntegrationSource::Grafana, parse(IntegrationSource::Grafana, GRAFANA_FIRING)).unwrap();
+        assert_eq!(outcome.duplicates, 1);
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored_and_missing_fingerprints_derived() {
+        let payload = serde_json::json!({
+            "version": "9",
+            "somethingNew": {"nested": true},
+            "alerts": [{
+                "status": "firing",
+                "labels": {"alertname": "Up", "job": "node"},
+                "extra": [1, 2, 3],
+            }],
+        });
+        let alerts = parse_notification(IntegrationSource::Alertmanager, &payload).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].fingerprint.len(), 16);
+
+        let reordered = serde_json::json!({"alerts": [{"status": "resolved", "labels": {"job": "node", "alertname": "Up"}}]});
+        assert_eq!(parse_notification(IntegrationSource::Alertmanager, &reordered).unwrap()[0].fingerprint, alerts[0].fingerprint);
+    }
+
+    #[test]
+    fn invalid_payloads_report_every_problem() {
+        let payload = serde_json::json!({
+            "alerts": [
+                {"labels": {"alertname": "A"}},
+                {"status": "pending", "labels": {"alertname": "B"}},
+                {"status": "firing", "labels": "alertname=C"},
+                "firing",
+            ],
+        });
+        let errors = parse_notification(IntegrationSource::Alertmanager, &payload).unwrap_err();
+        assert_eq!(errors, vec![
+            "alerts[0].status: missing".to_string(),
+            "alerts[1].status: \"pending\" is neither \"firing\" nor \"resolved\"".to_string(),
+            "alerts[2].labels: must be an object".to_string(),
+            "alerts[3]: must be an object".to_string(),
+        ]);
+
+        assert_eq!(parse_notification(IntegrationSource::Grafana, &serde_json::json!({"status": "firing"})).unwrap_err(),
+                   vec!["alerts: missing".to_string()]);
+        assert!(parse_notification(IntegrationSource::Grafana, &serde_json::json!([])).is_err());
+    }
+
+    #[test]
+    fn severity_labels() {
+        assert_eq!(severity_from_label("CRITICAL"), Some(AlertSeverity::Critical));
+        assert_eq!(severity_from_label("warning"), Some(AlertSeverity::Medium));
+        assert_eq!(severity_from_label("info"), Some(AlertSeverity::Low));
+        assert_eq!(severity_from_label("whatever"), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::network::InterfaceInfo;
+
+// NICs get renamed across reboots, so metadata of a vanished interface is kept
+// (and flagged) this long before it is dropped
+const MISSING_GRACE_DAYS: i64 = 30;
+
+// Operator-supplied labels for an interface, e.g. "uplink to ISP"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceMetadata {
+    pub name: String,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    pub updated_at: DateTime<Utc>,
+    pub updated_by: String,
+    // Set while the interface is not present on the host
+    #[serde(default)]
+    pub missing_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
+}
+
+// Partial update; absent fields are kept, empty strings clear description and owner,
+// a tag set to null is removed
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InterfaceMetadataPatch {
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: HashMap<String, Option<String>>,
+    // Site id, an empty string clears it
+    pub site_id: Option<String>,
+}
+
+fn valid_interface_name(name: &str) -> bool {
+    // Linux limits names to 15 bytes; anything else could escape the metadata directory
+    !name.is_empty()
+        && name.len() <= 15
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '@' | ':'))
+}
+
+#[derive(Clone)]
+pub struct InterfaceMetadataStore {
+    metadata_dir: PathBuf,
+    metadata: Arc<Mutex<HashMap<String, InterfaceMetadata>>>,
+}
+
+impl InterfaceMetadataStore {
+    pub fn new(metadata_dir: &str) -> Result<Self> {
+        let metadata_dir = PathBuf::from(metadata_dir);
+
+        if !metadata_dir.exists() {
+            fs::create_dir_all(&metadata_dir)
+                .context(format!("Failed to create interface metadata directory: {:?}", metadata_dir))?;
+            info!("Created interface metadata directory: {:?}", metadata_dir);
+        }
+
+        let mut metadata = HashMap::new();
+
+        for entry in fs::read_dir(&metadata_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read interface metadata file: {:?}", path))?;
+            match serde_json::from_str::<InterfaceMetadata>(&contents) {
+                Ok(entry) => {
+                    metadata.insert(entry.name.clone(), entry);
+                },
+                Err(e) => warn!("Skipping invalid interface metadata file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded metadata for {} interfaces", metadata.len());
+
+        Ok(Self {
+            metadata_dir,
+            metadata: Arc::new(Mutex::new(metadata)),
+        })
+    }
+
+    fn save_metadata(&self, entry: &InterfaceMetadata) -> Result<()> {
+        let path = self.metadata_dir.join(format!("{}.json", entry.name));
+        let json = serde_json::to_string_pretty(entry)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write interface metadata file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn delete_metadata_file(&self, name: &str) -> Result<()> {
+        let path = self.metadata_dir.join(format!("{}.json", name));
+        if path.exists() {
+            fs::remove_file(&path)
+                .context(format!("Failed to delete interface metadata file: {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    pub fn get_all(&self) -> Result<Vec<InterfaceMetadata>> {
+        match self.metadata.lock() {
+            Ok(metadata) => {
+                let mut all: Vec<InterfaceMetadata> = metadata.values().cloned().collect();
+                all.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on interface metadata")),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<InterfaceMetadata>> {
+        match self.metadata.lock() {
+            Ok(metadata) => Ok(metadata.get(name).cloned()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on interface metadata")),
+        }
+    }
+
+    pub fn update(&self, name: &str, patch: InterfaceMetadataPatch, updated_by: &str) -> Result<InterfaceMetadata> {
+        if !valid_interface_name(name) {
+            return Err(anyhow!("Invalid interface name: {}", name));
+        }
+        if patch.tags.keys().any(|key| key.trim().is_empty()) {
+            return Err(anyhow!("Tag names cannot be empty"));
+        }
+        let site_id = match patch.site_id.as_deref().map(str::trim) {
+            None => None,
+            Some("") => Some(None),
+            Some(id) => Some(Some(Uuid::parse_str(id).map_err(|_| anyhow!("Invalid site id: {}", id))?)),
+        };
+
+        match self.metadata.lock() {
+            Ok(mut metadata) => {
+                let entry = metadata.entry(name.to_string()).or_insert_with(|| InterfaceMetadata {
+                    name: name.to_string(),
+                    description: None,
+                    owner: None,
+                    tags: BTreeMap::new(),
+                    updated_at: Utc::now(),
+                    updated_by: updated_by.to_string(),
+                    missing_since: None,
+                    site_id: None,
+                });
+
+                if let Some(description) = patch.description {
+                    entry.description = Some(description).filter(|d| !d.trim().is_empty());
+                }
+                if let Some(owner) = patch.owner {
+                    entry.owner = Some(owner).filter(|o| !o.trim().is_empty());
+                }
+                for (key, value) in patch.tags {
+                    match value {
+                        Some(value) => entry.tags.insert(key, value),
+                        None => entry.tags.remove(&key),
+                    };
+                }
+                if let Some(site_id) = site_id {
+                    entry.site_id = site_id;
+                }
+                entry.updated_at = Utc::now();
+                entry.updated_by = updated_by.to_string();
+
+                let entry = entry.clone();
+                self.save_metadata(&entry)?;
+                info!("Updated metadata of interface {}", name);
+                Ok(entry)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on interface metadata")),
+        }
+    }
+
+    // Attaches the metadata to the interfaces present on the host and flags entries
+    // whose interface is gone; entries missing for longer than the grace period are dropped
+    pub fn apply(&self, interfaces: &mut [InterfaceInfo]) -> Result<()> {
+        let present: HashSet<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        let now = Utc::now();
+
+        match self.metadata.lock() {
+            Ok(mut metadata) => {
+                let mut expired = Vec::new();
+
+                for entry in metadata.values_mut() {
+                    let missing_since = if present.contains(entry.name.as_str()) {
+                        None
+                    } else {
+                        Some(entry.missing_since.unwrap_or(now))
+                    };
+
+                    if missing_since.map_or(false, |since| since + Duration::days(MISSING_GRACE_DAYS) <= now) {
+                        expired.push(entry.name.clone());
+                        continue;
+                    }
+
+                    if entry.missing_since != missing_since {
+                        entry.missing_since = missing_since;
+                        self.save_metadata(entry)?;
+                        match missing_since {
+                            Some(_) => warn!("Interface {} with metadata is no longer present", entry.name),
+                            None => info!("Interface {} is present again", entry.name),
+                        }
+                    }
+                }
+
+                for name in expired {
+                    metadata.remove(&name);
+                    self.delete_metadata_file(&name)?;
+                    info!("Dropped metadata of interface {}, missing for {} days", name, MISSING_GRACE_DAYS);
+                }
+
+                for interface in interfaces.iter_mut() {
+                    interface.metadata = metadata.get(&interface.name).cloned();
+                }
+
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on interface metadata")),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use axum::body::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use anyhow::{Result, anyhow};
+
+use crate::flow_export::csv_field;
+use crate::models::Asset;
+use crate::printers::{Printer, PrinterSupply, SupplyType};
+
+// Makes Excel read the CSV as UTF-8 instead of the locale's code page
+const UTF8_BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+const PRINTER_FIELDS: [&str; 15] = [
+    "id", "name", "ip_address", "mac_address", "model", "location", "location_id", "site_id",
+    "status", "last_seen", "color", "duplex", "scanner", "pages_per_minute", "queued_jobs",
+];
+
+// Column prefix of each supply type; a printer's supplies become `<prefix>_<field>` columns
+const SUPPLY_TYPES: [(SupplyType, &str); 9] = [
+    (SupplyType::Toner, "toner"),
+    (SupplyType::Ink, "ink"),
+    (SupplyType::Drum, "drum"),
+    (SupplyType::Fuser, "fuser"),
+    (SupplyType::TransferBelt, "transfer_belt"),
+    (SupplyType::WasteToner, "waste_toner"),
+    (SupplyType::Staples, "staples"),
+    (SupplyType::Paper, "paper"),
+    (SupplyType::Other, "other"),
+];
+
+const SUPPLY_FIELDS: [&str; 4] = ["level", "status", "part_number", "last_replaced"];
+
+const ASSET_FIELDS: [&str; 14] = [
+    "id", "name", "asset_type", "ip_address", "mac_address", "operating_system", "owner",
+    "location", "location_id", "site_id", "purchase_date", "status", "tags", "pending_drift",
+];
+
+pub fn printer_fields() -> Vec<String> {
+    let supplies = SUPPLY_TYPES.iter()
+        .flat_map(|(_, prefix)| SUPPLY_FIELDS.iter().map(move |field| format!("{}_{}", prefix, field)));
+    PRINTER_FIELDS.iter().map(|f| f.to_string()).chain(supplies).collect()
+}
+
+pub fn asset_fields() -> Vec<String> {
+    ASSET_FIELDS.iter().map(|f| f.to_string()).collect()
+}
+
+// Every base column plus the level of each supply type any of the printers has
+pub fn default_printer_fields(printers: &[Printer]) -> Vec<String> {
+    let present = SUPPLY_TYPES.iter()
+        .filter(|(supply_type, _)| printers.iter().any(|p| p.supplies.iter().any(|s| s.supply_type == *supply_type)))
+        .map(|(_, prefix)| format!("{}_level", prefix));
+    PRINTER_FIELDS.iter().map(|f| f.to_string()).chain(present).collect()
+}
+
+// The comma-separated `fields` parameter checked against the allowed columns, in the
+// order given; the defaults when it is absent
+pub fn select_fields(requested: Option<&str>, allowed: &[String], defaults: Vec<String>) -> Result<Vec<String>> {
+    let Some(requested) = requested else { return Ok(defaults) };
+
+    let mut fields: Vec<String> = Vec::new();
+    for field in requested.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        if !allowed.iter().any(|a| a == field) {
+            return Err(anyhow!("Unknown field {}, expected one of: {}", field, allowed.join(", ")));
+        }
+        if fields.iter().any(|f| f == field) {
+            return Err(anyhow!("Field {} is selected twice", field));
+        }
+        fields.push(field.to_string());
+    }
+    if fields.is_empty() {
+        return Err(anyhow!("Select at least one field"));
+    }
+    Ok(fields)
+}
+
+fn supply_value(supply: &PrinterSupply, field: &str) -> Value {
+    match field {
+        "level" => json!(supply.level),
+        "status" => json!(supply.status),
+        "part_number" => json!(supply.part_number),
+        "last_replaced" => json!(supply.last_replaced),
+        _ => Value::Null,
+    }
+}
+
+// A printer with several supplies of one type, e.g. four toners, gets them in one cell
+// as `Name: value; ...`
+fn supply_column(printer: &Printer, supply_type: &SupplyType, field: &str) -> Value {
+    let supplies: Vec<&PrinterSupply> = printer.supplies.iter().filter(|s| s.supply_type == *supply_type).collect();
+    match supplies.as_slice() {
+        [] => Value::Null,
+        [supply] => supply_value(supply, field),
+        several => Value::String(several.iter()
+            .map(|s| format!("{}: {}", s.name, render(&supply_value(s, field))))
+            .collect::<Vec<_>>()
+            .join("; ")),
+    }
+}
+
+pub fn printer_value(printer: &Printer, field: &str) -> Value {
+    match field {
+        "id" => json!(printer.id),
+        "name" => json!(printer.name),
+        "ip_address" => json!(printer.ip_address),
+        "mac_address" => json!(printer.mac_address),
+        "model" => json!(printer.model),
+        "location" => json!(printer.location),
+        "location_id" => json!(printer.location_id),
+        "site_id" => json!(printer.site_id),
+        "status" => json!(printer.status),
+        "last_seen" => json!(printer.last_seen),
+        "color" => json!(printer.capabilities.color),
+        "duplex" => json!(printer.capabilities.duplex),
+        "scanner" => json!(printer.capabilities.scanner),
+        "pages_per_minute" => json!(printer.capabilities.pages_per_minute),
+        "queued_jobs" => json!(printer.queue_status.len()),
+        _ => SUPPLY_TYPES.iter()
+            .find_map(|(supply_type, prefix)| {
+                let supply_field = field.strip_prefix(prefix)?.strip_prefix('_')?;
+                SUPPLY_FIELDS.contains(&supply_field).then(|| supply_column(printer, supply_type, supply_field))
+            })
+            .unwrap_or(Value::Null),
+    }
+}
+
+pub fn asset_value(asset: &Asset, field: &str) -> Value {
+    match field {
+        "id" => json!(asset.id),
+        "name" => json!(asset.name),
+        "asset_type" => json!(asset.asset_type),
+        "ip_address" => json!(asset.ip_address),
+        "mac_address" => json!(asset.mac_address),
+        "operating_system" => json!(asset.operating_system),
+        "owner" => json!(asset.owner),
+        "location" => json!(asset.location),
+        "location_id" => json!(asset.location_id),
+        "site_id" => json!(asset.site_id),
+        "purchase_date" => json!(asset.purchase_date),
+        "status" => json!(asset.status),
+        "tags" => json!(asset.tags),
+        "pending_drift" => json!(asset.drift.len()),
+        _ => Value::Null,
+    }
+}
+
+// Text of a cell; lists are joined with semicolons
+fn render(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(render).collect::<Vec<_>>().join(";"),
+        other => other.to_string(),
+    }
+}
+
+fn csv_row(cells: impl Iterator<Item = String>) -> String {
+    let mut row = cells.map(|c| csv_field(&c)).collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row
+}
+
+// Streams the records one row at a time: CSV with a header row, or a JSON array of
+// objects with the fields in the selected order
+pub fn export_stream<T, F>(format: ExportFormat,
+                           bom: bool,
+                           fields: Vec<String>,
+                           records: Vec<T>,
+                           value: F) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    T: Send + 'static,
+    F: Fn(&T, &str) -> Value + Send + 'static,
+{
+    let (head, tail) = match format {
+        ExportFormat::Csv => {
+            let bom = if bom { UTF8_BOM } else { "" };
+            (format!("{}{}", bom, csv_row(fields.iter().cloned())), String::new())
+        },
+        ExportFormat::Json => ("[".to_string(), "]".to_string()),
+    };
+
+    let rows = records.into_iter().enumerate().map(move |(index, record)| {
+        let row = match format {
+            ExportFormat::Csv => csv_row(fields.iter().map(|f| render(&value(&record, f)))),
+            ExportFormat::Json => {
+                let members: Vec<String> = fields.iter()
+                    .map(|f| format!("{}:{}", json!(f), value(&record, f)))
+                    .collect();
+                format!("{}{{{}}}", if index == 0 { "" } else { "," }, members.join(","))
+            },
+        };
+        Ok(Bytes::from(row))
+    });
+
+    stream::once(async move { Ok(Bytes::from(head)) })
+        .chain(stream::iter(rows))
+        .chain(stream::once(async move { Ok(Bytes::from(tail)) }))
+}
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use rtnetlink::constants::RTMGRP_LINK;
+use rtnetlink::packet::link::{LinkAttribute, State};
+use rtnetlink::packet::RouteNetlinkMessage;
+use rtnetlink::packet_core::NetlinkPayload;
+use rtnetlink::sys::{AsyncSocket, SocketAddr};
+use serde::Serialize;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::LinkFlapConfig;
+use crate::models::{AlertSeverity, AlertStatus};
+use crate::network::InterfaceInfo;
+
+const ALERT_SOURCE: &str = "link_flap";
+
+#[derive(Debug, Default)]
+struct FlapState {
+    is_up: Option<bool>,
+    transitions: VecDeque<DateTime<Utc>>,
+    alert_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlapStatus {
+    pub interface: String,
+    // Transitions within the window
+    pub score: usize,
+    pub flapping: bool,
+    pub alert_id: Option<Uuid>,
+}
+
+// Scores link state transitions per interface over a sliding window. Has its own lock,
+// independent of the network manager's, and alerts are raised after it is released.
+#[derive(Clone)]
+pub struct LinkFlapDetector {
+    config: LinkFlapConfig,
+    alerts: AlertsManager,
+    states: Arc<Mutex<HashMap<String, FlapState>>>,
+}
+
+impl LinkFlapDetector {
+    pub fn new(config: LinkFlapConfig, alerts: AlertsManager) -> Self {
+        Self {
+            config,
+            alerts,
+            states: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    fn window(&self) -> Duration {
+        Duration::seconds(self.config.window_secs.max(1) as i64)
+    }
+
+    // Notes the link state of an interface; only a change counts as a transition
+    pub fn observe(&self, interface: &str, is_up: bool, at: DateTime<Utc>) -> Result<()> {
+        match self.states.lock() {
+            Ok(mut states) => {
+                let state = states.entry(interface.to_string()).or_default();
+                if state.is_up.map_or(false, |was_up| was_up != is_up) {
+                    state.transitions.push_back(at);
+                }
+                state.is_up = Some(is_up);
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on link flap states")),
+        }
+        self.evaluate(at)
+    }
+
+    // Drops transitions older than the window, raises an alert for interfaces whose
+    // score reached the threshold and resolves it once the score fell to clear_below
+    pub fn evaluate(&self, now: DateTime<Utc>) -> Result<()> {
+        let cutoff = now - self.window();
+        let (raise, resolve) = match self.states.lock() {
+            Ok(mut states) => {
+                let mut raise = Vec::new();
+                let mut resolve = Vec::new();
+                for (name, state) in states.iter_mut() {
+                    while state.transitions.front().map_or(false, |at| *at < cutoff) {
+                        state.transitions.pop_front();
+                    }
+                    let score = state.transitions.len();
+                    if state.alert_id.is_none() && score >= self.config.flap_threshold {
+                        raise.push((name.clone(), score));
+                    } else if score <= self.config.clear_below {
+                        if let Some(id) = state.alert_id.take() {
+                            resolve.push((name.clone(), id));
+                        }
+                    }
+                }
+                (raise, resolve)
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on link flap states")),
+        };
+
+        for (name, score) in raise {
+            warn!("Interface {} is flapping: {} link transitions in {}s", name, score, self.config.window_secs);
+            let id = self.alerts.create_alert(
+                AlertSeverity::Medium,
+                format!("Interface {} is flapping", name),
+                format!("The link changed state {} times within {} seconds. Check the cable, the port on the other end and its negotiation settings.",
+                        score, self.config.window_secs),
+                ALERT_SOURCE.to_string(),
+                Vec::new(),
+            )?;
+            if let Ok(mut states) = self.states.lock() {
+                if let Some(state) = states.get_mut(&name) {
+                    state.alert_id = Some(id);
+                }
+            }
+        }
+
+        for (name, id) in resolve {
+            info!("Interface {} stopped flapping", name);
+            if let Ok(alert) = self.alerts.get_alert(id) {
+                if !matches!(alert.status, AlertStatus::Resolved | AlertStatus::Closed) {
+                    self.alerts.update_status(id, AlertStatus::Resolved)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn statuses(&self) -> Result<Vec<FlapStatus>> {
+        match self.states.lock() {
+            Ok(states) => {
+                let mut statuses: Vec<FlapStatus> = states.iter()
+                    .map(|(name, state)| FlapStatus {
+                        interface: name.clone(),
+                        score: state.transitions.len(),
+                        flapping: state.alert_id.is_some(),
+                        alert_id: state.alert_id,
+                    })
+                    .collect();
+                statuses.sort_by(|a, b| b.score.cmp(&a.score).then(a.interface.cmp(&b.interface)));
+                Ok(statuses)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on link flap states")),
+        }
+    }
+
+    // Sets the flapping flag of each interface; an interface is flapping while its alert is open
+    pub fn mark(&self, interfaces: &mut [InterfaceInfo]) -> Result<()> {
+        match self.states.lock() {
+            Ok(states) => {
+                for interface in interfaces.iter_mut() {
+                    interface.flapping = states.get(&interface.name).map_or(false, |s| s.alert_id.is_some());
+                }
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on link flap states")),
+        }
+    }
+
+    // Follows link notifications from the kernel; polling would miss a port bouncing
+    // faster than the poll interval
+    pub async fn watch(self) -> Result<()> {
+        let (mut connection, _handle, mut messages) = rtnetlink::new_connection()
+            .context("Failed to create netlink connection for link events")?;
+        connection.socket_mut().socket_mut()
+            .bind(&SocketAddr::new(0, RTMGRP_LINK))
+            .context("Failed to subscribe to link events")?;
+        tokio::spawn(connection);
+
+        info!("Watching link events for interface flaps");
+        while let Some((message, _)) = messages.next().await {
+            let link = match message.payload {
+                NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewLink(link)) => link,
+                _ => continue,
+            };
+
+            let name = link.attributes.iter().find_map(|attr| match attr {
+                LinkAttribute::IfName(name) => Some(name.clone()),
+                _ => None,
+            });
+            let is_up = link.attributes.iter().find_map(|attr| match attr {
+                LinkAttribute::OperState(state) => Some(*state == State::Up),
+                _ => None,
+            });
+
+            if let (Some(name), Some(is_up)) = (name, is_up) {
+                if let Err(e) = self.observe(&name, is_up, Utc::now()) {
+                    warn!("Failed to record link event of {}: {}", name, e);
+                }
+            }
+        }
+
+        Err(anyhow!("Link event subscription closed"))
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+// Level of a node in the site → building → floor hierarchy
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LocationKind {
+    Site,
+    Building,
+    Floor,
+}
+
+impl LocationKind {
+    // Kind a parent node must have, None for roots
+    fn parent_kind(&self) -> Option<LocationKind> {
+        match self {
+            LocationKind::Site => None,
+            LocationKind::Building => Some(LocationKind::Site),
+            LocationKind::Floor => Some(LocationKind::Building),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationNode {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: LocationKind,
+    pub parent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Human readable path of a node, e.g. "HQ / Building B / Floor 3"
+pub fn location_path(nodes: &HashMap<Uuid, LocationNode>, id: Uuid) -> String {
+    let mut names = Vec::new();
+    let mut current = nodes.get(&id);
+
+    while let Some(node) = current {
+        names.push(node.name.clone());
+        current = node.parent_id.and_then(|p| nodes.get(&p));
+    }
+
+    names.reverse();
+    names.join(" / ")
+}
+
+#[derive(Clone)]
+pub struct LocationManager {
+    locations_dir: PathBuf,
+    locations: Arc<Mutex<HashMap<Uuid, LocationNode>>>,
+}
+
+impl LocationManager {
+    pub fn new(locations_dir: &str) -> Result<Self> {
+        let locations_dir = PathBuf::from(locations_dir);
+
+        if !locations_dir.exists() {
+            fs::create_dir_all(&locations_dir)
+                .context(format!("Failed to create locations directory: {:?}", locations_dir))?;
+            info!("Created locations directory: {:?}", locations_dir);
+        }
+
+        let mut locations = HashMap::new();
+
+        for entry in fs::read_dir(&locations_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read location file: {:?}", path))?;
+            match serde_json::from_str::<LocationNode>(&contents) {
+                Ok(node) => {
+                    locations.insert(node.id, node);
+                },
+                Err(e) => warn!("Skipping invalid location file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} location nodes", locations.len());
+
+        Ok(Self {
+            locations_dir,
+            locations: Arc::new(Mutex::new(locations)),
+        })
+    }
+
+    fn save_location(&self, node: &LocationNode) -> Result<()> {
+        let path = self.locations_dir.join(format!("{}.json", node.id));
+        let json = serde_json::to_string_pretty(node)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write location file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn check_parent(locations: &HashMap<Uuid, LocationNode>, kind: LocationKind, parent_id: Option<Uuid>) -> Result<()> {
+        match (kind.parent_kind(), parent_id) {
+            (None, None) => Ok(()),
+            (None, Some(_)) => Err(anyhow!("A site cannot have a parent location")),
+            (Some(expected), None) => Err(anyhow!("A {:?} needs a parent {:?}", kind, expected)),
+            (Some(expected), Some(parent_id)) => {
+                let parent = locations.get(&parent_id)
+                    .ok_or_else(|| anyhow!("Parent location not found: {}", parent_id))?;
+
+                if parent.kind != expected {
+                    return Err(anyhow!("A {:?} must be placed under a {:?}, not a {:?}", kind, expected, parent.kind));
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    pub fn create_location(&self, name: String, kind: LocationKind, parent_id: Option<Uuid>) -> Result<LocationNode> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("Location name cannot be empty"));
+        }
+
+        match self.locations.lock() {
+            Ok(mut locations) => {
+                Self::check_parent(&locations, kind, parent_id)?;
+
+                let now = Utc::now();
+                let node = LocationNode {
+                    id: Uuid::new_v4(),
+                    name,
+                    kind,
+                    parent_id,
+                    created_at: now,
+                    updated_at: now,
+                };
+
+                self.save_location(&node)?;
+                locations.insert(node.id, node.clone());
+
+                info!("Created location {:?} {}", node.kind, node.name);
+                Ok(node)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on locations")),
+        }
+    }
+
+    // Renames and/or moves a node. Devices reference the node itself, so they move with it.
+    pub fn update_location(&self, id: Uuid, name: Option<String>, parent_id: Option<Uuid>) -> Result<LocationNode> {
+        match self.locations.lock() {
+            Ok(mut locations) => {
+                let mut node = locations.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Location not found: {}", id))?;
+
+                if let Some(name) = name {
+                    if name.trim().is_empty() {
+                        return Err(anyhow!("Location name cannot be empty"));
+                    }
+                    node.name = name;
+                }
+
+                if parent_id.is_some() {
+                    Self::check_parent(&locations, node.kind, parent_id)?;
+                    node.parent_id = parent_id;
+                }
+
+                node.updated_at = Utc::now();
+                self.save_location(&node)?;
+                locations.insert(id, node.clone());
+                Ok(node)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on locations")),
+        }
+    }
+
+    // Deletes a leaf node; callers must first reassign devices that reference it
+    pub fn delete_location(&self, id: Uuid) -> Result<()> {
+        match self.locations.lock() {
+            Ok(mut locations) => {
+                if !locations.contains_key(&id) {
+                    return Err(anyhow!("Location not found: {}", id));
+                }
+
+                if locations.values().any(|n| n.parent_id == Some(id)) {
+                    return Err(anyhow!("Location {} still has child locations", id));
+                }
+
+                let path = self.locations_dir.join(format!("{}.json", id));
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .context(format!("Failed to delete location file: {:?}", path))?;
+                }
+
+                locations.remove(&id);
+                info!("Deleted location: {}", id);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on locations")),
+        }
+    }
+
+    pub fn get_location(&self, id: Uuid) -> Result<LocationNode> {
+        match self.locations.lock() {
+            Ok(locations) => {
+                locations.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Location not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on locations")),
+        }
+    }
+
+    // Snapshot of the whole tree keyed by id
+    pub fn get_all_locations(&self) -> Result<HashMap<Uuid, LocationNode>> {
+        match self.locations.lock() {
+            Ok(locations) => Ok(locations.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on locations")),
+        }
+    }
+}
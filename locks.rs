@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::error;
+
+// Lock access for managers that survives a panic inside a critical section. A std lock
+// held across a panic is poisoned and every later lock() fails, leaving the subsystem
+// broken until restart. Here the guard is taken over instead, the poison is cleared and
+// the manager is flagged, since the panic may have left its state half-updated.
+#[derive(Clone)]
+pub struct LockHealth {
+    name: &'static str,
+    recoveries: Arc<AtomicU64>,
+    last_recovery: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockHealthStatus {
+    pub name: &'static str,
+    // False once a panic was caught inside a critical section, until restart
+    pub healthy: bool,
+    pub recoveries: u64,
+    pub last_recovery: Option<DateTime<Utc>>,
+}
+
+impl LockHealth {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            recoveries: Arc::new(AtomicU64::new(0)),
+            last_recovery: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn recover<G>(&self, poisoned: PoisonError<G>) -> G {
+        error!("Recovered {} after a panic while its lock was held; its state may be inconsistent", self.name);
+        self.recoveries.fetch_add(1, Ordering::SeqCst);
+        *self.last_recovery.lock().unwrap_or_else(PoisonError::into_inner) = Some(Utc::now());
+        poisoned.into_inner()
+    }
+
+    pub fn lock<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        mutex.lock().unwrap_or_else(|poisoned| {
+            mutex.clear_poison();
+            self.recover(poisoned)
+        })
+    }
+
+    pub fn read<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+        lock.read().unwrap_or_else(|poisoned| {
+            lock.clear_poison();
+            self.recover(poisoned)
+        })
+    }
+
+    pub fn write<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+        lock.write().unwrap_or_else(|poisoned| {
+            lock.clear_poison();
+            self.recover(poisoned)
+        })
+    }
+
+    pub fn status(&self) -> LockHealthStatus {
+        let recoveries = self.recoveries.load(Ordering::SeqCst);
+        LockHealthStatus {
+            name: self.name,
+            healthy: recoveries == 0,
+            recoveries,
+            last_recovery: *self.last_recovery.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use futures::stream::{self, Stream};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use anyhow::{Result, Context, anyhow};
+use tracing::info;
+
+use crate::config::LogTailConfig;
+use crate::models::{LogEntry, LogSeverity};
+use crate::sites::SiteScope;
+
+// Bounds the memory a client supplied pattern may compile to
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+
+// Filter given when the tail is opened
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TailParams {
+    pub min_severity: Option<LogSeverity>,
+    pub source: Option<String>,
+    // Regular expression matched against the message
+    pub pattern: Option<String>,
+}
+
+pub struct TailFilter {
+    min_severity: Option<LogSeverity>,
+    source: Option<String>,
+    pattern: Option<Regex>,
+    sites: SiteScope,
+}
+
+impl TailFilter {
+    pub fn compile(params: TailParams, sites: SiteScope) -> Result<Self> {
+        let pattern = match params.pattern.as_deref().filter(|p| !p.is_empty()) {
+            Some(pattern) => Some(RegexBuilder::new(pattern)
+                .size_limit(MAX_PATTERN_SIZE)
+                .dfa_size_limit(MAX_PATTERN_SIZE)
+                .build()
+                .context("Invalid message pattern")?),
+            None => None,
+        };
+
+        Ok(Self {
+            min_severity: params.min_severity,
+            source: params.source,
+            pattern,
+            sites,
+        })
+    }
+
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.min_severity.as_ref().map_or(true, |min| entry.severity >= *min)
+            && self.source.as_ref().map_or(true, |source| &entry.source == source)
+            && self.pattern.as_ref().map_or(true, |pattern| pattern.is_match(&entry.message))
+            && self.sites.allows(entry.site_id)
+    }
+}
+
+// What a tail client receives
+#[derive(Debug, Clone)]
+pub enum TailEvent {
+    Log(Arc<LogEntry>),
+    // Entries skipped because the client did not keep up
+    Dropped { count: u64 },
+}
+
+// Releases the session slot when the client goes away
+struct SessionGuard(Arc<AtomicUsize>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Fans ingested entries out to live tail clients. Publishing never waits: a client
+// that falls more than the buffer behind loses the oldest entries and is told how many.
+#[derive(Clone)]
+pub struct LogTail {
+    config: LogTailConfig,
+    sender: broadcast::Sender<Arc<LogEntry>>,
+    sessions: Arc<AtomicUsize>,
+}
+
+impl LogTail {
+    pub fn new(config: LogTailConfig) -> Self {
+        let (sender, _) = broadcast::channel(config.buffer.max(1));
+        Self {
+            config,
+            sender,
+            sessions: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn publish(&self, entry: &LogEntry) {
+        // Nothing to clone when nobody is tailing; send only fails in that case
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(Arc::new(entry.clone()));
+        }
+    }
+
+    pub fn subscribe(&self, filter: TailFilter) -> Result<impl Stream<Item = TailEvent>> {
+        let max = self.config.max_sessions;
+        if self.sessions.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| if n < max { Some(n + 1) } else { None }).is_err() {
+            return Err(anyhow!("Too many live tail sessions (limit {})", max));
+        }
+        let guard = SessionGuard(self.sessions.clone());
+        info!("Live tail session opened ({} active)", self.sessions.load(Ordering::SeqCst));
+
+        let receiver = self.sender.subscribe();
+        Ok(stream::unfold((receiver, filter, guard), |(mut receiver, filter, guard)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(entry) if filter.matches(&entry) => return Some((TailEvent::Log(entry), (receiver, filter, guard))),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(count)) => return Some((TailEvent::Dropped { count }, (receiver, filter, guard))),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow};
+
+use crate::models::{EventCategory, LogEntry, LogSeverity};
+use crate::sites::SiteScope;
+use crate::tags::{self, TaggedStore};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub min_severity: Option<LogSeverity>,
+    pub source: Option<String>,
+    pub host: Option<String>,
+    pub user: Option<String>,
+    pub event_type: Option<String>,
+    #[serde(default)]
+    pub category: Option<EventCategory>,
+    pub message_contains: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub limit: Option<usize>,
+    // Sites of the caller running the query, never stored with a saved search
+    #[serde(skip)]
+    pub sites: Option<SiteScope>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        if let Some(from) = self.from {
+            if entry.timestamp < from {
+                return false;
+            }
+        }
+
+        if let Some(to) = self.to {
+            if entry.timestamp > to {
+                return false;
+            }
+        }
+
+        if let Some(min_severity) = &self.min_severity {
+            if entry.severity < *min_severity {
+                return false;
+            }
+        }
+
+        if let Some(source) = &self.source {
+            if &entry.source != source {
+                return false;
+            }
+        }
+
+        if let Some(host) = &self.host {
+            if entry.host.as_ref() != Some(host) {
+                return false;
+            }
+        }
+
+        if let Some(user) = &self.user {
+            if entry.user.as_ref() != Some(user) {
+                return false;
+            }
+        }
+
+        if let Some(event_type) = &self.event_type {
+            if &entry.event_type != event_type {
+                return false;
+            }
+        }
+
+        if let Some(category) = &self.category {
+            if &entry.category != category {
+                return false;
+            }
+        }
+
+        if let Some(needle) = &self.message_contains {
+            if !entry.message.to_lowercase().contains(&needle.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if let Some(sites) = &self.sites {
+            if !sites.allows(entry.site_id) {
+                return false;
+            }
+        }
+
+        self.tags.iter().all(|tag| tags::has_tag(&entry.tags, tag))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogStats {
+    pub total: usize,
+    pub by_category: BTreeMap<EventCategory, usize>,
+    pub by_severity: BTreeMap<LogSeverity, usize>,
+    pub by_event_type: BTreeMap<String, usize>,
+    // For navigating by tag, see tagging
+    #[serde(default)]
+    pub by_tag: BTreeMap<String, usize>,
+}
+
+// In-memory store of the most recent log entries, newest last
+#[derive(Clone)]
+pub struct LogsManager {
+    entries: Arc<Mutex<VecDeque<LogEntry>>>,
+    capacity: usize,
+}
+
+impl LogsManager {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            capacity,
+        }
+    }
+
+    pub fn ingest(&self, entry: LogEntry) -> Result<()> {
+        match self.entries.lock() {
+            Ok(mut entries) => {
+                entries.push_back(entry);
+
+                while entries.len() > self.capacity {
+                    entries.pop_front();
+                }
+
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Returns matching entries, newest first
+    pub fn query(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        match self.entries.lock() {
+            Ok(entries) => {
+                let limit = filter.limit.unwrap_or(usize::MAX);
+
+                Ok(entries.iter()
+                    .rev()
+                    .filter(|e| filter.matches(e))
+                    .take(limit)
+                    .cloned()
+                    .collect())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Like query, with an extra predicate for conditions a LogFilter cannot express
+    pub fn query_where<F>(&self, filter: &LogFilter, predicate: F) -> Result<Vec<LogEntry>>
+    where
+        F: Fn(&LogEntry) -> bool,
+    {
+        match self.entries.lock() {
+            Ok(entries) => {
+                let limit = filter.limit.unwrap_or(usize::MAX);
+
+                Ok(entries.iter()
+                    .rev()
+                    .filter(|e| filter.matches(e) && predicate(e))
+                    .take(limit)
+                    .cloned()
+                    .collect())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Entries with the given ids in the order of the ids, in a single pass over the store;
+    // ids no longer in the store are skipped
+    pub fn get_many(&self, ids: &[Uuid]) -> Result<Vec<LogEntry>> {
+        let wanted: HashSet<&Uuid> = ids.iter().collect();
+
+        let mut found: HashMap<Uuid, LogEntry> = match self.entries.lock() {
+            Ok(entries) => entries.iter()
+                .filter(|e| wanted.contains(&e.id))
+                .map(|e| (e.id, e.clone()))
+                .collect(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on log entries")),
+        };
+
+        Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+    }
+
+    // Ids of all stored entries, oldest first
+    pub fn ids(&self) -> Result<Vec<Uuid>> {
+        match self.entries.lock() {
+            Ok(entries) => Ok(entries.iter().map(|e| e.id).collect()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Runs the update on the stored entries with the given ids in a single pass, returns
+    // how many it changed; ids no longer in the store are skipped
+    pub fn update_many<F>(&self, ids: &[Uuid], update: F) -> Result<usize>
+    where
+        F: Fn(&mut LogEntry) -> bool,
+    {
+        let wanted: HashSet<&Uuid> = ids.iter().collect();
+
+        match self.entries.lock() {
+            Ok(mut entries) => Ok(entries.iter_mut()
+                .filter(|e| wanted.contains(&e.id))
+                .map(|e| update(e))
+                .filter(|changed| *changed)
+                .count()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Up to `limit` entries in [from, to] after the cursor, oldest first by timestamp and
+    // then id, so a range can be walked page by page
+    pub fn page_between(&self,
+                        from: DateTime<Utc>,
+                        to: DateTime<Utc>,
+                        after: Option<(DateTime<Utc>, Uuid)>,
+                        limit: usize) -> Result<Vec<LogEntry>> {
+        match self.entries.lock() {
+            Ok(entries) => {
+                let mut page: Vec<&LogEntry> = entries.iter()
+                    .filter(|e| e.timestamp >= from && e.timestamp <= to)
+                    .filter(|e| after.map_or(true, |cursor| (e.timestamp, e.id) > cursor))
+                    .collect();
+                page.sort_by_key(|e| (e.timestamp, e.id));
+                Ok(page.into_iter().take(limit).cloned().collect())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Counts of matching entries grouped by category, severity, raw event type and tag
+    pub fn stats(&self, filter: &LogFilter) -> Result<LogStats> {
+        match self.entries.lock() {
+            Ok(entries) => {
+                let mut stats = LogStats {
+                    total: 0,
+                    by_category: EventCategory::all().into_iter().map(|c| (c, 0)).collect(),
+                    by_severity: BTreeMap::new(),
+                    by_event_type: BTreeMap::new(),
+                    by_tag: BTreeMap::new(),
+                };
+
+                for entry in entries.iter().filter(|e| filter.matches(e)) {
+                    stats.total += 1;
+                    *stats.by_category.entry(entry.category).or_insert(0) += 1;
+                    *stats.by_severity.entry(entry.severity.clone()).or_insert(0) += 1;
+                    *stats.by_event_type.entry(entry.event_type.clone()).or_insert(0) += 1;
+                    for tag in &entry.tags {
+                        *stats.by_tag.entry(tag.clone()).or_insert(0) += 1;
+                    }
+                }
+
+                Ok(stats)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    // Reassigns categories of stored entries, returns how many changed
+    pub fn reclassify<F>(&self, classify: F) -> Result<usize>
+    where
+        F: Fn(&LogEntry) -> EventCategory,
+    {
+        match self.entries.lock() {
+            Ok(mut entries) => {
+                let mut changed = 0;
+
+                for entry in entries.iter_mut() {
+                    let category = classify(entry);
+                    if category != entry.category {
+                        entry.category = category;
+                        changed += 1;
+                    }
+                }
+
+                Ok(changed)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+}
+
+// Only entries still in memory are retagged; stored entries keep the tags they were
+// written with
+impl TaggedStore for LogsManager {
+    fn resource(&self) -> &'static str {
+        "logs"
+    }
+
+    fn lists_tags(&self) -> bool {
+        false
+    }
+
+    fn tag_counts(&self) -> Result<HashMap<String, usize>> {
+        match self.entries.lock() {
+            Ok(entries) => Ok(tags::count(entries.iter().map(|e| &e.tags))),
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        match self.entries.lock() {
+            Ok(mut entries) => {
+                let mut previous = Vec::new();
+                for entry in entries.iter_mut() {
+                    if let Some(tags) = tags::replaced(&entry.tags, from, to) {
+                        previous.push((entry.id, std::mem::replace(&mut entry.tags, tags)));
+                    }
+                }
+                Ok(previous)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        match self.entries.lock() {
+            Ok(mut entries) => {
+                let previous: HashMap<&Uuid, &Vec<String>> = previous.iter().map(|(id, tags)| (id, tags)).collect();
+                for entry in entries.iter_mut() {
+                    if let Some(tags) = previous.get(&entry.id) {
+                        entry.tags = (*tags).clone();
+                    }
+                }
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log entries")),
+        }
+    }
+}
@@ -1,11 +1,10 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::Router;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::net::SocketAddr;
 use std::fs;
 use tokio;
-use tracing::{info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{info, warn};
 
 mod config;
 mod printers;
@@ -17,52 +16,262 @@ mod security;
 mod database;
 mod network;
 mod visualizations; // Added network and visualization modules
+mod auth;
+mod logs;
+mod searches;
+mod alerts;
+mod extraction;
+mod ingestion;
+mod ingestion_quotas;
+mod capture;
+mod services;
+mod tasks;
+mod classification;
+mod users;
+mod sessions;
+mod locations;
+mod firewall_import;
+mod flow_export;
+mod paths;
+mod disk_monitor;
+mod script_diff;
+mod builtin_scripts;
+mod script_approvals;
+mod activity;
+mod syslog;
+mod reports;
+mod assets;
+mod scans;
+mod timeline;
+mod notifications;
+mod escalation;
+mod geoip;
+mod travel;
+mod password_policy;
+mod interface_metadata;
+mod fleet;
+mod annotations;
+mod setup;
+mod audit_chain;
+mod ticket_import;
+mod oidc;
+mod digest;
+mod config_history;
+mod ticket_autoclose;
+mod resolver;
+mod sites;
+mod print_accounting;
+mod evidence;
+mod log_tail;
+mod source_health;
+mod graph_snapshots;
+mod body_limits;
+mod version;
+mod alert_events;
+mod link_flap;
+mod ticket_snippets;
+mod spool;
+mod graph_grouping;
+mod printer_reorder;
+mod tagging;
+mod attachment_scan;
+mod chargeback;
+mod remediation;
+mod traffic_history;
+mod script_lint;
+mod ups;
+mod ticket_portal;
+mod locks;
+mod outbound;
+mod drop_log;
+mod redaction;
+mod correlation;
+mod tags;
+mod bandwidth_quota;
+mod inventory_export;
+mod heartbeat;
+mod request_trace;
+mod firewall_backup;
+mod worklog_report;
+mod ingest_timing;
+mod bench;
+mod api_usage;
+mod replication;
+mod reparse;
+mod integrations;
+mod calendar;
+mod sla;
+mod after_hours;
+mod prometheus_rules;
+mod ticket_board;
+mod host_inventory;
+mod firewall_approvals;
+mod anonymize;
+mod dhcp_relay;
+mod references;
+mod startup;
+#[cfg(test)]
+mod testing;
 
 #[derive(Parser)]
 struct Args {
     #[clap(short, long, default_value = "config.toml")]
     config: String,
+    // Serves the application when absent
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(subcommand, about = "Load generation and measurement, see bench")]
+    Bench(bench::BenchCommand),
+    #[command(about = "Write TypeScript types and a typed client for the API, see client_types.rs")]
+    GenerateTypes {
+        // Printed when absent
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    #[command(about = "Copy the data directory with personal data pseudonymized as [anonymize] says, see anonymize")]
+    AnonymizeCopy {
+        // Created; must not exist or be empty
+        output: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
-
-    info!("Starting Admin Center...");
-
     // Parse command line arguments
     let args = Args::parse();
+    match args.command {
+        Some(Command::Bench(command)) => return bench::run(command).await,
+        Some(Command::GenerateTypes { output }) => return generate_types(output),
+        Some(Command::AnonymizeCopy { output }) => return anonymize_copy(&args.config, &output),
+        None => {}
+    }
 
-    // Load configuration
+    // Load configuration; logging is set up from it, so the outcome is logged after
     let config_path = &args.config;
-    let config = if fs::metadata(config_path).is_ok() {
-        info!("Loading configuration from {}", config_path);
+    let config_exists = fs::metadata(config_path).is_ok();
+    let mut config = if config_exists {
         config::load(config_path)?
     } else {
-        info!("Configuration file not found, creating default configuration");
         let default_config = config::default_config();
-        // Make sure config directory exists
-        fs::create_dir_all("config").ok();
-        let config_str = toml::to_string_pretty(&default_config)?;
-        fs::write(config_path, config_str)?;
+        config::save(&default_config, config_path)?;
         default_config
     };
 
-    info!("Initializing security manager...");
-    let security_manager = security::SecurityManager::new([0u8; 32]); // Production should use a proper key
+    // Initialize logging
+    request_trace::init(&config.tracing)?;
+
+    info!("Starting Admin Center...");
+    if config_exists {
+        info!("Loaded configuration from {}", config_path);
+    } else {
+        info!("Configuration file not found, created default configuration at {}", config_path);
+    }
+
+    // As written in the file, before directories are resolved, to recognise a fresh install
+    let loaded_config = config.clone();
+
+    let server_port = config.server_port;
+    let app = build_app(config_path, config, loaded_config, startup::Startup::new()).await?;
+
+    // Run the server
+    let addr = SocketAddr::from(([0, 0, 0, 0], server_port));
+    info!("Listening on {}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+
+    request_trace::shutdown();
+    Ok(())
+}
+
+// Generated by build.rs, see client_types.rs
+const CLIENT_TYPES: &str = include_str!(concat!(env!("OUT_DIR"), "/client.ts"));
+
+fn generate_types(output: Option<String>) -> Result<()> {
+    match output {
+        Some(path) => fs::write(&path, CLIENT_TYPES).context(format!("Failed to write {}", path))?,
+        None => print!("{}", CLIENT_TYPES),
+    }
+    Ok(())
+}
+
+// Copies only the JSON files of the data directory: attachments, scripts, reports,
+// archives and anything kept in PostgreSQL are left out
+fn anonymize_copy(config_path: &str, output: &str) -> Result<()> {
+    let config = config::load(config_path)?;
+    let paths = paths::Paths::resolve(&config, std::path::Path::new(config_path))?;
+    let skip = [paths.attachments_dir.clone(), paths.temp_dir.clone()];
+
+    let skipped = anonymize::copy_data_dir(&config.anonymize, &paths.data_dir, std::path::Path::new(output), &skip)?;
+    for path in &skipped {
+        eprintln!("Not copied: {}", path.display());
+    }
+    if config.database_url.is_some() {
+        eprintln!("Logs stored in PostgreSQL are not part of the copy");
+    }
+    Ok(())
+}
+
+// Every manager, background task and route wired up from the config, as served by
+// main and by the integration tests (see testing). `loaded_config` is the config as
+// written in the file, to recognise a fresh install. The subsystems listed in startup are
+// initialized through `startup`; a degradable one that fails is replaced by a stand-in
+// and its routes answer 503.
+async fn build_app(config_path: &str,
+                   mut config: config::Config,
+                   loaded_config: config::Config,
+                   mut startup: startup::Startup) -> Result<Router> {
+    info!("Resolving data directories...");
+    let paths = paths::Paths::resolve(&config, std::path::Path::new(config_path))?;
+    startup.require("storage", async { paths.validate_except(&["scripts"]) }).await?;
+
+    // Everything below reads the resolved, absolute directories
+    config.data_dir = paths.data_dir.display().to_string();
+    config.scripts_dir = paths.scripts_dir.display().to_string();
+    config.log_dir = paths.log_dir.display().to_string();
+
+    let password_policy = password_policy::PasswordPolicy::new(config.password_policy.clone());
+    let (security_manager, access_control, user_manager, setup) = startup.require("accounts", async {
+        info!("Initializing security manager...");
+        let key = security::load_or_create_key(&paths.data_dir.join("instance.key"))?;
+        let security_manager = security::SecurityManager::new(key, &format!("{}/audit", config.data_dir))?;
+
+        info!("Loading access control matrix...");
+        let access_control = security::AccessControl::new(&format!("{}/roles.json", config.data_dir))?;
+
+        info!("Loading user accounts...");
+        let user_manager = users::UserManager::new(&format!("{}/users", config.data_dir), password_policy.clone())?;
+
+        let setup = setup::SetupState::detect(&loaded_config, &user_manager, config_path)?;
+        if !setup.is_required() {
+            user_manager.ensure_initial_admin()?;
+        }
+        Ok((security_manager, access_control, user_manager, setup))
+    }).await?;
+    let session_manager = sessions::SessionManager::new();
+    setup::self_test(&config, &user_manager);
 
-    info!("Initializing database manager...");
-    // Initialize database manager if a database URL is provided
-    // This is temporarily commented out as database_url is not in the Config struct
-    let db_manager = None;
-    info!("Skipping database initialization for now");
+    // Every outbound HTTP client is built through this, honoring [proxy]
+    let http_clients = outbound::HttpClients::new(&config.proxy)?;
+
+    let database = match &config.database_url {
+        Some(url) => {
+            info!("Initializing database manager...");
+            startup.init("database", database::DatabaseManager::new(url, config.database.clone(), &paths.data_dir.join("spool"))).await?
+        },
+        None => {
+            info!("No database_url configured, skipping database initialization");
+            None
+        },
+    };
 
     info!("Initializing network manager...");
-    let network_manager = network::NetworkManager::new().await?;
+    let interface_metadata = interface_metadata::InterfaceMetadataStore::new(&format!("{}/network/interfaces", config.data_dir))?;
     
     // For example purposes, create some default interface config
     let default_interfaces = vec![
@@ -71,51 +280,797 @@ async fn main() -> Result<()> {
             dhcp: Some(true),
             address: None,
             nftables_zone: Some("wan".to_string()),
+            bond: None,
+            dhcp_relay: None,
         },
         network::InterfaceConfig {
             name: "eth1".to_string(),
             dhcp: None,
             address: Some("192.168.1.1/24".to_string()),
             nftables_zone: Some("lan".to_string()),
+            bond: None,
+            dhcp_relay: None,
         },
     ];
     
-    network_manager.load_config(default_interfaces).await?;
-    network_manager.initialize_nftables().await?;
+    let network = startup.init("network", async {
+        let manager = network::NetworkManager::new().await?;
+        manager.load_config(default_interfaces).await?;
+        manager.set_ingest_ports(network::ingest_ports(&config)).await;
+        manager.initialize_nftables(&config.firewall).await?;
+        Ok(manager)
+    }).await?;
+    let network_manager = std::sync::Arc::new(network.unwrap_or_else(network::NetworkManager::unavailable));
+
+    let dhcp_relays = dhcp_relay::DhcpRelayManager::new(config.dhcp_relay.clone());
+    startup.init("dhcp_relay", async {
+        dhcp_relays.sync(&network_manager.interface_configs().await)
+    }).await?;
     
+    info!("Initializing alerts manager...");
+    let alerts_manager = alerts::AlertsManager::new(&format!("{}/alerts", config.data_dir))?;
+    let alert_events = if config.alert_events.enabled {
+        Some(alerts_manager.enable_lifecycle_events())
+    } else {
+        None
+    };
+    let remediation_alerts = alerts_manager.enable_remediation();
+
+    info!("Initializing background task registry...");
+    let task_registry = tasks::TaskRegistry::new(config.tasks.clone(), alerts_manager.clone());
+
+    let sessions = session_manager.clone();
+    task_registry.spawn("session_cleanup", std::time::Duration::from_secs(300), move || {
+        let sessions = sessions.clone();
+        async move {
+            sessions.purge_expired()?;
+            Ok(())
+        }
+    })?;
+
+    // Periodic copy of the audit chain head, so a rewritten event file is detectable
+    let audit = security_manager.clone();
+    task_registry.spawn("audit_checkpoint", std::time::Duration::from_secs(300), move || {
+        let audit = audit.clone();
+        async move {
+            let checkpoint = audit.checkpoint_audit_chain()?;
+            info!("Audit chain checkpoint: {} events, head {}", checkpoint.events, checkpoint.head);
+            Ok(())
+        }
+    })?;
+
+    info!("Loading sites...");
+    let site_manager = sites::SiteManager::new(&format!("{}/sites", config.data_dir))?;
+
     info!("Initializing visualization manager...");
-    let visualization_manager = visualizations::VisualizationManager::new();
+    let traffic_history = traffic_history::TrafficHistory::new(&format!("{}/traffic_history", config.data_dir))?;
+    let bandwidth_quotas = bandwidth_quota::BandwidthQuotas::new(config.bandwidth_quota.clone(), &format!("{}/bandwidth_quota", config.data_dir))?;
+    let visualization_manager = visualizations::VisualizationManager::new(site_manager.clone(), traffic_history.clone(), bandwidth_quotas.clone());
     
     // Start traffic monitoring in the background
-    if let Err(e) = visualization_manager.start_traffic_monitoring() {
+    if let Err(e) = visualization_manager.start_traffic_monitoring(&task_registry, config.traffic_monitoring.interval_secs) {
         warn!("Failed to start traffic monitoring: {}", e);
     } else {
         info!("Traffic monitoring started successfully");
     }
+    task_registry.spawn("traffic_history_save", std::time::Duration::from_secs(300), move || {
+        let history = traffic_history.clone();
+        async move { history.save() }
+    })?;
+
+    info!("Loading config history...");
+    let config_history = config_history::ConfigHistory::new(
+        &format!("{}/config/history", config.data_dir),
+        config_path,
+        security_manager.clone(),
+        password_policy.clone(),
+        visualization_manager.clone(),
+        task_registry.clone(),
+    )?;
+    config_history.reload()?;
+
+    // The password policy and the traffic monitoring interval follow edits of the
+    // config file without a restart; hand edits are recorded in the config history
+    let history = config_history.clone();
+    task_registry.spawn("config_reload", std::time::Duration::from_secs(60), move || {
+        let history = history.clone();
+        async move {
+            history.reload()?;
+            Ok(())
+        }
+    })?;
+
+    info!("Starting disk space monitor...");
+    let mut volumes = vec![
+        ("logs".to_string(), paths.log_dir.clone()),
+        ("attachments".to_string(), paths.attachments_dir.clone()),
+    ];
+    if let Some(dir) = &config.disk.database_dir {
+        volumes.push(("database".to_string(), std::path::PathBuf::from(dir)));
+    }
+    let disk_monitor = disk_monitor::DiskMonitor::new(
+        config.disk.clone(),
+        volumes,
+        alerts_manager.clone(),
+        security_manager.clone(),
+    );
+
+    let monitor = disk_monitor.clone();
+    task_registry.spawn("disk_monitor", disk_monitor.interval(), move || {
+        let monitor = monitor.clone();
+        async move {
+            monitor.check()
+        }
+    })?;
+
+    if config.flow_export.enabled {
+        info!("Starting continuous flow export...");
+        let exporter = flow_export::FlowExporter::new(config.flow_export.clone(), &config.log_dir)?;
+
+        let purge = exporter.clone();
+        disk_monitor.register_purge("flow_export", move |max_age| purge.prune_older_than(max_age))?;
+
+        let flows = visualization_manager.clone();
+        let monitor = disk_monitor.clone();
+        task_registry.spawn("flow_export", exporter.interval(), move || {
+            let exporter = exporter.clone();
+            let flows = flows.clone();
+            let paused = monitor.is_active(disk_monitor::ProtectiveAction::PauseFlowExport);
+            async move {
+                if paused {
+                    return Ok(());
+                }
+                exporter.run_once(&flows)
+            }
+        })?;
+    }
+
+    info!("Loading alert escalation policies...");
+    let notifier = match startup.init("notifications", async {
+        notifications::Notifier::new(config.smtp.clone(), config.admin_email.clone(), &http_clients)
+    }).await? {
+        Some(notifier) => notifier,
+        None => notifications::Notifier::unavailable(
+            config.smtp.clone(),
+            config.admin_email.clone(),
+            startup.disabled("notifications").unwrap_or_default().to_string(),
+        ),
+    };
+    let escalation_engine = escalation::EscalationEngine::new(
+        &format!("{}/escalations", config.data_dir),
+        alerts_manager.clone(),
+        user_manager.clone(),
+        notifier.clone(),
+    )?;
+
+    let escalations = escalation_engine.clone();
+    task_registry.spawn("alert_escalation", std::time::Duration::from_secs(30), move || {
+        let escalations = escalations.clone();
+        async move {
+            escalations.tick().await
+        }
+    })?;
+
+    // Known this early so jobs that change data can sit out while this is a standby
+    let replication_role = replication::ReplicationRole::new(config.replication.mode);
 
     info!("Initializing scripts manager...");
-    let scripts_manager = scripts::ScriptsManager::new(&config.scripts_dir)?;
+    let loaded_scripts = startup.init("scripts", async {
+        paths::validate_dir("scripts", &paths.scripts_dir)?;
+        let linter = script_lint::ScriptLinter::new(&config.script_lint)?;
+        let manager = scripts::ScriptsManager::new(&paths.scripts_dir, &paths.temp_dir, linter)?;
+        scripts::alert_storage_issues(&manager, &alerts_manager)?;
+        Ok(manager)
+    }).await?;
+    let scripts_manager = std::sync::Arc::new(std::sync::Mutex::new(match loaded_scripts {
+        Some(manager) => manager,
+        None => scripts::ScriptsManager::unavailable(&paths.scripts_dir, &paths.temp_dir)?,
+    }));
+
+    let scripts = scripts_manager.clone();
+    let alerts = alerts_manager.clone();
+    let role = replication_role.clone();
+    task_registry.spawn("script_scheduler", std::time::Duration::from_secs(60), move || {
+        let scripts = scripts.clone();
+        let alerts = alerts.clone();
+        let role = role.clone();
+        async move {
+            // Schedules run on the primary only
+            if role.is_standby() {
+                return Ok(());
+            }
+            // Script execution blocks, keep it off the runtime threads
+            tokio::task::spawn_blocking(move || scripts::run_due_schedules(&scripts, &alerts)).await?
+        }
+    })?;
+
+    let script_approvals = script_approvals::ScriptApprovals::new(
+        user_manager.clone(),
+        access_control.clone(),
+        notifier.clone(),
+    );
+
+    let firewall_approvals = firewall_approvals::FirewallApprovals::new(
+        user_manager.clone(),
+        access_control.clone(),
+        notifier.clone(),
+    );
+
+    let scripts = scripts_manager.clone();
+    let approvals = script_approvals.clone();
+    let reminder_after = chrono::Duration::hours(config.script_approval.reminder_after_hours as i64);
+    task_registry.spawn("script_review_reminders", std::time::Duration::from_secs(900), move || {
+        let scripts = scripts.clone();
+        let approvals = approvals.clone();
+        async move {
+            let due = match scripts.lock() {
+                Ok(mut manager) => manager.take_due_reminders(chrono::Utc::now(), reminder_after)?,
+                Err(_) => return Err(anyhow::anyhow!("Failed to acquire lock on scripts manager")),
+            };
+            approvals.remind(&due).await
+        }
+    })?;
 
     info!("Initializing tickets manager...");
-    let tickets_manager = tickets::TicketsManager::new();
+    let activity_log = activity::ActivityLog::new();
+    let tickets_manager = tickets::TicketsManager::new(activity_log.clone());
+
+    if config.ticket_autoclose.policy.enabled || config.ticket_autoclose.categories.values().any(|p| p.enabled) {
+        let autoclose = ticket_autoclose::TicketAutoClose::new(
+            config.ticket_autoclose.clone(),
+            tickets_manager.clone(),
+            user_manager.clone(),
+            notifier.clone(),
+            security_manager.clone(),
+        );
+        let role = replication_role.clone();
+        task_registry.spawn("ticket_autoclose", std::time::Duration::from_secs(3600), move || {
+            let autoclose = autoclose.clone();
+            let role = role.clone();
+            async move {
+                // The primary closes the tickets, the standby gets them replicated
+                if role.is_standby() {
+                    return Ok(());
+                }
+                autoclose.run(chrono::Utc::now()).await
+            }
+        })?;
+    }
+
+    info!("Initializing printer manager...");
+    let print_accounting = print_accounting::PrintAccounting::new(
+        &format!("{}/print_accounting", config.data_dir),
+        config.print_accounting.clone(),
+        alerts_manager.clone(),
+        user_manager.clone(),
+        notifier.clone(),
+    )?;
+    let printer_manager = printers::start(print_accounting.clone())?;
+
+    info!("Loading location hierarchy...");
+    let location_manager = locations::LocationManager::new(&format!("{}/locations", config.data_dir))?;
+
+    info!("Initializing logs manager...");
+    let logs_manager = logs::LogsManager::new(100_000);
+
+    info!("Initializing saved searches manager...");
+    let saved_search_manager = searches::SavedSearchManager::new(&format!("{}/searches", config.data_dir))?;
+
+    info!("Initializing extraction rules...");
+    let extraction_manager = extraction::ExtractionManager::new(
+        &format!("{}/extractions", config.data_dir),
+        alerts_manager.clone(),
+    )?;
+
+    info!("Loading tagging rules...");
+    let tagging_manager = tagging::TaggingManager::new(&format!("{}/tagging", config.data_dir), logs_manager.clone())?;
+    let tagging_hits = tagging_manager.clone();
+    task_registry.spawn("tagging_hits", std::time::Duration::from_secs(300), move || {
+        let tagging = tagging_hits.clone();
+        async move {
+            tagging.flush_hits()
+        }
+    })?;
+
+    info!("Loading GeoIP database...");
+    let geoip = geoip::GeoIpResolver::new(&config.geoip)?;
+    let travel_detector = travel::TravelDetector::new(
+        config.impossible_travel.clone(),
+        geoip.clone(),
+        alerts_manager.clone(),
+    );
+
+    let ingestion_quotas = ingestion_quotas::IngestionQuotas::new(
+        &format!("{}/ingestion", config.data_dir),
+        config.ingestion_quota.clone(),
+        alerts_manager.clone(),
+    )?;
+
+    let source_health = source_health::SourceHealthMonitor::new(
+        &format!("{}/ingestion", config.data_dir),
+        config.source_health.clone(),
+        config.clock_skew.clone(),
+        alerts_manager.clone(),
+    )?;
+
+    let health = source_health.clone();
+    task_registry.spawn("source_health", source_health.interval(), move || {
+        let health = health.clone();
+        async move {
+            health.check(chrono::Utc::now())
+        }
+    })?;
+
+    // Never awaited here, the first check runs in the background
+    let update_checker = version::UpdateChecker::new(config.update_check.clone(), alerts_manager.clone(), &http_clients)?;
+    if update_checker.enabled() {
+        let checker = update_checker.clone();
+        task_registry.spawn("update_check", update_checker.interval(), move || {
+            let checker = checker.clone();
+            async move {
+                checker.check().await.map(|_| ())
+            }
+        })?;
+    }
+
+    // The summary is built from the live components at every beat
+    let heartbeat_summary: heartbeat::SummarySource = {
+        let database = database.clone();
+        let tickets = tickets_manager.clone();
+        let security = security_manager.clone();
+        let visualizations = visualization_manager.clone();
+        let disk = disk_monitor.clone();
+        let tasks = task_registry.clone();
+        std::sync::Arc::new(move |started: std::time::Instant| {
+            let mut components = std::collections::BTreeMap::new();
+            let mut queues = std::collections::BTreeMap::new();
+            if let Some(health) = database.as_ref().map(|db| db.health()) {
+                components.insert("database", health.healthy);
+                queues.insert("database_spool", health.spooled);
+            }
+            for lock in [tickets.lock_health(), security.lock_health(), visualizations.lock_health()] {
+                components.insert(lock.name, lock.healthy);
+            }
+            components.insert("disk", disk.status().map_or(false, |s| s.level != disk_monitor::DiskLevel::Critical));
+            components.insert("tasks", tasks.get_all_tasks().map_or(false, |t| t.iter().all(|t| t.consecutive_failures == 0)));
+
+            heartbeat::HeartbeatSummary {
+                status: if components.values().all(|healthy| *healthy) { "ok" } else { "degraded" },
+                version: env!("CARGO_PKG_VERSION"),
+                uptime_secs: started.elapsed().as_secs(),
+                components,
+                queues,
+            }
+        })
+    };
+    let heartbeat = heartbeat::Heartbeat::new(config.heartbeat.clone(), alerts_manager.clone(), &http_clients, heartbeat_summary)?;
+    if heartbeat.enabled() {
+        tokio::spawn(heartbeat.clone().run());
+    }
+
+    let link_flaps = link_flap::LinkFlapDetector::new(config.link_flap.clone(), alerts_manager.clone());
+    if link_flaps.enabled() {
+        let watcher = link_flaps.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watcher.watch().await {
+                warn!("Interface flap detection stopped: {}", e);
+            }
+        });
+
+        // Flapping interfaces calm down without any event, so the score is also re-evaluated
+        let flaps = link_flaps.clone();
+        task_registry.spawn("link_flap", std::time::Duration::from_secs(10), move || {
+            let flaps = flaps.clone();
+            async move {
+                flaps.evaluate(chrono::Utc::now())
+            }
+        })?;
+    }
+
+    if let Some(db) = &database {
+        let db = db.clone();
+        task_registry.spawn("database_probe", db.probe_interval(), move || {
+            let db = db.clone();
+            async move {
+                db.probe().await;
+                Ok(())
+            }
+        })?;
+    }
+
+    let log_tail = log_tail::LogTail::new(config.log_tail.clone());
+    let ingest_timings = ingest_timing::IngestTimings::new();
+    let ingestion_pipeline = ingestion::IngestionPipeline::new(
+        logs_manager.clone(),
+        extraction_manager.clone(),
+        travel_detector,
+        ingestion_quotas.clone(),
+        site_manager.clone(),
+        log_tail.clone(),
+        source_health.clone(),
+        config.clock_skew.clone(),
+        database.as_ref().map(|db| db.log_writer(ingest_timings.clone())),
+        tagging_manager.clone(),
+        ingest_timings.clone(),
+    );
+
+    if let Some(events) = alert_events {
+        tokio::spawn(alert_events::run(events, ingestion_pipeline.clone()));
+    }
+
+    // Kernel log lines of the zones' drop log rules
+    if config.drop_log.ingest {
+        tokio::spawn(drop_log::run(config.drop_log.clone(), ingestion_pipeline.clone()));
+    }
+
+    info!("Loading remediation bindings...");
+    let remediation_manager = remediation::RemediationManager::new(
+        &format!("{}/remediation", config.data_dir),
+        scripts_manager.clone(),
+        alerts_manager.clone(),
+        logs_manager.clone(),
+    )?;
+    tokio::spawn(remediation::run(remediation_alerts, remediation_manager.clone()));
+
+    let ups_monitor = ups::UpsMonitor::new(
+        config.ups.clone(),
+        alerts_manager.clone(),
+        remediation_manager.clone(),
+        scripts_manager.clone(),
+    );
+    if ups_monitor.is_configured() {
+        let monitor = ups_monitor.clone();
+        task_registry.spawn("ups_poll", ups_monitor.interval(), move || {
+            let monitor = monitor.clone();
+            async move { monitor.poll().await }
+        })?;
+    }
+
+    if bandwidth_quotas.is_configured() {
+        let quotas = bandwidth_quotas.clone();
+        let alerts = alerts_manager.clone();
+        let remediation = remediation_manager.clone();
+        let scripts = scripts_manager.clone();
+        task_registry.spawn("bandwidth_quota", bandwidth_quotas.interval(), move || {
+            let quotas = quotas.clone();
+            let alerts = alerts.clone();
+            let remediation = remediation.clone();
+            let scripts = scripts.clone();
+            async move { quotas.check(&alerts, &remediation, &scripts).await }
+        })?;
+    }
+
+    let firewall_backups = firewall_backup::FirewallBackups::new(
+        config.firewall_backup.clone(),
+        &paths.backups_dir.join("firewall").to_string_lossy(),
+        network_manager.clone(),
+        alerts_manager.clone(),
+        &http_clients,
+    )?;
+    if firewall_backups.enabled() {
+        let backups = firewall_backups.clone();
+        task_registry.spawn("firewall_backup", firewall_backups.interval(), move || {
+            let backups = backups.clone();
+            async move { backups.run().await.map(|_| ()) }
+        })?;
+    }
+
+    let api_usage = api_usage::ApiUsage::new(config.api_usage.clone(), &format!("{}/api_usage", config.data_dir))?;
+    let usage = api_usage.clone();
+    task_registry.spawn("api_usage_persist", api_usage.persist_interval(), move || {
+        let usage = usage.clone();
+        async move { usage.persist() }
+    })?;
+
+    if config.clock_skew.rewrite_timestamps {
+        warn!("Timestamps of log entries skewed by more than {}s are rewritten to receipt time", config.clock_skew.rewrite_after_secs);
+        security_manager.log_audit_event(
+            "system",
+            "clock_skew_rewrite_enabled",
+            "ingestion",
+            security::AuditStatus::Warning,
+            Some(format!("Entries skewed by more than {}s are stored at receipt time, the original timestamp kept in the {} tag",
+                         config.clock_skew.rewrite_after_secs, ingestion::ORIGINAL_TIMESTAMP_TAG)),
+        );
+    }
+
+    let syslog_listener = syslog::SyslogTlsListener::new(config.syslog_tls.clone());
+    if config.syslog_tls.enabled {
+        info!("Starting syslog TLS listener...");
+        startup.init("syslog_tls", syslog_listener.start(config.tls.as_ref(), ingestion_pipeline.clone())).await?;
+    }
+
+    info!("Loading evidence packages...");
+    let evidence_manager = evidence::EvidenceManager::new(
+        &paths.reports_dir.join("evidence").to_string_lossy(),
+        config.evidence.clone(),
+        logs_manager.clone(),
+        alerts_manager.clone(),
+        tickets_manager.clone(),
+        security_manager.clone(),
+    )?;
+    let purge = evidence_manager.clone();
+    disk_monitor.register_purge("evidence", move |max_age| purge.expire_older_than(max_age))?;
+
+    let graph_snapshots = graph_snapshots::GraphSnapshotStore::new(
+        &format!("{}/network/snapshots", config.data_dir),
+        config.graph_snapshots.clone(),
+    )?;
+    let purge = graph_snapshots.clone();
+    disk_monitor.register_purge("graph_snapshots", move |max_age| purge.prune_older_than(max_age))?;
+
+    let evidence = evidence_manager.clone();
+    task_registry.spawn("evidence_expiry", std::time::Duration::from_secs(600), move || {
+        let evidence = evidence.clone();
+        async move {
+            evidence.expire(chrono::Utc::now()).map(|_| ())
+        }
+    })?;
+
+    info!("Initializing capture manager...");
+    let capture_manager = capture::CaptureManager::new(
+        config.capture.clone(),
+        &format!("{}/captures", config.data_dir),
+    )?;
+
+    info!("Loading service definitions...");
+    let service_registry = services::ServiceRegistry::new(
+        &format!("{}/services.json", config.data_dir),
+    )?;
+
+    info!("Loading asset inventory...");
+    let asset_manager = assets::AssetManager::new(&format!("{}/assets", config.data_dir))?;
+    let scan_manager = scans::ScanManager::new();
+    let annotation_manager = annotations::AnnotationManager::new(&format!("{}/annotations", config.data_dir))?;
+
+    info!("Loading chargeback rollups...");
+    let chargeback = chargeback::ChargebackManager::new(
+        config.chargeback.clone(),
+        &format!("{}/chargeback", config.data_dir),
+        asset_manager.clone(),
+    )?;
+    let rollup = chargeback.clone();
+    let rollup_flows = visualization_manager.clone();
+    task_registry.spawn("chargeback_rollup", chargeback.interval(), move || {
+        let rollup = rollup.clone();
+        let flows = rollup_flows.clone();
+        async move {
+            rollup.run_once(&flows)
+        }
+    })?;
+
+    let fleet_runner = fleet::FleetRunner::new(
+        config.fleet.clone(),
+        scripts_manager.clone(),
+        asset_manager.clone(),
+        security_manager.clone(),
+    );
+
+    let backtester = correlation::Backtester::new(config.correlation.clone(), logs_manager.clone(), database.clone());
+
+    let tag_registry = tags::TagRegistry::new(
+        &format!("{}/tags.json", config.data_dir),
+        vec![
+            scripts_manager.clone() as std::sync::Arc<dyn tags::TaggedStore>,
+            std::sync::Arc::new(tickets_manager.clone()),
+            std::sync::Arc::new(asset_manager.clone()),
+            std::sync::Arc::new(tagging_manager.clone()),
+            std::sync::Arc::new(logs_manager.clone()),
+        ],
+    )?;
+
+    let oidc = if config.oidc.enabled {
+        Some(oidc::OidcClient::new(config.oidc.clone(), &http_clients)?)
+    } else {
+        None
+    };
+
+    let printer_reorder = printer_reorder::ReorderHook::new(config.printer_reorder.clone(), notifier.clone(), tickets_manager.clone());
+
+    let attachment_scanner = attachment_scan::AttachmentScanner::new(config.attachment_scan.clone());
+    let scanner_probe = attachment_scanner.clone();
+    task_registry.spawn("attachment_scanner_probe", std::time::Duration::from_secs(60), move || {
+        let scanner = scanner_probe.clone();
+        async move {
+            scanner.probe().await;
+            Ok(())
+        }
+    })?;
+
+    let log_reparser = reparse::LogReparser::new(
+        ingestion_pipeline.clone(),
+        logs_manager.clone(),
+        database.clone().map(std::sync::Arc::new),
+    );
+
+    let replication = replication::Replication::new(
+        config.replication.clone(),
+        replication_role,
+        &format!("{}/replication", config.data_dir),
+        tickets_manager.clone(),
+        scripts_manager.clone(),
+        asset_manager.clone(),
+        config_history.clone(),
+        network_manager.clone(),
+        alerts_manager.clone(),
+        &http_clients,
+    )?;
+    if replication.role().is_standby() {
+        let standby = replication.clone();
+        task_registry.spawn("replication_sync", replication.interval(), move || {
+            let standby = standby.clone();
+            async move { standby.sync().await.map(|_| ()) }
+        })?;
+    }
+
+    let integrations = integrations::Integrations::new(
+        config.integrations.clone(),
+        &format!("{}/integrations", config.data_dir),
+        alerts_manager.clone(),
+    )?;
+
+    let calendar = calendar::CalendarManager::new(
+        &format!("{}/calendar.json", config.data_dir),
+        &config.reports.timezone,
+    )?;
+
+    let sla = sla::SlaTracker::new(config.sla.clone(), tickets_manager.clone(), calendar.clone());
+    if config.sla.enabled {
+        let tracker = sla.clone();
+        let role = replication.role().clone();
+        task_registry.spawn("sla_tracker", std::time::Duration::from_secs(60), move || {
+            let tracker = tracker.clone();
+            let role = role.clone();
+            async move {
+                // Breaches are recorded on the primary and replicated with the tickets
+                if role.is_standby() {
+                    return Ok(());
+                }
+                tracker.check(chrono::Utc::now()).map(|_| ())
+            }
+        })?;
+    }
+
+    if config.after_hours.enabled {
+        let detector = after_hours::AfterHoursDetector::new(
+            config.after_hours.clone(),
+            security_manager.clone(),
+            alerts_manager.clone(),
+            calendar.clone(),
+        );
+        task_registry.spawn("after_hours_detector", std::time::Duration::from_secs(60), move || {
+            let detector = detector.clone();
+            async move { detector.run().map(|_| ()) }
+        })?;
+    }
+
+    let inventory = host_inventory::InventoryCollector::new(
+        config.inventory.clone(),
+        config.fleet.clone(),
+        &format!("{}/inventory", config.data_dir),
+        asset_manager.clone(),
+        alerts_manager.clone(),
+    )?;
+    if config.inventory.enabled {
+        let collector = inventory.clone();
+        let role = replication.role().clone();
+        task_registry.spawn("host_inventory", std::time::Duration::from_secs(config.inventory.interval_minutes.max(1) * 60), move || {
+            let collector = collector.clone();
+            let role = role.clone();
+            async move {
+                // Collection and the alerts it raises are left to the primary
+                if role.is_standby() {
+                    return Ok(());
+                }
+                collector.run().await.map(|_| ())
+            }
+        })?;
+    }
+
+    let ticket_portal = ticket_portal::TicketPortal::new(
+        config.portal.clone(),
+        security_manager.clone(),
+        std::sync::Arc::new(tickets_manager.clone()),
+        std::sync::Arc::new(notifier.clone()),
+    );
+
+    startup.log_banner();
 
     info!("Setting up API routes...");
-    let app = api::setup_routes(
+    Ok(api::setup_routes(
         config.clone(),
         security_manager,
+        access_control,
         scripts_manager,
         tickets_manager,
         network_manager,
-        visualization_manager
-    );
+        visualization_manager,
+        logs_manager,
+        saved_search_manager,
+        alerts_manager,
+        extraction_manager,
+        ingestion_pipeline,
+        capture_manager,
+        service_registry,
+        task_registry,
+        user_manager,
+        session_manager,
+        location_manager,
+        printer_manager,
+        paths,
+        disk_monitor,
+        activity_log,
+        syslog_listener,
+        asset_manager,
+        scan_manager,
+        escalation_engine,
+        geoip,
+        interface_metadata,
+        fleet_runner,
+        annotation_manager,
+        setup,
+        ingestion_quotas,
+        script_approvals,
+        oidc,
+        notifier,
+        config_history,
+        resolver::Resolver::new(config.dns.clone()),
+        site_manager,
+        print_accounting,
+        evidence_manager,
+        log_tail,
+        source_health,
+        graph_snapshots,
+        update_checker,
+        link_flaps,
+        ticket_snippets::SnippetManager::new(&format!("{}/tickets/snippets", config.data_dir))?,
+        database,
+        printer_reorder,
+        tagging_manager,
+        attachment_scanner,
+        chargeback,
+        remediation_manager,
+        ups_monitor,
+        ticket_portal,
+        http_clients,
+        backtester,
+        tag_registry,
+        bandwidth_quotas,
+        heartbeat,
+        firewall_backups,
+        ingest_timings,
+        api_usage,
+        replication,
+        log_reparser,
+        integrations,
+        calendar,
+        sla,
+        ticket_board::BoardPreferencesManager::new(&format!("{}/tickets/board_preferences.json", config.data_dir))?,
+        inventory,
+        firewall_approvals,
+        dhcp_relays,
+        startup,
+    ))
+}
 
-    // Run the server
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.server_port));
-    info!("Listening on {}", addr);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    #[test]
+    fn every_module_with_dtos_is_registered() {
+        assert_eq!(env!("SIEM_UNREGISTERED_DTO_MODULES"), "",
+                   "Modules with DTOs missing from SOURCES in client_types.rs");
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn client_types_follow_serde() {
+        let fingerprint = version::build_info().api_fingerprint;
+        assert!(CLIENT_TYPES.contains(&format!("export const API_FINGERPRINT = {:?};", fingerprint)));
+
+        // Unit enums are string unions of the serde names, tagged enums carry their tag
+        assert!(CLIENT_TYPES.contains(r#"export type AlertSeverity = "Low" | "Medium" | "High" | "Critical";"#));
+        assert!(CLIENT_TYPES.contains(r#"{ kind: "omit_elements_where"; field: string }"#));
+        assert!(CLIENT_TYPES.contains(r#"getVersion: { method: "GET", path: "/api/admin/version" },"#));
+    }
+}
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::references::ExternalReference;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
@@ -12,6 +15,37 @@ pub struct User {
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
+    // Accounts created before password expiry was tracked count from created_at
+    #[serde(default)]
+    pub password_changed_at: Option<DateTime<Utc>>,
+    // Set by an admin reset, cleared when the user picks a new password
+    #[serde(default)]
+    pub must_change_password: bool,
+    #[serde(default)]
+    pub notifications: NotificationPreferences,
+    // Issuer and subject of accounts provisioned by single sign-on; they have no
+    // local password
+    #[serde(default)]
+    pub external_id: Option<String>,
+    // Sites the user is restricted to, assigned together with the role; empty for all
+    #[serde(default)]
+    pub sites: Vec<Uuid>,
+}
+
+// How a user wants to receive workflow notifications such as script review requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub email: bool,
+    pub webhook_url: Option<String>,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            email: true,
+            webhook_url: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -19,6 +53,28 @@ pub enum UserRole {
     Admin,
     Technician,
     User,
+    Custom(String),
+}
+
+impl UserRole {
+    // Key of this role in the access control permission matrix
+    pub fn role_name(&self) -> String {
+        match self {
+            UserRole::Admin => "admin".to_string(),
+            UserRole::Technician => "technician".to_string(),
+            UserRole::User => "user".to_string(),
+            UserRole::Custom(name) => name.to_lowercase(),
+        }
+    }
+
+    pub fn from_role_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "admin" => UserRole::Admin,
+            "technician" => UserRole::Technician,
+            "user" => UserRole::User,
+            other => UserRole::Custom(other.to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,9 +90,121 @@ pub struct LogEntry {
     pub user: Option<String>,
     pub application: Option<String>,
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub category: EventCategory,
+    // Reverse DNS name of `host` when that is an address, filled in when logs are read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    // Site whose subnet the host is in, set at ingestion
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
+    // What turned raw_data into the fields above, so it can be parsed again, see reparse
+    #[serde(default)]
+    pub parser: LogParser,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogParser {
+    // RFC 5424 or 3164, see syslog::parse_syslog
+    Syslog,
+    // Kernel log record of a dropped packet, see drop_log
+    DropLog,
+    // Sent to /api/logs/ingest with its fields already set; raw_data is the message as sent
+    Api,
+    // Written by the SIEM itself, e.g. logins and alert lifecycle events
+    Internal,
+    // Stored before the parser was recorded
+    #[default]
+    Unknown,
+}
+
+impl LogParser {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogParser::Syslog => "syslog",
+            LogParser::DropLog => "drop_log",
+            LogParser::Api => "api",
+            LogParser::Internal => "internal",
+            LogParser::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> LogParser {
+        [LogParser::Syslog, LogParser::DropLog, LogParser::Api, LogParser::Internal]
+            .into_iter()
+            .find(|p| p.as_str() == value)
+            .unwrap_or_default()
+    }
+}
+
+// Normalized classification of an event; event_type keeps the raw source-specific detail
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum EventCategory {
+    Authentication,
+    Authorization,
+    NetworkTraffic,
+    SystemAvailability,
+    ConfigChange,
+    Malware,
+    Audit,
+    #[default]
+    Other,
+}
+
+impl EventCategory {
+    pub fn all() -> [EventCategory; 8] {
+        [
+            EventCategory::Authentication,
+            EventCategory::Authorization,
+            EventCategory::NetworkTraffic,
+            EventCategory::SystemAvailability,
+            EventCategory::ConfigChange,
+            EventCategory::Malware,
+            EventCategory::Audit,
+            EventCategory::Other,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventCategory::Authentication => "Authentication",
+            EventCategory::Authorization => "Authorization",
+            EventCategory::NetworkTraffic => "NetworkTraffic",
+            EventCategory::SystemAvailability => "SystemAvailability",
+            EventCategory::ConfigChange => "ConfigChange",
+            EventCategory::Malware => "Malware",
+            EventCategory::Audit => "Audit",
+            EventCategory::Other => "Other",
+        }
+    }
+
+    // Case-insensitive lookup, accepting snake_case as produced by parsers
+    pub fn parse(value: &str) -> Option<EventCategory> {
+        let normalized: String = value.chars()
+            .filter(|c| *c != '_' && *c != '-' && *c != ' ')
+            .collect::<String>()
+            .to_lowercase();
+
+        EventCategory::all()
+            .into_iter()
+            .find(|c| c.as_str().to_lowercase() == normalized)
+    }
+
+    // Categories counted as security relevant in compliance reporting
+    pub fn is_security(&self) -> bool {
+        matches!(self,
+            EventCategory::Authentication | EventCategory::Authorization | EventCategory::Malware)
+    }
+}
+
+impl std::fmt::Display for EventCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LogSeverity {
     Debug,
     Info,
@@ -68,6 +236,42 @@ pub struct Alert {
     pub source: String,
     pub related_logs: Vec<Uuid>,
     pub assigned_to: Option<String>,
+    // Escalation notifications sent for this alert, oldest first
+    #[serde(default)]
+    pub notifications: Vec<NotificationAttempt>,
+    // When the alert was last resolved or closed; cleared if it is reopened
+    #[serde(default)]
+    pub resolved_at: Option<DateTime<Utc>>,
+    // Automatic actions run for this alert, see remediation
+    #[serde(default)]
+    pub remediations: Vec<RemediationRecord>,
+    // Upstream issues, vendor cases and CVEs the alert relates to, see references
+    #[serde(default)]
+    pub references: Vec<ExternalReference>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemediationRecord {
+    pub binding_id: Uuid,
+    pub binding_name: String,
+    pub script_id: Uuid,
+    pub at: DateTime<Utc>,
+    // Recorded only, nothing was executed
+    pub dry_run: bool,
+    pub arguments: HashMap<String, String>,
+    pub execution_id: Option<Uuid>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationAttempt {
+    pub at: DateTime<Utc>,
+    pub policy_id: Uuid,
+    pub step: usize,
+    pub target: String,
+    pub success: bool,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -97,9 +301,59 @@ pub struct Asset {
     pub operating_system: Option<String>,
     pub owner: Option<String>,
     pub location: Option<String>,
+    #[serde(default)]
+    pub location_id: Option<Uuid>,
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
     pub purchase_date: Option<DateTime<Utc>>,
     pub status: AssetStatus,
     pub tags: Vec<String>,
+    // Every address the asset has had, oldest first; the open entry is the current one
+    #[serde(default)]
+    pub address_history: Vec<AddressAssignment>,
+    // Observed facts that disagree with the record; while any is pending the asset
+    // needs review
+    #[serde(default)]
+    pub drift: Vec<AssetDrift>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetDrift {
+    // ip_address, mac_address or operating_system
+    pub field: String,
+    pub recorded: Option<String>,
+    pub observed: String,
+    // Where the observation came from, e.g. "dhcp" or "scan:nmap"
+    pub source: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressAssignment {
+    pub address: String,
+    pub assigned_at: DateTime<Utc>,
+    pub released_at: Option<DateTime<Utc>>,
+}
+
+impl Asset {
+    pub fn review_needed(&self) -> bool {
+        !self.drift.is_empty()
+    }
+
+    // Addresses the asset held at the given time. The first assignment also covers
+    // everything before the asset was registered.
+    pub fn addresses_at(&self, at: DateTime<Utc>) -> Vec<&str> {
+        if self.address_history.is_empty() {
+            return self.ip_address.as_deref().into_iter().collect();
+        }
+
+        self.address_history.iter()
+            .enumerate()
+            .filter(|(i, a)| (*i == 0 || a.assigned_at <= at) && a.released_at.map_or(true, |r| at < r))
+            .map(|(_, a)| a.address.as_str())
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -2,13 +2,25 @@
 use anyhow::{Context, Result};
 use rtnetlink::{new_connection, Handle, IpVersion};
 use futures::stream::TryStreamExt;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
+use ipnetwork::IpNetwork;
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use std::process::Command;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 use tracing::{info, warn, error};
 
+use crate::config::{Config, FirewallConfig};
+use crate::services::ServiceDefinition;
+use crate::interface_metadata::InterfaceMetadata;
+use crate::dhcp_relay::DhcpRelayStatus;
+
 // Define NFTables module
 mod nftables {
     use serde::{Deserialize, Serialize};
@@ -29,6 +41,13 @@ mod nftables {
         
         pub fn add(&mut self, stmt: &Stmt, comment: Option<&str>) {
             let mut cmd = format!("{}", stmt);
+            // Rules without a comment of their own are marked as written by us, so rules
+            // added by hand stand out, see find_foreign_rules
+            if let Stmt::Add(rule) = stmt {
+                if !rule.expr.iter().any(|e| matches!(e, expr::Expr::Comment(_))) {
+                    cmd = format!("{} {}", cmd, expr::Comment { text: super::GENERATED_RULE_COMMENT.to_string() });
+                }
+            }
             if let Some(c) = comment {
                 cmd = format!("{} # {}", cmd, c);
             }
@@ -65,12 +84,18 @@ mod nftables {
                 commands: self.commands.clone(),
             }
         }
+        
+        pub fn commands(&self) -> Vec<String> {
+            self.commands.clone()
+        }
     }
     
     pub enum Stmt {
         AddTable(objects::AddTable),
         AddChain(objects::AddChain),
         Add(objects::Add),
+        AddSet(objects::AddSet),
+        AddElement(objects::AddElement),
         Flush(objects::Flush),
     }
     
@@ -95,6 +120,10 @@ mod nftables {
                     }
                     Ok(())
                 },
+                Stmt::AddSet(s) => write!(f, "add set {} {} {} {{ type {}; flags {}; }}",
+                                          s.family, s.table, s.name, s.set_type, s.flags.join(", ")),
+                Stmt::AddElement(e) => write!(f, "add element {} {} {} {{ {} }}",
+                                              e.family, e.table, e.name, e.elements.join(", ")),
                 Stmt::Flush(flush) => write!(f, "{}", flush),
             }
         }
@@ -130,6 +159,23 @@ mod nftables {
             pub expr: Vec<super::expr::Expr>,
         }
         
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct AddSet {
+            pub family: TableFamily,
+            pub table: String,
+            pub name: String,
+            pub set_type: String,
+            pub flags: Vec<String>,
+        }
+        
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct AddElement {
+            pub family: TableFamily,
+            pub table: String,
+            pub name: String,
+            pub elements: Vec<String>,
+        }
+        
         #[derive(Debug, Clone, Deserialize, Serialize)]
         pub enum Flush {
             Table {
@@ -141,6 +187,11 @@ mod nftables {
                 table: String,
                 name: String,
             },
+            Set {
+                family: TableFamily,
+                table: String,
+                name: String,
+            },
         }
         
         impl fmt::Display for Flush {
@@ -148,6 +199,7 @@ mod nftables {
                 match self {
                     Flush::Table { family, name } => write!(f, "flush table {} {}", family, name),
                     Flush::Chain { family, table, name } => write!(f, "flush chain {} {} {}", family, table, name),
+                    Flush::Set { family, table, name } => write!(f, "flush set {} {} {}", family, table, name),
                 }
             }
         }
@@ -164,6 +216,10 @@ mod nftables {
             Accept(Accept),
             Drop(Drop),
             Counter(Counter),
+            Masquerade(Masquerade),
+            Limit(Limit),
+            Log(Log),
+            Comment(Comment),
         }
         
         impl fmt::Display for Expr {
@@ -174,6 +230,10 @@ mod nftables {
                     Expr::Accept(a) => write!(f, "{}", a),
                     Expr::Drop(d) => write!(f, "{}", d),
                     Expr::Counter(c) => write!(f, "{}", c),
+                    Expr::Masquerade(m) => write!(f, "{}", m),
+                    Expr::Limit(l) => write!(f, "{}", l),
+                    Expr::Log(l) => write!(f, "{}", l),
+                    Expr::Comment(c) => write!(f, "{}", c),
                 }
             }
         }
@@ -251,6 +311,59 @@ mod nftables {
                 write!(f, "counter")
             }
         }
+        
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct Masquerade {
+        }
+        
+        impl fmt::Display for Masquerade {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "masquerade")
+            }
+        }
+        
+        // Matches while under the rate, e.g. to keep a log rule from flooding the log
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct Limit {
+            pub rate: u32,
+            // second, minute, hour or day
+            pub per: String,
+        }
+        
+        impl fmt::Display for Limit {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "limit rate {}/{}", self.rate, self.per)
+            }
+        }
+        
+        // Logs to the kernel log, or with a group to that nflog group in userspace
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct Log {
+            pub prefix: String,
+            #[serde(default)]
+            pub group: Option<u16>,
+        }
+        
+        impl fmt::Display for Log {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "log prefix \"{}\"", self.prefix.replace('"', "'"))?;
+                if let Some(group) = self.group {
+                    write!(f, " group {}", group)?;
+                }
+                Ok(())
+            }
+        }
+        
+        #[derive(Debug, Clone, Deserialize, Serialize)]
+        pub struct Comment {
+            pub text: String,
+        }
+        
+        impl fmt::Display for Comment {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "comment \"{}\"", self.text.replace('"', "'"))
+            }
+        }
     }
     
     pub mod schemas {
@@ -290,456 +403,3356 @@ pub struct InterfaceConfig {
     pub dhcp: Option<bool>,
     pub address: Option<String>,
     pub nftables_zone: Option<String>,
+    // Makes the interface a bond of the listed members
+    #[serde(default)]
+    pub bond: Option<BondConfig>,
+    // Relays DHCP from the interface's clients to upstream servers, see dhcp_relay
+    #[serde(default)]
+    pub dhcp_relay: Option<DhcpRelay>,
 }
 
-pub struct NetworkManager {
-    netlink_handle: Handle,
-    interfaces: Arc<Mutex<Vec<InterfaceConfig>>>,
-    nftables_handle: nftables::Batch,
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum BondMode {
+    #[serde(rename = "802.3ad")]
+    Lacp,
+    #[serde(rename = "active-backup")]
+    ActiveBackup,
 }
 
-impl NetworkManager {
-    pub async fn new() -> Result<Self> {
-        let (connection, handle, _) = new_connection()
-            .context("Failed to create netlink connection")?;
-        
-        // Spawn a task to drive the netlink connection
-        tokio::spawn(connection);
-        
-        // Create nftables handle
-        let nftables_handle = nftables::Batch::new();
-        
-        Ok(Self {
-            netlink_handle: handle,
-            interfaces: Arc::new(Mutex::new(Vec::new())),
-            nftables_handle,
-        })
+impl BondMode {
+    fn to_netlink(self) -> rtnetlink::packet::link::BondMode {
+        match self {
+            BondMode::Lacp => rtnetlink::packet::link::BondMode::Ieee8023Ad,
+            BondMode::ActiveBackup => rtnetlink::packet::link::BondMode::ActiveBackup,
+        }
     }
-    
-    pub async fn load_config(&self, interfaces: Vec<InterfaceConfig>) -> Result<()> {
-        let mut ifaces = self.interfaces.lock().await;
-        *ifaces = interfaces;
-        Ok(())
+
+    fn from_netlink(mode: &rtnetlink::packet::link::BondMode) -> Option<Self> {
+        match mode {
+            rtnetlink::packet::link::BondMode::Ieee8023Ad => Some(BondMode::Lacp),
+            rtnetlink::packet::link::BondMode::ActiveBackup => Some(BondMode::ActiveBackup),
+            _ => None,
+        }
     }
-    
-    pub async fn initialize_nftables(&self) -> Result<()> {
-        info!("Initializing nftables configuration");
-        
-        // Create a new batch for nftables commands
-        let mut batch = nftables::Batch::new();
-        
-        // Flush all existing rules to start fresh
-        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Table {
-            family: nftables::schemas::nftables::TableFamily::Inet,
-            name: "filter".to_string(),
-        }), None);
-        
-        // Create a new filter table
-        batch.add(&nftables::Stmt::AddTable(nftables::objects::AddTable {
-            family: nftables::schemas::nftables::TableFamily::Inet,
-            name: "filter".to_string(),
-        }), None);
-        
-        // Create basic chains
-        let chains = vec![
-            ("input", "type filter hook input priority 0; policy drop;"),
-            ("forward", "type filter hook forward priority 0; policy drop;"),
-            ("output", "type filter hook output priority 0; policy accept;"),
-        ];
-        
-        for (chain_name, chain_policy) in chains {
-            batch.add(&nftables::Stmt::AddChain(nftables::objects::AddChain {
-                family: nftables::schemas::nftables::TableFamily::Inet,
-                table: "filter".to_string(),
-                name: chain_name.to_string(),
-                handle: None,
-                constraint: Some(chain_policy.to_string()),
-            }), None);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BondConfig {
+    pub mode: BondMode,
+    pub members: Vec<String>,
+    // Members must be down to be enslaved; with bounce set, members that are up are taken down
+    #[serde(default)]
+    pub bounce: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DhcpRelay {
+    pub enabled: bool,
+    // IPv4 addresses of the DHCP servers requests are relayed to
+    pub servers: Vec<String>,
+}
+
+// Relay settings of an interface when its relay is switched on
+fn active_relay(config: &InterfaceConfig) -> Option<&DhcpRelay> {
+    config.dhcp_relay.as_ref().filter(|relay| relay.enabled)
+}
+
+// Traffic one of the firewall rules of a DHCP relay lets through
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayFlow {
+    // Clients to the relay on the interface (input, udp 67)
+    ClientRequests,
+    // Upstream servers to the relay (input, udp 67)
+    ServerReplies,
+    // Relay to the upstream servers (output, udp 67)
+    ToServers,
+    // Relay to the clients on the interface (output, udp 68)
+    ToClients,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DhcpRelayRule {
+    pub interface: String,
+    pub flow: RelayFlow,
+}
+
+// Packets the relay firewall rules of an interface have let through
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DhcpRelayCounters {
+    pub requests: u64,
+    pub replies: u64,
+}
+
+// A firewall rule added through the API, kept on top of the generated base ruleset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagedRule {
+    pub handle: u32,
+    pub chain: String,
+    pub rule: String,
+    pub description: String,
+    pub group: Option<Uuid>,
+    // Set on forward rules generated from the zone forwarding matrix; those have no
+    // handle of their own and change through their matrix entry
+    #[serde(default)]
+    pub forwarding: Option<Uuid>,
+    // Same for output rules generated from a zone egress policy
+    #[serde(default)]
+    pub egress: Option<Uuid>,
+    // Set on input rules opening a service of this host to a zone; those change through
+    // the zone's service list
+    #[serde(default)]
+    pub self_service: Option<SelfServiceRule>,
+    // Same for the rules of an interface's DHCP relay; those follow the interface config
+    #[serde(default)]
+    pub dhcp_relay: Option<DhcpRelayRule>,
+    pub created_at: DateTime<Utc>,
+    // Band the rule is ordered by within its chain, lower first; see place_rule
+    #[serde(default = "default_rule_priority")]
+    pub priority: i32,
+    expr: Vec<nftables::expr::Expr>,
+}
+
+// Band of rules added without a position
+pub const DEFAULT_RULE_PRIORITY: i32 = 100;
+
+fn default_rule_priority() -> i32 {
+    DEFAULT_RULE_PRIORITY
+}
+
+// Where a managed rule goes in its chain: next to another rule of the same chain, or
+// last in a priority band
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RulePosition {
+    Before(u32),
+    After(u32),
+    Priority(i32),
+}
+
+// Inserts the rule at its position. Managed rules are kept sorted by priority band and
+// within a band by insertion, except that a rule placed before or after another one
+// joins that rule's band right next to it.
+fn place_rule(rules: &mut Vec<ManagedRule>, mut rule: ManagedRule, position: &RulePosition) -> Result<usize> {
+    let index = match position {
+        RulePosition::Priority(priority) => {
+            rule.priority = *priority;
+            rules.iter().rposition(|r| r.priority <= *priority).map_or(0, |i| i + 1)
+        },
+        RulePosition::Before(handle) | RulePosition::After(handle) => {
+            let target = rules.iter().position(|r| r.handle == *handle)
+                .ok_or_else(|| anyhow::anyhow!("Firewall rule not found: {}", handle))?;
+            if rules[target].chain != rule.chain {
+                return Err(anyhow::anyhow!("Firewall rule {} is in chain {}, not {}", handle, rules[target].chain, rule.chain));
+            }
+            rule.priority = rules[target].priority;
+            if matches!(position, RulePosition::Before(_)) { target } else { target + 1 }
+        },
+    };
+
+    rules.insert(index, rule);
+    Ok(index)
+}
+
+// Moves a rule to a new position; on failure the rules are left as they were
+fn move_rule(rules: &mut Vec<ManagedRule>, handle: u32, position: &RulePosition) -> Result<()> {
+    if matches!(position, RulePosition::Before(h) | RulePosition::After(h) if *h == handle) {
+        return Err(anyhow::anyhow!("Firewall rule {} cannot be positioned relative to itself", handle));
+    }
+    let index = rules.iter().position(|r| r.handle == handle)
+        .ok_or_else(|| anyhow::anyhow!("Firewall rule not found: {}", handle))?;
+
+    let rule = rules.remove(index);
+    if let Err(e) = place_rule(rules, rule.clone(), position) {
+        rules.insert(index, rule);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Handles of the chain's managed rules in the order they are applied
+fn chain_order(rules: &[ManagedRule], chain: &str) -> Vec<u32> {
+    rules.iter().filter(|r| r.chain == chain).map(|r| r.handle).collect()
+}
+
+// Comment identifying a managed rule in the kernel ruleset, see parse_rule_counters
+const RULE_COMMENT_PREFIX: &str = "siem:rule:";
+
+// Comment of every other rule the ruleset is generated with
+const GENERATED_RULE_COMMENT: &str = "siem:generated";
+
+impl ManagedRule {
+    fn to_stmt(&self) -> nftables::Stmt {
+        let mut expr = self.expr.clone();
+        if self.handle != 0 {
+            expr.push(nftables::expr::Expr::Comment(nftables::expr::Comment {
+                text: format!("{}{}", RULE_COMMENT_PREFIX, self.handle),
+            }));
         }
         
-        // Allow established connections
-        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+        nftables::Stmt::Add(nftables::objects::Add {
             family: nftables::schemas::nftables::TableFamily::Inet,
             table: "filter".to_string(),
-            chain: "input".to_string(),
+            chain: self.chain.clone(),
             handle: None,
             index: None,
-            expr: vec![
-                nftables::expr::Expr::Match(nftables::expr::Match {
-                    op: "ct".to_string(),
-                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                        op: "state".to_string(),
-                        data: nftables::expr::Data::Set(vec![
-                            "established".to_string(),
-                            "related".to_string(),
-                        ]),
-                    })),
-                }),
-                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
-            ],
-        }), None);
+            expr,
+        })
+    }
+}
+
+// Packet and byte counts of a managed rule as last read from the kernel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleCounters {
+    pub handle: u32,
+    // Handle nft assigned to the rule, needed to reset it
+    pub kernel_handle: u64,
+    pub family: String,
+    pub table: String,
+    pub chain: String,
+    pub packets: u64,
+    pub bytes: u64,
+    pub collected_at: DateTime<Utc>,
+}
+
+// A managed rule together with its counters, None when nft could not be read
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagedRuleStatus {
+    #[serde(flatten)]
+    pub rule: ManagedRule,
+    pub counters: Option<RuleCounters>,
+    // 1-based position among the managed rules of its chain, as applied
+    pub position: usize,
+}
+
+// Reads the counters of managed rules from `nft -j list ruleset` output. Rules are
+// recognized by their comment; anything else in the ruleset (rules added by hand or
+// by other tools, named counters, sets) is skipped.
+pub fn parse_rule_counters(json: &str) -> Result<HashMap<u32, RuleCounters>> {
+    let document: serde_json::Value = serde_json::from_str(json)
+        .context("Failed to parse nft JSON output")?;
+    let collected_at = Utc::now();
+    let mut counters = HashMap::new();
+    
+    let objects = match document.get("nftables").and_then(|o| o.as_array()) {
+        Some(objects) => objects,
+        None => return Err(anyhow::anyhow!("nft JSON output has no nftables array")),
+    };
+    
+    for rule in objects.iter().filter_map(|o| o.get("rule")) {
+        let handle = match rule.get("comment")
+            .and_then(|c| c.as_str())
+            .and_then(|c| c.strip_prefix(RULE_COMMENT_PREFIX))
+            .and_then(|h| h.parse::<u32>().ok()) {
+            Some(handle) => handle,
+            None => continue,
+        };
         
-        // Allow loopback
-        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
-            family: nftables::schemas::nftables::TableFamily::Inet,
-            table: "filter".to_string(),
-            chain: "input".to_string(),
-            handle: None,
-            index: None,
-            expr: vec![
-                nftables::expr::Expr::Match(nftables::expr::Match {
-                    op: "meta".to_string(),
-                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                        op: "iifname".to_string(),
-                        data: nftables::expr::Data::StrVal("lo".to_string()),
-                    })),
-                }),
-                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
-            ],
-        }), None);
+        // Anonymous counters carry their values inline, named counter references are strings
+        let counter = rule.get("expr")
+            .and_then(|e| e.as_array())
+            .and_then(|exprs| exprs.iter().find_map(|e| e.get("counter").filter(|c| c.is_object())));
         
-        // Add zone-specific rules based on interface configuration
-        let ifaces = self.interfaces.lock().await;
+        let (packets, bytes) = match counter {
+            Some(counter) => (
+                counter.get("packets").and_then(|p| p.as_u64()).unwrap_or(0),
+                counter.get("bytes").and_then(|b| b.as_u64()).unwrap_or(0),
+            ),
+            None => continue,
+        };
         
-        // Collect interfaces by zone
-        let mut zone_interfaces: HashMap<String, Vec<String>> = HashMap::new();
+        let text = |key: &str| rule.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
         
-        for iface in ifaces.iter() {
-            if let Some(zone) = &iface.nftables_zone {
-                zone_interfaces
-                    .entry(zone.clone())
-                    .or_insert_with(Vec::new)
-                    .push(iface.name.clone());
-            }
+        counters.insert(handle, RuleCounters {
+            handle,
+            kernel_handle: rule.get("handle").and_then(|h| h.as_u64()).unwrap_or(0),
+            family: text("family"),
+            table: text("table"),
+            chain: text("chain"),
+            packets,
+            bytes,
+            collected_at,
+        });
+    }
+    
+    Ok(counters)
+}
+
+// A rule in the kernel ruleset that was not written by us, e.g. one added with nft by hand
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForeignRule {
+    pub family: String,
+    pub table: String,
+    pub chain: String,
+    pub kernel_handle: u64,
+    pub comment: Option<String>,
+    // The rule's expressions as nft prints them in JSON
+    pub expr: serde_json::Value,
+}
+
+// Rules of our tables in `nft -j list ruleset` output that carry neither the generated
+// comment nor the comment of a current managed rule. Tables we do not create (other
+// tools, container runtimes) are left alone.
+pub fn parse_foreign_rules(json: &str, tables: &[(String, String)], managed: &[u32]) -> Result<Vec<ForeignRule>> {
+    let document: serde_json::Value = serde_json::from_str(json)
+        .context("Failed to parse nft JSON output")?;
+    let objects = match document.get("nftables").and_then(|o| o.as_array()) {
+        Some(objects) => objects,
+        None => return Err(anyhow::anyhow!("nft JSON output has no nftables array")),
+    };
+    
+    let mut foreign = Vec::new();
+    for rule in objects.iter().filter_map(|o| o.get("rule")) {
+        let text = |key: &str| rule.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        if !tables.iter().any(|(family, table)| *family == text("family") && *table == text("table")) {
+            continue;
         }
         
-        // Create zone-specific rules
-        for (zone, interfaces) in zone_interfaces {
-            match zone.as_str() {
-                "wan" => {
-                    // Allow SSH from WAN zone
-                    for iface in &interfaces {
-                        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
-                            family: nftables::schemas::nftables::TableFamily::Inet,
-                            table: "filter".to_string(),
-                            chain: "input".to_string(),
-                            handle: None,
-                            index: None,
-                            expr: vec![
-                                nftables::expr::Expr::Match(nftables::expr::Match {
-                                    op: "meta".to_string(),
-                                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                                        op: "iifname".to_string(),
-                                        data: nftables::expr::Data::StrVal(iface.clone()),
-                                    })),
-                                }),
-                                nftables::expr::Expr::Match(nftables::expr::Match {
-                                    op: "tcp".to_string(),
-                                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                                        op: "dport".to_string(),
-                                        data: nftables::expr::Data::StrVal("22".to_string()),
-                                    })),
-                                }),
-                                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
-                            ],
-                        }), None);
-                    }
-                }
-                "lan" => {
-                    // Allow all traffic from LAN zone
-                    for iface in &interfaces {
-                        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
-                            family: nftables::schemas::nftables::TableFamily::Inet,
-                            table: "filter".to_string(),
-                            chain: "input".to_string(),
-                            handle: None,
-                            index: None,
-                            expr: vec![
-                                nftables::expr::Expr::Match(nftables::expr::Match {
-                                    op: "meta".to_string(),
-                                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                                        op: "iifname".to_string(),
-                                        data: nftables::expr::Data::StrVal(iface.clone()),
-                                    })),
-                                }),
-                                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
-                            ],
-                        }), None);
-                    }
-                }
-                _ => {
-                    // Default rules for other zones - just allow web traffic
-                    for iface in &interfaces {
-                        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
-                            family: nftables::schemas::nftables::TableFamily::Inet,
-                            table: "filter".to_string(),
-                            chain: "input".to_string(),
-                            handle: None,
-                            index: None,
-                            expr: vec![
-                                nftables::expr::Expr::Match(nftables::expr::Match {
-                                    op: "meta".to_string(),
-                                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                                        op: "iifname".to_string(),
-                                        data: nftables::expr::Data::StrVal(iface.clone()),
-                                    })),
-                                }),
-                                nftables::expr::Expr::Match(nftables::expr::Match {
-                                    op: "tcp".to_string(),
-                                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                                        op: "dport".to_string(),
-                                        data: nftables::expr::Data::Set(vec![
-                                            "80".to_string(),
-                                            "443".to_string(),
-                                        ]),
-                                    })),
-                                }),
-                                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
-                            ],
-                        }), None);
-                    }
-                }
-            }
+        let comment = rule.get("comment").and_then(|c| c.as_str());
+        let ours = match comment {
+            Some(GENERATED_RULE_COMMENT) => true,
+            Some(c) => c.strip_prefix(RULE_COMMENT_PREFIX)
+                .and_then(|h| h.parse::<u32>().ok())
+                .map_or(false, |h| managed.contains(&h)),
+            None => false,
+        };
+        if ours {
+            continue;
         }
         
-        // Execute the batch
-        self.nftables_handle = batch.clone();
-        
-        // In a real environment, we would execute:
-        // batch.execute().context("Failed to execute nftables rules")?;
-        // But in this implementation, we'll just log
-        info!("nftables rules configured successfully");
-        
-        Ok(())
+        foreign.push(ForeignRule {
+            family: text("family"),
+            table: text("table"),
+            chain: text("chain"),
+            kernel_handle: rule.get("handle").and_then(|h| h.as_u64()).unwrap_or(0),
+            comment: comment.map(str::to_string),
+            expr: rule.get("expr").cloned().unwrap_or(serde_json::Value::Null),
+        });
     }
     
-    pub async fn get_interfaces(&self) -> Result<Vec<InterfaceInfo>> {
-        let mut links = self.netlink_handle.link().get().execute();
-        let mut interfaces = Vec::new();
-        
-        while let Some(link) = links.try_next().await? {
-            let name = link.attributes.iter()
-                .find_map(|attr| {
-                    if let rtnetlink::packet::link::LinkAttribute::IfName(name) = attr {
-                        Some(name.clone())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
-                
-            let mut interface = InterfaceInfo {
-                name,
-                addresses: Vec::new(),
-                is_up: false,
-                mac_address: String::new(),
-            };
-            
-            // Check if the interface is up
-            if let Some(rtnetlink::packet::link::LinkAttribute::OperState(state)) = link.attributes.iter()
-                .find(|attr| matches!(attr, rtnetlink::packet::link::LinkAttribute::OperState(_))) {
-                interface.is_up = *state == rtnetlink::packet::link::State::Up;
-            }
-            
-            // Get MAC address
-            if let Some(rtnetlink::packet::link::LinkAttribute::Address(addr)) = link.attributes.iter()
-                .find(|attr| matches!(attr, rtnetlink::packet::link::LinkAttribute::Address(_))) {
-                interface.mac_address = addr.iter()
-                    .map(|b| format!("{:02x}", b))
-                    .collect::<Vec<String>>()
-                    .join(":");
+    Ok(foreign)
+}
+
+struct ManagedRules {
+    rules: Vec<ManagedRule>,
+    next_handle: u32,
+}
+
+// Everything the firewall is generated from besides the interface config: what a backup
+// holds and a restore puts back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirewallModel {
+    pub managed_rules: Vec<ManagedRule>,
+    pub next_handle: u32,
+    pub forwarding: Vec<ZoneForwarding>,
+    pub egress: Vec<ZoneEgress>,
+    pub drop_logging: Vec<ZoneDropLogging>,
+    pub threat_intel: Vec<String>,
+    pub zone_services: Vec<ZoneServices>,
+}
+
+// Rules generated from a service template, removable together
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleGroup {
+    pub id: Uuid,
+    pub rules: Vec<ManagedRule>,
+}
+
+fn set_or_value(values: Vec<String>) -> nftables::expr::Data {
+    if values.len() == 1 {
+        nftables::expr::Data::StrVal(values[0].clone())
+    } else {
+        nftables::expr::Data::Set(values)
+    }
+}
+
+fn match_expr(op: &str, field: &str, data: nftables::expr::Data) -> nftables::expr::Expr {
+    nftables::expr::Expr::Match(nftables::expr::Match {
+        op: op.to_string(),
+        expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
+            op: field.to_string(),
+            data,
+        })),
+    })
+}
+
+// Builds protocol/port matchers; several ports become a set, several protocols use th dport
+fn protocol_port_expressions(protocols: &[&str], ports: &[u16]) -> Vec<nftables::expr::Expr> {
+    let port_values: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+    let protocol_values: Vec<String> = protocols.iter().map(|p| p.to_string()).collect();
+
+    match (protocols.len(), ports.is_empty()) {
+        (0, _) => Vec::new(),
+        (_, true) => vec![match_expr("meta", "l4proto", set_or_value(protocol_values))],
+        (1, false) => vec![match_expr(protocols[0], "dport", set_or_value(port_values))],
+        (_, false) => vec![
+            match_expr("meta", "l4proto", set_or_value(protocol_values)),
+            match_expr("th", "dport", set_or_value(port_values)),
+        ],
+    }
+}
+
+fn action_expr(action: &str) -> Result<nftables::expr::Expr> {
+    match action.to_lowercase().as_str() {
+        "accept" => Ok(nftables::expr::Expr::Accept(nftables::expr::Accept {})),
+        "drop" => Ok(nftables::expr::Expr::Drop(nftables::expr::Drop {})),
+        _ => Err(anyhow::anyhow!("Unsupported action: {}", action)),
+    }
+}
+
+// Selector family of an address literal (address or CIDR). The filter table is inet,
+// where "ip saddr" only sees IPv4 packets, so IPv6 addresses must use "ip6".
+pub fn address_family(address: &str) -> Result<&'static str> {
+    match IpNetwork::from_str(address.trim()) {
+        Ok(IpNetwork::V4(_)) => Ok("ip"),
+        Ok(IpNetwork::V6(_)) => Ok("ip6"),
+        Err(_) => Err(anyhow::anyhow!("Invalid address: {}", address)),
+    }
+}
+
+fn address_expr(field: &str, address: &str) -> Result<nftables::expr::Expr> {
+    let family = address_family(address)?;
+    Ok(match_expr(family, field, nftables::expr::Data::StrVal(address.trim().to_string())))
+}
+
+// A rule whose addresses or ICMP protocols belong to different families could never
+// match, so it is rejected instead of rendered. Returns the family of the addresses.
+pub fn check_rule_family(addresses: &[&str], protocols: &[&str]) -> Result<Option<&'static str>> {
+    let mut family = None;
+    for address in addresses {
+        let address_family = address_family(address)?;
+        if family.map_or(false, |f| f != address_family) {
+            return Err(anyhow::anyhow!("Rule mixes IPv4 and IPv6 addresses"));
+        }
+        family = Some(address_family);
+    }
+
+    match family {
+        Some("ip6") if protocols.contains(&"icmp") => Err(anyhow::anyhow!("icmp cannot match IPv6 addresses, use icmpv6")),
+        Some("ip") if protocols.contains(&"icmpv6") => Err(anyhow::anyhow!("icmpv6 cannot match IPv4 addresses, use icmp")),
+        _ => Ok(family),
+    }
+}
+
+// ICMPv6 IPv6 does not work without (RFC 4890): error signalling and neighbor discovery
+const ICMPV6_ESSENTIAL_TYPES: &[&str] = &[
+    "destination-unreachable",
+    "packet-too-big",
+    "time-exceeded",
+    "parameter-problem",
+    "nd-neighbor-solicit",
+    "nd-neighbor-advert",
+];
+
+// Router discovery, only accepted on LAN zones
+const ICMPV6_ROUTER_TYPES: &[&str] = &["nd-router-solicit", "nd-router-advert"];
+
+fn input_rule(expr: Vec<nftables::expr::Expr>) -> nftables::Stmt {
+    nftables::Stmt::Add(nftables::objects::Add {
+        family: nftables::schemas::nftables::TableFamily::Inet,
+        table: "filter".to_string(),
+        chain: "input".to_string(),
+        handle: None,
+        index: None,
+        expr,
+    })
+}
+
+// Base input rules for ICMP; without them a drop policy breaks neighbor discovery
+fn icmp_base_rules(lan_interfaces: &[String], firewall: &FirewallConfig) -> Vec<nftables::Stmt> {
+    let types = |types: &[&str]| nftables::expr::Data::Set(types.iter().map(|t| t.to_string()).collect());
+    let accept = || nftables::expr::Expr::Accept(nftables::expr::Accept {});
+
+    let mut rules = vec![input_rule(vec![
+        match_expr("icmpv6", "type", types(ICMPV6_ESSENTIAL_TYPES)),
+        accept(),
+    ])];
+
+    if !lan_interfaces.is_empty() {
+        rules.push(input_rule(vec![
+            match_expr("meta", "iifname", set_or_value(lan_interfaces.to_vec())),
+            match_expr("icmpv6", "type", types(ICMPV6_ROUTER_TYPES)),
+            accept(),
+        ]));
+    }
+
+    if firewall.allow_icmp_echo {
+        for protocol in ["icmp", "icmpv6"] {
+            rules.push(input_rule(vec![
+                match_expr(protocol, "type", nftables::expr::Data::StrVal("echo-request".to_string())),
+                accept(),
+            ]));
+        }
+    }
+
+    rules
+}
+
+// Hook a filter rule is evaluated from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Input,
+    Output,
+    Forward,
+}
+
+impl Direction {
+    // The direction a base chain implies; custom chains imply none
+    pub fn of_chain(chain: &str) -> Option<Self> {
+        match chain {
+            "input" => Some(Direction::Input),
+            "output" => Some(Direction::Output),
+            "forward" => Some(Direction::Forward),
+            _ => None,
+        }
+    }
+
+    pub fn chain(&self) -> &'static str {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+            Direction::Forward => "forward",
+        }
+    }
+}
+
+// Address and interface matchers of a rule
+#[derive(Debug, Clone, Default)]
+pub struct RuleSelectors {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub in_interface: Option<String>,
+    pub out_interface: Option<String>,
+}
+
+impl RuleSelectors {
+    fn addresses(&self) -> Vec<&str> {
+        self.source.iter().chain(self.destination.iter()).map(|a| a.as_str()).collect()
+    }
+
+    fn expressions(&self) -> Result<Vec<nftables::expr::Expr>> {
+        let mut expressions = Vec::new();
+
+        if let Some(iface) = &self.in_interface {
+            expressions.push(match_expr("meta", "iifname", nftables::expr::Data::StrVal(iface.clone())));
+        }
+
+        if let Some(iface) = &self.out_interface {
+            expressions.push(match_expr("meta", "oifname", nftables::expr::Data::StrVal(iface.clone())));
+        }
+
+        if let Some(source) = &self.source {
+            expressions.push(address_expr("saddr", source)?);
+        }
+
+        if let Some(destination) = &self.destination {
+            expressions.push(address_expr("daddr", destination)?);
+        }
+
+        Ok(expressions)
+    }
+}
+
+// Rejects selectors that can never match in the chain. Packets this host sends have no
+// input interface and packets addressed to it no output interface. A base chain implies
+// its direction; for a custom chain the stated direction is checked instead.
+pub fn check_selectors(chain: &str, direction: Option<Direction>, selectors: &RuleSelectors) -> Result<()> {
+    let implied = Direction::of_chain(chain);
+    if let (Some(implied), Some(direction)) = (implied, direction) {
+        if implied != direction {
+            return Err(anyhow::anyhow!("Chain {} only sees {} traffic, not {}", chain, implied.chain(), direction.chain()));
+        }
+    }
+
+    match implied.or(direction) {
+        Some(Direction::Output) if selectors.in_interface.is_some() =>
+            Err(anyhow::anyhow!("Output rules cannot match an input interface, use the output interface")),
+        Some(Direction::Input) if selectors.out_interface.is_some() =>
+            Err(anyhow::anyhow!("Input rules cannot match an output interface, use the input interface")),
+        _ => Ok(()),
+    }
+}
+
+// Structured description of a single filter rule, used where rules are built from
+// external input (imports, staged changes) before being turned into expressions
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RuleSpec {
+    pub chain: String,
+    // Needed to check the selectors of rules in custom chains
+    #[serde(default)]
+    pub direction: Option<Direction>,
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub in_interface: Option<String>,
+    pub out_interface: Option<String>,
+    pub action: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl RuleSpec {
+    fn selectors(&self) -> RuleSelectors {
+        RuleSelectors {
+            source: self.source.clone(),
+            destination: self.destination.clone(),
+            in_interface: self.in_interface.clone(),
+            out_interface: self.out_interface.clone(),
+        }
+    }
+
+    fn to_expressions(&self) -> Result<Vec<nftables::expr::Expr>> {
+        if self.chain.is_empty() || !self.chain.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("Invalid chain name: {}", self.chain));
+        }
+
+        if !self.ports.is_empty() && self.protocols.is_empty() {
+            return Err(anyhow::anyhow!("A port requires a protocol"));
+        }
+
+        if let Some(p) = self.protocols.iter().find(|p| !matches!(p.as_str(), "tcp" | "udp" | "icmp" | "icmpv6")) {
+            return Err(anyhow::anyhow!("Unsupported protocol: {}", p));
+        }
+
+        let selectors = self.selectors();
+        check_selectors(&self.chain, self.direction, &selectors)?;
+
+        let protocols: Vec<&str> = self.protocols.iter().map(|p| p.as_str()).collect();
+        check_rule_family(&selectors.addresses(), &protocols)?;
+
+        let mut expressions = selectors.expressions()?;
+        expressions.extend(protocol_port_expressions(&protocols, &self.ports));
+        expressions.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+        expressions.push(action_expr(&self.action)?);
+
+        Ok(expressions)
+    }
+
+    // The nft statement this spec turns into, exactly as it would be applied
+    pub fn render(&self) -> Result<String> {
+        let rule = ManagedRule {
+            handle: 0,
+            chain: self.chain.clone(),
+            rule: String::new(),
+            description: self.description.clone(),
+            group: None,
+            forwarding: None,
+            egress: None,
+            self_service: None,
+            dhcp_relay: None,
+            created_at: Utc::now(),
+            priority: DEFAULT_RULE_PRIORITY,
+            expr: self.to_expressions()?,
+        };
+
+        Ok(rule.to_stmt().to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedRule {
+    pub spec: RuleSpec,
+    pub rendered: String,
+}
+
+// A rule to be moved, with the chain order the move would give as of staging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedMove {
+    pub handle: u32,
+    pub position: RulePosition,
+    pub chain: String,
+    pub order: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesetComment {
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub text: String,
+}
+
+// Rules prepared for review; nothing reaches the ruleset until the changeset is applied.
+// Staged changesets are the change requests a user with network:apply approves by
+// applying them, see firewall_approvals.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedChangeset {
+    pub id: Uuid,
+    pub description: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub rules: Vec<StagedRule>,
+    #[serde(default)]
+    pub moves: Vec<StagedMove>,
+    // Set when the changeset restores a backup; it replaces the whole model
+    #[serde(default)]
+    pub restore: Option<StagedRestore>,
+    #[serde(default)]
+    pub comments: Vec<ChangesetComment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffRule {
+    pub chain: String,
+    pub rule: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffReorder {
+    pub chain: String,
+    pub before: Vec<u32>,
+    pub after: Vec<u32>,
+}
+
+// What applying a changeset would change in our rules, against the rules as they are now
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangesetDiff {
+    pub id: Uuid,
+    pub added: Vec<DiffRule>,
+    pub removed: Vec<DiffRule>,
+    pub reordered: Vec<DiffReorder>,
+    // Parts of the model besides the rules that a restore replaces with different contents
+    pub replaced: Vec<&'static str>,
+}
+
+impl DiffRule {
+    fn of(rule: &ManagedRule) -> Self {
+        Self {
+            chain: rule.chain.clone(),
+            rule: rule.rule.clone(),
+            description: rule.description.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedRestore {
+    // None when the model was replicated from the primary, see replication
+    pub backup_id: Option<Uuid>,
+    pub backup_created_at: DateTime<Utc>,
+    pub model: FirewallModel,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardPolicy {
+    Accept,
+    Drop,
+}
+
+impl ForwardPolicy {
+    fn action(&self) -> &'static str {
+        match self {
+            ForwardPolicy::Accept => "accept",
+            ForwardPolicy::Drop => "drop",
+        }
+    }
+    
+    fn verdict(&self) -> nftables::expr::Expr {
+        match self {
+            ForwardPolicy::Accept => nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ForwardPolicy::Drop => nftables::expr::Expr::Drop(nftables::expr::Drop {}),
+        }
+    }
+    
+    fn opposite(&self) -> Self {
+        match self {
+            ForwardPolicy::Accept => ForwardPolicy::Drop,
+            ForwardPolicy::Drop => ForwardPolicy::Accept,
+        }
+    }
+}
+
+// One cell of the inter-zone forwarding matrix; there is at most one entry per zone pair
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneForwarding {
+    pub id: Uuid,
+    pub from_zone: String,
+    pub to_zone: String,
+    pub policy: ForwardPolicy,
+    // When set, the policy only covers these services
+    #[serde(default)]
+    pub services: Vec<ServiceDefinition>,
+    // Masquerade traffic leaving through the destination zone
+    #[serde(default)]
+    pub masquerade: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Egress policy of a zone: the default for traffic this host sends out through the zone's
+// interfaces. The listed services are the exceptions, blocked under an accept policy and
+// allowed under a drop policy. There is at most one entry per zone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneEgress {
+    pub id: Uuid,
+    pub zone: String,
+    pub policy: ForwardPolicy,
+    #[serde(default)]
+    pub services: Vec<ServiceDefinition>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Chains a zone's dropped packets can be logged in
+pub const DROP_LOG_CHAINS: [&str; 2] = ["input", "forward"];
+
+// Start of the log prefix of dropped packets, followed by `<chain>-<zone> `; see drop_log
+pub const DROP_LOG_PREFIX: &str = "siem-drop-";
+
+// Longest log prefix nft accepts
+const MAX_LOG_PREFIX: usize = 127;
+
+// Above this many lines per second the kernel log is flooded anyway
+pub const MAX_DROP_LOG_RATE: u32 = 1000;
+
+pub fn drop_log_prefix(chain: &str, zone: &str) -> String {
+    format!("{}{}-{} ", DROP_LOG_PREFIX, chain, zone)
+}
+
+// Logging of the packets a zone's interfaces get dropped in a chain: a rate-limited log
+// rule last in the chain, right before the policy drop. There is at most one entry per zone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneDropLogging {
+    pub zone: String,
+    pub chains: Vec<String>,
+    // Drops beyond the rate are not logged, and still dropped
+    pub rate_per_second: u32,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Drop log rules added and removed by a change of a zone's logging
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DropLogChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+// Named sets of known-bad addresses, one per family, filled through set_threat_intel
+const THREAT_INTEL_SETS: [(&str, &str, &str); 2] = [
+    ("ip", "threat_intel_v4", "ipv4_addr"),
+    ("ip6", "threat_intel_v6", "ipv6_addr"),
+];
+
+// Description of the rules added by the threat intel egress preset
+const THREAT_INTEL_PRESET: &str = "preset: drop traffic to threat intel addresses";
+
+// A service of this host a zone may reach through the input chain
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SelfService {
+    Dhcp,
+    Dns,
+    Ssh,
+    // The admin UI
+    Web,
+    // The configured log ingestion listeners, see ingest_ports
+    Ingest,
+}
+
+impl SelfService {
+    fn name(&self) -> &'static str {
+        match self {
+            SelfService::Dhcp => "dhcp",
+            SelfService::Dns => "dns",
+            SelfService::Ssh => "ssh",
+            SelfService::Web => "web",
+            SelfService::Ingest => "ingest",
+        }
+    }
+    
+    // None when there is nothing to open, as no rule must match every port
+    fn expressions(&self, ingest_ports: &[u16]) -> Option<Vec<nftables::expr::Expr>> {
+        Some(match self {
+            SelfService::Dhcp => protocol_port_expressions(&["udp"], &[67]),
+            SelfService::Dns => protocol_port_expressions(&["tcp", "udp"], &[53]),
+            SelfService::Ssh => protocol_port_expressions(&["tcp"], &[22]),
+            SelfService::Web => protocol_port_expressions(&["tcp"], &[80, 443]),
+            SelfService::Ingest if ingest_ports.is_empty() => return None,
+            SelfService::Ingest => protocol_port_expressions(&["tcp"], ingest_ports),
+        })
+    }
+}
+
+// TCP ports this host takes logs on: the API, which also serves the admin UI behind its
+// proxy, and syslog over TLS when enabled
+pub fn ingest_ports(config: &Config) -> Vec<u16> {
+    let mut ports = vec![config.server_port];
+    if config.syslog_tls.enabled {
+        match config.syslog_tls.bind_address.parse::<std::net::SocketAddr>() {
+            Ok(address) => ports.push(address.port()),
+            Err(e) => warn!("Not opening syslog over TLS, invalid bind address {}: {}", config.syslog_tls.bind_address, e),
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+// Services a zone reaches until its list is set: lan gets what a client network needs
+// from its router and the log ingestion its hosts send to, wan keeps remote SSH only,
+// other zones the admin UI
+pub fn default_self_services(zone: &str) -> Vec<SelfService> {
+    match zone {
+        "lan" => vec![SelfService::Dhcp, SelfService::Dns, SelfService::Ssh, SelfService::Web, SelfService::Ingest],
+        "wan" => vec![SelfService::Ssh],
+        _ => vec![SelfService::Web],
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SelfServiceRule {
+    pub zone: String,
+    pub service: SelfService,
+}
+
+// The services of this host a zone may reach; `customized` is false while the zone
+// uses its defaults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneServices {
+    pub zone: String,
+    pub services: Vec<SelfService>,
+    pub customized: bool,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+// Input rules added and removed when zone services are regenerated
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServiceRuleChanges {
+    pub added: Vec<ManagedRule>,
+    pub removed: Vec<ManagedRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ZoneChange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DhcpChange {
+    pub from: bool,
+    pub to: bool,
+}
+
+// None is no relay, or a relay switched off
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DhcpRelayChange {
+    pub from: Option<DhcpRelay>,
+    pub to: Option<DhcpRelay>,
+}
+
+// What applying a proposed config would change on one interface
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InterfaceDiff {
+    pub name: String,
+    // Not present on the host yet, e.g. a bond created on apply
+    pub created: bool,
+    pub addresses_added: Vec<String>,
+    pub addresses_removed: Vec<String>,
+    pub zone: Option<ZoneChange>,
+    pub dhcp: Option<DhcpChange>,
+    #[serde(default)]
+    pub dhcp_relay: Option<DhcpRelayChange>,
+}
+
+impl InterfaceDiff {
+    fn is_empty(&self) -> bool {
+        !self.created && self.addresses_added.is_empty() && self.addresses_removed.is_empty()
+            && self.zone.is_none() && self.dhcp.is_none() && self.dhcp_relay.is_none()
+    }
+}
+
+// A reviewed interface config; applying it by token applies exactly this
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigPreview {
+    pub token: Uuid,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub interfaces: Vec<InterfaceConfig>,
+    pub changes: Vec<InterfaceDiff>,
+    // Zones whose input rules are generated again on apply, and the matrix entries
+    // and egress policies whose rules follow them
+    pub regenerated_zones: Vec<String>,
+    pub regenerated_forwarding: Vec<Uuid>,
+    #[serde(default)]
+    pub regenerated_egress: Vec<Uuid>,
+    pub risks: Vec<String>,
+    // Digest of the configured and live state the diff was computed from
+    #[serde(skip)]
+    state_digest: String,
+}
+
+// Unapplied previews are dropped after this
+const PREVIEW_TTL_MINUTES: i64 = 15;
+
+// Applying a preview whose underlying state has changed since it was computed
+#[derive(Debug)]
+pub struct PreviewConflict(pub Uuid);
+
+impl fmt::Display for PreviewConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interface state changed since preview {} was computed, preview again", self.0)
+    }
+}
+
+impl std::error::Error for PreviewConflict {}
+
+fn state_digest(configured: &[InterfaceConfig], live: &[InterfaceInfo]) -> String {
+    let live: Vec<(&str, BTreeSet<&str>)> = live.iter()
+        .map(|i| (i.name.as_str(), i.addresses.iter().map(|a| a.as_str()).collect()))
+        .collect();
+
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(configured).unwrap_or_default());
+    hasher.update(serde_json::to_vec(&live).unwrap_or_default());
+    hex::encode(hasher.finalize())
+}
+
+fn validate_interface_configs(proposed: &[InterfaceConfig]) -> Result<()> {
+    let mut names = BTreeSet::new();
+    for config in proposed {
+        if config.name.is_empty() || !names.insert(config.name.as_str()) {
+            return Err(anyhow::anyhow!("Interface names must be unique and not empty: {:?}", config.name));
+        }
+        if let Some(address) = &config.address {
+            if !address.contains('/') || IpNetwork::from_str(address).is_err() {
+                return Err(anyhow::anyhow!("Invalid address format, expected IP/PREFIX: {}", address));
+            }
+        }
+        if let Some(relay) = active_relay(config) {
+            validate_relay(config, relay)?;
+        }
+    }
+    Ok(())
+}
+
+// The relay answers clients from the interface's own address, so the interface needs a
+// static one
+fn validate_relay(config: &InterfaceConfig, relay: &DhcpRelay) -> Result<()> {
+    if relay.servers.is_empty() {
+        return Err(anyhow::anyhow!("DHCP relay on {} needs at least one upstream server", config.name));
+    }
+    for server in &relay.servers {
+        match server.parse::<std::net::Ipv4Addr>() {
+            Ok(ip) if !ip.is_unspecified() && !ip.is_loopback() && !ip.is_multicast() && !ip.is_broadcast() => {}
+            _ => return Err(anyhow::anyhow!("Invalid DHCP relay server for {}, expected a unicast IPv4 address: {}", config.name, server)),
+        }
+    }
+    if config.address.is_none() || config.dhcp.unwrap_or(false) {
+        return Err(anyhow::anyhow!("DHCP relay on {} needs a static address on the interface", config.name));
+    }
+    Ok(())
+}
+
+// Per-interface differences between the current and the proposed config. setup_interface
+// replaces all addresses of an interface given a static one, so every other live address
+// counts as removed. Configured interfaces missing from the proposal leave their zone.
+pub fn diff_interface_config(configured: &[InterfaceConfig],
+                             live: &[InterfaceInfo],
+                             proposed: &[InterfaceConfig]) -> Vec<InterfaceDiff> {
+    let mut diffs = Vec::new();
+
+    for config in proposed {
+        let current = configured.iter().find(|c| c.name == config.name);
+        let live = live.iter().find(|i| i.name == config.name);
+        let mut diff = InterfaceDiff {
+            name: config.name.clone(),
+            created: live.is_none(),
+            ..Default::default()
+        };
+
+        if let Some(address) = &config.address {
+            let live_addresses = live.map(|i| i.addresses.as_slice()).unwrap_or_default();
+            if !live_addresses.contains(address) {
+                diff.addresses_added.push(address.clone());
+            }
+            diff.addresses_removed = live_addresses.iter()
+                .filter(|a| *a != address)
+                .cloned()
+                .collect();
+        }
+
+        let zone = current.and_then(|c| c.nftables_zone.clone());
+        if zone != config.nftables_zone {
+            diff.zone = Some(ZoneChange { from: zone, to: config.nftables_zone.clone() });
+        }
+
+        let dhcp = current.and_then(|c| c.dhcp).unwrap_or(false);
+        if dhcp != config.dhcp.unwrap_or(false) {
+            diff.dhcp = Some(DhcpChange { from: dhcp, to: config.dhcp.unwrap_or(false) });
+        }
+
+        let relay = current.and_then(active_relay);
+        if relay != active_relay(config) {
+            diff.dhcp_relay = Some(DhcpRelayChange { from: relay.cloned(), to: active_relay(config).cloned() });
+        }
+
+        if !diff.is_empty() {
+            diffs.push(diff);
+        }
+    }
+
+    for current in configured.iter().filter(|c| !proposed.iter().any(|p| p.name == c.name)) {
+        if current.nftables_zone.is_some() || active_relay(current).is_some() {
+            diffs.push(InterfaceDiff {
+                name: current.name.clone(),
+                zone: current.nftables_zone.as_ref().map(|zone| ZoneChange { from: Some(zone.clone()), to: None }),
+                dhcp_relay: active_relay(current).map(|relay| DhcpRelayChange { from: Some(relay.clone()), to: None }),
+                ..Default::default()
+            });
+        }
+    }
+
+    diffs
+}
+
+// Risk notes for changes to the interfaces carrying the management connection
+fn management_risks(changes: &[InterfaceDiff], management: &[String]) -> Vec<String> {
+    changes.iter()
+        .filter(|change| management.contains(&change.name))
+        .filter_map(|change| {
+            let mut what = Vec::new();
+            if !change.addresses_removed.is_empty() {
+                what.push(format!("removes {}", change.addresses_removed.join(", ")));
+            }
+            if let Some(zone) = &change.zone {
+                what.push(format!("moves it from zone {} to {}",
+                    zone.from.as_deref().unwrap_or("none"), zone.to.as_deref().unwrap_or("none")));
+            }
+            if change.dhcp.is_some() {
+                what.push("switches DHCP".to_string());
+            }
+            if what.is_empty() {
+                return None;
+            }
+            Some(format!("{} carries the management connection; the change {}, which may cut off access to this console",
+                change.name, what.join(" and ")))
+        })
+        .collect()
+}
+
+// Link state changes of one interface, as seen by record_link_states
+#[derive(Debug, Clone)]
+struct LinkHistory {
+    is_up: bool,
+    changes: Vec<DateTime<Utc>>,
+}
+
+// Interface whose link changed state repeatedly within a period
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkFlap {
+    pub interface: String,
+    pub changes: usize,
+    pub is_up: bool,
+}
+
+// Link changes are kept this long
+const LINK_HISTORY_HOURS: i64 = 48;
+
+pub struct NetworkManager {
+    // None when the manager stands in for one that failed to start, see unavailable
+    netlink_handle: Option<Handle>,
+    interfaces: Arc<Mutex<Vec<InterfaceConfig>>>,
+    base_ruleset: Mutex<nftables::Batch>,
+    nftables_handle: Mutex<nftables::Batch>,
+    managed_rules: Mutex<ManagedRules>,
+    staged: Mutex<HashMap<Uuid, StagedChangeset>>,
+    forwarding: Mutex<Vec<ZoneForwarding>>,
+    egress: Mutex<Vec<ZoneEgress>>,
+    drop_logging: Mutex<Vec<ZoneDropLogging>>,
+    threat_intel: Mutex<Vec<String>>,
+    // Zones whose service list was set, the others use default_self_services
+    zone_services: Mutex<HashMap<String, ZoneServices>>,
+    // Opened by the ingest service, see ingest_ports
+    ingest_ports: Mutex<Vec<u16>>,
+    previews: Mutex<HashMap<Uuid, ConfigPreview>>,
+    link_history: Mutex<HashMap<String, LinkHistory>>,
+}
+
+impl NetworkManager {
+    pub async fn new() -> Result<Self> {
+        let (connection, handle, _) = new_connection()
+            .context("Failed to create netlink connection")?;
+        
+        // Spawn a task to drive the netlink connection
+        tokio::spawn(connection);
+        
+        Ok(Self::with_handle(Some(handle)))
+    }
+
+    // Stand-in while the network subsystem is disabled, see startup: the managers holding
+    // a NetworkManager still start, and everything touching the host's links fails
+    pub fn unavailable() -> Self {
+        Self::with_handle(None)
+    }
+
+    fn with_handle(netlink_handle: Option<Handle>) -> Self {
+        Self {
+            netlink_handle,
+            interfaces: Arc::new(Mutex::new(Vec::new())),
+            base_ruleset: Mutex::new(nftables::Batch::new()),
+            nftables_handle: Mutex::new(nftables::Batch::new()),
+            managed_rules: Mutex::new(ManagedRules {
+                rules: Vec::new(),
+                next_handle: 1,
+            }),
+            staged: Mutex::new(HashMap::new()),
+            forwarding: Mutex::new(Vec::new()),
+            egress: Mutex::new(Vec::new()),
+            drop_logging: Mutex::new(Vec::new()),
+            threat_intel: Mutex::new(Vec::new()),
+            zone_services: Mutex::new(HashMap::new()),
+            ingest_ports: Mutex::new(Vec::new()),
+            previews: Mutex::new(HashMap::new()),
+            link_history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn netlink(&self) -> Result<&Handle> {
+        self.netlink_handle.as_ref().ok_or_else(|| anyhow::anyhow!("Network management is unavailable, netlink could not be opened at startup"))
+    }
+    
+    pub async fn load_config(&self, interfaces: Vec<InterfaceConfig>) -> Result<()> {
+        let mut ifaces = self.interfaces.lock().await;
+        *ifaces = interfaces;
+        Ok(())
+    }
+    
+    pub async fn interface_configs(&self) -> Vec<InterfaceConfig> {
+        self.interfaces.lock().await.clone()
+    }
+    
+    // Ports of the ingest service; applies at the next initialize_nftables
+    pub async fn set_ingest_ports(&self, ports: Vec<u16>) {
+        *self.ingest_ports.lock().await = ports;
+    }
+    
+    // Regenerates the base ruleset and the zone service rules, returning the service rules
+    // that changed
+    pub async fn initialize_nftables(&self, firewall: &FirewallConfig) -> Result<ServiceRuleChanges> {
+        info!("Initializing nftables configuration");
+        
+        // Create a new batch for nftables commands
+        let mut batch = nftables::Batch::new();
+        
+        // Flush all existing rules to start fresh
+        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Table {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            name: "filter".to_string(),
+        }), None);
+        
+        // Create a new filter table
+        batch.add(&nftables::Stmt::AddTable(nftables::objects::AddTable {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            name: "filter".to_string(),
+        }), None);
+        
+        // Create basic chains
+        let chains = vec![
+            ("input", "type filter hook input priority 0; policy drop;"),
+            ("forward", "type filter hook forward priority 0; policy drop;"),
+            ("output", "type filter hook output priority 0; policy accept;"),
+        ];
+        
+        for (chain_name, chain_policy) in chains {
+            batch.add(&nftables::Stmt::AddChain(nftables::objects::AddChain {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "filter".to_string(),
+                name: chain_name.to_string(),
+                handle: None,
+                constraint: Some(chain_policy.to_string()),
+            }), None);
+        }
+        
+        // NAT table; its postrouting chain only holds the matrix masquerade rules
+        batch.add(&nftables::Stmt::AddTable(nftables::objects::AddTable {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            name: "nat".to_string(),
+        }), None);
+        
+        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Table {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            name: "nat".to_string(),
+        }), None);
+        
+        batch.add(&nftables::Stmt::AddChain(nftables::objects::AddChain {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "nat".to_string(),
+            name: "postrouting".to_string(),
+            handle: None,
+            constraint: Some("type nat hook postrouting priority 100; policy accept;".to_string()),
+        }), None);
+        
+        // Allow established connections
+        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "filter".to_string(),
+            chain: "input".to_string(),
+            handle: None,
+            index: None,
+            expr: vec![
+                nftables::expr::Expr::Match(nftables::expr::Match {
+                    op: "ct".to_string(),
+                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
+                        op: "state".to_string(),
+                        data: nftables::expr::Data::Set(vec![
+                            "established".to_string(),
+                            "related".to_string(),
+                        ]),
+                    })),
+                }),
+                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ],
+        }), None);
+        
+        // Allow loopback
+        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "filter".to_string(),
+            chain: "input".to_string(),
+            handle: None,
+            index: None,
+            expr: vec![
+                nftables::expr::Expr::Match(nftables::expr::Match {
+                    op: "meta".to_string(),
+                    expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
+                        op: "iifname".to_string(),
+                        data: nftables::expr::Data::StrVal("lo".to_string()),
+                    })),
+                }),
+                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ],
+        }), None);
+        
+        // Add zone-specific rules based on interface configuration
+        let ifaces = self.interfaces.lock().await;
+        
+        // Collect interfaces by zone
+        let mut zone_interfaces: HashMap<String, Vec<String>> = HashMap::new();
+        
+        for iface in ifaces.iter() {
+            if let Some(zone) = &iface.nftables_zone {
+                zone_interfaces
+                    .entry(zone.clone())
+                    .or_insert_with(Vec::new)
+                    .push(iface.name.clone());
+            }
+        }
+        
+        // ICMPv6 neighbor and router discovery, ICMP echo when enabled
+        let lan_interfaces = zone_interfaces.get("lan").cloned().unwrap_or_default();
+        for rule in icmp_base_rules(&lan_interfaces, firewall) {
+            batch.add(&rule, None);
+        }
+        
+        // Execute the batch
+        drop(ifaces);
+        *self.base_ruleset.lock().await = batch;
+        
+        // Services of this host each zone may reach, and the DHCP relays, as managed rules
+        let mut changes = self.sync_self_service_rules().await;
+        let relay_changes = self.sync_dhcp_relay_rules().await;
+        changes.added.extend(relay_changes.added);
+        changes.removed.extend(relay_changes.removed);
+        self.rebuild_ruleset().await;
+        
+        // In a real environment, we would execute:
+        // batch.execute().context("Failed to execute nftables rules")?;
+        // But in this implementation, we'll just log
+        info!("nftables rules configured successfully");
+        
+        Ok(changes)
+    }
+    
+    // Notes link state changes since the previous sample; called periodically by the
+    // link monitor task
+    pub async fn record_link_states(&self, interfaces: &[InterfaceInfo]) {
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::hours(LINK_HISTORY_HOURS);
+        let mut history = self.link_history.lock().await;
+
+        for interface in interfaces {
+            let entry = history.entry(interface.name.clone()).or_insert(LinkHistory {
+                is_up: interface.is_up,
+                changes: Vec::new(),
+            });
+            if entry.is_up != interface.is_up {
+                entry.is_up = interface.is_up;
+                entry.changes.push(now);
+            }
+            entry.changes.retain(|at| *at >= cutoff);
+        }
+    }
+
+    // Interfaces that went down and up (or back) at least once since `from`
+    pub async fn link_flaps_since(&self, from: DateTime<Utc>) -> Vec<LinkFlap> {
+        let history = self.link_history.lock().await;
+        let mut flaps: Vec<LinkFlap> = history.iter()
+            .map(|(name, h)| LinkFlap {
+                interface: name.clone(),
+                changes: h.changes.iter().filter(|at| **at >= from).count(),
+                is_up: h.is_up,
+            })
+            .filter(|f| f.changes >= 2)
+            .collect();
+        flaps.sort_by(|a, b| b.changes.cmp(&a.changes).then(a.interface.cmp(&b.interface)));
+        flaps
+    }
+
+    pub async fn get_interfaces(&self) -> Result<Vec<InterfaceInfo>> {
+        let mut links = self.netlink()?.link().get().execute();
+        let mut interfaces = Vec::new();
+        // Interface index, controller index, and bond mode / active port index for bonds
+        let mut link_meta = Vec::new();
+        
+        while let Some(link) = links.try_next().await? {
+            let name = link.attributes.iter()
+                .find_map(|attr| {
+                    if let rtnetlink::packet::link::LinkAttribute::IfName(name) = attr {
+                        Some(name.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default();
+                
+            let mut interface = InterfaceInfo {
+                name,
+                addresses: Vec::new(),
+                is_up: false,
+                mac_address: String::new(),
+                bond_master: None,
+                bond: None,
+                metadata: None,
+                flapping: false,
+                dhcp_relay: None,
+            };
+            
+            // Check if the interface is up
+            if let Some(rtnetlink::packet::link::LinkAttribute::OperState(state)) = link.attributes.iter()
+                .find(|attr| matches!(attr, rtnetlink::packet::link::LinkAttribute::OperState(_))) {
+                interface.is_up = *state == rtnetlink::packet::link::State::Up;
+            }
+            
+            // Get MAC address
+            if let Some(rtnetlink::packet::link::LinkAttribute::Address(addr)) = link.attributes.iter()
+                .find(|attr| matches!(attr, rtnetlink::packet::link::LinkAttribute::Address(_))) {
+                interface.mac_address = addr.iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<String>>()
+                    .join(":");
+            }
+            
+            let controller = link.attributes.iter()
+                .find_map(|attr| match attr {
+                    rtnetlink::packet::link::LinkAttribute::Controller(index) => Some(*index),
+                    _ => None,
+                });
+            
+            // Bond kind, mode and active port from the link info
+            let mut bond = None;
+            if let Some(rtnetlink::packet::link::LinkAttribute::LinkInfo(infos)) = link.attributes.iter()
+                .find(|attr| matches!(attr, rtnetlink::packet::link::LinkAttribute::LinkInfo(_))) {
+                if infos.iter().any(|i| matches!(i, rtnetlink::packet::link::LinkInfo::Kind(rtnetlink::packet::link::InfoKind::Bond))) {
+                    let mut mode = None;
+                    let mut active_port = None;
+                    
+                    for info in infos {
+                        if let rtnetlink::packet::link::LinkInfo::Data(rtnetlink::packet::link::InfoData::Bond(attrs)) = info {
+                            for attr in attrs {
+                                match attr {
+                                    rtnetlink::packet::link::InfoBond::Mode(m) => mode = BondMode::from_netlink(m),
+                                    rtnetlink::packet::link::InfoBond::ActivePort(index) => active_port = Some(*index),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    
+                    bond = Some((mode, active_port));
+                }
+            }
+            
+            link_meta.push((link.header.index, controller, bond));
+            interfaces.push(interface);
+        }
+        
+        let names: HashMap<u32, String> = link_meta.iter()
+            .zip(interfaces.iter())
+            .map(|((index, _, _), interface)| (*index, interface.name.clone()))
+            .collect();
+        
+        // Resolve bond membership now that every interface is known
+        let snapshot = interfaces.clone();
+        for (interface, (index, controller, bond)) in interfaces.iter_mut().zip(link_meta.iter()) {
+            interface.bond_master = controller.and_then(|c| names.get(&c).cloned());
+            
+            if let Some((mode, active_port)) = bond {
+                let members = link_meta.iter()
+                    .zip(snapshot.iter())
+                    .filter(|((_, member_controller, _), _)| *member_controller == Some(*index))
+                    .map(|(_, member)| BondMemberStatus {
+                        name: member.name.clone(),
+                        is_up: member.is_up,
+                    })
+                    .collect();
+                
+                interface.bond = Some(BondStatus {
+                    mode: *mode,
+                    members,
+                    active_member: active_port.and_then(|p| names.get(&p).cloned()),
+                });
+            }
+        }
+        
+        // Get IP addresses for all interfaces
+        let mut addresses = self.netlink()?.address().get().execute();
+        while let Some(addr) = addresses.try_next().await? {
+            let if_index = addr.header.index;
+            
+            // Find the interface with this index
+            let position = link_meta.iter().position(|(index, _, _)| *index == if_index);
+            if let Some(interface) = position.and_then(|p| interfaces.get_mut(p)) {
+                if let Some(rtnetlink::packet::address::AddressAttribute::Address(ip)) = addr.attributes.iter()
+                    .find(|attr| matches!(attr, rtnetlink::packet::address::AddressAttribute::Address(_))) {
+                    let mut addr_str = format!("{}", ip);
+                    
+                    // Add prefix length
+                    if let Some(rtnetlink::packet::address::AddressAttribute::PrefixLen(prefix)) = addr.attributes.iter()
+                        .find(|attr| matches!(attr, rtnetlink::packet::address::AddressAttribute::PrefixLen(_))) {
+                        addr_str.push_str(&format!("/{}", prefix));
+                    }
+                    
+                    interface.addresses.push(addr_str);
+                }
+            }
+        }
+        
+        Ok(interfaces)
+    }
+    
+    pub async fn get_bonds(&self) -> Result<Vec<InterfaceInfo>> {
+        Ok(self.get_interfaces().await?
+            .into_iter()
+            .filter(|i| i.bond.is_some())
+            .collect())
+    }
+    
+    pub async fn create_bond(&self, name: &str, bond: &BondConfig) -> Result<InterfaceInfo> {
+        if bond.members.is_empty() {
+            return Err(anyhow::anyhow!("A bond needs at least one member interface"));
+        }
+        
+        let interfaces = self.get_interfaces().await?;
+        if interfaces.iter().any(|i| i.name == name) {
+            return Err(anyhow::anyhow!("Interface already exists: {}", name));
+        }
+        
+        for member in &bond.members {
+            let info = interfaces.iter()
+                .find(|i| i.name == *member)
+                .ok_or_else(|| anyhow::anyhow!("Member interface not found: {}", member))?;
+            
+            if info.bond.is_some() {
+                return Err(anyhow::anyhow!("Interface {} is a bond and cannot be a member", member));
+            }
+            if let Some(master) = &info.bond_master {
+                return Err(anyhow::anyhow!("Interface {} is already enslaved to {}", member, master));
+            }
+            if info.is_up && !bond.bounce {
+                return Err(anyhow::anyhow!("Interface {} is up, take it down first or set bounce", member));
+            }
+        }
+        
+        self.netlink()?.link()
+            .add()
+            .bond(name.to_string())
+            .mode(bond.mode.to_netlink())
+            .execute()
+            .await
+            .context(format!("Failed to create bond {}", name))?;
+        
+        let bond_index = self.get_interface_index(name).await?;
+        
+        if let Err(e) = self.enslave_members(bond_index, &bond.members).await {
+            // Deleting the bond releases any members enslaved so far
+            if let Err(del) = self.netlink()?.link().del(bond_index).execute().await {
+                error!("Failed to remove bond {} after a failed setup: {}", name, del);
+            }
+            return Err(e.context(format!("Failed to enslave members of bond {}", name)));
+        }
+        
+        self.netlink()?.link()
+            .set(bond_index)
+            .up()
+            .execute()
+            .await?;
+        
+        info!("Created bond {} ({:?}) with members {:?}", name, bond.mode, bond.members);
+        
+        self.get_interfaces().await?
+            .into_iter()
+            .find(|i| i.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Interface not found: {}", name))
+    }
+    
+    async fn enslave_members(&self, bond_index: u32, members: &[String]) -> Result<()> {
+        for member in members {
+            let index = self.get_interface_index(member).await?;
+            
+            // The kernel only enslaves interfaces that are down
+            self.netlink()?.link().set(index).down().execute().await?;
+            self.netlink()?.link().set(index).controller(bond_index).execute().await?;
+            self.netlink()?.link().set(index).up().execute().await?;
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn delete_bond(&self, name: &str) -> Result<()> {
+        let bond = self.get_interfaces().await?
+            .into_iter()
+            .find(|i| i.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Interface not found: {}", name))?;
+        
+        let status = bond.bond
+            .ok_or_else(|| anyhow::anyhow!("Interface {} is not a bond", name))?;
+        
+        let index = self.get_interface_index(name).await?;
+        self.netlink()?.link()
+            .del(index)
+            .execute()
+            .await
+            .context(format!("Failed to delete bond {}", name))?;
+        
+        // Released members stay down, bring them back as standalone interfaces
+        for member in &status.members {
+            match self.get_interface_index(&member.name).await {
+                Ok(member_index) => {
+                    if let Err(e) = self.netlink()?.link().set(member_index).up().execute().await {
+                        warn!("Failed to bring up released member {}: {}", member.name, e);
+                    }
+                },
+                Err(e) => warn!("Released member {} not found: {}", member.name, e),
+            }
+        }
+        
+        info!("Deleted bond {}", name);
+        Ok(())
+    }
+    
+    async fn get_interface_index(&self, name: &str) -> Result<u32> {
+        let mut links = self.netlink()?.link().get().match_name(name.to_string()).execute();
+        if let Some(link) = links.try_next().await? {
+            Ok(link.header.index)
+        } else {
+            Err(anyhow::anyhow!("Interface not found: {}", name))
+        }
+    }
+    
+    pub async fn setup_interface(&self, config: &InterfaceConfig) -> Result<()> {
+        info!("Setting up interface: {}", config.name);
+        
+        if let Some(bond) = &config.bond {
+            if self.get_interface_index(&config.name).await.is_err() {
+                self.create_bond(&config.name, bond).await?;
+            }
+        }
+        
+        let if_index = self.get_interface_index(&config.name).await?;
+        
+        // Set interface up
+        self.netlink()?.link()
+            .set(if_index)
+            .up()
+            .execute()
+            .await?;
+            
+        // Configure address if specified
+        if let Some(addr) = &config.address {
+            // Parse IP address
+            let addr_parts: Vec<&str> = addr.split('/').collect();
+            if addr_parts.len() != 2 {
+                return Err(anyhow::anyhow!("Invalid address format, expected IP/PREFIX: {}", addr));
+            }
+            
+            let ip_addr = addr_parts[0].parse()
+                .context(format!("Invalid IP address: {}", addr_parts[0]))?;
+            
+            let prefix_len: u8 = addr_parts[1].parse()
+                .context(format!("Invalid prefix length: {}", addr_parts[1]))?;
+            
+            // First delete any existing addresses
+            let mut addresses = self.netlink()?.address().get()
+                .set_link_index_filter(if_index)
+                .execute();
+                
+            while let Some(existing_addr) = addresses.try_next().await? {
+                self.netlink()?.address().del(existing_addr).execute().await?;
+            }
+            
+            // Add the new address
+            self.netlink()?.address()
+                .add(if_index, ip_addr, prefix_len, IpVersion::V4)
+                .execute()
+                .await?;
+                
+            info!("Configured address {} on interface {}", addr, config.name);
+        }
+        
+        Ok(())
+    }
+    
+    // Computes what applying the proposed interface config would change. The management
+    // address is the local address the client reached this host on, when known.
+    pub async fn preview_config(&self,
+                                proposed: Vec<InterfaceConfig>,
+                                management_address: Option<IpAddr>,
+                                created_by: &str) -> Result<ConfigPreview> {
+        validate_interface_configs(&proposed)?;
+        for config in &proposed {
+            if active_relay(config).is_some() {
+                self.check_not_serving_dhcp(config).await?;
+            }
+        }
+        
+        let configured = self.interfaces.lock().await.clone();
+        let live = self.get_interfaces().await?;
+        let changes = diff_interface_config(&configured, &live, &proposed);
+        
+        let regenerated_zones: Vec<String> = changes.iter()
+            .filter_map(|c| c.zone.as_ref())
+            .flat_map(|z| z.from.iter().chain(z.to.iter()).cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let regenerated_forwarding = self.forwarding.lock().await.iter()
+            .filter(|f| regenerated_zones.contains(&f.from_zone) || regenerated_zones.contains(&f.to_zone))
+            .map(|f| f.id)
+            .collect();
+        let regenerated_egress = self.egress.lock().await.iter()
+            .filter(|e| regenerated_zones.contains(&e.zone))
+            .map(|e| e.id)
+            .collect();
+        
+        let management: Vec<String> = live.iter()
+            .filter(|i| i.addresses.iter()
+                .any(|a| management_address.map_or(false, |m| a.split('/').next() == Some(m.to_string().as_str()))))
+            .map(|i| i.name.clone())
+            .collect();
+        
+        let now = Utc::now();
+        let preview = ConfigPreview {
+            token: Uuid::new_v4(),
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: now + chrono::Duration::minutes(PREVIEW_TTL_MINUTES),
+            interfaces: proposed,
+            risks: management_risks(&changes, &management),
+            changes,
+            regenerated_zones,
+            regenerated_forwarding,
+            regenerated_egress,
+            state_digest: state_digest(&configured, &live),
+        };
+        
+        let mut previews = self.previews.lock().await;
+        previews.retain(|_, p| p.expires_at > now);
+        previews.insert(preview.token, preview.clone());
+        
+        info!("Interface config preview {} by {}: {} interfaces change", preview.token, created_by, preview.changes.len());
+        Ok(preview)
+    }
+    
+    // Applies a preview exactly as computed; fails with PreviewConflict if the configured or
+    // live state moved on in between. A preview is used up by an apply attempt either way.
+    // Applies a preview, returning it with the zone service rules its zone changes moved
+    pub async fn apply_config_preview(&self, token: Uuid, firewall: &FirewallConfig) -> Result<(ConfigPreview, ServiceRuleChanges)> {
+        let preview = self.previews.lock().await.remove(&token)
+            .filter(|p| p.expires_at > Utc::now())
+            .ok_or_else(|| anyhow::anyhow!("Preview not found or expired: {}", token))?;
+        
+        let configured = self.interfaces.lock().await.clone();
+        let live = self.get_interfaces().await?;
+        if state_digest(&configured, &live) != preview.state_digest {
+            return Err(PreviewConflict(token).into());
+        }
+        
+        let changed: BTreeSet<&str> = preview.changes.iter().map(|c| c.name.as_str()).collect();
+        for config in preview.interfaces.iter().filter(|c| changed.contains(c.name.as_str())) {
+            self.setup_interface(config).await?;
+        }
+        
+        self.load_config(preview.interfaces.clone()).await?;
+        let mut changes = if preview.regenerated_zones.is_empty() {
+            ServiceRuleChanges::default()
+        } else {
+            self.initialize_nftables(firewall).await?
+        };
+        let relay_changes = self.sync_dhcp_relay_rules().await;
+        if !relay_changes.added.is_empty() || !relay_changes.removed.is_empty() {
+            self.rebuild_ruleset().await;
+            changes.added.extend(relay_changes.added);
+            changes.removed.extend(relay_changes.removed);
+        }
+        
+        info!("Applied interface config preview {}", token);
+        Ok((preview, changes))
+    }
+    
+    pub async fn get_nftables_rules(&self) -> Vec<String> {
+        // In a real implementation, we would use the nft list ruleset command
+        // For now, we'll return the rules as they are stored in our batch
+        
+        // Try to execute 'nft list ruleset' command if nftables is installed
+        match Command::new("nft")
+            .arg("list")
+            .arg("ruleset")
+            .output() {
+                Ok(output) if output.status.success() => {
+                    let rules_str = String::from_utf8_lossy(&output.stdout);
+                    rules_str.lines().map(|s| s.to_string()).collect()
+                },
+                _ => {
+                    // Fallback to our stored rules if nft command fails
+                    self.nftables_handle.lock().await.commands()
+                }
+            }
+    }
+    
+    // Regenerates the effective ruleset from the base rules plus all managed rules
+    async fn rebuild_ruleset(&self) {
+        let mut batch = self.base_ruleset.lock().await.clone();
+        
+        self.add_threat_intel_sets(&mut batch).await;
+        
+        for rule in self.managed_rules.lock().await.rules.iter().filter(|r| r.chain != "forward" && r.chain != "output") {
+            batch.add(&rule.to_stmt(), Some(&rule.description));
+        }
+        
+        for (zone, stmt) in self.drop_log_rules("input").await {
+            batch.add(&stmt, Some(&format!("drop log: input {}", zone)));
+        }
+        
+        self.add_output_chain(&mut batch).await;
+        self.add_forward_chain(&mut batch).await;
+        
+        // In a real environment, we would execute:
+        // batch.execute().context("Failed to apply firewall rules")?;
+        *self.nftables_handle.lock().await = batch;
+    }
+    
+    // Adds the rules at the position, or last in the default band; several rules stay
+    // together in the order given
+    async fn add_managed_rules(&self,
+                               rules: Vec<(String, Vec<nftables::expr::Expr>, String)>,
+                               group: Option<Uuid>,
+                               position: Option<RulePosition>) -> Result<Vec<ManagedRule>> {
+        let mut added: Vec<ManagedRule> = Vec::new();
+        
+        {
+            let mut managed = self.managed_rules.lock().await;
+            // Placed on a copy, so an invalid position leaves the rules untouched
+            let mut ordered = managed.rules.clone();
+            let mut next_handle = managed.next_handle;
+            let position = position.unwrap_or(RulePosition::Priority(DEFAULT_RULE_PRIORITY));
+            
+            for (chain, expr, description) in rules {
+                let mut rule = ManagedRule {
+                    handle: next_handle,
+                    chain,
+                    rule: String::new(),
+                    description,
+                    group,
+                    forwarding: None,
+                    egress: None,
+                    self_service: None,
+                    dhcp_relay: None,
+                    created_at: Utc::now(),
+                    priority: DEFAULT_RULE_PRIORITY,
+                    expr,
+                };
+                rule.rule = rule.to_stmt().to_string();
+                
+                // Later rules follow the previous one, or its band in another chain
+                let position = match added.last() {
+                    Some(previous) if previous.chain == rule.chain => RulePosition::After(previous.handle),
+                    Some(previous) => RulePosition::Priority(previous.priority),
+                    None => position.clone(),
+                };
+                
+                next_handle += 1;
+                let index = place_rule(&mut ordered, rule, &position)?;
+                added.push(ordered[index].clone());
+            }
+            
+            managed.rules = ordered;
+            managed.next_handle = next_handle;
+        }
+        
+        self.rebuild_ruleset().await;
+        Ok(added)
+    }
+    
+    // Rules added through the API within the range that satisfy the predicate, newest first
+    pub async fn managed_rules_between<F>(&self,
+                                          from: Option<DateTime<Utc>>,
+                                          to: Option<DateTime<Utc>>,
+                                          limit: usize,
+                                          predicate: F) -> Vec<ManagedRule>
+    where
+        F: Fn(&ManagedRule) -> bool,
+    {
+        self.managed_rules.lock().await.rules.iter()
+            .rev()
+            .filter(|r| from.map_or(true, |from| r.created_at >= from)
+                && to.map_or(true, |to| r.created_at <= to)
+                && predicate(r))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+    
+    // Rules added through the API followed by the rules generated from the forwarding
+    // matrix and the egress policies
+    pub async fn get_managed_rules(&self) -> Vec<ManagedRule> {
+        let mut rules = self.managed_rules.lock().await.rules.clone();
+        rules.extend(self.forwarding_rules().await);
+        rules.extend(self.egress_rules().await);
+        rules
+    }
+    
+    pub async fn add_firewall_rule(&self, 
+                                   chain: &str, 
+                                   protocol: &str, 
+                                   port: Option<u16>, 
+                                   selectors: &RuleSelectors, 
+                                   action: &str,
+                                   position: Option<RulePosition>) -> Result<ManagedRule> {
+        info!("Adding firewall rule: chain={}, protocol={}, port={:?}, selectors={:?}, action={}",
+              chain, protocol, port, selectors, action);
+        
+        let protocols: Vec<&str> = if !protocol.is_empty() && protocol != "any" {
+            vec![protocol]
+        } else {
+            Vec::new()
+        };
+        
+        if port.is_some() && protocols.is_empty() {
+            return Err(anyhow::anyhow!("A port requires a protocol"));
+        }
+        
+        check_rule_family(&selectors.addresses(), &protocols)?;
+        
+        let ports: Vec<u16> = port.into_iter().collect();
+        let description = format!("{} {}{}", action, protocol,
+                                  port.map(|p| format!("/{}", p)).unwrap_or_default());
+        
+        self.add_expression_rule(chain, protocol_port_expressions(&protocols, &ports), selectors, action, description, position).await
+    }
+    
+    pub async fn add_service_rule(&self,
+                                  chain: &str,
+                                  service: &ServiceDefinition,
+                                  selectors: &RuleSelectors,
+                                  action: &str,
+                                  position: Option<RulePosition>) -> Result<ManagedRule> {
+        info!("Adding firewall rule: chain={}, service={}, selectors={:?}, action={}",
+              chain, service.name, selectors, action);
+        
+        let protocols = service.protocol.nft_names();
+        check_rule_family(&selectors.addresses(), &protocols)?;
+        
+        let expressions = protocol_port_expressions(&protocols, &service.ports);
+        let description = format!("{} service {}", action, service.name);
+        
+        self.add_expression_rule(chain, expressions, selectors, action, description, position).await
+    }
+    
+    async fn add_expression_rule(&self,
+                                 chain: &str,
+                                 expressions: Vec<nftables::expr::Expr>,
+                                 selectors: &RuleSelectors,
+                                 action: &str,
+                                 description: String,
+                                 position: Option<RulePosition>) -> Result<ManagedRule> {
+        check_selectors(chain, None, selectors)?;
+        
+        // Interface and address matchers go first, then the protocol
+        let mut expressions = [selectors.expressions()?, expressions].concat();
+        
+        // Add counter
+        expressions.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+        
+        // Add action (accept or drop)
+        expressions.push(action_expr(action)?);
+        
+        let rule = self.add_managed_rules(vec![(chain.to_string(), expressions, description)], None, position).await?
+            .remove(0);
+        
+        info!("Firewall rule added successfully with handle {}", rule.handle);
+        Ok(rule)
+    }
+    
+    async fn zone_interfaces(&self, zone: &str) -> Vec<String> {
+        self.interfaces.lock().await.iter()
+            .filter(|iface| iface.nftables_zone.as_deref() == Some(zone))
+            .map(|iface| iface.name.clone())
+            .collect()
+    }
+    
+    // Generates the rules allowing a service from one zone to another. The "self" zone means
+    // traffic to this box (input chain); any other destination zone uses the forward chain.
+    pub async fn apply_service_template(&self,
+                                        service: &ServiceDefinition,
+                                        from_zone: &str,
+                                        to_zone: &str,
+                                        action: &str) -> Result<RuleGroup> {
+        let from_ifaces = self.zone_interfaces(from_zone).await;
+        if from_ifaces.is_empty() {
+            return Err(anyhow::anyhow!("No interfaces assigned to zone: {}", from_zone));
+        }
+        
+        let mut expressions = vec![match_expr("meta", "iifname", set_or_value(from_ifaces))];
+        
+        let chain = if to_zone == "self" {
+            "input"
+        } else {
+            let to_ifaces = self.zone_interfaces(to_zone).await;
+            if to_ifaces.is_empty() {
+                return Err(anyhow::anyhow!("No interfaces assigned to zone: {}", to_zone));
+            }
+            expressions.push(match_expr("meta", "oifname", set_or_value(to_ifaces)));
+            "forward"
+        };
+        
+        expressions.extend(protocol_port_expressions(&service.protocol.nft_names(), &service.ports));
+        expressions.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+        expressions.push(action_expr(action)?);
+        
+        let group = Uuid::new_v4();
+        let description = format!("template: {} service {} from {} to {}", action, service.name, from_zone, to_zone);
+        let rules = self.add_managed_rules(vec![(chain.to_string(), expressions, description)], Some(group), None).await?;
+        
+        info!("Applied service template {} ({} rules)", group, rules.len());
+        Ok(RuleGroup { id: group, rules })
+    }
+    
+    pub async fn remove_rule_group(&self, group: Uuid) -> Result<usize> {
+        let removed = {
+            let mut managed = self.managed_rules.lock().await;
+            let before = managed.rules.len();
+            managed.rules.retain(|r| r.group != Some(group));
+            before - managed.rules.len()
+        };
+        
+        if removed == 0 {
+            return Err(anyhow::anyhow!("Rule group not found: {}", group));
+        }
+        
+        self.rebuild_ruleset().await;
+        
+        info!("Removed {} rules of group {}", removed, group);
+        Ok(removed)
+    }
+    
+    // Validates the specs and stores them as a changeset awaiting confirmation
+    pub async fn stage_rules(&self, description: String, created_by: String, specs: Vec<RuleSpec>) -> Result<StagedChangeset> {
+        if specs.is_empty() {
+            return Err(anyhow::anyhow!("Nothing to stage"));
+        }
+        
+        let mut rules = Vec::new();
+        for spec in specs {
+            let rendered = spec.render()?;
+            rules.push(StagedRule { spec, rendered });
+        }
+        
+        let changeset = StagedChangeset {
+            id: Uuid::new_v4(),
+            description,
+            created_by,
+            created_at: Utc::now(),
+            rules,
+            moves: Vec::new(),
+            restore: None,
+            comments: Vec::new(),
+        };
+        
+        self.staged.lock().await.insert(changeset.id, changeset.clone());
+        
+        info!("Staged firewall changeset {} with {} rules", changeset.id, changeset.rules.len());
+        Ok(changeset)
+    }
+    
+    // Stages moving a rule, with the chain order it would result in; the move happens when
+    // the changeset is applied
+    pub async fn stage_move(&self, handle: u32, position: RulePosition, created_by: String) -> Result<StagedChangeset> {
+        let (chain, order) = {
+            let managed = self.managed_rules.lock().await;
+            let mut rules = managed.rules.clone();
+            move_rule(&mut rules, handle, &position)?;
+            let chain = rules.iter().find(|r| r.handle == handle).map(|r| r.chain.clone()).unwrap_or_default();
+            let order = chain_order(&rules, &chain);
+            (chain, order)
+        };
+        
+        let changeset = StagedChangeset {
+            id: Uuid::new_v4(),
+            description: format!("move rule {} {:?}", handle, position),
+            created_by,
+            created_at: Utc::now(),
+            rules: Vec::new(),
+            moves: vec![StagedMove { handle, position, chain, order }],
+            restore: None,
+            comments: Vec::new(),
+        };
+        
+        self.staged.lock().await.insert(changeset.id, changeset.clone());
+        
+        info!("Staged firewall changeset {} moving rule {}", changeset.id, handle);
+        Ok(changeset)
+    }
+    
+    // Applies the moves to a copy and swaps it in, then regenerates the ruleset once,
+    // so the chain changes in a single step or not at all
+    async fn move_rules(&self, moves: &[StagedMove]) -> Result<Vec<ManagedRule>> {
+        {
+            let mut managed = self.managed_rules.lock().await;
+            let mut rules = managed.rules.clone();
+            for staged in moves {
+                move_rule(&mut rules, staged.handle, &staged.position)?;
+            }
+            managed.rules = rules;
+        }
+        
+        self.rebuild_ruleset().await;
+        
+        let managed = self.managed_rules.lock().await;
+        Ok(moves.iter()
+            .filter_map(|m| managed.rules.iter().find(|r| r.handle == m.handle).cloned())
+            .collect())
+    }
+    
+    // Stages putting the model of a backup back; applied like any other changeset
+    pub async fn stage_restore(&self, backup_id: Uuid, backup_created_at: DateTime<Utc>, model: FirewallModel, created_by: String) -> Result<StagedChangeset> {
+        let changeset = StagedChangeset {
+            id: Uuid::new_v4(),
+            description: format!("restore firewall backup {} from {} ({} managed rules)",
+                                 backup_id, backup_created_at.to_rfc3339(), model.managed_rules.len()),
+            created_by,
+            created_at: Utc::now(),
+            rules: Vec::new(),
+            moves: Vec::new(),
+            restore: Some(StagedRestore { backup_id: Some(backup_id), backup_created_at, model }),
+            comments: Vec::new(),
+        };
+        
+        self.staged.lock().await.insert(changeset.id, changeset.clone());
+        
+        info!("Staged firewall changeset {} restoring backup {}", changeset.id, backup_id);
+        Ok(changeset)
+    }
+    
+    // Stages the model last replicated from the primary, when a standby is promoted
+    pub async fn stage_replicated_model(&self, primary: &str, replicated_at: DateTime<Utc>, model: FirewallModel, created_by: String) -> Result<StagedChangeset> {
+        let changeset = StagedChangeset {
+            id: Uuid::new_v4(),
+            description: format!("firewall model replicated from {} at {} ({} managed rules)",
+                                 primary, replicated_at.to_rfc3339(), model.managed_rules.len()),
+            created_by,
+            created_at: Utc::now(),
+            rules: Vec::new(),
+            moves: Vec::new(),
+            restore: Some(StagedRestore { backup_id: None, backup_created_at: replicated_at, model }),
+            comments: Vec::new(),
+        };
+        
+        self.staged.lock().await.insert(changeset.id, changeset.clone());
+        
+        info!("Staged firewall changeset {} with the model replicated from {}", changeset.id, primary);
+        Ok(changeset)
+    }
+    
+    pub async fn export_model(&self) -> FirewallModel {
+        let (managed_rules, next_handle) = {
+            let managed = self.managed_rules.lock().await;
+            (managed.rules.clone(), managed.next_handle)
+        };
+        let mut zone_services: Vec<ZoneServices> = self.zone_services.lock().await.values().cloned().collect();
+        zone_services.sort_by(|a, b| a.zone.cmp(&b.zone));
+        
+        FirewallModel {
+            managed_rules,
+            next_handle,
+            forwarding: self.forwarding.lock().await.clone(),
+            egress: self.egress.lock().await.clone(),
+            drop_logging: self.drop_logging.lock().await.clone(),
+            threat_intel: self.threat_intel.lock().await.clone(),
+            zone_services,
+        }
+    }
+    
+    // Replaces the model and regenerates the ruleset from it
+    async fn restore_model(&self, model: FirewallModel) -> Vec<ManagedRule> {
+        {
+            let mut managed = self.managed_rules.lock().await;
+            let next_handle = model.managed_rules.iter().map(|r| r.handle + 1).max().unwrap_or(1);
+            managed.rules = model.managed_rules;
+            managed.next_handle = model.next_handle.max(next_handle);
+        }
+        *self.forwarding.lock().await = model.forwarding;
+        *self.egress.lock().await = model.egress;
+        *self.drop_logging.lock().await = model.drop_logging;
+        *self.threat_intel.lock().await = model.threat_intel;
+        *self.zone_services.lock().await = model.zone_services.into_iter()
+            .map(|services| (services.zone.clone(), services))
+            .collect();
+        
+        // Relay rules follow the interface config, not the model
+        self.sync_dhcp_relay_rules().await;
+        self.rebuild_ruleset().await;
+        self.managed_rules.lock().await.rules.clone()
+    }
+    
+    pub async fn get_staged_changesets(&self) -> Vec<StagedChangeset> {
+        let mut list: Vec<StagedChangeset> = self.staged.lock().await.values().cloned().collect();
+        list.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        list
+    }
+    
+    pub async fn get_staged_changeset(&self, id: Uuid) -> Result<StagedChangeset> {
+        self.staged.lock().await
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Staged changeset not found: {}", id))
+    }
+    
+    pub async fn comment_changeset(&self, id: Uuid, author: &str, text: &str) -> Result<StagedChangeset> {
+        let mut staged = self.staged.lock().await;
+        let changeset = staged.get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Staged changeset not found: {}", id))?;
+        changeset.comments.push(ChangesetComment {
+            author: author.to_string(),
+            created_at: Utc::now(),
+            text: text.to_string(),
+        });
+        Ok(changeset.clone())
+    }
+
+    pub async fn changeset_diff(&self, id: Uuid) -> Result<ChangesetDiff> {
+        let changeset = self.get_staged_changeset(id).await?;
+        let current = self.managed_rules.lock().await.rules.clone();
+        let mut diff = ChangesetDiff {
+            id,
+            added: Vec::new(),
+            removed: Vec::new(),
+            reordered: Vec::new(),
+            replaced: Vec::new(),
+        };
+
+        if let Some(restore) = &changeset.restore {
+            let model = &restore.model;
+            let same = |a: &ManagedRule, b: &ManagedRule| a.handle == b.handle && a.rule == b.rule;
+            diff.removed = current.iter()
+                .filter(|r| !model.managed_rules.iter().any(|m| same(r, m)))
+                .map(DiffRule::of)
+                .collect();
+            diff.added = model.managed_rules.iter()
+                .filter(|m| !current.iter().any(|r| same(r, m)))
+                .map(DiffRule::of)
+                .collect();
+
+            let now = self.export_model().await;
+            let sections = [
+                ("forwarding", serde_json::to_value(&now.forwarding)?, serde_json::to_value(&model.forwarding)?),
+                ("egress", serde_json::to_value(&now.egress)?, serde_json::to_value(&model.egress)?),
+                ("drop_logging", serde_json::to_value(&now.drop_logging)?, serde_json::to_value(&model.drop_logging)?),
+                ("threat_intel", serde_json::to_value(&now.threat_intel)?, serde_json::to_value(&model.threat_intel)?),
+                ("zone_services", serde_json::to_value(&now.zone_services)?, serde_json::to_value(&model.zone_services)?),
+            ];
+            diff.replaced = sections.into_iter()
+                .filter(|(_, now, restored)| now != restored)
+                .map(|(name, _, _)| name)
+                .collect();
+            return Ok(diff);
+        }
+
+        diff.added = changeset.rules.iter()
+            .map(|staged| DiffRule {
+                chain: staged.spec.chain.clone(),
+                rule: staged.rendered.clone(),
+                description: if staged.spec.description.is_empty() {
+                    changeset.description.clone()
+                } else {
+                    staged.spec.description.clone()
+                },
+            })
+            .collect();
+        // Moves are replayed on the rules as they are now, as applying would
+        let mut rules = current.clone();
+        for staged in &changeset.moves {
+            move_rule(&mut rules, staged.handle, &staged.position)?;
+            if !diff.reordered.iter().any(|r| r.chain == staged.chain) {
+                diff.reordered.push(DiffReorder {
+                    chain: staged.chain.clone(),
+                    before: chain_order(&current, &staged.chain),
+                    after: Vec::new(),
+                });
+            }
+        }
+        for reorder in &mut diff.reordered {
+            reorder.after = chain_order(&rules, &reorder.chain);
+        }
+        Ok(diff)
+    }
+
+    pub async fn discard_changeset(&self, id: Uuid) -> Result<StagedChangeset> {
+        self.staged.lock().await
+            .remove(&id)
+            .ok_or_else(|| anyhow::anyhow!("Staged changeset not found: {}", id))
+    }
+    
+    // Applies all rules of a changeset at once; they form a group named after the changeset.
+    // Staged moves follow, and the returned rules include the moved ones.
+    pub async fn apply_changeset(&self, id: Uuid) -> Result<RuleGroup> {
+        let changeset = self.staged.lock().await
+            .remove(&id)
+            .ok_or_else(|| anyhow::anyhow!("Staged changeset not found: {}", id))?;
+        
+        if let Some(restore) = changeset.restore {
+            let rules = self.restore_model(restore.model).await;
+            match restore.backup_id {
+                Some(backup_id) => info!("Restored firewall backup {} with changeset {} ({} managed rules)", backup_id, id, rules.len()),
+                None => info!("Applied the replicated firewall model with changeset {} ({} managed rules)", id, rules.len()),
+            }
+            return Ok(RuleGroup { id, rules });
+        }
+        
+        let mut rules = Vec::new();
+        for staged in &changeset.rules {
+            let description = if staged.spec.description.is_empty() {
+                changeset.description.clone()
+            } else {
+                staged.spec.description.clone()
+            };
+            rules.push((staged.spec.chain.clone(), staged.spec.to_expressions()?, description));
+        }
+        
+        let mut rules = if rules.is_empty() {
+            Vec::new()
+        } else {
+            self.add_managed_rules(rules, Some(id), None).await?
+        };
+        if !changeset.moves.is_empty() {
+            rules.extend(self.move_rules(&changeset.moves).await?);
+        }
+        
+        info!("Applied firewall changeset {} ({} rules)", id, rules.len());
+        Ok(RuleGroup { id, rules })
+    }
+    
+    // Forward chain contents: return traffic, managed forward rules, then the matrix defaults.
+    // Also writes the masquerade rules, which are owned by the matrix as well.
+    async fn add_forward_chain(&self, batch: &mut nftables::Batch) {
+        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "filter".to_string(),
+            chain: "forward".to_string(),
+            handle: None,
+            index: None,
+            expr: vec![
+                match_expr("ct", "state", nftables::expr::Data::Set(vec![
+                    "established".to_string(),
+                    "related".to_string(),
+                ])),
+                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ],
+        }), None);
+        
+        for rule in self.managed_rules.lock().await.rules.iter().filter(|r| r.chain == "forward") {
+            batch.add(&rule.to_stmt(), Some(&rule.description));
+        }
+        
+        for rule in self.forwarding_rules().await {
+            batch.add(&rule.to_stmt(), Some(&rule.description));
+        }
+        
+        for (zone, stmt) in self.drop_log_rules("forward").await {
+            batch.add(&stmt, Some(&format!("drop log: forward {}", zone)));
+        }
+        
+        for (expr, description) in self.masquerade_rules().await {
+            batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "nat".to_string(),
+                chain: "postrouting".to_string(),
+                handle: None,
+                index: None,
+                expr,
+            }), Some(&description));
+        }
+    }
+    
+    // Interface matchers for a zone pair, None while either zone has no interfaces
+    async fn zone_pair_expressions(&self, entry: &ZoneForwarding) -> Option<Vec<nftables::expr::Expr>> {
+        let from_ifaces = self.zone_interfaces(&entry.from_zone).await;
+        let to_ifaces = self.zone_interfaces(&entry.to_zone).await;
+        
+        if from_ifaces.is_empty() || to_ifaces.is_empty() {
+            return None;
+        }
+        
+        Some(vec![
+            match_expr("meta", "iifname", set_or_value(from_ifaces)),
+            match_expr("meta", "oifname", set_or_value(to_ifaces)),
+        ])
+    }
+    
+    // One matcher list per covered service, or a single empty one for the whole zone pair
+    fn service_expressions(entry: &ZoneForwarding) -> Vec<(Vec<nftables::expr::Expr>, String)> {
+        if entry.services.is_empty() {
+            return vec![(Vec::new(), String::new())];
+        }
+        
+        entry.services.iter()
+            .map(|service| (
+                protocol_port_expressions(&service.protocol.nft_names(), &service.ports),
+                format!(" service {}", service.name),
+            ))
+            .collect()
+    }
+    
+    async fn forwarding_rules(&self) -> Vec<ManagedRule> {
+        let entries = self.forwarding.lock().await.clone();
+        let mut rules = Vec::new();
+        
+        for entry in &entries {
+            let pair = match self.zone_pair_expressions(entry).await {
+                Some(pair) => pair,
+                None => continue,
+            };
+            
+            for (service_expr, service_label) in Self::service_expressions(entry) {
+                let mut expr = pair.clone();
+                expr.extend(service_expr);
+                expr.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+                expr.push(entry.policy.verdict());
+                
+                let mut rule = ManagedRule {
+                    handle: 0,
+                    chain: "forward".to_string(),
+                    rule: String::new(),
+                    description: format!("forwarding: {} {} -> {}{}",
+                                         entry.policy.action(), entry.from_zone, entry.to_zone, service_label),
+                    group: None,
+                    forwarding: Some(entry.id),
+                    egress: None,
+                    self_service: None,
+                    dhcp_relay: None,
+                    created_at: entry.updated_at,
+                    priority: DEFAULT_RULE_PRIORITY,
+                    expr,
+                };
+                rule.rule = rule.to_stmt().to_string();
+                rules.push(rule);
+            }
+        }
+        
+        rules
+    }
+    
+    async fn masquerade_rules(&self) -> Vec<(Vec<nftables::expr::Expr>, String)> {
+        let entries = self.forwarding.lock().await.clone();
+        let mut rules = Vec::new();
+        
+        for entry in entries.iter().filter(|e| e.masquerade && e.policy == ForwardPolicy::Accept) {
+            let pair = match self.zone_pair_expressions(entry).await {
+                Some(pair) => pair,
+                None => continue,
+            };
+            
+            for (service_expr, service_label) in Self::service_expressions(entry) {
+                let mut expr = pair.clone();
+                expr.extend(service_expr);
+                expr.push(nftables::expr::Expr::Masquerade(nftables::expr::Masquerade {}));
+                rules.push((expr, format!("forwarding: masquerade {} -> {}{}", entry.from_zone, entry.to_zone, service_label)));
+            }
+        }
+        
+        rules
+    }
+    
+    // Matrix changes only replace the forward chain and the masquerade rules; the input
+    // and output chains are left untouched
+    async fn apply_forward_chain(&self) {
+        let mut batch = nftables::Batch::new();
+        
+        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Chain {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "filter".to_string(),
+            name: "forward".to_string(),
+        }), None);
+        
+        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Chain {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "nat".to_string(),
+            name: "postrouting".to_string(),
+        }), None);
+        
+        self.add_forward_chain(&mut batch).await;
+        
+        // In a real environment, we would execute:
+        // batch.execute().context("Failed to apply forward chain")?;
+        info!("Regenerated forward chain ({} statements)", batch.commands().len());
+        
+        // Keep the stored full ruleset in step with what was applied
+        self.rebuild_ruleset().await;
+    }
+    
+    pub async fn get_zone_forwarding(&self) -> Vec<ZoneForwarding> {
+        self.forwarding.lock().await.clone()
+    }
+    
+    // Sets the policy for a zone pair, replacing any existing entry for the same pair
+    pub async fn set_zone_forwarding(&self,
+                                     from_zone: &str,
+                                     to_zone: &str,
+                                     policy: ForwardPolicy,
+                                     services: Vec<ServiceDefinition>,
+                                     masquerade: bool) -> Result<ZoneForwarding> {
+        let valid_zone = |zone: &str| !zone.is_empty() && zone != "self"
+            && zone.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        
+        if !valid_zone(from_zone) || !valid_zone(to_zone) {
+            return Err(anyhow::anyhow!("Invalid zone pair: {} -> {}", from_zone, to_zone));
+        }
+        
+        if from_zone == to_zone {
+            return Err(anyhow::anyhow!("Source and destination zone must differ"));
+        }
+        
+        if masquerade && policy != ForwardPolicy::Accept {
+            return Err(anyhow::anyhow!("Masquerading requires an accept policy"));
+        }
+        
+        let entry = {
+            let mut forwarding = self.forwarding.lock().await;
+            let existing = forwarding.iter().position(|e| e.from_zone == from_zone && e.to_zone == to_zone);
+            
+            let entry = ZoneForwarding {
+                id: existing.map(|i| forwarding[i].id).unwrap_or_else(Uuid::new_v4),
+                from_zone: from_zone.to_string(),
+                to_zone: to_zone.to_string(),
+                policy,
+                services,
+                masquerade,
+                updated_at: Utc::now(),
+            };
+            
+            match existing {
+                Some(i) => forwarding[i] = entry.clone(),
+                None => forwarding.push(entry.clone()),
+            }
+            entry
+        };
+        
+        self.apply_forward_chain().await;
+        
+        info!("Set zone forwarding {} -> {}: {:?}", from_zone, to_zone, policy);
+        Ok(entry)
+    }
+    
+    // The common router setup: lan may reach wan, masqueraded behind the wan address
+    pub async fn apply_lan_wan_preset(&self) -> Result<ZoneForwarding> {
+        self.set_zone_forwarding("lan", "wan", ForwardPolicy::Accept, Vec::new(), true).await
+    }
+    
+    pub async fn delete_zone_forwarding(&self, id: Uuid) -> Result<()> {
+        {
+            let mut forwarding = self.forwarding.lock().await;
+            let before = forwarding.len();
+            forwarding.retain(|e| e.id != id);
+            
+            if forwarding.len() == before {
+                return Err(anyhow::anyhow!("Zone forwarding entry not found: {}", id));
+            }
+        }
+        
+        self.apply_forward_chain().await;
+        Ok(())
+    }
+    
+    // Output chain contents: return traffic and loopback, managed output rules, then the
+    // zone egress policies
+    async fn add_output_chain(&self, batch: &mut nftables::Batch) {
+        let base = vec![
+            vec![
+                match_expr("ct", "state", nftables::expr::Data::Set(vec![
+                    "established".to_string(),
+                    "related".to_string(),
+                ])),
+                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ],
+            vec![
+                match_expr("meta", "oifname", nftables::expr::Data::StrVal("lo".to_string())),
+                nftables::expr::Expr::Accept(nftables::expr::Accept {}),
+            ],
+        ];
+        
+        for expr in base {
+            batch.add(&nftables::Stmt::Add(nftables::objects::Add {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "filter".to_string(),
+                chain: "output".to_string(),
+                handle: None,
+                index: None,
+                expr,
+            }), None);
+        }
+        
+        for rule in self.managed_rules.lock().await.rules.iter().filter(|r| r.chain == "output") {
+            batch.add(&rule.to_stmt(), Some(&rule.description));
+        }
+        
+        for rule in self.egress_rules().await {
+            batch.add(&rule.to_stmt(), Some(&rule.description));
+        }
+    }
+    
+    // Per zone: the service exceptions first, then the zone default
+    async fn egress_rules(&self) -> Vec<ManagedRule> {
+        let entries = self.egress.lock().await.clone();
+        let mut rules = Vec::new();
+        
+        for entry in &entries {
+            let ifaces = self.zone_interfaces(&entry.zone).await;
+            if ifaces.is_empty() {
+                continue;
+            }
+            let oif = match_expr("meta", "oifname", set_or_value(ifaces));
+            
+            let exception = entry.policy.opposite();
+            let mut generated: Vec<(Vec<nftables::expr::Expr>, ForwardPolicy, String)> = entry.services.iter()
+                .map(|service| (
+                    protocol_port_expressions(&service.protocol.nft_names(), &service.ports),
+                    exception,
+                    format!("egress: {} {} service {}", exception.action(), entry.zone, service.name),
+                ))
+                .collect();
+            generated.push((Vec::new(), entry.policy, format!("egress: {} {}", entry.policy.action(), entry.zone)));
+            
+            for (service_expr, policy, description) in generated {
+                let mut expr = vec![oif.clone()];
+                expr.extend(service_expr);
+                expr.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+                expr.push(policy.verdict());
+                
+                let mut rule = ManagedRule {
+                    handle: 0,
+                    chain: "output".to_string(),
+                    rule: String::new(),
+                    description,
+                    group: None,
+                    forwarding: None,
+                    egress: Some(entry.id),
+                    self_service: None,
+                    dhcp_relay: None,
+                    created_at: entry.updated_at,
+                    priority: DEFAULT_RULE_PRIORITY,
+                    expr,
+                };
+                rule.rule = rule.to_stmt().to_string();
+                rules.push(rule);
+            }
+        }
+        
+        rules
+    }
+    
+    // Egress changes only replace the output chain
+    async fn apply_output_chain(&self) {
+        let mut batch = nftables::Batch::new();
+        
+        batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Chain {
+            family: nftables::schemas::nftables::TableFamily::Inet,
+            table: "filter".to_string(),
+            name: "output".to_string(),
+        }), None);
+        
+        self.add_output_chain(&mut batch).await;
+        
+        // In a real environment, we would execute:
+        // batch.execute().context("Failed to apply output chain")?;
+        info!("Regenerated output chain ({} statements)", batch.commands().len());
+        
+        // Keep the stored full ruleset in step with what was applied
+        self.rebuild_ruleset().await;
+    }
+    
+    pub async fn get_zone_egress(&self) -> Vec<ZoneEgress> {
+        self.egress.lock().await.clone()
+    }
+    
+    // Sets the egress policy of a zone, replacing any existing one
+    pub async fn set_zone_egress(&self,
+                                 zone: &str,
+                                 policy: ForwardPolicy,
+                                 services: Vec<ServiceDefinition>) -> Result<ZoneEgress> {
+        if zone.is_empty() || zone == "self" || !zone.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("Invalid zone: {}", zone));
+        }
+        
+        let entry = {
+            let mut egress = self.egress.lock().await;
+            let existing = egress.iter().position(|e| e.zone == zone);
+            
+            let entry = ZoneEgress {
+                id: existing.map(|i| egress[i].id).unwrap_or_else(Uuid::new_v4),
+                zone: zone.to_string(),
+                policy,
+                services,
+                updated_at: Utc::now(),
+            };
+            
+            match existing {
+                Some(i) => egress[i] = entry.clone(),
+                None => egress.push(entry.clone()),
+            }
+            entry
+        };
+        
+        self.apply_output_chain().await;
+        
+        info!("Set egress policy of zone {}: {:?}", zone, policy);
+        Ok(entry)
+    }
+    
+    pub async fn delete_zone_egress(&self, id: Uuid) -> Result<()> {
+        {
+            let mut egress = self.egress.lock().await;
+            let before = egress.len();
+            egress.retain(|e| e.id != id);
+            
+            if egress.len() == before {
+                return Err(anyhow::anyhow!("Zone egress policy not found: {}", id));
+            }
+        }
+        
+        self.apply_output_chain().await;
+        Ok(())
+    }
+    
+    // The rate-limited log rules of a chain, one per zone logging its drops there, with
+    // the zone; they go last, after every accept
+    async fn drop_log_rules(&self, chain: &str) -> Vec<(String, nftables::Stmt)> {
+        let entries = self.drop_logging.lock().await.clone();
+        let mut rules = Vec::new();
+        
+        for entry in entries.iter().filter(|e| e.chains.iter().any(|c| c == chain)) {
+            let ifaces = self.zone_interfaces(&entry.zone).await;
+            if ifaces.is_empty() {
+                continue;
+            }
+            
+            rules.push((entry.zone.clone(), nftables::Stmt::Add(nftables::objects::Add {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "filter".to_string(),
+                chain: chain.to_string(),
+                handle: None,
+                index: None,
+                expr: vec![
+                    match_expr("meta", "iifname", set_or_value(ifaces)),
+                    nftables::expr::Expr::Limit(nftables::expr::Limit {
+                        rate: entry.rate_per_second,
+                        per: "second".to_string(),
+                    }),
+                    nftables::expr::Expr::Log(nftables::expr::Log {
+                        prefix: drop_log_prefix(chain, &entry.zone),
+                        group: None,
+                    }),
+                    nftables::expr::Expr::Drop(nftables::expr::Drop {}),
+                ],
+            })));
+        }
+        
+        rules
+    }
+    
+    // The zone's drop log rules as nft statements, by chain
+    async fn zone_drop_log_rules(&self, zone: &str) -> Vec<(String, String)> {
+        let mut rules = Vec::new();
+        for chain in DROP_LOG_CHAINS {
+            rules.extend(self.drop_log_rules(chain).await.into_iter()
+                .filter(|(z, _)| z == zone)
+                .map(|(_, stmt)| (chain.to_string(), stmt.to_string())));
+        }
+        rules
+    }
+    
+    // Regenerates only the chains whose log rules changed
+    async fn apply_drop_log_change(&self, zone: &str, before: Vec<(String, String)>) -> DropLogChanges {
+        let after = self.zone_drop_log_rules(zone).await;
+        let removed: Vec<(String, String)> = before.iter().filter(|r| !after.contains(r)).cloned().collect();
+        let added: Vec<(String, String)> = after.iter().filter(|r| !before.contains(r)).cloned().collect();
+        
+        let touches = |chain: &str| removed.iter().chain(added.iter()).any(|(c, _)| c == chain);
+        if touches("forward") {
+            self.apply_forward_chain().await;
+        } else if touches("input") {
+            self.rebuild_ruleset().await;
+        }
+        
+        DropLogChanges {
+            added: added.into_iter().map(|(_, rule)| rule).collect(),
+            removed: removed.into_iter().map(|(_, rule)| rule).collect(),
+        }
+    }
+    
+    pub async fn get_zone_drop_logging(&self) -> Vec<ZoneDropLogging> {
+        self.drop_logging.lock().await.clone()
+    }
+    
+    // Logs the zone's dropped packets in the chains, replacing its previous setting
+    pub async fn set_zone_drop_logging(&self,
+                                       zone: &str,
+                                       chains: Vec<String>,
+                                       rate_per_second: u32) -> Result<(ZoneDropLogging, DropLogChanges)> {
+        if zone.is_empty() || zone == "self" || !zone.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("Invalid zone: {}", zone));
+        }
+        if drop_log_prefix("forward", zone).len() > MAX_LOG_PREFIX {
+            return Err(anyhow::anyhow!("Zone name is too long for a log prefix: {}", zone));
+        }
+        let chains: Vec<String> = chains.into_iter().collect::<BTreeSet<_>>().into_iter().collect();
+        if chains.is_empty() {
+            return Err(anyhow::anyhow!("No chain to log drops in; disable the zone's drop logging instead"));
+        }
+        if let Some(chain) = chains.iter().find(|c| !DROP_LOG_CHAINS.contains(&c.as_str())) {
+            return Err(anyhow::anyhow!("Drops can be logged in the input and forward chains, not {}", chain));
+        }
+        if rate_per_second == 0 || rate_per_second > MAX_DROP_LOG_RATE {
+            return Err(anyhow::anyhow!("rate_per_second must be between 1 and {}", MAX_DROP_LOG_RATE));
+        }
+        
+        let before = self.zone_drop_log_rules(zone).await;
+        let entry = ZoneDropLogging {
+            zone: zone.to_string(),
+            chains,
+            rate_per_second,
+            updated_at: Utc::now(),
+        };
+        {
+            let mut logging = self.drop_logging.lock().await;
+            logging.retain(|e| e.zone != zone);
+            logging.push(entry.clone());
+        }
+        
+        let changes = self.apply_drop_log_change(zone, before).await;
+        info!("Logging dropped packets of zone {} in {:?} at up to {}/s", zone, entry.chains, rate_per_second);
+        Ok((entry, changes))
+    }
+    
+    pub async fn disable_zone_drop_logging(&self, zone: &str) -> Result<DropLogChanges> {
+        let before = self.zone_drop_log_rules(zone).await;
+        {
+            let mut logging = self.drop_logging.lock().await;
+            let count = logging.len();
+            logging.retain(|e| e.zone != zone);
+            if logging.len() == count {
+                return Err(anyhow::anyhow!("Zone does not log dropped packets: {}", zone));
             }
-            
-            interfaces.push(interface);
         }
         
-        // Get IP addresses for all interfaces
-        let mut addresses = self.netlink_handle.address().get().execute();
-        while let Some(addr) = addresses.try_next().await? {
-            let if_index = addr.header.index;
-            
-            // Find the interface with this index
-            for interface in &mut interfaces {
-                if if_index == self.get_interface_index(&interface.name).await? {
-                    if let Some(rtnetlink::packet::address::AddressAttribute::Address(ip)) = addr.attributes.iter()
-                        .find(|attr| matches!(attr, rtnetlink::packet::address::AddressAttribute::Address(_))) {
-                        let mut addr_str = format!("{}", ip);
-                        
-                        // Add prefix length
-                        if let Some(rtnetlink::packet::address::AddressAttribute::PrefixLen(prefix)) = addr.attributes.iter()
-                            .find(|attr| matches!(attr, rtnetlink::packet::address::AddressAttribute::PrefixLen(_))) {
-                            addr_str.push_str(&format!("/{}", prefix));
-                        }
-                        
-                        interface.addresses.push(addr_str);
-                    }
-                }
+        let changes = self.apply_drop_log_change(zone, before).await;
+        info!("Stopped logging dropped packets of zone {}", zone);
+        Ok(changes)
+    }
+    
+    // Service lists of every zone with interfaces or a list of its own
+    pub async fn get_zone_services(&self) -> Vec<ZoneServices> {
+        let customized = self.zone_services.lock().await.clone();
+        let mut zones: BTreeSet<String> = self.interfaces.lock().await.iter()
+            .filter_map(|iface| iface.nftables_zone.clone())
+            .collect();
+        zones.extend(customized.keys().cloned());
+        
+        zones.into_iter()
+            .map(|zone| customized.get(&zone).cloned().unwrap_or_else(|| ZoneServices {
+                services: default_self_services(&zone),
+                zone,
+                customized: false,
+                updated_at: None,
+            }))
+            .collect()
+    }
+    
+    async fn zone_service_list(&self, zone: &str) -> Vec<SelfService> {
+        self.zone_services.lock().await.get(zone)
+            .map(|entry| entry.services.clone())
+            .unwrap_or_else(|| default_self_services(zone))
+    }
+    
+    // Replaces the services of this host the zone may reach and regenerates its input rules
+    pub async fn set_zone_services(&self, zone: &str, services: Vec<SelfService>) -> Result<(ZoneServices, ServiceRuleChanges)> {
+        if zone.is_empty() || zone == "self" || !zone.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("Invalid zone: {}", zone));
+        }
+        
+        let services: Vec<SelfService> = services.into_iter().collect::<BTreeSet<_>>().into_iter().collect();
+        if services.contains(&SelfService::Dhcp) {
+            if let Some(relay) = self.interfaces.lock().await.iter()
+                .find(|iface| iface.nftables_zone.as_deref() == Some(zone) && active_relay(iface).is_some()) {
+                return Err(anyhow::anyhow!("Interface {} of zone {} relays DHCP, switch its relay off before serving DHCP to the zone",
+                                           relay.name, zone));
             }
         }
+        let entry = ZoneServices {
+            zone: zone.to_string(),
+            services,
+            customized: true,
+            updated_at: Some(Utc::now()),
+        };
+        self.zone_services.lock().await.insert(zone.to_string(), entry.clone());
         
-        Ok(interfaces)
+        let changes = self.sync_self_service_rules().await;
+        self.rebuild_ruleset().await;
+        
+        info!("Set services of zone {}: {:?}", zone, entry.services);
+        Ok((entry, changes))
     }
     
-    async fn get_interface_index(&self, name: &str) -> Result<u32> {
-        let mut links = self.netlink_handle.link().get().match_name(name.to_string()).execute();
-        if let Some(link) = links.try_next().await? {
-            Ok(link.header.index)
-        } else {
-            Err(anyhow::anyhow!("Interface not found: {}", name))
+    // Opens or closes one service for a zone, e.g. when its DHCP server is switched on or off
+    pub async fn set_zone_service(&self, zone: &str, service: SelfService, enabled: bool) -> Result<(ZoneServices, ServiceRuleChanges)> {
+        let mut services = self.zone_service_list(zone).await;
+        services.retain(|s| *s != service);
+        if enabled {
+            services.push(service);
         }
+        self.set_zone_services(zone, services).await
     }
     
-    pub async fn setup_interface(&self, config: &InterfaceConfig) -> Result<()> {
-        info!("Setting up interface: {}", config.name);
+    // Puts the zone back on its default services
+    pub async fn reset_zone_services(&self, zone: &str) -> Result<(ZoneServices, ServiceRuleChanges)> {
+        if self.zone_services.lock().await.remove(zone).is_none() {
+            return Err(anyhow::anyhow!("Zone uses its default services: {}", zone));
+        }
         
-        let if_index = self.get_interface_index(&config.name).await?;
+        let changes = self.sync_self_service_rules().await;
+        self.rebuild_ruleset().await;
         
-        // Set interface up
-        self.netlink_handle.link()
-            .set(if_index)
-            .up()
-            .execute()
-            .await?;
-            
-        // Configure address if specified
-        if let Some(addr) = &config.address {
-            // Parse IP address
-            let addr_parts: Vec<&str> = addr.split('/').collect();
-            if addr_parts.len() != 2 {
-                return Err(anyhow::anyhow!("Invalid address format, expected IP/PREFIX: {}", addr));
+        let entry = ZoneServices {
+            zone: zone.to_string(),
+            services: default_self_services(zone),
+            customized: false,
+            updated_at: None,
+        };
+        info!("Reset services of zone {} to the defaults", zone);
+        Ok((entry, changes))
+    }
+    
+    // Brings the managed input rules opening zone services in line with the service lists
+    // and zone interfaces. Unchanged rules keep their handle, so their counters and history
+    // carry on; the caller rebuilds the ruleset.
+    async fn sync_self_service_rules(&self) -> ServiceRuleChanges {
+        let mut wanted: Vec<(SelfServiceRule, Vec<nftables::expr::Expr>)> = Vec::new();
+        let ingest_ports = self.ingest_ports.lock().await.clone();
+        for entry in self.get_zone_services().await {
+            let ifaces = self.zone_interfaces(&entry.zone).await;
+            if ifaces.is_empty() {
+                continue;
             }
             
-            let ip_addr = addr_parts[0].parse()
-                .context(format!("Invalid IP address: {}", addr_parts[0]))?;
+            for service in entry.services {
+                let Some(service_expr) = service.expressions(&ingest_ports) else { continue };
+                let mut expr = vec![match_expr("meta", "iifname", set_or_value(ifaces.clone()))];
+                expr.extend(service_expr);
+                expr.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+                expr.push(nftables::expr::Expr::Accept(nftables::expr::Accept {}));
+                wanted.push((SelfServiceRule { zone: entry.zone.clone(), service }, expr));
+            }
+        }
+        
+        let render = |expr: &Vec<nftables::expr::Expr>| serde_json::to_string(expr).unwrap_or_default();
+        let mut changes = ServiceRuleChanges::default();
+        let mut managed = self.managed_rules.lock().await;
+        
+        let (kept, removed): (Vec<ManagedRule>, Vec<ManagedRule>) = std::mem::take(&mut managed.rules).into_iter()
+            .partition(|rule| match &rule.self_service {
+                Some(key) => wanted.iter().any(|(k, expr)| k == key && render(expr) == render(&rule.expr)),
+                None => true,
+            });
+        managed.rules = kept;
+        changes.removed = removed;
+        
+        for (key, expr) in wanted {
+            if managed.rules.iter().any(|r| r.self_service.as_ref() == Some(&key)) {
+                continue;
+            }
             
-            let prefix_len: u8 = addr_parts[1].parse()
-                .context(format!("Invalid prefix length: {}", addr_parts[1]))?;
+            let mut rule = ManagedRule {
+                handle: managed.next_handle,
+                chain: "input".to_string(),
+                rule: String::new(),
+                description: format!("zone service: accept {} from {}", key.service.name(), key.zone),
+                group: None,
+                forwarding: None,
+                egress: None,
+                self_service: Some(key),
+                dhcp_relay: None,
+                created_at: Utc::now(),
+                priority: DEFAULT_RULE_PRIORITY,
+                expr,
+            };
+            rule.rule = rule.to_stmt().to_string();
             
-            // First delete any existing addresses
-            let mut addresses = self.netlink_handle.address().get()
-                .set_link_index_filter(if_index)
-                .execute();
-                
-            while let Some(existing_addr) = addresses.try_next().await? {
-                self.netlink_handle.address().del(existing_addr).execute().await?;
+            managed.next_handle += 1;
+            place_rule(&mut managed.rules, rule.clone(), &RulePosition::Priority(DEFAULT_RULE_PRIORITY))
+                .expect("placing by priority cannot fail");
+            changes.added.push(rule);
+        }
+        
+        changes
+    }
+    
+    // An interface either relays DHCP or its zone is served DHCP by this host (the zone's
+    // dhcp service), not both
+    async fn check_not_serving_dhcp(&self, config: &InterfaceConfig) -> Result<()> {
+        if let Some(zone) = &config.nftables_zone {
+            if self.zone_service_list(zone).await.contains(&SelfService::Dhcp) {
+                return Err(anyhow::anyhow!("Interface {} cannot relay DHCP while zone {} is served DHCP by this host, \
+                                            remove dhcp from the zone's services first", config.name, zone));
+            }
+        }
+        Ok(())
+    }
+    
+    // Brings the managed rules of the DHCP relays in line with the interface config, like
+    // sync_self_service_rules; the caller rebuilds the ruleset
+    async fn sync_dhcp_relay_rules(&self) -> ServiceRuleChanges {
+        let udp_port = |direction: &str, port: u16| match_expr("udp", direction, nftables::expr::Data::StrVal(port.to_string()));
+        let mut wanted: Vec<(DhcpRelayRule, &'static str, String, Vec<nftables::expr::Expr>)> = Vec::new();
+        for iface in self.interfaces.lock().await.iter() {
+            let relay = match active_relay(iface) {
+                Some(relay) => relay,
+                None => continue,
+            };
+            let iface_name = nftables::expr::Data::StrVal(iface.name.clone());
+            let servers = set_or_value(relay.servers.clone());
+            let flows = [
+                (RelayFlow::ClientRequests, "input", format!("accept DHCP requests from clients on {}", iface.name),
+                 vec![match_expr("meta", "iifname", iface_name.clone()), udp_port("dport", 67)]),
+                (RelayFlow::ServerReplies, "input", format!("accept DHCP replies from {} for {}", relay.servers.join(", "), iface.name),
+                 vec![match_expr("ip", "saddr", servers.clone()), udp_port("sport", 67), udp_port("dport", 67)]),
+                (RelayFlow::ToServers, "output", format!("relay DHCP requests from {} to {}", iface.name, relay.servers.join(", ")),
+                 vec![match_expr("ip", "daddr", servers), udp_port("dport", 67)]),
+                (RelayFlow::ToClients, "output", format!("relay DHCP replies to clients on {}", iface.name),
+                 vec![match_expr("meta", "oifname", iface_name), udp_port("dport", 68)]),
+            ];
+            for (flow, chain, description, mut expr) in flows {
+                expr.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+                expr.push(nftables::expr::Expr::Accept(nftables::expr::Accept {}));
+                wanted.push((DhcpRelayRule { interface: iface.name.clone(), flow }, chain, description, expr));
+            }
+        }
+        
+        let render = |expr: &Vec<nftables::expr::Expr>| serde_json::to_string(expr).unwrap_or_default();
+        let mut changes = ServiceRuleChanges::default();
+        let mut managed = self.managed_rules.lock().await;
+        
+        let (kept, removed): (Vec<ManagedRule>, Vec<ManagedRule>) = std::mem::take(&mut managed.rules).into_iter()
+            .partition(|rule| match &rule.dhcp_relay {
+                Some(key) => wanted.iter().any(|(k, _, _, expr)| k == key && render(expr) == render(&rule.expr)),
+                None => true,
+            });
+        managed.rules = kept;
+        changes.removed = removed;
+        
+        for (key, chain, description, expr) in wanted {
+            if managed.rules.iter().any(|r| r.dhcp_relay.as_ref() == Some(&key)) {
+                continue;
             }
             
-            // Add the new address
-            self.netlink_handle.address()
-                .add(if_index, ip_addr, prefix_len, IpVersion::V4)
-                .execute()
-                .await?;
-                
-            info!("Configured address {} on interface {}", addr, config.name);
+            let mut rule = ManagedRule {
+                handle: managed.next_handle,
+                chain: chain.to_string(),
+                rule: String::new(),
+                description: format!("dhcp relay: {}", description),
+                group: None,
+                forwarding: None,
+                egress: None,
+                self_service: None,
+                dhcp_relay: Some(key),
+                created_at: Utc::now(),
+                priority: DEFAULT_RULE_PRIORITY,
+                expr,
+            };
+            rule.rule = rule.to_stmt().to_string();
+            
+            managed.next_handle += 1;
+            place_rule(&mut managed.rules, rule.clone(), &RulePosition::Priority(DEFAULT_RULE_PRIORITY))
+                .expect("placing by priority cannot fail");
+            changes.added.push(rule);
         }
         
-        Ok(())
+        changes
     }
     
-    pub async fn get_nftables_rules(&self) -> Vec<String> {
-        // In a real implementation, we would use the nft list ruleset command
-        // For now, we'll return the rules as they are stored in our batch
+    // Counters of the relay rules per interface; empty when no relay is configured or nft
+    // cannot be read
+    pub async fn dhcp_relay_counters(&self) -> HashMap<String, DhcpRelayCounters> {
+        let relay_rules: Vec<(u32, DhcpRelayRule)> = self.managed_rules.lock().await.rules.iter()
+            .filter_map(|r| r.dhcp_relay.clone().map(|key| (r.handle, key)))
+            .collect();
+        if relay_rules.is_empty() {
+            return HashMap::new();
+        }
         
-        // Try to execute 'nft list ruleset' command if nftables is installed
-        match Command::new("nft")
-            .arg("list")
-            .arg("ruleset")
-            .output() {
-                Ok(output) if output.status.success() => {
-                    let rules_str = String::from_utf8_lossy(&output.stdout);
-                    rules_str.lines().map(|s| s.to_string()).collect()
-                },
-                _ => {
-                    // Fallback to our stored rules if nft command fails
-                    self.nftables_handle.commands.clone()
-                }
+        let counters = match self.get_rule_counters().await {
+            Ok(counters) => counters,
+            Err(e) => {
+                warn!("DHCP relay counters unavailable: {}", e);
+                return HashMap::new();
+            },
+        };
+        
+        let mut relays: HashMap<String, DhcpRelayCounters> = HashMap::new();
+        for (handle, key) in relay_rules {
+            let packets = match counters.get(&handle) {
+                Some(counters) => counters.packets,
+                None => continue,
+            };
+            let entry = relays.entry(key.interface).or_default();
+            match key.flow {
+                RelayFlow::ClientRequests => entry.requests = packets,
+                RelayFlow::ServerReplies => entry.replies = packets,
+                RelayFlow::ToServers | RelayFlow::ToClients => {}
             }
+        }
+        relays
     }
     
-    pub async fn add_firewall_rule(&self, 
-                                   chain: &str, 
-                                   protocol: &str, 
-                                   port: Option<u16>, 
-                                   source: Option<&str>, 
-                                   action: &str) -> Result<()> {
-        info!("Adding firewall rule: chain={}, protocol={}, port={:?}, source={:?}, action={}",
-              chain, protocol, port, source, action);
-              
-        let mut batch = self.nftables_handle.clone();
-        let mut expressions = Vec::new();
+    // Declares the threat intel sets and fills them; the sets exist even while empty so
+    // the preset rules always load
+    async fn add_threat_intel_sets(&self, batch: &mut nftables::Batch) {
+        let addresses = self.threat_intel.lock().await.clone();
         
-        // Add protocol matcher
-        if !protocol.is_empty() && protocol != "any" {
-            expressions.push(nftables::expr::Expr::Match(nftables::expr::Match {
-                op: protocol.to_string(),
-                expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                    op: "protocol".to_string(),
-                    data: nftables::expr::Data::StrVal(protocol.to_string()),
-                })),
-            }));
+        for (family, name, set_type) in THREAT_INTEL_SETS {
+            batch.add(&nftables::Stmt::AddSet(nftables::objects::AddSet {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "filter".to_string(),
+                name: name.to_string(),
+                set_type: set_type.to_string(),
+                flags: vec!["interval".to_string()],
+            }), None);
+            
+            batch.add(&nftables::Stmt::Flush(nftables::objects::Flush::Set {
+                family: nftables::schemas::nftables::TableFamily::Inet,
+                table: "filter".to_string(),
+                name: name.to_string(),
+            }), None);
+            
+            let elements: Vec<String> = addresses.iter()
+                .filter(|a| address_family(a).map_or(false, |f| f == family))
+                .cloned()
+                .collect();
+            if !elements.is_empty() {
+                batch.add(&nftables::Stmt::AddElement(nftables::objects::AddElement {
+                    family: nftables::schemas::nftables::TableFamily::Inet,
+                    table: "filter".to_string(),
+                    name: name.to_string(),
+                    elements,
+                }), None);
+            }
+        }
+    }
+    
+    pub async fn get_threat_intel(&self) -> Vec<String> {
+        self.threat_intel.lock().await.clone()
+    }
+    
+    // Replaces the contents of the threat intel sets with the given addresses or networks
+    pub async fn set_threat_intel(&self, addresses: Vec<String>) -> Result<usize> {
+        let mut normalized = BTreeSet::new();
+        for address in &addresses {
+            let network = IpNetwork::from_str(address.trim())
+                .map_err(|_| anyhow::anyhow!("Invalid address: {}", address))?;
+            normalized.insert(network.to_string());
         }
         
-        // Add port matcher if specified
-        if let Some(p) = port {
-            expressions.push(nftables::expr::Expr::Match(nftables::expr::Match {
-                op: protocol.to_string(),
-                expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                    op: "dport".to_string(),
-                    data: nftables::expr::Data::StrVal(p.to_string()),
-                })),
-            }));
+        let count = normalized.len();
+        *self.threat_intel.lock().await = normalized.into_iter().collect();
+        self.rebuild_ruleset().await;
+        
+        info!("Loaded {} threat intel addresses", count);
+        Ok(count)
+    }
+    
+    // Drops and logs traffic to the threat intel sets, both from this host (output) and
+    // from hosts routed through it (forward). The rules form a group removable as a template.
+    pub async fn apply_threat_intel_preset(&self) -> Result<RuleGroup> {
+        if let Some(rule) = self.managed_rules.lock().await.rules.iter().find(|r| r.description == THREAT_INTEL_PRESET) {
+            return Err(anyhow::anyhow!("Threat intel egress preset is already applied (group {})",
+                                       rule.group.map(|g| g.to_string()).unwrap_or_default()));
         }
         
-        // Add source address matcher if specified
-        if let Some(s) = source {
-            expressions.push(nftables::expr::Expr::Match(nftables::expr::Match {
-                op: "ip".to_string(),
-                expr: Box::new(nftables::expr::Expr::Cmp(nftables::expr::Cmp {
-                    op: "saddr".to_string(),
-                    data: nftables::expr::Data::StrVal(s.to_string()),
-                })),
-            }));
+        let mut rules = Vec::new();
+        for chain in ["output", "forward"] {
+            for (family, name, _) in THREAT_INTEL_SETS {
+                rules.push((chain.to_string(), vec![
+                    match_expr(family, "daddr", nftables::expr::Data::StrVal(format!("@{}", name))),
+                    nftables::expr::Expr::Counter(nftables::expr::Counter {}),
+                    nftables::expr::Expr::Log(nftables::expr::Log { prefix: "siem threat-intel egress: ".to_string(), group: None }),
+                    nftables::expr::Expr::Drop(nftables::expr::Drop {}),
+                ], THREAT_INTEL_PRESET.to_string()));
+            }
         }
         
-        // Add counter
-        expressions.push(nftables::expr::Expr::Counter(nftables::expr::Counter {}));
+        let group = Uuid::new_v4();
+        let rules = self.add_managed_rules(rules, Some(group), None).await?;
         
-        // Add action (accept or drop)
-        match action.to_lowercase().as_str() {
-            "accept" => expressions.push(nftables::expr::Expr::Accept(nftables::expr::Accept {})),
-            "drop" => expressions.push(nftables::expr::Expr::Drop(nftables::expr::Drop {})),
-            _ => return Err(anyhow::anyhow!("Unsupported action: {}", action)),
+        info!("Applied threat intel egress preset {} ({} rules)", group, rules.len());
+        Ok(RuleGroup { id: group, rules })
+    }
+    
+    // The kernel ruleset as `nft -j list ruleset` prints it
+    pub async fn live_ruleset_json(&self) -> Result<String> {
+        let output = Command::new("nft")
+            .arg("-j")
+            .arg("list")
+            .arg("ruleset")
+            .output()
+            .context("Failed to execute nft command")?;
+        
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("nft list ruleset failed: {}", String::from_utf8_lossy(&output.stderr)));
         }
         
-        // Add the rule
-        batch.add(&nftables::Stmt::Add(nftables::objects::Add {
-            family: nftables::schemas::nftables::TableFamily::Inet,
-            table: "filter".to_string(),
-            chain: chain.to_string(),
-            handle: None,
-            index: None,
-            expr: expressions,
-        }), None);
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+    
+    // Counters of all managed rules currently in the kernel ruleset
+    pub async fn get_rule_counters(&self) -> Result<HashMap<u32, RuleCounters>> {
+        parse_rule_counters(&self.live_ruleset_json().await?)
+    }
+    
+    // Rules in the kernel ruleset we did not write, see parse_foreign_rules
+    pub async fn find_foreign_rules(&self, ruleset_json: &str) -> Result<Vec<ForeignRule>> {
+        // Family and name of the tables the generated ruleset creates
+        let tables: Vec<(String, String)> = self.nftables_handle.lock().await.commands().iter()
+            .filter_map(|c| c.strip_prefix("add table "))
+            .filter_map(|t| {
+                let mut words = t.split_whitespace();
+                Some((words.next()?.to_string(), words.next()?.to_string()))
+            })
+            .collect();
+        let managed: Vec<u32> = self.managed_rules.lock().await.rules.iter().map(|r| r.handle).collect();
+        parse_foreign_rules(ruleset_json, &tables, &managed)
+    }
+    
+    pub async fn get_rule_counter(&self, handle: u32) -> Result<RuleCounters> {
+        if !self.managed_rules.lock().await.rules.iter().any(|r| r.handle == handle) {
+            return Err(anyhow::anyhow!("Firewall rule not found: {}", handle));
+        }
         
-        // In a real environment, we would execute:
-        // batch.execute().context("Failed to add firewall rule")?;
+        self.get_rule_counters().await?
+            .remove(&handle)
+            .ok_or_else(|| anyhow::anyhow!("Firewall rule {} is not present in the kernel ruleset", handle))
+    }
+    
+    // Managed rules with their counters; the listing still works when nft cannot be read
+    pub async fn get_managed_rules_with_counters(&self) -> Vec<ManagedRuleStatus> {
+        let mut counters = match self.get_rule_counters().await {
+            Ok(counters) => counters,
+            Err(e) => {
+                warn!("Firewall counters unavailable: {}", e);
+                HashMap::new()
+            },
+        };
+        
+        // Listed chain by chain in the order the rules are applied
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        self.get_managed_rules().await.into_iter()
+            .map(|rule| {
+                let position = positions.entry(rule.chain.clone()).or_insert(0);
+                *position += 1;
+                ManagedRuleStatus {
+                    counters: if rule.handle != 0 { counters.remove(&rule.handle) } else { None },
+                    position: *position,
+                    rule,
+                }
+            })
+            .collect()
+    }
+    
+    // Resets the rule's counters and returns the values they had before.
+    // `nft reset counters` only covers named counter objects, the anonymous counter
+    // of a rule is reset through the rule itself.
+    pub async fn reset_rule_counters(&self, handle: u32) -> Result<RuleCounters> {
+        let before = self.get_rule_counter(handle).await?;
         
-        // For now, just update our stored batch
-        self.nftables_handle = batch;
+        let output = Command::new("nft")
+            .arg("reset")
+            .arg("rule")
+            .arg(&before.family)
+            .arg(&before.table)
+            .arg(&before.chain)
+            .arg("handle")
+            .arg(before.kernel_handle.to_string())
+            .output()
+            .context("Failed to execute nft command")?;
         
-        info!("Firewall rule added successfully");
-        Ok(())
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("nft reset rule failed: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        
+        info!("Reset counters of firewall rule {} ({} packets, {} bytes)", handle, before.packets, before.bytes);
+        Ok(before)
     }
     
-    pub async fn delete_firewall_rule(&self, rule_handle: u32) -> Result<()> {
-        // In a real implementation, we would execute:
-        // nft delete rule inet filter <chain> handle <rule_handle>
+    // Managed rules older than the cutoff that have not matched a single packet
+    pub async fn unused_rules(&self, created_before: DateTime<Utc>) -> Result<Vec<ManagedRuleStatus>> {
+        let counters = self.get_rule_counters().await?;
+        let managed = self.managed_rules.lock().await;
         
+        Ok(managed.rules.iter()
+            .filter(|r| r.created_at < created_before)
+            .filter_map(|r| counters.get(&r.handle)
+                .filter(|c| c.packets == 0)
+                .map(|c| ManagedRuleStatus {
+                    rule: r.clone(),
+                    counters: Some(c.clone()),
+                    position: chain_order(&managed.rules, &r.chain).iter().position(|h| *h == r.handle).map_or(0, |i| i + 1),
+                }))
+            .collect())
+    }
+    
+    pub async fn delete_firewall_rule(&self, rule_handle: u32) -> Result<()> {
         info!("Deleting firewall rule with handle: {}", rule_handle);
         
-        // This is a simplified implementation
-        // In reality, we would need to find the specific rule by handle
+        {
+            let mut managed = self.managed_rules.lock().await;
+            if let Some(key) = managed.rules.iter().find(|r| r.handle == rule_handle).and_then(|r| r.self_service.as_ref()) {
+                return Err(anyhow::anyhow!("Firewall rule {} opens {} to zone {}, change it through the zone's services",
+                                           rule_handle, key.service.name(), key.zone));
+            }
+            if let Some(key) = managed.rules.iter().find(|r| r.handle == rule_handle).and_then(|r| r.dhcp_relay.as_ref()) {
+                return Err(anyhow::anyhow!("Firewall rule {} belongs to the DHCP relay on {}, change it through the interface config",
+                                           rule_handle, key.interface));
+            }
+            let before = managed.rules.len();
+            managed.rules.retain(|r| r.handle != rule_handle);
+            
+            if managed.rules.len() == before {
+                return Err(anyhow::anyhow!("Firewall rule not found: {}", rule_handle));
+            }
+        }
         
+        self.rebuild_ruleset().await;
         Ok(())
     }
 }
@@ -750,4 +3763,306 @@ pub struct InterfaceInfo {
     pub addresses: Vec<String>,
     pub is_up: bool,
     pub mac_address: String,
+    // Bond this interface is enslaved to
+    pub bond_master: Option<String>,
+    // Set when the interface is a bond
+    pub bond: Option<BondStatus>,
+    // Description, owner and tags, see interface_metadata
+    #[serde(default)]
+    pub metadata: Option<InterfaceMetadata>,
+    // Link is bouncing up and down, see link_flap
+    #[serde(default)]
+    pub flapping: bool,
+    // Set while the interface relays DHCP, see dhcp_relay
+    #[serde(default)]
+    pub dhcp_relay: Option<DhcpRelayStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BondMemberStatus {
+    pub name: String,
+    pub is_up: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BondStatus {
+    pub mode: Option<BondMode>,
+    pub members: Vec<BondMemberStatus>,
+    pub active_member: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(source: Option<&str>, destination: Option<&str>, protocols: &[&str]) -> RuleSpec {
+        RuleSpec {
+            chain: "input".to_string(),
+            protocols: protocols.iter().map(|p| p.to_string()).collect(),
+            source: source.map(|s| s.to_string()),
+            destination: destination.map(|d| d.to_string()),
+            action: "accept".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_ipv4_addresses_with_ip_selectors() {
+        let rule = RuleSpec {
+            ports: vec![22],
+            ..spec(Some("192.0.2.0/24"), Some("198.51.100.1"), &["tcp"])
+        };
+
+        assert_eq!(
+            rule.render().unwrap(),
+            "add rule inet filter input ip saddr 192.0.2.0/24 ip daddr 198.51.100.1 tcp dport 22 counter accept"
+        );
+    }
+
+    #[test]
+    fn renders_ipv6_addresses_with_ip6_selectors() {
+        let rendered = spec(Some("2001:db8::/32"), Some("2001:db8::1"), &["icmpv6"]).render().unwrap();
+
+        assert_eq!(
+            rendered,
+            "add rule inet filter input ip6 saddr 2001:db8::/32 ip6 daddr 2001:db8::1 meta l4proto icmpv6 counter accept"
+        );
+        assert!(!rendered.contains("ip saddr"));
+    }
+
+    #[test]
+    fn renders_mixed_rulesets_per_rule() {
+        let v4 = spec(Some("10.0.0.0/8"), None, &[]).render().unwrap();
+        let v6 = spec(Some("fd00::/8"), None, &[]).render().unwrap();
+
+        assert!(v4.contains("ip saddr 10.0.0.0/8"));
+        assert!(v6.contains("ip6 saddr fd00::/8"));
+    }
+
+    #[test]
+    fn rejects_rules_mixing_families() {
+        assert!(spec(Some("192.0.2.1"), Some("2001:db8::1"), &[]).render().is_err());
+        assert!(spec(Some("2001:db8::1"), None, &["icmp"]).render().is_err());
+        assert!(spec(Some("192.0.2.1"), None, &["icmpv6"]).render().is_err());
+        assert!(spec(Some("example.com"), None, &[]).render().is_err());
+    }
+
+    #[test]
+    fn output_rules_only_take_output_selectors() {
+        let output = |in_interface: Option<&str>, out_interface: Option<&str>| RuleSpec {
+            chain: "output".to_string(),
+            in_interface: in_interface.map(|i| i.to_string()),
+            out_interface: out_interface.map(|i| i.to_string()),
+            ..spec(None, Some("192.0.2.25"), &["tcp"])
+        };
+
+        assert_eq!(
+            output(None, Some("eth0")).render().unwrap(),
+            "add rule inet filter output meta oifname eth0 ip daddr 192.0.2.25 meta l4proto tcp counter accept"
+        );
+        assert!(output(Some("eth0"), None).render().is_err());
+        assert!(RuleSpec { direction: Some(Direction::Input), ..output(None, None) }.render().is_err());
+        assert!(RuleSpec { chain: "egress".to_string(), direction: Some(Direction::Output), ..output(Some("eth0"), None) }.render().is_err());
+    }
+
+    #[test]
+    fn address_family_follows_literal() {
+        assert_eq!(address_family("192.0.2.1").unwrap(), "ip");
+        assert_eq!(address_family("::ffff:192.0.2.1").unwrap(), "ip6");
+        assert_eq!(check_rule_family(&[], &["icmp"]).unwrap(), None);
+    }
+
+    #[test]
+    fn icmp_base_rules_keep_neighbor_discovery() {
+        let render = |rules: Vec<nftables::Stmt>| rules.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        let rules = render(icmp_base_rules(&["eth1".to_string()], &FirewallConfig::default()));
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].contains("icmpv6 type { destination-unreachable, packet-too-big, time-exceeded, parameter-problem, nd-neighbor-solicit, nd-neighbor-advert } accept"));
+        assert_eq!(rules[1], "add rule inet filter input meta iifname eth1 icmpv6 type { nd-router-solicit, nd-router-advert } accept");
+        assert!(rules.iter().all(|r| !r.contains("echo-request")));
+
+        let rules = render(icmp_base_rules(&[], &FirewallConfig { allow_icmp_echo: true, ..Default::default() }));
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[1], "add rule inet filter input icmp type echo-request accept");
+        assert_eq!(rules[2], "add rule inet filter input icmpv6 type echo-request accept");
+    }
+
+    #[test]
+    fn ingest_ports_follow_the_listeners() {
+        let mut config = crate::config::default_config();
+        assert_eq!(ingest_ports(&config), vec![8080]);
+        config.syslog_tls.enabled = true;
+        assert_eq!(ingest_ports(&config), vec![6514, 8080]);
+    }
+
+    #[tokio::test]
+    async fn lan_input_defaults_accept_the_ingest_ports() {
+        let manager = NetworkManager::unavailable();
+        manager.load_config(vec![InterfaceConfig {
+            name: "eth1".to_string(),
+            dhcp: None,
+            address: Some("192.168.1.1/24".to_string()),
+            nftables_zone: Some("lan".to_string()),
+            bond: None,
+            dhcp_relay: None,
+        }]).await.unwrap();
+        let ingest_rule = |rules: Vec<ManagedRule>| rules.into_iter()
+            .find(|r| r.description == "zone service: accept ingest from lan")
+            .map(|r| r.rule);
+
+        manager.initialize_nftables(&FirewallConfig::default()).await.unwrap();
+        assert_eq!(ingest_rule(manager.get_managed_rules().await), None);
+
+        manager.set_ingest_ports(vec![6514, 8080]).await;
+        manager.initialize_nftables(&FirewallConfig::default()).await.unwrap();
+        let rule = ingest_rule(manager.get_managed_rules().await).expect("ingest rule");
+        assert!(rule.contains("meta iifname eth1 tcp dport { 6514, 8080 } counter accept"), "{}", rule);
+    }
+
+    fn managed(handle: u32, chain: &str) -> ManagedRule {
+        ManagedRule {
+            handle,
+            chain: chain.to_string(),
+            rule: String::new(),
+            description: format!("rule {}", handle),
+            group: None,
+            forwarding: None,
+            egress: None,
+            self_service: None,
+            dhcp_relay: None,
+            created_at: Utc::now(),
+            priority: DEFAULT_RULE_PRIORITY,
+            expr: Vec::new(),
+        }
+    }
+
+    fn handles(rules: &[ManagedRule]) -> Vec<u32> {
+        rules.iter().map(|r| r.handle).collect()
+    }
+
+    fn assert_sorted_by_band(rules: &[ManagedRule]) {
+        assert!(rules.windows(2).all(|w| w[0].priority <= w[1].priority), "{:?}", rules.iter().map(|r| (r.handle, r.priority)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rules_are_ordered_by_band_then_insertion() {
+        let mut rules = Vec::new();
+        place_rule(&mut rules, managed(1, "input"), &RulePosition::Priority(DEFAULT_RULE_PRIORITY)).unwrap();
+        place_rule(&mut rules, managed(2, "input"), &RulePosition::Priority(DEFAULT_RULE_PRIORITY)).unwrap();
+        place_rule(&mut rules, managed(3, "input"), &RulePosition::Priority(10)).unwrap();
+        place_rule(&mut rules, managed(4, "input"), &RulePosition::Priority(500)).unwrap();
+        place_rule(&mut rules, managed(5, "input"), &RulePosition::Priority(10)).unwrap();
+
+        assert_eq!(handles(&rules), vec![3, 5, 1, 2, 4]);
+        assert_sorted_by_band(&rules);
+    }
+
+    #[test]
+    fn before_and_after_join_the_target_band() {
+        let mut rules = Vec::new();
+        for handle in 1..=3 {
+            place_rule(&mut rules, managed(handle, "input"), &RulePosition::Priority(handle as i32 * 10)).unwrap();
+        }
+
+        place_rule(&mut rules, managed(4, "input"), &RulePosition::Before(2)).unwrap();
+        place_rule(&mut rules, managed(5, "input"), &RulePosition::After(2)).unwrap();
+
+        assert_eq!(handles(&rules), vec![1, 4, 2, 5, 3]);
+        assert_eq!(rules[1].priority, 20);
+        assert_eq!(rules[3].priority, 20);
+        assert_sorted_by_band(&rules);
+    }
+
+    #[test]
+    fn reordering_never_drops_or_duplicates_rules() {
+        let mut rules = Vec::new();
+        for handle in 1..=12 {
+            let chain = if handle % 3 == 0 { "forward" } else { "input" };
+            place_rule(&mut rules, managed(handle, chain), &RulePosition::Priority((handle as i32 % 4) * 50)).unwrap();
+        }
+        let mut expected = handles(&rules);
+        expected.sort();
+
+        // A fixed pseudo-random walk over moves, including ones that must fail
+        let mut seed: u32 = 7;
+        let mut next = |n: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+        for _ in 0..500 {
+            let handle = next(12) + 1;
+            let target = next(13) + 1;
+            let position = match next(3) {
+                0 => RulePosition::Before(target),
+                1 => RulePosition::After(target),
+                _ => RulePosition::Priority(next(5) as i32 * 50 - 25),
+            };
+            let before = handles(&rules);
+            let result = move_rule(&mut rules, handle, &position);
+
+            let mut after = handles(&rules);
+            if result.is_err() {
+                assert_eq!(after, before, "failed move of {} to {:?} changed the order", handle, position);
+            }
+            after.sort();
+            assert_eq!(after, expected, "move of {} to {:?} lost or duplicated a rule", handle, position);
+            assert_sorted_by_band(&rules);
+        }
+    }
+
+    #[test]
+    fn invalid_moves_leave_the_chain_unchanged() {
+        let mut rules = Vec::new();
+        place_rule(&mut rules, managed(1, "input"), &RulePosition::Priority(DEFAULT_RULE_PRIORITY)).unwrap();
+        place_rule(&mut rules, managed(2, "input"), &RulePosition::Priority(DEFAULT_RULE_PRIORITY)).unwrap();
+        place_rule(&mut rules, managed(3, "forward"), &RulePosition::Priority(DEFAULT_RULE_PRIORITY)).unwrap();
+
+        assert!(move_rule(&mut rules, 1, &RulePosition::Before(1)).is_err());
+        assert!(move_rule(&mut rules, 1, &RulePosition::After(3)).is_err());
+        assert!(move_rule(&mut rules, 1, &RulePosition::After(99)).is_err());
+        assert!(move_rule(&mut rules, 99, &RulePosition::Priority(0)).is_err());
+        assert_eq!(handles(&rules), vec![1, 2, 3]);
+
+        move_rule(&mut rules, 2, &RulePosition::Before(1)).unwrap();
+        assert_eq!(chain_order(&rules, "input"), vec![2, 1]);
+        assert_eq!(chain_order(&rules, "forward"), vec![3]);
+    }
+
+    fn relay_interface(address: Option<&str>, dhcp: Option<bool>, servers: &[&str]) -> InterfaceConfig {
+        InterfaceConfig {
+            name: "lan0".to_string(),
+            dhcp,
+            address: address.map(|a| a.to_string()),
+            nftables_zone: None,
+            bond: None,
+            dhcp_relay: Some(DhcpRelay {
+                enabled: true,
+                servers: servers.iter().map(|s| s.to_string()).collect(),
+            }),
+        }
+    }
+
+    #[test]
+    fn relay_needs_unicast_ipv4_servers_and_a_static_address() {
+        let valid = relay_interface(Some("192.0.2.1/24"), Some(false), &["198.51.100.10", "198.51.100.11"]);
+        assert!(validate_interface_configs(&[valid]).is_ok());
+
+        for server in ["0.0.0.0", "127.0.0.1", "224.0.0.1", "255.255.255.255", "2001:db8::1", "dhcp.example"] {
+            let config = relay_interface(Some("192.0.2.1/24"), None, &[server]);
+            assert!(validate_interface_configs(&[config]).is_err(), "accepted relay server {}", server);
+        }
+
+        assert!(validate_interface_configs(&[relay_interface(Some("192.0.2.1/24"), None, &[])]).is_err());
+        assert!(validate_interface_configs(&[relay_interface(None, None, &["198.51.100.10"])]).is_err());
+        assert!(validate_interface_configs(&[relay_interface(Some("192.0.2.1/24"), Some(true), &["198.51.100.10"])]).is_err());
+    }
+
+    #[test]
+    fn disabled_relay_is_not_validated() {
+        let mut config = relay_interface(None, Some(true), &[]);
+        config.dhcp_relay.as_mut().unwrap().enabled = false;
+
+        assert!(validate_interface_configs(&[config]).is_ok());
+    }
 }
@@ -0,0 +1,253 @@
+use std::sync::Arc;
+use std::time::Duration;
+use base64::Engine;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName};
+use tokio_rustls::TlsConnector;
+use anyhow::{Result, Context, anyhow};
+use tracing::info;
+
+use crate::config::SmtpConfig;
+use crate::outbound::{self, HttpClients};
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// One SMTP conversation over a plain or TLS stream
+struct SmtpSession<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpSession<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    // Reads a possibly multi-line reply ("250-..." lines end with "250 ...")
+    async fn reply(&mut self, expected: u16) -> Result<String> {
+        let mut text = String::new();
+
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(anyhow!("SMTP server closed the connection"));
+            }
+
+            let code: u16 = line.get(..3).and_then(|c| c.parse().ok())
+                .ok_or_else(|| anyhow!("Malformed SMTP reply: {}", line.trim_end()))?;
+            text.push_str(line.get(4..).unwrap_or("").trim_end());
+            text.push('\n');
+
+            if line.as_bytes().get(3) != Some(&b'-') {
+                if code != expected {
+                    return Err(anyhow!("SMTP server replied {}: {}", code, text.trim_end()));
+                }
+                return Ok(text);
+            }
+        }
+    }
+
+    async fn command(&mut self, line: &str, expected: u16) -> Result<String> {
+        self.stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await?;
+        self.stream.get_mut().flush().await?;
+        self.reply(expected).await
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    async fn authenticate(&mut self, config: &SmtpConfig) -> Result<()> {
+        if config.username.is_empty() {
+            return Ok(());
+        }
+
+        let credentials = base64::engine::general_purpose::STANDARD
+            .encode(format!("\0{}\0{}", config.username, config.password));
+        self.command(&format!("AUTH PLAIN {}", credentials), 235).await?;
+        Ok(())
+    }
+
+    async fn finish(&mut self, from: &str, message: Option<(&[String], &str)>) -> Result<()> {
+        match message {
+            Some((to, message)) => self.deliver(from, to, message).await,
+            None => {
+                let _ = self.command("QUIT", 221).await;
+                Ok(())
+            },
+        }
+    }
+
+    async fn deliver(&mut self, from: &str, to: &[String], message: &str) -> Result<()> {
+        self.command(&format!("MAIL FROM:<{}>", from), 250).await?;
+        for recipient in to {
+            self.command(&format!("RCPT TO:<{}>", recipient), 250).await?;
+        }
+        self.command("DATA", 354).await?;
+
+        // Dot-stuffing: a line starting with "." gets another one
+        let mut body = String::new();
+        for line in message.lines() {
+            if line.starts_with('.') {
+                body.push('.');
+            }
+            body.push_str(line);
+            body.push_str("\r\n");
+        }
+        body.push_str(".\r\n");
+
+        self.stream.get_mut().write_all(body.as_bytes()).await?;
+        self.stream.get_mut().flush().await?;
+        self.reply(250).await?;
+
+        // The message is accepted at this point, a failed QUIT does not matter
+        let _ = self.command("QUIT", 221).await;
+        Ok(())
+    }
+}
+
+// Delivers notifications by email (SMTP) and webhook
+#[derive(Clone)]
+pub struct Notifier {
+    smtp: SmtpConfig,
+    sender: String,
+    http: reqwest::Client,
+    tls: TlsConnector,
+    // Why nothing is delivered, set when the notifier failed to start, see startup
+    unavailable: Option<String>,
+}
+
+fn tls_connector() -> TlsConnector {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let tls = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(tls))
+}
+
+impl Notifier {
+    pub fn new(smtp: SmtpConfig, sender: String, clients: &HttpClients) -> Result<Self> {
+        let http = clients.client(outbound::WEBHOOKS, WEBHOOK_TIMEOUT)?;
+
+        Ok(Self {
+            smtp,
+            sender,
+            http,
+            tls: tls_connector(),
+            unavailable: None,
+        })
+    }
+
+    // Stand-in while notifications are disabled: the managers holding a Notifier still
+    // start, and every delivery fails with the reason
+    pub fn unavailable(smtp: SmtpConfig, sender: String, reason: String) -> Self {
+        Self {
+            smtp,
+            sender,
+            http: reqwest::Client::new(),
+            tls: tls_connector(),
+            unavailable: Some(reason),
+        }
+    }
+
+    fn check_available(&self) -> Result<()> {
+        match &self.unavailable {
+            Some(reason) => Err(anyhow!("Notifications are disabled: {}", reason)),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn send_email(&self, to: &[String], subject: &str, body: &str) -> Result<()> {
+        self.send_message(to, subject, "text/plain", body).await
+    }
+
+    pub async fn send_html_email(&self, to: &[String], subject: &str, html: &str) -> Result<()> {
+        self.send_message(to, subject, "text/html", html).await
+    }
+
+    async fn send_message(&self, to: &[String], subject: &str, content_type: &str, body: &str) -> Result<()> {
+        self.check_available()?;
+        if to.is_empty() {
+            return Err(anyhow!("No email recipients"));
+        }
+
+        // Correlation ids are checked to be plain tokens, safe in a header line
+        let correlation = crate::request_trace::current()
+            .map(|id| format!("X-Correlation-Id: {}\r\n", id))
+            .unwrap_or_default();
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n{}MIME-Version: 1.0\r\nContent-Type: {}; charset=utf-8\r\n\r\n{}",
+            self.sender,
+            to.join(", "),
+            subject,
+            chrono::Utc::now().to_rfc2822(),
+            correlation,
+            content_type,
+            body,
+        );
+
+        tokio::time::timeout(SMTP_TIMEOUT, self.smtp_send(Some((to, &message)))).await
+            .map_err(|_| anyhow!("SMTP delivery timed out"))??;
+
+        info!("Sent email \"{}\" to {}", subject, to.join(", "));
+        Ok(())
+    }
+
+    // Without a message only the handshake and authentication are done
+    async fn smtp_send(&self, message: Option<(&[String], &str)>) -> Result<()> {
+        let server_name = ServerName::try_from(self.smtp.server.clone())
+            .map_err(|_| anyhow!("Invalid SMTP server name: {}", self.smtp.server))?;
+        let stream = TcpStream::connect((self.smtp.server.as_str(), self.smtp.port)).await
+            .context(format!("Failed to connect to SMTP server {}:{}", self.smtp.server, self.smtp.port))?;
+
+        // Port 465 speaks TLS from the start, anything else upgrades with STARTTLS
+        if self.smtp.use_tls && self.smtp.port == 465 {
+            let stream = self.tls.connect(server_name, stream).await?;
+            let mut session = SmtpSession::new(stream);
+            session.reply(220).await?;
+            session.command("EHLO siem", 250).await?;
+            session.authenticate(&self.smtp).await?;
+            return session.finish(&self.sender, message).await;
+        }
+
+        let mut session = SmtpSession::new(stream);
+        session.reply(220).await?;
+        session.command("EHLO siem", 250).await?;
+
+        if !self.smtp.use_tls {
+            session.authenticate(&self.smtp).await?;
+            return session.finish(&self.sender, message).await;
+        }
+
+        session.command("STARTTLS", 220).await?;
+        let stream = self.tls.connect(server_name, session.into_inner()).await?;
+        let mut session = SmtpSession::new(stream);
+        session.command("EHLO siem", 250).await?;
+        session.authenticate(&self.smtp).await?;
+        session.finish(&self.sender, message).await
+    }
+
+    // Connects, negotiates TLS and authenticates without sending anything
+    pub async fn verify_smtp(&self) -> Result<()> {
+        tokio::time::timeout(SMTP_TIMEOUT, self.smtp_send(None)).await
+            .map_err(|_| anyhow!("SMTP handshake timed out"))?
+    }
+
+    pub async fn post_webhook(&self, url: &str, payload: &serde_json::Value) -> Result<()> {
+        self.check_available()?;
+        crate::request_trace::correlate(self.http.post(url))
+            .json(payload)
+            .send()
+            .await
+            .context(format!("Failed to call webhook {}", url))?
+            .error_for_status()
+            .context(format!("Webhook {} rejected the notification", url))?;
+
+        Ok(())
+    }
+}
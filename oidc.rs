@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use base64::Engine;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::config::OidcConfig;
+use crate::outbound::{self, HttpClients};
+use crate::models::UserRole;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// A login has this long to come back from the provider
+const PENDING_LOGIN_SECS: u64 = 600;
+// Logins in progress at once; the oldest is dropped to make room
+const MAX_PENDING_LOGINS: usize = 1000;
+// Carries the state parameter back to the callback, so a callback URL planted in another
+// browser (login CSRF) is refused
+pub const STATE_COOKIE: &str = "siem_oidc_state";
+// An unknown key id triggers a JWKS refetch at most this often, so forged tokens
+// cannot make us hammer the provider
+const JWKS_MIN_REFRESH_SECS: u64 = 60;
+
+// Provider metadata from the discovery document
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    end_session_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+// Browser round trip started by /api/auth/oidc/login, keyed by the state parameter
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    started: Instant,
+}
+
+struct JwksCache {
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+// Validated identity from the ID token
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    // "<issuer>|<sub>", stable across renames at the provider
+    pub external_id: String,
+    pub username: String,
+    pub email: String,
+    pub full_name: String,
+    pub role: UserRole,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+fn random_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// PKCE S256 challenge of a code verifier
+fn code_challenge(verifier: &str) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+// Claim values as strings; group claims are usually arrays, some providers send one string
+fn claim_values(claims: &HashMap<String, serde_json::Value>, claim: &str) -> Vec<String> {
+    match claims.get(claim) {
+        Some(serde_json::Value::String(value)) => vec![value.clone()],
+        Some(serde_json::Value::Array(values)) => values.iter()
+            .filter_map(|v| v.as_str().map(|v| v.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// First matching rule wins; without a match the configured default role applies
+// Secure unless the callback is served over plain HTTP, where browsers would drop it
+fn cookie_attributes(config: &OidcConfig, max_age: u64) -> String {
+    let secure = if config.redirect_url.starts_with("https://") { "; Secure" } else { "" };
+    format!("Path=/api/auth/oidc; Max-Age={}; HttpOnly; SameSite=Lax{}", max_age, secure)
+}
+
+// Value of the state cookie among the Cookie headers of a request
+pub fn cookie_state<'a>(cookies: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    cookies.into_iter()
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == STATE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+pub fn map_role(config: &OidcConfig, claims: &HashMap<String, serde_json::Value>) -> UserRole {
+    let values = claim_values(claims, &config.role_claim);
+    let role = config.role_mapping.iter()
+        .find(|rule| values.iter().any(|v| *v == rule.value))
+        .map(|rule| rule.role.as_str())
+        .unwrap_or(&config.default_role);
+    UserRole::from_role_name(role)
+}
+
+// Relying party of the OpenID Connect authorization code flow (with PKCE)
+#[derive(Clone)]
+pub struct OidcClient {
+    config: OidcConfig,
+    client_secret: String,
+    http: reqwest::Client,
+    discovery: Arc<Mutex<Option<Discovery>>>,
+    jwks: Arc<Mutex<Option<JwksCache>>>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+impl OidcClient {
+    // The provider is contacted lazily, an unreachable provider does not stop startup
+    pub fn new(config: OidcConfig, clients: &HttpClients) -> Result<Self> {
+        let client_secret = match (&config.client_secret_file, &config.client_secret_env) {
+            (Some(path), _) => fs::read_to_string(path)
+                .context(format!("Failed to read OIDC client secret file: {}", path))?
+                .trim()
+                .to_string(),
+            (None, Some(var)) => std::env::var(var)
+                .context(format!("OIDC client secret environment variable {} is not set", var))?,
+            (None, None) => return Err(anyhow!("OIDC needs client_secret_file or client_secret_env")),
+        };
+
+        if config.issuer_url.is_empty() || config.client_id.is_empty() || config.redirect_url.is_empty() {
+            return Err(anyhow!("OIDC needs issuer_url, client_id and redirect_url"));
+        }
+
+        let http = clients.client(outbound::OIDC, HTTP_TIMEOUT)?;
+
+        info!("OpenID Connect single sign-on enabled for issuer {}", config.issuer_url);
+
+        Ok(Self {
+            config,
+            client_secret,
+            http,
+            discovery: Arc::new(Mutex::new(None)),
+            jwks: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    async fn discovery(&self) -> Result<Discovery> {
+        if let Ok(cached) = self.discovery.lock() {
+            if let Some(discovery) = cached.as_ref() {
+                return Ok(discovery.clone());
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer_url.trim_end_matches('/'));
+        let discovery: Discovery = self.http.get(&url).send().await?
+            .error_for_status()?
+            .json().await
+            .context("Failed to parse OIDC discovery document")?;
+
+        if discovery.issuer.trim_end_matches('/') != self.config.issuer_url.trim_end_matches('/') {
+            return Err(anyhow!("Discovery document is for issuer {}, expected {}", discovery.issuer, self.config.issuer_url));
+        }
+
+        match self.discovery.lock() {
+            Ok(mut cached) => *cached = Some(discovery.clone()),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on OIDC discovery")),
+        }
+        Ok(discovery)
+    }
+
+    // Set-Cookie header value for a login started with the state
+    pub fn state_cookie(&self, state: &str) -> String {
+        format!("{}={}; {}", STATE_COOKIE, state, cookie_attributes(&self.config, PENDING_LOGIN_SECS))
+    }
+
+    // Removes the state cookie once the callback was handled
+    pub fn clear_state_cookie(&self) -> String {
+        format!("{}=; {}", STATE_COOKIE, cookie_attributes(&self.config, 0))
+    }
+
+    fn remember_login(&self, state: String, login: PendingLogin) -> Result<()> {
+        match self.pending.lock() {
+            Ok(mut pending) => {
+                pending.retain(|_, login| login.started.elapsed().as_secs() < PENDING_LOGIN_SECS);
+                if pending.len() >= MAX_PENDING_LOGINS {
+                    let oldest = pending.iter()
+                        .min_by_key(|(_, login)| login.started)
+                        .map(|(state, _)| state.clone());
+                    if let Some(oldest) = oldest {
+                        pending.remove(&oldest);
+                    }
+                }
+                pending.insert(state, login);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on pending OIDC logins")),
+        }
+    }
+
+    // Provider URL the browser is sent to, with the state to set as cookie
+    pub async fn login_url(&self) -> Result<(String, String)> {
+        let discovery = self.discovery().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+
+        let url = reqwest::Url::parse_with_params(&discovery.authorization_endpoint, &[
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("scope", self.config.scopes.join(" ").as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge(&code_verifier).as_str()),
+            ("code_challenge_method", "S256"),
+        ])?;
+
+        self.remember_login(state.clone(), PendingLogin {
+            nonce,
+            code_verifier,
+            started: Instant::now(),
+        })?;
+
+        Ok((url.to_string(), state))
+    }
+
+    // Keys for the token's key id; a key id missing from the cache means the provider
+    // rotated its keys, so the set is refetched
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        let max_age = Duration::from_secs(self.config.jwks_cache_secs);
+
+        for attempt in 0..2 {
+            let (found, refetch) = match self.jwks.lock() {
+                Ok(cache) => match cache.as_ref() {
+                    Some(cache) => {
+                        let jwk = match kid {
+                            Some(kid) => cache.keys.find(kid),
+                            None => cache.keys.keys.first(),
+                        };
+                        let expired = cache.fetched.elapsed() > max_age;
+                        let may_refresh = cache.fetched.elapsed().as_secs() >= JWKS_MIN_REFRESH_SECS;
+                        match jwk {
+                            Some(jwk) if !expired => (Some(DecodingKey::from_jwk(jwk)?), false),
+                            _ => (None, expired || (attempt == 0 && may_refresh)),
+                        }
+                    },
+                    None => (None, true),
+                },
+                Err(_) => return Err(anyhow!("Failed to acquire lock on OIDC keys")),
+            };
+
+            if let Some(key) = found {
+                return Ok(key);
+            }
+            if !refetch {
+                break;
+            }
+
+            let discovery = self.discovery().await?;
+            let keys: JwkSet = self.http.get(&discovery.jwks_uri).send().await?
+                .error_for_status()?
+                .json().await
+                .context("Failed to parse OIDC key set")?;
+            info!("Fetched {} OIDC signing keys", keys.keys.len());
+
+            match self.jwks.lock() {
+                Ok(mut cache) => *cache = Some(JwksCache { keys, fetched: Instant::now() }),
+                Err(_) => return Err(anyhow!("Failed to acquire lock on OIDC keys")),
+            }
+        }
+
+        Err(anyhow!("No OIDC signing key with id {}", kid.unwrap_or("(none)")))
+    }
+
+    // Redeems the authorization code and validates the ID token: signature, issuer,
+    // audience, expiry (with the configured clock skew) and nonce. The state must also
+    // be the one in the browser's state cookie.
+    pub async fn complete_login(&self, code: &str, state: &str, cookie_state: Option<&str>) -> Result<OidcIdentity> {
+        if cookie_state != Some(state) {
+            return Err(anyhow!("Login state does not match the browser that started the login"));
+        }
+
+        let login = match self.pending.lock() {
+            Ok(mut pending) => pending.remove(state)
+                .ok_or_else(|| anyhow!("Unknown or already used login state"))?,
+            Err(_) => return Err(anyhow!("Failed to acquire lock on pending OIDC logins")),
+        };
+        if login.started.elapsed().as_secs() >= PENDING_LOGIN_SECS {
+            return Err(anyhow!("Login took too long, start again"));
+        }
+
+        let discovery = self.discovery().await?;
+        let response: TokenResponse = self.http.post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.config.redirect_url.as_str()),
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code_verifier", login.code_verifier.as_str()),
+            ])
+            .send().await?
+            .error_for_status()?
+            .json().await
+            .context("Token response has no ID token")?;
+
+        let header = decode_header(&response.id_token)?;
+        // Only asymmetric signatures; HS* would be keyed with the client secret
+        if !matches!(header.alg,
+                     Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512
+                     | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512
+                     | Algorithm::ES256 | Algorithm::ES384 | Algorithm::EdDSA) {
+            return Err(anyhow!("Unsupported ID token algorithm: {:?}", header.alg));
+        }
+
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+        validation.leeway = self.config.clock_skew_secs;
+
+        let claims = decode::<IdTokenClaims>(&response.id_token, &key, &validation)?.claims;
+
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(anyhow!("ID token nonce does not match the login"));
+        }
+
+        let username = claim_values(&claims.other, &self.config.username_claim).into_iter().next()
+            .or_else(|| claim_values(&claims.other, "email").into_iter().next())
+            .ok_or_else(|| anyhow!("ID token has no {} claim", self.config.username_claim))?;
+        let email = claim_values(&claims.other, "email").into_iter().next().unwrap_or_default();
+        let full_name = claim_values(&claims.other, "name").into_iter().next().unwrap_or_else(|| username.clone());
+        let role = map_role(&self.config, &claims.other);
+        if claim_values(&claims.other, &self.config.role_claim).is_empty() {
+            warn!("ID token of {} has no {} claim, using the default role", username, self.config.role_claim);
+        }
+
+        Ok(OidcIdentity {
+            external_id: format!("{}|{}", discovery.issuer, claims.sub),
+            username: username.to_lowercase(),
+            email,
+            full_name,
+            role,
+        })
+    }
+
+    // Provider logout URL, when configured and the provider has one
+    pub async fn end_session_url(&self) -> Result<Option<String>> {
+        if !self.config.end_session_on_logout {
+            return Ok(None);
+        }
+
+        let discovery = self.discovery().await?;
+        let endpoint = match discovery.end_session_endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+
+        let mut params = vec![("client_id", self.config.client_id.as_str())];
+        if let Some(redirect) = &self.config.post_logout_redirect_url {
+            params.push(("post_logout_redirect_uri", redirect.as_str()));
+        }
+        Ok(Some(reqwest::Url::parse_with_params(&endpoint, &params)?.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(dir: &tempfile::TempDir, redirect_url: &str) -> OidcClient {
+        let secret = dir.path().join("client_secret");
+        fs::write(&secret, "secret\n").unwrap();
+        let config = OidcConfig {
+            issuer_url: "https://login.example.com".to_string(),
+            client_id: "siem".to_string(),
+            redirect_url: redirect_url.to_string(),
+            client_secret_file: Some(secret.display().to_string()),
+            ..Default::default()
+        };
+        let clients = HttpClients::new(&crate::config::default_config().proxy).unwrap();
+        OidcClient::new(config, &clients).unwrap()
+    }
+
+    fn login() -> PendingLogin {
+        PendingLogin {
+            nonce: random_token(),
+            code_verifier: random_token(),
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn state_cookie_is_found_among_other_cookies() {
+        assert_eq!(cookie_state(["theme=dark; siem_oidc_state=abc123", "lang=cs"]), Some("abc123"));
+        assert_eq!(cookie_state(["lang=cs", "siem_oidc_state=xyz"]), Some("xyz"));
+        assert_eq!(cookie_state(["siem_oidc_state_old=abc"]), None);
+        assert_eq!(cookie_state(Vec::<&str>::new()), None);
+    }
+
+    #[test]
+    fn state_cookie_is_secure_over_https_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let cookie = client(&dir, "https://siem.example.com/api/auth/oidc/callback").state_cookie("abc");
+        assert!(cookie.starts_with("siem_oidc_state=abc; Path=/api/auth/oidc; Max-Age=600; HttpOnly; SameSite=Lax"));
+        assert!(cookie.ends_with("; Secure"));
+
+        let plain = client(&dir, "http://192.168.1.1:8080/api/auth/oidc/callback");
+        assert!(!plain.state_cookie("abc").contains("Secure"));
+        assert!(plain.clear_state_cookie().contains("Max-Age=0"));
+    }
+
+    #[tokio::test]
+    async fn callback_without_the_matching_cookie_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let oidc = client(&dir, "https://siem.example.com/api/auth/oidc/callback");
+        oidc.remember_login("state-1".to_string(), login()).unwrap();
+
+        for cookie in [None, Some("state-2")] {
+            let error = oidc.complete_login("code", "state-1", cookie).await.unwrap_err();
+            assert!(error.to_string().contains("does not match"), "{}", error);
+        }
+        // Refused callbacks leave the login to the browser that started it
+        assert!(oidc.pending.lock().unwrap().contains_key("state-1"));
+    }
+
+    #[test]
+    fn pending_logins_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let oidc = client(&dir, "https://siem.example.com/api/auth/oidc/callback");
+        oidc.remember_login("first".to_string(), login()).unwrap();
+        for i in 0..MAX_PENDING_LOGINS {
+            oidc.remember_login(format!("state-{}", i), login()).unwrap();
+        }
+
+        let pending = oidc.pending.lock().unwrap();
+        assert_eq!(pending.len(), MAX_PENDING_LOGINS);
+        assert!(!pending.contains_key("first"));
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, anyhow};
+use base64::Engine;
+use ipnetwork::IpNetwork;
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy, Url};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName};
+use tokio_rustls::TlsConnector;
+use tracing::info;
+
+use crate::config::{ProxyConfig, ProxyMode};
+
+// Integrations with their own proxy mode, keys of [proxy.integrations]
+pub const WEBHOOKS: &str = "webhooks";
+pub const OIDC: &str = "oidc";
+pub const UPDATE_CHECK: &str = "update_check";
+pub const CONNECTIVITY_TEST: &str = "connectivity_test";
+pub const HEARTBEAT: &str = "heartbeat";
+pub const FIREWALL_BACKUP: &str = "firewall_backup";
+pub const REPLICATION: &str = "replication";
+
+// Limit of each step of the connectivity test
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Scheme, host and port of a URL; what is logged and shown of a proxy
+fn redact(url: &Url) -> String {
+    match url.port_or_known_default() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), url.host_str().unwrap_or_default(), port),
+        None => format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default()),
+    }
+}
+
+fn parse_proxy(name: &str, value: &str) -> Result<Url> {
+    let url = Url::parse(value).map_err(|e| anyhow!("Invalid proxy.{}: {}", name, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(anyhow!("proxy.{} must be an http:// or https:// URL with a host", name));
+    }
+    // Would end up in logs and config dumps
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(anyhow!("proxy.{} must not contain credentials, set proxy.username with password_file or password_env", name));
+    }
+    Ok(url)
+}
+
+// Whether a no_proxy entry covers the host: "*", the host itself, a parent domain
+// (with or without the leading dot) or a network containing the address
+fn bypassed(no_proxy: &[String], host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+    let address = host.parse::<IpAddr>().ok();
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().to_lowercase();
+        if entry == "*" || entry == host {
+            return true;
+        }
+        match (address, entry.parse::<IpNetwork>()) {
+            (Some(address), Ok(network)) => network.contains(address),
+            _ => {
+                let domain = entry.trim_start_matches('.');
+                !domain.is_empty() && host.ends_with(&format!(".{}", domain))
+            },
+        }
+    })
+}
+
+// Settings an integration's requests go out with, as shown to admins
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveProxy {
+    pub integration: String,
+    pub mode: ProxyMode,
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub authenticated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectivityStep {
+    // dns, connect, proxy_tls, proxy_connect, tls or http
+    pub step: &'static str,
+    pub ok: bool,
+    pub detail: String,
+    pub duration_ms: u64,
+}
+
+// Steps up to and including the first failure
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectivityReport {
+    pub url: String,
+    pub settings: EffectiveProxy,
+    // Proxy the request went through; None when it went direct
+    pub via: Option<String>,
+    pub steps: Vec<ConnectivityStep>,
+    pub success: bool,
+}
+
+impl ConnectivityReport {
+    // Records a step; None ends the chain
+    fn step<T>(&mut self, step: &'static str, started: Instant, result: Result<(T, String)>) -> Option<T> {
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let (value, ok, detail) = match result {
+            Ok((value, detail)) => (Some(value), true, detail),
+            Err(e) => (None, false, e.to_string()),
+        };
+        self.steps.push(ConnectivityStep { step, ok, detail, duration_ms });
+        value
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// Builds every reqwest client that leaves the appliance. Proxies come from [proxy]
+// alone, HTTP_PROXY and friends in the environment are ignored so the config is the
+// one place that decides.
+#[derive(Clone)]
+pub struct HttpClients {
+    http_proxy: Option<Url>,
+    https_proxy: Option<Url>,
+    no_proxy: Vec<String>,
+    credentials: Option<(String, String)>,
+    default_mode: ProxyMode,
+    integrations: HashMap<String, ProxyMode>,
+    test_url: String,
+    tls: TlsConnector,
+}
+
+impl HttpClients {
+    pub fn new(config: &ProxyConfig) -> Result<Self> {
+        let http_proxy = config.http_proxy.as_deref().map(|url| parse_proxy("http_proxy", url)).transpose()?;
+        let https_proxy = config.https_proxy.as_deref().map(|url| parse_proxy("https_proxy", url)).transpose()?;
+
+        let credentials = match &config.username {
+            Some(username) => {
+                let password = match (&config.password_file, &config.password_env) {
+                    (Some(path), _) => fs::read_to_string(path)
+                        .context(format!("Failed to read proxy password file: {}", path))?
+                        .trim()
+                        .to_string(),
+                    (None, Some(var)) => std::env::var(var)
+                        .context(format!("Proxy password environment variable {} is not set", var))?,
+                    (None, None) => return Err(anyhow!("proxy.username needs password_file or password_env")),
+                };
+                Some((username.clone(), password))
+            },
+            None => None,
+        };
+
+        if http_proxy.is_some() || https_proxy.is_some() {
+            info!("Outbound HTTP through proxy (http: {}, https: {}, authenticated: {})",
+                  http_proxy.as_ref().map_or("none".to_string(), redact),
+                  https_proxy.as_ref().map_or("none".to_string(), redact),
+                  credentials.is_some());
+        }
+
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let tls = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            http_proxy,
+            https_proxy,
+            no_proxy: config.no_proxy.clone(),
+            credentials,
+            default_mode: config.default_mode,
+            integrations: config.integrations.clone(),
+            test_url: config.test_url.clone(),
+            tls: TlsConnector::from(Arc::new(tls)),
+        })
+    }
+
+    fn mode(&self, integration: &str) -> ProxyMode {
+        self.integrations.get(integration).copied().unwrap_or(self.default_mode)
+    }
+
+    fn proxy(&self, proxy: Proxy) -> Proxy {
+        let proxy = proxy.no_proxy(NoProxy::from_string(&self.no_proxy.join(",")));
+        match &self.credentials {
+            Some((username, password)) => proxy.basic_auth(username, password),
+            None => proxy,
+        }
+    }
+
+    // https destinations are tunnelled through the proxy with CONNECT
+    pub fn builder(&self, integration: &str) -> Result<ClientBuilder> {
+        let mut builder = Client::builder().no_proxy();
+        if self.mode(integration) == ProxyMode::Direct {
+            return Ok(builder);
+        }
+        if let Some(url) = &self.http_proxy {
+            builder = builder.proxy(self.proxy(Proxy::http(url.as_str())?));
+        }
+        if let Some(url) = &self.https_proxy {
+            builder = builder.proxy(self.proxy(Proxy::https(url.as_str())?));
+        }
+        Ok(builder)
+    }
+
+    pub fn client(&self, integration: &str, timeout: Duration) -> Result<Client> {
+        self.builder(integration)?
+            .timeout(timeout)
+            .build()
+            .context("Failed to create HTTP client")
+    }
+
+    pub fn effective(&self, integration: &str) -> EffectiveProxy {
+        EffectiveProxy {
+            integration: integration.to_string(),
+            mode: self.mode(integration),
+            http_proxy: self.http_proxy.as_ref().map(redact),
+            https_proxy: self.https_proxy.as_ref().map(redact),
+            no_proxy: self.no_proxy.clone(),
+            authenticated: self.credentials.is_some(),
+        }
+    }
+
+    // Proxy a request to the URL goes through, the same choice reqwest makes
+    fn route(&self, integration: &str, url: &Url) -> Option<&Url> {
+        if self.mode(integration) == ProxyMode::Direct || bypassed(&self.no_proxy, url.host_str().unwrap_or_default()) {
+            return None;
+        }
+        match url.scheme() {
+            "https" => self.https_proxy.as_ref(),
+            _ => self.http_proxy.as_ref(),
+        }
+    }
+
+    async fn tls_handshake(&self, host: &str, stream: Box<dyn Stream>) -> Result<(Box<dyn Stream>, String)> {
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|_| anyhow!("Invalid TLS server name: {}", host))?;
+        let stream = tokio::time::timeout(STEP_TIMEOUT, self.tls.connect(server_name, stream)).await
+            .map_err(|_| anyhow!("TLS handshake with {} timed out", host))?
+            .context(format!("TLS handshake with {} failed", host))?;
+        let version = stream.get_ref().1.protocol_version()
+            .map_or("unknown version".to_string(), |v| format!("{:?}", v));
+        Ok((Box::new(stream), format!("certificate of {} verified, {}", host, version)))
+    }
+
+    // Opens a tunnel to host:port; the proxy's answer is reported, never the request
+    async fn proxy_connect(&self, host: &str, port: u16, mut stream: Box<dyn Stream>) -> Result<(Box<dyn Stream>, String)> {
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some((username, password)) = &self.credentials {
+            let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+
+        let exchange = async {
+            stream.write_all(request.as_bytes()).await?;
+            stream.flush().await?;
+            let mut reader = BufReader::new(stream);
+            let mut status = String::new();
+            reader.read_line(&mut status).await?;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                    break;
+                }
+            }
+            Ok::<_, std::io::Error>((reader.into_inner(), status.trim().to_string()))
+        };
+        let (stream, status) = tokio::time::timeout(STEP_TIMEOUT, exchange).await
+            .map_err(|_| anyhow!("Proxy did not answer CONNECT in time"))??;
+
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok((stream, format!("tunnel to {}:{} open ({})", host, port, status))),
+            Some("407") => Err(anyhow!("Proxy requires authentication or rejected the credentials ({})", status)),
+            _ => Err(anyhow!("Proxy refused the tunnel: {}", status)),
+        }
+    }
+
+    // Fetches the URL (default: proxy.test_url) with an integration's settings, step by
+    // step: resolution of and connection to the first hop, tunnel and TLS where they
+    // apply, then the request itself through the integration's client
+    pub async fn connectivity_test(&self, url: Option<&str>, integration: &str) -> Result<ConnectivityReport> {
+        let url = Url::parse(url.unwrap_or(&self.test_url)).map_err(|e| anyhow!("Invalid URL: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(anyhow!("Only http and https URLs can be tested"));
+        }
+        let host = url.host_str().ok_or_else(|| anyhow!("URL has no host"))?.to_string();
+        let port = url.port_or_known_default().unwrap_or(443);
+
+        let via = self.route(integration, &url).cloned();
+        let mut report = ConnectivityReport {
+            url: url.to_string(),
+            settings: self.effective(integration),
+            via: via.as_ref().map(redact),
+            steps: Vec::new(),
+            success: false,
+        };
+
+        let (hop_host, hop_port) = match &via {
+            Some(proxy) => (proxy.host_str().unwrap_or_default().to_string(), proxy.port_or_known_default().unwrap_or(80)),
+            None => (host.clone(), port),
+        };
+
+        let started = Instant::now();
+        let resolved = match tokio::time::timeout(STEP_TIMEOUT, tokio::net::lookup_host((hop_host.as_str(), hop_port))).await {
+            Ok(Ok(addresses)) => {
+                let addresses: Vec<_> = addresses.collect();
+                let listed = addresses.iter().map(|a| a.ip().to_string()).collect::<Vec<_>>().join(", ");
+                Ok((addresses, format!("{} resolved to {}", hop_host, listed)))
+            },
+            Ok(Err(e)) => Err(anyhow!("Failed to resolve {}: {}", hop_host, e)),
+            Err(_) => Err(anyhow!("Resolving {} timed out", hop_host)),
+        };
+        let Some(addresses) = report.step("dns", started, resolved) else { return Ok(report) };
+
+        let started = Instant::now();
+        let connected = match tokio::time::timeout(STEP_TIMEOUT, TcpStream::connect(&addresses[..])).await {
+            Ok(Ok(stream)) => {
+                let peer = stream.peer_addr().map_or(hop_host.clone(), |a| a.to_string());
+                Ok((Box::new(stream) as Box<dyn Stream>, format!("connected to {}", peer)))
+            },
+            Ok(Err(e)) => Err(anyhow!("Failed to connect to {}:{}: {}", hop_host, hop_port, e)),
+            Err(_) => Err(anyhow!("Connecting to {}:{} timed out", hop_host, hop_port)),
+        };
+        let Some(mut stream) = report.step("connect", started, connected) else { return Ok(report) };
+
+        if via.as_ref().map_or(false, |proxy| proxy.scheme() == "https") {
+            let started = Instant::now();
+            let result = self.tls_handshake(&hop_host, stream).await;
+            let Some(tls) = report.step("proxy_tls", started, result) else { return Ok(report) };
+            stream = tls;
+        }
+
+        if url.scheme() == "https" {
+            if via.is_some() {
+                let started = Instant::now();
+                let result = self.proxy_connect(&host, port, stream).await;
+                let Some(tunnel) = report.step("proxy_connect", started, result) else { return Ok(report) };
+                stream = tunnel;
+            }
+            let started = Instant::now();
+            let result = self.tls_handshake(&host, stream).await;
+            if report.step("tls", started, result).is_none() {
+                return Ok(report);
+            }
+        }
+
+        let started = Instant::now();
+        let result = async {
+            let client = self.client(integration, STEP_TIMEOUT)?;
+            let response = client.get(url.clone()).send().await
+                .map_err(|e| anyhow!("Request failed: {}", e.without_url()))?;
+            let status = response.status();
+            if status.is_server_error() {
+                return Err(anyhow!("HTTP {}", status));
+            }
+            Ok(((), format!("HTTP {}", status)))
+        }.await;
+        report.success = report.step("http", started, result).is_some();
+        Ok(report)
+    }
+}
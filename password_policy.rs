@@ -0,0 +1,116 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Duration, Utc};
+use tracing::info;
+
+use crate::config::PasswordPolicyConfig;
+
+// Most frequent passwords from public breach corpora, compared case-insensitively
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "123456789", "12345678", "12345", "1234567", "1234567890", "123123",
+    "111111", "000000", "654321", "666666", "121212", "112233", "987654321",
+    "password", "password1", "password12", "password123", "password1234", "passw0rd",
+    "p@ssw0rd", "p@ssword", "qwerty", "qwerty123", "qwertyuiop", "1q2w3e4r", "1q2w3e4r5t",
+    "1qaz2wsx", "zaq12wsx", "asdfghjkl", "asdf1234", "zxcvbnm", "abc123", "abcd1234",
+    "iloveyou", "princess", "sunshine", "monkey", "dragon", "football", "baseball",
+    "letmein", "letmein123", "welcome", "welcome1", "welcome123", "admin", "admin123",
+    "administrator", "root", "toor", "changeme", "change-me", "secret", "master",
+    "shadow", "superman", "batman", "trustno1", "starwars", "whatever", "freedom",
+    "hello123", "login", "guest", "default", "summer2024", "winter2024", "spring2024",
+    "autumn2024", "summer2025", "winter2025", "spring2025", "autumn2025", "company123",
+    "qazwsx", "michael", "jennifer", "charlie", "jordan23", "computer", "internet",
+    "heslo", "heslo123", "aaaaaa", "aaaaaaaaaaaa", "123qwe", "qwe123",
+    "q1w2e3r4", "q1w2e3r4t5y6", "11111111", "00000000", "123456123456",
+];
+
+// Violations of the password policy, reported together
+#[derive(Debug, Clone)]
+pub struct PolicyViolations(pub Vec<String>);
+
+impl fmt::Display for PolicyViolations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Password does not meet the policy: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for PolicyViolations {}
+
+// The active password policy; clones share it, so a reload applies everywhere
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    config: Arc<RwLock<PasswordPolicyConfig>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(config: PasswordPolicyConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> PasswordPolicyConfig {
+        match self.config.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    // Replaces the policy, returns whether anything changed
+    pub fn reload(&self, config: PasswordPolicyConfig) -> bool {
+        let mut current = match self.config.write() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if *current == config {
+            return false;
+        }
+
+        info!("Password policy updated: {:?}", config);
+        *current = config;
+        true
+    }
+
+    // Every rule the password breaks; reuse of earlier passwords is checked by the caller
+    pub fn validate(&self, username: &str, password: &str) -> Vec<String> {
+        let config = self.get();
+        let mut violations = Vec::new();
+
+        if password.chars().count() < config.min_length {
+            violations.push(format!("must be at least {} characters long", config.min_length));
+        }
+        if config.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            violations.push("must contain an uppercase letter".to_string());
+        }
+        if config.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            violations.push("must contain a lowercase letter".to_string());
+        }
+        if config.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push("must contain a digit".to_string());
+        }
+        if config.require_symbol && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            violations.push("must contain a symbol".to_string());
+        }
+
+        let lower = password.to_lowercase();
+        if config.disallow_username && !username.is_empty() && lower.contains(&username.to_lowercase()) {
+            violations.push("must not contain the username".to_string());
+        }
+        if config.disallow_common && COMMON_PASSWORDS.iter().any(|common| common.eq_ignore_ascii_case(&lower)) {
+            violations.push("is a commonly used password".to_string());
+        }
+
+        violations
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.get().history_size
+    }
+
+    pub fn is_expired(&self, changed_at: DateTime<Utc>) -> bool {
+        match self.get().max_age_days {
+            Some(days) => changed_at + Duration::days(days as i64) <= Utc::now(),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use directories::ProjectDirs;
+use nix::sys::statvfs::statvfs;
+use nix::unistd::Uid;
+use serde::Serialize;
+use anyhow::{Result, anyhow};
+use tracing::info;
+
+use crate::config::Config;
+
+// Directories the server writes to, resolved once at startup
+#[derive(Debug, Clone, Serialize)]
+pub struct Paths {
+    pub config_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub scripts_dir: PathBuf,
+    pub log_dir: PathBuf,
+    pub temp_dir: PathBuf,
+    pub reports_dir: PathBuf,
+    pub topology_dir: PathBuf,
+    pub attachments_dir: PathBuf,
+    pub backups_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PathStatus {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+// Writes a file only its owner can read, also when it existed with a wider mode
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_ref())?;
+    Ok(())
+}
+
+// Free (available to unprivileged users) and total bytes of the filesystem holding `path`
+pub fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    let stat = statvfs(path).ok()?;
+    let fragment = stat.fragment_size() as u64;
+    Some((stat.blocks_available() as u64 * fragment, stat.blocks() as u64 * fragment))
+}
+
+pub fn validate_dir(name: &str, path: &Path) -> Result<()> {
+    fs::create_dir_all(path)
+        .map_err(|e| anyhow!("Cannot create {} directory {}: {}", name, path.display(), e))?;
+
+    let probe = path.join(".write-test");
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| anyhow!("The {} directory {} is not writable: {}", name, path.display(), e))?;
+
+    info!("Using {} directory {}", name, path.display());
+    Ok(())
+}
+
+// Base directories used when the config leaves a path empty: /var/lib/siem and
+// /var/log/siem for root, the XDG data and state directories for everyone else
+fn default_bases() -> Result<(PathBuf, PathBuf)> {
+    if Uid::effective().is_root() {
+        return Ok((PathBuf::from("/var/lib/siem"), PathBuf::from("/var/log/siem")));
+    }
+
+    let dirs = ProjectDirs::from("", "", "siem")
+        .ok_or_else(|| anyhow!("Cannot determine a home directory, set data_dir and log_dir in the config"))?;
+    let data = dirs.data_dir().to_path_buf();
+    let state = dirs.state_dir().map(|d| d.to_path_buf()).unwrap_or_else(|| data.clone());
+
+    Ok((data, state.join("logs")))
+}
+
+// Explicit config value if set, relative values are taken from the config file's directory
+fn explicit(base: &Path, value: &str) -> Option<PathBuf> {
+    match value.trim() {
+        "" => None,
+        value => Some(base.join(value)),
+    }
+}
+
+fn explicit_opt(base: &Path, value: &Option<String>) -> Option<PathBuf> {
+    value.as_deref().and_then(|v| explicit(base, v))
+}
+
+impl Paths {
+    pub fn resolve(config: &Config, config_file: &Path) -> Result<Self> {
+        let config_file = std::path::absolute(config_file)?;
+        let base = config_file.parent().map(Path::to_path_buf).unwrap_or_default();
+        let (default_data, default_logs) = default_bases()?;
+
+        let data_dir = explicit(&base, &config.data_dir).unwrap_or(default_data);
+        let dirs = &config.paths;
+
+        Ok(Self {
+            scripts_dir: explicit(&base, &config.scripts_dir).unwrap_or_else(|| data_dir.join("scripts")),
+            log_dir: explicit(&base, &config.log_dir).unwrap_or(default_logs),
+            temp_dir: explicit_opt(&base, &dirs.temp_dir).unwrap_or_else(|| data_dir.join("tmp")),
+            reports_dir: explicit_opt(&base, &dirs.reports_dir).unwrap_or_else(|| data_dir.join("reports")),
+            topology_dir: explicit_opt(&base, &dirs.topology_dir).unwrap_or_else(|| data_dir.join("topology")),
+            attachments_dir: explicit_opt(&base, &dirs.attachments_dir).unwrap_or_else(|| data_dir.join("attachments")),
+            backups_dir: explicit_opt(&base, &dirs.backups_dir).unwrap_or_else(|| data_dir.join("backups")),
+            data_dir,
+            config_file,
+        })
+    }
+
+    pub fn entries(&self) -> Vec<(&'static str, &Path)> {
+        vec![
+            ("data", &self.data_dir),
+            ("scripts", &self.scripts_dir),
+            ("logs", &self.log_dir),
+            ("temp", &self.temp_dir),
+            ("reports", &self.reports_dir),
+            ("topology", &self.topology_dir),
+            ("attachments", &self.attachments_dir),
+            ("backups", &self.backups_dir),
+        ]
+    }
+
+    // Creates every directory and probes it with a write so startup fails early,
+    // naming the directory, instead of on the first write at runtime. The skipped ones
+    // belong to a subsystem that may start disabled and are checked as part of its
+    // initialization, see startup.
+    pub fn validate_except(&self, skip: &[&str]) -> Result<()> {
+        for (name, path) in self.entries() {
+            if !skip.contains(&name) {
+                validate_dir(name, path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn status(&self) -> Vec<PathStatus> {
+        self.entries()
+            .into_iter()
+            .map(|(name, path)| {
+                let space = disk_space(path);
+                PathStatus {
+                    name,
+                    path: path.to_path_buf(),
+                    free_bytes: space.map(|(free, _)| free),
+                    total_bytes: space.map(|(_, total)| total),
+                }
+            })
+            .collect()
+    }
+}
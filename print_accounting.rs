@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::PrintAccountingConfig;
+use crate::models::AlertSeverity;
+use crate::notifications::Notifier;
+use crate::printers::PrintJob;
+use crate::users::UserManager;
+
+// Percentages of a quota that raise a warning, each once per month
+const THRESHOLDS: [u8; 2] = [80, 100];
+
+// Pages and jobs of one user in one month
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserMonth {
+    pub jobs: u64,
+    pub pages: u64,
+    // Part of the totals estimated from the job size
+    pub estimated_jobs: u64,
+    pub estimated_pages: u64,
+    pub pages_by_printer: HashMap<Uuid, u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct MonthTotals {
+    users: HashMap<String, UserMonth>,
+    // Quota key ("user:<name>" or "group:<name>") -> highest threshold already warned about
+    warned: HashMap<String, u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    // "user" for the user's own quota, otherwise the group name
+    pub quota: String,
+    pub used_pages: u64,
+    pub monthly_pages: u64,
+}
+
+// A user's month as returned by GET /api/printers/accounting
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountingEntry {
+    pub user: String,
+    pub month: String,
+    #[serde(flatten)]
+    pub totals: UserMonth,
+    pub quotas: Vec<QuotaUsage>,
+}
+
+// A threshold crossed by the last job
+struct Crossing {
+    quota: String,
+    threshold: u8,
+    used_pages: u64,
+    monthly_pages: u64,
+    recipients: Vec<String>,
+}
+
+pub fn month_of(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m").to_string()
+}
+
+pub fn validate_month(month: &str) -> Result<()> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map(|_| ())
+        .map_err(|_| anyhow!("Invalid month, expected YYYY-MM: {}", month))
+}
+
+// Totals are kept per month and updated as each job completes, one file per month
+#[derive(Clone)]
+pub struct PrintAccounting {
+    dir: PathBuf,
+    config: PrintAccountingConfig,
+    months: Arc<Mutex<HashMap<String, MonthTotals>>>,
+    alerts: AlertsManager,
+    users: UserManager,
+    notifier: Notifier,
+}
+
+impl PrintAccounting {
+    pub fn new(dir: &str,
+               config: PrintAccountingConfig,
+               alerts: AlertsManager,
+               users: UserManager,
+               notifier: Notifier) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create print accounting directory: {:?}", dir))?;
+            info!("Created print accounting directory: {:?}", dir);
+        }
+
+        let mut months = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let month = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(month) if path.extension().and_then(|e| e.to_str()) == Some("json") => month.to_string(),
+                _ => continue,
+            };
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read print accounting file: {:?}", path))?;
+            match serde_json::from_str::<MonthTotals>(&contents) {
+                Ok(totals) => {
+                    months.insert(month, totals);
+                },
+                Err(e) => warn!("Skipping invalid print accounting file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded print accounting for {} months", months.len());
+
+        Ok(Self {
+            dir,
+            config,
+            months: Arc::new(Mutex::new(months)),
+            alerts,
+            users,
+            notifier,
+        })
+    }
+
+    fn save_month(&self, month: &str, totals: &MonthTotals) -> Result<()> {
+        let path = self.dir.join(format!("{}.json", month));
+        let json = serde_json::to_string_pretty(totals)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write print accounting file: {:?}", path))?;
+        Ok(())
+    }
+
+    // Pages of a job the printer gave no count for; at least one page
+    pub fn estimate_pages(&self, size_kb: Option<u32>) -> u32 {
+        let kb_per_page = if self.config.kb_per_page > 0.0 { self.config.kb_per_page } else { 100.0 };
+        size_kb.map_or(1, |kb| ((kb as f64 / kb_per_page).ceil() as u32).max(1))
+    }
+
+    // Quotas that apply to the user with the members they are shared by
+    fn quotas_of(&self, user: &str) -> Vec<(String, u64, Vec<String>)> {
+        let mut quotas = Vec::new();
+        if let Some(limit) = self.config.user_quotas.get(user) {
+            quotas.push(("user".to_string(), *limit, vec![user.to_string()]));
+        }
+        let mut groups: Vec<_> = self.config.groups.iter()
+            .filter(|(_, group)| group.members.iter().any(|m| m == user))
+            .collect();
+        groups.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, group) in groups {
+            quotas.push((name.clone(), group.monthly_pages, group.members.clone()));
+        }
+        quotas
+    }
+
+    fn used_pages(totals: &MonthTotals, members: &[String]) -> u64 {
+        members.iter()
+            .filter_map(|m| totals.users.get(m))
+            .map(|u| u.pages)
+            .sum()
+    }
+
+    // Counts a completed job; jobs without a page count must already carry the estimate
+    pub fn record(&self, printer_id: Uuid, job: &PrintJob, completed_at: DateTime<Utc>) -> Result<()> {
+        let month = month_of(completed_at);
+        let pages = u64::from(job.pages.unwrap_or(0));
+
+        let crossings = match self.months.lock() {
+            Ok(mut months) => {
+                let totals = months.entry(month.clone()).or_default();
+                let user = totals.users.entry(job.user.clone()).or_default();
+                user.jobs += 1;
+                user.pages += pages;
+                if job.pages_estimated {
+                    user.estimated_jobs += 1;
+                    user.estimated_pages += pages;
+                }
+                *user.pages_by_printer.entry(printer_id).or_insert(0) += pages;
+
+                let mut crossings = Vec::new();
+                for (quota, limit, members) in self.quotas_of(&job.user) {
+                    if limit == 0 {
+                        continue;
+                    }
+                    let used = Self::used_pages(totals, &members);
+                    let reached = THRESHOLDS.iter()
+                        .rev()
+                        .find(|t| used * 100 >= limit * u64::from(**t))
+                        .copied();
+
+                    let key = if quota == "user" { format!("user:{}", job.user) } else { format!("group:{}", quota) };
+                    let warned = totals.warned.get(&key).copied().unwrap_or(0);
+                    if let Some(threshold) = reached.filter(|t| *t > warned) {
+                        totals.warned.insert(key, threshold);
+                        crossings.push(Crossing {
+                            quota,
+                            threshold,
+                            used_pages: used,
+                            monthly_pages: limit,
+                            recipients: members,
+                        });
+                    }
+                }
+
+                self.save_month(&month, totals)?;
+                crossings
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on print accounting")),
+        };
+
+        for crossing in crossings {
+            self.warn_quota(&job.user, &month, crossing)?;
+        }
+        Ok(())
+    }
+
+    fn warn_quota(&self, user: &str, month: &str, crossing: Crossing) -> Result<()> {
+        let subject = if crossing.quota == "user" {
+            format!("Print quota of {}", user)
+        } else {
+            format!("Print quota of group {}", crossing.quota)
+        };
+        let title = format!("{} at {}% for {}", subject, crossing.threshold, month);
+        let description = format!("{} of {} pages used in {}; the last job was printed by {}",
+                                  crossing.used_pages, crossing.monthly_pages, month, user);
+
+        // Medium is the warning level of alerts
+        self.alerts.create_alert(
+            AlertSeverity::Medium,
+            title.clone(),
+            description.clone(),
+            "print_quota".to_string(),
+            Vec::new(),
+        )?;
+
+        let recipients: Vec<String> = crossing.recipients.iter()
+            .filter_map(|member| self.users.get_user(member).ok())
+            .filter(|u| u.is_active && u.notifications.email && !u.email.is_empty())
+            .map(|u| u.email)
+            .collect();
+        if recipients.is_empty() {
+            return Ok(());
+        }
+
+        let notifier = self.notifier.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notifier.send_email(&recipients, &title, &description).await {
+                warn!("Failed to email print quota warning: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    // Every user's month unless a user is given; sorted by pages, most first
+    pub fn accounting(&self, user: Option<&str>, month: &str) -> Result<Vec<AccountingEntry>> {
+        validate_month(month)?;
+
+        match self.months.lock() {
+            Ok(months) => {
+                let totals = match months.get(month) {
+                    Some(totals) => totals,
+                    None => return Ok(Vec::new()),
+                };
+
+                let mut entries: Vec<AccountingEntry> = totals.users.iter()
+                    .filter(|(name, _)| user.map_or(true, |u| u == name.as_str()))
+                    .map(|(name, usage)| AccountingEntry {
+                        user: name.clone(),
+                        month: month.to_string(),
+                        totals: usage.clone(),
+                        quotas: self.quotas_of(name).into_iter()
+                            .map(|(quota, monthly_pages, members)| QuotaUsage {
+                                quota,
+                                used_pages: Self::used_pages(totals, &members),
+                                monthly_pages,
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                entries.sort_by(|a, b| b.totals.pages.cmp(&a.totals.pages).then(a.user.cmp(&b.user)));
+                Ok(entries)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on print accounting")),
+        }
+    }
+}
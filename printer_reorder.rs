@@ -0,0 +1,105 @@
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::config::PrinterReorderConfig;
+use crate::notifications::Notifier;
+use crate::printers::ReorderEvent;
+use crate::tickets::{TicketCategory, TicketPriority, TicketsManager};
+
+// Hands supplies that ran low to purchasing: a webhook for their procurement system
+// and optionally a pre-filled Hardware ticket. Duplicates are suppressed upstream, see
+// PrinterManager::update_supply_levels.
+#[derive(Clone)]
+pub struct ReorderHook {
+    config: PrinterReorderConfig,
+    notifier: Notifier,
+    tickets: TicketsManager,
+}
+
+impl ReorderHook {
+    pub fn new(config: PrinterReorderConfig, notifier: Notifier, tickets: TicketsManager) -> Self {
+        Self {
+            config,
+            notifier,
+            tickets,
+        }
+    }
+
+    // The supply's own part number, else the catalog entry for the model
+    fn part_number(&self, event: &ReorderEvent) -> Option<String> {
+        event.part_number.clone().or_else(|| {
+            self.config.part_numbers.get(&event.model)
+                .and_then(|parts| parts.get(&event.supply_name))
+                .cloned()
+        })
+    }
+
+    // Failures are logged per event; the supply stays marked as reordered either way
+    pub async fn dispatch(&self, events: Vec<ReorderEvent>) {
+        for mut event in events {
+            event.part_number = self.part_number(&event);
+            info!("Supply {} of printer {} needs reordering ({}%)", event.supply_name, event.printer_name, event.level);
+
+            if let Some(url) = &self.config.webhook_url {
+                let payload = serde_json::json!({
+                    "event": "printer.supply_reorder",
+                    "printer": {
+                        "id": event.printer_id,
+                        "name": event.printer_name,
+                        "model": event.model,
+                    },
+                    "location": event.location,
+                    "location_id": event.location_id,
+                    "supply": event.supply_name,
+                    "supply_type": event.supply_type,
+                    "part_number": event.part_number,
+                    "level": event.level,
+                    "status": event.status,
+                    "estimated_days_remaining": event.days_remaining.map(|d| d.floor()),
+                });
+                if let Err(e) = self.notifier.post_webhook(url, &payload).await {
+                    warn!("Failed to post reorder of {} for printer {}: {}", event.supply_name, event.printer_name, e);
+                }
+            }
+
+            if self.config.create_ticket {
+                if let Err(e) = self.create_ticket(&event) {
+                    warn!("Failed to create reorder ticket for printer {}: {}", event.printer_name, e);
+                }
+            }
+        }
+    }
+
+    fn create_ticket(&self, event: &ReorderEvent) -> Result<()> {
+        let days = event.days_remaining
+            .map(|d| format!("about {:.0} days", d.floor()))
+            .unwrap_or_else(|| "unknown".to_string());
+        let description = format!(
+            "Printer: {} ({})\nLocation: {}\nSupply: {} ({:?}), {}% {:?}\nPart number: {}\nEstimated time remaining: {}",
+            event.printer_name,
+            event.model,
+            event.location,
+            event.supply_name,
+            event.supply_type,
+            event.level,
+            event.status,
+            event.part_number.as_deref().unwrap_or("not set"),
+            days,
+        );
+
+        let mut tags = vec!["printer_reorder".to_string()];
+        tags.extend(event.part_number.as_ref().map(|part| format!("part:{}", part)));
+
+        self.tickets.create_ticket(
+            format!("Reorder {} for {}", event.supply_name, event.printer_name),
+            description,
+            TicketPriority::Medium,
+            "system".to_string(),
+            TicketCategory::Hardware,
+            tags,
+            None,
+            event.site_id,
+        )?;
+        Ok(())
+    }
+}
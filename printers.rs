@@ -5,6 +5,10 @@ use uuid::Uuid;
 use anyhow::{Result, anyhow};
 use tracing::{info, error, warn};
 
+use crate::locations::{location_path, LocationKind, LocationNode};
+use crate::print_accounting::PrintAccounting;
+use crate::sites::SiteScope;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Printer {
     pub id: Uuid,
@@ -13,6 +17,10 @@ pub struct Printer {
     pub mac_address: Option<String>,
     pub model: String,
     pub location: String,
+    #[serde(default)]
+    pub location_id: Option<Uuid>,
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
     pub status: PrinterStatus,
     pub last_seen: DateTime<Utc>,
     pub supplies: Vec<PrinterSupply>,
@@ -37,6 +45,45 @@ pub struct PrinterSupply {
     pub status: SupplyStatus,
     pub capacity: Option<u32>, // Pages or ml
     pub last_replaced: Option<DateTime<Utc>>,
+    // When the supply last went from OK (or unknown) to Low or Empty
+    #[serde(default)]
+    pub low_since: Option<DateTime<Utc>>,
+    // Vendor part to reorder; the model catalog in [printer_reorder] is used when unset
+    #[serde(default)]
+    pub part_number: Option<String>,
+    // When a reorder event was last emitted; no other is emitted until the supply is replaced
+    #[serde(default)]
+    pub reorder_sent_at: Option<DateTime<Utc>>,
+}
+
+// A level rising by at least this many points means the supply was replaced
+const REPLACEMENT_JUMP: u8 = 20;
+
+// A supply that went Low or Empty and needs reordering, see printer_reorder
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorderEvent {
+    pub printer_id: Uuid,
+    pub printer_name: String,
+    pub model: String,
+    pub location: String,
+    pub location_id: Option<Uuid>,
+    pub site_id: Option<Uuid>,
+    pub supply_type: SupplyType,
+    pub supply_name: String,
+    pub part_number: Option<String>,
+    pub level: u8,
+    pub status: SupplyStatus,
+    pub days_remaining: Option<f64>,
+}
+
+// Days until the supply runs out at the rate it was used since it was last replaced
+fn days_remaining(supply: &PrinterSupply, now: DateTime<Utc>) -> Option<f64> {
+    let elapsed_days = (now - supply.last_replaced?).num_seconds() as f64 / 86400.0;
+    let used = 100u8.saturating_sub(supply.level) as f64;
+    if elapsed_days <= 0.0 || used <= 0.0 {
+        return None;
+    }
+    Some(supply.level as f64 / (used / elapsed_days))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -79,6 +126,9 @@ pub struct PrintJob {
     pub pages: Option<u32>,
     pub status: PrintJobStatus,
     pub size_kb: Option<u32>,
+    // Set when pages was estimated from size_kb at completion
+    #[serde(default)]
+    pub pages_estimated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -90,14 +140,62 @@ pub enum PrintJobStatus {
     Cancelled,
 }
 
+// Aggregated fleet state of one location node, including all nodes below it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationSummary {
+    pub location_id: Option<Uuid>,
+    pub kind: Option<LocationKind>,
+    pub path: String,
+    pub printers: usize,
+    pub status_counts: HashMap<String, usize>,
+    pub low_supply_printers: usize,
+    pub empty_supply_printers: usize,
+    pub queue_depth: usize,
+}
+
+impl LocationSummary {
+    fn new(location_id: Option<Uuid>, kind: Option<LocationKind>, path: String) -> Self {
+        Self {
+            location_id,
+            kind,
+            path,
+            printers: 0,
+            status_counts: HashMap::new(),
+            low_supply_printers: 0,
+            empty_supply_printers: 0,
+            queue_depth: 0,
+        }
+    }
+
+    fn add(&mut self, printer: &Printer) {
+        self.printers += 1;
+        *self.status_counts.entry(format!("{:?}", printer.status)).or_insert(0) += 1;
+
+        if printer.supplies.iter().any(|s| s.status == SupplyStatus::Low) {
+            self.low_supply_printers += 1;
+        }
+
+        if printer.supplies.iter().any(|s| s.status == SupplyStatus::Empty) {
+            self.empty_supply_printers += 1;
+        }
+
+        self.queue_depth += printer.queue_status.iter()
+            .filter(|j| j.status == PrintJobStatus::Pending || j.status == PrintJobStatus::Processing)
+            .count();
+    }
+}
+
 pub struct PrinterManager {
     printers: HashMap<Uuid, Printer>,
+    // Completed jobs are counted here when set
+    accounting: Option<PrintAccounting>,
 }
 
 impl PrinterManager {
     pub fn new() -> Self {
         PrinterManager {
             printers: HashMap::new(),
+            accounting: None,
         }
     }
     
@@ -147,6 +245,81 @@ impl PrinterManager {
         Ok(())
     }
     
+    pub fn set_printer_location(&mut self, id: &Uuid, location_id: Option<Uuid>) -> Result<()> {
+        let printer = self.printers.get_mut(id)
+            .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
+        
+        printer.location_id = location_id;
+        
+        info!("Moved printer {} to location {:?}", id, location_id);
+        Ok(())
+    }
+    
+    pub fn set_printer_site(&mut self, id: &Uuid, site_id: Option<Uuid>) -> Result<()> {
+        let printer = self.printers.get_mut(id)
+            .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
+        
+        printer.site_id = site_id;
+        
+        info!("Moved printer {} to site {:?}", id, site_id);
+        Ok(())
+    }
+    
+    pub fn count_at_location(&self, location_id: &Uuid) -> usize {
+        self.printers.values()
+            .filter(|p| p.location_id.as_ref() == Some(location_id))
+            .count()
+    }
+    
+    // Moves every printer of one location node to another, returns how many moved
+    pub fn reassign_location(&mut self, from: &Uuid, to: Option<Uuid>) -> usize {
+        let mut moved = 0;
+        
+        for printer in self.printers.values_mut() {
+            if printer.location_id.as_ref() == Some(from) {
+                printer.location_id = to;
+                moved += 1;
+            }
+        }
+        
+        info!("Reassigned {} printers from location {} to {:?}", moved, from, to);
+        moved
+    }
+    
+    // Groups the fleet by location in a single pass over the printers; each printer is
+    // counted on its own node and on every ancestor up to the site
+    pub fn summarize_by_location(&self, locations: &HashMap<Uuid, LocationNode>, scope: &SiteScope) -> Vec<LocationSummary> {
+        let mut summaries: HashMap<Uuid, LocationSummary> = locations.values()
+            .map(|node| (node.id, LocationSummary::new(Some(node.id), Some(node.kind), location_path(locations, node.id))))
+            .collect();
+        let mut unassigned = LocationSummary::new(None, None, "Unassigned".to_string());
+        
+        for printer in self.printers.values().filter(|p| scope.allows(p.site_id)) {
+            let mut current = printer.location_id.filter(|id| locations.contains_key(id));
+            
+            if current.is_none() {
+                unassigned.add(printer);
+                continue;
+            }
+            
+            while let Some(id) = current {
+                if let Some(summary) = summaries.get_mut(&id) {
+                    summary.add(printer);
+                }
+                current = locations.get(&id).and_then(|node| node.parent_id);
+            }
+        }
+        
+        let mut result: Vec<LocationSummary> = summaries.into_values().collect();
+        result.sort_by(|a, b| a.path.cmp(&b.path));
+        
+        if unassigned.printers > 0 {
+            result.push(unassigned);
+        }
+        
+        result
+    }
+    
     pub fn update_printer_status(&mut self, id: &Uuid, status: PrinterStatus) -> Result<()> {
         let printer = self.printers.get_mut(id)
             .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
@@ -159,15 +332,62 @@ impl PrinterManager {
         Ok(())
     }
     
-    pub fn update_supply_levels(&mut self, id: &Uuid, supplies: Vec<PrinterSupply>) -> Result<()> {
+    // Returns the supplies that need reordering: those Low or Empty without a reorder
+    // event since they were last replaced
+    pub fn update_supply_levels(&mut self, id: &Uuid, supplies: Vec<PrinterSupply>) -> Result<Vec<ReorderEvent>> {
         let printer = self.printers.get_mut(id)
             .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
         
+        let now = Utc::now();
+        let mut supplies = supplies;
+        let mut reorders = Vec::new();
+        for supply in supplies.iter_mut() {
+            let previous = printer.supplies.iter()
+                .find(|s| s.supply_type == supply.supply_type && s.name == supply.name);
+            let was_low = previous.map_or(false, |s| matches!(s.status, SupplyStatus::Low | SupplyStatus::Empty));
+            supply.low_since = match supply.status {
+                SupplyStatus::Low | SupplyStatus::Empty if was_low => previous.and_then(|s| s.low_since),
+                SupplyStatus::Low | SupplyStatus::Empty => Some(now),
+                _ => None,
+            };
+            
+            // Reported levels rarely carry these, so they are kept from the previous report
+            if let Some(previous) = previous {
+                supply.part_number = supply.part_number.take().or_else(|| previous.part_number.clone());
+                if supply.level >= previous.level.saturating_add(REPLACEMENT_JUMP) {
+                    info!("Supply {} of printer {} was replaced", supply.name, printer.name);
+                    supply.last_replaced = Some(now);
+                    supply.reorder_sent_at = None;
+                } else {
+                    supply.last_replaced = supply.last_replaced.or(previous.last_replaced);
+                    supply.reorder_sent_at = previous.reorder_sent_at;
+                }
+            }
+            
+            if matches!(supply.status, SupplyStatus::Low | SupplyStatus::Empty) && supply.reorder_sent_at.is_none() {
+                supply.reorder_sent_at = Some(now);
+                reorders.push(ReorderEvent {
+                    printer_id: printer.id,
+                    printer_name: printer.name.clone(),
+                    model: printer.model.clone(),
+                    location: printer.location.clone(),
+                    location_id: printer.location_id,
+                    site_id: printer.site_id,
+                    supply_type: supply.supply_type.clone(),
+                    supply_name: supply.name.clone(),
+                    part_number: supply.part_number.clone(),
+                    level: supply.level,
+                    status: supply.status.clone(),
+                    days_remaining: days_remaining(supply, now),
+                });
+            }
+        }
+        
         printer.supplies = supplies;
-        printer.last_seen = Utc::now();
+        printer.last_seen = now;
         
         info!("Updated supplies for printer: {}", id);
-        Ok(())
+        Ok(reorders)
     }
     
     pub fn add_print_job(&mut self, id: &Uuid, job: PrintJob) -> Result<()> {
@@ -186,7 +406,19 @@ impl PrinterManager {
         
         if let Some(job) = printer.queue_status.iter_mut().find(|j| j.id == job_id) {
             let status_clone = status.clone();
+            let completed = status == PrintJobStatus::Completed && job.status != PrintJobStatus::Completed;
             job.status = status;
+
+            if let (true, Some(accounting)) = (completed, &self.accounting) {
+                if job.pages.is_none() {
+                    job.pages = Some(accounting.estimate_pages(job.size_kb));
+                    job.pages_estimated = true;
+                }
+                // The job is completed either way, a failed count is only logged
+                if let Err(e) = accounting.record(*id, job, Utc::now()) {
+                    warn!("Failed to account print job {}: {}", job_id, e);
+                }
+            }
             
             info!("Updated print job {} status to {:?}", job_id, status_clone);
             Ok(())
@@ -195,6 +427,36 @@ impl PrinterManager {
         }
     }
     
+    // Queue as reported by the printer: new jobs are added, known ones take the reported
+    // status and page count, so jobs completing are accounted once
+    pub fn sync_print_queue(&mut self, id: &Uuid, jobs: Vec<PrintJob>) -> Result<()> {
+        if !self.printers.contains_key(id) {
+            return Err(anyhow!("Printer not found: {}", id));
+        }
+
+        for mut job in jobs {
+            let status = job.status.clone();
+            let printer = self.printers.get_mut(id)
+                .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
+            match printer.queue_status.iter_mut().find(|j| j.id == job.id) {
+                Some(known) => {
+                    if known.status != PrintJobStatus::Completed && job.pages.is_some() {
+                        known.pages = job.pages;
+                        known.pages_estimated = false;
+                    }
+                },
+                None => {
+                    // Added as queued so a job reported already done still completes below
+                    job.status = PrintJobStatus::Pending;
+                    job.pages_estimated = false;
+                    self.add_print_job(id, job.clone())?;
+                },
+            }
+            self.update_print_job(id, &job.id, status)?;
+        }
+        Ok(())
+    }
+
     pub fn clean_completed_jobs(&mut self, id: &Uuid, older_than_hours: u32) -> Result<u32> {
         let printer = self.printers.get_mut(id)
             .ok_or_else(|| anyhow!("Printer not found: {}", id))?;
@@ -213,8 +475,9 @@ impl PrinterManager {
     }
 }
 
-pub fn start() -> Result<PrinterManager> {
-    let manager = PrinterManager::new();
+pub fn start(accounting: PrintAccounting) -> Result<PrinterManager> {
+    let mut manager = PrinterManager::new();
+    manager.accounting = Some(accounting);
     info!("Printer manager started");
     Ok(manager)
 }
@@ -224,7 +487,6 @@ pub fn start() -> Result<PrinterManager> {
 
 mod logging {
     use crate::models::{LogEntry, LogSeverity};
-    use chrono::Utc;
     use std::fmt::Write;
     use tracing::{info, warn, error};
 
@@ -235,6 +497,7 @@ mod logging {
         writeln!(&mut output, "ID: {}", entry.id).unwrap();
         writeln!(&mut output, "Timestamp: {}", entry.timestamp).unwrap();
         writeln!(&mut output, "Source: {}", entry.source).unwrap();
+        writeln!(&mut output, "Category: {}", entry.category).unwrap();
         writeln!(&mut output, "Type: {}", entry.event_type).unwrap();
         writeln!(&mut output, "Severity: {:?}", entry.severity).unwrap();
         writeln!(&mut output, "Message: {}", entry.message).unwrap();
@@ -274,129 +537,88 @@ mod logging {
             },
         }
     }
+}
 
-    pub fn generate_incident_report(entries: &[LogEntry], title: &str) -> String {
-        let mut report = String::new();
-        let now = Utc::now();
-
-        writeln!(&mut report, "========================================").unwrap();
-        writeln!(&mut report, "INCIDENT REPORT: {}", title).unwrap();
-        writeln!(&mut report, "Generated at: {}", now).unwrap();
-        writeln!(&mut report, "Total Events: {}", entries.len()).unwrap();
-        writeln!(&mut report, "========================================").unwrap();
-        writeln!(&mut report).unwrap();
-
-        // Summary by severity
-        let mut debug_count = 0;
-        let mut info_count = 0;
-        let mut warning_count = 0;
-        let mut error_count = 0;
-        let mut critical_count = 0;
-
-        for entry in entries {
-            match entry.severity {
-                LogSeverity::Debug => debug_count += 1,
-                LogSeverity::Info => info_count += 1,
-                LogSeverity::Warning => warning_count += 1,
-                LogSeverity::Error => error_count += 1,
-                LogSeverity::Critical => critical_count += 1,
-            }
-        }
-
-        writeln!(&mut report, "SEVERITY SUMMARY:").unwrap();
-        writeln!(&mut report, "Debug:    {}", debug_count).unwrap();
-        writeln!(&mut report, "Info:     {}", info_count).unwrap();
-        writeln!(&mut report, "Warning:  {}", warning_count).unwrap();
-        writeln!(&mut report, "Error:    {}", error_count).unwrap();
-        writeln!(&mut report, "Critical: {}", critical_count).unwrap();
-        writeln!(&mut report).unwrap();
-
-        // List critical and error events first
-        if critical_count > 0 || error_count > 0 {
-            writeln!(&mut report, "CRITICAL AND ERROR EVENTS:").unwrap();
-            for entry in entries {
-                if entry.severity == LogSeverity::Critical || entry.severity == LogSeverity::Error {
-                    writeln!(&mut report, "- [{}] {} ({}): {}", 
-                             entry.timestamp, entry.source, entry.severity, entry.message).unwrap();
-                }
-            }
-            writeln!(&mut report).unwrap();
-        }
-
-        // Timeline of all events
-        writeln!(&mut report, "EVENT TIMELINE:").unwrap();
-        for entry in entries {
-            writeln!(&mut report, "[{}] {} - {}: {}", 
-                     entry.timestamp, entry.severity, entry.source, entry.message).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertsManager;
+    use crate::config::PrintAccountingConfig;
+    use crate::notifications::Notifier;
+    use crate::password_policy::PasswordPolicy;
+    use crate::print_accounting::month_of;
+    use crate::users::UserManager;
+
+    fn printer() -> Printer {
+        Printer {
+            id: Uuid::new_v4(),
+            name: "Office".to_string(),
+            ip_address: "192.168.1.20".to_string(),
+            mac_address: None,
+            model: "LaserJet".to_string(),
+            location: "Office".to_string(),
+            location_id: None,
+            site_id: None,
+            status: PrinterStatus::Online,
+            last_seen: Utc::now(),
+            supplies: Vec::new(),
+            capabilities: PrinterCapabilities {
+                color: false,
+                duplex: true,
+                paper_sizes: vec!["A4".to_string()],
+                scanner: false,
+                fax: false,
+                pages_per_minute: None,
+            },
+            queue_status: Vec::new(),
         }
-
-        report
     }
 
-    pub fn generate_compliance_report(entries: &[LogEntry], start_date: chrono::DateTime<Utc>, end_date: chrono::DateTime<Utc>) -> String {
-        let mut report = String::new();
-        let now = Utc::now();
-
-        writeln!(&mut report, "========================================").unwrap();
-        writeln!(&mut report, "COMPLIANCE REPORT").unwrap();
-        writeln!(&mut report, "Generated at: {}", now).unwrap();
-        writeln!(&mut report, "Period: {} to {}", start_date, end_date).unwrap();
-        writeln!(&mut report, "========================================").unwrap();
-        writeln!(&mut report).unwrap();
-
-        // Filter events in the date range
-        let filtered_entries: Vec<_> = entries.iter()
-            .filter(|e| e.timestamp >= start_date && e.timestamp <= end_date)
-            .collect();
-
-        writeln!(&mut report, "Total Events in Period: {}", filtered_entries.len()).unwrap();
-        writeln!(&mut report).unwrap();
-
-        // Security incidents summary
-        let security_incidents: Vec<_> = filtered_entries.iter()
-            .filter(|e| e.event_type.contains("security") && 
-                   (e.severity == LogSeverity::Error || e.severity == LogSeverity::Critical))
-            .collect();
-
-        writeln!(&mut report, "SECURITY INCIDENTS: {}", security_incidents.len()).unwrap();
-        for incident in &security_incidents {
-            writeln!(&mut report, "- [{}] {}: {}", 
-                     incident.timestamp, incident.source, incident.message).unwrap();
-        }
-        writeln!(&mut report).unwrap();
-
-        // Access control events
-        let access_events: Vec<_> = filtered_entries.iter()
-            .filter(|e| e.event_type.contains("access") || e.event_type.contains("authentication"))
-            .collect();
-
-        writeln!(&mut report, "ACCESS CONTROL EVENTS: {}", access_events.len()).unwrap();
-        let failed_access = access_events.iter()
-            .filter(|e| e.message.contains("failed") || e.message.contains("denied"))
-            .count();
-
-        writeln!(&mut report, "Failed Access Attempts: {}", failed_access).unwrap();
-        writeln!(&mut report).unwrap();
-
-        // System availability
-        let availability_incidents: Vec<_> = filtered_entries.iter()
-            .filter(|e| e.event_type.contains("availability") && e.severity == LogSeverity::Critical)
-            .collect();
-
-        writeln!(&mut report, "AVAILABILITY INCIDENTS: {}", availability_incidents.len()).unwrap();
-        for incident in &availability_incidents {
-            writeln!(&mut report, "- [{}] {}: {}", 
-                     incident.timestamp, incident.source, incident.message).unwrap();
+    fn job(id: &str, status: PrintJobStatus, pages: Option<u32>, size_kb: Option<u32>) -> PrintJob {
+        PrintJob {
+            id: id.to_string(),
+            name: format!("{}.pdf", id),
+            user: "alice".to_string(),
+            submitted_at: Utc::now(),
+            pages,
+            status,
+            size_kb,
+            pages_estimated: false,
         }
-        writeln!(&mut report).unwrap();
-
-        // Compliance summary
-        writeln!(&mut report, "COMPLIANCE SUMMARY:").unwrap();
-        writeln!(&mut report, "- Security Incident Rate: {:.2}%", 
-                if filtered_entries.is_empty() { 0.0 } else { (security_incidents.len() as f64 / filtered_entries.len() as f64) * 100.0 }).unwrap();
-        writeln!(&mut report, "- Failed Access Rate: {:.2}%", 
-                if access_events.is_empty() { 0.0 } else { (failed_access as f64 / access_events.len() as f64) * 100.0 }).unwrap();
+    }
 
-        report
+    #[test]
+    fn reported_jobs_are_accounted_once_when_they_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let accounting = PrintAccounting::new(
+            &path("accounting"),
+            PrintAccountingConfig::default(),
+            AlertsManager::new(&path("alerts")).unwrap(),
+            UserManager::new(&path("users"), PasswordPolicy::new(Default::default())).unwrap(),
+            Notifier::unavailable(crate::config::default_config().smtp, String::new(), "disabled".to_string()),
+        ).unwrap();
+        let mut manager = start(accounting.clone()).unwrap();
+        let id = manager.add_printer(printer()).unwrap();
+
+        manager.sync_print_queue(&id, vec![
+            job("1", PrintJobStatus::Processing, None, None),
+            job("2", PrintJobStatus::Completed, None, Some(250)),
+        ]).unwrap();
+        manager.sync_print_queue(&id, vec![
+            job("1", PrintJobStatus::Completed, Some(4), None),
+            job("2", PrintJobStatus::Completed, None, Some(250)),
+        ]).unwrap();
+
+        let entries = accounting.accounting(Some("alice"), &month_of(Utc::now())).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].totals.jobs, 2);
+        assert_eq!(entries[0].totals.pages, 7);
+        assert_eq!(entries[0].totals.estimated_pages, 3);
+        assert_eq!(entries[0].totals.pages_by_printer.get(&id), Some(&7));
+
+        let queue = &manager.get_printer(&id).unwrap().queue_status;
+        assert!(queue.iter().all(|j| j.status == PrintJobStatus::Completed));
+        assert!(queue.iter().any(|j| j.id == "2" && j.pages_estimated));
     }
-}
\ No newline at end of file
+}
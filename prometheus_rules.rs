@@ -0,0 +1,393 @@
+use std::fmt::Write;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::correlation::{CorrelationRule, GroupField};
+use crate::ingest_timing::{self, LOG_EVENTS_METRIC};
+use crate::logs::LogFilter;
+use crate::models::LogSeverity;
+
+const SEVERITIES: [LogSeverity; 5] = [
+    LogSeverity::Debug,
+    LogSeverity::Info,
+    LogSeverity::Warning,
+    LogSeverity::Error,
+    LogSeverity::Critical,
+];
+
+const GROUP_NAME: &str = "siem-correlation";
+
+// A rule to export with the id it is known by and its filter resolved from its source,
+// or why that failed
+pub struct ExportSource {
+    pub id: Uuid,
+    pub rule: CorrelationRule,
+    pub filter: Result<LogFilter, String>,
+}
+
+// A rule left out of the document
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportWarning {
+    pub id: Uuid,
+    pub rule: String,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrometheusExport {
+    pub format: &'static str,
+    // Rules in the document
+    pub exported: usize,
+    // Prometheus rules YAML
+    pub document: String,
+    pub warnings: Vec<ExportWarning>,
+}
+
+fn group_label(field: GroupField) -> Option<&'static str> {
+    match field {
+        GroupField::Source => Some("source"),
+        GroupField::EventType => Some("event_type"),
+        GroupField::Host | GroupField::User => None,
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+// PromQL for the rule over LOG_EVENTS_METRIC, or why it has none. A rule translates when
+// everything it counts on is a label of the metric: equality on source and event type, a
+// minimum severity, and grouping by source or event type. The expression holds while at
+// least `threshold` matching entries were stored within the last `window_secs`, per group,
+// which is the condition under which the rule fires (see RuleEvaluator). Prometheus
+// estimates the count from scrapes, with increase() extrapolating at the edges of the
+// range, and keeps the alert firing while the condition holds instead of starting a new
+// window, so the translation is close but not exact.
+pub fn translate(rule: &CorrelationRule, filter: &LogFilter) -> Result<String, Vec<String>> {
+    let mut reasons = Vec::new();
+    if filter.from.is_some() || filter.to.is_some() {
+        reasons.push("filter has a fixed time range".to_string());
+    }
+    if filter.host.is_some() {
+        reasons.push("filter on host, which is not a metric label".to_string());
+    }
+    if filter.user.is_some() {
+        reasons.push("filter on user, which is not a metric label".to_string());
+    }
+    if filter.category.is_some() {
+        reasons.push("filter on category, which is not a metric label".to_string());
+    }
+    if filter.message_contains.is_some() {
+        reasons.push("filter on message text".to_string());
+    }
+    if !filter.tags.is_empty() || !rule.tags.is_empty() {
+        reasons.push("conditions on tags".to_string());
+    }
+    let by = match rule.group_by {
+        Some(field) => match group_label(field) {
+            Some(label) => Some(label),
+            None => {
+                reasons.push(format!("grouped by {:?}, which is not a metric label", field));
+                None
+            },
+        },
+        None => None,
+    };
+    if !reasons.is_empty() {
+        return Err(reasons);
+    }
+
+    let mut matchers = Vec::new();
+    if let Some(source) = &filter.source {
+        matchers.push(format!("source={}", quote(source)));
+    }
+    if let Some(event_type) = &filter.event_type {
+        matchers.push(format!("event_type={}", quote(event_type)));
+    }
+    if let Some(min) = filter.min_severity.as_ref().filter(|min| **min > LogSeverity::Debug) {
+        let severities: Vec<String> = SEVERITIES.iter()
+            .filter(|severity| *severity >= min)
+            .map(ingest_timing::severity_label)
+            .collect();
+        matchers.push(format!("severity=~{}", quote(&severities.join("|"))));
+    }
+
+    let selector = if matchers.is_empty() {
+        LOG_EVENTS_METRIC.to_string()
+    } else {
+        format!("{}{{{}}}", LOG_EVENTS_METRIC, matchers.join(","))
+    };
+    let increase = format!("increase({}[{}s])", selector, rule.window_secs);
+    Ok(match by {
+        Some(label) => format!("sum by ({}) ({}) >= {}", label, increase, rule.threshold),
+        None => format!("sum({}) >= {}", increase, rule.threshold),
+    })
+}
+
+// JSON strings are valid double-quoted YAML scalars
+fn yaml_str(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn comment(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+// One rule group in the Prometheus rules file format. Rules come out ordered by name
+// and id and every value is quoted the same way, so the same rules give the same document.
+pub fn export(mut sources: Vec<ExportSource>) -> PrometheusExport {
+    sources.sort_by(|a, b| a.rule.name.cmp(&b.rule.name).then(a.id.cmp(&b.id)));
+
+    let mut rules = String::new();
+    let mut warnings = Vec::new();
+    let mut exported = 0;
+    for source in &sources {
+        let translated = match &source.filter {
+            Ok(filter) => translate(&source.rule, filter),
+            Err(e) => Err(vec![format!("filter could not be resolved: {}", e)]),
+        };
+        let expr = match translated {
+            Ok(expr) => expr,
+            Err(reasons) => {
+                warnings.push(ExportWarning { id: source.id, rule: source.rule.name.clone(), reasons });
+                continue;
+            },
+        };
+
+        let rule = &source.rule;
+        let severity = format!("{:?}", rule.severity).to_lowercase();
+        let summary = match rule.group_by.and_then(group_label) {
+            Some(label) => format!("{}: {{{{ $value }}}} events from {{{{ $labels.{} }}}}", rule.name, label),
+            None => format!("{}: {{{{ $value }}}} events", rule.name),
+        };
+        let _ = writeln!(rules, "      - alert: {}", yaml_str(&rule.name));
+        let _ = writeln!(rules, "        expr: {}", yaml_str(&expr));
+        let _ = writeln!(rules, "        labels:");
+        let _ = writeln!(rules, "          severity: {}", yaml_str(&severity));
+        let _ = writeln!(rules, "          siem_rule_id: {}", yaml_str(&source.id.to_string()));
+        let _ = writeln!(rules, "        annotations:");
+        let _ = writeln!(rules, "          summary: {}", yaml_str(&summary));
+        let _ = writeln!(rules, "          description: {}", yaml_str(&format!(
+            "At least {} matching log entries within {} seconds", rule.threshold, rule.window_secs)));
+        let _ = writeln!(rules, "          siem_rule_id: {}", yaml_str(&source.id.to_string()));
+        let _ = writeln!(rules, "          siem_rule_url: {}", yaml_str(&format!("/api/correlation/rules/backtest/{}", source.id)));
+        exported += 1;
+    }
+
+    let mut document = String::new();
+    let _ = writeln!(document, "# Correlation rules of the SIEM over {}", LOG_EVENTS_METRIC);
+    for warning in &warnings {
+        let _ = writeln!(document, "# Not exported: {} ({}): {}", comment(&warning.rule), warning.id, comment(&warning.reasons.join("; ")));
+    }
+    let _ = writeln!(document, "groups:");
+    let _ = writeln!(document, "  - name: {}", GROUP_NAME);
+    if exported == 0 {
+        let _ = writeln!(document, "    rules: []");
+    } else {
+        let _ = writeln!(document, "    rules:");
+        document.push_str(&rules);
+    }
+
+    PrometheusExport { format: "prometheus", exported, document, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::correlation::RuleEvaluator;
+    use crate::models::{AlertSeverity, LogEntry};
+    use crate::searches::FilterSource;
+
+    fn entry(timestamp: DateTime<Utc>, source: &str, event_type: &str, severity: LogSeverity) -> LogEntry {
+        LogEntry {
+            id: Uuid::new_v4(),
+            timestamp,
+            source: source.to_string(),
+            event_type: event_type.to_string(),
+            severity,
+            message: String::new(),
+            raw_data: String::new(),
+            host: None,
+            user: None,
+            application: None,
+            tags: Vec::new(),
+            category: Default::default(),
+            hostname: None,
+            site_id: None,
+            parser: Default::default(),
+        }
+    }
+
+    fn rule(name: &str, filter: &LogFilter, group_by: Option<GroupField>, threshold: usize, window_secs: i64) -> CorrelationRule {
+        CorrelationRule {
+            name: name.to_string(),
+            filter: FilterSource::Inline(filter.clone()),
+            tags: Default::default(),
+            group_by,
+            threshold,
+            window_secs,
+            severity: AlertSeverity::High,
+        }
+    }
+
+    // The parts of `sum [by (label)] (increase(metric{matchers}[Ns])) >= T`
+    struct Parsed {
+        by: Option<String>,
+        matchers: BTreeMap<String, Vec<String>>,
+        range_secs: i64,
+        threshold: usize,
+    }
+
+    fn parse(expr: &str) -> Parsed {
+        let (query, threshold) = expr.split_once(" >= ").expect("comparison");
+        let (by, rest) = match query.strip_prefix("sum by (") {
+            Some(rest) => {
+                let (label, rest) = rest.split_once(") ").expect("by clause");
+                (Some(label.to_string()), rest)
+            },
+            None => (None, query.strip_prefix("sum").expect("sum")),
+        };
+        let inner = rest.strip_prefix("(increase(").and_then(|r| r.strip_suffix("]))")).expect("increase");
+        let (selector, range) = inner.rsplit_once('[').expect("range");
+        let mut matchers = BTreeMap::new();
+        let labels = match selector.strip_prefix(LOG_EVENTS_METRIC).expect("metric") {
+            "" => "",
+            braced => braced.strip_prefix('{').and_then(|b| b.strip_suffix('}')).expect("matchers"),
+        };
+        for matcher in labels.split(',').filter(|m| !m.is_empty()) {
+            let (label, values) = match matcher.split_once("=~") {
+                Some((label, pattern)) => (label, pattern.trim_matches('"').split('|').map(String::from).collect()),
+                None => {
+                    let (label, value) = matcher.split_once('=').expect("matcher");
+                    (label, vec![value.trim_matches('"').to_string()])
+                },
+            };
+            matchers.insert(label.to_string(), values);
+        }
+        Parsed {
+            by,
+            matchers,
+            range_secs: range.strip_suffix('s').expect("seconds").parse().expect("range"),
+            threshold: threshold.parse().expect("threshold"),
+        }
+    }
+
+    fn labels(entry: &LogEntry) -> BTreeMap<&'static str, String> {
+        BTreeMap::from([
+            ("source", entry.source.clone()),
+            ("event_type", entry.event_type.clone()),
+            ("severity", ingest_timing::severity_label(&entry.severity)),
+        ])
+    }
+
+    // First time per group the expression holds, taking the counter as scraped at every
+    // stored entry so increase() over the range is the exact number of entries in it
+    fn first_firing(parsed: &Parsed, entries: &[LogEntry]) -> BTreeMap<String, DateTime<Utc>> {
+        let counted: Vec<(DateTime<Utc>, String)> = entries.iter()
+            .filter(|e| {
+                let labels = labels(e);
+                parsed.matchers.iter().all(|(label, values)| values.contains(&labels[label.as_str()]))
+            })
+            .map(|e| (e.timestamp, parsed.by.as_ref().map(|by| labels(e)[by.as_str()].clone()).unwrap_or_default()))
+            .collect();
+
+        let mut firing = BTreeMap::new();
+        for (at, group) in &counted {
+            let since = *at - Duration::seconds(parsed.range_secs);
+            let count = counted.iter().filter(|(t, g)| g == group && *t > since && t <= at).count();
+            if count >= parsed.threshold {
+                firing.entry(group.clone()).or_insert(*at);
+            }
+        }
+        firing
+    }
+
+    fn evaluator_first_alerts(rule: &CorrelationRule, filter: &LogFilter, entries: &[LogEntry]) -> BTreeMap<String, DateTime<Utc>> {
+        let mut evaluator = RuleEvaluator::new(rule.clone(), filter.clone());
+        let mut firing = BTreeMap::new();
+        for entry in entries {
+            if let Some(alert) = evaluator.evaluate(entry) {
+                firing.entry(alert.group.unwrap_or_default()).or_insert(alert.window_end);
+            }
+        }
+        firing
+    }
+
+    #[test]
+    fn translated_expression_fires_where_the_rule_does() {
+        let start = Utc.with_ymd_and_hms(2026, 3, 2, 8, 0, 0).unwrap();
+        let kinds = [
+            ("sshd", "auth:login_failed", LogSeverity::Warning),
+            ("sshd", "auth:login_failed", LogSeverity::Info),
+            ("sshd", "auth:login", LogSeverity::Error),
+            ("vpn", "auth:login_failed", LogSeverity::Critical),
+            ("vpn", "auth:login_failed", LogSeverity::Warning),
+            ("firewall", "firewall:drop", LogSeverity::Warning),
+        ];
+        // Uneven gaps, none adding up to a window boundary exactly
+        let gaps_ms = [7_013, 1_009, 11_027, 2_003, 3_001, 23_011, 5_003];
+        let mut at = start;
+        let entries: Vec<LogEntry> = (0..400)
+            .map(|i| {
+                at += Duration::milliseconds(gaps_ms[i % gaps_ms.len()] * (1 + (i / 97) as i64));
+                let (source, event_type, severity) = &kinds[(i * 7 + i / 5) % kinds.len()];
+                entry(at, source, event_type, severity.clone())
+            })
+            .collect();
+
+        let filter = LogFilter {
+            event_type: Some("auth:login_failed".to_string()),
+            min_severity: Some(LogSeverity::Warning),
+            ..Default::default()
+        };
+        let cases = [
+            rule("Failed logins per source", &filter, Some(GroupField::Source), 6, 300),
+            rule("Failed logins", &filter, None, 20, 600),
+            rule("Events per type", &LogFilter::default(), Some(GroupField::EventType), 9, 300),
+        ];
+
+        for rule in &cases {
+            let filter = match &rule.filter {
+                FilterSource::Inline(filter) => filter,
+                FilterSource::SavedSearch(_) => unreachable!(),
+            };
+            let expr = translate(rule, filter).expect("translatable");
+            let parsed = parse(&expr);
+            assert_eq!(parsed.range_secs, rule.window_secs, "{}", expr);
+            assert_eq!(parsed.threshold, rule.threshold, "{}", expr);
+
+            let expected = evaluator_first_alerts(rule, filter, &entries);
+            assert!(!expected.is_empty(), "{} never fires", rule.name);
+            assert_eq!(first_firing(&parsed, &entries), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn untranslatable_rules_are_listed_and_output_is_stable() {
+        let by_host = rule("Per host", &LogFilter::default(), Some(GroupField::Host), 5, 60);
+        let by_message = rule("Message", &LogFilter { message_contains: Some("denied".to_string()), ..Default::default() }, None, 5, 60);
+        let simple = rule("Drops", &LogFilter { event_type: Some("firewall:drop".to_string()), ..Default::default() }, None, 100, 300);
+        let sources = |order: &[&CorrelationRule]| order.iter()
+            .map(|rule| ExportSource {
+                id: Uuid::from_u128(rule.name.len() as u128),
+                rule: (*rule).clone(),
+                filter: match &rule.filter {
+                    FilterSource::Inline(filter) => Ok(filter.clone()),
+                    FilterSource::SavedSearch(_) => unreachable!(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let first = export(sources(&[&by_host, &simple, &by_message]));
+        let second = export(sources(&[&by_message, &by_host, &simple]));
+        assert_eq!(first.document, second.document);
+        assert_eq!(first.exported, 1);
+        assert_eq!(first.warnings.iter().map(|w| w.rule.as_str()).collect::<Vec<_>>(), ["Message", "Per host"]);
+        assert!(first.document.contains(r#"expr: "sum(increase(siem_log_events_total{event_type=\"firewall:drop\"}[300s])) >= 100""#),
+                "{}", first.document);
+        assert!(first.document.contains("# Not exported: Per host"));
+    }
+}
@@ -0,0 +1,260 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use anyhow::Result;
+
+use crate::activity::ActivityPage;
+use crate::auth::AuthUser;
+use crate::config::Config;
+use crate::config_history::{secret_fields, SECRET_MASK};
+use crate::models::User;
+use crate::scripts::Script;
+use crate::tickets::Ticket;
+
+// Who may see a field of an API response
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    // Admins and technicians
+    Staff,
+    Admin,
+    // Admins and the user the object belongs to
+    OwnerOrAdmin,
+    // No one; the value can only be written
+    Nobody,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "field")]
+pub enum Redaction {
+    // The field is left out
+    Omit,
+    // A value that is set is replaced by the secret mask, so callers see that it is set
+    Mask,
+    // Elements of the array with this boolean field set are left out
+    OmitElementsWhere(&'static str),
+    // The second field is left out of elements of the array whose kind is the first
+    OmitFromElementsOfKind(&'static str, &'static str),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldRule {
+    // Dotted path into the serialized object
+    pub path: &'static str,
+    pub visible_to: Visibility,
+    pub redaction: Redaction,
+}
+
+impl FieldRule {
+    const fn new(path: &'static str, visible_to: Visibility, redaction: Redaction) -> Self {
+        Self { path, visible_to, redaction }
+    }
+}
+
+// A response type with fields not every caller may see. The rules apply to the
+// serialized JSON, so a field added to the type later is redacted as soon as it has
+// a rule, whichever handler returns it.
+pub trait Redact: Serialize {
+    // Name of the type in GET /api/redactions
+    const NAME: &'static str;
+    // Field naming the user an object belongs to, for Visibility::OwnerOrAdmin
+    const OWNER_FIELD: Option<&'static str> = None;
+
+    fn fields() -> Vec<FieldRule>;
+}
+
+impl<T: Redact> Redact for Vec<T> {
+    const NAME: &'static str = T::NAME;
+    const OWNER_FIELD: Option<&'static str> = T::OWNER_FIELD;
+
+    fn fields() -> Vec<FieldRule> {
+        T::fields()
+    }
+}
+
+impl<T: Redact> Redact for Option<T> {
+    const NAME: &'static str = T::NAME;
+    const OWNER_FIELD: Option<&'static str> = T::OWNER_FIELD;
+
+    fn fields() -> Vec<FieldRule> {
+        T::fields()
+    }
+}
+
+impl Redact for Config {
+    const NAME: &'static str = "config";
+
+    fn fields() -> Vec<FieldRule> {
+        secret_fields().iter()
+            .map(|path| FieldRule::new(path, Visibility::Nobody, Redaction::Mask))
+            .collect()
+    }
+}
+
+impl Redact for User {
+    const NAME: &'static str = "user";
+    const OWNER_FIELD: Option<&'static str> = Some("username");
+
+    fn fields() -> Vec<FieldRule> {
+        vec![
+            FieldRule::new("email", Visibility::OwnerOrAdmin, Redaction::Omit),
+            FieldRule::new("notifications", Visibility::OwnerOrAdmin, Redaction::Omit),
+            FieldRule::new("external_id", Visibility::Admin, Redaction::Omit),
+        ]
+    }
+}
+
+impl Redact for Ticket {
+    const NAME: &'static str = "ticket";
+
+    fn fields() -> Vec<FieldRule> {
+        vec![
+            FieldRule::new("comments", Visibility::Admin, Redaction::OmitElementsWhere("is_internal")),
+            FieldRule::new("requester_email", Visibility::Staff, Redaction::Omit),
+            // Billing detail
+            FieldRule::new("worklogs", Visibility::Staff, Redaction::Omit),
+            // Vendor case numbers and internal tracker links
+            FieldRule::new("references", Visibility::Staff, Redaction::Omit),
+            // Part of what the portal links sign; with the instance key it mints them, and
+            // rotating it revokes them
+            FieldRule::new("portal_secret", Visibility::Nobody, Redaction::Omit),
+        ]
+    }
+}
+
+impl Redact for ActivityPage {
+    const NAME: &'static str = "activity_page";
+
+    fn fields() -> Vec<FieldRule> {
+        vec![
+            // The text of internal comments, as hidden from the ticket's comments above.
+            // The entry itself stays, so pages keep their size.
+            FieldRule::new("items", Visibility::Admin, Redaction::OmitFromElementsOfKind("internal_note", "payload")),
+        ]
+    }
+}
+
+impl Redact for Script {
+    const NAME: &'static str = "script";
+
+    fn fields() -> Vec<FieldRule> {
+        vec![
+            FieldRule::new("dependencies.secrets", Visibility::Admin, Redaction::Omit),
+        ]
+    }
+}
+
+fn allows(visibility: Visibility, viewer: &AuthUser, owner: Option<&str>) -> bool {
+    match visibility {
+        Visibility::Staff => viewer.is_staff(),
+        Visibility::Admin => viewer.is_admin(),
+        Visibility::OwnerOrAdmin => viewer.is_admin() || owner == Some(viewer.username.as_str()),
+        Visibility::Nobody => false,
+    }
+}
+
+fn apply(object: &mut Value, path: &str, redaction: Redaction) {
+    let (parents, key) = match path.rsplit_once('.') {
+        Some((parents, key)) => (Some(parents), key),
+        None => (None, path),
+    };
+    let mut target = Some(object);
+    for parent in parents.into_iter().flat_map(|p| p.split('.')) {
+        target = target.and_then(|v| v.get_mut(parent));
+    }
+    let Some(Value::Object(fields)) = target else { return };
+
+    match redaction {
+        Redaction::Omit => {
+            fields.remove(key);
+        },
+        Redaction::Mask => {
+            if let Some(value) = fields.get_mut(key).filter(|v| v.as_str().map_or(false, |s| !s.is_empty())) {
+                *value = Value::String(SECRET_MASK.to_string());
+            }
+        },
+        Redaction::OmitElementsWhere(flag) => {
+            if let Some(Value::Array(elements)) = fields.get_mut(key) {
+                elements.retain(|element| element.get(flag) != Some(&Value::Bool(true)));
+            }
+        },
+        Redaction::OmitFromElementsOfKind(kind, field) => {
+            if let Some(Value::Array(elements)) = fields.get_mut(key) {
+                for element in elements.iter_mut().filter_map(Value::as_object_mut) {
+                    if element.get("kind").and_then(Value::as_str) == Some(kind) {
+                        element.remove(field);
+                    }
+                }
+            }
+        },
+    }
+}
+
+fn redact_value(value: &mut Value, rules: &[FieldRule], owner_field: Option<&str>, viewer: &AuthUser) {
+    if let Value::Array(objects) = value {
+        for object in objects {
+            redact_value(object, rules, owner_field, viewer);
+        }
+        return;
+    }
+
+    let owner = owner_field
+        .and_then(|field| value.get(field))
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    for rule in rules {
+        if !allows(rule.visible_to, viewer, owner.as_deref()) {
+            apply(value, rule.path, rule.redaction);
+        }
+    }
+}
+
+// The JSON of a response as the viewer may see it
+pub fn redact<T: Redact>(body: &T, viewer: &AuthUser) -> Result<Value> {
+    let mut value = serde_json::to_value(body)?;
+    redact_value(&mut value, &T::fields(), T::OWNER_FIELD, viewer);
+    Ok(value)
+}
+
+// Response body redacted for the caller; handlers return this instead of Json for
+// every Redact type
+pub struct Redacted<T>(pub T, pub AuthUser);
+
+impl<T: Redact> IntoResponse for Redacted<T> {
+    fn into_response(self) -> Response {
+        match redact(&self.0, &self.1) {
+            Ok(value) => Json(value).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedactedType {
+    pub name: &'static str,
+    pub owner_field: Option<&'static str>,
+    pub fields: Vec<FieldRule>,
+}
+
+fn describe<T: Redact>() -> RedactedType {
+    RedactedType {
+        name: T::NAME,
+        owner_field: T::OWNER_FIELD,
+        fields: T::fields(),
+    }
+}
+
+// The role-dependent fields of every redacted response type
+pub fn schema() -> Vec<RedactedType> {
+    vec![
+        describe::<Config>(),
+        describe::<User>(),
+        describe::<Ticket>(),
+        describe::<ActivityPage>(),
+        describe::<Script>(),
+    ]
+}
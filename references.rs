@@ -0,0 +1,260 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::warn;
+
+use crate::config::ReferencesConfig;
+
+const MAX_LABEL_LEN: usize = 200;
+const MAX_VALUE_LEN: usize = 2048;
+const MAX_REFERENCES: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceKind {
+    // Upstream or internal issue tracker
+    Issue,
+    VendorCase,
+    Cve,
+    Other,
+}
+
+impl ReferenceKind {
+    // Key of the kind's link template in the config
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReferenceKind::Issue => "issue",
+            ReferenceKind::VendorCase => "vendor_case",
+            ReferenceKind::Cve => "cve",
+            ReferenceKind::Other => "other",
+        }
+    }
+}
+
+// A pointer from an alert or ticket to something kept elsewhere: an upstream issue, a
+// vendor support case, a CVE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalReference {
+    pub id: Uuid,
+    pub kind: ReferenceKind,
+    pub label: String,
+    // An http(s) URL or a plain identifier such as CVE-2024-1234
+    pub value: String,
+    // Where the UI links the reference to: the URL itself, or the identifier filled into
+    // the kind's link template when the reference was saved. None renders as plain text.
+    pub href: Option<String>,
+    pub added_by: String,
+    pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReferenceInput {
+    pub kind: ReferenceKind,
+    // Defaults to the value
+    #[serde(default)]
+    pub label: Option<String>,
+    pub value: String,
+}
+
+struct Resolved {
+    kind: ReferenceKind,
+    label: String,
+    value: String,
+    href: Option<String>,
+}
+
+// Anything with a scheme separator is taken for a URL; the rest are identifiers, which are
+// never used as a link on their own
+fn is_url(value: &str) -> bool {
+    value.contains("://")
+}
+
+fn parse_http_url(value: &str) -> Result<String> {
+    let url = reqwest::Url::parse(value).map_err(|e| anyhow!("Invalid URL {}: {}", value, e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(anyhow!("Only http and https URLs can be referenced, not {}", url.scheme()));
+    }
+    if url.host_str().map_or(true, |host| host.is_empty()) {
+        return Err(anyhow!("URL has no host: {}", value));
+    }
+    Ok(url.to_string())
+}
+
+// CVE-YYYY-NNNN with four or more digits in the sequence number, upper-cased
+fn normalize_cve(value: &str) -> Result<String> {
+    let upper = value.to_ascii_uppercase();
+    let valid = upper.strip_prefix("CVE-")
+        .and_then(|rest| rest.split_once('-'))
+        .is_some_and(|(year, number)| year.len() == 4 && number.len() >= 4
+            && year.chars().chain(number.chars()).all(|c| c.is_ascii_digit()));
+    if !valid {
+        return Err(anyhow!("Not a CVE identifier: {}", value));
+    }
+    Ok(upper)
+}
+
+// Everything but unreserved characters is escaped, so an identifier stays one path segment
+// or query value of the template
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn template_href(kind: ReferenceKind, value: &str, config: &ReferencesConfig) -> Option<String> {
+    let template = config.link_templates.get(kind.as_str())?;
+    match parse_http_url(&template.replace("{value}", &percent_encode(value))) {
+        Ok(href) => Some(href),
+        Err(e) => {
+            warn!("Ignoring link template for {} references: {}", kind.as_str(), e);
+            None
+        },
+    }
+}
+
+impl ReferenceInput {
+    fn resolve(self, config: &ReferencesConfig) -> Result<Resolved> {
+        let value = self.value.trim();
+        if value.is_empty() {
+            return Err(anyhow!("Reference value is empty"));
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(anyhow!("Reference value is longer than {} characters", MAX_VALUE_LEN));
+        }
+        if value.chars().any(char::is_control) {
+            return Err(anyhow!("Reference value contains control characters"));
+        }
+
+        let (value, href) = if is_url(value) {
+            let url = parse_http_url(value)?;
+            (url.clone(), Some(url))
+        } else {
+            let value = match self.kind {
+                ReferenceKind::Cve => normalize_cve(value)?,
+                _ => value.to_string(),
+            };
+            let href = template_href(self.kind, &value, config);
+            (value, href)
+        };
+
+        let label = match self.label.as_deref().map(str::trim) {
+            Some(label) if !label.is_empty() => label.to_string(),
+            _ => value.clone(),
+        };
+        if label.chars().count() > MAX_LABEL_LEN {
+            return Err(anyhow!("Reference label is longer than {} characters", MAX_LABEL_LEN));
+        }
+
+        Ok(Resolved { kind: self.kind, label, value, href })
+    }
+}
+
+fn check_unique(references: &[ExternalReference], except: Option<Uuid>, kind: ReferenceKind, value: &str) -> Result<()> {
+    let duplicate = references.iter()
+        .any(|r| Some(r.id) != except && r.kind == kind && r.value.eq_ignore_ascii_case(value));
+    if duplicate {
+        return Err(anyhow!("{} is already referenced", value));
+    }
+    Ok(())
+}
+
+// The list operations below are shared by alerts and tickets, which keep their references
+// in a plain list
+pub fn add(references: &mut Vec<ExternalReference>, input: ReferenceInput, added_by: &str, config: &ReferencesConfig) -> Result<ExternalReference> {
+    if references.len() >= MAX_REFERENCES {
+        return Err(anyhow!("At most {} references can be added", MAX_REFERENCES));
+    }
+    let resolved = input.resolve(config)?;
+    check_unique(references, None, resolved.kind, &resolved.value)?;
+
+    let reference = ExternalReference {
+        id: Uuid::new_v4(),
+        kind: resolved.kind,
+        label: resolved.label,
+        value: resolved.value,
+        href: resolved.href,
+        added_by: added_by.to_string(),
+        added_at: Utc::now(),
+        updated_at: None,
+    };
+    references.push(reference.clone());
+    Ok(reference)
+}
+
+// Returns the reference before and after the update
+pub fn update(references: &mut [ExternalReference],
+              reference_id: Uuid,
+              input: ReferenceInput,
+              config: &ReferencesConfig) -> Result<(ExternalReference, ExternalReference)> {
+    let resolved = input.resolve(config)?;
+    check_unique(references, Some(reference_id), resolved.kind, &resolved.value)?;
+
+    let reference = references.iter_mut()
+        .find(|r| r.id == reference_id)
+        .ok_or_else(|| anyhow!("Reference not found: {}", reference_id))?;
+    let before = reference.clone();
+    reference.kind = resolved.kind;
+    reference.label = resolved.label;
+    reference.value = resolved.value;
+    reference.href = resolved.href;
+    reference.updated_at = Some(Utc::now());
+    Ok((before, reference.clone()))
+}
+
+pub fn remove(references: &mut Vec<ExternalReference>, reference_id: Uuid) -> Result<ExternalReference> {
+    let index = references.iter()
+        .position(|r| r.id == reference_id)
+        .ok_or_else(|| anyhow!("Reference not found: {}", reference_id))?;
+    Ok(references.remove(index))
+}
+
+// Activity payload of a reference
+pub fn payload(reference: &ExternalReference) -> serde_json::Value {
+    serde_json::json!({
+        "reference_id": reference.id,
+        "kind": reference.kind,
+        "label": reference.label,
+        "value": reference.value,
+    })
+}
+
+// Whether the reference is to the searched identifier or URL: the same value in any case,
+// or a URL with the identifier as one of its segments, such as an advisory page of the CVE
+pub fn matches(reference: &ExternalReference, needle: &str) -> bool {
+    let needle = needle.trim().to_ascii_lowercase();
+    if needle.is_empty() {
+        return false;
+    }
+    let value = reference.value.to_ascii_lowercase();
+    value == needle
+        || (is_url(&value) && value
+            .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .any(|segment| segment == needle))
+}
+
+// An alert or ticket found by a reference search
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferenceMatch {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    // The references that matched
+    pub references: Vec<ExternalReference>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferenceSearch {
+    pub value: String,
+    pub alerts: Vec<ReferenceMatch>,
+    pub tickets: Vec<ReferenceMatch>,
+}
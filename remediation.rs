@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::logs::LogsManager;
+use crate::models::{Alert, AlertSeverity, RemediationRecord};
+use crate::scripts::ScriptsManager;
+use crate::ticket_snippets;
+
+// Source of the follow-up alerts raised when an action fails; bindings cannot use it,
+// so a failing action cannot trigger itself
+pub const REMEDIATION_SOURCE: &str = "remediation";
+
+// An approved script run automatically when an alert from the source is raised. Argument
+// values are templates filled from the alert, e.g. {"Address": "{{log.host}}"}.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemediationBinding {
+    pub id: Uuid,
+    pub name: String,
+    pub alert_source: String,
+    // Severities that trigger the action; empty means any
+    #[serde(default)]
+    pub severities: Vec<AlertSeverity>,
+    pub script_id: Uuid,
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    // The action runs at most once per cooldown, however many alerts come in
+    pub cooldown_secs: u64,
+    // Only record what would have run
+    pub dry_run: bool,
+    pub enabled: bool,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_fired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemediationSpec {
+    pub name: String,
+    pub alert_source: String,
+    #[serde(default)]
+    pub severities: Vec<AlertSeverity>,
+    pub script_id: Uuid,
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_cooldown() -> u64 {
+    900
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Settings {
+    // Global switch; while off no binding fires, dry runs included
+    automation_enabled: bool,
+}
+
+// Values an argument template can use: the alert itself and its first related log entry
+fn alert_context(alert: &Alert, log: Option<&crate::models::LogEntry>) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    values.insert("alert.id".to_string(), alert.id.to_string());
+    values.insert("alert.title".to_string(), alert.title.clone());
+    values.insert("alert.source".to_string(), alert.source.clone());
+    values.insert("alert.severity".to_string(), format!("{:?}", alert.severity));
+    if let Some(log) = log {
+        values.insert("log.id".to_string(), log.id.to_string());
+        values.insert("log.source".to_string(), log.source.clone());
+        values.insert("log.event_type".to_string(), log.event_type.clone());
+        values.extend(log.host.clone().map(|v| ("log.host".to_string(), v)));
+        values.extend(log.user.clone().map(|v| ("log.user".to_string(), v)));
+        values.extend(log.application.clone().map(|v| ("log.application".to_string(), v)));
+    }
+    values.retain(|_, v| !v.trim().is_empty());
+    values
+}
+
+#[derive(Clone)]
+pub struct RemediationManager {
+    remediation_dir: PathBuf,
+    bindings: Arc<Mutex<HashMap<Uuid, RemediationBinding>>>,
+    settings: Arc<Mutex<Settings>>,
+    scripts: Arc<Mutex<ScriptsManager>>,
+    alerts: AlertsManager,
+    logs: LogsManager,
+}
+
+impl RemediationManager {
+    pub fn new(remediation_dir: &str,
+               scripts: Arc<Mutex<ScriptsManager>>,
+               alerts: AlertsManager,
+               logs: LogsManager) -> Result<Self> {
+        let remediation_dir = PathBuf::from(remediation_dir);
+
+        if !remediation_dir.exists() {
+            fs::create_dir_all(&remediation_dir)
+                .context(format!("Failed to create remediation directory: {:?}", remediation_dir))?;
+            info!("Created remediation directory: {:?}", remediation_dir);
+        }
+
+        let mut bindings = HashMap::new();
+        let mut settings = Settings::default();
+
+        for entry in fs::read_dir(&remediation_dir)? {
+            let path = entry?.path();
+
+            if path.file_name().map_or(false, |name| name == "settings.json") {
+                settings = serde_json::from_str(&fs::read_to_string(&path)?)
+                    .context(format!("Failed to read remediation settings: {:?}", path))?;
+            } else if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<RemediationBinding>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(binding) => {
+                        bindings.insert(binding.id, binding);
+                    },
+                    Err(e) => {
+                        error!("Failed to load remediation binding {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} remediation bindings, automation {}", bindings.len(),
+              if settings.automation_enabled { "enabled" } else { "disabled" });
+
+        Ok(Self {
+            remediation_dir,
+            bindings: Arc::new(Mutex::new(bindings)),
+            settings: Arc::new(Mutex::new(settings)),
+            scripts,
+            alerts,
+            logs,
+        })
+    }
+
+    fn save_binding(&self, binding: &RemediationBinding) -> Result<()> {
+        let file_path = self.remediation_dir.join(format!("{}.json", binding.id));
+        let json = serde_json::to_string_pretty(binding)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    fn validate(&self, spec: &RemediationSpec) -> Result<()> {
+        if spec.name.trim().is_empty() {
+            return Err(anyhow!("A name is required"));
+        }
+        if spec.alert_source.trim().is_empty() || spec.alert_source == REMEDIATION_SOURCE {
+            return Err(anyhow!("Invalid alert source: {:?}", spec.alert_source));
+        }
+
+        let script = self.scripts.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on scripts manager"))?
+            .get_script(spec.script_id)
+            .ok_or_else(|| anyhow!("Script not found: {}", spec.script_id))?;
+        if !script.is_approved {
+            return Err(anyhow!("Script {} is not approved", script.name));
+        }
+        for name in spec.arguments.keys() {
+            if !script.parameters.iter().any(|p| p.name == *name) {
+                return Err(anyhow!("Unknown parameter {} for script {}", name, script.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_binding(&self, spec: RemediationSpec, created_by: String) -> Result<RemediationBinding> {
+        self.validate(&spec)?;
+
+        let now = Utc::now();
+        let binding = RemediationBinding {
+            id: Uuid::new_v4(),
+            name: spec.name,
+            alert_source: spec.alert_source,
+            severities: spec.severities,
+            script_id: spec.script_id,
+            arguments: spec.arguments,
+            cooldown_secs: spec.cooldown_secs,
+            dry_run: spec.dry_run,
+            enabled: spec.enabled,
+            created_by,
+            created_at: now,
+            updated_at: now,
+            last_fired_at: None,
+        };
+
+        self.save_binding(&binding)?;
+
+        match self.bindings.lock() {
+            Ok(mut bindings) => {
+                bindings.insert(binding.id, binding.clone());
+                Ok(binding)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        }
+    }
+
+    pub fn update_binding(&self, id: Uuid, spec: RemediationSpec) -> Result<RemediationBinding> {
+        self.validate(&spec)?;
+
+        let updated = match self.bindings.lock() {
+            Ok(mut bindings) => {
+                let binding = bindings.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Remediation binding not found: {}", id))?;
+                binding.name = spec.name;
+                binding.alert_source = spec.alert_source;
+                binding.severities = spec.severities;
+                binding.script_id = spec.script_id;
+                binding.arguments = spec.arguments;
+                binding.cooldown_secs = spec.cooldown_secs;
+                binding.dry_run = spec.dry_run;
+                binding.enabled = spec.enabled;
+                binding.updated_at = Utc::now();
+                binding.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        };
+
+        self.save_binding(&updated)?;
+        Ok(updated)
+    }
+
+    pub fn delete_binding(&self, id: Uuid) -> Result<()> {
+        match self.bindings.lock() {
+            Ok(mut bindings) => {
+                if bindings.remove(&id).is_none() {
+                    return Err(anyhow!("Remediation binding not found: {}", id));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        }
+
+        fs::remove_file(self.remediation_dir.join(format!("{}.json", id)))?;
+        Ok(())
+    }
+
+    pub fn get_binding(&self, id: Uuid) -> Result<Option<RemediationBinding>> {
+        match self.bindings.lock() {
+            Ok(bindings) => Ok(bindings.get(&id).cloned()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        }
+    }
+
+    pub fn get_bindings(&self) -> Result<Vec<RemediationBinding>> {
+        match self.bindings.lock() {
+            Ok(bindings) => {
+                let mut list: Vec<RemediationBinding> = bindings.values().cloned().collect();
+                list.sort_by(|a, b| a.alert_source.cmp(&b.alert_source).then(a.name.cmp(&b.name)));
+                Ok(list)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        }
+    }
+
+    pub fn automation_enabled(&self) -> bool {
+        self.settings.lock().map(|s| s.automation_enabled).unwrap_or(false)
+    }
+
+    pub fn set_automation_enabled(&self, enabled: bool) -> Result<()> {
+        match self.settings.lock() {
+            Ok(mut settings) => {
+                settings.automation_enabled = enabled;
+                let json = serde_json::to_string_pretty(&*settings)?;
+                fs::write(self.remediation_dir.join("settings.json"), json)?;
+                info!("Remediation automation {}", if enabled { "enabled" } else { "disabled" });
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on remediation settings")),
+        }
+    }
+
+    // Bindings the alert triggers, marked as fired; those still cooling down are skipped
+    fn take_due(&self, alert: &Alert) -> Result<Vec<RemediationBinding>> {
+        let now = Utc::now();
+        let due: Vec<RemediationBinding> = match self.bindings.lock() {
+            Ok(mut bindings) => bindings.values_mut()
+                .filter(|b| b.enabled && b.alert_source == alert.source)
+                .filter(|b| b.severities.is_empty() || b.severities.contains(&alert.severity))
+                .filter(|b| {
+                    let cooling = b.last_fired_at
+                        .map_or(false, |at| now - at < chrono::Duration::seconds(b.cooldown_secs as i64));
+                    if cooling {
+                        info!("Remediation {} for alert {} skipped, cooling down", b.name, alert.id);
+                    }
+                    !cooling
+                })
+                .map(|b| {
+                    b.last_fired_at = Some(now);
+                    b.clone()
+                })
+                .collect(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on remediation bindings")),
+        };
+
+        for binding in &due {
+            self.save_binding(binding)?;
+        }
+        Ok(due)
+    }
+
+    // Runs the actions bound to the alert's source and records their outcome on the alert
+    pub async fn handle(&self, alert: Alert) {
+        if alert.source == REMEDIATION_SOURCE || !self.automation_enabled() {
+            return;
+        }
+
+        let due = match self.take_due(&alert) {
+            Ok(due) => due,
+            Err(e) => {
+                warn!("Failed to match remediation bindings for alert {}: {}", alert.id, e);
+                return;
+            },
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        let log = alert.related_logs.first()
+            .and_then(|id| self.logs.get_many(&[*id]).ok())
+            .and_then(|mut logs| logs.pop());
+        let context = alert_context(&alert, log.as_ref());
+
+        for binding in due {
+            self.execute(&alert, &binding, &context).await;
+        }
+    }
+
+    // Runs an action configured outside the bindings, such as the UPS runtime hook, the
+    // way a binding runs: only while automation is on, recorded on the alert and with a
+    // follow-up alert on failure. The caller owns the cooldown.
+    pub async fn run_hook(&self, alert: &Alert, binding: &RemediationBinding, extra: HashMap<String, String>) -> bool {
+        if !self.automation_enabled() {
+            info!("Remediation {} for alert {} skipped, automation is disabled", binding.name, alert.id);
+            return false;
+        }
+        let mut context = alert_context(alert, None);
+        context.extend(extra);
+        self.execute(alert, binding, &context).await;
+        true
+    }
+
+    async fn execute(&self, alert: &Alert, binding: &RemediationBinding, context: &HashMap<String, String>) {
+        let record = self.run(binding, context).await;
+        if !record.success {
+            self.raise_failure(alert, binding, &record);
+        }
+        if let Err(e) = self.alerts.record_remediation(alert.id, record) {
+            warn!("Failed to record remediation {} on alert {}: {}", binding.name, alert.id, e);
+        }
+    }
+
+    async fn run(&self, binding: &RemediationBinding, context: &HashMap<String, String>) -> RemediationRecord {
+        let mut record = RemediationRecord {
+            binding_id: binding.id,
+            binding_name: binding.name.clone(),
+            script_id: binding.script_id,
+            at: Utc::now(),
+            dry_run: binding.dry_run,
+            arguments: HashMap::new(),
+            execution_id: None,
+            success: false,
+            error: None,
+        };
+
+        for (name, template) in &binding.arguments {
+            match ticket_snippets::expand(template, context) {
+                Ok(value) => {
+                    record.arguments.insert(name.clone(), value);
+                },
+                Err(missing) => {
+                    record.error = Some(format!("Alert has no value for {} (parameter {})", missing.join(", "), name));
+                    return record;
+                },
+            }
+        }
+
+        if binding.dry_run {
+            info!("Remediation {} would run script {} with {:?}", binding.name, binding.script_id, record.arguments);
+            record.success = true;
+            return record;
+        }
+
+        // Same path as an execution requested through the API, off the runtime threads
+        let scripts = self.scripts.clone();
+        let script_id = binding.script_id;
+        let executed_by = format!("{}:{}", REMEDIATION_SOURCE, binding.name);
+        let arguments = record.arguments.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut manager = scripts.lock()
+                .map_err(|_| anyhow!("Failed to acquire lock on scripts manager"))?;
+            manager.execute_script_with_arguments(script_id, executed_by, &arguments)
+        }).await;
+
+        match result {
+            Ok(Ok(result)) => {
+                record.execution_id = Some(result.id);
+                record.success = result.success;
+                record.error = result.error;
+            },
+            Ok(Err(e)) => record.error = Some(e.to_string()),
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        info!("Remediation {} ran script {}: {}", binding.name, binding.script_id,
+              if record.success { "succeeded" } else { "failed" });
+        record
+    }
+
+    fn raise_failure(&self, alert: &Alert, binding: &RemediationBinding, record: &RemediationRecord) {
+        let description = format!(
+            "Remediation {} for alert {} ({}) failed: {}{}",
+            binding.name,
+            alert.title,
+            alert.id,
+            record.error.as_deref().unwrap_or("unknown error"),
+            record.execution_id.map(|id| format!("\nExecution: {}", id)).unwrap_or_default(),
+        );
+
+        if let Err(e) = self.alerts.create_alert(
+            AlertSeverity::High,
+            format!("Remediation failed: {}", binding.name),
+            description,
+            REMEDIATION_SOURCE.to_string(),
+            alert.related_logs.clone(),
+        ) {
+            error!("Failed to raise alert for failed remediation {}: {}", binding.name, e);
+        }
+    }
+}
+
+// Handles alerts as they are raised; runs until the alerts manager is gone
+pub async fn run(mut alerts: mpsc::UnboundedReceiver<Alert>, manager: RemediationManager) {
+    while let Some(alert) = alerts.recv().await {
+        // A slow script must not hold up actions for other alerts
+        let manager = manager.clone();
+        tokio::spawn(async move { manager.handle(alert).await });
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::{info, error};
+
+use crate::alert_events;
+use crate::database::DatabaseManager;
+use crate::drop_log;
+use crate::extraction::RuleMatch;
+use crate::ingestion::{self, IngestionPipeline};
+use crate::logs::{LogFilter, LogsManager};
+use crate::models::{LogEntry, LogParser};
+use crate::syslog;
+
+// Entries parsed again per page of a bulk job
+const BULK_BATCH: usize = 500;
+
+// Tags added at ingestion from outside the message (transport, quotas, clock skew,
+// location), which parsing the message again cannot reproduce
+const PRESERVED_TAG_PREFIXES: [&str; 6] = [
+    ingestion::ORIGINAL_TIMESTAMP_TAG, "clock_skew:", "sampled:", "source:", "geo_country:", "geo_city:",
+];
+
+// The parser of entries stored before it was recorded, from what each parser produces
+pub fn resolve_parser(entry: &LogEntry) -> LogParser {
+    match entry.parser {
+        LogParser::Unknown if alert_events::is_lifecycle(entry) || entry.source == "siem" => LogParser::Internal,
+        LogParser::Unknown if entry.source == "syslog" || entry.source == "syslog-tls" => LogParser::Syslog,
+        LogParser::Unknown if entry.event_type == "firewall:drop" => LogParser::DropLog,
+        LogParser::Unknown => LogParser::Api,
+        parser => parser,
+    }
+}
+
+// The entry as its parser makes it from raw_data, before extraction, classification and
+// tagging. Id, timestamp and source stay as stored. Fields of events sent through the API
+// were set by the sender, so only their message is taken from raw_data again.
+pub fn parse_raw(stored: &LogEntry) -> Result<LogEntry> {
+    let parser = resolve_parser(stored);
+    let mut entry = match parser {
+        LogParser::Syslog => syslog::parse_syslog(&stored.raw_data),
+        LogParser::DropLog => {
+            let packet = drop_log::parse(&stored.raw_data)
+                .ok_or_else(|| anyhow!("The raw data is no longer a dropped packet record"))?;
+            drop_log::to_entry(&packet, &stored.raw_data)
+        },
+        LogParser::Api => LogEntry {
+            message: stored.raw_data.clone(),
+            tags: Vec::new(),
+            ..stored.clone()
+        },
+        LogParser::Internal | LogParser::Unknown => {
+            return Err(anyhow!("Log entry {} was written by the SIEM itself and has nothing to parse", stored.id));
+        },
+    };
+
+    entry.id = stored.id;
+    entry.timestamp = stored.timestamp;
+    entry.source = stored.source.clone();
+    entry.parser = parser;
+    entry.hostname = None;
+    for tag in &stored.tags {
+        if PRESERVED_TAG_PREFIXES.iter().any(|prefix| tag.starts_with(prefix)) && !entry.tags.contains(tag) {
+            entry.tags.push(tag.clone());
+        }
+    }
+    Ok(entry)
+}
+
+// Names of the fields that differ between the two versions of an entry
+pub fn changed_fields(before: &LogEntry, after: &LogEntry) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if before.message != after.message {
+        changed.push("message");
+    }
+    if before.event_type != after.event_type {
+        changed.push("event_type");
+    }
+    if before.severity != after.severity {
+        changed.push("severity");
+    }
+    if before.host != after.host {
+        changed.push("host");
+    }
+    if before.user != after.user {
+        changed.push("user");
+    }
+    if before.application != after.application {
+        changed.push("application");
+    }
+    if before.category != after.category {
+        changed.push("category");
+    }
+    if before.site_id != after.site_id {
+        changed.push("site_id");
+    }
+    if before.parser != after.parser {
+        changed.push("parser");
+    }
+    let mut before_tags = before.tags.clone();
+    let mut after_tags = after.tags.clone();
+    before_tags.sort();
+    after_tags.sort();
+    if before_tags != after_tags {
+        changed.push("tags");
+    }
+    changed
+}
+
+// GET /api/logs/:id
+#[derive(Debug, Clone, Serialize)]
+pub struct LogDetail {
+    pub entry: LogEntry,
+    pub parser: LogParser,
+    // Extraction rules matching the entry's raw data as parsed now
+    pub extraction_rules: Vec<RuleMatch>,
+    // Whether the entry is still in the in-memory store or only in the database
+    pub in_memory: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReparseOutcome {
+    pub before: LogEntry,
+    pub after: LogEntry,
+    pub extraction_rules: Vec<RuleMatch>,
+    pub changed: Vec<&'static str>,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum ReparseJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+// Parsing the stored entries matching a filter again
+#[derive(Debug, Clone, Serialize)]
+pub struct ReparseJob {
+    pub id: Uuid,
+    pub requested_by: String,
+    pub filter: LogFilter,
+    pub started_at: DateTime<Utc>,
+    pub status: ReparseJobStatus,
+    // Entries the job goes through; with a database, those in the filter's time range
+    pub total: usize,
+    pub processed: usize,
+    pub matched: usize,
+    pub changed: usize,
+    // Entries their parser could not handle, e.g. ones written by the SIEM itself
+    pub skipped: usize,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+// Detail view and re-parsing of stored log entries. An entry is looked up in the in-memory
+// store first, then in the database; a re-parse updates both.
+#[derive(Clone)]
+pub struct LogReparser {
+    pipeline: IngestionPipeline,
+    logs: LogsManager,
+    database: Option<Arc<DatabaseManager>>,
+    jobs: Arc<Mutex<HashMap<Uuid, ReparseJob>>>,
+}
+
+impl LogReparser {
+    pub fn new(pipeline: IngestionPipeline, logs: LogsManager, database: Option<Arc<DatabaseManager>>) -> Self {
+        Self {
+            pipeline,
+            logs,
+            database,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // The stored entry and whether it came from memory
+    async fn find(&self, id: Uuid) -> Result<Option<(LogEntry, bool)>> {
+        if let Some(entry) = self.logs.get_many(&[id])?.pop() {
+            return Ok(Some((entry, true)));
+        }
+        match &self.database {
+            Some(database) => Ok(database.get_log(id).await?.map(|entry| (entry, false))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn detail(&self, id: Uuid) -> Result<Option<LogDetail>> {
+        let Some((entry, in_memory)) = self.find(id).await? else { return Ok(None) };
+        let parser = resolve_parser(&entry);
+        let extraction_rules = match self.pipeline.reparse(&entry) {
+            Ok((_, rules)) => rules,
+            Err(_) => Vec::new(),
+        };
+        Ok(Some(LogDetail { entry, parser, extraction_rules, in_memory }))
+    }
+
+    async fn store(&self, entry: &LogEntry) -> Result<()> {
+        self.logs.update_many(&[entry.id], |stored| {
+            *stored = entry.clone();
+            true
+        })?;
+        if let Some(database) = &self.database {
+            database.update_parsed_fields(entry).await?;
+        }
+        Ok(())
+    }
+
+    // None when no entry has the id
+    pub async fn reparse(&self, id: Uuid, dry_run: bool) -> Result<Option<ReparseOutcome>> {
+        let Some((before, _)) = self.find(id).await? else { return Ok(None) };
+        let (after, extraction_rules) = self.pipeline.reparse(&before)?;
+        let changed = changed_fields(&before, &after);
+        if !dry_run && !changed.is_empty() {
+            self.store(&after).await?;
+        }
+        Ok(Some(ReparseOutcome { before, after, extraction_rules, changed, dry_run }))
+    }
+
+    fn update_job<F: FnOnce(&mut ReparseJob)>(&self, id: Uuid, update: F) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            if let Some(job) = jobs.get_mut(&id) {
+                update(job);
+            }
+        }
+    }
+
+    // Parses the matching entries again in the background, through the database when
+    // there is one and otherwise through the in-memory store
+    pub async fn start_bulk(&self, filter: LogFilter, requested_by: &str) -> Result<ReparseJob> {
+        let from = filter.from.unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap());
+        let to = filter.to.unwrap_or_else(Utc::now);
+        let total = match &self.database {
+            Some(database) => database.count_logs_between(from, to).await? as usize,
+            None => self.logs.ids()?.len(),
+        };
+
+        let job = ReparseJob {
+            id: Uuid::new_v4(),
+            requested_by: requested_by.to_string(),
+            filter: filter.clone(),
+            started_at: Utc::now(),
+            status: ReparseJobStatus::Running,
+            total,
+            processed: 0,
+            matched: 0,
+            changed: 0,
+            skipped: 0,
+            finished_at: None,
+            error: None,
+        };
+        match self.jobs.lock() {
+            Ok(mut jobs) => {
+                jobs.insert(job.id, job.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on re-parse jobs")),
+        }
+
+        info!("Re-parsing stored logs from {} to {} for {}", from.to_rfc3339(), to.to_rfc3339(), requested_by);
+        let reparser = self.clone();
+        let job_id = job.id;
+        tokio::spawn(async move {
+            let result = reparser.run_bulk(job_id, &filter, from, to).await;
+            reparser.update_job(job_id, |job| {
+                job.finished_at = Some(Utc::now());
+                match result {
+                    Ok(()) => {
+                        job.status = ReparseJobStatus::Completed;
+                        info!("Re-parse job {} changed {} of {} matching entries", job.id, job.changed, job.matched);
+                    },
+                    Err(e) => {
+                        error!("Re-parse job {} failed: {}", job.id, e);
+                        job.status = ReparseJobStatus::Failed;
+                        job.error = Some(e.to_string());
+                    },
+                }
+            });
+        });
+
+        Ok(job)
+    }
+
+    async fn run_bulk(&self, job_id: Uuid, filter: &LogFilter, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<()> {
+        let mut after = None;
+        loop {
+            let page = match &self.database {
+                Some(database) => database.logs_page(from, to, after, BULK_BATCH as i64).await?,
+                None => self.logs.page_between(from, to, after, BULK_BATCH)?,
+            };
+            let Some(last) = page.last() else { return Ok(()) };
+            after = Some((last.timestamp, last.id));
+
+            let (mut matched, mut changed, mut skipped) = (0, 0, 0);
+            for before in page.iter().filter(|entry| filter.matches(entry)) {
+                matched += 1;
+                let after = match self.pipeline.reparse(before) {
+                    Ok((after, _)) => after,
+                    Err(_) => {
+                        skipped += 1;
+                        continue;
+                    },
+                };
+                if !changed_fields(before, &after).is_empty() {
+                    self.store(&after).await?;
+                    changed += 1;
+                }
+            }
+
+            let processed = page.len();
+            self.update_job(job_id, |job| {
+                job.processed += processed;
+                job.matched += matched;
+                job.changed += changed;
+                job.skipped += skipped;
+            });
+            tokio::task::yield_now().await;
+        }
+    }
+
+    pub fn get_job(&self, id: Uuid) -> Result<ReparseJob> {
+        match self.jobs.lock() {
+            Ok(jobs) => jobs.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Re-parse job not found: {}", id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on re-parse jobs")),
+        }
+    }
+}
@@ -0,0 +1,928 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::assets::AssetManager;
+use crate::config::{ReplicationConfig, ReplicationMode};
+use crate::config_history::ConfigHistory;
+use crate::models::{AlertSeverity, Asset};
+use crate::network::{FirewallModel, NetworkManager};
+use crate::outbound::{self, HttpClients};
+use crate::scripts::{Script, ScriptsManager};
+use crate::security::constant_time_eq;
+use crate::tickets::{Ticket, TicketsManager};
+
+// Header carrying the shared key on the changes feed
+pub const KEY_HEADER: &str = "x-replication-key";
+
+// Source of conflict alerts, and who replicated changes are recorded as
+pub const ALERT_SOURCE: &str = "replication";
+const REPLICATED_BY: &str = "replication";
+
+// Changes per page of the feed
+const PAGE_SIZE: usize = 500;
+
+const JOURNAL_FILE: &str = "journal.json";
+const STANDBY_FILE: &str = "standby.json";
+
+// Sections that describe this box rather than the site; never sent to the standby
+const LOCAL_SECTIONS: [&str; 14] = [
+    "server_port", "scripts_dir", "log_dir", "data_dir", "paths", "security", "tls",
+    "syslog_tls", "database_url", "database", "heartbeat", "tracing", "firewall_backup",
+    "replication",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Ticket,
+    Script,
+    Asset,
+    // The whole config file minus the local sections, with id "config"
+    Config,
+    // The firewall model, with id "model"
+    FirewallModel,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Ticket => "ticket",
+            ObjectKind::Script => "script",
+            ObjectKind::Asset => "asset",
+            ObjectKind::Config => "config",
+            ObjectKind::FirewallModel => "firewall_model",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        [ObjectKind::Ticket, ObjectKind::Script, ObjectKind::Asset, ObjectKind::Config, ObjectKind::FirewallModel]
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+    }
+}
+
+fn object_key(kind: ObjectKind, id: &str) -> String {
+    format!("{}/{}", kind.as_str(), id)
+}
+
+// serde_json objects keep their keys sorted, so equal objects hash equal
+fn hash_value(value: &serde_json::Value) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(value)?);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn read_key(config: &ReplicationConfig) -> Result<Option<String>> {
+    let key = match (&config.key_file, &config.key_env) {
+        (Some(path), _) => fs::read_to_string(path)
+            .context(format!("Failed to read replication key file: {}", path))?
+            .trim()
+            .to_string(),
+        (None, Some(var)) => std::env::var(var)
+            .context(format!("Replication key environment variable {} is not set", var))?,
+        (None, None) => return Ok(None),
+    };
+    if key.len() < 16 {
+        return Err(anyhow!("The replication key must be at least 16 characters"));
+    }
+    Ok(Some(key))
+}
+
+// Whether this instance is a standby. Cheap to clone and created before the managers, so
+// background jobs that change data (schedules, auto-close) can sit out while it is.
+#[derive(Clone)]
+pub struct ReplicationRole {
+    standby: Arc<AtomicBool>,
+}
+
+impl ReplicationRole {
+    pub fn new(mode: ReplicationMode) -> Self {
+        Self {
+            standby: Arc::new(AtomicBool::new(mode == ReplicationMode::Standby)),
+        }
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::SeqCst)
+    }
+
+    // True when this call flipped a standby to primary
+    fn promote(&self) -> bool {
+        self.standby.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    kind: ObjectKind,
+    id: String,
+    seq: u64,
+    // None once the object was deleted
+    hash: Option<String>,
+    changed_at: DateTime<Utc>,
+}
+
+// The primary's numbering of changes: each object carries the sequence number of its last
+// change, so a standby asks for everything past the last number it saw
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Journal {
+    // A new journal (e.g. after its file was lost) makes standbys start over
+    journal_id: Uuid,
+    latest_seq: u64,
+    entries: BTreeMap<String, JournalEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub seq: u64,
+    pub kind: ObjectKind,
+    pub id: String,
+    pub hash: Option<String>,
+    pub changed_at: DateTime<Utc>,
+    // None for deletions
+    pub object: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesPage {
+    pub journal_id: Uuid,
+    pub latest_seq: u64,
+    pub changes: Vec<Change>,
+    // More changes past the last one of this page
+    pub more: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppliedObject {
+    remote_hash: Option<String>,
+    // The local object right after it was applied; it differing now means it was changed here
+    local_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conflict {
+    pub kind: ObjectKind,
+    pub id: String,
+    // The primary's version that was held back
+    pub remote_hash: Option<String>,
+    pub detected_at: DateTime<Utc>,
+    pub alert_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingModel {
+    replicated_at: DateTime<Utc>,
+    model: FirewallModel,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StandbyState {
+    journal_id: Option<Uuid>,
+    // Starts over at every start; objects already applied are skipped by their hash
+    #[serde(skip)]
+    cursor: u64,
+    applied: BTreeMap<String, AppliedObject>,
+    conflicts: BTreeMap<String, Conflict>,
+    // Conflicts resolved in favour of the primary, overwritten at the next sync
+    forced: BTreeSet<String>,
+    // Staged, not applied, at promotion; the standby's ruleset is left alone
+    pending_model: Option<PendingModel>,
+    last_sync: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    pub mode: ReplicationMode,
+    pub primary_url: Option<String>,
+    pub key_configured: bool,
+    // Primary: the feed served to standbys
+    pub journal_id: Option<Uuid>,
+    pub latest_seq: Option<u64>,
+    // Standby: how far it followed the primary
+    pub cursor: Option<u64>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub replicated_objects: usize,
+    pub pending_firewall_model: Option<DateTime<Utc>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncSummary {
+    pub applied: usize,
+    pub deleted: usize,
+    pub conflicts: usize,
+    pub cursor: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Promotion {
+    pub promoted_at: DateTime<Utc>,
+    // Whether mode = "primary" reached the config file; otherwise a restart comes up as standby
+    pub config_saved: bool,
+    // The replicated firewall model, staged for review
+    pub staged_changeset: Option<Uuid>,
+}
+
+// Warm standby. The primary serves its tickets, scripts, assets, config and firewall model
+// as an incremental changes feed; a standby pulls it every interval and applies it, unless
+// the object was also changed locally, which is raised as an alert and left for an admin.
+#[derive(Clone)]
+pub struct Replication {
+    config: ReplicationConfig,
+    role: ReplicationRole,
+    key: Option<String>,
+    http: Option<reqwest::Client>,
+    dir: PathBuf,
+    tickets: TicketsManager,
+    scripts: Arc<Mutex<ScriptsManager>>,
+    assets: AssetManager,
+    config_history: ConfigHistory,
+    network: Arc<NetworkManager>,
+    alerts: AlertsManager,
+    journal: Arc<tokio::sync::Mutex<Journal>>,
+    standby: Arc<tokio::sync::Mutex<StandbyState>>,
+}
+
+impl Replication {
+    pub fn new(config: ReplicationConfig,
+               role: ReplicationRole,
+               dir: &str,
+               tickets: TicketsManager,
+               scripts: Arc<Mutex<ScriptsManager>>,
+               assets: AssetManager,
+               config_history: ConfigHistory,
+               network: Arc<NetworkManager>,
+               alerts: AlertsManager,
+               clients: &HttpClients) -> Result<Self> {
+        let key = read_key(&config)?;
+        let http = if config.mode == ReplicationMode::Standby {
+            if config.primary_url.is_none() || key.is_none() {
+                return Err(anyhow!("replication.mode standby needs primary_url and key_file or key_env"));
+            }
+            Some(clients.client(outbound::REPLICATION, Duration::from_secs(config.timeout_secs.max(1)))?)
+        } else {
+            None
+        };
+
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).context(format!("Failed to create replication directory: {:?}", dir))?;
+
+        let journal = load(&dir.join(JOURNAL_FILE)).unwrap_or_else(|| Journal {
+            journal_id: Uuid::new_v4(),
+            latest_seq: 0,
+            entries: BTreeMap::new(),
+        });
+        let standby: StandbyState = load(&dir.join(STANDBY_FILE)).unwrap_or_default();
+
+        if role.is_standby() {
+            info!("Replication: standby of {}, {} objects replicated, {} conflicts",
+                  config.primary_url.as_deref().unwrap_or_default(), standby.applied.len(), standby.conflicts.len());
+        }
+
+        Ok(Self {
+            config,
+            role,
+            key,
+            http,
+            dir,
+            tickets,
+            scripts,
+            assets,
+            config_history,
+            network,
+            alerts,
+            journal: Arc::new(tokio::sync::Mutex::new(journal)),
+            standby: Arc::new(tokio::sync::Mutex::new(standby)),
+        })
+    }
+
+    pub fn role(&self) -> &ReplicationRole {
+        &self.role
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs.max(5))
+    }
+
+    pub fn primary_url(&self) -> Option<&str> {
+        self.config.primary_url.as_deref()
+    }
+
+    // Whether the key presented on the changes feed is ours; false when none is configured
+    pub fn accepts_key(&self, presented: &str) -> bool {
+        match &self.key {
+            Some(key) => constant_time_eq(key.as_bytes(), presented.as_bytes()),
+            None => false,
+        }
+    }
+
+    fn write<T: Serialize>(&self, file: &str, value: &T) -> Result<()> {
+        let path = self.dir.join(file);
+        fs::write(&path, serde_json::to_string(value)?)
+            .context(format!("Failed to write replication file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn config_object(&self) -> Result<serde_json::Value> {
+        let mut value = serde_json::to_value(self.config_history.current()?)?;
+        if let Some(sections) = value.as_object_mut() {
+            for section in LOCAL_SECTIONS {
+                sections.remove(section);
+            }
+        }
+        Ok(value)
+    }
+
+    fn lock_scripts(&self) -> Result<std::sync::MutexGuard<'_, ScriptsManager>> {
+        self.scripts.lock().map_err(|_| anyhow!("Failed to acquire lock on scripts manager"))
+    }
+
+    // Every replicated object as it is now, by key; built-in scripts ship with the binary
+    async fn snapshot(&self) -> Result<BTreeMap<String, (ObjectKind, String, serde_json::Value)>> {
+        let mut objects = BTreeMap::new();
+        let mut add = |kind: ObjectKind, id: String, value: serde_json::Value| {
+            objects.insert(object_key(kind, &id), (kind, id, value));
+        };
+
+        for ticket in self.tickets.get_all_tickets()? {
+            add(ObjectKind::Ticket, ticket.id.to_string(), serde_json::to_value(&ticket)?);
+        }
+        let scripts: Vec<Script> = {
+            let manager = self.lock_scripts()?;
+            manager.get_all_scripts().into_iter().filter(|s| !manager.is_builtin(s.id)).collect()
+        };
+        for script in scripts {
+            add(ObjectKind::Script, script.id.to_string(), serde_json::to_value(&script)?);
+        }
+        for asset in self.assets.get_all_assets()? {
+            add(ObjectKind::Asset, asset.id.to_string(), serde_json::to_value(&asset)?);
+        }
+        add(ObjectKind::Config, "config".to_string(), self.config_object()?);
+        let model = self.network.export_model().await;
+        add(ObjectKind::FirewallModel, "model".to_string(), serde_json::to_value(&model)?);
+        Ok(objects)
+    }
+
+    // Primary: the changes past `since`, after numbering whatever changed since the last request
+    pub async fn changes(&self, since: u64) -> Result<ChangesPage> {
+        if self.role.is_standby() {
+            return Err(anyhow!("This instance is a standby and serves no changes feed"));
+        }
+
+        let objects = self.snapshot().await?;
+        let mut journal = self.journal.lock().await;
+        let now = Utc::now();
+        let mut changed = false;
+
+        for (key, (kind, id, value)) in &objects {
+            let hash = Some(hash_value(value)?);
+            if journal.entries.get(key).map_or(false, |e| e.hash == hash) {
+                continue;
+            }
+            journal.latest_seq += 1;
+            let seq = journal.latest_seq;
+            journal.entries.insert(key.clone(), JournalEntry { kind: *kind, id: id.clone(), seq, hash, changed_at: now });
+            changed = true;
+        }
+        let deleted: Vec<String> = journal.entries.iter()
+            .filter(|(key, entry)| entry.hash.is_some() && !objects.contains_key(*key))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in deleted {
+            journal.latest_seq += 1;
+            let seq = journal.latest_seq;
+            if let Some(entry) = journal.entries.get_mut(&key) {
+                entry.seq = seq;
+                entry.hash = None;
+                entry.changed_at = now;
+            }
+            changed = true;
+        }
+        if changed {
+            self.write(JOURNAL_FILE, &*journal)?;
+        }
+
+        let mut pending: Vec<&JournalEntry> = journal.entries.values().filter(|e| e.seq > since).collect();
+        pending.sort_by_key(|e| e.seq);
+        let more = pending.len() > PAGE_SIZE;
+        let changes = pending.into_iter()
+            .take(PAGE_SIZE)
+            .map(|entry| Change {
+                seq: entry.seq,
+                kind: entry.kind,
+                id: entry.id.clone(),
+                hash: entry.hash.clone(),
+                changed_at: entry.changed_at,
+                object: entry.hash.as_ref()
+                    .and_then(|_| objects.get(&object_key(entry.kind, &entry.id)))
+                    .map(|(_, _, value)| value.clone()),
+            })
+            .collect();
+
+        Ok(ChangesPage {
+            journal_id: journal.journal_id,
+            latest_seq: journal.latest_seq,
+            changes,
+            more,
+        })
+    }
+
+    async fn fetch(&self, since: u64) -> Result<ChangesPage> {
+        let (Some(http), Some(url), Some(key)) = (&self.http, &self.config.primary_url, &self.key) else {
+            return Err(anyhow!("Replication from a primary is not configured"));
+        };
+        let response = http.get(format!("{}/api/replication/changes", url.trim_end_matches('/')))
+            .query(&[("since", since)])
+            .header(KEY_HEADER, key)
+            .send()
+            .await
+            .context("Failed to reach the primary")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("The primary answered {}", response.status()));
+        }
+        response.json().await.context("Failed to parse the changes feed")
+    }
+
+    // Standby: pulls and applies everything the primary changed since the last sync
+    pub async fn sync(&self) -> Result<SyncSummary> {
+        if !self.role.is_standby() {
+            return Ok(SyncSummary { applied: 0, deleted: 0, conflicts: 0, cursor: 0 });
+        }
+
+        let mut state = self.standby.lock().await;
+        let result = self.pull(&mut state).await;
+        match &result {
+            Ok(summary) => {
+                state.last_sync = Some(Utc::now());
+                state.last_error = None;
+                if summary.applied + summary.deleted + summary.conflicts > 0 {
+                    info!("Replicated {} changes and {} deletions from the primary, {} conflicts",
+                          summary.applied, summary.deleted, summary.conflicts);
+                }
+            },
+            Err(e) => state.last_error = Some(format!("{:#}", e)),
+        }
+        self.write(STANDBY_FILE, &*state)?;
+        result
+    }
+
+    async fn pull(&self, state: &mut StandbyState) -> Result<SyncSummary> {
+        let mut summary = SyncSummary { applied: 0, deleted: 0, conflicts: 0, cursor: state.cursor };
+        loop {
+            let page = self.fetch(state.cursor).await?;
+            if state.journal_id != Some(page.journal_id) || page.latest_seq < state.cursor {
+                if state.cursor > 0 {
+                    warn!("The primary's change journal was reset, replicating everything again");
+                    state.cursor = 0;
+                    state.journal_id = Some(page.journal_id);
+                    continue;
+                }
+                state.journal_id = Some(page.journal_id);
+            }
+
+            for change in &page.changes {
+                match self.apply(state, change) {
+                    Ok(Applied::Written) => summary.applied += 1,
+                    Ok(Applied::Deleted) => summary.deleted += 1,
+                    Ok(Applied::Conflict) => summary.conflicts += 1,
+                    Ok(Applied::Unchanged) => {},
+                    // Left for the next full resync rather than skipped for good
+                    Err(e) => return Err(e.context(format!("Failed to apply {} {}", change.kind.as_str(), change.id))),
+                }
+                state.cursor = change.seq;
+            }
+            if !page.more || page.changes.is_empty() {
+                state.cursor = state.cursor.max(page.latest_seq);
+                break;
+            }
+        }
+        summary.cursor = state.cursor;
+        Ok(summary)
+    }
+
+    // The local object's hash, None when it does not exist here
+    fn local_hash(&self, kind: ObjectKind, id: &str) -> Result<Option<String>> {
+        let value = match kind {
+            ObjectKind::Ticket => self.tickets.get_ticket(Uuid::parse_str(id)?).ok()
+                .map(|t| serde_json::to_value(&t)).transpose()?,
+            ObjectKind::Script => {
+                let id = Uuid::parse_str(id)?;
+                let manager = self.lock_scripts()?;
+                manager.get_script(id).filter(|_| !manager.is_builtin(id))
+                    .map(|s| serde_json::to_value(&s)).transpose()?
+            },
+            ObjectKind::Asset => self.assets.get_asset(Uuid::parse_str(id)?).ok()
+                .map(|a| serde_json::to_value(&a)).transpose()?,
+            ObjectKind::Config => Some(self.config_object()?),
+            ObjectKind::FirewallModel => None,
+        };
+        value.as_ref().map(hash_value).transpose()
+    }
+
+    fn apply(&self, state: &mut StandbyState, change: &Change) -> Result<Applied> {
+        let key = object_key(change.kind, &change.id);
+        let forced = state.forced.contains(&key);
+        if !forced && state.applied.get(&key).map_or(false, |a| a.remote_hash == change.hash) {
+            return Ok(Applied::Unchanged);
+        }
+
+        // Only changeable here by promotion, so it cannot conflict
+        if change.kind == ObjectKind::FirewallModel {
+            if let Some(object) = &change.object {
+                state.pending_model = Some(PendingModel {
+                    replicated_at: Utc::now(),
+                    model: serde_json::from_value(object.clone())?,
+                });
+            }
+            state.applied.insert(key, AppliedObject { remote_hash: change.hash.clone(), local_hash: None });
+            return Ok(Applied::Written);
+        }
+
+        let local = self.local_hash(change.kind, &change.id)?;
+        let base = state.applied.get(&key).and_then(|a| a.local_hash.clone());
+        // The standby's own config is taken over at the first sync
+        let adopted = base.is_none() && change.kind == ObjectKind::Config;
+        let conflict = local.is_some() && local != change.hash && local != base && !adopted && !forced;
+
+        if conflict {
+            if state.conflicts.get(&key).map_or(true, |c| c.remote_hash != change.hash) {
+                let alert_id = self.raise_conflict(change);
+                state.conflicts.insert(key, Conflict {
+                    kind: change.kind,
+                    id: change.id.clone(),
+                    remote_hash: change.hash.clone(),
+                    detected_at: Utc::now(),
+                    alert_id,
+                });
+            }
+            return Ok(Applied::Conflict);
+        }
+
+        let applied = match &change.object {
+            Some(object) => {
+                if local != change.hash {
+                    self.write_object(change.kind, &change.id, object)?;
+                }
+                Applied::Written
+            },
+            None => {
+                if local.is_some() {
+                    self.delete_object(change.kind, &change.id)?;
+                }
+                Applied::Deleted
+            },
+        };
+
+        let local_hash = self.local_hash(change.kind, &change.id)?;
+        state.applied.insert(key.clone(), AppliedObject { remote_hash: change.hash.clone(), local_hash });
+        state.conflicts.remove(&key);
+        state.forced.remove(&key);
+        Ok(applied)
+    }
+
+    fn write_object(&self, kind: ObjectKind, id: &str, object: &serde_json::Value) -> Result<()> {
+        match kind {
+            ObjectKind::Ticket => {
+                let ticket: Ticket = serde_json::from_value(object.clone())?;
+                self.tickets.replicate_ticket(ticket, REPLICATED_BY)
+            },
+            ObjectKind::Script => {
+                let script: Script = serde_json::from_value(object.clone())?;
+                self.lock_scripts()?.replicate_script(script)
+            },
+            ObjectKind::Asset => {
+                let asset: Asset = serde_json::from_value(object.clone())?;
+                self.assets.replicate_asset(asset, REPLICATED_BY)
+            },
+            ObjectKind::Config => {
+                self.config_history.update(object, REPLICATED_BY, "replication")?;
+                Ok(())
+            },
+            ObjectKind::FirewallModel => Err(anyhow!("The firewall model is staged, not written: {}", id)),
+        }
+    }
+
+    fn delete_object(&self, kind: ObjectKind, id: &str) -> Result<()> {
+        match kind {
+            ObjectKind::Ticket => self.tickets.delete_ticket(Uuid::parse_str(id)?, REPLICATED_BY.to_string()),
+            ObjectKind::Script => self.lock_scripts()?.delete_script(Uuid::parse_str(id)?),
+            ObjectKind::Asset => self.assets.delete_asset(Uuid::parse_str(id)?, REPLICATED_BY),
+            ObjectKind::Config | ObjectKind::FirewallModel => Err(anyhow!("{} cannot be deleted", kind.as_str())),
+        }
+    }
+
+    fn raise_conflict(&self, change: &Change) -> Option<Uuid> {
+        let what = if change.object.is_some() { "changed" } else { "deleted" };
+        let description = format!(
+            "{} {} was {} on the primary ({}) but was also changed on this standby since it was last \
+             replicated. The local version was kept; resolve the conflict at \
+             DELETE /api/replication/conflicts/{}/{} to take the primary's version.",
+            change.kind.as_str(), change.id, what,
+            self.config.primary_url.as_deref().unwrap_or_default(),
+            change.kind.as_str(), change.id);
+        match self.alerts.create_alert(
+            AlertSeverity::Medium,
+            format!("Replication conflict on {} {}", change.kind.as_str(), change.id),
+            description,
+            ALERT_SOURCE.to_string(),
+            Vec::new(),
+        ) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                warn!("Failed to raise the replication conflict alert: {}", e);
+                None
+            },
+        }
+    }
+
+    // Takes the primary's version of a conflicting object at the next sync
+    pub async fn resolve_conflict(&self, kind: ObjectKind, id: &str) -> Result<Conflict> {
+        let key = object_key(kind, id);
+        let mut state = self.standby.lock().await;
+        let conflict = state.conflicts.remove(&key)
+            .ok_or_else(|| anyhow!("No replication conflict on {} {}", kind.as_str(), id))?;
+        state.forced.insert(key);
+        state.cursor = 0;
+        self.write(STANDBY_FILE, &*state)?;
+        Ok(conflict)
+    }
+
+    pub async fn status(&self) -> Result<ReplicationStatus> {
+        let standby = self.role.is_standby();
+        let (journal_id, latest_seq) = if standby {
+            (None, None)
+        } else {
+            let journal = self.journal.lock().await;
+            (Some(journal.journal_id), Some(journal.latest_seq))
+        };
+        let state = self.standby.lock().await;
+
+        Ok(ReplicationStatus {
+            mode: if standby { ReplicationMode::Standby } else { ReplicationMode::Primary },
+            primary_url: self.config.primary_url.clone(),
+            key_configured: self.key.is_some(),
+            journal_id,
+            latest_seq,
+            cursor: standby.then_some(state.cursor),
+            last_sync: state.last_sync,
+            last_error: state.last_error.clone(),
+            replicated_objects: state.applied.len(),
+            pending_firewall_model: state.pending_model.as_ref().map(|m| m.replicated_at),
+            conflicts: state.conflicts.values().cloned().collect(),
+        })
+    }
+
+    // Makes this standby the primary: local changes are accepted from now on, the mode is
+    // saved to the config file and the replicated firewall model is staged for review
+    pub async fn promote(&self, promoted_by: &str) -> Result<Promotion> {
+        if !self.role.promote() {
+            return Err(anyhow!("This instance is already the primary"));
+        }
+        let promoted_at = Utc::now();
+        warn!("Promoted to primary by {}", promoted_by);
+
+        let patch = serde_json::json!({ "replication": { "mode": ReplicationMode::Primary } });
+        let config_saved = match self.config_history.update(&patch, promoted_by, "replication_promote") {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to save the primary mode to the config file: {}", e);
+                false
+            },
+        };
+
+        let pending = self.standby.lock().await.pending_model.take();
+        let staged_changeset = match pending {
+            Some(pending) => {
+                let changeset = self.network.stage_replicated_model(
+                    self.config.primary_url.as_deref().unwrap_or("the primary"),
+                    pending.replicated_at,
+                    pending.model,
+                    promoted_by.to_string(),
+                ).await?;
+                Some(changeset.id)
+            },
+            None => None,
+        };
+        let state = self.standby.lock().await;
+        self.write(STANDBY_FILE, &*state)?;
+
+        Ok(Promotion {
+            promoted_at,
+            config_saved,
+            staged_changeset,
+        })
+    }
+}
+
+enum Applied {
+    Unchanged,
+    Written,
+    Deleted,
+    Conflict,
+}
+
+fn load<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring invalid replication file {:?}: {}", path, e);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activity::ActivityLog;
+    use crate::bandwidth_quota::BandwidthQuotas;
+    use crate::config;
+    use crate::password_policy::PasswordPolicy;
+    use crate::script_lint::ScriptLinter;
+    use crate::security::SecurityManager;
+    use crate::sites::SiteManager;
+    use crate::tasks::TaskRegistry;
+    use crate::tickets::{TicketCategory, TicketPriority};
+    use crate::traffic_history::TrafficHistory;
+    use crate::visualizations::VisualizationManager;
+
+    fn replication(dir: &tempfile::TempDir, mode: ReplicationMode) -> Replication {
+        let root = dir.path().to_str().unwrap();
+        let config_path = format!("{}/config.toml", root);
+        let defaults = config::default_config();
+        config::save(&defaults, &config_path).unwrap();
+        let key_file = format!("{}/replication.key", root);
+        fs::write(&key_file, "0123456789abcdef0123").unwrap();
+
+        let alerts = AlertsManager::new(&format!("{}/alerts", root)).unwrap();
+        let visualizations = VisualizationManager::new(
+            SiteManager::new(&format!("{}/sites", root)).unwrap(),
+            TrafficHistory::new(&format!("{}/traffic", root)).unwrap(),
+            BandwidthQuotas::new(Default::default(), &format!("{}/bandwidth", root)).unwrap(),
+        );
+        let config_history = ConfigHistory::new(
+            &format!("{}/history", root),
+            &config_path,
+            SecurityManager::new([7u8; 32], &format!("{}/audit", root)).unwrap(),
+            PasswordPolicy::new(Default::default()),
+            visualizations,
+            TaskRegistry::new(defaults.tasks.clone(), alerts.clone()),
+        ).unwrap();
+        let scripts = ScriptsManager::new(
+            &dir.path().join("scripts"),
+            &dir.path().join("tmp"),
+            ScriptLinter::new(&defaults.script_lint).unwrap(),
+        ).unwrap();
+
+        Replication::new(
+            ReplicationConfig {
+                mode,
+                primary_url: Some("http://127.0.0.1:9".to_string()),
+                key_file: Some(key_file),
+                ..Default::default()
+            },
+            ReplicationRole::new(mode),
+            &format!("{}/replication", root),
+            TicketsManager::new(ActivityLog::new()),
+            Arc::new(Mutex::new(scripts)),
+            AssetManager::new(&format!("{}/assets", root)).unwrap(),
+            config_history,
+            Arc::new(NetworkManager::unavailable()),
+            alerts,
+            &HttpClients::new(&defaults.proxy).unwrap(),
+        ).unwrap()
+    }
+
+    fn create_ticket(tickets: &TicketsManager, title: &str) -> Uuid {
+        tickets.create_ticket(title.to_string(), "details".to_string(), TicketPriority::Medium,
+                              "alice".to_string(), TicketCategory::Network, Vec::new(), None, None).unwrap()
+    }
+
+    fn change(seq: u64, kind: ObjectKind, id: &str, object: Option<serde_json::Value>) -> Change {
+        Change {
+            seq,
+            kind,
+            id: id.to_string(),
+            hash: object.as_ref().map(|o| hash_value(o).unwrap()),
+            changed_at: Utc::now(),
+            object,
+        }
+    }
+
+    fn ticket_change(seq: u64, ticket: &Ticket) -> Change {
+        change(seq, ObjectKind::Ticket, &ticket.id.to_string(), Some(serde_json::to_value(ticket).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn the_feed_carries_only_what_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let primary = replication(&dir, ReplicationMode::Primary);
+
+        let first = primary.changes(0).await.unwrap();
+        assert!(first.changes.iter().any(|c| c.kind == ObjectKind::Config));
+        assert!(first.changes.iter().any(|c| c.kind == ObjectKind::FirewallModel));
+
+        let id = create_ticket(&primary.tickets, "Printer offline");
+        let page = primary.changes(first.latest_seq).await.unwrap();
+        assert_eq!(page.journal_id, first.journal_id);
+        assert_eq!(page.changes.len(), 1);
+        assert_eq!(page.changes[0].id, id.to_string());
+        assert!(page.changes[0].object.is_some());
+
+        primary.tickets.delete_ticket(id, "alice".to_string()).unwrap();
+        let page = primary.changes(page.latest_seq).await.unwrap();
+        assert_eq!(page.changes.len(), 1);
+        assert_eq!(page.changes[0].hash, None);
+        assert_eq!(page.changes[0].object, None);
+    }
+
+    #[tokio::test]
+    async fn a_standby_serves_no_feed() {
+        let dir = tempfile::tempdir().unwrap();
+        let standby = replication(&dir, ReplicationMode::Standby);
+        assert!(standby.changes(0).await.is_err());
+        assert!(standby.accepts_key("0123456789abcdef0123"));
+        assert!(!standby.accepts_key("0123456789abcdef012"));
+    }
+
+    #[tokio::test]
+    async fn changes_are_applied_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let standby = replication(&dir, ReplicationMode::Standby);
+        let source = TicketsManager::new(ActivityLog::new());
+        let ticket = source.get_ticket(create_ticket(&source, "VPN down")).unwrap();
+        let mut state = StandbyState::default();
+
+        assert!(matches!(standby.apply(&mut state, &ticket_change(1, &ticket)).unwrap(), Applied::Written));
+        assert_eq!(standby.tickets.get_ticket(ticket.id).unwrap().title, "VPN down");
+        assert!(matches!(standby.apply(&mut state, &ticket_change(1, &ticket)).unwrap(), Applied::Unchanged));
+
+        let deletion = change(2, ObjectKind::Ticket, &ticket.id.to_string(), None);
+        assert!(matches!(standby.apply(&mut state, &deletion).unwrap(), Applied::Deleted));
+        assert!(standby.tickets.get_ticket(ticket.id).is_err());
+    }
+
+    #[tokio::test]
+    async fn local_edits_conflict_until_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let standby = replication(&dir, ReplicationMode::Standby);
+        let source = TicketsManager::new(ActivityLog::new());
+        let mut ticket = source.get_ticket(create_ticket(&source, "VPN down")).unwrap();
+        let mut state = StandbyState::default();
+        standby.apply(&mut state, &ticket_change(1, &ticket)).unwrap();
+
+        standby.tickets.update_ticket(ticket.id, Some("VPN down at branch".to_string()), None, None, None,
+                                      None, None, None, None, None, "bob".to_string()).unwrap();
+        ticket.title = "VPN restored".to_string();
+        assert!(matches!(standby.apply(&mut state, &ticket_change(2, &ticket)).unwrap(), Applied::Conflict));
+        // Seen again at the next sync: still one alert
+        assert!(matches!(standby.apply(&mut state, &ticket_change(2, &ticket)).unwrap(), Applied::Conflict));
+        assert_eq!(standby.tickets.get_ticket(ticket.id).unwrap().title, "VPN down at branch");
+        assert_eq!(standby.alerts.get_all_alerts().unwrap().len(), 1);
+        assert!(state.conflicts.values().all(|c| c.alert_id.is_some()));
+
+        *standby.standby.lock().await = state;
+        standby.resolve_conflict(ObjectKind::Ticket, &ticket.id.to_string()).await.unwrap();
+        let mut state = standby.standby.lock().await.clone();
+        assert!(matches!(standby.apply(&mut state, &ticket_change(2, &ticket)).unwrap(), Applied::Written));
+        assert_eq!(standby.tickets.get_ticket(ticket.id).unwrap().title, "VPN restored");
+        assert!(state.conflicts.is_empty() && state.forced.is_empty());
+        assert!(standby.resolve_conflict(ObjectKind::Ticket, &ticket.id.to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn promotion_happens_once_and_stages_the_replicated_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let standby = replication(&dir, ReplicationMode::Standby);
+        let model = serde_json::to_value(standby.network.export_model().await).unwrap();
+        {
+            let mut state = standby.standby.lock().await;
+            let applied = standby.apply(&mut state, &change(1, ObjectKind::FirewallModel, "model", Some(model))).unwrap();
+            assert!(matches!(applied, Applied::Written));
+            assert!(state.pending_model.is_some());
+        }
+
+        let promotion = standby.promote("admin").await.unwrap();
+        assert!(!standby.role().is_standby());
+        assert!(promotion.config_saved);
+        assert!(promotion.staged_changeset.is_some());
+        assert_eq!(standby.config_history.current().unwrap().replication.mode, ReplicationMode::Primary);
+        assert!(standby.standby.lock().await.pending_model.is_none());
+
+        assert!(standby.promote("admin").await.is_err());
+    }
+}
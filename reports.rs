@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+
+use crate::config::ReportsConfig;
+use crate::annotations::Annotation;
+use crate::audit_chain::ChainVerification;
+use crate::models::{Alert, EventCategory, LogEntry, LogSeverity};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "text/plain; charset=utf-8",
+            ReportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+// Heading keys with their English text; translations use the same keys
+const ENGLISH: &[(&str, &str)] = &[
+    ("incident_report", "Incident Report"),
+    ("compliance_report", "Compliance Report"),
+    ("generated_at", "Generated at"),
+    ("period", "Period"),
+    ("period_to", "to"),
+    ("total_events", "Total Events"),
+    ("severity_summary", "Severity Summary"),
+    ("category_summary", "Category Summary"),
+    ("critical_and_error_events", "Critical and Error Events"),
+    ("event_timeline", "Event Timeline"),
+    ("events_in_period", "Events in Period"),
+    ("security_incidents", "Security Incidents"),
+    ("access_control_events", "Access Control Events"),
+    ("failed_access_attempts", "Failed Access Attempts"),
+    ("availability_incidents", "Availability Incidents"),
+    ("compliance_summary", "Compliance Summary"),
+    ("security_incident_rate", "Security Incident Rate"),
+    ("failed_access_rate", "Failed Access Rate"),
+    ("audit_trail_integrity", "Audit Trail Integrity"),
+    ("audit_chain_intact", "Hash chain intact"),
+    ("audit_chain_broken", "Hash chain broken"),
+    ("audit_chain_head", "Chain head"),
+    ("alert_report", "Alert Report"),
+    ("alert_details", "Details"),
+    ("annotations", "Annotations"),
+    ("references", "References"),
+    ("daily_digest", "Daily Digest"),
+    ("digest_alerts", "Alerts"),
+    ("digest_tickets", "Ticket Backlog"),
+    ("digest_sla_breaches", "SLA Breaches"),
+    ("digest_firewall_changes", "Firewall Changes"),
+    ("digest_link_flaps", "Interface Flaps"),
+    ("digest_top_sources", "Top Log Sources"),
+    ("digest_script_failures", "Script Execution Failures"),
+    ("digest_printer_supplies", "Printer Supplies Running Low"),
+    ("digest_chargeback", "Traffic per Accounting Group"),
+    ("digest_saved_searches", "Saved Searches"),
+    ("section_disabled", "Disabled"),
+    ("section_failed", "Not available"),
+    ("nothing_to_report", "Nothing to report"),
+];
+
+const CZECH: &[(&str, &str)] = &[
+    ("incident_report", "Zpráva o incidentu"),
+    ("compliance_report", "Zpráva o souladu"),
+    ("generated_at", "Vygenerováno"),
+    ("period", "Období"),
+    ("period_to", "až"),
+    ("total_events", "Celkem událostí"),
+    ("severity_summary", "Přehled podle závažnosti"),
+    ("category_summary", "Přehled podle kategorie"),
+    ("critical_and_error_events", "Kritické a chybové události"),
+    ("event_timeline", "Časová osa událostí"),
+    ("events_in_period", "Události v období"),
+    ("security_incidents", "Bezpečnostní incidenty"),
+    ("access_control_events", "Události řízení přístupu"),
+    ("failed_access_attempts", "Neúspěšné pokusy o přístup"),
+    ("availability_incidents", "Incidenty dostupnosti"),
+    ("compliance_summary", "Shrnutí souladu"),
+    ("security_incident_rate", "Podíl bezpečnostních incidentů"),
+    ("failed_access_rate", "Podíl neúspěšných přístupů"),
+    ("audit_trail_integrity", "Integrita auditní stopy"),
+    ("audit_chain_intact", "Řetězec hashů je neporušený"),
+    ("audit_chain_broken", "Řetězec hashů je porušený"),
+    ("audit_chain_head", "Hlava řetězce"),
+    ("alert_report", "Zpráva o výstraze"),
+    ("alert_details", "Podrobnosti"),
+    ("annotations", "Poznámky"),
+    ("references", "Odkazy"),
+    ("daily_digest", "Denní přehled"),
+    ("digest_alerts", "Výstrahy"),
+    ("digest_tickets", "Nevyřízené požadavky"),
+    ("digest_sla_breaches", "Porušení SLA"),
+    ("digest_firewall_changes", "Změny firewallu"),
+    ("digest_link_flaps", "Kolísání rozhraní"),
+    ("digest_top_sources", "Nejaktivnější zdroje logů"),
+    ("digest_script_failures", "Neúspěšná spuštění skriptů"),
+    ("digest_printer_supplies", "Docházející spotřební materiál tiskáren"),
+    ("digest_chargeback", "Provoz podle nákladových skupin"),
+    ("digest_saved_searches", "Uložená vyhledávání"),
+    ("section_disabled", "Vypnuto"),
+    ("section_failed", "Nedostupné"),
+    ("nothing_to_report", "Nic k hlášení"),
+];
+
+fn bundled(locale: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    match locale {
+        "en" => Some(ENGLISH),
+        "cs" => Some(CZECH),
+        _ => None,
+    }
+}
+
+// Report headings in one locale; any missing heading falls back to English
+#[derive(Debug, Clone)]
+pub struct Translations {
+    pub locale: String,
+    headings: HashMap<String, String>,
+}
+
+impl Translations {
+    // Bundled locales need no files; others are read from <locales_dir>/<locale>.json,
+    // a flat object of heading key to text
+    pub fn load(locale: &str, locales_dir: &Path) -> Self {
+        let english = Self {
+            locale: "en".to_string(),
+            headings: HashMap::new(),
+        };
+
+        if let Some(table) = bundled(locale) {
+            return Self {
+                locale: locale.to_string(),
+                headings: table.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            };
+        }
+
+        if locale.is_empty() || !locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            warn!("Invalid report locale {:?}, using English", locale);
+            return english;
+        }
+
+        let path = locales_dir.join(format!("{}.json", locale));
+        let headings = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str::<HashMap<String, String>>(&content).map_err(|e| e.to_string()));
+
+        match headings {
+            Ok(headings) => Self {
+                locale: locale.to_string(),
+                headings,
+            },
+            Err(e) => {
+                warn!("Report locale {} unavailable ({}: {}), using English", locale, path.display(), e);
+                english
+            },
+        }
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        self.headings.get(key)
+            .cloned()
+            .or_else(|| ENGLISH.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string()))
+            .unwrap_or_else(|| key.to_string())
+    }
+}
+
+// Per-request overrides of the [reports] settings
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReportOverrides {
+    pub organization_name: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+}
+
+// Branding and rendering settings for one report
+#[derive(Debug, Clone)]
+pub struct ReportSettings {
+    pub organization_name: String,
+    // data: URI of the logo, ready to embed
+    pub logo: Option<String>,
+    pub timezone: Tz,
+    pub translations: Translations,
+}
+
+fn load_logo(path: &str) -> Option<String> {
+    let mime = match Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => {
+            warn!("Unsupported report logo type: {}", path);
+            return None;
+        },
+    };
+
+    match fs::read(path) {
+        Ok(bytes) => Some(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))),
+        Err(e) => {
+            warn!("Failed to read report logo {}: {}", path, e);
+            None
+        },
+    }
+}
+
+impl ReportSettings {
+    // Unreadable logos, unknown timezones and missing locales degrade to no logo, UTC and English
+    pub fn resolve(config: &ReportsConfig, overrides: &ReportOverrides, locales_dir: &Path) -> Self {
+        let timezone_name = overrides.timezone.as_deref().unwrap_or(&config.timezone);
+        let timezone = timezone_name.parse::<Tz>().unwrap_or_else(|_| {
+            warn!("Unknown report timezone {:?}, using UTC", timezone_name);
+            Tz::UTC
+        });
+
+        Self {
+            organization_name: overrides.organization_name.clone().unwrap_or_else(|| config.organization_name.clone()),
+            logo: config.logo_path.as_deref().and_then(load_logo),
+            timezone,
+            translations: Translations::load(overrides.locale.as_deref().unwrap_or(&config.locale), locales_dir),
+        }
+    }
+
+    // Local time with the UTC offset, e.g. 2024-03-01 14:05:00 +01:00
+    pub fn timestamp(&self, ts: DateTime<Utc>) -> String {
+        ts.with_timezone(&self.timezone).format("%Y-%m-%d %H:%M:%S %:z").to_string()
+    }
+
+    fn t(&self, key: &str) -> String {
+        self.translations.get(key)
+    }
+}
+
+struct Section {
+    heading: String,
+    lines: Vec<String>,
+}
+
+// Generated report content, rendered to text or HTML with the settings' branding
+pub struct Report {
+    title: String,
+    header: Vec<String>,
+    sections: Vec<Section>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Report {
+    pub fn render(&self, settings: &ReportSettings, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.render_text(settings),
+            ReportFormat::Html => self.render_html(settings),
+        }
+    }
+
+    fn render_text(&self, settings: &ReportSettings) -> String {
+        let mut report = String::new();
+
+        writeln!(&mut report, "========================================").unwrap();
+        if !settings.organization_name.is_empty() {
+            writeln!(&mut report, "{}", settings.organization_name).unwrap();
+        }
+        writeln!(&mut report, "{}", self.title.to_uppercase()).unwrap();
+        for line in &self.header {
+            writeln!(&mut report, "{}", line).unwrap();
+        }
+        writeln!(&mut report, "========================================").unwrap();
+        writeln!(&mut report).unwrap();
+
+        for section in &self.sections {
+            writeln!(&mut report, "{}:", section.heading.to_uppercase()).unwrap();
+            for line in &section.lines {
+                writeln!(&mut report, "- {}", line).unwrap();
+            }
+            writeln!(&mut report).unwrap();
+        }
+
+        report
+    }
+
+    fn render_html(&self, settings: &ReportSettings) -> String {
+        let mut report = String::new();
+
+        writeln!(&mut report, "<!DOCTYPE html>").unwrap();
+        writeln!(&mut report, "<html lang=\"{}\">", escape_html(&settings.translations.locale)).unwrap();
+        writeln!(&mut report, "<head><meta charset=\"utf-8\"><title>{}</title></head>", escape_html(&self.title)).unwrap();
+        writeln!(&mut report, "<body>").unwrap();
+
+        writeln!(&mut report, "<header>").unwrap();
+        if let Some(logo) = &settings.logo {
+            writeln!(&mut report, "<img src=\"{}\" alt=\"{}\" style=\"max-height:64px\">", logo, escape_html(&settings.organization_name)).unwrap();
+        }
+        if !settings.organization_name.is_empty() {
+            writeln!(&mut report, "<p><strong>{}</strong></p>", escape_html(&settings.organization_name)).unwrap();
+        }
+        writeln!(&mut report, "</header>").unwrap();
+
+        writeln!(&mut report, "<h1>{}</h1>", escape_html(&self.title)).unwrap();
+        for line in &self.header {
+            writeln!(&mut report, "<p>{}</p>", escape_html(line)).unwrap();
+        }
+
+        for section in &self.sections {
+            writeln!(&mut report, "<h2>{}</h2>", escape_html(&section.heading)).unwrap();
+            if !section.lines.is_empty() {
+                writeln!(&mut report, "<ul>").unwrap();
+                for line in &section.lines {
+                    writeln!(&mut report, "<li>{}</li>", escape_html(line)).unwrap();
+                }
+                writeln!(&mut report, "</ul>").unwrap();
+            }
+        }
+
+        writeln!(&mut report, "</body>").unwrap();
+        writeln!(&mut report, "</html>").unwrap();
+
+        report
+    }
+}
+
+pub fn incident_report(entries: &[LogEntry], title: &str, settings: &ReportSettings) -> Report {
+    let mut sections = Vec::new();
+
+    // Summary by severity
+    let mut severity_counts: BTreeMap<LogSeverity, usize> = BTreeMap::new();
+    for entry in entries {
+        *severity_counts.entry(entry.severity.clone()).or_insert(0) += 1;
+    }
+
+    sections.push(Section {
+        heading: settings.t("severity_summary"),
+        lines: [LogSeverity::Debug, LogSeverity::Info, LogSeverity::Warning, LogSeverity::Error, LogSeverity::Critical]
+            .iter()
+            .map(|severity| format!("{:?}: {}", severity, severity_counts.get(severity).copied().unwrap_or(0)))
+            .collect(),
+    });
+
+    // Summary by category
+    let mut category_counts: BTreeMap<EventCategory, usize> = BTreeMap::new();
+    for entry in entries {
+        *category_counts.entry(entry.category).or_insert(0) += 1;
+    }
+
+    sections.push(Section {
+        heading: settings.t("category_summary"),
+        lines: category_counts.iter().map(|(category, count)| format!("{}: {}", category, count)).collect(),
+    });
+
+    // List critical and error events first
+    let serious: Vec<String> = entries.iter()
+        .filter(|e| e.severity == LogSeverity::Critical || e.severity == LogSeverity::Error)
+        .map(|e| format!("[{}] {} ({}, {}/{}): {}",
+                         settings.timestamp(e.timestamp), e.source, e.severity,
+                         e.category, e.event_type, e.message))
+        .collect();
+
+    if !serious.is_empty() {
+        sections.push(Section {
+            heading: settings.t("critical_and_error_events"),
+            lines: serious,
+        });
+    }
+
+    // Timeline of all events
+    sections.push(Section {
+        heading: settings.t("event_timeline"),
+        lines: entries.iter()
+            .map(|e| format!("[{}] {} - {}: {}", settings.timestamp(e.timestamp), e.severity, e.source, e.message))
+            .collect(),
+    });
+
+    Report {
+        title: format!("{}: {}", settings.t("incident_report"), title),
+        header: vec![
+            format!("{}: {}", settings.t("generated_at"), settings.timestamp(Utc::now())),
+            format!("{}: {}", settings.t("total_events"), entries.len()),
+        ],
+        sections,
+    }
+}
+
+pub fn compliance_report(entries: &[LogEntry],
+                         start_date: DateTime<Utc>,
+                         end_date: DateTime<Utc>,
+                         audit_chain: &ChainVerification,
+                         settings: &ReportSettings) -> Report {
+    let mut sections = Vec::new();
+
+    // Filter events in the date range
+    let filtered_entries: Vec<_> = entries.iter()
+        .filter(|e| e.timestamp >= start_date && e.timestamp <= end_date)
+        .collect();
+
+    sections.push(Section {
+        heading: format!("{}: {}", settings.t("events_in_period"), filtered_entries.len()),
+        lines: EventCategory::all().into_iter()
+            .map(|category| format!("{}: {}", category, filtered_entries.iter().filter(|e| e.category == category).count()))
+            .collect(),
+    });
+
+    // Security incidents summary
+    let security_incidents: Vec<_> = filtered_entries.iter()
+        .filter(|e| e.category.is_security() &&
+               (e.severity == LogSeverity::Error || e.severity == LogSeverity::Critical))
+        .collect();
+
+    sections.push(Section {
+        heading: format!("{}: {}", settings.t("security_incidents"), security_incidents.len()),
+        lines: security_incidents.iter()
+            .map(|e| format!("[{}] {} ({}/{}): {}",
+                             settings.timestamp(e.timestamp), e.source, e.category, e.event_type, e.message))
+            .collect(),
+    });
+
+    // Access control events
+    let access_events: Vec<_> = filtered_entries.iter()
+        .filter(|e| e.category == EventCategory::Authentication || e.category == EventCategory::Authorization)
+        .collect();
+    let failed_access = access_events.iter()
+        .filter(|e| e.message.contains("failed") || e.message.contains("denied"))
+        .count();
+
+    sections.push(Section {
+        heading: format!("{}: {}", settings.t("access_control_events"), access_events.len()),
+        lines: vec![format!("{}: {}", settings.t("failed_access_attempts"), failed_access)],
+    });
+
+    // System availability
+    let availability_incidents: Vec<_> = filtered_entries.iter()
+        .filter(|e| e.category == EventCategory::SystemAvailability && e.severity == LogSeverity::Critical)
+        .collect();
+
+    sections.push(Section {
+        heading: format!("{}: {}", settings.t("availability_incidents"), availability_incidents.len()),
+        lines: availability_incidents.iter()
+            .map(|e| format!("[{}] {}: {}", settings.timestamp(e.timestamp), e.source, e.message))
+            .collect(),
+    });
+
+    // Compliance summary
+    let security_rate = if filtered_entries.is_empty() { 0.0 } else { (security_incidents.len() as f64 / filtered_entries.len() as f64) * 100.0 };
+    let failed_rate = if access_events.is_empty() { 0.0 } else { (failed_access as f64 / access_events.len() as f64) * 100.0 };
+
+    sections.push(Section {
+        heading: settings.t("compliance_summary"),
+        lines: vec![
+            format!("{}: {:.2}%", settings.t("security_incident_rate"), security_rate),
+            format!("{}: {:.2}%", settings.t("failed_access_rate"), failed_rate),
+        ],
+    });
+
+    // Whether the audit trail can be shown to be unedited
+    let mut integrity = vec![match &audit_chain.first_break {
+        None => format!("{} ({} events)", settings.t("audit_chain_intact"), audit_chain.events),
+        Some(chain_break) => format!("{}: #{} {}", settings.t("audit_chain_broken"), chain_break.index, chain_break.reason),
+    }];
+    integrity.push(format!("{}: {}", settings.t("audit_chain_head"), audit_chain.head));
+
+    sections.push(Section {
+        heading: settings.t("audit_trail_integrity"),
+        lines: integrity,
+    });
+
+    Report {
+        title: settings.t("compliance_report"),
+        header: vec![
+            format!("{}: {}", settings.t("generated_at"), settings.timestamp(Utc::now())),
+            format!("{}: {} {} {}", settings.t("period"),
+                    settings.timestamp(start_date), settings.t("period_to"), settings.timestamp(end_date)),
+        ],
+        sections,
+    }
+}
+
+// A single alert with the analysts' annotations, for handing over or archiving
+pub fn alert_report(alert: &Alert, annotations: &[Annotation], settings: &ReportSettings) -> Report {
+    let mut sections = vec![Section {
+        heading: settings.t("alert_details"),
+        lines: vec![
+            format!("{:?}: {}", alert.severity, alert.description),
+            format!("{:?}, {}", alert.status, alert.source),
+        ],
+    }];
+
+    if !annotations.is_empty() {
+        sections.push(Section {
+            heading: settings.t("annotations"),
+            lines: annotations.iter()
+                .map(|a| match a.end {
+                    Some(end) => format!("[{} - {}] {}: {}", settings.timestamp(a.start), settings.timestamp(end), a.author, a.text),
+                    None => format!("[{}] {}: {}", settings.timestamp(a.start), a.author, a.text),
+                })
+                .collect(),
+        });
+    }
+
+    if !alert.references.is_empty() {
+        sections.push(Section {
+            heading: settings.t("references"),
+            lines: alert.references.iter()
+                .map(|r| {
+                    let target = r.href.as_deref().unwrap_or(&r.value);
+                    if target == r.label {
+                        r.label.clone()
+                    } else {
+                        format!("{}: {}", r.label, target)
+                    }
+                })
+                .collect(),
+        });
+    }
+
+    Report {
+        title: format!("{}: {}", settings.t("alert_report"), alert.title),
+        header: vec![
+            format!("{}: {}", settings.t("generated_at"), settings.timestamp(Utc::now())),
+            settings.timestamp(alert.created_at),
+        ],
+        sections,
+    }
+}
+
+// Content of one digest section. Disabled and failed subsystems are reported as such,
+// a section is never silently left out.
+pub enum SectionContent {
+    Lines(Vec<String>),
+    Disabled(String),
+    Failed(String),
+}
+
+impl From<Vec<String>> for SectionContent {
+    fn from(lines: Vec<String>) -> Self {
+        SectionContent::Lines(lines)
+    }
+}
+
+pub struct DigestSection {
+    pub heading_key: &'static str,
+    pub content: SectionContent,
+}
+
+pub fn digest_report(digest: Vec<DigestSection>,
+                     start_date: DateTime<Utc>,
+                     end_date: DateTime<Utc>,
+                     settings: &ReportSettings) -> Report {
+    let sections = digest.into_iter()
+        .map(|section| Section {
+            heading: settings.t(section.heading_key),
+            lines: match section.content {
+                SectionContent::Lines(lines) if lines.is_empty() => vec![settings.t("nothing_to_report")],
+                SectionContent::Lines(lines) => lines,
+                SectionContent::Disabled(reason) => vec![format!("{}: {}", settings.t("section_disabled"), reason)],
+                SectionContent::Failed(error) => vec![format!("{}: {}", settings.t("section_failed"), error)],
+            },
+        })
+        .collect();
+
+    Report {
+        title: settings.t("daily_digest"),
+        header: vec![
+            format!("{}: {}", settings.t("generated_at"), settings.timestamp(Utc::now())),
+            format!("{}: {} {} {}", settings.t("period"),
+                    settings.timestamp(start_date), settings.t("period_to"), settings.timestamp(end_date)),
+        ],
+        sections,
+    }
+}
@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::time::Instant;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use anyhow::{Result, Context};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use tokio::task::JoinHandle;
+use tracing::{debug, Instrument, Span};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use uuid::Uuid;
+
+use crate::config::TracingConfig;
+
+// Taken from the caller when present, generated otherwise, and echoed on the response
+// and on outbound webhook and SMTP calls
+pub const CORRELATION_HEADER: HeaderName = HeaderName::from_static("x-correlation-id");
+
+const MAX_ID_LEN: usize = 64;
+
+// Paths whose next segment is a secret, recorded as the placeholder instead
+const SECRET_PATH_SEGMENTS: [(&str, &str); 1] = [
+    ("/api/portal/tickets/", ":token"),
+];
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+// Correlation id of the request the current task works for, None outside of requests
+// (schedules, collectors)
+pub fn current() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+// A caller's id is kept only when short and plain, so it goes into headers and logs as is
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// The request path for the span; the query is left out as it can carry tokens
+fn span_path(path: &str) -> String {
+    for (prefix, placeholder) in SECRET_PATH_SEGMENTS {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            let tail = rest.find('/').map(|i| &rest[i..]).unwrap_or("");
+            return format!("{}{}{}", prefix, placeholder, tail);
+        }
+    }
+    path.to_string()
+}
+
+// Runs every request in a span carrying its correlation id, method and path. Bodies and
+// headers are never recorded.
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let id = request.headers().get(&CORRELATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!("request",
+        correlation_id = %id,
+        method = %request.method(),
+        path = %span_path(request.uri().path()),
+        status = tracing::field::Empty,
+    );
+
+    let started = Instant::now();
+    let mut response = CORRELATION_ID.scope(id.clone(), next.run(request).instrument(span.clone())).await;
+    span.record("status", response.status().as_u16());
+    span.in_scope(|| debug!("Request finished in {}ms", started.elapsed().as_millis()));
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(CORRELATION_HEADER, value);
+    }
+    response
+}
+
+// tokio::spawn for background work a request starts; the task keeps the request's
+// correlation id and span
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = future.instrument(Span::current());
+    match current() {
+        Some(id) => tokio::spawn(CORRELATION_ID.scope(id, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+// tokio::task::spawn_blocking counterpart of spawn
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = Span::current();
+    let id = current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        match id {
+            Some(id) => CORRELATION_ID.sync_scope(id, f),
+            None => f(),
+        }
+    })
+}
+
+// Adds the correlation id header to an outbound HTTP call made for a request
+pub fn correlate(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match current() {
+        Some(id) => request.header(CORRELATION_HEADER, id),
+        None => request,
+    }
+}
+
+fn otlp_tracer(endpoint: &str, service_name: &str) -> Result<opentelemetry_sdk::trace::Tracer> {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![KeyValue::new("service.name", service_name.to_string())]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .context(format!("Failed to set up the OTLP exporter for {}", endpoint))
+}
+
+// Log output at INFO, plus span export over OTLP when an endpoint is configured
+pub fn init(config: &TracingConfig) -> Result<()> {
+    let tracer = match &config.otlp_endpoint {
+        Some(endpoint) => Some(otlp_tracer(endpoint, &config.service_name)?),
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .try_init()
+        .context("Failed to initialize logging")?;
+    Ok(())
+}
+
+// Flushes spans still waiting for export
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,550 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::warn;
+
+use crate::config::DnsConfig;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+// Lookups queued at once; beyond this a lookup is skipped and retried on the next miss
+const MAX_PENDING_LOOKUPS: usize = 1024;
+
+// A cached lookup; a failed or empty lookup is cached too, for negative_ttl_secs
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntry<V> {
+    pub value: V,
+    pub resolved_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl<V> CacheEntry<V> {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+}
+
+// Holds at most `capacity` entries; when full, the entry expiring first makes room
+struct BoundedCache<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> BoundedCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<CacheEntry<V>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, entry: CacheEntry<V>) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, e)| e.expires_at)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, entry);
+    }
+}
+
+// Name queried for the PTR record of an address
+fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        },
+        IpAddr::V6(v6) => {
+            let mut name = String::new();
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        },
+    }
+}
+
+fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(32 + name.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired; one question, no other sections
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow!("Invalid DNS name: {}", name));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    Ok(packet)
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Result<u16> {
+    match packet.get(pos..pos + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(anyhow!("Truncated DNS response")),
+    }
+}
+
+// Reads a possibly compressed name; returns it and the position after it
+fn read_name(packet: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Every pointer must go backwards, which also bounds the loop
+    let mut limit = pos;
+
+    loop {
+        let len = *packet.get(pos).ok_or_else(|| anyhow!("Truncated DNS response"))? as usize;
+        if len & 0xc0 == 0xc0 {
+            let target = (read_u16(packet, pos)? & 0x3fff) as usize;
+            if target >= limit {
+                return Err(anyhow!("Invalid DNS name compression"));
+            }
+            end.get_or_insert(pos + 2);
+            limit = target;
+            pos = target;
+        } else if len == 0 {
+            return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+        } else {
+            let label = packet.get(pos + 1..pos + 1 + len).ok_or_else(|| anyhow!("Truncated DNS response"))?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            pos += 1 + len;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Record {
+    Address(IpAddr),
+    Name(String),
+}
+
+// Records of the asked type and the lowest TTL among them; NXDOMAIN is an empty answer
+fn parse_response(packet: &[u8], id: u16, qtype: u16) -> Result<(Vec<Record>, u32)> {
+    if read_u16(packet, 0)? != id {
+        return Err(anyhow!("DNS response for another query"));
+    }
+    let flags = read_u16(packet, 2)?;
+    match flags & 0x000f {
+        0 | 3 => {},
+        rcode => return Err(anyhow!("DNS server answered with error code {}", rcode)),
+    }
+
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(packet, pos)?.1 + 4;
+    }
+
+    let mut records = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        pos = read_name(packet, pos)?.1;
+        let rtype = read_u16(packet, pos)?;
+        let record_ttl = (u32::from(read_u16(packet, pos + 4)?) << 16) | u32::from(read_u16(packet, pos + 6)?);
+        let rdlength = read_u16(packet, pos + 8)? as usize;
+        let rdata = pos + 10;
+        let data = packet.get(rdata..rdata + rdlength).ok_or_else(|| anyhow!("Truncated DNS response"))?;
+
+        let record = match (rtype, data.len()) {
+            (TYPE_A, 4) if rtype == qtype => Some(Record::Address(IpAddr::from([data[0], data[1], data[2], data[3]]))),
+            (TYPE_AAAA, 16) if rtype == qtype => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                Some(Record::Address(IpAddr::from(octets)))
+            },
+            (TYPE_PTR, _) if rtype == qtype => Some(Record::Name(read_name(packet, rdata)?.0)),
+            // CNAMEs and anything else; the target records follow in the same answer
+            _ => None,
+        };
+        if let Some(record) = record {
+            records.push(record);
+            ttl = ttl.min(record_ttl);
+        }
+        pos = rdata + rdlength;
+    }
+
+    Ok((records, ttl))
+}
+
+// Servers from the config, else the system's from /etc/resolv.conf
+fn dns_servers(config: &DnsConfig) -> Vec<SocketAddr> {
+    let configured: Vec<String> = if config.servers.is_empty() {
+        std::fs::read_to_string("/etc/resolv.conf")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .map(|server| server.trim().to_string())
+            .collect()
+    } else {
+        config.servers.clone()
+    };
+
+    configured.iter()
+        .filter_map(|server| match server.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr),
+            Err(_) => match server.parse::<IpAddr>() {
+                Ok(ip) => Some(SocketAddr::new(ip, 53)),
+                Err(_) => {
+                    warn!("Ignoring invalid DNS server: {}", server);
+                    None
+                },
+            },
+        })
+        .collect()
+}
+
+// Reverse DNS for addresses shown on dashboards and forward resolution of names.
+// Readers only ever see the cache: a miss or an expired entry queues a lookup in the
+// background and the name fills in on a later read.
+#[derive(Clone)]
+pub struct Resolver {
+    config: DnsConfig,
+    servers: Vec<SocketAddr>,
+    reverse: Arc<Mutex<BoundedCache<IpAddr, Option<String>>>>,
+    forward: Arc<Mutex<BoundedCache<String, Vec<IpAddr>>>>,
+    // Lookups queued or running, so a busy address is looked up once
+    pending: Arc<Mutex<HashSet<String>>>,
+    lookups: Arc<Semaphore>,
+}
+
+impl Resolver {
+    pub fn new(config: DnsConfig) -> Self {
+        let servers = dns_servers(&config);
+        if servers.is_empty() {
+            warn!("No DNS servers configured or found in /etc/resolv.conf, reverse lookups will fail");
+        }
+
+        Self {
+            servers,
+            reverse: Arc::new(Mutex::new(BoundedCache::new(config.cache_size))),
+            forward: Arc::new(Mutex::new(BoundedCache::new(config.cache_size))),
+            pending: Arc::new(Mutex::new(HashSet::new())),
+            lookups: Arc::new(Semaphore::new(config.max_concurrent_lookups.max(1))),
+            config,
+        }
+    }
+
+    fn expiry(&self, now: DateTime<Utc>, found: bool, ttl: u32) -> DateTime<Utc> {
+        let secs = if found {
+            u64::from(ttl).min(self.config.max_ttl_secs)
+        } else {
+            self.config.negative_ttl_secs
+        };
+        now + Duration::seconds(secs as i64)
+    }
+
+    async fn query(&self, name: &str, qtype: u16) -> Result<(Vec<Record>, u32)> {
+        let timeout = std::time::Duration::from_millis(self.config.timeout_ms);
+        let mut last_error = anyhow!("No DNS servers available");
+
+        for server in &self.servers {
+            let random = Uuid::new_v4();
+            let id = u16::from_be_bytes([random.as_bytes()[0], random.as_bytes()[1]]);
+            let attempt = async {
+                let bind: SocketAddr = if server.is_ipv4() { "0.0.0.0:0".parse()? } else { "[::]:0".parse()? };
+                let socket = UdpSocket::bind(bind).await.context("Failed to open DNS socket")?;
+                socket.send_to(&build_query(id, name, qtype)?, server).await
+                    .context(format!("Failed to query DNS server {}", server))?;
+                let mut buf = [0u8; 1500];
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                if from.ip() != server.ip() {
+                    return Err(anyhow!("DNS response from unexpected address {}", from));
+                }
+                parse_response(&buf[..len], id, qtype)
+            };
+
+            match tokio::time::timeout(timeout, attempt).await {
+                Ok(Ok(answer)) => return Ok(answer),
+                Ok(Err(e)) => last_error = e,
+                Err(_) => last_error = anyhow!("DNS server {} did not answer within {} ms", server, self.config.timeout_ms),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    // Looks the address up now and caches the result; failures are cached as "no name"
+    pub async fn lookup_reverse(&self, ip: IpAddr) -> CacheEntry<Option<String>> {
+        let (hostname, ttl) = match self.query(&reverse_name(&ip), TYPE_PTR).await {
+            Ok((records, ttl)) => {
+                let name = records.into_iter().find_map(|r| match r {
+                    Record::Name(name) => Some(name),
+                    Record::Address(_) => None,
+                });
+                (name, ttl)
+            },
+            Err(e) => {
+                warn!("Reverse lookup of {} failed: {}", ip, e);
+                (None, 0)
+            },
+        };
+
+        let now = Utc::now();
+        let entry = CacheEntry {
+            expires_at: self.expiry(now, hostname.is_some(), ttl),
+            value: hostname,
+            resolved_at: now,
+        };
+        if let Ok(mut cache) = self.reverse.lock() {
+            cache.insert(ip, entry.clone());
+        }
+        entry
+    }
+
+    // Without configured servers names go through the system resolver, so /etc/hosts
+    // applies; it does not report TTLs and answers are kept for max_ttl_secs
+    pub async fn lookup_forward(&self, name: &str) -> CacheEntry<Vec<IpAddr>> {
+        let result = if self.config.servers.is_empty() {
+            tokio::net::lookup_host((name, 0)).await
+                .map(|addrs| (addrs.map(|a| a.ip()).collect::<Vec<_>>(), u32::MAX))
+                .map_err(|e| anyhow!(e))
+        } else {
+            let mut addresses = Vec::new();
+            let mut ttl = u32::MAX;
+            let mut result = Ok(());
+            for qtype in [TYPE_A, TYPE_AAAA] {
+                match self.query(name, qtype).await {
+                    Ok((records, record_ttl)) => {
+                        addresses.extend(records.into_iter().filter_map(|r| match r {
+                            Record::Address(ip) => Some(ip),
+                            Record::Name(_) => None,
+                        }));
+                        ttl = ttl.min(record_ttl);
+                    },
+                    Err(e) => result = Err(e),
+                }
+            }
+            match result {
+                Err(e) if addresses.is_empty() => Err(e),
+                _ => Ok((addresses, ttl)),
+            }
+        };
+
+        let (mut addresses, ttl) = result.unwrap_or_else(|e| {
+            warn!("Lookup of {} failed: {}", name, e);
+            (Vec::new(), 0)
+        });
+        addresses.sort();
+        addresses.dedup();
+
+        let now = Utc::now();
+        let entry = CacheEntry {
+            expires_at: self.expiry(now, !addresses.is_empty(), ttl),
+            value: addresses,
+            resolved_at: now,
+        };
+        if let Ok(mut cache) = self.forward.lock() {
+            cache.insert(name.to_lowercase(), entry.clone());
+        }
+        entry
+    }
+
+    // Queues a lookup unless one for the same key is already queued or the queue is full
+    fn schedule<F>(&self, key: String, lookup: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        match self.pending.lock() {
+            Ok(mut pending) => {
+                if pending.len() >= MAX_PENDING_LOOKUPS || !pending.insert(key.clone()) {
+                    return;
+                }
+            },
+            Err(_) => return,
+        }
+
+        let pending = self.pending.clone();
+        let lookups = self.lookups.clone();
+        tokio::spawn(async move {
+            if let Ok(_permit) = lookups.acquire().await {
+                lookup.await;
+            }
+            if let Ok(mut pending) = pending.lock() {
+                pending.remove(&key);
+            }
+        });
+    }
+
+    pub fn cached_reverse(&self, ip: &IpAddr) -> Option<CacheEntry<Option<String>>> {
+        self.reverse.lock().ok()?.get(ip)
+    }
+
+    pub fn cached_forward(&self, name: &str) -> Option<CacheEntry<Vec<IpAddr>>> {
+        self.forward.lock().ok()?.get(&name.to_lowercase())
+    }
+
+    // Cached name of the address, never waiting on DNS; an expired name is still
+    // returned while it is refreshed
+    pub fn hostname(&self, ip: IpAddr) -> Option<String> {
+        if ip.is_unspecified() {
+            return None;
+        }
+
+        let cached = self.cached_reverse(&ip);
+        if !cached.as_ref().map_or(false, |e| e.is_fresh(Utc::now())) {
+            let resolver = self.clone();
+            self.schedule(format!("ptr:{}", ip), async move {
+                resolver.lookup_reverse(ip).await;
+            });
+        }
+        cached.and_then(|e| e.value)
+    }
+
+    // Same for an address kept as text; anything that is not an IP has no name to add
+    pub fn hostname_of(&self, address: &str) -> Option<String> {
+        address.parse::<IpAddr>().ok().and_then(|ip| self.hostname(ip))
+    }
+
+    // Cached addresses of a name, never waiting on DNS
+    pub fn addresses(&self, name: &str) -> Vec<IpAddr> {
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return vec![ip];
+        }
+
+        let cached = self.cached_forward(name);
+        if !cached.as_ref().map_or(false, |e| e.is_fresh(Utc::now())) {
+            let resolver = self.clone();
+            let name = name.to_string();
+            self.schedule(format!("name:{}", name.to_lowercase()), async move {
+                resolver.lookup_forward(&name).await;
+            });
+        }
+        cached.map(|e| e.value).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Response header for query 0x1234 with one question and `answers` answers
+    fn response(answers: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x81, 0x80, 0x00, 0x01];
+        packet.extend_from_slice(&answers.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        // Question: host.lan A IN, at offset 12
+        packet.extend_from_slice(b"\x04host\x03lan\x00\x00\x01\x00\x01");
+        packet
+    }
+
+    fn answer(packet: &mut Vec<u8>, rtype: u16, ttl: u32, data: &[u8]) {
+        // Name is a pointer to the question
+        packet.extend_from_slice(&[0xc0, 12]);
+        packet.extend_from_slice(&rtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet.extend_from_slice(&ttl.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+    }
+
+    #[test]
+    fn answers_are_parsed_with_the_lowest_ttl() {
+        let mut packet = response(3);
+        answer(&mut packet, TYPE_A, 300, &[192, 168, 1, 10]);
+        answer(&mut packet, 5, 10, b"\x05other\xc0\x11");
+        answer(&mut packet, TYPE_A, 120, &[192, 168, 1, 11]);
+
+        let (records, ttl) = parse_response(&packet, 0x1234, TYPE_A).unwrap();
+        assert_eq!(records, vec![
+            Record::Address("192.168.1.10".parse().unwrap()),
+            Record::Address("192.168.1.11".parse().unwrap()),
+        ]);
+        assert_eq!(ttl, 120);
+    }
+
+    #[test]
+    fn compressed_ptr_names_are_followed() {
+        let mut packet = response(1);
+        // router + pointer to "lan" in the question
+        answer(&mut packet, TYPE_PTR, 60, b"\x06router\xc0\x11");
+
+        let (records, _) = parse_response(&packet, 0x1234, TYPE_PTR).unwrap();
+        assert_eq!(records, vec![Record::Name("router.lan".to_string())]);
+    }
+
+    #[test]
+    fn truncated_responses_are_rejected() {
+        let mut packet = response(1);
+        answer(&mut packet, TYPE_A, 300, &[192, 168, 1, 10]);
+
+        for len in [0, 5, 12, 20, packet.len() - 12, packet.len() - 1] {
+            assert!(parse_response(&packet[..len], 0x1234, TYPE_A).is_err(), "length {}", len);
+        }
+        // Declared rdata longer than the packet
+        let mut long = response(1);
+        answer(&mut long, TYPE_A, 300, &[192, 168, 1, 10]);
+        let rdlength = long.len() - 6;
+        long[rdlength] = 0x40;
+        assert!(parse_response(&long, 0x1234, TYPE_A).is_err());
+    }
+
+    #[test]
+    fn pointer_loops_are_rejected() {
+        // A name pointing at itself
+        let mut packet = vec![0u8; 12];
+        packet.extend_from_slice(&[0xc0, 12]);
+        assert!(read_name(&packet, 12).is_err());
+
+        // Two names pointing at each other
+        let mut packet = vec![0u8; 12];
+        packet.extend_from_slice(&[0x01, b'a', 0xc0, 16, 0x01, b'b', 0xc0, 12]);
+        assert!(read_name(&packet, 12).is_err());
+        assert!(read_name(&packet, 16).is_err());
+    }
+
+    #[test]
+    fn out_of_range_offsets_are_rejected() {
+        let mut packet = vec![0u8; 12];
+        packet.extend_from_slice(&[0xc0, 0xff]);
+        assert!(read_name(&packet, 12).is_err());
+        assert!(read_name(&packet, 100).is_err());
+        assert!(read_u16(&packet, packet.len() - 1).is_err());
+    }
+
+    #[test]
+    fn errors_and_other_queries_are_rejected() {
+        let packet = response(0);
+        assert!(parse_response(&packet, 0x4321, TYPE_A).is_err());
+
+        let mut servfail = response(0);
+        servfail[3] = 0x82;
+        assert!(parse_response(&servfail, 0x1234, TYPE_A).is_err());
+
+        let mut nxdomain = response(0);
+        nxdomain[3] = 0x83;
+        assert_eq!(parse_response(&nxdomain, 0x1234, TYPE_A).unwrap().0, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn pending_lookups_are_capped() {
+        let resolver = Resolver::new(DnsConfig::default());
+        resolver.pending.lock().unwrap().extend((0..MAX_PENDING_LOOKUPS).map(|i| format!("ptr:{}", i)));
+
+        resolver.schedule("ptr:extra".to_string(), async {});
+        let pending = resolver.pending.lock().unwrap();
+        assert_eq!(pending.len(), MAX_PENDING_LOOKUPS);
+        assert!(!pending.contains("ptr:extra"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::info;
+
+use crate::models::{NetworkScan, ScanFinding, ScanStatus};
+
+// A finding together with the scan it came from
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanFindingRecord {
+    pub scan_id: Uuid,
+    pub scanned_at: DateTime<Utc>,
+    pub scan_type: String,
+    pub finding: ScanFinding,
+}
+
+// Results reported by network scanners, kept in scan time order
+#[derive(Clone)]
+pub struct ScanManager {
+    scans: Arc<Mutex<Vec<NetworkScan>>>,
+}
+
+impl ScanManager {
+    pub fn new() -> Self {
+        Self {
+            scans: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn record_scan(&self,
+                       timestamp: Option<DateTime<Utc>>,
+                       target_ip: String,
+                       scan_type: String,
+                       status: ScanStatus,
+                       findings: Vec<ScanFinding>,
+                       initiated_by: String) -> Result<NetworkScan> {
+        let scan = NetworkScan {
+            id: Uuid::new_v4(),
+            timestamp: timestamp.unwrap_or_else(Utc::now),
+            target_ip,
+            scan_type,
+            status,
+            findings,
+            initiated_by,
+        };
+
+        match self.scans.lock() {
+            Ok(mut scans) => {
+                let position = scans.partition_point(|s| s.timestamp <= scan.timestamp);
+                scans.insert(position, scan.clone());
+                info!("Recorded {} scan of {} with {} findings", scan.scan_type, scan.target_ip, scan.findings.len());
+                Ok(scan)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on scans")),
+        }
+    }
+
+    // Returns all scans, newest first
+    pub fn get_scans(&self) -> Result<Vec<NetworkScan>> {
+        match self.scans.lock() {
+            Ok(scans) => Ok(scans.iter().rev().cloned().collect()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on scans")),
+        }
+    }
+
+    // Findings of scans within the range that satisfy the predicate, newest first
+    pub fn findings_between<F>(&self,
+                               from: Option<DateTime<Utc>>,
+                               to: Option<DateTime<Utc>>,
+                               limit: usize,
+                               predicate: F) -> Result<Vec<ScanFindingRecord>>
+    where
+        F: Fn(&NetworkScan, &ScanFinding) -> bool,
+    {
+        match self.scans.lock() {
+            Ok(scans) => {
+                let end = to.map_or(scans.len(), |to| scans.partition_point(|s| s.timestamp <= to));
+                let start = from.map_or(0, |from| scans.partition_point(|s| s.timestamp < from));
+
+                Ok(scans[start..end.max(start)].iter()
+                    .rev()
+                    .flat_map(|scan| scan.findings.iter()
+                        .filter(|f| predicate(scan, f))
+                        .map(move |f| ScanFindingRecord {
+                            scan_id: scan.id,
+                            scanned_at: scan.timestamp,
+                            scan_type: scan.scan_type.clone(),
+                            finding: f.clone(),
+                        }))
+                    .take(limit)
+                    .collect())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on scans")),
+        }
+    }
+}
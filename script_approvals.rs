@@ -0,0 +1,142 @@
+use chrono::Utc;
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::models::User;
+use crate::notifications::Notifier;
+use crate::scripts::{ReviewStatus, Script};
+use crate::security::AccessControl;
+use crate::users::UserManager;
+
+// Permission of the users asked to review script changes
+pub const APPROVE_PERMISSION: &str = "script:approve";
+
+// Notifies approvers of scripts awaiting review and authors of the decision, by email
+// and/or webhook as each user's notification preferences say
+#[derive(Clone)]
+pub struct ScriptApprovals {
+    users: UserManager,
+    access_control: AccessControl,
+    notifier: Notifier,
+}
+
+impl ScriptApprovals {
+    pub fn new(users: UserManager, access_control: AccessControl, notifier: Notifier) -> Self {
+        Self {
+            users,
+            access_control,
+            notifier,
+        }
+    }
+
+    fn approvers(&self) -> Result<Vec<User>> {
+        Ok(self.users.get_all_users()?
+            .into_iter()
+            .filter(|u| u.is_active && self.access_control.check_permission(&u.role.role_name(), APPROVE_PERMISSION))
+            .collect())
+    }
+
+    // Failed deliveries are logged, a notification never fails the script change
+    async fn deliver(&self, recipients: &[User], subject: &str, body: &str, payload: serde_json::Value) {
+        let emails: Vec<String> = recipients.iter()
+            .filter(|u| u.notifications.email && !u.email.is_empty())
+            .map(|u| u.email.clone())
+            .collect();
+        if !emails.is_empty() {
+            if let Err(e) = self.notifier.send_email(&emails, subject, body).await {
+                warn!("Failed to email script approval notification: {}", e);
+            }
+        }
+
+        for user in recipients {
+            if let Some(url) = &user.notifications.webhook_url {
+                if let Err(e) = self.notifier.post_webhook(url, &payload).await {
+                    warn!("Failed to post script approval notification for {}: {}", user.username, e);
+                }
+            }
+        }
+    }
+
+    pub async fn review_requested(&self, script: &Script) -> Result<()> {
+        let approvers = self.approvers()?;
+        if approvers.is_empty() {
+            warn!("No active user holds {}, script {} waits unnoticed", APPROVE_PERMISSION, script.id);
+            return Ok(());
+        }
+
+        let author = script.review.requested_by.as_deref().unwrap_or(&script.created_by);
+        let subject = format!("Script review requested: {}", script.name);
+        let body = format!(
+            "{} asks for a review of script \"{}\" ({}).\n\nThe pending changes are listed by GET /api/scripts/pending-approvals.\n",
+            author,
+            script.name,
+            script.id,
+        );
+        let payload = serde_json::json!({
+            "event": "script.review_requested",
+            "script_id": script.id,
+            "name": script.name,
+            "author": author,
+        });
+
+        self.deliver(&approvers, &subject, &body, payload).await;
+        info!("Notified {} approvers of script {}", approvers.len(), script.id);
+        Ok(())
+    }
+
+    pub async fn decided(&self, script: &Script) -> Result<()> {
+        let author = match script.review.requested_by.as_deref() {
+            Some(author) => author,
+            None => &script.created_by,
+        };
+        let user = self.users.get_user(author)?;
+        if !user.is_active {
+            return Ok(());
+        }
+
+        let decided_by = script.review.decided_by.as_deref().unwrap_or("unknown");
+        let (verdict, event) = match script.review.status {
+            ReviewStatus::Approved => ("approved", "script.approved"),
+            ReviewStatus::Rejected => ("rejected", "script.rejected"),
+            ReviewStatus::ReviewRequested => return Ok(()),
+        };
+        let subject = format!("Script {}: {}", verdict, script.name);
+        let mut body = format!("Script \"{}\" ({}) was {} by {}.\n", script.name, script.id, verdict, decided_by);
+        if let Some(reason) = &script.review.reason {
+            body.push_str(&format!("\nReason: {}\n", reason));
+        }
+        let payload = serde_json::json!({
+            "event": event,
+            "script_id": script.id,
+            "name": script.name,
+            "decided_by": decided_by,
+            "reason": script.review.reason,
+        });
+
+        self.deliver(&[user], &subject, &body, payload).await;
+        Ok(())
+    }
+
+    pub async fn remind(&self, scripts: &[Script]) -> Result<()> {
+        if scripts.is_empty() {
+            return Ok(());
+        }
+
+        let approvers = self.approvers()?;
+        let now = Utc::now();
+        let subject = format!("{} script reviews are waiting", scripts.len());
+        let mut body = String::from("These scripts are still waiting for review:\n\n");
+        for script in scripts {
+            let requested_at = script.review.requested_at.unwrap_or(script.updated_at);
+            body.push_str(&format!("- {} ({}), requested {} hours ago\n",
+                                   script.name, script.id, (now - requested_at).num_hours()));
+        }
+        let payload = serde_json::json!({
+            "event": "script.review_reminder",
+            "script_ids": scripts.iter().map(|s| s.id).collect::<Vec<_>>(),
+        });
+
+        self.deliver(&approvers, &subject, &body, payload).await;
+        Ok(())
+    }
+}
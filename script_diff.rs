@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::scripts::{ScriptExecutionResult, ScriptOutputFormat};
+
+const CONTEXT_LINES: usize = 3;
+// Changed lines (after the common prefix and suffix) above which the change is shown as
+// one replaced block instead of searched for the shortest edit
+const MAX_DIFF_LINES: usize = 20_000;
+// Edit distance the search gives up at; its trace grows with the square of it
+const MAX_EDIT_DISTANCE: isize = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpKind {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// One step of the edit script, with the line positions in both inputs
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    kind: OpKind,
+    old: usize,
+    new: usize,
+}
+
+// Common prefix and suffix are matched directly, the lines between them with Myers' diff
+// while that stays within the bounds above
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<Op> = (0..prefix)
+        .map(|i| Op { kind: OpKind::Equal, old: i, new: i })
+        .collect();
+
+    let middle = if middle_a.len() + middle_b.len() > MAX_DIFF_LINES {
+        None
+    } else {
+        shortest_edit(middle_a, middle_b)
+    };
+    match middle {
+        Some(middle) => ops.extend(middle.into_iter()
+            .map(|op| Op { old: op.old + prefix, new: op.new + prefix, ..op })),
+        None => {
+            let (old_end, new_end) = (prefix + middle_a.len(), prefix + middle_b.len());
+            ops.extend((prefix..old_end).map(|old| Op { kind: OpKind::Delete, old, new: prefix }));
+            ops.extend((prefix..new_end).map(|new| Op { kind: OpKind::Insert, old: old_end, new }));
+        },
+    }
+
+    ops.extend((0..suffix).map(|i| Op {
+        kind: OpKind::Equal,
+        old: a.len() - suffix + i,
+        new: b.len() - suffix + i,
+    }));
+    ops
+}
+
+// Myers' O((N+M)D) diff; each trace row only keeps the diagonals reachable in that round.
+// None when the edit distance exceeds MAX_EDIT_DISTANCE.
+fn shortest_edit(a: &[&str], b: &[&str]) -> Option<Vec<Op>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let offset = max;
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut depth = None;
+
+    'search: for d in 0..=max.min(MAX_EDIT_DISTANCE) {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                depth = Some(d);
+                break 'search;
+            }
+        }
+    }
+    let depth = depth?;
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for d in (0..=depth).rev() {
+        let row = &trace[d as usize];
+        let at = |k: isize| row[(k + d) as usize];
+        let k = x - y;
+
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op { kind: OpKind::Equal, old: x as usize, new: y as usize });
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                ops.push(Op { kind: OpKind::Insert, old: x as usize, new: prev_y as usize });
+            } else {
+                ops.push(Op { kind: OpKind::Delete, old: prev_x as usize, new: y as usize });
+            }
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    Some(ops)
+}
+
+// Line-level unified diff with three lines of context, empty when the inputs are equal
+pub fn unified_diff(base: &str, compare: &str, base_label: &str, compare_label: &str) -> String {
+    let a: Vec<&str> = base.lines().collect();
+    let b: Vec<&str> = compare.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    if ops.iter().all(|op| op.kind == OpKind::Equal) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", base_label, compare_label);
+    let mut i = 0;
+
+    while i < ops.len() {
+        if ops[i].kind == OpKind::Equal {
+            i += 1;
+            continue;
+        }
+
+        // Extend the hunk while the unchanged runs between changes are short enough to share context
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let mut end = i;
+        let mut j = i;
+        while j < ops.len() {
+            if ops[j].kind != OpKind::Equal {
+                j += 1;
+                end = j;
+                continue;
+            }
+
+            let run_start = j;
+            while j < ops.len() && ops[j].kind == OpKind::Equal {
+                j += 1;
+            }
+            if j == ops.len() || j - run_start > 2 * CONTEXT_LINES {
+                break;
+            }
+        }
+        let stop = (end + CONTEXT_LINES).min(ops.len());
+        let hunk = &ops[start..stop];
+
+        let old_count = hunk.iter().filter(|op| op.kind != OpKind::Insert).count();
+        let new_count = hunk.iter().filter(|op| op.kind != OpKind::Delete).count();
+        let old_start = hunk[0].old + if old_count > 0 { 1 } else { 0 };
+        let new_start = hunk[0].new + if new_count > 0 { 1 } else { 0 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count));
+
+        for op in hunk {
+            match op.kind {
+                OpKind::Equal => out.push_str(&format!(" {}\n", a[op.old])),
+                OpKind::Delete => out.push_str(&format!("-{}\n", a[op.old])),
+                OpKind::Insert => out.push_str(&format!("+{}\n", b[op.new])),
+            }
+        }
+
+        i = stop;
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedRecord {
+    pub key: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+// Record-level changes between two JSON Lines outputs, matched on a key field
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StructuredDiff {
+    pub key_field: Option<String>,
+    pub added: Vec<serde_json::Value>,
+    pub removed: Vec<serde_json::Value>,
+    pub changed: Vec<ChangedRecord>,
+}
+
+impl StructuredDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Records without the key field (or without a key field configured) are keyed by their whole content
+fn record_key(record: &serde_json::Value, key_field: Option<&str>) -> String {
+    match key_field.and_then(|field| record.get(field)) {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+        None => record.to_string(),
+    }
+}
+
+pub fn structured_diff(base: &[serde_json::Value], compare: &[serde_json::Value], key_field: Option<&str>) -> StructuredDiff {
+    let before: BTreeMap<String, &serde_json::Value> = base.iter()
+        .map(|r| (record_key(r, key_field), r))
+        .collect();
+    let after: BTreeMap<String, &serde_json::Value> = compare.iter()
+        .map(|r| (record_key(r, key_field), r))
+        .collect();
+
+    let mut diff = StructuredDiff {
+        key_field: key_field.map(|f| f.to_string()),
+        ..Default::default()
+    };
+
+    for (key, record) in &after {
+        match before.get(key) {
+            None => diff.added.push((*record).clone()),
+            Some(old) if old != record => diff.changed.push(ChangedRecord {
+                key: key.clone(),
+                before: (*old).clone(),
+                after: (*record).clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for (key, record) in &before {
+        if !after.contains_key(key) {
+            diff.removed.push((*record).clone());
+        }
+    }
+
+    diff
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionDiff {
+    pub base_execution_id: Uuid,
+    pub compare_execution_id: Uuid,
+    pub changed: bool,
+    pub unified: String,
+    pub structured: Option<StructuredDiff>,
+}
+
+pub fn diff_executions(format: &ScriptOutputFormat,
+                       base: &ScriptExecutionResult,
+                       compare: &ScriptExecutionResult,
+                       key_field: Option<&str>) -> ExecutionDiff {
+    let unified = unified_diff(
+        &base.output,
+        &compare.output,
+        &format!("{} {}", base.id, base.executed_at.to_rfc3339()),
+        &format!("{} {}", compare.id, compare.executed_at.to_rfc3339()),
+    );
+
+    let structured = match format {
+        ScriptOutputFormat::JsonLines => Some(structured_diff(&base.structured_output, &compare.structured_output, key_field)),
+        _ => None,
+    };
+
+    // For JSON Lines only the records count, so reordered output is not a change
+    let changed = match &structured {
+        Some(diff) => !diff.is_empty(),
+        None => !unified.is_empty(),
+    };
+
+    ExecutionDiff {
+        base_execution_id: base.id,
+        compare_execution_id: compare.id,
+        changed,
+        unified,
+        structured,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(a: &[&str], b: &[&str], ops: &[Op]) -> Vec<String> {
+        ops.iter()
+            .filter(|op| op.kind != OpKind::Delete)
+            .map(|op| match op.kind {
+                OpKind::Equal => a[op.old].to_string(),
+                _ => b[op.new].to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unified_diff_shows_changed_lines_with_context() {
+        let diff = unified_diff("a\nb\nc\nd\n", "a\nb\nx\nd\n", "base", "compare");
+        assert!(diff.starts_with("--- base\n+++ compare\n"));
+        assert!(diff.contains("-c\n"));
+        assert!(diff.contains("+x\n"));
+        assert!(unified_diff("same\n", "same\n", "base", "compare").is_empty());
+    }
+
+    #[test]
+    fn edits_beyond_the_search_bound_become_one_replaced_block() {
+        let a: Vec<String> = (0..3 * MAX_EDIT_DISTANCE).map(|i| format!("old {}", i)).collect();
+        let b: Vec<String> = (0..3 * MAX_EDIT_DISTANCE).map(|i| format!("new {}", i)).collect();
+        let (mut a, mut b): (Vec<&str>, Vec<&str>) = (a.iter().map(|s| s.as_str()).collect(), b.iter().map(|s| s.as_str()).collect());
+        a.insert(0, "header");
+        b.insert(0, "header");
+        a.push("footer");
+        b.push("footer");
+
+        let ops = diff_lines(&a, &b);
+        assert_eq!(ops.first().map(|op| op.kind), Some(OpKind::Equal));
+        assert_eq!(ops.last().map(|op| op.kind), Some(OpKind::Equal));
+        assert_eq!(ops.iter().filter(|op| op.kind == OpKind::Delete).count(), a.len() - 2);
+        assert_eq!(applied(&a, &b, &ops), b);
+    }
+
+    #[test]
+    fn inputs_beyond_the_line_bound_are_not_searched() {
+        let a: Vec<String> = (0..MAX_DIFF_LINES).map(|i| i.to_string()).collect();
+        let a: Vec<&str> = a.iter().map(|s| s.as_str()).collect();
+        let b: Vec<&str> = a.iter().rev().copied().collect();
+
+        let ops = diff_lines(&a, &b);
+        assert_eq!(ops.iter().filter(|op| op.kind == OpKind::Insert).count(), b.len());
+        assert_eq!(applied(&a, &b, &ops), b);
+    }
+
+    #[test]
+    fn small_edits_keep_the_shortest_diff() {
+        let a = ["a", "b", "c", "d", "e"];
+        let b = ["a", "c", "d", "x", "e"];
+        let ops = diff_lines(&a, &b);
+        assert_eq!(ops.iter().filter(|op| op.kind != OpKind::Equal).count(), 2);
+        assert_eq!(applied(&a, &b, &ops), b);
+    }
+}
@@ -0,0 +1,227 @@
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+
+use crate::config::ScriptLintConfig;
+
+// Upper bound on the compiled program size, as for extraction patterns
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+// Findings reported per rule; a pattern hitting every line says nothing more after that
+const MAX_FINDINGS_PER_RULE: usize = 20;
+
+// Excerpts are cut to this many characters
+const MAX_EXCERPT_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LintSeverity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpreter {
+    Powershell,
+    Bash,
+    Any,
+}
+
+impl Interpreter {
+    // Scripts run through PowerShell unless a shebang names a POSIX shell
+    pub fn detect(content: &str) -> Self {
+        match content.lines().next() {
+            Some(line) if line.starts_with("#!") && line
+                .split(|c: char| c == '/' || c.is_whitespace())
+                .any(|word| matches!(word, "sh" | "bash" | "dash" | "zsh" | "ksh")) => Interpreter::Bash,
+            _ => Interpreter::Powershell,
+        }
+    }
+
+    fn applies_to(&self, interpreter: Interpreter) -> bool {
+        *self == Interpreter::Any || *self == interpreter
+    }
+}
+
+// One entry of the lint table. A rule matches per line, or with `when_absent` reports
+// once when the pattern appears nowhere in the script.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintRule {
+    pub id: String,
+    pub interpreter: Interpreter,
+    pub pattern: String,
+    pub severity: LintSeverity,
+    pub message: String,
+    #[serde(default)]
+    pub when_absent: bool,
+}
+
+// Built-in table: id, interpreter, pattern, severity, message, when_absent
+const BUILTIN_RULES: &[(&str, Interpreter, &str, LintSeverity, &str, bool)] = &[
+    ("bash.rm_rf_variable", Interpreter::Bash,
+     r"\brm\s+([^\n]*\s)?(-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)\s[^\n]*\$\{?\w",
+     LintSeverity::High, "Recursive rm on a path built from a variable; an empty variable deletes from the root", false),
+    ("bash.pipe_to_shell", Interpreter::Bash,
+     r"\b(curl|wget)\b[^\n|]*\|\s*(sudo\s+)?(ba|z|da)?sh\b",
+     LintSeverity::Critical, "Downloaded content piped straight into a shell", false),
+    ("bash.missing_set_e", Interpreter::Bash,
+     r"(?m)^\s*set\s+-[a-zA-Z]*e",
+     LintSeverity::Low, "No `set -e`; the script carries on after a failing command", true),
+    ("powershell.iex_download", Interpreter::Powershell,
+     r"(?i)\b(iex|invoke-expression)\b[^\n]*(downloadstring|invoke-webrequest|\biwr\b|invoke-restmethod|\birm\b|net\.webclient)|(downloadstring|invoke-webrequest|\biwr\b|invoke-restmethod|\birm\b)[^\n]*\|\s*(iex|invoke-expression)\b",
+     LintSeverity::Critical, "Invoke-Expression on downloaded content", false),
+    ("powershell.remove_recurse_variable", Interpreter::Powershell,
+     r"(?i)\b(remove-item|rm|del|rmdir)\b[^\n]*(\$[\w{][^\n]*-recurse|-recurse[^\n]*\$[\w{])",
+     LintSeverity::High, "Recursive Remove-Item on a path built from a variable", false),
+    ("powershell.plaintext_securestring", Interpreter::Powershell,
+     r#"(?i)convertto-securestring\s+["'][^"']+["'][^\n]*-asplaintext"#,
+     LintSeverity::High, "Literal password turned into a SecureString", false),
+    ("any.plaintext_credential", Interpreter::Any,
+     r#"(?i)\b(password|passwd|pwd|secret|api_?key|access_?token|token)\b\s*[:=]\s*["'][^"'$\s]{4,}["']"#,
+     LintSeverity::High, "Credential assigned as a literal; read it from a secret instead", false),
+    ("any.private_key", Interpreter::Any,
+     r"-----BEGIN( RSA| EC| DSA| OPENSSH)? PRIVATE KEY-----",
+     LintSeverity::Critical, "Private key embedded in the script", false),
+    ("any.aws_access_key", Interpreter::Any,
+     r"\bAKIA[0-9A-Z]{16}\b",
+     LintSeverity::High, "AWS access key id embedded in the script", false),
+];
+
+pub fn builtin_rules() -> Vec<LintRule> {
+    BUILTIN_RULES.iter()
+        .map(|&(id, interpreter, pattern, severity, message, when_absent)| LintRule {
+            id: id.to_string(),
+            interpreter,
+            pattern: pattern.to_string(),
+            severity,
+            message: message.to_string(),
+            when_absent,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintFinding {
+    pub rule_id: String,
+    pub severity: LintSeverity,
+    pub message: String,
+    // 1-based; None for rules reporting something missing
+    pub line: Option<usize>,
+    pub excerpt: Option<String>,
+}
+
+// Who accepted the findings of the current content, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintAcknowledgement {
+    pub by: String,
+    pub at: DateTime<Utc>,
+    pub reason: String,
+}
+
+// Lint result of a script's current content; an edit of the content lints again and
+// drops the acknowledgement
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScriptLint {
+    pub interpreter: Option<Interpreter>,
+    pub findings: Vec<LintFinding>,
+    pub linted_at: Option<DateTime<Utc>>,
+    pub acknowledgement: Option<LintAcknowledgement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptLinter {
+    rules: Vec<(LintRule, Regex)>,
+    block_severity: Option<LintSeverity>,
+}
+
+fn compile(rule: &LintRule) -> Result<Regex> {
+    RegexBuilder::new(&rule.pattern)
+        .size_limit(MAX_COMPILED_SIZE)
+        .dfa_size_limit(MAX_COMPILED_SIZE)
+        .build()
+        .context(format!("Invalid pattern of lint rule {}", rule.id))
+}
+
+impl ScriptLinter {
+    // Built-in rules, minus the disabled ones, with configured rules added or replacing
+    // a built-in rule of the same id
+    pub fn new(config: &ScriptLintConfig) -> Result<Self> {
+        let mut table = builtin_rules();
+        for rule in &config.rules {
+            if rule.id.trim().is_empty() {
+                return Err(anyhow!("Lint rules need an id"));
+            }
+            match table.iter_mut().find(|r| r.id == rule.id) {
+                Some(existing) => *existing = rule.clone(),
+                None => table.push(rule.clone()),
+            }
+        }
+        table.retain(|rule| !config.disabled_rules.contains(&rule.id));
+
+        let rules = table.into_iter()
+            .map(|rule| compile(&rule).map(|regex| (rule, regex)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            rules,
+            block_severity: config.block_severity,
+        })
+    }
+
+    pub fn lint(&self, content: &str) -> ScriptLint {
+        let interpreter = Interpreter::detect(content);
+        let mut findings = Vec::new();
+
+        for (rule, regex) in self.rules.iter().filter(|(rule, _)| rule.interpreter.applies_to(interpreter)) {
+            if rule.when_absent {
+                if !regex.is_match(content) {
+                    findings.push(LintFinding {
+                        rule_id: rule.id.clone(),
+                        severity: rule.severity,
+                        message: rule.message.clone(),
+                        line: None,
+                        excerpt: None,
+                    });
+                }
+                continue;
+            }
+
+            let hits = content.lines().enumerate()
+                .filter(|(_, line)| regex.is_match(line))
+                .take(MAX_FINDINGS_PER_RULE);
+            for (index, line) in hits {
+                findings.push(LintFinding {
+                    rule_id: rule.id.clone(),
+                    severity: rule.severity,
+                    message: rule.message.clone(),
+                    line: Some(index + 1),
+                    excerpt: Some(line.trim().chars().take(MAX_EXCERPT_CHARS).collect()),
+                });
+            }
+        }
+
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.line.cmp(&b.line)));
+        ScriptLint {
+            interpreter: Some(interpreter),
+            findings,
+            linted_at: Some(Utc::now()),
+            acknowledgement: None,
+        }
+    }
+
+    // Why approval is refused: unacknowledged findings at or above the threshold
+    pub fn approval_blocker(&self, lint: &ScriptLint) -> Option<String> {
+        let threshold = self.block_severity?;
+        if lint.acknowledgement.is_some() {
+            return None;
+        }
+        let blocking = lint.findings.iter().filter(|f| f.severity >= threshold).count();
+        (blocking > 0).then(|| format!(
+            "{} lint finding(s) at or above {:?} must be acknowledged with a reason before approval",
+            blocking, threshold))
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Mutex;
 use std::path::{Path, PathBuf};
 use std::fs::{self, File};
 use std::io::{Read, Write};
@@ -7,8 +8,18 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use anyhow::{Result, Context, anyhow};
+use base64::Engine;
 use tracing::{info, error, warn};
 
+use crate::alerts::AlertsManager;
+use crate::config::ScriptLintConfig;
+use crate::models::AlertSeverity;
+use crate::script_diff::{self, ExecutionDiff};
+use crate::builtin_scripts;
+use crate::script_lint::{LintAcknowledgement, ScriptLint, ScriptLinter};
+use crate::tags::{self, TaggedStore};
+use crate::version;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Script {
     pub id: Uuid,
@@ -22,6 +33,314 @@ pub struct Script {
     pub approved_by: Option<String>,
     pub category: ScriptCategory,
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub output_format: ScriptOutputFormat,
+    #[serde(default)]
+    pub parameters: Vec<ScriptParameter>,
+    // Built-in scripts ship with the binary and cannot be edited, approved or deleted
+    #[serde(default)]
+    pub is_builtin: bool,
+    // Built-in script this one was copied from
+    #[serde(default)]
+    pub cloned_from: Option<Uuid>,
+    #[serde(default)]
+    pub review: ScriptReview,
+    #[serde(default)]
+    pub dependencies: ScriptDependencies,
+    #[serde(default)]
+    pub lint: ScriptLint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ReviewStatus {
+    ReviewRequested,
+    Approved,
+    Rejected,
+}
+
+// Approval workflow state. Scripts stored before reviews were tracked load as
+// ReviewRequested and are corrected from is_approved on load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptReview {
+    pub status: ReviewStatus,
+    // Who created or last edited the script into review, notified of the decision
+    pub requested_by: Option<String>,
+    pub requested_at: Option<DateTime<Utc>>,
+    pub reminded_at: Option<DateTime<Utc>>,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+    // Content as last approved, the baseline of the review diff
+    pub approved_content: Option<String>,
+}
+
+impl Default for ScriptReview {
+    fn default() -> Self {
+        Self {
+            status: ReviewStatus::ReviewRequested,
+            requested_by: None,
+            requested_at: None,
+            reminded_at: None,
+            decided_by: None,
+            decided_at: None,
+            reason: None,
+            approved_content: None,
+        }
+    }
+}
+
+impl ScriptReview {
+    fn requested(by: &str, approved_content: Option<String>) -> Self {
+        Self {
+            requested_by: Some(by.to_string()),
+            requested_at: Some(Utc::now()),
+            approved_content,
+            ..Default::default()
+        }
+    }
+
+    pub fn approved(by: &str, content: &str) -> Self {
+        Self {
+            status: ReviewStatus::Approved,
+            decided_by: Some(by.to_string()),
+            decided_at: Some(Utc::now()),
+            approved_content: Some(content.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+// Entry of the pending-approval queue
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    pub script_id: Uuid,
+    pub name: String,
+    pub author: String,
+    pub requested_at: DateTime<Utc>,
+    pub age_hours: i64,
+    // Unified diff against the last approved content; a never approved script diffs
+    // against an empty file
+    pub diff: String,
+    pub lint: ScriptLint,
+    // Why the script cannot be approved yet, see ScriptLinter::approval_blocker
+    pub approval_blocked: Option<String>,
+}
+
+// Named argument passed to the script as -Name value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptParameter {
+    pub name: String,
+    pub description: String,
+    pub required: bool,
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OsFamily {
+    Windows,
+    Linux,
+    MacOs,
+}
+
+// What a script needs on the machine it runs on, checked by the pre-flight before
+// the script body runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScriptDependencies {
+    // Commands that must resolve (cmdlets, functions or executables on PATH)
+    #[serde(default)]
+    pub commands: Vec<String>,
+    // Minimum PowerShell version, e.g. "5.1"
+    #[serde(default)]
+    pub min_powershell_version: Option<String>,
+    #[serde(default)]
+    pub os_family: Option<OsFamily>,
+    // Environment variables the script reads its secrets from; only their presence
+    // on the target is checked, the values are never read back
+    #[serde(default)]
+    pub secrets: Vec<String>,
+}
+
+impl ScriptDependencies {
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+            && self.min_powershell_version.is_none()
+            && self.os_family.is_none()
+            && self.secrets.is_empty()
+    }
+
+    // Names are pasted into the probe script, so only plain names are accepted
+    pub fn validate(&self) -> Result<()> {
+        let plain = |name: &str| !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+        if let Some(name) = self.commands.iter().find(|n| !plain(n)) {
+            return Err(anyhow!("Invalid required command name: {:?}", name));
+        }
+        if let Some(name) = self.secrets.iter().find(|n| !plain(n)) {
+            return Err(anyhow!("Invalid required secret name: {:?}", name));
+        }
+        if let Some(version) = &self.min_powershell_version {
+            version::compare_versions(version, version)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MissingDependency {
+    Command { name: String },
+    PowershellVersion { required: String, found: String },
+    OsFamily { required: OsFamily, found: String },
+    Secret { name: String },
+}
+
+impl std::fmt::Display for MissingDependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MissingDependency::Command { name } => write!(f, "command {}", name),
+            MissingDependency::PowershellVersion { required, found } =>
+                write!(f, "PowerShell {} or later (found {})", required, found),
+            MissingDependency::OsFamily { required, found } => write!(f, "{:?} (found {})", required, found),
+            MissingDependency::Secret { name } => write!(f, "secret {}", name),
+        }
+    }
+}
+
+// Outcome of checking a script's dependencies against the machine it would run on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreflightResult {
+    pub checked_at: DateTime<Utc>,
+    // Remote host checked, None for the local machine
+    pub target: Option<String>,
+    pub passed: bool,
+    pub missing: Vec<MissingDependency>,
+    // The probe itself could not run or its answer was unreadable
+    pub error: Option<String>,
+}
+
+impl PreflightResult {
+    fn new(target: Option<String>, missing: Vec<MissingDependency>, error: Option<String>) -> Self {
+        Self {
+            checked_at: Utc::now(),
+            target,
+            passed: missing.is_empty() && error.is_none(),
+            missing,
+            error,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match &self.error {
+            Some(error) => format!("Pre-flight failed: {}", error),
+            None if self.passed => "Pre-flight passed".to_string(),
+            None => format!("Pre-flight failed, missing: {}",
+                            self.missing.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+// What the probe prints: the PowerShell version, the OS family and the required
+// commands and secrets it could not find
+#[derive(Debug, Deserialize)]
+struct ProbeReport {
+    version: String,
+    os: String,
+    #[serde(default)]
+    commands: Vec<String>,
+    #[serde(default)]
+    secrets: Vec<String>,
+}
+
+// PowerShell before 6 only runs on Windows and has no $IsWindows
+fn probe_script(dependencies: &ScriptDependencies) -> String {
+    let list = |names: &[String]| names.iter().map(|n| format!("'{}'", n)).collect::<Vec<_>>().join(", ");
+    format!(
+        "$ErrorActionPreference = 'SilentlyContinue'\n\
+         $os = if ($PSVersionTable.PSEdition -ne 'Core' -or $IsWindows) {{ 'Windows' }} elseif ($IsMacOS) {{ 'MacOs' }} elseif ($IsLinux) {{ 'Linux' }} else {{ 'Unknown' }}\n\
+         @{{\n\
+             version = $PSVersionTable.PSVersion.ToString()\n\
+             os = $os\n\
+             commands = @(@({}) | Where-Object {{ -not (Get-Command $_) }})\n\
+             secrets = @(@({}) | Where-Object {{ -not (Test-Path ('env:' + $_)) }})\n\
+         }} | ConvertTo-Json -Compress\n",
+        list(&dependencies.commands),
+        list(&dependencies.secrets),
+    )
+}
+
+// Compares the probe's answer with the dependencies; the answer is the last line of
+// stdout so anything a profile prints first is ignored
+fn evaluate_probe(dependencies: &ScriptDependencies, stdout: &str, target: Option<String>) -> PreflightResult {
+    let report: ProbeReport = match stdout.lines().rev().find(|l| !l.trim().is_empty()).map(serde_json::from_str) {
+        Some(Ok(report)) => report,
+        Some(Err(e)) => return PreflightResult::new(target, Vec::new(), Some(format!("Unreadable probe output: {}", e))),
+        None => return PreflightResult::new(target, Vec::new(), Some("The probe printed nothing".to_string())),
+    };
+
+    let mut missing: Vec<MissingDependency> = report.commands.into_iter()
+        .map(|name| MissingDependency::Command { name })
+        .collect();
+
+    if let Some(required) = &dependencies.min_powershell_version {
+        let too_old = version::compare_versions(&report.version, required)
+            .map_or(true, |ordering| ordering == std::cmp::Ordering::Less);
+        if too_old {
+            missing.push(MissingDependency::PowershellVersion { required: required.clone(), found: report.version.clone() });
+        }
+    }
+
+    if let Some(required) = dependencies.os_family {
+        if format!("{:?}", required) != report.os {
+            missing.push(MissingDependency::OsFamily { required, found: report.os.clone() });
+        }
+    }
+
+    missing.extend(report.secrets.into_iter().map(|name| MissingDependency::Secret { name }));
+    PreflightResult::new(target, missing, None)
+}
+
+// Checks the dependencies on this machine
+pub fn preflight_local(dependencies: &ScriptDependencies) -> PreflightResult {
+    if dependencies.is_empty() {
+        return PreflightResult::new(None, Vec::new(), None);
+    }
+
+    let output = Command::new("powershell")
+        .arg("-NoProfile")
+        .arg("-NonInteractive")
+        .arg("-EncodedCommand")
+        .arg(encode_powershell(&probe_script(dependencies)))
+        .stdin(Stdio::null())
+        .output();
+
+    match output {
+        Ok(output) => evaluate_probe(dependencies, &String::from_utf8_lossy(&output.stdout), None),
+        Err(e) => PreflightResult::new(None, Vec::new(), Some(format!("Failed to start powershell: {}", e))),
+    }
+}
+
+// Result of an execution stopped by its pre-flight; the script body never ran
+fn preflight_failure(script: &Script, executed_by: String, preflight: PreflightResult, duration_ms: u64) -> ScriptExecutionResult {
+    error!("Pre-flight of script {} ({}) failed{}: {}", script.name, script.id,
+           preflight.target.as_ref().map(|t| format!(" on {}", t)).unwrap_or_default(),
+           preflight.summary());
+
+    ScriptExecutionResult {
+        id: Uuid::new_v4(),
+        script_id: script.id,
+        executed_at: Utc::now(),
+        executed_by,
+        success: false,
+        output: String::new(),
+        error: Some(preflight.summary()),
+        duration_ms,
+        structured_output: Vec::new(),
+        parse_errors: Vec::new(),
+        target: preflight.target.clone(),
+        preflight: Some(preflight),
+        correlation_id: crate::request_trace::current(),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -34,6 +353,15 @@ pub enum ScriptCategory {
     Custom,
 }
 
+// How a script's stdout should be interpreted after execution
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum ScriptOutputFormat {
+    #[default]
+    PlainText,
+    JsonLines,
+    KeyValue,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScriptExecutionResult {
     pub id: Uuid,
@@ -44,17 +372,207 @@ pub struct ScriptExecutionResult {
     pub output: String,
     pub error: Option<String>,
     pub duration_ms: u64,
+    #[serde(default)]
+    pub structured_output: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub parse_errors: Vec<String>,
+    // Remote host the script ran on, None for local executions
+    #[serde(default)]
+    pub target: Option<String>,
+    // None when the script declares no dependencies
+    #[serde(default)]
+    pub preflight: Option<PreflightResult>,
+    // Request that started the execution, see request_trace
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+}
+
+// Periodic execution of an approved script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptSchedule {
+    pub id: Uuid,
+    pub script_id: Uuid,
+    pub interval_minutes: u64,
+    pub enabled: bool,
+    // Raise an alert whenever a run's output differs from the previous run
+    #[serde(default)]
+    pub alert_on_change: bool,
+    // Field identifying a record when diffing JSON Lines output
+    #[serde(default)]
+    pub diff_key: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_execution_id: Option<Uuid>,
+}
+
+// Outcome of one scheduled run. Every run is recorded, including unchanged ones,
+// so a quiet schedule can be told apart from one that stopped running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    pub schedule_id: Uuid,
+    pub execution_id: Uuid,
+    pub ran_at: DateTime<Utc>,
+    pub success: bool,
+    // None for the first run, which has nothing to compare against
+    pub changed: Option<bool>,
+    pub diff: Option<ExecutionDiff>,
+}
+
+// Parses captured stdout into records; malformed lines are reported, never fatal
+pub fn parse_structured_output(format: &ScriptOutputFormat, output: &str) -> (Vec<serde_json::Value>, Vec<String>) {
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+
+    if *format == ScriptOutputFormat::PlainText {
+        return (records, errors);
+    }
+
+    for (line_no, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match format {
+            ScriptOutputFormat::JsonLines => match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(value @ serde_json::Value::Object(_)) => records.push(value),
+                Ok(_) => errors.push(format!("Line {}: expected a JSON object", line_no + 1)),
+                Err(e) => errors.push(format!("Line {}: {}", line_no + 1, e)),
+            },
+            ScriptOutputFormat::KeyValue => match parse_key_value_line(line) {
+                Some(record) => records.push(serde_json::Value::Object(record)),
+                None => errors.push(format!("Line {}: no key=value pairs found", line_no + 1)),
+            },
+            ScriptOutputFormat::PlainText => {}
+        }
+    }
+
+    (records, errors)
+}
+
+// Splits `key=value key2="quoted value"` into a JSON object
+fn parse_key_value_line(line: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let mut record = serde_json::Map::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+
+        if key.is_empty() && chars.peek().is_none() {
+            break;
+        }
+
+        if chars.peek() != Some(&'=') {
+            // Bare word without a value, skip it
+            if chars.peek().is_none() {
+                break;
+            }
+            continue;
+        }
+        chars.next();
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            value.push(escaped);
+                        }
+                    },
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        if !key.is_empty() {
+            let json_value = value.parse::<i64>().map(serde_json::Value::from)
+                .or_else(|_| value.parse::<f64>().map(serde_json::Value::from))
+                .unwrap_or(serde_json::Value::String(value));
+            record.insert(key, json_value);
+        }
+    }
+
+    if record.is_empty() {
+        None
+    } else {
+        Some(record)
+    }
+}
+
+// Next to each `<id>.json`: the version before the last save, and a save in progress
+const BACKUP_EXTENSION: &str = "json.bak";
+const TEMP_SUFFIX: &str = ".tmp";
+
+// A problem with the stored scripts found by the startup consistency check
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageIssue {
+    // The script file was unreadable, e.g. after a torn write, and the previous version
+    // was restored from its backup
+    Recovered { path: PathBuf, error: String },
+    // Neither the script file nor its backup could be read; the script is not loaded
+    Lost { path: PathBuf, error: String },
+    // A backup without its script file, e.g. left by a delete that did not finish
+    OrphanedBackup { path: PathBuf },
+}
+
+// Writes `contents` so that a crash leaves either the old or the new file: the data goes
+// to a temporary file in the same directory, is synced, and replaces `path` by rename
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+
+    let mut file = File::create(&temp).context(format!("Failed to create {:?}", temp))?;
+    file.write_all(contents).context(format!("Failed to write {:?}", temp))?;
+    file.sync_all().context(format!("Failed to sync {:?}", temp))?;
+    drop(file);
+
+    fs::rename(&temp, path).context(format!("Failed to replace {:?}", path))?;
+    // The rename is only durable once the directory entry is
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
 }
 
 pub struct ScriptsManager {
     scripts_dir: PathBuf,
+    temp_dir: PathBuf,
     scripts: HashMap<Uuid, Script>,
+    builtins: HashMap<Uuid, Script>,
     execution_results: Vec<ScriptExecutionResult>,
+    schedules: HashMap<Uuid, ScriptSchedule>,
+    schedule_runs: Vec<ScheduleRun>,
+    linter: ScriptLinter,
+    storage_issues: Vec<StorageIssue>,
 }
 
 impl ScriptsManager {
-    pub fn new(scripts_dir: &str) -> Result<Self> {
-        let scripts_dir = PathBuf::from(scripts_dir);
+    pub fn new(scripts_dir: &Path, temp_dir: &Path, linter: ScriptLinter) -> Result<Self> {
+        let scripts_dir = scripts_dir.to_path_buf();
 
         // Create the scripts directory if it doesn't exist
         if !scripts_dir.exists() {
@@ -63,17 +581,36 @@ impl ScriptsManager {
             info!("Created scripts directory: {:?}", scripts_dir);
         }
 
-        let mut manager = Self {
-            scripts_dir,
-            scripts: HashMap::new(),
-            execution_results: Vec::new(),
-        };
-
+        let mut manager = Self::empty(scripts_dir, temp_dir, linter);
         manager.load_scripts()?;
+        manager.load_schedules()?;
 
         Ok(manager)
     }
 
+    // Stand-in while the scripts subsystem is disabled, see startup: nothing is read from
+    // the scripts directory, so there are no stored scripts or schedules to run
+    pub fn unavailable(scripts_dir: &Path, temp_dir: &Path) -> Result<Self> {
+        let linter = ScriptLinter::new(&ScriptLintConfig::default())?;
+        Ok(Self::empty(scripts_dir.to_path_buf(), temp_dir, linter))
+    }
+
+    fn empty(scripts_dir: PathBuf, temp_dir: &Path, linter: ScriptLinter) -> Self {
+        Self {
+            scripts_dir,
+            temp_dir: temp_dir.to_path_buf(),
+            scripts: HashMap::new(),
+            builtins: builtin_scripts::builtin_scripts(Utc::now()).into_iter()
+                .map(|script| (script.id, script))
+                .collect(),
+            execution_results: Vec::new(),
+            schedules: HashMap::new(),
+            schedule_runs: Vec::new(),
+            linter,
+            storage_issues: Vec::new(),
+        }
+    }
+
     fn load_scripts(&mut self) -> Result<()> {
         let scripts_dir = &self.scripts_dir;
 
@@ -81,13 +618,46 @@ impl ScriptsManager {
             return Ok(());
         }
 
+        let mut backups = Vec::new();
         for entry in fs::read_dir(scripts_dir)? {
             let entry = entry?;
             let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
 
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
-                match self.load_script(&path) {
-                    Ok(script) => {
+            // A save that did not finish; the script file still holds the version before it
+            if path.to_string_lossy().ends_with(TEMP_SUFFIX) {
+                info!("Removing unfinished script write {:?}", path);
+                if let Err(e) = fs::remove_file(&path) {
+                    warn!("Failed to remove {:?}: {}", path, e);
+                }
+                continue;
+            }
+            if path.to_string_lossy().ends_with(BACKUP_EXTENSION) {
+                backups.push(path);
+                continue;
+            }
+
+            if path.extension().map_or(false, |ext| ext == "json") {
+                match self.load_script_or_backup(&path) {
+                    Ok(script) if script.is_builtin || self.builtins.contains_key(&script.id) => {
+                        warn!("Ignoring stored copy of built-in script {:?}", path);
+                    },
+                    Ok(mut script) => {
+                        if script.is_approved && script.review.status == ReviewStatus::ReviewRequested {
+                            script.review = ScriptReview::approved(
+                                script.approved_by.as_deref().unwrap_or("unknown"),
+                                &script.content,
+                            );
+                        } else if script.review.requested_at.is_none() {
+                            script.review.requested_by = Some(script.created_by.clone());
+                            script.review.requested_at = Some(script.updated_at);
+                        }
+                        // Scripts stored before linting get their findings now
+                        if script.lint.linted_at.is_none() {
+                            script.lint = self.linter.lint(&script.content);
+                        }
                         info!("Loaded script: {} ({})", script.name, script.id);
                         self.scripts.insert(script.id, script);
                     },
@@ -98,9 +668,46 @@ impl ScriptsManager {
             }
         }
 
+        for backup in backups {
+            if !backup.with_extension("").exists() {
+                warn!("Script backup {:?} has no script file", backup);
+                self.storage_issues.push(StorageIssue::OrphanedBackup { path: backup });
+            }
+        }
+
         Ok(())
     }
 
+    // Loads the script file, or its backup when the file is unreadable; the backup then
+    // replaces the file
+    fn load_script_or_backup(&mut self, path: &Path) -> Result<Script> {
+        let error = match self.load_script(path) {
+            Ok(script) => return Ok(script),
+            Err(e) => e,
+        };
+
+        let backup = path.with_extension(BACKUP_EXTENSION);
+        match self.load_script(&backup) {
+            Ok(script) => {
+                warn!("Script file {:?} is unreadable ({}), loaded the previous version from {:?}", path, error, backup);
+                if let Err(e) = fs::read(&backup).map_err(anyhow::Error::from).and_then(|b| write_atomically(path, &b)) {
+                    warn!("Failed to restore {:?} from its backup: {}", path, e);
+                }
+                self.storage_issues.push(StorageIssue::Recovered { path: path.to_path_buf(), error: error.to_string() });
+                Ok(script)
+            },
+            Err(_) => {
+                self.storage_issues.push(StorageIssue::Lost { path: path.to_path_buf(), error: error.to_string() });
+                Err(error)
+            },
+        }
+    }
+
+    // Problems found with the stored scripts at startup
+    pub fn storage_issues(&self) -> &[StorageIssue] {
+        &self.storage_issues
+    }
+
     fn load_script(&self, path: &Path) -> Result<Script> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
@@ -114,9 +721,36 @@ impl ScriptsManager {
         let file_path = self.scripts_dir.join(format!("{}.json", script.id));
         let json = serde_json::to_string_pretty(script)?;
 
-        let mut file = File::create(file_path)?;
-        file.write_all(json.as_bytes())?;
+        // The current version becomes the backup, unless it is itself unreadable
+        if let Ok(current) = fs::read(&file_path) {
+            if serde_json::from_slice::<Script>(&current).is_ok() {
+                write_atomically(&file_path.with_extension(BACKUP_EXTENSION), &current)?;
+            }
+        }
 
+        write_atomically(&file_path, json.as_bytes())
+    }
+
+    fn find_script(&self, id: Uuid) -> Option<&Script> {
+        self.builtins.get(&id).or_else(|| self.scripts.get(&id))
+    }
+
+    pub fn is_builtin(&self, id: Uuid) -> bool {
+        self.builtins.contains_key(&id)
+    }
+
+    fn ensure_editable(&self, id: Uuid) -> Result<()> {
+        if self.is_builtin(id) {
+            return Err(anyhow!("Built-in script {} is read-only", id));
+        }
+        Ok(())
+    }
+
+    // The primary's copy of a script, replacing the local one, see replication
+    pub fn replicate_script(&mut self, script: Script) -> Result<()> {
+        self.ensure_editable(script.id)?;
+        self.save_script(&script)?;
+        self.scripts.insert(script.id, script);
         Ok(())
     }
 
@@ -126,9 +760,14 @@ impl ScriptsManager {
                      content: String, 
                      created_by: String,
                      category: ScriptCategory,
-                     tags: Vec<String>) -> Result<Uuid> {
+                     tags: Vec<String>,
+                     output_format: ScriptOutputFormat,
+                     parameters: Vec<ScriptParameter>,
+                     dependencies: ScriptDependencies) -> Result<Uuid> {
+        dependencies.validate()?;
         let id = Uuid::new_v4();
         let now = Utc::now();
+        let lint = self.linter.lint(&content);
 
         let script = Script {
             id,
@@ -137,11 +776,18 @@ impl ScriptsManager {
             content,
             created_at: now,
             updated_at: now,
+            review: ScriptReview::requested(&created_by, None),
             created_by,
             is_approved: false,
             approved_by: None,
             category,
-            tags,
+            tags: tags::normalize_all(tags),
+            output_format,
+            parameters,
+            is_builtin: false,
+            cloned_from: None,
+            dependencies,
+            lint,
         };
 
         self.save_script(&script)?;
@@ -150,13 +796,52 @@ impl ScriptsManager {
         Ok(id)
     }
 
+    // Editable user copy of a script. The copy needs its own approval and does not
+    // follow later changes of the original.
+    pub fn copy_script(&mut self, id: Uuid, created_by: String) -> Result<Script> {
+        let original = self.find_script(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Script not found: {}", id))?;
+
+        let now = Utc::now();
+        let copy = Script {
+            id: Uuid::new_v4(),
+            name: format!("{} (copy)", original.name),
+            created_at: now,
+            updated_at: now,
+            review: ScriptReview::requested(&created_by, None),
+            created_by,
+            is_approved: false,
+            approved_by: None,
+            tags: original.tags.into_iter().filter(|t| t != "builtin").collect(),
+            is_builtin: false,
+            cloned_from: Some(original.cloned_from.unwrap_or(id)),
+            lint: self.linter.lint(&original.content),
+            ..original
+        };
+
+        self.save_script(&copy)?;
+        self.scripts.insert(copy.id, copy.clone());
+
+        Ok(copy)
+    }
+
     pub fn update_script(&mut self, 
                       id: Uuid, 
                       name: Option<String>, 
                       description: Option<String>, 
                       content: Option<String>,
                       category: Option<ScriptCategory>,
-                      tags: Option<Vec<String>>) -> Result<()> {
+                      tags: Option<Vec<String>>,
+                      output_format: Option<ScriptOutputFormat>,
+                      parameters: Option<Vec<ScriptParameter>>,
+                      dependencies: Option<ScriptDependencies>,
+                      updated_by: &str) -> Result<Script> {
+        self.ensure_editable(id)?;
+        if let Some(dependencies) = &dependencies {
+            dependencies.validate()?;
+        }
+
         // Clone the script first so we don't hold a mutable borrow when calling save_script
         let mut script_clone = {
             let script = self.scripts.get(&id)
@@ -173,10 +858,15 @@ impl ScriptsManager {
         }
 
         if let Some(content) = content {
+            if content != script_clone.content {
+                // When the content changes, approval is reset and a new review requested
+                let approved_content = script_clone.review.approved_content.take();
+                script_clone.review = ScriptReview::requested(updated_by, approved_content);
+                script_clone.is_approved = false;
+                script_clone.approved_by = None;
+                script_clone.lint = self.linter.lint(&content);
+            }
             script_clone.content = content;
-            // When the content changes, approval is reset
-            script_clone.is_approved = false;
-            script_clone.approved_by = None;
         }
 
         if let Some(category) = category {
@@ -184,32 +874,91 @@ impl ScriptsManager {
         }
 
         if let Some(tags) = tags {
-            script_clone.tags = tags;
+            script_clone.tags = tags::normalize_all(tags);
+        }
+
+        if let Some(output_format) = output_format {
+            script_clone.output_format = output_format;
+        }
+
+        if let Some(parameters) = parameters {
+            script_clone.parameters = parameters;
+        }
+
+        if let Some(dependencies) = dependencies {
+            script_clone.dependencies = dependencies;
+        }
+
+        // Any edit of a rejected script submits it again
+        if script_clone.review.status == ReviewStatus::Rejected {
+            let approved_content = script_clone.review.approved_content.take();
+            script_clone.review = ScriptReview::requested(updated_by, approved_content);
         }
 
         script_clone.updated_at = Utc::now();
 
         // Save the cloned script and update in-memory storage
         self.save_script(&script_clone)?;
-        self.scripts.insert(id, script_clone);
+        self.scripts.insert(id, script_clone.clone());
+
+        Ok(script_clone)
+    }
 
+    // Built-in scripts keep their tags
+    fn retag(&mut self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        let mut previous = Vec::new();
+        let ids: Vec<Uuid> = self.scripts.keys().copied().collect();
+        for id in ids {
+            let mut script = self.scripts[&id].clone();
+            let Some(tags) = tags::replaced(&script.tags, from, to) else { continue };
+            let old = std::mem::replace(&mut script.tags, tags);
+            if let Err(e) = self.save_script(&script) {
+                if let Err(e) = self.restore_tags(&previous) {
+                    error!("Failed to restore script tags: {}", e);
+                }
+                return Err(e);
+            }
+            self.scripts.insert(id, script);
+            previous.push((id, old));
+        }
+        Ok(previous)
+    }
+
+    fn restore_tags(&mut self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        for (id, tags) in previous {
+            if let Some(mut script) = self.scripts.get(id).cloned() {
+                script.tags = tags.clone();
+                self.save_script(&script)?;
+                self.scripts.insert(*id, script);
+            }
+        }
         Ok(())
     }
 
     pub fn delete_script(&mut self, id: Uuid) -> Result<()> {
+        self.ensure_editable(id)?;
+
         if !self.scripts.contains_key(&id) {
             return Err(anyhow!("Script not found: {}", id));
         }
 
         let file_path = self.scripts_dir.join(format!("{}.json", id));
-        fs::remove_file(file_path)?;
+        fs::remove_file(&file_path)?;
+        if let Err(e) = fs::remove_file(file_path.with_extension(BACKUP_EXTENSION)) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove the backup of script {}: {}", id, e);
+            }
+        }
 
         self.scripts.remove(&id);
 
         Ok(())
     }
 
-    pub fn approve_script(&mut self, id: Uuid, approved_by: String) -> Result<()> {
+    // Only scripts awaiting review can be approved, see reject_script
+    pub fn approve_script(&mut self, id: Uuid, approved_by: String) -> Result<Script> {
+        self.ensure_editable(id)?;
+
         // Clone the script first so we don't hold a mutable borrow when calling save_script
         let mut script_clone = {
             let script = self.scripts.get(&id)
@@ -217,42 +966,232 @@ impl ScriptsManager {
             script.clone()
         };
 
+        if script_clone.review.status != ReviewStatus::ReviewRequested {
+            return Err(anyhow!("Script {} is not awaiting review", id));
+        }
+
+        if let Some(blocker) = self.linter.approval_blocker(&script_clone.lint) {
+            return Err(anyhow!(blocker));
+        }
+
+        script_clone.review = ScriptReview {
+            requested_by: script_clone.review.requested_by.take(),
+            requested_at: script_clone.review.requested_at,
+            ..ScriptReview::approved(&approved_by, &script_clone.content)
+        };
         script_clone.is_approved = true;
         script_clone.approved_by = Some(approved_by);
         script_clone.updated_at = Utc::now();
 
         // Save the cloned script and update in-memory storage
         self.save_script(&script_clone)?;
-        self.scripts.insert(id, script_clone);
+        self.scripts.insert(id, script_clone.clone());
 
-        Ok(())
+        Ok(script_clone)
     }
 
-    pub fn execute_script(&mut self, id: Uuid, executed_by: String) -> Result<ScriptExecutionResult> {
-        let script = self.scripts.get(&id)
-            .ok_or_else(|| anyhow!("Script not found: {}", id))?;
+    // Only scripts awaiting review can be rejected; the reason goes to the author
+    pub fn reject_script(&mut self, id: Uuid, rejected_by: &str, reason: &str) -> Result<Script> {
+        self.ensure_editable(id)?;
 
-        if !script.is_approved {
-            return Err(anyhow!("Cannot execute unapproved script"));
+        if reason.trim().is_empty() {
+            return Err(anyhow!("A reason is required to reject a script"));
         }
 
-        info!("Executing script: {} ({})", script.name, script.id);
+        let mut script_clone = {
+            let script = self.scripts.get(&id)
+                .ok_or_else(|| anyhow!("Script not found: {}", id))?;
+            script.clone()
+        };
+
+        if script_clone.review.status != ReviewStatus::ReviewRequested {
+            return Err(anyhow!("Script {} is not awaiting review", id));
+        }
+
+        script_clone.review.status = ReviewStatus::Rejected;
+        script_clone.review.decided_by = Some(rejected_by.to_string());
+        script_clone.review.decided_at = Some(Utc::now());
+        script_clone.review.reason = Some(reason.trim().to_string());
+        script_clone.is_approved = false;
+        script_clone.approved_by = None;
+
+        self.save_script(&script_clone)?;
+        self.scripts.insert(id, script_clone.clone());
+
+        Ok(script_clone)
+    }
+
+    // Whoever wrote the script or edited it into review
+    pub fn is_author(&self, id: Uuid, username: &str) -> bool {
+        self.scripts.get(&id).is_some_and(|script| {
+            script.created_by == username || script.review.requested_by.as_deref() == Some(username)
+        })
+    }
+
+    pub fn approval_blocker(&self, id: Uuid) -> Option<String> {
+        self.scripts.get(&id).and_then(|script| self.linter.approval_blocker(&script.lint))
+    }
+
+    // Accepts the lint findings of the script's current content so it can be approved;
+    // the acknowledgement lasts until the content changes
+    pub fn acknowledge_lint(&mut self, id: Uuid, acknowledged_by: &str, reason: &str) -> Result<Script> {
+        self.ensure_editable(id)?;
+
+        if reason.trim().is_empty() {
+            return Err(anyhow!("A reason is required to acknowledge lint findings"));
+        }
+
+        let mut script_clone = {
+            let script = self.scripts.get(&id)
+                .ok_or_else(|| anyhow!("Script not found: {}", id))?;
+            script.clone()
+        };
 
+        if script_clone.lint.findings.is_empty() {
+            return Err(anyhow!("Script {} has no lint findings", id));
+        }
+
+        script_clone.lint.acknowledgement = Some(LintAcknowledgement {
+            by: acknowledged_by.to_string(),
+            at: Utc::now(),
+            reason: reason.trim().to_string(),
+        });
+
+        self.save_script(&script_clone)?;
+        self.scripts.insert(id, script_clone.clone());
+
+        Ok(script_clone)
+    }
+
+    // Scripts awaiting review, oldest request first. Built-in scripts are pre-approved
+    // and never queued.
+    pub fn pending_approvals(&self, now: DateTime<Utc>) -> Vec<PendingApproval> {
+        let mut pending: Vec<PendingApproval> = self.scripts.values()
+            .filter(|s| !s.is_builtin && s.review.status == ReviewStatus::ReviewRequested)
+            .map(|s| {
+                let requested_at = s.review.requested_at.unwrap_or(s.updated_at);
+                PendingApproval {
+                    script_id: s.id,
+                    name: s.name.clone(),
+                    author: s.review.requested_by.clone().unwrap_or_else(|| s.created_by.clone()),
+                    requested_at,
+                    age_hours: (now - requested_at).num_hours(),
+                    diff: script_diff::unified_diff(
+                        s.review.approved_content.as_deref().unwrap_or(""),
+                        &s.content,
+                        "approved",
+                        "proposed",
+                    ),
+                    lint: s.lint.clone(),
+                    approval_blocked: self.linter.approval_blocker(&s.lint),
+                }
+            })
+            .collect();
+        pending.sort_by(|a, b| a.requested_at.cmp(&b.requested_at));
+        pending
+    }
+
+    // Reviews pending for longer than `after` since the request or the last reminder;
+    // they are marked reminded as they are returned
+    pub fn take_due_reminders(&mut self, now: DateTime<Utc>, after: chrono::Duration) -> Result<Vec<Script>> {
+        let due: Vec<Uuid> = self.scripts.values()
+            .filter(|s| s.review.status == ReviewStatus::ReviewRequested)
+            .filter(|s| {
+                let since = s.review.reminded_at.or(s.review.requested_at).unwrap_or(s.updated_at);
+                now - since >= after
+            })
+            .map(|s| s.id)
+            .collect();
+
+        let mut reminded = Vec::new();
+        for id in due {
+            if let Some(script) = self.scripts.get_mut(&id) {
+                script.review.reminded_at = Some(now);
+                let script = script.clone();
+                self.save_script(&script)?;
+                reminded.push(script);
+            }
+        }
+        Ok(reminded)
+    }
+
+    // Checks the arguments against the declared parameters and fills in defaults
+    fn resolve_arguments(script: &Script, arguments: &HashMap<String, String>) -> Result<Vec<(String, String)>> {
+        for name in arguments.keys() {
+            if !script.parameters.iter().any(|p| p.name == *name) {
+                return Err(anyhow!("Unknown parameter {} for script {}", name, script.name));
+            }
+        }
+
+        let mut resolved = Vec::new();
+        for parameter in &script.parameters {
+            let value = match arguments.get(&parameter.name).or(parameter.default.as_ref()) {
+                Some(value) => value,
+                None if parameter.required => {
+                    return Err(anyhow!("Missing required parameter {}", parameter.name));
+                },
+                None => continue,
+            };
+
+            // A leading dash would be read by PowerShell as another parameter name
+            if value.starts_with('-') {
+                return Err(anyhow!("Value of parameter {} must not start with '-'", parameter.name));
+            }
+
+            resolved.push((parameter.name.clone(), value.clone()));
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn execute_script_with_arguments(&mut self,
+                                         id: Uuid,
+                                         executed_by: String,
+                                         arguments: &HashMap<String, String>) -> Result<ScriptExecutionResult> {
+        let (script, arguments) = self.prepare_execution(id, arguments)?;
+        let result = Self::run_local(&self.temp_dir, &script, &arguments, executed_by)?;
+        self.execution_results.push(result.clone());
+        Ok(result)
+    }
+
+    // Runs a prepared script on this host; needs no access to the manager, so scheduled
+    // runs happen without holding its lock
+    pub fn run_local(temp_dir: &Path,
+                     script: &Script,
+                     arguments: &[(String, String)],
+                     executed_by: String) -> Result<ScriptExecutionResult> {
         let start_time = std::time::Instant::now();
+        let preflight = if script.dependencies.is_empty() {
+            None
+        } else {
+            Some(preflight_local(&script.dependencies))
+        };
+        if let Some(preflight) = preflight.clone().filter(|p| !p.passed) {
+            let result = preflight_failure(script, executed_by, preflight, start_time.elapsed().as_millis() as u64);
+            return Ok(result);
+        }
+
+        info!("Executing script: {} ({})", script.name, script.id);
+
         let execution_id = Uuid::new_v4();
 
-        // Save script to a temporary file
-        let temp_script_path = self.scripts_dir.join(format!("temp_{}.ps1", execution_id));
+        // Save script to a temporary file outside the repository
+        let temp_script_path = temp_dir.join(format!("temp_{}.ps1", execution_id));
         let mut temp_script = File::create(&temp_script_path)?;
         temp_script.write_all(script.content.as_bytes())?;
         temp_script.flush()?;
 
         // Execute the script
-        let output = Command::new("powershell")
-            .arg("-ExecutionPolicy")
+        let mut command = Command::new("powershell");
+        command.arg("-ExecutionPolicy")
             .arg("Bypass")
             .arg("-File")
-            .arg(&temp_script_path)
+            .arg(&temp_script_path);
+        for (name, value) in arguments {
+            command.arg(format!("-{}", name)).arg(value);
+        }
+
+        let output = command
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output();
@@ -281,15 +1220,26 @@ impl ScriptsManager {
                           script.name, script.id, error.clone().unwrap_or_default());
                 }
 
+                let (structured_output, parse_errors) = parse_structured_output(&script.output_format, &stdout);
+                if !parse_errors.is_empty() {
+                    warn!("Script {} ({}) produced {} unparseable output lines",
+                          script.name, script.id, parse_errors.len());
+                }
+
                 ScriptExecutionResult {
                     id: execution_id,
-                    script_id: id,
+                    script_id: script.id,
                     executed_at: Utc::now(),
                     executed_by,
                     success,
                     output: stdout,
                     error,
                     duration_ms: duration,
+                    structured_output,
+                    parse_errors,
+                    target: None,
+                    preflight,
+                    correlation_id: crate::request_trace::current(),
                 }
             },
             Err(e) => {
@@ -298,28 +1248,63 @@ impl ScriptsManager {
 
                 ScriptExecutionResult {
                     id: execution_id,
-                    script_id: id,
+                    script_id: script.id,
                     executed_at: Utc::now(),
                     executed_by,
                     success: false,
                     output: String::new(),
                     error: Some(error_message),
                     duration_ms: duration,
+                    structured_output: Vec::new(),
+                    parse_errors: Vec::new(),
+                    target: None,
+                    preflight,
+                    correlation_id: crate::request_trace::current(),
                 }
             }
         };
 
-        self.execution_results.push(result.clone());
-
         Ok(result)
     }
 
+    // Approved script and its resolved arguments, for executions that run outside the
+    // manager (remote targets) so the manager is not locked while they run
+    pub fn prepare_execution(&self, id: Uuid, arguments: &HashMap<String, String>) -> Result<(Script, Vec<(String, String)>)> {
+        let script = self.find_script(id)
+            .ok_or_else(|| anyhow!("Script not found: {}", id))?;
+
+        if !script.is_approved {
+            return Err(anyhow!("Cannot execute unapproved script"));
+        }
+
+        let arguments = Self::resolve_arguments(script, arguments)?;
+        Ok((script.clone(), arguments))
+    }
+
+    pub fn record_execution(&mut self, result: ScriptExecutionResult) {
+        self.execution_results.push(result);
+    }
+
     pub fn get_script(&self, id: Uuid) -> Option<Script> {
-        self.scripts.get(&id).cloned()
+        self.find_script(id).cloned()
     }
 
+    // Built-in scripts first, then user scripts, each by name
     pub fn get_all_scripts(&self) -> Vec<Script> {
-        self.scripts.values().cloned().collect()
+        let mut builtins: Vec<Script> = self.builtins.values().cloned().collect();
+        builtins.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut scripts: Vec<Script> = self.scripts.values().cloned().collect();
+        scripts.sort_by(|a, b| a.name.cmp(&b.name));
+
+        builtins.extend(scripts);
+        builtins
+    }
+
+    pub fn get_execution_result(&self, execution_id: Uuid) -> Option<ScriptExecutionResult> {
+        self.execution_results.iter()
+            .find(|r| r.id == execution_id)
+            .cloned()
     }
 
     pub fn get_execution_results(&self, script_id: Option<Uuid>) -> Vec<ScriptExecutionResult> {
@@ -331,12 +1316,492 @@ impl ScriptsManager {
             None => self.execution_results.clone(),
         }
     }
+
+    // Diff of two executions of the same script; JSON Lines output is also diffed per record
+    pub fn diff_executions(&self, script_id: Uuid, base: Uuid, compare: Uuid, key_field: Option<&str>) -> Result<ExecutionDiff> {
+        let script = self.find_script(script_id)
+            .ok_or_else(|| anyhow!("Script not found: {}", script_id))?;
+
+        let find = |id: Uuid| self.execution_results.iter()
+            .find(|r| r.id == id && r.script_id == script_id)
+            .ok_or_else(|| anyhow!("Execution {} of script {} not found", id, script_id));
+
+        Ok(script_diff::diff_executions(&script.output_format, find(base)?, find(compare)?, key_field))
+    }
+
+    fn schedules_dir(&self) -> PathBuf {
+        self.scripts_dir.join("schedules")
+    }
+
+    fn load_schedules(&mut self) -> Result<()> {
+        let dir = self.schedules_dir();
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                match fs::read_to_string(&path).map_err(anyhow::Error::from)
+                    .and_then(|c| serde_json::from_str::<ScriptSchedule>(&c).map_err(anyhow::Error::from)) {
+                    Ok(schedule) => {
+                        self.schedules.insert(schedule.id, schedule);
+                    },
+                    Err(e) => error!("Failed to load script schedule {:?}: {}", path, e),
+                }
+            }
+        }
+
+        info!("Loaded {} script schedules", self.schedules.len());
+        Ok(())
+    }
+
+    fn save_schedule(&self, schedule: &ScriptSchedule) -> Result<()> {
+        let dir = self.schedules_dir();
+        fs::create_dir_all(&dir)
+            .context(format!("Failed to create schedules directory: {:?}", dir))?;
+
+        let json = serde_json::to_string_pretty(schedule)?;
+        fs::write(dir.join(format!("{}.json", schedule.id)), json)?;
+        Ok(())
+    }
+
+    pub fn create_schedule(&mut self,
+                           script_id: Uuid,
+                           interval_minutes: u64,
+                           alert_on_change: bool,
+                           diff_key: Option<String>,
+                           created_by: String) -> Result<ScriptSchedule> {
+        if self.find_script(script_id).is_none() {
+            return Err(anyhow!("Script not found: {}", script_id));
+        }
+
+        if interval_minutes == 0 {
+            return Err(anyhow!("Schedule interval must be at least one minute"));
+        }
+
+        let schedule = ScriptSchedule {
+            id: Uuid::new_v4(),
+            script_id,
+            interval_minutes,
+            enabled: true,
+            alert_on_change,
+            diff_key,
+            created_by,
+            created_at: Utc::now(),
+            last_run: None,
+            last_execution_id: None,
+        };
+
+        self.save_schedule(&schedule)?;
+        self.schedules.insert(schedule.id, schedule.clone());
+
+        Ok(schedule)
+    }
+
+    pub fn delete_schedule(&mut self, id: Uuid) -> Result<()> {
+        if self.schedules.remove(&id).is_none() {
+            return Err(anyhow!("Schedule not found: {}", id));
+        }
+
+        let path = self.schedules_dir().join(format!("{}.json", id));
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_schedules(&self, script_id: Uuid) -> Vec<ScriptSchedule> {
+        self.schedules.values()
+            .filter(|s| s.script_id == script_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_schedule(&self, id: Uuid) -> Option<ScriptSchedule> {
+        self.schedules.get(&id).cloned()
+    }
+
+    pub fn get_schedule_runs(&self, schedule_id: Uuid) -> Vec<ScheduleRun> {
+        self.schedule_runs.iter()
+            .filter(|r| r.schedule_id == schedule_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn due_schedules(&self, now: DateTime<Utc>) -> Vec<Uuid> {
+        self.schedules.values()
+            .filter(|s| s.enabled)
+            .filter(|s| s.last_run.map_or(true, |last| now - last >= chrono::Duration::minutes(s.interval_minutes as i64)))
+            .map(|s| s.id)
+            .collect()
+    }
+
+    // Executes a schedule's script and compares the output with the previous run
+    // Everything a scheduled run needs, taken under the manager's lock
+    pub fn prepare_schedule(&self, id: Uuid) -> Result<PreparedRun> {
+        let schedule = self.schedules.get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Schedule not found: {}", id))?;
+        let (script, arguments) = self.prepare_execution(schedule.script_id, &HashMap::new())?;
+        let previous = schedule.last_execution_id
+            .and_then(|previous| self.get_execution_result(previous))
+            .filter(|previous| previous.script_id == schedule.script_id);
+
+        Ok(PreparedRun {
+            schedule,
+            script,
+            arguments,
+            temp_dir: self.temp_dir.clone(),
+            previous,
+        })
+    }
+
+    // Records a scheduled run; a schedule deleted while it ran keeps the execution only
+    pub fn finish_schedule(&mut self, id: Uuid, result: ScriptExecutionResult, diff: Option<ExecutionDiff>) -> Result<ScheduleRun> {
+        let run = ScheduleRun {
+            schedule_id: id,
+            execution_id: result.id,
+            ran_at: result.executed_at,
+            success: result.success,
+            changed: diff.as_ref().map(|d| d.changed),
+            diff: diff.filter(|d| d.changed),
+        };
+        self.execution_results.push(result);
+
+        if let Some(mut schedule) = self.schedules.get(&id).cloned() {
+            schedule.last_run = Some(run.ran_at);
+            schedule.last_execution_id = Some(run.execution_id);
+            self.save_schedule(&schedule)?;
+            self.schedules.insert(id, schedule);
+            self.schedule_runs.push(run.clone());
+        }
+
+        Ok(run)
+    }
+}
+
+// A due schedule taken out of the manager, see run_due_schedules
+pub struct PreparedRun {
+    schedule: ScriptSchedule,
+    script: Script,
+    arguments: Vec<(String, String)>,
+    temp_dir: PathBuf,
+    previous: Option<ScriptExecutionResult>,
 }
 
+impl PreparedRun {
+    // Runs the script and diffs its output against the previous run
+    pub fn execute(&self) -> Result<(ScriptExecutionResult, Option<ExecutionDiff>)> {
+        let executed_by = format!("schedule:{}", self.schedule.id);
+        let result = ScriptsManager::run_local(&self.temp_dir, &self.script, &self.arguments, executed_by)?;
+        let diff = self.previous.as_ref().map(|previous| {
+            script_diff::diff_executions(&self.script.output_format, previous, &result, self.schedule.diff_key.as_deref())
+        });
+        Ok((result, diff))
+    }
+}
+
+impl TaggedStore for Mutex<ScriptsManager> {
+    fn resource(&self) -> &'static str {
+        "scripts"
+    }
+
+    fn tag_counts(&self) -> Result<HashMap<String, usize>> {
+        let manager = self.lock().map_err(|_| anyhow!("Failed to acquire lock on scripts"))?;
+        Ok(tags::count(manager.builtins.values().chain(manager.scripts.values()).map(|s| &s.tags)))
+    }
+
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        self.lock().map_err(|_| anyhow!("Failed to acquire lock on scripts"))?.retag(from, to)
+    }
+
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        self.lock().map_err(|_| anyhow!("Failed to acquire lock on scripts"))?.restore_tags(previous)
+    }
+}
+
+// Raises an alert for each problem the startup check found with the stored scripts
+pub fn alert_storage_issues(manager: &ScriptsManager, alerts_manager: &AlertsManager) -> Result<()> {
+    for issue in manager.storage_issues() {
+        let (severity, title, description) = match issue {
+            StorageIssue::Recovered { path, error } => (
+                AlertSeverity::Medium,
+                "Corrupted script file recovered from backup".to_string(),
+                format!("{:?} could not be read ({}); the previous version was restored, the last change to the script is lost", path, error),
+            ),
+            StorageIssue::Lost { path, error } => (
+                AlertSeverity::High,
+                "Corrupted script file could not be recovered".to_string(),
+                format!("{:?} could not be read ({}) and has no readable backup; the script is not loaded", path, error),
+            ),
+            StorageIssue::OrphanedBackup { path } => (
+                AlertSeverity::Low,
+                "Script backup without script file".to_string(),
+                format!("{:?} has no script file; restore it by renaming it to .json, or delete it", path),
+            ),
+        };
+        alerts_manager.create_alert(severity, title, description, "scripts".to_string(), Vec::new())?;
+    }
+
+    Ok(())
+}
+
+// Runs every due schedule; with alert_on_change, a run whose output differs from the
+// previous one raises an alert carrying the diff
+pub fn run_due_schedules(manager: &Mutex<ScriptsManager>, alerts_manager: &AlertsManager) -> Result<()> {
+    let lock = || manager.lock().map_err(|_| anyhow!("Failed to acquire lock on scripts manager"));
+
+    let due = lock()?.due_schedules(Utc::now());
+    for id in due {
+        let prepared = lock()?.prepare_schedule(id);
+        let prepared = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                error!("Scheduled run {} failed: {}", id, e);
+                continue;
+            },
+        };
+
+        // The script runs and its output is diffed without the manager locked
+        let (result, diff) = match prepared.execute() {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("Scheduled run {} failed: {}", id, e);
+                continue;
+            },
+        };
+
+        let run = lock()?.finish_schedule(id, result, diff);
+        let run = match run {
+            Ok(run) => run,
+            Err(e) => {
+                error!("Failed to record scheduled run {}: {}", id, e);
+                continue;
+            },
+        };
+
+        if let (true, Some(diff)) = (prepared.schedule.alert_on_change, &run.diff) {
+            let details = match &diff.structured {
+                Some(structured) => serde_json::to_string_pretty(structured).unwrap_or_else(|_| diff.unified.clone()),
+                None => diff.unified.clone(),
+            };
+
+            // One failed alert must not keep the remaining schedules from running
+            if let Err(e) = alerts_manager.create_alert(
+                AlertSeverity::Medium,
+                format!("Output of scheduled script {} changed", prepared.script.name),
+                details,
+                "scripts".to_string(),
+                Vec::new(),
+            ) {
+                error!("Failed to raise the change alert of schedule {}: {}", id, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// How remote targets are reached: SSH into Windows OpenSSH, running Windows PowerShell.
+// The inventory probes of other hosts run under their POSIX shell instead.
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    pub address: String,
+    pub ssh_user: Option<String>,
+    pub identity_file: Option<String>,
+    pub connect_timeout_secs: u32,
+}
+
+// -EncodedCommand form (base64 of UTF-16LE), which survives the remote shell's quoting
+fn encode_powershell(command: &str) -> String {
+    let utf16: Vec<u8> = command.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+    base64::engine::general_purpose::STANDARD.encode(utf16)
+}
+
+// The script runs as a script block so its param() block receives the arguments
+fn encoded_command(script: &Script, arguments: &[(String, String)]) -> String {
+    let mut command = format!("& {{\n{}\n}}", script.content);
+    for (name, value) in arguments {
+        command.push_str(&format!(" -{} '{}'", name, value.replace('\'', "''")));
+    }
+    encode_powershell(&command)
+}
+
+// An IP address or a host name; the address comes from asset records, which observations
+// and imports fill in, so nothing else may reach ssh's command line
+fn check_host(address: &str) -> Result<()> {
+    if address.parse::<std::net::IpAddr>().is_ok() {
+        return Ok(());
+    }
+    let valid = address.len() <= 253 && address.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    if !valid {
+        return Err(anyhow!("Invalid remote host: {}", address));
+    }
+    Ok(())
+}
+
+impl RemoteTarget {
+    fn destination(&self) -> Result<String> {
+        check_host(&self.address)?;
+        match &self.ssh_user {
+            Some(user) if user.is_empty() || user.starts_with('-')
+                || user.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+                Err(anyhow!("Invalid SSH user: {}", user)),
+            Some(user) => Ok(format!("{}@{}", user, self.address)),
+            None => Ok(self.address.clone()),
+        }
+    }
+}
+
+fn ssh_to(target: &RemoteTarget) -> Result<tokio::process::Command> {
+    let destination = target.destination()?;
+
+    let mut command = tokio::process::Command::new("ssh");
+    command.arg("-o").arg("BatchMode=yes")
+        .arg("-o").arg(format!("ConnectTimeout={}", target.connect_timeout_secs));
+    if let Some(identity) = &target.identity_file {
+        command.arg("-i").arg(identity);
+    }
+    command.arg("--").arg(&destination);
+    Ok(command)
+}
+
+fn ssh_command(target: &RemoteTarget, encoded: &str) -> Result<tokio::process::Command> {
+    let mut command = ssh_to(target)?;
+    command
+        .arg(format!(
+            "powershell -NoProfile -NonInteractive -ExecutionPolicy Bypass -EncodedCommand {}",
+            encoded,
+        ))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    Ok(command)
+}
+
+// Checks the dependencies on a remote host over the same SSH path executions use
+pub async fn preflight_remote(dependencies: &ScriptDependencies, target: &RemoteTarget) -> PreflightResult {
+    let address = Some(target.address.clone());
+    if dependencies.is_empty() {
+        return PreflightResult::new(address, Vec::new(), None);
+    }
+
+    let mut command = match ssh_command(target, &encode_powershell(&probe_script(dependencies))) {
+        Ok(command) => command,
+        Err(e) => return PreflightResult::new(address, Vec::new(), Some(e.to_string())),
+    };
+    match command.output().await {
+        Ok(output) if output.status.success() =>
+            evaluate_probe(dependencies, &String::from_utf8_lossy(&output.stdout), address),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            PreflightResult::new(address, Vec::new(), Some(format!("Probe exited with {}: {}", output.status, stderr)))
+        },
+        Err(e) => PreflightResult::new(address, Vec::new(), Some(format!("Failed to start ssh: {}", e))),
+    }
+}
+
+fn probe_output(output: std::io::Result<std::process::Output>) -> Result<String> {
+    match output {
+        Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        Ok(output) => Err(anyhow!("Probe exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim())),
+        Err(e) => Err(anyhow!("Failed to start ssh: {}", e)),
+    }
+}
+
+// Runs a read-only probe shipped with the binary (see host_inventory) on a Windows host
+// over the execution path and returns its standard output
+pub async fn run_probe_powershell(content: &str, target: &RemoteTarget) -> Result<String> {
+    probe_output(ssh_command(target, &encode_powershell(content))?.output().await)
+}
+
+// The same for hosts with a POSIX shell. The probe is written to the shell's standard
+// input, so it needs no quoting for the remote command line.
+pub async fn run_probe_shell(content: &str, target: &RemoteTarget) -> Result<String> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = ssh_to(target)?
+        .arg("sh -s")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start ssh: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes()).await?;
+    }
+    probe_output(child.wait_with_output().await)
+}
+
+// Runs an approved script on a remote host. Dropping the future kills the ssh process.
+pub async fn execute_remote(script: &Script,
+                            arguments: &[(String, String)],
+                            target: &RemoteTarget,
+                            executed_by: String) -> ScriptExecutionResult {
+    let start_time = std::time::Instant::now();
+    let preflight = if script.dependencies.is_empty() {
+        None
+    } else {
+        Some(preflight_remote(&script.dependencies, target).await)
+    };
+    if let Some(preflight) = preflight.clone().filter(|p| !p.passed) {
+        return preflight_failure(script, executed_by, preflight, start_time.elapsed().as_millis() as u64);
+    }
+
+    info!("Executing script {} ({}) on {}", script.name, script.id, target.address);
+    let output = match ssh_command(target, &encoded_command(script, arguments)) {
+        Ok(mut command) => command.output().await.map_err(|e| format!("Failed to start ssh: {}", e)),
+        Err(e) => Err(e.to_string()),
+    };
+    let duration = start_time.elapsed().as_millis() as u64;
+
+    let (success, stdout, error) = match output {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            (
+                output.status.success(),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                if stderr.is_empty() { None } else { Some(stderr) },
+            )
+        },
+        Err(e) => (false, String::new(), Some(e)),
+    };
+
+    if !success {
+        error!("Script execution failed: {} ({}) on {}: {}",
+               script.name, script.id, target.address, error.clone().unwrap_or_default());
+    }
+
+    let (structured_output, parse_errors) = parse_structured_output(&script.output_format, &stdout);
+
+    ScriptExecutionResult {
+        id: Uuid::new_v4(),
+        script_id: script.id,
+        executed_at: Utc::now(),
+        executed_by,
+        success,
+        output: stdout,
+        error,
+        duration_ms: duration,
+        structured_output,
+        parse_errors,
+        target: Some(target.address.clone()),
+        preflight,
+        correlation_id: crate::request_trace::current(),
+    }
+}
 
 pub async fn start(config: &Config, _storage: impl Send + Sync + 'static) -> Result<ScriptsManager> {
-    let scripts_dir = config.scripts.repository_path.clone();
-    let repository = ScriptsManager::new(&scripts_dir)?;
+    let scripts_dir = PathBuf::from(&config.scripts.repository_path);
+    let linter = ScriptLinter::new(&crate::config::ScriptLintConfig::default())?;
+    let repository = ScriptsManager::new(&scripts_dir, &std::env::temp_dir(), linter)?;
     info!("Script management module started with {} scripts", repository.scripts.len());
     Ok(repository)
 }
@@ -350,4 +1815,78 @@ pub struct Config {
 pub struct ScriptsConfig {
     pub repository_path: String,
     // Add other config fields as needed
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScriptLintConfig;
+
+    fn manager(dir: &Path) -> ScriptsManager {
+        let linter = ScriptLinter::new(&ScriptLintConfig::default()).unwrap();
+        ScriptsManager::new(dir, &std::env::temp_dir(), linter).unwrap()
+    }
+
+    #[test]
+    fn torn_write_is_recovered_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = manager(dir.path());
+        let id = scripts.create_script("Disk usage".to_string(), String::new(), "df -h".to_string(),
+                                       "alice".to_string(), ScriptCategory::System, Vec::new(),
+                                       ScriptOutputFormat::PlainText, Vec::new(), ScriptDependencies::default()).unwrap();
+        scripts.update_script(id, None, None, Some("df -h /".to_string()), None, None, None, None, None, "alice").unwrap();
+
+        // A crash halfway through writing the next version
+        let path = dir.path().join(format!("{}.json", id));
+        let written = fs::read(&path).unwrap();
+        fs::write(&path, &written[..written.len() / 2]).unwrap();
+        fs::write(dir.path().join(format!("{}.json.tmp", id)), b"{").unwrap();
+
+        let recovered = manager(dir.path());
+        assert_eq!(recovered.get_script(id).unwrap().content, "df -h");
+        assert!(matches!(recovered.storage_issues(), [StorageIssue::Recovered { .. }]));
+        // The script file is whole again and the unfinished write is gone
+        assert!(serde_json::from_slice::<Script>(&fs::read(&path).unwrap()).is_ok());
+        assert!(!dir.path().join(format!("{}.json.tmp", id)).exists());
+
+        let reloaded = manager(dir.path());
+        assert!(reloaded.storage_issues().is_empty());
+    }
+
+    #[test]
+    fn backup_without_script_file_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let orphan = dir.path().join(format!("{}.json.bak", Uuid::new_v4()));
+        fs::write(&orphan, b"{}").unwrap();
+
+        let scripts = manager(dir.path());
+        assert_eq!(scripts.storage_issues(), &[StorageIssue::OrphanedBackup { path: orphan }]);
+    }
+
+    fn target(address: &str, ssh_user: Option<&str>) -> RemoteTarget {
+        RemoteTarget {
+            address: address.to_string(),
+            ssh_user: ssh_user.map(str::to_string),
+            identity_file: None,
+            connect_timeout_secs: 5,
+        }
+    }
+
+    #[test]
+    fn ssh_destination_follows_the_end_of_options() {
+        for (address, user, destination) in [("10.0.0.5", None, "10.0.0.5"),
+                                             ("fe80::1", None, "fe80::1"),
+                                             ("fileserver.corp.example", Some("admin"), "admin@fileserver.corp.example")] {
+            let command = ssh_to(&target(address, user)).unwrap();
+            let args: Vec<_> = command.as_std().get_args().map(|a| a.to_string_lossy().to_string()).collect();
+            assert_eq!(&args[args.len() - 2..], ["--", destination]);
+        }
+    }
+
+    #[test]
+    fn option_like_hosts_and_users_are_refused() {
+        for address in ["-oProxyCommand=touch /tmp/x", "host name", "-host", "a..b", "host;id"] {
+            assert!(ssh_to(&target(address, None)).is_err(), "{:?} accepted", address);
+        }
+        assert!(ssh_to(&target("10.0.0.5", Some("-oProxyCommand=x"))).is_err());
+    }
+}
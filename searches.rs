@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error};
+
+use crate::auth::AuthUser;
+use crate::logs::LogFilter;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: Uuid,
+    pub name: String,
+    pub owner: String,
+    pub shared: bool,
+    pub filter: LogFilter,
+    pub default_window_hours: Option<u32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SavedSearch {
+    // Private searches are visible only to their owner, shared ones also to staff
+    pub fn can_view(&self, user: &AuthUser) -> bool {
+        self.owner == user.username || (self.shared && user.is_staff())
+    }
+
+    pub fn can_edit(&self, user: &AuthUser) -> bool {
+        self.owner == user.username || (self.shared && user.is_admin())
+    }
+
+    // Builds the effective filter, preferring an explicit time range over the default window
+    pub fn effective_filter(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> LogFilter {
+        let mut filter = self.filter.clone();
+
+        if from.is_some() || to.is_some() {
+            filter.from = from.or(filter.from);
+            filter.to = to.or(filter.to);
+        } else if filter.from.is_none() && filter.to.is_none() {
+            if let Some(hours) = self.default_window_hours {
+                let now = Utc::now();
+                filter.from = Some(now - Duration::hours(hours as i64));
+                filter.to = Some(now);
+            }
+        }
+
+        filter
+    }
+}
+
+// Where a correlation rule or scheduled report takes its log filter from,
+// so a filter can be defined once as a saved search and referenced by id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FilterSource {
+    Inline(LogFilter),
+    SavedSearch(Uuid),
+}
+
+#[derive(Clone)]
+pub struct SavedSearchManager {
+    searches_dir: PathBuf,
+    searches: Arc<Mutex<HashMap<Uuid, SavedSearch>>>,
+}
+
+impl SavedSearchManager {
+    pub fn new(searches_dir: &str) -> Result<Self> {
+        let searches_dir = PathBuf::from(searches_dir);
+
+        if !searches_dir.exists() {
+            fs::create_dir_all(&searches_dir)
+                .context(format!("Failed to create saved searches directory: {:?}", searches_dir))?;
+            info!("Created saved searches directory: {:?}", searches_dir);
+        }
+
+        let mut searches = HashMap::new();
+
+        for entry in fs::read_dir(&searches_dir)? {
+            let path = entry?.path();
+
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<SavedSearch>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(search) => {
+                        searches.insert(search.id, search);
+                    },
+                    Err(e) => {
+                        error!("Failed to load saved search {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} saved searches", searches.len());
+
+        Ok(Self {
+            searches_dir,
+            searches: Arc::new(Mutex::new(searches)),
+        })
+    }
+
+    fn save_search(&self, search: &SavedSearch) -> Result<()> {
+        let file_path = self.searches_dir.join(format!("{}.json", search.id));
+        let json = serde_json::to_string_pretty(search)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    pub fn create_search(&self,
+                         name: String,
+                         owner: String,
+                         shared: bool,
+                         filter: LogFilter,
+                         default_window_hours: Option<u32>) -> Result<SavedSearch> {
+        let now = Utc::now();
+        let search = SavedSearch {
+            id: Uuid::new_v4(),
+            name,
+            owner,
+            shared,
+            filter,
+            default_window_hours,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.save_search(&search)?;
+
+        match self.searches.lock() {
+            Ok(mut searches) => {
+                searches.insert(search.id, search.clone());
+                Ok(search)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on saved searches")),
+        }
+    }
+
+    pub fn update_search(&self,
+                         id: Uuid,
+                         name: Option<String>,
+                         shared: Option<bool>,
+                         filter: Option<LogFilter>,
+                         default_window_hours: Option<Option<u32>>) -> Result<SavedSearch> {
+        let updated = match self.searches.lock() {
+            Ok(mut searches) => {
+                let search = searches.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Saved search not found: {}", id))?;
+
+                if let Some(name) = name {
+                    search.name = name;
+                }
+
+                if let Some(shared) = shared {
+                    search.shared = shared;
+                }
+
+                if let Some(filter) = filter {
+                    search.filter = filter;
+                }
+
+                if let Some(default_window_hours) = default_window_hours {
+                    search.default_window_hours = default_window_hours;
+                }
+
+                search.updated_at = Utc::now();
+                search.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on saved searches")),
+        };
+
+        self.save_search(&updated)?;
+        Ok(updated)
+    }
+
+    pub fn delete_search(&self, id: Uuid) -> Result<()> {
+        match self.searches.lock() {
+            Ok(mut searches) => {
+                if searches.remove(&id).is_none() {
+                    return Err(anyhow!("Saved search not found: {}", id));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on saved searches")),
+        }
+
+        let file_path = self.searches_dir.join(format!("{}.json", id));
+        fs::remove_file(file_path)?;
+
+        Ok(())
+    }
+
+    pub fn get_search(&self, id: Uuid) -> Result<Option<SavedSearch>> {
+        match self.searches.lock() {
+            Ok(searches) => Ok(searches.get(&id).cloned()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on saved searches")),
+        }
+    }
+
+    pub fn get_visible_searches(&self, user: &AuthUser) -> Result<Vec<SavedSearch>> {
+        match self.searches.lock() {
+            Ok(searches) => {
+                let mut visible: Vec<SavedSearch> = searches.values()
+                    .filter(|s| s.can_view(user))
+                    .cloned()
+                    .collect();
+                visible.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(visible)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on saved searches")),
+        }
+    }
+
+    // Resolves the filter a rule or report should use
+    pub fn resolve_filter(&self, source: &FilterSource) -> Result<LogFilter> {
+        match source {
+            FilterSource::Inline(filter) => Ok(filter.clone()),
+            FilterSource::SavedSearch(id) => {
+                let search = self.get_search(*id)?
+                    .ok_or_else(|| anyhow!("Saved search not found: {}", id))?;
+                Ok(search.effective_filter(None, None))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+
+    fn user(username: &str, role: UserRole) -> AuthUser {
+        AuthUser {
+            username: username.to_string(),
+            role,
+            session_id: Uuid::new_v4(),
+            sites: Vec::new(),
+        }
+    }
+
+    fn search(owner: &str, shared: bool) -> SavedSearch {
+        let now = Utc::now();
+        SavedSearch {
+            id: Uuid::new_v4(),
+            name: "failed logins".to_string(),
+            owner: owner.to_string(),
+            shared,
+            filter: LogFilter {
+                event_type: Some("login_failed".to_string()),
+                ..Default::default()
+            },
+            default_window_hours: Some(24),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn private_searches_belong_to_their_owner() {
+        let private = search("alice", false);
+
+        assert!(private.can_view(&user("alice", UserRole::User)));
+        assert!(private.can_edit(&user("alice", UserRole::User)));
+        for other in [user("bob", UserRole::User), user("tech", UserRole::Technician), user("admin", UserRole::Admin)] {
+            assert!(!private.can_view(&other), "{} can view a private search", other.username);
+            assert!(!private.can_edit(&other), "{} can edit a private search", other.username);
+        }
+    }
+
+    #[test]
+    fn shared_searches_are_visible_to_staff_and_editable_by_admins() {
+        let shared = search("alice", true);
+
+        assert!(!shared.can_view(&user("bob", UserRole::User)));
+        assert!(shared.can_view(&user("tech", UserRole::Technician)));
+        assert!(!shared.can_edit(&user("tech", UserRole::Technician)));
+        assert!(shared.can_view(&user("admin", UserRole::Admin)));
+        assert!(shared.can_edit(&user("admin", UserRole::Admin)));
+    }
+
+    #[test]
+    fn explicit_range_wins_over_the_default_window() {
+        let saved = search("alice", false);
+        let from = Utc::now() - Duration::hours(2);
+
+        let filter = saved.effective_filter(Some(from), None);
+        assert_eq!(filter.from, Some(from));
+        assert_eq!(filter.to, None);
+        assert_eq!(filter.event_type.as_deref(), Some("login_failed"));
+
+        let filter = saved.effective_filter(None, None);
+        let (start, end) = (filter.from.unwrap(), filter.to.unwrap());
+        assert_eq!(end - start, Duration::hours(24));
+
+        // A range stored with the search is kept as is
+        let mut fixed = search("alice", false);
+        fixed.filter.from = Some(from);
+        let filter = fixed.effective_filter(None, None);
+        assert_eq!(filter.from, Some(from));
+        assert_eq!(filter.to, None);
+    }
+
+    #[test]
+    fn searches_persist_and_resolve_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().to_str().unwrap();
+        let manager = SavedSearchManager::new(dir_path).unwrap();
+
+        let created = manager.create_search("errors".to_string(), "alice".to_string(), false, LogFilter {
+            source: Some("syslog".to_string()),
+            ..Default::default()
+        }, None).unwrap();
+        manager.create_search("shared".to_string(), "bob".to_string(), true, LogFilter::default(), None).unwrap();
+        manager.update_search(created.id, Some("all errors".to_string()), None, None, Some(Some(1))).unwrap();
+
+        let reloaded = SavedSearchManager::new(dir_path).unwrap();
+        let search = reloaded.get_search(created.id).unwrap().unwrap();
+        assert_eq!(search.name, "all errors");
+        assert_eq!(search.default_window_hours, Some(1));
+
+        let names = |user: &AuthUser| -> Vec<String> {
+            reloaded.get_visible_searches(user).unwrap().into_iter().map(|s| s.name).collect()
+        };
+        assert_eq!(names(&user("alice", UserRole::User)), vec!["all errors"]);
+        assert_eq!(names(&user("tech", UserRole::Technician)), vec!["shared"]);
+
+        let filter = reloaded.resolve_filter(&FilterSource::SavedSearch(created.id)).unwrap();
+        assert_eq!(filter.source.as_deref(), Some("syslog"));
+        assert!(filter.from.is_some());
+
+        reloaded.delete_search(created.id).unwrap();
+        assert!(reloaded.resolve_filter(&FilterSource::SavedSearch(created.id)).is_err());
+        assert!(reloaded.delete_search(created.id).is_err());
+        assert!(SavedSearchManager::new(dir_path).unwrap().get_search(created.id).unwrap().is_none());
+    }
+}
@@ -1,18 +1,64 @@
 use aes::{Aes256, cipher::{BlockEncrypt, BlockDecrypt}};
 use base64::{Engine as _, engine::general_purpose};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
 use uuid::Uuid;
 use chrono::Utc;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use anyhow::{anyhow, Context};
+use sha2::{Digest, Sha256};
+use axum::http::Method;
 use tracing::{info, warn, error};
 
+use crate::audit_chain::{AuditChain, ChainCheckpoint, ChainVerification};
+use crate::locks::{LockHealth, LockHealthStatus};
+
+// Key of this installation, created with random bytes on first start and kept next to
+// the data it protects; signatures and encrypted secrets are only good with the same file
+pub fn load_or_create_key(path: &Path) -> anyhow::Result<[u8; 32]> {
+    if path.exists() {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read instance key: {:?}", path))?;
+        let bytes = hex::decode(contents.trim())
+            .with_context(|| format!("Invalid instance key: {:?}", path))?;
+        return bytes.try_into()
+            .map_err(|_| anyhow!("Instance key {:?} must be 32 bytes", path));
+    }
+
+    let mut key = [0u8; 32];
+    SecureRandom::fill(&SystemRandom::new(), &mut key)
+        .map_err(|_| anyhow!("Failed to generate the instance key"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::paths::write_private(path, format!("{}\n", hex::encode(key)))
+        .with_context(|| format!("Failed to write instance key: {:?}", path))?;
+    info!("Generated instance key {:?}", path);
+    Ok(key)
+}
+
+// Compares secrets in time independent of where the inputs differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 #[derive(Clone)]
 pub struct SecurityManager {
     key: [u8; 32],
-    audit_log: Arc<Mutex<Vec<AuditEvent>>>,
+    audit_log: Arc<Mutex<AuditTrail>>,
+    health: LockHealth,
+}
+
+// Events in memory and their hash-chained copy on disk, updated under one lock
+struct AuditTrail {
+    events: Vec<AuditEvent>,
+    chain: AuditChain,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AuditEvent {
     pub id: Uuid,
     pub timestamp: chrono::DateTime<Utc>,
@@ -21,9 +67,19 @@ pub struct AuditEvent {
     pub resource: String,
     pub status: AuditStatus,
     pub details: Option<String>,
+    // Event this one is part of, e.g. one target of a bulk script execution
+    pub parent_id: Option<Uuid>,
+    // Request the event was recorded for, see request_trace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    // Tamper evidence, see audit_chain
+    #[serde(default)]
+    pub prev_hash: String,
+    #[serde(default)]
+    pub hash: String,
 }
 
-#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum AuditStatus {
     Success,
     Failure,
@@ -31,34 +87,91 @@ pub enum AuditStatus {
 }
 
 impl SecurityManager {
-    pub fn new(key: [u8; 32]) -> Self {
-        Self { 
+    pub fn new(key: [u8; 32], audit_dir: &str) -> anyhow::Result<Self> {
+        let (chain, events) = AuditChain::open(audit_dir)?;
+
+        Ok(Self { 
             key,
-            audit_log: Arc::new(Mutex::new(Vec::new()))
-        }
+            audit_log: Arc::new(Mutex::new(AuditTrail { events, chain })),
+            health: LockHealth::new("security"),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AuditTrail> {
+        self.health.lock(&self.audit_log)
+    }
+
+    pub fn lock_health(&self) -> LockHealthStatus {
+        self.health.status()
+    }
+
+    fn cipher(&self) -> anyhow::Result<LessSafeKey> {
+        UnboundKey::new(&AES_256_GCM, &self.key)
+            .map(LessSafeKey::new)
+            .map_err(|_| anyhow!("Invalid instance key"))
+    }
+
+    // AES-256-GCM under the instance key with a random nonce; base64 of the nonce
+    // followed by the ciphertext and tag
+    pub fn encrypt_data(&self, data: &str) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SecureRandom::fill(&SystemRandom::new(), &mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+
+        let mut sealed = data.as_bytes().to_vec();
+        self.cipher()?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow!("Failed to encrypt data"))?;
+        Ok(general_purpose::STANDARD.encode([&nonce[..], &sealed].concat()))
     }
 
-    pub fn encrypt_data(&self, data: &str) -> String {
-        // This is a simplified implementation for demonstration
-        // In production, use a proper encryption method with IV, etc.
-        general_purpose::STANDARD.encode(data.as_bytes())
+    // Fails for data encrypted under another key or changed since
+    pub fn decrypt_data(&self, encrypted_data: &str) -> anyhow::Result<String> {
+        let bytes = general_purpose::STANDARD.decode(encrypted_data)
+            .map_err(|_| anyhow!("Invalid base64 data"))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(anyhow!("Encrypted data is too short"));
+        }
+        let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("Invalid nonce"))?;
+
+        let mut sealed = sealed.to_vec();
+        let plain = self.cipher()?
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow!("Failed to decrypt data, wrong key or tampered"))?;
+        String::from_utf8(plain.to_vec()).map_err(|_| anyhow!("Invalid UTF-8 data"))
     }
 
-    pub fn decrypt_data(&self, encrypted_data: &str) -> Result<String, String> {
-        // This is a simplified implementation for demonstration
-        match general_purpose::STANDARD.decode(encrypted_data) {
-            Ok(bytes) => match String::from_utf8(bytes) {
-                Ok(s) => Ok(s),
-                Err(_) => Err("Invalid UTF-8 data".to_string()),
-            },
-            Err(_) => Err("Invalid base64 data".to_string()),
+    // HMAC-SHA256 of the data under the instance key, hex encoded
+    pub fn sign(&self, data: &[u8]) -> String {
+        const BLOCK: usize = 64;
+        let mut ipad = [0x36u8; BLOCK];
+        let mut opad = [0x5cu8; BLOCK];
+        for (i, byte) in self.key.iter().enumerate() {
+            ipad[i] ^= byte;
+            opad[i] ^= byte;
         }
+
+        let inner = Sha256::new().chain_update(ipad).chain_update(data).finalize();
+        let outer = Sha256::new().chain_update(opad).chain_update(inner).finalize();
+        hex::encode(outer)
     }
 
     pub fn log_audit_event(&self, user: &str, action: &str, resource: &str, status: AuditStatus, details: Option<String>) {
+        self.log_nested_audit_event(None, user, action, resource, status, details);
+    }
+
+    // Records an event under a parent event and returns its id, so children can refer to it
+    pub fn log_nested_audit_event(&self,
+                                  parent_id: Option<Uuid>,
+                                  user: &str,
+                                  action: &str,
+                                  resource: &str,
+                                  status: AuditStatus,
+                                  details: Option<String>) -> Uuid {
         let details_clone = details.clone(); // Clone it first to avoid the move
         
-        let event = AuditEvent {
+        let mut event = AuditEvent {
             id: Uuid::new_v4(),
             timestamp: Utc::now(),
             user: user.to_string(),
@@ -66,42 +179,51 @@ impl SecurityManager {
             resource: resource.to_string(),
             status,
             details,
+            parent_id,
+            correlation_id: crate::request_trace::current(),
+            prev_hash: String::new(),
+            hash: String::new(),
         };
+        let id = event.id;
 
-        match self.audit_log.lock() {
-            Ok(mut log) => {
-                log.push(event.clone());
-
-                // Log to tracing based on status
-                match event.status {
-                    AuditStatus::Success => info!(
-                        "AUDIT: [{}] User '{}' performed '{}' on '{}': Success",
-                        event.id, user, action, resource
-                    ),
-                    AuditStatus::Warning => warn!(
-                        "AUDIT: [{}] User '{}' performed '{}' on '{}': Warning: {}",
-                        event.id, user, action, resource, details_clone.unwrap_or_default()
-                    ),
-                    AuditStatus::Failure => error!(
-                        "AUDIT: [{}] User '{}' attempted '{}' on '{}': Failed: {}",
-                        event.id, user, action, resource, details_clone.unwrap_or_default()
-                    ),
-                }
-            },
-            Err(e) => {
-                error!("Failed to log audit event: {:?}", e);
-            }
+        let mut trail = self.lock();
+        // Chained while holding the lock, so concurrent events cannot fork the chain
+        if let Err(e) = trail.chain.append(&mut event) {
+            error!("Failed to persist audit event {}: {}", event.id, e);
+        }
+        trail.events.push(event.clone());
+
+        // Log to tracing based on status
+        match event.status {
+            AuditStatus::Success => info!(
+                "AUDIT: [{}] User '{}' performed '{}' on '{}': Success",
+                event.id, user, action, resource
+            ),
+            AuditStatus::Warning => warn!(
+                "AUDIT: [{}] User '{}' performed '{}' on '{}': Warning: {}",
+                event.id, user, action, resource, details_clone.unwrap_or_default()
+            ),
+            AuditStatus::Failure => error!(
+                "AUDIT: [{}] User '{}' attempted '{}' on '{}': Failed: {}",
+                event.id, user, action, resource, details_clone.unwrap_or_default()
+            ),
         }
+
+        id
     }
 
     pub fn get_audit_logs(&self) -> Vec<AuditEvent> {
-        match self.audit_log.lock() {
-            Ok(log) => log.clone(),
-            Err(_) => {
-                error!("Failed to access audit logs");
-                Vec::new()
-            }
-        }
+        self.lock().events.clone()
+    }
+
+    // Writes the current chain head to the checkpoint file
+    pub fn checkpoint_audit_chain(&self) -> anyhow::Result<ChainCheckpoint> {
+        self.lock().chain.checkpoint()
+    }
+
+    pub fn verify_audit_chain(&self) -> anyhow::Result<ChainVerification> {
+        let snapshot = self.lock().chain.snapshot()?;
+        snapshot.verify()
     }
 
     pub fn verify_access(&self, user: &str, resource: &str, action: &str) -> bool {
@@ -122,63 +244,432 @@ impl SecurityManager {
 }
 
 // Access control implementation
+const BUILTIN_ROLES: [&str; 3] = ["admin", "technician", "user"];
+
+// Staging firewall changes as change requests, and applying them, see firewall_approvals
+pub const PROPOSE_PERMISSION: &str = "network:propose";
+pub const APPLY_PERMISSION: &str = "network:apply";
+const FIREWALL_PERMISSIONS: [&str; 2] = [PROPOSE_PERMISSION, APPLY_PERMISSION];
+
+// Migrations of the stored permission matrix run once each; the file records how far it got
+const MATRIX_VERSION: u32 = 2;
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StoredMatrix {
+    Versioned {
+        version: u32,
+        roles: HashMap<String, Vec<String>>,
+    },
+    // Written before the file carried a version
+    Unversioned(HashMap<String, Vec<String>>),
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RolePermissions {
+    pub role: String,
+    pub builtin: bool,
+    pub permissions: Vec<String>,
+}
+
+// Permission matrix shared by the RBAC middleware and the role management API.
+// Cloning shares the same live matrix, so edits apply on the next request.
+#[derive(Clone)]
 pub struct AccessControl {
-    permissions: HashMap<String, Vec<String>>,
+    path: PathBuf,
+    permissions: Arc<RwLock<HashMap<String, Vec<String>>>>,
+}
+
+fn default_permissions() -> HashMap<String, Vec<String>> {
+    let mut permissions = HashMap::new();
+
+    permissions.insert("admin".to_string(), vec![
+        "script:read".to_string(),
+        "script:write".to_string(),
+        "script:execute".to_string(),
+        "script:approve".to_string(),
+        "ticket:read".to_string(),
+        "ticket:write".to_string(),
+        "printer:read".to_string(),
+        "printer:manage".to_string(),
+        "user:read".to_string(),
+        "user:write".to_string(),
+        "network:read".to_string(),
+        "network:write".to_string(),
+        "network:propose".to_string(),
+        "network:apply".to_string(),
+        "alert:manage".to_string(),
+    ]);
+
+    permissions.insert("technician".to_string(), vec![
+        "script:read".to_string(),
+        "script:execute".to_string(),
+        "ticket:read".to_string(),
+        "ticket:write".to_string(),
+        "printer:read".to_string(),
+        "network:read".to_string(),
+        "network:propose".to_string(),
+    ]);
+
+    permissions.insert("user".to_string(), vec![
+        "ticket:read_own".to_string(),
+        "ticket:create".to_string(),
+    ]);
+
+    permissions
+}
+
+fn is_valid_permission(permission: &str) -> bool {
+    match permission.split_once(':') {
+        Some((resource, action)) => {
+            !resource.is_empty() && !action.is_empty()
+                && permission.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == ':')
+        },
+        None => false,
+    }
 }
 
 impl AccessControl {
-    pub fn new() -> Self {
-        let mut ac = Self {
-            permissions: HashMap::new(),
+    // Loads the matrix from disk, seeding it with the default roles on first start
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(path);
+
+        let permissions = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read permission matrix: {:?}", path))?;
+            let stored: StoredMatrix = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse permission matrix: {:?}", path))?;
+            let (version, mut permissions) = match stored {
+                StoredMatrix::Versioned { version, roles } => (version, roles),
+                StoredMatrix::Unversioned(roles) => (0, roles),
+            };
+
+            // Built-in roles must always exist even if removed from the file by hand
+            for (role, defaults) in default_permissions() {
+                permissions.entry(role).or_insert(defaults);
+            }
+
+            // Firewall changes used to need only network:write. A matrix from before they
+            // were split has neither firewall permission anywhere, and its roles keep what
+            // network:write gave them.
+            let split = permissions.values().flatten().any(|p| FIREWALL_PERMISSIONS.contains(&p.as_str()));
+            if version < 1 && !split {
+                for perms in permissions.values_mut().filter(|perms| perms.iter().any(|p| p == "network:write")) {
+                    perms.extend(FIREWALL_PERMISSIONS.iter().map(|p| p.to_string()));
+                }
+            }
+
+            // Escalation policies used to be open to any signed-in user. A matrix from
+            // before has no alert:manage anywhere, and the roles managing users get it.
+            if version < 2 && !permissions.values().flatten().any(|p| p == "alert:manage") {
+                for perms in permissions.values_mut().filter(|perms| perms.iter().any(|p| p == "user:write")) {
+                    perms.push("alert:manage".to_string());
+                }
+            }
+
+            permissions
+        } else {
+            default_permissions()
         };
-        
-        // Set up default permissions
-        ac.permissions.insert("admin".to_string(), vec![
-            "script:read".to_string(),
-            "script:write".to_string(),
-            "script:execute".to_string(),
-            "ticket:read".to_string(),
-            "ticket:write".to_string(),
-            "printer:read".to_string(),
-            "printer:manage".to_string(),
-            "user:read".to_string(),
-            "user:write".to_string(),
-        ]);
-        
-        ac.permissions.insert("technician".to_string(), vec![
-            "script:read".to_string(),
-            "script:execute".to_string(),
-            "ticket:read".to_string(),
-            "ticket:write".to_string(),
-            "printer:read".to_string(),
-        ]);
-        
-        ac.permissions.insert("user".to_string(), vec![
-            "ticket:read_own".to_string(),
-            "ticket:create".to_string(),
-        ]);
-        
-        ac
+
+        let ac = Self {
+            path,
+            permissions: Arc::new(RwLock::new(permissions)),
+        };
+        ac.save()?;
+
+        info!("Loaded access control matrix from {:?}", ac.path);
+        Ok(ac)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let permissions = self.permissions.read()
+            .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let json = serde_json::to_string_pretty(&serde_json::json!({
+            "version": MATRIX_VERSION,
+            "roles": &*permissions,
+        }))?;
+        fs::write(&self.path, json)?;
+        Ok(())
     }
-    
+
     pub fn check_permission(&self, role: &str, permission: &str) -> bool {
-        if let Some(perms) = self.permissions.get(role) {
-            perms.contains(&permission.to_string())
-        } else {
-            false
+        match self.permissions.read() {
+            Ok(permissions) => permissions
+                .get(role)
+                .map_or(false, |perms| perms.iter().any(|p| p == permission)),
+            Err(_) => {
+                error!("Failed to access permission matrix");
+                false
+            }
+        }
+    }
+
+    pub fn list_roles(&self) -> anyhow::Result<Vec<RolePermissions>> {
+        let permissions = self.permissions.read()
+            .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+        let mut roles: Vec<RolePermissions> = permissions.iter()
+            .map(|(role, perms)| RolePermissions {
+                role: role.clone(),
+                builtin: BUILTIN_ROLES.contains(&role.as_str()),
+                permissions: perms.clone(),
+            })
+            .collect();
+        roles.sort_by(|a, b| a.role.cmp(&b.role));
+
+        Ok(roles)
+    }
+
+    pub fn create_role(&self, role: &str, permissions: Vec<String>) -> anyhow::Result<()> {
+        let role = role.trim().to_lowercase();
+
+        if role.is_empty() || !role.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow!("Invalid role name: {}", role));
+        }
+
+        if let Some(invalid) = permissions.iter().find(|p| !is_valid_permission(p)) {
+            return Err(anyhow!("Invalid permission: {}", invalid));
+        }
+
+        {
+            let mut matrix = self.permissions.write()
+                .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+            if matrix.contains_key(&role) {
+                return Err(anyhow!("Role already exists: {}", role));
+            }
+
+            matrix.insert(role, permissions);
+        }
+
+        self.save()
+    }
+
+    pub fn delete_role(&self, role: &str) -> anyhow::Result<()> {
+        if BUILTIN_ROLES.contains(&role) {
+            return Err(anyhow!("Built-in role cannot be deleted: {}", role));
+        }
+
+        {
+            let mut matrix = self.permissions.write()
+                .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+            if matrix.remove(role).is_none() {
+                return Err(anyhow!("Role not found: {}", role));
+            }
         }
+
+        self.save()
     }
-    
-    pub fn add_permission(&mut self, role: &str, permission: &str) {
-        self.permissions
-            .entry(role.to_string())
-            .or_insert_with(Vec::new)
-            .push(permission.to_string());
+
+    pub fn add_permission(&self, role: &str, permission: &str) -> anyhow::Result<()> {
+        if !is_valid_permission(permission) {
+            return Err(anyhow!("Invalid permission: {}", permission));
+        }
+
+        {
+            let mut matrix = self.permissions.write()
+                .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+            let perms = matrix.get_mut(role)
+                .ok_or_else(|| anyhow!("Role not found: {}", role))?;
+
+            if !perms.iter().any(|p| p == permission) {
+                perms.push(permission.to_string());
+            }
+        }
+
+        self.save()
     }
-    
-    pub fn remove_permission(&mut self, role: &str, permission: &str) {
-        if let Some(perms) = self.permissions.get_mut(role) {
+
+    pub fn remove_permission(&self, role: &str, permission: &str) -> anyhow::Result<()> {
+        {
+            let mut matrix = self.permissions.write()
+                .map_err(|_| anyhow!("Failed to acquire lock on permission matrix"))?;
+
+            let perms = matrix.get_mut(role)
+                .ok_or_else(|| anyhow!("Role not found: {}", role))?;
+
             perms.retain(|p| p != permission);
         }
+
+        self.save()
+    }
+}
+
+// Permissions guarding each API area; a caller needs any one of the listed permissions.
+// Routes not listed here only require authentication (or none at all).
+pub fn required_permissions(method: &Method, path: &str) -> Option<&'static [&'static str]> {
+    let read = *method == Method::GET || *method == Method::HEAD;
+
+    if path.starts_with("/api/scripts") {
+        if path.ends_with("/execute") || path.ends_with("/execute-bulk") || path.ends_with("/cancel") {
+            Some(&["script:execute"])
+        } else if path.ends_with("/approve") || path.ends_with("/reject") || path.ends_with("/pending-approvals")
+            || path.ends_with("/lint/acknowledge") {
+            Some(&["script:approve"])
+        } else if read {
+            Some(&["script:read"])
+        } else {
+            Some(&["script:write"])
+        }
+    } else if path.starts_with("/api/alerts/escalation") {
+        // Policies hold webhook targets the server will call
+        Some(&["alert:manage"])
+    } else if path.starts_with("/api/references") {
+        // Searches every ticket, so access to one's own is not enough
+        Some(&["ticket:read"])
+    } else if path.starts_with("/api/tickets") {
+        if read {
+            Some(&["ticket:read", "ticket:read_own"])
+        } else if *method == Method::POST && path == "/api/tickets" {
+            Some(&["ticket:create", "ticket:write"])
+        } else {
+            Some(&["ticket:write"])
+        }
+    } else if path.starts_with("/api/network/firewall") || path.starts_with("/api/network/zones") {
+        let staged = path.strip_prefix("/api/network/firewall/staged");
+        if read {
+            Some(&["network:read"])
+        } else if staged == Some("") || path.ends_with("/position") || path.ends_with("/import")
+            || path.starts_with("/api/network/firewall/backups/restore/") {
+            // Staged as change requests, nothing reaches the ruleset
+            Some(&[PROPOSE_PERMISSION])
+        } else if path.ends_with("/comments") || (*method == Method::DELETE && staged.is_some()) {
+            // Proposers withdraw their own change requests, the handler checks whose it is
+            Some(&[PROPOSE_PERMISSION, APPLY_PERMISSION])
+        } else {
+            Some(&[APPLY_PERMISSION])
+        }
+    } else if path.starts_with("/api/network") || path.starts_with("/api/assets") || path.starts_with("/api/scans")
+        || path.starts_with("/api/visualizations") {
+        if read {
+            Some(&["network:read"])
+        } else {
+            Some(&["network:write"])
+        }
+    } else if path.starts_with("/api/printers") || path.starts_with("/api/locations") {
+        if read {
+            Some(&["printer:read"])
+        } else {
+            Some(&["printer:manage"])
+        }
+    } else if path.starts_with("/api/users") {
+        if path.ends_with("/password") || path.ends_with("/notifications") {
+            // Users may change their own password and preferences, the handler checks who is asking
+            None
+        } else if read {
+            Some(&["user:read"])
+        } else {
+            Some(&["user:write"])
+        }
+    } else if path.starts_with("/api/roles") {
+        if read {
+            Some(&["user:read"])
+        } else {
+            Some(&["user:write"])
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn panic_inside_a_critical_section_leaves_the_audit_log_usable() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SecurityManager::new([7u8; 32], dir.path().to_str().unwrap()).unwrap();
+        manager.log_audit_event("alice", "login", "session", AuditStatus::Success, None);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _trail = manager.lock();
+            panic!("injected");
+        }));
+        assert!(result.is_err());
+        assert!(manager.audit_log.is_poisoned());
+
+        manager.log_audit_event("bob", "update_ticket", "ticket", AuditStatus::Success, None);
+        assert_eq!(manager.get_audit_logs().len(), 2);
+        assert!(manager.verify_audit_chain().unwrap().valid);
+
+        let health = manager.lock_health();
+        assert!(!health.healthy);
+        assert_eq!(health.recoveries, 1);
+    }
+
+    #[test]
+    fn encrypted_data_only_opens_with_the_same_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SecurityManager::new([7u8; 32], dir.path().to_str().unwrap()).unwrap();
+        let other = SecurityManager::new([8u8; 32], dir.path().to_str().unwrap()).unwrap();
+
+        let sealed = manager.encrypt_data("hunter2").unwrap();
+        assert_ne!(sealed, manager.encrypt_data("hunter2").unwrap());
+        assert!(!String::from_utf8_lossy(&general_purpose::STANDARD.decode(&sealed).unwrap()).contains("hunter2"));
+        assert_eq!(manager.decrypt_data(&sealed).unwrap(), "hunter2");
+        assert!(other.decrypt_data(&sealed).is_err());
+
+        let mut tampered = general_purpose::STANDARD.decode(&sealed).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(manager.decrypt_data(&general_purpose::STANDARD.encode(tampered)).is_err());
+    }
+
+    #[test]
+    fn instance_key_is_random_private_and_kept() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("instance.key");
+        let key = load_or_create_key(&path).unwrap();
+        assert_ne!(key, [0u8; 32]);
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(load_or_create_key(&path).unwrap(), key);
+        assert_ne!(load_or_create_key(&dir.path().join("other.key")).unwrap(), key);
+
+        fs::write(&path, "abcd").unwrap();
+        assert!(load_or_create_key(&path).is_err());
+    }
+
+    #[test]
+    fn matrix_migrations_run_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roles.json");
+        let path = path.to_str().unwrap();
+        // As written before the firewall permissions were split and alert:manage existed
+        fs::write(path, r#"{
+            "admin": ["network:read", "network:write", "user:write"],
+            "technician": ["network:read"],
+            "user": ["ticket:create"],
+            "ops": ["network:write", "user:write"],
+            "viewer": ["network:read"]
+        }"#).unwrap();
+
+        let ac = AccessControl::new(path).unwrap();
+        for permission in [PROPOSE_PERMISSION, APPLY_PERMISSION, "alert:manage"] {
+            assert!(ac.check_permission("ops", permission));
+            assert!(!ac.check_permission("viewer", permission));
+        }
+
+        // Taken away again by an admin, everywhere; a restart leaves it that way
+        for role in ["admin", "technician", "ops"] {
+            for permission in [PROPOSE_PERMISSION, APPLY_PERMISSION, "alert:manage"] {
+                ac.remove_permission(role, permission).unwrap();
+            }
+        }
+        let ac = AccessControl::new(path).unwrap();
+        for permission in [PROPOSE_PERMISSION, APPLY_PERMISSION, "alert:manage"] {
+            assert!(!ac.check_permission("ops", permission));
+            assert!(!ac.check_permission("admin", permission));
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::info;
+
+// Named network service used in firewall rules instead of raw protocol/port pairs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceDefinition {
+    pub name: String,
+    pub protocol: ServiceProtocol,
+    pub ports: Vec<u16>,
+    pub description: String,
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ServiceProtocol {
+    Tcp,
+    Udp,
+    TcpUdp,
+}
+
+impl ServiceProtocol {
+    pub fn nft_names(&self) -> Vec<&'static str> {
+        match self {
+            ServiceProtocol::Tcp => vec!["tcp"],
+            ServiceProtocol::Udp => vec!["udp"],
+            ServiceProtocol::TcpUdp => vec!["tcp", "udp"],
+        }
+    }
+}
+
+fn builtin(name: &str, protocol: ServiceProtocol, ports: &[u16], description: &str) -> ServiceDefinition {
+    ServiceDefinition {
+        name: name.to_string(),
+        protocol,
+        ports: ports.to_vec(),
+        description: description.to_string(),
+        builtin: true,
+    }
+}
+
+fn default_services() -> Vec<ServiceDefinition> {
+    vec![
+        builtin("ssh", ServiceProtocol::Tcp, &[22], "Secure Shell"),
+        builtin("http", ServiceProtocol::Tcp, &[80], "Web traffic"),
+        builtin("https", ServiceProtocol::Tcp, &[443], "Encrypted web traffic"),
+        builtin("web", ServiceProtocol::Tcp, &[80, 443], "Web traffic, plain and encrypted"),
+        builtin("dns", ServiceProtocol::TcpUdp, &[53], "Domain name resolution"),
+        builtin("dhcp", ServiceProtocol::Udp, &[67, 68], "Dynamic host configuration"),
+        builtin("ntp", ServiceProtocol::Udp, &[123], "Network time"),
+        builtin("smtp", ServiceProtocol::Tcp, &[25], "Mail transfer"),
+        builtin("submission", ServiceProtocol::Tcp, &[587], "Mail submission"),
+        builtin("imaps", ServiceProtocol::Tcp, &[993], "IMAP over TLS"),
+        builtin("ldap", ServiceProtocol::TcpUdp, &[389], "Directory access"),
+        builtin("ldaps", ServiceProtocol::Tcp, &[636], "Directory access over TLS"),
+        builtin("kerberos", ServiceProtocol::TcpUdp, &[88], "Kerberos authentication"),
+        builtin("smb", ServiceProtocol::Tcp, &[445], "Windows file sharing"),
+        builtin("rdp", ServiceProtocol::Tcp, &[3389], "Remote desktop"),
+        builtin("winrm", ServiceProtocol::Tcp, &[5985, 5986], "Windows remote management"),
+        builtin("snmp", ServiceProtocol::Udp, &[161], "SNMP polling"),
+        builtin("syslog", ServiceProtocol::Udp, &[514], "Syslog"),
+        builtin("ipp", ServiceProtocol::Tcp, &[631], "Internet printing protocol"),
+        builtin("jetdirect", ServiceProtocol::Tcp, &[9100], "Raw printing"),
+        builtin("postgres", ServiceProtocol::Tcp, &[5432], "PostgreSQL"),
+    ]
+}
+
+fn is_valid_service_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    path: PathBuf,
+    services: Arc<Mutex<HashMap<String, ServiceDefinition>>>,
+}
+
+impl ServiceRegistry {
+    // Loads user edits from disk on top of the built-in service list
+    pub fn new(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let mut services: HashMap<String, ServiceDefinition> = default_services()
+            .into_iter()
+            .map(|s| (s.name.clone(), s))
+            .collect();
+
+        if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read service definitions: {:?}", path))?;
+            let stored: Vec<ServiceDefinition> = serde_json::from_str(&contents)
+                .context(format!("Failed to parse service definitions: {:?}", path))?;
+
+            for service in stored {
+                services.insert(service.name.clone(), service);
+            }
+        }
+
+        info!("Loaded {} service definitions", services.len());
+
+        Ok(Self {
+            path,
+            services: Arc::new(Mutex::new(services)),
+        })
+    }
+
+    fn save(&self, services: &HashMap<String, ServiceDefinition>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut list: Vec<&ServiceDefinition> = services.values().collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let json = serde_json::to_string_pretty(&list)?;
+        fs::write(&self.path, json)?;
+        Ok(())
+    }
+
+    pub fn get_service(&self, name: &str) -> Result<ServiceDefinition> {
+        match self.services.lock() {
+            Ok(services) => {
+                services.get(&name.to_lowercase())
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown service: {}", name))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on service definitions")),
+        }
+    }
+
+    pub fn get_all_services(&self) -> Result<Vec<ServiceDefinition>> {
+        match self.services.lock() {
+            Ok(services) => {
+                let mut all: Vec<ServiceDefinition> = services.values().cloned().collect();
+                all.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on service definitions")),
+        }
+    }
+
+    // Creates or replaces a service definition
+    pub fn upsert_service(&self, name: &str, protocol: ServiceProtocol, ports: Vec<u16>, description: String) -> Result<ServiceDefinition> {
+        let name = name.to_lowercase();
+
+        if !is_valid_service_name(&name) {
+            return Err(anyhow!("Invalid service name: {}", name));
+        }
+
+        if ports.is_empty() || ports.contains(&0) {
+            return Err(anyhow!("A service needs at least one non-zero port"));
+        }
+
+        match self.services.lock() {
+            Ok(mut services) => {
+                let builtin = services.get(&name).map_or(false, |s| s.builtin);
+                let service = ServiceDefinition {
+                    name: name.clone(),
+                    protocol,
+                    ports,
+                    description,
+                    builtin,
+                };
+
+                services.insert(name, service.clone());
+                self.save(&services)?;
+                Ok(service)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on service definitions")),
+        }
+    }
+
+    pub fn delete_service(&self, name: &str) -> Result<()> {
+        let name = name.to_lowercase();
+
+        match self.services.lock() {
+            Ok(mut services) => {
+                match services.get(&name) {
+                    Some(service) if service.builtin => {
+                        return Err(anyhow!("Built-in service cannot be deleted: {}", name));
+                    },
+                    Some(_) => {},
+                    None => return Err(anyhow!("Unknown service: {}", name)),
+                }
+
+                services.remove(&name);
+                self.save(&services)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on service definitions")),
+        }
+    }
+}
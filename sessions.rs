@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::info;
+
+use crate::models::UserRole;
+
+// A logged-in client. Tokens reference the session, so removing it logs the client out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub username: String,
+    pub role: UserRole,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    // Expired or reset password: the session may only be used to change it
+    #[serde(default)]
+    pub password_change_required: bool,
+    // Copied from the user at login, see sites::SiteScope
+    #[serde(default)]
+    pub sites: Vec<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct SessionManager {
+    sessions: Arc<Mutex<HashMap<Uuid, Session>>>,
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn create_session(&self,
+                          username: &str,
+                          role: UserRole,
+                          expires_at: DateTime<Utc>,
+                          source_ip: Option<String>,
+                          user_agent: Option<String>,
+                          password_change_required: bool,
+                          sites: Vec<Uuid>) -> Result<Session> {
+        let now = Utc::now();
+        let session = Session {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            role,
+            created_at: now,
+            last_activity: now,
+            expires_at,
+            source_ip,
+            user_agent,
+            password_change_required,
+            sites,
+        };
+
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                sessions.insert(session.id, session.clone());
+                info!("Session {} created for {}", session.id, username);
+                Ok(session)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    // Validates the session behind a request and records the activity
+    pub fn touch(&self, id: Uuid) -> Result<Session> {
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                let now = Utc::now();
+                let session = sessions.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Session not found: {}", id))?;
+
+                if session.expires_at <= now {
+                    sessions.remove(&id);
+                    return Err(anyhow!("Session expired: {}", id));
+                }
+
+                session.last_activity = now;
+                Ok(session.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    pub fn get_session(&self, id: Uuid) -> Result<Session> {
+        match self.sessions.lock() {
+            Ok(sessions) => {
+                sessions.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Session not found: {}", id))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    // All sessions, or only those of one user, most recently active first
+    pub fn get_sessions(&self, username: Option<&str>) -> Result<Vec<Session>> {
+        match self.sessions.lock() {
+            Ok(sessions) => {
+                let mut list: Vec<Session> = sessions.values()
+                    .filter(|s| username.map_or(true, |u| s.username == u))
+                    .cloned()
+                    .collect();
+                list.sort_by(|a, b| b.last_activity.cmp(&a.last_activity));
+                Ok(list)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    pub fn revoke(&self, id: Uuid) -> Result<Session> {
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                let session = sessions.remove(&id)
+                    .ok_or_else(|| anyhow!("Session not found: {}", id))?;
+                info!("Session {} of {} revoked", id, session.username);
+                Ok(session)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    // Revokes every session of a user, returns how many were removed
+    pub fn revoke_user(&self, username: &str) -> Result<usize> {
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                let before = sessions.len();
+                sessions.retain(|_, s| s.username != username);
+                let removed = before - sessions.len();
+                info!("Revoked {} sessions of {}", removed, username);
+                Ok(removed)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+
+    pub fn purge_expired(&self) -> Result<usize> {
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                let now = Utc::now();
+                let before = sessions.len();
+                sessions.retain(|_, s| s.expires_at > now);
+                Ok(before - sessions.len())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sessions")),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use serde::Serialize;
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::config::{self, Config};
+use crate::models::UserRole;
+use crate::users::UserManager;
+
+// One startup check; failed checks are reported by /api/setup/status
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+// Placeholders written by config::default_config
+fn is_placeholder(value: &str) -> bool {
+    value.is_empty() || value.contains("example.com") || value == "change-me"
+}
+
+pub fn self_test(config: &Config, users: &UserManager) -> Vec<SelfTestCheck> {
+    let admins = users.get_all_users()
+        .map(|users| users.iter().filter(|u| u.role == UserRole::Admin && u.is_active).count())
+        .unwrap_or(0);
+    let default_secret = config::SecurityConfig::default().jwt_secret;
+
+    let mut checks = vec![
+        SelfTestCheck {
+            name: "admin_account",
+            ok: admins > 0,
+            detail: format!("{} active admin accounts", admins),
+        },
+        SelfTestCheck {
+            name: "jwt_secret",
+            ok: config.security.jwt_secret != default_secret && config.security.jwt_secret.len() >= 32,
+            detail: "The token signing secret must be changed and at least 32 characters long".to_string(),
+        },
+        SelfTestCheck {
+            name: "admin_email",
+            ok: !is_placeholder(&config.admin_email),
+            detail: config.admin_email.clone(),
+        },
+        SelfTestCheck {
+            name: "smtp",
+            ok: !is_placeholder(&config.smtp.server),
+            detail: format!("{}:{}", config.smtp.server, config.smtp.port),
+        },
+    ];
+
+    if config.ad_integration.enabled {
+        checks.push(SelfTestCheck {
+            name: "ad_integration",
+            ok: !is_placeholder(&config.ad_integration.server) && !is_placeholder(&config.ad_integration.bind_password),
+            detail: config.ad_integration.server.clone(),
+        });
+    }
+
+    for check in checks.iter().filter(|c| !c.ok) {
+        warn!("Self-test {} failed: {}", check.name, check.detail);
+    }
+
+    checks
+}
+
+// Whether the first-run setup is still pending. While it is, only /api/setup/* is served.
+#[derive(Clone)]
+pub struct SetupState {
+    required: Arc<AtomicBool>,
+    config_path: String,
+}
+
+impl SetupState {
+    // Setup is needed on a fresh install: no user accounts and the config file still
+    // holds the generated defaults
+    pub fn detect(config: &Config, users: &UserManager, config_path: &str) -> Result<Self> {
+        let untouched = serde_json::to_value(config)? == serde_json::to_value(config::default_config())?;
+        let required = untouched && users.get_all_users()?.is_empty();
+
+        if required {
+            warn!("First run: API is in setup mode until /api/setup/complete is called");
+        }
+
+        Ok(Self {
+            required: Arc::new(AtomicBool::new(required)),
+            config_path: config_path.to_string(),
+        })
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required.load(Ordering::SeqCst)
+    }
+
+    pub fn config_path(&self) -> &str {
+        &self.config_path
+    }
+
+    // Returns false when setup had already been completed (e.g. by a concurrent request)
+    pub fn complete(&self) -> bool {
+        let was_required = self.required.swap(false, Ordering::SeqCst);
+        if was_required {
+            info!("Setup completed, API switched to normal mode");
+        }
+        was_required
+    }
+
+    // Lets a failed setup attempt be retried
+    pub fn reopen(&self) {
+        self.required.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+// A branch office whose data is kept apart from the others. Unlike a location of kind
+// Site, which places devices in the building hierarchy, this is the unit users are
+// restricted to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site {
+    pub id: Uuid,
+    pub name: String,
+    // CIDR networks; ingested logs and flows from these addresses belong to the site
+    pub subnets: Vec<String>,
+    // Team responsible for the site
+    pub team: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Editable part of a site, used for both creation and updates
+#[derive(Debug, Clone, Deserialize)]
+pub struct SiteFields {
+    pub name: String,
+    #[serde(default)]
+    pub subnets: Vec<String>,
+    pub team: Option<String>,
+}
+
+// Sites a caller may see. An unrestricted caller sees every object, including those
+// attributed to no site; a restricted one only objects of its sites.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SiteScope {
+    All,
+    Only(Vec<Uuid>),
+}
+
+impl SiteScope {
+    pub fn new(sites: &[Uuid]) -> Self {
+        if sites.is_empty() {
+            SiteScope::All
+        } else {
+            SiteScope::Only(sites.to_vec())
+        }
+    }
+
+    pub fn allows(&self, site_id: Option<Uuid>) -> bool {
+        match self {
+            SiteScope::All => true,
+            SiteScope::Only(sites) => site_id.map_or(false, |id| sites.contains(&id)),
+        }
+    }
+
+    // The scope narrowed to the site asked for; None when that site is outside it
+    pub fn narrow(&self, site: Option<Uuid>) -> Option<SiteScope> {
+        match site {
+            None => Some(self.clone()),
+            Some(site) if self.allows(Some(site)) => Some(SiteScope::Only(vec![site])),
+            Some(_) => None,
+        }
+    }
+}
+
+fn parse_subnets(subnets: &[String]) -> Result<Vec<IpNetwork>> {
+    subnets.iter()
+        .map(|s| IpNetwork::from_str(s.trim()).map_err(|e| anyhow!("Invalid subnet {}: {}", s, e)))
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct SiteManager {
+    sites_dir: PathBuf,
+    sites: Arc<Mutex<HashMap<Uuid, Site>>>,
+}
+
+impl SiteManager {
+    pub fn new(sites_dir: &str) -> Result<Self> {
+        let sites_dir = PathBuf::from(sites_dir);
+
+        if !sites_dir.exists() {
+            fs::create_dir_all(&sites_dir)
+                .context(format!("Failed to create sites directory: {:?}", sites_dir))?;
+            info!("Created sites directory: {:?}", sites_dir);
+        }
+
+        let mut sites = HashMap::new();
+
+        for entry in fs::read_dir(&sites_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read site file: {:?}", path))?;
+            match serde_json::from_str::<Site>(&contents) {
+                Ok(site) => {
+                    sites.insert(site.id, site);
+                },
+                Err(e) => warn!("Skipping invalid site file {:?}: {}", path, e),
+            }
+        }
+
+        info!("Loaded {} sites", sites.len());
+
+        Ok(Self {
+            sites_dir,
+            sites: Arc::new(Mutex::new(sites)),
+        })
+    }
+
+    fn save_site(&self, site: &Site) -> Result<()> {
+        let path = self.sites_dir.join(format!("{}.json", site.id));
+        let json = serde_json::to_string_pretty(site)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write site file: {:?}", path))?;
+        Ok(())
+    }
+
+    fn validate(sites: &HashMap<Uuid, Site>, id: Option<Uuid>, fields: &SiteFields) -> Result<()> {
+        if fields.name.trim().is_empty() {
+            return Err(anyhow!("Site name cannot be empty"));
+        }
+        if sites.values().any(|s| Some(s.id) != id && s.name.eq_ignore_ascii_case(fields.name.trim())) {
+            return Err(anyhow!("A site named {} already exists", fields.name.trim()));
+        }
+
+        // Overlapping subnets go to the most specific one, an identical subnet would be ambiguous
+        let subnets = parse_subnets(&fields.subnets)?;
+        for site in sites.values().filter(|s| Some(s.id) != id) {
+            for other in parse_subnets(&site.subnets)? {
+                if let Some(subnet) = subnets.iter().find(|s| **s == other) {
+                    return Err(anyhow!("Subnet {} already belongs to site {}", subnet, site.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_site(&self, fields: SiteFields) -> Result<Site> {
+        match self.sites.lock() {
+            Ok(mut sites) => {
+                Self::validate(&sites, None, &fields)?;
+
+                let now = Utc::now();
+                let site = Site {
+                    id: Uuid::new_v4(),
+                    name: fields.name.trim().to_string(),
+                    subnets: fields.subnets,
+                    team: fields.team,
+                    created_at: now,
+                    updated_at: now,
+                };
+
+                self.save_site(&site)?;
+                sites.insert(site.id, site.clone());
+
+                info!("Created site {}", site.name);
+                Ok(site)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sites")),
+        }
+    }
+
+    pub fn update_site(&self, id: Uuid, fields: SiteFields) -> Result<Site> {
+        match self.sites.lock() {
+            Ok(mut sites) => {
+                let mut site = sites.get(&id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Site not found: {}", id))?;
+                Self::validate(&sites, Some(id), &fields)?;
+
+                site.name = fields.name.trim().to_string();
+                site.subnets = fields.subnets;
+                site.team = fields.team;
+                site.updated_at = Utc::now();
+
+                self.save_site(&site)?;
+                sites.insert(id, site.clone());
+                Ok(site)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sites")),
+        }
+    }
+
+    // Objects attributed to the site keep its id; they are then only visible to
+    // unrestricted users
+    pub fn delete_site(&self, id: Uuid) -> Result<()> {
+        match self.sites.lock() {
+            Ok(mut sites) => {
+                if sites.remove(&id).is_none() {
+                    return Err(anyhow!("Site not found: {}", id));
+                }
+
+                let path = self.sites_dir.join(format!("{}.json", id));
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .context(format!("Failed to delete site file: {:?}", path))?;
+                }
+
+                info!("Deleted site: {}", id);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sites")),
+        }
+    }
+
+    pub fn get_site(&self, id: Uuid) -> Result<Site> {
+        match self.sites.lock() {
+            Ok(sites) => sites.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Site not found: {}", id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on sites")),
+        }
+    }
+
+    pub fn get_all_sites(&self) -> Result<Vec<Site>> {
+        match self.sites.lock() {
+            Ok(sites) => {
+                let mut all: Vec<Site> = sites.values().cloned().collect();
+                all.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on sites")),
+        }
+    }
+
+    // Site whose most specific subnet contains the address
+    pub fn site_for_ip(&self, ip: IpAddr) -> Option<Uuid> {
+        let sites = self.sites.lock().ok()?;
+        sites.values()
+            .flat_map(|site| site.subnets.iter()
+                .filter_map(|s| IpNetwork::from_str(s).ok())
+                .filter(|network| network.contains(ip))
+                .map(move |network| (network.prefix(), site.id)))
+            .max_by_key(|(prefix, _)| *prefix)
+            .map(|(_, id)| id)
+    }
+
+    // Same for an address kept as text; names and malformed addresses have no site
+    pub fn site_for_address(&self, address: &str) -> Option<Uuid> {
+        address.parse::<IpAddr>().ok().and_then(|ip| self.site_for_ip(ip))
+    }
+}
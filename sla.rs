@@ -0,0 +1,121 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::calendar::{Calendar, CalendarManager};
+use crate::config::{SlaConfig, SlaPolicy};
+use crate::tickets::{Ticket, TicketStatus, TicketsManager};
+
+// Where a ticket stands against its resolution target
+#[derive(Debug, Clone, Serialize)]
+pub struct SlaStatus {
+    pub ticket_id: Uuid,
+    pub resolve_within_hours: f64,
+    pub business_hours: bool,
+    // Counted time since the ticket was opened; for resolved tickets up to their last update
+    pub elapsed_seconds: i64,
+    // Negative once the target is missed
+    pub remaining_seconds: i64,
+    // None if the calendar has no business hours within reach
+    pub due_at: Option<DateTime<Utc>>,
+    // Business-hours clocks stand still outside business hours
+    pub paused: bool,
+    pub breached_at: Option<DateTime<Utc>>,
+    pub calendar_version: u64,
+}
+
+fn target(policy: &SlaPolicy) -> Duration {
+    Duration::seconds((policy.resolve_within_hours * 3600.0) as i64)
+}
+
+fn is_open(ticket: &Ticket) -> bool {
+    !matches!(ticket.status, TicketStatus::Resolved | TicketStatus::Closed)
+}
+
+pub fn status(policy: &SlaPolicy, ticket: &Ticket, calendar: &Calendar, now: DateTime<Utc>) -> SlaStatus {
+    let until = if is_open(ticket) { now } else { ticket.updated_at.min(now) };
+    let target = target(policy);
+    let (elapsed, due_at) = if policy.business_hours {
+        (calendar.business_duration(ticket.created_at, until), calendar.add_business_duration(ticket.created_at, target))
+    } else {
+        (until - ticket.created_at, Some(ticket.created_at + target))
+    };
+
+    SlaStatus {
+        ticket_id: ticket.id,
+        resolve_within_hours: policy.resolve_within_hours,
+        business_hours: policy.business_hours,
+        elapsed_seconds: elapsed.num_seconds(),
+        remaining_seconds: (target - elapsed).num_seconds(),
+        due_at,
+        paused: is_open(ticket) && policy.business_hours && !calendar.is_business_time(now),
+        breached_at: ticket.sla_breached_at,
+        calendar_version: calendar.version,
+    }
+}
+
+// Records a breach on each open ticket past its target. Time is measured on the calendar
+// as it is at the check; a ticket marked once stays breached (see
+// TicketsManager::mark_sla_breached), so editing the calendar or the policy later never
+// clears or repeats a breach.
+#[derive(Clone)]
+pub struct SlaTracker {
+    config: SlaConfig,
+    tickets: TicketsManager,
+    calendar: CalendarManager,
+}
+
+impl SlaTracker {
+    pub fn new(config: SlaConfig, tickets: TicketsManager, calendar: CalendarManager) -> Self {
+        Self {
+            config,
+            tickets,
+            calendar,
+        }
+    }
+
+    pub fn status(&self, ticket: &Ticket, now: DateTime<Utc>) -> Result<SlaStatus> {
+        Ok(status(self.config.policy_for(&ticket.priority), ticket, &self.calendar.calendar()?, now))
+    }
+
+    // Returns how many tickets newly breached
+    pub fn check(&self, now: DateTime<Utc>) -> Result<usize> {
+        let calendar = self.calendar.calendar()?;
+        let mut breached = 0;
+
+        for ticket in self.tickets.get_all_tickets()? {
+            if !is_open(&ticket) || ticket.sla_breached_at.is_some() {
+                continue;
+            }
+            let policy = self.config.policy_for(&ticket.priority);
+            let status = status(policy, &ticket, &calendar, now);
+            if status.remaining_seconds > 0 {
+                continue;
+            }
+
+            let details = serde_json::json!({
+                "priority": ticket.priority,
+                "resolve_within_hours": policy.resolve_within_hours,
+                "business_hours": policy.business_hours,
+                "elapsed_seconds": status.elapsed_seconds,
+                "due_at": status.due_at,
+                "calendar_version": calendar.version,
+            });
+            match self.tickets.mark_sla_breached(ticket.id, now, details) {
+                Ok(true) => {
+                    warn!("Ticket {} missed its {:?} SLA of {} hours", ticket.id, ticket.priority, policy.resolve_within_hours);
+                    breached += 1;
+                },
+                Ok(false) => {},
+                Err(e) => warn!("Failed to record SLA breach of ticket {}: {}", ticket.id, e),
+            }
+        }
+
+        if breached > 0 {
+            info!("{} tickets breached their SLA", breached);
+        }
+        Ok(breached)
+    }
+}
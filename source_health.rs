@@ -0,0 +1,605 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::{ClockSkewConfig, SourceHealthConfig};
+use crate::ingestion_quotas::SourceStatus;
+use crate::models::{AlertSeverity, AlertStatus};
+
+// Gaps between events kept per source for learning its cadence
+const MAX_GAPS: usize = 1000;
+
+// Gaps needed before an interval can be learned
+const MIN_LEARNING_GAPS: usize = 50;
+
+// A learned interval is this many times the 95th percentile gap
+const LEARNING_FACTOR: i64 = 3;
+
+// Clock skews kept per source for its median
+const MAX_SKEWS: usize = 200;
+
+// Sources nobody expects are forgotten after this long without events
+const FORGET_DAYS: i64 = 30;
+
+// Sources tracked at most; when full, the longest quiet tenth of the unexpected ones
+// without open alerts makes room, so spoofed source names cannot grow the map
+const MAX_SOURCES: usize = 5000;
+
+// Hours a source is expected to log in; silence outside them is not counted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceSchedule {
+    // IANA timezone the times are in
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default = "all_days")]
+    pub days: Vec<Weekday>,
+    // HH:MM; an end before the start spans midnight
+    pub start: String,
+    pub end: String,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn all_days() -> Vec<Weekday> {
+    vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun]
+}
+
+impl SourceSchedule {
+    fn parse(&self) -> Result<(Tz, NaiveTime, NaiveTime)> {
+        let tz = self.timezone.parse::<Tz>()
+            .map_err(|_| anyhow!("Unknown timezone: {}", self.timezone))?;
+        let time = |t: &str| NaiveTime::parse_from_str(t, "%H:%M")
+            .map_err(|_| anyhow!("Invalid time, expected HH:MM: {}", t));
+        let (start, end) = (time(&self.start)?, time(&self.end)?);
+        if start == end {
+            return Err(anyhow!("Schedule start and end must differ"));
+        }
+        if self.days.is_empty() {
+            return Err(anyhow!("Schedule needs at least one day"));
+        }
+        Ok((tz, start, end))
+    }
+
+    // Start of the scheduled window containing the instant, None outside the schedule
+    fn window_start(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let (tz, start, end) = self.parse().ok()?;
+        let local = at.with_timezone(&tz);
+        let time = local.time();
+        let today = local.date_naive();
+
+        let started_on = if start < end {
+            (time >= start && time < end).then_some(today)
+        } else if time >= start {
+            Some(today)
+        } else if time < end {
+            today.pred_opt()
+        } else {
+            None
+        }?;
+
+        if !self.days.contains(&started_on.weekday()) {
+            return None;
+        }
+        tz.from_local_datetime(&started_on.and_time(start))
+            .earliest()
+            .map(|s| s.with_timezone(&Utc))
+    }
+}
+
+// A source that must not go quiet for longer than its interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpectedSource {
+    pub max_silence_minutes: i64,
+    // Set when the interval was learned from the source's cadence
+    pub learned: bool,
+    pub schedule: Option<SourceSchedule>,
+    pub updated_by: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Body of PUT /api/logs/sources/:source/expected; learn replaces max_silence_minutes
+#[derive(Debug, Clone, Deserialize)]
+pub struct Expectation {
+    pub max_silence_minutes: Option<i64>,
+    #[serde(default)]
+    pub learn: bool,
+    pub schedule: Option<SourceSchedule>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SourceRecord {
+    last_seen: Option<DateTime<Utc>>,
+    // Seconds between consecutive events, oldest first
+    #[serde(default)]
+    gaps: VecDeque<i64>,
+    expected: Option<ExpectedSource>,
+    // Open silence alert and when it was raised
+    alert_id: Option<Uuid>,
+    alert_raised_at: Option<DateTime<Utc>>,
+    // Receipt time minus event timestamp in seconds, oldest first; positive means the
+    // source's clock is behind
+    #[serde(default)]
+    skews: VecDeque<i64>,
+    // Events stored at receipt time because of their skew
+    #[serde(default)]
+    rewritten: u64,
+    #[serde(default)]
+    skew_alert_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceState {
+    // Not marked as expected
+    Unmonitored,
+    Reporting,
+    OutsideSchedule,
+    Silent,
+}
+
+// A source as shown by GET /api/logs/sources
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceHealth {
+    pub source: String,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub events_per_minute: u64,
+    pub expected: bool,
+    pub expectation: Option<ExpectedSource>,
+    pub state: SilenceState,
+    // Start of the silence that is counted, i.e. the last event or the schedule start
+    pub silent_since: Option<DateTime<Utc>>,
+    pub alert_id: Option<Uuid>,
+    // Quota counters while the source is active, see ingestion_quotas
+    pub ingestion: Option<SourceStatus>,
+    // Over the recent events, see clock_skew
+    pub median_skew_secs: Option<i64>,
+    pub rewritten: u64,
+    pub skew_alert_id: Option<Uuid>,
+}
+
+// Silence is counted from the last event, or from the start of the current scheduled
+// window when that is later, so a source that only logs during business hours is not
+// silent overnight
+fn silence(record: &SourceRecord, now: DateTime<Utc>) -> (SilenceState, Option<DateTime<Utc>>) {
+    let expected = match &record.expected {
+        Some(expected) => expected,
+        None => return (SilenceState::Unmonitored, None),
+    };
+
+    let mut since = record.last_seen.unwrap_or(expected.updated_at);
+    if let Some(schedule) = &expected.schedule {
+        match schedule.window_start(now) {
+            Some(start) => since = since.max(start),
+            None => return (SilenceState::OutsideSchedule, None),
+        }
+    }
+
+    if now - since > Duration::minutes(expected.max_silence_minutes) {
+        (SilenceState::Silent, Some(since))
+    } else {
+        (SilenceState::Reporting, Some(since))
+    }
+}
+
+fn median_skew(skews: &VecDeque<i64>) -> Option<i64> {
+    if skews.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<i64> = skews.iter().copied().collect();
+    sorted.sort_unstable();
+    Some(sorted[sorted.len() / 2])
+}
+
+// Interval from the source's cadence: a multiple of the 95th percentile gap
+fn learn_interval(gaps: &VecDeque<i64>, min_minutes: i64) -> Result<i64> {
+    if gaps.len() < MIN_LEARNING_GAPS {
+        return Err(anyhow!("Not enough history to learn an interval ({} of {} events)", gaps.len(), MIN_LEARNING_GAPS));
+    }
+    let mut sorted: Vec<i64> = gaps.iter().copied().collect();
+    sorted.sort_unstable();
+    let p95 = sorted[(sorted.len() * 95 / 100).min(sorted.len() - 1)];
+    Ok(((p95 * LEARNING_FACTOR + 59) / 60).max(min_minutes))
+}
+
+// Tracks when each source (host, or source name without a host) was last heard from and
+// raises an alert when an expected source stays quiet for longer than its interval, or
+// when its clock is consistently off
+#[derive(Clone)]
+pub struct SourceHealthMonitor {
+    path: PathBuf,
+    config: SourceHealthConfig,
+    clock_skew: ClockSkewConfig,
+    sources: Arc<Mutex<HashMap<String, SourceRecord>>>,
+    alerts: AlertsManager,
+}
+
+impl SourceHealthMonitor {
+    pub fn new(dir: &str, config: SourceHealthConfig, clock_skew: ClockSkewConfig, alerts: AlertsManager) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create source health directory: {:?}", dir))?;
+            info!("Created source health directory: {:?}", dir);
+        }
+
+        let path = dir.join("sources.json");
+        let sources: HashMap<String, SourceRecord> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring invalid source health file {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        info!("Loaded {} log sources, {} expected", sources.len(), sources.values().filter(|s| s.expected.is_some()).count());
+
+        Ok(Self {
+            path,
+            config,
+            clock_skew,
+            sources: Arc::new(Mutex::new(sources)),
+            alerts,
+        })
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.check_interval_secs.max(10))
+    }
+
+    // Whether a new source can be tracked, forgetting others if needed
+    fn make_room(sources: &mut HashMap<String, SourceRecord>) -> bool {
+        if sources.len() < MAX_SOURCES {
+            return true;
+        }
+
+        let mut forgettable: Vec<(Option<DateTime<Utc>>, String)> = sources.iter()
+            .filter(|(_, r)| r.expected.is_none() && r.alert_id.is_none() && r.skew_alert_id.is_none())
+            .map(|(source, r)| (r.last_seen, source.clone()))
+            .collect();
+        forgettable.sort_unstable();
+        for (_, source) in forgettable.into_iter().take(MAX_SOURCES / 10) {
+            sources.remove(&source);
+        }
+        sources.len() < MAX_SOURCES
+    }
+
+    fn save(&self, sources: &HashMap<String, SourceRecord>) -> Result<()> {
+        let json = serde_json::to_string(sources)?;
+        fs::write(&self.path, json)
+            .context(format!("Failed to write source health file: {:?}", self.path))?;
+        Ok(())
+    }
+
+    // Called for every event at ingestion, dropped ones included: they still prove the
+    // source is alive. The skew is in seconds, see SourceRecord.
+    pub fn record(&self, source: &str, at: DateTime<Utc>, skew_secs: i64, rewritten: bool) -> Result<()> {
+        match self.sources.lock() {
+            Ok(mut sources) => {
+                if !sources.contains_key(source) && !Self::make_room(&mut sources) {
+                    return Ok(());
+                }
+                let record = sources.entry(source.to_string()).or_default();
+                record.skews.push_back(skew_secs);
+                if record.skews.len() > MAX_SKEWS {
+                    record.skews.pop_front();
+                }
+                if rewritten {
+                    record.rewritten += 1;
+                }
+
+                if let Some(last) = record.last_seen {
+                    if at <= last {
+                        return Ok(());
+                    }
+                    record.gaps.push_back((at - last).num_seconds());
+                    if record.gaps.len() > MAX_GAPS {
+                        record.gaps.pop_front();
+                    }
+                }
+                record.last_seen = Some(at);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log sources")),
+        }
+    }
+
+    pub fn set_expected(&self, source: &str, expectation: Expectation, user: &str) -> Result<ExpectedSource> {
+        if let Some(schedule) = &expectation.schedule {
+            schedule.parse()?;
+        }
+
+        match self.sources.lock() {
+            Ok(mut sources) => {
+                let record = sources.entry(source.to_string()).or_default();
+                let max_silence_minutes = match (expectation.learn, expectation.max_silence_minutes) {
+                    (true, _) => learn_interval(&record.gaps, self.config.min_silence_minutes)?,
+                    (false, Some(minutes)) if minutes > 0 => minutes,
+                    (false, Some(_)) => return Err(anyhow!("max_silence_minutes must be positive")),
+                    (false, None) => return Err(anyhow!("Either max_silence_minutes or learn is required")),
+                };
+
+                let expected = ExpectedSource {
+                    max_silence_minutes,
+                    learned: expectation.learn,
+                    schedule: expectation.schedule,
+                    updated_by: user.to_string(),
+                    updated_at: Utc::now(),
+                };
+                record.expected = Some(expected.clone());
+                self.save(&sources)?;
+
+                info!("Source {} expected to log at least every {} minutes", source, max_silence_minutes);
+                Ok(expected)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log sources")),
+        }
+    }
+
+    // Stops monitoring the source; an open silence alert is resolved with it
+    pub fn clear_expected(&self, source: &str) -> Result<()> {
+        let alert_id = match self.sources.lock() {
+            Ok(mut sources) => {
+                let record = sources.get_mut(source)
+                    .filter(|r| r.expected.is_some())
+                    .ok_or_else(|| anyhow!("Source is not expected: {}", source))?;
+                record.expected = None;
+                record.alert_raised_at = None;
+                let alert_id = record.alert_id.take();
+                self.save(&sources)?;
+                alert_id
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on log sources")),
+        };
+
+        if let Some(id) = alert_id {
+            self.resolve_alert(id)?;
+        }
+        Ok(())
+    }
+
+    // Resolves unless someone already closed it
+    fn resolve_alert(&self, id: Uuid) -> Result<()> {
+        match self.alerts.get_alert(id) {
+            Ok(alert) if !matches!(alert.status, AlertStatus::Resolved | AlertStatus::Closed) => {
+                self.alerts.update_status(id, AlertStatus::Resolved)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Raises alerts for expected sources past their interval and resolves those whose
+    // source has sent events since; same for sources whose clock is off
+    pub fn check(&self, now: DateTime<Utc>) -> Result<()> {
+        let mut raise = Vec::new();
+        let mut resolve = Vec::new();
+        let mut raise_skew = Vec::new();
+        let mut resolve_skew = Vec::new();
+
+        let mut sources = match self.sources.lock() {
+            Ok(sources) => sources,
+            Err(_) => return Err(anyhow!("Failed to acquire lock on log sources")),
+        };
+
+        sources.retain(|_, r| r.expected.is_some()
+            || r.last_seen.map_or(false, |seen| now - seen < Duration::days(FORGET_DAYS)));
+
+        for (source, record) in sources.iter_mut() {
+            // Median over enough recent events, so a single delayed batch does not count
+            let skew = median_skew(&record.skews).filter(|_| record.skews.len() >= self.clock_skew.min_samples);
+            let skewed = skew.map_or(false, |s| s.abs() > self.clock_skew.alert_after_secs);
+            match (record.skew_alert_id, skew) {
+                (None, Some(skew)) if skewed => raise_skew.push((source.clone(), skew, record.skews.len())),
+                (Some(id), Some(_)) if !skewed => {
+                    record.skew_alert_id = None;
+                    resolve_skew.push((source.clone(), id));
+                },
+                _ => {},
+            }
+
+            if let (Some(id), Some(raised_at)) = (record.alert_id, record.alert_raised_at) {
+                if record.last_seen.map_or(false, |seen| seen > raised_at) {
+                    record.alert_id = None;
+                    record.alert_raised_at = None;
+                    resolve.push((source.clone(), id));
+                }
+                continue;
+            }
+
+            if let (SilenceState::Silent, Some(since)) = silence(record, now) {
+                let minutes = record.expected.as_ref().map_or(0, |e| e.max_silence_minutes);
+                raise.push((source.clone(), since, minutes));
+            }
+        }
+
+        for (source, since, minutes) in raise {
+            warn!("Log source {} has been silent since {}", source, since);
+            let id = self.alerts.create_alert(
+                AlertSeverity::High,
+                format!("Log source {} went silent", source),
+                format!("No events since {} (allowed silence {} minutes). A source that stops logging may have failed or been tampered with.",
+                        since.to_rfc3339(), minutes),
+                "source_health".to_string(),
+                Vec::new(),
+            )?;
+            if let Some(record) = sources.get_mut(&source) {
+                record.alert_id = Some(id);
+                record.alert_raised_at = Some(now);
+            }
+        }
+
+        for (source, skew, samples) in raise_skew {
+            warn!("Clock of log source {} is off by {} seconds", source, skew);
+            let id = self.alerts.create_alert(
+                AlertSeverity::Medium,
+                format!("Clock skew on log source {}", source),
+                format!("Events from {} are {} seconds {} the time they are received (median of the last {}). Check its time synchronization (NTP); skewed timestamps break correlation windows and timelines.",
+                        source, skew.abs(), if skew > 0 { "behind" } else { "ahead of" }, samples),
+                "source_health".to_string(),
+                Vec::new(),
+            )?;
+            if let Some(record) = sources.get_mut(&source) {
+                record.skew_alert_id = Some(id);
+            }
+        }
+
+        self.save(&sources)?;
+        drop(sources);
+
+        for (source, id) in resolve {
+            info!("Log source {} is reporting again", source);
+            self.resolve_alert(id)?;
+        }
+        for (source, id) in resolve_skew {
+            info!("Clock of log source {} is back in line", source);
+            self.resolve_alert(id)?;
+        }
+        Ok(())
+    }
+
+    // All known sources with their silence state, silent ones first, then by name
+    pub fn sources(&self, now: DateTime<Utc>) -> Result<Vec<SourceHealth>> {
+        match self.sources.lock() {
+            Ok(sources) => {
+                let mut list: Vec<SourceHealth> = sources.iter()
+                    .map(|(source, record)| {
+                        let (state, silent_since) = silence(record, now);
+                        SourceHealth {
+                            source: source.clone(),
+                            last_seen: record.last_seen,
+                            events_per_minute: 0,
+                            expected: record.expected.is_some(),
+                            expectation: record.expected.clone(),
+                            state,
+                            silent_since,
+                            alert_id: record.alert_id,
+                            ingestion: None,
+                            median_skew_secs: median_skew(&record.skews),
+                            rewritten: record.rewritten,
+                            skew_alert_id: record.skew_alert_id,
+                        }
+                    })
+                    .collect();
+                list.sort_by(|a, b| (b.state == SilenceState::Silent).cmp(&(a.state == SilenceState::Silent))
+                    .then(a.source.cmp(&b.source)));
+                Ok(list)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on log sources")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(dir: &tempfile::TempDir) -> SourceHealthMonitor {
+        let root = dir.path().to_str().unwrap();
+        SourceHealthMonitor::new(
+            &format!("{}/sources", root),
+            SourceHealthConfig::default(),
+            ClockSkewConfig::default(),
+            AlertsManager::new(&format!("{}/alerts", root)).unwrap(),
+        ).unwrap()
+    }
+
+    fn expect(minutes: i64) -> Expectation {
+        Expectation { max_silence_minutes: Some(minutes), learn: false, schedule: None }
+    }
+
+    fn state(monitor: &SourceHealthMonitor, source: &str, now: DateTime<Utc>) -> SilenceState {
+        monitor.sources(now).unwrap().into_iter().find(|s| s.source == source).unwrap().state
+    }
+
+    #[test]
+    fn silent_sources_raise_and_resolve_an_alert() {
+        let dir = tempfile::tempdir().unwrap();
+        let monitor = monitor(&dir);
+        let start = Utc::now();
+        monitor.record("fw01", start, 0, false).unwrap();
+        monitor.set_expected("fw01", expect(10), "admin").unwrap();
+
+        monitor.check(start + Duration::minutes(5)).unwrap();
+        assert_eq!(state(&monitor, "fw01", start + Duration::minutes(5)), SilenceState::Reporting);
+        assert!(monitor.alerts.get_all_alerts().unwrap().is_empty());
+
+        let late = start + Duration::minutes(11);
+        monitor.check(late).unwrap();
+        monitor.check(late).unwrap();
+        let alerts = monitor.alerts.get_all_alerts().unwrap();
+        assert_eq!(alerts.len(), 1);
+
+        monitor.record("fw01", late + Duration::minutes(1), 0, false).unwrap();
+        monitor.check(late + Duration::minutes(2)).unwrap();
+        assert!(matches!(monitor.alerts.get_alert(alerts[0].id).unwrap().status, AlertStatus::Resolved));
+    }
+
+    #[test]
+    fn silence_outside_the_schedule_is_not_counted() {
+        let schedule = SourceSchedule {
+            timezone: "UTC".to_string(),
+            days: all_days(),
+            start: "08:00".to_string(),
+            end: "18:00".to_string(),
+        };
+        let day = Utc.with_ymd_and_hms(2026, 3, 2, 0, 0, 0).unwrap();
+        assert_eq!(schedule.window_start(day + Duration::hours(12)), Some(day + Duration::hours(8)));
+        assert_eq!(schedule.window_start(day + Duration::hours(20)), None);
+
+        let overnight = SourceSchedule { start: "22:00".to_string(), end: "06:00".to_string(), ..schedule };
+        assert_eq!(overnight.window_start(day + Duration::hours(3)), Some(day - Duration::hours(2)));
+        assert_eq!(overnight.window_start(day + Duration::hours(12)), None);
+
+        let record = SourceRecord {
+            last_seen: Some(day - Duration::days(1)),
+            expected: Some(ExpectedSource {
+                max_silence_minutes: 30,
+                learned: false,
+                schedule: Some(overnight),
+                updated_by: "admin".to_string(),
+                updated_at: day - Duration::days(2),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(silence(&record, day + Duration::hours(12)).0, SilenceState::OutsideSchedule);
+        assert_eq!(silence(&record, day - Duration::minutes(90)), (SilenceState::Reporting, Some(day - Duration::hours(2))));
+        assert_eq!(silence(&record, day + Duration::hours(1)).0, SilenceState::Silent);
+    }
+
+    #[test]
+    fn intervals_are_learned_from_the_cadence() {
+        let mut gaps: VecDeque<i64> = VecDeque::from(vec![60; MIN_LEARNING_GAPS - 1]);
+        assert!(learn_interval(&gaps, 15).is_err());
+        gaps.push_back(60);
+        assert_eq!(learn_interval(&gaps, 15).unwrap(), 15);
+        let gaps: VecDeque<i64> = VecDeque::from(vec![600; 100]);
+        assert_eq!(learn_interval(&gaps, 15).unwrap(), 30);
+    }
+
+    #[test]
+    fn tracked_sources_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let monitor = monitor(&dir);
+        let start = Utc::now() - Duration::hours(1);
+        monitor.record("fw01", start, 0, false).unwrap();
+        monitor.set_expected("fw01", expect(120), "admin").unwrap();
+        for i in 0..MAX_SOURCES {
+            monitor.record(&format!("spoofed-{}", i), start + Duration::seconds(i as i64), 0, false).unwrap();
+        }
+
+        let sources = monitor.sources.lock().unwrap();
+        assert!(sources.len() <= MAX_SOURCES);
+        assert!(sources.contains_key("fw01"));
+        assert!(!sources.contains_key("spoofed-0"));
+        assert!(sources.contains_key(&format!("spoofed-{}", MAX_SOURCES - 1)));
+    }
+}
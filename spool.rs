@@ -0,0 +1,98 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, de::DeserializeOwned};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+#[derive(Debug, Default)]
+struct SpoolState {
+    bytes: u64,
+    entries: u64,
+    dropped: u64,
+}
+
+// Bounded on-disk queue of JSON lines for writes that cannot be delivered right now.
+// Once the file reaches max_bytes further entries are dropped and counted.
+#[derive(Clone)]
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    state: Arc<Mutex<SpoolState>>,
+}
+
+impl Spool {
+    pub fn new(dir: &Path, name: &str, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .context(format!("Failed to create spool directory: {:?}", dir))?;
+        let path = dir.join(format!("{}.jsonl", name));
+
+        let mut state = SpoolState::default();
+        if path.exists() {
+            state.bytes = fs::metadata(&path)?.len();
+            state.entries = BufReader::new(File::open(&path)?).lines().count() as u64;
+            if state.entries > 0 {
+                info!("Spool {} holds {} entries from a previous run", name, state.entries);
+            }
+        }
+
+        Ok(Self {
+            path,
+            max_bytes,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    // Returns false when the spool is full and the entry was dropped
+    pub fn push<T: Serialize>(&self, item: &T) -> Result<bool> {
+        let mut line = serde_json::to_string(item)?;
+        line.push('\n');
+
+        match self.state.lock() {
+            Ok(mut state) => {
+                if state.bytes + line.len() as u64 > self.max_bytes {
+                    state.dropped += 1;
+                    return Ok(false);
+                }
+                OpenOptions::new().create(true).append(true).open(&self.path)?
+                    .write_all(line.as_bytes())?;
+                state.bytes += line.len() as u64;
+                state.entries += 1;
+                Ok(true)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on spool")),
+        }
+    }
+
+    // Removes and returns everything spooled; unreadable lines are skipped
+    pub fn take<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        match self.state.lock() {
+            Ok(mut state) => {
+                if state.entries == 0 {
+                    return Ok(Vec::new());
+                }
+                let mut items = Vec::new();
+                for line in BufReader::new(File::open(&self.path)?).lines() {
+                    match serde_json::from_str(&line?) {
+                        Ok(item) => items.push(item),
+                        Err(e) => warn!("Skipping unreadable spool entry in {:?}: {}", self.path, e),
+                    }
+                }
+                fs::remove_file(&self.path)?;
+                state.bytes = 0;
+                state.entries = 0;
+                Ok(items)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on spool")),
+        }
+    }
+
+    // Number of spooled entries and of entries dropped because the spool was full
+    pub fn counts(&self) -> Result<(u64, u64)> {
+        match self.state.lock() {
+            Ok(state) => Ok((state.entries, state.dropped)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on spool")),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::future::Future;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use anyhow::{Result, anyhow};
+use tracing::{error, info, warn};
+
+// What a failed initialization does to the rest of the server
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    // Startup is aborted; nothing works without it
+    Required,
+    // The subsystem is disabled and the server starts without it, so the problem can be
+    // looked at and fixed through the web UI
+    Degradable,
+}
+
+// A part of the server initialized on its own
+pub struct Subsystem {
+    pub name: &'static str,
+    pub policy: FailurePolicy,
+    // Subsystems that must have started; one depending on a disabled subsystem is
+    // disabled too, without running its initialization
+    pub depends_on: &'static [&'static str],
+    // Request path prefixes answered with 503 while the subsystem is disabled
+    pub routes: &'static [&'static str],
+}
+
+// In initialization order; a subsystem only depends on ones listed before it
+pub const SUBSYSTEMS: &[Subsystem] = &[
+    // The data, log and other directories, minus the scripts directory
+    Subsystem { name: "storage", policy: FailurePolicy::Required, depends_on: &[], routes: &[] },
+    // Audit log, roles, user accounts and sessions; signing in needs all of them
+    Subsystem { name: "accounts", policy: FailurePolicy::Required, depends_on: &["storage"], routes: &[] },
+    // Log storage in PostgreSQL; without it logs stay in memory as when no database_url is set
+    Subsystem { name: "database", policy: FailurePolicy::Degradable, depends_on: &["storage"], routes: &[] },
+    // Netlink and the nftables ruleset, neither of which exists on a non-Linux machine
+    Subsystem { name: "network", policy: FailurePolicy::Degradable, depends_on: &[], routes: &["/api/network"] },
+    Subsystem { name: "dhcp_relay", policy: FailurePolicy::Degradable, depends_on: &["network"], routes: &[] },
+    // Email and webhook delivery
+    Subsystem { name: "notifications", policy: FailurePolicy::Degradable, depends_on: &[], routes: &[] },
+    Subsystem { name: "scripts", policy: FailurePolicy::Degradable, depends_on: &["storage"], routes: &["/api/scripts", "/api/alerts/remediations"] },
+    Subsystem { name: "syslog_tls", policy: FailurePolicy::Degradable, depends_on: &[], routes: &[] },
+];
+
+fn subsystem(name: &str) -> Result<&'static Subsystem> {
+    SUBSYSTEMS.iter()
+        .find(|s| s.name == name)
+        .ok_or_else(|| anyhow!("Unknown subsystem: {}", name))
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum SubsystemState {
+    Ready,
+    Disabled { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsystemStatus {
+    pub name: &'static str,
+    pub policy: FailurePolicy,
+    pub depends_on: &'static [&'static str],
+    #[serde(flatten)]
+    pub state: SubsystemState,
+    pub at: DateTime<Utc>,
+}
+
+// Outcome of initializing the subsystems, kept for /api/health and the route gate.
+// Subsystems not initialized at all (switched off in the config) are not listed.
+#[derive(Debug, Clone, Default)]
+pub struct Startup {
+    statuses: Vec<SubsystemStatus>,
+    // Subsystems whose initialization is replaced by a failure, to test degraded startup
+    simulated_failures: HashSet<&'static str>,
+}
+
+impl Startup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub fn failing(names: &[&'static str]) -> Self {
+        Self {
+            statuses: Vec::new(),
+            simulated_failures: names.iter().copied().collect(),
+        }
+    }
+
+    fn state(&self, name: &str) -> Option<&SubsystemState> {
+        self.statuses.iter().find(|s| s.name == name).map(|s| &s.state)
+    }
+
+    // Runs the initialization of a subsystem once its dependencies have started. The error
+    // of a required subsystem is returned; a degradable one that fails is recorded as
+    // disabled and gives None, and the caller goes on with a stand-in.
+    pub async fn init<T, F>(&mut self, name: &'static str, init: F) -> Result<Option<T>>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let subsystem = subsystem(name)?;
+        if self.state(name).is_some() {
+            return Err(anyhow!("Subsystem {} initialized twice", name));
+        }
+
+        let mut blocked = None;
+        for dependency in subsystem.depends_on {
+            match self.state(dependency) {
+                Some(SubsystemState::Ready) => {},
+                Some(SubsystemState::Disabled { .. }) => {
+                    blocked = Some(format!("Depends on {}, which is disabled", dependency));
+                    break;
+                },
+                None => return Err(anyhow!("Subsystem {} initialized before its dependency {}", name, dependency)),
+            }
+        }
+
+        let result = match blocked {
+            Some(reason) => Err(anyhow!(reason)),
+            None if self.simulated_failures.contains(name) => Err(anyhow!("Simulated failure of {}", name)),
+            None => init.await,
+        };
+
+        let (value, state) = match result {
+            Ok(value) => {
+                info!("Subsystem {} started", name);
+                (Some(value), SubsystemState::Ready)
+            },
+            Err(e) if subsystem.policy == FailurePolicy::Required => {
+                error!("Required subsystem {} failed to start: {:#}", name, e);
+                return Err(e.context(format!("Failed to start {}", name)));
+            },
+            Err(e) => {
+                warn!("Subsystem {} failed to start and is disabled: {:#}", name, e);
+                (None, SubsystemState::Disabled { error: format!("{:#}", e) })
+            },
+        };
+
+        self.statuses.push(SubsystemStatus {
+            name,
+            policy: subsystem.policy,
+            depends_on: subsystem.depends_on,
+            state,
+            at: Utc::now(),
+        });
+        Ok(value)
+    }
+
+    // For required subsystems, which either start or abort startup
+    pub async fn require<T, F>(&mut self, name: &'static str, init: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        self.init(name, init).await?
+            .ok_or_else(|| anyhow!("Subsystem {} is not required", name))
+    }
+
+    pub fn statuses(&self) -> &[SubsystemStatus] {
+        &self.statuses
+    }
+
+    // The initialization error of a disabled subsystem
+    pub fn disabled(&self, name: &str) -> Option<&str> {
+        match self.state(name) {
+            Some(SubsystemState::Disabled { error }) => Some(error),
+            _ => None,
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.statuses.iter().any(|s| s.state != SubsystemState::Ready)
+    }
+
+    // The disabled subsystem serving a request path, with its initialization error
+    pub fn blocking(&self, path: &str) -> Option<(&'static str, &str)> {
+        SUBSYSTEMS.iter()
+            .filter(|s| s.routes.iter().any(|prefix| path == *prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))))
+            .find_map(|s| self.disabled(s.name).map(|error| (s.name, error)))
+    }
+
+    // Logged once everything is up, so a degraded start stands out in the log
+    pub fn log_banner(&self) {
+        let disabled: Vec<&SubsystemStatus> = self.statuses.iter()
+            .filter(|s| s.state != SubsystemState::Ready)
+            .collect();
+        if disabled.is_empty() {
+            info!("All {} subsystems started", self.statuses.len());
+            return;
+        }
+
+        warn!("==============================================================");
+        warn!("Started in degraded mode, {} of {} subsystems disabled:", disabled.len(), self.statuses.len());
+        for status in &disabled {
+            if let SubsystemState::Disabled { error } = &status.state {
+                warn!("  {}: {}", status.name, error);
+            }
+        }
+        warn!("Their routes answer 503; fix the cause and restart. See /api/health.");
+        warn!("==============================================================");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_are_known_and_listed_first() {
+        for (i, subsystem) in SUBSYSTEMS.iter().enumerate() {
+            for dependency in subsystem.depends_on {
+                let position = SUBSYSTEMS.iter().position(|s| s.name == *dependency);
+                assert!(position.is_some_and(|p| p < i), "{} depends on {}", subsystem.name, dependency);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn degradable_failure_disables_dependents() {
+        let mut startup = Startup::new();
+        assert_eq!(startup.init("network", async { Err::<(), _>(anyhow!("no netlink")) }).await.unwrap(), None);
+
+        // Never polled
+        let ran = std::sync::atomic::AtomicBool::new(false);
+        let relay = startup.init("dhcp_relay", async {
+            ran.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }).await.unwrap();
+        assert_eq!(relay, None);
+        assert!(!ran.load(std::sync::atomic::Ordering::SeqCst));
+
+        assert_eq!(startup.disabled("network"), Some("no netlink"));
+        assert_eq!(startup.disabled("dhcp_relay"), Some("Depends on network, which is disabled"));
+        assert_eq!(startup.blocking("/api/network/interfaces").map(|(name, _)| name), Some("network"));
+        assert_eq!(startup.blocking("/api/networks"), None);
+        assert!(startup.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn required_failure_aborts() {
+        let mut startup = Startup::new();
+        let result = startup.init("storage", async { Err::<(), _>(anyhow!("read-only filesystem")) }).await;
+        assert!(result.is_err());
+
+        let mut startup = Startup::failing(&["accounts"]);
+        startup.init("storage", async { Ok(()) }).await.unwrap();
+        assert!(startup.init("accounts", async { Ok(()) }).await.is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, NaiveDateTime, Utc, Datelike};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_rustls::rustls::{self, pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject}};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::TlsAcceptor;
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn, error};
+
+use crate::config::{SyslogTlsConfig, TlsConfig};
+use crate::ingestion::IngestionPipeline;
+use crate::models::{EventCategory, LogEntry, LogParser, LogSeverity};
+
+// Syslog severities 0-7 mapped onto ours
+fn severity_from_pri(pri: u8) -> LogSeverity {
+    match pri % 8 {
+        0..=2 => LogSeverity::Critical,
+        3 => LogSeverity::Error,
+        4 => LogSeverity::Warning,
+        5 | 6 => LogSeverity::Info,
+        _ => LogSeverity::Debug,
+    }
+}
+
+fn nil(value: &str) -> Option<String> {
+    if value == "-" || value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+// Splits off the next space separated token
+fn next_token(input: &str) -> (&str, &str) {
+    match input.find(' ') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => (input, ""),
+    }
+}
+
+// Skips RFC 5424 structured data ("-" or one or more [id param="value"] elements)
+fn skip_structured_data(input: &str) -> &str {
+    if let Some(rest) = input.strip_prefix('-') {
+        return rest.strip_prefix(' ').unwrap_or(rest);
+    }
+
+    let bytes = input.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] == b'[' {
+        let mut in_quotes = false;
+        i += 1;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if in_quotes => i += 1,
+                b'"' => in_quotes = !in_quotes,
+                b']' if !in_quotes => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+
+    let rest = &input[i.min(input.len())..];
+    rest.strip_prefix(' ').unwrap_or(rest)
+}
+
+// Parses an RFC 5424 or (best effort) RFC 3164 message. Anything unparseable is
+// kept as the message so no event is lost.
+pub fn parse_syslog(raw: &str) -> LogEntry {
+    let mut entry = LogEntry {
+        id: Uuid::new_v4(),
+        timestamp: Utc::now(),
+        source: "syslog".to_string(),
+        event_type: "syslog".to_string(),
+        severity: LogSeverity::Info,
+        message: raw.to_string(),
+        raw_data: raw.to_string(),
+        host: None,
+        user: None,
+        application: None,
+        tags: Vec::new(),
+        category: EventCategory::default(),
+        hostname: None,
+        site_id: None,
+        parser: LogParser::Syslog,
+    };
+
+    let rest = match raw.strip_prefix('<').and_then(|r| r.split_once('>')) {
+        Some((pri, rest)) => match pri.parse::<u8>() {
+            Ok(pri) => {
+                entry.severity = severity_from_pri(pri);
+                entry.tags.push(format!("facility:{}", pri / 8));
+                rest
+            },
+            Err(_) => return entry,
+        },
+        None => return entry,
+    };
+
+    if let Some(rest) = rest.strip_prefix("1 ") {
+        // RFC 5424: TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+        let (timestamp, rest) = next_token(rest);
+        let (hostname, rest) = next_token(rest);
+        let (app_name, rest) = next_token(rest);
+        let (_proc_id, rest) = next_token(rest);
+        let (msg_id, rest) = next_token(rest);
+        let message = skip_structured_data(rest);
+
+        if let Ok(ts) = DateTime::parse_from_rfc3339(timestamp) {
+            entry.timestamp = ts.with_timezone(&Utc);
+        }
+        entry.host = nil(hostname);
+        entry.application = nil(app_name);
+        entry.event_type = nil(msg_id)
+            .or_else(|| entry.application.clone())
+            .unwrap_or_else(|| "syslog".to_string());
+        entry.message = message.trim_start_matches('\u{feff}').to_string();
+    } else {
+        // RFC 3164: "Mmm dd hh:mm:ss HOSTNAME TAG: MSG"
+        let stamp: String = rest.chars().take(15).collect();
+        let with_year = format!("{} {}", Utc::now().year(), stamp);
+        let rest = match NaiveDateTime::parse_from_str(&with_year, "%Y %b %e %H:%M:%S") {
+            Ok(ts) => {
+                entry.timestamp = ts.and_utc();
+                rest.get(15..).unwrap_or("").trim_start()
+            },
+            Err(_) => rest,
+        };
+
+        let (hostname, rest) = next_token(rest);
+        entry.host = nil(hostname);
+
+        match rest.split_once(": ") {
+            Some((tag, message)) if !tag.contains(' ') => {
+                let app = tag.split('[').next().unwrap_or(tag);
+                entry.application = nil(app);
+                entry.event_type = app.to_string();
+                entry.message = message.to_string();
+            },
+            _ => entry.message = rest.to_string(),
+        }
+    }
+
+    entry
+}
+
+#[derive(Debug)]
+enum FrameError {
+    Closed,
+    Malformed(String),
+    Io(std::io::Error),
+}
+
+// Reads one RFC 5425 octet-counted frame: MSG-LEN SP SYSLOG-MSG
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max_len: usize) -> std::result::Result<Vec<u8>, FrameError> {
+    let mut len: usize = 0;
+    let mut digits = 0;
+
+    loop {
+        let byte = match reader.read_u8().await {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && digits == 0 => return Err(FrameError::Closed),
+            Err(e) => return Err(FrameError::Io(e)),
+        };
+
+        match byte {
+            b' ' if digits > 0 => break,
+            b'0'..=b'9' => {
+                if digits == 0 && byte == b'0' {
+                    return Err(FrameError::Malformed("frame length has a leading zero".to_string()));
+                }
+                digits += 1;
+                len = len.saturating_mul(10).saturating_add((byte - b'0') as usize);
+                if len > max_len {
+                    return Err(FrameError::Malformed(format!("frame length exceeds {} bytes", max_len)));
+                }
+            },
+            other => return Err(FrameError::Malformed(format!("unexpected byte 0x{:02x} in frame length", other))),
+        }
+    }
+
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await.map_err(FrameError::Io)?;
+    Ok(frame)
+}
+
+// Token bucket limiting the events a single connection may submit
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        Self {
+            rate: per_second.max(1) as f64,
+            tokens: per_second.max(1) as f64,
+            last: Instant::now(),
+        }
+    }
+
+    // Waits for a token; reading stops meanwhile, so TCP pushes back on the sender
+    async fn acquire(&mut self) {
+        loop {
+            let now = Instant::now();
+            self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+            self.last = now;
+
+            if self.tokens >= 1.0 {
+                self.tokens -= 1.0;
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)).await;
+        }
+    }
+}
+
+#[derive(Default)]
+struct ListenerStats {
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+    rejected_connections: AtomicU64,
+    dropped_connections: AtomicU64,
+    events_total: AtomicU64,
+}
+
+fn load_tls(config: &SyslogTlsConfig, server_tls: Option<&TlsConfig>) -> Result<rustls::ServerConfig> {
+    let (cert_path, key_path) = match (&config.cert_path, &config.key_path, server_tls) {
+        (Some(cert), Some(key), _) => (cert.clone(), key.clone()),
+        (None, None, Some(tls)) => (tls.cert_path.clone(), tls.key_path.clone()),
+        _ => return Err(anyhow!("Syslog TLS needs cert_path and key_path, or a server [tls] certificate")),
+    };
+
+    let certs = CertificateDer::pem_file_iter(&cert_path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("Failed to read certificate {}: {}", cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(&key_path)
+        .map_err(|e| anyhow!("Failed to read private key {}: {}", key_path, e))?;
+
+    let builder = rustls::ServerConfig::builder();
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .map_err(|e| anyhow!("Failed to read client CA {}: {}", ca_path, e))? {
+                roots.add(cert.map_err(|e| anyhow!("Invalid client CA {}: {}", ca_path, e))?)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+
+    builder.with_single_cert(certs, key).context("Invalid syslog TLS certificate or key")
+}
+
+fn common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let cn = parsed.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|s| s.to_string())
+}
+
+// RFC 5425 syslog over TLS listener feeding the ingestion pipeline
+#[derive(Clone)]
+pub struct SyslogTlsListener {
+    config: SyslogTlsConfig,
+    stats: Arc<ListenerStats>,
+    // Events counted at the last metrics scrape, for the events/sec gauge
+    rate_window: Arc<Mutex<(Instant, u64)>>,
+}
+
+impl SyslogTlsListener {
+    pub fn new(config: SyslogTlsConfig) -> Self {
+        Self {
+            config,
+            stats: Arc::new(ListenerStats::default()),
+            rate_window: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    pub async fn start(&self, server_tls: Option<&TlsConfig>, pipeline: IngestionPipeline) -> Result<()> {
+        let acceptor = TlsAcceptor::from(Arc::new(load_tls(&self.config, server_tls)?));
+        let listener = TcpListener::bind(&self.config.bind_address).await
+            .context(format!("Failed to bind syslog TLS listener on {}", self.config.bind_address))?;
+        let slots = Arc::new(Semaphore::new(self.config.max_connections));
+
+        info!("Syslog TLS listener on {}", self.config.bind_address);
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Syslog TLS accept failed: {}", e);
+                        continue;
+                    },
+                };
+
+                let permit = match slots.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        this.stats.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                        warn!("Rejecting syslog TLS connection from {}: connection limit reached", peer);
+                        continue;
+                    },
+                };
+
+                let acceptor = acceptor.clone();
+                let pipeline = pipeline.clone();
+                let this = this.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    this.stats.total_connections.fetch_add(1, Ordering::Relaxed);
+                    this.stats.active_connections.fetch_add(1, Ordering::Relaxed);
+
+                    if let Err(reason) = this.handle_connection(acceptor, stream, &pipeline).await {
+                        this.stats.dropped_connections.fetch_add(1, Ordering::Relaxed);
+                        warn!("Dropped syslog TLS connection from {}: {}", peer, reason);
+                    }
+
+                    this.stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn handle_connection(&self,
+                               acceptor: TlsAcceptor,
+                               stream: tokio::net::TcpStream,
+                               pipeline: &IngestionPipeline) -> std::result::Result<(), String> {
+        let idle = Duration::from_secs(self.config.idle_timeout_seconds.max(1));
+
+        let tls = tokio::time::timeout(idle, acceptor.accept(stream)).await
+            .map_err(|_| "TLS handshake timed out".to_string())?
+            .map_err(|e| format!("TLS handshake failed: {}", e))?;
+
+        // Client certificate CN becomes a source tag, optionally renamed by the config
+        let source_tag = tls.get_ref().1.peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(common_name)
+            .map(|cn| self.config.cn_sources.get(&cn).cloned().unwrap_or(cn));
+
+        let mut reader = BufReader::new(tls);
+        let mut limiter = RateLimiter::new(self.config.max_events_per_second);
+
+        loop {
+            let frame = match tokio::time::timeout(idle, read_frame(&mut reader, self.config.max_message_bytes)).await {
+                Err(_) => return Err("idle timeout".to_string()),
+                Ok(Err(FrameError::Closed)) => return Ok(()),
+                Ok(Err(FrameError::Malformed(reason))) => return Err(format!("malformed framing: {}", reason)),
+                Ok(Err(FrameError::Io(e))) => return Err(format!("read error: {}", e)),
+                Ok(Ok(frame)) => frame,
+            };
+
+            limiter.acquire().await;
+
+            let mut entry = parse_syslog(String::from_utf8_lossy(&frame).trim_end());
+            entry.source = "syslog-tls".to_string();
+            if let Some(tag) = &source_tag {
+                entry.tags.push(format!("source:{}", tag));
+            }
+
+            if let Err(e) = pipeline.ingest(entry) {
+                error!("Failed to ingest syslog TLS event: {}", e);
+            }
+            self.stats.events_total.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Prometheus text exposition of the listener status
+    pub fn render_metrics(&self) -> String {
+        let events = self.stats.events_total.load(Ordering::Relaxed);
+        let rate = match self.rate_window.lock() {
+            Ok(mut window) => {
+                let elapsed = window.0.elapsed().as_secs_f64();
+                let rate = if elapsed > 0.0 { events.saturating_sub(window.1) as f64 / elapsed } else { 0.0 };
+                *window = (Instant::now(), events);
+                rate
+            },
+            Err(_) => 0.0,
+        };
+
+        let mut out = String::new();
+        out.push_str("# HELP siem_syslog_tls_active_connections Open syslog TLS connections\n");
+        out.push_str("# TYPE siem_syslog_tls_active_connections gauge\n");
+        out.push_str(&format!("siem_syslog_tls_active_connections {}\n", self.stats.active_connections.load(Ordering::Relaxed)));
+        out.push_str("# HELP siem_syslog_tls_connections_total Accepted syslog TLS connections\n");
+        out.push_str("# TYPE siem_syslog_tls_connections_total counter\n");
+        out.push_str(&format!("siem_syslog_tls_connections_total {}\n", self.stats.total_connections.load(Ordering::Relaxed)));
+        out.push_str("# HELP siem_syslog_tls_rejected_connections_total Connections refused at the connection limit\n");
+        out.push_str("# TYPE siem_syslog_tls_rejected_connections_total counter\n");
+        out.push_str(&format!("siem_syslog_tls_rejected_connections_total {}\n", self.stats.rejected_connections.load(Ordering::Relaxed)));
+        out.push_str("# HELP siem_syslog_tls_dropped_connections_total Connections dropped for bad framing, TLS errors or idleness\n");
+        out.push_str("# TYPE siem_syslog_tls_dropped_connections_total counter\n");
+        out.push_str(&format!("siem_syslog_tls_dropped_connections_total {}\n", self.stats.dropped_connections.load(Ordering::Relaxed)));
+        out.push_str("# HELP siem_syslog_tls_events_total Events received over syslog TLS\n");
+        out.push_str("# TYPE siem_syslog_tls_events_total counter\n");
+        out.push_str(&format!("siem_syslog_tls_events_total {}\n", events));
+        out.push_str("# HELP siem_syslog_tls_events_per_second Event rate since the previous scrape\n");
+        out.push_str("# TYPE siem_syslog_tls_events_per_second gauge\n");
+        out.push_str(&format!("siem_syslog_tls_events_per_second {:.2}\n", rate));
+        out
+    }
+}
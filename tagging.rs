@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, TimeZone, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error};
+
+use crate::logs::LogsManager;
+use crate::models::{EventCategory, LogEntry, LogSeverity};
+use crate::tags::{self, TaggedStore};
+
+// Stored entries a backfill tags per lock of the log store
+const BACKFILL_BATCH: usize = 1000;
+
+// Upper bound on the compiled program size, as for extraction patterns
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+// Adds tags to entries matching all of its conditions; unset conditions match anything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaggingRule {
+    pub id: Uuid,
+    pub name: String,
+    pub order: u32,
+    pub enabled: bool,
+    pub source: Option<String>,
+    pub host: Option<String>,
+    pub min_severity: Option<LogSeverity>,
+    pub category: Option<EventCategory>,
+    pub message_pattern: Option<String>,
+    pub tags: Vec<String>,
+    // Entries tagged at ingestion, backfills not counted; a rule that stays at 0 is dead
+    #[serde(default)]
+    pub hits: u64,
+    #[serde(default)]
+    pub last_hit_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Body of POST and PUT /api/logs/tagging-rules
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaggingRuleSpec {
+    pub name: String,
+    #[serde(default)]
+    pub order: u32,
+    pub enabled: Option<bool>,
+    pub source: Option<String>,
+    pub host: Option<String>,
+    pub min_severity: Option<LogSeverity>,
+    pub category: Option<EventCategory>,
+    pub message_pattern: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl TaggingRuleSpec {
+    fn validate(&self) -> Result<Option<Regex>> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Rule name must not be empty"));
+        }
+        if self.tags.is_empty() || self.tags.iter().any(|t| t.trim().is_empty()) {
+            return Err(anyhow!("At least one non-empty tag is required"));
+        }
+        if self.source.is_none() && self.host.is_none() && self.min_severity.is_none()
+            && self.category.is_none() && self.message_pattern.is_none() {
+            return Err(anyhow!("A rule without conditions would tag every entry"));
+        }
+        self.message_pattern.as_deref().map(compile_pattern).transpose()
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .size_limit(MAX_COMPILED_SIZE)
+        .dfa_size_limit(MAX_COMPILED_SIZE)
+        .build()
+        .context("Invalid message pattern")
+}
+
+#[derive(Default)]
+struct RuleCounters {
+    hits: AtomicU64,
+    // Unix seconds, 0 for never
+    last_hit: AtomicI64,
+}
+
+struct CompiledRule {
+    rule: TaggingRule,
+    regex: Option<Regex>,
+    counters: Arc<RuleCounters>,
+}
+
+impl CompiledRule {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        let rule = &self.rule;
+        rule.source.as_ref().map_or(true, |source| &entry.source == source)
+            && rule.host.as_ref().map_or(true, |host| entry.host.as_ref() == Some(host))
+            && rule.min_severity.as_ref().map_or(true, |min| entry.severity >= *min)
+            && rule.category.as_ref().map_or(true, |category| &entry.category == category)
+            && self.regex.as_ref().map_or(true, |regex| regex.is_match(&entry.message))
+    }
+
+    // Returns whether a tag was added
+    fn tag(&self, entry: &mut LogEntry) -> bool {
+        let mut added = false;
+        for tag in &self.rule.tags {
+            if !entry.tags.contains(tag) {
+                entry.tags.push(tag.clone());
+                added = true;
+            }
+        }
+        added
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum BackfillStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+// Applying one rule to the entries already stored
+#[derive(Debug, Clone, Serialize)]
+pub struct BackfillJob {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub requested_by: String,
+    pub started_at: DateTime<Utc>,
+    pub status: BackfillStatus,
+    pub total: usize,
+    pub processed: usize,
+    // Entries that got at least one new tag
+    pub tagged: usize,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+// Tagging rules evaluated at ingestion after extraction and classification. Matching
+// uses a compiled snapshot of the enabled rules that is rebuilt on every change, so the
+// ingestion path takes only a read lock.
+#[derive(Clone)]
+pub struct TaggingManager {
+    rules_dir: PathBuf,
+    rules: Arc<Mutex<HashMap<Uuid, TaggingRule>>>,
+    compiled: Arc<RwLock<Arc<Vec<CompiledRule>>>>,
+    counters: Arc<Mutex<HashMap<Uuid, Arc<RuleCounters>>>>,
+    logs: LogsManager,
+    backfills: Arc<Mutex<HashMap<Uuid, BackfillJob>>>,
+}
+
+impl TaggingManager {
+    pub fn new(rules_dir: &str, logs: LogsManager) -> Result<Self> {
+        let rules_dir = PathBuf::from(rules_dir);
+
+        if !rules_dir.exists() {
+            fs::create_dir_all(&rules_dir)
+                .context(format!("Failed to create tagging rules directory: {:?}", rules_dir))?;
+            info!("Created tagging rules directory: {:?}", rules_dir);
+        }
+
+        let mut rules = HashMap::new();
+
+        for entry in fs::read_dir(&rules_dir)? {
+            let path = entry?.path();
+
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<TaggingRule>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(rule) => {
+                        rules.insert(rule.id, rule);
+                    },
+                    Err(e) => {
+                        error!("Failed to load tagging rule {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} tagging rules", rules.len());
+
+        let manager = Self {
+            rules_dir,
+            rules: Arc::new(Mutex::new(rules)),
+            compiled: Arc::new(RwLock::new(Arc::new(Vec::new()))),
+            counters: Arc::new(Mutex::new(HashMap::new())),
+            logs,
+            backfills: Arc::new(Mutex::new(HashMap::new())),
+        };
+        manager.rebuild()?;
+        Ok(manager)
+    }
+
+    fn save_rule(&self, rule: &TaggingRule) -> Result<()> {
+        let file_path = self.rules_dir.join(format!("{}.json", rule.id));
+        let json = serde_json::to_string_pretty(rule)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    fn counters_for(&self, rule: &TaggingRule) -> Result<Arc<RuleCounters>> {
+        match self.counters.lock() {
+            Ok(mut counters) => Ok(counters.entry(rule.id)
+                .or_insert_with(|| Arc::new(RuleCounters {
+                    hits: AtomicU64::new(rule.hits),
+                    last_hit: AtomicI64::new(rule.last_hit_at.map_or(0, |at| at.timestamp())),
+                }))
+                .clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on tagging rule counters")),
+        }
+    }
+
+    // Recompiles the enabled rules in evaluation order; a rule whose pattern no longer
+    // compiles is left out
+    fn rebuild(&self) -> Result<()> {
+        let mut rules = self.get_all_rules()?;
+        rules.retain(|r| r.enabled);
+
+        let mut compiled = Vec::new();
+        for rule in rules {
+            let regex = match rule.message_pattern.as_deref().map(compile_pattern).transpose() {
+                Ok(regex) => regex,
+                Err(e) => {
+                    error!("Failed to compile tagging rule {} ({}): {}", rule.name, rule.id, e);
+                    continue;
+                },
+            };
+            compiled.push(CompiledRule {
+                counters: self.counters_for(&rule)?,
+                rule,
+                regex,
+            });
+        }
+
+        match self.compiled.write() {
+            Ok(mut current) => {
+                *current = Arc::new(compiled);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on compiled tagging rules")),
+        }
+    }
+
+    pub fn create_rule(&self, spec: TaggingRuleSpec) -> Result<TaggingRule> {
+        spec.validate()?;
+        let now = Utc::now();
+
+        let rule = TaggingRule {
+            id: Uuid::new_v4(),
+            name: spec.name,
+            order: spec.order,
+            enabled: spec.enabled.unwrap_or(true),
+            source: spec.source,
+            host: spec.host,
+            min_severity: spec.min_severity,
+            category: spec.category,
+            message_pattern: spec.message_pattern,
+            tags: tags::normalize_all(&spec.tags),
+            hits: 0,
+            last_hit_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.save_rule(&rule)?;
+
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                rules.insert(rule.id, rule.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        }
+
+        self.rebuild()?;
+        Ok(rule)
+    }
+
+    pub fn update_rule(&self, id: Uuid, spec: TaggingRuleSpec) -> Result<TaggingRule> {
+        spec.validate()?;
+
+        let updated = match self.rules.lock() {
+            Ok(mut rules) => {
+                let rule = rules.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Tagging rule not found: {}", id))?;
+
+                rule.name = spec.name;
+                rule.order = spec.order;
+                if let Some(enabled) = spec.enabled {
+                    rule.enabled = enabled;
+                }
+                rule.source = spec.source;
+                rule.host = spec.host;
+                rule.min_severity = spec.min_severity;
+                rule.category = spec.category;
+                rule.message_pattern = spec.message_pattern;
+                rule.tags = tags::normalize_all(&spec.tags);
+                rule.updated_at = Utc::now();
+                rule.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        };
+
+        self.save_rule(&updated)?;
+        self.rebuild()?;
+        Ok(updated)
+    }
+
+    pub fn delete_rule(&self, id: Uuid) -> Result<()> {
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                if rules.remove(&id).is_none() {
+                    return Err(anyhow!("Tagging rule not found: {}", id));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        }
+
+        if let Ok(mut counters) = self.counters.lock() {
+            counters.remove(&id);
+        }
+
+        let file_path = self.rules_dir.join(format!("{}.json", id));
+        fs::remove_file(file_path)?;
+
+        self.rebuild()
+    }
+
+    // With the live hit counters
+    fn with_counters(&self, mut rule: TaggingRule) -> TaggingRule {
+        if let Ok(counters) = self.counters.lock() {
+            if let Some(counter) = counters.get(&rule.id) {
+                rule.hits = counter.hits.load(Ordering::Relaxed);
+                let last_hit = counter.last_hit.load(Ordering::Relaxed);
+                if last_hit > 0 {
+                    rule.last_hit_at = Utc.timestamp_opt(last_hit, 0).single();
+                }
+            }
+        }
+        rule
+    }
+
+    pub fn get_rule(&self, id: Uuid) -> Result<TaggingRule> {
+        let rule = match self.rules.lock() {
+            Ok(rules) => rules.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Tagging rule not found: {}", id))?,
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        };
+        Ok(self.with_counters(rule))
+    }
+
+    // Returns all rules in evaluation order
+    pub fn get_all_rules(&self) -> Result<Vec<TaggingRule>> {
+        let mut all: Vec<TaggingRule> = match self.rules.lock() {
+            Ok(rules) => rules.values().cloned().collect(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        };
+        all.sort_by(|a, b| a.order.cmp(&b.order).then(a.created_at.cmp(&b.created_at)));
+        Ok(all.into_iter().map(|rule| self.with_counters(rule)).collect())
+    }
+
+    // Adds the tags of every enabled, matching rule to the entry
+    pub fn apply(&self, entry: &mut LogEntry) -> Result<()> {
+        self.apply_rules(entry, true)
+    }
+
+    // As apply, for entries parsed again: like backfills they do not count as hits
+    pub fn apply_uncounted(&self, entry: &mut LogEntry) -> Result<()> {
+        self.apply_rules(entry, false)
+    }
+
+    fn apply_rules(&self, entry: &mut LogEntry, count: bool) -> Result<()> {
+        let compiled = match self.compiled.read() {
+            Ok(compiled) => compiled.clone(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on compiled tagging rules")),
+        };
+
+        for rule in compiled.iter() {
+            if !rule.matches(entry) {
+                continue;
+            }
+            rule.tag(entry);
+            if count {
+                rule.counters.hits.fetch_add(1, Ordering::Relaxed);
+                rule.counters.last_hit.store(Utc::now().timestamp(), Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the hit counters to the rule files; they are only kept in memory in between
+    pub fn flush_hits(&self) -> Result<()> {
+        let changed: Vec<TaggingRule> = match self.rules.lock() {
+            Ok(mut rules) => rules.values_mut()
+                .filter_map(|rule| {
+                    let counted = self.with_counters(rule.clone());
+                    if counted.hits == rule.hits {
+                        return None;
+                    }
+                    rule.hits = counted.hits;
+                    rule.last_hit_at = counted.last_hit_at;
+                    Some(rule.clone())
+                })
+                .collect(),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        };
+
+        for rule in changed {
+            self.save_rule(&rule)?;
+        }
+        Ok(())
+    }
+
+    fn update_backfill<F: FnOnce(&mut BackfillJob)>(&self, id: Uuid, update: F) {
+        if let Ok(mut jobs) = self.backfills.lock() {
+            if let Some(job) = jobs.get_mut(&id) {
+                update(job);
+            }
+        }
+    }
+
+    // Applies one rule, enabled or not, to the stored entries in batches so ingestion
+    // is not blocked for long
+    pub fn start_backfill(&self, rule_id: Uuid, requested_by: &str) -> Result<BackfillJob> {
+        let rule = self.get_rule(rule_id)?;
+        let compiled = CompiledRule {
+            regex: rule.message_pattern.as_deref().map(compile_pattern).transpose()?,
+            counters: Arc::new(RuleCounters::default()),
+            rule,
+        };
+        let ids = self.logs.ids()?;
+
+        let job = BackfillJob {
+            id: Uuid::new_v4(),
+            rule_id,
+            requested_by: requested_by.to_string(),
+            started_at: Utc::now(),
+            status: BackfillStatus::Running,
+            total: ids.len(),
+            processed: 0,
+            tagged: 0,
+            finished_at: None,
+            error: None,
+        };
+
+        match self.backfills.lock() {
+            Ok(mut jobs) => {
+                jobs.insert(job.id, job.clone());
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging backfills")),
+        }
+
+        info!("Backfilling tagging rule {} over {} stored entries", compiled.rule.name, ids.len());
+        let manager = self.clone();
+        let job_id = job.id;
+        tokio::spawn(async move {
+            for batch in ids.chunks(BACKFILL_BATCH) {
+                match manager.logs.update_many(batch, |entry| compiled.matches(entry) && compiled.tag(entry)) {
+                    Ok(tagged) => manager.update_backfill(job_id, |job| {
+                        job.processed += batch.len();
+                        job.tagged += tagged;
+                    }),
+                    Err(e) => {
+                        error!("Tagging backfill {} failed: {}", job_id, e);
+                        manager.update_backfill(job_id, |job| {
+                            job.status = BackfillStatus::Failed;
+                            job.error = Some(e.to_string());
+                            job.finished_at = Some(Utc::now());
+                        });
+                        return;
+                    },
+                }
+                tokio::task::yield_now().await;
+            }
+
+            manager.update_backfill(job_id, |job| {
+                job.status = BackfillStatus::Completed;
+                job.finished_at = Some(Utc::now());
+                info!("Tagging backfill {} tagged {} of {} entries", job.id, job.tagged, job.total);
+            });
+        });
+
+        Ok(job)
+    }
+
+    pub fn get_backfill(&self, id: Uuid) -> Result<BackfillJob> {
+        match self.backfills.lock() {
+            Ok(jobs) => jobs.get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Tagging backfill not found: {}", id)),
+            Err(_) => Err(anyhow!("Failed to acquire lock on tagging backfills")),
+        }
+    }
+}
+
+impl TaggedStore for TaggingManager {
+    fn resource(&self) -> &'static str {
+        "tagging_rules"
+    }
+
+    fn tag_counts(&self) -> Result<HashMap<String, usize>> {
+        Ok(tags::count(self.get_all_rules()?.iter().map(|r| &r.tags)))
+    }
+
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        let mut previous: Vec<(Uuid, Vec<String>)> = Vec::new();
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                let ids: Vec<Uuid> = rules.keys().copied().collect();
+                for id in ids {
+                    let Some(rule) = rules.get_mut(&id) else { continue };
+                    let Some(tags) = tags::replaced(&rule.tags, from, to) else { continue };
+                    let old = std::mem::replace(&mut rule.tags, tags);
+                    if let Err(e) = self.save_rule(rule) {
+                        rule.tags = old;
+                        for (id, tags) in &previous {
+                            if let Some(rule) = rules.get_mut(id) {
+                                rule.tags = tags.clone();
+                                if let Err(e) = self.save_rule(rule) {
+                                    error!("Failed to restore the tags of tagging rule {}: {}", id, e);
+                                }
+                            }
+                        }
+                        return Err(e);
+                    }
+                    previous.push((id, old));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        }
+
+        self.rebuild()?;
+        Ok(previous)
+    }
+
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                for (id, tags) in previous {
+                    if let Some(rule) = rules.get_mut(id) {
+                        rule.tags = tags.clone();
+                        self.save_rule(rule)?;
+                    }
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on tagging rules")),
+        }
+        self.rebuild()
+    }
+}
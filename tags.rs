@@ -0,0 +1,425 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error};
+
+// Longest tag accepted after normalization
+const MAX_TAG_CHARS: usize = 64;
+
+// Tags compare case-insensitively with surrounding whitespace ignored; this is the form
+// they are stored in. None for a blank tag.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+// Normalized, without blanks and duplicates, in their original order
+pub fn normalize_all<I, S>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.into_iter().filter_map(|t| normalize(t.as_ref())) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+// Whether the tags include `tag`, compared as normalized
+pub fn has_tag(tags: &[String], tag: &str) -> bool {
+    let Some(tag) = normalize(tag) else { return false };
+    tags.iter().any(|t| normalize(t).as_deref() == Some(tag.as_str()))
+}
+
+// The tags with every tag of `from` replaced by `to`, or None when they have none of them
+pub fn replaced(tags: &[String], from: &[String], to: &str) -> Option<Vec<String>> {
+    let replace = |tag: &String| from.iter().any(|f| has_tag(std::slice::from_ref(tag), f));
+    if !tags.iter().any(replace) {
+        return None;
+    }
+    Some(normalize_all(tags.iter().map(|t| if replace(t) { to } else { t.as_str() })))
+}
+
+// Which tags a resource must carry: every tag of `all`, at least one of `any` when it is
+// not empty, and none of `none`. An empty selector matches everything. A plain list of
+// tags is read as `all`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(from = "SelectorFields")]
+pub struct TagSelector {
+    pub all: Vec<String>,
+    pub any: Vec<String>,
+    pub none: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SelectorFields {
+    All(Vec<String>),
+    Fields {
+        #[serde(default)]
+        all: Vec<String>,
+        #[serde(default)]
+        any: Vec<String>,
+        #[serde(default)]
+        none: Vec<String>,
+    },
+}
+
+impl From<SelectorFields> for TagSelector {
+    fn from(fields: SelectorFields) -> Self {
+        let (all, any, none) = match fields {
+            SelectorFields::All(all) => (all, Vec::new(), Vec::new()),
+            SelectorFields::Fields { all, any, none } => (all, any, none),
+        };
+        Self {
+            all: normalize_all(all),
+            any: normalize_all(any),
+            none: normalize_all(none),
+        }
+    }
+}
+
+impl TagSelector {
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty() && self.any.is_empty() && self.none.is_empty()
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.all.iter().all(|tag| has_tag(tags, tag))
+            && (self.any.is_empty() || self.any.iter().any(|tag| has_tag(tags, tag)))
+            && !self.none.iter().any(|tag| has_tag(tags, tag))
+    }
+}
+
+// A store of tagged resources: counted by GET /api/tags and rewritten by renames and merges
+pub trait TaggedStore: Send + Sync {
+    // Resource type in the usage counts, e.g. "tickets"
+    fn resource(&self) -> &'static str;
+
+    // Whether its tags are listed by GET /api/tags. Log tags are mostly generated
+    // `key:value` pairs, so logs are only counted for tags known from elsewhere.
+    fn lists_tags(&self) -> bool {
+        true
+    }
+
+    // Resources carrying each tag, by normalized tag
+    fn tag_counts(&self) -> Result<HashMap<String, usize>>;
+
+    // Replaces the tags of `from` by `to` everywhere; returns the previous tags of every
+    // changed resource. On failure the store is left as it was.
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>>;
+
+    // Puts back tags returned by retag
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()>;
+}
+
+// Counts tags over resources, by normalized tag
+pub fn count<'a, I>(tag_lists: I) -> HashMap<String, usize>
+where
+    I: IntoIterator<Item = &'a Vec<String>>,
+{
+    let mut counts = HashMap::new();
+    for tags in tag_lists {
+        for tag in normalize_all(tags) {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagDefinition {
+    pub name: String,
+    // `#rrggbb`
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagUsage {
+    pub name: String,
+    // Defined in the registry rather than only found on resources
+    pub registered: bool,
+    pub color: Option<String>,
+    pub description: Option<String>,
+    // Resources carrying the tag, by resource type
+    pub usage: BTreeMap<&'static str, usize>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RetagReport {
+    pub from: Vec<String>,
+    pub to: String,
+    // Changed resources by resource type
+    pub changed: BTreeMap<&'static str, usize>,
+}
+
+fn validate_color(color: &str) -> Result<()> {
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Color must be of the form #rrggbb: {}", color));
+    }
+    Ok(())
+}
+
+fn validate_tag(tag: &str) -> Result<String> {
+    let tag = normalize(tag).ok_or_else(|| anyhow!("Tag must not be empty"))?;
+    if tag.chars().count() > MAX_TAG_CHARS {
+        return Err(anyhow!("Tag is longer than {} characters", MAX_TAG_CHARS));
+    }
+    Ok(tag)
+}
+
+// Known tags with their color and description, and renames and merges across every store
+// holding tags. A rename or merge runs through the stores one after the other; when one
+// fails, the stores already changed are put back, so the tag is changed everywhere or
+// nowhere.
+#[derive(Clone)]
+pub struct TagRegistry {
+    path: PathBuf,
+    tags: Arc<Mutex<BTreeMap<String, TagDefinition>>>,
+    stores: Arc<Vec<Arc<dyn TaggedStore>>>,
+    // Held by a rename or merge, so two never interleave
+    retagging: Arc<Mutex<()>>,
+}
+
+impl TagRegistry {
+    pub fn new(path: &str, stores: Vec<Arc<dyn TaggedStore>>) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let tags = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read tag registry: {:?}", path))?;
+            serde_json::from_str(&contents)
+                .context(format!("Failed to parse tag registry: {:?}", path))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            tags: Arc::new(Mutex::new(tags)),
+            stores: Arc::new(stores),
+            retagging: Arc::new(Mutex::new(())),
+        })
+    }
+
+    fn save(&self, tags: &BTreeMap<String, TagDefinition>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(tags)?)
+            .context(format!("Failed to write tag registry: {:?}", self.path))?;
+        Ok(())
+    }
+
+    // Registers the tag, or updates its color and description
+    pub fn define(&self, name: &str, color: Option<String>, description: Option<String>) -> Result<TagDefinition> {
+        let name = validate_tag(name)?;
+        if let Some(color) = &color {
+            validate_color(color)?;
+        }
+
+        let mut tags = self.tags.lock().map_err(|_| anyhow!("Failed to acquire lock on tags"))?;
+        let now = Utc::now();
+        let definition = TagDefinition {
+            name: name.clone(),
+            color,
+            description: description.filter(|d| !d.trim().is_empty()),
+            created_at: tags.get(&name).map_or(now, |existing| existing.created_at),
+            updated_at: now,
+        };
+        let previous = tags.insert(name.clone(), definition.clone());
+        if let Err(e) = self.save(&tags) {
+            match previous {
+                Some(previous) => tags.insert(name, previous),
+                None => tags.remove(&name),
+            };
+            return Err(e);
+        }
+        Ok(definition)
+    }
+
+    // Forgets the definition; resources keep the tag
+    pub fn undefine(&self, name: &str) -> Result<()> {
+        let name = normalize(name).unwrap_or_default();
+        let mut tags = self.tags.lock().map_err(|_| anyhow!("Failed to acquire lock on tags"))?;
+        let removed = tags.remove(&name).ok_or_else(|| anyhow!("Tag not registered: {}", name))?;
+        if let Err(e) = self.save(&tags) {
+            tags.insert(name, removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Registered tags and tags in use, with the number of resources of each type
+    // carrying them
+    pub fn usage(&self) -> Result<Vec<TagUsage>> {
+        let definitions = self.tags.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on tags"))?
+            .clone();
+
+        let mut usage: BTreeMap<String, TagUsage> = definitions.values()
+            .map(|d| (d.name.clone(), TagUsage {
+                name: d.name.clone(),
+                registered: true,
+                color: d.color.clone(),
+                description: d.description.clone(),
+                usage: BTreeMap::new(),
+                total: 0,
+            }))
+            .collect();
+
+        let mut counts = Vec::new();
+        for store in self.stores.iter() {
+            counts.push((store.as_ref(), store.tag_counts()?));
+        }
+        let listed = counts.iter()
+            .filter(|(store, _)| store.lists_tags())
+            .flat_map(|(_, counts)| counts.keys());
+        for tag in listed {
+            usage.entry(tag.clone()).or_insert_with(|| TagUsage {
+                name: tag.clone(),
+                registered: false,
+                color: None,
+                description: None,
+                usage: BTreeMap::new(),
+                total: 0,
+            });
+        }
+        for (store, counts) in &counts {
+            for (tag, entry) in usage.iter_mut() {
+                if let Some(count) = counts.get(tag) {
+                    entry.usage.insert(store.resource(), *count);
+                    entry.total += count;
+                }
+            }
+        }
+
+        Ok(usage.into_values().collect())
+    }
+
+    // Renames the tag everywhere; the new name must not be in use, see merge
+    pub fn rename(&self, from: &str, to: &str) -> Result<RetagReport> {
+        let from = validate_tag(from)?;
+        let to = validate_tag(to)?;
+        if from == to {
+            return Err(anyhow!("Tag is already named {}", to));
+        }
+        if self.usage()?.iter().any(|u| u.name == to) {
+            return Err(anyhow!("Tag {} already exists, merge instead", to));
+        }
+        self.retag(vec![from], to)
+    }
+
+    // Replaces the tags of `sources` by `into` everywhere
+    pub fn merge(&self, sources: &[String], into: &str) -> Result<RetagReport> {
+        let into = validate_tag(into)?;
+        let sources: Vec<String> = normalize_all(sources).into_iter().filter(|s| *s != into).collect();
+        if sources.is_empty() {
+            return Err(anyhow!("Name at least one tag other than {} to merge", into));
+        }
+        self.retag(sources, into)
+    }
+
+    fn retag(&self, from: Vec<String>, to: String) -> Result<RetagReport> {
+        let _retagging = self.retagging.lock().map_err(|_| anyhow!("Failed to acquire lock on tags"))?;
+
+        let mut done: Vec<(&Arc<dyn TaggedStore>, Vec<(Uuid, Vec<String>)>)> = Vec::new();
+        for store in self.stores.iter() {
+            match store.retag(&from, &to) {
+                Ok(previous) => done.push((store, previous)),
+                Err(e) => {
+                    for (store, previous) in done.iter().rev() {
+                        if let Err(e) = store.restore_tags(previous) {
+                            error!("Failed to restore the tags of {} after a failed retag: {}", store.resource(), e);
+                        }
+                    }
+                    return Err(e.context(format!("Failed to retag {}, nothing was changed", store.resource())));
+                },
+            }
+        }
+
+        // The target keeps its definition, or takes over the first source's
+        let mut tags = self.tags.lock().map_err(|_| anyhow!("Failed to acquire lock on tags"))?;
+        let mut inherited = None;
+        for source in &from {
+            if let Some(definition) = tags.remove(source) {
+                inherited.get_or_insert(definition);
+            }
+        }
+        if let (false, Some(definition)) = (tags.contains_key(&to), inherited) {
+            tags.insert(to.clone(), TagDefinition { name: to.clone(), updated_at: Utc::now(), ..definition });
+        }
+        if let Err(e) = self.save(&tags) {
+            error!("Tags were retagged, but the registry could not be saved: {}", e);
+        }
+
+        let changed = done.iter()
+            .map(|(store, previous)| (store.resource(), previous.len()))
+            .collect();
+        info!("Retagged {:?} as {}", from, to);
+        Ok(RetagReport { from, to, changed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn normalization_trims_lowercases_and_dedupes() {
+        assert_eq!(normalize("  Printer "), Some("printer".to_string()));
+        assert_eq!(normalize("   "), None);
+        assert_eq!(normalize_all(["VPN", "vpn ", "", "Floor-2"]), tags(&["vpn", "floor-2"]));
+    }
+
+    #[test]
+    fn selector_requires_all_any_and_none() {
+        let selector = TagSelector {
+            all: tags(&["server"]),
+            any: tags(&["prod", "staging"]),
+            none: tags(&["decommissioned"]),
+        };
+        assert!(selector.matches(&tags(&["Server", "PROD"])));
+        assert!(!selector.matches(&tags(&["server"])));
+        assert!(!selector.matches(&tags(&["prod", "staging"])));
+        assert!(!selector.matches(&tags(&["server", "prod", " Decommissioned "])));
+        assert!(TagSelector::default().matches(&[]));
+    }
+
+    #[test]
+    fn selector_reads_a_plain_list_as_all() {
+        let selector: TagSelector = serde_json::from_str(r#"["Server", "prod"]"#).unwrap();
+        assert_eq!(selector.all, tags(&["server", "prod"]));
+        assert!(selector.any.is_empty() && selector.none.is_empty());
+
+        let selector: TagSelector = serde_json::from_str(r#"{"any": ["VPN"]}"#).unwrap();
+        assert_eq!(selector.any, tags(&["vpn"]));
+    }
+
+    #[test]
+    fn replaced_merges_into_one_tag() {
+        assert_eq!(replaced(&tags(&["wifi", "Floor-2", "wlan"]), &tags(&["wlan", "wifi"]), "wireless"),
+                   Some(tags(&["wireless", "floor-2"])));
+        assert_eq!(replaced(&tags(&["floor-2"]), &tags(&["wifi"]), "wireless"), None);
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow};
+use tokio::task::AbortHandle;
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::TasksConfig;
+use crate::models::AlertSeverity;
+
+// Health of a periodic background task as reported by the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub interval_seconds: u64,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub total_runs: u64,
+    pub total_failures: u64,
+    pub next_delay_seconds: u64,
+}
+
+#[derive(Clone)]
+pub struct TaskRegistry {
+    config: TasksConfig,
+    tasks: Arc<Mutex<HashMap<String, TaskStatus>>>,
+    // Running loops, so a task can be restarted with another interval
+    handles: Arc<Mutex<HashMap<String, AbortHandle>>>,
+    alerts_manager: AlertsManager,
+}
+
+impl TaskRegistry {
+    pub fn new(config: TasksConfig, alerts_manager: AlertsManager) -> Self {
+        Self {
+            config,
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            handles: Arc::new(Mutex::new(HashMap::new())),
+            alerts_manager,
+        }
+    }
+
+    // Runs `task` every `interval`. Errors and panics count as failures and stretch the
+    // delay exponentially up to the configured maximum; a success resets it.
+    pub fn spawn<F, Fut>(&self, name: &str, interval: Duration, task: F) -> Result<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let status = TaskStatus {
+            name: name.to_string(),
+            interval_seconds: interval.as_secs(),
+            last_run: None,
+            last_success: None,
+            last_error: None,
+            consecutive_failures: 0,
+            total_runs: 0,
+            total_failures: 0,
+            next_delay_seconds: interval.as_secs(),
+        };
+
+        match self.tasks.lock() {
+            Ok(mut tasks) => {
+                if tasks.contains_key(name) {
+                    return Err(anyhow!("Background task already registered: {}", name));
+                }
+                tasks.insert(name.to_string(), status);
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on background tasks")),
+        }
+
+        info!("Registered background task {} (every {}s)", name, interval.as_secs());
+        self.run(name, interval, task)
+    }
+
+    // Stops a registered task and starts it again with a new interval; the first run is
+    // immediate and the counters carry over
+    pub fn respawn<F, Fut>(&self, name: &str, interval: Duration, task: F) -> Result<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        match self.tasks.lock() {
+            Ok(mut tasks) => {
+                let status = tasks.get_mut(name)
+                    .ok_or_else(|| anyhow!("Background task not registered: {}", name))?;
+                status.interval_seconds = interval.as_secs();
+                status.next_delay_seconds = interval.as_secs();
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on background tasks")),
+        }
+
+        info!("Restarting background task {} (every {}s)", name, interval.as_secs());
+        self.run(name, interval, task)
+    }
+
+    fn run<F, Fut>(&self, name: &str, interval: Duration, task: F) -> Result<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut handles = self.handles.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on background tasks"))?;
+        if let Some(previous) = handles.remove(name) {
+            previous.abort();
+        }
+
+        let registry = self.clone();
+        let task_name = name.to_string();
+        let handle = tokio::spawn(async move {
+            loop {
+                let outcome = match AssertUnwindSafe(task()).catch_unwind().await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow!("Task panicked")),
+                };
+
+                let delay = registry.record_run(&task_name, interval, outcome);
+                tokio::time::sleep(delay).await;
+            }
+        });
+        handles.insert(name.to_string(), handle.abort_handle());
+
+        Ok(())
+    }
+
+    // Updates the task status and returns how long to wait before the next run
+    fn record_run(&self, name: &str, interval: Duration, outcome: Result<()>) -> Duration {
+        let now = Utc::now();
+        let mut raise_alert = None;
+
+        let delay = match self.tasks.lock() {
+            Ok(mut tasks) => {
+                let status = match tasks.get_mut(name) {
+                    Some(status) => status,
+                    None => return interval,
+                };
+
+                status.last_run = Some(now);
+                status.total_runs += 1;
+
+                match outcome {
+                    Ok(()) => {
+                        if status.consecutive_failures > 0 {
+                            info!("Background task {} recovered after {} failures", name, status.consecutive_failures);
+                        }
+                        status.last_success = Some(now);
+                        status.last_error = None;
+                        status.consecutive_failures = 0;
+                    },
+                    Err(e) => {
+                        status.consecutive_failures += 1;
+                        status.total_failures += 1;
+                        status.last_error = Some(e.to_string());
+
+                        // Log the first failure and then only every tenth, the status carries the rest
+                        if status.consecutive_failures == 1 || status.consecutive_failures % 10 == 0 {
+                            warn!("Background task {} failed ({} in a row): {}", name, status.consecutive_failures, e);
+                        }
+
+                        if status.consecutive_failures == self.config.failure_alert_threshold {
+                            raise_alert = Some((status.consecutive_failures, e.to_string()));
+                        }
+                    },
+                }
+
+                let backoff = 2u32.saturating_pow(status.consecutive_failures.min(16));
+                let delay = interval
+                    .saturating_mul(backoff)
+                    .min(Duration::from_secs(self.config.max_backoff_seconds))
+                    .max(interval);
+                status.next_delay_seconds = delay.as_secs();
+                delay
+            },
+            Err(_) => {
+                error!("Failed to acquire lock on background tasks");
+                interval
+            },
+        };
+
+        if let Some((failures, message)) = raise_alert {
+            if let Err(e) = self.alerts_manager.create_alert(
+                AlertSeverity::High,
+                format!("Background task {} is failing", name),
+                format!("{} consecutive failures, last error: {}", failures, message),
+                "tasks".to_string(),
+                Vec::new(),
+            ) {
+                error!("Failed to raise alert for background task {}: {}", name, e);
+            }
+        }
+
+        delay
+    }
+
+    pub fn get_all_tasks(&self) -> Result<Vec<TaskStatus>> {
+        match self.tasks.lock() {
+            Ok(tasks) => {
+                let mut all: Vec<TaskStatus> = tasks.values().cloned().collect();
+                all.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on background tasks")),
+        }
+    }
+
+    // Prometheus text exposition of the task health
+    pub fn render_metrics(&self) -> Result<String> {
+        let tasks = self.get_all_tasks()?;
+        let mut out = String::new();
+
+        out.push_str("# HELP siem_task_runs_total Total runs of a background task\n");
+        out.push_str("# TYPE siem_task_runs_total counter\n");
+        for task in &tasks {
+            out.push_str(&format!("siem_task_runs_total{{task=\"{}\"}} {}\n", task.name, task.total_runs));
+        }
+
+        out.push_str("# HELP siem_task_failures_total Total failed runs of a background task\n");
+        out.push_str("# TYPE siem_task_failures_total counter\n");
+        for task in &tasks {
+            out.push_str(&format!("siem_task_failures_total{{task=\"{}\"}} {}\n", task.name, task.total_failures));
+        }
+
+        out.push_str("# HELP siem_task_consecutive_failures Current run of consecutive failures\n");
+        out.push_str("# TYPE siem_task_consecutive_failures gauge\n");
+        for task in &tasks {
+            out.push_str(&format!("siem_task_consecutive_failures{{task=\"{}\"}} {}\n", task.name, task.consecutive_failures));
+        }
+
+        out.push_str("# HELP siem_task_last_success_timestamp_seconds Unix time of the last successful run\n");
+        out.push_str("# TYPE siem_task_last_success_timestamp_seconds gauge\n");
+        for task in &tasks {
+            let ts = task.last_success.map(|t| t.timestamp()).unwrap_or(0);
+            out.push_str(&format!("siem_task_last_success_timestamp_seconds{{task=\"{}\"}} {}\n", task.name, ts));
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,756 @@
+use std::net::SocketAddr;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use sqlx::{Connection, Executor, PgConnection};
+use tempfile::TempDir;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::config;
+use crate::models::UserRole;
+use crate::password_policy::PasswordPolicy;
+use crate::startup::Startup;
+use crate::users::UserManager;
+
+// Largest response body the helpers read
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+pub const ADMIN_USERNAME: &str = "admin";
+pub const ADMIN_PASSWORD: &str = "Harness-Pass-2048";
+
+// Server URL for tests that also exercise the database. Each TestApp gets a database of
+// its own on that server, dropped with it; without the variable no database is used.
+pub const DATABASE_URL_VAR: &str = "SIEM_TEST_DATABASE_URL";
+
+// Database created for one TestApp
+struct TestDatabase {
+    server_url: String,
+    name: String,
+}
+
+impl TestDatabase {
+    async fn create(server_url: &str) -> anyhow::Result<(Self, String)> {
+        let name = format!("siem_test_{}", Uuid::new_v4().simple());
+        let mut connection = PgConnection::connect(server_url).await?;
+        connection.execute(format!("CREATE DATABASE {}", name).as_str()).await?;
+
+        // Same server and options, other database
+        let (base, options) = match server_url.split_once('?') {
+            Some((base, options)) => (base, format!("?{}", options)),
+            None => (server_url, String::new()),
+        };
+        let server = base.rsplit_once('/').map_or(base, |(server, _)| server);
+        let url = format!("{}/{}{}", server, name, options);
+
+        Ok((Self { server_url: server_url.to_string(), name }, url))
+    }
+}
+
+impl Drop for TestDatabase {
+    // The test runtime may be shutting down, so the drop runs on a runtime of its own
+    fn drop(&mut self) {
+        let (server_url, name) = (self.server_url.clone(), self.name.clone());
+        let result = std::thread::spawn(move || -> anyhow::Result<()> {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(async {
+                let mut connection = PgConnection::connect(&server_url).await?;
+                connection.execute(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name).as_str()).await?;
+                Ok(())
+            })
+        }).join();
+        if !matches!(result, Ok(Ok(()))) {
+            eprintln!("Failed to drop test database {}", self.name);
+        }
+    }
+}
+
+// The full application in process: every route and middleware as served by main, over
+// data, script and log directories in a temporary directory, with an admin account
+// already logged in. Requests go straight to the router, no socket is opened.
+//
+// There is no fake network backend: firewall rules, interfaces and zones live in the
+// NetworkManager's memory and are never applied to the host, so the real manager is
+// used. It only needs to open a netlink socket.
+pub struct TestApp {
+    router: Router,
+    token: String,
+    _database: Option<TestDatabase>,
+    // Removed last, after everything writing into it is gone
+    _dir: TempDir,
+}
+
+impl TestApp {
+    pub async fn spawn() -> Self {
+        Self::spawn_with(Startup::new(), |_| {}).await.expect("application")
+    }
+
+    // With the config changed before startup, and subsystems made to fail through
+    // `startup`; fails when the application does not start
+    pub async fn spawn_with<F>(startup: Startup, configure: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce(&mut config::Config),
+    {
+        let dir = tempfile::tempdir().expect("temporary directory");
+
+        let mut config = config::default_config();
+        // Relative to the config file, so inside the temporary directory
+        config.data_dir = "data".to_string();
+        config.scripts_dir = "scripts".to_string();
+        config.log_dir = "logs".to_string();
+        // Watch the host's links and kernel log
+        config.link_flap.enabled = false;
+        config.drop_log.ingest = false;
+        configure(&mut config);
+
+        let database = match std::env::var(DATABASE_URL_VAR) {
+            Ok(server_url) => {
+                let (database, url) = TestDatabase::create(&server_url).await.expect("test database");
+                config.database_url = Some(url);
+                Some(database)
+            },
+            Err(_) => None,
+        };
+
+        let config_path = dir.path().join("config.toml").display().to_string();
+        config::save(&config, &config_path).expect("test config");
+
+        // An existing user keeps the API out of setup mode and no initial admin is generated
+        UserManager::new(
+            &dir.path().join("data/users").display().to_string(),
+            PasswordPolicy::new(config.password_policy.clone()),
+        )
+            .and_then(|users| users.create_user(ADMIN_USERNAME, "admin@example.com", "Test Admin", UserRole::Admin, ADMIN_PASSWORD))
+            .expect("test admin");
+
+        let router = crate::build_app(&config_path, config.clone(), config, startup).await?;
+
+        let mut app = Self {
+            router,
+            token: String::new(),
+            _database: database,
+            _dir: dir,
+        };
+        app.token = app.login(ADMIN_USERNAME, ADMIN_PASSWORD).await;
+        Ok(app)
+    }
+
+    // Bearer token of a new session
+    pub async fn login(&self, username: &str, password: &str) -> String {
+        let (status, body) = self.send(Method::POST, "/api/auth/login", None, Some(json!({
+            "username": username,
+            "password": password,
+        }))).await;
+        assert_eq!(status, StatusCode::OK, "login of {} failed: {}", username, body);
+        body["token"].as_str().expect("token in login response").to_string()
+    }
+
+    // Creates an account with the role and returns the bearer token of a session of it
+    pub async fn login_as(&self, username: &str, role: &str) -> String {
+        let (status, user) = self.post("/api/users", json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "full_name": username,
+            "role": role,
+            "password": ADMIN_PASSWORD,
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", user);
+        self.login(username, ADMIN_PASSWORD).await
+    }
+
+    // JSON bodies come back parsed, anything else as a string
+    pub async fn send(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }.expect("request");
+
+        let response = self.router.clone().oneshot(request).await.expect("response");
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), MAX_RESPONSE_BYTES).await.expect("response body");
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        (status, body)
+    }
+
+    // As the admin
+    pub async fn request(&self, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        self.send(method, uri, Some(&self.token), body).await
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, Some(body)).await
+    }
+
+    pub async fn put(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::PUT, uri, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::DELETE, uri, None).await
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::startup::{FailurePolicy, SUBSYSTEMS};
+
+    // A route of each subsystem that has routes of its own
+    const SUBSYSTEM_PROBES: [(&str, &str); 3] = [
+        ("network", "/api/network/interfaces"),
+        ("scripts", "/api/scripts"),
+        ("scripts", "/api/alerts/remediations"),
+    ];
+
+    fn subsystem_state<'a>(health: &'a Value, name: &str) -> &'a Value {
+        &health["subsystems"].as_array().expect("subsystems in health").iter()
+            .find(|s| s["name"] == name)
+            .unwrap_or_else(|| panic!("{} not in health: {}", name, health))["state"]
+    }
+
+    #[tokio::test]
+    async fn degradable_subsystem_failures_leave_the_rest_serving() {
+        for subsystem in SUBSYSTEMS.iter().filter(|s| s.policy == FailurePolicy::Degradable) {
+            // Optional subsystems are switched on so the simulated failure is reached; it
+            // replaces their initialization, nothing is contacted
+            let app = TestApp::spawn_with(Startup::failing(&[subsystem.name]), |config| match subsystem.name {
+                "database" => {
+                    config.database_url.get_or_insert_with(|| "postgres://siem@127.0.0.1:1/siem".to_string());
+                },
+                "syslog_tls" => config.syslog_tls.enabled = true,
+                _ => {},
+            }).await.unwrap_or_else(|e| panic!("failing {} stopped startup: {:#}", subsystem.name, e));
+
+            let (status, health) = app.send(Method::GET, "/api/health", None, None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(health["status"], "degraded", "{}", health);
+            assert_eq!(*subsystem_state(&health, subsystem.name), "disabled");
+            assert_eq!(*subsystem_state(&health, "accounts"), "ready");
+            for dependent in SUBSYSTEMS.iter().filter(|s| s.depends_on.contains(&subsystem.name)) {
+                assert_eq!(*subsystem_state(&health, dependent.name), "disabled", "{} depends on {}", dependent.name, subsystem.name);
+            }
+
+            let token = app.login(ADMIN_USERNAME, ADMIN_PASSWORD).await;
+            assert!(!token.is_empty());
+
+            for (owner, uri) in SUBSYSTEM_PROBES {
+                let (status, body) = app.get(uri).await;
+                if owner == subsystem.name {
+                    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}: {}", uri, body);
+                    assert_eq!(body["subsystem"], owner);
+                    assert_eq!(body["initialization_error"], format!("Simulated failure of {}", owner));
+                } else {
+                    assert_ne!(status, StatusCode::SERVICE_UNAVAILABLE, "{} with {} failing: {}", uri, subsystem.name, body);
+                }
+            }
+
+            let (status, tickets) = app.get("/api/tickets").await;
+            assert_eq!(status, StatusCode::OK, "{}", tickets);
+            let (status, entry) = app.post("/api/logs/ingest", json!({
+                "source": "harness-degraded",
+                "message": format!("{} is disabled", subsystem.name),
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", entry);
+        }
+    }
+
+    #[tokio::test]
+    async fn required_subsystem_failure_stops_startup() {
+        for subsystem in SUBSYSTEMS.iter().filter(|s| s.policy == FailurePolicy::Required) {
+            let result = TestApp::spawn_with(Startup::failing(&[subsystem.name]), |_| {}).await;
+            assert!(result.is_err(), "started with {} failing", subsystem.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn unwritable_scripts_directory_disables_scripts_only() {
+        // A file where the directory should be
+        let app = TestApp::spawn_with(Startup::new(), |config| {
+            config.scripts_dir = "config.toml".to_string();
+        }).await.expect("application");
+
+        let (_, health) = app.send(Method::GET, "/api/health", None, None).await;
+        assert_eq!(*subsystem_state(&health, "scripts"), "disabled");
+        let (status, body) = app.get("/api/scripts").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        assert!(body["initialization_error"].as_str().expect("error").contains("scripts directory"), "{}", body);
+
+        let (status, _) = app.get("/api/tickets").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_token_are_refused() {
+        let app = TestApp::spawn().await;
+
+        let (status, _) = app.send(Method::GET, "/api/scripts", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = app.send(Method::GET, "/api/scripts", Some("not-a-token"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = app.send(Method::GET, "/api/redactions", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        for uri in ["/api/visualizations/traffic-stats/collector", "/api/visualizations/snapshots", "/api/visualizations/snapshots/diff?from=2026-01-01T00:00:00Z&to=2026-01-02T00:00:00Z"] {
+            let (status, _) = app.send(Method::GET, uri, None, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn ticket_lifecycle() {
+        let app = TestApp::spawn().await;
+        let csv = "Title,Description,Status,Priority\nPrinter offline,Second floor printer does not respond,open,high\n";
+
+        // Without a mapping the import only proposes one
+        let (status, preview) = app.post("/api/tickets/import", json!({ "content": csv })).await;
+        assert_eq!(status, StatusCode::OK, "{}", preview);
+        assert_eq!(preview["rows"], 1);
+
+        let (status, report) = app.post("/api/tickets/import", json!({
+            "content": csv,
+            "mapping": preview["proposed_mapping"],
+            "dry_run": false,
+        })).await;
+        assert_eq!(status, StatusCode::OK, "{}", report);
+        assert_eq!(report["imported"], 1);
+        assert_eq!(report["failed"], 0);
+        let ticket_id = report["rows"][0]["ticket_id"].as_str().expect("imported ticket").to_string();
+
+        let (status, comment) = app.post(&format!("/api/tickets/{}/comments", ticket_id), json!({
+            "content": "Power cycled, looking at the network port next",
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", comment);
+
+        let (status, _) = app.post(&format!("/api/tickets/{}/alerts", ticket_id), json!({
+            "alert_id": Uuid::new_v4(),
+        })).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, activity) = app.get(&format!("/api/tickets/{}/activity", ticket_id)).await;
+        assert_eq!(status, StatusCode::OK, "{}", activity);
+        let kinds: Vec<&str> = activity["items"].as_array().expect("activity items").iter()
+            .filter_map(|item| item["kind"].as_str())
+            .collect();
+        assert!(kinds.contains(&"created"), "{:?}", kinds);
+        assert!(kinds.contains(&"comment_added"), "{:?}", kinds);
+    }
+
+    #[tokio::test]
+    async fn ticket_detail_is_redacted_by_role() {
+        let app = TestApp::spawn().await;
+        let csv = "Title,Description,Status,Priority\nVPN drops,Disconnects every hour,open,high\n";
+        let (_, preview) = app.post("/api/tickets/import", json!({ "content": csv })).await;
+        let (status, report) = app.post("/api/tickets/import", json!({
+            "content": csv,
+            "mapping": preview["proposed_mapping"],
+            "dry_run": false,
+        })).await;
+        assert_eq!(status, StatusCode::OK, "{}", report);
+        let ticket_id = report["rows"][0]["ticket_id"].as_str().expect("imported ticket").to_string();
+
+        for (content, is_internal) in [("Asked the user for logs", false), ("Probably the firewall upgrade", true)] {
+            let (status, comment) = app.post(&format!("/api/tickets/{}/comments", ticket_id), json!({
+                "content": content,
+                "is_internal": is_internal,
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", comment);
+        }
+        let contents = |ticket: &Value| -> Vec<String> {
+            ticket["comments"].as_array().expect("comments").iter()
+                .filter_map(|c| c["content"].as_str().map(str::to_string))
+                .collect()
+        };
+
+        let (status, ticket) = app.get(&format!("/api/tickets/{}", ticket_id)).await;
+        assert_eq!(status, StatusCode::OK, "{}", ticket);
+        assert_eq!(contents(&ticket), ["Asked the user for logs", "Probably the firewall upgrade"]);
+
+        let technician = app.login_as("tech", "Technician").await;
+        let (status, ticket) = app.send(Method::GET, &format!("/api/tickets/{}", ticket_id), Some(&technician), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", ticket);
+        assert_eq!(contents(&ticket), ["Asked the user for logs"]);
+        assert!(ticket.get("portal_secret").is_none());
+
+        // Internal comments are also recorded in the activity feed
+        let activity_uri = format!("/api/tickets/{}/activity", ticket_id);
+        let (status, activity) = app.get(&activity_uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", activity);
+        assert!(activity.to_string().contains("Probably the firewall upgrade"));
+        let (status, activity) = app.send(Method::GET, &activity_uri, Some(&technician), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", activity);
+        assert!(!activity.to_string().contains("Probably the firewall upgrade"), "{}", activity);
+        assert!(activity.to_string().contains("Asked the user for logs"));
+    }
+
+    #[tokio::test]
+    async fn script_crud_and_execution() {
+        let app = TestApp::spawn().await;
+
+        let (status, script) = app.post("/api/scripts", json!({
+            "name": "Disk report",
+            "content": "Get-PSDrive -PSProvider FileSystem",
+            "category": "Maintenance",
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", script);
+        let id = script["id"].as_str().expect("script id").to_string();
+
+        let (status, script) = app.put(&format!("/api/scripts/{}", id), json!({
+            "description": "Free space per drive",
+        })).await;
+        assert_eq!(status, StatusCode::OK, "{}", script);
+
+        let (status, script) = app.get(&format!("/api/scripts/{}", id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(script["description"], "Free space per drive");
+
+        let (status, scripts) = app.get("/api/scripts").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(scripts.as_array().expect("script list").iter().any(|s| s["id"] == id.as_str()));
+
+        // Nothing runs before a review
+        let (status, error) = app.post(&format!("/api/scripts/{}/execute", id), json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error, "Cannot execute unapproved script");
+
+        // The author cannot approve their own script, and an approved one is not reviewed again
+        let approve = format!("/api/scripts/{}/approve", id);
+        let (status, _) = app.post(&approve, json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let reviewer = app.login_as("reviewer", "Admin").await;
+        let (status, script) = app.send(Method::POST, &approve, Some(&reviewer), Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK, "{}", script);
+        assert_eq!(script["approved_by"], "reviewer");
+        let (status, _) = app.send(Method::POST, &approve, Some(&reviewer), Some(json!({}))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Whether PowerShell is installed decides success, not whether the run is recorded
+        let (status, result) = app.post(&format!("/api/scripts/{}/execute", id), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", result);
+        assert_eq!(result["script_id"], id.as_str());
+        assert_eq!(result["executed_by"], ADMIN_USERNAME);
+
+        let (status, _) = app.delete(&format!("/api/scripts/{}", id)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = app.get(&format!("/api/scripts/{}", id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn firewall_rule_add_list_delete() {
+        let app = TestApp::spawn().await;
+
+        let (status, rule) = app.post("/api/network/firewall/rules", json!({
+            "chain": "input",
+            "protocol": "tcp",
+            "port": 2222,
+            "source": "10.0.0.0/8",
+            "action": "accept",
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", rule);
+        let handle = rule["handle"].as_u64().expect("rule handle");
+
+        let (status, managed) = app.get("/api/network/firewall/managed").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(managed.as_array().expect("managed rules").iter().any(|r| r["handle"] == handle));
+
+        let (status, _) = app.delete(&format!("/api/network/firewall/rules/{}", handle)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, managed) = app.get("/api/network/firewall/managed").await;
+        assert!(!managed.as_array().expect("managed rules").iter().any(|r| r["handle"] == handle));
+        let (status, _) = app.delete(&format!("/api/network/firewall/rules/{}", handle)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn firewall_change_requests_need_a_second_person() {
+        let app = TestApp::spawn().await;
+        let rules = json!({
+            "description": "SSH from the office",
+            "rules": [{ "chain": "input", "protocols": ["tcp"], "ports": [2222], "source": "10.0.0.0/8", "action": "accept" }],
+        });
+
+        let (status, _) = app.post("/api/roles", json!({ "role": "viewer", "permissions": ["network:read"] })).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, body) = app.post("/api/users", json!({
+            "username": "viewer",
+            "role": { "Custom": "viewer" },
+            "password": ADMIN_PASSWORD,
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        let viewer = app.login("viewer", ADMIN_PASSWORD).await;
+        let (status, _) = app.send(Method::GET, "/api/network/firewall/staged", Some(&viewer), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.send(Method::POST, "/api/network/firewall/staged", Some(&viewer), Some(rules.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Technicians propose but do not apply
+        let proposer = app.login_as("proposer", "Technician").await;
+        let (status, changeset) = app.send(Method::POST, "/api/network/firewall/staged", Some(&proposer), Some(rules.clone())).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", changeset);
+        let uri = format!("/api/network/firewall/staged/{}", changeset["id"].as_str().expect("changeset id"));
+        let (status, _) = app.send(Method::POST, &format!("{}/apply", uri), Some(&proposer), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Only the proposer withdraws it, not another proposer
+        let other = app.login_as("other", "Technician").await;
+        let (status, _) = app.send(Method::DELETE, &uri, Some(&other), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app.send(Method::DELETE, &uri, Some(&proposer), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // The admin's own change request is applied by someone else
+        let (status, own) = app.post("/api/network/firewall/staged", rules.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", own);
+        let own_uri = format!("/api/network/firewall/staged/{}", own["id"].as_str().expect("changeset id"));
+        let (status, _) = app.post(&format!("{}/apply", own_uri), json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, changeset) = app.send(Method::POST, "/api/network/firewall/staged", Some(&proposer), Some(rules)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let uri = format!("/api/network/firewall/staged/{}", changeset["id"].as_str().expect("changeset id"));
+        let (status, body) = app.post(&format!("{}/apply", uri), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn log_ingest_and_query() {
+        let app = TestApp::spawn().await;
+
+        for message in ["Accepted publickey for deploy", "Failed password for root"] {
+            let (status, entry) = app.post("/api/logs/ingest", json!({
+                "source": "harness-sshd",
+                "message": message,
+                "host": "10.0.0.5",
+                "tags": ["ssh"],
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", entry);
+        }
+
+        let (status, logs) = app.get("/api/logs?source=harness-sshd").await;
+        assert_eq!(status, StatusCode::OK, "{}", logs);
+        assert_eq!(logs.as_array().expect("log entries").len(), 2);
+
+        let (status, logs) = app.get("/api/logs?source=harness-sshd&message_contains=Failed").await;
+        assert_eq!(status, StatusCode::OK);
+        let logs = logs.as_array().expect("log entries");
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0]["message"], "Failed password for root");
+        assert!(logs[0]["tags"].as_array().expect("tags").contains(&json!("ssh")));
+    }
+
+    #[tokio::test]
+    async fn flow_export_needs_network_read() {
+        let app = TestApp::spawn().await;
+        let uri = "/api/visualizations/traffic-flows/export?format=jsonl";
+
+        let (status, _) = app.send(Method::GET, uri, None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let requester = app.login_as("requester", "User").await;
+        let (status, _) = app.send(Method::GET, uri, Some(&requester), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = app.get(uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn escalation_policies_need_alert_manage() {
+        let app = TestApp::spawn().await;
+        let policy = |url: &str| json!({
+            "name": "Night shift",
+            "steps": [{ "targets": [{ "type": "webhook", "url": url }], "wait_minutes": 5 }],
+        });
+
+        let technician = app.login_as("technician", "Technician").await;
+        let (status, _) = app.send(Method::GET, "/api/alerts/escalation-policies", Some(&technician), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app.send(Method::POST, "/api/alerts/escalation-policies", Some(&technician),
+                                   Some(policy("https://hooks.example.com/alert"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = app.post("/api/alerts/escalation-policies", policy("http://169.254.169.254/latest")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = app.post("/api/alerts/escalation-policies", policy("https://hooks.example.com/alert")).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn visualizations_are_scoped_to_the_sites_of_a_restricted_user() {
+        let app = TestApp::spawn().await;
+        let mut sites = Vec::new();
+        for (name, subnet) in [("Prague", "10.1.0.0/16"), ("Brno", "10.2.0.0/16")] {
+            let (status, site) = app.post("/api/sites", json!({
+                "name": name,
+                "subnets": [subnet],
+                "team": null,
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", site);
+            sites.push(site["id"].as_str().unwrap().to_string());
+        }
+        let (status, user) = app.post("/api/users", json!({
+            "username": "prague-tech",
+            "role": "Technician",
+            "password": ADMIN_PASSWORD,
+            "sites": [sites[0]],
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", user);
+        let technician = app.login("prague-tech", ADMIN_PASSWORD).await;
+        let get = |uri: String| {
+            let technician = technician.clone();
+            let app = &app;
+            async move { app.send(Method::GET, &uri, Some(&technician), None).await }
+        };
+
+        let (status, _) = get(format!("/api/visualizations/traffic-flows?site={}", sites[0])).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(format!("/api/visualizations/traffic-flows?site={}", sites[1])).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Without interfaces in their sites the technician sees no more than the router
+        let (status, graph) = get("/api/visualizations/network-graph".to_string()).await;
+        assert_eq!(status, StatusCode::OK, "{}", graph);
+        assert!(graph["nodes"].as_array().unwrap().iter().all(|node| node["id"] == "router-main"));
+        assert!(graph["zones"].as_array().unwrap().is_empty());
+        let (status, stats) = get("/api/visualizations/traffic-stats".to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats, json!({}));
+        let (status, _) = get("/api/visualizations/network-graph/groups?collapse=zone&key=lan".to_string()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let requester = app.login_as("requester", "User").await;
+        let (status, _) = app.send(Method::GET, "/api/visualizations/network-graph/groups?collapse=zone&key=lan",
+                                   Some(&requester), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = get("/api/visualizations/traffic-history/eth0".to_string()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = app.get("/api/visualizations/traffic-history/eth0").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn bulk_runs_and_tickets_are_scoped_to_the_sites_of_a_restricted_user() {
+        let app = TestApp::spawn().await;
+        let mut sites = Vec::new();
+        for (name, subnet) in [("Prague", "10.1.0.0/16"), ("Brno", "10.2.0.0/16")] {
+            let (status, site) = app.post("/api/sites", json!({
+                "name": name,
+                "subnets": [subnet],
+                "team": null,
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", site);
+            sites.push(site["id"].as_str().unwrap().to_string());
+        }
+        // Without an address every target is skipped, so nothing is executed
+        let mut assets = Vec::new();
+        for (name, site) in [("prague-pc", &sites[0]), ("brno-pc", &sites[1])] {
+            let (status, asset) = app.post("/api/assets", json!({
+                "name": name,
+                "asset_type": "Workstation",
+                "ip_address": null,
+                "mac_address": null,
+                "operating_system": "Windows 11",
+                "owner": null,
+                "location": null,
+                "location_id": null,
+                "site_id": site,
+                "purchase_date": null,
+                "status": "Active",
+                "tags": ["env=office"],
+            })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", asset);
+            assets.push(asset["id"].as_str().unwrap().to_string());
+        }
+        let (status, user) = app.post("/api/users", json!({
+            "username": "prague-tech",
+            "role": "Technician",
+            "password": ADMIN_PASSWORD,
+            "sites": [sites[0]],
+        })).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", user);
+        let technician = app.login("prague-tech", ADMIN_PASSWORD).await;
+
+        let (_, script) = app.post("/api/scripts", json!({
+            "name": "Inventory",
+            "content": "Get-ComputerInfo",
+            "category": "Maintenance",
+        })).await;
+        let script_id = script["id"].as_str().expect("script id").to_string();
+        let reviewer = app.login_as("reviewer", "Admin").await;
+        let (status, _) = app.send(Method::POST, &format!("/api/scripts/{}/approve", script_id), Some(&reviewer),
+                                   Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let bulk = format!("/api/scripts/{}/execute-bulk", script_id);
+
+        // A tag filter only reaches the technician's site, an asset named elsewhere is not found
+        let (status, batch) = app.send(Method::POST, &bulk, Some(&technician),
+                                       Some(json!({ "filter": { "tags": { "all": ["env=office"] } } }))).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", batch);
+        let targets: Vec<&str> = batch["targets"].as_array().unwrap().iter()
+            .map(|t| t["asset_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(targets, ["prague-pc"]);
+        let (status, _) = app.send(Method::POST, &bulk, Some(&technician),
+                                   Some(json!({ "filter": { "ids": [assets[1]] } }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Of a batch over both sites the technician sees their target only, and cannot cancel it
+        let (status, everywhere) = app.post(&bulk, json!({ "filter": { "tags": { "all": ["env=office"] } } })).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", everywhere);
+        assert_eq!(everywhere["targets"].as_array().unwrap().len(), 2);
+        let (status, seen) = app.send(Method::GET, &format!("/api/scripts/batches/{}", everywhere["id"].as_str().unwrap()),
+                                      Some(&technician), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", seen);
+        assert_eq!(seen["summary"]["total"], 1);
+        let (status, _) = app.send(Method::POST, &format!("/api/scripts/batches/{}/cancel", everywhere["id"].as_str().unwrap()),
+                                   Some(&technician), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, brno) = app.post(&bulk, json!({ "filter": { "ids": [assets[1]] } })).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", brno);
+        let brno_id = brno["id"].as_str().unwrap();
+        for uri in [format!("/api/scripts/batches/{}", brno_id), format!("/api/scripts/batches/{}/output", brno_id)] {
+            let (status, _) = app.send(Method::GET, &uri, Some(&technician), None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        }
+        let (status, batches) = app.send(Method::GET, "/api/scripts/batches", Some(&technician), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(batches.as_array().unwrap().len(), 2);
+        let (_, batches) = app.get("/api/scripts/batches").await;
+        assert_eq!(batches.as_array().unwrap().len(), 3);
+
+        // Imported tickets belong to no site, so only unrestricted staff list them
+        let csv = "Title,Description,Status,Priority\nVPN drops,Disconnects every hour,open,high\n";
+        let (_, preview) = app.post("/api/tickets/import", json!({ "content": csv })).await;
+        let (status, _) = app.post("/api/tickets/import", json!({
+            "content": csv,
+            "mapping": preview["proposed_mapping"],
+            "dry_run": false,
+        })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, tickets) = app.get("/api/tickets").await;
+        assert_eq!(status, StatusCode::OK, "{}", tickets);
+        assert_eq!(tickets.as_array().unwrap().len(), 1);
+        let (status, tickets) = app.send(Method::GET, "/api/tickets", Some(&technician), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tickets, json!([]));
+        let (status, _) = app.send(Method::GET, &format!("/api/tickets?site={}", sites[1]), Some(&technician), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let requester = app.login_as("requester", "User").await;
+        let (_, tickets) = app.send(Method::GET, "/api/tickets", Some(&requester), None).await;
+        assert_eq!(tickets, json!([]));
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Duration, Utc};
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::config::TicketAutoCloseConfig;
+use crate::notifications::Notifier;
+use crate::security::{AuditStatus, SecurityManager};
+use crate::tickets::{Ticket, TicketStatus, TicketsManager, NO_AUTOCLOSE_TAG};
+use crate::users::UserManager;
+
+// Warns and then closes resolved tickets nobody touches. Each step checks the ticket
+// itself (see TicketsManager::warn_inactive), so running it again, or after a
+// restart, never repeats a warning.
+#[derive(Clone)]
+pub struct TicketAutoClose {
+    config: TicketAutoCloseConfig,
+    tickets: TicketsManager,
+    users: UserManager,
+    notifier: Notifier,
+    security: SecurityManager,
+}
+
+impl TicketAutoClose {
+    pub fn new(config: TicketAutoCloseConfig,
+               tickets: TicketsManager,
+               users: UserManager,
+               notifier: Notifier,
+               security: SecurityManager) -> Self {
+        Self {
+            config,
+            tickets,
+            users,
+            notifier,
+            security,
+        }
+    }
+
+    pub async fn run(&self, now: DateTime<Utc>) -> Result<()> {
+        let candidates: Vec<Ticket> = self.tickets.get_all_tickets()?
+            .into_iter()
+            .filter(|t| t.status == TicketStatus::Resolved && !t.tags.iter().any(|tag| tag == NO_AUTOCLOSE_TAG))
+            .collect();
+
+        for ticket in candidates {
+            let policy = self.config.policy_for(&ticket.category);
+            if !policy.enabled {
+                continue;
+            }
+
+            match ticket.inactivity_warned_at.filter(|warned| *warned >= ticket.updated_at) {
+                Some(warned_at) => {
+                    if now - warned_at >= Duration::days(policy.close_after_days as i64)
+                        && self.tickets.close_inactive(ticket.id, warned_at)? {
+                        self.security.log_audit_event(
+                            "system",
+                            "ticket:auto_close",
+                            &ticket.id.to_string(),
+                            AuditStatus::Success,
+                            Some(format!("No activity for {} days after the warning", policy.close_after_days)),
+                        );
+                        info!("Closed ticket {} after inactivity", ticket.id);
+                    }
+                },
+                None => {
+                    if now - ticket.updated_at < Duration::days(policy.warn_after_days as i64) {
+                        continue;
+                    }
+
+                    let content = format!(
+                        "This ticket has been resolved without activity for {} days. It will be closed automatically in {} days unless someone updates or comments on it.",
+                        policy.warn_after_days,
+                        policy.close_after_days,
+                    );
+                    if self.tickets.warn_inactive(ticket.id, ticket.updated_at, content.clone())? {
+                        self.security.log_audit_event(
+                            "system",
+                            "ticket:auto_close_warning",
+                            &ticket.id.to_string(),
+                            AuditStatus::Success,
+                            Some(format!("Closing in {} days", policy.close_after_days)),
+                        );
+                        self.notify_creator(&ticket, &content).await;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // A failed notification is logged; the warning comment stays on the ticket
+    async fn notify_creator(&self, ticket: &Ticket, content: &str) {
+        let user = match self.users.get_user(&ticket.created_by) {
+            Ok(user) if user.is_active => user,
+            Ok(_) => return,
+            Err(e) => {
+                warn!("No user to notify of inactive ticket {}: {}", ticket.id, e);
+                return;
+            },
+        };
+
+        let subject = format!("Ticket will be closed: {}", ticket.title);
+        if user.notifications.email && !user.email.is_empty() {
+            if let Err(e) = self.notifier.send_email(&[user.email.clone()], &subject, content).await {
+                warn!("Failed to email inactivity warning for ticket {}: {}", ticket.id, e);
+            }
+        }
+        if let Some(url) = &user.notifications.webhook_url {
+            let payload = serde_json::json!({
+                "event": "ticket.inactivity_warning",
+                "ticket_id": ticket.id,
+                "title": ticket.title,
+            });
+            if let Err(e) = self.notifier.post_webhook(url, &payload).await {
+                warn!("Failed to post inactivity warning for ticket {}: {}", ticket.id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use anyhow::{Context, Result, anyhow};
+use tracing::warn;
+
+use crate::tags;
+use crate::tickets::{board_order, Ticket, TicketCategory, TicketPriority, TicketStatus, TicketSummary};
+
+const ALL_COLUMNS: [TicketStatus; 5] = [
+    TicketStatus::Open,
+    TicketStatus::InProgress,
+    TicketStatus::Pending,
+    TicketStatus::Resolved,
+    TicketStatus::Closed,
+];
+
+// Rows splitting every column of the board
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Swimlane {
+    Priority,
+    Assignee,
+}
+
+// Tickets shown on the board; each list matches any of its values and is ignored when empty
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoardFilters {
+    pub priorities: Vec<TicketPriority>,
+    pub categories: Vec<TicketCategory>,
+    pub assignees: Vec<String>,
+    // Tickets must carry all of these
+    pub tags: Vec<String>,
+}
+
+impl BoardFilters {
+    fn matches(&self, ticket: &Ticket) -> bool {
+        (self.priorities.is_empty() || self.priorities.contains(&ticket.priority))
+            && (self.categories.is_empty() || self.categories.contains(&ticket.category))
+            && (self.assignees.is_empty() || ticket.assigned_to.as_ref().map_or(false, |a| self.assignees.contains(a)))
+            && self.tags.iter().all(|tag| ticket.tags.contains(tag))
+    }
+}
+
+// How a user's board looks, kept on the server so it is the same on every device
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoardPreferences {
+    // Columns shown, in this order; all statuses when empty
+    pub columns: Vec<TicketStatus>,
+    pub filters: BoardFilters,
+    pub swimlanes: Option<Swimlane>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl BoardPreferences {
+    fn validate(&mut self) -> Result<()> {
+        for (i, column) in self.columns.iter().enumerate() {
+            if self.columns[..i].contains(column) {
+                return Err(anyhow!("Column {:?} is listed twice", column));
+            }
+        }
+        self.filters.tags = tags::normalize_all(&self.filters.tags);
+        Ok(())
+    }
+
+    fn columns(&self) -> Vec<TicketStatus> {
+        if self.columns.is_empty() {
+            ALL_COLUMNS.to_vec()
+        } else {
+            self.columns.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardCard {
+    #[serde(flatten)]
+    pub summary: TicketSummary,
+    pub board_position: Option<i64>,
+    pub category: TicketCategory,
+    pub tags: Vec<String>,
+}
+
+// A swimlane within a column; the key is None when the board has no swimlanes and for
+// unassigned tickets
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardLane {
+    pub key: Option<String>,
+    pub tickets: Vec<BoardCard>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardColumn {
+    pub status: TicketStatus,
+    pub count: usize,
+    pub lanes: Vec<BoardLane>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Board {
+    pub swimlanes: Option<Swimlane>,
+    pub columns: Vec<BoardColumn>,
+}
+
+fn priority_rank(priority: &TicketPriority) -> u8 {
+    match priority {
+        TicketPriority::Critical => 0,
+        TicketPriority::High => 1,
+        TicketPriority::Medium => 2,
+        TicketPriority::Low => 3,
+    }
+}
+
+// Lane of a ticket with the key lanes are ordered by: most urgent priority first,
+// assignees by name with the unassigned last
+fn lane(swimlanes: Option<Swimlane>, ticket: &Ticket) -> (String, Option<String>) {
+    match swimlanes {
+        None => (String::new(), None),
+        Some(Swimlane::Priority) => (priority_rank(&ticket.priority).to_string(), Some(format!("{:?}", ticket.priority))),
+        Some(Swimlane::Assignee) => match &ticket.assigned_to {
+            Some(assignee) => (format!("0{}", assignee), Some(assignee.clone())),
+            None => ("1".to_string(), None),
+        },
+    }
+}
+
+// The tickets laid out by the preferences, each column in board order. Every column has
+// the same lanes, empty ones included, so the rows line up.
+pub fn build(tickets: Vec<Ticket>, preferences: &BoardPreferences) -> Board {
+    let columns = preferences.columns();
+    let mut tickets: Vec<Ticket> = tickets.into_iter()
+        .filter(|t| columns.contains(&t.status) && preferences.filters.matches(t))
+        .collect();
+    tickets.sort_by(board_order);
+
+    let lanes: BTreeMap<String, Option<String>> = tickets.iter()
+        .map(|t| lane(preferences.swimlanes, t))
+        .collect();
+    let lanes = if lanes.is_empty() { BTreeMap::from([(String::new(), None)]) } else { lanes };
+
+    let columns = columns.into_iter()
+        .map(|status| {
+            let in_column: Vec<&Ticket> = tickets.iter().filter(|t| t.status == status).collect();
+            BoardColumn {
+                count: in_column.len(),
+                lanes: lanes.iter()
+                    .map(|(order, key)| BoardLane {
+                        key: key.clone(),
+                        tickets: in_column.iter()
+                            .filter(|t| lane(preferences.swimlanes, t).0 == *order)
+                            .map(|t| BoardCard {
+                                summary: TicketSummary::from(*t),
+                                board_position: t.board_position,
+                                category: t.category.clone(),
+                                tags: t.tags.clone(),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                status,
+            }
+        })
+        .collect();
+
+    Board { swimlanes: preferences.swimlanes, columns }
+}
+
+// Board preferences per user, in one file
+#[derive(Clone)]
+pub struct BoardPreferencesManager {
+    path: PathBuf,
+    preferences: Arc<Mutex<BTreeMap<String, BoardPreferences>>>,
+}
+
+impl BoardPreferencesManager {
+    pub fn new(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let preferences = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring invalid board preferences file {:?}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            path,
+            preferences: Arc::new(Mutex::new(preferences)),
+        })
+    }
+
+    // Defaults for users who never saved any
+    pub fn get(&self, username: &str) -> Result<BoardPreferences> {
+        let preferences = self.preferences.lock().map_err(|_| anyhow!("Failed to acquire lock on board preferences"))?;
+        Ok(preferences.get(username).cloned().unwrap_or_default())
+    }
+
+    pub fn set(&self, username: &str, mut update: BoardPreferences) -> Result<BoardPreferences> {
+        update.validate()?;
+        update.updated_at = Some(Utc::now());
+
+        let mut preferences = self.preferences.lock().map_err(|_| anyhow!("Failed to acquire lock on board preferences"))?;
+        let mut stored = preferences.clone();
+        stored.insert(username.to_string(), update.clone());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&stored)?)
+            .context(format!("Failed to write board preferences file: {:?}", self.path))?;
+        *preferences = stored;
+        Ok(update)
+    }
+}
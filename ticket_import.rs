@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::tickets::{Ticket, TicketCategory, TicketPriority, TicketStatus};
+
+// Tag added to every imported ticket
+pub const IMPORTED_TAG: &str = "imported";
+
+// Ticket fields a CSV column can be mapped to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketField {
+    Title,
+    Description,
+    Status,
+    Priority,
+    Category,
+    CreatedAt,
+    CreatedBy,
+    AssignedTo,
+    Tags,
+    DueDate,
+    Resolution,
+}
+
+impl TicketField {
+    const ALL: [TicketField; 11] = [
+        TicketField::Title,
+        TicketField::Description,
+        TicketField::Status,
+        TicketField::Priority,
+        TicketField::Category,
+        TicketField::CreatedAt,
+        TicketField::CreatedBy,
+        TicketField::AssignedTo,
+        TicketField::Tags,
+        TicketField::DueDate,
+        TicketField::Resolution,
+    ];
+
+    // Header names other helpdesks and spreadsheets commonly use, normalized
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            TicketField::Title => &["title", "subject", "summary", "name"],
+            TicketField::Description => &["description", "body", "details", "text"],
+            TicketField::Status => &["status", "state"],
+            TicketField::Priority => &["priority", "urgency", "severity"],
+            TicketField::Category => &["category", "type", "queue"],
+            TicketField::CreatedAt => &["createdat", "created", "createddate", "opened", "openedat", "date"],
+            TicketField::CreatedBy => &["createdby", "reporter", "requester", "author", "reportedby"],
+            TicketField::AssignedTo => &["assignedto", "assignee", "owner", "agent"],
+            TicketField::Tags => &["tags", "labels", "keywords"],
+            TicketField::DueDate => &["duedate", "due", "deadline"],
+            TicketField::Resolution => &["resolution", "solution"],
+        }
+    }
+}
+
+fn normalize_header(header: &str) -> String {
+    header.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+// Parsed upload: the header row and the data records with their line numbers
+#[derive(Debug, Clone)]
+pub struct CsvTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<(usize, Vec<String>)>,
+}
+
+// Excel writes ';' separated files in locales using the decimal comma, so the
+// separator is whichever of the two is more frequent in the header line
+fn detect_separator(content: &str) -> char {
+    let header = content.lines().next().unwrap_or("");
+    if header.matches(';').count() > header.matches(',').count() {
+        ';'
+    } else {
+        ','
+    }
+}
+
+// RFC 4180 parser; quoted fields may contain separators, doubled quotes and line breaks
+pub fn parse_csv(content: &str) -> Result<CsvTable, String> {
+    let content = content.trim_start_matches('\u{feff}');
+    let separator = detect_separator(content);
+
+    let mut records: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            },
+            '"' if field.is_empty() => in_quotes = true,
+            '\n' if in_quotes => {
+                line += 1;
+                field.push(c);
+            },
+            c if in_quotes => field.push(c),
+            c if c == separator => record.push(std::mem::take(&mut field)),
+            '\r' => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            },
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quoted field starting on line {}", record_line));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    // Blank lines carry no record
+    records.retain(|(_, values)| values.iter().any(|v| !v.trim().is_empty()));
+
+    let mut records = records.into_iter();
+    let columns: Vec<String> = match records.next() {
+        Some((_, header)) => header.into_iter().map(|h| h.trim().to_string()).collect(),
+        None => return Err("The file is empty".to_string()),
+    };
+
+    Ok(CsvTable {
+        columns,
+        rows: records.collect(),
+    })
+}
+
+// Column name -> ticket field
+pub type ColumnMapping = HashMap<String, TicketField>;
+
+// Maps every column whose header matches a known alias; each field is used once
+pub fn propose_mapping(columns: &[String]) -> ColumnMapping {
+    let mut mapping = ColumnMapping::new();
+
+    for column in columns {
+        let normalized = normalize_header(column);
+        let field = TicketField::ALL.iter()
+            .find(|field| field.aliases().contains(&normalized.as_str()) && !mapping.values().any(|f| f == *field));
+        if let Some(field) = field {
+            mapping.insert(column.clone(), *field);
+        }
+    }
+
+    mapping
+}
+
+pub fn validate_mapping(columns: &[String], mapping: &ColumnMapping) -> Result<(), String> {
+    if let Some(column) = mapping.keys().find(|c| !columns.contains(c)) {
+        return Err(format!("Mapped column not in the file: {}", column));
+    }
+    for field in TicketField::ALL {
+        if mapping.values().filter(|f| **f == field).count() > 1 {
+            return Err(format!("Field {:?} is mapped to more than one column", field));
+        }
+    }
+    if !mapping.values().any(|f| *f == TicketField::Title) {
+        return Err("A column must be mapped to the title".to_string());
+    }
+    Ok(())
+}
+
+fn parse_status(value: &str) -> Result<TicketStatus, String> {
+    match normalize_header(value).as_str() {
+        "" | "open" | "new" => Ok(TicketStatus::Open),
+        "inprogress" | "active" | "assigned" => Ok(TicketStatus::InProgress),
+        "pending" | "waiting" | "onhold" => Ok(TicketStatus::Pending),
+        "resolved" | "solved" | "done" => Ok(TicketStatus::Resolved),
+        "closed" => Ok(TicketStatus::Closed),
+        _ => Err(format!("Unknown status: {}", value)),
+    }
+}
+
+fn parse_priority(value: &str) -> Result<TicketPriority, String> {
+    match normalize_header(value).as_str() {
+        "low" => Ok(TicketPriority::Low),
+        "" | "medium" | "normal" => Ok(TicketPriority::Medium),
+        "high" => Ok(TicketPriority::High),
+        "critical" | "urgent" => Ok(TicketPriority::Critical),
+        _ => Err(format!("Unknown priority: {}", value)),
+    }
+}
+
+fn parse_category(value: &str) -> Result<TicketCategory, String> {
+    match normalize_header(value).as_str() {
+        "access" => Ok(TicketCategory::Access),
+        "hardware" => Ok(TicketCategory::Hardware),
+        "software" => Ok(TicketCategory::Software),
+        "network" => Ok(TicketCategory::Network),
+        "security" => Ok(TicketCategory::Security),
+        "" | "other" => Ok(TicketCategory::Other),
+        _ => Err(format!("Unknown category: {}", value)),
+    }
+}
+
+// RFC 3339 or the usual spreadsheet layouts; times without an offset are taken as UTC
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%d.%m.%Y %H:%M:%S", "%d.%m.%Y %H:%M", "%m/%d/%Y %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(date.and_utc());
+        }
+    }
+    for format in ["%Y-%m-%d", "%d.%m.%Y", "%m/%d/%Y"] {
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+        }
+    }
+
+    Err(format!("Unrecognized date: {}", value))
+}
+
+fn optional(value: Option<&str>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn collect<T>(result: Result<T, String>, errors: &mut Vec<String>) -> Option<T> {
+    result.map_err(|e| errors.push(e)).ok()
+}
+
+// Builds a ticket from one record, collecting every problem rather than stopping at the first
+pub fn ticket_from_row(columns: &[String],
+                       mapping: &ColumnMapping,
+                       values: &[String],
+                       imported_by: &str) -> Result<Ticket, Vec<String>> {
+    let value = |field: TicketField| mapping.iter()
+        .find(|(_, f)| **f == field)
+        .and_then(|(column, _)| columns.iter().position(|c| c == column))
+        .and_then(|index| values.get(index))
+        .map(|v| v.as_str());
+
+    let mut errors = Vec::new();
+
+    let title = optional(value(TicketField::Title));
+    let status = collect(parse_status(value(TicketField::Status).unwrap_or("")), &mut errors);
+    let priority = collect(parse_priority(value(TicketField::Priority).unwrap_or("")), &mut errors);
+    let category = collect(parse_category(value(TicketField::Category).unwrap_or("")), &mut errors);
+    let created_at = optional(value(TicketField::CreatedAt)).and_then(|v| collect(parse_date(&v), &mut errors));
+    let due_date = optional(value(TicketField::DueDate)).and_then(|v| collect(parse_date(&v), &mut errors));
+
+    if title.is_none() {
+        errors.push("Title is empty".to_string());
+    }
+    if values.len() != columns.len() {
+        errors.push(format!("Expected {} values, found {}", columns.len(), values.len()));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut tags: Vec<String> = value(TicketField::Tags).unwrap_or("")
+        .split(|c| c == ',' || c == ';' || c == '|')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if !tags.iter().any(|t| t == IMPORTED_TAG) {
+        tags.push(IMPORTED_TAG.to_string());
+    }
+
+    let created_at = created_at.unwrap_or_else(Utc::now);
+
+    Ok(Ticket {
+        id: Uuid::new_v4(),
+        title: title.unwrap_or_default(),
+        description: optional(value(TicketField::Description)).unwrap_or_default(),
+        status: status.unwrap_or(TicketStatus::Open),
+        priority: priority.unwrap_or(TicketPriority::Medium),
+        created_at,
+        updated_at: created_at,
+        created_by: optional(value(TicketField::CreatedBy)).unwrap_or_else(|| imported_by.to_string()),
+        assigned_to: optional(value(TicketField::AssignedTo)),
+        comments: Vec::new(),
+        attachments: Vec::new(),
+        worklogs: Vec::new(),
+        category: category.unwrap_or(TicketCategory::Other),
+        tags,
+        due_date,
+        resolution: optional(value(TicketField::Resolution)),
+        linked_alerts: Vec::new(),
+        inactivity_warned_at: None,
+        site_id: None,
+        requester_email: None,
+        portal_secret: None,
+        sla_breached_at: None,
+        board_position: None,
+        references: Vec::new(),
+    })
+}
+
+// Outcome of one record; a record without errors is imported unless the import is a
+// dry run, then the ticket id is set
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportRowResult {
+    pub line_number: usize,
+    pub ticket_id: Option<Uuid>,
+    pub errors: Vec<String>,
+}
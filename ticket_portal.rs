@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::warn;
+
+use crate::config::PortalConfig;
+use crate::notifications::Notifier;
+use crate::security::{constant_time_eq, SecurityManager};
+use crate::tickets::{Ticket, TicketCategory, TicketStatus, TicketsManager};
+
+// Longest requester reply accepted through the portal
+const MAX_COMMENT_CHARS: usize = 10_000;
+
+// What a requester sees of their ticket: no internal notes, assignee, tags or links
+#[derive(Debug, Clone, Serialize)]
+pub struct PortalTicket {
+    pub id: Uuid,
+    pub title: String,
+    pub description: String,
+    pub status: TicketStatus,
+    pub category: TicketCategory,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub resolution: Option<String>,
+    pub comments: Vec<PortalComment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortalComment {
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub author: String,
+    // Written by the requester rather than by staff
+    pub from_requester: bool,
+}
+
+impl From<&Ticket> for PortalTicket {
+    fn from(ticket: &Ticket) -> Self {
+        let requester = ticket.requester_email.as_deref();
+        Self {
+            id: ticket.id,
+            title: ticket.title.clone(),
+            description: ticket.description.clone(),
+            status: ticket.status.clone(),
+            category: ticket.category.clone(),
+            created_at: ticket.created_at,
+            updated_at: ticket.updated_at,
+            resolution: ticket.resolution.clone(),
+            comments: ticket.comments.iter()
+                .filter(|c| !c.is_internal)
+                .map(|c| PortalComment {
+                    content: c.content.clone(),
+                    created_at: c.created_at,
+                    author: c.created_by.clone(),
+                    from_requester: Some(c.created_by.as_str()) == requester,
+                })
+                .collect(),
+        }
+    }
+}
+
+// Signed, expiring links for requesters without an account. A token is
+// `<ticket id>.<expiry>.<signature>`, the signature an HMAC under the instance key over
+// the ticket id, the expiry and the ticket's portal secret.
+#[derive(Clone)]
+pub struct TicketPortal {
+    config: PortalConfig,
+    security: SecurityManager,
+    tickets: Arc<TicketsManager>,
+    notifier: Arc<Notifier>,
+    // Requests per source address in the current minute
+    requests: Arc<Mutex<HashMap<String, (i64, u32)>>>,
+}
+
+impl TicketPortal {
+    pub fn new(config: PortalConfig,
+               security: SecurityManager,
+               tickets: Arc<TicketsManager>,
+               notifier: Arc<Notifier>) -> Self {
+        Self {
+            config,
+            security,
+            tickets,
+            notifier,
+            requests: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn signature(&self, ticket_id: Uuid, expires: i64, secret: &str) -> String {
+        self.security.sign(format!("ticket-portal:{}:{}:{}", ticket_id, expires, secret).as_bytes())
+    }
+
+    pub fn issue(&self, ticket_id: Uuid) -> Result<String> {
+        let secret = self.tickets.portal_secret(ticket_id)?;
+        let expires = (Utc::now() + Duration::days(self.config.token_ttl_days as i64)).timestamp();
+        Ok(format!("{}.{}.{}", ticket_id.simple(), expires, self.signature(ticket_id, expires, &secret)))
+    }
+
+    // The ticket a token grants access to. Malformed, expired, tampered and revoked
+    // tokens, and tickets without a requester, all come back as None alike.
+    pub fn resolve(&self, token: &str) -> Option<Ticket> {
+        let mut parts = token.splitn(3, '.');
+        let ticket_id = Uuid::parse_str(parts.next()?).ok()?;
+        let expires: i64 = parts.next()?.parse().ok()?;
+        let signature = parts.next()?;
+
+        let ticket = self.tickets.get_ticket(ticket_id).ok()?;
+        let secret = ticket.portal_secret.as_deref()?;
+        let expected = self.signature(ticket_id, expires, secret);
+        if !constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        if expires < Utc::now().timestamp() || ticket.requester_email.is_none() {
+            return None;
+        }
+        Some(ticket)
+    }
+
+    // Counts a request from the address; false once it is over the limit for the minute
+    pub fn allow(&self, source_ip: &str) -> bool {
+        let minute = Utc::now().timestamp() / 60;
+        match self.requests.lock() {
+            Ok(mut requests) => {
+                requests.retain(|_, (window, _)| *window == minute);
+                let (_, count) = requests.entry(source_ip.to_string()).or_insert((minute, 0));
+                *count += 1;
+                *count <= self.config.requests_per_minute
+            },
+            Err(_) => false,
+        }
+    }
+
+    pub fn add_reply(&self, ticket: &Ticket, content: &str) -> Result<Uuid> {
+        let requester = ticket.requester_email.clone()
+            .ok_or_else(|| anyhow!("Ticket {} has no requester", ticket.id))?;
+        let content = content.trim();
+        if content.is_empty() {
+            return Err(anyhow!("Reply must not be empty"));
+        }
+        if content.chars().count() > MAX_COMMENT_CHARS {
+            return Err(anyhow!("Reply is longer than {} characters", MAX_COMMENT_CHARS));
+        }
+        self.tickets.add_comment(ticket.id, content.to_string(), requester, false)
+    }
+
+    fn link(&self, token: &str) -> String {
+        match &self.config.base_url {
+            Some(base) => format!("{}/{}", base.trim_end_matches('/'), token),
+            None => format!("/api/portal/tickets/{}", token),
+        }
+    }
+
+    // Emails the requester a fresh link; tickets without a requester are skipped
+    pub async fn notify_requester(&self, ticket_id: Uuid, message: &str) -> Result<()> {
+        let ticket = self.tickets.get_ticket(ticket_id)?;
+        let Some(email) = ticket.requester_email.clone() else {
+            return Ok(());
+        };
+
+        let token = self.issue(ticket_id)?;
+        let subject = format!("[Ticket {}] {}", ticket.id.simple(), ticket.title);
+        let body = format!(
+            "{}\n\nView the ticket and reply: {}\n\nThe link is valid for {} days. Do not forward it, anyone holding it can read and answer the ticket.\n",
+            message,
+            self.link(&token),
+            self.config.token_ttl_days,
+        );
+        self.notifier.send_email(&[email], &subject, &body).await
+    }
+}
+
+// Mail goes out in the background so SMTP does not hold up the request
+pub fn spawn_notification(portal: &TicketPortal, ticket_id: Uuid, message: String) {
+    let portal = portal.clone();
+    tokio::spawn(async move {
+        if let Err(e) = portal.notify_requester(ticket_id, &message).await {
+            warn!("Failed to email the requester of ticket {}: {}", ticket_id, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activity::ActivityLog;
+    use crate::config::default_config;
+    use crate::tickets::TicketPriority;
+
+    struct Fixture {
+        portal: TicketPortal,
+        tickets: Arc<TicketsManager>,
+        _dir: tempfile::TempDir,
+    }
+
+    fn fixture(key: [u8; 32], config: PortalConfig) -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let defaults = default_config();
+        let tickets = Arc::new(TicketsManager::new(ActivityLog::new()));
+        let portal = TicketPortal::new(
+            config,
+            SecurityManager::new(key, dir.path().to_str().unwrap()).unwrap(),
+            tickets.clone(),
+            Arc::new(Notifier::unavailable(defaults.smtp, defaults.admin_email, "not configured".to_string())),
+        );
+        Fixture { portal, tickets, _dir: dir }
+    }
+
+    fn ticket(tickets: &TicketsManager, requester: Option<&str>) -> Uuid {
+        let id = tickets.create_ticket("VPN drops".to_string(), "details".to_string(), TicketPriority::Medium,
+                                       "alice".to_string(), TicketCategory::Network, Vec::new(), None, None).unwrap();
+        tickets.set_requester(id, requester.map(str::to_string), "alice").unwrap();
+        id
+    }
+
+    fn with_part(token: &str, index: usize, part: &str) -> String {
+        let mut parts: Vec<&str> = token.split('.').collect();
+        parts[index] = part;
+        parts.join(".")
+    }
+
+    #[test]
+    fn valid_token_resolves_to_its_ticket() {
+        let f = fixture([7u8; 32], PortalConfig::default());
+        let id = ticket(&f.tickets, Some("requester@example.com"));
+
+        let token = f.portal.issue(id).unwrap();
+        assert_eq!(f.portal.resolve(&token).map(|t| t.id), Some(id));
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let f = fixture([7u8; 32], PortalConfig::default());
+        let id = ticket(&f.tickets, Some("requester@example.com"));
+        let other = ticket(&f.tickets, Some("other@example.com"));
+        let token = f.portal.issue(id).unwrap();
+        let expires: i64 = token.split('.').nth(1).unwrap().parse().unwrap();
+        let signature = token.split('.').nth(2).unwrap();
+        let flipped: String = signature.chars().rev().collect();
+
+        for tampered in [
+            with_part(&token, 0, &other.simple().to_string()),
+            with_part(&token, 1, &(expires + 86_400).to_string()),
+            with_part(&token, 2, &flipped),
+            with_part(&token, 2, ""),
+            token[..token.rfind('.').unwrap()].to_string(),
+            "not-a-token".to_string(),
+        ] {
+            assert!(f.portal.resolve(&tampered).is_none(), "resolved {}", tampered);
+        }
+
+        // Signed with the instance key, so the ticket's secret alone does not make a link
+        let elsewhere = fixture([8u8; 32], PortalConfig::default());
+        let copy = f.tickets.get_ticket(id).unwrap();
+        elsewhere.tickets.import_ticket(copy, "alice").unwrap();
+        assert!(elsewhere.portal.resolve(&token).is_none());
+    }
+
+    #[test]
+    fn expired_and_revoked_tokens_are_refused() {
+        let f = fixture([7u8; 32], PortalConfig::default());
+        let id = ticket(&f.tickets, Some("requester@example.com"));
+        let secret = f.tickets.portal_secret(id).unwrap();
+        let expired = Utc::now().timestamp() - 1;
+        let token = format!("{}.{}.{}", id.simple(), expired, f.portal.signature(id, expired, &secret));
+        assert!(f.portal.resolve(&token).is_none());
+
+        let token = f.portal.issue(id).unwrap();
+        f.tickets.rotate_portal_secret(id, "admin").unwrap();
+        assert!(f.portal.resolve(&token).is_none());
+        assert!(f.portal.resolve(&f.portal.issue(id).unwrap()).is_some());
+    }
+
+    #[test]
+    fn tickets_without_a_requester_are_refused() {
+        let f = fixture([7u8; 32], PortalConfig::default());
+        let id = ticket(&f.tickets, Some("requester@example.com"));
+        let token = f.portal.issue(id).unwrap();
+
+        f.tickets.set_requester(id, None, "alice").unwrap();
+        assert!(f.portal.resolve(&token).is_none());
+    }
+
+    #[test]
+    fn requests_are_limited_per_address() {
+        let f = fixture([7u8; 32], PortalConfig {
+            requests_per_minute: 2,
+            ..Default::default()
+        });
+
+        assert!(f.portal.allow("198.51.100.7"));
+        assert!(f.portal.allow("198.51.100.7"));
+        assert!(!f.portal.allow("198.51.100.7"));
+        assert!(f.portal.allow("198.51.100.8"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, error};
+
+use crate::auth::AuthUser;
+use crate::models::User;
+use crate::tickets::Ticket;
+
+// Canned reply for ticket comments; {{name}} placeholders are filled in when it is used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    pub id: Uuid,
+    pub name: String,
+    pub body: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    pub owner: String,
+    pub shared: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    // Comments created from it, for pruning unused snippets
+    #[serde(default)]
+    pub usage_count: u64,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl Snippet {
+    // Personal snippets are visible only to their owner, shared ones to all staff
+    pub fn can_view(&self, user: &AuthUser) -> bool {
+        self.owner == user.username || (self.shared && user.is_staff())
+    }
+
+    pub fn can_edit(&self, user: &AuthUser) -> bool {
+        self.owner == user.username || (self.shared && user.is_admin())
+    }
+}
+
+fn placeholder_pattern() -> Regex {
+    Regex::new(r"\{\{\s*([A-Za-z0-9_.]+)\s*\}\}").expect("valid placeholder pattern")
+}
+
+// Placeholder names used in a body, each once, in order of appearance
+pub fn placeholders(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for captures in placeholder_pattern().captures_iter(body) {
+        let name = captures[1].to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+// Values the server knows for a comment on the ticket by the user; empty ones are left
+// out so they count as unresolved
+pub fn context_values(ticket: &Ticket, username: &str, user: Option<&User>) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    values.insert("ticket.id".to_string(), ticket.id.to_string());
+    values.insert("ticket.title".to_string(), ticket.title.clone());
+    values.insert("ticket.status".to_string(), format!("{:?}", ticket.status));
+    values.insert("ticket.priority".to_string(), format!("{:?}", ticket.priority));
+    values.insert("ticket.category".to_string(), format!("{:?}", ticket.category));
+    values.insert("ticket.created_by".to_string(), ticket.created_by.clone());
+    if let Some(assignee) = &ticket.assigned_to {
+        values.insert("ticket.assigned_to".to_string(), assignee.clone());
+    }
+    values.insert("user.username".to_string(), username.to_string());
+    if let Some(user) = user {
+        values.insert("user.name".to_string(), user.full_name.clone());
+        values.insert("user.email".to_string(), user.email.clone());
+    }
+    values.retain(|_, v| !v.trim().is_empty());
+    values
+}
+
+// Fills in every placeholder, or returns the names that have no value
+pub fn expand(body: &str, values: &HashMap<String, String>) -> std::result::Result<String, Vec<String>> {
+    let missing: Vec<String> = placeholders(body).into_iter()
+        .filter(|name| !values.contains_key(name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    Ok(placeholder_pattern()
+        .replace_all(body, |captures: &regex::Captures| values[&captures[1]].clone())
+        .into_owned())
+}
+
+#[derive(Clone)]
+pub struct SnippetManager {
+    snippets_dir: PathBuf,
+    snippets: Arc<Mutex<HashMap<Uuid, Snippet>>>,
+}
+
+impl SnippetManager {
+    pub fn new(snippets_dir: &str) -> Result<Self> {
+        let snippets_dir = PathBuf::from(snippets_dir);
+
+        if !snippets_dir.exists() {
+            fs::create_dir_all(&snippets_dir)
+                .context(format!("Failed to create snippets directory: {:?}", snippets_dir))?;
+            info!("Created snippets directory: {:?}", snippets_dir);
+        }
+
+        let mut snippets = HashMap::new();
+
+        for entry in fs::read_dir(&snippets_dir)? {
+            let path = entry?.path();
+
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+                let loaded = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| serde_json::from_str::<Snippet>(&contents).map_err(anyhow::Error::from));
+
+                match loaded {
+                    Ok(snippet) => {
+                        snippets.insert(snippet.id, snippet);
+                    },
+                    Err(e) => {
+                        error!("Failed to load snippet {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+
+        info!("Loaded {} ticket snippets", snippets.len());
+
+        Ok(Self {
+            snippets_dir,
+            snippets: Arc::new(Mutex::new(snippets)),
+        })
+    }
+
+    fn save_snippet(&self, snippet: &Snippet) -> Result<()> {
+        let file_path = self.snippets_dir.join(format!("{}.json", snippet.id));
+        let json = serde_json::to_string_pretty(snippet)?;
+        fs::write(file_path, json)?;
+        Ok(())
+    }
+
+    pub fn create_snippet(&self,
+                          name: String,
+                          body: String,
+                          category: Option<String>,
+                          owner: String,
+                          shared: bool) -> Result<Snippet> {
+        let now = Utc::now();
+        let snippet = Snippet {
+            id: Uuid::new_v4(),
+            name,
+            body,
+            category,
+            owner,
+            shared,
+            created_at: now,
+            updated_at: now,
+            usage_count: 0,
+            last_used_at: None,
+        };
+
+        self.save_snippet(&snippet)?;
+
+        match self.snippets.lock() {
+            Ok(mut snippets) => {
+                snippets.insert(snippet.id, snippet.clone());
+                Ok(snippet)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on snippets")),
+        }
+    }
+
+    pub fn update_snippet(&self,
+                          id: Uuid,
+                          name: Option<String>,
+                          body: Option<String>,
+                          category: Option<Option<String>>,
+                          shared: Option<bool>) -> Result<Snippet> {
+        let updated = match self.snippets.lock() {
+            Ok(mut snippets) => {
+                let snippet = snippets.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Snippet not found: {}", id))?;
+
+                if let Some(name) = name {
+                    snippet.name = name;
+                }
+
+                if let Some(body) = body {
+                    snippet.body = body;
+                }
+
+                if let Some(category) = category {
+                    snippet.category = category;
+                }
+
+                if let Some(shared) = shared {
+                    snippet.shared = shared;
+                }
+
+                snippet.updated_at = Utc::now();
+                snippet.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on snippets")),
+        };
+
+        self.save_snippet(&updated)?;
+        Ok(updated)
+    }
+
+    pub fn delete_snippet(&self, id: Uuid) -> Result<()> {
+        match self.snippets.lock() {
+            Ok(mut snippets) => {
+                if snippets.remove(&id).is_none() {
+                    return Err(anyhow!("Snippet not found: {}", id));
+                }
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on snippets")),
+        }
+
+        let file_path = self.snippets_dir.join(format!("{}.json", id));
+        fs::remove_file(file_path)?;
+
+        Ok(())
+    }
+
+    pub fn get_snippet(&self, id: Uuid) -> Result<Option<Snippet>> {
+        match self.snippets.lock() {
+            Ok(snippets) => Ok(snippets.get(&id).cloned()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on snippets")),
+        }
+    }
+
+    // Snippets the user can see, by category then name
+    pub fn get_visible_snippets(&self, user: &AuthUser, category: Option<&str>) -> Result<Vec<Snippet>> {
+        match self.snippets.lock() {
+            Ok(snippets) => {
+                let mut visible: Vec<Snippet> = snippets.values()
+                    .filter(|s| s.can_view(user))
+                    .filter(|s| category.map_or(true, |c| s.category.as_deref() == Some(c)))
+                    .cloned()
+                    .collect();
+                visible.sort_by(|a, b| a.category.cmp(&b.category).then(a.name.cmp(&b.name)));
+                Ok(visible)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on snippets")),
+        }
+    }
+
+    pub fn record_use(&self, id: Uuid) -> Result<()> {
+        let updated = match self.snippets.lock() {
+            Ok(mut snippets) => {
+                let snippet = snippets.get_mut(&id)
+                    .ok_or_else(|| anyhow!("Snippet not found: {}", id))?;
+                snippet.usage_count += 1;
+                snippet.last_used_at = Some(Utc::now());
+                snippet.clone()
+            },
+            Err(_) => return Err(anyhow!("Failed to acquire lock on snippets")),
+        };
+
+        self.save_snippet(&updated)
+    }
+}
@@ -1,10 +1,17 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use anyhow::{Result, anyhow};
 
+use crate::activity::{ActivityKind, ActivityLog, ResourceKind};
+use crate::config::{ReferencesConfig, TicketWorklogConfig};
+use crate::locks::{LockHealth, LockHealthStatus};
+use crate::references::{self, ExternalReference, ReferenceInput};
+use crate::tags::{self, TaggedStore};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ticket {
     pub id: Uuid,
@@ -18,10 +25,86 @@ pub struct Ticket {
     pub assigned_to: Option<String>,
     pub comments: Vec<TicketComment>,
     pub attachments: Vec<TicketAttachment>,
+    // Time spent on the ticket, for billing
+    #[serde(default)]
+    pub worklogs: Vec<TicketWorklog>,
     pub category: TicketCategory,
     pub tags: Vec<String>,
     pub due_date: Option<DateTime<Utc>>, //Added from original code
     pub resolution: Option<String>, //Added from original code
+    #[serde(default)]
+    pub linked_alerts: Vec<Uuid>,
+    // Set when the auto-close warning was posted, see ticket_autoclose
+    #[serde(default)]
+    pub inactivity_warned_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
+    // End user without an account who reported the ticket, reached through portal links
+    #[serde(default)]
+    pub requester_email: Option<String>,
+    // Mixed into every portal token of the ticket; rotating it revokes issued links
+    #[serde(default)]
+    pub portal_secret: Option<String>,
+    // When the ticket missed its SLA target, see sla; kept once set
+    #[serde(default)]
+    pub sla_breached_at: Option<DateTime<Utc>>,
+    // Place in its status column on the kanban board, see move_on_board; cleared when
+    // the status changes otherwise
+    #[serde(default)]
+    pub board_position: Option<i64>,
+    // Upstream issues, vendor cases and CVEs the ticket relates to, see references
+    #[serde(default)]
+    pub references: Vec<ExternalReference>,
+}
+
+// Tickets with this tag are never closed for inactivity
+pub const NO_AUTOCLOSE_TAG: &str = "no-autoclose";
+
+// Resolution of tickets closed for inactivity
+pub const AUTO_CLOSED_RESOLUTION: &str = "auto-closed after inactivity";
+
+// Room left between neighbouring cards when a board column is renumbered
+const BOARD_GAP: i64 = 1024;
+
+// Order of the cards in a board column: placed tickets by position, then the others
+// oldest first
+pub fn board_order(a: &Ticket, b: &Ticket) -> Ordering {
+    (a.board_position.is_none(), a.board_position, a.created_at, a.id)
+        .cmp(&(b.board_position.is_none(), b.board_position, b.created_at, b.id))
+}
+
+// Where a ticket ended up on the board
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardMove {
+    pub ticket_id: Uuid,
+    pub status: TicketStatus,
+    pub board_position: i64,
+    // Whether the column was renumbered to make room
+    pub rebalanced: bool,
+}
+
+// What an alert shows of the ticket linked to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub status: TicketStatus,
+    pub priority: TicketPriority,
+    pub assigned_to: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<&Ticket> for TicketSummary {
+    fn from(ticket: &Ticket) -> Self {
+        Self {
+            id: ticket.id,
+            title: ticket.title.clone(),
+            status: ticket.status.clone(),
+            priority: ticket.priority.clone(),
+            assigned_to: ticket.assigned_to.clone(),
+            updated_at: ticket.updated_at,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -42,7 +125,7 @@ pub enum TicketPriority {
     Critical,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum TicketCategory {
     Access,
     Hardware,
@@ -73,18 +156,94 @@ pub struct TicketAttachment {
     pub created_by: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketWorklog {
+    pub id: Uuid,
+    pub ticket_id: Uuid,
+    pub author: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_minutes: u32,
+    pub note: String,
+    pub billable: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+// What a worklog entry is created or edited with
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorklogInput {
+    pub started_at: DateTime<Utc>,
+    pub duration_minutes: u32,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default = "default_billable")]
+    pub billable: bool,
+}
+
+fn default_billable() -> bool {
+    true
+}
+
+impl WorklogInput {
+    fn validate(&self, config: &TicketWorklogConfig) -> Result<()> {
+        if self.duration_minutes == 0 {
+            return Err(anyhow!("Duration must be at least one minute"));
+        }
+        if self.duration_minutes > config.max_duration_minutes {
+            return Err(anyhow!("Duration of {} minutes is over the limit of {} minutes for one entry",
+                               self.duration_minutes, config.max_duration_minutes));
+        }
+        // Some slack for clocks that are a little ahead
+        if self.started_at > Utc::now() + chrono::Duration::minutes(5) {
+            return Err(anyhow!("Work cannot start in the future"));
+        }
+        Ok(())
+    }
+}
+
+// Logged time of a ticket
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketWorklogs {
+    pub ticket_id: Uuid,
+    pub entries: Vec<TicketWorklog>,
+    pub total_minutes: u64,
+    pub billable_minutes: u64,
+}
+
+fn new_portal_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
 #[derive(Clone)]
 pub struct TicketsManager {
     tickets: Arc<Mutex<HashMap<Uuid, Ticket>>>,
+    activity: ActivityLog,
+    health: LockHealth,
 }
 
 impl TicketsManager {
-    pub fn new() -> Self {
+    pub fn new(activity: ActivityLog) -> Self {
         Self {
             tickets: Arc::new(Mutex::new(HashMap::new())),
+            activity,
+            health: LockHealth::new("tickets"),
         }
     }
 
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, Ticket>> {
+        self.health.lock(&self.tickets)
+    }
+
+    pub fn lock_health(&self) -> LockHealthStatus {
+        self.health.status()
+    }
+
+    // Every mutation below appends to the ticket's activity feed
+    fn record(&self, ticket_id: Uuid, actor: &str, kind: ActivityKind, payload: serde_json::Value) -> Result<()> {
+        self.activity.record(ResourceKind::Ticket, &ticket_id.to_string(), actor, kind, payload)?;
+        Ok(())
+    }
+
     pub fn create_ticket(&self, 
                       title: String, 
                       description: String, 
@@ -92,7 +251,8 @@ impl TicketsManager {
                       created_by: String,
                       category: TicketCategory,
                       tags: Vec<String>,
-                      due_date: Option<DateTime<Utc>>) -> Result<Uuid> { //Added due_date
+                      due_date: Option<DateTime<Utc>>, //Added due_date
+                      site_id: Option<Uuid>) -> Result<Uuid> {
         let id = Uuid::new_v4();
         let now = Utc::now();
 
@@ -104,23 +264,68 @@ impl TicketsManager {
             priority,
             created_at: now,
             updated_at: now,
-            created_by,
+            created_by: created_by.clone(),
             assigned_to: None,
             comments: Vec::new(),
             attachments: Vec::new(),
+            worklogs: Vec::new(),
             category,
-            tags,
+            tags: tags::normalize_all(tags),
             due_date, //Added due_date
             resolution: None, //Added resolution
+            linked_alerts: Vec::new(),
+            inactivity_warned_at: None,
+            site_id,
+            requester_email: None,
+            portal_secret: None,
+            sla_breached_at: None,
+            board_position: None,
+            references: Vec::new(),
         };
 
-        match self.tickets.lock() {
-            Ok(mut tickets) => {
-                tickets.insert(id, ticket);
-                Ok(id)
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
+        let mut tickets = self.lock();
+        self.record(id, &created_by, ActivityKind::Created, serde_json::json!({
+            "title": ticket.title,
+            "priority": ticket.priority,
+            "category": ticket.category,
+        }))?;
+        tickets.insert(id, ticket);
+        Ok(id)
+    }
+
+    // Adds a ticket built elsewhere (see ticket_import), keeping its own creation date
+    pub fn import_ticket(&self, mut ticket: Ticket, imported_by: &str) -> Result<Uuid> {
+        let id = ticket.id;
+        ticket.tags = tags::normalize_all(&ticket.tags);
+
+        let mut tickets = self.lock();
+        if tickets.contains_key(&id) {
+            return Err(anyhow!("Ticket already exists: {}", id));
         }
+
+        self.record(id, imported_by, ActivityKind::Created, serde_json::json!({
+            "title": ticket.title,
+            "priority": ticket.priority,
+            "category": ticket.category,
+            "imported": true,
+            "created_at": ticket.created_at,
+        }))?;
+        tickets.insert(id, ticket);
+        Ok(id)
+    }
+
+    // The primary's copy of a ticket, replacing the local one, see replication
+    pub fn replicate_ticket(&self, ticket: Ticket, replicated_by: &str) -> Result<()> {
+        let id = ticket.id;
+        let mut tickets = self.lock();
+        let kind = if tickets.contains_key(&id) { ActivityKind::Updated } else { ActivityKind::Created };
+        self.record(id, replicated_by, kind, serde_json::json!({
+            "title": ticket.title,
+            "status": ticket.status,
+            "replicated": true,
+        }))?;
+        tickets.insert(id, ticket);
+        Ok(())
     }
 
     pub fn update_ticket(&self, 
@@ -133,79 +338,140 @@ impl TicketsManager {
                       category: Option<TicketCategory>,
                       tags: Option<Vec<String>>,
                       resolution: Option<Option<String>>, //Added resolution
-                      due_date: Option<Option<DateTime<Utc>>>) -> Result<()> { //Added due_date
-        match self.tickets.lock() {
-            Ok(mut tickets) => {
-                let ticket = tickets.get_mut(&id)
-                    .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
-
-                if let Some(title) = title {
-                    ticket.title = title;
-                }
+                      due_date: Option<Option<DateTime<Utc>>>, //Added due_date
+                      updated_by: String) -> Result<()> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
 
-                if let Some(description) = description {
-                    ticket.description = description;
-                }
+        let mut updated_fields = Vec::new();
 
-                if let Some(status) = status {
-                    ticket.status = status;
-                }
+        if let Some(title) = title {
+            ticket.title = title;
+            updated_fields.push("title");
+        }
 
-                if let Some(priority) = priority {
-                    ticket.priority = priority;
-                }
+        if let Some(description) = description {
+            ticket.description = description;
+            updated_fields.push("description");
+        }
 
-                if let Some(assigned_to) = assigned_to {
-                    ticket.assigned_to = assigned_to.into_iter().flatten(); // Correctly flatten Option<Option<T>>
-                }
+        if let Some(status) = status {
+            if status != ticket.status {
+                self.record(id, &updated_by, ActivityKind::StatusChanged, serde_json::json!({
+                    "from": ticket.status,
+                    "to": status,
+                }))?;
+                ticket.board_position = None;
+            }
+            ticket.status = status;
+        }
 
-                if let Some(category) = category {
-                    ticket.category = category;
-                }
+        if let Some(priority) = priority {
+            ticket.priority = priority;
+            updated_fields.push("priority");
+        }
 
-                if let Some(tags) = tags {
-                    ticket.tags = tags;
-                }
+        if let Some(assigned_to) = assigned_to {
+            if assigned_to != ticket.assigned_to {
+                self.record(id, &updated_by, ActivityKind::AssignmentChanged, serde_json::json!({
+                    "from": ticket.assigned_to,
+                    "to": assigned_to,
+                }))?;
+            }
+            ticket.assigned_to = assigned_to;
+        }
 
-                if let Some(resolution) = resolution {
-                    ticket.resolution = resolution.into_iter().flatten(); // Correctly flatten Option<Option<T>>
-                }
+        if let Some(category) = category {
+            ticket.category = category;
+            updated_fields.push("category");
+        }
 
-                if let Some(due_date) = due_date {
-                    ticket.due_date = due_date.into_iter().flatten(); // Correctly flatten Option<Option<DateTime<Utc>>>
-                }
+        if let Some(tags) = tags {
+            ticket.tags = tags::normalize_all(tags);
+            updated_fields.push("tags");
+        }
 
-                ticket.updated_at = Utc::now();
+        if let Some(resolution) = resolution {
+            ticket.resolution = resolution;
+            updated_fields.push("resolution");
+        }
 
-                Ok(())
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
+        if let Some(due_date) = due_date {
+            ticket.due_date = due_date;
+            updated_fields.push("due_date");
+        }
+
+        if !updated_fields.is_empty() {
+            self.record(id, &updated_by, ActivityKind::Updated, serde_json::json!({
+                "fields": updated_fields,
+            }))?;
         }
+
+        ticket.updated_at = Utc::now();
+
+        Ok(())
     }
 
     pub fn add_comment(&self, ticket_id: Uuid, content: String, created_by: String, is_internal: bool) -> Result<Uuid> { //Added is_internal
-        match self.tickets.lock() {
-            Ok(mut tickets) => {
-                let ticket = tickets.get_mut(&ticket_id)
-                    .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
-
-                let comment_id = Uuid::new_v4();
-                let comment = TicketComment {
-                    id: comment_id,
-                    ticket_id,
-                    content,
-                    created_at: Utc::now(),
-                    created_by,
-                    is_internal, //Added is_internal
-                };
-
-                ticket.comments.push(comment);
-                ticket.updated_at = Utc::now();
-
-                Ok(comment_id)
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
-        }
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let comment_id = Uuid::new_v4();
+        let comment = TicketComment {
+            id: comment_id,
+            ticket_id,
+            content,
+            created_at: Utc::now(),
+            created_by: created_by.clone(),
+            is_internal, //Added is_internal
+        };
+
+        let kind = if is_internal { ActivityKind::InternalNote } else { ActivityKind::CommentAdded };
+        self.record(ticket_id, &created_by, kind, serde_json::json!({
+            "comment_id": comment_id,
+            "content": comment.content,
+        }))?;
+
+        ticket.comments.push(comment);
+        ticket.updated_at = Utc::now();
+
+        Ok(comment_id)
+    }
+
+    pub fn set_requester(&self, id: Uuid, email: Option<String>, updated_by: &str) -> Result<()> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
+
+        self.record(id, updated_by, ActivityKind::Updated, serde_json::json!({
+            "fields": ["requester_email"],
+        }))?;
+        ticket.requester_email = email;
+        ticket.updated_at = Utc::now();
+        Ok(())
+    }
+
+    // The ticket's portal secret, created on first use
+    pub fn portal_secret(&self, id: Uuid) -> Result<String> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
+        Ok(ticket.portal_secret.get_or_insert_with(new_portal_secret).clone())
+    }
+
+    // Replaces the portal secret, so every link issued so far stops working
+    pub fn rotate_portal_secret(&self, id: Uuid, rotated_by: &str) -> Result<()> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
+
+        self.record(id, rotated_by, ActivityKind::Updated, serde_json::json!({
+            "fields": ["portal_secret"],
+        }))?;
+        ticket.portal_secret = Some(new_portal_secret());
+        Ok(())
     }
 
     pub fn add_attachment(&self, 
@@ -214,62 +480,548 @@ impl TicketsManager {
                        content_type: String, 
                        size: usize, 
                        created_by: String) -> Result<Uuid> {
-        match self.tickets.lock() {
-            Ok(mut tickets) => {
-                let ticket = tickets.get_mut(&ticket_id)
-                    .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
-
-                let attachment_id = Uuid::new_v4();
-                let attachment = TicketAttachment {
-                    id: attachment_id,
-                    ticket_id,
-                    filename,
-                    content_type,
-                    size,
-                    created_at: Utc::now(),
-                    created_by,
-                };
-
-                ticket.attachments.push(attachment);
-                ticket.updated_at = Utc::now();
-
-                Ok(attachment_id)
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
-        }
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let attachment_id = Uuid::new_v4();
+        let attachment = TicketAttachment {
+            id: attachment_id,
+            ticket_id,
+            filename,
+            content_type,
+            size,
+            created_at: Utc::now(),
+            created_by: created_by.clone(),
+        };
+
+        self.record(ticket_id, &created_by, ActivityKind::AttachmentAdded, serde_json::json!({
+            "attachment_id": attachment_id,
+            "filename": attachment.filename,
+            "size": attachment.size,
+        }))?;
+
+        ticket.attachments.push(attachment);
+        ticket.updated_at = Utc::now();
+
+        Ok(attachment_id)
     }
 
     pub fn get_ticket(&self, id: Uuid) -> Result<Ticket> {
-        match self.tickets.lock() {
-            Ok(tickets) => {
-                tickets.get(&id)
-                    .cloned()
-                    .ok_or_else(|| anyhow!("Ticket not found: {}", id))
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
-        }
+        let tickets = self.lock();
+        tickets.get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))
     }
 
     pub fn get_all_tickets(&self) -> Result<Vec<Ticket>> {
-        match self.tickets.lock() {
-            Ok(tickets) => {
-                Ok(tickets.values().cloned().collect())
-            },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
+        let tickets = self.lock();
+        Ok(tickets.values().cloned().collect())
+    }
+
+    // Alert id -> the earliest created ticket linking it, among the tickets that satisfy
+    // the predicate, in a single pass over the tickets
+    pub fn tickets_for_alerts<F>(&self, alert_ids: &[Uuid], predicate: F) -> Result<HashMap<Uuid, TicketSummary>>
+    where
+        F: Fn(&Ticket) -> bool,
+    {
+        let wanted: HashSet<&Uuid> = alert_ids.iter().collect();
+
+        let tickets = self.lock();
+        let mut linked: HashMap<Uuid, &Ticket> = HashMap::new();
+        for ticket in tickets.values().filter(|t| !t.linked_alerts.is_empty() && predicate(t)) {
+            for alert_id in ticket.linked_alerts.iter().filter(|id| wanted.contains(id)) {
+                let earliest = linked.entry(*alert_id).or_insert(ticket);
+                if ticket.created_at < earliest.created_at {
+                    *earliest = ticket;
+                }
+            }
+        }
+        Ok(linked.into_iter().map(|(id, t)| (id, TicketSummary::from(t))).collect())
+    }
+
+    // Tickets created within the range that satisfy the predicate, newest first
+    pub fn tickets_between<F>(&self,
+                              from: Option<DateTime<Utc>>,
+                              to: Option<DateTime<Utc>>,
+                              limit: usize,
+                              predicate: F) -> Result<Vec<Ticket>>
+    where
+        F: Fn(&Ticket) -> bool,
+    {
+        let tickets = self.lock();
+        let mut matching: Vec<&Ticket> = tickets.values()
+            .filter(|t| from.map_or(true, |from| t.created_at >= from)
+                && to.map_or(true, |to| t.created_at <= to)
+                && predicate(t))
+            .collect();
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(matching.into_iter().take(limit).cloned().collect())
+    }
+
+    // Marks an open ticket as having missed its SLA target and records the breach in its
+    // activity feed. Returns false if it was already marked or is no longer open, so a
+    // breach is recorded once whatever happens to the calendar or policy afterwards.
+    pub fn mark_sla_breached(&self, ticket_id: Uuid, at: DateTime<Utc>, details: serde_json::Value) -> Result<bool> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        if ticket.sla_breached_at.is_some() || matches!(ticket.status, TicketStatus::Resolved | TicketStatus::Closed) {
+            return Ok(false);
         }
+
+        self.record(ticket_id, "system", ActivityKind::SlaEvent, serde_json::json!({
+            "event": "breach",
+            "details": details,
+        }))?;
+        ticket.sla_breached_at = Some(at);
+        Ok(true)
     }
 
-    pub fn delete_ticket(&self, id: Uuid) -> Result<()> {
-        match self.tickets.lock() {
-            Ok(mut tickets) => {
-                if tickets.remove(&id).is_none() {
-                    return Err(anyhow!("Ticket not found: {}", id));
+    // Posts the inactivity warning unless the ticket changed since `last_activity` or was
+    // already warned; the warning leaves updated_at alone so it does not restart the
+    // clock. Returns whether the comment was posted.
+    pub fn warn_inactive(&self, ticket_id: Uuid, last_activity: DateTime<Utc>, content: String) -> Result<bool> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        if ticket.status != TicketStatus::Resolved
+            || ticket.updated_at != last_activity
+            || ticket.inactivity_warned_at.map_or(false, |warned| warned >= ticket.updated_at) {
+            return Ok(false);
+        }
+
+        let now = Utc::now();
+        let comment_id = Uuid::new_v4();
+        self.record(ticket_id, "system", ActivityKind::CommentAdded, serde_json::json!({
+            "comment_id": comment_id,
+            "content": content,
+            "reason": "inactivity_warning",
+        }))?;
+
+        ticket.comments.push(TicketComment {
+            id: comment_id,
+            ticket_id,
+            content,
+            created_at: now,
+            created_by: "system".to_string(),
+            is_internal: false,
+        });
+        ticket.inactivity_warned_at = Some(now);
+        Ok(true)
+    }
+
+    // Closes a ticket warned at `warned_at` that saw no activity since. Returns whether
+    // the ticket was closed.
+    pub fn close_inactive(&self, ticket_id: Uuid, warned_at: DateTime<Utc>) -> Result<bool> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        if ticket.status != TicketStatus::Resolved
+            || ticket.inactivity_warned_at != Some(warned_at)
+            || ticket.updated_at > warned_at {
+            return Ok(false);
+        }
+
+        self.record(ticket_id, "system", ActivityKind::StatusChanged, serde_json::json!({
+            "from": ticket.status,
+            "to": TicketStatus::Closed,
+            "reason": AUTO_CLOSED_RESOLUTION,
+        }))?;
+
+        ticket.status = TicketStatus::Closed;
+        ticket.resolution = Some(match ticket.resolution.take() {
+            Some(resolution) if !resolution.is_empty() => format!("{} ({})", resolution, AUTO_CLOSED_RESOLUTION),
+            _ => AUTO_CLOSED_RESOLUTION.to_string(),
+        });
+        ticket.updated_at = Utc::now();
+        Ok(true)
+    }
+
+    // Moves a ticket on the board, into the `status` column when given, right after
+    // `after`, right before `before`, or to the end of the column with neither. With both,
+    // they must be next to each other, so a move made on an outdated view of the column
+    // fails instead of landing somewhere else. The column is renumbered when there is no
+    // room between the neighbours. All of it happens under the tickets lock, so concurrent
+    // moves within a column are applied one after the other on each other's result.
+    // Reordering alone leaves updated_at alone, as it is not activity on the ticket.
+    pub fn move_on_board(&self,
+                         id: Uuid,
+                         status: Option<TicketStatus>,
+                         after: Option<Uuid>,
+                         before: Option<Uuid>,
+                         moved_by: &str) -> Result<BoardMove> {
+        let mut tickets = self.lock();
+        let current = tickets.get(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?
+            .status.clone();
+        let status = status.unwrap_or(current.clone());
+        if after == Some(id) || before == Some(id) {
+            return Err(anyhow!("A ticket cannot be placed next to itself"));
+        }
+
+        let mut column: Vec<&Ticket> = tickets.values()
+            .filter(|t| t.status == status && t.id != id)
+            .collect();
+        column.sort_by(|a, b| board_order(a, b));
+        let index_of = |neighbour: Uuid| column.iter()
+            .position(|t| t.id == neighbour)
+            .ok_or_else(|| anyhow!("Ticket {} is not in the {:?} column", neighbour, status));
+        let index = match (after, before) {
+            (Some(after), Some(before)) => {
+                let index = index_of(after)? + 1;
+                if index_of(before)? != index {
+                    return Err(anyhow!("Tickets {} and {} are no longer next to each other", after, before));
                 }
-                Ok(())
+                index
+            },
+            (Some(after), None) => index_of(after)? + 1,
+            (None, Some(before)) => index_of(before)?,
+            (None, None) => column.len(),
+        };
+
+        let previous = index.checked_sub(1).map(|i| column[i].board_position);
+        // Unplaced tickets sort after every placed one
+        let next = column.get(index).and_then(|t| t.board_position);
+        let position = match (previous, next) {
+            (None, None) => Some(BOARD_GAP),
+            (None, Some(next)) => Some(next - BOARD_GAP),
+            (Some(Some(previous)), None) => Some(previous + BOARD_GAP),
+            (Some(Some(previous)), Some(next)) if next - previous > 1 => Some(previous + (next - previous) / 2),
+            _ => None,
+        };
+
+        let renumbered: Vec<Uuid> = match position {
+            Some(_) => Vec::new(),
+            None => {
+                let mut order: Vec<Uuid> = column.iter().map(|t| t.id).collect();
+                order.insert(index, id);
+                order
             },
-            Err(_) => Err(anyhow!("Failed to acquire lock on tickets")),
+        };
+        for (i, ticket_id) in renumbered.iter().enumerate() {
+            if let Some(ticket) = tickets.get_mut(ticket_id) {
+                ticket.board_position = Some((i as i64 + 1) * BOARD_GAP);
+            }
+        }
+
+        let ticket = tickets.get_mut(&id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", id))?;
+        if let Some(position) = position {
+            ticket.board_position = Some(position);
         }
+        if status != current {
+            self.record(id, moved_by, ActivityKind::StatusChanged, serde_json::json!({
+                "from": current,
+                "to": status,
+                "board": true,
+            }))?;
+            ticket.status = status.clone();
+            ticket.updated_at = Utc::now();
+        }
+
+        Ok(BoardMove {
+            ticket_id: id,
+            status,
+            board_position: ticket.board_position.unwrap_or_default(),
+            rebalanced: !renumbered.is_empty(),
+        })
+    }
+
+    // Returns false when the alert was already linked
+    pub fn link_alert(&self, ticket_id: Uuid, alert_id: Uuid, linked_by: &str) -> Result<bool> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        if ticket.linked_alerts.contains(&alert_id) {
+            return Ok(false);
+        }
+
+        self.record(ticket_id, linked_by, ActivityKind::AlertLinked, serde_json::json!({
+            "alert_id": alert_id,
+        }))?;
+        ticket.linked_alerts.push(alert_id);
+        ticket.updated_at = Utc::now();
+        Ok(true)
+    }
+
+    pub fn worklogs(&self, ticket_id: Uuid) -> Result<TicketWorklogs> {
+        let tickets = self.lock();
+        let ticket = tickets.get(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let mut entries = ticket.worklogs.clone();
+        entries.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+        Ok(TicketWorklogs {
+            ticket_id,
+            total_minutes: entries.iter().map(|w| w.duration_minutes as u64).sum(),
+            billable_minutes: entries.iter().filter(|w| w.billable).map(|w| w.duration_minutes as u64).sum(),
+            entries,
+        })
+    }
+
+    pub fn add_worklog(&self, ticket_id: Uuid, author: &str, input: WorklogInput, config: &TicketWorklogConfig) -> Result<TicketWorklog> {
+        input.validate(config)?;
+
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let worklog = TicketWorklog {
+            id: Uuid::new_v4(),
+            ticket_id,
+            author: author.to_string(),
+            started_at: input.started_at,
+            duration_minutes: input.duration_minutes,
+            note: input.note,
+            billable: input.billable,
+            created_at: Utc::now(),
+            updated_at: None,
+        };
+
+        self.record(ticket_id, author, ActivityKind::WorklogAdded, serde_json::json!({
+            "worklog_id": worklog.id,
+            "started_at": worklog.started_at,
+            "duration_minutes": worklog.duration_minutes,
+            "billable": worklog.billable,
+            "note": worklog.note,
+        }))?;
+
+        ticket.worklogs.push(worklog.clone());
+        ticket.updated_at = Utc::now();
+        Ok(worklog)
+    }
+
+    // Authors can edit their entries for edit_grace_minutes after creating them, admins at
+    // any time. Returns the entry before and after the edit.
+    pub fn update_worklog(&self,
+                          ticket_id: Uuid,
+                          worklog_id: Uuid,
+                          editor: &str,
+                          is_admin: bool,
+                          input: WorklogInput,
+                          config: &TicketWorklogConfig) -> Result<(TicketWorklog, TicketWorklog)> {
+        input.validate(config)?;
+
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+        let worklog = ticket.worklogs.iter_mut()
+            .find(|w| w.id == worklog_id)
+            .ok_or_else(|| anyhow!("Worklog not found: {}", worklog_id))?;
+
+        if !is_admin {
+            if worklog.author != editor {
+                return Err(anyhow!("Only the author or an admin can edit a worklog"));
+            }
+            let grace_ends = worklog.created_at + chrono::Duration::minutes(config.edit_grace_minutes as i64);
+            if Utc::now() > grace_ends {
+                return Err(anyhow!("Worklog can no longer be edited, the grace period ended at {}", grace_ends));
+            }
+        }
+
+        let before = worklog.clone();
+        worklog.started_at = input.started_at;
+        worklog.duration_minutes = input.duration_minutes;
+        worklog.note = input.note;
+        worklog.billable = input.billable;
+        worklog.updated_at = Some(Utc::now());
+        let after = worklog.clone();
+
+        self.record(ticket_id, editor, ActivityKind::WorklogUpdated, serde_json::json!({
+            "worklog_id": worklog_id,
+            "before": {
+                "started_at": before.started_at,
+                "duration_minutes": before.duration_minutes,
+                "billable": before.billable,
+                "note": before.note,
+            },
+            "after": {
+                "started_at": after.started_at,
+                "duration_minutes": after.duration_minutes,
+                "billable": after.billable,
+                "note": after.note,
+            },
+        }))?;
+
+        ticket.updated_at = Utc::now();
+        Ok((before, after))
+    }
+
+    // Only the author or an admin can delete an entry
+    pub fn delete_worklog(&self, ticket_id: Uuid, worklog_id: Uuid, deleted_by: &str, is_admin: bool) -> Result<TicketWorklog> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+        let index = ticket.worklogs.iter()
+            .position(|w| w.id == worklog_id)
+            .ok_or_else(|| anyhow!("Worklog not found: {}", worklog_id))?;
+
+        if !is_admin && ticket.worklogs[index].author != deleted_by {
+            return Err(anyhow!("Only the author or an admin can delete a worklog"));
+        }
+
+        self.record(ticket_id, deleted_by, ActivityKind::WorklogDeleted, serde_json::json!({
+            "worklog_id": worklog_id,
+            "author": ticket.worklogs[index].author,
+            "duration_minutes": ticket.worklogs[index].duration_minutes,
+        }))?;
+
+        let worklog = ticket.worklogs.remove(index);
+        ticket.updated_at = Utc::now();
+        Ok(worklog)
+    }
+
+    pub fn add_reference(&self, ticket_id: Uuid, input: ReferenceInput, added_by: &str, config: &ReferencesConfig) -> Result<ExternalReference> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let mut updated = ticket.references.clone();
+        let reference = references::add(&mut updated, input, added_by, config)?;
+        self.record(ticket_id, added_by, ActivityKind::ReferenceAdded, references::payload(&reference))?;
+
+        ticket.references = updated;
+        ticket.updated_at = Utc::now();
+        Ok(reference)
+    }
+
+    // Returns the reference before and after the update
+    pub fn update_reference(&self,
+                            ticket_id: Uuid,
+                            reference_id: Uuid,
+                            input: ReferenceInput,
+                            updated_by: &str,
+                            config: &ReferencesConfig) -> Result<(ExternalReference, ExternalReference)> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let mut updated = ticket.references.clone();
+        let (before, after) = references::update(&mut updated, reference_id, input, config)?;
+        self.record(ticket_id, updated_by, ActivityKind::ReferenceUpdated, serde_json::json!({
+            "before": references::payload(&before),
+            "after": references::payload(&after),
+        }))?;
+
+        ticket.references = updated;
+        ticket.updated_at = Utc::now();
+        Ok((before, after))
+    }
+
+    pub fn remove_reference(&self, ticket_id: Uuid, reference_id: Uuid, removed_by: &str) -> Result<ExternalReference> {
+        let mut tickets = self.lock();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or_else(|| anyhow!("Ticket not found: {}", ticket_id))?;
+
+        let mut updated = ticket.references.clone();
+        let reference = references::remove(&mut updated, reference_id)?;
+        self.record(ticket_id, removed_by, ActivityKind::ReferenceRemoved, references::payload(&reference))?;
+
+        ticket.references = updated;
+        ticket.updated_at = Utc::now();
+        Ok(reference)
+    }
+
+    pub fn delete_ticket(&self, id: Uuid, deleted_by: String) -> Result<()> {
+        let mut tickets = self.lock();
+        if tickets.remove(&id).is_none() {
+            return Err(anyhow!("Ticket not found: {}", id));
+        }
+
+        // The feed outlives the ticket so the deletion stays traceable
+        self.record(id, &deleted_by, ActivityKind::Deleted, serde_json::json!({}))?;
+        Ok(())
+    }
+}
+
+impl TaggedStore for TicketsManager {
+    fn resource(&self) -> &'static str {
+        "tickets"
+    }
+
+    fn tag_counts(&self) -> Result<HashMap<String, usize>> {
+        Ok(tags::count(self.lock().values().map(|t| &t.tags)))
+    }
+
+    fn retag(&self, from: &[String], to: &str) -> Result<Vec<(Uuid, Vec<String>)>> {
+        let mut tickets = self.lock();
+        let mut previous = Vec::new();
+        for ticket in tickets.values_mut() {
+            if let Some(tags) = tags::replaced(&ticket.tags, from, to) {
+                previous.push((ticket.id, std::mem::replace(&mut ticket.tags, tags)));
+            }
+        }
+        Ok(previous)
+    }
+
+    fn restore_tags(&self, previous: &[(Uuid, Vec<String>)]) -> Result<()> {
+        let mut tickets = self.lock();
+        for (id, tags) in previous {
+            if let Some(ticket) = tickets.get_mut(id) {
+                ticket.tags = tags.clone();
+            }
+        }
+        Ok(())
     }
 }
 
-//The rest of the original code is removed because it's replaced by TicketsManager.
\ No newline at end of file
+//The rest of the original code is removed because it's replaced by TicketsManager.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    fn create(manager: &TicketsManager, title: &str) -> Uuid {
+        manager.create_ticket(title.to_string(), "details".to_string(), TicketPriority::Medium,
+                              "alice".to_string(), TicketCategory::Network, Vec::new(), None, None).unwrap()
+    }
+
+    #[test]
+    fn panic_inside_a_critical_section_leaves_tickets_usable() {
+        let manager = TicketsManager::new(ActivityLog::new());
+        let id = create(&manager, "Printer offline");
+
+        // A predicate runs while the ticket lock is held
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            manager.tickets_between(None, None, 10, |_| panic!("injected"))
+        }));
+        assert!(result.is_err());
+        assert!(manager.tickets.is_poisoned());
+
+        manager.add_comment(id, "Still there".to_string(), "bob".to_string(), false).unwrap();
+        let second = create(&manager, "VPN down");
+        assert_eq!(manager.get_ticket(id).unwrap().comments.len(), 1);
+        assert_eq!(manager.get_ticket(second).unwrap().title, "VPN down");
+        assert_eq!(manager.get_all_tickets().unwrap().len(), 2);
+
+        // Recovered once, then the lock is clean again
+        assert!(!manager.tickets.is_poisoned());
+        let health = manager.lock_health();
+        assert!(!health.healthy);
+        assert_eq!(health.recoveries, 1);
+    }
+
+    #[test]
+    fn linking_an_alert_is_recorded_once() {
+        let activity = ActivityLog::new();
+        let manager = TicketsManager::new(activity.clone());
+        let id = create(&manager, "Port scan from the guest network");
+        let alert_id = Uuid::new_v4();
+
+        assert!(manager.link_alert(id, alert_id, "alice").unwrap());
+        assert!(!manager.link_alert(id, alert_id, "alice").unwrap());
+        assert_eq!(manager.get_ticket(id).unwrap().linked_alerts, vec![alert_id]);
+
+        let feed = activity.page(ResourceKind::Ticket, &id.to_string(), true, 0, 100).unwrap();
+        let links: Vec<_> = feed.items.iter().filter(|a| a.kind == ActivityKind::AlertLinked).collect();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].payload["alert_id"], serde_json::json!(alert_id));
+        assert!(manager.link_alert(Uuid::new_v4(), alert_id, "alice").is_err());
+    }
+}
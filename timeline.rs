@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::Asset;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineItemKind {
+    Log,
+    Alert,
+    Ticket,
+    ScanFinding,
+    FirewallRule,
+}
+
+// One entry of an asset timeline; data holds the full source record
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineItem {
+    pub kind: TimelineItemKind,
+    pub timestamp: DateTime<Utc>,
+    pub id: String,
+    pub summary: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelinePage {
+    pub offset: usize,
+    pub limit: usize,
+    pub has_more: bool,
+    pub items: Vec<TimelineItem>,
+}
+
+// Decides whether a record refers to an asset, by name or by an address the asset
+// held at the time of the record
+pub struct AssetMatcher {
+    asset: Asset,
+    name: String,
+}
+
+// True when needle occurs in text as a whole token, so 10.0.0.1 does not match 10.0.0.15
+fn mentions(text: &str, needle: &str) -> bool {
+    if needle.is_empty() {
+        return false;
+    }
+
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '_');
+
+    text.match_indices(needle).any(|(pos, _)| {
+        let before = text[..pos].chars().next_back();
+        let mut after = text[pos + needle.len()..].chars();
+
+        // A trailing dot ends a sentence unless the token continues after it
+        let after_ok = match after.next() {
+            None => true,
+            Some('.') => !after.next().map_or(false, |c| c.is_ascii_alphanumeric()),
+            Some(c) => !is_token_char(c),
+        };
+
+        !before.map_or(false, is_token_char) && after_ok
+    })
+}
+
+impl AssetMatcher {
+    pub fn new(asset: Asset) -> Self {
+        let name = asset.name.to_lowercase();
+        Self { asset, name }
+    }
+
+    pub fn address_matches(&self, address: &str, at: DateTime<Utc>) -> bool {
+        self.asset.addresses_at(at).iter().any(|a| *a == address)
+    }
+
+    pub fn host_matches(&self, host: &str, at: DateTime<Utc>) -> bool {
+        host.to_lowercase() == self.name || self.address_matches(host, at)
+    }
+
+    pub fn address_mentioned_in(&self, text: &str, at: DateTime<Utc>) -> bool {
+        self.asset.addresses_at(at).iter().any(|a| mentions(text, a))
+    }
+
+    // Free text mentioning the asset name or an address it held at the time
+    pub fn mentioned_in(&self, text: &str, at: DateTime<Utc>) -> bool {
+        mentions(&text.to_lowercase(), &self.name) || self.address_mentioned_in(text, at)
+    }
+
+    // Tags naming the asset by id or name, with or without an "asset:" prefix
+    pub fn tagged(&self, tags: &[String]) -> bool {
+        let id = self.asset.id.to_string();
+        tags.iter().any(|tag| {
+            let tag = tag.to_lowercase();
+            let tag = tag.strip_prefix("asset:").unwrap_or(&tag);
+            tag == id || tag == self.name
+        })
+    }
+}
+
+struct Head {
+    timestamp: DateTime<Utc>,
+    source: usize,
+    position: usize,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    // Newest first; ties keep the source order stable
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp.cmp(&other.timestamp)
+            .then_with(|| other.source.cmp(&self.source))
+    }
+}
+
+// K-way merge of newest-first streams. Each stream only needs offset + limit + 1 items,
+// which is what the stores are asked for.
+pub fn merge(mut sources: Vec<Vec<TimelineItem>>, offset: usize, limit: usize) -> TimelinePage {
+    // Stores already answer newest first; this only guards against logs ingested out of order
+    for source in sources.iter_mut() {
+        source.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    }
+
+    let mut heap: BinaryHeap<Head> = sources.iter()
+        .enumerate()
+        .filter_map(|(source, items)| items.first().map(|item| Head {
+            timestamp: item.timestamp,
+            source,
+            position: 0,
+        }))
+        .collect();
+
+    let mut merged = Vec::new();
+    let mut seen = 0;
+    let mut has_more = false;
+
+    while let Some(head) = heap.pop() {
+        if seen >= offset.saturating_add(limit) {
+            has_more = true;
+            break;
+        }
+
+        if seen >= offset {
+            merged.push(sources[head.source][head.position].clone());
+        }
+        seen += 1;
+
+        if let Some(next) = sources[head.source].get(head.position + 1) {
+            heap.push(Head {
+                timestamp: next.timestamp,
+                source: head.source,
+                position: head.position + 1,
+            });
+        }
+    }
+
+    TimelinePage {
+        offset,
+        limit,
+        has_more,
+        items: merged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn item(kind: TimelineItemKind, minutes_ago: i64) -> TimelineItem {
+        TimelineItem {
+            kind,
+            timestamp: Utc::now() - Duration::minutes(minutes_ago),
+            id: minutes_ago.to_string(),
+            summary: String::new(),
+            data: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn merged_page_is_newest_first_across_sources() {
+        let sources = vec![
+            vec![item(TimelineItemKind::Log, 1), item(TimelineItemKind::Log, 4)],
+            vec![item(TimelineItemKind::Alert, 2), item(TimelineItemKind::Alert, 3)],
+        ];
+        let page = merge(sources, 1, 2);
+        let ids: Vec<_> = page.items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "3"]);
+        assert!(page.has_more);
+    }
+
+    #[test]
+    fn offset_past_the_end_is_an_empty_page() {
+        let sources = vec![vec![item(TimelineItemKind::Log, 1)]];
+        let page = merge(sources, usize::MAX, 1000);
+        assert!(page.items.is_empty());
+        assert!(!page.has_more);
+    }
+}
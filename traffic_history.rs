@@ -0,0 +1,347 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+// Pseudo-interface summing every interface except loopback
+pub const ALL_INTERFACES: &str = "all";
+
+// (resolution, retention) of each tier in seconds, finest first
+const TIERS: [(i64, i64); 2] = [
+    (60, 2 * 86400),
+    (3600, 90 * 86400),
+];
+
+// Upper bound on points per window in a comparison
+const MAX_POINTS: i64 = 5000;
+
+// Bytes transferred during one bucket of a tier
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct InterfaceHistory {
+    // Indexed like TIERS
+    tiers: Vec<VecDeque<Bucket>>,
+}
+
+// A closed-open time range, written as "<from>/<to>" in RFC 3339
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Window {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Window {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (from, to) = value.split_once('/')
+            .ok_or_else(|| anyhow!("Invalid range '{}', expected <from>/<to>", value))?;
+        let from = DateTime::parse_from_rfc3339(from.trim())
+            .map_err(|e| anyhow!("Invalid range start '{}': {}", from, e))?
+            .with_timezone(&Utc);
+        let to = DateTime::parse_from_rfc3339(to.trim())
+            .map_err(|e| anyhow!("Invalid range end '{}': {}", to, e))?
+            .with_timezone(&Utc);
+        if to <= from {
+            return Err(anyhow!("Range '{}' ends before it starts", value));
+        }
+        Ok(Self { from, to })
+    }
+
+    fn seconds(&self) -> i64 {
+        (self.to - self.from).num_seconds()
+    }
+}
+
+// One step of an aligned series; None marks a gap with no samples at all
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparePoint {
+    pub offset_secs: i64,
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+// Rates are bytes per second over a series step; gaps are left out
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowSummary {
+    pub total_bytes: u64,
+    pub peak_rate: f64,
+    pub p95_rate: f64,
+    pub gaps: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompareWindow {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub series: Vec<ComparePoint>,
+    pub summary: WindowSummary,
+}
+
+// Window b relative to window a; percentages are None when a is zero
+#[derive(Debug, Clone, Serialize)]
+pub struct CompareDeltas {
+    pub total_bytes: i64,
+    pub total_bytes_pct: Option<f64>,
+    pub peak_rate: f64,
+    pub peak_rate_pct: Option<f64>,
+    pub p95_rate: f64,
+    pub p95_rate_pct: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrafficComparison {
+    pub interface: String,
+    pub resolution_secs: i64,
+    pub range_a: CompareWindow,
+    pub range_b: CompareWindow,
+    pub deltas: CompareDeltas,
+}
+
+fn bucket_start(at: DateTime<Utc>, resolution: i64) -> DateTime<Utc> {
+    let secs = at.timestamp();
+    DateTime::from_timestamp(secs - secs.rem_euclid(resolution), 0).unwrap_or(at)
+}
+
+fn percent(delta: f64, base: f64) -> Option<f64> {
+    (base != 0.0).then(|| delta / base * 100.0)
+}
+
+fn summarize(series: &[ComparePoint], resolution: i64) -> WindowSummary {
+    let mut rates: Vec<f64> = series.iter()
+        .filter_map(|p| p.total_bytes)
+        .map(|bytes| bytes as f64 / resolution as f64)
+        .collect();
+    rates.sort_by(|a, b| a.total_cmp(b));
+    let p95_rate = if rates.is_empty() {
+        0.0
+    } else {
+        rates[(rates.len() * 95 / 100).min(rates.len() - 1)]
+    };
+    WindowSummary {
+        total_bytes: series.iter().filter_map(|p| p.total_bytes).sum(),
+        peak_rate: rates.last().copied().unwrap_or(0.0),
+        p95_rate,
+        gaps: series.iter().filter(|p| p.total_bytes.is_none()).count(),
+    }
+}
+
+// Per-interface byte counts rolled up into fixed tiers (minutes for two days, hours for
+// ninety), fed from the counter deltas of each traffic collection. Buckets with no
+// samples are never stored, so missing data stays distinguishable from idle links.
+#[derive(Clone)]
+pub struct TrafficHistory {
+    path: PathBuf,
+    interfaces: Arc<Mutex<HashMap<String, InterfaceHistory>>>,
+}
+
+impl TrafficHistory {
+    pub fn new(dir: &str) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)
+                .context(format!("Failed to create traffic history directory: {:?}", dir))?;
+            info!("Created traffic history directory: {:?}", dir);
+        }
+
+        let path = dir.join("history.json");
+        let interfaces = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .context(format!("Failed to read traffic history: {:?}", path))?;
+            serde_json::from_str(&content)
+                .context(format!("Failed to parse traffic history: {:?}", path))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path,
+            interfaces: Arc::new(Mutex::new(interfaces)),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let content = {
+            let interfaces = self.interfaces.lock()
+                .map_err(|_| anyhow!("Failed to acquire lock on traffic history"))?;
+            serde_json::to_string(&*interfaces)?
+        };
+        fs::write(&self.path, content)
+            .context(format!("Failed to write traffic history: {:?}", self.path))?;
+        Ok(())
+    }
+
+    // Adds bytes seen on an interface since the previous collection to every tier
+    pub fn record(&self, interface: &str, at: DateTime<Utc>, rx_bytes: u64, tx_bytes: u64) -> Result<()> {
+        let mut interfaces = self.interfaces.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on traffic history"))?;
+        let history = interfaces.entry(interface.to_string()).or_default();
+        history.tiers.resize_with(TIERS.len(), VecDeque::new);
+
+        for (tier, &(resolution, retention)) in history.tiers.iter_mut().zip(TIERS.iter()) {
+            let start = bucket_start(at, resolution);
+            match tier.back_mut() {
+                Some(last) if last.start == start => {
+                    last.rx_bytes += rx_bytes;
+                    last.tx_bytes += tx_bytes;
+                }
+                _ => tier.push_back(Bucket { start, rx_bytes, tx_bytes }),
+            }
+            let cutoff = at - Duration::seconds(retention);
+            while tier.front().map_or(false, |b| b.start < cutoff) {
+                tier.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    // Finest tier that still holds `since` and whose resolution divides `resolution`
+    fn pick_tier(&self, since: DateTime<Utc>, resolution: Option<i64>) -> Result<(usize, i64)> {
+        let now = Utc::now();
+        for (index, &(tier_resolution, retention)) in TIERS.iter().enumerate() {
+            if since < now - Duration::seconds(retention) {
+                continue;
+            }
+            match resolution {
+                None => return Ok((index, tier_resolution)),
+                Some(r) if r >= tier_resolution && r % tier_resolution == 0 => return Ok((index, r)),
+                Some(_) => continue,
+            }
+        }
+        let (coarsest, retention) = TIERS[TIERS.len() - 1];
+        match resolution {
+            Some(r) if since >= now - Duration::seconds(retention) => Err(anyhow!(
+                "Resolution must be a multiple of {} seconds for ranges this old, got {}", coarsest, r)),
+            _ => Err(anyhow!("History only reaches back {} days", retention / 86400)),
+        }
+    }
+
+    fn series(&self,
+              interfaces: &HashMap<String, InterfaceHistory>,
+              names: &[&String],
+              tier: usize,
+              window: &Window,
+              resolution: i64) -> Vec<ComparePoint> {
+        let steps = (window.seconds() + resolution - 1) / resolution;
+        let mut points: Vec<ComparePoint> = (0..steps)
+            .map(|step| ComparePoint {
+                offset_secs: step * resolution,
+                rx_bytes: None,
+                tx_bytes: None,
+                total_bytes: None,
+            })
+            .collect();
+
+        for name in names {
+            let Some(buckets) = interfaces.get(*name).and_then(|h| h.tiers.get(tier)) else {
+                continue;
+            };
+            for bucket in buckets.iter().filter(|b| b.start >= window.from && b.start < window.to) {
+                let step = ((bucket.start - window.from).num_seconds() / resolution) as usize;
+                let point = &mut points[step];
+                point.rx_bytes = Some(point.rx_bytes.unwrap_or(0) + bucket.rx_bytes);
+                point.tx_bytes = Some(point.tx_bytes.unwrap_or(0) + bucket.tx_bytes);
+                point.total_bytes = Some(point.total_bytes.unwrap_or(0) + bucket.rx_bytes + bucket.tx_bytes);
+            }
+        }
+        points
+    }
+
+    // Aligns both windows on offset from their start so they can be overlaid. Windows
+    // must be the same length; `resolution` defaults to the finest tier covering both.
+    // Interfaces `visible` rejects are left out, as if they had no history.
+    pub fn compare(&self,
+                   interface: &str,
+                   range_a: Window,
+                   range_b: Window,
+                   resolution: Option<i64>,
+                   visible: impl Fn(&str) -> bool) -> Result<TrafficComparison> {
+        if range_a.seconds() != range_b.seconds() {
+            return Err(anyhow!(
+                "Ranges must have the same length: range_a is {} seconds, range_b is {} seconds",
+                range_a.seconds(), range_b.seconds()));
+        }
+        if let Some(r) = resolution {
+            if r <= 0 {
+                return Err(anyhow!("Resolution must be a positive number of seconds"));
+            }
+        }
+        let (tier, resolution) = self.pick_tier(range_a.from.min(range_b.from), resolution)?;
+        if range_a.seconds() / resolution > MAX_POINTS {
+            return Err(anyhow!("Ranges of {} seconds at {} second resolution exceed {} points",
+                               range_a.seconds(), resolution, MAX_POINTS));
+        }
+
+        let interfaces = self.interfaces.lock()
+            .map_err(|_| anyhow!("Failed to acquire lock on traffic history"))?;
+        let names: Vec<&String> = if interface == ALL_INTERFACES {
+            interfaces.keys().filter(|name| name.as_str() != "lo" && visible(name)).collect()
+        } else {
+            match interfaces.get_key_value(interface).filter(|(name, _)| visible(name)) {
+                Some((name, _)) => vec![name],
+                None => return Err(anyhow!("No traffic history for interface {}", interface)),
+            }
+        };
+
+        let build = |window: Window| {
+            let series = self.series(&interfaces, &names, tier, &window, resolution);
+            let summary = summarize(&series, resolution);
+            CompareWindow { from: window.from, to: window.to, series, summary }
+        };
+        let a = build(range_a);
+        let b = build(range_b);
+
+        let total = b.summary.total_bytes as i64 - a.summary.total_bytes as i64;
+        let peak = b.summary.peak_rate - a.summary.peak_rate;
+        let p95 = b.summary.p95_rate - a.summary.p95_rate;
+        let deltas = CompareDeltas {
+            total_bytes: total,
+            total_bytes_pct: percent(total as f64, a.summary.total_bytes as f64),
+            peak_rate: peak,
+            peak_rate_pct: percent(peak, a.summary.peak_rate),
+            p95_rate: p95,
+            p95_rate_pct: percent(p95, a.summary.p95_rate),
+        };
+
+        Ok(TrafficComparison {
+            interface: interface.to_string(),
+            resolution_secs: resolution,
+            range_a: a,
+            range_b: b,
+            deltas,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparisons_leave_out_interfaces_that_are_not_visible() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = TrafficHistory::new(dir.path().to_str().unwrap()).unwrap();
+        let now = Utc::now();
+        for interface in ["eth0", "eth1"] {
+            history.record(interface, now - Duration::minutes(30), 100, 100).unwrap();
+        }
+        let range_a = Window { from: now - Duration::hours(2), to: now - Duration::hours(1) };
+        let range_b = Window { from: now - Duration::hours(1), to: now };
+
+        let all = history.compare(ALL_INTERFACES, range_a, range_b, Some(3600), |_| true).unwrap();
+        assert_eq!(all.range_b.summary.total_bytes, 400);
+        let only_eth0 = |name: &str| name == "eth0";
+        let scoped = history.compare(ALL_INTERFACES, range_a, range_b, Some(3600), only_eth0).unwrap();
+        assert_eq!(scoped.range_b.summary.total_bytes, 200);
+        assert!(history.compare("eth1", range_a, range_b, Some(3600), only_eth0).is_err());
+    }
+}
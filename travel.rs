@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::warn;
+
+use crate::alerts::AlertsManager;
+use crate::config::ImpossibleTravelConfig;
+use crate::geoip::{self, GeoIpResolver, GeoLocation};
+use crate::models::{AlertSeverity, EventCategory, LogEntry};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Tags carrying the client address of an authentication event
+const SOURCE_IP_TAG_PREFIXES: &[&str] = &["src_ip:", "source_ip:", "client_ip:"];
+
+// A located authentication event
+#[derive(Debug, Clone)]
+pub struct LoginObservation {
+    pub log_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub ip: IpAddr,
+    pub success: bool,
+    pub location: GeoLocation,
+}
+
+#[derive(Debug, Clone)]
+pub struct TravelFinding {
+    pub previous: LoginObservation,
+    pub distance_km: f64,
+    pub hours: f64,
+    pub speed_kmh: f64,
+}
+
+pub fn haversine_km(a: &GeoLocation, b: &GeoLocation) -> f64 {
+    let (lat1, lat2) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.longitude - a.longitude).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+// Flags the pair when covering the distance in the time between the events needs more
+// than max_speed_kmh. Distances under min_distance_km are within GeoIP accuracy and ignored.
+pub fn evaluate(previous: &LoginObservation,
+                current: &LoginObservation,
+                max_speed_kmh: f64,
+                min_distance_km: f64) -> Option<TravelFinding> {
+    let distance_km = haversine_km(&previous.location, &current.location);
+    if distance_km < min_distance_km {
+        return None;
+    }
+
+    let hours = (current.timestamp - previous.timestamp).num_seconds().abs() as f64 / 3600.0;
+    let speed_kmh = if hours > 0.0 { distance_km / hours } else { f64::INFINITY };
+
+    if speed_kmh <= max_speed_kmh {
+        return None;
+    }
+
+    Some(TravelFinding {
+        previous: previous.clone(),
+        distance_km,
+        hours,
+        speed_kmh,
+    })
+}
+
+// Last located login per user; entries older than the TTL are forgotten
+pub struct LoginCache {
+    ttl: Duration,
+    latest: HashMap<String, LoginObservation>,
+}
+
+impl LoginCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            latest: HashMap::new(),
+        }
+    }
+
+    // Records the login and returns the previous one if it is still fresh
+    pub fn observe(&mut self, user: &str, observation: LoginObservation) -> Option<LoginObservation> {
+        let cutoff = observation.timestamp - self.ttl;
+        self.latest.retain(|_, o| o.timestamp >= cutoff);
+
+        let key = user.to_lowercase();
+        match self.latest.get(&key) {
+            // Events ingested out of order do not replace a newer login
+            Some(previous) if previous.timestamp > observation.timestamp => Some(previous.clone()),
+            _ => self.latest.insert(key, observation),
+        }
+    }
+}
+
+// Client address of an authentication event: an explicit tag, otherwise the address
+// after "from" as written by sshd, pam and most other auth logs
+pub fn source_ip(entry: &LogEntry) -> Option<IpAddr> {
+    let tagged = entry.tags.iter()
+        .find_map(|tag| SOURCE_IP_TAG_PREFIXES.iter().find_map(|prefix| tag.strip_prefix(prefix)))
+        .and_then(geoip::parse_endpoint);
+    if tagged.is_some() {
+        return tagged;
+    }
+
+    let mut words = entry.message.split_whitespace();
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case("from") {
+            if let Some(ip) = words.next().and_then(|w| geoip::parse_endpoint(w.trim_matches(|c| c == ',' || c == ';'))) {
+                return Some(ip);
+            }
+        }
+    }
+
+    None
+}
+
+fn is_failure(entry: &LogEntry) -> bool {
+    let text = format!("{} {}", entry.event_type, entry.message).to_lowercase();
+    ["fail", "invalid", "denied", "rejected"].iter().any(|w| text.contains(w))
+}
+
+fn describe(observation: &LoginObservation) -> String {
+    let place = match (&observation.location.city, &observation.location.country_code) {
+        (Some(city), Some(code)) => format!("{}, {}", city, code),
+        (None, Some(code)) => code.clone(),
+        _ => format!("{:.2}, {:.2}", observation.location.latitude, observation.location.longitude),
+    };
+
+    format!("{} login from {} ({}) at {} [log {}]",
+            if observation.success { "successful" } else { "failed" },
+            observation.ip,
+            place,
+            observation.timestamp.to_rfc3339(),
+            observation.log_id)
+}
+
+// Geolocates authentication events and raises alerts for impossible travel and
+// logins from watched countries
+#[derive(Clone)]
+pub struct TravelDetector {
+    config: ImpossibleTravelConfig,
+    resolver: GeoIpResolver,
+    cache: Arc<Mutex<LoginCache>>,
+    alerts: AlertsManager,
+}
+
+impl TravelDetector {
+    pub fn new(config: ImpossibleTravelConfig, resolver: GeoIpResolver, alerts: AlertsManager) -> Self {
+        let ttl = Duration::hours(config.cache_ttl_hours as i64);
+        Self {
+            config,
+            resolver,
+            cache: Arc::new(Mutex::new(LoginCache::new(ttl))),
+            alerts,
+        }
+    }
+
+    // Private and unresolvable addresses say nothing about where the user is
+    fn locate(&self, ip: IpAddr) -> Option<GeoLocation> {
+        if self.resolver.is_internal(ip) {
+            return None;
+        }
+        self.resolver.lookup(ip)
+    }
+
+    // Adds geo tags to an authentication event before it is stored
+    pub fn enrich(&self, entry: &mut LogEntry) -> Option<LoginObservation> {
+        if !self.config.enabled || entry.category != EventCategory::Authentication || entry.user.is_none() {
+            return None;
+        }
+
+        let ip = source_ip(entry)?;
+        let location = self.locate(ip)?;
+
+        if let Some(code) = &location.country_code {
+            entry.tags.push(format!("geo_country:{}", code));
+        }
+        if let Some(city) = &location.city {
+            entry.tags.push(format!("geo_city:{}", city));
+        }
+
+        Some(LoginObservation {
+            log_id: entry.id,
+            timestamp: entry.timestamp,
+            ip,
+            success: !is_failure(entry),
+            location,
+        })
+    }
+
+    // Compares the event with the user's previous login; call after the entry is stored
+    pub fn check(&self, user: &str, observation: LoginObservation) -> Result<()> {
+        // Anyone can fail a login for any name from anywhere; only one that succeeded
+        // says where the user is
+        if !observation.success {
+            return Ok(());
+        }
+
+        let watched = observation.location.country_code.as_ref()
+            .filter(|code| self.config.watch_countries.iter().any(|w| w.eq_ignore_ascii_case(code)));
+
+        if let Some(code) = watched {
+            self.alerts.create_alert(
+                AlertSeverity::High,
+                format!("Login for {} from watched country {}", user, code),
+                describe(&observation),
+                "impossible_travel".to_string(),
+                vec![observation.log_id],
+            )?;
+        }
+
+        let previous = match self.cache.lock() {
+            Ok(mut cache) => cache.observe(user, observation.clone()),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on login cache")),
+        };
+
+        let finding = previous.and_then(|previous| {
+            evaluate(&previous, &observation, self.config.max_speed_kmh, self.config.min_distance_km)
+        });
+
+        if let Some(finding) = finding {
+            warn!("Impossible travel for {}: {:.0} km in {:.2} h", user, finding.distance_km, finding.hours);
+
+            self.alerts.create_alert(
+                AlertSeverity::High,
+                format!("Impossible travel for {}", user),
+                format!("{:.0} km in {:.2} h ({:.0} km/h, limit {:.0} km/h)\n1. {}\n2. {}",
+                        finding.distance_km,
+                        finding.hours,
+                        finding.speed_kmh,
+                        self.config.max_speed_kmh,
+                        describe(&finding.previous),
+                        describe(&observation)),
+                "impossible_travel".to_string(),
+                vec![finding.previous.log_id, observation.log_id],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRAGUE: (f64, f64) = (50.0755, 14.4378);
+    const LONDON: (f64, f64) = (51.5074, -0.1278);
+    const BRNO: (f64, f64) = (49.1951, 16.6068);
+
+    fn login(at: DateTime<Utc>, (latitude, longitude): (f64, f64)) -> LoginObservation {
+        LoginObservation {
+            log_id: Uuid::new_v4(),
+            timestamp: at,
+            ip: "203.0.113.10".parse().unwrap(),
+            success: true,
+            location: GeoLocation {
+                country_code: None,
+                country: None,
+                city: None,
+                latitude,
+                longitude,
+            },
+        }
+    }
+
+    #[test]
+    fn distance_prague_london() {
+        let a = login(Utc::now(), PRAGUE);
+        let b = login(Utc::now(), LONDON);
+        let distance = haversine_km(&a.location, &b.location);
+        assert!((distance - 1035.0).abs() < 10.0, "distance was {}", distance);
+    }
+
+    #[test]
+    fn flags_speed_just_above_threshold() {
+        let start = Utc::now();
+        let a = login(start, PRAGUE);
+        // ~1035 km in 66 minutes is ~940 km/h
+        let b = login(start + Duration::minutes(66), LONDON);
+        let finding = evaluate(&a, &b, 900.0, 100.0).expect("should be flagged");
+        assert!(finding.speed_kmh > 900.0 && finding.speed_kmh < 1000.0);
+    }
+
+    #[test]
+    fn ignores_speed_just_below_threshold() {
+        let start = Utc::now();
+        let a = login(start, PRAGUE);
+        // ~1035 km in 70 minutes is ~887 km/h
+        let b = login(start + Duration::minutes(70), LONDON);
+        assert!(evaluate(&a, &b, 900.0, 100.0).is_none());
+    }
+
+    #[test]
+    fn ignores_short_distances_even_without_time_gap() {
+        let start = Utc::now();
+        // ~185 km apart, below the GeoIP accuracy floor
+        assert!(evaluate(&login(start, PRAGUE), &login(start, BRNO), 900.0, 200.0).is_none());
+        assert!(evaluate(&login(start, PRAGUE), &login(start, BRNO), 900.0, 100.0).is_some());
+    }
+
+    #[test]
+    fn cache_forgets_logins_after_ttl() {
+        let mut cache = LoginCache::new(Duration::hours(1));
+        let start = Utc::now();
+
+        assert!(cache.observe("alice", login(start, PRAGUE)).is_none());
+        assert!(cache.observe("Alice", login(start + Duration::minutes(30), LONDON)).is_some());
+        assert!(cache.observe("alice", login(start + Duration::hours(3), PRAGUE)).is_none());
+    }
+
+    fn detector() -> TravelDetector {
+        TravelDetector {
+            config: ImpossibleTravelConfig::default(),
+            resolver: GeoIpResolver::new(&crate::config::GeoIpConfig::default()).unwrap(),
+            cache: Arc::new(Mutex::new(LoginCache::new(Duration::hours(1)))),
+            alerts: AlertsManager::new(std::env::temp_dir().join(format!("travel-test-{}", Uuid::new_v4())).to_str().unwrap()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn failed_logins_are_not_checked_for_travel() {
+        let detector = detector();
+        let start = Utc::now();
+        detector.check("alice", login(start, PRAGUE)).unwrap();
+
+        let failed = LoginObservation { success: false, ..login(start + Duration::minutes(5), LONDON) };
+        detector.check("alice", failed).unwrap();
+        assert!(detector.alerts.get_all_alerts().unwrap().is_empty());
+
+        // The failed attempt did not replace where alice was last seen
+        detector.check("alice", login(start + Duration::minutes(10), PRAGUE)).unwrap();
+        assert!(detector.alerts.get_all_alerts().unwrap().is_empty());
+        detector.check("alice", login(start + Duration::minutes(15), LONDON)).unwrap();
+        assert_eq!(detector.alerts.get_all_alerts().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn private_and_unknown_addresses_are_not_located() {
+        let detector = detector();
+
+        assert!(detector.locate("10.1.2.3".parse().unwrap()).is_none());
+        assert!(detector.locate("fe80::1".parse().unwrap()).is_none());
+        // No GeoIP database loaded, so public addresses are unknown as well
+        assert!(detector.locate("203.0.113.10".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn finds_source_ip_in_tags_and_messages() {
+        let mut entry = LogEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            source: "sshd".to_string(),
+            event_type: "auth".to_string(),
+            severity: crate::models::LogSeverity::Info,
+            message: "Failed password for alice from 198.51.100.7 port 52144 ssh2".to_string(),
+            raw_data: String::new(),
+            host: None,
+            user: Some("alice".to_string()),
+            application: None,
+            tags: Vec::new(),
+            category: EventCategory::Authentication,
+            hostname: None,
+            site_id: None,
+            parser: crate::models::LogParser::Api,
+        };
+
+        assert_eq!(source_ip(&entry), Some("198.51.100.7".parse().unwrap()));
+        assert!(is_failure(&entry));
+
+        entry.tags.push("src_ip:192.0.2.1".to_string());
+        assert_eq!(source_ip(&entry), Some("192.0.2.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+use tracing::{info, warn, error};
+
+use crate::alerts::AlertsManager;
+use crate::config::{UpsActionConfig, UpsConfig, UpsDriver, UpsUnitConfig};
+use crate::models::AlertSeverity;
+use crate::remediation::{RemediationBinding, RemediationManager};
+use crate::scripts::ScriptsManager;
+
+// Source of the alerts raised here; remediation bindings can attach to it as well
+pub const UPS_SOURCE: &str = "ups";
+
+// Lines of a LIST VAR answer read at most; a real UPS has a few dozen variables
+const MAX_NUT_LINES: usize = 500;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpsReading {
+    // Raw status as reported, e.g. "OB LB" (NUT) or "ONBATT LOWBATT" (apcupsd)
+    pub status: String,
+    pub on_battery: bool,
+    pub low_battery: bool,
+    pub charge_percent: Option<f64>,
+    pub runtime_secs: Option<u64>,
+    pub load_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpsStatus {
+    pub name: String,
+    pub driver: UpsDriver,
+    // Answered the last poll
+    pub reachable: bool,
+    // Last successful reading, kept while communication is lost
+    pub reading: Option<UpsReading>,
+    pub low_battery: bool,
+    pub last_poll: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    pub error: Option<String>,
+    pub last_action_at: Option<DateTime<Utc>>,
+}
+
+// Transitions found by a poll, alerted once the state lock is released
+enum UpsEvent {
+    OnBattery(UpsReading),
+    OnMains,
+    LowBattery(UpsReading),
+    CommLost(String),
+    CommRestored,
+    RuntimeAction(UpsReading, UpsActionConfig),
+}
+
+fn parse_leading_number(value: &str) -> Option<f64> {
+    value.split_whitespace().next().and_then(|v| v.parse().ok())
+}
+
+// NUT: LIST VAR <ups>, answered by `VAR <ups> <name> "<value>"` lines up to END LIST VAR
+async fn poll_nut(unit: &UpsUnitConfig) -> Result<UpsReading> {
+    let ups = unit.ups.as_deref().ok_or_else(|| anyhow!("NUT unit {} has no ups name", unit.name))?;
+    let stream = TcpStream::connect((unit.host.as_str(), unit.port.unwrap_or(3493))).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(format!("LIST VAR {}\n", ups).as_bytes()).await?;
+
+    let mut vars: HashMap<String, String> = HashMap::new();
+    let mut lines = BufReader::new(reader).lines();
+    for _ in 0..MAX_NUT_LINES {
+        let line = lines.next_line().await?
+            .ok_or_else(|| anyhow!("upsd closed the connection"))?;
+        if let Some(error) = line.strip_prefix("ERR ") {
+            return Err(anyhow!("upsd: {}", error));
+        }
+        if line.starts_with("END LIST VAR") {
+            let _ = writer.write_all(b"LOGOUT\n").await;
+            let status = vars.get("ups.status").cloned().unwrap_or_default();
+            let flags: Vec<&str> = status.split_whitespace().collect();
+            return Ok(UpsReading {
+                on_battery: flags.contains(&"OB"),
+                low_battery: flags.contains(&"LB"),
+                charge_percent: vars.get("battery.charge").and_then(|v| parse_leading_number(v)),
+                runtime_secs: vars.get("battery.runtime").and_then(|v| parse_leading_number(v)).map(|v| v as u64),
+                load_percent: vars.get("ups.load").and_then(|v| parse_leading_number(v)),
+                status,
+            });
+        }
+        if let Some(rest) = line.strip_prefix("VAR ") {
+            let mut parts = rest.splitn(3, ' ');
+            if let (Some(_), Some(name), Some(value)) = (parts.next(), parts.next(), parts.next()) {
+                vars.insert(name.to_string(), value.trim_matches('"').to_string());
+            }
+        }
+    }
+    Err(anyhow!("upsd answer exceeds {} lines", MAX_NUT_LINES))
+}
+
+// apcupsd NIS: length-prefixed "status" request, answered by length-prefixed
+// `KEY : value` records up to an empty one
+async fn poll_apcupsd(unit: &UpsUnitConfig) -> Result<UpsReading> {
+    let mut stream = TcpStream::connect((unit.host.as_str(), unit.port.unwrap_or(3551))).await?;
+    stream.write_all(&6u16.to_be_bytes()).await?;
+    stream.write_all(b"status").await?;
+
+    let mut vars: HashMap<String, String> = HashMap::new();
+    loop {
+        let length = stream.read_u16().await? as usize;
+        if length == 0 {
+            break;
+        }
+        let mut record = vec![0u8; length];
+        stream.read_exact(&mut record).await?;
+        let record = String::from_utf8_lossy(&record);
+        if let Some((key, value)) = record.split_once(':') {
+            vars.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    let status = vars.get("STATUS").cloned()
+        .ok_or_else(|| anyhow!("apcupsd reported no STATUS"))?;
+    Ok(UpsReading {
+        on_battery: status.contains("ONBATT"),
+        low_battery: status.contains("LOWBATT"),
+        charge_percent: vars.get("BCHARGE").and_then(|v| parse_leading_number(v)),
+        runtime_secs: vars.get("TIMELEFT").and_then(|v| parse_leading_number(v)).map(|minutes| (minutes * 60.0) as u64),
+        load_percent: vars.get("LOADPCT").and_then(|v| parse_leading_number(v)),
+        status,
+    })
+}
+
+// Polls the configured UPS units and raises alerts when a unit goes on battery, runs
+// low or stops answering. With a runtime action configured, an approved script runs
+// through the remediation path once runtime on battery falls below its threshold.
+#[derive(Clone)]
+pub struct UpsMonitor {
+    config: UpsConfig,
+    units: Arc<Mutex<Vec<UpsStatus>>>,
+    alerts: AlertsManager,
+    remediation: RemediationManager,
+    scripts: Arc<Mutex<ScriptsManager>>,
+}
+
+impl UpsMonitor {
+    pub fn new(config: UpsConfig,
+               alerts: AlertsManager,
+               remediation: RemediationManager,
+               scripts: Arc<Mutex<ScriptsManager>>) -> Self {
+        let units = config.units.iter()
+            .map(|unit| UpsStatus {
+                name: unit.name.clone(),
+                driver: unit.driver,
+                reachable: false,
+                reading: None,
+                low_battery: false,
+                last_poll: None,
+                last_success: None,
+                consecutive_failures: 0,
+                error: None,
+                last_action_at: None,
+            })
+            .collect();
+        Self {
+            config,
+            units: Arc::new(Mutex::new(units)),
+            alerts,
+            remediation,
+            scripts,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.poll_interval_secs.max(1))
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.config.units.is_empty()
+    }
+
+    pub fn status(&self) -> Result<Vec<UpsStatus>> {
+        match self.units.lock() {
+            Ok(units) => Ok(units.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on UPS status")),
+        }
+    }
+
+    async fn poll_unit(&self, unit: &UpsUnitConfig) -> Result<UpsReading> {
+        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let poll = async {
+            match unit.driver {
+                UpsDriver::Nut => poll_nut(unit).await,
+                UpsDriver::Apcupsd => poll_apcupsd(unit).await,
+            }
+        };
+        tokio::time::timeout(timeout, poll).await
+            .map_err(|_| anyhow!("No answer within {} seconds", timeout.as_secs()))?
+    }
+
+    fn is_low(&self, reading: &UpsReading) -> bool {
+        reading.low_battery
+            || reading.charge_percent.map_or(false, |c| c <= self.config.low_battery_percent)
+            || reading.runtime_secs.map_or(false, |r| r <= self.config.low_runtime_secs)
+    }
+
+    // Applies a poll result to the unit's state and returns the transitions
+    fn update(&self, status: &mut UpsStatus, result: Result<UpsReading>, now: DateTime<Utc>) -> Vec<UpsEvent> {
+        let mut events = Vec::new();
+        status.last_poll = Some(now);
+
+        let reading = match result {
+            Ok(reading) => reading,
+            Err(e) => {
+                status.consecutive_failures += 1;
+                status.reachable = false;
+                status.error = Some(e.to_string());
+                if status.consecutive_failures == self.config.comm_loss_polls.max(1) {
+                    events.push(UpsEvent::CommLost(e.to_string()));
+                }
+                return events;
+            },
+        };
+
+        if status.consecutive_failures >= self.config.comm_loss_polls.max(1) {
+            events.push(UpsEvent::CommRestored);
+        }
+        status.reachable = true;
+        status.consecutive_failures = 0;
+        status.error = None;
+        status.last_success = Some(now);
+
+        let was_on_battery = status.reading.as_ref().map_or(false, |r| r.on_battery);
+        if reading.on_battery && !was_on_battery {
+            events.push(UpsEvent::OnBattery(reading.clone()));
+        } else if !reading.on_battery && was_on_battery {
+            events.push(UpsEvent::OnMains);
+        }
+
+        let low = self.is_low(&reading);
+        if low && !status.low_battery {
+            events.push(UpsEvent::LowBattery(reading.clone()));
+        }
+        status.low_battery = low;
+
+        if let (Some(action), Some(runtime)) = (&self.config.runtime_action, reading.runtime_secs) {
+            let cooling = status.last_action_at
+                .map_or(false, |at| now - at < chrono::Duration::seconds(action.cooldown_secs as i64));
+            if reading.on_battery && runtime < action.below_secs && !cooling {
+                status.last_action_at = Some(now);
+                events.push(UpsEvent::RuntimeAction(reading.clone(), action.clone()));
+            }
+        }
+
+        status.reading = Some(reading);
+        events
+    }
+
+    pub async fn poll(&self) -> Result<()> {
+        for (index, unit) in self.config.units.iter().enumerate() {
+            let result = self.poll_unit(unit).await;
+            if let Err(e) = &result {
+                warn!("Failed to poll UPS {}: {}", unit.name, e);
+            }
+
+            let events = match self.units.lock() {
+                Ok(mut units) => match units.get_mut(index) {
+                    Some(status) => self.update(status, result, Utc::now()),
+                    None => Vec::new(),
+                },
+                Err(_) => return Err(anyhow!("Failed to acquire lock on UPS status")),
+            };
+
+            for event in events {
+                self.handle(unit, event).await;
+            }
+        }
+        Ok(())
+    }
+
+    fn raise(&self, severity: AlertSeverity, title: String, description: String) -> Option<Uuid> {
+        match self.alerts.create_alert(severity, title.clone(), description, UPS_SOURCE.to_string(), Vec::new()) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                error!("Failed to raise UPS alert {}: {}", title, e);
+                None
+            },
+        }
+    }
+
+    async fn handle(&self, unit: &UpsUnitConfig, event: UpsEvent) {
+        match event {
+            UpsEvent::OnBattery(reading) => {
+                self.raise(
+                    AlertSeverity::High,
+                    format!("UPS {} on battery", unit.name),
+                    format!("UPS {} lost mains power and runs on battery ({}).", unit.name, describe(&reading)),
+                );
+            },
+            UpsEvent::OnMains => info!("UPS {} is back on mains power", unit.name),
+            UpsEvent::LowBattery(reading) => {
+                self.raise(
+                    AlertSeverity::Critical,
+                    format!("UPS {} battery low", unit.name),
+                    format!("UPS {} battery is low ({}).", unit.name, describe(&reading)),
+                );
+            },
+            UpsEvent::CommLost(error) => {
+                self.raise(
+                    AlertSeverity::High,
+                    format!("Lost communication with UPS {}", unit.name),
+                    format!("UPS {} at {} did not answer {} polls in a row: {}",
+                            unit.name, unit.host, self.config.comm_loss_polls, error),
+                );
+            },
+            UpsEvent::CommRestored => info!("Communication with UPS {} restored", unit.name),
+            UpsEvent::RuntimeAction(reading, action) => self.run_action(unit, &reading, &action).await,
+        }
+    }
+
+    async fn run_action(&self, unit: &UpsUnitConfig, reading: &UpsReading, action: &UpsActionConfig) {
+        let runtime = reading.runtime_secs.unwrap_or(0);
+        let Some(alert_id) = self.raise(
+            AlertSeverity::Critical,
+            format!("UPS {} runtime below {} seconds", unit.name, action.below_secs),
+            format!("UPS {} has {} seconds of runtime left, running script \"{}\".", unit.name, runtime, action.script),
+        ) else {
+            return;
+        };
+        let alert = match self.alerts.get_alert(alert_id) {
+            Ok(alert) => alert,
+            Err(e) => {
+                error!("Failed to load UPS alert {}: {}", alert_id, e);
+                return;
+            },
+        };
+
+        // An unknown name runs as the nil script and fails like any missing script
+        let script_id = match self.scripts.lock() {
+            Ok(scripts) => scripts.get_all_scripts().into_iter()
+                .find(|s| s.name == action.script)
+                .map(|s| s.id)
+                .unwrap_or_else(Uuid::nil),
+            Err(_) => Uuid::nil(),
+        };
+
+        let now = Utc::now();
+        let binding = RemediationBinding {
+            id: Uuid::nil(),
+            name: format!("ups:{}", unit.name),
+            alert_source: UPS_SOURCE.to_string(),
+            severities: Vec::new(),
+            script_id,
+            arguments: action.arguments.clone(),
+            cooldown_secs: action.cooldown_secs,
+            dry_run: action.dry_run,
+            enabled: true,
+            created_by: "config".to_string(),
+            created_at: now,
+            updated_at: now,
+            last_fired_at: Some(now),
+        };
+
+        let mut context = HashMap::new();
+        context.insert("ups.name".to_string(), unit.name.clone());
+        context.insert("ups.runtime_secs".to_string(), runtime.to_string());
+        context.extend(reading.charge_percent.map(|c| ("ups.charge".to_string(), format!("{:.0}", c))));
+        self.remediation.run_hook(&alert, &binding, context).await;
+    }
+
+    pub fn render_metrics(&self) -> Result<String> {
+        let units = self.status()?;
+        let mut out = String::new();
+
+        out.push_str("# HELP siem_ups_reachable Whether the UPS answered its last polls\n");
+        out.push_str("# TYPE siem_ups_reachable gauge\n");
+        for unit in &units {
+            out.push_str(&format!("siem_ups_reachable{{ups=\"{}\"}} {}\n", unit.name, if unit.reachable { 1 } else { 0 }));
+        }
+
+        let readings: Vec<(&str, &UpsReading)> = units.iter()
+            .filter(|u| u.reachable)
+            .filter_map(|u| u.reading.as_ref().map(|r| (u.name.as_str(), r)))
+            .collect();
+
+        out.push_str("# HELP siem_ups_on_battery Whether the UPS runs on battery\n");
+        out.push_str("# TYPE siem_ups_on_battery gauge\n");
+        for (name, reading) in &readings {
+            out.push_str(&format!("siem_ups_on_battery{{ups=\"{}\"}} {}\n", name, if reading.on_battery { 1 } else { 0 }));
+        }
+
+        out.push_str("# HELP siem_ups_battery_charge_percent Battery charge\n");
+        out.push_str("# TYPE siem_ups_battery_charge_percent gauge\n");
+        for (name, reading) in &readings {
+            if let Some(charge) = reading.charge_percent {
+                out.push_str(&format!("siem_ups_battery_charge_percent{{ups=\"{}\"}} {}\n", name, charge));
+            }
+        }
+
+        out.push_str("# HELP siem_ups_runtime_seconds Estimated runtime on battery\n");
+        out.push_str("# TYPE siem_ups_runtime_seconds gauge\n");
+        for (name, reading) in &readings {
+            if let Some(runtime) = reading.runtime_secs {
+                out.push_str(&format!("siem_ups_runtime_seconds{{ups=\"{}\"}} {}\n", name, runtime));
+            }
+        }
+
+        out.push_str("# HELP siem_ups_load_percent Load as a share of the UPS capacity\n");
+        out.push_str("# TYPE siem_ups_load_percent gauge\n");
+        for (name, reading) in &readings {
+            if let Some(load) = reading.load_percent {
+                out.push_str(&format!("siem_ups_load_percent{{ups=\"{}\"}} {}\n", name, load));
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+fn describe(reading: &UpsReading) -> String {
+    let mut parts = vec![format!("status {}", reading.status)];
+    parts.extend(reading.charge_percent.map(|c| format!("charge {:.0}%", c)));
+    parts.extend(reading.runtime_secs.map(|r| format!("runtime {} s", r)));
+    parts.extend(reading.load_percent.map(|l| format!("load {:.0}%", l)));
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::logs::LogsManager;
+    use crate::script_lint::ScriptLinter;
+
+    fn monitor(dir: &tempfile::TempDir, config: UpsConfig) -> UpsMonitor {
+        let root = dir.path().to_str().unwrap();
+        let alerts = AlertsManager::new(&format!("{}/alerts", root)).unwrap();
+        let scripts = Arc::new(Mutex::new(ScriptsManager::new(
+            &dir.path().join("scripts"),
+            &dir.path().join("tmp"),
+            ScriptLinter::new(&crate::config::default_config().script_lint).unwrap(),
+        ).unwrap()));
+        let remediation = RemediationManager::new(&format!("{}/remediation", root), scripts.clone(),
+                                                  alerts.clone(), LogsManager::new(100)).unwrap();
+        UpsMonitor::new(config, alerts, remediation, scripts)
+    }
+
+    // Answers one connection with the bytes, after reading the request
+    async fn serve(answer: Vec<u8>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 64];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(&answer).await.unwrap();
+        });
+        port
+    }
+
+    fn unit(driver: UpsDriver, port: u16) -> UpsUnitConfig {
+        UpsUnitConfig {
+            name: "rack".to_string(),
+            driver,
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            ups: Some("ups".to_string()),
+        }
+    }
+
+    fn record(text: &str) -> Vec<u8> {
+        let mut bytes = (text.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    fn reading(on_battery: bool, charge: f64, runtime_secs: u64) -> UpsReading {
+        UpsReading {
+            status: if on_battery { "OB" } else { "OL" }.to_string(),
+            on_battery,
+            charge_percent: Some(charge),
+            runtime_secs: Some(runtime_secs),
+            ..Default::default()
+        }
+    }
+
+    fn names(events: &[UpsEvent]) -> Vec<&'static str> {
+        events.iter().map(|event| match event {
+            UpsEvent::OnBattery(_) => "on_battery",
+            UpsEvent::OnMains => "on_mains",
+            UpsEvent::LowBattery(_) => "low_battery",
+            UpsEvent::CommLost(_) => "comm_lost",
+            UpsEvent::CommRestored => "comm_restored",
+            UpsEvent::RuntimeAction(..) => "runtime_action",
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn nut_variables_are_read() {
+        let port = serve(b"VAR ups ups.status \"OB LB\"\n\
+                           VAR ups battery.charge \"25\"\n\
+                           VAR ups battery.runtime \"300\"\n\
+                           VAR ups ups.load \"41.5\"\n\
+                           VAR ups ups.mfr \"Eaton Corp\"\n\
+                           END LIST VAR ups\n".to_vec()).await;
+        let reading = poll_nut(&unit(UpsDriver::Nut, port)).await.unwrap();
+        assert_eq!(reading.status, "OB LB");
+        assert!(reading.on_battery && reading.low_battery);
+        assert_eq!(reading.charge_percent, Some(25.0));
+        assert_eq!(reading.runtime_secs, Some(300));
+        assert_eq!(reading.load_percent, Some(41.5));
+
+        let port = serve(b"ERR UNKNOWN-UPS\n".to_vec()).await;
+        let error = poll_nut(&unit(UpsDriver::Nut, port)).await.unwrap_err();
+        assert!(error.to_string().contains("UNKNOWN-UPS"));
+
+        let port = serve(b"VAR ups ups.status \"OL\"\n".to_vec()).await;
+        assert!(poll_nut(&unit(UpsDriver::Nut, port)).await.is_err());
+
+        let nameless = UpsUnitConfig { ups: None, ..unit(UpsDriver::Nut, 1) };
+        assert!(poll_nut(&nameless).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn apcupsd_records_are_read() {
+        let mut answer = Vec::new();
+        for line in ["APC      : 001,036,0855", "STATUS   : ONBATT LOWBATT", "BCHARGE  : 80.0 Percent",
+                     "TIMELEFT : 12.5 Minutes", "LOADPCT  : 20.0 Percent"] {
+            answer.extend(record(line));
+        }
+        answer.extend_from_slice(&[0, 0]);
+        let port = serve(answer).await;
+        let reading = poll_apcupsd(&unit(UpsDriver::Apcupsd, port)).await.unwrap();
+        assert!(reading.on_battery && reading.low_battery);
+        assert_eq!(reading.charge_percent, Some(80.0));
+        assert_eq!(reading.runtime_secs, Some(750));
+        assert_eq!(reading.load_percent, Some(20.0));
+
+        let mut answer = record("BCHARGE  : 80.0 Percent");
+        answer.extend_from_slice(&[0, 0]);
+        let port = serve(answer).await;
+        let error = poll_apcupsd(&unit(UpsDriver::Apcupsd, port)).await.unwrap_err();
+        assert!(error.to_string().contains("no STATUS"));
+    }
+
+    #[tokio::test]
+    async fn transitions_are_reported_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = UpsConfig {
+            units: vec![unit(UpsDriver::Nut, 3493)],
+            comm_loss_polls: 2,
+            ..Default::default()
+        };
+        let monitor = monitor(&dir, config);
+        let mut status = monitor.status().unwrap().remove(0);
+        let now = Utc::now();
+        let mut update = |result: Result<UpsReading>| names(&monitor.update(&mut status, result, now));
+
+        assert!(update(Err(anyhow!("refused"))).is_empty());
+        assert_eq!(update(Err(anyhow!("refused"))), ["comm_lost"]);
+        assert!(update(Err(anyhow!("refused"))).is_empty());
+        assert_eq!(update(Ok(reading(true, 90.0, 3600))), ["comm_restored", "on_battery"]);
+        assert!(update(Ok(reading(true, 85.0, 3000))).is_empty());
+        assert_eq!(update(Ok(reading(true, 25.0, 2000))), ["low_battery"]);
+        assert!(update(Ok(reading(true, 20.0, 1500))).is_empty());
+        assert_eq!(update(Ok(reading(false, 20.0, 1500))), ["on_mains"]);
+        assert!(update(Ok(reading(false, 60.0, 2400))).is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_runtime_action_waits_for_its_cooldown() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = UpsConfig {
+            units: vec![unit(UpsDriver::Nut, 3493)],
+            runtime_action: Some(UpsActionConfig {
+                script: "shutdown-hosts".to_string(),
+                below_secs: 900,
+                arguments: HashMap::new(),
+                cooldown_secs: 3600,
+                dry_run: true,
+            }),
+            ..Default::default()
+        };
+        let monitor = monitor(&dir, config);
+        let mut status = monitor.status().unwrap().remove(0);
+        let now = Utc::now();
+
+        assert_eq!(names(&monitor.update(&mut status, Ok(reading(true, 90.0, 1200)), now)), ["on_battery"]);
+        let events = monitor.update(&mut status, Ok(reading(true, 80.0, 800)), now);
+        assert_eq!(names(&events), ["runtime_action"]);
+        assert!(monitor.update(&mut status, Ok(reading(true, 70.0, 700)), now + chrono::Duration::minutes(30)).is_empty());
+        let later = now + chrono::Duration::minutes(61);
+        assert_eq!(names(&monitor.update(&mut status, Ok(reading(true, 60.0, 650)), later)), ["runtime_action"]);
+    }
+}
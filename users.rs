@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::models::{NotificationPreferences, User, UserRole};
+use crate::password_policy::{PasswordPolicy, PolicyViolations};
+use crate::paths::write_private;
+
+// User record as persisted, the password hash never leaves this module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredUser {
+    #[serde(flatten)]
+    user: User,
+    password_hash: String,
+    // Earlier hashes, newest first, checked against reuse
+    #[serde(default)]
+    password_history: Vec<String>,
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[derive(Clone)]
+pub struct UserManager {
+    users_dir: PathBuf,
+    users: Arc<Mutex<HashMap<String, StoredUser>>>,
+    policy: PasswordPolicy,
+}
+
+impl UserManager {
+    pub fn new(users_dir: &str, policy: PasswordPolicy) -> Result<Self> {
+        let users_dir = PathBuf::from(users_dir);
+
+        if !users_dir.exists() {
+            fs::create_dir_all(&users_dir)
+                .context(format!("Failed to create users directory: {:?}", users_dir))?;
+            info!("Created users directory: {:?}", users_dir);
+        }
+
+        let mut users = HashMap::new();
+
+        for entry in fs::read_dir(&users_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path)
+                .context(format!("Failed to read user file: {:?}", path))?;
+            match serde_json::from_str::<StoredUser>(&contents) {
+                Ok(stored) => {
+                    users.insert(stored.user.username.clone(), stored);
+                },
+                Err(e) => warn!("Skipping invalid user file {:?}: {}", path, e),
+            }
+        }
+
+        let manager = Self {
+            users_dir,
+            users: Arc::new(Mutex::new(users)),
+            policy,
+        };
+
+        info!("Loaded {} user accounts", manager.get_all_users()?.len());
+        Ok(manager)
+    }
+
+    // Without the setup wizard (the config was edited by hand), an empty user store gets
+    // an admin account with a one-time password that has to be changed. The password goes
+    // to a file only the service account can read, never to the log.
+    pub fn ensure_initial_admin(&self) -> Result<()> {
+        if self.get_all_users()?.is_empty() {
+            let password = Uuid::new_v4().simple().to_string();
+            let path = self.initial_admin_password_path();
+            write_private(&path, format!("{}\n", password))
+                .context(format!("Failed to write initial admin password: {:?}", path))?;
+            self.insert_user("admin", "", "Administrator", UserRole::Admin, &password, true)?;
+            warn!("Created initial admin account, its password is in {:?} (change it after first login)", path);
+        }
+        Ok(())
+    }
+
+    fn initial_admin_password_path(&self) -> PathBuf {
+        self.users_dir.join("initial-admin-password")
+    }
+
+    // Checks a password against the policy without storing anything
+    pub fn check_password(&self, username: &str, password: &str) -> Result<()> {
+        let violations = self.policy.validate(username, password);
+        if !violations.is_empty() {
+            return Err(PolicyViolations(violations).into());
+        }
+        Ok(())
+    }
+
+    fn save_user(&self, stored: &StoredUser) -> Result<()> {
+        let path = self.users_dir.join(format!("{}.json", stored.user.id));
+        let json = serde_json::to_string_pretty(stored)?;
+        fs::write(&path, json)
+            .context(format!("Failed to write user file: {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn create_user(&self,
+                       username: &str,
+                       email: &str,
+                       full_name: &str,
+                       role: UserRole,
+                       password: &str) -> Result<User> {
+        let violations = self.policy.validate(username, password);
+        if !violations.is_empty() {
+            return Err(PolicyViolations(violations).into());
+        }
+
+        self.insert_user(username, email, full_name, role, password, false)
+    }
+
+    fn insert_user(&self,
+                   username: &str,
+                   email: &str,
+                   full_name: &str,
+                   role: UserRole,
+                   password: &str,
+                   must_change_password: bool) -> Result<User> {
+        if username.is_empty()
+            || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_') {
+            return Err(anyhow!("Invalid username: {}", username));
+        }
+
+        let now = Utc::now();
+        let stored = StoredUser {
+            user: User {
+                id: Uuid::new_v4(),
+                username: username.to_string(),
+                email: email.to_string(),
+                full_name: full_name.to_string(),
+                role,
+                is_active: true,
+                created_at: now,
+                last_login: None,
+                password_changed_at: Some(now),
+                must_change_password,
+                notifications: NotificationPreferences::default(),
+                external_id: None,
+                sites: Vec::new(),
+            },
+            password_hash: hash_password(password)?,
+            password_history: Vec::new(),
+        };
+
+        match self.users.lock() {
+            Ok(mut users) => {
+                if users.contains_key(username) {
+                    return Err(anyhow!("User already exists: {}", username));
+                }
+
+                self.save_user(&stored)?;
+                users.insert(username.to_string(), stored.clone());
+                info!("Created user: {}", username);
+                Ok(stored.user)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    // Checks the credentials of an active account and records the login time
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<User> {
+        match self.users.lock() {
+            Ok(mut users) => {
+                let stored = users.get_mut(username)
+                    .ok_or_else(|| anyhow!("Invalid username or password"))?;
+
+                if !verify_password(password, &stored.password_hash) {
+                    return Err(anyhow!("Invalid username or password"));
+                }
+
+                if !stored.user.is_active {
+                    return Err(anyhow!("Account is deactivated"));
+                }
+
+                stored.user.last_login = Some(Utc::now());
+                let stored = stored.clone();
+                self.save_user(&stored)?;
+                Ok(stored.user)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    // Creates or refreshes the account of a single sign-on user. Profile and role follow
+    // the identity provider on every login; a local account of the same name is never
+    // taken over.
+    pub fn upsert_external_user(&self,
+                                username: &str,
+                                external_id: &str,
+                                email: &str,
+                                full_name: &str,
+                                role: UserRole) -> Result<User> {
+        if username.is_empty()
+            || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' || c == '@') {
+            return Err(anyhow!("Invalid username: {}", username));
+        }
+
+        let now = Utc::now();
+
+        match self.users.lock() {
+            Ok(mut users) => {
+                if let Some(stored) = users.get_mut(username) {
+                    if stored.user.external_id.as_deref() != Some(external_id) {
+                        return Err(anyhow!("User {} exists and is not linked to this identity", username));
+                    }
+                    if !stored.user.is_active {
+                        return Err(anyhow!("User account is disabled"));
+                    }
+
+                    stored.user.email = email.to_string();
+                    stored.user.full_name = full_name.to_string();
+                    if stored.user.role != role {
+                        info!("Role of {} changed by identity provider: {:?} -> {:?}", username, stored.user.role, role);
+                        stored.user.role = role;
+                    }
+                    stored.user.last_login = Some(now);
+                    self.save_user(stored)?;
+                    return Ok(stored.user.clone());
+                }
+
+                // An empty hash never verifies, so the account cannot log in with a password
+                let stored = StoredUser {
+                    user: User {
+                        id: Uuid::new_v4(),
+                        username: username.to_string(),
+                        email: email.to_string(),
+                        full_name: full_name.to_string(),
+                        role,
+                        is_active: true,
+                        created_at: now,
+                        last_login: Some(now),
+                        password_changed_at: None,
+                        must_change_password: false,
+                        notifications: NotificationPreferences::default(),
+                        external_id: Some(external_id.to_string()),
+                        sites: Vec::new(),
+                    },
+                    password_hash: String::new(),
+                    password_history: Vec::new(),
+                };
+
+                self.save_user(&stored)?;
+                users.insert(username.to_string(), stored.clone());
+                info!("Provisioned single sign-on user: {}", username);
+                Ok(stored.user)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    pub fn verify_credentials(&self, username: &str, password: &str) -> Result<bool> {
+        match self.users.lock() {
+            Ok(users) => Ok(users.get(username)
+                .map_or(false, |stored| verify_password(password, &stored.password_hash))),
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    // Expired or reset passwords only allow logging in to change the password
+    pub fn password_change_required(&self, user: &User) -> bool {
+        if user.external_id.is_some() {
+            return false;
+        }
+        user.must_change_password
+            || self.policy.is_expired(user.password_changed_at.unwrap_or(user.created_at))
+    }
+
+    // Sets a new password that satisfies the policy. A reset by an admin (forced)
+    // makes the user pick their own password at the next login.
+    pub fn change_password(&self, username: &str, new_password: &str, forced: bool) -> Result<()> {
+        let mut violations = self.policy.validate(username, new_password);
+        let history_size = self.policy.history_size();
+        let password_hash = hash_password(new_password)?;
+
+        match self.users.lock() {
+            Ok(mut users) => {
+                let stored = users.get_mut(username)
+                    .ok_or_else(|| anyhow!("User not found: {}", username))?;
+
+                if stored.user.external_id.is_some() {
+                    return Err(anyhow!("The password of {} is managed by the identity provider", username));
+                }
+
+                // The current password counts as the most recent one
+                let reused = std::iter::once(&stored.password_hash)
+                    .chain(stored.password_history.iter())
+                    .take(history_size)
+                    .any(|hash| verify_password(new_password, hash));
+                if reused {
+                    violations.push(format!("must not match any of the last {} passwords", history_size));
+                }
+                if !violations.is_empty() {
+                    return Err(PolicyViolations(violations).into());
+                }
+
+                let previous = std::mem::replace(&mut stored.password_hash, password_hash);
+                stored.password_history.insert(0, previous);
+                stored.password_history.truncate(history_size);
+                stored.user.password_changed_at = Some(Utc::now());
+                stored.user.must_change_password = forced;
+                self.save_user(stored)?;
+                info!("Password {} for user: {}", if forced { "reset" } else { "changed" }, username);
+                Ok(())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    pub fn set_active(&self, username: &str, active: bool) -> Result<User> {
+        match self.users.lock() {
+            Ok(mut users) => {
+                let stored = users.get_mut(username)
+                    .ok_or_else(|| anyhow!("User not found: {}", username))?;
+
+                stored.user.is_active = active;
+                self.save_user(stored)?;
+                info!("User {} {}", username, if active { "activated" } else { "deactivated" });
+                Ok(stored.user.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    pub fn set_notifications(&self, username: &str, notifications: NotificationPreferences) -> Result<User> {
+        if let Some(url) = &notifications.webhook_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(anyhow!("Webhook URL must be http or https: {}", url));
+            }
+        }
+
+        match self.users.lock() {
+            Ok(mut users) => {
+                let stored = users.get_mut(username)
+                    .ok_or_else(|| anyhow!("User not found: {}", username))?;
+
+                stored.user.notifications = notifications;
+                self.save_user(stored)?;
+                Ok(stored.user.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    // Sets the role and the sites it applies to; no sites means all of them
+    pub fn assign_role(&self, username: &str, role: UserRole, sites: Vec<Uuid>) -> Result<User> {
+        match self.users.lock() {
+            Ok(mut users) => {
+                let stored = users.get_mut(username)
+                    .ok_or_else(|| anyhow!("User not found: {}", username))?;
+
+                stored.user.role = role;
+                stored.user.sites = sites;
+                self.save_user(stored)?;
+                info!("Assigned role {} to {} for {} sites", stored.user.role.role_name(), username, stored.user.sites.len());
+                Ok(stored.user.clone())
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    pub fn get_user(&self, username: &str) -> Result<User> {
+        match self.users.lock() {
+            Ok(users) => {
+                users.get(username)
+                    .map(|stored| stored.user.clone())
+                    .ok_or_else(|| anyhow!("User not found: {}", username))
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+
+    pub fn get_all_users(&self) -> Result<Vec<User>> {
+        match self.users.lock() {
+            Ok(users) => {
+                let mut all: Vec<User> = users.values().map(|stored| stored.user.clone()).collect();
+                all.sort_by(|a, b| a.username.cmp(&b.username));
+                Ok(all)
+            },
+            Err(_) => Err(anyhow!("Failed to acquire lock on users")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn initial_admin_password_is_written_to_an_owner_only_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = UserManager::new(dir.path().to_str().unwrap(), PasswordPolicy::new(Default::default())).unwrap();
+        users.ensure_initial_admin().unwrap();
+
+        let path = users.initial_admin_password_path();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let password = fs::read_to_string(&path).unwrap();
+        assert!(users.authenticate("admin", password.trim()).is_ok());
+    }
+}
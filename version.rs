@@ -0,0 +1,198 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, anyhow};
+use tracing::{info, warn};
+
+use crate::alerts::AlertsManager;
+use crate::config::UpdateCheckConfig;
+use crate::outbound::{self, HttpClients};
+use crate::models::{AlertSeverity, AlertStatus};
+
+// Ed25519 key release manifests are signed with, hex encoded, fixed at build time.
+// Builds without one cannot verify manifests and never report updates.
+const UPDATE_PUBLIC_KEY: Option<&str> = option_env!("SIEM_UPDATE_PUBLIC_KEY");
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Source of the update alerts
+const ALERT_SOURCE: &str = "update_check";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_date: Option<DateTime<Utc>>,
+    // Of the generated client types, see client_types
+    pub api_fingerprint: &'static str,
+}
+
+// Set by build.rs; a build outside a git checkout reports "unknown"
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("SIEM_GIT_HASH").unwrap_or("unknown"),
+        build_date: option_env!("SIEM_BUILD_TIMESTAMP")
+            .and_then(|t| t.parse().ok())
+            .and_then(|t| Utc.timestamp_opt(t, 0).single()),
+        api_fingerprint: option_env!("SIEM_API_FINGERPRINT").unwrap_or("unknown"),
+    }
+}
+
+// Release manifest as published at the manifest URL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateManifest {
+    pub latest_version: String,
+    pub minimum_supported_version: String,
+    pub release_notes_url: String,
+}
+
+// What is served at the manifest URL: the manifest JSON, base64 encoded, and the
+// base64 Ed25519 signature over exactly those decoded bytes
+#[derive(Debug, Deserialize)]
+struct SignedManifest {
+    manifest: String,
+    signature: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateStatus {
+    pub checked_at: DateTime<Utc>,
+    pub manifest: UpdateManifest,
+    pub update_available: bool,
+    pub unsupported: bool,
+}
+
+// Numeric release components; a leading "v" and any pre-release or build suffix are ignored
+fn parse_version(version: &str) -> Result<Vec<u64>> {
+    let core = version.trim().trim_start_matches('v')
+        .split(|c| c == '-' || c == '+')
+        .next()
+        .unwrap_or_default();
+    core.split('.')
+        .map(|part| part.parse::<u64>().map_err(|_| anyhow!("Invalid version: {}", version)))
+        .collect()
+}
+
+pub fn compare_versions(a: &str, b: &str) -> Result<Ordering> {
+    let (mut a, mut b) = (parse_version(a)?, parse_version(b)?);
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    Ok(a.cmp(&b))
+}
+
+fn verify_manifest(body: &[u8], public_key: &str) -> Result<UpdateManifest> {
+    let key = hex::decode(public_key.trim()).context("Embedded update public key is not hex")?;
+    let signed: SignedManifest = serde_json::from_slice(body).context("Update manifest is not a signed manifest")?;
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let manifest = engine.decode(signed.manifest.trim()).context("Update manifest is not base64")?;
+    let signature = engine.decode(signed.signature.trim()).context("Update manifest signature is not base64")?;
+
+    UnparsedPublicKey::new(&ED25519, &key)
+        .verify(&manifest, &signature)
+        .map_err(|_| anyhow!("Update manifest signature is invalid"))?;
+
+    let manifest: UpdateManifest = serde_json::from_slice(&manifest).context("Invalid update manifest")?;
+    parse_version(&manifest.latest_version)?;
+    parse_version(&manifest.minimum_supported_version)?;
+    Ok(manifest)
+}
+
+#[derive(Clone)]
+pub struct UpdateChecker {
+    config: UpdateCheckConfig,
+    http: reqwest::Client,
+    alerts: AlertsManager,
+    last: Arc<Mutex<Option<UpdateStatus>>>,
+}
+
+impl UpdateChecker {
+    pub fn new(config: UpdateCheckConfig, alerts: AlertsManager, clients: &HttpClients) -> Result<Self> {
+        let http = clients.client(outbound::UPDATE_CHECK, HTTP_TIMEOUT)?;
+
+        if config.enabled && UPDATE_PUBLIC_KEY.is_none() {
+            warn!("Update check enabled but this build has no update public key; updates will not be checked");
+        }
+
+        Ok(Self {
+            config,
+            http,
+            alerts,
+            last: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // Off for air-gapped sites, and in builds that could not verify a manifest anyway
+    pub fn enabled(&self) -> bool {
+        self.config.enabled && !self.config.manifest_url.is_empty() && UPDATE_PUBLIC_KEY.is_some()
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_hours.max(1) * 3600)
+    }
+
+    pub fn last_status(&self) -> Result<Option<UpdateStatus>> {
+        match self.last.lock() {
+            Ok(last) => Ok(last.clone()),
+            Err(_) => Err(anyhow!("Failed to acquire lock on update status")),
+        }
+    }
+
+    // Fetches and verifies the manifest, compares it with the running version and raises
+    // an alert for an available update or an unsupported running version
+    pub async fn check(&self) -> Result<UpdateStatus> {
+        let public_key = UPDATE_PUBLIC_KEY
+            .ok_or_else(|| anyhow!("This build has no update public key, manifests cannot be verified"))?;
+
+        let body = self.http.get(&self.config.manifest_url)
+            .send().await?
+            .error_for_status()?
+            .bytes().await?;
+        let manifest = verify_manifest(&body, public_key)?;
+
+        let running = env!("CARGO_PKG_VERSION");
+        let status = UpdateStatus {
+            checked_at: Utc::now(),
+            update_available: compare_versions(running, &manifest.latest_version)? == Ordering::Less,
+            unsupported: compare_versions(running, &manifest.minimum_supported_version)? == Ordering::Less,
+            manifest,
+        };
+
+        if status.unsupported {
+            self.raise(AlertSeverity::Medium,
+                       format!("Running version {} is no longer supported", running),
+                       format!("The minimum supported version is {}, the latest is {}. Release notes: {}",
+                               status.manifest.minimum_supported_version, status.manifest.latest_version, status.manifest.release_notes_url))?;
+        } else if status.update_available {
+            self.raise(AlertSeverity::Low,
+                       format!("Update available: {}", status.manifest.latest_version),
+                       format!("Running version {}. Release notes: {}", running, status.manifest.release_notes_url))?;
+        }
+
+        match self.last.lock() {
+            Ok(mut last) => *last = Some(status.clone()),
+            Err(_) => return Err(anyhow!("Failed to acquire lock on update status")),
+        }
+        Ok(status)
+    }
+
+    // One open alert per title, so the daily check does not repeat it
+    fn raise(&self, severity: AlertSeverity, title: String, description: String) -> Result<()> {
+        let open = self.alerts.get_all_alerts()?.into_iter().any(|a| a.source == ALERT_SOURCE
+            && a.title == title
+            && !matches!(a.status, AlertStatus::Resolved | AlertStatus::Closed));
+        if open {
+            return Ok(());
+        }
+
+        info!("{}", title);
+        self.alerts.create_alert(severity, title, description, ALERT_SOURCE.to_string(), Vec::new())?;
+        Ok(())
+    }
+}
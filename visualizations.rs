@@ -1,10 +1,17 @@
 
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use geo::{Point, LineString, MultiLineString, Polygon};
 use uuid::Uuid;
 use crate::network::InterfaceInfo;
+use crate::tasks::TaskRegistry;
+use crate::geoip::{self, GeoIpResolver, GeoLocation};
+use crate::sites::{SiteManager, SiteScope};
+use crate::graph_grouping;
+use crate::traffic_history::TrafficHistory;
+use crate::bandwidth_quota::BandwidthQuotas;
+use crate::locks::{LockHealth, LockHealthStatus};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkNode {
@@ -15,6 +22,119 @@ pub struct NetworkNode {
     pub properties: HashMap<String, String>,
 }
 
+// Diagram label lines: name and type, then the description and owner when set
+fn node_label(node: &NetworkNode) -> Vec<String> {
+    let node_type = format!("{:?}", node.node_type).to_lowercase();
+    let mut lines = vec![format!("{} ({})", node.name, node_type)];
+    lines.extend(node.properties.get("description").cloned());
+    lines.extend(node.properties.get("owner").map(|owner| format!("owner: {}", owner)));
+    lines
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Draws the graph at the node positions, scaled to fit
+fn render_svg(graph: &NetworkGraph) -> String {
+    const MARGIN: f64 = 80.0;
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+    for node in &graph.nodes {
+        min_x = min_x.min(node.position.x());
+        min_y = min_y.min(node.position.y());
+        max_x = max_x.max(node.position.x());
+        max_y = max_y.max(node.position.y());
+    }
+    let width = max_x - min_x + 4.0 * MARGIN;
+    let height = max_y - min_y + 2.0 * MARGIN;
+    let position = |point: Point<f64>| (point.x() - min_x + 2.0 * MARGIN, point.y() - min_y + MARGIN);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+        width, height,
+    );
+
+    for link in &graph.links {
+        let source = graph.nodes.iter().find(|n| n.id == link.source_id);
+        let target = graph.nodes.iter().find(|n| n.id == link.target_id);
+        if let (Some(source), Some(target)) = (source, target) {
+            let (x1, y1) = position(source.position);
+            let (x2, y2) = position(target.position);
+            svg.push_str(&format!(
+                "  <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#888\"/>\n",
+                x1, y1, x2, y2,
+            ));
+        }
+    }
+
+    for node in &graph.nodes {
+        let (x, y) = position(node.position);
+        let fill = if node.properties.get("is_up").map(|v| v == "false").unwrap_or(false) { "#f4cccc" } else { "#cfe2f3" };
+        let radius = if node.properties.contains_key(graph_grouping::GROUP_PROPERTY) { 18 } else { 12 };
+        svg.push_str(&format!(
+            "  <g id=\"{}\">\n    <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"{}\" stroke=\"#35608a\"/>\n",
+            xml_escape(&node.id), x, y, radius, fill,
+        ));
+        for (i, line) in node_label(node).iter().enumerate() {
+            svg.push_str(&format!(
+                "    <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+                x, y + 26.0 + 13.0 * i as f64, xml_escape(line),
+            ));
+        }
+        svg.push_str("  </g>\n");
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Renders the graph as json, dot or svg
+pub fn export_graph(graph: &NetworkGraph, format: &str) -> Result<Vec<u8>, String> {
+    match format {
+        "json" => {
+            match serde_json::to_vec_pretty(graph) {
+                Ok(data) => Ok(data),
+                Err(e) => Err(format!("Failed to serialize graph: {}", e)),
+            }
+        },
+        "dot" => {
+            // Generate Graphviz DOT format
+            let mut dot = String::new();
+            dot.push_str("digraph network {\n");
+            dot.push_str("  rankdir=TB;\n");
+            dot.push_str("  node [shape=box, style=filled, fillcolor=lightblue];\n\n");
+            
+            // Add nodes
+            for node in &graph.nodes {
+                let label = node_label(node).join("\\n").replace('"', "\\\"");
+                
+                dot.push_str(&format!("  \"{}\" [label=\"{}\"];\n", node.id, label));
+            }
+            
+            // Add edges
+            for link in &graph.links {
+                let mut link_type = format!("{:?}", link.link_type).to_lowercase();
+                // Merged links of collapsed groups, see graph_grouping
+                if let Some(count) = link.properties.get("link_count").filter(|c| *c != "1") {
+                    link_type.push_str(&format!(" x{}", count));
+                }
+                dot.push_str(&format!("  \"{}\" -> \"{}\" [label=\"{}\"];\n", 
+                                      link.source_id, link.target_id, link_type));
+            }
+            
+            dot.push_str("}\n");
+            
+            Ok(dot.into_bytes())
+        },
+        "svg" => Ok(render_svg(graph).into_bytes()),
+        _ => Err(format!("Unsupported format: {}", format)),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NodeType {
     Router,
@@ -81,12 +201,118 @@ pub struct TrafficFlow {
     pub bytes: u64,
     pub packets: u64,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    // Reverse DNS names, filled in when flows are read (see resolver::Resolver)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination_hostname: Option<String>,
+    // Site of the source address, or else of the destination, set when the flow is added
+    #[serde(default)]
+    pub site_id: Option<Uuid>,
+}
+
+// Recent flows with a running sequence number, so readers can page through the
+// store while the collector keeps appending and evicting
+struct FlowStore {
+    flows: VecDeque<TrafficFlow>,
+    first_seq: u64,
+}
+
+impl FlowStore {
+    fn next_seq(&self) -> u64 {
+        self.first_seq + self.flows.len() as u64
+    }
+}
+
+// Traffic between the site and one remote location, keyed by city (or country) centroid
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoFlowLocation {
+    pub label: String,
+    #[serde(flatten)]
+    pub location: Option<GeoLocation>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub flows: u64,
+    pub remote_hosts: usize,
+    pub local_hosts: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoFlowSummary {
+    pub site: String,
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // Locations dropped by the top-N cap
+    pub omitted_locations: usize,
+    pub internal_flows_excluded: u64,
+}
+
+// GeoJSON geometry; coordinates are [longitude, latitude]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum GeoGeometry {
+    Point { coordinates: [f64; 2] },
+    LineString { coordinates: Vec<[f64; 2]> },
+}
+
+impl GeoGeometry {
+    fn point(point: Point<f64>) -> Self {
+        GeoGeometry::Point { coordinates: [point.x(), point.y()] }
+    }
+    
+    fn arc(from: Point<f64>, to: Point<f64>) -> Self {
+        GeoGeometry::LineString { coordinates: vec![[from.x(), from.y()], [to.x(), to.y()]] }
+    }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoFeature {
+    #[serde(rename = "type")]
+    pub feature_type: &'static str,
+    // Null for traffic that could not be located
+    pub geometry: Option<GeoGeometry>,
+    pub properties: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoFeatureCollection {
+    #[serde(rename = "type")]
+    pub collection_type: &'static str,
+    pub features: Vec<GeoFeature>,
+    // Foreign member with the totals of the window
+    pub properties: GeoFlowSummary,
+}
+
+// The central node every interface hangs off
+const ROUTER_ID: &str = "router-main";
+
+#[derive(Clone)]
 pub struct VisualizationManager {
     network_graph: Arc<Mutex<NetworkGraph>>,
-    traffic_flows: Arc<Mutex<Vec<TrafficFlow>>>,
+    traffic_flows: Arc<Mutex<FlowStore>>,
     traffic_stats: Arc<Mutex<HashMap<String, InterfaceTrafficStats>>>,
+    traffic_history: TrafficHistory,
+    bandwidth_quotas: BandwidthQuotas,
+    sites: SiteManager,
+    health: LockHealth,
+    collector: Arc<Mutex<TrafficCollectorStatus>>,
+    // Held for a whole collection, so a refresh and the periodic run do not interleave
+    collecting: Arc<tokio::sync::Mutex<()>>,
+}
+
+// Name of the collector in the task registry
+const TRAFFIC_TASK: &str = "traffic_stats";
+
+// Runs of the traffic collector, so stale statistics can be told from a stopped collector
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrafficCollectorStatus {
+    pub interval_secs: u64,
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_duration_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub runs: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,7 +334,7 @@ pub struct TrafficDataPoint {
 }
 
 impl VisualizationManager {
-    pub fn new() -> Self {
+    pub fn new(sites: SiteManager, traffic_history: TrafficHistory, bandwidth_quotas: BandwidthQuotas) -> Self {
         // Create an empty network graph
         let network_graph = NetworkGraph {
             nodes: Vec::new(),
@@ -118,35 +344,94 @@ impl VisualizationManager {
         
         Self {
             network_graph: Arc::new(Mutex::new(network_graph)),
-            traffic_flows: Arc::new(Mutex::new(Vec::new())),
+            traffic_flows: Arc::new(Mutex::new(FlowStore {
+                flows: VecDeque::new(),
+                first_seq: 0,
+            })),
             traffic_stats: Arc::new(Mutex::new(HashMap::new())),
+            traffic_history,
+            bandwidth_quotas,
+            sites,
+            health: LockHealth::new("visualizations"),
+            collector: Arc::new(Mutex::new(TrafficCollectorStatus::default())),
+            collecting: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+    
+    pub fn start_traffic_monitoring(&self, tasks: &TaskRegistry, interval_secs: u64) -> anyhow::Result<()> {
+        self.spawn_collector(tasks, interval_secs, false)
+    }
+    
+    // Restarts the collector when the configured interval changed; returns whether it did
+    pub fn set_traffic_interval(&self, tasks: &TaskRegistry, interval_secs: u64) -> anyhow::Result<bool> {
+        if self.health.lock(&self.collector).interval_secs == interval_secs {
+            return Ok(false);
         }
+        self.spawn_collector(tasks, interval_secs, true)?;
+        Ok(true)
     }
     
-    pub fn start_traffic_monitoring(&self) -> Result<(), std::io::Error> {
-        let traffic_stats = self.traffic_stats.clone();
+    fn spawn_collector(&self, tasks: &TaskRegistry, interval_secs: u64, restart: bool) -> anyhow::Result<()> {
+        if interval_secs == 0 {
+            return Err(anyhow::anyhow!("Traffic monitoring interval must be at least 1 second"));
+        }
+        let interval = std::time::Duration::from_secs(interval_secs);
         
-        // Start a background task to collect traffic statistics
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
-            
-            loop {
-                interval.tick().await;
-                
-                if let Err(e) = Self::collect_traffic_stats(traffic_stats.clone()).await {
-                    eprintln!("Error collecting traffic stats: {}", e);
-                }
+        // Collect traffic statistics as a supervised background task
+        let manager = self.clone();
+        let task = move || {
+            let manager = manager.clone();
+            async move {
+                manager.collect_traffic().await?;
+                Ok(())
             }
-        });
+        };
+        if restart {
+            tasks.respawn(TRAFFIC_TASK, interval, task)?;
+        } else {
+            tasks.spawn(TRAFFIC_TASK, interval, task)?;
+        }
         
+        self.health.lock(&self.collector).interval_secs = interval_secs;
         Ok(())
     }
     
-    async fn collect_traffic_stats(traffic_stats: Arc<Mutex<HashMap<String, InterfaceTrafficStats>>>) -> Result<(), std::io::Error> {
+    // One collection cycle, timed and recorded in the collector status; returns the
+    // statistics it left behind
+    pub async fn collect_traffic(&self) -> anyhow::Result<HashMap<String, InterfaceTrafficStats>> {
+        let _running = self.collecting.lock().await;
+        let started_at = chrono::Utc::now();
+        let timer = std::time::Instant::now();
+        
+        let result = Self::collect_traffic_stats(self.traffic_stats.clone(),
+                                                 self.traffic_history.clone(),
+                                                 self.bandwidth_quotas.clone(),
+                                                 self.health.clone()).await;
+        
+        {
+            let mut status = self.health.lock(&self.collector);
+            status.last_run = Some(started_at);
+            status.last_duration_ms = Some(timer.elapsed().as_millis() as u64);
+            status.last_error = result.as_ref().err().map(|e| e.to_string());
+            status.runs += 1;
+        }
+        
+        result?;
+        Ok(self.get_traffic_statistics())
+    }
+    
+    pub fn traffic_collector_status(&self) -> TrafficCollectorStatus {
+        self.health.lock(&self.collector).clone()
+    }
+    
+    async fn collect_traffic_stats(traffic_stats: Arc<Mutex<HashMap<String, InterfaceTrafficStats>>>,
+                                   traffic_history: TrafficHistory,
+                                   bandwidth_quotas: BandwidthQuotas,
+                                   health: LockHealth) -> Result<(), std::io::Error> {
         // On Linux, read from /proc/net/dev
         let content = tokio::fs::read_to_string("/proc/net/dev").await?;
         
-        let mut stats = traffic_stats.lock().unwrap();
+        let mut stats = health.lock(&traffic_stats);
         let now = chrono::Utc::now();
         
         for line in content.lines().skip(2) { // Skip the header lines
@@ -174,6 +459,19 @@ impl VisualizationManager {
                 history: Vec::new(),
             });
             
+            // Counter deltas feed the tiered history; the first sample of an interface
+            // and counter resets leave a gap instead
+            if !entry.history.is_empty() {
+                if let (Some(rx), Some(tx)) = (rx_bytes.checked_sub(entry.rx_bytes), tx_bytes.checked_sub(entry.tx_bytes)) {
+                    if let Err(e) = traffic_history.record(&name, now, rx, tx) {
+                        tracing::warn!("Failed to record traffic history for {}: {}", name, e);
+                    }
+                    if let Err(e) = bandwidth_quotas.record(&name, now, rx, tx) {
+                        tracing::warn!("Failed to record bandwidth usage for {}: {}", name, e);
+                    }
+                }
+            }
+            
             // Save historical data point (keep last 1000 points)
             entry.history.push(TrafficDataPoint {
                 timestamp: entry.timestamp,
@@ -197,14 +495,14 @@ impl VisualizationManager {
     }
     
     pub fn get_network_graph(&self) -> NetworkGraph {
-        self.network_graph.lock().unwrap().clone()
+        self.health.lock(&self.network_graph).clone()
     }
     
     pub fn update_from_interfaces(&self, interfaces: &[InterfaceInfo]) {
-        let mut graph = self.network_graph.lock().unwrap();
+        let mut graph = self.health.lock(&self.network_graph);
         
         // Create a central router node if it doesn't exist
-        let router_id = "router-main".to_string();
+        let router_id = ROUTER_ID.to_string();
         if !graph.nodes.iter().any(|n| n.id == router_id) {
             graph.nodes.push(NetworkNode {
                 id: router_id.clone(),
@@ -215,20 +513,42 @@ impl VisualizationManager {
             });
         }
         
+        // Bond members hang off their bond instead of the router, so place
+        // standalone interfaces and bonds first
+        let mut ordered: Vec<&InterfaceInfo> = interfaces.iter().collect();
+        ordered.sort_by_key(|i| i.bond_master.is_some());
+        let top_level = ordered.iter().filter(|i| i.bond_master.is_none()).count().max(1);
+        
         // Create nodes for each interface
-        for (i, interface) in interfaces.iter().enumerate() {
+        for (i, interface) in ordered.iter().enumerate() {
             let interface_id = format!("interface-{}", interface.name);
+            let parent_id = interface.bond_master.as_ref()
+                .map(|master| format!("interface-{}", master))
+                .unwrap_or_else(|| router_id.clone());
+            let parent_position = graph.nodes.iter()
+                .find(|n| n.id == parent_id)
+                .map(|n| n.position)
+                .unwrap_or_else(|| Point::new(0.0, 0.0));
             
             // Check if the node already exists
             if !graph.nodes.iter().any(|n| n.id == interface_id) {
-                // Calculate position in a circle around the router
-                let angle = 2.0 * std::f64::consts::PI * (i as f64) / (interfaces.len() as f64);
-                let distance = 100.0;
-                let x = distance * angle.cos();
-                let y = distance * angle.sin();
+                // Calculate position in a circle around the router, members in a small
+                // circle around their bond
+                let (angle, distance) = match &interface.bond_master {
+                    Some(master) => {
+                        let siblings: Vec<&&InterfaceInfo> = ordered.iter()
+                            .filter(|s| s.bond_master.as_ref() == Some(master))
+                            .collect();
+                        let index = siblings.iter().position(|s| s.name == interface.name).unwrap_or(0);
+                        (2.0 * std::f64::consts::PI * (index as f64) / (siblings.len() as f64), 40.0)
+                    },
+                    None => (2.0 * std::f64::consts::PI * (i as f64) / (top_level as f64), 100.0),
+                };
+                let x = parent_position.x() + distance * angle.cos();
+                let y = parent_position.y() + distance * angle.sin();
                 
                 // Determine node type based on interface name
-                let node_type = if interface.name.starts_with("eth") {
+                let node_type = if interface.bond.is_some() || interface.name.starts_with("eth") {
                     NodeType::Switch
                 } else if interface.name.starts_with("wlan") {
                     NodeType::Wireless
@@ -250,48 +570,133 @@ impl VisualizationManager {
                 };
                 
                 graph.nodes.push(node);
-                
-                // Create a link between the router and this interface
-                let link = NetworkLink {
-                    id: Uuid::new_v4().to_string(),
-                    source_id: router_id.clone(),
-                    target_id: interface_id.clone(),
-                    link_type: LinkType::Ethernet,
-                    path: LineString::from(vec![(0.0, 0.0), (x, y)]),
-                    properties: HashMap::new(),
-                };
-                
-                graph.links.push(link);
+            }
+            
+            let position = graph.nodes.iter()
+                .find(|n| n.id == interface_id)
+                .map(|n| n.position)
+                .unwrap_or_else(|| Point::new(0.0, 0.0));
+            
+            // Link the interface to its parent, re-linking it when bond membership changed
+            match graph.links.iter_mut().find(|l| l.target_id == interface_id) {
+                Some(link) => {
+                    if link.source_id != parent_id {
+                        link.source_id = parent_id.clone();
+                        link.path = LineString::from(vec![parent_position.x_y(), position.x_y()]);
+                    }
+                },
+                None => {
+                    graph.links.push(NetworkLink {
+                        id: Uuid::new_v4().to_string(),
+                        source_id: parent_id.clone(),
+                        target_id: interface_id.clone(),
+                        link_type: LinkType::Ethernet,
+                        path: LineString::from(vec![parent_position.x_y(), position.x_y()]),
+                        properties: HashMap::new(),
+                    });
+                },
             }
             
             // Update properties for the interface node
             if let Some(node) = graph.nodes.iter_mut().find(|n| n.id == interface_id) {
                 node.properties.insert("is_up".to_string(), interface.is_up.to_string());
+                node.properties.insert("flapping".to_string(), interface.flapping.to_string());
                 
                 // Add IP addresses
                 for (i, addr) in interface.addresses.iter().enumerate() {
                     node.properties.insert(format!("ip_address_{}", i), addr.clone());
                 }
+                
+                if let Some(bond) = &interface.bond {
+                    if let Some(mode) = bond.mode {
+                        node.properties.insert("bond_mode".to_string(), format!("{:?}", mode));
+                    }
+                    match &bond.active_member {
+                        Some(active) => node.properties.insert("bond_active_member".to_string(), active.clone()),
+                        None => node.properties.remove("bond_active_member"),
+                    };
+                }
+
+                // Operator metadata replaces whatever the previous refresh put there
+                node.properties.retain(|key, _| !key.starts_with("tag_")
+                    && !matches!(key.as_str(), "description" | "owner" | "metadata_missing_since"));
+                if let Some(metadata) = &interface.metadata {
+                    if let Some(description) = &metadata.description {
+                        node.properties.insert("description".to_string(), description.clone());
+                    }
+                    if let Some(owner) = &metadata.owner {
+                        node.properties.insert("owner".to_string(), owner.clone());
+                    }
+                    for (key, value) in &metadata.tags {
+                        node.properties.insert(format!("tag_{}", key), value.clone());
+                    }
+                }
             }
         }
     }
     
-    pub fn add_traffic_flow(&self, flow: TrafficFlow) {
-        let mut flows = self.traffic_flows.lock().unwrap();
-        flows.push(flow);
+    pub fn add_traffic_flow(&self, mut flow: TrafficFlow) {
+        if flow.site_id.is_none() {
+            flow.site_id = self.sites.site_for_address(&flow.source)
+                .or_else(|| self.sites.site_for_address(&flow.destination));
+        }
+
+        let mut store = self.health.lock(&self.traffic_flows);
+        store.flows.push_back(flow);
         
         // Keep only the latest 1000 flows to avoid using too much memory
-        if flows.len() > 1000 {
-            flows.remove(0);
+        if store.flows.len() > 1000 {
+            store.flows.pop_front();
+            store.first_seq += 1;
         }
     }
     
     pub fn get_traffic_flows(&self) -> Vec<TrafficFlow> {
-        self.traffic_flows.lock().unwrap().clone()
+        self.health.lock(&self.traffic_flows).flows.iter().cloned().collect()
+    }
+    
+    // Sequence number the next stored flow will get
+    pub fn next_flow_seq(&self) -> u64 {
+        self.health.lock(&self.traffic_flows).next_seq()
+    }
+    
+    // Copies at most `limit` flows with sequence numbers in [from_seq, until_seq) whose
+    // timestamp lies in the window and whose site is in scope. Returns them with the
+    // sequence number to continue from.
+    pub fn get_traffic_flows_chunk(&self,
+                                   from_seq: u64,
+                                   until_seq: u64,
+                                   from: Option<chrono::DateTime<chrono::Utc>>,
+                                   to: Option<chrono::DateTime<chrono::Utc>>,
+                                   scope: &SiteScope,
+                                   limit: usize) -> (Vec<TrafficFlow>, u64) {
+        let store = self.health.lock(&self.traffic_flows);
+        
+        // Flows evicted since the last call are skipped
+        let start = from_seq.max(store.first_seq);
+        let mut chunk = Vec::new();
+        let mut next = start;
+        
+        for flow in store.flows.iter().skip((start - store.first_seq) as usize) {
+            if next >= until_seq {
+                break;
+            }
+            next += 1;
+            
+            if from.map_or(true, |f| flow.timestamp >= f) && to.map_or(true, |t| flow.timestamp <= t)
+                && scope.allows(flow.site_id) {
+                chunk.push(flow.clone());
+                if chunk.len() >= limit {
+                    break;
+                }
+            }
+        }
+        
+        (chunk, next)
     }
     
     pub fn create_zone(&self, name: &str, zone_type: ZoneType, nodes: &[String]) {
-        let mut graph = self.network_graph.lock().unwrap();
+        let mut graph = self.health.lock(&self.network_graph);
         
         // Find nodes in this zone
         let zone_nodes: Vec<&NetworkNode> = graph.nodes.iter()
@@ -348,21 +753,76 @@ impl VisualizationManager {
     }
     
     pub fn generate_topology_json(&self) -> String {
-        let graph = self.network_graph.lock().unwrap();
+        let graph = self.health.lock(&self.network_graph);
         serde_json::to_string_pretty(&*graph).unwrap_or_else(|_| "{}".to_string())
     }
     
     pub fn generate_traffic_flow_json(&self) -> String {
-        let flows = self.traffic_flows.lock().unwrap();
-        serde_json::to_string_pretty(&*flows).unwrap_or_else(|_| "[]".to_string())
+        let store = self.health.lock(&self.traffic_flows);
+        serde_json::to_string_pretty(&store.flows).unwrap_or_else(|_| "[]".to_string())
     }
     
     pub fn get_traffic_statistics(&self) -> HashMap<String, InterfaceTrafficStats> {
-        self.traffic_stats.lock().unwrap().clone()
+        self.health.lock(&self.traffic_stats).clone()
+    }
+
+    // The graph as a caller restricted to `scope` sees it: the router they share, the
+    // interfaces with an address in one of the sites and the members of those bonds.
+    // Other nodes, the links to them and the zones are left out.
+    pub fn scope_graph(&self, mut graph: NetworkGraph, scope: &SiteScope) -> NetworkGraph {
+        if *scope == SiteScope::All {
+            return graph;
+        }
+
+        let in_scope = |node: &NetworkNode| node.properties.iter()
+            .filter(|(key, _)| key.starts_with("ip_address_"))
+            .any(|(_, address)| {
+                let address = address.split('/').next().unwrap_or(address);
+                scope.allows(self.sites.site_for_address(address))
+            });
+        let mut kept: HashSet<String> = graph.nodes.iter()
+            .filter(|node| node.id == ROUTER_ID || in_scope(node))
+            .map(|node| node.id.clone())
+            .collect();
+        // Bond members carry no addresses of their own
+        let members: Vec<String> = graph.links.iter()
+            .filter(|link| link.source_id != ROUTER_ID && kept.contains(&link.source_id))
+            .map(|link| link.target_id.clone())
+            .collect();
+        kept.extend(members);
+
+        graph.nodes.retain(|node| kept.contains(&node.id));
+        graph.links.retain(|link| kept.contains(&link.source_id) && kept.contains(&link.target_id));
+        graph.zones.clear();
+        graph
+    }
+
+    // Whether the interface is in the graph a caller restricted to `scope` sees
+    pub fn interface_in_scope(&self, interface: &str, scope: &SiteScope) -> bool {
+        *scope == SiteScope::All || self.scope_graph(self.get_network_graph(), scope).nodes.iter()
+            .any(|node| node.id == format!("interface-{}", interface))
+    }
+
+    // Statistics of the interfaces in scope
+    pub fn scoped_traffic_statistics(&self, scope: &SiteScope) -> HashMap<String, InterfaceTrafficStats> {
+        let mut stats = self.get_traffic_statistics();
+        if *scope != SiteScope::All {
+            let graph = self.scope_graph(self.get_network_graph(), scope);
+            stats.retain(|name, _| graph.nodes.iter().any(|node| node.id == format!("interface-{}", name)));
+        }
+        stats
+    }
+    
+    pub fn traffic_history(&self) -> &TrafficHistory {
+        &self.traffic_history
+    }
+    
+    pub fn lock_health(&self) -> LockHealthStatus {
+        self.health.status()
     }
     
     pub fn get_traffic_history(&self, interface_name: &str) -> Vec<TrafficDataPoint> {
-        let stats = self.traffic_stats.lock().unwrap();
+        let stats = self.health.lock(&self.traffic_stats);
         if let Some(interface) = stats.get(interface_name) {
             interface.history.clone()
         } else {
@@ -370,43 +830,275 @@ impl VisualizationManager {
         }
     }
     
-    pub fn export_network_diagram(&self, format: &str) -> Result<Vec<u8>, String> {
-        let graph = self.network_graph.lock().unwrap();
-        
-        match format {
-            "json" => {
-                match serde_json::to_vec_pretty(&*graph) {
-                    Ok(data) => Ok(data),
-                    Err(e) => Err(format!("Failed to serialize graph: {}", e)),
-                }
-            },
-            "dot" => {
-                // Generate Graphviz DOT format
-                let mut dot = String::new();
-                dot.push_str("digraph network {\n");
-                dot.push_str("  rankdir=TB;\n");
-                dot.push_str("  node [shape=box, style=filled, fillcolor=lightblue];\n\n");
-                
-                // Add nodes
-                for node in &graph.nodes {
-                    let node_type = format!("{:?}", node.node_type).to_lowercase();
-                    let label = format!("{} ({})", node.name, node_type);
-                    
-                    dot.push_str(&format!("  \"{}\" [label=\"{}\"];\n", node.id, label));
-                }
-                
-                // Add edges
-                for link in &graph.links {
-                    let link_type = format!("{:?}", link.link_type).to_lowercase();
-                    dot.push_str(&format!("  \"{}\" -> \"{}\" [label=\"{}\"];\n", 
-                                          link.source_id, link.target_id, link_type));
-                }
-                
-                dot.push_str("}\n");
-                
-                Ok(dot.into_bytes())
-            },
-            _ => Err(format!("Unsupported format: {}", format)),
+    // Aggregates flows crossing the site boundary by remote location. Internal-to-internal
+    // and transit flows are left out; external addresses the GeoIP database does not know
+    // are grouped under "unknown". Only flows of sites in scope count, and only the `top`
+    // locations by volume are returned.
+    pub fn geo_flows(&self,
+                     resolver: &GeoIpResolver,
+                     site_name: &str,
+                     site: Point<f64>,
+                     from: chrono::DateTime<chrono::Utc>,
+                     to: chrono::DateTime<chrono::Utc>,
+                     scope: &SiteScope,
+                     top: usize) -> GeoFeatureCollection {
+        struct Aggregate {
+            location: Option<GeoLocation>,
+            bytes_in: u64,
+            bytes_out: u64,
+            flows: u64,
+            remote_hosts: std::collections::HashSet<std::net::IpAddr>,
+            local_hosts: std::collections::HashSet<std::net::IpAddr>,
         }
+        
+        let flows: Vec<TrafficFlow> = self.health.lock(&self.traffic_flows).flows.iter()
+            .filter(|f| f.timestamp >= from && f.timestamp <= to && scope.allows(f.site_id))
+            .cloned()
+            .collect();
+        
+        let mut aggregates: HashMap<String, Aggregate> = HashMap::new();
+        let mut internal_flows_excluded = 0;
+        
+        for flow in &flows {
+            let (source, destination) = match (geoip::parse_endpoint(&flow.source), geoip::parse_endpoint(&flow.destination)) {
+                (Some(source), Some(destination)) => (source, destination),
+                _ => continue,
+            };
+            
+            let (local, remote, outbound) = match (resolver.is_internal(source), resolver.is_internal(destination)) {
+                (true, false) => (source, destination, true),
+                (false, true) => (destination, source, false),
+                (true, true) => {
+                    internal_flows_excluded += 1;
+                    continue;
+                },
+                (false, false) => continue,
+            };
+            
+            let location = resolver.lookup(remote);
+            let label = match &location {
+                Some(GeoLocation { city: Some(city), country_code: Some(code), .. }) => format!("{}, {}", city, code),
+                Some(GeoLocation { country: Some(country), .. }) => country.clone(),
+                Some(location) => format!("{:.2}, {:.2}", location.latitude, location.longitude),
+                None => "unknown".to_string(),
+            };
+            
+            let aggregate = aggregates.entry(label).or_insert_with(|| Aggregate {
+                location,
+                bytes_in: 0,
+                bytes_out: 0,
+                flows: 0,
+                remote_hosts: std::collections::HashSet::new(),
+                local_hosts: std::collections::HashSet::new(),
+            });
+            
+            if outbound {
+                aggregate.bytes_out += flow.bytes;
+            } else {
+                aggregate.bytes_in += flow.bytes;
+            }
+            aggregate.flows += 1;
+            aggregate.remote_hosts.insert(remote);
+            aggregate.local_hosts.insert(local);
+        }
+        
+        let mut locations: Vec<GeoFlowLocation> = aggregates.into_iter()
+            .map(|(label, aggregate)| GeoFlowLocation {
+                label,
+                location: aggregate.location,
+                bytes_in: aggregate.bytes_in,
+                bytes_out: aggregate.bytes_out,
+                flows: aggregate.flows,
+                remote_hosts: aggregate.remote_hosts.len(),
+                local_hosts: aggregate.local_hosts.len(),
+            })
+            .collect();
+        locations.sort_by(|a, b| (b.bytes_in + b.bytes_out).cmp(&(a.bytes_in + a.bytes_out))
+            .then_with(|| a.label.cmp(&b.label)));
+        
+        let summary = GeoFlowSummary {
+            site: site_name.to_string(),
+            from,
+            to,
+            bytes_in: locations.iter().map(|l| l.bytes_in).sum(),
+            bytes_out: locations.iter().map(|l| l.bytes_out).sum(),
+            omitted_locations: locations.len().saturating_sub(top),
+            internal_flows_excluded,
+        };
+        locations.truncate(top);
+        
+        let mut features = vec![GeoFeature {
+            feature_type: "Feature",
+            geometry: Some(GeoGeometry::point(site)),
+            properties: serde_json::json!({ "kind": "site", "label": site_name }),
+        }];
+        
+        for location in locations {
+            let position = location.location.as_ref()
+                .map(|l| Point::new(l.longitude, l.latitude));
+            
+            let mut properties = serde_json::to_value(&location).unwrap_or_default();
+            properties["kind"] = serde_json::Value::from("location");
+            
+            if let Some(position) = position {
+                let mut arc_properties = properties.clone();
+                arc_properties["kind"] = serde_json::Value::from("arc");
+                features.push(GeoFeature {
+                    feature_type: "Feature",
+                    geometry: Some(GeoGeometry::arc(site, position)),
+                    properties: arc_properties,
+                });
+            }
+            
+            features.push(GeoFeature {
+                feature_type: "Feature",
+                geometry: position.map(GeoGeometry::point),
+                properties,
+            });
+        }
+        
+        GeoFeatureCollection {
+            collection_type: "FeatureCollection",
+            features,
+            properties: summary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    fn flow(source: &str) -> TrafficFlow {
+        TrafficFlow {
+            source: source.to_string(),
+            destination: "192.0.2.10".to_string(),
+            protocol: "tcp".to_string(),
+            port: 443,
+            bytes: 1500,
+            packets: 3,
+            timestamp: chrono::Utc::now(),
+            source_hostname: None,
+            destination_hostname: None,
+            site_id: None,
+        }
+    }
+
+    #[test]
+    fn panic_inside_a_critical_section_leaves_visualizations_usable() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let manager = VisualizationManager::new(
+            SiteManager::new(&format!("{}/sites", root)).unwrap(),
+            TrafficHistory::new(&format!("{}/traffic", root)).unwrap(),
+            BandwidthQuotas::new(Default::default(), &format!("{}/bandwidth", root)).unwrap(),
+        );
+        manager.add_traffic_flow(flow("10.0.0.5"));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _graph = manager.health.lock(&manager.network_graph);
+            let _flows = manager.health.lock(&manager.traffic_flows);
+            panic!("injected");
+        }));
+        assert!(result.is_err());
+        assert!(manager.network_graph.is_poisoned() && manager.traffic_flows.is_poisoned());
+
+        manager.add_traffic_flow(flow("10.0.0.6"));
+        assert_eq!(manager.get_traffic_flows().len(), 2);
+        assert_eq!(manager.next_flow_seq(), 2);
+        manager.create_zone("lab", ZoneType::Private, &[]);
+        assert!(manager.get_network_graph().nodes.is_empty());
+
+        let health = manager.lock_health();
+        assert!(!health.healthy);
+        assert_eq!(health.recoveries, 2);
+    }
+
+    #[test]
+    fn geo_flows_only_count_flows_of_sites_in_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let sites = SiteManager::new(&format!("{}/sites", root)).unwrap();
+        let site = |name: &str, subnet: &str| sites.create_site(crate::sites::SiteFields {
+            name: name.to_string(),
+            subnets: vec![subnet.to_string()],
+            team: None,
+        }).unwrap().id;
+        let (prague, brno) = (site("Prague", "10.1.0.0/16"), site("Brno", "10.2.0.0/16"));
+        let manager = VisualizationManager::new(
+            sites,
+            TrafficHistory::new(&format!("{}/traffic", root)).unwrap(),
+            BandwidthQuotas::new(Default::default(), &format!("{}/bandwidth", root)).unwrap(),
+        );
+        for source in ["10.1.0.5", "10.2.0.5", "10.2.0.6"] {
+            manager.add_traffic_flow(TrafficFlow { destination: "8.8.8.8".to_string(), ..flow(source) });
+        }
+
+        let resolver = GeoIpResolver::new(&Default::default()).unwrap();
+        let now = chrono::Utc::now();
+        let bytes_out = |scope: &SiteScope| manager.geo_flows(&resolver, "HQ", Point::new(0.0, 0.0),
+                                                              now - chrono::Duration::hours(1), now, scope, 10)
+            .properties.bytes_out;
+        assert_eq!(bytes_out(&SiteScope::All), 4500);
+        assert_eq!(bytes_out(&SiteScope::Only(vec![prague])), 1500);
+        assert_eq!(bytes_out(&SiteScope::Only(vec![brno])), 3000);
+        assert_eq!(bytes_out(&SiteScope::Only(vec![Uuid::new_v4()])), 0);
+    }
+
+    #[test]
+    fn scoped_graph_keeps_the_router_and_the_interfaces_of_allowed_sites() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let sites = SiteManager::new(&format!("{}/sites", root)).unwrap();
+        let prague = sites.create_site(crate::sites::SiteFields {
+            name: "Prague".to_string(),
+            subnets: vec!["10.1.0.0/16".to_string()],
+            team: None,
+        }).unwrap().id;
+        let manager = VisualizationManager::new(
+            sites,
+            TrafficHistory::new(&format!("{}/traffic", root)).unwrap(),
+            BandwidthQuotas::new(Default::default(), &format!("{}/bandwidth", root)).unwrap(),
+        );
+
+        let node = |id: &str, address: Option<&str>| NetworkNode {
+            id: id.to_string(),
+            name: id.to_string(),
+            node_type: NodeType::Switch,
+            position: Point::new(0.0, 0.0),
+            properties: address.into_iter()
+                .map(|address| ("ip_address_0".to_string(), address.to_string()))
+                .collect(),
+        };
+        let link = |source: &str, target: &str| NetworkLink {
+            id: format!("{}-{}", source, target),
+            source_id: source.to_string(),
+            target_id: target.to_string(),
+            link_type: LinkType::Ethernet,
+            path: LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]),
+            properties: HashMap::new(),
+        };
+        let graph = NetworkGraph {
+            nodes: vec![
+                node(ROUTER_ID, None),
+                node("interface-bond0", Some("10.1.0.1/16")),
+                node("interface-eth0", None),
+                node("interface-eth1", Some("10.2.0.1/16")),
+            ],
+            links: vec![
+                link(ROUTER_ID, "interface-bond0"),
+                link("interface-bond0", "interface-eth0"),
+                link(ROUTER_ID, "interface-eth1"),
+            ],
+            zones: Vec::new(),
+        };
+
+        let ids = |graph: NetworkGraph| graph.nodes.into_iter().map(|node| node.id).collect::<Vec<_>>();
+        assert_eq!(ids(manager.scope_graph(graph.clone(), &SiteScope::All)).len(), 4);
+        let scoped = manager.scope_graph(graph.clone(), &SiteScope::Only(vec![prague]));
+        assert_eq!(scoped.links.len(), 2);
+        assert_eq!(ids(scoped), vec![ROUTER_ID, "interface-bond0", "interface-eth0"]);
+        assert_eq!(ids(manager.scope_graph(graph, &SiteScope::Only(vec![Uuid::new_v4()]))), vec![ROUTER_ID]);
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use chrono::NaiveDate;
+use serde::{Serialize, Deserialize};
+
+use crate::flow_export::csv_field;
+use crate::tickets::Ticket;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WorklogGrouping {
+    #[default]
+    Ticket,
+    // The ticket's assignee; time on unassigned tickets is under "unassigned"
+    Assignee,
+    // Who logged the time
+    Author,
+    Category,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorklogTotal {
+    pub key: String,
+    // Ticket title when grouped by ticket
+    pub label: Option<String>,
+    pub entries: usize,
+    pub total_minutes: u64,
+    pub billable_minutes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorklogReport {
+    pub from: NaiveDate,
+    // Inclusive
+    pub to: NaiveDate,
+    pub group_by: WorklogGrouping,
+    pub totals: Vec<WorklogTotal>,
+    pub total_minutes: u64,
+    pub billable_minutes: u64,
+}
+
+impl WorklogReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("key,label,entries,total_minutes,billable_minutes\n");
+        for total in &self.totals {
+            csv.push_str(&format!("{},{},{},{},{}\n",
+                                  csv_field(&total.key),
+                                  csv_field(total.label.as_deref().unwrap_or_default()),
+                                  total.entries,
+                                  total.total_minutes,
+                                  total.billable_minutes));
+        }
+        csv
+    }
+}
+
+// Time logged on the tickets between the two dates (by the day work started), summed per
+// group; groups with the most time first
+pub fn worklog_report(tickets: &[Ticket], from: NaiveDate, to: NaiveDate, group_by: WorklogGrouping) -> WorklogReport {
+    let mut totals: BTreeMap<String, WorklogTotal> = BTreeMap::new();
+
+    for ticket in tickets {
+        let (key, label) = match group_by {
+            WorklogGrouping::Ticket => (ticket.id.to_string(), Some(ticket.title.clone())),
+            WorklogGrouping::Assignee => (ticket.assigned_to.clone().unwrap_or_else(|| "unassigned".to_string()), None),
+            WorklogGrouping::Author => (String::new(), None),
+            WorklogGrouping::Category => (format!("{:?}", ticket.category), None),
+        };
+
+        for worklog in &ticket.worklogs {
+            let day = worklog.started_at.date_naive();
+            if day < from || day > to {
+                continue;
+            }
+
+            let key = if group_by == WorklogGrouping::Author { worklog.author.clone() } else { key.clone() };
+            let total = totals.entry(key.clone()).or_insert_with(|| WorklogTotal {
+                key,
+                label: label.clone(),
+                entries: 0,
+                total_minutes: 0,
+                billable_minutes: 0,
+            });
+            total.entries += 1;
+            total.total_minutes += worklog.duration_minutes as u64;
+            if worklog.billable {
+                total.billable_minutes += worklog.duration_minutes as u64;
+            }
+        }
+    }
+
+    let mut totals: Vec<WorklogTotal> = totals.into_values().collect();
+    totals.sort_by(|a, b| b.total_minutes.cmp(&a.total_minutes).then_with(|| a.key.cmp(&b.key)));
+
+    WorklogReport {
+        from,
+        to,
+        group_by,
+        total_minutes: totals.iter().map(|t| t.total_minutes).sum(),
+        billable_minutes: totals.iter().map(|t| t.billable_minutes).sum(),
+        totals,
+    }
+}